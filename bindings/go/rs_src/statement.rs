@@ -93,7 +93,7 @@ pub extern "C" fn stmt_parameter_count(ctx: *mut c_void) -> i32 {
         stmt.err = Some(LimboError::InternalError("Statement is closed".to_string()));
         return -1;
     };
-    statement.parameters_count() as i32
+    statement.parameter_count() as i32
 }
 
 #[no_mangle]
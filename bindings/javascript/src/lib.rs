@@ -587,7 +587,7 @@ fn bind_named_params(
     stmt: &mut RefMut<'_, turso_core::Statement>,
     obj: &napi::JsObject,
 ) -> Result<(), napi::Error> {
-    for idx in 1..stmt.parameters_count() + 1 {
+    for idx in 1..stmt.parameter_count() + 1 {
         let non_zero_idx = NonZero::new(idx).unwrap();
 
         let param = stmt.parameters().name(non_zero_idx);
@@ -779,6 +779,14 @@ impl turso_core::DatabaseStorage for DatabaseFile {
     fn size(&self) -> turso_core::Result<u64> {
         self.file.size()
     }
+
+    fn truncate(
+        &self,
+        len: usize,
+        c: turso_core::Completion,
+    ) -> turso_core::Result<Arc<turso_core::Completion>> {
+        self.file.truncate(len, c.into())
+    }
 }
 
 #[inline]
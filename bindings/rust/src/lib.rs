@@ -170,6 +170,16 @@ impl Connection {
         stmt.execute(params).await
     }
 
+    /// Execute a batch of semicolon-separated SQL statements on the database.
+    pub async fn execute_batch(&self, sql: &str) -> Result<()> {
+        let conn = self
+            .inner
+            .lock()
+            .map_err(|e| Error::MutexError(e.to_string()))?;
+        conn.execute_batch(sql, None)?;
+        Ok(())
+    }
+
     /// Prepare a SQL statement for later execution.
     pub async fn prepare(&self, sql: &str) -> Result<Statement> {
         let conn = self
@@ -222,6 +232,17 @@ impl Connection {
         Ok(())
     }
 
+    /// Sets how long statements wait for a lock held by another connection before failing with
+    /// a busy error.
+    pub fn busy_timeout(&self, timeout: std::time::Duration) -> Result<()> {
+        let conn = self
+            .inner
+            .lock()
+            .map_err(|e| Error::MutexError(e.to_string()))?;
+        conn.set_busy_timeout(timeout);
+        Ok(())
+    }
+
     pub fn is_autocommit(&self) -> Result<bool> {
         let conn = self
             .inner
@@ -230,6 +251,101 @@ impl Connection {
 
         Ok(conn.get_auto_commit())
     }
+
+    /// Returns the rowid of the last row inserted by a statement of this connection.
+    pub fn last_insert_rowid(&self) -> Result<i64> {
+        let conn = self
+            .inner
+            .lock()
+            .map_err(|e| Error::MutexError(e.to_string()))?;
+
+        Ok(conn.last_insert_rowid())
+    }
+
+    /// Returns the number of rows changed by the last INSERT, UPDATE or DELETE statement.
+    pub fn changes(&self) -> Result<u64> {
+        let conn = self
+            .inner
+            .lock()
+            .map_err(|e| Error::MutexError(e.to_string()))?;
+
+        Ok(conn.changes() as u64)
+    }
+
+    /// Returns the number of rows changed since the connection was opened.
+    pub fn total_changes(&self) -> Result<u64> {
+        let conn = self
+            .inner
+            .lock()
+            .map_err(|e| Error::MutexError(e.to_string()))?;
+
+        Ok(conn.total_changes() as u64)
+    }
+
+    /// Interrupts the statements of the connection that are running, for example from another
+    /// thread than the one running them.
+    pub fn interrupt(&self) -> Result<()> {
+        let conn = self
+            .inner
+            .lock()
+            .map_err(|e| Error::MutexError(e.to_string()))?;
+
+        conn.interrupt();
+        Ok(())
+    }
+
+    /// Start an online backup of this database into the database of `dest`.
+    pub fn backup(&self, dest: &Connection) -> Result<Backup> {
+        let source = self
+            .inner
+            .lock()
+            .map_err(|e| Error::MutexError(e.to_string()))?
+            .clone();
+        let dest = dest
+            .inner
+            .lock()
+            .map_err(|e| Error::MutexError(e.to_string()))?
+            .clone();
+        Ok(Backup {
+            inner: turso_core::Backup::new(source, dest)?,
+        })
+    }
+}
+
+/// An online backup of a database, copying its pages into another database a few at a time.
+pub struct Backup {
+    inner: turso_core::Backup,
+}
+
+unsafe impl Send for Backup {}
+
+impl Backup {
+    /// Copy up to `pages` pages, or all the remaining ones if `pages` is negative.
+    /// Returns whether the backup is complete.
+    pub async fn step(&mut self, pages: i32) -> Result<bool> {
+        Ok(self.inner.step(pages)?)
+    }
+
+    /// The number of pages left to copy.
+    pub fn remaining(&self) -> usize {
+        self.inner.remaining()
+    }
+
+    /// The number of pages of the source database.
+    pub fn page_count(&self) -> usize {
+        self.inner.page_count()
+    }
+
+    /// Finish the backup, leaving the destination unchanged if it isn't complete.
+    pub fn finish(self) -> Result<()> {
+        Ok(self.inner.finish()?)
+    }
+}
+
+impl Debug for Backup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Backup").finish()
+    }
 }
 
 impl Debug for Connection {
@@ -343,7 +459,7 @@ impl Statement {
             let name = stmt.get_column_name(i).into_owned();
             cols.push(Column {
                 name,
-                decl_type: None, // TODO
+                decl_type: stmt.get_column_decltype(i).map(str::to_owned),
             });
         }
 
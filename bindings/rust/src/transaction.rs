@@ -95,8 +95,7 @@ impl Transaction<'_> {
             TransactionBehavior::Immediate => "BEGIN IMMEDIATE",
             TransactionBehavior::Exclusive => "BEGIN EXCLUSIVE",
         };
-        // TODO: Use execute_batch instead
-        conn.execute(query, ()).await.map(move |_| Transaction {
+        conn.execute_batch(query).await.map(move |_| Transaction {
             conn,
             drop_behavior: DropBehavior::Rollback,
             must_finish: true,
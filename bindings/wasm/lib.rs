@@ -257,6 +257,17 @@ impl turso_core::File for File {
         Ok(c)
     }
 
+    fn truncate(
+        &self,
+        len: usize,
+        c: Arc<turso_core::Completion>,
+    ) -> Result<Arc<turso_core::Completion>> {
+        self.vfs.truncate(self.fd, len);
+        c.complete(0);
+        #[allow(clippy::arc_with_non_send_sync)]
+        Ok(c)
+    }
+
     fn size(&self) -> Result<u64> {
         Ok(self.vfs.size(self.fd))
     }
@@ -379,6 +390,14 @@ impl turso_core::DatabaseStorage for DatabaseFile {
     fn size(&self) -> Result<u64> {
         self.file.size()
     }
+
+    fn truncate(
+        &self,
+        len: usize,
+        c: turso_core::Completion,
+    ) -> Result<Arc<turso_core::Completion>> {
+        self.file.truncate(len, c.into())
+    }
 }
 
 #[cfg(all(feature = "web", not(feature = "nodejs")))]
@@ -405,6 +424,9 @@ extern "C" {
 
     #[wasm_bindgen(method)]
     fn sync(this: &VFS, fd: i32);
+
+    #[wasm_bindgen(method)]
+    fn truncate(this: &VFS, fd: i32, len: usize);
 }
 
 #[cfg(all(feature = "nodejs", not(feature = "web")))]
@@ -431,6 +453,9 @@ extern "C" {
 
     #[wasm_bindgen(method)]
     fn sync(this: &VFS, fd: i32);
+
+    #[wasm_bindgen(method)]
+    fn truncate(this: &VFS, fd: i32, len: usize);
 }
 
 #[wasm_bindgen(start)]
@@ -129,6 +129,12 @@ class VFS {
     this.worker.postMessage({ cmd: "sync", fd });
     Atomics.wait(this.statusArray, 0, 0);
   }
+
+  truncate(fd, len) {
+    Atomics.store(this.statusArray, 0, 0);
+    this.worker.postMessage({ cmd: "truncate", fd, size: len });
+    Atomics.wait(this.statusArray, 0, 0);
+  }
 }
 
 // logLevel:
@@ -26,4 +26,8 @@ export class VFS {
   sync(fd) {
     return self.vfs.sync(fd);
   }
+
+  truncate(fd, len) {
+    return self.vfs.truncate(fd, len);
+  }
 }
@@ -1,13 +1,14 @@
 use crate::{
     commands::{
-        args::{EchoMode, HeadersMode, TimerMode},
+        args::{BailMode, EchoMode, HeadersMode, StatsMode, TimerMode},
         import::ImportFile,
         Command, CommandParser,
     },
     config::Config,
     helper::LimboHelper,
-    input::{get_io, get_writer, DbLocation, OutputMode, Settings},
+    input::{get_io, get_writer, is_complete, DbLocation, OutputMode, Settings},
     opcodes_dictionary::OPCODE_DESCRIPTIONS,
+    output::{indent_sql, quote_ident, sql_literal, RowWriter},
     HISTORY_FILE,
 };
 use anyhow::anyhow;
@@ -15,7 +16,6 @@ use clap::Parser;
 use comfy_table::{Attribute, Cell, CellAlignment, ContentArrangement, Row, Table};
 use rustyline::{error::ReadlineError, history::DefaultHistory, Editor};
 use std::{
-    fmt,
     io::{self, BufRead as _, IsTerminal, Write},
     path::PathBuf,
     sync::{
@@ -27,7 +27,9 @@ use std::{
 
 use tracing_appender::non_blocking::WorkerGuard;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter};
-use turso_core::{Connection, Database, LimboError, OpenFlags, Statement, StepResult, Value};
+use turso_core::{
+    Connection, Database, LimboError, OpenFlags, Statement, StatementStats, StepResult, Value,
+};
 
 #[derive(Parser, Debug)]
 #[command(name = "Turso")]
@@ -66,6 +68,19 @@ pub struct Opts {
     pub tracing_output: Option<String>,
     #[clap(long, help = "Start MCP server instead of interactive shell")]
     pub mcp: bool,
+    #[clap(
+        long,
+        help = "Read and run the statements and commands of FILE at startup"
+    )]
+    pub init: Option<PathBuf>,
+    #[clap(
+        long,
+        value_name = "COMMAND",
+        help = "Run COMMAND at startup, after the init file (can be repeated)"
+    )]
+    pub cmd: Vec<String>,
+    #[clap(long, help = "Stop after hitting an error")]
+    pub bail: bool,
 }
 
 const PROMPT: &str = "turso> ";
@@ -80,11 +95,48 @@ pub struct Limbo {
     opts: Settings,
     pub rl: Option<Editor<LimboHelper, DefaultHistory>>,
     config: Option<Config>,
+    /// Whether a statement failed since the last call to `should_bail`.
+    failed: bool,
 }
 
+/// What a statement cost, which `.timer` and `.stats` print once it's done.
 struct QueryStatistics {
+    start: Instant,
+    /// The user and system CPU time of the process when the statement started.
+    cpu_start: (Duration, Duration),
     io_time_elapsed_samples: Vec<Duration>,
     execute_time_elapsed_samples: Vec<Duration>,
+    statement: StatementStats,
+}
+
+impl QueryStatistics {
+    fn new() -> Self {
+        Self {
+            start: Instant::now(),
+            cpu_start: cpu_times(),
+            io_time_elapsed_samples: vec![],
+            execute_time_elapsed_samples: vec![],
+            statement: StatementStats::default(),
+        }
+    }
+}
+
+/// The user and system CPU time the process has used so far.
+#[cfg(unix)]
+fn cpu_times() -> (Duration, Duration) {
+    let mut usage = unsafe { std::mem::zeroed::<libc::rusage>() };
+    if unsafe { libc::getrusage(libc::RUSAGE_SELF, &mut usage) } != 0 {
+        return (Duration::ZERO, Duration::ZERO);
+    }
+    let duration = |time: libc::timeval| {
+        Duration::from_secs(time.tv_sec as u64) + Duration::from_micros(time.tv_usec as u64)
+    };
+    (duration(usage.ru_utime), duration(usage.ru_stime))
+}
+
+#[cfg(not(unix))]
+fn cpu_times() -> (Duration, Duration) {
+    (Duration::ZERO, Duration::ZERO)
 }
 
 macro_rules! query_internal {
@@ -154,6 +206,8 @@ impl Limbo {
         }
         let sql = opts.sql.clone();
         let quiet = opts.quiet;
+        let init = opts.init.clone();
+        let cmds = opts.cmd.clone();
         let config = Config::for_output_mode(opts.output_mode);
         let mut app = Self {
             prompt: PROMPT.to_string(),
@@ -165,8 +219,10 @@ impl Limbo {
             opts: Settings::from(opts),
             rl: None,
             config: Some(config),
+            failed: false,
         };
         let guard = app.init_tracing()?;
+        app.run_startup_commands(init, cmds);
         app.first_run(sql, quiet)?;
         Ok((app, guard))
     }
@@ -186,6 +242,36 @@ impl Limbo {
         self
     }
 
+    /// Runs the init file and then the commands given on the command line, exiting if one of
+    /// them fails while `--bail` is on.
+    fn run_startup_commands(&mut self, init: Option<PathBuf>, cmds: Vec<String>) {
+        if self.is_mcp_mode() {
+            return;
+        }
+        let mut bail = false;
+        if let Some(init) = init {
+            if let Err(e) = self.read_file(&init.to_string_lossy()) {
+                let _ = self.writeln(format!("Error: {e}"));
+                self.failed = true;
+            }
+            bail = self.should_bail();
+        }
+        for cmd in cmds {
+            if bail {
+                break;
+            }
+            if let Err(e) = self.run_script(&cmd) {
+                let _ = self.writeln(format!("Error: {e}"));
+                self.failed = true;
+            }
+            bail = self.should_bail();
+        }
+        if bail {
+            let _ = self.close_conn();
+            std::process::exit(1);
+        }
+    }
+
     fn first_run(&mut self, sql: Option<String>, quiet: bool) -> Result<(), LimboError> {
         // Skip startup messages and SQL execution in MCP mode
         if self.is_mcp_mode() {
@@ -212,8 +298,49 @@ impl Limbo {
         } else {
             self.run_query(cmd);
         }
+        let code = if self.should_bail() { 1 } else { 0 };
         self.close_conn()?;
-        std::process::exit(0);
+        std::process::exit(code);
+    }
+
+    /// Returns whether a statement failed since the last call while `.bail` is on, in which case
+    /// the script being run should stop.
+    pub fn should_bail(&mut self) -> bool {
+        std::mem::take(&mut self.failed) && self.opts.bail
+    }
+
+    pub fn is_interactive(&self) -> bool {
+        self.rl.is_some()
+    }
+
+    fn read_file(&mut self, path: &str) -> anyhow::Result<()> {
+        let script =
+            std::fs::read_to_string(path).map_err(|e| anyhow!("cannot open \"{path}\": {e}"))?;
+        self.run_script(&script)
+    }
+
+    /// Runs the statements and commands of a script like lines typed in the shell.
+    fn run_script(&mut self, script: &str) -> anyhow::Result<()> {
+        let saved_input = std::mem::take(&mut self.input_buff);
+        let mut result = Ok(());
+        for line in script.lines() {
+            if let Err(e) = self.handle_input_line(line) {
+                let _ = self.writeln(e.to_string());
+            }
+            if self.should_bail() {
+                result = Err(anyhow!("stopped at the first error, as .bail is on"));
+                break;
+            }
+        }
+        if result.is_ok() {
+            self.handle_remaining_input();
+            if self.should_bail() {
+                result = Err(anyhow!("stopped at the first error, as .bail is on"));
+            }
+        }
+        self.reset_input();
+        self.input_buff = saved_input;
+        result
     }
 
     fn set_multiline_prompt(&mut self) {
@@ -238,63 +365,46 @@ impl Limbo {
     }
 
     fn dump_table(&mut self, name: &str) -> Result<(), LimboError> {
-        let query = format!("pragma table_info={name}");
-        let mut cols = vec![];
-        let mut value_types = vec![];
-        query_internal!(
-            self,
-            query,
-            |row: &turso_core::Row| -> Result<(), LimboError> {
-                let name: &str = row.get::<&str>(1)?;
-                cols.push(name.to_string());
-                let value_type: &str = row.get::<&str>(2)?;
-                value_types.push(value_type.to_string());
-                Ok(())
-            }
-        )?;
         // FIXME: sqlite has logic to check rowid and optionally preserve
         // it, but it requires pragma index_list, and it seems to be relevant
         // only for indexes.
-        let cols_str = cols.join(", ");
-        let select = format!("select {cols_str} from {name}");
+        let name = quote_ident(name);
+        let select = format!("SELECT * FROM {name}");
         query_internal!(
             self,
             select,
             |row: &turso_core::Row| -> Result<(), LimboError> {
-                let values = row
-                    .get_values()
-                    .zip(value_types.iter())
-                    .map(|(value, value_type)| {
-                        // If the type affinity is TEXT, replace each single
-                        // quotation mark with two single quotation marks, and
-                        // wrap it with single quotation marks.
-                        if value_type.contains("CHAR")
-                            || value_type.contains("CLOB")
-                            || value_type.contains("TEXT")
-                        {
-                            format!("'{}'", value.to_string().replace("'", "''"))
-                        } else if value_type.contains("BLOB") {
-                            let blob = value.to_blob().unwrap_or(&[]);
-                            let hex_string: String =
-                                blob.iter().fold(String::new(), |mut output, b| {
-                                    let _ =
-                                        fmt::Write::write_fmt(&mut output, format_args!("{b:02x}"));
-                                    output
-                                });
-                            format!("X'{hex_string}'")
-                        } else {
-                            value.to_string()
-                        }
-                    })
-                    .collect::<Vec<_>>()
-                    .join(",");
-                self.write_fmt(format_args!("INSERT INTO {name} VALUES({values});"))?;
+                let values = row.get_values().map(sql_literal).collect::<Vec<_>>();
+                self.write_fmt(format_args!(
+                    "INSERT INTO {name} VALUES({});",
+                    values.join(",")
+                ))?;
                 Ok(())
             }
         )?;
         Ok(())
     }
 
+    /// Writes the statement creating the table `name` in a dump, and returns whether its rows
+    /// should follow.
+    fn dump_create_table(&mut self, name: &str, sql: &str) -> io::Result<bool> {
+        if name.eq_ignore_ascii_case("sqlite_sequence") {
+            // Created along with the first AUTOINCREMENT table
+            self.writeln("DELETE FROM sqlite_sequence;")?;
+            return Ok(true);
+        }
+        if name.to_ascii_lowercase().starts_with("sqlite_") {
+            return Ok(false);
+        }
+        self.write_fmt(format_args!("{sql};"))?;
+        // The rows of a virtual table are stored by its module.
+        let is_virtual = sql
+            .trim_start()
+            .to_ascii_uppercase()
+            .starts_with("CREATE VIRTUAL TABLE");
+        Ok(!is_virtual)
+    }
+
     fn dump_database(&mut self) -> anyhow::Result<()> {
         self.writeln("PRAGMA foreign_keys=OFF;")?;
         self.writeln("BEGIN TRANSACTION;")?;
@@ -314,8 +424,10 @@ impl Limbo {
             |row: &turso_core::Row| -> Result<(), LimboError> {
                 let sql: &str = row.get::<&str>(2)?;
                 let name: &str = row.get::<&str>(0)?;
-                self.write_fmt(format_args!("{sql};"))?;
-                self.dump_table(name)
+                if self.dump_create_table(name, sql)? {
+                    self.dump_table(name)?;
+                }
+                Ok(())
             }
         );
 
@@ -330,7 +442,48 @@ impl Limbo {
             Err(x) => Err(x),
         }?;
 
-        self.conn.close()?;
+        // The indexes, triggers and views come after the rows, so that the rows are inserted
+        // once the indexes are built and without firing the triggers.
+        let query = r#"
+    SELECT sql
+    FROM sqlite_schema AS o
+    WHERE type IN ('index', 'trigger', 'view')
+        AND sql NOT NULL
+    ORDER BY type == 'view', rowid"#;
+        query_internal!(
+            self,
+            query,
+            |row: &turso_core::Row| -> Result<(), LimboError> {
+                let sql: &str = row.get::<&str>(0)?;
+                self.write_fmt(format_args!("{sql};"))?;
+                Ok(())
+            }
+        )?;
+
+        self.writeln("COMMIT;")?;
+        Ok(())
+    }
+
+    /// Dumps what can be read from a damaged database, walking its pages rather than its btrees.
+    fn recover_database(&mut self) -> anyhow::Result<()> {
+        let recovery = self.conn.recover()?;
+        self.writeln("BEGIN;")?;
+        for table in &recovery.tables {
+            if !self.dump_create_table(&table.name, &table.sql)? {
+                continue;
+            }
+            let name = quote_ident(&table.name);
+            for row in &table.rows {
+                let values = row.iter().map(sql_literal).collect::<Vec<_>>();
+                self.write_fmt(format_args!(
+                    "INSERT INTO {name} VALUES({});",
+                    values.join(",")
+                ))?;
+            }
+        }
+        for sql in &recovery.schema {
+            self.write_fmt(format_args!("{sql};"))?;
+        }
         self.writeln("COMMIT;")?;
         Ok(())
     }
@@ -442,7 +595,6 @@ impl Limbo {
 
     fn buffer_input(&mut self, line: &str) {
         self.input_buff.push_str(line);
-        self.input_buff.push(' ');
     }
 
     fn run_query(&mut self, input: &str) {
@@ -451,11 +603,6 @@ impl Limbo {
             let _ = self.writeln(input);
         }
 
-        let start = Instant::now();
-        let mut stats = QueryStatistics {
-            io_time_elapsed_samples: vec![],
-            execute_time_elapsed_samples: vec![],
-        };
         // TODO this is a quickfix. Some ideas to do case insensitive comparisons is to use
         // Uncased or Unicase.
         let explain_str = "explain";
@@ -465,120 +612,138 @@ impl Limbo {
             .map(|s| s.eq_ignore_ascii_case(explain_str))
             .unwrap_or(false)
         {
+            let stats = QueryStatistics::new();
             match self.conn.query(input) {
+                Ok(Some(stmt)) if is_explain_query_plan(input) => {
+                    if let Err(e) = self.print_query_plan(stmt) {
+                        let _ = self.writeln(e.to_string());
+                    }
+                }
                 Ok(Some(stmt)) => {
                     let _ = self.writeln(stmt.explain().as_bytes());
                 }
                 Err(e) => {
                     let _ = self.writeln(e.to_string());
+                    self.failed = true;
                 }
                 _ => {}
             }
+            self.print_query_performance_stats(stats);
         } else {
             let conn = self.conn.clone();
-            let runner = conn.query_runner(input.as_bytes());
-            for output in runner {
-                if self
-                    .print_query_result(input, output, Some(&mut stats))
-                    .is_err()
-                {
+            let mut runner = conn.query_runner(input.as_bytes());
+            loop {
+                let mut stats = QueryStatistics::new();
+                let Some(output) = runner.next() else {
+                    break;
+                };
+                let result = self.print_query_result(input, output, Some(&mut stats));
+                self.print_query_performance_stats(stats);
+                if result.is_err() {
+                    self.failed = true;
                     break;
                 }
             }
         }
-        self.print_query_performance_stats(start, stats);
         self.reset_input();
     }
 
-    fn print_query_performance_stats(&mut self, start: Instant, stats: QueryStatistics) {
-        let elapsed_as_str = |duration: Duration| {
-            if duration.as_secs() >= 1 {
-                format!("{} s", duration.as_secs_f64())
-            } else if duration.as_millis() >= 1 {
-                format!("{} ms", duration.as_millis() as f64)
-            } else if duration.as_micros() >= 1 {
-                format!("{} us", duration.as_micros() as f64)
-            } else {
-                format!("{} ns", duration.as_nanos())
-            }
-        };
-        let sample_stats_as_str = |name: &str, samples: Vec<Duration>| {
-            if samples.is_empty() {
-                return format!("{name}: No samples available");
+    /// Prints the rows of an `EXPLAIN QUERY PLAN` statement as a tree, like the SQLite shell.
+    fn print_query_plan(&mut self, mut stmt: Statement) -> anyhow::Result<()> {
+        let mut rows = Vec::new();
+        loop {
+            match stmt.step()? {
+                StepResult::Row => {
+                    let row = stmt.row().unwrap();
+                    let id = row.get::<i64>(0)?;
+                    let parent = row.get::<i64>(1)?;
+                    let detail = row.get::<&str>(3)?.to_string();
+                    rows.push((id, parent, detail));
+                }
+                StepResult::IO => {
+                    stmt.run_once()?;
+                }
+                StepResult::Interrupt | StepResult::Done => break,
+                StepResult::Busy => return Err(anyhow!("database is busy")),
             }
-            let avg_time_spent = samples.iter().sum::<Duration>() / samples.len() as u32;
-            let total_time = samples.iter().fold(Duration::ZERO, |acc, x| acc + *x);
-            format!(
-                "{}: avg={}, total={}",
-                name,
-                elapsed_as_str(avg_time_spent),
-                elapsed_as_str(total_time),
-            )
-        };
+        }
+        let mut output = String::from("QUERY PLAN\n");
+        format_query_plan(&rows, 0, "", &mut output);
+        let _ = self.writer.write_all(output.as_bytes());
+        Ok(())
+    }
+
+    /// Prints the times of a statement for `.timer` and its counters for `.stats`, like the
+    /// SQLite shell.
+    fn print_query_performance_stats(&mut self, stats: QueryStatistics) {
         if self.opts.timer {
-            let _ = self.writeln("Command stats:\n----------------------------");
+            let (user, sys) = cpu_times();
             let _ = self.writeln(format!(
-                "total: {} (this includes parsing/coloring of cli app)\n",
-                elapsed_as_str(start.elapsed())
+                "Run Time: real {:.3} user {:.6} sys {:.6}",
+                stats.start.elapsed().as_secs_f64(),
+                user.saturating_sub(stats.cpu_start.0).as_secs_f64(),
+                sys.saturating_sub(stats.cpu_start.1).as_secs_f64(),
             ));
-
-            let _ = self.writeln("query execution stats:\n----------------------------");
-            let _ = self.writeln(sample_stats_as_str(
-                "Execution",
-                stats.execute_time_elapsed_samples,
-            ));
-            let _ = self.writeln(sample_stats_as_str("I/O", stats.io_time_elapsed_samples));
+        }
+        if self.opts.stats {
+            let total = |samples: &[Duration]| samples.iter().sum::<Duration>().as_secs_f64();
+            let lines = [
+                (
+                    "Virtual Machine Steps",
+                    stats.statement.vm_steps.to_string(),
+                ),
+                ("Pages Read", stats.statement.pages_read.to_string()),
+                ("Pages Written", stats.statement.pages_written.to_string()),
+                ("Page Cache Hits", stats.statement.cache_hits.to_string()),
+                (
+                    "Execution Time",
+                    format!("{:.6}", total(&stats.execute_time_elapsed_samples)),
+                ),
+                (
+                    "I/O Time",
+                    format!("{:.6}", total(&stats.io_time_elapsed_samples)),
+                ),
+            ];
+            for (name, value) in lines {
+                let _ = self.writeln(format!("{:<36} {value}", format!("{name}:")));
+            }
         }
     }
 
     fn reset_line(&mut self, _line: &str) -> rustyline::Result<()> {
-        // Entry is auto added to history
-        // self.rl.add_history_entry(line.to_owned())?;
         self.interrupt_count.store(0, Ordering::SeqCst);
         Ok(())
     }
 
     pub fn handle_input_line(&mut self, line: &str) -> anyhow::Result<()> {
         if self.input_buff.is_empty() {
+            let line = line.trim();
             if line.is_empty() {
                 return Ok(());
             }
             if let Some(command) = line.strip_prefix('.') {
+                self.add_history_entry(line);
                 self.handle_dot_command(command);
                 let _ = self.reset_line(line);
                 return Ok(());
             }
         }
-        if line.trim_start().starts_with("--") {
-            if let Some(remaining) = line.split_once('\n') {
-                let after_comment = remaining.1.trim();
-                if !after_comment.is_empty() {
-                    if after_comment.ends_with(';') {
-                        self.run_query(after_comment);
-                        if self.opts.echo {
-                            let _ = self.writeln(after_comment);
-                        }
-                        let conn = self.conn.clone();
-                        let runner = conn.query_runner(after_comment.as_bytes());
-                        for output in runner {
-                            if let Err(e) = self.print_query_result(after_comment, output, None) {
-                                let _ = self.writeln(e.to_string());
-                            }
-                        }
-                        self.reset_input();
-                        return self.handle_input_line(after_comment);
-                    } else {
-                        self.set_multiline_prompt();
-                        let _ = self.reset_line(line);
-                        return Ok(());
-                    }
-                }
-            }
+        // The lines after the first one of a statement are kept verbatim, as they may continue
+        // a string literal
+        let line = if self.input_buff.is_empty() {
+            line.trim_start()
+        } else {
+            line
+        };
+        // Lines with nothing but comments don't start a statement: they are complete after a
+        // semicolon without being complete statements themselves
+        if self.input_buff.is_empty() && is_complete(&format!(";{line}")) && !is_complete(line) {
             return Ok(());
         }
-        if line.ends_with(';') {
+        if is_complete(&format!("{}{line}", self.input_buff)) {
             self.buffer_input(line);
             let buff = self.input_buff.clone();
+            self.add_history_entry(buff.trim_end());
             self.run_query(buff.as_str());
         } else {
             self.buffer_input(format!("{line}\n").as_str());
@@ -615,7 +780,7 @@ impl Limbo {
                     }
                 }
                 Command::Schema(args) => {
-                    if let Err(e) = self.display_schema(args.table_name.as_deref()) {
+                    if let Err(e) = self.display_schema(args.table_name.as_deref(), args.indent) {
                         let _ = self.writeln(e.to_string());
                     }
                 }
@@ -644,6 +809,15 @@ impl Limbo {
                     if let Err(e) = self.set_mode(args.mode) {
                         let _ = self.write_fmt(format_args!("Error: {e}"));
                     }
+                    if let Some(table) = args.table {
+                        self.opts.insert_table = table;
+                    }
+                }
+                Command::Separator(args) => {
+                    self.opts.separator = args.column;
+                    if let Some(row) = args.row {
+                        self.opts.row_separator = row;
+                    }
                 }
                 Command::SetOutput(args) => {
                     if let Some(path) = args.path {
@@ -678,6 +852,11 @@ impl Limbo {
                         let _ = self.write_fmt(format_args!("/****** ERROR: {e} ******/"));
                     }
                 }
+                Command::Recover => {
+                    if let Err(e) = self.recover_database() {
+                        let _ = self.write_fmt(format_args!("/****** ERROR: {e} ******/"));
+                    }
+                }
                 Command::DbConfig(_args) => {
                     let _ = self.writeln("dbconfig currently ignored");
                 }
@@ -698,6 +877,18 @@ impl Limbo {
                         TimerMode::Off => false,
                     };
                 }
+                Command::Stats(args) => {
+                    self.opts.stats = args.mode == StatsMode::On;
+                }
+                Command::Bail(args) => {
+                    self.opts.bail = args.mode == BailMode::On;
+                }
+                Command::Read(args) => {
+                    if let Err(e) = self.read_file(&args.path) {
+                        let _ = self.writeln(format!("Error: {e}"));
+                        self.failed = true;
+                    }
+                }
                 Command::Headers(headers_mode) => {
                     self.opts.headers = match headers_mode.mode {
                         HeadersMode::On => true,
@@ -716,82 +907,6 @@ impl Limbo {
     ) -> anyhow::Result<()> {
         match output {
             Ok(Some(ref mut rows)) => match self.opts.output_mode {
-                OutputMode::List => {
-                    let mut headers_printed = false;
-                    loop {
-                        if self.interrupt_count.load(Ordering::SeqCst) > 0 {
-                            println!("Query interrupted.");
-                            return Ok(());
-                        }
-
-                        let start = Instant::now();
-
-                        match rows.step() {
-                            Ok(StepResult::Row) => {
-                                if let Some(ref mut stats) = statistics {
-                                    stats.execute_time_elapsed_samples.push(start.elapsed());
-                                }
-
-                                // Print headers if enabled and not already printed
-                                if self.opts.headers && !headers_printed {
-                                    for i in 0..rows.num_columns() {
-                                        if i > 0 {
-                                            let _ = self.writer.write(b"|");
-                                        }
-                                        let _ =
-                                            self.writer.write(rows.get_column_name(i).as_bytes());
-                                    }
-                                    let _ = self.writeln("");
-                                    headers_printed = true;
-                                }
-
-                                let row = rows.row().unwrap();
-                                for (i, value) in row.get_values().enumerate() {
-                                    if i > 0 {
-                                        let _ = self.writer.write(b"|");
-                                    }
-                                    if matches!(value, Value::Null) {
-                                        let _ =
-                                            self.writer.write(self.opts.null_value.as_bytes())?;
-                                    } else {
-                                        let _ = self.writer.write(format!("{value}").as_bytes())?;
-                                    }
-                                }
-                                let _ = self.writeln("");
-                            }
-                            Ok(StepResult::IO) => {
-                                let start = Instant::now();
-                                rows.run_once()?;
-                                if let Some(ref mut stats) = statistics {
-                                    stats.io_time_elapsed_samples.push(start.elapsed());
-                                }
-                            }
-                            Ok(StepResult::Interrupt) => break,
-                            Ok(StepResult::Done) => {
-                                if let Some(ref mut stats) = statistics {
-                                    stats.execute_time_elapsed_samples.push(start.elapsed());
-                                }
-                                break;
-                            }
-                            Ok(StepResult::Busy) => {
-                                if let Some(ref mut stats) = statistics {
-                                    stats.execute_time_elapsed_samples.push(start.elapsed());
-                                }
-                                let _ = self.writeln("database is busy");
-                                break;
-                            }
-                            Err(err) => {
-                                if let Some(ref mut stats) = statistics {
-                                    stats.execute_time_elapsed_samples.push(start.elapsed());
-                                }
-                                let report =
-                                    miette::Error::from(err).with_source_code(sql.to_owned());
-                                let _ = self.write_fmt(format_args!("{report:?}"));
-                                break;
-                            }
-                        }
-                    }
-                }
                 OutputMode::Pretty => {
                     if self.interrupt_count.load(Ordering::SeqCst) > 0 {
                         println!("Query interrupted.");
@@ -881,15 +996,78 @@ impl Limbo {
                                 let report =
                                     miette::Error::from(err).with_source_code(sql.to_owned());
                                 let _ = self.write_fmt(format_args!("{report:?}"));
+                                self.failed = true;
                                 break;
                             }
                         }
                     }
 
+                    if let Some(stats) = statistics {
+                        stats.statement = rows.stats();
+                    }
                     if !table.is_empty() {
                         let _ = self.write_fmt(format_args!("{table}"));
                     }
                 }
+                _ => {
+                    let columns = (0..rows.num_columns())
+                        .map(|i| rows.get_column_name(i).into_owned())
+                        .collect();
+                    let mut row_writer = RowWriter::new(&self.opts, columns);
+                    loop {
+                        if self.interrupt_count.load(Ordering::SeqCst) > 0 {
+                            println!("Query interrupted.");
+                            return Ok(());
+                        }
+
+                        let start = Instant::now();
+
+                        match rows.step() {
+                            Ok(StepResult::Row) => {
+                                if let Some(ref mut stats) = statistics {
+                                    stats.execute_time_elapsed_samples.push(start.elapsed());
+                                }
+                                let row = rows.row().unwrap();
+                                row_writer.write_row(&mut self.writer, row.get_values())?;
+                            }
+                            Ok(StepResult::IO) => {
+                                let start = Instant::now();
+                                rows.run_once()?;
+                                if let Some(ref mut stats) = statistics {
+                                    stats.io_time_elapsed_samples.push(start.elapsed());
+                                }
+                            }
+                            Ok(StepResult::Interrupt) => break,
+                            Ok(StepResult::Done) => {
+                                if let Some(ref mut stats) = statistics {
+                                    stats.execute_time_elapsed_samples.push(start.elapsed());
+                                }
+                                break;
+                            }
+                            Ok(StepResult::Busy) => {
+                                if let Some(ref mut stats) = statistics {
+                                    stats.execute_time_elapsed_samples.push(start.elapsed());
+                                }
+                                let _ = self.writeln("database is busy");
+                                break;
+                            }
+                            Err(err) => {
+                                if let Some(ref mut stats) = statistics {
+                                    stats.execute_time_elapsed_samples.push(start.elapsed());
+                                }
+                                let report =
+                                    miette::Error::from(err).with_source_code(sql.to_owned());
+                                let _ = self.write_fmt(format_args!("{report:?}"));
+                                self.failed = true;
+                                break;
+                            }
+                        }
+                    }
+                    row_writer.finish(&mut self.writer)?;
+                    if let Some(stats) = statistics {
+                        stats.statement = rows.stats();
+                    }
+                }
             },
             Ok(None) => {}
             Err(err) => {
@@ -936,15 +1114,16 @@ impl Limbo {
         Ok(guard)
     }
 
-    fn display_schema(&mut self, table: Option<&str>) -> anyhow::Result<()> {
+    fn display_schema(&mut self, table: Option<&str>, indent: bool) -> anyhow::Result<()> {
         let sql = match table {
-        Some(table_name) => format!(
-            "SELECT sql FROM sqlite_schema WHERE type IN ('table', 'index') AND tbl_name = '{table_name}' AND name NOT LIKE 'sqlite_%'"
-        ),
-        None => String::from(
-            "SELECT sql FROM sqlite_schema WHERE type IN ('table', 'index') AND name NOT LIKE 'sqlite_%'"
-        ),
-    };
+            Some(pattern) => format!(
+                "SELECT sql FROM sqlite_schema WHERE type IN ('table', 'index', 'view', 'trigger') AND sql NOT NULL AND tbl_name LIKE '{}' AND name NOT LIKE 'sqlite_%'",
+                pattern.replace('\'', "''")
+            ),
+            None => String::from(
+                "SELECT sql FROM sqlite_schema WHERE type IN ('table', 'index', 'view', 'trigger') AND sql NOT NULL AND name NOT LIKE 'sqlite_%'"
+            ),
+        };
 
         match self.conn.query(&sql) {
             Ok(Some(ref mut rows)) => {
@@ -954,7 +1133,12 @@ impl Limbo {
                         StepResult::Row => {
                             let row = rows.row().unwrap();
                             if let Ok(Value::Text(schema)) = row.get::<&Value>(0) {
-                                let _ = self.write_fmt(format_args!("{};", schema.as_str()));
+                                let schema = if indent {
+                                    indent_sql(schema.as_str())
+                                } else {
+                                    schema.as_str().to_string()
+                                };
+                                let _ = self.write_fmt(format_args!("{schema};"));
                                 found = true;
                             }
                         }
@@ -985,7 +1169,7 @@ impl Limbo {
                 if err.to_string().contains("no such table: sqlite_schema") {
                     return Err(anyhow::anyhow!("Unable to access database schema. The database may be using an older SQLite version or may not be properly initialized."));
                 } else {
-                    return Err(anyhow::anyhow!("Error querying schema: {}", err));
+                    return Err(anyhow::anyhow!("Error querying schema: {err}"));
                 }
             }
         }
@@ -996,7 +1180,8 @@ impl Limbo {
     fn display_indexes(&mut self, maybe_table: Option<String>) -> anyhow::Result<()> {
         let sql = match maybe_table {
             Some(ref tbl_name) => format!(
-                "SELECT name FROM sqlite_schema WHERE type='index' AND tbl_name = '{tbl_name}' ORDER BY 1"
+                "SELECT name FROM sqlite_schema WHERE type='index' AND tbl_name LIKE '{}' ORDER BY 1",
+                tbl_name.replace('\'', "''")
             ),
             None => String::from("SELECT name FROM sqlite_schema WHERE type='index' ORDER BY 1"),
         };
@@ -1032,7 +1217,7 @@ impl Limbo {
                 if err.to_string().contains("no such table: sqlite_schema") {
                     return Err(anyhow::anyhow!("Unable to access database schema. The database may be using an older SQLite version or may not be properly initialized."));
                 } else {
-                    return Err(anyhow::anyhow!("Error querying schema: {}", err));
+                    return Err(anyhow::anyhow!("Error querying schema: {err}"));
                 }
             }
             Ok(None) => {}
@@ -1044,10 +1229,11 @@ impl Limbo {
     fn display_tables(&mut self, pattern: Option<&str>) -> anyhow::Result<()> {
         let sql = match pattern {
             Some(pattern) => format!(
-                "SELECT name FROM sqlite_schema WHERE type='table' AND name NOT LIKE 'sqlite_%' AND name LIKE '{pattern}' ORDER BY 1"
+                "SELECT name FROM sqlite_schema WHERE type IN ('table', 'view') AND name NOT LIKE 'sqlite_%' AND name LIKE '{}' ORDER BY 1",
+                pattern.replace('\'', "''")
             ),
             None => String::from(
-                "SELECT name FROM sqlite_schema WHERE type='table' AND name NOT LIKE 'sqlite_%' ORDER BY 1"
+                "SELECT name FROM sqlite_schema WHERE type IN ('table', 'view') AND name NOT LIKE 'sqlite_%' ORDER BY 1"
             ),
         };
 
@@ -1092,7 +1278,7 @@ impl Limbo {
                 if err.to_string().contains("no such table: sqlite_schema") {
                     return Err(anyhow::anyhow!("Unable to access database schema. The database may be using an older SQLite version or may not be properly initialized."));
                 } else {
-                    return Err(anyhow::anyhow!("Error querying schema: {}", err));
+                    return Err(anyhow::anyhow!("Error querying schema: {err}"));
                 }
             }
         }
@@ -1131,13 +1317,45 @@ impl Limbo {
         }
     }
 
-    fn save_history(&mut self) {
+    /// Adds a dot command or a whole statement to the history, so that a statement typed over
+    /// several lines is recalled as one entry.
+    fn add_history_entry(&mut self, entry: &str) {
+        if let Some(rl) = &mut self.rl {
+            let _ = rl.add_history_entry(entry);
+        }
+    }
+
+    pub fn save_history(&mut self) {
         if let Some(rl) = &mut self.rl {
             let _ = rl.save_history(HISTORY_FILE.as_path());
         }
     }
 }
 
+fn is_explain_query_plan(input: &str) -> bool {
+    let mut words = input.split_whitespace();
+    ["explain", "query", "plan"].iter().all(|keyword| {
+        words
+            .next()
+            .is_some_and(|w| w.eq_ignore_ascii_case(keyword))
+    })
+}
+
+/// Appends the rows of a query plan that are children of `parent` to `output`, each one prefixed
+/// with the branches of the tree.
+fn format_query_plan(rows: &[(i64, i64, String)], parent: i64, prefix: &str, output: &mut String) {
+    let children: Vec<_> = rows.iter().filter(|(_, p, _)| *p == parent).collect();
+    for (i, (id, _, detail)) in children.iter().enumerate() {
+        let is_last = i == children.len() - 1;
+        output.push_str(prefix);
+        output.push_str(if is_last { "`--" } else { "|--" });
+        output.push_str(detail);
+        output.push('\n');
+        let prefix = format!("{prefix}{}", if is_last { "   " } else { "|  " });
+        format_query_plan(rows, *id, &prefix, output);
+    }
+}
+
 impl Drop for Limbo {
     fn drop(&mut self) {
         self.save_history()
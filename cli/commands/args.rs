@@ -5,7 +5,7 @@ use crate::{input::OutputMode, opcodes_dictionary::OPCODE_DESCRIPTIONS};
 
 #[derive(Debug, Clone, Args)]
 pub struct IndexesArgs {
-    /// Name of table
+    /// Name of table, or LIKE pattern of the names of tables
     pub tbl_name: Option<String>,
 }
 
@@ -30,8 +30,11 @@ pub struct OpenArgs {
 #[derive(Debug, Clone, Args)]
 pub struct SchemaArgs {
     // TODO depends on PRAGMA table_list for completions
-    /// Table name to visualize schema
+    /// Table name to visualize schema, or LIKE pattern of the names of tables
     pub table_name: Option<String>,
+    /// Put each column of a table on its own line
+    #[arg(long)]
+    pub indent: bool,
 }
 
 #[derive(Debug, Clone, Args)]
@@ -45,6 +48,28 @@ pub struct SetOutputArgs {
 pub struct OutputModeArgs {
     #[arg(value_enum)]
     pub mode: OutputMode,
+    /// Table the statements of the insert mode write to
+    pub table: Option<String>,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct SeparatorArgs {
+    /// Separator of the columns, e.g. ',' or '\t'
+    #[arg(value_parser = unescape)]
+    pub column: String,
+    /// Separator of the rows
+    #[arg(value_parser = unescape)]
+    pub row: Option<String>,
+}
+
+/// Replaces the escapes `\t`, `\n`, `\r` and `\\` of a separator, which can't be typed as is.
+fn unescape(sep: &str) -> Result<String, String> {
+    Ok(sep
+        .replace("\\\\", "\0")
+        .replace("\\t", "\t")
+        .replace("\\n", "\n")
+        .replace("\\r", "\r")
+        .replace('\0', "\\"))
 }
 
 fn opcodes_completer(current: &std::ffi::OsStr) -> Vec<CompletionCandidate> {
@@ -103,6 +128,7 @@ pub enum EchoMode {
 
 #[derive(Debug, Clone, Args)]
 pub struct TablesArgs {
+    /// LIKE pattern of the names of tables and views
     pub pattern: Option<String>,
 }
 
@@ -125,6 +151,18 @@ pub struct TimerArgs {
     pub mode: TimerMode,
 }
 
+#[derive(Debug, ValueEnum, Clone, PartialEq)]
+pub enum StatsMode {
+    On,
+    Off,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct StatsArgs {
+    #[arg(value_enum)]
+    pub mode: StatsMode,
+}
+
 #[derive(Debug, ValueEnum, Clone)]
 pub enum DbConfigMode {
     On,
@@ -148,3 +186,21 @@ pub enum HeadersMode {
     On,
     Off,
 }
+
+#[derive(Debug, Clone, Args)]
+pub struct BailArgs {
+    pub mode: BailMode,
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+pub enum BailMode {
+    On,
+    Off,
+}
+
+#[derive(Debug, Clone, Args)]
+pub struct ReadArgs {
+    /// Path to the file of SQL statements and commands
+    #[arg(add = ArgValueCompleter::new(PathCompleter::file()))]
+    pub path: String,
+}
@@ -8,6 +8,18 @@ pub struct ImportArgs {
     /// Use , and \n as column and row separators
     #[arg(long, default_value = "true")]
     csv: bool,
+    /// Use \t and \n as column and row separators
+    #[arg(long, conflicts_with = "ascii")]
+    tsv: bool,
+    /// Use \x1F and \x1E as column and row separators
+    #[arg(long)]
+    ascii: bool,
+    /// Use SEP as the column separator, e.g. ';' or '\t'
+    #[arg(long, value_name = "SEP", value_parser = parse_separator)]
+    colsep: Option<u8>,
+    /// Use SEP as the row separator
+    #[arg(long, value_name = "SEP", value_parser = parse_separator)]
+    rowsep: Option<u8>,
     /// "Verbose" - increase auxiliary output
     #[arg(short, default_value = "false")]
     verbose: bool,
@@ -100,8 +112,22 @@ impl<'a> ImportFile<'a> {
             }
         };
 
+        let (colsep, rowsep) = if args.ascii {
+            (0x1F, Some(0x1E))
+        } else if args.tsv {
+            (b'\t', None)
+        } else {
+            (b',', None)
+        };
         let mut rdr = csv::ReaderBuilder::new()
             .has_headers(false)
+            .delimiter(args.colsep.unwrap_or(colsep))
+            .terminator(match args.rowsep.or(rowsep) {
+                Some(rowsep) => csv::Terminator::Any(rowsep),
+                None => csv::Terminator::CRLF,
+            })
+            // Only CSV has quoted fields
+            .quoting(!args.tsv && !args.ascii)
             .from_reader(file);
 
         let mut success_rows = 0u64;
@@ -169,6 +195,17 @@ impl<'a> ImportFile<'a> {
                 let _ = self.writer.write_all(b"Error: Empty input file\n");
                 return;
             }
+        } else if let Some(Ok(first)) = records.peek() {
+            // A first row naming the columns of the table is a header, not data
+            let columns = self.table_columns(&args.table);
+            if columns.len() == first.len()
+                && columns
+                    .iter()
+                    .zip(first.iter())
+                    .all(|(column, field)| column.eq_ignore_ascii_case(&normalize_ident(field)))
+            {
+                records.next();
+            }
         }
 
         /// TODO: should this be in a single transaction (i.e. all or nothing)?
@@ -324,6 +361,32 @@ impl<'a> ImportFile<'a> {
             );
         }
     }
+
+    /// Returns the names of the columns of `table`, or no names if they can't be read.
+    fn table_columns(&self, table: &str) -> Vec<String> {
+        match self.conn.prepare(format!("SELECT * FROM {table}")) {
+            Ok(stmt) => (0..stmt.num_columns())
+                .map(|i| stmt.get_column_name(i).into_owned())
+                .collect(),
+            Err(_) => Vec::new(),
+        }
+    }
+}
+
+/// Parses a separator given as a single character, or as one of the escapes `\t`, `\n`, `\r`
+/// and `\\`.
+fn parse_separator(sep: &str) -> Result<u8, String> {
+    let sep = match sep {
+        "\\t" => "\t",
+        "\\n" => "\n",
+        "\\r" => "\r",
+        "\\\\" => "\\",
+        sep => sep,
+    };
+    match sep.as_bytes() {
+        [byte] => Ok(*byte),
+        _ => Err(format!("separator must be a single character, not {sep:?}")),
+    }
 }
 
 // https://sqlite.org/lang_keywords.html
@@ -2,9 +2,9 @@ pub mod args;
 pub mod import;
 
 use args::{
-    CwdArgs, DbConfigArgs, EchoArgs, ExitArgs, HeadersArgs, IndexesArgs, LoadExtensionArgs,
-    NullValueArgs, OpcodesArgs, OpenArgs, OutputModeArgs, SchemaArgs, SetOutputArgs, TablesArgs,
-    TimerArgs,
+    BailArgs, CwdArgs, DbConfigArgs, EchoArgs, ExitArgs, HeadersArgs, IndexesArgs,
+    LoadExtensionArgs, NullValueArgs, OpcodesArgs, OpenArgs, OutputModeArgs, ReadArgs, SchemaArgs,
+    SeparatorArgs, SetOutputArgs, StatsArgs, TablesArgs, TimerArgs,
 };
 use clap::Parser;
 use import::ImportArgs;
@@ -54,6 +54,9 @@ pub enum Command {
     /// Display information about settings
     #[command(name = "show", display_name = ".show")]
     ShowInfo,
+    /// Change the column and row separators of 'list' mode
+    #[command(name = "separator", display_name = ".separator")]
+    Separator(SeparatorArgs),
     /// Set the value of NULL to be printed in 'list' mode
     #[command(name = "nullvalue", display_name = ".nullvalue")]
     NullValue(NullValueArgs),
@@ -70,6 +73,9 @@ pub enum Command {
     LoadExtension(LoadExtensionArgs),
     /// Dump the current database as a list of SQL statements
     Dump,
+    /// Recover as much data as possible from a corrupted database, as SQL statements
+    #[command(name = "recover", display_name = ".recover")]
+    Recover,
     /// Print or set the current configuration for the database. Currently ignored.
     #[command(name = "dbconfig", display_name = ".dbconfig")]
     DbConfig(DbConfigArgs),
@@ -79,8 +85,18 @@ pub enum Command {
     /// Show names of indexes
     #[command(name = "indexes", display_name = ".indexes")]
     ListIndexes(IndexesArgs),
+    /// Print the real, user and system time of each statement
     #[command(name = "timer", display_name = ".timer")]
     Timer(TimerArgs),
+    /// Print the instructions and pages each statement used
+    #[command(name = "stats", display_name = ".stats")]
+    Stats(StatsArgs),
+    /// Stop after hitting an error
+    #[command(name = "bail", display_name = ".bail")]
+    Bail(BailArgs),
+    /// Read input from FILE
+    #[command(name = "read", display_name = ".read")]
+    Read(ReadArgs),
     /// Toggle column headers on/off in list mode
     #[command(name = "headers", display_name = ".headers")]
     Headers(HeadersArgs),
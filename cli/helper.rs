@@ -203,7 +203,9 @@ impl<C: Parser + Send + Sync + 'static> SqlCompleter<C> {
 
         let query = try_result!(
             self.conn.query(format!(
-                "SELECT candidate FROM completion('{prefix}', '{line}') ORDER BY 1;"
+                "SELECT DISTINCT candidate FROM completion('{}', '{}') ORDER BY 1;",
+                prefix.replace('\'', "''"),
+                line.replace('\'', "''")
             )),
             (prefix_pos, candidates)
         );
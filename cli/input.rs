@@ -60,8 +60,28 @@ impl Default for Io {
 
 #[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
 pub enum OutputMode {
+    /// Values delimited by the separator
     List,
+    /// Tables drawn with colors
     Pretty,
+    /// Comma-separated values
+    Csv,
+    /// Tab-separated values
+    Tabs,
+    /// An array of JSON objects
+    Json,
+    /// HTML table rows
+    Html,
+    /// A Markdown table
+    Markdown,
+    /// A table drawn with box characters
+    Box,
+    /// A table drawn with ASCII characters
+    Table,
+    /// One value per line
+    Line,
+    /// SQL INSERT statements
+    Insert,
 }
 
 impl std::fmt::Display for OutputMode {
@@ -78,12 +98,21 @@ pub struct Settings {
     pub db_file: String,
     pub null_value: String,
     pub output_mode: OutputMode,
+    /// The table the INSERT statements of the insert mode write to.
+    pub insert_table: String,
+    /// The separators of the columns and rows in list mode.
+    pub separator: String,
+    pub row_separator: String,
     pub echo: bool,
     pub is_stdout: bool,
     pub io: Io,
     pub tracing_output: Option<String>,
     pub timer: bool,
+    /// Whether the counters of each statement are printed after it.
+    pub stats: bool,
     pub headers: bool,
+    /// Whether scripts stop at the first statement that fails.
+    pub bail: bool,
     pub mcp: bool,
 }
 
@@ -92,6 +121,9 @@ impl From<Opts> for Settings {
         Self {
             null_value: String::new(),
             output_mode: opts.output_mode,
+            insert_table: "table".to_string(),
+            separator: "|".to_string(),
+            row_separator: "\n".to_string(),
             echo: false,
             is_stdout: opts.output.is_empty(),
             output_filename: opts.output,
@@ -109,7 +141,9 @@ impl From<Opts> for Settings {
             },
             tracing_output: opts.tracing_output,
             timer: false,
+            stats: false,
             headers: false,
+            bail: opts.bail,
             mcp: opts.mcp,
         }
     }
@@ -119,7 +153,7 @@ impl std::fmt::Display for Settings {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(
             f,
-            "Settings:\nOutput mode: {}\nDB: {}\nOutput: {}\nNull value: {}\nCWD: {}\nEcho: {}\nHeaders: {}",
+            "Settings:\nOutput mode: {}\nDB: {}\nOutput: {}\nNull value: {}\nCWD: {}\nEcho: {}\nHeaders: {}\nSeparator: {:?} {:?}",
             self.output_mode,
             self.db_file,
             match self.is_stdout {
@@ -135,7 +169,9 @@ impl std::fmt::Display for Settings {
             match self.headers {
                 true => "on",
                 false => "off",
-            }
+            },
+            self.separator,
+            self.row_separator,
         )
     }
 }
@@ -180,6 +216,97 @@ pub fn get_io(db_location: DbLocation, io_choice: &str) -> anyhow::Result<Arc<dy
     })
 }
 
+/// Whether the text ends with a complete SQL statement, like `sqlite3_complete`: it ends with a
+/// semicolon that isn't inside a string, an identifier or a comment, nor inside the body of a
+/// CREATE TRIGGER statement, which is only complete after its `END;`.
+pub fn is_complete(sql: &str) -> bool {
+    #[derive(Clone, Copy)]
+    enum Token {
+        Semi,
+        Space,
+        Other,
+        Explain,
+        Create,
+        Temp,
+        Trigger,
+        End,
+    }
+    // The states of the statement, indexed by the state and the token that follows:
+    // 0 start of input, 1 after a semicolon, 2 in a statement, 3 after EXPLAIN, 4 after CREATE,
+    // 5 in a trigger body, 6 after a semicolon in a trigger body, 7 after its END.
+    const TRANSITIONS: [[u8; 8]; 8] = [
+        [1, 0, 2, 3, 4, 2, 2, 2],
+        [1, 1, 2, 3, 4, 2, 2, 2],
+        [1, 2, 2, 2, 2, 2, 2, 2],
+        [1, 3, 3, 2, 4, 2, 2, 2],
+        [1, 4, 2, 2, 2, 4, 5, 2],
+        [6, 5, 5, 5, 5, 5, 5, 5],
+        [6, 6, 5, 5, 5, 5, 5, 7],
+        [1, 7, 5, 5, 5, 5, 5, 5],
+    ];
+    let bytes = sql.as_bytes();
+    let mut state = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let token = match bytes[i] {
+            b';' => Token::Semi,
+            b' ' | b'\t' | b'\n' | b'\r' | b'\x0c' => Token::Space,
+            b'/' if bytes.get(i + 1) == Some(&b'*') => {
+                match sql[i + 2..].find("*/") {
+                    Some(end) => i += end + 3,
+                    None => return false,
+                }
+                Token::Space
+            }
+            b'-' if bytes.get(i + 1) == Some(&b'-') => match sql[i..].find('\n') {
+                Some(end) => {
+                    i += end;
+                    Token::Space
+                }
+                None => return state == 1,
+            },
+            quote @ (b'\'' | b'"' | b'`' | b'[') => {
+                let close = if quote == b'[' { b']' } else { quote };
+                match bytes[i + 1..].iter().position(|&b| b == close) {
+                    // A doubled quote is read as two strings in a row, which is just as complete
+                    Some(end) => i += end + 1,
+                    None => return false,
+                }
+                Token::Other
+            }
+            b if b.is_ascii_alphabetic() || b == b'_' => {
+                let start = i;
+                while bytes
+                    .get(i + 1)
+                    .is_some_and(|b| b.is_ascii_alphanumeric() || *b == b'_' || *b == b'$')
+                {
+                    i += 1;
+                }
+                let word = &sql[start..=i];
+                if word.eq_ignore_ascii_case("create") {
+                    Token::Create
+                } else if word.eq_ignore_ascii_case("trigger") {
+                    Token::Trigger
+                } else if word.eq_ignore_ascii_case("temp")
+                    || word.eq_ignore_ascii_case("temporary")
+                {
+                    Token::Temp
+                } else if word.eq_ignore_ascii_case("end") {
+                    Token::End
+                } else if word.eq_ignore_ascii_case("explain") {
+                    Token::Explain
+                } else {
+                    Token::Other
+                }
+            }
+            _ => Token::Other,
+        };
+        state = TRANSITIONS[state][token as usize] as usize;
+        i += 1;
+    }
+    state == 1
+}
+
 pub const BEFORE_HELP_MSG: &str = r#"
 
 Limbo SQL Shell Help
@@ -238,3 +365,28 @@ pub const AFTER_HELP_MSG: &str = r#"Usage Examples:
 Note:
 - All SQL commands must end with a semicolon (;).
 - Special commands start with a dot (.) and are not required to end with a semicolon."#;
+
+#[cfg(test)]
+mod tests {
+    use super::is_complete;
+
+    #[test]
+    fn test_is_complete() {
+        assert!(is_complete("SELECT 1;"));
+        assert!(is_complete("SELECT 1; -- done"));
+        assert!(is_complete("SELECT 1;\nSELECT 2;"));
+        assert!(!is_complete(""));
+        assert!(!is_complete("SELECT 1"));
+        assert!(!is_complete("SELECT ';'"));
+        assert!(!is_complete("SELECT 1 /* ; */"));
+        assert!(!is_complete("SELECT \"a;"));
+        assert!(is_complete("SELECT 'it''s';"));
+        assert!(!is_complete(
+            "CREATE TRIGGER tr AFTER INSERT ON t BEGIN DELETE FROM t;"
+        ));
+        assert!(is_complete(
+            "CREATE TEMP TRIGGER tr AFTER INSERT ON t BEGIN DELETE FROM t; END;"
+        ));
+        assert!(is_complete("CREATE TABLE trigger (end);"));
+    }
+}
@@ -6,6 +6,7 @@ mod helper;
 mod input;
 mod mcp_server;
 mod opcodes_dictionary;
+mod output;
 
 use config::CONFIG_DIR;
 use mcp_server::TursoMcpServer;
@@ -18,7 +19,8 @@ use std::{
 fn rustyline_config() -> Config {
     Config::builder()
         .completion_type(rustyline::CompletionType::List)
-        .auto_add_history(true)
+        // The shell adds whole statements rather than each of their lines
+        .auto_add_history(false)
         .build()
 }
 
@@ -61,16 +63,21 @@ fn main() -> anyhow::Result<()> {
     loop {
         let readline = app.readline();
         match readline {
-            Ok(line) => match app.handle_input_line(line.trim()) {
-                Ok(_) => {}
-                Err(e) => {
+            Ok(line) => {
+                if let Err(e) = app.handle_input_line(&line) {
                     eprintln!("{e}");
                 }
-            },
+                // Only scripts piped into the shell stop at the first error
+                if app.should_bail() && !app.is_interactive() {
+                    let _ = app.close_conn();
+                    std::process::exit(1);
+                }
+            }
             Err(ReadlineError::Interrupted) => {
                 // At prompt, increment interrupt count
                 if app.interrupt_count.fetch_add(1, Ordering::SeqCst) >= 1 {
                     eprintln!("Interrupted. Exiting...");
+                    app.save_history();
                     let _ = app.close_conn();
                     break;
                 }
@@ -80,6 +87,7 @@ fn main() -> anyhow::Result<()> {
             }
             Err(ReadlineError::Eof) => {
                 app.handle_remaining_input();
+                app.save_history();
                 let _ = app.close_conn();
                 break;
             }
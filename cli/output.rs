@@ -0,0 +1,350 @@
+//! The output modes of the shell other than the pretty one, which write the rows of a query the
+//! way the sqlite3 shell does.
+
+use crate::input::{OutputMode, Settings};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+use turso_core::Value;
+
+/// Writes the rows of a query in one of the output modes. The modes that align their columns
+/// hold the rows until the query is done.
+pub struct RowWriter {
+    mode: OutputMode,
+    headers: bool,
+    null_value: String,
+    separator: String,
+    row_separator: String,
+    insert_table: String,
+    columns: Vec<String>,
+    rows_written: usize,
+    aligned_rows: Vec<Vec<String>>,
+}
+
+impl RowWriter {
+    pub fn new(settings: &Settings, columns: Vec<String>) -> Self {
+        Self {
+            mode: settings.output_mode,
+            headers: settings.headers,
+            null_value: settings.null_value.clone(),
+            separator: settings.separator.clone(),
+            row_separator: settings.row_separator.clone(),
+            insert_table: settings.insert_table.clone(),
+            columns,
+            rows_written: 0,
+            aligned_rows: Vec::new(),
+        }
+    }
+
+    pub fn write_row<'a>(
+        &mut self,
+        out: &mut dyn Write,
+        values: impl Iterator<Item = &'a Value>,
+    ) -> io::Result<()> {
+        let first = self.rows_written == 0;
+        self.rows_written += 1;
+        match self.mode {
+            OutputMode::List | OutputMode::Csv | OutputMode::Tabs | OutputMode::Pretty => {
+                let (separator, row_separator) = match self.mode {
+                    OutputMode::Csv => (",", "\n"),
+                    OutputMode::Tabs => ("\t", "\n"),
+                    _ => (self.separator.as_str(), self.row_separator.as_str()),
+                };
+                if first && self.headers {
+                    let names = self.columns.iter().map(|name| self.delimited(name));
+                    write!(
+                        out,
+                        "{}{row_separator}",
+                        names.collect::<Vec<_>>().join(separator)
+                    )?;
+                }
+                let fields = values.map(|value| match value {
+                    Value::Null => self.null_value.clone(),
+                    value => self.delimited(&value.to_string()),
+                });
+                write!(
+                    out,
+                    "{}{row_separator}",
+                    fields.collect::<Vec<_>>().join(separator)
+                )?;
+            }
+            OutputMode::Json => {
+                out.write_all(if first { "[{" } else { ",\n{" }.as_bytes())?;
+                let fields =
+                    self.columns.iter().zip(values).map(|(name, value)| {
+                        format!("{}:{}", json_string(name), json_value(value))
+                    });
+                write!(out, "{}}}", fields.collect::<Vec<_>>().join(","))?;
+            }
+            OutputMode::Html => {
+                if first && self.headers {
+                    out.write_all(b"<TR>")?;
+                    for name in &self.columns {
+                        writeln!(out, "<TH>{}</TH>", html_escape(name))?;
+                    }
+                    out.write_all(b"</TR>\n")?;
+                }
+                out.write_all(b"<TR>")?;
+                for value in values {
+                    writeln!(out, "<TD>{}</TD>", html_escape(&self.text(value)))?;
+                }
+                out.write_all(b"</TR>\n")?;
+            }
+            OutputMode::Line => {
+                let width = self
+                    .columns
+                    .iter()
+                    .map(|name| name.chars().count())
+                    .fold(5, usize::max);
+                if !first {
+                    out.write_all(b"\n")?;
+                }
+                for (name, value) in self.columns.iter().zip(values) {
+                    writeln!(out, "{name:>width$} = {}", self.text(value))?;
+                }
+            }
+            OutputMode::Insert => {
+                let columns = if self.headers {
+                    let names = self.columns.iter().map(|name| quote_ident(name));
+                    format!("({})", names.collect::<Vec<_>>().join(","))
+                } else {
+                    String::new()
+                };
+                let values = values.map(sql_literal).collect::<Vec<_>>();
+                writeln!(
+                    out,
+                    "INSERT INTO {}{columns} VALUES({});",
+                    quote_ident(&self.insert_table),
+                    values.join(",")
+                )?;
+            }
+            OutputMode::Markdown | OutputMode::Box | OutputMode::Table => {
+                let row = values.map(|value| self.text(value)).collect();
+                self.aligned_rows.push(row);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes what follows the last row, if any row was written.
+    pub fn finish(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        if self.rows_written == 0 {
+            return Ok(());
+        }
+        match self.mode {
+            OutputMode::Json => out.write_all(b"]\n"),
+            OutputMode::Markdown | OutputMode::Box | OutputMode::Table => {
+                out.write_all(self.aligned_table().as_bytes())
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn text(&self, value: &Value) -> String {
+        match value {
+            Value::Null => self.null_value.clone(),
+            value => value.to_string(),
+        }
+    }
+
+    /// Quotes a CSV field that contains a separator, a quote or a line break.
+    fn delimited(&self, field: &str) -> String {
+        let needs_quotes = self.mode == OutputMode::Csv
+            && (field.contains([',', '"', '\n', '\r'])
+                || field.starts_with(' ')
+                || field.ends_with(' '));
+        if needs_quotes {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+
+    /// Draws the rows held by the aligned modes, under the names of the columns.
+    fn aligned_table(&self) -> String {
+        let mut widths = self
+            .columns
+            .iter()
+            .map(|name| name.chars().count())
+            .collect::<Vec<_>>();
+        for row in &self.aligned_rows {
+            for (width, field) in widths.iter_mut().zip(row) {
+                *width = (*width).max(field.chars().count());
+            }
+        }
+        let line = |left: &str, fill: &str, middle: &str, right: &str| {
+            let cells = widths.iter().map(|width| fill.repeat(width + 2));
+            format!("{left}{}{right}\n", cells.collect::<Vec<_>>().join(middle))
+        };
+        let row = |fields: Vec<String>, border: &str| {
+            format!(
+                "{border} {} {border}\n",
+                fields.join(&format!(" {border} "))
+            )
+        };
+        let (border, top, separator, bottom) = match self.mode {
+            OutputMode::Box => (
+                "│",
+                Some(line("┌", "─", "┬", "┐")),
+                line("├", "─", "┼", "┤"),
+                Some(line("└", "─", "┴", "┘")),
+            ),
+            OutputMode::Table => {
+                let line = line("+", "-", "+", "+");
+                ("|", Some(line.clone()), line.clone(), Some(line))
+            }
+            _ => ("|", None, line("|", "-", "|", "|"), None),
+        };
+        let header = self.columns.iter().zip(&widths).map(|(name, width)| {
+            let padding = width - name.chars().count();
+            let left = padding / 2;
+            format!("{}{name}{}", " ".repeat(left), " ".repeat(padding - left))
+        });
+        let mut table = top.unwrap_or_default();
+        table.push_str(&row(header.collect(), border));
+        table.push_str(&separator);
+        for fields in &self.aligned_rows {
+            let fields = fields.iter().zip(&widths).map(|(field, width)| {
+                let padding = width - field.chars().count();
+                format!("{field}{}", " ".repeat(padding))
+            });
+            table.push_str(&row(fields.collect(), border));
+        }
+        table.push_str(&bottom.unwrap_or_default());
+        table
+    }
+}
+
+/// Quotes an identifier in a SQL statement, unless it can be written as is.
+pub fn quote_ident(name: &str) -> String {
+    let is_plain = name
+        .chars()
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if is_plain {
+        name.to_string()
+    } else {
+        format!("\"{}\"", name.replace('"', "\"\""))
+    }
+}
+
+/// Writes a value as the SQL literal that evaluates to it, so that the value is read back with
+/// its type.
+pub fn sql_literal(value: &Value) -> String {
+    match value {
+        Value::Null => "NULL".to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Float(f) if f.is_nan() => "NULL".to_string(),
+        Value::Float(f) if f.is_infinite() => if *f > 0.0 { "1e999" } else { "-1e999" }.to_string(),
+        // The shortest representation that reads back as the same float
+        Value::Float(f) => format!("{f:?}"),
+        Value::Text(text) => format!("'{}'", text.as_str().replace('\'', "''")),
+        Value::Blob(blob) => {
+            let hex = blob.iter().fold(String::new(), |mut output, b| {
+                let _ = write!(output, "{b:02x}");
+                output
+            });
+            format!("X'{hex}'")
+        }
+    }
+}
+
+/// Pretty-prints a statement of the schema: runs of whitespace become single spaces, and each
+/// column and constraint of a CREATE TABLE statement goes on its own line.
+pub fn indent_sql(sql: &str) -> String {
+    let is_table = sql
+        .trim_start()
+        .get(..12)
+        .is_some_and(|start| start.eq_ignore_ascii_case("CREATE TABLE"));
+    let mut indented = String::with_capacity(sql.len());
+    let mut quote = None;
+    let mut depth = 0;
+    let mut chars = sql.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        if let Some(end) = quote {
+            indented.push(c);
+            if c == end {
+                quote = None;
+            }
+            continue;
+        }
+        match c {
+            '\'' | '"' | '`' => quote = Some(c),
+            '[' => quote = Some(']'),
+            _ => {}
+        }
+        match c {
+            c if c.is_whitespace() => {
+                while chars.next_if(|c| c.is_whitespace()).is_some() {}
+                let after_break = indented.ends_with('\n') || indented.ends_with("\n  ");
+                if !after_break && chars.peek().is_some_and(|&next| next != ')' || depth != 1) {
+                    indented.push(' ');
+                }
+            }
+            '(' => {
+                depth += 1;
+                indented.push('(');
+                if is_table && depth == 1 {
+                    indented.push_str("\n  ");
+                }
+            }
+            ')' => {
+                if is_table && depth == 1 {
+                    indented.truncate(indented.trim_end().len());
+                    indented.push('\n');
+                }
+                depth -= 1;
+                indented.push(')');
+            }
+            ',' if is_table && depth == 1 => indented.push_str(",\n  "),
+            c => indented.push(c),
+        }
+    }
+    indented
+}
+
+fn json_value(value: &Value) -> String {
+    match value {
+        Value::Null => "null".to_string(),
+        Value::Integer(i) => i.to_string(),
+        Value::Float(f) if !f.is_finite() => "null".to_string(),
+        Value::Float(f) => format!("{f:?}"),
+        Value::Text(text) => json_string(text.as_str()),
+        Value::Blob(blob) => json_string(&String::from_utf8_lossy(blob)),
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut json = String::with_capacity(text.len() + 2);
+    json.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(json, "\\u{:04x}", c as u32);
+            }
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn html_escape(text: &str) -> String {
+    let mut html = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '<' => html.push_str("&lt;"),
+            '>' => html.push_str("&gt;"),
+            '&' => html.push_str("&amp;"),
+            '"' => html.push_str("&quot;"),
+            '\'' => html.push_str("&#39;"),
+            c => html.push(c),
+        }
+    }
+    html
+}
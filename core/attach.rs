@@ -0,0 +1,315 @@
+//! Databases attached to a connection with `ATTACH DATABASE`.
+//!
+//! Each attached database is opened with a connection of its own, whose pager the programs of
+//! the main connection use for the cursors on its tables. Programs refer to a database by its
+//! index, like SQLite's `iDb`: 0 is the main database, 1 is reserved for the temp database, and
+//! the attached databases get the following ones. Indexes aren't reused, so a statement prepared
+//! before a `DETACH` can't end up using a database attached after it.
+//!
+//! The temp database, which holds the `TEMP` tables of the connection, is handled as an attached
+//! database named `temp` with index 1. It's a private in-memory database, created by the first
+//! statement that needs it and discarded when the connection is closed.
+//!
+//! The attached databases take part in the transactions of the main connection: a statement
+//! starts a transaction on each database it uses, and they are all committed or rolled back
+//! with the main one. Like with SQLite in WAL mode, the commit is atomic for each database, but
+//! not across them.
+
+use crate::schema::{AttachedSchema, Schema};
+use crate::storage::pager::Pager;
+use crate::util::{normalize_ident, IOExt, OpenOptions, MEMORY_PATH};
+use crate::{Connection, Database, LimboError, Limit, Result, TransactionState};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// The index of the temp database.
+const TEMP_DATABASE: usize = 1;
+
+/// A database attached to a connection.
+pub(crate) struct AttachedDatabase {
+    pub(crate) name: String,
+    pub(crate) path: String,
+    /// The index of the database in the programs of the connection.
+    pub(crate) index: usize,
+    pub(crate) conn: Arc<Connection>,
+}
+
+/// The databases attached to a connection, in the order they were attached.
+pub(crate) struct AttachedDatabases {
+    pub(crate) databases: Vec<AttachedDatabase>,
+    /// The index the next attached database gets.
+    next_index: usize,
+}
+
+impl Default for AttachedDatabases {
+    fn default() -> Self {
+        Self {
+            databases: Vec::new(),
+            // After the main and temp databases.
+            next_index: 2,
+        }
+    }
+}
+
+impl Connection {
+    /// Attaches the database file at `path` under the schema name `name`. An empty path or
+    /// `:memory:` attaches a new in-memory database. Like with [Connection::from_uri], `file:`
+    /// URIs can name a shared in-memory database, e.g. `file:name?mode=memory&cache=shared`.
+    pub(crate) fn attach_database(&self, path: &str, name: &str) -> Result<()> {
+        if !self.auto_commit.get() {
+            return Err(LimboError::TxError(
+                "cannot ATTACH database within transaction".to_string(),
+            ));
+        }
+        if self._db.mv_store.is_some() {
+            return Err(LimboError::TxError(
+                "ATTACH is not supported with MVCC".to_string(),
+            ));
+        }
+        let name = normalize_ident(name);
+        let mut attached = self.attached.borrow_mut();
+        if name == "main" || name == "temp" || attached.databases.iter().any(|db| db.name == name) {
+            return Err(LimboError::InvalidArgument(format!(
+                "database {name} is already in use"
+            )));
+        }
+        let attached_count = attached
+            .databases
+            .iter()
+            .filter(|db| db.index != TEMP_DATABASE)
+            .count();
+        let max_attached = self.limits.get().get(Limit::Attached);
+        if attached_count >= max_attached {
+            return Err(LimboError::InvalidArgument(format!(
+                "too many attached databases - max {max_attached}"
+            )));
+        }
+        let indexes_enabled = self.schema.borrow().indexes_enabled();
+        let opts = OpenOptions::parse(path)?;
+        let db = if path.is_empty() {
+            Database::open_memory(false, indexes_enabled)?
+        } else if let Some(db) = opts.open_memory(false, indexes_enabled)? {
+            db
+        } else {
+            self.open_attached_file(&opts.path, indexes_enabled)?
+        };
+        let conn = db.connect()?;
+        conn.set_readonly(self.readonly.get());
+        // Text is compared in the encoding of the main database, so like in SQLite, a new database
+        // is created with it and one that exists must already have it.
+        let encoding = self.pager.borrow().text_encoding()?;
+        let attached_pager = conn.pager.borrow().clone();
+        attached_pager.set_text_encoding(encoding);
+        if attached_pager.text_encoding()? != encoding {
+            return Err(LimboError::InvalidArgument(
+                "attached databases must use the same text encoding as main database".to_string(),
+            ));
+        }
+        let index = attached.next_index;
+        attached.next_index += 1;
+        attached.databases.push(AttachedDatabase {
+            name,
+            path: path.to_string(),
+            index,
+            conn,
+        });
+        Ok(())
+    }
+
+    /// Creates the temp database of the connection, if it doesn't exist yet. Returns whether it
+    /// was created.
+    pub(crate) fn ensure_temp_database(&self) -> Result<bool> {
+        let mut attached = self.attached.borrow_mut();
+        if attached
+            .databases
+            .iter()
+            .any(|db| db.index == TEMP_DATABASE)
+        {
+            return Ok(false);
+        }
+        if self._db.mv_store.is_some() {
+            return Err(LimboError::TxError(
+                "TEMP tables are not supported with MVCC".to_string(),
+            ));
+        }
+        let indexes_enabled = self.schema.borrow().indexes_enabled();
+        let conn = Database::open_memory(false, indexes_enabled)?.connect()?;
+        conn.pager
+            .borrow()
+            .set_text_encoding(self.pager.borrow().text_encoding()?);
+        attached.databases.push(AttachedDatabase {
+            name: "temp".to_string(),
+            path: MEMORY_PATH.to_string(),
+            index: TEMP_DATABASE,
+            conn,
+        });
+        Ok(true)
+    }
+
+    #[cfg(feature = "fs")]
+    fn open_attached_file(&self, path: &str, indexes_enabled: bool) -> Result<Arc<Database>> {
+        Database::open_file_with_flags(
+            self._db.io.clone(),
+            path,
+            self._db.open_flags,
+            false,
+            indexes_enabled,
+        )
+    }
+
+    #[cfg(not(feature = "fs"))]
+    fn open_attached_file(&self, _path: &str, _indexes_enabled: bool) -> Result<Arc<Database>> {
+        Err(LimboError::InternalError(
+            "attaching a database file requires the fs feature".to_string(),
+        ))
+    }
+
+    /// Detaches the database attached under the schema name `name`.
+    pub(crate) fn detach_database(&self, name: &str) -> Result<()> {
+        if !self.auto_commit.get() {
+            return Err(LimboError::TxError(
+                "cannot DETACH database within transaction".to_string(),
+            ));
+        }
+        let name = normalize_ident(name);
+        if name == "main" || name == "temp" {
+            return Err(LimboError::InvalidArgument(format!(
+                "cannot detach database {name}"
+            )));
+        }
+        let mut attached = self.attached.borrow_mut();
+        let Some(position) = attached.databases.iter().position(|db| db.name == name) else {
+            return Err(LimboError::InvalidArgument(format!(
+                "no such database: {name}"
+            )));
+        };
+        let db = attached.databases.remove(position);
+        db.conn.close()
+    }
+
+    /// Returns the rows of `PRAGMA database_list`: the index, name and file of the main database,
+    /// of the temp one if it exists, and of each attached one. In-memory databases have no file.
+    pub(crate) fn database_list(&self) -> Vec<(usize, String, String)> {
+        let file = |path: &str| {
+            if path == MEMORY_PATH {
+                String::new()
+            } else {
+                path.to_string()
+            }
+        };
+        let mut databases = vec![(0, "main".to_string(), file(&self._db.path))];
+        databases.extend(
+            self.attached
+                .borrow()
+                .databases
+                .iter()
+                .map(|db| (db.index, db.name.clone(), file(&db.path))),
+        );
+        databases.sort_by_key(|(index, ..)| *index);
+        databases
+    }
+
+    /// Returns the name of the database whose pager is `pager`: `main` or the name of an attached
+    /// database.
+    pub(crate) fn database_name(&self, pager: &Rc<Pager>) -> String {
+        if Rc::ptr_eq(pager, &self.pager.borrow()) {
+            return "main".to_string();
+        }
+        self.attached
+            .borrow()
+            .databases
+            .iter()
+            .find(|db| Rc::ptr_eq(pager, &db.conn.pager.borrow()))
+            .map_or_else(|| "main".to_string(), |db| db.name.clone())
+    }
+
+    /// Returns the connection of the database with the schema name `name`: the connection itself
+    /// for the main database, or that of the temp or an attached database.
+    pub(crate) fn database_connection(self: &Arc<Self>, name: &str) -> Result<Arc<Connection>> {
+        let name = normalize_ident(name);
+        if name == "main" {
+            return Ok(self.clone());
+        }
+        self.attached
+            .borrow()
+            .databases
+            .iter()
+            .find(|db| db.name == name)
+            .map(|db| db.conn.clone())
+            .ok_or_else(|| LimboError::InvalidArgument(format!("no such database: {name}")))
+    }
+
+    /// Returns the connection of the attached database with index `db`.
+    pub(crate) fn attached_connection(&self, db: usize) -> Result<Arc<Connection>> {
+        self.attached
+            .borrow()
+            .databases
+            .iter()
+            .find(|attached| attached.index == db)
+            .map(|attached| attached.conn.clone())
+            .ok_or_else(|| LimboError::InternalError(format!("no database with index {db}")))
+    }
+
+    /// Returns the schema statements are translated against: that of the main database, along
+    /// with those of the temp and attached databases.
+    pub(crate) fn translation_schema(&self) -> Result<Arc<Schema>> {
+        let schema = self.schema.borrow().clone();
+        let attached = self.attached.borrow();
+        if attached.databases.is_empty() {
+            return Ok(schema);
+        }
+        let mut schema = schema.as_ref().clone();
+        for db in &attached.databases {
+            db.conn.maybe_update_schema()?;
+            schema.attached.push(AttachedSchema {
+                name: db.name.clone(),
+                index: db.index,
+                schema: db.conn.schema.borrow().clone(),
+            });
+        }
+        Ok(Arc::new(schema))
+    }
+
+    /// Commits, or rolls back, the transactions the statements of the connection started on the
+    /// attached databases.
+    pub(crate) fn end_attached_transactions(&self, rollback: bool) -> Result<()> {
+        let connections: Vec<_> = self
+            .attached
+            .borrow()
+            .databases
+            .iter()
+            .map(|db| db.conn.clone())
+            .collect();
+        for conn in connections {
+            let pager = conn.pager.borrow().clone();
+            match conn.transaction_state.get() {
+                TransactionState::Write { schema_did_change } => {
+                    if rollback {
+                        pager.rollback(schema_did_change, &conn)?;
+                    }
+                    pager.io.block(|| {
+                        pager.end_tx(
+                            rollback,
+                            schema_did_change,
+                            &conn,
+                            conn.wal_checkpoint_disabled.get(),
+                        )
+                    })?;
+                }
+                TransactionState::Read => pager.end_read_tx()?,
+                TransactionState::None => {}
+            }
+            conn.transaction_state.set(TransactionState::None);
+        }
+        Ok(())
+    }
+
+    /// Closes the connections of the attached databases.
+    pub(crate) fn close_attached(&self) -> Result<()> {
+        let databases = std::mem::take(&mut self.attached.borrow_mut().databases);
+        for db in databases {
+            db.conn.close()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+//! The authorizer of a connection, see [crate::Connection::set_authorizer].
+
+/// What the authorizer of a connection decides for an action, like the result codes of the
+/// callback of `sqlite3_set_authorizer`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Authorization {
+    /// The action is allowed.
+    Allow,
+    /// The statement fails to prepare with [crate::LimboError::AuthorizationDenied].
+    Deny,
+    /// The action is skipped: a column that is read is read as NULL, a column that is updated
+    /// is left unchanged, and any other statement does nothing. Like in SQLite, a DELETE that
+    /// is ignored still deletes its rows.
+    Ignore,
+}
+
+/// An action a statement performs, which the authorizer is asked about while the statement is
+/// prepared, like the action codes of `sqlite3_set_authorizer`. The names are those used in the
+/// statement, normalized like the names of the schema.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthAction<'a> {
+    CreateTable {
+        table: &'a str,
+    },
+    CreateIndex {
+        index: &'a str,
+        table: &'a str,
+    },
+    CreateView {
+        view: &'a str,
+    },
+    CreateTrigger {
+        trigger: &'a str,
+        table: &'a str,
+    },
+    CreateVTable {
+        table: &'a str,
+        module: &'a str,
+    },
+    DropTable {
+        table: &'a str,
+    },
+    DropIndex {
+        index: &'a str,
+        table: &'a str,
+    },
+    DropView {
+        view: &'a str,
+    },
+    DropTrigger {
+        trigger: &'a str,
+        table: &'a str,
+    },
+    DropVTable {
+        table: &'a str,
+    },
+    AlterTable {
+        table: &'a str,
+    },
+    /// `ANALYZE`, of the table or index with the given name if there is one.
+    Analyze {
+        target: Option<&'a str>,
+    },
+    Attach {
+        file: &'a str,
+    },
+    Detach {
+        database: &'a str,
+    },
+    Pragma {
+        name: &'a str,
+        arg: Option<&'a str>,
+    },
+    /// `BEGIN`, `COMMIT` or `ROLLBACK`.
+    Transaction {
+        operation: &'a str,
+    },
+    /// `BEGIN` for `SAVEPOINT`, `RELEASE` or `ROLLBACK` for `ROLLBACK TO`.
+    Savepoint {
+        operation: &'a str,
+        name: &'a str,
+    },
+    Select,
+    Read {
+        table: &'a str,
+        column: &'a str,
+    },
+    Insert {
+        table: &'a str,
+    },
+    Update {
+        table: &'a str,
+        column: &'a str,
+    },
+    Delete {
+        table: &'a str,
+    },
+}
+
+/// Decides whether a statement may perform an action, like `sqlite3_set_authorizer`. Along with
+/// the action, it is given the name of the database the action applies to, if any, and the name
+/// of the trigger whose body performs it, if it is performed by a trigger.
+pub type Authorizer = Box<dyn Fn(&AuthAction<'_>, Option<&str>, Option<&str>) -> Authorization>;
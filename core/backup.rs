@@ -0,0 +1,244 @@
+//! Online backup of a database into another one, like SQLite's `sqlite3_backup_*` API.
+//!
+//! The pages of the source database are copied a few at a time into the destination, which is
+//! kept in a write transaction until the last page is copied, so that the backup replaces it
+//! atomically. Each step reads the source in its own read transaction, so other connections can
+//! keep reading and writing it in between. If the source changes between two steps, the backup
+//! starts over from the first page.
+
+use crate::result::LimboResult;
+use crate::schema::Schema;
+use crate::storage::header_accessor;
+use crate::storage::pager::Pager;
+use crate::util::IOExt;
+use crate::{Connection, LimboError, OpenFlags, Result, TransactionState, DB_STATE_UNINITIALIZED};
+use std::rc::Rc;
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+
+/// A backup of the database of one connection into the database of another.
+///
+/// ```ignore
+/// let mut backup = Backup::new(source, dest)?;
+/// while !backup.step(100)? {}
+/// backup.finish()?;
+/// ```
+///
+/// The destination connection should not be used until the backup is finished.
+pub struct Backup {
+    source: Arc<Connection>,
+    dest: Arc<Connection>,
+    /// The next page of the source to copy.
+    next_page: usize,
+    /// The number of pages of the source, as of the last step.
+    page_count: usize,
+    /// The WAL snapshot of the source the copied pages come from.
+    source_snapshot: Option<(u64, u32)>,
+    /// The schema cookie of the destination before the backup.
+    dest_schema_cookie: u32,
+    /// Whether the destination is in the write transaction of the backup.
+    dest_locked: bool,
+    done: bool,
+}
+
+impl Backup {
+    /// Starts a backup of the database of `source` into the database of `dest`, which is
+    /// overwritten once the backup completes.
+    pub fn new(source: Arc<Connection>, dest: Arc<Connection>) -> Result<Self> {
+        if Arc::ptr_eq(&source._db, &dest._db) {
+            return Err(LimboError::InvalidArgument(
+                "source and destination must be distinct".to_string(),
+            ));
+        }
+        if source._db.mv_store.is_some() || dest._db.mv_store.is_some() {
+            return Err(LimboError::TxError(
+                "backup is not supported with MVCC".to_string(),
+            ));
+        }
+        if dest._db.open_flags.contains(OpenFlags::ReadOnly) {
+            return Err(LimboError::ReadOnly);
+        }
+        if !matches!(dest.transaction_state.get(), TransactionState::None) {
+            return Err(LimboError::TxError(
+                "destination database is in use".to_string(),
+            ));
+        }
+        Ok(Self {
+            source,
+            dest,
+            next_page: 1,
+            page_count: 0,
+            source_snapshot: None,
+            dest_schema_cookie: 0,
+            dest_locked: false,
+            done: false,
+        })
+    }
+
+    /// Copies up to `pages` pages, or all the remaining ones if `pages` is negative. Returns
+    /// whether the backup is complete, in which case the destination was committed.
+    ///
+    /// Fails with [LimboError::Busy] if either database is locked, after which the step can be
+    /// retried.
+    pub fn step(&mut self, pages: i32) -> Result<bool> {
+        if self.done {
+            return Ok(true);
+        }
+        if self.source.closed.get() || self.dest.closed.get() {
+            return Err(LimboError::InternalError("Connection closed".to_string()));
+        }
+        let source_pager = self.source.pager.borrow().clone();
+        // A transaction already open on the source connection is used as is, but its changes
+        // can't be copied before they are committed.
+        let read_tx = match self.source.transaction_state.get() {
+            TransactionState::None => true,
+            TransactionState::Read => false,
+            TransactionState::Write { .. } => {
+                return Err(LimboError::TxError(
+                    "source database is being written to by its connection".to_string(),
+                ))
+            }
+        };
+        if read_tx
+            && matches!(
+                source_pager.io.block(|| source_pager.begin_read_tx())?,
+                LimboResult::Busy
+            )
+        {
+            return Err(LimboError::Busy);
+        }
+        let result = self.copy_pages(&source_pager, pages);
+        if read_tx {
+            source_pager.end_read_tx()?;
+        }
+        result
+    }
+
+    /// The number of pages left to copy, as of the last step.
+    pub fn remaining(&self) -> usize {
+        self.page_count.saturating_sub(self.next_page - 1)
+    }
+
+    /// The number of pages of the source database, as of the last step.
+    pub fn page_count(&self) -> usize {
+        self.page_count
+    }
+
+    /// Ends the backup. If it isn't complete, the destination is left unchanged.
+    pub fn finish(mut self) -> Result<()> {
+        self.release()
+    }
+
+    fn copy_pages(&mut self, source_pager: &Rc<Pager>, pages: i32) -> Result<bool> {
+        if !self.dest_locked {
+            self.lock_dest(source_pager)?;
+        }
+        let dest_pager = self.dest.pager.borrow().clone();
+        let snapshot = source_pager.current_wal_snapshot();
+        if self.source_snapshot.replace(snapshot) != Some(snapshot) {
+            self.next_page = 1;
+        }
+        self.page_count = header_accessor::get_database_size(source_pager)? as usize;
+        if self.next_page == 1 {
+            let dest_size = header_accessor::get_database_size(&dest_pager)? as usize;
+            if dest_size > self.page_count {
+                dest_pager.truncate_db(self.page_count as u32)?;
+            }
+        }
+        let last_page = match usize::try_from(pages) {
+            Ok(pages) => self.page_count.min(self.next_page - 1 + pages),
+            Err(_) => self.page_count,
+        };
+        while self.next_page <= last_page {
+            let page = source_pager.read_page_blocking(self.next_page)?;
+            dest_pager.overwrite_page(self.next_page, page.get_contents().as_ptr())?;
+            self.next_page += 1;
+        }
+        if self.next_page <= self.page_count {
+            return Ok(false);
+        }
+        self.commit_dest(&dest_pager)?;
+        self.done = true;
+        Ok(true)
+    }
+
+    /// Starts the write transaction of the backup on the destination.
+    fn lock_dest(&mut self, source_pager: &Rc<Pager>) -> Result<()> {
+        let page_size = header_accessor::get_page_size(source_pager)?;
+        if self.dest._db.db_state.load(Ordering::SeqCst) == DB_STATE_UNINITIALIZED {
+            self.dest.reset_page_size(page_size)?;
+        }
+        let dest_pager = self.dest.pager.borrow().clone();
+        if matches!(
+            dest_pager.io.block(|| dest_pager.begin_read_tx())?,
+            LimboResult::Busy
+        ) {
+            return Err(LimboError::Busy);
+        }
+        if header_accessor::get_page_size(&dest_pager)? != page_size {
+            dest_pager.end_read_tx()?;
+            return Err(LimboError::InvalidArgument(
+                "source and destination page sizes differ".to_string(),
+            ));
+        }
+        if matches!(
+            dest_pager.io.block(|| dest_pager.begin_write_tx())?,
+            LimboResult::Busy
+        ) {
+            dest_pager.end_read_tx()?;
+            return Err(LimboError::Busy);
+        }
+        self.dest_schema_cookie = header_accessor::get_schema_cookie(&dest_pager)?;
+        self.dest_locked = true;
+        Ok(())
+    }
+
+    /// Commits the copied pages to the destination, and loads its new schema.
+    fn commit_dest(&mut self, dest_pager: &Rc<Pager>) -> Result<()> {
+        // Bump the schema cookie so that the other connections to the destination notice that
+        // its schema was replaced.
+        let schema_version = self.dest_schema_cookie + 1;
+        header_accessor::set_schema_cookie(dest_pager, schema_version)?;
+        let mut schema = Schema::new(self.dest.schema.borrow().indexes_enabled());
+        schema.schema_version = schema_version;
+        match schema.make_from_btree(None, dest_pager.clone(), &self.dest.syms.borrow()) {
+            // A virtual table whose module isn't loaded on the destination connection.
+            Ok(()) | Err(LimboError::ExtensionError(_)) => {}
+            Err(e) => return Err(e),
+        }
+        self.dest.schema.replace(Arc::new(schema));
+        dest_pager.io.block(|| {
+            dest_pager.end_tx(
+                false,
+                true,
+                &self.dest,
+                self.dest.wal_checkpoint_disabled.get(),
+            )
+        })?;
+        self.dest_locked = false;
+        Ok(())
+    }
+
+    /// Rolls back the write transaction of the backup on the destination, if it's still open.
+    fn release(&mut self) -> Result<()> {
+        if !self.dest_locked {
+            return Ok(());
+        }
+        self.dest_locked = false;
+        let dest_pager = self.dest.pager.borrow().clone();
+        dest_pager.rollback(true, &self.dest)?;
+        dest_pager.end_tx(
+            true,
+            true,
+            &self.dest,
+            self.dest.wal_checkpoint_disabled.get(),
+        )?;
+        Ok(())
+    }
+}
+
+impl Drop for Backup {
+    fn drop(&mut self) {
+        let _ = self.release();
+    }
+}
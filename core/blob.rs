@@ -0,0 +1,368 @@
+//! Incremental I/O on the blobs and texts of tables, like SQLite's `sqlite3_blob_*` API.
+//!
+//! A [Blob] reads and writes byte ranges of a value in place, straight from and to the pages of
+//! its row, so that a value spanning many overflow pages never has to be loaded whole. Like in
+//! SQLite, the size of a value can't be changed this way. The row is looked up again by its rowid
+//! before every access, so that a handle whose value was changed or deleted by a statement fails
+//! instead of reading or overwriting something else.
+//!
+//! A handle reads, and writes, in a transaction of its connection: an open one is used as is,
+//! otherwise one is started, and committed when the handle is closed unless the connection is in
+//! an explicit transaction.
+
+use crate::result::LimboResult;
+use crate::storage::btree::BTreeCursor;
+use crate::storage::pager::Pager;
+use crate::storage::sqlite3_ondisk::read_varint;
+use crate::types::{SeekKey, SeekOp, SeekResult, SerialType, SerialTypeKind};
+use crate::util::IOExt;
+use crate::{Connection, LimboError, OpenFlags, Result, TransactionState};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// The largest size of the varint the header of a record starts with.
+const MAX_VARINT_SIZE: usize = 9;
+
+/// A handle on a blob or text value of a table, opened with [Connection::blob_open].
+///
+/// ```ignore
+/// let mut blob = conn.blob_open("main", "files", "content", rowid, false)?;
+/// let mut chunk = vec![0; 4096];
+/// blob.read(&mut chunk, 0)?;
+/// blob.close()?;
+/// ```
+pub struct Blob {
+    /// The connection that opened the handle.
+    conn: Arc<Connection>,
+    /// The connection of the database the table is in: `conn` itself for the main database, or
+    /// that of the temp or an attached database.
+    db_conn: Arc<Connection>,
+    pager: Rc<Pager>,
+    cursor: BTreeCursor,
+    /// The position of the value in the records of the table, which don't hold the VIRTUAL
+    /// generated columns.
+    record_column: usize,
+    writable: bool,
+    rowid: i64,
+    /// The offset of the value in the payload of its row, and its size, or `None` if the last
+    /// reseek failed.
+    value: Option<(usize, usize)>,
+    /// Whether the handle started the transaction it's in, which it ends when closed.
+    owns_tx: bool,
+}
+
+impl Connection {
+    /// Opens the value of `column` in the row with `rowid` of `table`, in the database named
+    /// `database` (`main`, `temp` or the name of an attached one), for incremental I/O. The value
+    /// must be a blob or a text. A `writable` handle starts a write transaction, and can't be
+    /// opened on an indexed or generated column.
+    pub fn blob_open(
+        self: &Arc<Connection>,
+        database: &str,
+        table: &str,
+        column: &str,
+        rowid: i64,
+        writable: bool,
+    ) -> Result<Blob> {
+        Blob::open(self, database, table, column, rowid, writable)
+    }
+}
+
+impl Blob {
+    fn open(
+        conn: &Arc<Connection>,
+        database: &str,
+        table: &str,
+        column: &str,
+        rowid: i64,
+        writable: bool,
+    ) -> Result<Self> {
+        if conn.closed.get() {
+            return Err(LimboError::InternalError("Connection closed".to_string()));
+        }
+        if conn._db.mv_store.is_some() {
+            return Err(LimboError::TxError(
+                "incremental blob I/O is not supported with MVCC".to_string(),
+            ));
+        }
+        let db_conn = conn.database_connection(database)?;
+        db_conn.check_encryption_key()?;
+        if writable && (conn.readonly.get() || db_conn._db.open_flags.contains(OpenFlags::ReadOnly))
+        {
+            return Err(LimboError::ReadOnly);
+        }
+        db_conn.maybe_update_schema()?;
+        let schema = db_conn.schema.borrow().clone();
+        let Some(btree_table) = schema.get_btree_table(table) else {
+            return Err(LimboError::InvalidArgument(format!(
+                "no such table: {database}.{table}"
+            )));
+        };
+        if !btree_table.has_rowid {
+            return Err(LimboError::InvalidArgument(format!(
+                "cannot open table without rowid: {table}"
+            )));
+        }
+        let Some((column_idx, table_column)) = btree_table.get_column(column) else {
+            return Err(LimboError::InvalidArgument(format!(
+                "no such column: \"{column}\""
+            )));
+        };
+        if table_column.is_virtual_generated() {
+            return Err(LimboError::InvalidArgument(format!(
+                "cannot open virtual column: \"{column}\""
+            )));
+        }
+        if writable {
+            if table_column.generated.is_some() {
+                return Err(LimboError::InvalidArgument(
+                    "cannot open generated column for writing".to_string(),
+                ));
+            }
+            // An expression or a WHERE clause may use the column too, so like SQLite with an
+            // expression index, they are taken as indexing it.
+            let indexed = schema.get_indices(&btree_table.name).iter().any(|index| {
+                index.where_clause.is_some()
+                    || index
+                        .columns
+                        .iter()
+                        .any(|c| c.pos_in_table == column_idx || c.expr.is_some())
+            });
+            if indexed {
+                return Err(LimboError::InvalidArgument(
+                    "cannot open indexed column for writing".to_string(),
+                ));
+            }
+        }
+        let record_column = btree_table.columns[..column_idx]
+            .iter()
+            .filter(|column| !column.is_virtual_generated())
+            .count();
+        let pager = db_conn.pager.borrow().clone();
+        let cursor = BTreeCursor::new_table(
+            None,
+            pager.clone(),
+            btree_table.root_page,
+            btree_table.columns.len(),
+        );
+        let mut blob = Self {
+            conn: conn.clone(),
+            db_conn,
+            pager,
+            cursor,
+            record_column,
+            writable,
+            rowid,
+            value: None,
+            owns_tx: false,
+        };
+        blob.begin_tx()?;
+        // Dropping the handle ends the transaction if the value can't be opened.
+        blob.value = Some(blob.locate(rowid)?);
+        Ok(blob)
+    }
+
+    /// The size in bytes of the value.
+    pub fn len(&self) -> usize {
+        self.value.map_or(0, |(_, size)| size)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Reads `buf.len()` bytes of the value, starting at `offset`.
+    pub fn read(&mut self, buf: &mut [u8], offset: usize) -> Result<()> {
+        let start = self.check_range(offset, buf.len())?;
+        let mut data = Vec::with_capacity(buf.len());
+        self.access(start, buf.len(), &mut data, false)?;
+        buf.copy_from_slice(&data);
+        Ok(())
+    }
+
+    /// Overwrites the bytes of the value starting at `offset` with `data`. The size of the value
+    /// doesn't change, so the range must be within it.
+    pub fn write(&mut self, data: &[u8], offset: usize) -> Result<()> {
+        if !self.writable {
+            return Err(LimboError::ReadOnly);
+        }
+        let start = self.check_range(offset, data.len())?;
+        self.access(start, data.len(), &mut data.to_vec(), true)
+    }
+
+    /// Moves the handle to the value of the same column in the row with `rowid`. If that fails,
+    /// the handle can't be used anymore, except to be closed or moved again.
+    pub fn reseek(&mut self, rowid: i64) -> Result<()> {
+        self.value = None;
+        self.begin_tx()?;
+        let value = self.locate(rowid)?;
+        self.rowid = rowid;
+        self.value = Some(value);
+        Ok(())
+    }
+
+    /// Closes the handle, committing the transaction it started, if any.
+    pub fn close(mut self) -> Result<()> {
+        self.end_tx()
+    }
+
+    /// Returns the offset in the payload of the row of the `len` bytes of the value at `offset`,
+    /// after checking that the value didn't change since the handle was opened.
+    fn check_range(&mut self, offset: usize, len: usize) -> Result<usize> {
+        let Some((start, size)) = self.value else {
+            return Err(LimboError::TxError(
+                "the blob handle was aborted by a failed reseek".to_string(),
+            ));
+        };
+        if offset.checked_add(len).is_none_or(|end| end > size) {
+            return Err(LimboError::InvalidArgument(format!(
+                "range {offset}..{} is out of the blob of {size} bytes",
+                offset.saturating_add(len)
+            )));
+        }
+        self.begin_tx()?;
+        let value = match self.locate(self.rowid) {
+            Ok(value) => Some(value),
+            // The row was deleted, or the value isn't a blob or text anymore.
+            Err(LimboError::InvalidArgument(_)) => None,
+            Err(e) => return Err(e),
+        };
+        if value != Some((start, size)) {
+            self.value = None;
+            return Err(LimboError::TxError(
+                "the blob handle expired since its row was changed".to_string(),
+            ));
+        }
+        Ok(start + offset)
+    }
+
+    /// Positions the cursor on the row with `rowid`, and returns the offset of the value in its
+    /// payload and its size.
+    fn locate(&mut self, rowid: i64) -> Result<(usize, usize)> {
+        let pager = self.pager.clone();
+        let seek = pager.io.block(|| {
+            self.cursor
+                .seek(SeekKey::TableRowId(rowid), SeekOp::GE { eq_only: true })
+        })?;
+        if !matches!(seek, SeekResult::Found) {
+            return Err(LimboError::InvalidArgument(format!(
+                "no such rowid: {rowid}"
+            )));
+        }
+        let payload_size = pager.io.block(|| self.cursor.payload_size())? as usize;
+        let mut header = Vec::new();
+        self.access(0, payload_size.min(MAX_VARINT_SIZE), &mut header, false)?;
+        let (header_size, mut pos) = read_varint(&header)?;
+        let header_size = header_size as usize;
+        if header_size > payload_size {
+            return Err(LimboError::Corrupt(format!(
+                "record header of row {rowid} is larger than its payload"
+            )));
+        }
+        header.clear();
+        self.access(0, header_size, &mut header, false)?;
+        let mut offset = header_size;
+        let mut column = 0;
+        while pos < header_size {
+            let (serial_type, len) = read_varint(&header[pos..])?;
+            pos += len;
+            let serial_type = SerialType::try_from(serial_type)?;
+            if column == self.record_column {
+                return match serial_type.kind() {
+                    SerialTypeKind::Blob | SerialTypeKind::Text => Ok((offset, serial_type.size())),
+                    SerialTypeKind::Null => Err(cannot_open("null")),
+                    SerialTypeKind::F64 => Err(cannot_open("real")),
+                    _ => Err(cannot_open("integer")),
+                };
+            }
+            offset += serial_type.size();
+            column += 1;
+        }
+        // A column added after the row was written.
+        Err(cannot_open("null"))
+    }
+
+    /// Reads `len` bytes of the payload of the current row at `offset` into `buffer`, or writes
+    /// them from it.
+    fn access(
+        &mut self,
+        offset: usize,
+        len: usize,
+        buffer: &mut Vec<u8>,
+        is_write: bool,
+    ) -> Result<()> {
+        if len == 0 {
+            return Ok(());
+        }
+        let pager = self.pager.clone();
+        pager.io.block(|| {
+            self.cursor
+                .read_write_payload_with_offset(offset as u32, buffer, len as u32, is_write)
+        })
+    }
+
+    /// Starts a transaction on the database of the value, if there's none yet: a read one, or a
+    /// write one for a writable handle.
+    fn begin_tx(&mut self) -> Result<()> {
+        let state = self.db_conn.transaction_state.get();
+        if matches!(state, TransactionState::None) {
+            if let LimboResult::Busy = self.pager.io.block(|| self.pager.begin_read_tx())? {
+                return Err(LimboError::Busy);
+            }
+            self.db_conn.transaction_state.set(TransactionState::Read);
+            self.owns_tx = self.conn.auto_commit.get();
+        }
+        if self.writable
+            && !matches!(
+                self.db_conn.transaction_state.get(),
+                TransactionState::Write { .. }
+            )
+        {
+            if let LimboResult::Busy = self.pager.io.block(|| self.pager.begin_write_tx())? {
+                if matches!(state, TransactionState::None) {
+                    self.pager.end_read_tx()?;
+                    self.db_conn.transaction_state.set(TransactionState::None);
+                    self.owns_tx = false;
+                }
+                return Err(LimboError::Busy);
+            }
+            self.db_conn.transaction_state.set(TransactionState::Write {
+                schema_did_change: false,
+            });
+        }
+        Ok(())
+    }
+
+    /// Commits the transaction the handle started, if any.
+    fn end_tx(&mut self) -> Result<()> {
+        if !std::mem::take(&mut self.owns_tx) {
+            return Ok(());
+        }
+        match self.db_conn.transaction_state.get() {
+            TransactionState::Write { schema_did_change } => {
+                self.pager.io.block(|| {
+                    self.pager.end_tx(
+                        false,
+                        schema_did_change,
+                        &self.db_conn,
+                        self.db_conn.wal_checkpoint_disabled.get(),
+                    )
+                })?;
+            }
+            TransactionState::Read => self.pager.end_read_tx()?,
+            TransactionState::None => {}
+        }
+        self.db_conn.transaction_state.set(TransactionState::None);
+        Ok(())
+    }
+}
+
+impl Drop for Blob {
+    fn drop(&mut self) {
+        let _ = self.end_tx();
+    }
+}
+
+fn cannot_open(ty: &str) -> LimboError {
+    LimboError::InvalidArgument(format!("cannot open value of type {ty}"))
+}
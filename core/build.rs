@@ -18,4 +18,14 @@ fn main() {
         ),
     )
     .expect("Failed to append to built file");
+
+    // The variadic routines of the API of the SQLite extensions that can be loaded
+    let is_wasm = std::env::var("CARGO_CFG_TARGET_FAMILY")
+        .map(|families| families.split(',').any(|family| family == "wasm"))
+        .unwrap_or(false);
+    if std::env::var_os("CARGO_FEATURE_FS").is_some() && !is_wasm {
+        cc::Build::new()
+            .file("ext/sqlite3ext/printf.c")
+            .compile("sqlite3ext_printf");
+    }
 }
@@ -59,6 +59,16 @@ pub enum LimboError {
     ReadOnly,
     #[error("Database is busy")]
     Busy,
+    #[error("Bad parameter or other API misuse: {0}")]
+    Misuse(String),
+    #[error("Interrupted")]
+    Interrupt,
+    #[error("Transaction aborted because of a write-write conflict")]
+    WriteWriteConflict,
+    #[error("Authorization error: {0}")]
+    AuthorizationDenied(String),
+    #[error("String or blob too big: {0}")]
+    TooBig(String),
 }
 
 #[macro_export]
@@ -88,6 +98,11 @@ impl From<turso_ext::ResultCode> for LimboError {
     }
 }
 
+pub const SQLITE_ERROR: usize = 1;
 pub const SQLITE_CONSTRAINT: usize = 19;
 pub const SQLITE_CONSTRAINT_PRIMARYKEY: usize = SQLITE_CONSTRAINT | (6 << 8);
 pub const SQLITE_CONSTRAINT_NOTNULL: usize = SQLITE_CONSTRAINT | (5 << 8);
+pub const SQLITE_CONSTRAINT_TRIGGER: usize = SQLITE_CONSTRAINT | (7 << 8);
+pub const SQLITE_CONSTRAINT_CHECK: usize = SQLITE_CONSTRAINT | (1 << 8);
+pub const SQLITE_CONSTRAINT_UNIQUE: usize = SQLITE_CONSTRAINT | (8 << 8);
+pub const SQLITE_CONSTRAINT_DATATYPE: usize = SQLITE_CONSTRAINT | (12 << 8);
@@ -4,12 +4,16 @@ use crate::{
 };
 use libloading::{Library, Symbol};
 use std::{
-    ffi::{c_char, CString},
+    ffi::{c_char, CStr, CString},
     sync::{Arc, Mutex, OnceLock},
 };
-use turso_ext::{ExtensionApi, ExtensionApiRef, ExtensionEntryPoint, ResultCode, VfsImpl};
+use turso_ext::{
+    ExtensionApi, ExtensionApiRef, ExtensionEntryPoint, ExtensionVersionFn, ResultCode, VfsImpl,
+};
 
-type ExtensionStore = Vec<(Arc<Library>, ExtensionApiRef)>;
+/// The libraries of the loaded extensions, with the API given to them, which the extensions
+/// compiled for SQLite don't get.
+type ExtensionStore = Vec<(Arc<Library>, Option<ExtensionApiRef>)>;
 static EXTENSIONS: OnceLock<Arc<Mutex<ExtensionStore>>> = OnceLock::new();
 pub fn get_extension_libraries() -> Arc<Mutex<ExtensionStore>> {
     EXTENSIONS
@@ -35,9 +39,27 @@ impl Connection {
     ) -> crate::Result<()> {
         use turso_ext::ExtensionApiRef;
 
+        let lib = unsafe {
+            Library::new(path.as_ref()).map_err(|e| LimboError::ExtensionError(e.to_string()))?
+        };
+        // Extensions compiled for SQLite have their own entry point
+        if unsafe { lib.get::<ExtensionEntryPoint>(b"register_extension") }.is_err() {
+            let result =
+                super::sqlite3ext::load_extension(self, &lib, std::path::Path::new(path.as_ref()));
+            // The library stays loaded even if the extension failed, since it may have created
+            // functions before failing
+            get_extension_libraries()
+                .lock()
+                .map_err(|_| {
+                    LimboError::ExtensionError("Error locking extension libraries".to_string())
+                })?
+                .push((Arc::new(lib), None));
+            result?;
+            self.parse_schema_rows()?;
+            return Ok(());
+        }
+        check_api_version(&lib)?;
         let api = Box::new(self.build_turso_ext());
-        let lib =
-            unsafe { Library::new(path).map_err(|e| LimboError::ExtensionError(e.to_string()))? };
         let entry: Symbol<ExtensionEntryPoint> = unsafe {
             lib.get(b"register_extension")
                 .map_err(|e| LimboError::ExtensionError(e.to_string()))?
@@ -52,7 +74,7 @@ impl Connection {
                 .map_err(|_| {
                     LimboError::ExtensionError("Error locking extension libraries".to_string())
                 })?
-                .push((Arc::new(lib), api_ref));
+                .push((Arc::new(lib), Some(api_ref)));
             {
                 self.parse_schema_rows()?;
             }
@@ -68,6 +90,32 @@ impl Connection {
     }
 }
 
+/// Refuses extensions built against another version of `turso_ext`, since the API structs they
+/// are handed may not have the layout they expect.
+fn check_api_version(lib: &Library) -> crate::Result<()> {
+    let version = match unsafe { lib.get::<ExtensionVersionFn>(b"turso_ext_api_version") } {
+        Ok(version) => unsafe { version() },
+        Err(_) => std::ptr::null(),
+    };
+    let version =
+        (!version.is_null()).then(|| unsafe { CStr::from_ptr(version) }.to_string_lossy());
+    validate_api_version(version.as_deref())
+}
+
+/// Checks the `turso_ext` version an extension reports, if it reports one at all.
+fn validate_api_version(version: Option<&str>) -> crate::Result<()> {
+    let expected = turso_ext::API_VERSION.trim_end_matches('\0');
+    match version {
+        Some(version) if version == expected => Ok(()),
+        Some(version) => Err(LimboError::ExtensionError(format!(
+            "Extension was built against turso_ext {version}, expected {expected}"
+        ))),
+        None => Err(LimboError::ExtensionError(format!(
+            "Extension was built against an unknown turso_ext version, expected {expected}"
+        ))),
+    }
+}
+
 #[allow(clippy::arc_with_non_send_sync)]
 pub(crate) unsafe extern "C" fn register_vfs(
     name: *const c_char,
@@ -177,3 +225,22 @@ pub fn get_vfs_modules() -> Vec<Vfs> {
         .unwrap()
         .clone()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_validate_api_version() {
+        let expected = turso_ext::API_VERSION.trim_end_matches('\0');
+        assert!(validate_api_version(Some(expected)).is_ok());
+        assert!(matches!(
+            validate_api_version(Some("0.0.0")),
+            Err(LimboError::ExtensionError(msg)) if msg.contains("built against turso_ext 0.0.0")
+        ));
+        assert!(matches!(
+            validate_api_version(None),
+            Err(LimboError::ExtensionError(msg)) if msg.contains("unknown turso_ext version")
+        ));
+    }
+}
@@ -1,5 +1,7 @@
 #[cfg(feature = "fs")]
 mod dynamic;
+#[cfg(feature = "fs")]
+mod sqlite3ext;
 mod vtab_xconnect;
 use crate::vtab::VirtualTable;
 #[cfg(all(target_os = "linux", feature = "io_uring"))]
@@ -7,6 +9,8 @@ use crate::UringIO;
 use crate::{function::ExternalFunc, Connection, Database, LimboError, IO};
 #[cfg(feature = "fs")]
 pub use dynamic::{add_builtin_vfs_extensions, add_vfs_module, list_vfs_modules, VfsMod};
+#[cfg(feature = "fs")]
+pub(crate) use sqlite3ext::{SqliteVirtualTable, SqliteVirtualTableCursor};
 use std::{
     ffi::{c_char, c_void, CStr, CString},
     rc::Rc,
@@ -22,7 +26,16 @@ type ExternAggFunc = (InitAggFunction, StepFunction, FinalizeFunction);
 #[derive(Clone)]
 pub struct VTabImpl {
     pub module_kind: VTabKind,
-    pub implementation: Rc<VTabModuleImpl>,
+    pub implementation: VTabModule,
+}
+
+/// The implementation of a virtual table module: registered through the turso extension API,
+/// or with `sqlite3_create_module` by an extension compiled for SQLite.
+#[derive(Clone)]
+pub enum VTabModule {
+    Ext(Rc<VTabModuleImpl>),
+    #[cfg(feature = "fs")]
+    Sqlite(Rc<sqlite3ext::SqliteModule>),
 }
 
 pub(crate) unsafe extern "C" fn register_scalar_function(
@@ -140,23 +153,28 @@ impl Connection {
         module: VTabModuleImpl,
         kind: VTabKind,
     ) -> ResultCode {
-        let module = Rc::new(module);
         let vmodule = VTabImpl {
             module_kind: kind,
-            implementation: module,
+            implementation: VTabModule::Ext(Rc::new(module)),
         };
+        match self.add_vtab_module(name, vmodule) {
+            Ok(()) => ResultCode::OK,
+            Err(_) => ResultCode::Error,
+        }
+    }
+
+    /// Registers a virtual table module, and the table-valued function if the module is one.
+    fn add_vtab_module(&self, name: &str, module: VTabImpl) -> crate::Result<()> {
+        let kind = module.module_kind;
         self.syms
             .borrow_mut()
             .vtab_modules
-            .insert(name.to_string(), vmodule.into());
+            .insert(name.to_string(), module.into());
         if kind == VTabKind::TableValuedFunction {
-            if let Ok(vtab) = VirtualTable::function(name, &self.syms.borrow()) {
-                self.with_schema_mut(|schema| schema.add_virtual_table(vtab));
-            } else {
-                return ResultCode::Error;
-            }
+            let vtab = VirtualTable::function(name, &self.syms.borrow())?;
+            self.with_schema_mut(|schema| schema.add_virtual_table(vtab));
         }
-        ResultCode::OK
+        Ok(())
     }
 
     pub fn build_turso_ext(&self) -> ExtensionApi {
@@ -181,6 +199,12 @@ impl Connection {
         crate::uuid::register_extension(&mut ext_api);
         #[cfg(feature = "series")]
         crate::series::register_extension(&mut ext_api);
+        #[cfg(feature = "json")]
+        crate::json::register_extension(&mut ext_api);
+        #[cfg(feature = "fts5")]
+        crate::fts5::register_extension(&mut ext_api);
+        #[cfg(feature = "rtree")]
+        crate::rtree::register_extension(&mut ext_api);
         #[cfg(feature = "fs")]
         {
             let vfslist = add_builtin_vfs_extensions(Some(ext_api)).map_err(|e| e.to_string())?;
@@ -0,0 +1,468 @@
+//! Loading the extensions compiled for SQLite, the shared libraries with a
+//! `sqlite3_extension_init` entry point written against `sqlite3ext.h`.
+//!
+//! The entry point gets a `sqlite3_api_routines` table, through which the extension creates
+//! its functions, collations and virtual table modules. The routines of the table are
+//! implemented on top of the connection that loads the extension, and the routines it can't
+//! support, like the ones that prepare statements or use UTF-16 text, are NULL pointers: SQLite
+//! also leaves out the routines of the features it's compiled without. The variadic routines,
+//! like `sqlite3_mprintf`, can't be written in Rust and are in `printf.c`.
+
+mod routines;
+mod value;
+mod vtab;
+
+use crate::{Connection, LimboError};
+use libloading::Library;
+use std::cell::RefCell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::{Arc, Weak};
+pub(crate) use vtab::{SqliteModule, SqliteVirtualTable, SqliteVirtualTableCursor};
+
+const SQLITE_OK: c_int = 0;
+const SQLITE_ERROR: c_int = 1;
+const SQLITE_NOMEM: c_int = 7;
+const SQLITE_TOOBIG: c_int = 18;
+const SQLITE_MISUSE: c_int = 21;
+const SQLITE_OK_LOAD_PERMANENTLY: c_int = 256;
+
+type DestroyCallback = unsafe extern "C" fn(*mut c_void);
+type EntryPoint =
+    unsafe extern "C" fn(*mut c_void, *mut *mut c_char, *const routines::ApiRoutines) -> c_int;
+
+/// The `sqlite3*` the extensions get: the connection that loaded them, and the state that the
+/// routines keep between calls.
+pub(crate) struct ExtDb {
+    conn: Weak<Connection>,
+    /// The schema declared with `sqlite3_declare_vtab` by the virtual table being created
+    declared_schema: RefCell<Option<String>>,
+    /// The code and message of the last error, for `sqlite3_errcode` and `sqlite3_errmsg`
+    error: RefCell<(c_int, CString)>,
+}
+
+impl ExtDb {
+    fn new(conn: &Arc<Connection>) -> Rc<Self> {
+        Rc::new(Self {
+            conn: Arc::downgrade(conn),
+            declared_schema: RefCell::new(None),
+            error: RefCell::new((SQLITE_OK, CString::default())),
+        })
+    }
+
+    /// Gets the `ExtDb` behind a `sqlite3*` given to an extension, which the extension can only
+    /// have gotten from a call while the `ExtDb` is alive.
+    unsafe fn from_ptr(db: *mut c_void) -> Option<Rc<Self>> {
+        if db.is_null() {
+            return None;
+        }
+        let db = db as *const ExtDb;
+        Rc::increment_strong_count(db);
+        Some(Rc::from_raw(db))
+    }
+
+    fn as_ptr(self: &Rc<Self>) -> *mut c_void {
+        Rc::as_ptr(self) as *mut c_void
+    }
+
+    fn connection(&self) -> Option<Arc<Connection>> {
+        self.conn.upgrade()
+    }
+
+    fn set_error(&self, code: c_int, message: &str) {
+        let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+        *self.error.borrow_mut() = (code, message);
+    }
+}
+
+/// Loads the SQLite extension in `lib` into `conn`, calling its entry point. The library must
+/// stay loaded as long as the functions and modules it created.
+pub(crate) fn load_extension(
+    conn: &Arc<Connection>,
+    lib: &Library,
+    path: &Path,
+) -> crate::Result<()> {
+    let mut entry_point = None;
+    for name in entry_point_names(path) {
+        let symbol = CString::new(name).expect("entry point names have no NUL");
+        if let Ok(symbol) = unsafe { lib.get::<EntryPoint>(symbol.as_bytes_with_nul()) } {
+            entry_point = Some(*symbol);
+            break;
+        }
+    }
+    let Some(entry_point) = entry_point else {
+        return Err(LimboError::ExtensionError(format!(
+            "no entry point [{}] in shared library {}",
+            entry_point_names(path).join(", "),
+            path.display()
+        )));
+    };
+    let db = ExtDb::new(conn);
+    let mut message: *mut c_char = std::ptr::null_mut();
+    let rc = unsafe { entry_point(db.as_ptr(), &mut message, routines::routines()) };
+    let message = unsafe { take_message(message) };
+    match rc {
+        SQLITE_OK | SQLITE_OK_LOAD_PERMANENTLY => Ok(()),
+        _ => Err(LimboError::ExtensionError(message.unwrap_or_else(|| {
+            error_string(rc).to_string_lossy().into_owned()
+        }))),
+    }
+}
+
+/// The names of the entry point of an extension, in the order SQLite looks them up:
+/// `sqlite3_extension_init`, then `sqlite3_X_init`, where X are the lowercase letters of the
+/// name of the file without its extension and its `lib` prefix.
+fn entry_point_names(path: &Path) -> Vec<String> {
+    let stem = path
+        .file_name()
+        .and_then(|name| name.to_str())
+        .map(|name| name.split('.').next().unwrap_or(name))
+        .unwrap_or_default();
+    let stem = if stem.len() > 3 && stem[..3].eq_ignore_ascii_case("lib") {
+        &stem[3..]
+    } else {
+        stem
+    };
+    let name: String = stem
+        .chars()
+        .filter(char::is_ascii_alphabetic)
+        .map(|c| c.to_ascii_lowercase())
+        .collect();
+    vec![
+        "sqlite3_extension_init".to_string(),
+        format!("sqlite3_{name}_init"),
+    ]
+}
+
+/// Takes an error message the extension allocated with the memory routines.
+unsafe fn take_message(message: *mut c_char) -> Option<String> {
+    if message.is_null() {
+        return None;
+    }
+    let text = CStr::from_ptr(message).to_string_lossy().into_owned();
+    free(message as *mut c_void);
+    Some(text)
+}
+
+fn error_string(code: c_int) -> &'static CStr {
+    match code & 0xff {
+        SQLITE_OK => c"not an error",
+        SQLITE_ERROR => c"SQL logic error",
+        2 => c"internal error",
+        3 => c"access permission denied",
+        4 => c"query aborted",
+        5 => c"database is locked",
+        6 => c"database table is locked",
+        SQLITE_NOMEM => c"out of memory",
+        8 => c"attempt to write a readonly database",
+        9 => c"interrupted",
+        10 => c"disk I/O error",
+        11 => c"database disk image is malformed",
+        12 => c"unknown operation",
+        13 => c"database or disk is full",
+        14 => c"unable to open database file",
+        15 => c"locking protocol",
+        17 => c"database schema has changed",
+        SQLITE_TOOBIG => c"string or blob too big",
+        19 => c"constraint failed",
+        20 => c"datatype mismatch",
+        SQLITE_MISUSE => c"bad parameter or other API misuse",
+        23 => c"authorization denied",
+        25 => c"column index out of range",
+        26 => c"file is not a database",
+        100 => c"another row available",
+        101 => c"no more rows available",
+        _ => c"unknown error",
+    }
+}
+
+/// The size of the header in front of the allocations, which holds their size
+const HEADER: usize = 16;
+
+fn layout(size: usize) -> Option<std::alloc::Layout> {
+    std::alloc::Layout::from_size_align(size.checked_add(HEADER)?, HEADER).ok()
+}
+
+/// `sqlite3_malloc64`, also used by `printf.c`
+#[no_mangle]
+pub extern "C" fn turso_sqlite3_malloc64(n: u64) -> *mut c_void {
+    malloc64(n)
+}
+
+/// `sqlite3_free`, also used by `printf.c`
+///
+/// # Safety
+/// `ptr` must be NULL or come from the memory routines.
+#[no_mangle]
+pub unsafe extern "C" fn turso_sqlite3_free(ptr: *mut c_void) {
+    free(ptr)
+}
+
+/// `sqlite3_log`, called by `printf.c` with the formatted message
+///
+/// # Safety
+/// `message` must be a NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn turso_sqlite3_log(code: c_int, message: *const c_char) {
+    if !message.is_null() {
+        let message = CStr::from_ptr(message).to_string_lossy();
+        tracing::info!("extension log ({code}): {message}");
+    }
+}
+
+fn malloc64(n: u64) -> *mut c_void {
+    let Some(layout) = usize::try_from(n).ok().and_then(layout) else {
+        return std::ptr::null_mut();
+    };
+    unsafe {
+        let ptr = std::alloc::alloc(layout);
+        if ptr.is_null() {
+            return std::ptr::null_mut();
+        }
+        (ptr as *mut usize).write(n as usize);
+        ptr.add(HEADER) as *mut c_void
+    }
+}
+
+unsafe fn free(ptr: *mut c_void) {
+    if ptr.is_null() {
+        return;
+    }
+    let ptr = (ptr as *mut u8).sub(HEADER);
+    let size = (ptr as *const usize).read();
+    std::alloc::dealloc(ptr, layout(size).expect("allocated with this layout"));
+}
+
+unsafe fn msize(ptr: *mut c_void) -> u64 {
+    if ptr.is_null() {
+        return 0;
+    }
+    ((ptr as *const u8).sub(HEADER) as *const usize).read() as u64
+}
+
+unsafe fn realloc64(ptr: *mut c_void, n: u64) -> *mut c_void {
+    if ptr.is_null() {
+        return malloc64(n);
+    }
+    if n == 0 {
+        free(ptr);
+        return std::ptr::null_mut();
+    }
+    let Some(new_layout) = usize::try_from(n).ok().and_then(layout) else {
+        return std::ptr::null_mut();
+    };
+    let base = (ptr as *mut u8).sub(HEADER);
+    let size = (base as *const usize).read();
+    let base = std::alloc::realloc(
+        base,
+        layout(size).expect("allocated with this layout"),
+        new_layout.size(),
+    );
+    if base.is_null() {
+        return std::ptr::null_mut();
+    }
+    (base as *mut usize).write(n as usize);
+    base.add(HEADER) as *mut c_void
+}
+
+unsafe extern "C" fn sqlite3_malloc(n: c_int) -> *mut c_void {
+    if n <= 0 {
+        return std::ptr::null_mut();
+    }
+    malloc64(n as u64)
+}
+
+unsafe extern "C" fn sqlite3_malloc64(n: u64) -> *mut c_void {
+    malloc64(n)
+}
+
+unsafe extern "C" fn sqlite3_realloc(ptr: *mut c_void, n: c_int) -> *mut c_void {
+    realloc64(ptr, n.max(0) as u64)
+}
+
+unsafe extern "C" fn sqlite3_realloc64(ptr: *mut c_void, n: u64) -> *mut c_void {
+    realloc64(ptr, n)
+}
+
+unsafe extern "C" fn sqlite3_free(ptr: *mut c_void) {
+    free(ptr)
+}
+
+unsafe extern "C" fn sqlite3_msize(ptr: *mut c_void) -> u64 {
+    msize(ptr)
+}
+
+/// The version of SQLite whose extension API is implemented
+const SQLITE_VERSION: &CStr = c"3.50.1";
+const SQLITE_VERSION_NUMBER: c_int = 3050001;
+const SQLITE_SOURCE_ID: &CStr = c"turso";
+
+unsafe extern "C" fn sqlite3_libversion() -> *const c_char {
+    SQLITE_VERSION.as_ptr()
+}
+
+unsafe extern "C" fn sqlite3_libversion_number() -> c_int {
+    SQLITE_VERSION_NUMBER
+}
+
+unsafe extern "C" fn sqlite3_sourceid() -> *const c_char {
+    SQLITE_SOURCE_ID.as_ptr()
+}
+
+unsafe extern "C" fn sqlite3_threadsafe() -> c_int {
+    0
+}
+
+unsafe extern "C" fn sqlite3_errcode(db: *mut c_void) -> c_int {
+    ExtDb::from_ptr(db).map_or(SQLITE_MISUSE, |db| db.error.borrow().0 & 0xff)
+}
+
+unsafe extern "C" fn sqlite3_extended_errcode(db: *mut c_void) -> c_int {
+    ExtDb::from_ptr(db).map_or(SQLITE_MISUSE, |db| db.error.borrow().0)
+}
+
+unsafe extern "C" fn sqlite3_errmsg(db: *mut c_void) -> *const c_char {
+    let Some(db) = ExtDb::from_ptr(db) else {
+        return c"out of memory".as_ptr();
+    };
+    let (code, message) = &*db.error.borrow();
+    if message.is_empty() {
+        return sqlite3_errstr(*code);
+    }
+    // The message lives as long as the error, like in SQLite
+    message.as_ptr()
+}
+
+unsafe extern "C" fn sqlite3_errstr(code: c_int) -> *const c_char {
+    error_string(code).as_ptr()
+}
+
+unsafe extern "C" fn sqlite3_get_autocommit(db: *mut c_void) -> c_int {
+    with_connection(db, 1, |conn| conn.get_auto_commit() as c_int)
+}
+
+unsafe extern "C" fn sqlite3_last_insert_rowid(db: *mut c_void) -> i64 {
+    with_connection(db, 0, |conn| conn.last_insert_rowid())
+}
+
+unsafe extern "C" fn sqlite3_set_last_insert_rowid(db: *mut c_void, rowid: i64) {
+    with_connection(db, (), |conn| conn.update_last_rowid(rowid))
+}
+
+unsafe extern "C" fn sqlite3_changes(db: *mut c_void) -> c_int {
+    with_connection(db, 0, |conn| conn.changes() as c_int)
+}
+
+unsafe extern "C" fn sqlite3_changes64(db: *mut c_void) -> i64 {
+    with_connection(db, 0, |conn| conn.changes())
+}
+
+unsafe extern "C" fn sqlite3_total_changes(db: *mut c_void) -> c_int {
+    with_connection(db, 0, |conn| conn.total_changes() as c_int)
+}
+
+unsafe extern "C" fn sqlite3_total_changes64(db: *mut c_void) -> i64 {
+    with_connection(db, 0, |conn| conn.total_changes())
+}
+
+unsafe extern "C" fn sqlite3_interrupt(db: *mut c_void) {
+    with_connection(db, (), |conn| conn.interrupt())
+}
+
+unsafe fn with_connection<T>(db: *mut c_void, default: T, f: impl FnOnce(&Connection) -> T) -> T {
+    match ExtDb::from_ptr(db).and_then(|db| db.connection()) {
+        Some(conn) => f(&conn),
+        None => default,
+    }
+}
+
+unsafe extern "C" fn sqlite3_sleep(ms: c_int) -> c_int {
+    let ms = ms.max(0);
+    std::thread::sleep(std::time::Duration::from_millis(ms as u64));
+    ms
+}
+
+unsafe extern "C" fn sqlite3_randomness(n: c_int, ptr: *mut c_void) {
+    if n > 0 && !ptr.is_null() {
+        let buf = std::slice::from_raw_parts_mut(ptr as *mut u8, n as usize);
+        getrandom::getrandom(buf).expect("failed to generate random bytes");
+    }
+}
+
+unsafe extern "C" fn sqlite3_stricmp(a: *const c_char, b: *const c_char) -> c_int {
+    sqlite3_strnicmp(a, b, c_int::MAX)
+}
+
+unsafe extern "C" fn sqlite3_strnicmp(a: *const c_char, b: *const c_char, n: c_int) -> c_int {
+    match (a.is_null(), b.is_null()) {
+        (true, true) => return 0,
+        (true, false) => return -1,
+        (false, true) => return 1,
+        (false, false) => {}
+    }
+    let a = CStr::from_ptr(a).to_bytes().iter();
+    let b = CStr::from_ptr(b).to_bytes().iter();
+    let n = n.max(0) as usize;
+    // Compare the NUL terminators too, so that a prefix comes first
+    let a = a.chain(std::iter::once(&0)).take(n);
+    let b = b.chain(std::iter::once(&0)).take(n);
+    for (a, b) in a.zip(b) {
+        let (a, b) = (a.to_ascii_lowercase(), b.to_ascii_lowercase());
+        if a != b || a == 0 {
+            return a as c_int - b as c_int;
+        }
+    }
+    0
+}
+
+unsafe extern "C" fn sqlite3_compileoption_used(_name: *const c_char) -> c_int {
+    0
+}
+
+unsafe extern "C" fn sqlite3_compileoption_get(_n: c_int) -> *const c_char {
+    std::ptr::null()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_point_names() {
+        let names = |path: &str| entry_point_names(Path::new(path))[1].clone();
+        assert_eq!(names("/usr/lib/libkvstore.so"), "sqlite3_kvstore_init");
+        assert_eq!(names("ext/Vector-0.2.dylib"), "sqlite3_vector_init");
+        assert_eq!(names("uuid.dll"), "sqlite3_uuid_init");
+        assert_eq!(names("lib"), "sqlite3_lib_init");
+        assert_eq!(
+            entry_point_names(Path::new("x.so"))[0],
+            "sqlite3_extension_init"
+        );
+    }
+
+    #[test]
+    fn test_memory_routines() {
+        unsafe {
+            let ptr = malloc64(10) as *mut u8;
+            assert!(!ptr.is_null());
+            assert_eq!(msize(ptr as *mut c_void), 10);
+            std::ptr::copy_nonoverlapping(b"0123456789".as_ptr(), ptr, 10);
+            let ptr = realloc64(ptr as *mut c_void, 100) as *mut u8;
+            assert_eq!(msize(ptr as *mut c_void), 100);
+            assert_eq!(std::slice::from_raw_parts(ptr, 10), b"0123456789");
+            assert!(realloc64(ptr as *mut c_void, 0).is_null());
+            free(std::ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_strnicmp() {
+        unsafe {
+            assert_eq!(sqlite3_stricmp(c"Hello".as_ptr(), c"hELLO".as_ptr()), 0);
+            assert!(sqlite3_stricmp(c"abc".as_ptr(), c"abd".as_ptr()) < 0);
+            assert!(sqlite3_stricmp(c"ab".as_ptr(), c"abc".as_ptr()) < 0);
+            assert_eq!(sqlite3_strnicmp(c"abcX".as_ptr(), c"ABCy".as_ptr(), 3), 0);
+        }
+    }
+}
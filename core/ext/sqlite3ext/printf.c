@@ -0,0 +1,422 @@
+/*
+** The routines of the SQLite extension API that take a variable number of
+** arguments, which can't be defined in Rust: the printf() family, the
+** sqlite3_str string builder and the variadic configuration routines.
+**
+** Formatting follows sqlite3_mprintf(): the standard conversions plus %q, %Q,
+** %w and %z. The standard conversions are delegated to the C library one at a
+** time. Memory returned to the extension comes from turso_sqlite3_malloc64()
+** so that it can be released with sqlite3_free().
+*/
+#include <stdarg.h>
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+/* Implemented in mod.rs */
+extern void *turso_sqlite3_malloc64(unsigned long long n);
+extern void turso_sqlite3_free(void *p);
+extern void turso_sqlite3_log(int err_code, const char *msg);
+
+#define SQLITE_OK 0
+#define SQLITE_ERROR 1
+#define SQLITE_NOMEM 7
+#define SQLITE_TOOBIG 18
+#define SQLITE_MISUSE 21
+
+/* Formatted strings larger than this fail like SQLITE_MAX_LENGTH */
+#define MAX_LENGTH 1000000000
+
+typedef struct Buf {
+  char *z;
+  size_t n;
+  size_t cap;
+  int err;
+} Buf;
+
+/* The sqlite3_str objects are buffers */
+typedef Buf sqlite3_str;
+
+static void buf_reserve(Buf *b, size_t extra) {
+  size_t cap;
+  char *z;
+  if (b->err) return;
+  if (b->n + extra + 1 > MAX_LENGTH) {
+    b->err = SQLITE_TOOBIG;
+    return;
+  }
+  if (b->n + extra + 1 <= b->cap) return;
+  cap = b->cap ? b->cap : 64;
+  while (cap < b->n + extra + 1) cap *= 2;
+  z = realloc(b->z, cap);
+  if (z == NULL) {
+    b->err = SQLITE_NOMEM;
+    return;
+  }
+  b->z = z;
+  b->cap = cap;
+}
+
+static void buf_append(Buf *b, const char *z, size_t n) {
+  buf_reserve(b, n);
+  if (b->err) return;
+  memcpy(b->z + b->n, z, n);
+  b->n += n;
+  b->z[b->n] = 0;
+}
+
+static void buf_append_char(Buf *b, size_t count, char c) {
+  buf_reserve(b, count);
+  if (b->err) return;
+  memset(b->z + b->n, c, count);
+  b->n += count;
+  b->z[b->n] = 0;
+}
+
+/* Appends z with the quotes it contains doubled, for %q, %Q and %w */
+static void buf_append_escaped(Buf *b, const char *z, int precision, char quote,
+                               int enclose) {
+  size_t i;
+  size_t n = strlen(z);
+  if (precision >= 0 && (size_t)precision < n) n = (size_t)precision;
+  if (enclose) buf_append_char(b, 1, quote);
+  for (i = 0; i < n; i++) {
+    buf_append_char(b, z[i] == quote ? 2 : 1, z[i]);
+  }
+  if (enclose) buf_append_char(b, 1, quote);
+}
+
+/* Appends the output of snprintf() with the single conversion in spec */
+#define APPEND_CONVERSION(b, spec, value)                         \
+  do {                                                            \
+    int len_ = snprintf(NULL, 0, spec, value);                    \
+    if (len_ < 0) break;                                          \
+    buf_reserve(b, (size_t)len_);                                 \
+    if ((b)->err) break;                                          \
+    snprintf((b)->z + (b)->n, (size_t)len_ + 1, spec, value);     \
+    (b)->n += (size_t)len_;                                       \
+  } while (0)
+
+static void buf_vappendf(Buf *b, const char *fmt, va_list ap) {
+  const char *p = fmt;
+  while (*p && !b->err) {
+    char spec[64];
+    size_t spec_len = 0;
+    int width = -1;
+    int precision = -1;
+    int left_align = 0;
+    int longs = 0;
+    char conv;
+    const char *start = p;
+
+    if (*p != '%') {
+      const char *end = strchr(p, '%');
+      size_t n = end ? (size_t)(end - p) : strlen(p);
+      buf_append(b, p, n);
+      p += n;
+      continue;
+    }
+    p++;
+    spec[spec_len++] = '%';
+    /* Flags. The SQLite-specific '!' and ',' are dropped. */
+    while (*p && strchr("-+ #0!,", *p)) {
+      if (*p == '-') left_align = 1;
+      if (*p != '!' && *p != ',' && spec_len < 8) spec[spec_len++] = *p;
+      p++;
+    }
+    if (*p == '*') {
+      width = va_arg(ap, int);
+      if (width < 0) {
+        left_align = 1;
+        width = -width;
+        if (spec_len < 8) spec[spec_len++] = '-';
+      }
+      p++;
+    } else {
+      while (*p >= '0' && *p <= '9') {
+        width = (width < 0 ? 0 : width) * 10 + (*p - '0');
+        if (width > MAX_LENGTH) width = MAX_LENGTH;
+        p++;
+      }
+    }
+    if (*p == '.') {
+      p++;
+      precision = 0;
+      if (*p == '*') {
+        precision = va_arg(ap, int);
+        if (precision < 0) precision = -1;
+        p++;
+      } else {
+        while (*p >= '0' && *p <= '9') {
+          precision = precision * 10 + (*p - '0');
+          if (precision > MAX_LENGTH) precision = MAX_LENGTH;
+          p++;
+        }
+      }
+    }
+    if (width >= 0) spec_len += (size_t)sprintf(spec + spec_len, "%d", width);
+    if (precision >= 0) {
+      spec_len += (size_t)sprintf(spec + spec_len, ".%d", precision);
+    }
+    while (*p == 'l') {
+      longs++;
+      p++;
+    }
+    conv = *p;
+    if (conv == 0) {
+      buf_append(b, start, strlen(start));
+      break;
+    }
+    p++;
+    switch (conv) {
+      case 'd':
+      case 'i': {
+        long long v;
+        if (longs >= 2) {
+          v = va_arg(ap, long long);
+        } else if (longs == 1) {
+          v = va_arg(ap, long);
+        } else {
+          v = va_arg(ap, int);
+        }
+        spec[spec_len++] = 'l';
+        spec[spec_len++] = 'l';
+        spec[spec_len++] = conv;
+        spec[spec_len] = 0;
+        APPEND_CONVERSION(b, spec, v);
+        break;
+      }
+      case 'u':
+      case 'x':
+      case 'X':
+      case 'o': {
+        unsigned long long v;
+        if (longs >= 2) {
+          v = va_arg(ap, unsigned long long);
+        } else if (longs == 1) {
+          v = va_arg(ap, unsigned long);
+        } else {
+          v = va_arg(ap, unsigned int);
+        }
+        spec[spec_len++] = 'l';
+        spec[spec_len++] = 'l';
+        spec[spec_len++] = conv;
+        spec[spec_len] = 0;
+        APPEND_CONVERSION(b, spec, v);
+        break;
+      }
+      case 'f':
+      case 'e':
+      case 'E':
+      case 'g':
+      case 'G': {
+        double v = va_arg(ap, double);
+        spec[spec_len++] = conv;
+        spec[spec_len] = 0;
+        APPEND_CONVERSION(b, spec, v);
+        break;
+      }
+      case 'c': {
+        int v = va_arg(ap, int);
+        spec[spec_len++] = 'c';
+        spec[spec_len] = 0;
+        APPEND_CONVERSION(b, spec, v);
+        break;
+      }
+      case 'p': {
+        void *v = va_arg(ap, void *);
+        spec[spec_len++] = 'p';
+        spec[spec_len] = 0;
+        APPEND_CONVERSION(b, spec, v);
+        break;
+      }
+      case 's':
+      case 'z': {
+        char *v = va_arg(ap, char *);
+        spec[spec_len++] = 's';
+        spec[spec_len] = 0;
+        APPEND_CONVERSION(b, spec, v ? v : "");
+        if (conv == 'z') turso_sqlite3_free(v);
+        break;
+      }
+      case 'q':
+      case 'Q':
+      case 'w': {
+        const char *v = va_arg(ap, const char *);
+        Buf escaped = {0};
+        if (v == NULL) {
+          buf_append(&escaped, conv == 'Q' ? "NULL" : "(NULL)",
+                     conv == 'Q' ? 4 : 6);
+        } else {
+          buf_append_escaped(&escaped, v, precision, conv == 'w' ? '"' : '\'',
+                             conv == 'Q');
+        }
+        if (escaped.err) {
+          b->err = escaped.err;
+        } else {
+          size_t n = escaped.n;
+          size_t pad = width > 0 && (size_t)width > n ? (size_t)width - n : 0;
+          if (!left_align) buf_append_char(b, pad, ' ');
+          if (n) buf_append(b, escaped.z, n);
+          if (left_align) buf_append_char(b, pad, ' ');
+        }
+        free(escaped.z);
+        break;
+      }
+      case '%':
+        buf_append_char(b, 1, '%');
+        break;
+      case 'n':
+        /* Nothing is written back, like SQLite does */
+        (void)va_arg(ap, int *);
+        break;
+      default:
+        /* Unknown conversions are output as they are */
+        buf_append(b, start, (size_t)(p - start));
+        break;
+    }
+  }
+}
+
+/* Moves the content of the buffer to memory released with sqlite3_free() */
+static char *buf_finish(Buf *b) {
+  char *z = NULL;
+  if (!b->err) {
+    z = turso_sqlite3_malloc64(b->n + 1);
+    if (z) {
+      if (b->n) memcpy(z, b->z, b->n);
+      z[b->n] = 0;
+    }
+  }
+  free(b->z);
+  memset(b, 0, sizeof(*b));
+  return z;
+}
+
+char *turso_sqlite3_vmprintf(const char *fmt, va_list ap) {
+  Buf b = {0};
+  if (fmt == NULL) return NULL;
+  buf_vappendf(&b, fmt, ap);
+  return buf_finish(&b);
+}
+
+char *turso_sqlite3_mprintf(const char *fmt, ...) {
+  va_list ap;
+  char *z;
+  va_start(ap, fmt);
+  z = turso_sqlite3_vmprintf(fmt, ap);
+  va_end(ap);
+  return z;
+}
+
+char *turso_sqlite3_vsnprintf(int n, char *buf, const char *fmt, va_list ap) {
+  Buf b = {0};
+  size_t len;
+  if (n <= 0 || buf == NULL || fmt == NULL) return buf;
+  buf_vappendf(&b, fmt, ap);
+  len = b.err ? 0 : b.n;
+  if (len > (size_t)n - 1) len = (size_t)n - 1;
+  if (len) memcpy(buf, b.z, len);
+  buf[len] = 0;
+  free(b.z);
+  return buf;
+}
+
+char *turso_sqlite3_snprintf(int n, char *buf, const char *fmt, ...) {
+  va_list ap;
+  va_start(ap, fmt);
+  turso_sqlite3_vsnprintf(n, buf, fmt, ap);
+  va_end(ap);
+  return buf;
+}
+
+void turso_sqlite3_log_printf(int err_code, const char *fmt, ...) {
+  va_list ap;
+  Buf b = {0};
+  if (fmt == NULL) return;
+  va_start(ap, fmt);
+  buf_vappendf(&b, fmt, ap);
+  va_end(ap);
+  if (!b.err) turso_sqlite3_log(err_code, b.z ? b.z : "");
+  free(b.z);
+}
+
+sqlite3_str *turso_sqlite3_str_new(void *db) {
+  (void)db;
+  return calloc(1, sizeof(sqlite3_str));
+}
+
+char *turso_sqlite3_str_finish(sqlite3_str *s) {
+  char *z;
+  if (s == NULL) return NULL;
+  /* An empty string finishes as NULL, like SQLite */
+  if (s->n) {
+    z = buf_finish(s);
+  } else {
+    free(s->z);
+    z = NULL;
+  }
+  free(s);
+  return z;
+}
+
+void turso_sqlite3_str_vappendf(sqlite3_str *s, const char *fmt, va_list ap) {
+  if (s == NULL || fmt == NULL) return;
+  buf_vappendf(s, fmt, ap);
+}
+
+void turso_sqlite3_str_appendf(sqlite3_str *s, const char *fmt, ...) {
+  va_list ap;
+  va_start(ap, fmt);
+  turso_sqlite3_str_vappendf(s, fmt, ap);
+  va_end(ap);
+}
+
+void turso_sqlite3_str_append(sqlite3_str *s, const char *z, int n) {
+  if (s == NULL || z == NULL || n <= 0) return;
+  buf_append(s, z, (size_t)n);
+}
+
+void turso_sqlite3_str_appendall(sqlite3_str *s, const char *z) {
+  if (s == NULL || z == NULL) return;
+  buf_append(s, z, strlen(z));
+}
+
+void turso_sqlite3_str_appendchar(sqlite3_str *s, int n, char c) {
+  if (s == NULL || n <= 0) return;
+  buf_append_char(s, (size_t)n, c);
+}
+
+void turso_sqlite3_str_reset(sqlite3_str *s) {
+  if (s == NULL) return;
+  free(s->z);
+  memset(s, 0, sizeof(*s));
+}
+
+int turso_sqlite3_str_errcode(sqlite3_str *s) {
+  return s == NULL ? SQLITE_NOMEM : s->err;
+}
+
+int turso_sqlite3_str_length(sqlite3_str *s) {
+  return s == NULL || s->err ? 0 : (int)s->n;
+}
+
+char *turso_sqlite3_str_value(sqlite3_str *s) {
+  return s == NULL || s->err || s->n == 0 ? NULL : s->z;
+}
+
+/*
+** The configuration options of virtual tables only affect how SQLite plans
+** queries, so they are all accepted.
+*/
+int turso_sqlite3_vtab_config(void *db, int op, ...) {
+  (void)db;
+  return op >= 1 && op <= 4 ? SQLITE_OK : SQLITE_MISUSE;
+}
+
+/* None of the options of the database connection can be configured */
+int turso_sqlite3_db_config(void *db, int op, ...) {
+  (void)db;
+  (void)op;
+  return SQLITE_ERROR;
+}
@@ -0,0 +1,456 @@
+//! The `sqlite3_api_routines` table given to the entry point of the extensions.
+
+use super::*;
+use std::sync::OnceLock;
+
+type Routine = *const c_void;
+
+// The variadic routines, and the ones that take a `va_list`, from `printf.c`. Only their
+// addresses are used, so the `va_list` arguments are left opaque.
+extern "C" {
+    fn turso_sqlite3_mprintf(format: *const c_char, ...) -> *mut c_char;
+    fn turso_sqlite3_vmprintf(format: *const c_char, args: *mut c_void) -> *mut c_char;
+    fn turso_sqlite3_snprintf(
+        n: c_int,
+        buf: *mut c_char,
+        format: *const c_char,
+        ...
+    ) -> *mut c_char;
+    fn turso_sqlite3_vsnprintf(
+        n: c_int,
+        buf: *mut c_char,
+        format: *const c_char,
+        args: *mut c_void,
+    ) -> *mut c_char;
+    fn turso_sqlite3_log_printf(code: c_int, format: *const c_char, ...);
+    fn turso_sqlite3_str_new(db: *mut c_void) -> *mut c_void;
+    fn turso_sqlite3_str_finish(s: *mut c_void) -> *mut c_char;
+    fn turso_sqlite3_str_appendf(s: *mut c_void, format: *const c_char, ...);
+    fn turso_sqlite3_str_vappendf(s: *mut c_void, format: *const c_char, args: *mut c_void);
+    fn turso_sqlite3_str_append(s: *mut c_void, text: *const c_char, n: c_int);
+    fn turso_sqlite3_str_appendall(s: *mut c_void, text: *const c_char);
+    fn turso_sqlite3_str_appendchar(s: *mut c_void, n: c_int, c: c_char);
+    fn turso_sqlite3_str_reset(s: *mut c_void);
+    fn turso_sqlite3_str_errcode(s: *mut c_void) -> c_int;
+    fn turso_sqlite3_str_length(s: *mut c_void) -> c_int;
+    fn turso_sqlite3_str_value(s: *mut c_void) -> *mut c_char;
+    fn turso_sqlite3_db_config(db: *mut c_void, op: c_int, ...) -> c_int;
+    fn turso_sqlite3_vtab_config(db: *mut c_void, op: c_int, ...) -> c_int;
+}
+
+/// `sqlite3_api_routines`, with the fields in the order of `sqlite3ext.h`. The fields are
+/// pointers to functions, NULL for the routines that aren't supported.
+#[repr(C)]
+#[allow(dead_code)] // The fields are only read by the extensions
+pub(super) struct ApiRoutines {
+    aggregate_context: Routine,
+    aggregate_count: Routine,
+    bind_blob: Routine,
+    bind_double: Routine,
+    bind_int: Routine,
+    bind_int64: Routine,
+    bind_null: Routine,
+    bind_parameter_count: Routine,
+    bind_parameter_index: Routine,
+    bind_parameter_name: Routine,
+    bind_text: Routine,
+    bind_text16: Routine,
+    bind_value: Routine,
+    busy_handler: Routine,
+    busy_timeout: Routine,
+    changes: Routine,
+    close: Routine,
+    collation_needed: Routine,
+    collation_needed16: Routine,
+    column_blob: Routine,
+    column_bytes: Routine,
+    column_bytes16: Routine,
+    column_count: Routine,
+    column_database_name: Routine,
+    column_database_name16: Routine,
+    column_decltype: Routine,
+    column_decltype16: Routine,
+    column_double: Routine,
+    column_int: Routine,
+    column_int64: Routine,
+    column_name: Routine,
+    column_name16: Routine,
+    column_origin_name: Routine,
+    column_origin_name16: Routine,
+    column_table_name: Routine,
+    column_table_name16: Routine,
+    column_text: Routine,
+    column_text16: Routine,
+    column_type: Routine,
+    column_value: Routine,
+    commit_hook: Routine,
+    complete: Routine,
+    complete16: Routine,
+    create_collation: Routine,
+    create_collation16: Routine,
+    create_function: Routine,
+    create_function16: Routine,
+    create_module: Routine,
+    data_count: Routine,
+    db_handle: Routine,
+    declare_vtab: Routine,
+    enable_shared_cache: Routine,
+    errcode: Routine,
+    errmsg: Routine,
+    errmsg16: Routine,
+    exec: Routine,
+    expired: Routine,
+    finalize: Routine,
+    free: Routine,
+    free_table: Routine,
+    get_autocommit: Routine,
+    get_auxdata: Routine,
+    get_table: Routine,
+    global_recover: Routine,
+    interruptx: Routine,
+    last_insert_rowid: Routine,
+    libversion: Routine,
+    libversion_number: Routine,
+    malloc: Routine,
+    mprintf: Routine,
+    open: Routine,
+    open16: Routine,
+    prepare: Routine,
+    prepare16: Routine,
+    profile: Routine,
+    progress_handler: Routine,
+    realloc: Routine,
+    reset: Routine,
+    result_blob: Routine,
+    result_double: Routine,
+    result_error: Routine,
+    result_error16: Routine,
+    result_int: Routine,
+    result_int64: Routine,
+    result_null: Routine,
+    result_text: Routine,
+    result_text16: Routine,
+    result_text16be: Routine,
+    result_text16le: Routine,
+    result_value: Routine,
+    rollback_hook: Routine,
+    set_authorizer: Routine,
+    set_auxdata: Routine,
+    xsnprintf: Routine,
+    step: Routine,
+    table_column_metadata: Routine,
+    thread_cleanup: Routine,
+    total_changes: Routine,
+    trace: Routine,
+    transfer_bindings: Routine,
+    update_hook: Routine,
+    user_data: Routine,
+    value_blob: Routine,
+    value_bytes: Routine,
+    value_bytes16: Routine,
+    value_double: Routine,
+    value_int: Routine,
+    value_int64: Routine,
+    value_numeric_type: Routine,
+    value_text: Routine,
+    value_text16: Routine,
+    value_text16be: Routine,
+    value_text16le: Routine,
+    value_type: Routine,
+    vmprintf: Routine,
+    // Added ???
+    overload_function: Routine,
+    // Added by 3.3.13
+    prepare_v2: Routine,
+    prepare16_v2: Routine,
+    clear_bindings: Routine,
+    // Added by 3.4.1
+    create_module_v2: Routine,
+    // Added by 3.5.0
+    bind_zeroblob: Routine,
+    blob_bytes: Routine,
+    blob_close: Routine,
+    blob_open: Routine,
+    blob_read: Routine,
+    blob_write: Routine,
+    create_collation_v2: Routine,
+    file_control: Routine,
+    memory_highwater: Routine,
+    memory_used: Routine,
+    mutex_alloc: Routine,
+    mutex_enter: Routine,
+    mutex_free: Routine,
+    mutex_leave: Routine,
+    mutex_try: Routine,
+    open_v2: Routine,
+    release_memory: Routine,
+    result_error_nomem: Routine,
+    result_error_toobig: Routine,
+    sleep: Routine,
+    soft_heap_limit: Routine,
+    vfs_find: Routine,
+    vfs_register: Routine,
+    vfs_unregister: Routine,
+    xthreadsafe: Routine,
+    result_zeroblob: Routine,
+    result_error_code: Routine,
+    test_control: Routine,
+    randomness: Routine,
+    context_db_handle: Routine,
+    extended_result_codes: Routine,
+    limit: Routine,
+    next_stmt: Routine,
+    sql: Routine,
+    status: Routine,
+    backup_finish: Routine,
+    backup_init: Routine,
+    backup_pagecount: Routine,
+    backup_remaining: Routine,
+    backup_step: Routine,
+    compileoption_get: Routine,
+    compileoption_used: Routine,
+    create_function_v2: Routine,
+    db_config: Routine,
+    db_mutex: Routine,
+    db_status: Routine,
+    extended_errcode: Routine,
+    log: Routine,
+    soft_heap_limit64: Routine,
+    sourceid: Routine,
+    stmt_status: Routine,
+    strnicmp: Routine,
+    unlock_notify: Routine,
+    wal_autocheckpoint: Routine,
+    wal_checkpoint: Routine,
+    wal_hook: Routine,
+    blob_reopen: Routine,
+    vtab_config: Routine,
+    vtab_on_conflict: Routine,
+    // Version 3.7.16 and later
+    close_v2: Routine,
+    db_filename: Routine,
+    db_readonly: Routine,
+    db_release_memory: Routine,
+    errstr: Routine,
+    stmt_busy: Routine,
+    stmt_readonly: Routine,
+    stricmp: Routine,
+    uri_boolean: Routine,
+    uri_int64: Routine,
+    uri_parameter: Routine,
+    xvsnprintf: Routine,
+    wal_checkpoint_v2: Routine,
+    // Version 3.8.7 and later
+    auto_extension: Routine,
+    bind_blob64: Routine,
+    bind_text64: Routine,
+    cancel_auto_extension: Routine,
+    load_extension: Routine,
+    malloc64: Routine,
+    msize: Routine,
+    realloc64: Routine,
+    reset_auto_extension: Routine,
+    result_blob64: Routine,
+    result_text64: Routine,
+    strglob: Routine,
+    // Version 3.8.11 and later
+    value_dup: Routine,
+    value_free: Routine,
+    result_zeroblob64: Routine,
+    bind_zeroblob64: Routine,
+    // Version 3.9.0 and later
+    value_subtype: Routine,
+    result_subtype: Routine,
+    // Version 3.10.0 and later
+    status64: Routine,
+    strlike: Routine,
+    db_cacheflush: Routine,
+    // Version 3.12.0 and later
+    system_errno: Routine,
+    // Version 3.14.0 and later
+    trace_v2: Routine,
+    expanded_sql: Routine,
+    // Version 3.18.0 and later
+    set_last_insert_rowid: Routine,
+    // Version 3.20.0 and later
+    prepare_v3: Routine,
+    prepare16_v3: Routine,
+    bind_pointer: Routine,
+    result_pointer: Routine,
+    value_pointer: Routine,
+    vtab_nochange: Routine,
+    value_nochange: Routine,
+    vtab_collation: Routine,
+    // Version 3.24.0 and later
+    keyword_count: Routine,
+    keyword_name: Routine,
+    keyword_check: Routine,
+    str_new: Routine,
+    str_finish: Routine,
+    str_appendf: Routine,
+    str_vappendf: Routine,
+    str_append: Routine,
+    str_appendall: Routine,
+    str_appendchar: Routine,
+    str_reset: Routine,
+    str_errcode: Routine,
+    str_length: Routine,
+    str_value: Routine,
+    // Version 3.25.0 and later
+    create_window_function: Routine,
+    // Version 3.26.0 and later
+    normalized_sql: Routine,
+    // Version 3.28.0 and later
+    stmt_isexplain: Routine,
+    value_frombind: Routine,
+    // Version 3.30.0 and later
+    drop_modules: Routine,
+    // Version 3.31.0 and later
+    hard_heap_limit64: Routine,
+    uri_key: Routine,
+    filename_database: Routine,
+    filename_journal: Routine,
+    filename_wal: Routine,
+    // Version 3.32.0 and later
+    create_filename: Routine,
+    free_filename: Routine,
+    database_file_object: Routine,
+    // Version 3.34.0 and later
+    txn_state: Routine,
+    // Version 3.36.1 and later
+    changes64: Routine,
+    total_changes64: Routine,
+    // Version 3.37.0 and later
+    autovacuum_pages: Routine,
+    // Version 3.38.0 and later
+    error_offset: Routine,
+    vtab_rhs_value: Routine,
+    vtab_distinct: Routine,
+    vtab_in: Routine,
+    vtab_in_first: Routine,
+    vtab_in_next: Routine,
+    // Version 3.39.0 and later
+    deserialize: Routine,
+    serialize: Routine,
+    db_name: Routine,
+    // Version 3.40.0 and later
+    value_encoding: Routine,
+    // Version 3.41.0 and later
+    is_interrupted: Routine,
+    // Version 3.43.0 and later
+    stmt_explain: Routine,
+    // Version 3.44.0 and later
+    get_clientdata: Routine,
+    set_clientdata: Routine,
+    // Version 3.50.0 and later
+    setlk_timeout: Routine,
+}
+
+// Only holds pointers to functions
+unsafe impl Send for ApiRoutines {}
+unsafe impl Sync for ApiRoutines {}
+
+pub(super) fn routines() -> &'static ApiRoutines {
+    static ROUTINES: OnceLock<ApiRoutines> = OnceLock::new();
+    ROUTINES.get_or_init(|| {
+        // SAFETY: all the fields are pointers, for which zero is NULL
+        let mut r: ApiRoutines = unsafe { std::mem::zeroed() };
+        r.aggregate_context = value::aggregate_context as Routine;
+        r.changes = sqlite3_changes as Routine;
+        r.create_collation = value::create_collation as Routine;
+        r.create_function = value::create_function_v1 as Routine;
+        r.create_module = vtab::create_module as Routine;
+        r.declare_vtab = vtab::declare_vtab as Routine;
+        r.errcode = sqlite3_errcode as Routine;
+        r.errmsg = sqlite3_errmsg as Routine;
+        r.free = sqlite3_free as Routine;
+        r.get_autocommit = sqlite3_get_autocommit as Routine;
+        r.get_auxdata = value::get_auxdata as Routine;
+        r.interruptx = sqlite3_interrupt as Routine;
+        r.last_insert_rowid = sqlite3_last_insert_rowid as Routine;
+        r.libversion = sqlite3_libversion as Routine;
+        r.libversion_number = sqlite3_libversion_number as Routine;
+        r.malloc = sqlite3_malloc as Routine;
+        r.mprintf = turso_sqlite3_mprintf as Routine;
+        r.realloc = sqlite3_realloc as Routine;
+        r.result_blob = value::result_blob as Routine;
+        r.result_double = value::result_double as Routine;
+        r.result_error = value::result_error as Routine;
+        r.result_int = value::result_int as Routine;
+        r.result_int64 = value::result_int64 as Routine;
+        r.result_null = value::result_null as Routine;
+        r.result_text = value::result_text as Routine;
+        r.result_value = value::result_value as Routine;
+        r.set_auxdata = value::set_auxdata as Routine;
+        r.xsnprintf = turso_sqlite3_snprintf as Routine;
+        r.total_changes = sqlite3_total_changes as Routine;
+        r.user_data = value::user_data as Routine;
+        r.value_blob = value::value_blob as Routine;
+        r.value_bytes = value::value_bytes as Routine;
+        r.value_double = value::value_double as Routine;
+        r.value_int = value::value_int as Routine;
+        r.value_int64 = value::value_int64 as Routine;
+        r.value_numeric_type = value::value_numeric_type as Routine;
+        r.value_text = value::value_text as Routine;
+        r.value_type = value::value_type as Routine;
+        r.vmprintf = turso_sqlite3_vmprintf as Routine;
+        r.create_module_v2 = vtab::create_module_v2 as Routine;
+        r.create_collation_v2 = value::create_collation_v2 as Routine;
+        r.result_error_nomem = value::result_error_nomem as Routine;
+        r.result_error_toobig = value::result_error_toobig as Routine;
+        r.sleep = sqlite3_sleep as Routine;
+        r.xthreadsafe = sqlite3_threadsafe as Routine;
+        r.result_zeroblob = value::result_zeroblob as Routine;
+        r.result_error_code = value::result_error_code as Routine;
+        r.randomness = sqlite3_randomness as Routine;
+        r.context_db_handle = value::context_db_handle as Routine;
+        r.compileoption_get = sqlite3_compileoption_get as Routine;
+        r.compileoption_used = sqlite3_compileoption_used as Routine;
+        r.create_function_v2 = value::create_function_v2 as Routine;
+        r.db_config = turso_sqlite3_db_config as Routine;
+        r.extended_errcode = sqlite3_extended_errcode as Routine;
+        r.log = turso_sqlite3_log_printf as Routine;
+        r.sourceid = sqlite3_sourceid as Routine;
+        r.strnicmp = sqlite3_strnicmp as Routine;
+        r.vtab_config = turso_sqlite3_vtab_config as Routine;
+        r.vtab_on_conflict = vtab::vtab_on_conflict as Routine;
+        r.errstr = sqlite3_errstr as Routine;
+        r.stricmp = sqlite3_stricmp as Routine;
+        r.xvsnprintf = turso_sqlite3_vsnprintf as Routine;
+        r.malloc64 = sqlite3_malloc64 as Routine;
+        r.msize = sqlite3_msize as Routine;
+        r.realloc64 = sqlite3_realloc64 as Routine;
+        r.result_blob64 = value::result_blob64 as Routine;
+        r.result_text64 = value::result_text64 as Routine;
+        r.value_dup = value::value_dup as Routine;
+        r.value_free = value::value_free as Routine;
+        r.result_zeroblob64 = value::result_zeroblob64 as Routine;
+        r.value_subtype = value::value_subtype as Routine;
+        r.result_subtype = value::result_subtype as Routine;
+        r.set_last_insert_rowid = sqlite3_set_last_insert_rowid as Routine;
+        r.value_pointer = value::value_pointer as Routine;
+        r.vtab_nochange = vtab::vtab_nochange as Routine;
+        r.value_nochange = value::value_false as Routine;
+        r.vtab_collation = vtab::vtab_collation as Routine;
+        r.str_new = turso_sqlite3_str_new as Routine;
+        r.str_finish = turso_sqlite3_str_finish as Routine;
+        r.str_appendf = turso_sqlite3_str_appendf as Routine;
+        r.str_vappendf = turso_sqlite3_str_vappendf as Routine;
+        r.str_append = turso_sqlite3_str_append as Routine;
+        r.str_appendall = turso_sqlite3_str_appendall as Routine;
+        r.str_appendchar = turso_sqlite3_str_appendchar as Routine;
+        r.str_reset = turso_sqlite3_str_reset as Routine;
+        r.str_errcode = turso_sqlite3_str_errcode as Routine;
+        r.str_length = turso_sqlite3_str_length as Routine;
+        r.str_value = turso_sqlite3_str_value as Routine;
+        r.create_window_function = value::create_window_function as Routine;
+        r.value_frombind = value::value_false as Routine;
+        r.changes64 = sqlite3_changes64 as Routine;
+        r.total_changes64 = sqlite3_total_changes64 as Routine;
+        r.vtab_rhs_value = vtab::vtab_rhs_value as Routine;
+        r.vtab_distinct = vtab::vtab_distinct as Routine;
+        r.vtab_in = vtab::vtab_in as Routine;
+        r.value_encoding = value::value_encoding as Routine;
+        r
+    })
+}
@@ -0,0 +1,804 @@
+//! `sqlite3_value` and `sqlite3_context`, and the routines that create functions and
+//! collations from the callbacks of an extension.
+
+use super::{free, malloc64, DestroyCallback, ExtDb, SQLITE_ERROR, SQLITE_MISUSE, SQLITE_OK};
+use crate::function::{AggregateState, FunctionFlags};
+#[cfg(feature = "json")]
+use crate::types::Text;
+use crate::util::{cast_text_to_integer, cast_text_to_real, checked_cast_text_to_numeric};
+use crate::{LimboError, Value};
+use std::cell::{Cell, OnceCell};
+use std::cmp::Ordering;
+use std::ffi::{c_char, c_int, c_uint, c_void, CStr};
+use std::rc::Rc;
+
+const SQLITE_INTEGER: c_int = 1;
+const SQLITE_FLOAT: c_int = 2;
+const SQLITE_TEXT: c_int = 3;
+const SQLITE_BLOB: c_int = 4;
+const SQLITE_NULL: c_int = 5;
+
+const SQLITE_UTF8: c_int = 1;
+const SQLITE_ANY: c_int = 5;
+const SQLITE_DETERMINISTIC: c_int = 0x800;
+/// The subtype of the JSON text values, like SQLite's JSON functions use
+#[cfg(feature = "json")]
+const JSON_SUBTYPE: c_uint = b'J' as c_uint;
+
+/// The largest blob, like SQLite's default `SQLITE_MAX_LENGTH`
+const MAX_LENGTH: usize = 1_000_000_000;
+
+/// The destructor argument meaning that the memory doesn't need to be freed
+const SQLITE_STATIC: usize = 0;
+/// The destructor argument meaning that the memory must be copied right away
+const SQLITE_TRANSIENT: usize = usize::MAX;
+
+type FunctionCallback = unsafe extern "C" fn(*mut c_void, c_int, *mut *mut c_void);
+type FinalCallback = unsafe extern "C" fn(*mut c_void);
+type CompareCallback =
+    unsafe extern "C" fn(*mut c_void, c_int, *const c_void, c_int, *const c_void) -> c_int;
+
+/// A `sqlite3_value`: a value, with the NUL-terminated text it converts to, made the first
+/// time the extension asks for it so that the pointer stays valid as long as the value.
+pub(crate) struct SqliteValue {
+    value: Value,
+    text: OnceCell<Vec<u8>>,
+}
+
+impl SqliteValue {
+    pub(crate) fn new(value: Value) -> Self {
+        Self {
+            value,
+            text: OnceCell::new(),
+        }
+    }
+
+    fn text(&self) -> &[u8] {
+        self.text.get_or_init(|| {
+            let mut text = match &self.value {
+                Value::Null => Vec::new(),
+                Value::Text(text) => text.value.clone(),
+                Value::Blob(blob) => blob.clone(),
+                value => value.to_string().into_bytes(),
+            };
+            text.push(0);
+            text
+        })
+    }
+
+    fn as_i64(&self) -> i64 {
+        match &self.value {
+            Value::Null => 0,
+            Value::Integer(i) => *i,
+            Value::Float(f) => *f as i64,
+            Value::Text(text) => integer_of(cast_text_to_integer(text.as_str())),
+            Value::Blob(blob) => integer_of(cast_text_to_integer(&String::from_utf8_lossy(blob))),
+        }
+    }
+
+    fn as_f64(&self) -> f64 {
+        match &self.value {
+            Value::Null => 0.0,
+            Value::Integer(i) => *i as f64,
+            Value::Float(f) => *f,
+            Value::Text(text) => float_of(cast_text_to_real(text.as_str())),
+            Value::Blob(blob) => float_of(cast_text_to_real(&String::from_utf8_lossy(blob))),
+        }
+    }
+}
+
+fn integer_of(value: Value) -> i64 {
+    match value {
+        Value::Integer(i) => i,
+        _ => 0,
+    }
+}
+
+fn float_of(value: Value) -> f64 {
+    match value {
+        Value::Float(f) => f,
+        Value::Integer(i) => i as f64,
+        _ => 0.0,
+    }
+}
+
+fn type_of(value: &Value) -> c_int {
+    match value {
+        Value::Null => SQLITE_NULL,
+        Value::Integer(_) => SQLITE_INTEGER,
+        Value::Float(_) => SQLITE_FLOAT,
+        Value::Text(_) => SQLITE_TEXT,
+        Value::Blob(_) => SQLITE_BLOB,
+    }
+}
+
+/// The values of the arguments of a callback, and the `sqlite3_value**` that points to them.
+pub(crate) struct SqliteArgs {
+    values: Vec<SqliteValue>,
+    pointers: Vec<*mut c_void>,
+}
+
+impl SqliteArgs {
+    pub(crate) fn new(args: impl IntoIterator<Item = Value>) -> Self {
+        let values: Vec<SqliteValue> = args.into_iter().map(SqliteValue::new).collect();
+        let pointers = values
+            .iter()
+            .map(|value| value as *const SqliteValue as *mut c_void)
+            .collect();
+        Self { values, pointers }
+    }
+
+    pub(crate) fn argc(&self) -> c_int {
+        self.values.len() as c_int
+    }
+
+    pub(crate) fn argv(&mut self) -> *mut *mut c_void {
+        self.pointers.as_mut_ptr()
+    }
+}
+
+/// A `sqlite3_context`: where a function, or the `xColumn` method of a virtual table, puts
+/// its result.
+pub(crate) struct SqliteContext<'a> {
+    db: *const ExtDb,
+    user_data: *mut c_void,
+    /// The memory of `sqlite3_aggregate_context`, for the calls of aggregate functions
+    aggregate: Option<&'a Cell<*mut c_void>>,
+    result: crate::Result<Value>,
+    #[cfg_attr(not(feature = "json"), allow(dead_code))]
+    subtype: c_uint,
+}
+
+impl<'a> SqliteContext<'a> {
+    pub(crate) fn new(
+        db: *const ExtDb,
+        user_data: *mut c_void,
+        aggregate: Option<&'a Cell<*mut c_void>>,
+    ) -> Self {
+        Self {
+            db,
+            user_data,
+            aggregate,
+            result: Ok(Value::Null),
+            subtype: 0,
+        }
+    }
+
+    pub(crate) fn as_ptr(&mut self) -> *mut c_void {
+        self as *mut Self as *mut c_void
+    }
+
+    pub(crate) fn into_result(self) -> crate::Result<Value> {
+        match self.result? {
+            #[cfg(feature = "json")]
+            Value::Text(text) if self.subtype == JSON_SUBTYPE => {
+                Ok(Value::Text(Text::json(text.as_str().to_string())))
+            }
+            value => Ok(value),
+        }
+    }
+
+    fn set(&mut self, value: Value) {
+        self.result = Ok(value);
+    }
+
+    fn set_error(&mut self, message: String) {
+        self.result = Err(LimboError::ExtensionError(message));
+    }
+}
+
+unsafe fn value<'a>(value: *mut c_void) -> Option<&'a SqliteValue> {
+    (value as *const SqliteValue).as_ref()
+}
+
+unsafe fn context<'a, 'b>(ctx: *mut c_void) -> Option<&'a mut SqliteContext<'b>> {
+    (ctx as *mut SqliteContext).as_mut()
+}
+
+/// Reads the `n` bytes at `ptr`, or up to the NUL terminator if `n` is negative.
+unsafe fn bytes<'a>(ptr: *const c_void, n: i64) -> &'a [u8] {
+    if ptr.is_null() {
+        &[]
+    } else if n < 0 {
+        CStr::from_ptr(ptr as *const c_char).to_bytes()
+    } else {
+        std::slice::from_raw_parts(ptr as *const u8, n as usize)
+    }
+}
+
+/// Releases the memory of a result passed with a destructor, once it was copied.
+unsafe fn release(ptr: *const c_void, destructor: *const c_void) {
+    match destructor as usize {
+        SQLITE_STATIC | SQLITE_TRANSIENT => {}
+        _ => {
+            let destructor: DestroyCallback = std::mem::transmute(destructor);
+            destructor(ptr as *mut c_void);
+        }
+    }
+}
+
+pub(super) unsafe extern "C" fn value_type(v: *mut c_void) -> c_int {
+    value(v).map_or(SQLITE_NULL, |v| type_of(&v.value))
+}
+
+pub(super) unsafe extern "C" fn value_numeric_type(v: *mut c_void) -> c_int {
+    match value(v).map(|v| &v.value) {
+        Some(Value::Text(text)) => match checked_cast_text_to_numeric(text.as_str()) {
+            Ok(numeric) => type_of(&numeric),
+            Err(()) => SQLITE_TEXT,
+        },
+        Some(value) => type_of(value),
+        None => SQLITE_NULL,
+    }
+}
+
+pub(super) unsafe extern "C" fn value_int(v: *mut c_void) -> c_int {
+    value(v).map_or(0, |v| v.as_i64() as c_int)
+}
+
+pub(super) unsafe extern "C" fn value_int64(v: *mut c_void) -> i64 {
+    value(v).map_or(0, SqliteValue::as_i64)
+}
+
+pub(super) unsafe extern "C" fn value_double(v: *mut c_void) -> f64 {
+    value(v).map_or(0.0, SqliteValue::as_f64)
+}
+
+pub(super) unsafe extern "C" fn value_text(v: *mut c_void) -> *const u8 {
+    match value(v) {
+        Some(v) if !matches!(v.value, Value::Null) => v.text().as_ptr(),
+        _ => std::ptr::null(),
+    }
+}
+
+pub(super) unsafe extern "C" fn value_blob(v: *mut c_void) -> *const c_void {
+    match value(v) {
+        // Empty blobs are NULL pointers, like in SQLite
+        Some(v) if value_bytes_of(v) > 0 => v.text().as_ptr() as *const c_void,
+        _ => std::ptr::null(),
+    }
+}
+
+fn value_bytes_of(v: &SqliteValue) -> usize {
+    match &v.value {
+        Value::Null => 0,
+        Value::Text(text) => text.value.len(),
+        Value::Blob(blob) => blob.len(),
+        _ => v.text().len() - 1,
+    }
+}
+
+pub(super) unsafe extern "C" fn value_bytes(v: *mut c_void) -> c_int {
+    value(v).map_or(0, |v| value_bytes_of(v) as c_int)
+}
+
+pub(super) unsafe extern "C" fn value_subtype(v: *mut c_void) -> c_uint {
+    match value(v).map(|v| &v.value) {
+        #[cfg(feature = "json")]
+        Some(Value::Text(text)) if text.subtype == crate::types::TextSubtype::Json => JSON_SUBTYPE,
+        _ => 0,
+    }
+}
+
+pub(super) unsafe extern "C" fn value_encoding(_v: *mut c_void) -> c_int {
+    SQLITE_UTF8
+}
+
+/// `sqlite3_value_nochange` and `sqlite3_value_frombind`, always false
+pub(super) unsafe extern "C" fn value_false(_v: *mut c_void) -> c_int {
+    0
+}
+
+pub(super) unsafe extern "C" fn value_dup(v: *const c_void) -> *mut c_void {
+    match value(v as *mut c_void) {
+        Some(v) => Box::into_raw(Box::new(SqliteValue::new(v.value.clone()))) as *mut c_void,
+        None => std::ptr::null_mut(),
+    }
+}
+
+pub(super) unsafe extern "C" fn value_free(v: *mut c_void) {
+    if !v.is_null() {
+        drop(Box::from_raw(v as *mut SqliteValue));
+    }
+}
+
+pub(super) unsafe extern "C" fn value_pointer(
+    _v: *mut c_void,
+    _kind: *const c_char,
+) -> *mut c_void {
+    std::ptr::null_mut()
+}
+
+pub(super) unsafe extern "C" fn result_null(ctx: *mut c_void) {
+    if let Some(ctx) = context(ctx) {
+        ctx.set(Value::Null);
+    }
+}
+
+pub(super) unsafe extern "C" fn result_int(ctx: *mut c_void, i: c_int) {
+    if let Some(ctx) = context(ctx) {
+        ctx.set(Value::Integer(i as i64));
+    }
+}
+
+pub(super) unsafe extern "C" fn result_int64(ctx: *mut c_void, i: i64) {
+    if let Some(ctx) = context(ctx) {
+        ctx.set(Value::Integer(i));
+    }
+}
+
+pub(super) unsafe extern "C" fn result_double(ctx: *mut c_void, f: f64) {
+    if let Some(ctx) = context(ctx) {
+        ctx.set(if f.is_nan() {
+            Value::Null
+        } else {
+            Value::Float(f)
+        });
+    }
+}
+
+pub(super) unsafe extern "C" fn result_text(
+    ctx: *mut c_void,
+    text: *const c_char,
+    n: c_int,
+    destructor: *const c_void,
+) {
+    set_text(ctx, text, n as i64);
+    release(text as *const c_void, destructor);
+}
+
+/// Sets the text of `n` bytes as result, or up to the NUL terminator if `n` is negative.
+unsafe fn set_text(ctx: *mut c_void, text: *const c_char, n: i64) {
+    if let Some(ctx) = context(ctx) {
+        if text.is_null() {
+            ctx.set(Value::Null);
+        } else {
+            let text = bytes(text as *const c_void, n);
+            ctx.set(Value::build_text(String::from_utf8_lossy(text)));
+        }
+    }
+}
+
+pub(super) unsafe extern "C" fn result_text64(
+    ctx: *mut c_void,
+    text: *const c_char,
+    n: u64,
+    destructor: *const c_void,
+    encoding: u8,
+) {
+    if encoding as c_int == SQLITE_UTF8 {
+        set_text(ctx, text, n.min(i64::MAX as u64) as i64);
+    } else if let Some(ctx) = context(ctx) {
+        ctx.set_error("only UTF-8 text is supported".to_string());
+    }
+    release(text as *const c_void, destructor);
+}
+
+pub(super) unsafe extern "C" fn result_blob(
+    ctx: *mut c_void,
+    blob: *const c_void,
+    n: c_int,
+    destructor: *const c_void,
+) {
+    if n < 0 {
+        if let Some(ctx) = context(ctx) {
+            ctx.set_error("negative blob size".to_string());
+        }
+        release(blob, destructor);
+        return;
+    }
+    result_blob64(ctx, blob, n as u64, destructor);
+}
+
+pub(super) unsafe extern "C" fn result_blob64(
+    ctx: *mut c_void,
+    blob: *const c_void,
+    n: u64,
+    destructor: *const c_void,
+) {
+    if let Some(ctx) = context(ctx) {
+        if blob.is_null() {
+            ctx.set(Value::Null);
+        } else {
+            let blob = bytes(blob, n.min(i64::MAX as u64) as i64);
+            ctx.set(Value::Blob(blob.to_vec()));
+        }
+    }
+    release(blob, destructor);
+}
+
+pub(super) unsafe extern "C" fn result_zeroblob(ctx: *mut c_void, n: c_int) {
+    result_zeroblob64(ctx, n.max(0) as u64);
+}
+
+pub(super) unsafe extern "C" fn result_zeroblob64(ctx: *mut c_void, n: u64) -> c_int {
+    let Some(ctx) = context(ctx) else {
+        return SQLITE_MISUSE;
+    };
+    match usize::try_from(n) {
+        Ok(n) if n <= MAX_LENGTH => {
+            ctx.set(Value::Blob(vec![0; n]));
+            SQLITE_OK
+        }
+        _ => {
+            ctx.set_error("string or blob too big".to_string());
+            super::SQLITE_TOOBIG
+        }
+    }
+}
+
+pub(super) unsafe extern "C" fn result_value(ctx: *mut c_void, v: *mut c_void) {
+    if let Some(ctx) = context(ctx) {
+        ctx.set(value(v).map_or(Value::Null, |v| v.value.clone()));
+    }
+}
+
+pub(super) unsafe extern "C" fn result_subtype(ctx: *mut c_void, subtype: c_uint) {
+    if let Some(ctx) = context(ctx) {
+        ctx.subtype = subtype;
+    }
+}
+
+pub(super) unsafe extern "C" fn result_error(ctx: *mut c_void, message: *const c_char, n: c_int) {
+    if let Some(ctx) = context(ctx) {
+        let message = bytes(message as *const c_void, n as i64);
+        ctx.set_error(String::from_utf8_lossy(message).into_owned());
+    }
+}
+
+pub(super) unsafe extern "C" fn result_error_code(ctx: *mut c_void, code: c_int) {
+    if let Some(ctx) = context(ctx) {
+        // Keep the message set by `sqlite3_result_error`, if any
+        if ctx.result.is_ok() {
+            ctx.set_error(super::error_string(code).to_string_lossy().into_owned());
+        }
+    }
+}
+
+pub(super) unsafe extern "C" fn result_error_nomem(ctx: *mut c_void) {
+    if let Some(ctx) = context(ctx) {
+        ctx.set_error("out of memory".to_string());
+    }
+}
+
+pub(super) unsafe extern "C" fn result_error_toobig(ctx: *mut c_void) {
+    if let Some(ctx) = context(ctx) {
+        ctx.set_error("string or blob too big".to_string());
+    }
+}
+
+pub(super) unsafe extern "C" fn user_data(ctx: *mut c_void) -> *mut c_void {
+    context(ctx).map_or(std::ptr::null_mut(), |ctx| ctx.user_data)
+}
+
+pub(super) unsafe extern "C" fn context_db_handle(ctx: *mut c_void) -> *mut c_void {
+    context(ctx).map_or(std::ptr::null_mut(), |ctx| ctx.db as *mut c_void)
+}
+
+pub(super) unsafe extern "C" fn aggregate_context(ctx: *mut c_void, n: c_int) -> *mut c_void {
+    let Some(memory) = context(ctx).and_then(|ctx| ctx.aggregate) else {
+        return std::ptr::null_mut();
+    };
+    if memory.get().is_null() && n > 0 {
+        let ptr = malloc64(n as u64);
+        if !ptr.is_null() {
+            std::ptr::write_bytes(ptr as *mut u8, 0, n as usize);
+        }
+        memory.set(ptr);
+    }
+    memory.get()
+}
+
+/// Auxiliary data is never kept, which SQLite allows: it may discard it at any time.
+pub(super) unsafe extern "C" fn get_auxdata(_ctx: *mut c_void, _arg: c_int) -> *mut c_void {
+    std::ptr::null_mut()
+}
+
+pub(super) unsafe extern "C" fn set_auxdata(
+    _ctx: *mut c_void,
+    _arg: c_int,
+    data: *mut c_void,
+    destructor: Option<DestroyCallback>,
+) {
+    if let Some(destructor) = destructor {
+        destructor(data);
+    }
+}
+
+/// The callbacks a function of an extension was created with.
+struct ExtFunction {
+    db: Rc<ExtDb>,
+    user_data: *mut c_void,
+    func: Option<FunctionCallback>,
+    step: Option<FunctionCallback>,
+    finalize: Option<FinalCallback>,
+    value: Option<FinalCallback>,
+    inverse: Option<FunctionCallback>,
+    destroy: Option<DestroyCallback>,
+}
+
+impl ExtFunction {
+    fn call(
+        &self,
+        callback: FunctionCallback,
+        args: &[Value],
+        aggregate: Option<&Cell<*mut c_void>>,
+    ) -> crate::Result<Value> {
+        let mut args = SqliteArgs::new(args.iter().cloned());
+        let mut ctx = SqliteContext::new(Rc::as_ptr(&self.db), self.user_data, aggregate);
+        unsafe { callback(ctx.as_ptr(), args.argc(), args.argv()) };
+        ctx.into_result()
+    }
+
+    fn call_final(
+        &self,
+        callback: FinalCallback,
+        aggregate: &Cell<*mut c_void>,
+    ) -> crate::Result<Value> {
+        let mut ctx = SqliteContext::new(Rc::as_ptr(&self.db), self.user_data, Some(aggregate));
+        unsafe { callback(ctx.as_ptr()) };
+        ctx.into_result()
+    }
+}
+
+impl Drop for ExtFunction {
+    fn drop(&mut self) {
+        if let Some(destroy) = self.destroy {
+            unsafe { destroy(self.user_data) };
+        }
+    }
+}
+
+/// The state of an aggregate function of an extension for a group of rows: the memory
+/// the function got from `sqlite3_aggregate_context`.
+struct ExtAggregate {
+    function: Rc<ExtFunction>,
+    memory: Cell<*mut c_void>,
+}
+
+impl AggregateState for ExtAggregate {
+    fn step(&mut self, args: &[Value]) -> crate::Result<()> {
+        let step = self.function.step.ok_or_else(missing_callback)?;
+        self.function.call(step, args, Some(&self.memory))?;
+        Ok(())
+    }
+
+    fn finalize(&mut self) -> crate::Result<Value> {
+        let finalize = self.function.finalize.ok_or_else(missing_callback)?;
+        self.function.call_final(finalize, &self.memory)
+    }
+
+    fn value(&self) -> crate::Result<Value> {
+        let value = self.function.value.ok_or_else(missing_callback)?;
+        self.function.call_final(value, &self.memory)
+    }
+
+    fn inverse(&mut self, args: &[Value]) -> crate::Result<()> {
+        let inverse = self.function.inverse.ok_or_else(missing_callback)?;
+        self.function.call(inverse, args, Some(&self.memory))?;
+        Ok(())
+    }
+}
+
+impl Drop for ExtAggregate {
+    fn drop(&mut self) {
+        unsafe { free(self.memory.get()) };
+    }
+}
+
+fn missing_callback() -> LimboError {
+    LimboError::InternalError("function callback is missing".to_string())
+}
+
+unsafe fn name<'a>(name: *const c_char) -> Option<&'a str> {
+    if name.is_null() {
+        return None;
+    }
+    CStr::from_ptr(name).to_str().ok()
+}
+
+/// Creates, or deletes if it has no callbacks, a function of the connection of the extension.
+unsafe fn create_function(
+    func_name: *const c_char,
+    n_arg: c_int,
+    text_rep: c_int,
+    function: ExtFunction,
+) -> c_int {
+    let (Some(name), Some(conn)) = (name(func_name), function.db.connection()) else {
+        return SQLITE_MISUSE;
+    };
+    if !(-1..=127).contains(&n_arg) {
+        return SQLITE_MISUSE;
+    }
+    let nargs = usize::try_from(n_arg).ok();
+    let flags = if text_rep & SQLITE_DETERMINISTIC != 0 {
+        FunctionFlags::DETERMINISTIC
+    } else {
+        FunctionFlags::empty()
+    };
+    let is_scalar = function.func.is_some();
+    let is_aggregate = function.step.is_some() && function.finalize.is_some();
+    let is_window = function.value.is_some() && function.inverse.is_some();
+    let has_window_callbacks = function.value.is_some() || function.inverse.is_some();
+    match (is_scalar, is_aggregate) {
+        (true, false) if function.step.is_none() && function.finalize.is_none() => {
+            let func = function.func.expect("scalar function has a callback");
+            let function = Rc::new(function);
+            conn.create_scalar_function(
+                name,
+                nargs,
+                flags,
+                Box::new(move |args: &[Value]| function.call(func, args, None)),
+            );
+        }
+        (false, true) if is_window || !has_window_callbacks => {
+            let function = Rc::new(function);
+            let init = Box::new(move || {
+                Box::new(ExtAggregate {
+                    function: function.clone(),
+                    memory: Cell::new(std::ptr::null_mut()),
+                }) as Box<dyn AggregateState>
+            });
+            if is_window {
+                conn.create_window_function(name, nargs, flags, init);
+            } else {
+                conn.create_aggregate_function(name, nargs, flags, init);
+            }
+        }
+        (false, false)
+            if function.step.is_none() && function.finalize.is_none() && !has_window_callbacks =>
+        {
+            conn.syms
+                .borrow_mut()
+                .functions
+                .remove(&crate::util::normalize_ident(name));
+        }
+        _ => return SQLITE_MISUSE,
+    }
+    SQLITE_OK
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) unsafe extern "C" fn create_function_v2(
+    db: *mut c_void,
+    func_name: *const c_char,
+    n_arg: c_int,
+    text_rep: c_int,
+    user_data: *mut c_void,
+    func: Option<FunctionCallback>,
+    step: Option<FunctionCallback>,
+    finalize: Option<FinalCallback>,
+    destroy: Option<DestroyCallback>,
+) -> c_int {
+    let Some(db) = ExtDb::from_ptr(db) else {
+        return SQLITE_MISUSE;
+    };
+    let function = ExtFunction {
+        db,
+        user_data,
+        func,
+        step,
+        finalize,
+        value: None,
+        inverse: None,
+        destroy,
+    };
+    create_function(func_name, n_arg, text_rep, function)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) unsafe extern "C" fn create_function_v1(
+    db: *mut c_void,
+    func_name: *const c_char,
+    n_arg: c_int,
+    text_rep: c_int,
+    user_data: *mut c_void,
+    func: Option<FunctionCallback>,
+    step: Option<FunctionCallback>,
+    finalize: Option<FinalCallback>,
+) -> c_int {
+    create_function_v2(
+        db, func_name, n_arg, text_rep, user_data, func, step, finalize, None,
+    )
+}
+
+#[allow(clippy::too_many_arguments)]
+pub(super) unsafe extern "C" fn create_window_function(
+    db: *mut c_void,
+    func_name: *const c_char,
+    n_arg: c_int,
+    text_rep: c_int,
+    user_data: *mut c_void,
+    step: Option<FunctionCallback>,
+    finalize: Option<FinalCallback>,
+    value: Option<FinalCallback>,
+    inverse: Option<FunctionCallback>,
+    destroy: Option<DestroyCallback>,
+) -> c_int {
+    let Some(db) = ExtDb::from_ptr(db) else {
+        return SQLITE_MISUSE;
+    };
+    let function = ExtFunction {
+        db,
+        user_data,
+        func: None,
+        step,
+        finalize,
+        value,
+        inverse,
+        destroy,
+    };
+    create_function(func_name, n_arg, text_rep, function)
+}
+
+/// The callback of a collation of an extension, and what it was created with.
+struct ExtCollation {
+    arg: *mut c_void,
+    compare: CompareCallback,
+    destroy: Option<DestroyCallback>,
+}
+
+impl ExtCollation {
+    fn compare(&self, a: &str, b: &str) -> Ordering {
+        let result = unsafe {
+            (self.compare)(
+                self.arg,
+                a.len() as c_int,
+                a.as_ptr() as *const c_void,
+                b.len() as c_int,
+                b.as_ptr() as *const c_void,
+            )
+        };
+        result.cmp(&0)
+    }
+}
+
+impl Drop for ExtCollation {
+    fn drop(&mut self) {
+        if let Some(destroy) = self.destroy {
+            unsafe { destroy(self.arg) };
+        }
+    }
+}
+
+pub(super) unsafe extern "C" fn create_collation_v2(
+    db: *mut c_void,
+    collation_name: *const c_char,
+    text_rep: c_int,
+    arg: *mut c_void,
+    compare: Option<CompareCallback>,
+    destroy: Option<DestroyCallback>,
+) -> c_int {
+    let collation = compare.map(|compare| ExtCollation {
+        arg,
+        compare,
+        destroy,
+    });
+    let (Some(db), Some(name)) = (ExtDb::from_ptr(db), name(collation_name)) else {
+        return SQLITE_MISUSE;
+    };
+    let Some(conn) = db.connection() else {
+        return SQLITE_MISUSE;
+    };
+    // Strings are always compared as UTF-8
+    if !matches!(text_rep & 0x7, SQLITE_UTF8 | SQLITE_ANY) {
+        return SQLITE_ERROR;
+    }
+    let collation = collation.map(|collation| {
+        Box::new(move |a: &str, b: &str| collation.compare(a, b)) as crate::CollationFn
+    });
+    match conn.create_collation(name, collation) {
+        Ok(()) => SQLITE_OK,
+        Err(_) => SQLITE_ERROR,
+    }
+}
+
+pub(super) unsafe extern "C" fn create_collation(
+    db: *mut c_void,
+    collation_name: *const c_char,
+    text_rep: c_int,
+    arg: *mut c_void,
+    compare: Option<CompareCallback>,
+) -> c_int {
+    create_collation_v2(db, collation_name, text_rep, arg, compare, None)
+}
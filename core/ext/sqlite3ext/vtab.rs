@@ -0,0 +1,686 @@
+//! Virtual table modules of the extensions: `sqlite3_module` and its tables and cursors.
+
+use super::value::{SqliteArgs, SqliteContext};
+use super::{free, take_message, DestroyCallback, ExtDb, SQLITE_ERROR, SQLITE_MISUSE, SQLITE_OK};
+use crate::ext::{VTabImpl, VTabModule};
+use crate::vtab::{VTabSavepointOp, VTabTransactionOp};
+use crate::{LimboError, Value};
+use std::cell::Cell;
+use std::ffi::{c_char, c_int, c_void, CStr, CString};
+use std::rc::Rc;
+use turso_ext::{ConstraintInfo, ConstraintOp, ConstraintUsage, IndexInfo, OrderByInfo, VTabKind};
+
+const SQLITE_ABORT: c_int = 4;
+const SQLITE_NOTFOUND: c_int = 12;
+
+const SQLITE_INDEX_CONSTRAINT_EQ: u8 = 2;
+const SQLITE_INDEX_CONSTRAINT_GT: u8 = 4;
+const SQLITE_INDEX_CONSTRAINT_LE: u8 = 8;
+const SQLITE_INDEX_CONSTRAINT_LT: u8 = 16;
+const SQLITE_INDEX_CONSTRAINT_GE: u8 = 32;
+
+type XConnect = unsafe extern "C" fn(
+    *mut c_void,
+    *mut c_void,
+    c_int,
+    *const *const c_char,
+    *mut *mut Sqlite3Vtab,
+    *mut *mut c_char,
+) -> c_int;
+type XVtab = unsafe extern "C" fn(*mut Sqlite3Vtab) -> c_int;
+type XCursor = unsafe extern "C" fn(*mut Sqlite3VtabCursor) -> c_int;
+
+/// `sqlite3_module`
+#[repr(C)]
+#[allow(dead_code)] // The methods that aren't supported are never read
+pub(crate) struct Sqlite3Module {
+    version: c_int,
+    create: Option<XConnect>,
+    connect: Option<XConnect>,
+    best_index: Option<unsafe extern "C" fn(*mut Sqlite3Vtab, *mut Sqlite3IndexInfo) -> c_int>,
+    disconnect: Option<XVtab>,
+    destroy: Option<XVtab>,
+    open: Option<unsafe extern "C" fn(*mut Sqlite3Vtab, *mut *mut Sqlite3VtabCursor) -> c_int>,
+    close: Option<XCursor>,
+    filter: Option<
+        unsafe extern "C" fn(
+            *mut Sqlite3VtabCursor,
+            c_int,
+            *const c_char,
+            c_int,
+            *mut *mut c_void,
+        ) -> c_int,
+    >,
+    next: Option<XCursor>,
+    eof: Option<XCursor>,
+    column: Option<unsafe extern "C" fn(*mut Sqlite3VtabCursor, *mut c_void, c_int) -> c_int>,
+    rowid: Option<unsafe extern "C" fn(*mut Sqlite3VtabCursor, *mut i64) -> c_int>,
+    update:
+        Option<unsafe extern "C" fn(*mut Sqlite3Vtab, c_int, *mut *mut c_void, *mut i64) -> c_int>,
+    begin: Option<XVtab>,
+    sync: Option<XVtab>,
+    commit: Option<XVtab>,
+    rollback: Option<XVtab>,
+    find_function: *const c_void,
+    rename: *const c_void,
+    // Version 2 and later
+    savepoint: Option<unsafe extern "C" fn(*mut Sqlite3Vtab, c_int) -> c_int>,
+    release: Option<unsafe extern "C" fn(*mut Sqlite3Vtab, c_int) -> c_int>,
+    rollback_to: Option<unsafe extern "C" fn(*mut Sqlite3Vtab, c_int) -> c_int>,
+    // Version 3 and later
+    shadow_name: *const c_void,
+    // Version 4 and later
+    integrity: *const c_void,
+}
+
+/// `sqlite3_vtab`, the start of the tables the modules allocate
+#[repr(C)]
+#[allow(dead_code)] // Some fields are only read by the modules
+pub(crate) struct Sqlite3Vtab {
+    module: *const Sqlite3Module,
+    n_ref: c_int,
+    err_msg: *mut c_char,
+}
+
+/// `sqlite3_vtab_cursor`, the start of the cursors the modules allocate
+#[repr(C)]
+#[allow(dead_code)] // Only read by the modules
+pub(crate) struct Sqlite3VtabCursor {
+    vtab: *mut Sqlite3Vtab,
+}
+
+#[repr(C)]
+#[allow(dead_code)] // Some fields are only read by the modules
+struct IndexConstraint {
+    column: c_int,
+    op: u8,
+    usable: u8,
+    term_offset: c_int,
+}
+
+#[repr(C)]
+#[allow(dead_code)] // Only read by the modules
+struct IndexOrderBy {
+    column: c_int,
+    desc: u8,
+}
+
+#[repr(C)]
+struct IndexConstraintUsage {
+    argv_index: c_int,
+    omit: u8,
+}
+
+/// `sqlite3_index_info`
+#[repr(C)]
+#[allow(dead_code)] // Some fields are only read by the modules
+pub(crate) struct Sqlite3IndexInfo {
+    n_constraint: c_int,
+    constraints: *const IndexConstraint,
+    n_order_by: c_int,
+    order_by: *const IndexOrderBy,
+    constraint_usages: *mut IndexConstraintUsage,
+    idx_num: c_int,
+    idx_str: *mut c_char,
+    need_to_free_idx_str: c_int,
+    order_by_consumed: c_int,
+    estimated_cost: f64,
+    estimated_rows: i64,
+    idx_flags: c_int,
+    col_used: u64,
+}
+
+/// A module created with `sqlite3_create_module_v2`.
+pub struct SqliteModule {
+    db: Rc<ExtDb>,
+    name: String,
+    module: *const Sqlite3Module,
+    aux: *mut c_void,
+    destroy: Option<DestroyCallback>,
+}
+
+impl SqliteModule {
+    /// Modules without `xCreate` are eponymous-only: they can only be used as table-valued
+    /// functions.
+    fn kind(&self) -> VTabKind {
+        if self.methods().create.is_none() {
+            VTabKind::TableValuedFunction
+        } else {
+            VTabKind::VirtualTable
+        }
+    }
+
+    fn methods(&self) -> &Sqlite3Module {
+        unsafe { &*self.module }
+    }
+}
+
+impl Drop for SqliteModule {
+    fn drop(&mut self) {
+        if let Some(destroy) = self.destroy {
+            unsafe { destroy(self.aux) };
+        }
+    }
+}
+
+pub(super) unsafe extern "C" fn create_module_v2(
+    db: *mut c_void,
+    name: *const c_char,
+    module: *const Sqlite3Module,
+    aux: *mut c_void,
+    destroy: Option<DestroyCallback>,
+) -> c_int {
+    let (Some(db), false) = (ExtDb::from_ptr(db), name.is_null()) else {
+        return SQLITE_MISUSE;
+    };
+    let Ok(name) = CStr::from_ptr(name).to_str() else {
+        return SQLITE_MISUSE;
+    };
+    let Some(conn) = db.connection() else {
+        return SQLITE_MISUSE;
+    };
+    if module.is_null() {
+        if let Some(destroy) = destroy {
+            destroy(aux);
+        }
+        conn.syms.borrow_mut().vtab_modules.remove(name);
+        return SQLITE_OK;
+    }
+    let module = Rc::new(SqliteModule {
+        db: db.clone(),
+        name: name.to_string(),
+        module,
+        aux,
+        destroy,
+    });
+    let module = VTabImpl {
+        module_kind: module.kind(),
+        implementation: VTabModule::Sqlite(module),
+    };
+    match conn.add_vtab_module(name, module) {
+        Ok(()) => SQLITE_OK,
+        Err(e) => {
+            db.set_error(SQLITE_ERROR, &e.to_string());
+            SQLITE_ERROR
+        }
+    }
+}
+
+pub(super) unsafe extern "C" fn create_module(
+    db: *mut c_void,
+    name: *const c_char,
+    module: *const Sqlite3Module,
+    aux: *mut c_void,
+) -> c_int {
+    create_module_v2(db, name, module, aux, None)
+}
+
+pub(super) unsafe extern "C" fn declare_vtab(db: *mut c_void, sql: *const c_char) -> c_int {
+    let (Some(db), false) = (ExtDb::from_ptr(db), sql.is_null()) else {
+        return SQLITE_MISUSE;
+    };
+    let sql = CStr::from_ptr(sql).to_string_lossy().into_owned();
+    if !sql
+        .trim_start()
+        .get(..6)
+        .is_some_and(|create| create.eq_ignore_ascii_case("create"))
+    {
+        db.set_error(
+            SQLITE_ERROR,
+            &format!("vtable declaration is not a CREATE TABLE: {sql}"),
+        );
+        return SQLITE_ERROR;
+    }
+    *db.declared_schema.borrow_mut() = Some(sql);
+    SQLITE_OK
+}
+
+pub(super) unsafe extern "C" fn vtab_on_conflict(_db: *mut c_void) -> c_int {
+    SQLITE_ABORT
+}
+
+pub(super) unsafe extern "C" fn vtab_nochange(_ctx: *mut c_void) -> c_int {
+    0
+}
+
+pub(super) unsafe extern "C" fn vtab_collation(
+    _info: *mut Sqlite3IndexInfo,
+    _constraint: c_int,
+) -> *const c_char {
+    c"BINARY".as_ptr()
+}
+
+pub(super) unsafe extern "C" fn vtab_distinct(_info: *mut Sqlite3IndexInfo) -> c_int {
+    0
+}
+
+/// IN constraints are never passed to the modules, so none can be processed all at once.
+pub(super) unsafe extern "C" fn vtab_in(
+    _info: *mut Sqlite3IndexInfo,
+    _constraint: c_int,
+    _handle: c_int,
+) -> c_int {
+    0
+}
+
+pub(super) unsafe extern "C" fn vtab_rhs_value(
+    _info: *mut Sqlite3IndexInfo,
+    _constraint: c_int,
+    value: *mut *mut c_void,
+) -> c_int {
+    if !value.is_null() {
+        *value = std::ptr::null_mut();
+    }
+    SQLITE_NOTFOUND
+}
+
+/// A table of a module of an extension, created with `xCreate` or `xConnect`.
+pub struct SqliteVirtualTable {
+    module: Rc<SqliteModule>,
+    name: String,
+    vtab: *mut Sqlite3Vtab,
+    /// Whether `xDestroy` was called, after which the table must not be disconnected
+    destroyed: Cell<bool>,
+}
+
+impl std::fmt::Debug for SqliteVirtualTable {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SqliteVirtualTable")
+            .field("module", &self.module.name)
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SqliteVirtualTable {
+    /// Creates the table `name` of `module` with `xCreate` if `create`, or connects to it with
+    /// `xConnect`. Returns it with the schema it declared.
+    pub(crate) fn connect(
+        module: &Rc<SqliteModule>,
+        name: &str,
+        args: &[String],
+        create: bool,
+    ) -> crate::Result<(Rc<Self>, String)> {
+        let methods = module.methods();
+        let constructor = if create {
+            methods.create
+        } else {
+            methods.connect
+        };
+        let constructor = constructor.ok_or_else(|| {
+            LimboError::ExtensionError(format!("module {} can't create tables", module.name))
+        })?;
+        let argv = [module.name.as_str(), "main", name]
+            .into_iter()
+            .chain(args.iter().map(String::as_str))
+            .map(|arg| CString::new(arg.replace('\0', "")).expect("NULs are removed"))
+            .collect::<Vec<_>>();
+        let argv_ptrs = argv.iter().map(|arg| arg.as_ptr()).collect::<Vec<_>>();
+        let mut vtab: *mut Sqlite3Vtab = std::ptr::null_mut();
+        let mut message: *mut c_char = std::ptr::null_mut();
+        module.db.declared_schema.replace(None);
+        let rc = unsafe {
+            constructor(
+                module.db.as_ptr(),
+                module.aux,
+                argv_ptrs.len() as c_int,
+                argv_ptrs.as_ptr(),
+                &mut vtab,
+                &mut message,
+            )
+        };
+        let message = unsafe { take_message(message) };
+        let schema = module.db.declared_schema.take();
+        if rc != SQLITE_OK || vtab.is_null() {
+            return Err(LimboError::ExtensionError(
+                message.unwrap_or_else(|| format!("vtable constructor failed: {name}")),
+            ));
+        }
+        unsafe {
+            (*vtab).module = module.module;
+            (*vtab).err_msg = std::ptr::null_mut();
+        }
+        let table = Rc::new(Self {
+            module: module.clone(),
+            name: name.to_string(),
+            vtab,
+            destroyed: Cell::new(false),
+        });
+        let schema = schema.ok_or_else(|| {
+            LimboError::ExtensionError(format!("vtable constructor did not declare schema: {name}"))
+        })?;
+        Ok((table, schema))
+    }
+
+    /// Returns the error of a failed method: the message the module set on the table, if any.
+    fn error(&self, rc: c_int, method: &str) -> LimboError {
+        let message = unsafe {
+            let message = (*self.vtab).err_msg;
+            (*self.vtab).err_msg = std::ptr::null_mut();
+            take_message(message)
+        };
+        LimboError::ExtensionError(message.unwrap_or_else(|| {
+            format!(
+                "{method} failed: {}",
+                super::error_string(rc).to_string_lossy()
+            )
+        }))
+    }
+
+    pub(crate) fn best_index(
+        &self,
+        constraints: &[ConstraintInfo],
+        order_by: &[OrderByInfo],
+    ) -> IndexInfo {
+        let unused = || IndexInfo {
+            constraint_usages: vec![
+                ConstraintUsage {
+                    argv_index: None,
+                    omit: false,
+                };
+                constraints.len()
+            ],
+            ..Default::default()
+        };
+        let Some(best_index) = self.module.methods().best_index else {
+            return unused();
+        };
+        let index_constraints = constraints
+            .iter()
+            .map(|constraint| {
+                let op = match constraint.op {
+                    ConstraintOp::Eq | ConstraintOp::In => SQLITE_INDEX_CONSTRAINT_EQ,
+                    ConstraintOp::Lt => SQLITE_INDEX_CONSTRAINT_LT,
+                    ConstraintOp::Le => SQLITE_INDEX_CONSTRAINT_LE,
+                    ConstraintOp::Gt => SQLITE_INDEX_CONSTRAINT_GT,
+                    ConstraintOp::Ge => SQLITE_INDEX_CONSTRAINT_GE,
+                    // The other operators have the same codes
+                    op => op as u8,
+                };
+                IndexConstraint {
+                    column: constraint.column_index as c_int,
+                    op,
+                    // The values of IN lists can't be passed to xFilter
+                    usable: (constraint.usable && constraint.op != ConstraintOp::In) as u8,
+                    term_offset: 0,
+                }
+            })
+            .collect::<Vec<_>>();
+        let index_order_by = order_by
+            .iter()
+            .map(|order_by| IndexOrderBy {
+                column: order_by.column_index as c_int,
+                desc: order_by.desc as u8,
+            })
+            .collect::<Vec<_>>();
+        let mut usages = index_constraints
+            .iter()
+            .map(|_| IndexConstraintUsage {
+                argv_index: 0,
+                omit: 0,
+            })
+            .collect::<Vec<_>>();
+        let mut info = Sqlite3IndexInfo {
+            n_constraint: index_constraints.len() as c_int,
+            constraints: index_constraints.as_ptr(),
+            n_order_by: index_order_by.len() as c_int,
+            order_by: index_order_by.as_ptr(),
+            constraint_usages: usages.as_mut_ptr(),
+            idx_num: 0,
+            idx_str: std::ptr::null_mut(),
+            need_to_free_idx_str: 0,
+            order_by_consumed: 0,
+            estimated_cost: 5e98,
+            estimated_rows: 25,
+            idx_flags: 0,
+            col_used: u64::MAX,
+        };
+        let rc = unsafe { best_index(self.vtab, &mut info) };
+        let idx_str = (!info.idx_str.is_null())
+            .then(|| unsafe { CStr::from_ptr(info.idx_str).to_string_lossy().into_owned() });
+        if info.need_to_free_idx_str != 0 {
+            unsafe { free(info.idx_str as *mut c_void) };
+        }
+        if rc != SQLITE_OK {
+            tracing::error!("{}", self.error(rc, "xBestIndex"));
+            return unused();
+        }
+        // The arguments of xFilter must be numbered from 1 without gaps, and only come from
+        // usable constraints
+        let mut argv_indexes = usages
+            .iter()
+            .filter(|usage| usage.argv_index > 0)
+            .map(|usage| usage.argv_index)
+            .collect::<Vec<_>>();
+        argv_indexes.sort_unstable();
+        let well_formed = argv_indexes
+            .iter()
+            .enumerate()
+            .all(|(i, argv_index)| *argv_index == i as c_int + 1)
+            && usages
+                .iter()
+                .zip(&index_constraints)
+                .all(|(usage, constraint)| usage.argv_index <= 0 || constraint.usable != 0);
+        if !well_formed {
+            tracing::error!("xBestIndex malfunction in {}", self.name);
+            return unused();
+        }
+        IndexInfo {
+            idx_num: info.idx_num,
+            idx_str,
+            order_by_consumed: info.order_by_consumed != 0,
+            estimated_cost: info.estimated_cost,
+            estimated_rows: info.estimated_rows.clamp(0, u32::MAX as i64) as u32,
+            constraint_usages: usages
+                .iter()
+                .map(|usage| ConstraintUsage {
+                    argv_index: (usage.argv_index > 0).then_some(usage.argv_index as u32),
+                    omit: usage.omit != 0,
+                })
+                .collect(),
+        }
+    }
+
+    pub(crate) fn open(self: &Rc<Self>) -> crate::Result<SqliteVirtualTableCursor> {
+        let open = self
+            .module
+            .methods()
+            .open
+            .ok_or_else(|| LimboError::ExtensionError("xOpen is missing".to_string()))?;
+        let mut cursor: *mut Sqlite3VtabCursor = std::ptr::null_mut();
+        let rc = unsafe { open(self.vtab, &mut cursor) };
+        if rc != SQLITE_OK || cursor.is_null() {
+            return Err(self.error(rc, "xOpen"));
+        }
+        unsafe { (*cursor).vtab = self.vtab };
+        Ok(SqliteVirtualTableCursor {
+            table: self.clone(),
+            cursor,
+        })
+    }
+
+    /// Calls `xUpdate` with the arguments of `VUpdate`: the old rowid, the new rowid and the
+    /// columns, or only the old rowid and a NULL to delete a row.
+    pub(crate) fn update(&self, args: &[Value]) -> crate::Result<Option<i64>> {
+        let update = self.module.methods().update.ok_or_else(|| {
+            LimboError::ExtensionError(format!("table {} may not be modified", self.name))
+        })?;
+        let is_delete = args.len() == 2 && args[1] == Value::Null;
+        let is_insert = args.first().is_some_and(|rowid| *rowid == Value::Null);
+        let args = if is_delete { &args[..1] } else { args };
+        let mut args = SqliteArgs::new(args.iter().cloned());
+        let mut rowid = 0;
+        let rc = unsafe { update(self.vtab, args.argc(), args.argv(), &mut rowid) };
+        if rc != SQLITE_OK {
+            return Err(self.error(rc, "xUpdate"));
+        }
+        Ok(is_insert.then_some(rowid))
+    }
+
+    /// Calls one of `xBegin`, `xSync`, `xCommit` and `xRollback`, if the module has it.
+    pub(crate) fn transaction(&self, op: VTabTransactionOp) -> crate::Result<()> {
+        let methods = self.module.methods();
+        let (method, name) = match op {
+            VTabTransactionOp::Begin => (methods.begin, "xBegin"),
+            VTabTransactionOp::Sync => (methods.sync, "xSync"),
+            VTabTransactionOp::Commit => (methods.commit, "xCommit"),
+            VTabTransactionOp::Rollback => (methods.rollback, "xRollback"),
+        };
+        let Some(method) = method else {
+            return Ok(());
+        };
+        let rc = unsafe { method(self.vtab) };
+        if rc != SQLITE_OK {
+            return Err(self.error(rc, name));
+        }
+        Ok(())
+    }
+
+    /// Calls one of `xSavepoint`, `xRelease` and `xRollbackTo`, which only modules of version 2
+    /// and later have.
+    pub(crate) fn savepoint(&self, op: VTabSavepointOp, savepoint: usize) -> crate::Result<()> {
+        let methods = self.module.methods();
+        if methods.version < 2 {
+            return Ok(());
+        }
+        let (method, name) = match op {
+            VTabSavepointOp::Begin => (methods.savepoint, "xSavepoint"),
+            VTabSavepointOp::Release => (methods.release, "xRelease"),
+            VTabSavepointOp::RollbackTo => (methods.rollback_to, "xRollbackTo"),
+        };
+        let Some(method) = method else {
+            return Ok(());
+        };
+        let rc = unsafe { method(self.vtab, savepoint as c_int) };
+        if rc != SQLITE_OK {
+            return Err(self.error(rc, name));
+        }
+        Ok(())
+    }
+
+    pub(crate) fn destroy(&self) -> crate::Result<()> {
+        let Some(destroy) = self.module.methods().destroy else {
+            return Ok(());
+        };
+        let rc = unsafe { destroy(self.vtab) };
+        if rc != SQLITE_OK {
+            return Err(self.error(rc, "xDestroy"));
+        }
+        self.destroyed.set(true);
+        Ok(())
+    }
+}
+
+impl Drop for SqliteVirtualTable {
+    fn drop(&mut self) {
+        if self.destroyed.get() {
+            return;
+        }
+        if let Some(disconnect) = self.module.methods().disconnect {
+            let rc = unsafe { disconnect(self.vtab) };
+            if rc != SQLITE_OK {
+                tracing::error!("failed to disconnect virtual table {}", self.name);
+            }
+        }
+    }
+}
+
+/// A cursor over a table of a module of an extension.
+pub struct SqliteVirtualTableCursor {
+    table: Rc<SqliteVirtualTable>,
+    cursor: *mut Sqlite3VtabCursor,
+}
+
+impl SqliteVirtualTableCursor {
+    fn methods(&self) -> &Sqlite3Module {
+        self.table.module.methods()
+    }
+
+    fn check(&self, rc: c_int, method: &str) -> crate::Result<()> {
+        if rc == SQLITE_OK {
+            Ok(())
+        } else {
+            Err(self.table.error(rc, method))
+        }
+    }
+
+    fn eof(&self) -> bool {
+        match self.methods().eof {
+            Some(eof) => unsafe { eof(self.cursor) != 0 },
+            None => true,
+        }
+    }
+
+    pub(crate) fn filter(
+        &mut self,
+        idx_num: i32,
+        idx_str: Option<String>,
+        args: Vec<Value>,
+    ) -> crate::Result<bool> {
+        let filter = self
+            .methods()
+            .filter
+            .ok_or_else(|| LimboError::ExtensionError("xFilter is missing".to_string()))?;
+        let idx_str = idx_str.map(|s| CString::new(s.replace('\0', "")).expect("NULs are removed"));
+        let mut args = SqliteArgs::new(args);
+        let rc = unsafe {
+            filter(
+                self.cursor,
+                idx_num,
+                idx_str.as_ref().map_or(std::ptr::null(), |s| s.as_ptr()),
+                args.argc(),
+                args.argv(),
+            )
+        };
+        self.check(rc, "xFilter")?;
+        Ok(!self.eof())
+    }
+
+    pub(crate) fn next(&mut self) -> crate::Result<bool> {
+        let next = self
+            .methods()
+            .next
+            .ok_or_else(|| LimboError::ExtensionError("xNext is missing".to_string()))?;
+        let rc = unsafe { next(self.cursor) };
+        self.check(rc, "xNext")?;
+        Ok(!self.eof())
+    }
+
+    pub(crate) fn rowid(&self) -> i64 {
+        let Some(rowid_method) = self.methods().rowid else {
+            return 0;
+        };
+        let mut rowid = 0;
+        let rc = unsafe { rowid_method(self.cursor, &mut rowid) };
+        if let Err(e) = self.check(rc, "xRowid") {
+            tracing::error!("{e}");
+        }
+        rowid
+    }
+
+    pub(crate) fn column(&self, column: usize) -> crate::Result<Value> {
+        let column_method = self
+            .methods()
+            .column
+            .ok_or_else(|| LimboError::ExtensionError("xColumn is missing".to_string()))?;
+        let mut ctx = SqliteContext::new(
+            Rc::as_ptr(&self.table.module.db),
+            std::ptr::null_mut(),
+            None,
+        );
+        let rc = unsafe { column_method(self.cursor, ctx.as_ptr(), column as c_int) };
+        self.check(rc, "xColumn")?;
+        ctx.into_result()
+    }
+}
+
+impl Drop for SqliteVirtualTableCursor {
+    fn drop(&mut self) {
+        if let Some(close) = self.methods().close {
+            let rc = unsafe { close(self.cursor) };
+            if rc != SQLITE_OK {
+                tracing::error!("failed to close virtual table cursor");
+            }
+        }
+    }
+}
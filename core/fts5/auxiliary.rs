@@ -0,0 +1,502 @@
+//! The auxiliary functions `bm25()`, `highlight()` and `snippet()`.
+//!
+//! They take the hidden column named after the full-text table as their first argument.
+//! For every row, the cursor returns a [MatchInfo] in that column, describing where the
+//! phrases of the current query matched, which is all the functions need to work with.
+
+use super::tokenizer::{tokenize, Token};
+use turso_ext::{scalar, Value, ValueType};
+
+const MATCH_INFO_MAGIC: &[u8; 4] = b"FTS5";
+
+/// The BM25 parameters used by FTS5
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+/// One occurrence of a query phrase in a row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct Instance {
+    pub phrase: usize,
+    pub column: usize,
+    /// Position of the first token of the occurrence within the column
+    pub position: usize,
+    /// Number of tokens of the occurrence
+    pub length: usize,
+}
+
+/// Everything the auxiliary functions know about the current row of a full-text query.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct MatchInfo {
+    /// Number of rows in the table
+    pub row_count: u64,
+    /// Number of tokens in all rows and columns of the table
+    pub total_tokens: u64,
+    /// For every phrase of the query, the number of rows it occurs in
+    pub phrase_row_counts: Vec<u64>,
+    /// Number of tokens of every column of the row
+    pub column_sizes: Vec<u64>,
+    /// The text of every column of the row
+    pub column_texts: Vec<Option<String>>,
+    pub instances: Vec<Instance>,
+}
+
+fn put_u64(out: &mut Vec<u8>, value: u64) {
+    out.extend_from_slice(&value.to_le_bytes());
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> Option<&[u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn u64(&mut self) -> Option<u64> {
+        Some(u64::from_le_bytes(self.bytes(8)?.try_into().ok()?))
+    }
+
+    fn usize(&mut self) -> Option<usize> {
+        usize::try_from(self.u64()?).ok()
+    }
+}
+
+impl MatchInfo {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = MATCH_INFO_MAGIC.to_vec();
+        put_u64(&mut out, self.row_count);
+        put_u64(&mut out, self.total_tokens);
+        put_u64(&mut out, self.phrase_row_counts.len() as u64);
+        for count in &self.phrase_row_counts {
+            put_u64(&mut out, *count);
+        }
+        put_u64(&mut out, self.column_sizes.len() as u64);
+        for (size, text) in self.column_sizes.iter().zip(&self.column_texts) {
+            put_u64(&mut out, *size);
+            match text {
+                Some(text) => {
+                    out.push(1);
+                    put_u64(&mut out, text.len() as u64);
+                    out.extend_from_slice(text.as_bytes());
+                }
+                None => out.push(0),
+            }
+        }
+        put_u64(&mut out, self.instances.len() as u64);
+        for instance in &self.instances {
+            put_u64(&mut out, instance.phrase as u64);
+            put_u64(&mut out, instance.column as u64);
+            put_u64(&mut out, instance.position as u64);
+            put_u64(&mut out, instance.length as u64);
+        }
+        out
+    }
+
+    pub fn deserialize(data: &[u8]) -> Option<Self> {
+        let mut reader = Reader { data, pos: 0 };
+        if reader.bytes(MATCH_INFO_MAGIC.len())? != MATCH_INFO_MAGIC {
+            return None;
+        }
+        let row_count = reader.u64()?;
+        let total_tokens = reader.u64()?;
+        let phrase_count = reader.usize()?;
+        let phrase_row_counts = (0..phrase_count)
+            .map(|_| reader.u64())
+            .collect::<Option<Vec<_>>>()?;
+        let column_count = reader.usize()?;
+        let mut column_sizes = Vec::new();
+        let mut column_texts = Vec::new();
+        for _ in 0..column_count {
+            column_sizes.push(reader.u64()?);
+            column_texts.push(match reader.bytes(1)?[0] {
+                0 => None,
+                _ => {
+                    let len = reader.usize()?;
+                    Some(String::from_utf8(reader.bytes(len)?.to_vec()).ok()?)
+                }
+            });
+        }
+        let instance_count = reader.usize()?;
+        let instances = (0..instance_count)
+            .map(|_| {
+                Some(Instance {
+                    phrase: reader.usize()?,
+                    column: reader.usize()?,
+                    position: reader.usize()?,
+                    length: reader.usize()?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()?;
+        if reader.pos != data.len() {
+            return None;
+        }
+        Some(MatchInfo {
+            row_count,
+            total_tokens,
+            phrase_row_counts,
+            column_sizes,
+            column_texts,
+            instances,
+        })
+    }
+
+    /// Computes the BM25 score of the row, negated so that better matches sort first,
+    /// like FTS5's `bm25()`. Columns without a weight are weighted 1.0.
+    pub fn bm25(&self, weights: &[f64]) -> f64 {
+        if self.row_count == 0 {
+            return 0.0;
+        }
+        let n = self.row_count as f64;
+        let avgdl = self.total_tokens as f64 / n;
+        let row_tokens = self.column_sizes.iter().sum::<u64>() as f64;
+        let mut frequencies = vec![0.0; self.phrase_row_counts.len()];
+        for instance in &self.instances {
+            frequencies[instance.phrase] += weights.get(instance.column).copied().unwrap_or(1.0);
+        }
+        let score: f64 = frequencies
+            .iter()
+            .zip(&self.phrase_row_counts)
+            .map(|(frequency, row_count)| {
+                let hits = *row_count as f64;
+                // Phrases occurring in more than half of the rows would get a negative IDF
+                let idf = ((n - hits + 0.5) / (hits + 0.5)).ln().max(1e-6);
+                let length_norm = if avgdl > 0.0 {
+                    1.0 - BM25_B + BM25_B * row_tokens / avgdl
+                } else {
+                    1.0
+                };
+                idf * (frequency * (BM25_K1 + 1.0)) / (frequency + BM25_K1 * length_norm)
+            })
+            .sum();
+        -score
+    }
+
+    /// Returns the token ranges matched in `column`, sorted and with overlapping ranges merged.
+    fn matched_ranges(&self, column: usize) -> Vec<(usize, usize)> {
+        let mut ranges: Vec<(usize, usize)> = self
+            .instances
+            .iter()
+            .filter(|i| i.column == column && i.length > 0)
+            .map(|i| (i.position, i.position + i.length))
+            .collect();
+        ranges.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(ranges.len());
+        for (start, end) in ranges {
+            match merged.last_mut() {
+                Some(last) if start < last.1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        merged
+    }
+}
+
+/// Appends `text[from..to]` to `out`, wrapping the matched tokens in `open` and `close`.
+/// `tokens` are the tokens of `text`, and `ranges` the merged token ranges of the matches.
+fn append_highlighted(
+    out: &mut String,
+    text: &str,
+    tokens: &[Token],
+    ranges: &[(usize, usize)],
+    (from, to): (usize, usize),
+    (open, close): (&str, &str),
+) {
+    let mut pos = from;
+    for &(start, end) in ranges {
+        let (Some(first), Some(last)) = (tokens.get(start), tokens.get(end - 1)) else {
+            continue;
+        };
+        if first.start < pos || last.end > to {
+            continue;
+        }
+        out.push_str(&text[pos..first.start]);
+        out.push_str(open);
+        out.push_str(&text[first.start..last.end]);
+        out.push_str(close);
+        pos = last.end;
+    }
+    out.push_str(&text[pos..to]);
+}
+
+fn match_info_arg(args: &[Value], function: &str) -> Result<MatchInfo, Value> {
+    args.first()
+        .filter(|v| v.value_type() == ValueType::Blob)
+        .and_then(|v| v.to_blob())
+        .and_then(|blob| MatchInfo::deserialize(&blob))
+        .ok_or_else(|| {
+            Value::error_with_message(format!(
+                "unable to use function {function} in the requested context"
+            ))
+        })
+}
+
+fn text_arg(args: &[Value], idx: usize) -> String {
+    match args.get(idx) {
+        Some(v) if v.value_type() == ValueType::Text => v.to_text().unwrap_or("").to_string(),
+        Some(v) if v.value_type() == ValueType::Integer => v.to_integer().unwrap_or(0).to_string(),
+        Some(v) if v.value_type() == ValueType::Float => v.to_float().unwrap_or(0.0).to_string(),
+        _ => String::new(),
+    }
+}
+
+fn integer_arg(args: &[Value], idx: usize) -> Option<i64> {
+    let arg = args.get(idx)?;
+    match arg.value_type() {
+        ValueType::Integer => arg.to_integer(),
+        ValueType::Float => arg.to_float().map(|f| f as i64),
+        ValueType::Text => arg.to_text()?.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+fn float_arg(args: &[Value], idx: usize) -> Option<f64> {
+    let arg = args.get(idx)?;
+    match arg.value_type() {
+        ValueType::Integer => arg.to_integer().map(|i| i as f64),
+        ValueType::Float => arg.to_float(),
+        ValueType::Text => arg.to_text()?.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// bm25(tbl, weight1, weight2, ...)
+#[scalar(name = "bm25")]
+fn fts5_bm25(args: &[Value]) -> Value {
+    let info = match match_info_arg(args, "bm25") {
+        Ok(info) => info,
+        Err(err) => return err,
+    };
+    let weights: Vec<f64> = (1..args.len())
+        .map(|i| float_arg(args, i).unwrap_or(0.0))
+        .collect();
+    Value::from_float(info.bm25(&weights))
+}
+
+/// highlight(tbl, column, open, close)
+#[scalar(name = "highlight")]
+fn fts5_highlight(args: &[Value]) -> Value {
+    if args.len() != 4 {
+        return Value::error_with_message(
+            "wrong number of arguments to function highlight()".to_string(),
+        );
+    }
+    let info = match match_info_arg(args, "highlight") {
+        Ok(info) => info,
+        Err(err) => return err,
+    };
+    let Some(column) = integer_arg(args, 1)
+        .and_then(|c| usize::try_from(c).ok())
+        .filter(|c| *c < info.column_texts.len())
+    else {
+        return Value::error_with_message("no such column".to_string());
+    };
+    let Some(text) = &info.column_texts[column] else {
+        return Value::null();
+    };
+    let (open, close) = (text_arg(args, 2), text_arg(args, 3));
+    let tokens = tokenize(text);
+    let mut out = String::with_capacity(text.len());
+    append_highlighted(
+        &mut out,
+        text,
+        &tokens,
+        &info.matched_ranges(column),
+        (0, text.len()),
+        (&open, &close),
+    );
+    Value::from_text(out)
+}
+
+/// Picks the window of at most `max_tokens` tokens of `column` covering the most phrases.
+/// Returns the score of the window and its first token.
+fn best_window(
+    info: &MatchInfo,
+    column: usize,
+    token_count: usize,
+    max_tokens: usize,
+) -> (usize, usize) {
+    let mut best = (0, 0);
+    for instance in info.instances.iter().filter(|i| i.column == column) {
+        // Center the occurrence in the window, as far as the column allows
+        let start = instance
+            .position
+            .saturating_sub(max_tokens.saturating_sub(instance.length.saturating_sub(1)) / 2)
+            .min(token_count.saturating_sub(max_tokens));
+        let end = start + max_tokens;
+        let in_window: Vec<&Instance> = info
+            .instances
+            .iter()
+            .filter(|i| i.column == column && i.position >= start && i.position + i.length <= end)
+            .collect();
+        let mut phrases: Vec<usize> = in_window.iter().map(|i| i.phrase).collect();
+        phrases.sort_unstable();
+        phrases.dedup();
+        let score = phrases.len() * 1000 + in_window.len();
+        if score > best.0 {
+            best = (score, start);
+        }
+    }
+    best
+}
+
+/// snippet(tbl, column, open, close, ellipsis, max_tokens)
+#[scalar(name = "snippet")]
+fn fts5_snippet(args: &[Value]) -> Value {
+    if args.len() != 6 {
+        return Value::error_with_message(
+            "wrong number of arguments to function snippet()".to_string(),
+        );
+    }
+    let info = match match_info_arg(args, "snippet") {
+        Ok(info) => info,
+        Err(err) => return err,
+    };
+    let (open, close, ellipsis) = (text_arg(args, 2), text_arg(args, 3), text_arg(args, 4));
+    let max_tokens = integer_arg(args, 5).unwrap_or(0).clamp(1, 64) as usize;
+    // A negative column lets the function pick the column with the best window
+    let columns: Vec<usize> = match integer_arg(args, 1) {
+        Some(c) if c < 0 => (0..info.column_texts.len()).collect(),
+        Some(c) if (c as usize) < info.column_texts.len() => vec![c as usize],
+        _ => return Value::error_with_message("no such column".to_string()),
+    };
+
+    let mut best: Option<(usize, usize, usize, Vec<Token>)> = None;
+    for column in columns {
+        let Some(text) = &info.column_texts[column] else {
+            continue;
+        };
+        let tokens = tokenize(text);
+        let (score, start) = best_window(&info, column, tokens.len(), max_tokens);
+        if best
+            .as_ref()
+            .is_none_or(|(best_score, ..)| score > *best_score)
+        {
+            best = Some((score, column, start, tokens));
+        }
+    }
+    let Some((_, column, start, tokens)) = best else {
+        return Value::from_text(String::new());
+    };
+    let text = info.column_texts[column].as_deref().unwrap_or("");
+    if tokens.is_empty() {
+        return Value::from_text(text.to_string());
+    }
+    let end = (start + max_tokens).min(tokens.len());
+    // The snippet keeps the text before the first and after the last token of the column
+    let from = if start == 0 { 0 } else { tokens[start].start };
+    let to = if end == tokens.len() {
+        text.len()
+    } else {
+        tokens[end - 1].end
+    };
+
+    let mut out = String::new();
+    if start > 0 {
+        out.push_str(&ellipsis);
+    }
+    append_highlighted(
+        &mut out,
+        text,
+        &tokens,
+        &info.matched_ranges(column),
+        (from, to),
+        (&open, &close),
+    );
+    if end < tokens.len() {
+        out.push_str(&ellipsis);
+    }
+    Value::from_text(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn info() -> MatchInfo {
+        MatchInfo {
+            row_count: 3,
+            total_tokens: 12,
+            phrase_row_counts: vec![1, 2],
+            column_sizes: vec![2, 4],
+            column_texts: vec![Some("Hello world".to_string()), None],
+            instances: vec![
+                Instance {
+                    phrase: 0,
+                    column: 0,
+                    position: 1,
+                    length: 1,
+                },
+                Instance {
+                    phrase: 1,
+                    column: 1,
+                    position: 0,
+                    length: 2,
+                },
+            ],
+        }
+    }
+
+    #[test]
+    fn test_match_info_roundtrip() {
+        let info = info();
+        let data = info.serialize();
+        assert_eq!(MatchInfo::deserialize(&data), Some(info));
+        assert_eq!(MatchInfo::deserialize(&data[..data.len() - 1]), None);
+        assert_eq!(MatchInfo::deserialize(b"not a match info"), None);
+    }
+
+    #[test]
+    fn test_bm25() {
+        let info = info();
+        let score = info.bm25(&[]);
+        assert!(score < 0.0);
+        // Weighting the column the rarer phrase occurs in makes the row score better
+        assert!(info.bm25(&[10.0, 1.0]) < score);
+    }
+
+    #[test]
+    fn test_matched_ranges_merge_overlaps() {
+        let mut info = info();
+        info.instances = vec![
+            Instance {
+                phrase: 0,
+                column: 0,
+                position: 3,
+                length: 2,
+            },
+            Instance {
+                phrase: 1,
+                column: 0,
+                position: 0,
+                length: 1,
+            },
+            Instance {
+                phrase: 1,
+                column: 0,
+                position: 4,
+                length: 2,
+            },
+        ];
+        assert_eq!(info.matched_ranges(0), vec![(0, 1), (3, 6)]);
+    }
+
+    #[test]
+    fn test_append_highlighted() {
+        let text = "the quick brown fox";
+        let tokens = tokenize(text);
+        let mut out = String::new();
+        append_highlighted(
+            &mut out,
+            text,
+            &tokens,
+            &[(1, 3)],
+            (0, text.len()),
+            ("[", "]"),
+        );
+        assert_eq!(out, "the [quick brown] fox");
+    }
+}
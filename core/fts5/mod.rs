@@ -0,0 +1,593 @@
+//! A full-text search virtual table modeled after SQLite's FTS5.
+//!
+//! ```sql
+//! CREATE VIRTUAL TABLE docs USING fts5(title, body);
+//! INSERT INTO docs VALUES ('Limbo', 'An in-process SQL database');
+//! SELECT highlight(docs, 1, '[', ']') FROM docs WHERE docs MATCH 'sql' ORDER BY rank;
+//! ```
+//!
+//! Besides the declared columns, the table has two hidden columns: one named after the
+//! table, which is the left-hand side of `MATCH` and the first argument of the auxiliary
+//! functions (see [auxiliary]), and `rank`, the `bm25()` score of the row.
+//!
+//! The inverted index lives in memory, owned by the table instance of the connection.
+
+mod auxiliary;
+mod query;
+mod tokenizer;
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::ext::register_scalar_function;
+use auxiliary::{Instance, MatchInfo};
+use query::{parse_query, Phrase, Query, QueryExpr};
+use tokenizer::tokenize;
+use turso_ext::{
+    Connection, ConstraintInfo, ConstraintOp, ConstraintUsage, ExtensionApi, IndexInfo,
+    OrderByInfo, ResultCode, VTabCursor, VTabKind, VTabModule, VTabModuleDerive, VTable, Value,
+    ValueType,
+};
+
+pub fn register_extension(ext_api: &mut ExtensionApi) {
+    // FIXME: Add macro magic to register functions automatically.
+    unsafe {
+        Fts5VTabModule::register_Fts5VTabModule(ext_api);
+        register_scalar_function(ext_api.ctx, c"bm25".as_ptr(), auxiliary::fts5_bm25);
+        register_scalar_function(
+            ext_api.ctx,
+            c"highlight".as_ptr(),
+            auxiliary::fts5_highlight,
+        );
+        register_scalar_function(ext_api.ctx, c"snippet".as_ptr(), auxiliary::fts5_snippet);
+    }
+}
+
+/// A value stored in one of the columns of a full-text table.
+#[derive(Debug, Clone, PartialEq)]
+enum StoredValue {
+    Null,
+    Integer(i64),
+    Float(f64),
+    Text(String),
+    Blob(Vec<u8>),
+}
+
+impl StoredValue {
+    fn from_ext_value(value: &Value) -> Self {
+        match value.value_type() {
+            ValueType::Integer => StoredValue::Integer(value.to_integer().unwrap_or(0)),
+            ValueType::Float => StoredValue::Float(value.to_float().unwrap_or(0.0)),
+            ValueType::Text => StoredValue::Text(value.to_text().unwrap_or("").to_string()),
+            ValueType::Blob => StoredValue::Blob(value.to_blob().unwrap_or_default()),
+            _ => StoredValue::Null,
+        }
+    }
+
+    fn to_ext_value(&self) -> Value {
+        match self {
+            StoredValue::Null => Value::null(),
+            StoredValue::Integer(i) => Value::from_integer(*i),
+            StoredValue::Float(f) => Value::from_float(*f),
+            StoredValue::Text(s) => Value::from_text(s.clone()),
+            StoredValue::Blob(b) => Value::from_blob(b.clone()),
+        }
+    }
+
+    /// The text that gets tokenized, or `None` for values that are not indexed.
+    fn text(&self) -> Option<String> {
+        match self {
+            StoredValue::Null | StoredValue::Blob(_) => None,
+            StoredValue::Integer(i) => Some(i.to_string()),
+            StoredValue::Float(f) => Some(f.to_string()),
+            StoredValue::Text(s) => Some(s.clone()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Fts5Column {
+    name: String,
+    /// `UNINDEXED` columns are stored but not tokenized
+    unindexed: bool,
+}
+
+/// A row of a full-text table and the number of tokens of each of its columns.
+#[derive(Debug)]
+struct Document {
+    values: Vec<StoredValue>,
+    sizes: Vec<u64>,
+}
+
+/// Positions of a token within a row: (column, token position)
+type Postings = BTreeMap<i64, Vec<(usize, usize)>>;
+
+#[derive(Debug, Default)]
+struct Fts5Index {
+    columns: Vec<Fts5Column>,
+    documents: BTreeMap<i64, Document>,
+    /// The inverted index: every token, and where it occurs
+    postings: BTreeMap<String, Postings>,
+    total_tokens: u64,
+}
+
+impl Fts5Index {
+    fn insert(&mut self, rowid: i64, values: Vec<StoredValue>) {
+        let mut sizes = vec![0; self.columns.len()];
+        for (column, value) in values.iter().enumerate() {
+            if self.columns[column].unindexed {
+                continue;
+            }
+            let Some(text) = value.text() else {
+                continue;
+            };
+            let tokens = tokenize(&text);
+            sizes[column] = tokens.len() as u64;
+            for (position, token) in tokens.into_iter().enumerate() {
+                self.postings
+                    .entry(token.text)
+                    .or_default()
+                    .entry(rowid)
+                    .or_default()
+                    .push((column, position));
+            }
+        }
+        self.total_tokens += sizes.iter().sum::<u64>();
+        self.documents.insert(rowid, Document { values, sizes });
+    }
+
+    fn delete(&mut self, rowid: i64) -> Option<Document> {
+        let document = self.documents.remove(&rowid)?;
+        self.total_tokens -= document.sizes.iter().sum::<u64>();
+        for (column, value) in document.values.iter().enumerate() {
+            if self.columns[column].unindexed {
+                continue;
+            }
+            let Some(text) = value.text() else {
+                continue;
+            };
+            for token in tokenize(&text) {
+                if let Some(postings) = self.postings.get_mut(&token.text) {
+                    postings.remove(&rowid);
+                    if postings.is_empty() {
+                        self.postings.remove(&token.text);
+                    }
+                }
+            }
+        }
+        Some(document)
+    }
+
+    fn next_rowid(&self) -> i64 {
+        self.documents
+            .last_key_value()
+            .map(|(rowid, _)| rowid.saturating_add(1))
+            .unwrap_or(1)
+    }
+
+    /// Returns the postings of `token`, or of every token it is a prefix of.
+    fn token_postings(&self, token: &str, prefix: bool) -> Postings {
+        if !prefix {
+            return self.postings.get(token).cloned().unwrap_or_default();
+        }
+        let mut merged = Postings::new();
+        for (_, postings) in self
+            .postings
+            .range(token.to_string()..)
+            .take_while(|(t, _)| t.starts_with(token))
+        {
+            for (rowid, positions) in postings {
+                merged.entry(*rowid).or_default().extend(positions);
+            }
+        }
+        merged
+    }
+
+    /// Finds every occurrence of `phrase`, grouped by row.
+    fn phrase_instances(&self, phrase_idx: usize, phrase: &Phrase) -> BTreeMap<i64, Vec<Instance>> {
+        let mut result = BTreeMap::new();
+        let Some(last) = phrase.tokens.len().checked_sub(1) else {
+            return result;
+        };
+        let postings: Vec<Postings> = phrase
+            .tokens
+            .iter()
+            .enumerate()
+            .map(|(i, token)| self.token_postings(token, phrase.prefix && i == last))
+            .collect();
+        for (rowid, first_positions) in &postings[0] {
+            let mut instances: Vec<Instance> = first_positions
+                .iter()
+                .filter(|(column, _)| {
+                    phrase
+                        .columns
+                        .as_ref()
+                        .is_none_or(|columns| columns.contains(column))
+                })
+                .filter(|(column, position)| {
+                    postings[1..].iter().enumerate().all(|(i, p)| {
+                        p.get(rowid).is_some_and(|positions| {
+                            positions.contains(&(*column, position + i + 1))
+                        })
+                    })
+                })
+                .map(|(column, position)| Instance {
+                    phrase: phrase_idx,
+                    column: *column,
+                    position: *position,
+                    length: phrase.tokens.len(),
+                })
+                .collect();
+            if !instances.is_empty() {
+                instances.sort_by_key(|i| (i.column, i.position));
+                result.insert(*rowid, instances);
+            }
+        }
+        result
+    }
+}
+
+/// Evaluates a query expression, given the rows each phrase occurs in.
+fn evaluate(expr: &QueryExpr, phrase_rows: &[BTreeSet<i64>]) -> BTreeSet<i64> {
+    match expr {
+        QueryExpr::Phrase(idx) => phrase_rows[*idx].clone(),
+        QueryExpr::And(lhs, rhs) => {
+            let lhs = evaluate(lhs, phrase_rows);
+            let rhs = evaluate(rhs, phrase_rows);
+            lhs.intersection(&rhs).copied().collect()
+        }
+        QueryExpr::Or(lhs, rhs) => {
+            let mut lhs = evaluate(lhs, phrase_rows);
+            lhs.extend(evaluate(rhs, phrase_rows));
+            lhs
+        }
+        QueryExpr::Not(lhs, rhs) => {
+            let lhs = evaluate(lhs, phrase_rows);
+            let rhs = evaluate(rhs, phrase_rows);
+            lhs.difference(&rhs).copied().collect()
+        }
+    }
+}
+
+#[derive(Debug, VTabModuleDerive, Default)]
+struct Fts5VTabModule;
+
+impl VTabModule for Fts5VTabModule {
+    type Table = Fts5Table;
+    const NAME: &'static str = "fts5";
+    const VTAB_KIND: VTabKind = VTabKind::VirtualTable;
+
+    fn create(args: &[Value]) -> Result<(String, Self::Table), ResultCode> {
+        let mut columns = Vec::new();
+        for arg in args {
+            let Some(arg) = arg.to_text() else {
+                return Err(ResultCode::InvalidArgs);
+            };
+            let arg = arg.trim();
+            if let Some((option, value)) = arg.split_once('=') {
+                // Only the default tokenizer is available
+                let value = value.trim().trim_matches(|c| c == '\'' || c == '"');
+                if !option.trim().eq_ignore_ascii_case("tokenize")
+                    || !value.eq_ignore_ascii_case("unicode61")
+                {
+                    return Err(ResultCode::InvalidArgs);
+                }
+                continue;
+            }
+            let mut words = arg.split_whitespace();
+            let Some(name) = words.next() else {
+                return Err(ResultCode::InvalidArgs);
+            };
+            let unindexed = match words.next() {
+                None => false,
+                Some(word) if word.eq_ignore_ascii_case("unindexed") => true,
+                Some(_) => return Err(ResultCode::InvalidArgs),
+            };
+            let name = crate::util::normalize_ident(name);
+            if words.next().is_some()
+                || matches!(name.as_str(), "rank" | "rowid" | "fts5")
+                || columns.iter().any(|c: &Fts5Column| c.name == name)
+            {
+                return Err(ResultCode::InvalidArgs);
+            }
+            columns.push(Fts5Column { name, unindexed });
+        }
+        if columns.is_empty() {
+            return Err(ResultCode::InvalidArgs);
+        }
+
+        // The hidden column named after the module gets renamed to the table name by the core
+        let schema = format!(
+            "CREATE TABLE x({}, fts5 HIDDEN, rank HIDDEN)",
+            columns
+                .iter()
+                .map(|c| format!("\"{}\"", c.name.replace('"', "\"\"")))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        let index = Fts5Index {
+            columns,
+            ..Default::default()
+        };
+        Ok((
+            schema,
+            Fts5Table {
+                index: Rc::new(RefCell::new(index)),
+            },
+        ))
+    }
+}
+
+struct Fts5Table {
+    index: Rc<RefCell<Fts5Index>>,
+}
+
+impl Fts5Table {
+    /// Reads the values of the declared columns out of the arguments of an insert or update,
+    /// which also contain the hidden columns.
+    fn stored_values(&self, args: &[Value]) -> Vec<StoredValue> {
+        let column_count = self.index.borrow().columns.len();
+        (0..column_count)
+            .map(|i| {
+                args.get(i)
+                    .map(StoredValue::from_ext_value)
+                    .unwrap_or(StoredValue::Null)
+            })
+            .collect()
+    }
+}
+
+impl VTable for Fts5Table {
+    type Cursor = Fts5Cursor;
+    type Error = ResultCode;
+
+    fn open(&self, _conn: Option<Arc<Connection>>) -> Result<Self::Cursor, Self::Error> {
+        Ok(Fts5Cursor {
+            index: Rc::clone(&self.index),
+            rows: Vec::new(),
+            current: 0,
+        })
+    }
+
+    fn best_index(constraints: &[ConstraintInfo], _order_by: &[OrderByInfo]) -> IndexInfo {
+        // Every usable MATCH constraint is passed to the filter method, and `idx_str` lists
+        // the columns they apply to, in argument order. `best_index` can't tell the table
+        // column from the declared ones, so the filter method sorts that out.
+        let mut match_columns = Vec::new();
+        let constraint_usages = constraints
+            .iter()
+            .map(|c| {
+                if c.usable && c.op == ConstraintOp::Match {
+                    match_columns.push(c.column_index.to_string());
+                    ConstraintUsage {
+                        argv_index: Some(match_columns.len() as u32),
+                        omit: true,
+                    }
+                } else {
+                    ConstraintUsage {
+                        argv_index: None,
+                        omit: false,
+                    }
+                }
+            })
+            .collect();
+        if match_columns.is_empty() {
+            return IndexInfo {
+                idx_num: 0,
+                idx_str: None,
+                estimated_cost: 1_000_000.0,
+                constraint_usages,
+                ..Default::default()
+            };
+        }
+        IndexInfo {
+            idx_num: 1,
+            idx_str: Some(match_columns.join(",")),
+            estimated_cost: 100.0,
+            estimated_rows: 100,
+            constraint_usages,
+            ..Default::default()
+        }
+    }
+
+    fn insert(&mut self, args: &[Value]) -> Result<i64, Self::Error> {
+        let values = self.stored_values(args);
+        let mut index = self.index.borrow_mut();
+        let rowid = index.next_rowid();
+        index.insert(rowid, values);
+        Ok(rowid)
+    }
+
+    fn update(&mut self, rowid: i64, args: &[Value]) -> Result<(), Self::Error> {
+        let values = self.stored_values(args);
+        let mut index = self.index.borrow_mut();
+        if index.delete(rowid).is_some() {
+            index.insert(rowid, values);
+        }
+        Ok(())
+    }
+
+    fn delete(&mut self, rowid: i64) -> Result<(), Self::Error> {
+        self.index.borrow_mut().delete(rowid);
+        Ok(())
+    }
+}
+
+struct Fts5Row {
+    rowid: i64,
+    /// Set when the rows come from a full-text query
+    match_info: Option<MatchInfo>,
+}
+
+struct Fts5Cursor {
+    index: Rc<RefCell<Fts5Index>>,
+    rows: Vec<Fts5Row>,
+    current: usize,
+}
+
+impl Fts5Cursor {
+    fn collect_matches(&mut self, query: &Query) {
+        let index = self.index.borrow();
+        let instances: Vec<BTreeMap<i64, Vec<Instance>>> = query
+            .phrases
+            .iter()
+            .enumerate()
+            .map(|(i, phrase)| index.phrase_instances(i, phrase))
+            .collect();
+        let phrase_rows: Vec<BTreeSet<i64>> = instances
+            .iter()
+            .map(|rows| rows.keys().copied().collect())
+            .collect();
+        let phrase_row_counts: Vec<u64> = phrase_rows.iter().map(|r| r.len() as u64).collect();
+        for rowid in evaluate(&query.expr, &phrase_rows) {
+            let Some(document) = index.documents.get(&rowid) else {
+                continue;
+            };
+            let match_info = MatchInfo {
+                row_count: index.documents.len() as u64,
+                total_tokens: index.total_tokens,
+                phrase_row_counts: phrase_row_counts.clone(),
+                column_sizes: document.sizes.clone(),
+                column_texts: document.values.iter().map(StoredValue::text).collect(),
+                instances: instances
+                    .iter()
+                    .filter_map(|rows| rows.get(&rowid))
+                    .flatten()
+                    .copied()
+                    .collect(),
+            };
+            self.rows.push(Fts5Row {
+                rowid,
+                match_info: Some(match_info),
+            });
+        }
+    }
+
+    fn document_match_info(&self, rowid: i64) -> MatchInfo {
+        let index = self.index.borrow();
+        let document = index.documents.get(&rowid);
+        MatchInfo {
+            row_count: index.documents.len() as u64,
+            total_tokens: index.total_tokens,
+            phrase_row_counts: Vec::new(),
+            column_sizes: document.map(|d| d.sizes.clone()).unwrap_or_default(),
+            column_texts: document
+                .map(|d| d.values.iter().map(StoredValue::text).collect())
+                .unwrap_or_default(),
+            instances: Vec::new(),
+        }
+    }
+}
+
+impl VTabCursor for Fts5Cursor {
+    type Error = ResultCode;
+
+    fn filter(&mut self, args: &[Value], idx_info: Option<(&str, i32)>) -> ResultCode {
+        self.rows.clear();
+        self.current = 0;
+        match idx_info {
+            Some((idx_str, idx_num)) if idx_num & 1 != 0 => {
+                let column_names: Vec<String> = self
+                    .index
+                    .borrow()
+                    .columns
+                    .iter()
+                    .map(|c| c.name.clone())
+                    .collect();
+                let mut combined: Option<Query> = None;
+                for (arg, column) in args.iter().zip(idx_str.split(',')) {
+                    // A NULL query matches nothing
+                    let Some(text) = arg.to_text() else {
+                        return ResultCode::EOF;
+                    };
+                    let Ok(mut query) = parse_query(text, &column_names) else {
+                        return ResultCode::InvalidArgs;
+                    };
+                    // `col MATCH 'query'` is `tbl MATCH 'col:(query)'`
+                    if let Some(column) = column
+                        .parse::<usize>()
+                        .ok()
+                        .filter(|c| *c < column_names.len())
+                    {
+                        query.restrict_to_column(column);
+                    }
+                    combined = Some(match combined {
+                        Some(lhs) => lhs.and(query),
+                        None => query,
+                    });
+                }
+                if let Some(query) = combined {
+                    self.collect_matches(&query);
+                }
+            }
+            _ => {
+                self.rows = self
+                    .index
+                    .borrow()
+                    .documents
+                    .keys()
+                    .map(|rowid| Fts5Row {
+                        rowid: *rowid,
+                        match_info: None,
+                    })
+                    .collect();
+            }
+        }
+        if self.rows.is_empty() {
+            return ResultCode::EOF;
+        }
+        ResultCode::OK
+    }
+
+    fn rowid(&self) -> i64 {
+        self.rows.get(self.current).map(|r| r.rowid).unwrap_or(-1)
+    }
+
+    fn column(&self, idx: u32) -> Result<Value, Self::Error> {
+        let Some(row) = self.rows.get(self.current) else {
+            return Err(ResultCode::OutOfRange);
+        };
+        let index = self.index.borrow();
+        let column_count = index.columns.len();
+        let idx = idx as usize;
+        if idx < column_count {
+            return Ok(index
+                .documents
+                .get(&row.rowid)
+                .map(|d| d.values[idx].to_ext_value())
+                .unwrap_or_else(Value::null));
+        }
+        drop(index);
+        match idx - column_count {
+            // The table column
+            0 => {
+                let info = match &row.match_info {
+                    Some(info) => info.serialize(),
+                    None => self.document_match_info(row.rowid).serialize(),
+                };
+                Ok(Value::from_blob(info))
+            }
+            // rank
+            1 => Ok(row
+                .match_info
+                .as_ref()
+                .map(|info| Value::from_float(info.bm25(&[])))
+                .unwrap_or_else(Value::null)),
+            _ => Ok(Value::null()),
+        }
+    }
+
+    fn eof(&self) -> bool {
+        self.current >= self.rows.len()
+    }
+
+    fn next(&mut self) -> ResultCode {
+        self.current += 1;
+        if self.eof() {
+            return ResultCode::EOF;
+        }
+        ResultCode::OK
+    }
+}
@@ -0,0 +1,405 @@
+use super::tokenizer::tokenize;
+
+/// A parsed full-text query, as accepted on the right-hand side of `MATCH`.
+///
+/// The query syntax is a subset of FTS5's: phrases are barewords or double-quoted
+/// strings, a trailing `*` turns the last token of a phrase into a prefix, phrases can be
+/// combined with `AND`, `OR` and `NOT` (plain juxtaposition is an implicit `AND`),
+/// grouped with parentheses and restricted to columns with `col:` or `{col1 col2}:`.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Query {
+    pub expr: QueryExpr,
+    /// Every phrase of the query, in the order they appear in the query text.
+    pub phrases: Vec<Phrase>,
+}
+
+impl Query {
+    /// Restricts every phrase of the query to `column`, like a `col:` filter around it.
+    pub fn restrict_to_column(&mut self, column: usize) {
+        for phrase in &mut self.phrases {
+            restrict_phrase(phrase, &[column]);
+        }
+    }
+
+    /// Combines two queries into one matching the rows both of them match.
+    pub fn and(mut self, other: Query) -> Query {
+        let offset = self.phrases.len();
+        self.phrases.extend(other.phrases);
+        Query {
+            expr: QueryExpr::And(
+                Box::new(self.expr),
+                Box::new(other.expr.with_phrase_offset(offset)),
+            ),
+            phrases: self.phrases,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum QueryExpr {
+    /// Index into [Query::phrases]
+    Phrase(usize),
+    And(Box<QueryExpr>, Box<QueryExpr>),
+    Or(Box<QueryExpr>, Box<QueryExpr>),
+    /// Rows matching the left-hand side but not the right-hand side
+    Not(Box<QueryExpr>, Box<QueryExpr>),
+}
+
+impl QueryExpr {
+    fn with_phrase_offset(self, offset: usize) -> QueryExpr {
+        match self {
+            QueryExpr::Phrase(idx) => QueryExpr::Phrase(idx + offset),
+            QueryExpr::And(lhs, rhs) => QueryExpr::And(
+                Box::new(lhs.with_phrase_offset(offset)),
+                Box::new(rhs.with_phrase_offset(offset)),
+            ),
+            QueryExpr::Or(lhs, rhs) => QueryExpr::Or(
+                Box::new(lhs.with_phrase_offset(offset)),
+                Box::new(rhs.with_phrase_offset(offset)),
+            ),
+            QueryExpr::Not(lhs, rhs) => QueryExpr::Not(
+                Box::new(lhs.with_phrase_offset(offset)),
+                Box::new(rhs.with_phrase_offset(offset)),
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Phrase {
+    /// The case-folded tokens that must appear consecutively
+    pub tokens: Vec<String>,
+    /// Whether the last token matches any token it is a prefix of
+    pub prefix: bool,
+    /// The columns the phrase may match in, or `None` for every column
+    pub columns: Option<Vec<usize>>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Lexeme {
+    Bareword(String),
+    String(String),
+    LParen,
+    RParen,
+    LBrace,
+    RBrace,
+    Colon,
+    Star,
+}
+
+/// Narrows the columns `phrase` may match in down to `columns`.
+fn restrict_phrase(phrase: &mut Phrase, columns: &[usize]) {
+    phrase.columns = Some(match phrase.columns.take() {
+        Some(inner) => inner.into_iter().filter(|c| columns.contains(c)).collect(),
+        None => columns.to_vec(),
+    });
+}
+
+fn is_bareword_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '_' || c == '\u{1a}' || !c.is_ascii()
+}
+
+fn lex(text: &str) -> Result<Vec<Lexeme>, String> {
+    let mut lexemes = Vec::new();
+    let mut chars = text.char_indices().peekable();
+    while let Some((offset, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '(' => lexemes.push(Lexeme::LParen),
+            ')' => lexemes.push(Lexeme::RParen),
+            '{' => lexemes.push(Lexeme::LBrace),
+            '}' => lexemes.push(Lexeme::RBrace),
+            ':' => lexemes.push(Lexeme::Colon),
+            '*' => lexemes.push(Lexeme::Star),
+            '"' => {
+                let mut string = String::new();
+                loop {
+                    match chars.next() {
+                        // A doubled quote stands for a single quote character
+                        Some((_, '"')) if chars.peek().is_some_and(|(_, c)| *c == '"') => {
+                            chars.next();
+                            string.push('"');
+                        }
+                        Some((_, '"')) => break,
+                        Some((_, c)) => string.push(c),
+                        None => {
+                            return Err(format!("fts5: syntax error near \"{}\"", &text[offset..]))
+                        }
+                    }
+                }
+                lexemes.push(Lexeme::String(string));
+            }
+            c if is_bareword_char(c) => {
+                let mut word = String::from(c);
+                while let Some((_, c)) = chars.next_if(|(_, c)| is_bareword_char(*c)) {
+                    word.push(c);
+                }
+                lexemes.push(Lexeme::Bareword(word));
+            }
+            _ => return Err(format!("fts5: syntax error near \"{c}\"")),
+        }
+    }
+    Ok(lexemes)
+}
+
+struct Parser<'a> {
+    lexemes: Vec<Lexeme>,
+    pos: usize,
+    column_names: &'a [String],
+    phrases: Vec<Phrase>,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.pos)
+    }
+
+    fn peek_keyword(&self, keyword: &str) -> bool {
+        // Operators are case-sensitive in FTS5: `and` is an ordinary term
+        matches!(self.peek(), Some(Lexeme::Bareword(word)) if word == keyword)
+    }
+
+    fn syntax_error(&self) -> String {
+        match self.peek() {
+            Some(Lexeme::Bareword(word)) => format!("fts5: syntax error near \"{word}\""),
+            Some(Lexeme::String(string)) => format!("fts5: syntax error near \"{string}\""),
+            Some(Lexeme::LParen) => "fts5: syntax error near \"(\"".to_string(),
+            Some(Lexeme::RParen) => "fts5: syntax error near \")\"".to_string(),
+            Some(Lexeme::LBrace) => "fts5: syntax error near \"{\"".to_string(),
+            Some(Lexeme::RBrace) => "fts5: syntax error near \"}\"".to_string(),
+            Some(Lexeme::Colon) => "fts5: syntax error near \":\"".to_string(),
+            Some(Lexeme::Star) => "fts5: syntax error near \"*\"".to_string(),
+            None => "fts5: syntax error near \"\"".to_string(),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<QueryExpr, String> {
+        let mut expr = self.parse_and()?;
+        while self.peek_keyword("OR") {
+            self.pos += 1;
+            let rhs = self.parse_and()?;
+            expr = QueryExpr::Or(Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn parse_and(&mut self) -> Result<QueryExpr, String> {
+        let mut expr = self.parse_not()?;
+        loop {
+            if self.peek_keyword("AND") {
+                self.pos += 1;
+            } else if !self.starts_primary() {
+                break;
+            }
+            let rhs = self.parse_not()?;
+            expr = QueryExpr::And(Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn parse_not(&mut self) -> Result<QueryExpr, String> {
+        let mut expr = self.parse_primary()?;
+        while self.peek_keyword("NOT") {
+            self.pos += 1;
+            let rhs = self.parse_primary()?;
+            expr = QueryExpr::Not(Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn starts_primary(&self) -> bool {
+        match self.peek() {
+            Some(Lexeme::Bareword(word)) => !matches!(word.as_str(), "AND" | "OR" | "NOT"),
+            Some(Lexeme::String(_) | Lexeme::LParen | Lexeme::LBrace) => true,
+            _ => false,
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<QueryExpr, String> {
+        let columns = self.parse_column_filter()?;
+        let first_phrase = self.phrases.len();
+        let expr = match self.peek().cloned() {
+            Some(Lexeme::LParen) => {
+                self.pos += 1;
+                let expr = self.parse_or()?;
+                if self.peek() != Some(&Lexeme::RParen) {
+                    return Err(self.syntax_error());
+                }
+                self.pos += 1;
+                expr
+            }
+            Some(Lexeme::Bareword(text) | Lexeme::String(text)) if self.starts_primary() => {
+                self.pos += 1;
+                let prefix = self.peek() == Some(&Lexeme::Star);
+                if prefix {
+                    self.pos += 1;
+                }
+                self.phrases.push(Phrase {
+                    tokens: tokenize(&text).into_iter().map(|t| t.text).collect(),
+                    prefix,
+                    columns: None,
+                });
+                QueryExpr::Phrase(self.phrases.len() - 1)
+            }
+            _ => return Err(self.syntax_error()),
+        };
+        if let Some(columns) = columns {
+            // A column filter applies to every phrase of the expression it precedes
+            for phrase in &mut self.phrases[first_phrase..] {
+                restrict_phrase(phrase, &columns);
+            }
+        }
+        Ok(expr)
+    }
+
+    /// Parses an optional `col:` or `{col1 col2 ...}:` prefix.
+    fn parse_column_filter(&mut self) -> Result<Option<Vec<usize>>, String> {
+        match self.peek() {
+            Some(Lexeme::Bareword(name))
+                if self.lexemes.get(self.pos + 1) == Some(&Lexeme::Colon) =>
+            {
+                let column = self.resolve_column(name)?;
+                self.pos += 2;
+                Ok(Some(vec![column]))
+            }
+            Some(Lexeme::LBrace) => {
+                self.pos += 1;
+                let mut columns = Vec::new();
+                while let Some(Lexeme::Bareword(name)) = self.peek() {
+                    columns.push(self.resolve_column(name)?);
+                    self.pos += 1;
+                }
+                if self.peek() != Some(&Lexeme::RBrace) {
+                    return Err(self.syntax_error());
+                }
+                self.pos += 1;
+                if self.peek() != Some(&Lexeme::Colon) {
+                    return Err(self.syntax_error());
+                }
+                self.pos += 1;
+                Ok(Some(columns))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    fn resolve_column(&self, name: &str) -> Result<usize, String> {
+        self.column_names
+            .iter()
+            .position(|c| c.eq_ignore_ascii_case(name))
+            .ok_or_else(|| format!("no such column: {name}"))
+    }
+}
+
+/// Parses the text of a full-text query against a table with the given columns.
+pub(crate) fn parse_query(text: &str, column_names: &[String]) -> Result<Query, String> {
+    let mut parser = Parser {
+        lexemes: lex(text)?,
+        pos: 0,
+        column_names,
+        phrases: Vec::new(),
+    };
+    let expr = parser.parse_or()?;
+    if parser.peek().is_some() {
+        return Err(parser.syntax_error());
+    }
+    Ok(Query {
+        expr,
+        phrases: parser.phrases,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn columns() -> Vec<String> {
+        vec!["title".to_string(), "body".to_string()]
+    }
+
+    fn phrase(tokens: &[&str], prefix: bool, columns: Option<Vec<usize>>) -> Phrase {
+        Phrase {
+            tokens: tokens.iter().map(|t| t.to_string()).collect(),
+            prefix,
+            columns,
+        }
+    }
+
+    #[test]
+    fn test_parse_implicit_and() {
+        let query = parse_query("Hello world", &columns()).unwrap();
+        assert_eq!(
+            query.expr,
+            QueryExpr::And(
+                Box::new(QueryExpr::Phrase(0)),
+                Box::new(QueryExpr::Phrase(1))
+            )
+        );
+        assert_eq!(query.phrases[0], phrase(&["hello"], false, None));
+    }
+
+    #[test]
+    fn test_parse_phrase_and_prefix() {
+        let query = parse_query("\"quick brown\" fox*", &columns()).unwrap();
+        assert_eq!(query.phrases[0], phrase(&["quick", "brown"], false, None));
+        assert_eq!(query.phrases[1], phrase(&["fox"], true, None));
+    }
+
+    #[test]
+    fn test_parse_precedence() {
+        // NOT binds tighter than AND, which binds tighter than OR
+        let query = parse_query("a OR b c NOT d", &columns()).unwrap();
+        assert_eq!(
+            query.expr,
+            QueryExpr::Or(
+                Box::new(QueryExpr::Phrase(0)),
+                Box::new(QueryExpr::And(
+                    Box::new(QueryExpr::Phrase(1)),
+                    Box::new(QueryExpr::Not(
+                        Box::new(QueryExpr::Phrase(2)),
+                        Box::new(QueryExpr::Phrase(3))
+                    ))
+                ))
+            )
+        );
+    }
+
+    #[test]
+    fn test_parse_column_filters() {
+        let query = parse_query("title:(a OR b) {title body}:c", &columns()).unwrap();
+        assert_eq!(query.phrases[0].columns, Some(vec![0]));
+        assert_eq!(query.phrases[1].columns, Some(vec![0]));
+        assert_eq!(query.phrases[2].columns, Some(vec![0, 1]));
+        assert!(parse_query("author:x", &columns()).is_err());
+    }
+
+    #[test]
+    fn test_combine_queries() {
+        let mut rhs = parse_query("b OR title:c", &columns()).unwrap();
+        rhs.restrict_to_column(1);
+        let query = parse_query("a", &columns()).unwrap().and(rhs);
+        assert_eq!(
+            query.expr,
+            QueryExpr::And(
+                Box::new(QueryExpr::Phrase(0)),
+                Box::new(QueryExpr::Or(
+                    Box::new(QueryExpr::Phrase(1)),
+                    Box::new(QueryExpr::Phrase(2))
+                ))
+            )
+        );
+        assert_eq!(query.phrases[0].columns, None);
+        assert_eq!(query.phrases[1].columns, Some(vec![1]));
+        // `title:c` restricted to `body` can't match anywhere
+        assert_eq!(query.phrases[2].columns, Some(vec![]));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(parse_query("", &columns()).is_err());
+        assert!(parse_query("a AND", &columns()).is_err());
+        assert!(parse_query("(a", &columns()).is_err());
+        assert!(parse_query("\"a", &columns()).is_err());
+        assert!(parse_query("a - b", &columns()).is_err());
+    }
+}
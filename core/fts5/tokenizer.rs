@@ -0,0 +1,70 @@
+/// A single token of a document or query, along with the byte range it was read from.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Token {
+    /// The case-folded token text
+    pub text: String,
+    /// Byte offset of the first character of the token
+    pub start: usize,
+    /// Byte offset one past the last character of the token
+    pub end: usize,
+}
+
+/// Returns true if `c` is part of a token, like the default `unicode61` tokenizer of SQLite:
+/// letters and numbers are token characters, everything else separates tokens.
+fn is_token_char(c: char) -> bool {
+    c.is_alphanumeric()
+}
+
+/// Splits `text` into case-folded tokens.
+pub(crate) fn tokenize(text: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut current: Option<(usize, String)> = None;
+    for (offset, c) in text.char_indices() {
+        if is_token_char(c) {
+            let (_, folded) = current.get_or_insert_with(|| (offset, String::new()));
+            folded.extend(c.to_lowercase());
+        } else if let Some((start, folded)) = current.take() {
+            tokens.push(Token {
+                text: folded,
+                start,
+                end: offset,
+            });
+        }
+    }
+    if let Some((start, folded)) = current {
+        tokens.push(Token {
+            text: folded,
+            start,
+            end: text.len(),
+        });
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tokenize() {
+        let tokens = tokenize("Hello, World! foo_bar 42");
+        let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts, vec!["hello", "world", "foo", "bar", "42"]);
+        assert_eq!((tokens[1].start, tokens[1].end), (7, 12));
+        assert_eq!((tokens[4].start, tokens[4].end), (22, 24));
+    }
+
+    #[test]
+    fn test_tokenize_unicode() {
+        let tokens = tokenize("Ärger über Straße");
+        let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts, vec!["ärger", "über", "straße"]);
+        assert_eq!(tokens[1].start, 7);
+    }
+
+    #[test]
+    fn test_tokenize_empty() {
+        assert!(tokenize("").is_empty());
+        assert!(tokenize(" ,.; ").is_empty());
+    }
+}
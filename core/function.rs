@@ -1,38 +1,133 @@
+use bitflags::bitflags;
 use std::fmt;
 use std::fmt::{Debug, Display};
 use std::rc::Rc;
 use turso_ext::{FinalizeFunction, InitAggFunction, ScalarFunction, StepFunction};
 
-use crate::LimboError;
+use crate::{LimboError, Value};
+
+bitflags! {
+    /// The flags of a function created with the `create_*_function` methods of
+    /// [crate::Connection], named and valued like the flags of `sqlite3_create_function`.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+    pub struct FunctionFlags: u32 {
+        /// The function always returns the same result for the same arguments, so its calls
+        /// with constant arguments are evaluated once per statement.
+        const DETERMINISTIC = 0x800;
+    }
+}
+
+/// The implementation of a scalar function created with
+/// [crate::Connection::create_scalar_function], called with the values of its arguments.
+pub type ScalarClosure = Box<dyn Fn(&[Value]) -> crate::Result<Value>>;
+
+/// The state of an aggregate function created with
+/// [crate::Connection::create_aggregate_function] for a group of rows, which the rows are added
+/// to with the values of the arguments of the function.
+pub trait AggregateState {
+    /// Adds a row, like `xStep`.
+    fn step(&mut self, args: &[Value]) -> crate::Result<()>;
+
+    /// Returns the result once all the rows were added, like `xFinal`.
+    fn finalize(&mut self) -> crate::Result<Value>;
+
+    /// Returns the result for the rows added so far, like `xValue`. Only called for the
+    /// functions created with [crate::Connection::create_window_function].
+    fn value(&self) -> crate::Result<Value> {
+        Err(LimboError::InternalError(
+            "aggregate state has no value".to_string(),
+        ))
+    }
+
+    /// Removes a row that was added, the oldest one, like `xInverse`. Only called for the
+    /// functions created with [crate::Connection::create_window_function].
+    fn inverse(&mut self, _args: &[Value]) -> crate::Result<()> {
+        Err(LimboError::InternalError(
+            "aggregate state has no inverse".to_string(),
+        ))
+    }
+}
+
+/// Creates the state of an aggregate function for a new group of rows.
+pub type AggregateInit = Box<dyn Fn() -> Box<dyn AggregateState>>;
 
 pub struct ExternalFunc {
     pub name: String,
     pub func: ExtFunc,
+    /// Extension functions can be whatever, so only the closures created with
+    /// [FunctionFlags::DETERMINISTIC] are known to be deterministic.
+    deterministic: bool,
 }
 
 impl ExternalFunc {
     pub fn is_deterministic(&self) -> bool {
-        false // external functions can be whatever so let's just default to false
+        self.deterministic
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Clone)]
 pub enum ExtFunc {
     Scalar(ScalarFunction),
+    /// A scalar function implemented by a closure, taking `argc` arguments or any number of
+    /// them if `argc` is `None`.
+    Closure {
+        argc: Option<usize>,
+        func: Rc<ScalarClosure>,
+    },
     Aggregate {
         argc: usize,
         init: InitAggFunction,
         step: StepFunction,
         finalize: FinalizeFunction,
     },
+    /// An aggregate function implemented by the states `init` creates, taking `argc` arguments
+    /// or any number of them if `argc` is `None`. The calls of the function have their number
+    /// of arguments in `argc`. It is also a window function if `window` is set.
+    ClosureAggregate {
+        argc: Option<usize>,
+        init: Rc<AggregateInit>,
+        window: bool,
+    },
+}
+
+impl Debug for ExtFunc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Scalar(func) => f.debug_tuple("Scalar").field(func).finish(),
+            Self::Closure { argc, .. } => f.debug_struct("Closure").field("argc", argc).finish(),
+            Self::Aggregate { argc, .. } => {
+                f.debug_struct("Aggregate").field("argc", argc).finish()
+            }
+            Self::ClosureAggregate { argc, window, .. } => f
+                .debug_struct("ClosureAggregate")
+                .field("argc", argc)
+                .field("window", window)
+                .finish(),
+        }
+    }
 }
 
 impl ExtFunc {
     pub fn agg_args(&self) -> Result<usize, ()> {
-        if let ExtFunc::Aggregate { argc, .. } = self {
-            return Ok(*argc);
+        match self {
+            ExtFunc::Aggregate { argc, .. }
+            | ExtFunc::ClosureAggregate {
+                argc: Some(argc), ..
+            } => Ok(*argc),
+            _ => Err(()),
         }
-        Err(())
+    }
+
+    pub fn is_aggregate(&self) -> bool {
+        matches!(
+            self,
+            ExtFunc::Aggregate { .. } | ExtFunc::ClosureAggregate { .. }
+        )
+    }
+
+    /// Whether the aggregate can be called with an OVER clause.
+    pub fn is_window(&self) -> bool {
+        matches!(self, ExtFunc::ClosureAggregate { window: true, .. })
     }
 }
 
@@ -41,9 +136,72 @@ impl ExternalFunc {
         Self {
             name,
             func: ExtFunc::Scalar(func),
+            deterministic: false,
         }
     }
 
+    pub fn new_closure(
+        name: String,
+        argc: Option<usize>,
+        flags: FunctionFlags,
+        func: ScalarClosure,
+    ) -> Self {
+        Self {
+            name,
+            func: ExtFunc::Closure {
+                argc,
+                func: Rc::new(func),
+            },
+            deterministic: flags.contains(FunctionFlags::DETERMINISTIC),
+        }
+    }
+
+    pub fn new_closure_aggregate(
+        name: String,
+        argc: Option<usize>,
+        flags: FunctionFlags,
+        init: AggregateInit,
+        window: bool,
+    ) -> Self {
+        Self {
+            name,
+            func: ExtFunc::ClosureAggregate {
+                argc,
+                init: Rc::new(init),
+                window,
+            },
+            deterministic: flags.contains(FunctionFlags::DETERMINISTIC),
+        }
+    }
+
+    /// Whether the function can be called with that many arguments.
+    pub fn accepts_arg_count(&self, arg_count: usize) -> bool {
+        match &self.func {
+            ExtFunc::Closure {
+                argc: Some(argc), ..
+            }
+            | ExtFunc::ClosureAggregate {
+                argc: Some(argc), ..
+            } => *argc == arg_count,
+            _ => true,
+        }
+    }
+
+    /// The aggregate function for a call of this one with `arg_count` arguments, if this is an
+    /// aggregate function.
+    pub fn aggregate(&self, arg_count: usize) -> Option<AggFunc> {
+        let func = match &self.func {
+            ExtFunc::Aggregate { .. } => self.func.clone(),
+            ExtFunc::ClosureAggregate { init, window, .. } => ExtFunc::ClosureAggregate {
+                argc: Some(arg_count),
+                init: init.clone(),
+                window: *window,
+            },
+            _ => return None,
+        };
+        Some(AggFunc::External(Rc::new(func)))
+    }
+
     pub fn new_aggregate(
         name: String,
         argc: i32,
@@ -57,6 +215,7 @@ impl ExternalFunc {
                 step: func.1,
                 finalize: func.2,
             },
+            deterministic: false,
         }
     }
 }
@@ -267,6 +426,68 @@ impl AggFunc {
     }
 }
 
+/// A function called with an OVER clause: one of the built-in window functions, or an aggregate
+/// function computed over the window frame of each row.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WindowFunc {
+    Agg(AggFunc),
+    RowNumber,
+    Rank,
+    DenseRank,
+    PercentRank,
+    CumeDist,
+    Ntile,
+    Lag,
+    Lead,
+    FirstValue,
+    LastValue,
+    NthValue,
+}
+
+impl WindowFunc {
+    pub fn resolve_function(name: &str, arg_count: usize) -> Result<Self, LimboError> {
+        let normalized_name = crate::util::normalize_ident(name);
+        let (func, valid_arg_count) = match normalized_name.as_str() {
+            "row_number" => (Self::RowNumber, arg_count == 0),
+            "rank" => (Self::Rank, arg_count == 0),
+            "dense_rank" => (Self::DenseRank, arg_count == 0),
+            "percent_rank" => (Self::PercentRank, arg_count == 0),
+            "cume_dist" => (Self::CumeDist, arg_count == 0),
+            "ntile" => (Self::Ntile, arg_count == 1),
+            "lag" => (Self::Lag, (1..=3).contains(&arg_count)),
+            "lead" => (Self::Lead, (1..=3).contains(&arg_count)),
+            "first_value" => (Self::FirstValue, arg_count == 1),
+            "last_value" => (Self::LastValue, arg_count == 1),
+            "nth_value" => (Self::NthValue, arg_count == 2),
+            _ => match Func::resolve_function(name, arg_count)? {
+                Func::Agg(agg_func) => (Self::Agg(agg_func), true),
+                _ => crate::bail_parse_error!("{}() may not be used as a window function", name),
+            },
+        };
+        if !valid_arg_count {
+            crate::bail_parse_error!("wrong number of arguments to function {}()", name);
+        }
+        Ok(func)
+    }
+
+    pub fn to_string(&self) -> &str {
+        match self {
+            Self::Agg(agg_func) => agg_func.to_string(),
+            Self::RowNumber => "row_number",
+            Self::Rank => "rank",
+            Self::DenseRank => "dense_rank",
+            Self::PercentRank => "percent_rank",
+            Self::CumeDist => "cume_dist",
+            Self::Ntile => "ntile",
+            Self::Lag => "lag",
+            Self::Lead => "lead",
+            Self::FirstValue => "first_value",
+            Self::LastValue => "last_value",
+            Self::NthValue => "nth_value",
+        }
+    }
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum ScalarFunc {
     Cast,
@@ -318,6 +539,7 @@ pub enum ScalarFunc {
     LoadExtension,
     StrfTime,
     Printf,
+    Format,
     Likely,
     TimeDiff,
     Likelihood,
@@ -377,6 +599,7 @@ impl ScalarFunc {
             ScalarFunc::LoadExtension => true,
             ScalarFunc::StrfTime => false,
             ScalarFunc::Printf => false,
+            ScalarFunc::Format => false,
             ScalarFunc::Likely => true,
             ScalarFunc::TimeDiff => false,
             ScalarFunc::Likelihood => true,
@@ -438,6 +661,7 @@ impl Display for ScalarFunc {
             Self::LoadExtension => "load_extension".to_string(),
             Self::StrfTime => "strftime".to_string(),
             Self::Printf => "printf".to_string(),
+            Self::Format => "format".to_string(),
             Self::Likely => "likely".to_string(),
             Self::TimeDiff => "timediff".to_string(),
             Self::Likelihood => "likelihood".to_string(),
@@ -705,7 +929,7 @@ impl Func {
             "total_changes" => Ok(Self::Scalar(ScalarFunc::TotalChanges)),
             "glob" => Ok(Self::Scalar(ScalarFunc::Glob)),
             "ifnull" => Ok(Self::Scalar(ScalarFunc::IfNull)),
-            "iif" => Ok(Self::Scalar(ScalarFunc::Iif)),
+            "iif" | "if" => Ok(Self::Scalar(ScalarFunc::Iif)),
             "instr" => Ok(Self::Scalar(ScalarFunc::Instr)),
             "like" => Ok(Self::Scalar(ScalarFunc::Like)),
             "abs" => Ok(Self::Scalar(ScalarFunc::Abs)),
@@ -821,6 +1045,7 @@ impl Func {
             "load_extension" => Ok(Self::Scalar(ScalarFunc::LoadExtension)),
             "strftime" => Ok(Self::Scalar(ScalarFunc::StrfTime)),
             "printf" => Ok(Self::Scalar(ScalarFunc::Printf)),
+            "format" => Ok(Self::Scalar(ScalarFunc::Format)),
             "vector" => Ok(Self::Vector(VectorFunc::Vector)),
             "vector32" => Ok(Self::Vector(VectorFunc::Vector32)),
             "vector64" => Ok(Self::Vector(VectorFunc::Vector64)),
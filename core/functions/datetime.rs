@@ -2,7 +2,8 @@ use crate::LimboError::InvalidModifier;
 use crate::Result;
 use crate::{types::Value, vdbe::Register};
 use chrono::{
-    DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, TimeDelta, TimeZone, Timelike, Utc,
+    DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, Offset, TimeDelta, TimeZone, Timelike,
+    Utc,
 };
 
 /// Execution of date/time/datetime functions
@@ -44,43 +45,166 @@ enum DateTimeOutput {
     // Holds the format string
     StrfTime(String),
     JuliaDay,
+    UnixEpoch,
 }
 
+/// Computes a date and time function from its time value and modifiers. Like in SQLite, the
+/// result is NULL if the time value or any modifier is invalid, or if the date and time ends up
+/// out of the supported range.
 fn exec_datetime(values: &[Register], output_type: DateTimeOutput) -> Value {
-    if values.is_empty() {
-        let now = parse_naive_date_time(&Value::build_text("now")).unwrap();
-        return format_dt(now, output_type, false);
+    let mut time_value = match values.first() {
+        Some(value) => match TimeValue::parse(value.get_owned_value()) {
+            Some(time_value) => time_value,
+            None => return Value::Null,
+        },
+        None => TimeValue::now(),
+    };
+    for (i, modifier) in values.iter().enumerate().skip(1) {
+        let Value::Text(modifier) = modifier.get_owned_value() else {
+            return Value::Null;
+        };
+        if time_value
+            .apply_modifier(modifier.as_str(), i == 1)
+            .is_err()
+        {
+            return Value::Null;
+        }
     }
-    if let Some(mut dt) = parse_naive_date_time(values[0].get_owned_value()) {
-        // if successful, treat subsequent entries as modifiers
-        modify_dt(&mut dt, &values[1..], output_type)
-    } else {
-        // if the first argument is NOT a valid date/time, treat the entire set of values as modifiers.
-        let mut dt = chrono::Local::now().to_utc().naive_utc();
-        modify_dt(&mut dt, values, output_type)
+    match time_value.dt {
+        Some(dt)
+            if !is_leap_second(&dt)
+                && dt >= get_min_datetime()
+                && dt < get_max_datetime_exclusive() =>
+        {
+            format_dt(dt, output_type, time_value.subsec)
+        }
+        _ => Value::Null,
     }
 }
 
-fn modify_dt(dt: &mut NaiveDateTime, mods: &[Register], output_type: DateTimeOutput) -> Value {
-    let mut subsec_requested = false;
-
-    for modifier in mods {
-        if let Value::Text(ref text_rc) = modifier.get_owned_value() {
-            // TODO: to prevent double conversion and properly support 'utc'/'localtime', we also
-            // need to keep track of the current timezone and apply it to the modifier.
-            match apply_modifier(dt, text_rc.as_str()) {
-                Ok(true) => subsec_requested = true,
-                Ok(false) => {}
-                Err(_) => return Value::build_text(""),
-            }
-        } else {
-            return Value::build_text("");
+/// A time value, as its modifiers are applied to it.
+struct TimeValue {
+    /// The date and time, which is None while the time value is a number that isn't a valid
+    /// Julian day number, as long as the `unixepoch` or `auto` modifiers may still make it one.
+    dt: Option<NaiveDateTime>,
+    /// The number the time value was given as, which the `unixepoch`, `julianday` and `auto`
+    /// modifiers reinterpret when they come first.
+    number: Option<f64>,
+    /// Whether the date and time is known to be in UTC, in which case `utc` does nothing.
+    is_utc: bool,
+    /// Whether the date and time was converted to local time, in which case `localtime` does
+    /// nothing.
+    is_local: bool,
+    /// The days by which the last month or year offset went past the end of the month, which
+    /// `floor` takes back.
+    overflow_days: i64,
+    /// Whether the `subsec` modifier was applied, which adds milliseconds to the output.
+    subsec: bool,
+}
+
+impl TimeValue {
+    fn new(dt: Option<NaiveDateTime>, number: Option<f64>, is_utc: bool) -> Self {
+        Self {
+            dt,
+            number,
+            is_utc,
+            is_local: false,
+            overflow_days: 0,
+            subsec: false,
         }
     }
-    if is_leap_second(dt) || *dt > get_max_datetime_exclusive() {
-        return Value::build_text("");
+
+    fn now() -> Self {
+        Self::new(Some(Utc::now().naive_utc()), None, true)
+    }
+
+    /// Parses the first argument of a date and time function.
+    fn parse(value: &Value) -> Option<Self> {
+        let number = match value {
+            Value::Integer(i) => *i as f64,
+            Value::Float(f) => *f,
+            Value::Text(s) => {
+                let s = s.as_str();
+                if s.trim().eq_ignore_ascii_case("now") {
+                    return Some(Self::now());
+                }
+                match s.parse::<f64>() {
+                    Ok(number) => number,
+                    Err(_) => {
+                        let dt = get_date_time_from_time_value_string(s)?;
+                        return Some(Self::new(Some(dt), None, has_timezone(s)));
+                    }
+                }
+            }
+            _ => return None,
+        };
+        Some(Self::new(
+            get_date_time_from_time_value_float(number),
+            Some(number),
+            false,
+        ))
+    }
+
+    fn dt_mut(&mut self) -> Result<&mut NaiveDateTime> {
+        self.dt
+            .as_mut()
+            .ok_or_else(|| InvalidModifier("Invalid time value".to_string()))
+    }
+
+    /// Applies `modifier`. The `unixepoch`, `julianday` and `auto` modifiers are only valid as
+    /// the `first` modifier, after a time value given as a number.
+    fn apply_modifier(&mut self, modifier: &str, first: bool) -> Result<()> {
+        let modifier = parse_modifier(modifier)?;
+        let number = self.number.take().filter(|_| first);
+        let overflow_days = std::mem::take(&mut self.overflow_days);
+        let number_modifier_error = || {
+            InvalidModifier(
+                "unixepoch, julianday and auto must come first, after a numeric time value"
+                    .to_string(),
+            )
+        };
+        match modifier {
+            Modifier::UnixEpoch => {
+                let seconds = number.ok_or_else(number_modifier_error)?;
+                self.dt = Some(datetime_from_unixepoch(seconds)?);
+            }
+            Modifier::JulianDay => {
+                number.ok_or_else(number_modifier_error)?;
+                self.dt_mut()?;
+            }
+            Modifier::Auto => {
+                let number = number.ok_or_else(number_modifier_error)?;
+                if !is_julian_day_value(number) {
+                    self.dt = Some(datetime_from_unixepoch(number)?);
+                }
+            }
+            Modifier::Localtime => {
+                if !self.is_local {
+                    let dt = self.dt_mut()?;
+                    let offset = chrono::Local.offset_from_utc_datetime(dt).fix();
+                    *dt = add_delta(*dt, TimeDelta::seconds(offset.local_minus_utc().into()))?;
+                    self.is_local = true;
+                    self.is_utc = false;
+                }
+            }
+            Modifier::Utc => {
+                if !self.is_utc {
+                    let dt = self.dt_mut()?;
+                    *dt = local_to_utc(*dt)?;
+                    self.is_utc = true;
+                    self.is_local = false;
+                }
+            }
+            Modifier::Ceiling => {}
+            Modifier::Floor => {
+                let dt = self.dt_mut()?;
+                *dt = add_delta(*dt, -TimeDelta::days(overflow_days))?;
+            }
+            Modifier::Subsec => self.subsec = true,
+            modifier => self.overflow_days = shift_datetime(self.dt_mut()?, modifier)?,
+        }
+        Ok(())
     }
-    format_dt(*dt, output_type, subsec_requested)
 }
 
 fn format_dt(dt: NaiveDateTime, output_type: DateTimeOutput, subsec: bool) -> Value {
@@ -106,6 +230,15 @@ fn format_dt(dt: NaiveDateTime, output_type: DateTimeOutput, subsec: bool) -> Va
             Value::from_text(strftime_format(&dt, &format_str).as_str())
         }
         DateTimeOutput::JuliaDay => Value::Float(to_julian_day_exact(&dt)),
+        DateTimeOutput::UnixEpoch => {
+            // Like in SQLite, the seconds are truncated towards zero.
+            let millis = dt.and_utc().timestamp_millis();
+            if subsec {
+                Value::Float(millis as f64 / 1000.0)
+            } else {
+                Value::Integer(millis / 1000)
+            }
+        }
     }
 }
 
@@ -134,35 +267,31 @@ fn strftime_format(dt: &NaiveDateTime, format_str: &str) -> String {
     }
 }
 
-// to prevent stripping the modifier string and comparing multiple times, this returns
-// whether the modifier was a subsec modifier because it impacts the format string
-fn apply_modifier(dt: &mut NaiveDateTime, modifier: &str) -> Result<bool> {
-    let parsed_modifier = parse_modifier(modifier)?;
-
-    match parsed_modifier {
-        Modifier::Days(days) => *dt += TimeDelta::days(days),
-        Modifier::Hours(hours) => *dt += TimeDelta::hours(hours),
-        Modifier::Minutes(minutes) => *dt += TimeDelta::minutes(minutes),
-        Modifier::Seconds(seconds) => *dt += TimeDelta::seconds(seconds),
-        Modifier::Months(m) => {
-            // Convert months to years + leftover months
-            let years = m / 12;
-            let leftover = m % 12;
-            add_years_and_months(dt, years, leftover)?;
+/// Applies a modifier that only depends on the date and time it shifts. Returns the days by
+/// which a month or year offset went past the end of the month.
+fn shift_datetime(dt: &mut NaiveDateTime, modifier: Modifier) -> Result<i64> {
+    let mut overflow_days = 0;
+    match modifier {
+        Modifier::Days(days) => *dt = add_delta(*dt, checked_delta(TimeDelta::try_days(days))?)?,
+        Modifier::Hours(hours) => {
+            *dt = add_delta(*dt, checked_delta(TimeDelta::try_hours(hours))?)?
+        }
+        Modifier::Minutes(minutes) => {
+            *dt = add_delta(*dt, checked_delta(TimeDelta::try_minutes(minutes))?)?
         }
-        Modifier::Years(y) => {
-            add_years_and_months(dt, y, 0)?;
+        Modifier::Seconds(seconds) => {
+            *dt = add_delta(*dt, checked_delta(TimeDelta::try_seconds(seconds))?)?
         }
-        Modifier::TimeOffset(offset) => *dt += offset,
+        Modifier::Months(months) => overflow_days = add_years_and_months(dt, 0, months)?,
+        Modifier::Years(years) => overflow_days = add_years_and_months(dt, years, 0)?,
+        Modifier::TimeOffset(offset) => *dt = add_delta(*dt, offset)?,
         Modifier::DateOffset {
             years,
             months,
             days,
         } => {
-            *dt = dt
-                .checked_add_months(chrono::Months::new((years * 12 + months) as u32))
-                .ok_or_else(|| InvalidModifier("Invalid date offset".to_string()))?;
-            *dt += TimeDelta::days(days as i64);
+            overflow_days = add_years_and_months(dt, years, months)?;
+            *dt = add_delta(*dt, TimeDelta::days(days.into()))?;
         }
         Modifier::DateTimeOffset {
             years,
@@ -170,12 +299,10 @@ fn apply_modifier(dt: &mut NaiveDateTime, modifier: &str) -> Result<bool> {
             days,
             seconds,
         } => {
-            add_years_and_months(dt, years, months)?;
-            *dt += chrono::Duration::days(days as i64);
-            *dt += chrono::Duration::seconds(seconds.into());
+            overflow_days = add_years_and_months(dt, years, months)?;
+            *dt = add_delta(*dt, TimeDelta::days(days.into()))?;
+            *dt = add_delta(*dt, TimeDelta::seconds(seconds.into()))?;
         }
-        Modifier::Ceiling => todo!(),
-        Modifier::Floor => todo!(),
         Modifier::StartOfMonth => {
             *dt = NaiveDate::from_ymd_opt(dt.year(), dt.month(), 1)
                 .unwrap()
@@ -195,117 +322,81 @@ fn apply_modifier(dt: &mut NaiveDateTime, modifier: &str) -> Result<bool> {
             let current_day = dt.weekday().num_days_from_sunday();
             let target_day = day;
             let days_to_add = (target_day + 7 - current_day) % 7;
-            *dt += TimeDelta::days(days_to_add as i64);
-        }
-        Modifier::Auto => todo!(), // Will require storing info about the original arg passed when
-        Modifier::UnixEpoch => todo!(), // applying modifiers. All numbers passed to date/time/dt are
-        Modifier::JulianDay => todo!(), // assumed to be julianday, so adding these now is redundant
-        Modifier::Localtime => {
-            let utc_dt = DateTime::<Utc>::from_naive_utc_and_offset(*dt, Utc);
-            *dt = utc_dt.with_timezone(&chrono::Local).naive_local();
+            *dt = add_delta(*dt, TimeDelta::days(days_to_add as i64))?;
         }
-        Modifier::Utc => {
-            // TODO: handle datetime('now', 'utc') no-op
-            let local_dt = chrono::Local.from_local_datetime(dt).unwrap();
-            *dt = local_dt.with_timezone(&Utc).naive_utc();
-        }
-        Modifier::Subsec => {
-            *dt = dt.with_nanosecond(dt.nanosecond()).unwrap();
-            return Ok(true);
-        }
-    }
-
-    Ok(false)
+        Modifier::Ceiling
+        | Modifier::Floor
+        | Modifier::UnixEpoch
+        | Modifier::JulianDay
+        | Modifier::Auto
+        | Modifier::Localtime
+        | Modifier::Utc
+        | Modifier::Subsec => unreachable!("{modifier:?} depends on more than the date and time"),
+    }
+    Ok(overflow_days)
 }
 
-fn is_julian_day_value(value: f64) -> bool {
-    (0.0..5373484.5).contains(&value)
+fn checked_delta(delta: Option<TimeDelta>) -> Result<TimeDelta> {
+    delta.ok_or_else(|| InvalidModifier("Offset out of range".to_string()))
 }
 
-fn add_years_and_months(dt: &mut NaiveDateTime, years: i32, months: i32) -> Result<()> {
-    add_whole_years(dt, years)?;
-    add_months_in_increments(dt, months)?;
-    Ok(())
+fn add_delta(dt: NaiveDateTime, delta: TimeDelta) -> Result<NaiveDateTime> {
+    dt.checked_add_signed(delta)
+        .ok_or_else(|| InvalidModifier("Date and time out of range".to_string()))
 }
 
-fn add_whole_years(dt: &mut NaiveDateTime, years: i32) -> Result<()> {
-    if years == 0 {
-        return Ok(());
-    }
-    let target_year = dt.year() + years;
-    let (m, d, hh, mm, ss) = (dt.month(), dt.day(), dt.hour(), dt.minute(), dt.second());
-
-    // attempt same (month, day) in new year
-    if let Some(date) = NaiveDate::from_ymd_opt(target_year, m, d) {
-        *dt = date
-            .and_hms_opt(hh, mm, ss)
-            .ok_or_else(|| InvalidModifier("Invalid datetime format".to_string()))?;
-        return Ok(());
-    }
-
-    // if invalid: compute overflow days
-    let last_day_in_feb = last_day_in_month(target_year, m);
-    if d > last_day_in_feb {
-        // leftover = d - last_day_in_feb
-        let leftover = d - last_day_in_feb;
-        // base date is last_day_in_feb
-        let base_date = NaiveDate::from_ymd_opt(target_year, m, last_day_in_feb)
-            .ok_or_else(|| InvalidModifier("Invalid datetime format".to_string()))?
-            .and_hms_opt(hh, mm, ss)
-            .ok_or_else(|| InvalidModifier("Invalid time format".to_string()))?;
-
-        *dt = base_date + chrono::Duration::days(leftover as i64);
-    } else {
-        // do we fall back here?
-    }
-    Ok(())
+/// Converts a local date and time to UTC, like the `utc` modifier. The offset of the local
+/// timezone is the one at the resulting UTC time, which is found by refining a first guess
+/// made at the local time, so that times around DST changes convert like in SQLite.
+fn local_to_utc(dt: NaiveDateTime) -> Result<NaiveDateTime> {
+    let offset_at = |utc: NaiveDateTime| {
+        TimeDelta::seconds(
+            chrono::Local
+                .offset_from_utc_datetime(&utc)
+                .fix()
+                .local_minus_utc()
+                .into(),
+        )
+    };
+    let guess = add_delta(dt, -offset_at(dt))?;
+    add_delta(dt, -offset_at(guess))
 }
 
-fn add_months_in_increments(dt: &mut NaiveDateTime, months: i32) -> Result<()> {
-    let step = if months >= 0 { 1 } else { -1 };
-    for _ in 0..months.abs() {
-        add_one_month(dt, step)?;
-    }
-    Ok(())
+/// Converts a number of seconds since 1970-01-01 to a date and time, like the `unixepoch`
+/// modifier.
+fn datetime_from_unixepoch(seconds: f64) -> Result<NaiveDateTime> {
+    // The seconds from the Julian day 0 to 9999-12-31 23:59:59.
+    if !(-210_866_760_000.0..=253_402_300_799.0).contains(&seconds) {
+        return Err(InvalidModifier(format!(
+            "Unix timestamp out of range: {seconds}"
+        )));
+    }
+    DateTime::from_timestamp_millis((seconds * 1000.0).round() as i64)
+        .map(|dt| dt.naive_utc())
+        .ok_or_else(|| InvalidModifier(format!("Unix timestamp out of range: {seconds}")))
 }
 
-// sqlite resolves any ambiguity between advancing months by using the 'ceiling'
-// value, computing overflow days and advancing to the next valid date
-// e.g. 2024-01-31 + 1 month = 2024-03-02
-//
-// the modifiers 'ceiling' and 'floor' will determine behavior, so we'll need to eagerly
-// evaluate modifiers in the future to support those, and 'julianday'/'unixepoch'
-fn add_one_month(dt: &mut NaiveDateTime, step: i32) -> Result<()> {
-    let (y0, m0, d0) = (dt.year(), dt.month(), dt.day());
-    let (hh, mm, ss) = (dt.hour(), dt.minute(), dt.second());
-
-    let mut new_year = y0;
-    let mut new_month = m0 as i32 + step;
-    if new_month > 12 {
-        new_month -= 12;
-        new_year += 1;
-    } else if new_month < 1 {
-        new_month += 12;
-        new_year -= 1;
-    }
-
-    let last_day = last_day_in_month(new_year, new_month as u32);
-    if d0 <= last_day {
-        // valid date
-        *dt = NaiveDate::from_ymd_opt(new_year, new_month as u32, d0)
-            .ok_or_else(|| InvalidModifier("Invalid Auto format".to_string()))?
-            .and_hms_opt(hh, mm, ss)
-            .ok_or_else(|| InvalidModifier("Invalid Auto format".to_string()))?;
-    } else {
-        let leftover = d0 - last_day;
-        let base_date = NaiveDate::from_ymd_opt(new_year, new_month as u32, last_day)
-            .ok_or_else(|| InvalidModifier("Invalid Auto format".to_string()))?
-            .and_hms_opt(hh, mm, ss)
-            .ok_or_else(|| InvalidModifier("Invalid Auto format".to_string()))?;
+fn is_julian_day_value(value: f64) -> bool {
+    (0.0..5373484.5).contains(&value)
+}
 
-        *dt = base_date + chrono::Duration::days(leftover as i64);
-    }
-    Ok(())
+/// Adds years and months like SQLite: the day of the month is kept, and if the resulting month
+/// is shorter, the days past its end carry over into the next month, e.g. 2024-01-31 plus one
+/// month is 2024-03-02. Returns the number of days that carried over.
+fn add_years_and_months(dt: &mut NaiveDateTime, years: i32, months: i32) -> Result<i64> {
+    let total_months = i64::from(dt.year()) * 12
+        + i64::from(dt.month0())
+        + i64::from(years) * 12
+        + i64::from(months);
+    let year = i32::try_from(total_months.div_euclid(12))
+        .map_err(|_| InvalidModifier("Date and time out of range".to_string()))?;
+    let month = total_months.rem_euclid(12) as u32 + 1;
+    let last_day = last_day_in_month(year, month);
+    let overflow_days = i64::from(dt.day().saturating_sub(last_day));
+    let date = NaiveDate::from_ymd_opt(year, month, dt.day().min(last_day))
+        .ok_or_else(|| InvalidModifier("Date and time out of range".to_string()))?;
+    *dt = add_delta(date.and_time(dt.time()), TimeDelta::days(overflow_days))?;
+    Ok(overflow_days)
 }
 
 #[inline(always)]
@@ -349,21 +440,8 @@ fn to_julian_day_exact(dt: &NaiveDateTime) -> f64 {
     jd_days + jd_fraction
 }
 
-pub fn exec_unixepoch(time_value: &Value) -> Result<Value> {
-    let dt = parse_naive_date_time(time_value);
-    match dt {
-        Some(dt) if !is_leap_second(&dt) => {
-            Ok(Value::Integer(get_unixepoch_from_naive_datetime(dt)))
-        }
-        _ => Ok(Value::Null),
-    }
-}
-
-fn get_unixepoch_from_naive_datetime(value: NaiveDateTime) -> i64 {
-    if is_leap_second(&value) {
-        return 0;
-    }
-    value.and_utc().timestamp()
+pub fn exec_unixepoch(values: &[Register]) -> Value {
+    exec_datetime(values, DateTimeOutput::UnixEpoch)
 }
 
 fn parse_naive_date_time(time_value: &Value) -> Option<NaiveDateTime> {
@@ -449,6 +527,20 @@ fn parse_datetime_with_optional_tz(value: &str, format: &str) -> Option<NaiveDat
     None
 }
 
+/// Whether a time value string ends with a timezone, `Z` or `[+-]HH:MM`, in which case it was
+/// converted to UTC when parsed.
+fn has_timezone(value: &str) -> bool {
+    let value = value.trim_end();
+    if value.ends_with('Z') {
+        return true;
+    }
+    let bytes = value.as_bytes();
+    bytes.len() > 6
+        && matches!(bytes[bytes.len() - 6], b'+' | b'-')
+        && bytes[bytes.len() - 3] == b':'
+        && value[..bytes.len() - 6].contains(':')
+}
+
 fn get_date_time_from_time_value_integer(value: i64) -> Option<NaiveDateTime> {
     i32::try_from(value).map_or_else(
         |_| None,
@@ -473,6 +565,14 @@ fn is_leap_second(dt: &NaiveDateTime) -> bool {
     dt.second() == 59 && dt.nanosecond() > 999_999_999
 }
 
+fn get_min_datetime() -> NaiveDateTime {
+    // The Julian day 0, the earliest date in SQLite
+    NaiveDate::from_ymd_opt(-4713, 11, 24)
+        .unwrap()
+        .and_hms_opt(12, 0, 0)
+        .unwrap()
+}
+
 fn get_max_datetime_exclusive() -> NaiveDateTime {
     // The maximum date in SQLite is 9999-12-31
     NaiveDateTime::new(
@@ -569,73 +669,104 @@ fn parse_modifier(modifier: &str) -> Result<Modifier> {
         "localtime" => Ok(Modifier::Localtime),
         "utc" => Ok(Modifier::Utc),
         "subsec" | "subsecond" => Ok(Modifier::Subsec),
-        s if s.ends_with(" day") => Ok(Modifier::Days(parse_modifier_number(&s[..s.len() - 4])?)),
-        s if s.ends_with(" days") => Ok(Modifier::Days(parse_modifier_number(&s[..s.len() - 5])?)),
-        s if s.ends_with(" hour") => Ok(Modifier::Hours(parse_modifier_number(&s[..s.len() - 5])?)),
-        s if s.ends_with(" hours") => {
-            Ok(Modifier::Hours(parse_modifier_number(&s[..s.len() - 6])?))
-        }
-        s if s.ends_with(" minute") => {
-            Ok(Modifier::Minutes(parse_modifier_number(&s[..s.len() - 7])?))
+        s => match parse_unit_offset(s) {
+            Some(offset) => offset,
+            None if s.starts_with('+') || s.starts_with('-') => parse_date_time_offset(s),
+            None => Err(InvalidModifier(
+                "Invalid date/time offset format".to_string(),
+            )),
+        },
+    }
+}
+
+/// Parses an offset like `+5 days` or `-1.5 hours`. Returns None if `s` doesn't end with a unit.
+fn parse_unit_offset(s: &str) -> Option<Result<Modifier>> {
+    let (number, unit) = s.rsplit_once(' ')?;
+    let unit = unit.strip_suffix('s').unwrap_or(unit);
+    let unit_millis = match unit {
+        "day" => 86_400_000.0,
+        "hour" => 3_600_000.0,
+        "minute" => 60_000.0,
+        "second" => 1_000.0,
+        "month" | "year" => 0.0,
+        _ => return None,
+    };
+    let amount = match number.trim().parse::<f64>() {
+        Ok(amount) if amount.is_finite() => amount,
+        _ => return Some(Err(InvalidModifier(format!("Invalid number: {number}")))),
+    };
+    let out_of_range = || InvalidModifier(format!("Offset out of range: {amount}"));
+    let offset = if amount.fract() == 0.0 && amount.abs() < i32::MAX as f64 {
+        let amount = amount as i32;
+        match unit {
+            "day" => Modifier::Days(amount.into()),
+            "hour" => Modifier::Hours(amount.into()),
+            "minute" => Modifier::Minutes(amount.into()),
+            "second" => Modifier::Seconds(amount.into()),
+            "month" => Modifier::Months(amount),
+            _ => Modifier::Years(amount),
         }
-        s if s.ends_with(" minutes") => {
-            Ok(Modifier::Minutes(parse_modifier_number(&s[..s.len() - 8])?))
+    } else if unit_millis > 0.0 {
+        match TimeDelta::try_milliseconds((amount * unit_millis).round() as i64) {
+            Some(delta) => Modifier::TimeOffset(delta),
+            None => return Some(Err(out_of_range())),
         }
-        s if s.ends_with(" second") => {
-            Ok(Modifier::Seconds(parse_modifier_number(&s[..s.len() - 7])?))
+    } else {
+        // Like in SQLite, the fraction of a month counts as 30 days, and of a year as 365 days.
+        let whole = amount.trunc();
+        if whole.abs() >= i32::MAX as f64 {
+            return Some(Err(out_of_range()));
         }
-        s if s.ends_with(" seconds") => {
-            Ok(Modifier::Seconds(parse_modifier_number(&s[..s.len() - 8])?))
+        let days_in_unit = if unit == "month" { 30.0 } else { 365.0 };
+        let seconds = ((amount - whole) * days_in_unit * 86_400.0).round() as i32;
+        let (years, months) = if unit == "month" {
+            (0, whole as i32)
+        } else {
+            (whole as i32, 0)
+        };
+        Modifier::DateTimeOffset {
+            years,
+            months,
+            days: 0,
+            seconds,
         }
-        s if s.ends_with(" month") => Ok(Modifier::Months(
-            parse_modifier_number(&s[..s.len() - 6])? as i32,
-        )),
-        s if s.ends_with(" months") => Ok(Modifier::Months(
-            parse_modifier_number(&s[..s.len() - 7])? as i32,
-        )),
-        s if s.ends_with(" year") => Ok(Modifier::Years(
-            parse_modifier_number(&s[..s.len() - 5])? as i32
-        )),
-        s if s.ends_with(" years") => Ok(Modifier::Years(
-            parse_modifier_number(&s[..s.len() - 6])? as i32,
-        )),
-        s if s.starts_with('+') || s.starts_with('-') => {
-            let sign = if s.starts_with('-') { -1 } else { 1 };
-            let parts: Vec<&str> = s[1..].split(' ').collect();
-            let digits_in_date = 10;
-            match parts.len() {
-                1 => {
-                    if parts[0].len() == digits_in_date {
-                        let date = parse_modifier_date(parts[0])?;
-                        Ok(Modifier::DateOffset {
-                            years: sign * date.year(),
-                            months: sign * date.month() as i32,
-                            days: sign * date.day() as i32,
-                        })
-                    } else {
-                        // time values are either 12, 8 or 5 digits
-                        let time = parse_modifier_time(parts[0])?;
-                        let time_delta = sign * (time.num_seconds_from_midnight() as i32);
-                        Ok(Modifier::TimeOffset(TimeDelta::seconds(time_delta.into())))
-                    }
-                }
-                2 => {
-                    let date = parse_modifier_date(parts[0])?;
-                    let time = parse_modifier_time(parts[1])?;
-                    // Convert time to total seconds (with sign)
-                    let time_delta = sign * (time.num_seconds_from_midnight() as i32);
-                    Ok(Modifier::DateTimeOffset {
-                        years: sign * (date.year()),
-                        months: sign * (date.month() as i32),
-                        days: sign * date.day() as i32,
-                        seconds: time_delta,
-                    })
-                }
-                _ => Err(InvalidModifier(
-                    "Invalid date/time offset format".to_string(),
-                )),
+    };
+    Some(Ok(offset))
+}
+
+/// Parses an offset like `+YYYY-MM-DD`, `-HH:MM:SS.SSS` or `+YYYY-MM-DD HH:MM`.
+fn parse_date_time_offset(s: &str) -> Result<Modifier> {
+    let sign = if s.starts_with('-') { -1 } else { 1 };
+    let parts: Vec<&str> = s[1..].split(' ').collect();
+    let digits_in_date = 10;
+    match parts.len() {
+        1 => {
+            if parts[0].len() == digits_in_date {
+                let date = parse_modifier_date(parts[0])?;
+                Ok(Modifier::DateOffset {
+                    years: sign * date.year(),
+                    months: sign * date.month() as i32,
+                    days: sign * date.day() as i32,
+                })
+            } else {
+                // time values are either 12, 8 or 5 digits
+                let time = parse_modifier_time(parts[0])?;
+                let time_delta = time.signed_duration_since(NaiveTime::MIN);
+                Ok(Modifier::TimeOffset(time_delta * sign))
             }
         }
+        2 => {
+            let date = parse_modifier_date(parts[0])?;
+            let time = parse_modifier_time(parts[1])?;
+            // Convert time to total seconds (with sign)
+            let time_delta = sign * (time.num_seconds_from_midnight() as i32);
+            Ok(Modifier::DateTimeOffset {
+                years: sign * (date.year()),
+                months: sign * (date.month() as i32),
+                days: sign * date.day() as i32,
+                seconds: time_delta,
+            })
+        }
         _ => Err(InvalidModifier(
             "Invalid date/time offset format".to_string(),
         )),
@@ -697,6 +828,13 @@ fn format_time_duration(duration: &chrono::Duration) -> Value {
 mod tests {
     use super::*;
 
+    fn apply_modifier(dt: &mut NaiveDateTime, modifier: &str) -> Result<()> {
+        let mut time_value = TimeValue::new(Some(*dt), None, false);
+        time_value.apply_modifier(modifier, false)?;
+        *dt = time_value.dt.unwrap();
+        Ok(())
+    }
+
     #[test]
     fn test_valid_get_date_from_time_value() {
         let now = chrono::Local::now().to_utc().format("%Y-%m-%d").to_string();
@@ -847,10 +985,7 @@ mod tests {
 
         for case in invalid_cases.iter() {
             let result = exec_date(&[Register::Value(case.clone())]);
-            match result {
-                Value::Text(ref result_str) if result_str.value.is_empty() => (),
-                _ => panic!("Expected empty string for input: {case:?}, but got: {result:?}"),
-            }
+            assert_eq!(result, Value::Null, "Expected NULL for input: {case:?}");
         }
     }
 
@@ -979,10 +1114,7 @@ mod tests {
 
         for case in invalid_cases {
             let result = exec_time(&[Register::Value(case.clone())]);
-            match result {
-                Value::Text(ref result_str) if result_str.value.is_empty() => (),
-                _ => panic!("Expected empty string for input: {case:?}, but got: {result:?}"),
-            }
+            assert_eq!(result, Value::Null, "Expected NULL for input: {case:?}");
         }
     }
 
@@ -1062,7 +1194,10 @@ mod tests {
         assert_eq!(
             parse_modifier("+02:15:30.250").unwrap(),
             Modifier::TimeOffset(
-                TimeDelta::hours(2) + TimeDelta::minutes(15) + TimeDelta::seconds(30)
+                TimeDelta::hours(2)
+                    + TimeDelta::minutes(15)
+                    + TimeDelta::seconds(30)
+                    + TimeDelta::milliseconds(250)
             )
         );
     }
@@ -1443,9 +1578,9 @@ mod tests {
             .unwrap()
             .and_hms_nano_opt(23, 59, 59, 1_500_000_000)
             .unwrap();
-        let expected = String::new(); // SQLite ignores leap seconds
+        // SQLite ignores leap seconds
         let result = exec_datetime(&[text(&leap_second.to_string())], DateTimeOutput::DateTime);
-        assert_eq!(result, *text(&expected).get_owned_value());
+        assert_eq!(result, Value::Null);
     }
 
     #[test]
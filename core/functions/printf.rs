@@ -2,19 +2,26 @@ use crate::types::Value;
 use crate::vdbe::Register;
 use crate::LimboError;
 
-// TODO: Support %!.3s %i, %x, %X, %o, %e, %E, %c. flags: - + 0 ! ,
-#[inline(always)]
+/// The largest string printf() builds, like SQLite's default `SQLITE_MAX_LENGTH`.
+const MAX_LENGTH: usize = 1_000_000_000;
+
+/// Formats its arguments like SQLite's printf() and format(), which support the conversions of
+/// C's printf with SQLite's extensions (`%q`, `%Q`, `%w`, `%r` and the `!` and `,` flags).
+/// Arguments missing for a conversion count as NULL, and an unknown conversion ends the output.
 pub fn exec_printf(values: &[Register]) -> crate::Result<Value> {
     if values.is_empty() {
         return Ok(Value::Null);
     }
     let format_str = match &values[0].get_owned_value() {
-        Value::Text(t) => t.as_str(),
-        _ => return Ok(Value::Null),
+        Value::Null => return Ok(Value::Null),
+        value => value.to_string(),
     };
 
     let mut result = String::new();
-    let mut args_index = 1;
+    let mut args = Arguments {
+        values: &values[1..],
+        next: 0,
+    };
     let mut chars = format_str.chars().peekable();
 
     while let Some(c) = chars.next() {
@@ -23,61 +30,421 @@ pub fn exec_printf(values: &[Register]) -> crate::Result<Value> {
             continue;
         }
 
-        match chars.next() {
-            Some('%') => {
-                result.push('%');
-                continue;
-            }
-            Some('d') => {
-                if args_index >= values.len() {
-                    return Err(LimboError::InvalidArgument("not enough arguments".into()));
-                }
-                let value = &values[args_index].get_owned_value();
-                match value {
-                    Value::Integer(_) => result.push_str(&format!("{value}")),
-                    Value::Float(_) => result.push_str(&format!("{value}")),
-                    _ => result.push('0'),
-                }
-                args_index += 1;
-            }
-            Some('s') => {
-                if args_index >= values.len() {
-                    return Err(LimboError::InvalidArgument("not enough arguments".into()));
-                }
-                match &values[args_index].get_owned_value() {
-                    Value::Text(t) => result.push_str(t.as_str()),
-                    Value::Null => result.push_str("(null)"),
-                    v => result.push_str(&format!("{v}")),
-                }
-                args_index += 1;
-            }
-            Some('f') => {
-                if args_index >= values.len() {
-                    return Err(LimboError::InvalidArgument("not enough arguments".into()));
-                }
-                let value = &values[args_index].get_owned_value();
-                match value {
-                    Value::Float(f) => result.push_str(&format!("{f:.6}")),
-                    Value::Integer(i) => result.push_str(&format!("{:.6}", *i as f64)),
-                    _ => result.push_str("0.0"),
-                }
-                args_index += 1;
+        let mut spec = Spec::default();
+        while let Some(&flag) = chars.peek() {
+            match flag {
+                '-' => spec.left_justify = true,
+                '+' => spec.plus_sign = true,
+                ' ' => spec.blank_sign = true,
+                '#' => spec.alternate_form = true,
+                '!' => spec.alternate_form_2 = true,
+                '0' => spec.zero_pad = true,
+                ',' => spec.thousands = true,
+                _ => break,
             }
-            None => {
-                return Err(LimboError::InvalidArgument(
-                    "incomplete format specifier".into(),
-                ))
+            chars.next();
+        }
+
+        if chars.peek() == Some(&'*') {
+            chars.next();
+            let width = args.integer();
+            if width < 0 {
+                spec.left_justify = true;
             }
-            _ => {
-                return Err(LimboError::InvalidFormatter(
-                    "this formatter is not supported".into(),
-                ));
+            spec.width = checked_length(width.unsigned_abs())?;
+        } else {
+            spec.width = checked_length(parse_number(&mut chars))?;
+        }
+
+        if chars.peek() == Some(&'.') {
+            chars.next();
+            let precision = if chars.peek() == Some(&'*') {
+                chars.next();
+                args.integer().unsigned_abs()
+            } else {
+                parse_number(&mut chars)
+            };
+            spec.precision = Some(checked_length(precision)?);
+        }
+
+        // The length modifiers of C's printf make no difference to SQL values.
+        while chars.peek() == Some(&'l') {
+            chars.next();
+        }
+
+        let Some(conversion) = chars.next() else {
+            result.push('%');
+            break;
+        };
+        let formatted = match conversion {
+            '%' => "%".to_string(),
+            'd' | 'i' | 'u' | 'x' | 'X' | 'o' | 'p' | 'r' => {
+                format_integer(&spec, conversion, args.integer())
             }
+            'f' | 'e' | 'E' | 'g' | 'G' => format_float(&spec, conversion, args.float()),
+            's' | 'z' => format_string(&spec, args.text().unwrap_or_default()),
+            'c' => format_char(&spec, args.text()),
+            'q' | 'Q' | 'w' => format_escaped(&spec, conversion, args.text()),
+            // `%n` stores the length of the output in C, which means nothing in SQL.
+            'n' => String::new(),
+            _ => break,
+        };
+        if result.len() + formatted.len() > MAX_LENGTH {
+            return Err(LimboError::TooBig("printf() result".to_string()));
         }
+        result.push_str(&formatted);
     }
     Ok(Value::build_text(result))
 }
 
+/// The flags, width and precision of a conversion.
+#[derive(Default)]
+struct Spec {
+    left_justify: bool,
+    plus_sign: bool,
+    blank_sign: bool,
+    alternate_form: bool,
+    /// The `!` flag, which counts string widths and precisions in characters rather than bytes,
+    /// and shows up to 26 significant digits of floats rather than 16.
+    alternate_form_2: bool,
+    zero_pad: bool,
+    /// The `,` flag, which separates the thousands of decimal integers.
+    thousands: bool,
+    width: usize,
+    precision: Option<usize>,
+}
+
+/// The arguments of printf() that follow its format, converted for each conversion like
+/// `sqlite3_value_int64()`, `sqlite3_value_double()` and `sqlite3_value_text()` would.
+struct Arguments<'a> {
+    values: &'a [Register],
+    next: usize,
+}
+
+impl Arguments<'_> {
+    fn next_value(&mut self) -> Option<&Value> {
+        let value = self.values.get(self.next)?.get_owned_value();
+        self.next += 1;
+        Some(value)
+    }
+
+    fn integer(&mut self) -> i64 {
+        match self.next_value().map(|value| value.exec_cast("INTEGER")) {
+            Some(Value::Integer(i)) => i,
+            _ => 0,
+        }
+    }
+
+    fn float(&mut self) -> f64 {
+        match self.next_value().map(|value| value.exec_cast("REAL")) {
+            Some(Value::Float(f)) => f,
+            _ => 0.0,
+        }
+    }
+
+    fn text(&mut self) -> Option<String> {
+        match self.next_value()? {
+            Value::Null => None,
+            Value::Text(t) => Some(t.as_str().to_string()),
+            Value::Blob(b) => Some(String::from_utf8_lossy(b).into_owned()),
+            value => Some(value.to_string()),
+        }
+    }
+}
+
+fn parse_number(chars: &mut std::iter::Peekable<std::str::Chars>) -> u64 {
+    let mut number: u64 = 0;
+    while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+        number = number.saturating_mul(10).saturating_add(digit.into());
+        chars.next();
+    }
+    number
+}
+
+fn checked_length(length: u64) -> crate::Result<usize> {
+    match usize::try_from(length) {
+        Ok(length) if length <= MAX_LENGTH => Ok(length),
+        _ => Err(LimboError::TooBig(
+            "printf() width or precision".to_string(),
+        )),
+    }
+}
+
+fn format_integer(spec: &Spec, conversion: char, value: i64) -> String {
+    let signed = matches!(conversion, 'd' | 'i' | 'r');
+    let (sign, magnitude) = if !signed {
+        ("", value as u64)
+    } else if value < 0 {
+        ("-", value.unsigned_abs())
+    } else if spec.plus_sign {
+        ("+", value as u64)
+    } else if spec.blank_sign {
+        (" ", value as u64)
+    } else {
+        ("", value as u64)
+    };
+
+    let mut digits = match conversion {
+        'x' => format!("{magnitude:x}"),
+        'X' | 'p' => format!("{magnitude:X}"),
+        'o' => format!("{magnitude:o}"),
+        _ => magnitude.to_string(),
+    };
+    // Like in SQLite, zero padding pads the digits up to the width, as a precision would.
+    let mut precision = spec.precision.unwrap_or(0);
+    if spec.zero_pad {
+        precision = precision.max(spec.width.saturating_sub(sign.len()));
+    }
+    if digits.len() < precision {
+        digits.insert_str(0, &"0".repeat(precision - digits.len()));
+    }
+    if spec.thousands && matches!(conversion, 'd' | 'i' | 'u' | 'r') {
+        digits = separate_thousands(&digits);
+    }
+    if conversion == 'r' {
+        let last_digit = magnitude % 10;
+        let suffix = if last_digit >= 4 || (magnitude / 10) % 10 == 1 {
+            "th"
+        } else {
+            ["th", "st", "nd", "rd"][last_digit as usize]
+        };
+        digits.push_str(suffix);
+    }
+
+    let prefix = match conversion {
+        _ if !spec.alternate_form || magnitude == 0 => "",
+        'o' if !digits.starts_with('0') => "0",
+        'x' => "0x",
+        'X' => "0X",
+        _ => "",
+    };
+    pad(format!("{prefix}{sign}{digits}"), spec, false)
+}
+
+fn separate_thousands(digits: &str) -> String {
+    let mut separated = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            separated.push(',');
+        }
+        separated.push(digit);
+    }
+    separated
+}
+
+fn format_float(spec: &Spec, conversion: char, value: f64) -> String {
+    let sign = if value < 0.0 {
+        "-"
+    } else if spec.plus_sign {
+        "+"
+    } else if spec.blank_sign {
+        " "
+    } else {
+        ""
+    };
+    if value.is_nan() {
+        return pad(
+            if spec.zero_pad { "null" } else { "NaN" }.to_string(),
+            spec,
+            false,
+        );
+    }
+    if value.is_infinite() {
+        let body = if spec.zero_pad { "9.0e999" } else { "Inf" };
+        return pad(format!("{sign}{body}"), spec, false);
+    }
+
+    let value = value.abs();
+    let precision = spec.precision.unwrap_or(6);
+    // Like SQLite, show at most 16 significant digits, or 26 with the `!` flag, and zeros
+    // past them.
+    let max_digits = if spec.alternate_form_2 { 26 } else { 16 };
+    let upper = conversion.is_ascii_uppercase();
+    let mut body = match conversion {
+        'f' => format_fixed(value, precision, max_digits),
+        'e' | 'E' => format_exponential(value, precision, max_digits, upper),
+        _ => {
+            let precision = precision.max(1);
+            let (_, exponent) = significant_digits(value, precision.min(max_digits));
+            let mut body = if exponent < -4 || exponent >= precision as i32 {
+                format_exponential(value, precision - 1, max_digits, upper)
+            } else {
+                format_fixed(
+                    value,
+                    (precision as i32 - 1 - exponent) as usize,
+                    max_digits,
+                )
+            };
+            if !spec.alternate_form {
+                body = remove_trailing_zeros(&body);
+            }
+            body
+        }
+    };
+
+    if spec.alternate_form || spec.alternate_form_2 {
+        let exponent_at = body.find(['e', 'E']).unwrap_or(body.len());
+        let (mantissa, exponent) = body.split_at(exponent_at);
+        let mut mantissa = mantissa.to_string();
+        if !mantissa.contains('.') {
+            mantissa.push('.');
+        }
+        if spec.alternate_form_2 && mantissa.ends_with('.') {
+            mantissa.push('0');
+        }
+        body = mantissa + exponent;
+    }
+
+    if spec.zero_pad && !spec.left_justify {
+        let length = sign.len() + body.len();
+        if length < spec.width {
+            body.insert_str(0, &"0".repeat(spec.width - length));
+        }
+    }
+    pad(format!("{sign}{body}"), spec, false)
+}
+
+/// Rounds a positive float to `count` significant digits, and returns them with the decimal
+/// exponent of the first.
+fn significant_digits(value: f64, count: usize) -> (Vec<u8>, i32) {
+    let formatted = format!("{:.*e}", count.max(1) - 1, value);
+    let (mantissa, exponent) = formatted
+        .split_once('e')
+        .expect("floats format with an exponent");
+    let digits = mantissa.bytes().filter(u8::is_ascii_digit).collect();
+    (digits, exponent.parse().unwrap_or(0))
+}
+
+fn format_fixed(value: f64, precision: usize, max_digits: usize) -> String {
+    let formatted = format!("{value:.precision$}");
+    let count = formatted
+        .bytes()
+        .filter(u8::is_ascii_digit)
+        .skip_while(|digit| *digit == b'0')
+        .count();
+    if count <= max_digits {
+        return formatted;
+    }
+    let (digits, exponent) = significant_digits(value, max_digits);
+    let digit_at = |i: i64| -> char {
+        usize::try_from(i)
+            .ok()
+            .and_then(|i| digits.get(i))
+            .map_or('0', |digit| *digit as char)
+    };
+    let exponent = i64::from(exponent);
+    let mut fixed: String = if exponent >= 0 {
+        (0..=exponent).map(digit_at).collect()
+    } else {
+        "0".to_string()
+    };
+    if precision > 0 {
+        fixed.push('.');
+        fixed.extend((1..=precision as i64).map(|place| digit_at(exponent + place)));
+    }
+    fixed
+}
+
+fn format_exponential(value: f64, precision: usize, max_digits: usize, upper: bool) -> String {
+    let (mut digits, exponent) = significant_digits(value, (precision + 1).min(max_digits));
+    digits.resize(precision + 1, b'0');
+    let mut formatted = String::with_capacity(precision + 6);
+    formatted.push(digits[0] as char);
+    if precision > 0 {
+        formatted.push('.');
+        formatted.extend(digits[1..].iter().map(|digit| *digit as char));
+    }
+    formatted.push(if upper { 'E' } else { 'e' });
+    formatted.push(if exponent < 0 { '-' } else { '+' });
+    formatted.push_str(&format!("{:02}", exponent.unsigned_abs()));
+    formatted
+}
+
+/// Removes the trailing zeros of the fraction of a `%g` conversion, and its decimal point if
+/// nothing is left of it.
+fn remove_trailing_zeros(formatted: &str) -> String {
+    let exponent_at = formatted.find(['e', 'E']).unwrap_or(formatted.len());
+    let (mantissa, exponent) = formatted.split_at(exponent_at);
+    if !mantissa.contains('.') {
+        return formatted.to_string();
+    }
+    let mantissa = mantissa.trim_end_matches('0').trim_end_matches('.');
+    format!("{mantissa}{exponent}")
+}
+
+/// Cuts a string to the precision, which counts bytes, or characters with the `!` flag.
+fn truncate(text: &str, spec: &Spec) -> String {
+    let Some(precision) = spec.precision else {
+        return text.to_string();
+    };
+    if spec.alternate_form_2 {
+        return text.chars().take(precision).collect();
+    }
+    let mut end = precision.min(text.len());
+    while !text.is_char_boundary(end) {
+        end -= 1;
+    }
+    text[..end].to_string()
+}
+
+fn format_string(spec: &Spec, text: String) -> String {
+    pad(truncate(&text, spec), spec, spec.alternate_form_2)
+}
+
+fn format_char(spec: &Spec, text: Option<String>) -> String {
+    let Some(c) = text.and_then(|text| text.chars().next()) else {
+        return pad(String::new(), spec, spec.alternate_form_2);
+    };
+    let count = spec.precision.unwrap_or(1).max(1);
+    pad(c.to_string().repeat(count), spec, spec.alternate_form_2)
+}
+
+/// Formats `%q` and `%Q`, which escape the quotes of SQL strings, and `%w`, which escapes the
+/// quotes of SQL identifiers.
+fn format_escaped(spec: &Spec, conversion: char, text: Option<String>) -> String {
+    let Some(text) = text else {
+        let null = if conversion == 'Q' { "NULL" } else { "(NULL)" };
+        return pad(null.to_string(), spec, spec.alternate_form_2);
+    };
+    let quote = if conversion == 'w' { '"' } else { '\'' };
+    let text: String = match spec.precision {
+        Some(precision) => text.chars().take(precision).collect(),
+        None => text,
+    };
+    let mut escaped = String::with_capacity(text.len() + 2);
+    if conversion == 'Q' {
+        escaped.push('\'');
+    }
+    for c in text.chars() {
+        if c == quote {
+            escaped.push(c);
+        }
+        escaped.push(c);
+    }
+    if conversion == 'Q' {
+        escaped.push('\'');
+    }
+    pad(escaped, spec, spec.alternate_form_2)
+}
+
+/// Pads a conversion with spaces up to the width, which counts bytes unless `count_chars`.
+fn pad(formatted: String, spec: &Spec, count_chars: bool) -> String {
+    let length = if count_chars {
+        formatted.chars().count()
+    } else {
+        formatted.len()
+    };
+    if length >= spec.width {
+        return formatted;
+    }
+    let padding = " ".repeat(spec.width - length);
+    if spec.left_justify {
+        formatted + &padding
+    } else {
+        padding + &formatted
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -123,7 +490,7 @@ mod tests {
             // String with null value
             (
                 vec![text("Hello, %s!"), Register::Value(Value::Null)],
-                text("Hello, (null)!"),
+                text("Hello, !"),
             ),
             // String with number conversion
             (vec![text("Value: %s"), integer(42)], text("Value: 42")),
@@ -184,7 +551,7 @@ mod tests {
             // Non-numeric value defaults to 0.0
             (
                 vec![text("Number: %f"), text("not a number")],
-                text("Number: 0.0"),
+                text("Number: 0.000000"),
             ),
         ];
 
@@ -229,18 +596,105 @@ mod tests {
     }
 
     #[test]
-    fn test_printf_error_cases() {
-        let error_cases = vec![
-            // Not enough arguments
-            vec![text("%d %d"), integer(42)],
-            // Invalid format string
-            vec![text("%z"), integer(42)],
-            // Incomplete format specifier
-            vec![text("incomplete %")],
+    fn test_printf_missing_arguments_and_invalid_conversions() {
+        let test_cases = vec![
+            // Missing arguments count as NULL
+            (vec![text("%d %d"), integer(42)], text("42 0")),
+            (vec![text("[%s]")], text("[]")),
+            // %z is the same as %s
+            (vec![text("%z"), integer(42)], text("42")),
+            // A trailing percent sign is kept
+            (vec![text("incomplete %")], text("incomplete %")),
+            // An unknown conversion ends the output
+            (vec![text("a%yb"), integer(42)], text("a")),
         ];
 
-        for case in error_cases {
-            assert!(exec_printf(&case).is_err());
+        for (input, expected) in test_cases {
+            assert_eq!(exec_printf(&input).unwrap(), *expected.get_owned_value());
+        }
+    }
+
+    #[test]
+    fn test_printf_flags_width_and_precision() {
+        let test_cases = vec![
+            (
+                vec![text("%5d|%-5d|%05d"), integer(42), integer(42), integer(42)],
+                text("   42|42   |00042"),
+            ),
+            (vec![text("%+d % d"), integer(5), integer(5)], text("+5  5")),
+            (vec![text("%*d"), integer(5), integer(42)], text("   42")),
+            (vec![text("%-*d|"), integer(4), integer(7)], text("7   |")),
+            (vec![text("%,d"), integer(1234567)], text("1,234,567")),
+            (vec![text("%,d"), integer(-1234)], text("-1,234")),
+            (vec![text("%.3f"), float(1.23456)], text("1.235")),
+            (vec![text("%10.2f|"), float(1.23456)], text("      1.23|")),
+            (vec![text("%08.2f"), float(-1.23456)], text("-0001.23")),
+            (vec![text("%.2s"), text("hello")], text("he")),
+            (vec![text("%-6s|"), text("ab")], text("ab    |")),
+            (vec![text("%d"), text("12abc")], text("12")),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(exec_printf(&input).unwrap(), *expected.get_owned_value());
+        }
+    }
+
+    #[test]
+    fn test_printf_conversions() {
+        let test_cases = vec![
+            (
+                vec![
+                    text("%x %X %o %#x %#o"),
+                    integer(255),
+                    integer(255),
+                    integer(8),
+                    integer(255),
+                    integer(8),
+                ],
+                text("ff FF 10 0xff 010"),
+            ),
+            (vec![text("%u"), integer(-1)], text("18446744073709551615")),
+            (vec![text("%e"), float(12345.678)], text("1.234568e+04")),
+            (vec![text("%.2E"), float(0.000123)], text("1.23E-04")),
+            (
+                vec![
+                    text("%g %g %g %g"),
+                    float(100000.0),
+                    float(1000000.0),
+                    float(0.0001),
+                    float(2.5),
+                ],
+                text("100000 1e+06 0.0001 2.5"),
+            ),
+            (
+                vec![text("%.20f"), float(0.1)],
+                text("0.10000000000000000000"),
+            ),
+            (vec![text("%c"), text("xyz")], text("x")),
+            (vec![text("%.3c"), text("x")], text("xxx")),
+            (
+                vec![
+                    text("%r %r %r %r %r"),
+                    integer(1),
+                    integer(2),
+                    integer(3),
+                    integer(11),
+                    integer(22),
+                ],
+                text("1st 2nd 3rd 11th 22nd"),
+            ),
+            (vec![text("%q"), text("it's")], text("it''s")),
+            (vec![text("%Q"), text("it's")], text("'it''s'")),
+            (
+                vec![text("%Q %q"), Register::Value(Value::Null)],
+                text("NULL (NULL)"),
+            ),
+            (vec![text("%w"), text("a\"b")], text("a\"\"b")),
+            (vec![text("%f"), float(f64::INFINITY)], text("Inf")),
+        ];
+
+        for (input, expected) in test_cases {
+            assert_eq!(exec_printf(&input).unwrap(), *expected.get_owned_value());
         }
     }
 
@@ -124,6 +124,14 @@ impl File for GenericFile {
         Ok(c)
     }
 
+    fn truncate(&self, len: usize, c: Arc<Completion>) -> Result<Arc<Completion>> {
+        let file = self.file.borrow_mut();
+        file.set_len(len as u64)
+            .map_err(|err| LimboError::IOError(err))?;
+        c.complete(0);
+        Ok(c)
+    }
+
     fn size(&self) -> Result<u64> {
         let file = self.file.borrow();
         Ok(file.metadata().unwrap().len())
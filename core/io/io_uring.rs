@@ -361,9 +361,21 @@ impl File for UringFile {
         Ok(c)
     }
 
+    fn truncate(&self, len: usize, c: Arc<Completion>) -> Result<Arc<Completion>> {
+        trace!("truncate(len = {})", len);
+        // Not every kernel supports truncating through the ring, so this one is synchronous.
+        fs::ftruncate(self.file.as_fd(), len as u64)?;
+        c.complete(0);
+        Ok(c)
+    }
+
     fn size(&self) -> Result<u64> {
         Ok(self.file.metadata()?.len())
     }
+
+    fn punch_hole(&self, pos: usize, len: usize) -> Result<()> {
+        super::unix::punch_hole(self.file.as_fd(), pos, len)
+    }
 }
 
 impl Drop for UringFile {
@@ -171,6 +171,21 @@ impl File for MemoryFile {
         Ok(c)
     }
 
+    fn truncate(&self, len: usize, c: Arc<Completion>) -> Result<Arc<Completion>> {
+        if len < self.size.get() {
+            let pages = unsafe { &mut *self.pages.get() };
+            // Drop the pages past the end and zero the tail of the last one, so that growing the
+            // file again reads zeroes like it would on disk.
+            pages.retain(|page_no, _| page_no * PAGE_SIZE < len);
+            if let Some(page) = pages.get_mut(&(len / PAGE_SIZE)) {
+                page[len % PAGE_SIZE..].fill(0);
+            }
+        }
+        self.size.set(len);
+        c.complete(0);
+        Ok(c)
+    }
+
     fn size(&self) -> Result<u64> {
         Ok(self.size.get() as u64)
     }
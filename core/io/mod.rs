@@ -22,7 +22,13 @@ pub trait File: Send + Sync {
         c: Arc<Completion>,
     ) -> Result<Arc<Completion>>;
     fn sync(&self, c: Arc<Completion>) -> Result<Arc<Completion>>;
+    fn truncate(&self, len: usize, c: Arc<Completion>) -> Result<Arc<Completion>>;
     fn size(&self) -> Result<u64>;
+    /// Deallocates the storage of `len` bytes at `pos`, which must already be zeros, where the
+    /// file system supports it. The size of the file doesn't change.
+    fn punch_hole(&self, _pos: usize, _len: usize) -> Result<()> {
+        Ok(())
+    }
 }
 
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -57,6 +63,7 @@ pub trait IO: Clock + Send + Sync {
 pub type Complete = dyn Fn(Arc<RefCell<Buffer>>, i32);
 pub type WriteComplete = dyn Fn(i32);
 pub type SyncComplete = dyn Fn(i32);
+pub type TruncateComplete = dyn Fn(i32);
 
 pub struct Completion {
     pub completion_type: CompletionType,
@@ -67,6 +74,7 @@ pub enum CompletionType {
     Read(ReadCompletion),
     Write(WriteCompletion),
     Sync(SyncCompletion),
+    Truncate(TruncateCompletion),
 }
 
 pub struct ReadCompletion {
@@ -109,6 +117,15 @@ impl Completion {
         ))))
     }
 
+    pub fn new_trunc<F>(complete: F) -> Self
+    where
+        F: Fn(i32) + 'static,
+    {
+        Self::new(CompletionType::Truncate(TruncateCompletion::new(Box::new(
+            complete,
+        ))))
+    }
+
     pub fn is_completed(&self) -> bool {
         self.is_completed.get()
     }
@@ -118,6 +135,7 @@ impl Completion {
             CompletionType::Read(r) => r.complete(result),
             CompletionType::Write(w) => w.complete(result),
             CompletionType::Sync(s) => s.complete(result), // fix
+            CompletionType::Truncate(t) => t.complete(result),
         };
         self.is_completed.set(true);
     }
@@ -140,6 +158,10 @@ pub struct SyncCompletion {
     pub complete: Box<SyncComplete>,
 }
 
+pub struct TruncateCompletion {
+    pub complete: Box<TruncateComplete>,
+}
+
 impl ReadCompletion {
     pub fn new(buf: Arc<RefCell<Buffer>>, complete: Box<Complete>) -> Self {
         Self { buf, complete }
@@ -178,6 +200,16 @@ impl SyncCompletion {
     }
 }
 
+impl TruncateCompletion {
+    pub fn new(complete: Box<TruncateComplete>) -> Self {
+        Self { complete }
+    }
+
+    pub fn complete(&self, res: i32) {
+        (self.complete)(res);
+    }
+}
+
 pub type BufferData = Pin<Vec<u8>>;
 
 pub type BufferDropFn = Rc<dyn Fn(BufferData)>;
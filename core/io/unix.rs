@@ -6,7 +6,7 @@ use super::{Completion, File, MemoryIO, OpenFlags, IO};
 use crate::io::clock::{Clock, Instant};
 use polling::{Event, Events, Poller};
 use rustix::{
-    fd::{AsFd, AsRawFd},
+    fd::{AsFd, AsRawFd, BorrowedFd},
     fs::{self, FlockOperation, OFlags, OpenOptionsExt},
     io::Errno,
 };
@@ -439,11 +439,43 @@ impl File for UnixFile<'_> {
         }
     }
 
+    #[instrument(err, skip_all, level = Level::TRACE)]
+    fn truncate(&self, len: usize, c: Arc<Completion>) -> Result<Arc<Completion>> {
+        let file = self.file.borrow();
+        fs::ftruncate(file.as_fd(), len as u64)?;
+        trace!("ftruncate(len = {})", len);
+        c.complete(0);
+        Ok(c)
+    }
+
     #[instrument(err, skip_all, level = Level::TRACE)]
     fn size(&self) -> Result<u64> {
         let file = self.file.borrow();
         Ok(file.metadata()?.len())
     }
+
+    #[instrument(err, skip_all, level = Level::TRACE)]
+    fn punch_hole(&self, pos: usize, len: usize) -> Result<()> {
+        let file = self.file.borrow();
+        punch_hole(file.as_fd(), pos, len)
+    }
+}
+
+/// Deallocates the storage of `len` bytes at `pos` of a file, keeping its size. Only Linux
+/// file systems support it, and not all of them.
+pub(super) fn punch_hole(fd: BorrowedFd<'_>, pos: usize, len: usize) -> Result<()> {
+    #[cfg(target_os = "linux")]
+    {
+        let flags = fs::FallocateFlags::PUNCH_HOLE | fs::FallocateFlags::KEEP_SIZE;
+        match fs::fallocate(fd, flags, pos as u64, len as u64) {
+            Ok(()) | Err(Errno::OPNOTSUPP) => {}
+            Err(e) => return Err(e.into()),
+        }
+        trace!("punch_hole(pos = {}, len = {})", pos, len);
+    }
+    #[cfg(not(target_os = "linux"))]
+    let _ = (fd, pos, len);
+    Ok(())
 }
 
 impl Drop for UnixFile<'_> {
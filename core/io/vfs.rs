@@ -157,6 +157,12 @@ impl File for VfsFileImpl {
         }
     }
 
+    fn truncate(&self, _len: usize, _c: Arc<Completion>) -> Result<Arc<Completion>> {
+        Err(LimboError::ExtensionError(
+            "truncate is not supported by extension VFSes".to_string(),
+        ))
+    }
+
     fn size(&self) -> Result<u64> {
         let vfs = unsafe { &*self.vfs };
         let result = unsafe { (vfs.size)(self.file) };
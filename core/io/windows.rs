@@ -117,6 +117,13 @@ impl File for WindowsFile {
         Ok(c)
     }
 
+    fn truncate(&self, len: usize, c: Arc<Completion>) -> Result<Arc<Completion>> {
+        let file = self.file.borrow_mut();
+        file.set_len(len as u64).map_err(LimboError::IOError)?;
+        c.complete(0);
+        Ok(c)
+    }
+
     fn size(&self) -> Result<u64> {
         let file = self.file.borrow();
         Ok(file.metadata().unwrap().len())
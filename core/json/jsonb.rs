@@ -1471,7 +1471,7 @@ impl Jsonb {
                 b',' if !first => {
                     pos += 1; // consume ','
                     pos = skip_whitespace(input, pos);
-                    if input[pos] == b',' || input[pos] == b'{' {
+                    if pos < input.len() && matches!(input[pos], b',' | b'{') {
                         return Err(PError::Message {
                             msg: "Two commas in a row".to_string(),
                             location: Some(pos),
@@ -1552,7 +1552,7 @@ impl Jsonb {
                 b',' if !first => {
                     pos += 1; // consume ','
                     pos = skip_whitespace(input, pos);
-                    if input[pos] == b',' {
+                    if pos < input.len() && input[pos] == b',' {
                         return Err(PError::Message {
                             msg: "Two commas in a row".to_string(),
                             location: Some(pos),
@@ -2171,6 +2171,102 @@ impl Jsonb {
         Ok(element_type)
     }
 
+    /// Returns the cursors of the direct children of the array or object at `cursor`,
+    /// as `(key, value)` pairs. Array elements have no key, primitives have no children.
+    pub fn children_at(&self, cursor: usize) -> Result<Vec<(Option<usize>, usize)>> {
+        let (JsonbHeader(element_type, len), header_size) = self.read_header(cursor)?;
+        let end = cursor + header_size + len;
+        let mut pos = cursor + header_size;
+        let mut children = Vec::new();
+        match element_type {
+            ElementType::ARRAY => {
+                while pos < end {
+                    children.push((None, pos));
+                    pos = self.skip_element(pos)?;
+                }
+            }
+            ElementType::OBJECT => {
+                while pos < end {
+                    let value = self.skip_element(pos)?;
+                    children.push((Some(pos), value));
+                    pos = self.skip_element(value)?;
+                }
+            }
+            _ => {}
+        }
+        Ok(children)
+    }
+
+    /// Copies the element at `cursor` into a standalone document.
+    pub fn element_at(&self, cursor: usize) -> Result<Jsonb> {
+        let end = self.skip_element(cursor)?;
+        match self.data.get(cursor..end) {
+            Some(data) => Ok(Jsonb::from_raw_data(data)),
+            None => bail_parse_error!("malformed JSON"),
+        }
+    }
+
+    /// Unlike [Jsonb::is_valid], checks every element of the document and not only the outer header.
+    pub fn is_valid_strict(&self) -> bool {
+        self.malformed_offset().is_none()
+    }
+
+    /// Returns the offset of the first malformed element of the document, if there is one.
+    pub fn malformed_offset(&self) -> Option<usize> {
+        match self.validate_element(0, 0) {
+            Ok(end) if end == self.data.len() => None,
+            Ok(end) => Some(end),
+            Err(offset) => Some(offset),
+        }
+    }
+
+    /// Validates the element at `cursor` and returns the offset past it,
+    /// or the offset of the malformed element.
+    fn validate_element(&self, cursor: usize, depth: usize) -> std::result::Result<usize, usize> {
+        if depth > MAX_JSON_DEPTH {
+            return Err(cursor);
+        }
+        let (JsonbHeader(element_type, len), header_size) =
+            self.read_header(cursor).map_err(|_| cursor)?;
+        let start = cursor + header_size;
+        let end = start + len;
+        let payload = self.data.get(start..end).ok_or(cursor)?;
+        match element_type {
+            ElementType::NULL | ElementType::TRUE | ElementType::FALSE if len == 0 => {}
+            ElementType::INT | ElementType::INT5 | ElementType::FLOAT | ElementType::FLOAT5
+                if len > 0 && payload.is_ascii() => {}
+            ElementType::TEXT | ElementType::TEXTJ | ElementType::TEXT5 | ElementType::TEXTRAW
+                if std::str::from_utf8(payload).is_ok() => {}
+            ElementType::ARRAY => {
+                let mut pos = start;
+                while pos < end {
+                    pos = self.validate_element(pos, depth + 1)?;
+                }
+                if pos != end {
+                    return Err(cursor);
+                }
+            }
+            ElementType::OBJECT => {
+                let mut pos = start;
+                while pos < end {
+                    if !self.element_type_at(pos).is_ok_and(|t| t.is_valid_key()) {
+                        return Err(pos);
+                    }
+                    pos = self.validate_element(pos, depth + 1)?;
+                    if pos >= end {
+                        return Err(cursor);
+                    }
+                    pos = self.validate_element(pos, depth + 1)?;
+                }
+                if pos != end {
+                    return Err(cursor);
+                }
+            }
+            _ => return Err(cursor),
+        }
+        Ok(end)
+    }
+
     pub fn array_len(&self) -> Result<usize> {
         let (header, header_skip) = self.read_header(0)?;
         if header.0 != ElementType::ARRAY {
@@ -3589,6 +3685,26 @@ world""#,
         }
     }
 
+    #[test]
+    fn test_jsonb_is_valid_strict() {
+        let jsonb = Jsonb::from_str(r#"{"a":[1,2.5,"x",null]}"#).unwrap();
+        assert!(jsonb.is_valid_strict());
+        assert_eq!(jsonb.malformed_offset(), None);
+
+        // A header that claims more payload than there is
+        let mut truncated = jsonb.data.clone();
+        truncated.pop();
+        assert_eq!(Jsonb::new(0, Some(&truncated)).malformed_offset(), Some(0));
+
+        // An object key that is not text
+        let mut bad_key = Jsonb::make_empty_obj(8);
+        bad_key.append_jsonb_to_end(Jsonb::from_str("1").unwrap().data());
+        bad_key.append_jsonb_to_end(Jsonb::from_str("2").unwrap().data());
+        bad_key.finalize_unsafe(ElementType::OBJECT).unwrap();
+        assert!(bad_key.is_valid().is_ok());
+        assert!(!bad_key.is_valid_strict());
+    }
+
     #[test]
     fn test_special_characters_in_strings() {
         // Test handling of various special characters
@@ -3,6 +3,7 @@ mod error;
 pub(crate) mod jsonb;
 mod ops;
 pub(crate) mod path;
+mod vtab;
 
 use crate::json::error::Error as JsonError;
 pub use crate::json::ops::{
@@ -17,6 +18,7 @@ pub use cache::JsonCacheCell;
 use jsonb::{ElementType, Jsonb, JsonbHeader, PathOperationMode, SearchOperation, SetOperation};
 use std::borrow::Cow;
 use std::str::FromStr;
+pub use vtab::register_extension;
 
 #[derive(Debug, Clone, Copy)]
 pub enum Conv {
@@ -367,7 +369,12 @@ pub fn jsonb_extract(
     let jsonb = json_cache.get_or_insert_with(value, convert_to_jsonb)?;
 
     let (json, element_type) = jsonb_extract_internal(jsonb, paths)?;
-    let result = json_string_to_db_type(json, element_type, OutputVariant::ElementType)?;
+    // Arrays and objects stay in the binary format, primitives become SQL values
+    let flag = match element_type {
+        ElementType::ARRAY | ElementType::OBJECT => OutputVariant::Binary,
+        _ => OutputVariant::ElementType,
+    };
+    let result = json_string_to_db_type(json, element_type, flag)?;
 
     Ok(result)
 }
@@ -553,8 +560,9 @@ pub fn json_error_position(json: &Value) -> crate::Result<Value> {
                 }
             }
         },
-        Value::Blob(_) => {
-            bail_parse_error!("Unsupported")
+        Value::Blob(b) => {
+            let position = Jsonb::from_raw_data(b).malformed_offset();
+            Ok(Value::Integer(position.map_or(0, |pos| pos as i64 + 1)))
         }
         Value::Null => Ok(Value::Null),
         _ => Ok(Value::Integer(0)),
@@ -606,13 +614,156 @@ pub fn jsonb_object(values: &[Register]) -> crate::Result<Value> {
     json_string_to_db_type(json, ElementType::OBJECT, OutputVariant::Binary)
 }
 
-pub fn is_json_valid(json_value: &Value) -> Value {
-    if matches!(json_value, Value::Null) {
-        return Value::Null;
+/// Checks whether the value is well formed JSON.
+/// The optional flags select what counts as valid, see https://sqlite.org/json1.html#jvalid:
+/// - 0x01: canonical RFC-8259 JSON text
+/// - 0x02: JSON5 text
+/// - 0x04: a BLOB that superficially looks like JSONB
+/// - 0x08: a BLOB that is strictly conforming JSONB
+pub fn is_json_valid(json_value: &Value, flags: Option<&Value>) -> crate::Result<Value> {
+    let flags = match flags {
+        None => 0x01,
+        Some(Value::Integer(flags)) if (1..=15).contains(flags) => *flags,
+        Some(_) => {
+            bail_constraint_error!("FLAGS parameter to json_valid() must be between 1 and 15")
+        }
+    };
+    let text = match json_value {
+        Value::Null => return Ok(Value::Null),
+        Value::Blob(blob) => {
+            let json = Jsonb::from_raw_data(blob);
+            if json.is_valid().is_ok() {
+                let valid = if flags & 0x04 != 0 {
+                    true
+                } else if flags & 0x08 != 0 {
+                    json.is_valid_strict()
+                } else {
+                    false
+                };
+                return Ok(Value::Integer(valid as i64));
+            }
+            // Blobs that are not JSONB are interpreted as text, like SQLite does
+            String::from_utf8_lossy(blob).to_string()
+        }
+        _ => json_value.to_string(),
+    };
+    if flags & 0x03 == 0 || Jsonb::from_str(&text).is_err() {
+        return Ok(Value::Integer(0));
+    }
+    let valid = flags & 0x02 != 0 || is_rfc8259_json(text.as_bytes());
+    Ok(Value::Integer(valid as i64))
+}
+
+/// Checks that the text is canonical RFC-8259 JSON, without any of the JSON5 extensions.
+/// The nesting depth is not checked here, callers parse the text with [Jsonb] first.
+fn is_rfc8259_json(input: &[u8]) -> bool {
+    fn skip_ws(input: &[u8], mut pos: usize) -> usize {
+        while matches!(input.get(pos), Some(b' ' | b'\t' | b'\n' | b'\r')) {
+            pos += 1;
+        }
+        pos
+    }
+
+    fn skip_digits(input: &[u8], mut pos: usize) -> Option<usize> {
+        let start = pos;
+        while input.get(pos).is_some_and(u8::is_ascii_digit) {
+            pos += 1;
+        }
+        (pos > start).then_some(pos)
+    }
+
+    fn string(input: &[u8], mut pos: usize) -> Option<usize> {
+        if input.get(pos) != Some(&b'"') {
+            return None;
+        }
+        pos += 1;
+        loop {
+            match *input.get(pos)? {
+                b'"' => return Some(pos + 1),
+                b'\\' => match *input.get(pos + 1)? {
+                    b'"' | b'\\' | b'/' | b'b' | b'f' | b'n' | b'r' | b't' => pos += 2,
+                    b'u' => {
+                        let hex = input.get(pos + 2..pos + 6)?;
+                        if !hex.iter().all(u8::is_ascii_hexdigit) {
+                            return None;
+                        }
+                        pos += 6;
+                    }
+                    _ => return None,
+                },
+                c if c < 0x20 => return None,
+                _ => pos += 1,
+            }
+        }
+    }
+
+    fn number(input: &[u8], mut pos: usize) -> Option<usize> {
+        if input.get(pos) == Some(&b'-') {
+            pos += 1;
+        }
+        pos = match input.get(pos)? {
+            b'0' => pos + 1,
+            b'1'..=b'9' => skip_digits(input, pos)?,
+            _ => return None,
+        };
+        if input.get(pos) == Some(&b'.') {
+            pos = skip_digits(input, pos + 1)?;
+        }
+        if matches!(input.get(pos), Some(b'e' | b'E')) {
+            pos += 1;
+            if matches!(input.get(pos), Some(b'+' | b'-')) {
+                pos += 1;
+            }
+            pos = skip_digits(input, pos)?;
+        }
+        Some(pos)
+    }
+
+    fn value(input: &[u8], pos: usize) -> Option<usize> {
+        let pos = skip_ws(input, pos);
+        let end = match *input.get(pos)? {
+            b'{' => {
+                let mut pos = skip_ws(input, pos + 1);
+                if input.get(pos) == Some(&b'}') {
+                    return Some(pos + 1);
+                }
+                loop {
+                    pos = skip_ws(input, string(input, pos)?);
+                    if input.get(pos) != Some(&b':') {
+                        return None;
+                    }
+                    pos = skip_ws(input, value(input, pos + 1)?);
+                    match *input.get(pos)? {
+                        b',' => pos = skip_ws(input, pos + 1),
+                        b'}' => break pos + 1,
+                        _ => return None,
+                    }
+                }
+            }
+            b'[' => {
+                let mut pos = skip_ws(input, pos + 1);
+                if input.get(pos) == Some(&b']') {
+                    return Some(pos + 1);
+                }
+                loop {
+                    pos = skip_ws(input, value(input, pos)?);
+                    match *input.get(pos)? {
+                        b',' => pos += 1,
+                        b']' => break pos + 1,
+                        _ => return None,
+                    }
+                }
+            }
+            b'"' => string(input, pos)?,
+            b't' if input[pos..].starts_with(b"true") => pos + 4,
+            b'f' if input[pos..].starts_with(b"false") => pos + 5,
+            b'n' if input[pos..].starts_with(b"null") => pos + 4,
+            _ => number(input, pos)?,
+        };
+        Some(skip_ws(input, end))
     }
-    convert_dbtype_to_jsonb(json_value, Conv::Strict)
-        .map(|_| Value::Integer(1))
-        .unwrap_or(Value::Integer(0))
+
+    value(input, 0) == Some(input.len())
 }
 
 pub fn json_quote(value: &Value) -> crate::Result<Value> {
@@ -1521,4 +1672,40 @@ mod tests {
 
         assert_eq!(result.unwrap().to_text().unwrap(), r#"{"field":"value"}"#,);
     }
+
+    #[test]
+    fn test_is_rfc8259_json() {
+        assert!(is_rfc8259_json(
+            br#" {"a": [1, -2.5e3, true, null, "x\u00e9"]} "#
+        ));
+        assert!(is_rfc8259_json(b"0"));
+        assert!(!is_rfc8259_json(b"{a: 1}"));
+        assert!(!is_rfc8259_json(b"[1,]"));
+        assert!(!is_rfc8259_json(b"01"));
+        assert!(!is_rfc8259_json(b"'x'"));
+        assert!(!is_rfc8259_json(b"[1] // comment"));
+    }
+
+    #[test]
+    fn test_json_valid_flags() {
+        let json5 = Value::build_text("{a: 1}");
+        assert_eq!(is_json_valid(&json5, None).unwrap(), Value::Integer(0));
+        assert_eq!(
+            is_json_valid(&json5, Some(&Value::Integer(2))).unwrap(),
+            Value::Integer(1)
+        );
+        assert_eq!(
+            is_json_valid(&json5, Some(&Value::Integer(4))).unwrap(),
+            Value::Integer(0)
+        );
+        assert!(is_json_valid(&json5, Some(&Value::Integer(16))).is_err());
+
+        let jsonb = convert_dbtype_to_jsonb(&Value::build_text("[1,2]"), Conv::Strict).unwrap();
+        let jsonb = Value::Blob(jsonb.data());
+        assert_eq!(is_json_valid(&jsonb, None).unwrap(), Value::Integer(0));
+        assert_eq!(
+            is_json_valid(&jsonb, Some(&Value::Integer(8))).unwrap(),
+            Value::Integer(1)
+        );
+    }
 }
@@ -0,0 +1,477 @@
+use std::sync::Arc;
+
+use turso_ext::{
+    Connection, ConstraintInfo, ConstraintOp, ConstraintUsage, ExtensionApi, IndexInfo,
+    OrderByInfo, ResultCode, VTabCursor, VTabKind, VTabModule, VTabModuleDerive, VTable, Value,
+    ValueType,
+};
+
+use super::jsonb::{unescape_string, ElementType, Jsonb, PathOperationMode};
+use super::path::{json_path, PathElement};
+use super::{convert_dbtype_to_jsonb, json_string_to_db_type, Conv, OutputVariant};
+use crate::types::{Text, TextSubtype, Value as DbValue};
+
+pub fn register_extension(ext_api: &mut ExtensionApi) {
+    unsafe {
+        JsonEachVTabModule::register_JsonEachVTabModule(ext_api);
+        JsonTreeVTabModule::register_JsonTreeVTabModule(ext_api);
+        JsonbEachVTabModule::register_JsonbEachVTabModule(ext_api);
+        JsonbTreeVTabModule::register_JsonbTreeVTabModule(ext_api);
+    }
+}
+
+const SCHEMA_COLUMNS: &str = "(
+    key,
+    value,
+    type,
+    atom,
+    id,
+    parent,
+    fullkey,
+    path,
+    json HIDDEN,
+    root HIDDEN
+)";
+
+const COLUMN_JSON: u32 = 8;
+const COLUMN_ROOT: u32 = 9;
+
+/// `json_each(json[, path])`: one row for each direct child of the top-level element.
+/// https://sqlite.org/json1.html#jeach
+#[derive(Debug, VTabModuleDerive, Default)]
+struct JsonEachVTabModule;
+
+impl VTabModule for JsonEachVTabModule {
+    type Table = JsonTable;
+    const NAME: &'static str = "json_each";
+    const VTAB_KIND: VTabKind = VTabKind::TableValuedFunction;
+
+    fn create(_args: &[Value]) -> Result<(String, Self::Table), ResultCode> {
+        let schema = format!("CREATE TABLE json_each{SCHEMA_COLUMNS}");
+        Ok((
+            schema,
+            JsonTable {
+                recursive: false,
+                binary: false,
+            },
+        ))
+    }
+}
+
+/// `json_tree(json[, path])`: one row for the top-level element and each of its descendants.
+/// https://sqlite.org/json1.html#jtree
+#[derive(Debug, VTabModuleDerive, Default)]
+struct JsonTreeVTabModule;
+
+impl VTabModule for JsonTreeVTabModule {
+    type Table = JsonTable;
+    const NAME: &'static str = "json_tree";
+    const VTAB_KIND: VTabKind = VTabKind::TableValuedFunction;
+
+    fn create(_args: &[Value]) -> Result<(String, Self::Table), ResultCode> {
+        let schema = format!("CREATE TABLE json_tree{SCHEMA_COLUMNS}");
+        Ok((
+            schema,
+            JsonTable {
+                recursive: true,
+                binary: false,
+            },
+        ))
+    }
+}
+
+/// `jsonb_each(json[, path])`: like `json_each`, but arrays and objects are returned as JSONB.
+#[derive(Debug, VTabModuleDerive, Default)]
+struct JsonbEachVTabModule;
+
+impl VTabModule for JsonbEachVTabModule {
+    type Table = JsonTable;
+    const NAME: &'static str = "jsonb_each";
+    const VTAB_KIND: VTabKind = VTabKind::TableValuedFunction;
+
+    fn create(_args: &[Value]) -> Result<(String, Self::Table), ResultCode> {
+        let schema = format!("CREATE TABLE jsonb_each{SCHEMA_COLUMNS}");
+        Ok((
+            schema,
+            JsonTable {
+                recursive: false,
+                binary: true,
+            },
+        ))
+    }
+}
+
+/// `jsonb_tree(json[, path])`: like `json_tree`, but arrays and objects are returned as JSONB.
+#[derive(Debug, VTabModuleDerive, Default)]
+struct JsonbTreeVTabModule;
+
+impl VTabModule for JsonbTreeVTabModule {
+    type Table = JsonTable;
+    const NAME: &'static str = "jsonb_tree";
+    const VTAB_KIND: VTabKind = VTabKind::TableValuedFunction;
+
+    fn create(_args: &[Value]) -> Result<(String, Self::Table), ResultCode> {
+        let schema = format!("CREATE TABLE jsonb_tree{SCHEMA_COLUMNS}");
+        Ok((
+            schema,
+            JsonTable {
+                recursive: true,
+                binary: true,
+            },
+        ))
+    }
+}
+
+struct JsonTable {
+    recursive: bool,
+    /// Whether arrays and objects are returned as JSONB instead of JSON text.
+    binary: bool,
+}
+
+impl VTable for JsonTable {
+    type Cursor = JsonCursor;
+    type Error = ResultCode;
+
+    fn open(&self, _conn: Option<Arc<Connection>>) -> Result<Self::Cursor, Self::Error> {
+        Ok(JsonCursor {
+            recursive: self.recursive,
+            binary: self.binary,
+            json_arg: DbValue::Null,
+            root_arg: None,
+            json: Jsonb::new(0, None),
+            rows: Vec::new(),
+            current: 0,
+        })
+    }
+
+    fn best_index(constraints: &[ConstraintInfo], _order_by: &[OrderByInfo]) -> IndexInfo {
+        // The bits of `idx_num` are used to indicate which arguments are available to the filter method:
+        // - Bit 0 set -> 'json' is available
+        // - Bit 1 set -> 'root' is available
+        let mut idx_num = 0;
+        let mut json_idx = None;
+        let mut root_idx = None;
+
+        for (i, c) in constraints.iter().enumerate() {
+            if !c.usable || c.op != ConstraintOp::Eq {
+                continue;
+            }
+            match c.column_index {
+                COLUMN_JSON => {
+                    json_idx = Some(i);
+                    idx_num |= 1;
+                }
+                COLUMN_ROOT => {
+                    root_idx = Some(i);
+                    idx_num |= 2;
+                }
+                _ => {}
+            }
+        }
+
+        let constraint_usages = constraints
+            .iter()
+            .enumerate()
+            .map(|(i, _)| {
+                if Some(i) == json_idx {
+                    ConstraintUsage {
+                        argv_index: Some(1),
+                        omit: true,
+                    }
+                } else if Some(i) == root_idx {
+                    ConstraintUsage {
+                        argv_index: Some(if json_idx.is_some() { 2 } else { 1 }),
+                        omit: true,
+                    }
+                } else {
+                    ConstraintUsage {
+                        argv_index: Some(0),
+                        omit: false,
+                    }
+                }
+            })
+            .collect();
+
+        IndexInfo {
+            idx_num,
+            idx_str: Some(idx_num.to_string()),
+            constraint_usages,
+            ..Default::default()
+        }
+    }
+}
+
+/// A row of `json_each` or `json_tree`, the element itself is read from the document on demand.
+#[derive(Debug)]
+struct JsonRow {
+    key: DbValue,
+    /// Offset of the element in the JSONB document, also used as its `id`.
+    cursor: usize,
+    parent: Option<usize>,
+    fullkey: String,
+    path: String,
+}
+
+struct JsonCursor {
+    recursive: bool,
+    binary: bool,
+    json_arg: DbValue,
+    root_arg: Option<String>,
+    json: Jsonb,
+    rows: Vec<JsonRow>,
+    current: usize,
+}
+
+impl JsonCursor {
+    fn collect_rows(&mut self) -> crate::Result<()> {
+        let root = self.root_arg.as_deref().unwrap_or("$");
+        let path = json_path(root)?;
+        self.json = convert_dbtype_to_jsonb(&self.json_arg, Conv::Strict)?;
+        // A root that does not exist in the document yields no rows
+        let Ok(mut stack) = self
+            .json
+            .navigate_path(&path, PathOperationMode::ReplaceExisting)
+        else {
+            return Ok(());
+        };
+        let target = stack.pop().expect("Should exist");
+        let root_cursor = target.get_array_index().unwrap_or(target.field_value_index);
+
+        let (parent_elements, last) = path.elements.split_at(path.elements.len() - 1);
+        let mut root_path = String::new();
+        for element in parent_elements {
+            append_path_element(&mut root_path, element);
+        }
+        let mut root_fullkey = root_path.clone();
+        append_path_element(&mut root_fullkey, &last[0]);
+        if root_path.is_empty() {
+            root_path.push('$');
+        }
+        let root_key = match &last[0] {
+            PathElement::Key(key, _) => DbValue::build_text(key),
+            PathElement::ArrayLocator(Some(idx)) if *idx >= 0 => DbValue::Integer(*idx as i64),
+            _ => DbValue::Null,
+        };
+
+        let is_container = matches!(
+            self.json.element_type_at(root_cursor)?,
+            ElementType::ARRAY | ElementType::OBJECT
+        );
+        if self.recursive || !is_container {
+            self.rows.push(JsonRow {
+                key: root_key,
+                cursor: root_cursor,
+                parent: None,
+                fullkey: root_fullkey.clone(),
+                path: root_path,
+            });
+        }
+        if is_container {
+            self.collect_children(root_cursor, &root_fullkey)?;
+        }
+        Ok(())
+    }
+
+    /// Adds a row for each child of the container at `cursor`, descending into them for `json_tree`.
+    fn collect_children(&mut self, cursor: usize, fullkey: &str) -> crate::Result<()> {
+        for (idx, (key_cursor, value_cursor)) in
+            self.json.children_at(cursor)?.into_iter().enumerate()
+        {
+            let (key, child_fullkey) = match key_cursor {
+                Some(key_cursor) => {
+                    let key = decode_text(&self.json.element_at(key_cursor)?)?;
+                    let mut child_fullkey = fullkey.to_string();
+                    append_object_key(&mut child_fullkey, &key);
+                    (DbValue::build_text(key), child_fullkey)
+                }
+                None => (DbValue::Integer(idx as i64), format!("{fullkey}[{idx}]")),
+            };
+            self.rows.push(JsonRow {
+                key,
+                cursor: value_cursor,
+                parent: self.recursive.then_some(cursor),
+                fullkey: child_fullkey.clone(),
+                path: fullkey.to_string(),
+            });
+            if self.recursive {
+                self.collect_children(value_cursor, &child_fullkey)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn element_value(&self, row: &JsonRow) -> crate::Result<DbValue> {
+        let element = self.json.element_at(row.cursor)?;
+        let element_type = element.is_valid()?;
+        match element_type {
+            ElementType::TEXT | ElementType::TEXTJ | ElementType::TEXT5 | ElementType::TEXTRAW => {
+                Ok(DbValue::build_text(decode_text(&element)?))
+            }
+            ElementType::ARRAY | ElementType::OBJECT if self.binary => {
+                Ok(DbValue::Blob(element.data()))
+            }
+            _ => json_string_to_db_type(element, element_type, OutputVariant::ElementType),
+        }
+    }
+
+    fn element_column(&self, row: &JsonRow, idx: u32) -> crate::Result<Value> {
+        Ok(match idx {
+            0 => to_ext_value(&row.key),
+            1 => to_ext_value(&self.element_value(row)?),
+            2 => {
+                let element_type: String = self.json.element_type_at(row.cursor)?.into();
+                Value::from_text(element_type)
+            }
+            3 => match self.json.element_type_at(row.cursor)? {
+                ElementType::ARRAY | ElementType::OBJECT => Value::null(),
+                _ => to_ext_value(&self.element_value(row)?),
+            },
+            4 => Value::from_integer(row.cursor as i64),
+            5 => row
+                .parent
+                .map(|parent| Value::from_integer(parent as i64))
+                .unwrap_or_else(Value::null),
+            6 => Value::from_text(row.fullkey.clone()),
+            7 => Value::from_text(row.path.clone()),
+            COLUMN_JSON => to_ext_value(&self.json_arg),
+            COLUMN_ROOT => self
+                .root_arg
+                .clone()
+                .map(Value::from_text)
+                .unwrap_or_else(Value::null),
+            _ => Value::null(),
+        })
+    }
+}
+
+impl VTabCursor for JsonCursor {
+    type Error = ResultCode;
+
+    fn filter(&mut self, args: &[Value], idx_info: Option<(&str, i32)>) -> ResultCode {
+        self.rows.clear();
+        self.current = 0;
+        self.json_arg = DbValue::Null;
+        self.root_arg = None;
+
+        if let Some((_, idx_num)) = idx_info {
+            let mut arg_idx = 0;
+            // For the semantics of `idx_num`, see the comment in the `best_index` method.
+            if idx_num & 1 != 0 {
+                self.json_arg = args
+                    .get(arg_idx)
+                    .map(from_ext_value)
+                    .unwrap_or(DbValue::Null);
+                arg_idx += 1;
+            }
+            if idx_num & 2 != 0 {
+                match args.get(arg_idx).map(|v| v.value_type()) {
+                    Some(ValueType::Text) => {
+                        self.root_arg = args[arg_idx].to_text().map(str::to_string);
+                    }
+                    // A NULL root yields no rows, like a NULL document
+                    _ => return ResultCode::EOF,
+                }
+            }
+        }
+
+        if matches!(self.json_arg, DbValue::Null) {
+            return ResultCode::EOF;
+        }
+        if self.collect_rows().is_err() {
+            return ResultCode::Error;
+        }
+        if self.rows.is_empty() {
+            return ResultCode::EOF;
+        }
+        ResultCode::OK
+    }
+
+    fn next(&mut self) -> ResultCode {
+        if self.eof() {
+            return ResultCode::EOF;
+        }
+        self.current += 1;
+        if self.eof() {
+            return ResultCode::EOF;
+        }
+        ResultCode::OK
+    }
+
+    fn eof(&self) -> bool {
+        self.current >= self.rows.len()
+    }
+
+    fn column(&self, idx: u32) -> Result<Value, Self::Error> {
+        let Some(row) = self.rows.get(self.current) else {
+            return Ok(Value::null());
+        };
+        self.element_column(row, idx).map_err(|_| ResultCode::Error)
+    }
+
+    fn rowid(&self) -> i64 {
+        self.current as i64
+    }
+}
+
+/// Decodes a JSON string element into its text, without the quotes and escapes.
+fn decode_text(element: &Jsonb) -> crate::Result<String> {
+    let quoted = element.to_string()?;
+    let inner = quoted
+        .strip_prefix('"')
+        .and_then(|s| s.strip_suffix('"'))
+        .unwrap_or(&quoted);
+    Ok(unescape_string(inner))
+}
+
+/// Appends an object label to a path, quoting it unless it is a plain identifier.
+fn append_object_key(path: &mut String, key: &str) {
+    let mut chars = key.chars();
+    let is_identifier = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric());
+    if is_identifier {
+        path.push('.');
+        path.push_str(key);
+    } else {
+        path.push_str(".\"");
+        path.push_str(key);
+        path.push('"');
+    }
+}
+
+fn append_path_element(path: &mut String, element: &PathElement) {
+    match element {
+        PathElement::Root() => path.push('$'),
+        PathElement::Key(key, _) => append_object_key(path, key),
+        PathElement::ArrayLocator(Some(idx)) if *idx >= 0 => path.push_str(&format!("[{idx}]")),
+        PathElement::ArrayLocator(Some(idx)) => path.push_str(&format!("[#{idx}]")),
+        PathElement::ArrayLocator(None) => path.push_str("[#]"),
+    }
+}
+
+/// Converts a value into an extension value, keeping the JSON subtype of text.
+fn to_ext_value(value: &DbValue) -> Value {
+    match value {
+        DbValue::Text(text) if text.subtype == TextSubtype::Json => {
+            Value::from_json(text.as_str().to_string())
+        }
+        _ => value.to_ffi(),
+    }
+}
+
+/// Converts an extension argument into a value without taking ownership of it.
+fn from_ext_value(value: &Value) -> DbValue {
+    match value.value_type() {
+        ValueType::Integer => value.to_integer().map(DbValue::Integer),
+        ValueType::Float => value.to_float().map(DbValue::Float),
+        ValueType::Text => value.to_text().map(|text| {
+            if value.is_json() {
+                DbValue::Text(Text::json(text.to_string()))
+            } else {
+                DbValue::build_text(text)
+            }
+        }),
+        ValueType::Blob => value.to_blob().map(DbValue::Blob),
+        _ => None,
+    }
+    .unwrap_or(DbValue::Null)
+}
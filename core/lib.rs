@@ -1,20 +1,31 @@
 #![allow(clippy::arc_with_non_send_sync)]
 
 mod assert;
+mod attach;
+mod authorizer;
+mod backup;
+mod blob;
 mod error;
 mod ext;
 mod fast_lock;
+#[cfg(feature = "fts5")]
+mod fts5;
 mod function;
 mod functions;
 mod info;
 mod io;
 #[cfg(feature = "json")]
 mod json;
+mod limits;
 pub mod mvcc;
 mod parameters;
 mod pragma;
+mod preupdate;
 mod pseudo;
+mod recover;
 pub mod result;
+#[cfg(feature = "rtree")]
+mod rtree;
 mod schema;
 #[cfg(feature = "series")]
 mod series;
@@ -24,6 +35,7 @@ mod storage;
 mod time;
 mod translate;
 pub mod types;
+mod unicode;
 mod util;
 #[cfg(feature = "uuid")]
 mod uuid;
@@ -43,13 +55,17 @@ static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 use crate::storage::sqlite3_ondisk::is_valid_page_size;
 use crate::storage::{header_accessor, wal::DummyWAL};
-use crate::translate::optimizer::optimize_plan;
+use crate::translate::collate::{CollationSeq, Collations};
 use crate::translate::pragma::TURSO_CDC_DEFAULT_TABLE_NAME;
-use crate::util::{OpenMode, OpenOptions};
+use crate::util::{normalize_ident, OpenOptions, MEMORY_PATH};
 use crate::vtab::VirtualTable;
+pub use authorizer::{AuthAction, Authorization, Authorizer};
+pub use backup::Backup;
+pub use blob::Blob;
 use core::str;
 pub use error::LimboError;
 use fallible_iterator::FallibleIterator;
+pub use function::{AggregateInit, AggregateState, FunctionFlags, ScalarClosure};
 pub use io::clock::{Clock, Instant};
 #[cfg(all(feature = "fs", target_family = "unix"))]
 pub use io::UnixIO;
@@ -59,10 +75,13 @@ pub use io::{
     Buffer, Completion, CompletionType, File, MemoryIO, OpenFlags, PlatformIO, SyscallIO,
     WriteCompletion, IO,
 };
+pub use limits::{Limit, Limits};
 use parking_lot::RwLock;
+pub use preupdate::PreUpdate;
+pub use recover::{RecoveredTable, Recovery};
 use schema::Schema;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock, Weak};
 use std::{
     borrow::Cow,
     cell::{Cell, RefCell, UnsafeCell},
@@ -73,27 +92,29 @@ use std::{
     ops::Deref,
     rc::Rc,
     sync::Arc,
+    time::Duration,
 };
 #[cfg(feature = "fs")]
 use storage::database::DatabaseFile;
+use storage::database::FileMemoryStorage;
 use storage::page_cache::DumbLruPageCache;
 use storage::pager::{DB_STATE_INITIALIZED, DB_STATE_UNINITIALIZED};
 pub use storage::{
     buffer_pool::BufferPool,
     database::DatabaseStorage,
+    encryption::{CipherMode, EncryptionKey},
     pager::PageRef,
-    pager::{Page, Pager},
+    pager::{Page, Pager, PagerStats},
     wal::{CheckpointMode, CheckpointResult, Wal, WalFile, WalFileShared},
 };
 use tracing::{instrument, Level};
-use translate::select::prepare_select_plan;
+pub use translate::collate::CollationFn;
 use turso_sqlite3_parser::{ast, ast::Cmd, lexer::sql::Parser};
 use types::IOResult;
 pub use types::RefValue;
 pub use types::Value;
 use util::parse_schema_rows;
 use vdbe::builder::QueryMode;
-use vdbe::builder::TableRefIdCounter;
 
 pub type Result<T, E = LimboError> = std::result::Result<T, E>;
 
@@ -104,10 +125,78 @@ enum TransactionState {
     None,
 }
 
+/// A savepoint opened by `SAVEPOINT name`.
+struct Savepoint {
+    name: String,
+    /// The schema when the savepoint was opened, which `ROLLBACK TO` restores.
+    schema: Arc<Schema>,
+    /// Whether `SAVEPOINT` started the transaction, which releasing the savepoint then commits.
+    starts_transaction: bool,
+    /// The index of the matching pager savepoint.
+    pager_savepoint: usize,
+}
+
+/// Called when a statement can't get a lock because another connection holds it, with the number
+/// of times the statement already retried. Returning `true` retries the statement, `false` fails
+/// it with [LimboError::Busy].
+pub type BusyHandler = Box<dyn Fn(u32) -> bool>;
+
+/// The kind of change reported to an [UpdateHook].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum UpdateKind {
+    Insert,
+    Update,
+    Delete,
+}
+
+/// Called for each row inserted, updated or deleted in a table, with the kind of change, the
+/// names of the database and of the table, and the rowid of the row, like `sqlite3_update_hook`.
+/// The rows changed by triggers are reported too, but not the rows of the schema tables or the
+/// rows deleted by the REPLACE conflict resolution.
+pub type UpdateHook = Box<dyn Fn(UpdateKind, &str, &str, i64)>;
+
+/// Called when a write transaction is about to commit, like `sqlite3_commit_hook`. Returning
+/// `true` rolls the transaction back instead, and the statement that was committing it fails
+/// with [LimboError::Constraint].
+pub type CommitHook = Box<dyn Fn() -> bool>;
+
+/// Called when a transaction is rolled back, like `sqlite3_rollback_hook`. Undoing the changes
+/// of a single failed statement inside a transaction is not a rollback of the transaction.
+pub type RollbackHook = Box<dyn Fn()>;
+
+/// Called before each row is inserted, updated or deleted in a table, like
+/// `sqlite3_preupdate_hook`, with the row, whose values can be read before and after the change.
+/// Unlike with the [UpdateHook], the rows deleted by the REPLACE conflict resolution are reported.
+pub type PreUpdateHook = Box<dyn Fn(&PreUpdate<'_>)>;
+
+/// Called periodically while the statements of a connection run, like the callback of
+/// `sqlite3_progress_handler`. Returning `true` interrupts the statement.
+pub type ProgressHandler = Box<dyn Fn() -> bool>;
+
+/// Called by [Connection::execute_batch] for each row returned by its statements, with the
+/// statement the row can be read from. Returning an error stops the batch.
+pub type BatchRowCallback<'a> = &'a mut dyn FnMut(&Statement) -> Result<()>;
+
+/// Interrupts the statements of a connection from any thread, see [Connection::interrupt].
+#[derive(Clone)]
+pub struct InterruptHandle {
+    interrupt_count: Arc<AtomicU64>,
+}
+
+impl InterruptHandle {
+    pub fn interrupt(&self) {
+        self.interrupt_count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
 pub(crate) type MvStore = mvcc::MvStore<mvcc::LocalClock>;
 
 pub(crate) type MvCursor = mvcc::cursor::ScanCursor<mvcc::LocalClock>;
 
+/// The shared in-memory databases of the process, by name. A database is dropped, and its name
+/// can be reused for a new one, once nothing refers to it anymore.
+static SHARED_MEMORY_DATABASES: OnceLock<Mutex<HashMap<String, Weak<Database>>>> = OnceLock::new();
+
 pub struct Database {
     mv_store: Option<Rc<MvStore>>,
     schema: Mutex<Arc<Schema>>,
@@ -121,6 +210,9 @@ pub struct Database {
     db_state: Arc<AtomicUsize>,
     init_lock: Arc<Mutex<()>>,
     open_flags: OpenFlags,
+    /// Whether the database is encrypted, in which case its schema is only read once a
+    /// connection gives the key.
+    encrypted: AtomicBool,
 }
 
 unsafe impl Send for Database {}
@@ -150,6 +242,41 @@ impl Database {
         Self::open_with_flags(io, path, db_file, flags, enable_mvcc, enable_indexes)
     }
 
+    /// Opens a new in-memory database, which lives until the last reference to it is dropped.
+    /// Its pages, and those of its WAL, are kept in memory and never reach the filesystem.
+    pub fn open_memory(enable_mvcc: bool, enable_indexes: bool) -> Result<Arc<Database>> {
+        let io: Arc<dyn IO> = Arc::new(MemoryIO::new());
+        let file = io.open_file(MEMORY_PATH, OpenFlags::Create, false)?;
+        Self::open(
+            io,
+            MEMORY_PATH,
+            Arc::new(FileMemoryStorage::new(file)),
+            enable_mvcc,
+            enable_indexes,
+        )
+    }
+
+    /// Opens the shared in-memory database named `name`, like SQLite does for
+    /// `file:name?mode=memory&cache=shared`: it's created by the first caller, and the following
+    /// ones get the same database for as long as it's in use.
+    pub fn open_shared_memory(
+        name: &str,
+        enable_mvcc: bool,
+        enable_indexes: bool,
+    ) -> Result<Arc<Database>> {
+        let mut databases = SHARED_MEMORY_DATABASES
+            .get_or_init(Default::default)
+            .lock()
+            .unwrap();
+        if let Some(db) = databases.get(name).and_then(Weak::upgrade) {
+            return Ok(db);
+        }
+        databases.retain(|_, db| db.strong_count() > 0);
+        let db = Self::open_memory(enable_mvcc, enable_indexes)?;
+        databases.insert(name.to_string(), Arc::downgrade(&db));
+        Ok(db)
+    }
+
     #[allow(clippy::arc_with_non_send_sync)]
     pub fn open(
         io: Arc<dyn IO>,
@@ -209,31 +336,37 @@ impl Database {
             open_flags: flags,
             db_state: Arc::new(AtomicUsize::new(db_state)),
             init_lock: Arc::new(Mutex::new(())),
+            encrypted: AtomicBool::new(false),
         });
 
         // Check: https://github.com/tursodatabase/turso/pull/1761#discussion_r2154013123
         if db_state == DB_STATE_INITIALIZED {
-            // parse schema
             let conn = db.connect()?;
-
-            let syms = conn.syms.borrow();
-            let pager = conn.pager.borrow().clone();
-
-            db.with_schema_mut(|schema| {
-                schema.schema_version = get_schema_version(&conn)?;
-                if let Err(LimboError::ExtensionError(e)) =
-                    schema.make_from_btree(None, pager, &syms)
-                {
-                    // this means that a vtab exists and we no longer have the module loaded. we print
-                    // a warning to the user to load the module
-                    eprintln!("Warning: {e}");
-                }
-                Ok(())
-            })?;
+            if conn.pager.borrow().is_encrypted()? {
+                db.encrypted.store(true, Ordering::SeqCst);
+            } else {
+                db.load_schema(&conn)?;
+            }
         }
         Ok(db)
     }
 
+    /// Reads the schema of the database with `conn`.
+    fn load_schema(&self, conn: &Arc<Connection>) -> Result<()> {
+        let syms = conn.syms.borrow();
+        let pager = conn.pager.borrow().clone();
+
+        self.with_schema_mut(|schema| {
+            schema.schema_version = get_schema_version(conn)?;
+            if let Err(LimboError::ExtensionError(e)) = schema.make_from_btree(None, pager, &syms) {
+                // this means that a vtab exists and we no longer have the module loaded. we print
+                // a warning to the user to load the module
+                eprintln!("Warning: {e}");
+            }
+            Ok(())
+        })
+    }
+
     #[instrument(skip_all, level = Level::INFO)]
     pub fn connect(self: &Arc<Database>) -> Result<Arc<Connection>> {
         let pager = self.init_pager(None)?;
@@ -253,8 +386,11 @@ impl Database {
                     .clone(),
             ),
             auto_commit: Cell::new(true),
+            concurrent: Cell::new(false),
             mv_transactions: RefCell::new(Vec::new()),
             transaction_state: Cell::new(TransactionState::None),
+            savepoints: RefCell::new(Vec::new()),
+            vtab_transactions: RefCell::new(Vec::new()),
             last_insert_rowid: Cell::new(0),
             last_change: Cell::new(0),
             total_changes: Cell::new(0),
@@ -265,6 +401,23 @@ impl Database {
             readonly: Cell::new(false),
             wal_checkpoint_disabled: Cell::new(false),
             capture_data_changes: RefCell::new(CaptureDataChangesMode::Off),
+            busy_handler: RefCell::new(None),
+            update_hook: RefCell::new(None),
+            commit_hook: RefCell::new(None),
+            rollback_hook: RefCell::new(None),
+            preupdate_hook: RefCell::new(None),
+            authorizer: RefCell::new(None),
+            progress_handler: RefCell::new(None),
+            interrupt_count: Arc::new(AtomicU64::new(0)),
+            limits: Cell::new(Limits::default()),
+            collations: Collations::default(),
+            busy_timeout: Cell::new(Duration::ZERO),
+            case_sensitive_like: Cell::new(false),
+            index_advice: RefCell::new(None),
+            cipher: Cell::new(CipherMode::default()),
+            cache_spill: Cell::new(true),
+            cache_spill_size: Cell::new(0),
+            attached: RefCell::new(attach::AttachedDatabases::default()),
             closed: Cell::new(false),
         });
 
@@ -356,7 +509,6 @@ impl Database {
     where
         S: AsRef<str> + std::fmt::Display,
     {
-        use crate::util::MEMORY_PATH;
         let vfsmods = ext::add_builtin_vfs_extensions(None)?;
         match vfs {
             Some(vfs) => {
@@ -507,8 +659,14 @@ pub struct Connection {
     schema: RefCell<Arc<Schema>>,
     /// Whether to automatically commit transaction
     auto_commit: Cell<bool>,
+    /// Whether the explicit transaction was started with `BEGIN CONCURRENT`.
+    concurrent: Cell<bool>,
     mv_transactions: RefCell<Vec<crate::mvcc::database::TxID>>,
     transaction_state: Cell<TransactionState>,
+    /// The open savepoints, innermost last.
+    savepoints: RefCell<Vec<Savepoint>>,
+    /// The virtual tables written in the transaction, whose modules are told when it ends.
+    vtab_transactions: RefCell<Vec<vtab::VTabTransaction>>,
     last_insert_rowid: Cell<i64>,
     last_change: Cell<i64>,
     total_changes: Cell<i64>,
@@ -521,6 +679,37 @@ pub struct Connection {
     readonly: Cell<bool>,
     wal_checkpoint_disabled: Cell<bool>,
     capture_data_changes: RefCell<CaptureDataChangesMode>,
+    busy_handler: RefCell<Option<Rc<BusyHandler>>>,
+    update_hook: RefCell<Option<Rc<UpdateHook>>>,
+    commit_hook: RefCell<Option<Rc<CommitHook>>>,
+    rollback_hook: RefCell<Option<Rc<RollbackHook>>>,
+    preupdate_hook: RefCell<Option<Rc<PreUpdateHook>>>,
+    authorizer: RefCell<Option<Rc<Authorizer>>>,
+    /// The progress handler, called every that many instructions.
+    progress_handler: RefCell<Option<(u64, Rc<ProgressHandler>)>>,
+    /// The number of times the connection was interrupted, which the running statements compare
+    /// to the one they started with.
+    interrupt_count: Arc<AtomicU64>,
+    /// The limits on the statements prepared by the connection.
+    limits: Cell<Limits>,
+    /// The collations created on the connection, see [Connection::create_collation].
+    collations: Collations,
+    /// How long the default busy handler retries for, set by `PRAGMA busy_timeout`.
+    busy_timeout: Cell<Duration>,
+    /// Whether LIKE is case sensitive, set by `PRAGMA case_sensitive_like`.
+    case_sensitive_like: Cell<bool>,
+    /// The automatic indexes the statements prepared since `PRAGMA index_advisor = ON` would have
+    /// used a real index for, or None when the advisor is off.
+    index_advice: RefCell<Option<Vec<String>>>,
+    /// The cipher a new database is encrypted with, set by `PRAGMA cipher`.
+    cipher: Cell<CipherMode>,
+    /// Whether sorters may spill to temporary files, set by `PRAGMA cache_spill`.
+    cache_spill: Cell<bool>,
+    /// The minimum number of pages sorters buffer in memory before spilling, set by
+    /// `PRAGMA cache_spill=N`. The cache size is used when it is larger.
+    cache_spill_size: Cell<u32>,
+    /// The databases attached with `ATTACH DATABASE`.
+    attached: RefCell<attach::AttachedDatabases>,
     closed: Cell<bool>,
 }
 
@@ -548,21 +737,21 @@ impl Connection {
             .trim();
         self.maybe_update_schema()?;
         let pager = self.pager.borrow().clone();
+        let query_mode = QueryMode::from(&cmd);
         match cmd {
-            Cmd::Stmt(stmt) => {
+            Cmd::Stmt(stmt) | Cmd::ExplainQueryPlan(stmt) => {
                 let program = Rc::new(translate::translate(
-                    self.schema.borrow().deref(),
+                    self.translation_schema()?.deref(),
                     stmt,
                     pager.clone(),
                     self.clone(),
                     &syms,
-                    QueryMode::Normal,
+                    query_mode,
                     input,
                 )?);
                 Ok(Statement::new(program, self._db.mv_store.clone(), pager))
             }
             Cmd::Explain(_stmt) => todo!(),
-            Cmd::ExplainQueryPlan(_stmt) => todo!(),
         }
     }
 
@@ -596,39 +785,21 @@ impl Connection {
         }
         let syms = self.syms.borrow();
         let pager = self.pager.borrow().clone();
+        let query_mode = QueryMode::from(&cmd);
         match cmd {
-            Cmd::Stmt(ref stmt) | Cmd::Explain(ref stmt) => {
+            Cmd::Stmt(stmt) | Cmd::Explain(stmt) | Cmd::ExplainQueryPlan(stmt) => {
                 let program = translate::translate(
-                    self.schema.borrow().deref(),
-                    stmt.clone(),
+                    self.translation_schema()?.deref(),
+                    stmt,
                     pager.clone(),
                     self.clone(),
                     &syms,
-                    cmd.into(),
+                    query_mode,
                     input,
                 )?;
                 let stmt = Statement::new(program.into(), self._db.mv_store.clone(), pager);
                 Ok(Some(stmt))
             }
-            Cmd::ExplainQueryPlan(stmt) => {
-                let mut table_ref_counter = TableRefIdCounter::new();
-                match stmt {
-                    ast::Stmt::Select(select) => {
-                        let mut plan = prepare_select_plan(
-                            self.schema.borrow().deref(),
-                            *select,
-                            &syms,
-                            &[],
-                            &mut table_ref_counter,
-                            translate::plan::QueryDestination::ResultRows,
-                        )?;
-                        optimize_plan(&mut plan, self.schema.borrow().deref())?;
-                        let _ = std::io::stdout().write_all(plan.to_string().as_bytes());
-                    }
-                    _ => todo!(),
-                }
-                Ok(None)
-            }
         }
     }
 
@@ -653,10 +824,11 @@ impl Connection {
                 .unwrap()
                 .trim();
             self.maybe_update_schema()?;
+            let query_mode = QueryMode::from(&cmd);
             match cmd {
                 Cmd::Explain(stmt) => {
                     let program = translate::translate(
-                        self.schema.borrow().deref(),
+                        self.translation_schema()?.deref(),
                         stmt,
                         pager,
                         self.clone(),
@@ -666,27 +838,30 @@ impl Connection {
                     )?;
                     let _ = std::io::stdout().write_all(program.explain().as_bytes());
                 }
-                Cmd::ExplainQueryPlan(_stmt) => todo!(),
-                Cmd::Stmt(stmt) => {
+                Cmd::Stmt(stmt) | Cmd::ExplainQueryPlan(stmt) => {
                     let program = translate::translate(
-                        self.schema.borrow().deref(),
+                        self.translation_schema()?.deref(),
                         stmt,
                         pager.clone(),
                         self.clone(),
                         &syms,
-                        QueryMode::Normal,
+                        query_mode,
                         input,
                     )?;
 
                     let mut state =
                         vdbe::ProgramState::new(program.max_registers, program.cursor_ref.len());
+                    let mut busy_count = 0;
                     loop {
                         let res =
                             program.step(&mut state, self._db.mv_store.clone(), pager.clone())?;
-                        if matches!(res, StepResult::Done) {
-                            break;
+                        match res {
+                            StepResult::Done => break,
+                            StepResult::Busy if self.handle_busy(busy_count) => busy_count += 1,
+                            StepResult::Busy => return Err(LimboError::Busy),
+                            StepResult::Interrupt => return Err(LimboError::Interrupt),
+                            _ => self.run_once()?,
                         }
-                        self.run_once()?;
                     }
                 }
             }
@@ -694,6 +869,51 @@ impl Connection {
         Ok(())
     }
 
+    /// Runs all the statements in `sql` one after the other, like `sqlite3_exec`. For each row
+    /// a statement returns, `on_row` is called with the statement, from which it can read the
+    /// row and the column names. An error, including one returned by `on_row`, stops the batch.
+    #[instrument(skip_all, level = Level::INFO)]
+    pub fn execute_batch(
+        self: &Arc<Connection>,
+        sql: impl AsRef<str>,
+        mut on_row: Option<BatchRowCallback<'_>>,
+    ) -> Result<()> {
+        if self.closed.get() {
+            return Err(LimboError::InternalError("Connection closed".to_string()));
+        }
+        let sql = sql.as_ref();
+        let mut parser = Parser::new(sql.as_bytes());
+        let mut last_offset = 0;
+        while let Some(cmd) = parser.next()? {
+            let byte_offset_end = parser.offset();
+            let input = sql[last_offset..byte_offset_end].trim();
+            last_offset = byte_offset_end;
+            // The program of an EXPLAIN isn't returned as rows, so it is only translated.
+            let explain = matches!(cmd, Cmd::Explain(_));
+            self.maybe_update_schema()?;
+            let Some(mut stmt) = self.run_cmd(cmd, input)? else {
+                continue;
+            };
+            if explain {
+                continue;
+            }
+            loop {
+                match stmt.step()? {
+                    StepResult::Row => {
+                        if let Some(on_row) = &mut on_row {
+                            on_row(&stmt)?;
+                        }
+                    }
+                    StepResult::IO => stmt.run_once()?,
+                    StepResult::Done => break,
+                    StepResult::Interrupt => return Err(LimboError::Interrupt),
+                    StepResult::Busy => return Err(LimboError::Busy),
+                }
+            }
+        }
+        Ok(())
+    }
+
     fn run_once(&self) -> Result<()> {
         if self.closed.get() {
             return Err(LimboError::InternalError("Connection closed".to_string()));
@@ -714,15 +934,11 @@ impl Connection {
         use_indexes: bool,
         mvcc: bool,
     ) -> Result<(Arc<dyn IO>, Arc<Connection>)> {
-        use crate::util::MEMORY_PATH;
         let opts = OpenOptions::parse(uri)?;
         let flags = opts.get_flags()?;
-        if opts.path == MEMORY_PATH || matches!(opts.mode, OpenMode::Memory) {
-            let io = Arc::new(MemoryIO::new());
-            let db =
-                Database::open_file_with_flags(io.clone(), MEMORY_PATH, flags, mvcc, use_indexes)?;
+        if let Some(db) = opts.open_memory(mvcc, use_indexes)? {
             let conn = db.connect()?;
-            return Ok((io, conn));
+            return Ok((db.io.clone(), conn));
         }
         let (io, db) = Database::open_new(&opts.path, opts.vfs.as_ref(), flags, use_indexes, mvcc)?;
         if let Some(modeof) = opts.modeof {
@@ -782,13 +998,15 @@ impl Connection {
         Ok(())
     }
 
-    pub fn checkpoint(&self) -> Result<CheckpointResult> {
+    /// Checkpoint the WAL into the database file. Modes other than [CheckpointMode::Passive]
+    /// fail with [LimboError::Busy] if they can't do everything they're asked to.
+    pub fn checkpoint(&self, mode: CheckpointMode) -> Result<CheckpointResult> {
         if self.closed.get() {
             return Err(LimboError::InternalError("Connection closed".to_string()));
         }
         self.pager
             .borrow()
-            .wal_checkpoint(self.wal_checkpoint_disabled.get())
+            .wal_checkpoint(self.wal_checkpoint_disabled.get(), mode)
     }
 
     /// Close a connection and checkpoint.
@@ -797,6 +1015,7 @@ impl Connection {
             return Ok(());
         }
         self.closed.set(true);
+        self.close_attached()?;
         self.pager
             .borrow()
             .checkpoint_shutdown(self.wal_checkpoint_disabled.get())
@@ -806,6 +1025,8 @@ impl Connection {
         self.wal_checkpoint_disabled.set(true);
     }
 
+    /// The rowid of the most recent row inserted by an INSERT statement, like
+    /// `sqlite3_last_insert_rowid`.
     pub fn last_insert_rowid(&self) -> i64 {
         self.last_insert_rowid.get()
     }
@@ -814,16 +1035,23 @@ impl Connection {
         self.last_insert_rowid.set(rowid);
     }
 
-    pub fn set_changes(&self, nchange: i64) {
+    /// Records the rows changed by a statement once it is done: `nchange` rows changed by the
+    /// statement itself and `ntrigger_change` rows changed by the triggers it fired.
+    pub(crate) fn set_changes(&self, nchange: i64, ntrigger_change: i64) {
         self.last_change.set(nchange);
         let prev_total_changes = self.total_changes.get();
-        self.total_changes.set(prev_total_changes + nchange);
+        self.total_changes
+            .set(prev_total_changes + nchange + ntrigger_change);
     }
 
+    /// The number of rows inserted, updated or deleted by the most recent INSERT, UPDATE or
+    /// DELETE statement, like `sqlite3_changes`. Rows changed by triggers are not counted.
     pub fn changes(&self) -> i64 {
         self.last_change.get()
     }
 
+    /// The number of rows inserted, updated or deleted since the connection was opened, like
+    /// `sqlite3_total_changes`. Rows changed by triggers are counted.
     pub fn total_changes(&self) -> i64 {
         self.total_changes.get()
     }
@@ -835,12 +1063,384 @@ impl Connection {
         self.cache_size.set(size);
     }
 
+    pub fn get_cache_spill(&self) -> bool {
+        self.cache_spill.get()
+    }
+    pub fn set_cache_spill(&self, enabled: bool) {
+        self.cache_spill.set(enabled);
+    }
+    pub fn set_cache_spill_size(&self, pages: u32) {
+        self.cache_spill_size.set(pages);
+    }
+
+    /// Returns the number of pages sorters buffer in memory before spilling sorted runs to
+    /// temporary files: the larger of the cache size and the spill size.
+    pub fn get_cache_spill_pages(&self, page_size: usize) -> usize {
+        let cache_size = self.cache_size.get();
+        let cache_pages = if cache_size < 0 {
+            (cache_size.unsigned_abs() as usize * 1024) / page_size.max(1)
+        } else {
+            cache_size as usize
+        };
+        cache_pages.max(self.cache_spill_size.get() as usize)
+    }
+
+    /// Returns how many bytes a sorter buffers in memory before spilling, or `usize::MAX` if
+    /// spilling is disabled with `PRAGMA cache_spill=OFF`.
+    pub fn sorter_buffer_size(&self, page_size: usize) -> usize {
+        if !self.cache_spill.get() {
+            return usize::MAX;
+        }
+        self.get_cache_spill_pages(page_size)
+            .saturating_mul(page_size)
+    }
+
+    /// Sets the handler deciding whether to retry statements that fail because the database is
+    /// locked, replacing any busy timeout.
+    pub fn set_busy_handler(&self, handler: Option<BusyHandler>) {
+        self.busy_handler.replace(handler.map(Rc::new));
+        self.busy_timeout.set(Duration::ZERO);
+    }
+
+    /// Retries statements that fail because the database is locked for up to `timeout`,
+    /// replacing any busy handler. A zero timeout fails them right away.
+    pub fn set_busy_timeout(&self, timeout: Duration) {
+        self.busy_handler.replace(None);
+        self.busy_timeout.set(timeout);
+    }
+
+    pub fn get_busy_timeout(&self) -> Duration {
+        self.busy_timeout.get()
+    }
+
+    /// Sets the hook called for each row changed by the statements of the connection.
+    pub fn set_update_hook(&self, hook: Option<UpdateHook>) {
+        self.update_hook.replace(hook.map(Rc::new));
+    }
+
+    /// Sets the hook called when a write transaction of the connection is about to commit.
+    pub fn set_commit_hook(&self, hook: Option<CommitHook>) {
+        self.commit_hook.replace(hook.map(Rc::new));
+    }
+
+    /// Sets the hook called when a transaction of the connection is rolled back.
+    pub fn set_rollback_hook(&self, hook: Option<RollbackHook>) {
+        self.rollback_hook.replace(hook.map(Rc::new));
+    }
+
+    /// Sets the hook called before each row is changed by the statements of the connection.
+    pub fn set_preupdate_hook(&self, hook: Option<PreUpdateHook>) {
+        self.preupdate_hook.replace(hook.map(Rc::new));
+    }
+
+    /// Sets the authorizer asked about the actions of the statements of the connection as they
+    /// are prepared. Statements prepared before are not affected.
+    pub fn set_authorizer(&self, authorizer: Option<Authorizer>) {
+        self.authorizer.replace(authorizer.map(Rc::new));
+    }
+
+    pub(crate) fn get_authorizer(&self) -> Option<Rc<Authorizer>> {
+        self.authorizer.borrow().clone()
+    }
+
+    /// Returns the value of a limit on the statements of the connection, like `sqlite3_limit`,
+    /// after setting it to `value` if given. Limits can't be raised above their defaults, and
+    /// only apply to the statements prepared afterwards.
+    pub fn limit(&self, limit: Limit, value: Option<usize>) -> usize {
+        let mut limits = self.limits.get();
+        let previous = match value {
+            Some(value) => limits.set(limit, value),
+            None => limits.get(limit),
+        };
+        self.limits.set(limits);
+        previous
+    }
+
+    pub(crate) fn get_limits(&self) -> Limits {
+        self.limits.get()
+    }
+
+    /// Creates a collation sequence named `name` that compares strings with `collation`, like
+    /// `sqlite3_create_collation`, replacing any collation of the connection with the same name,
+    /// or removes it if `collation` is `None`. Built-in collations can't be overridden. Tables and
+    /// indexes that use a collation that doesn't exist compare like `BINARY`.
+    pub fn create_collation(&self, name: &str, collation: Option<CollationFn>) -> Result<()> {
+        let CollationSeq::Custom(name) = CollationSeq::from_name(name) else {
+            return Err(LimboError::InvalidArgument(format!(
+                "cannot override built-in collation {name}"
+            )));
+        };
+        let mut collations = self.collations.borrow_mut();
+        match collation {
+            Some(collation) => collations.insert(name.as_str().to_string(), Rc::new(collation)),
+            None => collations.remove(name.as_str()),
+        };
+        Ok(())
+    }
+
+    pub(crate) fn get_collations(&self) -> &Collations {
+        &self.collations
+    }
+
+    /// Creates a scalar function implemented by `func`, like `sqlite3_create_function`, replacing
+    /// any function of the connection with the same name. It takes `nargs` arguments, or any
+    /// number of them if `nargs` is `None`. Built-in functions can't be overridden, except upper(),
+    /// lower(), like() and glob(), which the LIKE and GLOB operators then call too.
+    pub fn create_scalar_function(
+        &self,
+        name: &str,
+        nargs: Option<usize>,
+        flags: FunctionFlags,
+        func: ScalarClosure,
+    ) {
+        let name = normalize_ident(name);
+        let func = function::ExternalFunc::new_closure(name.clone(), nargs, flags, func);
+        self.syms.borrow_mut().functions.insert(name, Rc::new(func));
+    }
+
+    /// Creates an aggregate function, like `sqlite3_create_function` with `xStep` and `xFinal`.
+    /// `init` creates its state for each group of rows, see [AggregateState]. The arguments and
+    /// the name are handled like with [Self::create_scalar_function].
+    pub fn create_aggregate_function(
+        &self,
+        name: &str,
+        nargs: Option<usize>,
+        flags: FunctionFlags,
+        init: AggregateInit,
+    ) {
+        self.insert_aggregate_function(name, nargs, flags, init, false);
+    }
+
+    /// Creates an aggregate function that can also be used as a window function, like
+    /// `sqlite3_create_window_function`. Its states must implement [AggregateState::value] and
+    /// [AggregateState::inverse], with which they follow the frame of the rows of the window.
+    pub fn create_window_function(
+        &self,
+        name: &str,
+        nargs: Option<usize>,
+        flags: FunctionFlags,
+        init: AggregateInit,
+    ) {
+        self.insert_aggregate_function(name, nargs, flags, init, true);
+    }
+
+    fn insert_aggregate_function(
+        &self,
+        name: &str,
+        nargs: Option<usize>,
+        flags: FunctionFlags,
+        init: AggregateInit,
+        window: bool,
+    ) {
+        let name = normalize_ident(name);
+        let func =
+            function::ExternalFunc::new_closure_aggregate(name.clone(), nargs, flags, init, window);
+        self.syms.borrow_mut().functions.insert(name, Rc::new(func));
+    }
+
+    /// Sets the handler called every `n` instructions run by a statement of the connection. A
+    /// zero `n` removes the handler.
+    pub fn set_progress_handler(&self, n: u64, handler: Option<ProgressHandler>) {
+        self.progress_handler.replace(
+            handler
+                .filter(|_| n > 0)
+                .map(|handler| (n, Rc::new(handler))),
+        );
+    }
+
+    pub(crate) fn get_progress_handler(&self) -> Option<(u64, Rc<ProgressHandler>)> {
+        self.progress_handler.borrow().clone()
+    }
+
+    /// Interrupts the statements of the connection that are running, like `sqlite3_interrupt`:
+    /// their changes are undone and they return [StepResult::Interrupt] until they are reset.
+    /// The statements started afterwards are not affected.
+    pub fn interrupt(&self) {
+        self.interrupt_handle().interrupt();
+    }
+
+    /// Returns a handle to interrupt the statements of the connection from another thread.
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        InterruptHandle {
+            interrupt_count: self.interrupt_count.clone(),
+        }
+    }
+
+    pub(crate) fn interrupt_count(&self) -> u64 {
+        self.interrupt_count.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn has_update_hook(&self) -> bool {
+        self.update_hook.borrow().is_some()
+    }
+
+    pub(crate) fn call_update_hook(
+        &self,
+        kind: UpdateKind,
+        database: &str,
+        table: &str,
+        rowid: i64,
+    ) {
+        // The hook is cloned out so that it may replace itself.
+        let hook = self.update_hook.borrow().clone();
+        if let Some(hook) = hook {
+            (*hook)(kind, database, table, rowid);
+        }
+    }
+
+    /// Invokes the commit hook, returning whether it turns the commit into a rollback.
+    pub(crate) fn call_commit_hook(&self) -> bool {
+        let hook = self.commit_hook.borrow().clone();
+        hook.is_some_and(|hook| (*hook)())
+    }
+
+    pub(crate) fn call_rollback_hook(&self) {
+        let hook = self.rollback_hook.borrow().clone();
+        if let Some(hook) = hook {
+            (*hook)();
+        }
+    }
+
+    pub(crate) fn has_preupdate_hook(&self) -> bool {
+        self.preupdate_hook.borrow().is_some()
+    }
+
+    pub(crate) fn call_preupdate_hook(&self, row: &PreUpdate) {
+        let hook = self.preupdate_hook.borrow().clone();
+        if let Some(hook) = hook {
+            (*hook)(row);
+        }
+    }
+
+    /// Invokes the busy handler after a statement was retried `count` times, returning whether to
+    /// retry it again.
+    fn handle_busy(&self, count: u32) -> bool {
+        // A transaction that already read can't wait for the writer, which may be waiting for
+        // it to finish.
+        if matches!(self.transaction_state.get(), TransactionState::Read) {
+            return false;
+        }
+        let handler = self.busy_handler.borrow().clone();
+        if let Some(handler) = handler {
+            return (*handler)(count);
+        }
+        // Like SQLite, sleep for increasing delays until the timeout is reached.
+        const DELAYS: [u64; 12] = [1, 2, 5, 10, 15, 20, 25, 25, 25, 50, 50, 100];
+        const TOTALS: [u64; 12] = [0, 1, 3, 8, 18, 33, 53, 78, 103, 128, 178, 228];
+        let timeout = self.busy_timeout.get().as_millis() as u64;
+        let count = count as usize;
+        let (mut delay, prior) = match DELAYS.get(count) {
+            Some(delay) => (*delay, TOTALS[count]),
+            None => (100, 228 + 100 * (count as u64 - 11)),
+        };
+        if prior + delay > timeout {
+            if prior >= timeout {
+                return false;
+            }
+            delay = timeout - prior;
+        }
+        std::thread::sleep(Duration::from_millis(delay));
+        true
+    }
+
     pub fn get_capture_data_changes(&self) -> std::cell::Ref<'_, CaptureDataChangesMode> {
         self.capture_data_changes.borrow()
     }
     pub fn set_capture_data_changes(&self, opts: CaptureDataChangesMode) {
         self.capture_data_changes.replace(opts);
     }
+    pub fn get_case_sensitive_like(&self) -> bool {
+        self.case_sensitive_like.get()
+    }
+    pub fn set_case_sensitive_like(&self, case_sensitive: bool) {
+        self.case_sensitive_like.set(case_sensitive);
+    }
+
+    /// Turns the index advisor on or off, forgetting its advice either way.
+    pub fn set_index_advisor(&self, enabled: bool) {
+        self.index_advice.replace(enabled.then(Vec::new));
+    }
+
+    /// The advice of the index advisor, like `automatic index on t(a)`, each given once.
+    pub fn get_index_advice(&self) -> Vec<String> {
+        self.index_advice.borrow().clone().unwrap_or_default()
+    }
+
+    /// Records that a statement builds an automatic index, which is logged as a warning if the
+    /// index advisor is on, like SQLite does with SQLITE_WARNING_AUTOINDEX.
+    fn advise_index(&self, index: &str) {
+        if let Some(advice) = self.index_advice.borrow_mut().as_mut() {
+            let advice_line = format!("automatic index on {index}");
+            tracing::warn!("{advice_line}");
+            if !advice.contains(&advice_line) {
+                advice.push(advice_line);
+            }
+        }
+    }
+    pub fn get_cipher(&self) -> CipherMode {
+        match self.pager.borrow().encryption() {
+            Some(encryption) => encryption.mode(),
+            None => self.cipher.get(),
+        }
+    }
+
+    pub fn set_cipher(&self, cipher: CipherMode) {
+        self.cipher.set(cipher);
+    }
+
+    /// Gives the key of an encrypted database, or encrypts a new database with it and the cipher
+    /// set by `PRAGMA cipher`. Like in SQLCipher, the statements of a connection to an encrypted
+    /// database fail with [LimboError::NotADB] until it gives the right key.
+    pub fn set_encryption_key(self: &Arc<Connection>, key: EncryptionKey) -> Result<()> {
+        if !self.auto_commit.get() {
+            return Err(LimboError::TxError(
+                "cannot set the key from within a transaction".to_string(),
+            ));
+        }
+        let pager = self.pager.borrow().clone();
+        if pager.encryption().is_some() {
+            return Err(LimboError::InvalidArgument(
+                "the key was already given, use PRAGMA rekey to change it".to_string(),
+            ));
+        }
+        let is_new = self._db.db_state.load(Ordering::SeqCst) < DB_STATE_INITIALIZED;
+        pager.set_encryption_key(self.cipher.get(), &key)?;
+        if is_new {
+            self._db.encrypted.store(true, Ordering::SeqCst);
+        } else if self.schema.borrow().schema_version == 0 {
+            // The schema of an encrypted database is read by the first connection that gives its
+            // key.
+            self._db.load_schema(self)?;
+            self.schema.replace(
+                self._db
+                    .schema
+                    .lock()
+                    .map_err(|_| LimboError::SchemaLocked)?
+                    .clone(),
+            );
+        }
+        Ok(())
+    }
+
+    /// Encrypts the database again with a new key, with `PRAGMA rekey`.
+    pub fn rekey(self: &Arc<Connection>, key: EncryptionKey) -> Result<()> {
+        if !self.auto_commit.get() {
+            return Err(LimboError::TxError(
+                "cannot change the key from within a transaction".to_string(),
+            ));
+        }
+        let pager = self.pager.borrow().clone();
+        pager.rekey(&key, self)
+    }
+
+    /// Fails if the database is encrypted and the connection didn't give its key yet.
+    pub(crate) fn check_encryption_key(&self) -> Result<()> {
+        if self._db.encrypted.load(Ordering::SeqCst) && self.pager.borrow().encryption().is_none() {
+            return Err(LimboError::NotADB);
+        }
+        Ok(())
+    }
+
     pub fn get_page_size(&self) -> u32 {
         self.page_size.get()
     }
@@ -863,6 +1463,7 @@ impl Connection {
 
         *self._db.maybe_shared_wal.write() = None;
         let pager = self._db.init_pager(Some(size as usize))?;
+        self.pager.borrow().copy_settings_to(&pager)?;
         self.pager.replace(Rc::new(pager));
         self.pager.borrow().set_initial_page_size(size);
 
@@ -895,6 +1496,11 @@ impl Connection {
         self.auto_commit.get()
     }
 
+    /// Whether the main database is an in-memory one, without a file.
+    pub(crate) fn is_in_memory(&self) -> bool {
+        self._db.path == MEMORY_PATH
+    }
+
     pub fn parse_schema_rows(self: &Arc<Connection>) -> Result<()> {
         if self.closed.get() {
             return Err(LimboError::InternalError("Connection closed".to_string()));
@@ -1009,11 +1615,40 @@ impl Connection {
     }
 }
 
+/// Where a [Statement] is in its execution.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum StatementStatus {
+    /// Not stepped since it was prepared or reset.
+    Ready,
+    /// Stepped, but not run to completion.
+    Running,
+    /// Run to completion.
+    Done,
+    /// Failed with an error.
+    Failed,
+}
+
+/// What a statement did since it was last prepared or reset, like `sqlite3_stmt_status`.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StatementStats {
+    /// The number of VDBE instructions run.
+    pub vm_steps: u64,
+    /// The pages read from the WAL or the database file.
+    pub pages_read: u64,
+    /// The pages written to the WAL.
+    pub pages_written: u64,
+    /// The pages found in the page cache.
+    pub cache_hits: u64,
+}
+
 pub struct Statement {
     program: Rc<vdbe::Program>,
     state: vdbe::ProgramState,
     mv_store: Option<Rc<MvStore>>,
     pager: Rc<Pager>,
+    status: StatementStatus,
+    /// The counters of the pager when the statement was first stepped.
+    pager_stats: PagerStats,
 }
 
 impl Statement {
@@ -1028,6 +1663,8 @@ impl Statement {
             state,
             mv_store,
             pager,
+            status: StatementStatus::Ready,
+            pager_stats: PagerStats::default(),
         }
     }
 
@@ -1039,9 +1676,52 @@ impl Statement {
         self.state.interrupt();
     }
 
+    /// Runs the statement until it produces a row, needs I/O or finishes. Once it has finished,
+    /// it keeps returning [StepResult::Done] until it is [reset](Statement::reset), and once it
+    /// has failed, stepping it is a misuse until it is reset.
     pub fn step(&mut self) -> Result<StepResult> {
-        self.program
-            .step(&mut self.state, self.mv_store.clone(), self.pager.clone())
+        match self.status {
+            StatementStatus::Done => return Ok(StepResult::Done),
+            StatementStatus::Failed => {
+                return Err(LimboError::Misuse(
+                    "statement must be reset after an error".to_string(),
+                ))
+            }
+            StatementStatus::Ready => self.pager_stats = self.pager.stats(),
+            StatementStatus::Running => {}
+        }
+        let mut busy_count = 0;
+        loop {
+            let result =
+                match self
+                    .program
+                    .step(&mut self.state, self.mv_store.clone(), self.pager.clone())
+                {
+                    Ok(result) => result,
+                    // A busy statement can be stepped again, like after [StepResult::Busy].
+                    Err(err @ LimboError::Busy) => return Err(err),
+                    Err(err) => {
+                        self.status = StatementStatus::Failed;
+                        return Err(err);
+                    }
+                };
+            if matches!(result, StepResult::Busy) && self.program.connection.handle_busy(busy_count)
+            {
+                busy_count += 1;
+                continue;
+            }
+            self.status = match result {
+                StepResult::Done => StatementStatus::Done,
+                _ => StatementStatus::Running,
+            };
+            return Ok(result);
+        }
+    }
+
+    /// Whether the statement has been stepped but has not run to completion or been reset,
+    /// like `sqlite3_stmt_busy`.
+    pub fn is_busy(&self) -> bool {
+        self.status == StatementStatus::Running
     }
 
     pub fn run_once(&self) -> Result<()> {
@@ -1068,26 +1748,76 @@ impl Statement {
         }
     }
 
+    /// The name of the table a result column comes from, if it is a table column, like
+    /// `sqlite3_column_table_name`.
+    pub fn get_column_table_name(&self, idx: usize) -> Option<&str> {
+        let column = self.program.result_columns.get(idx)?;
+        column.table_name(&self.program.table_references)
+    }
+
+    /// The declared type of a result column, if it is a table column, like
+    /// `sqlite3_column_decltype`.
+    pub fn get_column_decltype(&self, idx: usize) -> Option<&str> {
+        let column = self.program.result_columns.get(idx)?;
+        column.decltype(&self.program.table_references)
+    }
+
     pub fn parameters(&self) -> &parameters::Parameters {
         &self.program.parameters
     }
 
-    pub fn parameters_count(&self) -> usize {
+    /// The largest parameter number in the statement, like `sqlite3_bind_parameter_count`.
+    pub fn parameter_count(&self) -> usize {
         self.program.parameters.count()
     }
 
+    /// The name of a parameter, like `sqlite3_bind_parameter_name`.
+    pub fn parameter_name(&self, index: NonZero<usize>) -> Option<String> {
+        self.program.parameters.name(index)
+    }
+
+    /// The number of the parameter with the given name, like `sqlite3_bind_parameter_index`.
+    pub fn parameter_index(&self, name: &str) -> Option<NonZero<usize>> {
+        self.program.parameters.index(name)
+    }
+
     pub fn bind_at(&mut self, index: NonZero<usize>, value: Value) {
         self.state.bind_at(index, value);
     }
 
+    /// Sets all the parameters back to NULL, like `sqlite3_clear_bindings`.
+    pub fn clear_bindings(&mut self) {
+        self.state.clear_bindings();
+    }
+
+    /// Rewinds the statement so that it can be stepped again from the start. The parameters
+    /// keep their values, see [Statement::clear_bindings].
     pub fn reset(&mut self) {
         self.state.reset();
+        self.status = StatementStatus::Ready;
     }
 
     pub fn row(&self) -> Option<&Row> {
         self.state.result_row.as_ref()
     }
 
+    /// The instructions the statement ran and the pages it accessed since it was prepared or
+    /// reset. The pages are counted on the connection's pager, so the statements stepped in
+    /// between are counted too.
+    pub fn stats(&self) -> StatementStats {
+        if self.status == StatementStatus::Ready {
+            return StatementStats::default();
+        }
+        let start = self.pager_stats;
+        let now = self.pager.stats();
+        StatementStats {
+            vm_steps: self.state.n_steps,
+            pages_read: now.cache_misses - start.cache_misses,
+            pages_written: now.pages_written - start.pages_written,
+            cache_hits: now.cache_hits - start.cache_hits,
+        }
+    }
+
     pub fn explain(&self) -> String {
         self.program.explain()
     }
@@ -1148,9 +1878,13 @@ impl SymbolTable {
     pub fn resolve_function(
         &self,
         name: &str,
-        _arg_count: usize,
+        arg_count: usize,
     ) -> Option<Rc<function::ExternalFunc>> {
-        self.functions.get(name).cloned()
+        self.functions
+            .get(name)
+            .or_else(|| self.functions.get(&normalize_ident(name)))
+            .filter(|func| func.accepts_arg_count(arg_count))
+            .cloned()
     }
 }
 
@@ -0,0 +1,60 @@
+//! The limits on the statements of a connection, see [crate::Connection::limit].
+
+/// A limit on the statements of a connection, like the categories of `sqlite3_limit`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    /// The length of the SQL text of a statement, in bytes.
+    SqlLength,
+    /// The depth of the expression trees of a statement.
+    ExprDepth,
+    /// The number of SELECTs in a compound SELECT.
+    CompoundSelect,
+    /// The number of instructions in the program of a statement.
+    VdbeOp,
+    /// The number of databases that can be attached to the connection.
+    Attached,
+}
+
+impl Limit {
+    const COUNT: usize = 5;
+
+    /// The largest value of the limit, which is also its default, like SQLite's compile time
+    /// `SQLITE_MAX_*` bounds.
+    pub fn max(self) -> usize {
+        match self {
+            Limit::SqlLength => 1_000_000_000,
+            Limit::ExprDepth => 1000,
+            Limit::CompoundSelect => 500,
+            Limit::VdbeOp => 250_000_000,
+            Limit::Attached => 10,
+        }
+    }
+}
+
+/// The values of the limits of a connection.
+#[derive(Debug, Clone, Copy)]
+pub struct Limits([usize; Limit::COUNT]);
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self([
+            Limit::SqlLength.max(),
+            Limit::ExprDepth.max(),
+            Limit::CompoundSelect.max(),
+            Limit::VdbeOp.max(),
+            Limit::Attached.max(),
+        ])
+    }
+}
+
+impl Limits {
+    pub fn get(&self, limit: Limit) -> usize {
+        self.0[limit as usize]
+    }
+
+    /// Sets a limit, which can't be raised above its largest value, and returns its previous
+    /// value.
+    pub fn set(&mut self, limit: Limit, value: usize) -> usize {
+        std::mem::replace(&mut self.0[limit as usize], value.min(limit.max()))
+    }
+}
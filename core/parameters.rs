@@ -1,6 +1,7 @@
 use std::num::NonZero;
 
-pub const PARAM_PREFIX: &str = "__param_";
+use turso_sqlite3_parser::ast::fmt::TokenStream;
+use turso_sqlite3_parser::dialect::TokenType;
 
 #[derive(Clone, Debug)]
 pub enum Parameter {
@@ -25,6 +26,11 @@ impl Parameter {
     }
 }
 
+/// The parameters of a statement, numbered like in SQLite: `?NNN` is parameter NNN, and `?` or
+/// the first occurrence of a named parameter is the parameter after the largest one before it.
+/// The parameters are collected from the statement before it is translated (see the
+/// [TokenStream] implementation), because the numbers depend on the order in which they
+/// appear in the statement.
 #[derive(Debug)]
 pub struct Parameters {
     index: NonZero<usize>,
@@ -45,26 +51,34 @@ impl Parameters {
         }
     }
 
+    /// The number of parameters, which is the largest parameter number, like
+    /// `sqlite3_bind_parameter_count`. Unused numbers below it are counted too.
     pub fn count(&self) -> usize {
-        let mut params = self.list.clone();
-        params.dedup();
-        params.len()
+        self.index.get() - 1
     }
 
+    /// The name of a parameter, like `sqlite3_bind_parameter_name`: `?NNN` or the name of a
+    /// named parameter, including its prefix. A `?` has no name.
     pub fn name(&self, index: NonZero<usize>) -> Option<String> {
         self.list.iter().find_map(|p| match p {
-            Parameter::Anonymous(i) if *i == index => Some("?".to_string()),
             Parameter::Indexed(i) if *i == index => Some(format!("?{i}")),
             Parameter::Named(name, i) if *i == index => Some(name.to_owned()),
             _ => None,
         })
     }
 
+    /// The number of the parameter with the given name, like `sqlite3_bind_parameter_index`.
+    /// `?NNN` parameters can be looked up by name too.
     pub fn index(&self, name: impl AsRef<str>) -> Option<NonZero<usize>> {
         self.list
             .iter()
             .find_map(|p| match p {
                 Parameter::Named(n, index) if n == name.as_ref() => Some(index),
+                Parameter::Indexed(index)
+                    if name.as_ref().strip_prefix('?') == Some(&index.to_string()) =>
+                {
+                    Some(index)
+                }
                 _ => None,
             })
             .copied()
@@ -76,49 +90,73 @@ impl Parameters {
         index
     }
 
+    /// Returns the number of a parameter as it appears in [turso_sqlite3_parser::ast::Expr::Variable],
+    /// adding the parameter if it has not been seen yet.
     pub fn push(&mut self, name: impl AsRef<str>) -> NonZero<usize> {
         match name.as_ref() {
-            param if param.is_empty() || param.starts_with(PARAM_PREFIX) => {
+            "" => {
                 let index = self.next_index();
-                let use_idx = if let Some(idx) = param.strip_prefix(PARAM_PREFIX) {
-                    idx.parse().unwrap()
-                } else {
-                    index
-                };
-                self.list.push(Parameter::Anonymous(use_idx));
-                tracing::trace!("anonymous parameter at {use_idx}");
-                use_idx
+                self.list.push(Parameter::Anonymous(index));
+                tracing::trace!("anonymous parameter at {index}");
+                index
             }
-            name if name.starts_with(['$', ':', '@', '#']) => {
-                match self
-                    .list
-                    .iter()
-                    .find(|p| matches!(p, Parameter::Named(n, _) if name == n))
-                {
-                    Some(t) => {
-                        let index = t.index();
-                        self.list.push(t.clone());
-                        tracing::trace!("named parameter at {index} as {name}");
-                        index
-                    }
-                    None => {
-                        let index = self.next_index();
-                        self.list.push(Parameter::Named(name.to_owned(), index));
-                        tracing::trace!("named parameter at {index} as {name}");
-                        index
-                    }
-                }
+            name if name.starts_with('?') => {
+                // The parser numbers every `?`, see [turso_sqlite3_parser::ast::Expr::Variable].
+                let index: NonZero<usize> = name[1..].parse().unwrap();
+                self.add(Parameter::Anonymous(index));
+                tracing::trace!("anonymous parameter at {index}");
+                index
             }
+            name if name.starts_with(['$', ':', '@', '#']) => match self.index(name) {
+                Some(index) => index,
+                None => {
+                    let index = self.next_index();
+                    self.list.push(Parameter::Named(name.to_owned(), index));
+                    tracing::trace!("named parameter at {index} as {name}");
+                    index
+                }
+            },
             index => {
                 // SAFETY: Guaranteed from parser that the index is bigger than 0.
                 let index: NonZero<usize> = index.parse().unwrap();
-                if index > self.index {
-                    self.index = index.checked_add(1).unwrap();
-                }
-                self.list.push(Parameter::Indexed(index));
+                self.add(Parameter::Indexed(index));
                 tracing::trace!("indexed parameter at {index}");
                 index
             }
         }
     }
+
+    fn add(&mut self, parameter: Parameter) {
+        let index = parameter.index();
+        if index >= self.index {
+            self.index = index.checked_add(1).unwrap();
+        }
+        if !self.list.iter().any(|p| p.index() == index) {
+            self.list.push(parameter);
+        }
+    }
+}
+
+/// Collects the parameters of a statement in the order they appear in it, so that named
+/// parameters are numbered like in SQLite no matter in which order they are translated.
+impl TokenStream for Parameters {
+    type Error = std::convert::Infallible;
+
+    fn append(&mut self, ty: TokenType, value: Option<&str>) -> Result<(), Self::Error> {
+        if let (TokenType::TK_VARIABLE, Some(variable)) = (ty, value) {
+            match variable.strip_prefix('?') {
+                Some("") => {
+                    let index = self.next_index();
+                    self.list.push(Parameter::Anonymous(index));
+                }
+                Some(index) => {
+                    self.push(index);
+                }
+                None => {
+                    self.push(variable);
+                }
+            }
+        }
+        Ok(())
+    }
 }
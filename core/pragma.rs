@@ -35,6 +35,11 @@ pub fn pragma_for(pragma: &PragmaName) -> Pragma {
     use PragmaName::*;
 
     match pragma {
+        ApplicationId => Pragma::new(
+            PragmaFlags::NoColumns1 | PragmaFlags::Result0,
+            &["application_id"],
+        ),
+        BusyTimeout => Pragma::new(PragmaFlags::Result0, &["timeout"]),
         CacheSize => Pragma::new(
             PragmaFlags::NeedSchema
                 | PragmaFlags::Result0
@@ -42,6 +47,22 @@ pub fn pragma_for(pragma: &PragmaName) -> Pragma {
                 | PragmaFlags::NoColumns1,
             &["cache_size"],
         ),
+        CacheSpill => Pragma::new(
+            PragmaFlags::Result0 | PragmaFlags::NoColumns1,
+            &["cache_spill"],
+        ),
+        CaseSensitiveLike => Pragma::new(PragmaFlags::NoColumns, &[]),
+        Cipher => Pragma::new(PragmaFlags::Result0 | PragmaFlags::NoColumns1, &["cipher"]),
+        Compression => Pragma::new(
+            PragmaFlags::Result0 | PragmaFlags::NoColumns1,
+            &["compression"],
+        ),
+        Hexkey | Hexrekey | Key | Rekey => Pragma::new(PragmaFlags::NoColumns, &[]),
+        DatabaseList => Pragma::new(PragmaFlags::Result0, &["seq", "name", "file"]),
+        Encoding => Pragma::new(
+            PragmaFlags::Result0 | PragmaFlags::NoColumns1,
+            &["encoding"],
+        ),
         JournalMode => Pragma::new(
             PragmaFlags::NeedSchema | PragmaFlags::Result0 | PragmaFlags::SchemaReq,
             &["journal_mode"],
@@ -49,6 +70,7 @@ pub fn pragma_for(pragma: &PragmaName) -> Pragma {
         LegacyFileFormat => {
             unreachable!("pragma_for() called with LegacyFileFormat, which is unsupported")
         }
+        Optimize => Pragma::new(PragmaFlags::NeedSchema | PragmaFlags::NoColumns, &[]),
         PageCount => Pragma::new(
             PragmaFlags::NeedSchema | PragmaFlags::Result0 | PragmaFlags::SchemaReq,
             &["page_count"],
@@ -61,6 +83,13 @@ pub fn pragma_for(pragma: &PragmaName) -> Pragma {
             PragmaFlags::NoColumns1 | PragmaFlags::Result0,
             &["schema_version"],
         ),
+        Synchronous => Pragma::new(
+            PragmaFlags::NeedSchema
+                | PragmaFlags::Result0
+                | PragmaFlags::SchemaReq
+                | PragmaFlags::NoColumns1,
+            &["synchronous"],
+        ),
         TableInfo => Pragma::new(
             PragmaFlags::NeedSchema | PragmaFlags::Result1 | PragmaFlags::SchemaOpt,
             &["cid", "name", "type", "notnull", "dflt_value", "pk"],
@@ -69,12 +98,23 @@ pub fn pragma_for(pragma: &PragmaName) -> Pragma {
             PragmaFlags::NoColumns1 | PragmaFlags::Result0,
             &["user_version"],
         ),
+        WalAutocheckpoint => Pragma::new(PragmaFlags::empty(), &["wal_autocheckpoint"]),
         WalCheckpoint => Pragma::new(PragmaFlags::NeedSchema, &["busy", "log", "checkpointed"]),
         AutoVacuum => Pragma::new(
             PragmaFlags::NoColumns1 | PragmaFlags::Result0,
             &["auto_vacuum"],
         ),
-        IntegrityCheck => Pragma::new(
+        IncrementalVacuum => Pragma::new(PragmaFlags::NeedSchema | PragmaFlags::NoColumns, &[]),
+        IndexAdvisor => Pragma::new(PragmaFlags::Result0 | PragmaFlags::NoColumns1, &["advice"]),
+        IndexInfo => Pragma::new(
+            PragmaFlags::NeedSchema | PragmaFlags::Result1 | PragmaFlags::SchemaOpt,
+            &["seqno", "cid", "name"],
+        ),
+        IndexList => Pragma::new(
+            PragmaFlags::NeedSchema | PragmaFlags::Result1 | PragmaFlags::SchemaOpt,
+            &["seq", "name", "unique", "origin", "partial"],
+        ),
+        IntegrityCheck | QuickCheck => Pragma::new(
             PragmaFlags::NeedSchema | PragmaFlags::ReadOnly | PragmaFlags::Result0,
             &["message"],
         ),
@@ -0,0 +1,94 @@
+//! The row passed to the preupdate hook of a connection, see [crate::Connection::set_preupdate_hook].
+
+use crate::schema::BTreeTable;
+use crate::types::ImmutableRecord;
+use crate::vdbe::builder::constant_default_value;
+use crate::{LimboError, Result, UpdateKind, Value};
+
+/// A row about to be inserted, updated or deleted, whose values can be read as they are before
+/// and after the change, like with `sqlite3_preupdate_old` and `sqlite3_preupdate_new`.
+pub struct PreUpdate<'a> {
+    pub(crate) kind: UpdateKind,
+    pub(crate) database: &'a str,
+    pub(crate) table: &'a BTreeTable,
+    /// The rowid and the record of the row before the change, unless it is an insert.
+    pub(crate) old: Option<(i64, &'a ImmutableRecord)>,
+    /// The rowid and the record of the row after the change, unless it is a delete.
+    pub(crate) new: Option<(i64, &'a ImmutableRecord)>,
+}
+
+impl PreUpdate<'_> {
+    pub fn kind(&self) -> UpdateKind {
+        self.kind
+    }
+
+    /// The name of the database of the table: `main` or the name of an attached database.
+    pub fn database(&self) -> &str {
+        self.database
+    }
+
+    pub fn table_name(&self) -> &str {
+        &self.table.name
+    }
+
+    /// The rowid of the row before the change, or of the inserted row for an insert.
+    pub fn old_rowid(&self) -> i64 {
+        self.old.or(self.new).map(|(rowid, _)| rowid).unwrap()
+    }
+
+    /// The rowid of the row after the change, or of the deleted row for a delete.
+    pub fn new_rowid(&self) -> i64 {
+        self.new.or(self.old).map(|(rowid, _)| rowid).unwrap()
+    }
+
+    /// The number of columns of the table, like `sqlite3_preupdate_count`.
+    pub fn count(&self) -> usize {
+        self.table.columns.len()
+    }
+
+    /// The value of a column before the change. Inserted rows have no old values.
+    pub fn old_value(&self, column: usize) -> Result<Value> {
+        match self.old {
+            Some(row) => self.value(row, column),
+            None => Err(LimboError::Misuse(
+                "an inserted row has no old values".to_string(),
+            )),
+        }
+    }
+
+    /// The value of a column after the change. Deleted rows have no new values.
+    pub fn new_value(&self, column: usize) -> Result<Value> {
+        match self.new {
+            Some(row) => self.value(row, column),
+            None => Err(LimboError::Misuse(
+                "a deleted row has no new values".to_string(),
+            )),
+        }
+    }
+
+    fn value(&self, (rowid, record): (i64, &ImmutableRecord), column: usize) -> Result<Value> {
+        let Some(col) = self.table.columns.get(column) else {
+            return Err(LimboError::InvalidArgument(format!(
+                "column index {column} out of range"
+            )));
+        };
+        if col.is_rowid_alias {
+            return Ok(Value::Integer(rowid));
+        }
+        // VIRTUAL generated columns are not stored.
+        if col.is_virtual_generated() {
+            return Ok(Value::Null);
+        }
+        // The columns added after the row was written take their default value.
+        Ok(
+            match record.get_value_opt(self.table.column_record_pos(column)) {
+                Some(value) => value.to_owned(),
+                None => col
+                    .default
+                    .as_ref()
+                    .and_then(constant_default_value)
+                    .unwrap_or(Value::Null),
+            },
+        )
+    }
+}
@@ -0,0 +1,352 @@
+//! Best-effort recovery of the rows of a damaged database, like the `.recover` command of the
+//! SQLite shell.
+//!
+//! The btree cursors give up on the first inconsistency they find, so the pages are read and
+//! parsed one by one instead, skipping the cells and pages that can't be parsed. Each table of
+//! the schema is recovered by walking its btree from its root page, and the rows of the table
+//! leaf pages that no btree reaches are collected into a `lost_and_found` table.
+
+use crate::result::LimboResult;
+use crate::schema::BTreeTable;
+use crate::storage::header_accessor;
+use crate::storage::pager::Pager;
+use crate::storage::sqlite3_ondisk::{read_u32, read_value, read_varint};
+use crate::types::SerialType;
+use crate::util::IOExt as _;
+use crate::{Connection, LimboError, PageRef, Result, TransactionState, Value};
+use std::collections::HashSet;
+
+const TABLE_INTERIOR_PAGE: u8 = 5;
+const TABLE_LEAF_PAGE: u8 = 13;
+
+/// What could be recovered from a database.
+#[derive(Debug, Default)]
+pub struct Recovery {
+    pub tables: Vec<RecoveredTable>,
+    /// The statements creating the indexes, views and triggers of the schema, to be run once
+    /// the tables are filled.
+    pub schema: Vec<String>,
+}
+
+/// A table and the rows recovered from it.
+#[derive(Debug)]
+pub struct RecoveredTable {
+    pub name: String,
+    /// The statement that creates the table. For a table whose statement can't be read from the
+    /// schema, it has as many columns named `c0`, `c1`, ... as the widest row.
+    pub sql: String,
+    pub rows: Vec<Vec<Value>>,
+}
+
+impl Connection {
+    /// Recovers what it can from the database, whatever the damage.
+    pub fn recover(&self) -> Result<Recovery> {
+        let pager = self.pager.borrow().clone();
+        // A transaction already open on the connection is used as is.
+        let read_tx = matches!(self.transaction_state.get(), TransactionState::None);
+        if read_tx && matches!(pager.io.block(|| pager.begin_read_tx())?, LimboResult::Busy) {
+            return Err(LimboError::Busy);
+        }
+        let result = PageWalker::new(&pager).map(|mut walker| walker.recover());
+        if read_tx {
+            pager.end_read_tx()?;
+        }
+        result
+    }
+}
+
+struct PageWalker<'a> {
+    pager: &'a Pager,
+    usable_space: usize,
+    page_count: usize,
+    /// The pages already read, which are never read twice so that cycles end.
+    visited: HashSet<usize>,
+}
+
+impl<'a> PageWalker<'a> {
+    fn new(pager: &'a Pager) -> Result<Self> {
+        Ok(Self {
+            pager,
+            usable_space: pager.usable_space(),
+            page_count: header_accessor::get_database_size(pager)? as usize,
+            visited: HashSet::new(),
+        })
+    }
+
+    fn recover(&mut self) -> Recovery {
+        let mut recovery = Recovery::default();
+        self.visit_freelist();
+        let mut schema_rows = Vec::new();
+        self.walk_table(1, &mut schema_rows);
+        for (_, row) in schema_rows {
+            let text = |i: usize| match row.get(i) {
+                Some(Value::Text(text)) => Some(text.as_str().to_string()),
+                _ => None,
+            };
+            let (Some(kind), Some(name)) = (text(0), text(1)) else {
+                continue;
+            };
+            let root_page = match row.get(3) {
+                Some(Value::Integer(root_page)) => *root_page,
+                _ => 0,
+            };
+            match (kind.as_str(), text(4)) {
+                ("table", sql) => {
+                    let mut rows = Vec::new();
+                    if root_page > 0 {
+                        self.walk_table(root_page as usize, &mut rows);
+                    }
+                    recovery
+                        .tables
+                        .push(recovered_table(name, sql, root_page as usize, rows));
+                }
+                (_, Some(sql)) => recovery.schema.push(sql),
+                _ => {}
+            }
+        }
+        let lost_and_found = self.lost_and_found();
+        if !lost_and_found.is_empty() {
+            let mut name = "lost_and_found".to_string();
+            let mut suffix = 0;
+            while recovery
+                .tables
+                .iter()
+                .any(|t| t.name.eq_ignore_ascii_case(&name))
+            {
+                name = format!("lost_and_found_{suffix}");
+                suffix += 1;
+            }
+            let width = lost_and_found.iter().map(Vec::len).max().unwrap_or(0);
+            let columns = [
+                "rootpgno INTEGER",
+                "pgno INTEGER",
+                "nfield INTEGER",
+                "id INTEGER",
+            ]
+            .into_iter()
+            .map(str::to_string)
+            .chain((0..width - 4).map(|i| format!("c{i}")))
+            .collect::<Vec<_>>();
+            recovery.tables.push(RecoveredTable {
+                sql: format!("CREATE TABLE {name}({})", columns.join(", ")),
+                name,
+                rows: lost_and_found,
+            });
+        }
+        recovery
+    }
+
+    /// Marks the pages of the freelist as visited, as the rows left on them were deleted.
+    fn visit_freelist(&mut self) {
+        let mut trunk = header_accessor::get_freelist_trunk_page(self.pager).unwrap_or(0) as usize;
+        while let Some(page) = self.read_page(trunk) {
+            let buf = page.get_contents().as_ptr();
+            trunk = read_u32(buf, 0) as usize;
+            let leaves = (read_u32(buf, 4) as usize).min((self.usable_space - 8) / 4);
+            for i in 0..leaves {
+                self.visited.insert(read_u32(buf, 8 + i * 4) as usize);
+            }
+        }
+    }
+
+    /// Reads a page that wasn't visited yet, and marks it as visited.
+    fn read_page(&mut self, page_idx: usize) -> Option<PageRef> {
+        if page_idx == 0 || page_idx > self.page_count || !self.visited.insert(page_idx) {
+            return None;
+        }
+        self.pager.read_page_blocking(page_idx).ok()
+    }
+
+    /// Collects the rows of the table btree rooted at `root_page`, along with their rowids.
+    fn walk_table(&mut self, root_page: usize, rows: &mut Vec<(i64, Vec<Value>)>) {
+        let mut stack = vec![root_page];
+        while let Some(page_idx) = stack.pop() {
+            let Some(page) = self.read_page(page_idx) else {
+                continue;
+            };
+            let buf = page.get_contents().as_ptr();
+            let offset = if page_idx == 1 { 100 } else { 0 };
+            match buf[offset] {
+                TABLE_INTERIOR_PAGE => {
+                    let mut children = cell_pointers(buf, offset, 12)
+                        .filter_map(|pos| buf.get(pos..pos + 4).map(|_| read_u32(buf, pos)))
+                        .collect::<Vec<_>>();
+                    children.push(read_u32(buf, offset + 8));
+                    // The leftmost child is walked first, to recover the rows in rowid order.
+                    stack.extend(children.into_iter().rev().map(|child| child as usize));
+                }
+                TABLE_LEAF_PAGE => rows.extend(self.leaf_rows(buf, offset)),
+                _ => {}
+            }
+        }
+    }
+
+    /// Collects the rows of the table leaf pages that weren't visited by walking the btrees.
+    fn lost_and_found(&mut self) -> Vec<Vec<Value>> {
+        let mut lost_and_found = Vec::new();
+        for page_idx in 2..=self.page_count {
+            if self.visited.contains(&page_idx) {
+                continue;
+            }
+            let Ok(page) = self.pager.read_page_blocking(page_idx) else {
+                continue;
+            };
+            let buf = page.get_contents().as_ptr();
+            if buf[0] != TABLE_LEAF_PAGE {
+                continue;
+            }
+            self.visited.insert(page_idx);
+            for (rowid, values) in self.leaf_rows(buf, 0) {
+                let mut row = vec![
+                    Value::Null,
+                    Value::Integer(page_idx as i64),
+                    Value::Integer(values.len() as i64),
+                    Value::Integer(rowid),
+                ];
+                row.extend(values);
+                lost_and_found.push(row);
+            }
+        }
+        let width = lost_and_found.iter().map(Vec::len).max().unwrap_or(0);
+        for row in lost_and_found.iter_mut() {
+            row.resize(width, Value::Null);
+        }
+        lost_and_found
+    }
+
+    fn leaf_rows(&mut self, buf: &[u8], offset: usize) -> Vec<(i64, Vec<Value>)> {
+        cell_pointers(buf, offset, 8)
+            .filter_map(|pos| self.leaf_cell(buf, pos))
+            .collect()
+    }
+
+    /// Reads the rowid and the values of a table leaf cell. The values of a record whose
+    /// overflow pages are missing are truncated.
+    fn leaf_cell(&mut self, buf: &[u8], pos: usize) -> Option<(i64, Vec<Value>)> {
+        let (payload_size, n) = varint(buf.get(pos..)?)?;
+        let (rowid, m) = varint(buf.get(pos + n..)?)?;
+        let start = pos + n + m;
+        let payload_size = payload_size as usize;
+        let max_local = self.usable_space - 35;
+        if payload_size <= max_local {
+            let payload = buf.get(start..start + payload_size)?;
+            return Some((rowid as i64, read_record(payload)));
+        }
+        let min_local = ((self.usable_space - 12) * 32 / 255) - 23;
+        let local = min_local + (payload_size - min_local) % (self.usable_space - 4);
+        let local = if local <= max_local { local } else { min_local };
+        let mut payload = buf.get(start..start + local)?.to_vec();
+        let mut next = read_u32(buf.get(start + local..start + local + 4)?, 0) as usize;
+        while payload.len() < payload_size {
+            let Some(page) = self.read_page(next) else {
+                break;
+            };
+            let overflow = page.get_contents().as_ptr();
+            next = read_u32(overflow, 0) as usize;
+            let len = (payload_size - payload.len()).min(self.usable_space - 4);
+            payload.extend_from_slice(&overflow[4..4 + len]);
+        }
+        Some((rowid as i64, read_record(&payload)))
+    }
+}
+
+/// The positions of the cells of the btree page whose header of `header_size` bytes starts at
+/// `offset`, leaving out the ones that point outside of the page.
+fn cell_pointers(
+    buf: &[u8],
+    offset: usize,
+    header_size: usize,
+) -> impl Iterator<Item = usize> + '_ {
+    let cell_count = u16::from_be_bytes([buf[offset + 3], buf[offset + 4]]) as usize;
+    let pointers = offset + header_size;
+    (0..cell_count)
+        .map_while(move |i| buf.get(pointers + i * 2..pointers + i * 2 + 2))
+        .map(|pointer| u16::from_be_bytes([pointer[0], pointer[1]]) as usize)
+        .filter(move |&pos| pos > pointers && pos < buf.len())
+}
+
+fn varint(buf: &[u8]) -> Option<(u64, usize)> {
+    // A varint cut short by the end of the buffer
+    if buf.len() < 9 && buf.iter().all(|byte| byte & 0x80 != 0) {
+        return None;
+    }
+    read_varint(buf).ok()
+}
+
+/// Reads the values of a record, up to the first one that can't be read.
+fn read_record(payload: &[u8]) -> Vec<Value> {
+    let mut values = Vec::new();
+    let Some((header_size, mut header_pos)) = varint(payload) else {
+        return values;
+    };
+    let header_size = (header_size as usize).min(payload.len());
+    let mut value_pos = header_size;
+    while header_pos < header_size {
+        let Some((serial_type, n)) = varint(&payload[header_pos..header_size]) else {
+            break;
+        };
+        header_pos += n;
+        let Ok(serial_type) = SerialType::try_from(serial_type) else {
+            break;
+        };
+        let Ok((value, n)) = read_value(&payload[value_pos..], serial_type) else {
+            break;
+        };
+        values.push(value.to_owned());
+        value_pos += n;
+    }
+    values
+}
+
+/// Makes the rows of a table fit its columns, putting back the rowids of rowid alias columns.
+fn recovered_table(
+    name: String,
+    sql: Option<String>,
+    root_page: usize,
+    rows: Vec<(i64, Vec<Value>)>,
+) -> RecoveredTable {
+    let table = sql
+        .as_deref()
+        .filter(|sql| {
+            let sql = sql.trim_start().to_ascii_uppercase();
+            sql.starts_with("CREATE TABLE")
+        })
+        .and_then(|sql| BTreeTable::from_sql(sql, root_page).ok());
+    let Some(table) = table else {
+        let width = rows.iter().map(|(_, row)| row.len()).max().unwrap_or(0);
+        let sql = sql.unwrap_or_else(|| {
+            let columns = (0..width.max(1))
+                .map(|i| format!("c{i}"))
+                .collect::<Vec<_>>();
+            format!(
+                "CREATE TABLE \"{}\"({})",
+                name.replace('"', "\"\""),
+                columns.join(", ")
+            )
+        });
+        let rows = rows
+            .into_iter()
+            .map(|(_, mut row)| {
+                row.resize(width, Value::Null);
+                row
+            })
+            .collect();
+        return RecoveredTable { name, sql, rows };
+    };
+    let rowid_alias = table.get_rowid_alias_column().map(|(i, _)| i);
+    let rows = rows
+        .into_iter()
+        .map(|(rowid, mut row)| {
+            row.resize(table.columns.len(), Value::Null);
+            if let Some(i) = rowid_alias {
+                row[i] = Value::Integer(rowid);
+            }
+            row
+        })
+        .collect();
+    RecoveredTable {
+        name,
+        sql: sql.expect("the table was parsed from its statement"),
+        rows,
+    }
+}
@@ -0,0 +1,924 @@
+//! R-tree virtual tables for range queries over bounding boxes, modeled after SQLite's
+//! R*Tree module.
+//!
+//! ```sql
+//! CREATE VIRTUAL TABLE boxes USING rtree(id, min_x, max_x, min_y, max_y);
+//! INSERT INTO boxes VALUES (1, 0, 10, 0, 10);
+//! SELECT id FROM boxes WHERE max_x >= 5 AND min_x <= 7;
+//! ```
+//!
+//! The first column is the integer id of the box, which is also the rowid, followed by a
+//! minimum and a maximum coordinate for each of the 1 to 5 dimensions. Columns prefixed with
+//! `+` are auxiliary columns that are stored alongside the box but not indexed.
+//! `rtree` stores coordinates as 32-bit floats, rounding boxes outwards, and `rtree_i32` stores
+//! them as 32-bit integers.
+//!
+//! The tree lives in memory, owned by the table instance of the connection.
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::Arc;
+
+use crate::types::Value as DbValue;
+use turso_ext::{
+    Connection, ConstraintInfo, ConstraintOp, ConstraintUsage, ExtensionApi, IndexInfo,
+    OrderByInfo, ResultCode, VTabCursor, VTabKind, VTabModule, VTabModuleDerive, VTable, Value,
+    ValueType,
+};
+
+pub fn register_extension(ext_api: &mut ExtensionApi) {
+    // FIXME: Add macro magic to register functions automatically.
+    unsafe {
+        RTreeVTabModule::register_RTreeVTabModule(ext_api);
+        RTreeI32VTabModule::register_RTreeI32VTabModule(ext_api);
+    }
+}
+
+/// Maximum number of entries of a node before it gets split
+const MAX_ENTRIES: usize = 8;
+/// Minimum number of entries of a node other than the root
+const MIN_ENTRIES: usize = 3;
+const MAX_DIMENSIONS: usize = 5;
+
+/// An axis-aligned bounding box, with one (min, max) pair per dimension.
+#[derive(Debug, Clone, PartialEq)]
+struct BBox {
+    min: Vec<f64>,
+    max: Vec<f64>,
+}
+
+impl BBox {
+    fn area(&self) -> f64 {
+        self.min
+            .iter()
+            .zip(&self.max)
+            .map(|(min, max)| max - min)
+            .product()
+    }
+
+    fn union(&self, other: &BBox) -> BBox {
+        BBox {
+            min: self
+                .min
+                .iter()
+                .zip(&other.min)
+                .map(|(a, b)| a.min(*b))
+                .collect(),
+            max: self
+                .max
+                .iter()
+                .zip(&other.max)
+                .map(|(a, b)| a.max(*b))
+                .collect(),
+        }
+    }
+
+    fn enlargement(&self, other: &BBox) -> f64 {
+        self.union(other).area() - self.area()
+    }
+
+    fn contains(&self, other: &BBox) -> bool {
+        self.min.iter().zip(&other.min).all(|(a, b)| a <= b)
+            && self.max.iter().zip(&other.max).all(|(a, b)| a >= b)
+    }
+
+    fn union_all<'a>(mut boxes: impl Iterator<Item = &'a BBox>) -> Option<BBox> {
+        let first = boxes.next()?.clone();
+        Some(boxes.fold(first, |acc, b| acc.union(b)))
+    }
+}
+
+/// The entries of a node: the bounding box of each rowid or child node.
+type Entries<T> = Vec<(BBox, T)>;
+
+#[derive(Debug)]
+enum Node {
+    Leaf(Vec<(BBox, i64)>),
+    Internal(Vec<(BBox, Box<Node>)>),
+}
+
+impl Node {
+    fn len(&self) -> usize {
+        match self {
+            Node::Leaf(entries) => entries.len(),
+            Node::Internal(children) => children.len(),
+        }
+    }
+
+    fn bbox(&self) -> Option<BBox> {
+        match self {
+            Node::Leaf(entries) => BBox::union_all(entries.iter().map(|(b, _)| b)),
+            Node::Internal(children) => BBox::union_all(children.iter().map(|(b, _)| b)),
+        }
+    }
+
+    /// Inserts an entry below this node. If the node overflows, it is split and the
+    /// new sibling is returned.
+    fn insert(&mut self, bbox: BBox, id: i64) -> Option<(BBox, Node)> {
+        match self {
+            Node::Leaf(entries) => {
+                entries.push((bbox, id));
+                if entries.len() <= MAX_ENTRIES {
+                    return None;
+                }
+                let (kept, split) = quadratic_split(std::mem::take(entries));
+                *entries = kept;
+                let sibling = Node::Leaf(split);
+                Some((sibling.bbox()?, sibling))
+            }
+            Node::Internal(children) => {
+                // Descend into the child that needs the least enlargement, then the smallest one
+                let (idx, _) = children
+                    .iter()
+                    .enumerate()
+                    .min_by(|(_, (a, _)), (_, (b, _))| {
+                        (a.enlargement(&bbox), a.area())
+                            .partial_cmp(&(b.enlargement(&bbox), b.area()))
+                            .unwrap_or(std::cmp::Ordering::Equal)
+                    })?;
+                let split = children[idx].1.insert(bbox, id);
+                if let Some(child_bbox) = children[idx].1.bbox() {
+                    children[idx].0 = child_bbox;
+                }
+                if let Some((sibling_bbox, sibling)) = split {
+                    children.push((sibling_bbox, Box::new(sibling)));
+                }
+                if children.len() <= MAX_ENTRIES {
+                    return None;
+                }
+                let (kept, split) = quadratic_split(std::mem::take(children));
+                *children = kept;
+                let sibling = Node::Internal(split);
+                Some((sibling.bbox()?, sibling))
+            }
+        }
+    }
+
+    /// Removes the entry `id` with the given box from below this node. Children left with
+    /// too few entries are removed, and their entries are collected in `orphans` to be
+    /// inserted again. Returns whether the entry was found.
+    fn delete(&mut self, bbox: &BBox, id: i64, orphans: &mut Vec<(BBox, i64)>) -> bool {
+        match self {
+            Node::Leaf(entries) => match entries.iter().position(|(_, e)| *e == id) {
+                Some(pos) => {
+                    entries.remove(pos);
+                    true
+                }
+                None => false,
+            },
+            Node::Internal(children) => {
+                for idx in 0..children.len() {
+                    if !children[idx].0.contains(bbox) || !children[idx].1.delete(bbox, id, orphans)
+                    {
+                        continue;
+                    }
+                    if children[idx].1.len() < MIN_ENTRIES {
+                        let (_, child) = children.remove(idx);
+                        child.collect_entries(orphans);
+                    } else if let Some(child_bbox) = children[idx].1.bbox() {
+                        children[idx].0 = child_bbox;
+                    }
+                    return true;
+                }
+                false
+            }
+        }
+    }
+
+    fn collect_entries(self, out: &mut Vec<(BBox, i64)>) {
+        match self {
+            Node::Leaf(entries) => out.extend(entries),
+            Node::Internal(children) => {
+                for (_, child) in children {
+                    child.collect_entries(out);
+                }
+            }
+        }
+    }
+
+    /// Collects the ids of the entries whose box satisfies every constraint. Subtrees are
+    /// skipped when no box inside their bounding box could satisfy them.
+    fn search(&self, constraints: &[CoordConstraint], out: &mut Vec<i64>) {
+        match self {
+            Node::Leaf(entries) => out.extend(
+                entries
+                    .iter()
+                    .filter(|(b, _)| constraints.iter().all(|c| c.matches_entry(b)))
+                    .map(|(_, id)| *id),
+            ),
+            Node::Internal(children) => {
+                for (bbox, child) in children {
+                    if constraints.iter().all(|c| c.matches_node(bbox)) {
+                        child.search(constraints, out);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Splits an overflowing node in two with Guttman's quadratic split.
+fn quadratic_split<T>(mut entries: Entries<T>) -> (Entries<T>, Entries<T>) {
+    // Pick as seeds the two entries that would waste the most area if grouped together
+    let mut seeds = (0, 1);
+    let mut worst = f64::NEG_INFINITY;
+    for i in 0..entries.len() {
+        for j in i + 1..entries.len() {
+            let (a, b) = (&entries[i].0, &entries[j].0);
+            let waste = a.union(b).area() - a.area() - b.area();
+            if waste > worst {
+                worst = waste;
+                seeds = (i, j);
+            }
+        }
+    }
+    // Remove the later seed first so the index of the earlier one stays valid
+    let second = entries.swap_remove(seeds.1);
+    let first = entries.swap_remove(seeds.0);
+    let mut bboxes = (first.0.clone(), second.0.clone());
+    let mut groups = (vec![first], vec![second]);
+
+    while !entries.is_empty() {
+        // Make sure both groups end up with the minimum number of entries
+        if groups.0.len() + entries.len() == MIN_ENTRIES {
+            groups.0.append(&mut entries);
+            break;
+        }
+        if groups.1.len() + entries.len() == MIN_ENTRIES {
+            groups.1.append(&mut entries);
+            break;
+        }
+        // Assign next the entry with the strongest preference for one of the groups
+        let (idx, _) = entries
+            .iter()
+            .enumerate()
+            .map(|(i, (b, _))| (i, (bboxes.0.enlargement(b) - bboxes.1.enlargement(b)).abs()))
+            .max_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal))
+            .unwrap_or((0, 0.0));
+        let entry = entries.swap_remove(idx);
+        let growth = (
+            bboxes.0.enlargement(&entry.0),
+            bboxes.1.enlargement(&entry.0),
+        );
+        let into_first =
+            growth.0 < growth.1 || (growth.0 == growth.1 && groups.0.len() <= groups.1.len());
+        if into_first {
+            bboxes.0 = bboxes.0.union(&entry.0);
+            groups.0.push(entry);
+        } else {
+            bboxes.1 = bboxes.1.union(&entry.0);
+            groups.1.push(entry);
+        }
+    }
+    groups
+}
+
+/// A comparison between one coordinate column and a value, pushed down by `best_index`.
+#[derive(Debug, Clone, Copy)]
+struct CoordConstraint {
+    dimension: usize,
+    /// Whether the constraint is on the maximum coordinate of the dimension
+    is_max: bool,
+    op: ConstraintOp,
+    value: f64,
+}
+
+impl CoordConstraint {
+    fn holds(&self, coord: f64) -> bool {
+        match self.op {
+            ConstraintOp::Eq => coord == self.value,
+            ConstraintOp::Lt => coord < self.value,
+            ConstraintOp::Le => coord <= self.value,
+            ConstraintOp::Gt => coord > self.value,
+            ConstraintOp::Ge => coord >= self.value,
+            _ => true,
+        }
+    }
+
+    fn matches_entry(&self, bbox: &BBox) -> bool {
+        let coord = if self.is_max {
+            bbox.max[self.dimension]
+        } else {
+            bbox.min[self.dimension]
+        };
+        self.holds(coord)
+    }
+
+    /// Whether some box inside `bbox` may satisfy the constraint: the constrained
+    /// coordinate of every such box lies between the minimum and the maximum of `bbox`.
+    fn matches_node(&self, bbox: &BBox) -> bool {
+        let (lo, hi) = (bbox.min[self.dimension], bbox.max[self.dimension]);
+        match self.op {
+            ConstraintOp::Eq => lo <= self.value && self.value <= hi,
+            ConstraintOp::Lt | ConstraintOp::Le => self.holds(lo),
+            ConstraintOp::Gt | ConstraintOp::Ge => self.holds(hi),
+            _ => true,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Row {
+    bbox: BBox,
+    aux: Vec<DbValue>,
+}
+
+#[derive(Debug)]
+struct RTree {
+    dimensions: usize,
+    aux_columns: usize,
+    /// Whether coordinates are 32-bit integers (`rtree_i32`) rather than 32-bit floats
+    integer_coords: bool,
+    root: Node,
+    rows: BTreeMap<i64, Row>,
+}
+
+impl RTree {
+    fn insert(&mut self, id: i64, row: Row) {
+        self.insert_entry(row.bbox.clone(), id);
+        self.rows.insert(id, row);
+    }
+
+    /// Inserts an entry in the tree, growing a new root if the old one was split.
+    fn insert_entry(&mut self, bbox: BBox, id: i64) {
+        let Some((sibling_bbox, sibling)) = self.root.insert(bbox, id) else {
+            return;
+        };
+        let old_root = std::mem::replace(&mut self.root, Node::Leaf(Vec::new()));
+        if let Some(old_bbox) = old_root.bbox() {
+            self.root = Node::Internal(vec![
+                (old_bbox, Box::new(old_root)),
+                (sibling_bbox, Box::new(sibling)),
+            ]);
+        }
+    }
+
+    fn delete(&mut self, id: i64) -> Option<Row> {
+        let row = self.rows.remove(&id)?;
+        let mut orphans = Vec::new();
+        self.root.delete(&row.bbox, id, &mut orphans);
+        // Shrink the tree while the root has a single child
+        loop {
+            match &mut self.root {
+                Node::Internal(children) if children.len() == 1 => {
+                    let (_, child) = children.pop()?;
+                    self.root = *child;
+                }
+                Node::Internal(children) if children.is_empty() => {
+                    self.root = Node::Leaf(Vec::new());
+                }
+                _ => break,
+            }
+        }
+        for (bbox, orphan) in orphans {
+            self.insert_entry(bbox, orphan);
+        }
+        Some(row)
+    }
+
+    /// Converts the values of an insert or update into a row, or fails if they don't
+    /// describe a valid box.
+    fn build_row(&self, args: &[Value]) -> Result<Row, ResultCode> {
+        let mut min = Vec::with_capacity(self.dimensions);
+        let mut max = Vec::with_capacity(self.dimensions);
+        for dimension in 0..self.dimensions {
+            let lo = coord_value(args.get(1 + 2 * dimension));
+            let hi = coord_value(args.get(2 + 2 * dimension));
+            let (lo, hi) = if self.integer_coords {
+                (
+                    lo.clamp(i32::MIN as f64, i32::MAX as f64).trunc(),
+                    hi.clamp(i32::MIN as f64, i32::MAX as f64).trunc(),
+                )
+            } else {
+                (round_down_f32(lo), round_up_f32(hi))
+            };
+            // rtree constraint failed
+            if lo > hi {
+                return Err(ResultCode::InvalidArgs);
+            }
+            min.push(lo);
+            max.push(hi);
+        }
+        let first_aux = 1 + 2 * self.dimensions;
+        let aux = (first_aux..first_aux + self.aux_columns)
+            .map(|i| args.get(i).map(from_ext_value).unwrap_or(DbValue::Null))
+            .collect();
+        Ok(Row {
+            bbox: BBox { min, max },
+            aux,
+        })
+    }
+
+    fn next_id(&self) -> i64 {
+        self.rows
+            .last_key_value()
+            .map(|(id, _)| id.saturating_add(1))
+            .unwrap_or(1)
+    }
+}
+
+/// Reads a coordinate; values that are not numbers count as 0.
+fn coord_value(value: Option<&Value>) -> f64 {
+    let Some(value) = value else {
+        return 0.0;
+    };
+    match value.value_type() {
+        ValueType::Integer => value.to_integer().unwrap_or(0) as f64,
+        ValueType::Float => value.to_float().unwrap_or(0.0),
+        ValueType::Text => value
+            .to_text()
+            .and_then(|t| t.trim().parse().ok())
+            .unwrap_or(0.0),
+        _ => 0.0,
+    }
+}
+
+/// Rounds to the largest 32-bit float that is not greater than `value`.
+fn round_down_f32(value: f64) -> f64 {
+    let rounded = value as f32;
+    if (rounded as f64) > value {
+        rounded.next_down() as f64
+    } else {
+        rounded as f64
+    }
+}
+
+/// Rounds to the smallest 32-bit float that is not less than `value`.
+fn round_up_f32(value: f64) -> f64 {
+    let rounded = value as f32;
+    if (rounded as f64) < value {
+        rounded.next_up() as f64
+    } else {
+        rounded as f64
+    }
+}
+
+/// Converts an extension argument into a value without taking ownership of it.
+fn from_ext_value(value: &Value) -> DbValue {
+    match value.value_type() {
+        ValueType::Integer => value.to_integer().map(DbValue::Integer),
+        ValueType::Float => value.to_float().map(DbValue::Float),
+        ValueType::Text => value.to_text().map(DbValue::build_text),
+        ValueType::Blob => value.to_blob().map(DbValue::Blob),
+        _ => None,
+    }
+    .unwrap_or(DbValue::Null)
+}
+
+fn create_rtree(args: &[Value], integer_coords: bool) -> Result<(String, RTreeTable), ResultCode> {
+    let mut names = Vec::new();
+    let mut aux_names = Vec::new();
+    for arg in args {
+        let name = arg.to_text().ok_or(ResultCode::InvalidArgs)?.trim();
+        match name.strip_prefix('+') {
+            Some(aux) => aux_names.push(aux.trim().to_string()),
+            // Auxiliary columns come after the coordinates
+            None if aux_names.is_empty() => names.push(name.to_string()),
+            None => return Err(ResultCode::InvalidArgs),
+        }
+    }
+    // An id and a (min, max) pair for each dimension
+    if names.len() < 3 || names.len() % 2 == 0 || (names.len() - 1) / 2 > MAX_DIMENSIONS {
+        return Err(ResultCode::InvalidArgs);
+    }
+    if names.iter().chain(&aux_names).any(|n| n.is_empty()) {
+        return Err(ResultCode::InvalidArgs);
+    }
+    let schema = format!(
+        "CREATE TABLE x({})",
+        names
+            .iter()
+            .chain(&aux_names)
+            .map(|n| format!("\"{}\"", n.trim_matches('"').replace('"', "\"\"")))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    let tree = RTree {
+        dimensions: (names.len() - 1) / 2,
+        aux_columns: aux_names.len(),
+        integer_coords,
+        root: Node::Leaf(Vec::new()),
+        rows: BTreeMap::new(),
+    };
+    Ok((
+        schema,
+        RTreeTable {
+            tree: Rc::new(RefCell::new(tree)),
+        },
+    ))
+}
+
+/// An R-tree with 32-bit float coordinates
+#[derive(Debug, VTabModuleDerive, Default)]
+struct RTreeVTabModule;
+
+impl VTabModule for RTreeVTabModule {
+    type Table = RTreeTable;
+    const NAME: &'static str = "rtree";
+    const VTAB_KIND: VTabKind = VTabKind::VirtualTable;
+
+    fn create(args: &[Value]) -> Result<(String, Self::Table), ResultCode> {
+        create_rtree(args, false)
+    }
+}
+
+/// An R-tree with 32-bit integer coordinates
+#[derive(Debug, VTabModuleDerive, Default)]
+struct RTreeI32VTabModule;
+
+impl VTabModule for RTreeI32VTabModule {
+    type Table = RTreeTable;
+    const NAME: &'static str = "rtree_i32";
+    const VTAB_KIND: VTabKind = VTabKind::VirtualTable;
+
+    fn create(args: &[Value]) -> Result<(String, Self::Table), ResultCode> {
+        create_rtree(args, true)
+    }
+}
+
+struct RTreeTable {
+    tree: Rc<RefCell<RTree>>,
+}
+
+impl VTable for RTreeTable {
+    type Cursor = RTreeCursor;
+    type Error = ResultCode;
+
+    fn open(&self, _conn: Option<Arc<Connection>>) -> Result<Self::Cursor, Self::Error> {
+        Ok(RTreeCursor {
+            tree: Rc::clone(&self.tree),
+            ids: Vec::new(),
+            current: 0,
+        })
+    }
+
+    fn best_index(constraints: &[ConstraintInfo], _order_by: &[OrderByInfo]) -> IndexInfo {
+        // idx_num 1: look up a single id, passed as the only argument.
+        // idx_num 2: search the tree. `idx_str` lists the operator and the column of each
+        // argument, e.g. "ge:2,le:1". `best_index` can't tell coordinates from auxiliary
+        // columns, so these constraints are also checked again by the core.
+        if let Some(id_idx) = constraints
+            .iter()
+            .position(|c| c.usable && c.column_index == 0 && c.op == ConstraintOp::Eq)
+        {
+            return IndexInfo {
+                idx_num: 1,
+                idx_str: Some("id".to_string()),
+                estimated_cost: 10.0,
+                estimated_rows: 1,
+                constraint_usages: constraints
+                    .iter()
+                    .enumerate()
+                    .map(|(i, _)| ConstraintUsage {
+                        argv_index: (i == id_idx).then_some(1),
+                        omit: i == id_idx,
+                    })
+                    .collect(),
+                ..Default::default()
+            };
+        }
+
+        let mut pushed = Vec::new();
+        let constraint_usages = constraints
+            .iter()
+            .map(|c| {
+                let op = match c.op {
+                    ConstraintOp::Eq => "eq",
+                    ConstraintOp::Lt => "lt",
+                    ConstraintOp::Le => "le",
+                    ConstraintOp::Gt => "gt",
+                    ConstraintOp::Ge => "ge",
+                    _ => "",
+                };
+                if !c.usable || c.column_index == 0 || op.is_empty() {
+                    return ConstraintUsage {
+                        argv_index: None,
+                        omit: false,
+                    };
+                }
+                pushed.push(format!("{op}:{}", c.column_index));
+                ConstraintUsage {
+                    argv_index: Some(pushed.len() as u32),
+                    omit: false,
+                }
+            })
+            .collect();
+        if pushed.is_empty() {
+            return IndexInfo {
+                idx_num: 0,
+                idx_str: None,
+                estimated_cost: 1_000_000.0,
+                constraint_usages,
+                ..Default::default()
+            };
+        }
+        IndexInfo {
+            idx_num: 2,
+            idx_str: Some(pushed.join(",")),
+            estimated_cost: 1_000.0 / pushed.len() as f64,
+            estimated_rows: 100,
+            constraint_usages,
+            ..Default::default()
+        }
+    }
+
+    fn insert(&mut self, args: &[Value]) -> Result<i64, Self::Error> {
+        let mut tree = self.tree.borrow_mut();
+        let row = tree.build_row(args)?;
+        let id = match args.first() {
+            Some(v) if v.value_type() == ValueType::Integer => v.to_integer().unwrap_or(0),
+            Some(v) if v.value_type() == ValueType::Null => tree.next_id(),
+            None => tree.next_id(),
+            // The id must be an integer
+            Some(_) => return Err(ResultCode::InvalidArgs),
+        };
+        // UNIQUE constraint failed
+        if tree.rows.contains_key(&id) {
+            return Err(ResultCode::AlreadyExists);
+        }
+        tree.insert(id, row);
+        Ok(id)
+    }
+
+    fn update(&mut self, rowid: i64, args: &[Value]) -> Result<(), Self::Error> {
+        let mut tree = self.tree.borrow_mut();
+        let row = tree.build_row(args)?;
+        let id = match args.first() {
+            Some(v) if v.value_type() == ValueType::Integer => v.to_integer().unwrap_or(rowid),
+            _ => rowid,
+        };
+        if id != rowid && tree.rows.contains_key(&id) {
+            return Err(ResultCode::AlreadyExists);
+        }
+        tree.delete(rowid);
+        tree.insert(id, row);
+        Ok(())
+    }
+
+    fn delete(&mut self, rowid: i64) -> Result<(), Self::Error> {
+        self.tree.borrow_mut().delete(rowid);
+        Ok(())
+    }
+}
+
+struct RTreeCursor {
+    tree: Rc<RefCell<RTree>>,
+    ids: Vec<i64>,
+    current: usize,
+}
+
+impl RTreeCursor {
+    /// Parses the constraints described by `idx_str`, dropping those on auxiliary columns
+    /// or with values that are not numbers. Returns `None` if a constraint can't match.
+    fn coord_constraints(&self, idx_str: &str, args: &[Value]) -> Option<Vec<CoordConstraint>> {
+        let dimensions = self.tree.borrow().dimensions;
+        let mut constraints = Vec::new();
+        for (spec, arg) in idx_str.split(',').zip(args) {
+            let Some((op, column)) = spec.split_once(':') else {
+                continue;
+            };
+            let Some(coord) = column
+                .parse::<usize>()
+                .ok()
+                .and_then(|c| c.checked_sub(1))
+                .filter(|c| *c < 2 * dimensions)
+            else {
+                continue;
+            };
+            let value = match arg.value_type() {
+                // Comparisons with NULL are never true
+                ValueType::Null => return None,
+                ValueType::Integer => arg.to_integer().map(|i| i as f64),
+                ValueType::Float => arg.to_float(),
+                ValueType::Text => arg.to_text().and_then(|t| t.trim().parse().ok()),
+                _ => None,
+            };
+            let Some(value) = value else {
+                continue;
+            };
+            let op = match op {
+                "eq" => ConstraintOp::Eq,
+                "lt" => ConstraintOp::Lt,
+                "le" => ConstraintOp::Le,
+                "gt" => ConstraintOp::Gt,
+                "ge" => ConstraintOp::Ge,
+                _ => continue,
+            };
+            constraints.push(CoordConstraint {
+                dimension: coord / 2,
+                is_max: coord % 2 == 1,
+                op,
+                value,
+            });
+        }
+        Some(constraints)
+    }
+}
+
+impl VTabCursor for RTreeCursor {
+    type Error = ResultCode;
+
+    fn filter(&mut self, args: &[Value], idx_info: Option<(&str, i32)>) -> ResultCode {
+        self.ids.clear();
+        self.current = 0;
+        match idx_info {
+            Some((_, 1)) => {
+                let id = match args.first() {
+                    Some(v) if v.value_type() == ValueType::Integer => v.to_integer(),
+                    Some(v) if v.value_type() == ValueType::Float => {
+                        v.to_float().filter(|f| f.fract() == 0.0).map(|f| f as i64)
+                    }
+                    Some(v) if v.value_type() == ValueType::Text => {
+                        v.to_text().and_then(|t| t.trim().parse().ok())
+                    }
+                    _ => None,
+                };
+                if let Some(id) = id.filter(|id| self.tree.borrow().rows.contains_key(id)) {
+                    self.ids.push(id);
+                }
+            }
+            Some((idx_str, 2)) => {
+                let Some(constraints) = self.coord_constraints(idx_str, args) else {
+                    return ResultCode::EOF;
+                };
+                self.tree.borrow().root.search(&constraints, &mut self.ids);
+                self.ids.sort_unstable();
+            }
+            _ => {
+                self.ids = self.tree.borrow().rows.keys().copied().collect();
+            }
+        }
+        if self.ids.is_empty() {
+            return ResultCode::EOF;
+        }
+        ResultCode::OK
+    }
+
+    fn rowid(&self) -> i64 {
+        self.ids.get(self.current).copied().unwrap_or(-1)
+    }
+
+    fn column(&self, idx: u32) -> Result<Value, Self::Error> {
+        let Some(id) = self.ids.get(self.current) else {
+            return Err(ResultCode::OutOfRange);
+        };
+        let tree = self.tree.borrow();
+        let Some(row) = tree.rows.get(id) else {
+            return Err(ResultCode::NotFound);
+        };
+        let idx = idx as usize;
+        if idx == 0 {
+            return Ok(Value::from_integer(*id));
+        }
+        let coord = idx - 1;
+        if coord < 2 * tree.dimensions {
+            let value = if coord % 2 == 0 {
+                row.bbox.min[coord / 2]
+            } else {
+                row.bbox.max[coord / 2]
+            };
+            return Ok(if tree.integer_coords {
+                Value::from_integer(value as i64)
+            } else {
+                Value::from_float(value)
+            });
+        }
+        Ok(row
+            .aux
+            .get(coord - 2 * tree.dimensions)
+            .map(DbValue::to_ffi)
+            .unwrap_or_else(Value::null))
+    }
+
+    fn eof(&self) -> bool {
+        self.current >= self.ids.len()
+    }
+
+    fn next(&mut self) -> ResultCode {
+        self.current += 1;
+        if self.eof() {
+            return ResultCode::EOF;
+        }
+        ResultCode::OK
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bbox(min_x: f64, max_x: f64, min_y: f64, max_y: f64) -> BBox {
+        BBox {
+            min: vec![min_x, min_y],
+            max: vec![max_x, max_y],
+        }
+    }
+
+    fn tree_with_grid(n: i64) -> RTree {
+        let mut tree = RTree {
+            dimensions: 2,
+            aux_columns: 0,
+            integer_coords: false,
+            root: Node::Leaf(Vec::new()),
+            rows: BTreeMap::new(),
+        };
+        for x in 0..n {
+            for y in 0..n {
+                let (x0, y0) = (x as f64, y as f64);
+                tree.insert(
+                    x * n + y,
+                    Row {
+                        bbox: bbox(x0, x0 + 0.5, y0, y0 + 0.5),
+                        aux: Vec::new(),
+                    },
+                );
+            }
+        }
+        tree
+    }
+
+    fn search(tree: &RTree, constraints: &[CoordConstraint]) -> Vec<i64> {
+        let mut ids = Vec::new();
+        tree.root.search(constraints, &mut ids);
+        ids.sort_unstable();
+        ids
+    }
+
+    fn constraint(dimension: usize, is_max: bool, op: ConstraintOp, value: f64) -> CoordConstraint {
+        CoordConstraint {
+            dimension,
+            is_max,
+            op,
+            value,
+        }
+    }
+
+    /// Checks that every node's box covers its children and that nodes are filled properly.
+    fn check_node(node: &Node, bbox: Option<&BBox>, is_root: bool) -> usize {
+        if !is_root {
+            assert!(node.len() >= MIN_ENTRIES, "underfull node");
+        }
+        assert!(node.len() <= MAX_ENTRIES, "overfull node");
+        if let (Some(bbox), Some(actual)) = (bbox, node.bbox()) {
+            assert_eq!(bbox, &actual);
+        }
+        match node {
+            Node::Leaf(entries) => entries.len(),
+            Node::Internal(children) => children
+                .iter()
+                .map(|(b, child)| check_node(child, Some(b), false))
+                .sum(),
+        }
+    }
+
+    #[test]
+    fn test_insert_keeps_tree_balanced() {
+        let tree = tree_with_grid(20);
+        assert_eq!(check_node(&tree.root, None, true), 400);
+        assert!(matches!(tree.root, Node::Internal(_)));
+    }
+
+    #[test]
+    fn test_search() {
+        let tree = tree_with_grid(20);
+        // Boxes overlapping the region x in [2.2, 4], y in [0, 1]
+        let ids = search(
+            &tree,
+            &[
+                constraint(0, true, ConstraintOp::Ge, 2.2),
+                constraint(0, false, ConstraintOp::Le, 4.0),
+                constraint(1, false, ConstraintOp::Le, 1.0),
+            ],
+        );
+        assert_eq!(ids, vec![40, 41, 60, 61, 80, 81]);
+        let ids = search(&tree, &[constraint(1, false, ConstraintOp::Eq, 19.0)]);
+        assert_eq!(ids.len(), 20);
+        assert!(search(&tree, &[constraint(0, true, ConstraintOp::Gt, 100.0)]).is_empty());
+    }
+
+    #[test]
+    fn test_delete() {
+        let mut tree = tree_with_grid(20);
+        for id in (0..400).filter(|id| id % 3 != 0) {
+            assert!(tree.delete(id).is_some());
+        }
+        assert!(tree.delete(1).is_none());
+        assert_eq!(check_node(&tree.root, None, true), 134);
+        let ids = search(&tree, &[constraint(0, false, ConstraintOp::Lt, 1.0)]);
+        assert_eq!(ids, vec![0, 3, 6, 9, 12, 15, 18]);
+        for id in (0..400).filter(|id| id % 3 == 0) {
+            assert!(tree.delete(id).is_some());
+        }
+        assert_eq!(check_node(&tree.root, None, true), 0);
+    }
+
+    #[test]
+    fn test_f32_rounding() {
+        assert_eq!(round_down_f32(1.0), 1.0);
+        assert!(round_down_f32(0.1) <= 0.1);
+        assert!(round_up_f32(0.1) >= 0.1);
+        assert_eq!(round_up_f32(0.1), 0.1f32 as f64);
+        assert!(round_down_f32(0.1) < round_up_f32(0.1));
+    }
+}
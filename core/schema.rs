@@ -1,7 +1,9 @@
 use crate::result::LimboResult;
 use crate::storage::btree::BTreeCursor;
 use crate::translate::collate::CollationSeq;
-use crate::translate::plan::SelectPlan;
+use crate::translate::expr::{walk_expr, WalkControl};
+use crate::translate::plan::{RecursiveCte, SelectPlan};
+use crate::translate::schema::SQLITE_STAT1_TABLE;
 use crate::util::{module_args_from_sql, module_name_from_sql, IOExt, UnparsedFromSqlIndex};
 use crate::{util::normalize_ident, Result};
 use crate::{LimboError, MvCursor, Pager, RefValue, SymbolTable, VirtualTable};
@@ -13,7 +15,9 @@ use std::collections::{BTreeSet, HashMap};
 use std::rc::Rc;
 use std::sync::Arc;
 use tracing::trace;
-use turso_sqlite3_parser::ast::{self, ColumnDefinition, Expr, Literal, SortOrder, TableOptions};
+use turso_sqlite3_parser::ast::{
+    self, ColumnDefinition, Expr, Literal, ResolveType, SortOrder, TableOptions,
+};
 use turso_sqlite3_parser::{
     ast::{Cmd, CreateTableBody, QualifiedName, ResultColumn, Stmt},
     lexer::sql::Parser,
@@ -28,8 +32,38 @@ pub struct Schema {
     /// table_name to list of indexes for the table
     pub indexes: HashMap<String, Vec<Arc<Index>>>,
     pub has_indexes: std::collections::HashSet<String>,
+    /// The root pages of the indexes that aren't loaded because indexes are disabled, so that
+    /// `PRAGMA integrity_check` still finds their pages.
+    pub unloaded_index_root_pages: Vec<usize>,
     pub indexes_enabled: bool,
     pub schema_version: u32,
+    /// table_name to list of triggers on the table
+    pub triggers: HashMap<String, Vec<Arc<Trigger>>>,
+    pub views: HashMap<String, Arc<View>>,
+    /// The temp database and the databases attached to the connection. Only set on the schema
+    /// statements are translated against, see [crate::Connection::translation_schema].
+    pub attached: Vec<AttachedSchema>,
+    /// table_name to the statistics `ANALYZE` stored for the table in `sqlite_stat1`
+    pub stats: HashMap<String, TableStats>,
+}
+
+/// The statistics of a table and its indexes, as stored in `sqlite_stat1` by `ANALYZE`.
+#[derive(Debug, Clone, Default)]
+pub struct TableStats {
+    /// The number of rows in the table.
+    pub row_count: u64,
+    /// index_name to the average number of rows that have the same values in the first column
+    /// of the index, in its first two columns, and so on.
+    pub rows_per_key: HashMap<String, Vec<u64>>,
+}
+
+/// The schema of a database attached with `ATTACH DATABASE`.
+#[derive(Debug, Clone)]
+pub struct AttachedSchema {
+    pub name: String,
+    /// The index of the database in the programs of the connection.
+    pub index: usize,
+    pub schema: Arc<Schema>,
 }
 
 impl Schema {
@@ -49,11 +83,84 @@ impl Schema {
             tables,
             indexes,
             has_indexes,
+            unloaded_index_root_pages: Vec::new(),
             indexes_enabled,
             schema_version: 0,
+            triggers: HashMap::new(),
+            views: HashMap::new(),
+            attached: Vec::new(),
+            stats: HashMap::new(),
         }
     }
 
+    /// Returns the index of the database named `name`: 0 for `main`, or that of the temp or an
+    /// attached database.
+    pub fn database_index(&self, name: &str) -> Result<usize> {
+        let name = normalize_ident(name);
+        if name == "main" {
+            return Ok(0);
+        }
+        self.attached
+            .iter()
+            .find(|db| db.name == name)
+            .map(|db| db.index)
+            .ok_or_else(|| LimboError::ParseError(format!("unknown database {name}")))
+    }
+
+    /// Returns the schema of the database with index `db`.
+    pub fn database(&self, db: usize) -> &Schema {
+        if db == 0 {
+            return self;
+        }
+        &self
+            .attached
+            .iter()
+            .find(|attached| attached.index == db)
+            .expect("database should be attached")
+            .schema
+    }
+
+    /// Returns the name of the database with index `db`: `main`, `temp` or the name it was
+    /// attached as.
+    pub fn database_name(&self, db: usize) -> &str {
+        if db == 0 {
+            return "main";
+        }
+        &self
+            .attached
+            .iter()
+            .find(|attached| attached.index == db)
+            .expect("database should be attached")
+            .name
+    }
+
+    /// Returns the index of the database `name` refers to: the one it's qualified with, or else
+    /// the first of the temp, main and attached databases that has a table or view with that
+    /// name.
+    pub fn object_database(&self, name: &QualifiedName) -> Result<usize> {
+        if let Some(db_name) = &name.db_name {
+            return self.database_index(&db_name.0);
+        }
+        let has_object = |schema: &Schema| {
+            schema.get_table(&name.name.0).is_some() || schema.get_view(&name.name.0).is_some()
+        };
+        if let Some(temp) = self
+            .attached
+            .iter()
+            .find(|db| db.name == "temp" && has_object(&db.schema))
+        {
+            return Ok(temp.index);
+        }
+        if has_object(self) {
+            return Ok(0);
+        }
+        Ok(self
+            .attached
+            .iter()
+            .find(|db| has_object(&db.schema))
+            .map_or(0, |db| db.index))
+    }
+
     pub fn is_unique_idx_name(&self, name: &str) -> bool {
         !self
             .indexes
@@ -97,10 +204,15 @@ impl Schema {
 
     pub fn add_index(&mut self, index: Arc<Index>) {
         let table_name = normalize_ident(&index.table_name);
-        self.indexes
-            .entry(table_name)
-            .or_default()
-            .push(index.clone())
+        let indexes = self.indexes.entry(table_name).or_default();
+        // Reparsing the schema, e.g. after an ALTER TABLE, replaces the indexes it already knows.
+        match indexes
+            .iter_mut()
+            .find(|existing| existing.name == index.name)
+        {
+            Some(existing) => *existing = index,
+            None => indexes.push(index),
+        }
     }
 
     pub fn get_indices(&self, table_name: &str) -> &[Arc<Index>] {
@@ -131,18 +243,95 @@ impl Schema {
             .retain_mut(|other_idx| other_idx.name != idx.name);
     }
 
+    /// Updates the table or index whose root page was moved from `from` to `to`, which happens
+    /// to the last root page when a table or index is dropped in an auto-vacuum database.
+    pub fn root_page_moved(&mut self, from: usize, to: usize) {
+        for table in self.tables.values_mut() {
+            if let Table::BTree(btree) = table.as_ref() {
+                if btree.root_page == from {
+                    let mut btree = btree.as_ref().clone();
+                    btree.root_page = to;
+                    *table = Arc::new(Table::BTree(Rc::new(btree)));
+                }
+            }
+        }
+        for index in self.indexes.values_mut().flatten() {
+            if index.root_page == from {
+                let mut moved = index.as_ref().clone();
+                moved.root_page = to;
+                *index = Arc::new(moved);
+            }
+        }
+        for root_page in self.unloaded_index_root_pages.iter_mut() {
+            if *root_page == from {
+                *root_page = to;
+            }
+        }
+    }
+
     pub fn table_has_indexes(&self, table_name: &str) -> bool {
         self.has_indexes.contains(table_name)
     }
 
-    pub fn table_set_has_index(&mut self, table_name: &str) {
+    /// Records that the table has an index, rooted at `root_page`, which isn't loaded because
+    /// indexes are disabled.
+    pub fn table_set_has_index(&mut self, table_name: &str, root_page: usize) {
         self.has_indexes.insert(table_name.to_string());
+        self.unloaded_index_root_pages.push(root_page);
     }
 
     pub fn indexes_enabled(&self) -> bool {
         self.indexes_enabled
     }
 
+    pub fn add_trigger(&mut self, trigger: Arc<Trigger>) {
+        let table_name = normalize_ident(&trigger.table_name);
+        self.triggers.entry(table_name).or_default().push(trigger);
+    }
+
+    pub fn get_trigger(&self, name: &str) -> Option<&Arc<Trigger>> {
+        let name = normalize_ident(name);
+        self.triggers
+            .values()
+            .flatten()
+            .find(|trigger| trigger.name == name)
+    }
+
+    /// Returns the triggers on `table_name`, in the order they were created.
+    pub fn get_triggers(&self, table_name: &str) -> &[Arc<Trigger>] {
+        let name = normalize_ident(table_name);
+        self.triggers
+            .get(&name)
+            .map_or_else(|| &[] as &[Arc<Trigger>], |v| v.as_slice())
+    }
+
+    pub fn remove_trigger(&mut self, name: &str) {
+        let name = normalize_ident(name);
+        for triggers in self.triggers.values_mut() {
+            triggers.retain(|trigger| trigger.name != name);
+        }
+    }
+
+    pub fn remove_triggers_for_table(&mut self, table_name: &str) {
+        let name = normalize_ident(table_name);
+        self.triggers.remove(&name);
+    }
+
+    pub fn add_view(&mut self, view: Arc<View>) {
+        let name = normalize_ident(&view.name);
+        self.views.insert(name, view);
+    }
+
+    pub fn get_view(&self, name: &str) -> Option<&Arc<View>> {
+        let name = normalize_ident(name);
+        self.views.get(&name)
+    }
+
+    pub fn remove_view(&mut self, name: &str) {
+        let name = normalize_ident(name);
+        self.views.remove(&name);
+    }
+
     /// Update [Schema] by scanning the first root page (sqlite_schema)
     pub fn make_from_btree(
         &mut self,
@@ -266,6 +455,20 @@ impl Schema {
                         }
                     }
                 }
+                "trigger" => {
+                    let sql_value = record_cursor.get_value(&row, 4)?;
+                    let RefValue::Text(sql_text) = sql_value else {
+                        return Err(LimboError::ConversionError("Expected text value".into()));
+                    };
+                    self.add_trigger(Arc::new(Trigger::from_sql(sql_text.as_str())?));
+                }
+                "view" => {
+                    let sql_value = record_cursor.get_value(&row, 4)?;
+                    let RefValue::Text(sql_text) = sql_value else {
+                        return Err(LimboError::ConversionError("Expected text value".into()));
+                    };
+                    self.add_view(Arc::new(View::from_sql(sql_text.as_str())?));
+                }
                 _ => {}
             };
             drop(record_cursor);
@@ -274,11 +477,15 @@ impl Schema {
             pager.io.block(|| cursor.next())?;
         }
 
+        self.load_stats(&pager)?;
         pager.end_read_tx()?;
 
         for unparsed_sql_from_index in from_sql_indexes {
             if !self.indexes_enabled() {
-                self.table_set_has_index(&unparsed_sql_from_index.table_name);
+                self.table_set_has_index(
+                    &unparsed_sql_from_index.table_name,
+                    unparsed_sql_from_index.root_page,
+                );
             } else {
                 let table = self
                     .get_btree_table(&unparsed_sql_from_index.table_name)
@@ -294,7 +501,9 @@ impl Schema {
 
         for automatic_index in automatic_indices {
             if !self.indexes_enabled() {
-                self.table_set_has_index(&automatic_index.0);
+                for (_, root_page) in automatic_index.1 {
+                    self.table_set_has_index(&automatic_index.0, root_page);
+                }
             } else {
                 let table = self.get_btree_table(&automatic_index.0).unwrap();
                 let ret_index = Index::automatic_from_primary_key_and_unique(
@@ -309,6 +518,59 @@ impl Schema {
 
         Ok(())
     }
+
+    /// Loads the statistics stored in `sqlite_stat1`, if the table exists, replacing the current
+    /// ones. The pager must hold a read transaction.
+    pub fn load_stats(&mut self, pager: &Rc<Pager>) -> Result<()> {
+        self.stats.clear();
+        let Some(table) = self.get_btree_table(SQLITE_STAT1_TABLE) else {
+            return Ok(());
+        };
+        let mut cursor = BTreeCursor::new_table(None, pager.clone(), table.root_page, 3);
+        let text = |value: RefValue| match value {
+            RefValue::Text(text) => Some(text.as_str().to_string()),
+            _ => None,
+        };
+        let mut rows = Vec::new();
+        pager.io.block(|| cursor.rewind())?;
+        loop {
+            let Some(row) = pager.io.block(|| cursor.record())? else {
+                break;
+            };
+            let mut record_cursor = cursor.record_cursor.borrow_mut();
+            rows.push((
+                text(record_cursor.get_value(&row, 0)?),
+                text(record_cursor.get_value(&row, 1)?),
+                text(record_cursor.get_value(&row, 2)?),
+            ));
+            drop(record_cursor);
+            drop(row);
+            pager.io.block(|| cursor.next())?;
+        }
+
+        // The stat column holds the row count of the table, followed for an index by the average
+        // number of rows per key of each prefix of its columns. SQLite may append options like
+        // `unordered` to it, which are ignored.
+        for (tbl, idx, stat) in rows {
+            let (Some(tbl), Some(stat)) = (tbl, stat) else {
+                continue;
+            };
+            let mut numbers = stat
+                .split_whitespace()
+                .map_while(|number| number.parse::<u64>().ok());
+            let Some(row_count) = numbers.next() else {
+                continue;
+            };
+            let stats = self.stats.entry(normalize_ident(&tbl)).or_default();
+            stats.row_count = row_count;
+            if let Some(idx) = idx {
+                stats
+                    .rows_per_key
+                    .insert(normalize_ident(&idx), numbers.collect());
+            }
+        }
+        Ok(())
+    }
 }
 
 #[derive(Clone, Debug)]
@@ -387,7 +649,48 @@ pub struct BTreeTable {
     pub columns: Vec<Column>,
     pub has_rowid: bool,
     pub is_strict: bool,
-    pub unique_sets: Option<Vec<Vec<(String, SortOrder)>>>,
+    pub unique_sets: Option<Vec<UniqueSet>>,
+    /// Both table and column CHECK constraints, in the order they were defined.
+    pub check_constraints: Vec<CheckConstraint>,
+    /// The ON CONFLICT clause of the PRIMARY KEY constraint, if any.
+    pub primary_key_conflict_clause: Option<ResolveType>,
+    /// Whether the PRIMARY KEY was declared AUTOINCREMENT, in which case the largest rowid
+    /// ever used is tracked in sqlite_sequence.
+    pub has_autoincrement: bool,
+}
+
+/// The columns of a table UNIQUE constraint.
+#[derive(Clone, Debug)]
+pub struct UniqueSet {
+    pub columns: Vec<(String, SortOrder)>,
+    pub conflict_clause: Option<ResolveType>,
+}
+
+/// A CHECK constraint of a table.
+#[derive(Clone, Debug)]
+pub struct CheckConstraint {
+    /// The name given to the constraint with `CONSTRAINT name`, if any.
+    pub name: Option<String>,
+    pub expr: Expr,
+    /// The column a column constraint is defined on, `None` for a table constraint.
+    pub column: Option<String>,
+}
+
+impl CheckConstraint {
+    pub fn to_sql(&self) -> String {
+        match &self.name {
+            Some(name) => format!("CONSTRAINT {name} CHECK ({})", self.expr),
+            None => format!("CHECK ({})", self.expr),
+        }
+    }
+
+    /// The name reported when the constraint fails: its own name, or else its expression.
+    pub fn display_name(&self) -> String {
+        match &self.name {
+            Some(name) => name.clone(),
+            None => self.expr.to_string(),
+        }
+    }
 }
 
 impl BTreeTable {
@@ -442,18 +745,74 @@ impl BTreeTable {
                 sql.push_str(&column.ty_str);
             }
 
-            if column.unique {
-                sql.push_str(" UNIQUE");
+            if let [(pk_column, order)] = self.primary_key_columns.as_slice() {
+                if column.name.as_ref() == Some(pk_column) {
+                    sql.push_str(" PRIMARY KEY");
+                    if *order == SortOrder::Desc {
+                        sql.push_str(" DESC");
+                    }
+                    push_conflict_clause(&mut sql, self.primary_key_conflict_clause);
+                    if self.has_autoincrement {
+                        sql.push_str(" AUTOINCREMENT");
+                    }
+                }
+            }
+
+            if column.notnull {
+                sql.push_str(" NOT NULL");
+                push_conflict_clause(&mut sql, column.notnull_conflict_clause);
             }
 
-            if column.primary_key {
-                sql.push_str(" PRIMARY KEY");
+            if column.unique {
+                sql.push_str(" UNIQUE");
+                push_conflict_clause(&mut sql, column.unique_conflict_clause);
             }
 
             if let Some(default) = &column.default {
                 sql.push_str(" DEFAULT ");
                 sql.push_str(&default.to_string());
             }
+
+            if let Some(collation) = &column.collation {
+                sql.push_str(" COLLATE ");
+                sql.push_str(&collation.to_string().to_uppercase());
+            }
+
+            if let Some(generated) = &column.generated {
+                sql.push_str(" AS (");
+                sql.push_str(&generated.expr.to_string());
+                sql.push(')');
+                if generated.stored {
+                    sql.push_str(" STORED");
+                }
+            }
+
+            for check in self
+                .check_constraints
+                .iter()
+                .filter(|check| check.column.as_ref() == column.name.as_ref())
+            {
+                sql.push(' ');
+                sql.push_str(&check.to_sql());
+            }
+        }
+        if self.primary_key_columns.len() > 1 {
+            sql.push_str(", PRIMARY KEY ");
+            push_column_list(&mut sql, &self.primary_key_columns);
+            push_conflict_clause(&mut sql, self.primary_key_conflict_clause);
+        }
+        for unique_set in self.unique_sets.iter().flatten() {
+            sql.push_str(", UNIQUE ");
+            push_column_list(&mut sql, &unique_set.columns);
+            push_conflict_clause(&mut sql, unique_set.conflict_clause);
+        }
+        for check in self
+            .check_constraints
+            .iter()
+            .filter(|check| check.column.is_none())
+        {
+            sql.push_str(", ");
+            sql.push_str(&check.to_sql());
         }
         sql.push(')');
         sql
@@ -462,6 +821,163 @@ impl BTreeTable {
     pub fn column_collations(&self) -> Vec<Option<CollationSeq>> {
         self.columns.iter().map(|column| column.collation).collect()
     }
+
+    /// Returns the position of the value of the column at `column_idx` in the table's records,
+    /// which leave out the VIRTUAL generated columns.
+    pub fn column_record_pos(&self, column_idx: usize) -> usize {
+        if !self.has_rowid {
+            return self
+                .record_columns()
+                .iter()
+                .position(|idx| *idx == column_idx)
+                .expect("VIRTUAL generated columns are not stored");
+        }
+        self.columns[..column_idx]
+            .iter()
+            .filter(|column| !column.is_virtual_generated())
+            .count()
+    }
+
+    /// Returns the positions of the columns stored in the table's records, in the order they are
+    /// stored. The records of a WITHOUT ROWID table start with its PRIMARY KEY columns, which
+    /// are the key of its btree.
+    pub fn record_columns(&self) -> Vec<usize> {
+        let primary_key = if self.has_rowid {
+            vec![]
+        } else {
+            self.primary_key_columns
+                .iter()
+                .filter_map(|(name, _)| self.get_column(name).map(|(idx, _)| idx))
+                .collect()
+        };
+        let rest = (0..self.columns.len())
+            .filter(|idx| !primary_key.contains(idx) && !self.columns[*idx].is_virtual_generated());
+        primary_key.iter().copied().chain(rest).collect()
+    }
+
+    /// Returns an index describing the btree of a WITHOUT ROWID table, which is keyed by the
+    /// PRIMARY KEY columns like an index, so that the table can be written through an index
+    /// cursor. The index is not unique: the writer checks the PRIMARY KEY constraint itself.
+    pub fn without_rowid_index(&self) -> Index {
+        assert!(!self.has_rowid, "table {} has a rowid", self.name);
+        let columns = self
+            .record_columns()
+            .into_iter()
+            .map(|pos_in_table| {
+                let column = &self.columns[pos_in_table];
+                let name = column.name.clone().unwrap_or_default();
+                let order = self
+                    .primary_key_columns
+                    .iter()
+                    .find(|(pk_name, _)| normalize_ident(pk_name) == normalize_ident(&name))
+                    .map_or(SortOrder::Asc, |(_, order)| *order);
+                IndexColumn {
+                    name,
+                    order,
+                    pos_in_table,
+                    collation: column.collation,
+                    default: column.default.clone(),
+                    expr: None,
+                }
+            })
+            .collect();
+        Index {
+            name: self.name.clone(),
+            table_name: self.name.clone(),
+            root_page: self.root_page,
+            columns,
+            unique: false,
+            ephemeral: false,
+            conflict_clause: self.primary_key_conflict_clause,
+            has_rowid: false,
+            where_clause: None,
+        }
+    }
+
+    /// Returns the positions of the generated columns, ordered so that a generated column
+    /// comes after the generated columns its expression refers to.
+    pub fn generated_columns_in_order(&self) -> Result<Vec<usize>> {
+        #[derive(Clone, Copy, PartialEq)]
+        enum Visit {
+            NotVisited,
+            InProgress,
+            Done,
+        }
+
+        fn visit(
+            table: &BTreeTable,
+            column_idx: usize,
+            visits: &mut [Visit],
+            order: &mut Vec<usize>,
+        ) -> Result<()> {
+            let column = &table.columns[column_idx];
+            let Some(generated) = &column.generated else {
+                return Ok(());
+            };
+            visits[column_idx] = Visit::InProgress;
+            let mut dependencies = Vec::new();
+            walk_expr(&generated.expr, &mut |expr: &Expr| -> Result<WalkControl> {
+                if let Expr::Id(id) = expr {
+                    if let Some((idx, _)) = table.get_column(&id.0) {
+                        dependencies.push(idx);
+                    }
+                }
+                Ok(WalkControl::Continue)
+            })?;
+            for dependency in dependencies {
+                match visits[dependency] {
+                    Visit::InProgress => {
+                        return Err(LimboError::ParseError(format!(
+                            "generated column loop on \"{}\"",
+                            column.name.as_deref().unwrap_or_default()
+                        )));
+                    }
+                    Visit::NotVisited => visit(table, dependency, visits, order)?,
+                    Visit::Done => {}
+                }
+            }
+            visits[column_idx] = Visit::Done;
+            order.push(column_idx);
+            Ok(())
+        }
+
+        let mut visits = vec![Visit::NotVisited; self.columns.len()];
+        let mut order = Vec::new();
+        for column_idx in 0..self.columns.len() {
+            if visits[column_idx] == Visit::NotVisited {
+                visit(self, column_idx, &mut visits, &mut order)?;
+            }
+        }
+        Ok(order)
+    }
+}
+
+fn push_column_list(sql: &mut String, columns: &[(String, SortOrder)]) {
+    sql.push('(');
+    for (i, (name, order)) in columns.iter().enumerate() {
+        if i > 0 {
+            sql.push_str(", ");
+        }
+        sql.push_str(name);
+        if *order == SortOrder::Desc {
+            sql.push_str(" DESC");
+        }
+    }
+    sql.push(')');
+}
+
+fn push_conflict_clause(sql: &mut String, conflict_clause: Option<ResolveType>) {
+    let Some(resolve_type) = conflict_clause else {
+        return;
+    };
+    sql.push_str(" ON CONFLICT ");
+    sql.push_str(match resolve_type {
+        ResolveType::Rollback => "ROLLBACK",
+        ResolveType::Abort => "ABORT",
+        ResolveType::Fail => "FAIL",
+        ResolveType::Ignore => "IGNORE",
+        ResolveType::Replace => "REPLACE",
+    });
 }
 
 #[derive(Debug, Default, Clone, Copy)]
@@ -493,6 +1009,9 @@ pub struct FromClauseSubquery {
     /// The start register for the result columns of the derived table;
     /// must be set before data is read from it.
     pub result_columns_start_reg: Option<usize>,
+    /// For a recursive CTE, the recursive part of its definition.
+    /// [FromClauseSubquery::plan] is then the initial (non-recursive) part.
+    pub recursive: Option<Box<RecursiveCte>>,
 }
 
 #[derive(Debug, Eq)]
@@ -528,10 +1047,13 @@ fn create_table(
     trace!("Creating table {}", table_name);
     let mut has_rowid = true;
     let mut primary_key_columns = vec![];
+    let mut primary_key_conflict_clause = None;
+    let mut has_autoincrement = false;
     let mut cols = vec![];
     let is_strict: bool;
     // BtreeSet here to preserve order of inserted keys
-    let mut unique_sets: Vec<BTreeSet<UniqueColumnProps>> = vec![];
+    let mut unique_sets: Vec<(BTreeSet<UniqueColumnProps>, Option<ResolveType>)> = vec![];
+    let mut check_constraints = vec![];
     match body {
         CreateTableBody::ColumnsAndConstraints {
             columns,
@@ -542,9 +1064,13 @@ fn create_table(
             if let Some(constraints) = constraints {
                 for c in constraints {
                     if let turso_sqlite3_parser::ast::TableConstraint::PrimaryKey {
-                        columns, ..
+                        columns,
+                        conflict_clause,
+                        auto_increment,
                     } = c.constraint
                     {
+                        primary_key_conflict_clause = conflict_clause;
+                        has_autoincrement |= auto_increment;
                         for column in columns {
                             let col_name = match column.expr {
                                 Expr::Id(id) => normalize_ident(&id.0),
@@ -563,9 +1089,6 @@ fn create_table(
                         conflict_clause,
                     } = c.constraint
                     {
-                        if conflict_clause.is_some() {
-                            unimplemented!("ON CONFLICT not implemented");
-                        }
                         let unique_set = columns
                             .into_iter()
                             .map(|column| {
@@ -581,7 +1104,15 @@ fn create_table(
                                 }
                             })
                             .collect();
-                        unique_sets.push(unique_set);
+                        unique_sets.push((unique_set, conflict_clause));
+                    } else if let turso_sqlite3_parser::ast::TableConstraint::Check(expr) =
+                        c.constraint
+                    {
+                        check_constraints.push(CheckConstraint {
+                            name: c.name.map(|name| normalize_ident(&name.0)),
+                            expr,
+                            column: None,
+                        });
                     }
                 }
             }
@@ -608,7 +1139,7 @@ fn create_table(
                         let mut type_name = data_type.name;
                         type_name.make_ascii_uppercase();
 
-                        if type_name.is_empty() {
+                        if type_name.is_empty() || (is_strict && type_name == "ANY") {
                             break 'ty Type::Blob;
                         }
 
@@ -641,35 +1172,52 @@ fn create_table(
                 let mut default = None;
                 let mut primary_key = false;
                 let mut notnull = false;
+                let mut notnull_conflict_clause = None;
                 let mut order = SortOrder::Asc;
                 let mut unique = false;
+                let mut unique_conflict_clause = None;
                 let mut collation = None;
+                let mut generated = None;
                 for c_def in col_def.constraints {
                     match c_def.constraint {
                         turso_sqlite3_parser::ast::ColumnConstraint::PrimaryKey {
                             order: o,
-                            ..
+                            conflict_clause,
+                            auto_increment,
                         } => {
                             primary_key = true;
+                            has_autoincrement |= auto_increment;
                             if let Some(o) = o {
                                 order = o;
                             }
+                            primary_key_conflict_clause = conflict_clause;
                         }
-                        turso_sqlite3_parser::ast::ColumnConstraint::NotNull { .. } => {
+                        turso_sqlite3_parser::ast::ColumnConstraint::NotNull {
+                            nullable: false,
+                            conflict_clause,
+                        } => {
                             notnull = true;
+                            notnull_conflict_clause = conflict_clause;
                         }
                         turso_sqlite3_parser::ast::ColumnConstraint::Default(expr) => {
                             default = Some(expr)
                         }
-                        // TODO: for now we don't check Resolve type of unique
-                        turso_sqlite3_parser::ast::ColumnConstraint::Unique(on_conflict) => {
-                            if on_conflict.is_some() {
-                                unimplemented!("ON CONFLICT not implemented");
-                            }
+                        turso_sqlite3_parser::ast::ColumnConstraint::Unique(conflict_clause) => {
                             unique = true;
+                            unique_conflict_clause = conflict_clause;
                         }
                         turso_sqlite3_parser::ast::ColumnConstraint::Collate { collation_name } => {
-                            collation = Some(CollationSeq::new(collation_name.0.as_str())?);
+                            collation = Some(CollationSeq::from_name(collation_name.0.as_str()));
+                        }
+                        turso_sqlite3_parser::ast::ColumnConstraint::Check(expr) => {
+                            check_constraints.push(CheckConstraint {
+                                name: c_def.name.map(|name| normalize_ident(&name.0)),
+                                expr,
+                                column: Some(normalize_ident(&name)),
+                            });
+                        }
+                        turso_sqlite3_parser::ast::ColumnConstraint::Generated { expr, typ } => {
+                            generated = Some(GeneratedColumn::from_constraint(&name, expr, typ)?);
                         }
                         _ => {}
                     }
@@ -691,10 +1239,13 @@ fn create_table(
                     primary_key,
                     is_rowid_alias: typename_exactly_integer && primary_key,
                     notnull,
+                    notnull_conflict_clause,
                     default,
                     unique,
+                    unique_conflict_clause,
                     collation,
                     hidden: false,
+                    generated,
                 });
             }
             if options.contains(TableOptions::WITHOUT_ROWID) {
@@ -717,18 +1268,23 @@ fn create_table(
         primary_key_columns,
         columns: cols,
         is_strict,
+        check_constraints,
+        primary_key_conflict_clause,
+        has_autoincrement,
         unique_sets: if unique_sets.is_empty() {
             None
         } else {
             // Sort first so that dedup operation removes all duplicates
-            unique_sets.dedup();
+            unique_sets.dedup_by(|(a, _), (b, _)| a == b);
             Some(
                 unique_sets
                     .into_iter()
-                    .map(|set| {
-                        set.into_iter()
+                    .map(|(set, conflict_clause)| UniqueSet {
+                        columns: set
+                            .into_iter()
                             .map(|UniqueColumnProps { column_name, order }| (column_name, order))
-                            .collect()
+                            .collect(),
+                        conflict_clause,
                     })
                     .collect(),
             )
@@ -763,42 +1319,100 @@ pub struct Column {
     pub primary_key: bool,
     pub is_rowid_alias: bool,
     pub notnull: bool,
+    /// The ON CONFLICT clause of the NOT NULL constraint, if any.
+    pub notnull_conflict_clause: Option<ResolveType>,
     pub default: Option<Expr>,
     pub unique: bool,
+    /// The ON CONFLICT clause of the UNIQUE constraint, if any.
+    pub unique_conflict_clause: Option<ResolveType>,
     pub collation: Option<CollationSeq>,
     pub hidden: bool,
+    /// The expression of a `GENERATED ALWAYS AS` column.
+    pub generated: Option<GeneratedColumn>,
 }
 
 impl Column {
     pub fn affinity(&self) -> Affinity {
+        // The ANY columns of STRICT tables are typed as BLOB, so that they keep the values
+        // they're given as they are.
+        if self.ty == Type::Blob && self.ty_str.eq_ignore_ascii_case("ANY") {
+            return Affinity::Blob;
+        }
         affinity(&self.ty_str)
     }
+
+    /// VIRTUAL generated columns are computed when they are read, so the table's
+    /// records don't have a value for them.
+    pub fn is_virtual_generated(&self) -> bool {
+        self.generated
+            .as_ref()
+            .is_some_and(|generated| !generated.stored)
+    }
+}
+
+/// A column whose value is computed from the other columns of its row.
+#[derive(Clone, Debug)]
+pub struct GeneratedColumn {
+    pub expr: Expr,
+    /// STORED columns are computed when the row is written and saved in its record,
+    /// VIRTUAL ones are computed every time they are read.
+    pub stored: bool,
+}
+
+impl GeneratedColumn {
+    fn from_constraint(column_name: &str, expr: Expr, typ: Option<ast::Id>) -> Result<Self> {
+        let stored = match typ {
+            None => false,
+            Some(typ) if typ.0.eq_ignore_ascii_case("VIRTUAL") => false,
+            Some(typ) if typ.0.eq_ignore_ascii_case("STORED") => true,
+            Some(_) => {
+                return Err(LimboError::ParseError(format!(
+                    "error in generated column \"{column_name}\""
+                )))
+            }
+        };
+        Ok(Self { expr, stored })
+    }
 }
 
 // TODO: This might replace some of util::columns_from_create_table_body
-impl From<ColumnDefinition> for Column {
-    fn from(value: ColumnDefinition) -> Self {
+impl TryFrom<ColumnDefinition> for Column {
+    type Error = LimboError;
+
+    fn try_from(value: ColumnDefinition) -> Result<Self> {
         let ast::Name(name) = value.col_name;
 
         let mut default = None;
         let mut notnull = false;
+        let mut notnull_conflict_clause = None;
         let mut primary_key = false;
         let mut unique = false;
+        let mut unique_conflict_clause = None;
         let mut collation = None;
+        let mut generated = None;
 
         for ast::NamedColumnConstraint { constraint, .. } in value.constraints {
             match constraint {
                 ast::ColumnConstraint::PrimaryKey { .. } => primary_key = true,
-                ast::ColumnConstraint::NotNull { .. } => notnull = true,
-                ast::ColumnConstraint::Unique(..) => unique = true,
+                ast::ColumnConstraint::NotNull {
+                    nullable: false,
+                    conflict_clause,
+                } => {
+                    notnull = true;
+                    notnull_conflict_clause = conflict_clause;
+                }
+                ast::ColumnConstraint::Unique(conflict_clause) => {
+                    unique = true;
+                    unique_conflict_clause = conflict_clause;
+                }
                 ast::ColumnConstraint::Default(expr) => {
                     default.replace(expr);
                 }
                 ast::ColumnConstraint::Collate { collation_name } => {
-                    collation.replace(
-                        CollationSeq::new(&collation_name.0)
-                            .expect("collation should have been set correctly in create table"),
-                    );
+                    collation.replace(CollationSeq::from_name(&collation_name.0));
+                }
+                ast::ColumnConstraint::Generated { expr, typ } => {
+                    generated.replace(GeneratedColumn::from_constraint(&name, expr, typ)?);
                 }
                 _ => {}
             };
@@ -835,18 +1449,21 @@ impl From<ColumnDefinition> for Column {
             .map(|t| t.name.to_string())
             .unwrap_or_default();
 
-        Column {
+        Ok(Column {
             name: Some(name),
             ty,
             default,
             notnull,
+            notnull_conflict_clause,
             ty_str,
             primary_key,
             is_rowid_alias: primary_key && matches!(ty, Type::Integer),
             unique,
+            unique_conflict_clause,
             collation,
             hidden: false,
-        }
+            generated,
+        })
     }
 }
 
@@ -865,7 +1482,8 @@ impl From<ColumnDefinition> for Column {
 ///
 /// Note that the order of the rules for determining column affinity is important. A column whose declared type is "CHARINT" will match both rules 1 and 2 but the first rule takes precedence and so the column affinity will be INTEGER.
 pub fn affinity(datatype: &str) -> Affinity {
-    // Note: callers of this function must ensure that the datatype is uppercase.
+    // Like the rest of SQL, type names are case-insensitive.
+    let datatype = datatype.to_ascii_uppercase();
     // Rule 1: INT -> INTEGER affinity
     if datatype.contains("INT") {
         return Affinity::Integer;
@@ -877,7 +1495,7 @@ pub fn affinity(datatype: &str) -> Affinity {
     }
 
     // Rule 3: BLOB or empty -> BLOB affinity (historically called NONE)
-    if datatype.contains("BLOB") || datatype.is_empty() || datatype.contains("ANY") {
+    if datatype.contains("BLOB") || datatype.is_empty() {
         return Affinity::Blob;
     }
 
@@ -1043,6 +1661,108 @@ impl fmt::Display for Type {
     }
 }
 
+/// A row trigger, as defined by a CREATE TRIGGER statement.
+#[derive(Debug, Clone)]
+pub struct Trigger {
+    pub name: String,
+    pub table_name: String,
+    pub time: ast::TriggerTime,
+    pub event: ast::TriggerEvent,
+    pub when_clause: Option<Expr>,
+    pub commands: Vec<ast::TriggerCmd>,
+}
+
+impl Trigger {
+    pub fn from_sql(sql: &str) -> Result<Trigger> {
+        let mut parser = Parser::new(sql.as_bytes());
+        let cmd = parser.next()?;
+        match cmd {
+            Some(Cmd::Stmt(Stmt::CreateTrigger(trigger))) => {
+                let ast::CreateTrigger {
+                    trigger_name,
+                    time,
+                    event,
+                    tbl_name,
+                    when_clause,
+                    commands,
+                    ..
+                } = *trigger;
+                Ok(Trigger {
+                    name: normalize_ident(&trigger_name.name.0),
+                    table_name: normalize_ident(&tbl_name.name.0),
+                    time: time.unwrap_or(ast::TriggerTime::Before),
+                    event,
+                    when_clause,
+                    commands,
+                })
+            }
+            _ => todo!("Expected CREATE TRIGGER statement"),
+        }
+    }
+
+    /// Returns true if the trigger fires on `event`. An UPDATE OF trigger only fires on
+    /// UPDATE statements that assign one of its columns, whose positions in the table are
+    /// listed in `updated_columns`.
+    pub fn fires_on(
+        &self,
+        event: &ast::TriggerEvent,
+        table: &BTreeTable,
+        updated_columns: &[usize],
+    ) -> bool {
+        match (&self.event, event) {
+            (ast::TriggerEvent::UpdateOf(columns), ast::TriggerEvent::Update) => {
+                columns.iter().any(|name| {
+                    table
+                        .get_column(&name.0)
+                        .is_some_and(|(idx, _)| updated_columns.contains(&idx))
+                })
+            }
+            (ast::TriggerEvent::Update, ast::TriggerEvent::Update)
+            | (ast::TriggerEvent::Insert, ast::TriggerEvent::Insert)
+            | (ast::TriggerEvent::Delete, ast::TriggerEvent::Delete) => true,
+            _ => false,
+        }
+    }
+}
+
+/// A view, as defined by a CREATE VIEW statement.
+#[derive(Debug, Clone)]
+pub struct View {
+    pub name: String,
+    /// The column names given after the view name, if any.
+    pub columns: Option<Vec<String>>,
+    pub select: ast::Select,
+}
+
+impl View {
+    pub fn from_sql(sql: &str) -> Result<View> {
+        let mut parser = Parser::new(sql.as_bytes());
+        let cmd = parser.next()?;
+        match cmd {
+            Some(Cmd::Stmt(Stmt::CreateView {
+                view_name,
+                columns,
+                select,
+                ..
+            })) => Ok(View::new(&view_name.name.0, columns.as_deref(), *select)),
+            _ => todo!("Expected CREATE VIEW statement"),
+        }
+    }
+
+    pub fn new(name: &str, columns: Option<&[ast::IndexedColumn]>, select: ast::Select) -> View {
+        View {
+            name: normalize_ident(name),
+            columns: columns.map(|columns| {
+                columns
+                    .iter()
+                    .map(|column| normalize_ident(&column.col_name.0))
+                    .collect()
+            }),
+            select,
+        }
+    }
+}
+
 pub fn sqlite_schema_table() -> BTreeTable {
     BTreeTable {
         root_page: 1,
@@ -1058,10 +1778,13 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 primary_key: false,
                 is_rowid_alias: false,
                 notnull: false,
+                notnull_conflict_clause: None,
                 default: None,
                 unique: false,
+                unique_conflict_clause: None,
                 collation: None,
                 hidden: false,
+                generated: None,
             },
             Column {
                 name: Some("name".to_string()),
@@ -1070,10 +1793,13 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 primary_key: false,
                 is_rowid_alias: false,
                 notnull: false,
+                notnull_conflict_clause: None,
                 default: None,
                 unique: false,
+                unique_conflict_clause: None,
                 collation: None,
                 hidden: false,
+                generated: None,
             },
             Column {
                 name: Some("tbl_name".to_string()),
@@ -1082,10 +1808,13 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 primary_key: false,
                 is_rowid_alias: false,
                 notnull: false,
+                notnull_conflict_clause: None,
                 default: None,
                 unique: false,
+                unique_conflict_clause: None,
                 collation: None,
                 hidden: false,
+                generated: None,
             },
             Column {
                 name: Some("rootpage".to_string()),
@@ -1094,10 +1823,13 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 primary_key: false,
                 is_rowid_alias: false,
                 notnull: false,
+                notnull_conflict_clause: None,
                 default: None,
                 unique: false,
+                unique_conflict_clause: None,
                 collation: None,
                 hidden: false,
+                generated: None,
             },
             Column {
                 name: Some("sql".to_string()),
@@ -1106,18 +1838,24 @@ pub fn sqlite_schema_table() -> BTreeTable {
                 primary_key: false,
                 is_rowid_alias: false,
                 notnull: false,
+                notnull_conflict_clause: None,
                 default: None,
                 unique: false,
+                unique_conflict_clause: None,
                 collation: None,
                 hidden: false,
+                generated: None,
             },
         ],
         unique_sets: None,
+        check_constraints: vec![],
+        primary_key_conflict_clause: None,
+        has_autoincrement: false,
     }
 }
 
 #[allow(dead_code)]
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Index {
     pub name: String,
     pub table_name: String,
@@ -1125,12 +1863,19 @@ pub struct Index {
     pub columns: Vec<IndexColumn>,
     pub unique: bool,
     pub ephemeral: bool,
+    /// The ON CONFLICT clause of the PRIMARY KEY or UNIQUE constraint the index was
+    /// automatically created for, if any.
+    pub conflict_clause: Option<ResolveType>,
     /// Does the index have a rowid as the last column?
     /// This is the case for btree indexes (persistent or ephemeral) that
     /// have been created based on a table with a rowid.
     /// For example, WITHOUT ROWID tables (not supported in Limbo yet),
     /// and  SELECT DISTINCT ephemeral indexes will not have a rowid.
     pub has_rowid: bool,
+    /// The WHERE clause of a partial index, e.g. `x > 0` in
+    /// `CREATE INDEX idx ON t(x) WHERE x > 0`. Only the rows it is true for have an entry in
+    /// the index.
+    pub where_clause: Option<Expr>,
 }
 
 #[allow(dead_code)]
@@ -1146,6 +1891,52 @@ pub struct IndexColumn {
     pub pos_in_table: usize,
     pub collation: Option<CollationSeq>,
     pub default: Option<Expr>,
+    /// The expression the column is computed from, e.g. `lower(name)` in
+    /// `CREATE INDEX idx ON t(lower(name))`, in which case `pos_in_table` is
+    /// [EXPR_INDEX_COLUMN].
+    pub expr: Option<Box<Expr>>,
+}
+
+/// The [IndexColumn::pos_in_table] of an index column that is an expression rather than a column
+/// of the table.
+pub const EXPR_INDEX_COLUMN: usize = usize::MAX;
+
+impl IndexColumn {
+    /// Resolves a column of a `CREATE INDEX` statement on `table`. A name that isn't one of the
+    /// columns of the table is an error, while any other expression makes an expression column.
+    pub fn from_sorted_column(table: &BTreeTable, column: &ast::SortedColumn) -> Result<Self> {
+        let (expr, collation) = match &column.expr {
+            Expr::Collate(expr, collation) => {
+                (expr.as_ref(), Some(CollationSeq::from_name(collation)))
+            }
+            expr => (expr, None),
+        };
+        let order = column.order.unwrap_or(SortOrder::Asc);
+        match expr {
+            Expr::Id(ast::Id(name)) | Expr::Name(ast::Name(name)) => {
+                let name = normalize_ident(name);
+                let Some((pos_in_table, table_column)) = table.get_column(&name) else {
+                    return Err(LimboError::ParseError(format!("no such column: {name}")));
+                };
+                Ok(IndexColumn {
+                    name,
+                    order,
+                    pos_in_table,
+                    collation: collation.or(table_column.collation),
+                    default: table_column.default.clone(),
+                    expr: None,
+                })
+            }
+            expr => Ok(IndexColumn {
+                name: expr.to_string(),
+                order,
+                pos_in_table: EXPR_INDEX_COLUMN,
+                collation,
+                default: None,
+                expr: Some(Box::new(expr.clone())),
+            }),
+        }
+    }
 }
 
 impl Index {
@@ -1158,26 +1949,19 @@ impl Index {
                 tbl_name,
                 columns,
                 unique,
+                where_clause,
                 ..
             })) => {
                 let index_name = normalize_ident(&idx_name.name.0);
                 let mut index_columns = Vec::with_capacity(columns.len());
-                for col in columns.into_iter() {
-                    let name = normalize_ident(&col.expr.to_string());
-                    let Some((pos_in_table, _)) = table.get_column(&name) else {
-                        return Err(crate::LimboError::InternalError(format!(
-                            "Column {} is in index {} but not found in table {}",
-                            name, index_name, table.name
-                        )));
-                    };
-                    let (_, column) = table.get_column(&name).unwrap();
-                    index_columns.push(IndexColumn {
-                        name,
-                        order: col.order.unwrap_or(SortOrder::Asc),
-                        pos_in_table,
-                        collation: column.collation,
-                        default: column.default.clone(),
-                    });
+                for col in columns.iter() {
+                    let column = IndexColumn::from_sorted_column(table, col).map_err(|err| {
+                        crate::LimboError::InternalError(format!(
+                            "Column {} of index {} is invalid for table {}: {err}",
+                            col.expr, index_name, table.name
+                        ))
+                    })?;
+                    index_columns.push(column);
                 }
                 Ok(Index {
                     name: index_name,
@@ -1186,7 +1970,9 @@ impl Index {
                     columns: index_columns,
                     unique,
                     ephemeral: false,
+                    conflict_clause: None,
                     has_rowid: table.has_rowid,
+                    where_clause: where_clause.map(|expr| *expr),
                 })
             }
             _ => todo!("Expected create index statement"),
@@ -1241,6 +2027,7 @@ impl Index {
                         pos_in_table,
                         collation: column.collation,
                         default: column.default.clone(),
+                        expr: None,
                     }
                 })
                 .collect::<Vec<_>>();
@@ -1252,7 +2039,9 @@ impl Index {
                 columns: primary_keys,
                 unique: true,
                 ephemeral: false,
+                conflict_clause: table.primary_key_conflict_clause,
                 has_rowid: table.has_rowid,
+                where_clause: None,
             });
         }
 
@@ -1283,10 +2072,13 @@ impl Index {
                             pos_in_table,
                             collation: column.collation,
                             default: column.default.clone(),
+                            expr: None,
                         }],
                         unique: true,
                         ephemeral: false,
+                        conflict_clause: col.unique_conflict_clause,
                         has_rowid: table.has_rowid,
+                        where_clause: None,
                     })
                 } else {
                     None
@@ -1319,11 +2111,11 @@ impl Index {
                 .iter()
                 .filter(|set| {
                     if has_primary_key_index
-                        && table.primary_key_columns.len() == set.len()
+                        && table.primary_key_columns.len() == set.columns.len()
                         && table
                             .primary_key_columns
                             .iter()
-                            .all(|col| set.contains(col))
+                            .all(|col| set.columns.contains(col))
                     {
                         // skip unique columns that are satisfied with pk constraint
                         false
@@ -1336,7 +2128,7 @@ impl Index {
                     "number of auto_indices in schema should be same number of indices calculated",
                 );
 
-                    let index_cols = set.iter().map(|(col_name, order)| {
+                    let index_cols = set.columns.iter().map(|(col_name, order)| {
                         let Some((pos_in_table, _)) = table.get_column(col_name) else {
                             // This is clearly an invariant that should be maintained, so a panic seems more correct here
                             panic!(
@@ -1351,6 +2143,7 @@ impl Index {
                             pos_in_table,
                             collation: column.collation,
                             default: column.default.clone(),
+                            expr: None,
                         }
                     });
                     Index {
@@ -1360,7 +2153,9 @@ impl Index {
                         columns: index_cols.collect(),
                         unique: true,
                         ephemeral: false,
+                        conflict_clause: set.conflict_clause,
                         has_rowid: table.has_rowid,
+                        where_clause: None,
                     }
                 });
             indices.extend(unique_set_indices);
@@ -1384,6 +2179,11 @@ impl Index {
             .iter()
             .position(|c| c.pos_in_table == table_pos)
     }
+
+    /// Returns true if some column of the index is an expression, see [IndexColumn::expr].
+    pub fn has_expr_columns(&self) -> bool {
+        self.columns.iter().any(|c| c.expr.is_some())
+    }
 }
 
 #[cfg(test)]
@@ -1720,12 +2520,18 @@ mod tests {
                 primary_key: false,
                 is_rowid_alias: false,
                 notnull: false,
+                notnull_conflict_clause: None,
                 default: None,
                 unique: false,
+                unique_conflict_clause: None,
                 collation: None,
                 hidden: false,
+                generated: None,
             }],
             unique_sets: None,
+            check_constraints: vec![],
+            primary_key_conflict_clause: None,
+            has_autoincrement: false,
         };
 
         let _result = Index::automatic_from_primary_key_and_unique(
@@ -54,14 +54,18 @@ impl VTable for GenerateSeriesTable {
             stop: 0,
             step: 0,
             current: 0,
+            arguments: [0; 3],
         })
     }
 
-    fn best_index(constraints: &[ConstraintInfo], _order_by: &[OrderByInfo]) -> IndexInfo {
+    fn best_index(constraints: &[ConstraintInfo], order_by: &[OrderByInfo]) -> IndexInfo {
         // The bits of `idx_num` are used to indicate which arguments are available to the filter method:
         // - Bit 0 set -> 'start' is available
         // - Bit 1 set -> 'stop' is available
         // - Bit 2 set -> 'step' is available
+        // and the order the values must be returned in:
+        // - Bit 3 set -> ascending
+        // - Bit 4 set -> descending
         let mut idx_num = 0;
         let mut start_idx = None;
         let mut stop_idx = None;
@@ -109,9 +113,19 @@ impl VTable for GenerateSeriesTable {
             })
             .collect();
 
+        // The series can be returned in either order, by iterating it in reverse
+        let order_by_consumed = match order_by {
+            [order_by] if order_by.column_index == 0 => {
+                idx_num |= if order_by.desc { 16 } else { 8 };
+                true
+            }
+            _ => false,
+        };
+
         IndexInfo {
             idx_num,
             idx_str: Some(idx_num.to_string()),
+            order_by_consumed,
             constraint_usages,
             ..Default::default()
         }
@@ -125,6 +139,9 @@ struct GenerateSeriesCursor {
     stop: i64,
     step: i64,
     current: i64,
+    /// The start, stop and step arguments, which the other fields are the reverse of when the
+    /// query asked for the values in the opposite order.
+    arguments: [i64; 3],
 }
 
 impl GenerateSeriesCursor {
@@ -143,6 +160,24 @@ impl GenerateSeriesCursor {
         self.is_invalid_ascending_series() || self.is_invalid_descending_series()
     }
 
+    /// Makes the cursor return the values of the series from the last one to the first one.
+    /// Returns false if the series has several values and its step, i64::MIN, can't be negated.
+    fn reverse(&mut self) -> bool {
+        let count = (self.stop as i128 - self.start as i128) / self.step as i128;
+        if count == 0 {
+            return true;
+        }
+        let Some(step) = self.step.checked_neg() else {
+            return false;
+        };
+        let last = (self.start as i128 + count * self.step as i128) as i64;
+        self.stop = self.start;
+        self.start = last;
+        self.step = step;
+        self.current = last;
+        true
+    }
+
     /// Returns true if we would exceed the stop value in the current direction
     fn would_exceed(&self) -> bool {
         (self.step > 0 && self.current.saturating_add(self.step) > self.stop)
@@ -157,8 +192,10 @@ impl VTabCursor for GenerateSeriesCursor {
         let mut start = -1;
         let mut stop = -1;
         let mut step = 1;
+        let mut order = 0;
 
         if let Some((_, idx_num)) = idx_info {
+            order = idx_num & (8 | 16);
             let mut arg_idx = 0;
             // For the semantics of `idx_num`, see the comment in the `best_index` method.
             if idx_num & 1 != 0 {
@@ -192,6 +229,7 @@ impl VTabCursor for GenerateSeriesCursor {
         self.start = start;
         self.step = step;
         self.stop = stop;
+        self.arguments = [start, stop, step];
 
         // Set initial value based on range validity
         // For invalid input SQLite returns an empty series
@@ -201,6 +239,10 @@ impl VTabCursor for GenerateSeriesCursor {
             start
         };
 
+        if ((order == 8 && step < 0) || (order == 16 && step > 0)) && !self.reverse() {
+            return ResultCode::Error;
+        }
+
         ResultCode::OK
     }
 
@@ -244,9 +286,7 @@ impl VTabCursor for GenerateSeriesCursor {
     fn column(&self, idx: u32) -> Result<Value, Self::Error> {
         Ok(match idx {
             0 => Value::from_integer(self.current),
-            1 => Value::from_integer(self.start),
-            2 => Value::from_integer(self.stop),
-            3 => Value::from_integer(self.step),
+            1..=3 => Value::from_integer(self.arguments[idx as usize - 1]),
             _ => Value::null(),
         })
     }
@@ -305,6 +345,10 @@ mod tests {
     }
     // Helper function to collect all values from a cursor, returns Result with error code
     fn collect_series(series: Series) -> Result<Vec<i64>, ResultCode> {
+        collect_series_with_idx_num(series, 1 | 2 | 4)
+    }
+
+    fn collect_series_with_idx_num(series: Series, idx_num: i32) -> Result<Vec<i64>, ResultCode> {
         let tbl = GenerateSeriesTable {};
         let mut cursor = tbl.open(None)?;
 
@@ -316,7 +360,7 @@ mod tests {
         ];
 
         // Initialize cursor through filter
-        match cursor.filter(&args, Some(("idx", 1 | 2 | 4))) {
+        match cursor.filter(&args, Some(("idx", idx_num))) {
             ResultCode::OK => (),
             ResultCode::EOF => return Ok(vec![]),
             err => return Err(err),
@@ -493,6 +537,40 @@ mod tests {
         );
     }
 
+    #[quickcheck]
+    /// Test that the series is returned in the order requested by the query, by iterating it
+    /// in reverse if needed
+    fn prop_series_in_requested_order(series: Series) {
+        let Ok(mut values) = collect_series(series.clone()) else {
+            return;
+        };
+        let ascending = collect_series_with_idx_num(series.clone(), 1 | 2 | 4 | 8).unwrap();
+        let descending = collect_series_with_idx_num(series, 1 | 2 | 4 | 16).unwrap();
+        values.sort_unstable();
+        assert_eq!(ascending, values);
+        values.reverse();
+        assert_eq!(descending, values);
+    }
+
+    #[test]
+    fn test_series_in_requested_order() {
+        let series = Series {
+            start: 1,
+            stop: 10,
+            step: 4,
+        };
+        let values = collect_series_with_idx_num(series, 1 | 2 | 4 | 16).unwrap();
+        assert_eq!(values, vec![9, 5, 1]);
+
+        let series = Series {
+            start: 10,
+            stop: 1,
+            step: -4,
+        };
+        let values = collect_series_with_idx_num(series, 1 | 2 | 4 | 8).unwrap();
+        assert_eq!(values, vec![2, 6, 10]);
+    }
+
     #[test]
     fn test_series_empty_negative_step() {
         let values = collect_series(Series {
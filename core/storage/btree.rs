@@ -10,6 +10,7 @@ use crate::{
             TableLeafCell, CELL_PTR_SIZE_BYTES, INTERIOR_PAGE_HEADER_SIZE_BYTES,
             LEAF_PAGE_HEADER_SIZE_BYTES, LEFT_CHILD_PTR_SIZE_BYTES,
         },
+        text_encoding::{transcode_record, TextEncoding},
     },
     translate::plan::IterationDirection,
     turso_assert,
@@ -807,15 +808,7 @@ impl BTreeCursor {
         );
         let mut payload_swap = Vec::new();
         std::mem::swap(payload, &mut payload_swap);
-
-        let mut reuse_immutable = self.get_immutable_record_or_create();
-        reuse_immutable.as_mut().unwrap().invalidate();
-
-        reuse_immutable
-            .as_mut()
-            .unwrap()
-            .start_serialization(&payload_swap);
-        self.record_cursor.borrow_mut().invalidate();
+        self.load_record(&payload_swap)?;
 
         let _ = read_overflow_state.take();
         Ok(IOResult::Done(()))
@@ -895,7 +888,7 @@ impl BTreeCursor {
 
         let page = page_btree.get();
         let contents = page.get().contents.as_ref().unwrap();
-        let cell_idx = self.stack.current_cell_index() as usize - 1;
+        let cell_idx = self.stack.current_cell_index() as usize;
 
         if cell_idx >= contents.cell_count() {
             return Err(LimboError::Corrupt("Invalid cell index".into()));
@@ -995,9 +988,23 @@ impl BTreeCursor {
                     buffer_offset,
                     is_write,
                 }) => {
+                    // The state is put back while a page loads, so that the payload before it
+                    // isn't processed again.
+                    let page = self.read_page(*next_page as usize)?;
+                    if page.get().is_locked() {
+                        self.state = CursorState::ReadWritePayload(
+                            PayloadOverflowWithOffset::SkipOverflowPages {
+                                next_page: *next_page,
+                                pages_left_to_skip: *pages_left_to_skip,
+                                page_offset: *page_offset,
+                                amount: *amount,
+                                buffer_offset: *buffer_offset,
+                                is_write: *is_write,
+                            },
+                        );
+                        return Ok(IOResult::IO);
+                    }
                     if *pages_left_to_skip == 0 {
-                        let page = self.read_page(*next_page as usize)?;
-                        return_if_locked_maybe_load!(self.pager, page);
                         self.state =
                             CursorState::ReadWritePayload(PayloadOverflowWithOffset::ProcessPage {
                                 next_page: *next_page,
@@ -1011,8 +1018,6 @@ impl BTreeCursor {
                         continue;
                     }
 
-                    let page = self.read_page(*next_page as usize)?;
-                    return_if_locked_maybe_load!(self.pager, page);
                     let page = page.get();
                     let contents = page.get_contents();
                     let next = contents.read_u32_no_offset(0);
@@ -1078,7 +1083,7 @@ impl BTreeCursor {
                             payload_offset as u32,
                             bytes_to_process,
                             page_payload,
-                            buffer,
+                            &mut buffer[*buffer_offset..],
                             page_btree.clone(),
                         );
                     } else {
@@ -1108,7 +1113,8 @@ impl BTreeCursor {
                     *current_offset = 0; // Reset offset for new page
                     *page_btree = self.read_page(next as usize)?;
 
-                    // Return IO to allow other operations
+                    // Return IO to allow other operations, resuming at the next page
+                    self.state = state;
                     return Ok(IOResult::IO);
                 }
                 _ => {
@@ -1600,15 +1606,7 @@ impl BTreeCursor {
                 if let Some(next_page) = first_overflow_page {
                     return_if_io!(self.process_overflow_read(payload, *next_page, *payload_size))
                 } else {
-                    self.get_immutable_record_or_create()
-                        .as_mut()
-                        .unwrap()
-                        .invalidate();
-                    self.get_immutable_record_or_create()
-                        .as_mut()
-                        .unwrap()
-                        .start_serialization(payload);
-                    self.record_cursor.borrow_mut().invalidate();
+                    self.load_record(payload)?;
                 };
                 let (target_leaf_page_is_in_left_subtree, is_eq) = {
                     let record = self.get_immutable_record();
@@ -1957,16 +1955,7 @@ impl BTreeCursor {
             if let Some(next_page) = first_overflow_page {
                 return_if_io!(self.process_overflow_read(payload, *next_page, *payload_size))
             } else {
-                self.get_immutable_record_or_create()
-                    .as_mut()
-                    .unwrap()
-                    .invalidate();
-                self.get_immutable_record_or_create()
-                    .as_mut()
-                    .unwrap()
-                    .start_serialization(payload);
-
-                self.record_cursor.borrow_mut().invalidate();
+                self.load_record(payload)?;
             };
             let (cmp, found) = self.compare_with_current_record(
                 key_values.as_slice(),
@@ -2096,7 +2085,6 @@ impl BTreeCursor {
         let record = bkey
             .get_record()
             .expect("expected record present on insert");
-        let record_values = record.get_values();
         if let CursorState::None = &self.state {
             self.state = CursorState::Write(WriteInfo::new());
         }
@@ -2160,6 +2148,8 @@ impl BTreeCursor {
                             }
                             BTreeCell::IndexLeafCell(..) | BTreeCell::IndexInteriorCell(..) => {
                                 return_if_io!(self.record());
+                                // Only table records leave zeros out, so an index key can be read whole.
+                                let record_values = record.get_values();
                                 let cmp = compare_immutable(
                                     record_values.as_slice(),
                                     self.get_immutable_record()
@@ -2199,7 +2189,8 @@ impl BTreeCursor {
 
                     // insert cell
 
-                    let mut cell_payload: Vec<u8> = Vec::with_capacity(record_values.len() + 4);
+                    let mut cell_payload: Vec<u8> =
+                        Vec::with_capacity(record.get_payload().len() + 4);
                     fill_cell_payload(
                         page.get().get().contents.as_ref().unwrap(),
                         bkey.maybe_rowid(),
@@ -2208,7 +2199,7 @@ impl BTreeCursor {
                         record,
                         self.usable_space() as u16,
                         self.pager.clone(),
-                    );
+                    )?;
 
                     // insert
                     let overflow = {
@@ -3947,6 +3938,10 @@ impl BTreeCursor {
         self.root_page
     }
 
+    pub fn pager(&self) -> &Rc<Pager> {
+        &self.pager
+    }
+
     #[instrument(skip_all, level = Level::DEBUG)]
     pub fn rewind(&mut self) -> Result<IOResult<()>> {
         if let Some(mv_cursor) = &self.mv_cursor {
@@ -4130,15 +4125,7 @@ impl BTreeCursor {
         if let Some(next_page) = first_overflow_page {
             return_if_io!(self.process_overflow_read(payload, next_page, payload_size))
         } else {
-            self.get_immutable_record_or_create()
-                .as_mut()
-                .unwrap()
-                .invalidate();
-            self.get_immutable_record_or_create()
-                .as_mut()
-                .unwrap()
-                .start_serialization(payload);
-            self.record_cursor.borrow_mut().invalidate();
+            self.load_record(payload)?;
         };
 
         *self.parse_record_state.borrow_mut() = ParseRecordState::Init;
@@ -4161,7 +4148,9 @@ impl BTreeCursor {
             Some(mv_cursor) => match key.maybe_rowid() {
                 Some(rowid) => {
                     let row_id = crate::mvcc::database::RowID::new(self.table_id() as u64, rowid);
-                    let record_buf = key.get_record().unwrap().get_payload().to_vec();
+                    let mut record = key.get_record().unwrap().clone();
+                    record.expand_zero_tail();
+                    let record_buf = record.get_payload().to_vec();
                     let row = crate::mvcc::database::Row::new(row_id, record_buf);
                     mv_cursor.borrow_mut().insert(row).unwrap();
                 }
@@ -4859,9 +4848,10 @@ impl BTreeCursor {
                         destroy_info.state = DestroyState::ProcessPage;
                     } else {
                         self.state = CursorState::None;
-                        //  TODO: For now, no-op the result return None always. This will change once [AUTO_VACUUM](https://www.sqlite.org/lang_vacuum.html) is introduced
-                        //  At that point, the last root page(call this x) will be moved into the position of the root page of this table and the value returned will be x
-                        return Ok(IOResult::Done(None));
+                        //  In an auto-vacuum database, the last root page is moved into the root page of this table
+                        //  and its former page number is returned.
+                        let moved = self.pager.btree_destroyed(page_id as u32)?;
+                        return Ok(IOResult::Done(moved.map(|page_id| page_id as usize)));
                     }
                 }
             }
@@ -4872,6 +4862,24 @@ impl BTreeCursor {
         self.root_page
     }
 
+    /// Returns the size of the payload of the cell the cursor points to, including the part of
+    /// it in overflow pages.
+    pub fn payload_size(&self) -> Result<IOResult<u64>> {
+        let page = self.stack.top();
+        return_if_locked_maybe_load!(self.pager, page);
+        let page = page.get();
+        let contents = page.get_contents();
+        let cell_idx = self.stack.current_cell_index() as usize;
+        match contents.cell_get(cell_idx, self.usable_space())? {
+            BTreeCell::TableLeafCell(cell) => Ok(IOResult::Done(cell.payload_size)),
+            BTreeCell::IndexLeafCell(cell) => Ok(IOResult::Done(cell.payload_size)),
+            BTreeCell::IndexInteriorCell(cell) => Ok(IOResult::Done(cell.payload_size)),
+            BTreeCell::TableInteriorCell(_) => Err(LimboError::Corrupt(
+                "Cannot access payload of table interior cell".into(),
+            )),
+        }
+    }
+
     pub fn overwrite_cell(
         &mut self,
         page_ref: BTreePage,
@@ -4892,7 +4900,7 @@ impl BTreeCursor {
             record,
             self.usable_space() as u16,
             self.pager.clone(),
-        );
+        )?;
 
         // figure out old cell offset & size
         let (old_offset, old_local_size) = {
@@ -4936,6 +4944,24 @@ impl BTreeCursor {
         Ok(IOResult::Done(()))
     }
 
+    /// Makes the record in `payload` the one the cursor is at. The text of a UTF-16 database is
+    /// converted to UTF-8, the only encoding the rest of the engine knows.
+    fn load_record(&self, payload: &[u8]) -> Result<()> {
+        let mut record = self.get_immutable_record_or_create();
+        let record = record.as_mut().unwrap();
+        record.invalidate();
+        match self.pager.text_encoding()? {
+            TextEncoding::Utf8 => record.start_serialization(payload),
+            encoding => record.start_serialization(&transcode_record(
+                payload,
+                encoding,
+                TextEncoding::Utf8,
+            )?),
+        }
+        self.record_cursor.borrow_mut().invalidate();
+        Ok(())
+    }
+
     fn get_immutable_record_or_create(&self) -> std::cell::RefMut<'_, Option<ImmutableRecord>> {
         if self.reusable_immutable_record.borrow().is_none() {
             let record = ImmutableRecord::new(4096);
@@ -5157,48 +5183,163 @@ pub enum IntegrityCheckError {
         got: usize,
         expected: usize,
     },
+    #[error("Page {page_id}: not a b-tree page")]
+    NotBTreePage { page_id: usize },
+    #[error("invalid page number {page_id}")]
+    InvalidPageNumber { page_id: usize },
+    #[error("2nd reference to page {page_id}")]
+    PageReferencedTwice { page_id: usize },
+    #[error("Page {page_id}: never used")]
+    PageNeverUsed { page_id: usize },
+    #[error("overflow list length is {got} but should be {expected} on page {first_page}")]
+    OverflowListLength {
+        first_page: usize,
+        got: usize,
+        expected: usize,
+    },
+    #[error("freelist leaf count too big on page {page_id}")]
+    FreelistLeafCountTooBig { page_id: usize },
+    #[error("Freelist: size is {got} but should be {expected}")]
+    FreelistSize { got: usize, expected: usize },
+    #[error("wrong # of entries in index {index_name}")]
+    IndexEntryCount { index_name: String },
 }
 
 #[derive(Clone)]
 struct IntegrityCheckPageEntry {
     page_idx: usize,
-    level: usize,
-    max_intkey: i64,
+    kind: IntegrityCheckPageKind,
+}
+
+#[derive(Clone)]
+enum IntegrityCheckPageKind {
+    /// A page of the b-tree being checked, at depth `level`, whose rowids can't exceed
+    /// `max_intkey`.
+    BTree { level: usize, max_intkey: i64 },
+    /// A page of the overflow chain that starts at `first_page`, after `seen` pages of it.
+    Overflow {
+        first_page: usize,
+        seen: usize,
+        expected: usize,
+    },
+    /// A trunk page of the freelist, after `seen` pages of it.
+    FreelistTrunk { seen: usize, expected: usize },
 }
+
+/// The progress of an integrity check over the freelist and the b-trees of a database. The pages
+/// are tracked across all of them, so that a page used twice or not at all is reported.
 pub struct IntegrityCheckState {
     pub current_page: usize,
     page_stack: Vec<IntegrityCheckPageEntry>,
     first_leaf_level: Option<usize>,
+    /// The number of pages of the database, which no page number may exceed.
+    db_size: usize,
+    max_errors: usize,
+    page_references: HashSet<usize>,
+    /// The number of entries of the b-tree being checked: its rows for a table, or its keys for
+    /// an index.
+    pub entries: u64,
 }
 
 impl IntegrityCheckState {
-    pub fn new(page_idx: usize) -> Self {
+    pub fn new(db_size: usize, max_errors: usize) -> Self {
         Self {
-            current_page: page_idx,
-            page_stack: vec![IntegrityCheckPageEntry {
-                page_idx,
+            current_page: 0,
+            page_stack: Vec::new(),
+            first_leaf_level: None,
+            db_size,
+            max_errors,
+            page_references: HashSet::new(),
+            entries: 0,
+        }
+    }
+
+    /// Starts checking the b-tree rooted at `root_page`.
+    pub fn start_btree(&mut self, root_page: usize, errors: &mut Vec<IntegrityCheckError>) {
+        self.first_leaf_level = None;
+        self.entries = 0;
+        self.push_page(
+            root_page,
+            IntegrityCheckPageKind::BTree {
                 level: 0,
                 max_intkey: i64::MAX,
-            }],
-            first_leaf_level: None,
+            },
+            errors,
+        );
+    }
+
+    /// Starts checking the freelist, which the database header says starts at `first_trunk` and
+    /// has `expected` pages.
+    pub fn start_freelist(
+        &mut self,
+        first_trunk: usize,
+        expected: usize,
+        errors: &mut Vec<IntegrityCheckError>,
+    ) {
+        if first_trunk == 0 {
+            if expected != 0 {
+                errors.push(IntegrityCheckError::FreelistSize { got: 0, expected });
+            }
+            return;
+        }
+        self.push_page(
+            first_trunk,
+            IntegrityCheckPageKind::FreelistTrunk { seen: 0, expected },
+            errors,
+        );
+    }
+
+    /// The pages that neither a b-tree nor the freelist uses.
+    pub fn unused_pages(&self) -> impl Iterator<Item = usize> + '_ {
+        (1..=self.db_size).filter(|page_idx| !self.page_references.contains(page_idx))
+    }
+
+    /// Records a reference to a page, which is only checked later if it is a valid page that
+    /// wasn't referenced before.
+    fn reference_page(&mut self, page_idx: usize, errors: &mut Vec<IntegrityCheckError>) -> bool {
+        if page_idx == 0 || page_idx > self.db_size {
+            errors.push(IntegrityCheckError::InvalidPageNumber { page_id: page_idx });
+            return false;
+        }
+        if !self.page_references.insert(page_idx) {
+            errors.push(IntegrityCheckError::PageReferencedTwice { page_id: page_idx });
+            return false;
+        }
+        true
+    }
+
+    fn push_page(
+        &mut self,
+        page_idx: usize,
+        kind: IntegrityCheckPageKind,
+        errors: &mut Vec<IntegrityCheckError>,
+    ) {
+        if self.reference_page(page_idx, errors) {
+            self.page_stack
+                .push(IntegrityCheckPageEntry { page_idx, kind });
         }
     }
 }
+
 impl std::fmt::Debug for IntegrityCheckState {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("IntegrityCheckState")
             .field("current_page", &self.current_page)
             .field("first_leaf_level", &self.first_leaf_level)
+            .field("entries", &self.entries)
             .finish()
     }
 }
 
-/// Perform integrity check on a whole table/index. We check for:
+/// Checks the pages pushed by [IntegrityCheckState::start_btree] or
+/// [IntegrityCheckState::start_freelist], along with the pages they point to. We check for:
 /// 1. Correct order of keys in case of rowids.
 /// 2. There are no overlap between cells.
 /// 3. Cells do not scape outside expected range.
 /// 4. Depth of leaf pages are equal.
-/// 5. Overflow pages are correct (TODO)
+/// 5. Overflow chains have as many pages as the payloads of their cells need.
+/// 6. The freelist has as many pages as the database header says.
+/// 7. No page is used twice, and every page number is within the database.
 ///
 /// In order to keep this reentrant, we keep a stack of pages we need to check. Ideally, like in
 /// SQLlite, we would have implemented a recursive solution which would make it easier to check the
@@ -5208,22 +5349,111 @@ pub fn integrity_check(
     errors: &mut Vec<IntegrityCheckError>,
     pager: &Rc<Pager>,
 ) -> Result<IOResult<()>> {
-    let Some(IntegrityCheckPageEntry {
-        page_idx,
-        level,
-        max_intkey,
-    }) = state.page_stack.last().cloned()
-    else {
-        return Ok(IOResult::Done(()));
-    };
-    let page = btree_read_page(pager, page_idx)?;
-    return_if_locked_maybe_load!(pager, page);
-    state.page_stack.pop();
+    loop {
+        if errors.len() >= state.max_errors {
+            state.page_stack.clear();
+        }
+        let Some(IntegrityCheckPageEntry { page_idx, kind }) = state.page_stack.last().cloned()
+        else {
+            return Ok(IOResult::Done(()));
+        };
+        state.current_page = page_idx;
+        let page = btree_read_page(pager, page_idx)?;
+        return_if_locked_maybe_load!(pager, page);
+        state.page_stack.pop();
 
-    let page = page.get();
-    let contents = page.get_contents();
+        let page = page.get();
+        let contents = page.get_contents();
+        match kind {
+            IntegrityCheckPageKind::BTree { level, max_intkey } => {
+                if contents.maybe_page_type().is_none() {
+                    errors.push(IntegrityCheckError::NotBTreePage { page_id: page_idx });
+                    continue;
+                }
+                integrity_check_btree_page(state, errors, pager, contents, level, max_intkey)?;
+            }
+            IntegrityCheckPageKind::Overflow {
+                first_page,
+                seen,
+                expected,
+            } => {
+                let seen = seen + 1;
+                let next = contents.read_u32_no_offset(0) as usize;
+                if next == 0 {
+                    if seen != expected {
+                        errors.push(IntegrityCheckError::OverflowListLength {
+                            first_page,
+                            got: seen,
+                            expected,
+                        });
+                    }
+                } else {
+                    state.push_page(
+                        next,
+                        IntegrityCheckPageKind::Overflow {
+                            first_page,
+                            seen,
+                            expected,
+                        },
+                        errors,
+                    );
+                }
+            }
+            IntegrityCheckPageKind::FreelistTrunk { seen, expected } => {
+                let usable_space = pager.usable_space();
+                let next = contents.read_u32_no_offset(0) as usize;
+                let leaf_count = contents.read_u32_no_offset(4) as usize;
+                let mut seen = seen + 1;
+                if leaf_count > usable_space / 4 - 2 {
+                    errors.push(IntegrityCheckError::FreelistLeafCountTooBig { page_id: page_idx });
+                } else {
+                    for i in 0..leaf_count {
+                        let leaf = contents.read_u32_no_offset(8 + 4 * i) as usize;
+                        state.reference_page(leaf, errors);
+                    }
+                    seen += leaf_count;
+                }
+                if next == 0 {
+                    if seen != expected {
+                        errors.push(IntegrityCheckError::FreelistSize {
+                            got: seen,
+                            expected,
+                        });
+                    }
+                } else {
+                    state.push_page(
+                        next,
+                        IntegrityCheckPageKind::FreelistTrunk { seen, expected },
+                        errors,
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn integrity_check_btree_page(
+    state: &mut IntegrityCheckState,
+    errors: &mut Vec<IntegrityCheckError>,
+    pager: &Rc<Pager>,
+    contents: &PageContent,
+    level: usize,
+    max_intkey: i64,
+) -> Result<()> {
+    let page_idx = state.current_page;
     let usable_space = pager.usable_space() as u16;
-    let mut coverage_checker = CoverageChecker::new(page.get().id);
+    let mut coverage_checker = CoverageChecker::new(page_idx);
+
+    if let Some(rightmost) = contents.rightmost_pointer() {
+        state.push_page(
+            rightmost as usize,
+            IntegrityCheckPageKind::BTree {
+                level: level + 1,
+                max_intkey,
+            },
+            errors,
+        );
+    }
 
     // Now we check every cell for few things:
     // 1. Check cell is in correct range. Not exceeds page and not starts before we have marked
@@ -5232,7 +5462,7 @@ pub fn integrity_check(
     // 3. We check order of rowids in case of table pages. We iterate backwards in order to check
     //    if current cell's rowid is less than the next cell. We also check rowid is less than the
     //    parent's divider cell. In case of this page being root page max rowid will be i64::MAX.
-    // 4. We append pages to the stack to check later.
+    // 4. We append pages to the stack to check later, including the overflow pages of the cell.
     // 5. In case of leaf page, check if the current level(depth) is equal to other leaf pages we
     //    have seen.
     let mut next_rowid = max_intkey;
@@ -5244,36 +5474,42 @@ pub fn integrity_check(
         {
             errors.push(IntegrityCheckError::CellOutOfRange {
                 cell_idx,
-                page_id: page.get().id,
+                page_id: page_idx,
                 cell_start,
                 cell_end: cell_start + cell_length,
                 content_area: contents.cell_content_area() as usize,
                 usable_space: usable_space as usize,
             });
+            continue;
         }
         if cell_start + cell_length > usable_space as usize {
             errors.push(IntegrityCheckError::CellOverflowsPage {
                 cell_idx,
-                page_id: page.get().id,
+                page_id: page_idx,
                 cell_start,
                 cell_end: cell_start + cell_length,
                 content_area: contents.cell_content_area() as usize,
                 usable_space: usable_space as usize,
             });
+            continue;
         }
         coverage_checker.add_cell(cell_start, cell_start + cell_length);
         let cell = contents.cell_get(cell_idx, usable_space as usize)?;
+        let mut overflow = None;
         match cell {
             BTreeCell::TableInteriorCell(table_interior_cell) => {
-                state.page_stack.push(IntegrityCheckPageEntry {
-                    page_idx: table_interior_cell.left_child_page as usize,
-                    level: level + 1,
-                    max_intkey: table_interior_cell.rowid,
-                });
+                state.push_page(
+                    table_interior_cell.left_child_page as usize,
+                    IntegrityCheckPageKind::BTree {
+                        level: level + 1,
+                        max_intkey: table_interior_cell.rowid,
+                    },
+                    errors,
+                );
                 let rowid = table_interior_cell.rowid;
                 if rowid > max_intkey || rowid > next_rowid {
                     errors.push(IntegrityCheckError::CellRowidOutOfRange {
-                        page_id: page.get().id,
+                        page_id: page_idx,
                         cell_idx,
                         rowid,
                         max_intkey,
@@ -5283,22 +5519,11 @@ pub fn integrity_check(
                 next_rowid = rowid;
             }
             BTreeCell::TableLeafCell(table_leaf_cell) => {
-                // check depth of leaf pages are equal
-                if let Some(expected_leaf_level) = state.first_leaf_level {
-                    if expected_leaf_level != level {
-                        errors.push(IntegrityCheckError::LeafDepthMismatch {
-                            page_id: page.get().id,
-                            this_page_depth: level,
-                            other_page_depth: expected_leaf_level,
-                        });
-                    }
-                } else {
-                    state.first_leaf_level = Some(level);
-                }
+                check_leaf_level(state, errors, page_idx, level);
                 let rowid = table_leaf_cell.rowid;
                 if rowid > max_intkey || rowid > next_rowid {
                     errors.push(IntegrityCheckError::CellRowidOutOfRange {
-                        page_id: page.get().id,
+                        page_id: page_idx,
                         cell_idx,
                         rowid,
                         max_intkey,
@@ -5306,29 +5531,49 @@ pub fn integrity_check(
                     });
                 }
                 next_rowid = rowid;
+                state.entries += 1;
+                overflow = table_leaf_cell.first_overflow_page.map(|first_page| {
+                    let local = table_leaf_cell.payload.len() as u64;
+                    (first_page, table_leaf_cell.payload_size - local)
+                });
             }
             BTreeCell::IndexInteriorCell(index_interior_cell) => {
-                state.page_stack.push(IntegrityCheckPageEntry {
-                    page_idx: index_interior_cell.left_child_page as usize,
-                    level: level + 1,
-                    max_intkey, // we don't care about intkey in non-table pages
+                state.push_page(
+                    index_interior_cell.left_child_page as usize,
+                    IntegrityCheckPageKind::BTree {
+                        level: level + 1,
+                        max_intkey, // we don't care about intkey in non-table pages
+                    },
+                    errors,
+                );
+                // The keys of an index are on its interior pages too
+                state.entries += 1;
+                overflow = index_interior_cell.first_overflow_page.map(|first_page| {
+                    let local = index_interior_cell.payload.len() as u64;
+                    (first_page, index_interior_cell.payload_size - local)
                 });
             }
-            BTreeCell::IndexLeafCell(_) => {
-                // check depth of leaf pages are equal
-                if let Some(expected_leaf_level) = state.first_leaf_level {
-                    if expected_leaf_level != level {
-                        errors.push(IntegrityCheckError::LeafDepthMismatch {
-                            page_id: page.get().id,
-                            this_page_depth: level,
-                            other_page_depth: expected_leaf_level,
-                        });
-                    }
-                } else {
-                    state.first_leaf_level = Some(level);
-                }
+            BTreeCell::IndexLeafCell(index_leaf_cell) => {
+                check_leaf_level(state, errors, page_idx, level);
+                state.entries += 1;
+                overflow = index_leaf_cell.first_overflow_page.map(|first_page| {
+                    let local = index_leaf_cell.payload.len() as u64;
+                    (first_page, index_leaf_cell.payload_size - local)
+                });
             }
         }
+        if let Some((first_page, overflow_size)) = overflow {
+            let expected = overflow_size.div_ceil(usable_space as u64 - 4) as usize;
+            state.push_page(
+                first_page as usize,
+                IntegrityCheckPageKind::Overflow {
+                    first_page: first_page as usize,
+                    seen: 0,
+                    expected,
+                },
+                errors,
+            );
+        }
     }
 
     // Now we add free blocks to the coverage checker
@@ -5336,18 +5581,27 @@ pub fn integrity_check(
     if first_freeblock > 0 {
         let mut pc = first_freeblock;
         while pc > 0 {
-            let next = contents.read_u16_no_offset(pc as usize);
-            let size = contents.read_u16_no_offset(pc as usize + 2) as usize;
             // check it doesn't go out of range
             if pc > usable_space - 4 {
                 errors.push(IntegrityCheckError::FreeBlockOutOfRange {
-                    page_id: page.get().id,
+                    page_id: page_idx,
                     start: pc as usize,
-                    end: pc as usize + size,
+                    end: pc as usize + 4,
                 });
                 break;
             }
+            let next = contents.read_u16_no_offset(pc as usize);
+            let size = contents.read_u16_no_offset(pc as usize + 2) as usize;
             coverage_checker.add_free_block(pc as usize, pc as usize + size);
+            // Freeblocks are in increasing order, which also keeps a cycle from looping forever
+            if next != 0 && next <= pc {
+                errors.push(IntegrityCheckError::FreeBlockOutOfRange {
+                    page_id: page_idx,
+                    start: next as usize,
+                    end: pc as usize + size,
+                });
+                break;
+            }
             pc = next;
         }
     }
@@ -5360,7 +5614,27 @@ pub fn integrity_check(
         contents.num_frag_free_bytes() as usize,
     );
 
-    Ok(IOResult::Done(()))
+    Ok(())
+}
+
+/// Checks that the leaf pages of a b-tree are all at the same depth.
+fn check_leaf_level(
+    state: &mut IntegrityCheckState,
+    errors: &mut Vec<IntegrityCheckError>,
+    page_idx: usize,
+    level: usize,
+) {
+    match state.first_leaf_level {
+        Some(expected_leaf_level) if expected_leaf_level != level => {
+            errors.push(IntegrityCheckError::LeafDepthMismatch {
+                page_id: page_idx,
+                this_page_depth: level,
+                other_page_depth: expected_leaf_level,
+            });
+        }
+        Some(_) => {}
+        None => state.first_leaf_level = Some(level),
+    }
 }
 
 pub fn btree_read_page(pager: &Rc<Pager>, page_idx: usize) -> Result<BTreePage> {
@@ -6405,9 +6679,18 @@ fn fill_cell_payload(
     record: &ImmutableRecord,
     usable_space: u16,
     pager: Rc<Pager>,
-) {
+) -> Result<()> {
     // TODO: make record raw from start, having to serialize is not good
-    let record_buf = record.get_payload().to_vec();
+    let transcoded;
+    let record_buf = match pager.text_encoding()? {
+        TextEncoding::Utf8 => record.get_payload(),
+        encoding => {
+            transcoded = transcode_record(record.get_payload(), TextEncoding::Utf8, encoding)?;
+            &transcoded
+        }
+    };
+    // The zeros at the end of the record aren't stored in it, they're written as they're reached.
+    let payload_size = record_buf.len() + record.zero_tail();
 
     let page_type = page_contents.page_type();
     // fill in header
@@ -6419,28 +6702,29 @@ fn fill_cell_payload(
     }
     if matches!(page_type, PageType::TableLeaf) {
         let int_key = int_key.unwrap();
-        write_varint_to_vec(record_buf.len() as u64, cell_payload);
+        write_varint_to_vec(payload_size as u64, cell_payload);
         write_varint_to_vec(int_key as u64, cell_payload);
     } else {
-        write_varint_to_vec(record_buf.len() as u64, cell_payload);
+        write_varint_to_vec(payload_size as u64, cell_payload);
     }
 
     let payload_overflow_threshold_max = payload_overflow_threshold_max(page_type, usable_space);
     tracing::debug!(
         "fill_cell_payload(record_size={}, payload_overflow_threshold_max={})",
-        record_buf.len(),
+        payload_size,
         payload_overflow_threshold_max
     );
-    if record_buf.len() <= payload_overflow_threshold_max {
+    if payload_size <= payload_overflow_threshold_max {
         // enough allowed space to fit inside a btree page
-        cell_payload.extend_from_slice(record_buf.as_slice());
-        return;
+        cell_payload.extend_from_slice(record_buf);
+        cell_payload.resize(cell_payload.len() + record.zero_tail(), 0);
+        return Ok(());
     }
 
     let payload_overflow_threshold_min = payload_overflow_threshold_min(page_type, usable_space);
     // see e.g. https://github.com/sqlite/sqlite/blob/9591d3fe93936533c8c3b0dc4d025ac999539e11/src/dbstat.c#L371
     let mut space_left = payload_overflow_threshold_min
-        + (record_buf.len() - payload_overflow_threshold_min) % (usable_space as usize - 4);
+        + (payload_size - payload_overflow_threshold_min) % (usable_space as usize - 4);
 
     if space_left > payload_overflow_threshold_max {
         space_left = payload_overflow_threshold_min;
@@ -6448,27 +6732,33 @@ fn fill_cell_payload(
 
     // cell_size must be equal to first value of space_left as this will be the bytes copied to non-overflow page.
     let cell_size = space_left + cell_payload.len() + 4; // 4 is the number of bytes of pointer to first overflow page
-    let mut to_copy_buffer = record_buf.as_slice();
+    let mut written = 0;
 
     let prev_size = cell_payload.len();
     cell_payload.resize(prev_size + space_left + 4, 0);
     let mut pointer = unsafe { cell_payload.as_mut_ptr().add(prev_size) };
     let mut pointer_to_next = unsafe { cell_payload.as_mut_ptr().add(prev_size + space_left) };
+    let mut prev_overflow_page = None;
 
     loop {
-        let to_copy = space_left.min(to_copy_buffer.len());
-        unsafe { std::ptr::copy(to_copy_buffer.as_ptr(), pointer, to_copy) };
+        let to_copy = space_left.min(payload_size - written);
+        let stored = to_copy.min(record_buf.len().saturating_sub(written));
+        if stored > 0 {
+            unsafe { std::ptr::copy(record_buf[written..].as_ptr(), pointer, stored) };
+        }
+        unsafe { std::ptr::write_bytes(pointer.add(stored), 0, to_copy - stored) };
 
-        let left = to_copy_buffer.len() - to_copy;
-        if left == 0 {
+        written += to_copy;
+        if written == payload_size {
             break;
         }
 
         // we still have bytes to add, we will need to allocate new overflow page
         // FIXME: handle page cache is full
-        let overflow_page = pager.allocate_overflow_page();
+        let overflow_page = pager.allocate_overflow_page(prev_overflow_page);
         {
             let id = overflow_page.get().id as u32;
+            prev_overflow_page = Some(id);
             let contents = overflow_page.get().contents.as_mut().unwrap();
 
             // TODO: take into account offset here?
@@ -6481,11 +6771,10 @@ fn fill_cell_payload(
             pointer_to_next = buf.as_mut_ptr();
             space_left = usable_space as usize - 4;
         }
-
-        to_copy_buffer = &to_copy_buffer[to_copy..];
     }
 
     assert_eq!(cell_size, cell_payload.len());
+    Ok(())
 }
 
 /// Returns the maximum payload size (X) that can be stored directly on a b-tree page without spilling to overflow pages.
@@ -6658,7 +6947,8 @@ mod tests {
             &record,
             4096,
             conn.pager.borrow().clone(),
-        );
+        )
+        .unwrap();
         insert_into_cell(page, &payload, pos, 4096).unwrap();
         payload
     }
@@ -7166,13 +7456,16 @@ mod tests {
                         collation: None,
                         pos_in_table: i,
                         default: None,
+                        expr: None,
                     })
                     .collect(),
                 table_name: "test".to_string(),
                 root_page: index_root_page,
                 unique: false,
                 ephemeral: false,
+                conflict_clause: None,
                 has_rowid: false,
+                where_clause: None,
             };
             let num_columns = index_def.columns.len();
             let mut cursor = BTreeCursor::new_index(
@@ -7814,7 +8107,8 @@ mod tests {
                         &record,
                         4096,
                         conn.pager.borrow().clone(),
-                    );
+                    )
+                    .unwrap();
                     if (free as usize) < payload.len() + 2 {
                         // do not try to insert overflow pages because they require balancing
                         continue;
@@ -7888,7 +8182,8 @@ mod tests {
                             &record,
                             4096,
                             conn.pager.borrow().clone(),
-                        );
+                        )
+                        .unwrap();
                         if (free as usize) < payload.len() - 2 {
                             // do not try to insert overflow pages because they require balancing
                             continue;
@@ -8253,7 +8548,8 @@ mod tests {
             &record,
             4096,
             conn.pager.borrow().clone(),
-        );
+        )
+        .unwrap();
         let page = page.get();
         insert(0, page.get_contents());
         defragment(page.get_contents());
@@ -8331,7 +8627,8 @@ mod tests {
             &record,
             4096,
             conn.pager.borrow().clone(),
-        );
+        )
+        .unwrap();
         insert_into_cell(page.get().get_contents(), &payload, 0, 4096).unwrap();
         let free = compute_free_space(page.get().get_contents(), usable_space);
         let total_size = payload.len() + 2;
@@ -8505,10 +8802,6 @@ mod tests {
         )
         .unwrap();
 
-        cursor
-            .stack
-            .set_cell_index(cursor.stack.current_cell_index() + 1);
-
         let mut read_buffer = Vec::new();
         run_until_done(
             || {
@@ -8582,10 +8875,6 @@ mod tests {
         )
         .unwrap();
 
-        cursor
-            .stack
-            .set_cell_index(cursor.stack.current_cell_index() + 1);
-
         let offset_to_hello_world = 4 + (large_blob.len() - 11) as u32; // this offset depends on the records type.
         let mut read_buffer = Vec::new();
         run_until_done(
@@ -8739,7 +9028,8 @@ mod tests {
             &record,
             pager.usable_space() as u16,
             pager.clone(),
-        );
+        )
+        .unwrap();
         insert_into_cell(
             contents,
             &payload,
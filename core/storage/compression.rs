@@ -0,0 +1,211 @@
+//! Compression of the pages of the database file, set with `PRAGMA compression` before the
+//! database is created.
+//!
+//! Pages stay in their fixed-size slots, so that the file can be read and written page by page
+//! as usual. A compressed page leaves the rest of its slot zeroed, and once it is written, the
+//! file system blocks of the zeros are deallocated by punching a hole in the file, on Linux file
+//! systems that support it. So the file keeps its size, but takes less space on disk, as long as
+//! pages are larger than file system blocks, e.g. with `PRAGMA page_size = 32768`.
+//!
+//! A compressed database reserves [RESERVED_SPACE] bytes at the end of every page for the header
+//! of its slot, the big-endian size of the compressed page, so that a page that doesn't compress
+//! can be stored as is behind a size of 0. The mode is recorded in the database header, in the
+//! last 4 of the 20 bytes SQLite reserves for expansion, at offset 88, which are zero in SQLite
+//! databases. SQLite can't read a compressed database.
+//!
+//! Page 1 is never compressed, since its header has to be read before anything else, and neither
+//! are WAL frames: pages are compressed when they are checkpointed into the database file.
+
+use crate::{LimboError, Result};
+use strum::{Display, EnumString};
+
+/// The bytes at the end of every page of a compressed database, for the header of its slot.
+pub const RESERVED_SPACE: u8 = 4;
+const SLOT_HEADER_SIZE: usize = RESERVED_SPACE as usize;
+/// The size of the blocks of file systems, the unit in which holes are punched.
+const FILE_SYSTEM_BLOCK_SIZE: usize = 4096;
+#[cfg(not(target_family = "wasm"))]
+const ZSTD_LEVEL: i32 = 3;
+
+/// The algorithm the pages of the database file are compressed with.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum CompressionMode {
+    #[default]
+    None,
+    Lz4,
+    Zstd,
+}
+
+impl CompressionMode {
+    /// The mode recorded in the database header as `id`.
+    pub fn from_header_id(id: u32) -> Result<Self> {
+        match id {
+            0 => Ok(CompressionMode::None),
+            1 => Ok(CompressionMode::Lz4),
+            2 => Ok(CompressionMode::Zstd),
+            _ => Err(LimboError::Corrupt(format!(
+                "unknown page compression {id} in the database header"
+            ))),
+        }
+    }
+
+    pub fn header_id(&self) -> u32 {
+        match self {
+            CompressionMode::None => 0,
+            CompressionMode::Lz4 => 1,
+            CompressionMode::Zstd => 2,
+        }
+    }
+
+    /// Writes `page` to `slot`, both a page in size, compressed if that makes it smaller.
+    /// Returns the number of bytes of the slot that are used, the rest being zeros.
+    pub fn compress_page(&self, page: &[u8], slot: &mut [u8]) -> Result<usize> {
+        assert_eq!(page.len(), slot.len());
+        let usable = &page[..page.len() - SLOT_HEADER_SIZE];
+        slot.fill(0);
+        let compressed = match self {
+            CompressionMode::None => None,
+            // lz4 only compresses into a buffer big enough for the worst case, larger than a page.
+            CompressionMode::Lz4 => {
+                let compressed = lz4_flex::block::compress(usable);
+                slot.get_mut(SLOT_HEADER_SIZE..SLOT_HEADER_SIZE + compressed.len())
+                    .map(|dst| {
+                        dst.copy_from_slice(&compressed);
+                        compressed.len()
+                    })
+            }
+            #[cfg(not(target_family = "wasm"))]
+            CompressionMode::Zstd => {
+                zstd::bulk::compress_to_buffer(usable, &mut slot[SLOT_HEADER_SIZE..], ZSTD_LEVEL)
+                    .ok()
+            }
+            #[cfg(target_family = "wasm")]
+            CompressionMode::Zstd => return Err(zstd_unavailable()),
+        };
+        match compressed {
+            Some(size) if size < usable.len() => {
+                slot[..SLOT_HEADER_SIZE].copy_from_slice(&(size as u32).to_be_bytes());
+                Ok(SLOT_HEADER_SIZE + size)
+            }
+            _ => {
+                slot[..SLOT_HEADER_SIZE].fill(0);
+                slot[SLOT_HEADER_SIZE..].copy_from_slice(usable);
+                Ok(slot.len())
+            }
+        }
+    }
+
+    /// Reads the page with index `page_idx` from `slot` into `page`, both a page in size.
+    pub fn decompress_page(&self, slot: &[u8], page: &mut [u8], page_idx: usize) -> Result<()> {
+        assert_eq!(page.len(), slot.len());
+        let corrupt = || LimboError::Corrupt(format!("page {page_idx} failed to decompress"));
+        let size = u32::from_be_bytes(slot[..SLOT_HEADER_SIZE].try_into().unwrap()) as usize;
+        let usable_len = page.len() - SLOT_HEADER_SIZE;
+        let (usable, reserved) = page.split_at_mut(usable_len);
+        reserved.fill(0);
+        if size == 0 {
+            usable.copy_from_slice(&slot[SLOT_HEADER_SIZE..]);
+            return Ok(());
+        }
+        let compressed = slot
+            .get(SLOT_HEADER_SIZE..SLOT_HEADER_SIZE + size)
+            .ok_or_else(corrupt)?;
+        let decompressed = match self {
+            CompressionMode::None => return Err(corrupt()),
+            CompressionMode::Lz4 => lz4_flex::block::decompress_into(compressed, usable).ok(),
+            #[cfg(not(target_family = "wasm"))]
+            CompressionMode::Zstd => zstd::bulk::decompress_to_buffer(compressed, usable).ok(),
+            #[cfg(target_family = "wasm")]
+            CompressionMode::Zstd => return Err(zstd_unavailable()),
+        };
+        match decompressed {
+            Some(len) if len == usable_len => Ok(()),
+            _ => Err(corrupt()),
+        }
+    }
+}
+
+/// The zeros at the end of a slot of `page_size` bytes of which `used` are used, that whole file
+/// system blocks cover, as their offset in the slot and their length. None if there are none.
+pub fn slot_hole(page_size: usize, used: usize) -> Option<(usize, usize)> {
+    let start = used.next_multiple_of(FILE_SYSTEM_BLOCK_SIZE);
+    (start < page_size).then(|| (start, page_size - start))
+}
+
+#[cfg(target_family = "wasm")]
+fn zstd_unavailable() -> LimboError {
+    LimboError::InvalidArgument("zstd compression isn't available on this platform".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text_page() -> Vec<u8> {
+        let mut page = b"{\"name\": \"limbo\", \"tags\": [\"sqlite\", \"rust\"]}"
+            .iter()
+            .copied()
+            .cycle()
+            .take(4096)
+            .collect::<Vec<_>>();
+        page[4096 - SLOT_HEADER_SIZE..].fill(0);
+        page
+    }
+
+    #[test]
+    fn test_roundtrip() {
+        for mode in [CompressionMode::Lz4, CompressionMode::Zstd] {
+            let page = text_page();
+            let mut slot = vec![0; page.len()];
+            let used = mode.compress_page(&page, &mut slot).unwrap();
+            assert_ne!(&slot[..SLOT_HEADER_SIZE], &[0; SLOT_HEADER_SIZE]);
+            assert!(used < page.len() / 2);
+            assert!(slot[used..].iter().all(|b| *b == 0));
+
+            let mut read = vec![0xff; page.len()];
+            mode.decompress_page(&slot, &mut read, 2).unwrap();
+            assert_eq!(read, page);
+        }
+    }
+
+    #[test]
+    fn test_incompressible_page() {
+        let mut page = vec![0; 4096];
+        getrandom::getrandom(&mut page).unwrap();
+        page[4096 - SLOT_HEADER_SIZE..].fill(0);
+        let mut slot = vec![0; page.len()];
+        CompressionMode::Lz4
+            .compress_page(&page, &mut slot)
+            .unwrap();
+        assert_eq!(&slot[..SLOT_HEADER_SIZE], &[0; SLOT_HEADER_SIZE]);
+
+        let mut read = vec![0; page.len()];
+        CompressionMode::Lz4
+            .decompress_page(&slot, &mut read, 2)
+            .unwrap();
+        assert_eq!(read, page);
+    }
+
+    #[test]
+    fn test_slot_hole() {
+        assert_eq!(slot_hole(65536, 100), Some((4096, 61440)));
+        assert_eq!(slot_hole(65536, 4096), Some((4096, 61440)));
+        assert_eq!(slot_hole(65536, 65536), None);
+        assert_eq!(slot_hole(4096, 100), None);
+    }
+
+    #[test]
+    fn test_corrupt_page() {
+        let page = text_page();
+        let mut slot = vec![0; page.len()];
+        CompressionMode::Lz4
+            .compress_page(&page, &mut slot)
+            .unwrap();
+        slot[SLOT_HEADER_SIZE..SLOT_HEADER_SIZE + 8].fill(0xff);
+        let mut read = vec![0; page.len()];
+        assert!(CompressionMode::Lz4
+            .decompress_page(&slot, &mut read, 2)
+            .is_err());
+    }
+}
@@ -19,6 +19,12 @@ pub trait DatabaseStorage: Send + Sync {
     ) -> Result<()>;
     fn sync(&self, c: Completion) -> Result<()>;
     fn size(&self) -> Result<u64>;
+    fn truncate(&self, len: usize, c: Completion) -> Result<Arc<Completion>>;
+    /// Deallocates the storage of `len` bytes at `pos`, which must already be zeros, where
+    /// supported.
+    fn punch_hole(&self, _pos: usize, _len: usize) -> Result<()> {
+        Ok(())
+    }
 }
 
 #[cfg(feature = "fs")]
@@ -73,6 +79,16 @@ impl DatabaseStorage for DatabaseFile {
     fn size(&self) -> Result<u64> {
         self.file.size()
     }
+
+    #[instrument(skip_all, level = Level::DEBUG)]
+    fn truncate(&self, len: usize, c: Completion) -> Result<Arc<Completion>> {
+        self.file.truncate(len, c.into())
+    }
+
+    #[instrument(skip_all, level = Level::DEBUG)]
+    fn punch_hole(&self, pos: usize, len: usize) -> Result<()> {
+        self.file.punch_hole(pos, len)
+    }
 }
 
 #[cfg(feature = "fs")]
@@ -132,6 +148,16 @@ impl DatabaseStorage for FileMemoryStorage {
     fn size(&self) -> Result<u64> {
         self.file.size()
     }
+
+    #[instrument(skip_all, level = Level::DEBUG)]
+    fn truncate(&self, len: usize, c: Completion) -> Result<Arc<Completion>> {
+        self.file.truncate(len, c.into())
+    }
+
+    #[instrument(skip_all, level = Level::DEBUG)]
+    fn punch_hole(&self, pos: usize, len: usize) -> Result<()> {
+        self.file.punch_hole(pos, len)
+    }
 }
 
 impl FileMemoryStorage {
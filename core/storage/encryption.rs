@@ -0,0 +1,316 @@
+//! Encryption of the pages of the database file and of the WAL, keyed with `PRAGMA key`.
+//!
+//! Like in SQLCipher, every page is encrypted on its own with an authenticated cipher, and its
+//! nonce and tag are kept in the reserved space at the end of the page, so the b-trees only see
+//! pages with a smaller usable size. The first 100 bytes of page 1, the database header, stay in
+//! plaintext so that the page size and the reserved space can be read before the key is known,
+//! and are authenticated along with the rest of the page. The salt the key of a passphrase is
+//! derived with is stored in the header, in the bytes SQLite reserves for expansion.
+//!
+//! WAL frames hold pages encrypted the same way. Their checksums are computed over the encrypted
+//! pages, so that the WAL can be recovered without the key.
+
+use crate::{LimboError, Result};
+use aes_gcm::aead::{self, AeadInPlace, KeyInit};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::XChaCha20Poly1305;
+use strum::{Display, EnumString};
+
+use super::sqlite3_ondisk::{DATABASE_HEADER_PAGE_ID, DATABASE_HEADER_SIZE};
+
+/// The size in bytes of the keys of all the ciphers.
+pub const KEY_SIZE: usize = 32;
+/// The size in bytes of the salt of the key derivation.
+pub const SALT_SIZE: usize = 16;
+/// The offset of the salt in the database header, at the start of the bytes reserved for
+/// expansion.
+pub const SALT_OFFSET: usize = 72;
+const HEADER_OFFSET_RESERVED_SPACE: usize = 20;
+const TAG_SIZE: usize = 16;
+/// The PBKDF2-HMAC-SHA512 iterations that derive the key of a passphrase, as in SQLCipher 4.
+const KDF_ITERATIONS: u32 = 256_000;
+
+/// The cipher pages are encrypted with, as set by `PRAGMA cipher` before the key of a new
+/// database is given. The cipher of an existing database is told by the size of the reserved
+/// space of its pages.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum CipherMode {
+    #[default]
+    Aes256Gcm,
+    XChaCha20Poly1305,
+}
+
+impl CipherMode {
+    fn nonce_size(&self) -> usize {
+        match self {
+            CipherMode::Aes256Gcm => 12,
+            CipherMode::XChaCha20Poly1305 => 24,
+        }
+    }
+
+    /// The bytes at the end of every page that hold its nonce and tag.
+    pub fn reserved_space(&self) -> u8 {
+        (self.nonce_size() + TAG_SIZE) as u8
+    }
+
+    /// The cipher of a database whose header is `header`, if it's encrypted.
+    pub fn of_header(header: &[u8]) -> Option<Self> {
+        let salt = &header[SALT_OFFSET..SALT_OFFSET + SALT_SIZE];
+        if salt.iter().all(|b| *b == 0) {
+            return None;
+        }
+        [CipherMode::Aes256Gcm, CipherMode::XChaCha20Poly1305]
+            .into_iter()
+            .find(|mode| mode.reserved_space() == header[HEADER_OFFSET_RESERVED_SPACE])
+    }
+}
+
+enum Cipher {
+    Aes256Gcm(Box<Aes256Gcm>),
+    XChaCha20Poly1305(Box<XChaCha20Poly1305>),
+}
+
+/// Encrypts the pages written by a connection and decrypts the pages it reads.
+pub struct EncryptionContext {
+    mode: CipherMode,
+    cipher: Cipher,
+}
+
+impl std::fmt::Debug for EncryptionContext {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EncryptionContext")
+            .field("mode", &self.mode)
+            .finish_non_exhaustive()
+    }
+}
+
+impl EncryptionContext {
+    pub fn new(mode: CipherMode, key: &[u8; KEY_SIZE]) -> Self {
+        let cipher = match mode {
+            CipherMode::Aes256Gcm => Cipher::Aes256Gcm(Box::new(Aes256Gcm::new(key.into()))),
+            CipherMode::XChaCha20Poly1305 => {
+                Cipher::XChaCha20Poly1305(Box::new(XChaCha20Poly1305::new(key.into())))
+            }
+        };
+        Self { mode, cipher }
+    }
+
+    pub fn mode(&self) -> CipherMode {
+        self.mode
+    }
+
+    /// Encrypts page `page_idx` in place, with a new random nonce.
+    pub fn encrypt_page(&self, page: &mut [u8], page_idx: usize) -> Result<()> {
+        let (start, end) = self.encrypted_range(page, page_idx)?;
+        let aad = associated_data(page, page_idx, start);
+        let nonce_size = self.mode.nonce_size();
+        let (body, reserved) = page.split_at_mut(end);
+        let (nonce, tag) = reserved.split_at_mut(nonce_size);
+        getrandom::getrandom(nonce)
+            .map_err(|e| LimboError::InternalError(format!("failed to generate a nonce: {e}")))?;
+        match &self.cipher {
+            Cipher::Aes256Gcm(cipher) => seal(&**cipher, nonce, &aad, &mut body[start..], tag),
+            Cipher::XChaCha20Poly1305(cipher) => {
+                seal(&**cipher, nonce, &aad, &mut body[start..], tag)
+            }
+        }
+    }
+
+    /// Decrypts page `page_idx` in place. Fails if the page wasn't encrypted with this key, or
+    /// was changed since.
+    pub fn decrypt_page(&self, page: &mut [u8], page_idx: usize) -> Result<()> {
+        let (start, end) = self.encrypted_range(page, page_idx)?;
+        let aad = associated_data(page, page_idx, start);
+        let nonce_size = self.mode.nonce_size();
+        let (body, reserved) = page.split_at_mut(end);
+        let (nonce, tag) = reserved.split_at(nonce_size);
+        let decrypted = match &self.cipher {
+            Cipher::Aes256Gcm(cipher) => open(&**cipher, nonce, &aad, &mut body[start..], tag),
+            Cipher::XChaCha20Poly1305(cipher) => {
+                open(&**cipher, nonce, &aad, &mut body[start..], tag)
+            }
+        };
+        decrypted.map_err(|_| LimboError::Corrupt(format!("page {page_idx} failed to decrypt")))
+    }
+
+    fn encrypted_range(&self, page: &[u8], page_idx: usize) -> Result<(usize, usize)> {
+        let start = if page_idx == DATABASE_HEADER_PAGE_ID {
+            DATABASE_HEADER_SIZE
+        } else {
+            0
+        };
+        let end = page
+            .len()
+            .checked_sub(self.mode.reserved_space() as usize)
+            .filter(|end| *end > start)
+            .ok_or_else(|| {
+                LimboError::InternalError(format!("page {page_idx} is too small to encrypt"))
+            })?;
+        Ok((start, end))
+    }
+}
+
+/// The page number, and the database header for page 1, which are authenticated but not
+/// encrypted, so that a page can't be swapped with another one.
+fn associated_data(page: &[u8], page_idx: usize, header_size: usize) -> Vec<u8> {
+    let mut aad = Vec::with_capacity(4 + header_size);
+    aad.extend_from_slice(&(page_idx as u32).to_be_bytes());
+    aad.extend_from_slice(&page[..header_size]);
+    aad
+}
+
+fn seal<C: AeadInPlace>(
+    cipher: &C,
+    nonce: &[u8],
+    aad: &[u8],
+    body: &mut [u8],
+    tag: &mut [u8],
+) -> Result<()> {
+    let computed = cipher
+        .encrypt_in_place_detached(aead::Nonce::<C>::from_slice(nonce), aad, body)
+        .map_err(|_| LimboError::InternalError("failed to encrypt page".to_string()))?;
+    tag[..TAG_SIZE].copy_from_slice(&computed);
+    Ok(())
+}
+
+fn open<C: AeadInPlace>(
+    cipher: &C,
+    nonce: &[u8],
+    aad: &[u8],
+    body: &mut [u8],
+    tag: &[u8],
+) -> std::result::Result<(), aead::Error> {
+    cipher.decrypt_in_place_detached(
+        aead::Nonce::<C>::from_slice(nonce),
+        aad,
+        body,
+        aead::Tag::<C>::from_slice(&tag[..TAG_SIZE]),
+    )
+}
+
+/// A key given to `PRAGMA key` or `PRAGMA hexkey`.
+#[derive(Clone)]
+pub enum EncryptionKey {
+    /// A passphrase the key is derived from, with the salt of the database.
+    Passphrase(String),
+    Raw([u8; KEY_SIZE]),
+}
+
+impl EncryptionKey {
+    /// The key of `PRAGMA key`, which is a passphrase unless it's a raw key written as `x'...'`
+    /// like in SQLCipher.
+    pub fn parse(key: &str) -> Result<Self> {
+        let raw_key = key
+            .strip_prefix(['x', 'X'])
+            .and_then(|key| key.strip_prefix('\''))
+            .and_then(|key| key.strip_suffix('\''));
+        match raw_key {
+            Some(hex) => Self::parse_hex(hex),
+            None => Ok(EncryptionKey::Passphrase(key.to_string())),
+        }
+    }
+
+    /// The raw key of `PRAGMA hexkey`.
+    pub fn parse_hex(hex: &str) -> Result<Self> {
+        let mut key = [0; KEY_SIZE];
+        hex::decode_to_slice(hex, &mut key).map_err(|_| {
+            LimboError::InvalidArgument(format!("a raw key must be {} hex digits", KEY_SIZE * 2))
+        })?;
+        Ok(EncryptionKey::Raw(key))
+    }
+
+    pub fn derive(&self, salt: &[u8]) -> [u8; KEY_SIZE] {
+        match self {
+            EncryptionKey::Passphrase(passphrase) => {
+                let mut key = [0; KEY_SIZE];
+                pbkdf2::pbkdf2_hmac::<sha2::Sha512>(
+                    passphrase.as_bytes(),
+                    salt,
+                    KDF_ITERATIONS,
+                    &mut key,
+                );
+                key
+            }
+            EncryptionKey::Raw(key) => *key,
+        }
+    }
+}
+
+/// A new random salt for the key derivation, which is never all zeros since that marks a
+/// database that isn't encrypted.
+pub fn new_salt() -> Result<[u8; SALT_SIZE]> {
+    let mut salt = [0; SALT_SIZE];
+    while salt.iter().all(|b| *b == 0) {
+        getrandom::getrandom(&mut salt)
+            .map_err(|e| LimboError::InternalError(format!("failed to generate a salt: {e}")))?;
+    }
+    Ok(salt)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; KEY_SIZE] = [7; KEY_SIZE];
+
+    fn page(size: usize, reserved: u8) -> Vec<u8> {
+        let mut page: Vec<u8> = (0..size).map(|i| (i % 251) as u8).collect();
+        page[HEADER_OFFSET_RESERVED_SPACE] = reserved;
+        page[SALT_OFFSET..SALT_OFFSET + SALT_SIZE].fill(1);
+        page
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_roundtrip() {
+        for mode in [CipherMode::Aes256Gcm, CipherMode::XChaCha20Poly1305] {
+            let ctx = EncryptionContext::new(mode, &KEY);
+            for page_idx in [1, 2] {
+                let plain = page(4096, mode.reserved_space());
+                let mut encrypted = plain.clone();
+                ctx.encrypt_page(&mut encrypted, page_idx).unwrap();
+                let end = 4096 - mode.reserved_space() as usize;
+                assert_ne!(
+                    encrypted[DATABASE_HEADER_SIZE..end],
+                    plain[DATABASE_HEADER_SIZE..end]
+                );
+                if page_idx == DATABASE_HEADER_PAGE_ID {
+                    assert_eq!(
+                        encrypted[..DATABASE_HEADER_SIZE],
+                        plain[..DATABASE_HEADER_SIZE]
+                    );
+                    assert_eq!(CipherMode::of_header(&encrypted), Some(mode));
+                }
+                ctx.decrypt_page(&mut encrypted, page_idx).unwrap();
+                assert_eq!(encrypted[..end], plain[..end]);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decrypt_fails_with_wrong_key_or_page() {
+        let mode = CipherMode::Aes256Gcm;
+        let ctx = EncryptionContext::new(mode, &KEY);
+        let mut encrypted = page(4096, mode.reserved_space());
+        ctx.encrypt_page(&mut encrypted, 2).unwrap();
+
+        let other = EncryptionContext::new(mode, &[8; KEY_SIZE]);
+        assert!(other.decrypt_page(&mut encrypted.clone(), 2).is_err());
+        assert!(ctx.decrypt_page(&mut encrypted.clone(), 3).is_err());
+        encrypted[10] ^= 1;
+        assert!(ctx.decrypt_page(&mut encrypted, 2).is_err());
+    }
+
+    #[test]
+    fn test_parse_key() {
+        let raw = format!("x'{}'", "ab".repeat(KEY_SIZE));
+        let key = EncryptionKey::parse(&raw).unwrap();
+        assert_eq!(key.derive(&[1; SALT_SIZE]), [0xab; KEY_SIZE]);
+        assert!(EncryptionKey::parse_hex("abcd").is_err());
+        assert!(matches!(
+            EncryptionKey::parse("secret").unwrap(),
+            EncryptionKey::Passphrase(_)
+        ));
+        assert_eq!(CipherMode::of_header(&[0; DATABASE_HEADER_SIZE]), None);
+    }
+}
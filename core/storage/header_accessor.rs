@@ -31,6 +31,8 @@ const HEADER_OFFSET_USER_VERSION: usize = 60;
 const HEADER_OFFSET_INCREMENTAL_VACUUM_ENABLED: usize = 64;
 const HEADER_OFFSET_APPLICATION_ID: usize = 68;
 //const HEADER_OFFSET_RESERVED_FOR_EXPANSION: usize = 72;
+/// The last 4 of the 20 bytes reserved for expansion, which are zero in SQLite databases.
+const HEADER_OFFSET_COMPRESSION: usize = 88;
 const HEADER_OFFSET_VERSION_VALID_FOR: usize = 92;
 const HEADER_OFFSET_VERSION_NUMBER: usize = 96;
 
@@ -192,6 +194,7 @@ impl_header_field_accessor!(
 );
 impl_header_field_accessor!(application_id, u32, HEADER_OFFSET_APPLICATION_ID);
 //impl_header_field_accessor!(reserved_for_expansion, [u8; 20], HEADER_OFFSET_RESERVED_FOR_EXPANSION);
+impl_header_field_accessor!(compression, u32, HEADER_OFFSET_COMPRESSION);
 impl_header_field_accessor!(version_valid_for, u32, HEADER_OFFSET_VERSION_VALID_FOR);
 impl_header_field_accessor!(version_number, u32, HEADER_OFFSET_VERSION_NUMBER);
 
@@ -12,12 +12,15 @@
 //! for the database, also either local or remote.
 pub(crate) mod btree;
 pub(crate) mod buffer_pool;
+pub(crate) mod compression;
 pub(crate) mod database;
+pub(crate) mod encryption;
 pub(crate) mod header_accessor;
 pub(crate) mod page_cache;
 #[allow(clippy::arc_with_non_send_sync)]
 pub(crate) mod pager;
 pub(crate) mod sqlite3_ondisk;
+pub(crate) mod text_encoding;
 #[allow(clippy::arc_with_non_send_sync)]
 pub(crate) mod wal;
 
@@ -147,6 +147,22 @@ impl DumbLruPageCache {
         Ok(())
     }
 
+    /// Deletes a page even if it is pinned, for pages that are no longer part of the database.
+    pub fn delete_even_if_pinned(&mut self, key: PageCacheKey) -> Result<(), CacheError> {
+        trace!("cache_delete_even_if_pinned(key={:?})", key);
+        if !self.contains_key(&key) {
+            return Ok(());
+        }
+
+        let ptr = *self.map.borrow().get(&key).unwrap();
+        self.detach_even_if_pinned(ptr, true)?;
+        let ptr = self.map.borrow_mut().remove(&key).unwrap();
+        unsafe {
+            let _ = Box::from_raw(ptr.as_ptr());
+        };
+        Ok(())
+    }
+
     fn get_ptr(&mut self, key: &PageCacheKey) -> Option<NonNull<PageCacheEntry>> {
         let m = self.map.borrow_mut();
         let ptr = m.get(key);
@@ -373,6 +389,10 @@ impl DumbLruPageCache {
         self.map.borrow().len()
     }
 
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
     #[cfg(test)]
     fn get_entry_ptr(&self, key: &PageCacheKey) -> Option<NonNull<PageCacheEntry>> {
         self.map.borrow().get(key).copied()
@@ -1,29 +1,44 @@
 use crate::result::LimboResult;
 use crate::storage::btree::BTreePageInner;
 use crate::storage::buffer_pool::BufferPool;
+use crate::storage::compression::{CompressionMode, RESERVED_SPACE as COMPRESSION_RESERVED_SPACE};
 use crate::storage::database::DatabaseStorage;
+use crate::storage::encryption::{
+    new_salt, CipherMode, EncryptionContext, EncryptionKey, SALT_OFFSET, SALT_SIZE,
+};
 use crate::storage::header_accessor;
 use crate::storage::sqlite3_ondisk::{self, DatabaseHeader, PageContent, PageType};
+use crate::storage::text_encoding::TextEncoding;
 use crate::storage::wal::{CheckpointResult, Wal};
 use crate::types::IOResult;
 use crate::util::IOExt as _;
 use crate::{return_if_io, Completion};
 use crate::{turso_assert, Buffer, Connection, LimboError, Result};
 use parking_lot::RwLock;
-use std::cell::{Cell, OnceCell, RefCell, UnsafeCell};
-use std::collections::HashSet;
+use std::cell::{Cell, RefCell, UnsafeCell};
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
 use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::{Arc, Mutex};
+use strum::{Display, EnumString};
 use tracing::{instrument, trace, Level};
 
 use super::btree::{btree_init_page, BTreePage};
 use super::page_cache::{CacheError, CacheResizeResult, DumbLruPageCache, PageCacheKey};
-use super::sqlite3_ondisk::{begin_write_btree_page, DATABASE_HEADER_SIZE};
+use super::sqlite3_ondisk::{
+    begin_write_btree_page, DATABASE_HEADER_PAGE_ID, DATABASE_HEADER_SIZE,
+};
 use super::wal::CheckpointMode;
 
 #[cfg(not(feature = "omit_autovacuum"))]
-use {crate::io::Buffer as IoBuffer, ptrmap::*};
+use {
+    super::btree::offset::BTREE_RIGHTMOST_PTR,
+    super::sqlite3_ondisk::{
+        BTreeCell, IndexInteriorCell, IndexLeafCell, TableInteriorCell, TableLeafCell,
+    },
+    crate::io::Buffer as IoBuffer,
+    ptrmap::*,
+};
 
 pub struct PageInner {
     pub flags: AtomicUsize,
@@ -244,6 +259,40 @@ pub enum AutoVacuumMode {
     Incremental,
 }
 
+/// How the changes of a transaction reach the database file, as set by `PRAGMA journal_mode`.
+///
+/// In WAL mode the committed pages stay in the WAL until a checkpoint copies them to the database
+/// file. The other modes keep the database file up to date: the WAL plays the part of the
+/// rollback journal of the transaction being committed, and it's checkpointed and truncated as
+/// soon as the transaction ends.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, EnumString, Display)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum JournalMode {
+    Delete,
+    Truncate,
+    Persist,
+    /// The journal isn't synced, so a crash while committing can corrupt the database.
+    Memory,
+    Wal,
+    /// Like `Memory`, since the pages of a transaction have to be kept somewhere until it commits.
+    Off,
+}
+
+/// When the pager fsyncs the WAL and the database file, as set by `PRAGMA synchronous`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, EnumString)]
+#[strum(serialize_all = "lowercase", ascii_case_insensitive)]
+pub enum Synchronous {
+    /// Never fsync, leaving it to the operating system to write the files.
+    Off = 0,
+    /// In WAL mode, only fsync when checkpointing, so that a power loss can roll back the last
+    /// transactions but can't corrupt the database.
+    Normal = 1,
+    /// Fsync the WAL when committing every transaction.
+    Full = 2,
+    /// Like `Full`, since the journal is never deleted.
+    Extra = 3,
+}
+
 pub const DB_STATE_UNINITIALIZED: usize = 0;
 pub const DB_STATE_INITIALIZING: usize = 1;
 pub const DB_STATE_INITIALIZED: usize = 2;
@@ -262,6 +311,14 @@ pub struct Pager {
     /// I/O interface for input/output operations.
     pub io: Arc<dyn crate::io::IO>,
     dirty_pages: Rc<RefCell<HashSet<usize>>>,
+    /// The open savepoints of the current write transaction, innermost last.
+    savepoints: RefCell<Vec<PagerSavepoint>>,
+    /// The max frame and checkpoint sequence of the WAL the page cache is up to date with. The
+    /// cache is cleared when a read transaction sees that another connection changed the WAL.
+    wal_snapshot: Cell<Option<(u64, u32)>>,
+    /// The pages read by a `BEGIN CONCURRENT` transaction that didn't take the write lock yet,
+    /// which it does when committing.
+    concurrent_reads: RefCell<Option<HashSet<usize>>>,
 
     commit_info: RefCell<CommitInfo>,
     flush_info: RefCell<FlushInfo>,
@@ -269,6 +326,22 @@ pub struct Pager {
     checkpoint_inflight: Rc<RefCell<usize>>,
     syncing: Rc<RefCell<bool>>,
     auto_vacuum_mode: RefCell<AutoVacuumMode>,
+    journal_mode: Cell<JournalMode>,
+    synchronous: Cell<Synchronous>,
+    /// Encrypts the pages written to the WAL and the database file, and decrypts the pages read
+    /// from them, once the connection gave the key of the database.
+    encryption: RefCell<Option<Arc<EncryptionContext>>>,
+    /// The salt of the key derivation, written to the header when an encrypted database is
+    /// created.
+    encryption_salt: Cell<Option<[u8; SALT_SIZE]>>,
+    /// The compression a new database is created with, set by `PRAGMA compression`.
+    compression_mode: Cell<CompressionMode>,
+    /// The compression of the pages of the database file, cached once read from its header.
+    compression: Cell<Option<CompressionMode>>,
+    /// The text encoding a new database is created with, set by `PRAGMA encoding`.
+    text_encoding_mode: Cell<TextEncoding>,
+    /// The text encoding of the database file, cached once read from its header.
+    text_encoding: Cell<Option<TextEncoding>>,
     /// 0 -> Database is empty,
     /// 1 -> Database is being initialized,
     /// 2 -> Database is initialized and ready for use.
@@ -277,11 +350,50 @@ pub struct Pager {
     init_lock: Arc<Mutex<()>>,
     allocate_page1_state: RefCell<AllocatePage1State>,
     /// Cache page_size and reserved_space at Pager init and reuse for subsequent
-    /// `usable_space` calls. TODO: Invalidate reserved_space when we add the functionality
-    /// to change it.
+    /// `usable_space` calls. The reserved space is set again when page 1 is allocated, since an
+    /// encrypted database reserves space for the nonce and tag of its pages.
     page_size: Cell<Option<u32>>,
-    reserved_space: OnceCell<u8>,
+    reserved_space: Cell<Option<u8>>,
     free_page_state: RefCell<FreePageState>,
+    #[cfg(not(feature = "omit_autovacuum"))]
+    ptrmap_changes: RefCell<PtrmapChanges>,
+    stats: Cell<PagerStats>,
+}
+
+/// The page accesses of a pager since it was opened. A statement reports its own accesses as
+/// the difference between the counters before and after it ran.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct PagerStats {
+    /// The pages found in the page cache.
+    pub cache_hits: u64,
+    /// The pages read from the WAL or the database file because they weren't cached.
+    pub cache_misses: u64,
+    /// The pages appended to the WAL.
+    pub pages_written: u64,
+}
+
+/// The undo log of a savepoint of a write transaction. Since changes stay in the page cache until
+/// commit, a page is copied the first time it is made dirty after the savepoint was opened, or
+/// recorded as `None` if it wasn't dirty then and can be read back from the WAL or the database
+/// file.
+struct PagerSavepoint {
+    pages: HashMap<usize, Option<Vec<u8>>>,
+    /// The pending pointer map entries as they were before they were first changed after the
+    /// savepoint was opened.
+    #[cfg(not(feature = "omit_autovacuum"))]
+    ptrmap: HashMap<u32, Option<PtrmapEntry>>,
+}
+
+/// The pointer map changes of the current write transaction that weren't written to the pointer
+/// map pages yet. Allocating and freeing pages records their entries right away, while the
+/// entries of the pages a b-tree page points to are recorded by scanning it again before the
+/// pointer map is needed, since b-tree pages are changed in too many places to track each pointer.
+#[cfg(not(feature = "omit_autovacuum"))]
+#[derive(Default)]
+struct PtrmapChanges {
+    pending: HashMap<u32, PtrmapEntry>,
+    /// The pages made dirty since the pointers on them were last recorded.
+    stale: HashSet<usize>,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -338,6 +450,9 @@ impl Pager {
             page_cache,
             io,
             dirty_pages: Rc::new(RefCell::new(HashSet::new())),
+            savepoints: RefCell::new(Vec::new()),
+            wal_snapshot: Cell::new(None),
+            concurrent_reads: RefCell::new(None),
             commit_info: RefCell::new(CommitInfo {
                 state: CommitState::Start,
                 in_flight_writes: Rc::new(RefCell::new(0)),
@@ -347,37 +462,267 @@ impl Pager {
             checkpoint_inflight: Rc::new(RefCell::new(0)),
             buffer_pool,
             auto_vacuum_mode: RefCell::new(AutoVacuumMode::None),
+            journal_mode: Cell::new(JournalMode::Wal),
+            synchronous: Cell::new(Synchronous::Full),
+            encryption: RefCell::new(None),
+            encryption_salt: Cell::new(None),
+            compression_mode: Cell::new(CompressionMode::None),
+            compression: Cell::new(None),
+            text_encoding_mode: Cell::new(TextEncoding::Utf8),
+            text_encoding: Cell::new(None),
             db_state,
             init_lock,
             allocate_page1_state,
             page_size: Cell::new(None),
-            reserved_space: OnceCell::new(),
+            reserved_space: Cell::new(None),
             flush_info: RefCell::new(FlushInfo {
                 state: CacheFlushState::Start,
                 in_flight_writes: Rc::new(RefCell::new(0)),
             }),
             free_page_state: RefCell::new(FreePageState::Start),
+            #[cfg(not(feature = "omit_autovacuum"))]
+            ptrmap_changes: RefCell::new(PtrmapChanges::default()),
+            stats: Cell::new(PagerStats::default()),
         })
     }
 
+    /// The page accesses since the pager was opened.
+    pub fn stats(&self) -> PagerStats {
+        self.stats.get()
+    }
+
+    fn count(&self, update: impl FnOnce(&mut PagerStats)) {
+        let mut stats = self.stats.get();
+        update(&mut stats);
+        self.stats.set(stats);
+    }
+
     pub fn set_wal(&mut self, wal: Rc<RefCell<dyn Wal>>) {
         self.wal = wal;
     }
 
+    /// Returns the auto-vacuum mode of the database, which its header records once it exists.
     pub fn get_auto_vacuum_mode(&self) -> AutoVacuumMode {
-        *self.auto_vacuum_mode.borrow()
+        if self.db_state.load(Ordering::SeqCst) < DB_STATE_INITIALIZED {
+            return *self.auto_vacuum_mode.borrow();
+        }
+        match (
+            header_accessor::get_vacuum_mode_largest_root_page(self),
+            header_accessor::get_incremental_vacuum_enabled(self),
+        ) {
+            (Ok(0), _) => AutoVacuumMode::None,
+            (Ok(_), Ok(0)) => AutoVacuumMode::Full,
+            (Ok(_), Ok(_)) => AutoVacuumMode::Incremental,
+            _ => *self.auto_vacuum_mode.borrow(),
+        }
     }
 
     pub fn set_auto_vacuum_mode(&self, mode: AutoVacuumMode) {
         *self.auto_vacuum_mode.borrow_mut() = mode;
     }
 
+    pub fn journal_mode(&self) -> JournalMode {
+        self.journal_mode.get()
+    }
+
+    /// Switches to another journal mode. Leaving WAL mode checkpoints the whole WAL into the
+    /// database file and truncates it, which fails with `Busy` while other connections read it.
+    pub fn set_journal_mode(&self, mode: JournalMode) -> Result<()> {
+        if self.journal_mode.get() == JournalMode::Wal && mode != JournalMode::Wal {
+            self.wal_checkpoint(false, CheckpointMode::Truncate)?;
+        }
+        self.journal_mode.set(mode);
+        Ok(())
+    }
+
+    pub fn synchronous(&self) -> Synchronous {
+        self.synchronous.get()
+    }
+
+    pub fn set_synchronous(&self, synchronous: Synchronous) {
+        self.synchronous.set(synchronous);
+    }
+
+    /// Whether committing a transaction fsyncs the WAL it was appended to.
+    fn syncs_wal_on_commit(&self) -> bool {
+        match self.journal_mode.get() {
+            JournalMode::Memory | JournalMode::Off => false,
+            JournalMode::Wal => self.synchronous.get() >= Synchronous::Full,
+            _ => self.synchronous.get() >= Synchronous::Normal,
+        }
+    }
+
+    /// The encryption of the pages, once the key of the database was given.
+    pub fn encryption(&self) -> Option<Arc<EncryptionContext>> {
+        self.encryption.borrow().clone()
+    }
+
+    /// Whether the database is encrypted, as told by its header, which stays in plaintext.
+    pub fn is_encrypted(&self) -> Result<bool> {
+        if self.db_state.load(Ordering::SeqCst) < DB_STATE_INITIALIZED {
+            return Ok(self.encryption.borrow().is_some());
+        }
+        let page = self.read_page_blocking(DATABASE_HEADER_PAGE_ID)?;
+        Ok(CipherMode::of_header(page.get_contents().as_ptr()).is_some())
+    }
+
+    /// Sets the key the pages are encrypted with. A database that doesn't exist yet is created
+    /// encrypted with `mode`. The key of an existing database is derived with the salt in its
+    /// header, and is checked by decrypting page 1, failing with [LimboError::NotADB] if it's
+    /// wrong.
+    pub fn set_encryption_key(&self, mode: CipherMode, key: &EncryptionKey) -> Result<()> {
+        if self.db_state.load(Ordering::SeqCst) < DB_STATE_INITIALIZED {
+            if self.compression_mode.get() != CompressionMode::None {
+                return Err(LimboError::InvalidArgument(
+                    "a compressed database can't be encrypted".to_string(),
+                ));
+            }
+            let salt = new_salt()?;
+            let encryption = EncryptionContext::new(mode, &key.derive(&salt));
+            self.encryption.replace(Some(Arc::new(encryption)));
+            self.encryption_salt.set(Some(salt));
+            return Ok(());
+        }
+        // Page 1 is read again without decrypting it, since the key isn't known to be right.
+        self.encryption.replace(None);
+        self.clear_page_cache();
+        if matches!(self.io.block(|| self.begin_read_tx())?, LimboResult::Busy) {
+            return Err(LimboError::Busy);
+        }
+        let page = self.read_page_blocking(DATABASE_HEADER_PAGE_ID);
+        self.end_read_tx()?;
+        let mut page = page?.get_contents().as_ptr().to_vec();
+        self.clear_page_cache();
+
+        let mode = CipherMode::of_header(&page).ok_or(LimboError::NotADB)?;
+        let mut salt = [0; SALT_SIZE];
+        salt.copy_from_slice(&page[SALT_OFFSET..SALT_OFFSET + SALT_SIZE]);
+        let encryption = EncryptionContext::new(mode, &key.derive(&salt));
+        encryption
+            .decrypt_page(&mut page, DATABASE_HEADER_PAGE_ID)
+            .map_err(|_| LimboError::NotADB)?;
+        self.encryption.replace(Some(Arc::new(encryption)));
+        self.encryption_salt.set(Some(salt));
+        Ok(())
+    }
+
+    /// Returns the compression of the pages of the database file, which its header records once
+    /// it exists.
+    pub fn compression(&self) -> Result<CompressionMode> {
+        if self.db_state.load(Ordering::SeqCst) < DB_STATE_INITIALIZED {
+            return Ok(self.compression_mode.get());
+        }
+        if let Some(compression) = self.compression.get() {
+            return Ok(compression);
+        }
+        let compression = CompressionMode::from_header_id(header_accessor::get_compression(self)?)?;
+        self.compression.set(Some(compression));
+        Ok(compression)
+    }
+
+    /// Sets the compression the pages of the database are written with, which can only be
+    /// chosen before the database is created.
+    pub fn set_compression(&self, mode: CompressionMode) -> Result<()> {
+        if self.db_state.load(Ordering::SeqCst) >= DB_STATE_INITIALIZED {
+            if mode == self.compression()? {
+                return Ok(());
+            }
+            return Err(LimboError::InvalidArgument(
+                "the compression of a database can only be set before it's created".to_string(),
+            ));
+        }
+        if mode != CompressionMode::None && self.encryption().is_some() {
+            return Err(LimboError::InvalidArgument(
+                "an encrypted database can't be compressed".to_string(),
+            ));
+        }
+        self.compression_mode.set(mode);
+        Ok(())
+    }
+
+    /// Returns the encoding of the text of the database, which its header records once it exists.
+    pub fn text_encoding(&self) -> Result<TextEncoding> {
+        if self.db_state.load(Ordering::SeqCst) < DB_STATE_INITIALIZED {
+            return Ok(self.text_encoding_mode.get());
+        }
+        if let Some(encoding) = self.text_encoding.get() {
+            return Ok(encoding);
+        }
+        let encoding = TextEncoding::from_header_id(header_accessor::get_text_encoding(self)?)?;
+        self.text_encoding.set(Some(encoding));
+        Ok(encoding)
+    }
+
+    /// Sets the encoding of the text of a new database. Like in SQLite, the encoding of a database
+    /// that exists can't be changed, and setting it does nothing.
+    pub fn set_text_encoding(&self, encoding: TextEncoding) {
+        if self.db_state.load(Ordering::SeqCst) < DB_STATE_INITIALIZED {
+            self.text_encoding_mode.set(encoding);
+        }
+    }
+
+    /// Encrypts the database again with a new key, derived with a new salt. Every page is
+    /// rewritten in a write transaction of its own, so the page cache grows to hold the whole
+    /// database for the duration.
+    pub fn rekey(&self, key: &EncryptionKey, connection: &Connection) -> Result<()> {
+        let Some(mode) = self.encryption().map(|encryption| encryption.mode()) else {
+            return Err(LimboError::InvalidArgument(
+                "the database isn't encrypted".to_string(),
+            ));
+        };
+        let salt = new_salt()?;
+        let encryption = Arc::new(EncryptionContext::new(mode, &key.derive(&salt)));
+        if matches!(self.io.block(|| self.begin_read_tx())?, LimboResult::Busy) {
+            return Err(LimboError::Busy);
+        }
+        if matches!(self.io.block(|| self.begin_write_tx())?, LimboResult::Busy) {
+            self.end_read_tx()?;
+            return Err(LimboError::Busy);
+        }
+        let capacity = self.page_cache.read().capacity();
+        let dirtied = (|| -> Result<()> {
+            let db_size = header_accessor::get_database_size(self)? as usize;
+            self.change_page_cache_size(capacity.max(db_size + 1))?;
+            // The pages are all read with the old key before any is written with the new one.
+            for page_id in 1..=db_size {
+                let page = self.read_page_blocking(page_id)?;
+                self.add_dirty(page_id);
+                page.set_dirty();
+                if page_id == DATABASE_HEADER_PAGE_ID {
+                    page.get_contents().as_ptr()[SALT_OFFSET..SALT_OFFSET + SALT_SIZE]
+                        .copy_from_slice(&salt);
+                }
+            }
+            Ok(())
+        })();
+        if let Err(e) = dirtied {
+            self.rollback(false, connection)?;
+            self.io
+                .block(|| self.end_tx(true, false, connection, false))?;
+            self.change_page_cache_size(capacity)?;
+            return Err(e);
+        }
+        self.encryption.replace(Some(encryption));
+        self.encryption_salt.set(Some(salt));
+        self.io
+            .block(|| self.end_tx(false, false, connection, false))?;
+        self.change_page_cache_size(capacity)?;
+        // The older frames of the WAL, encrypted with the old key, go away when it's restarted.
+        match self.wal_checkpoint(false, CheckpointMode::Truncate) {
+            Ok(_) | Err(LimboError::Busy) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+
     /// Retrieves the pointer map entry for a given database page.
     /// `target_page_num` (1-indexed) is the page whose entry is sought.
     /// Returns `Ok(None)` if the page is not supposed to have a ptrmap entry (e.g. header, or a ptrmap page itself).
     #[cfg(not(feature = "omit_autovacuum"))]
     pub fn ptrmap_get(&self, target_page_num: u32) -> Result<IOResult<Option<PtrmapEntry>>> {
         tracing::trace!("ptrmap_get(page_idx = {})", target_page_num);
+        if let Some(entry) = self.ptrmap_changes.borrow().pending.get(&target_page_num) {
+            return Ok(IOResult::Done(Some(*entry)));
+        }
         let configured_page_size = match header_accessor::get_page_size_async(self)? {
             IOResult::Done(size) => size as usize,
             IOResult::IO => return Ok(IOResult::IO),
@@ -507,6 +852,9 @@ impl Pager {
             }
         };
 
+        ptrmap_page.set_dirty();
+        self.add_dirty(ptrmap_pg_no as usize);
+
         let mut page_buffer_guard = page_content.buffer.borrow_mut();
         let full_buffer_slice = page_buffer_guard.as_mut_slice();
 
@@ -528,9 +876,6 @@ impl Pager {
             &mut full_buffer_slice
                 [offset_in_ptrmap_page..offset_in_ptrmap_page + PTRMAP_ENTRY_SIZE],
         )?;
-
-        ptrmap_page.set_dirty();
-        self.add_dirty(ptrmap_pg_no as usize);
         Ok(IOResult::Done(()))
     }
 
@@ -553,65 +898,92 @@ impl Pager {
         //  If autovacuum is enabled, we need to allocate a new page number that is greater than the largest root page number
         #[cfg(not(feature = "omit_autovacuum"))]
         {
-            let auto_vacuum_mode = self.auto_vacuum_mode.borrow();
-            match *auto_vacuum_mode {
+            match self.get_auto_vacuum_mode() {
                 AutoVacuumMode::None => {
                     let page = self.do_allocate_page(page_type, 0, BtreePageAllocMode::Any)?;
                     let page_id = page.get().get().id;
                     Ok(IOResult::Done(page_id as u32))
                 }
-                AutoVacuumMode::Full => {
+                AutoVacuumMode::Full | AutoVacuumMode::Incremental => {
                     let mut root_page_num =
-                        match header_accessor::get_vacuum_mode_largest_root_page_async(self)? {
-                            IOResult::Done(value) => value,
-                            IOResult::IO => return Ok(IOResult::IO),
-                        };
+                        header_accessor::get_vacuum_mode_largest_root_page(self)?;
                     assert!(root_page_num > 0); //  Largest root page number cannot be 0 because that is set to 1 when creating the database with autovacuum enabled
                     root_page_num += 1;
                     assert!(root_page_num >= FIRST_PTRMAP_PAGE_NO); //  can never be less than 2 because we have already incremented
 
-                    let page_size = match header_accessor::get_page_size_async(self)? {
-                        IOResult::Done(size) => size as usize,
-                        IOResult::IO => return Ok(IOResult::IO),
-                    };
-
+                    let page_size = header_accessor::get_page_size(self)? as usize;
                     while is_ptrmap_page(root_page_num, page_size) {
                         root_page_num += 1;
                     }
                     assert!(root_page_num >= 3); //  the very first root page is page 3
 
-                    //  root_page_num here is the desired root page
-                    let page = self.do_allocate_page(
-                        page_type,
-                        0,
-                        BtreePageAllocMode::Exact(root_page_num),
-                    )?;
-                    let allocated_page_id = page.get().get().id as u32;
-                    if allocated_page_id != root_page_num {
-                        //  TODO(Zaid): Handle swapping the allocated page with the desired root page
-                    }
-
-                    //  TODO(Zaid): Update the header metadata to reflect the new root page number
-
-                    //  For now map allocated_page_id since we are not swapping it with root_page_num
-                    match self.ptrmap_put(allocated_page_id, PtrmapType::RootPage, 0)? {
-                        IOResult::Done(_) => Ok(IOResult::Done(allocated_page_id)),
-                        IOResult::IO => Ok(IOResult::IO),
+                    //  Vacuuming never moves root pages, so they are kept at the start of the file:
+                    //  root_page_num here is the desired root page, and the page there is moved
+                    //  out of the way if it is in use.
+                    if root_page_num > header_accessor::get_database_size(self)? {
+                        let page = self.do_allocate_page(
+                            page_type,
+                            0,
+                            BtreePageAllocMode::Exact(root_page_num),
+                        )?;
+                        assert_eq!(page.get().get().id as u32, root_page_num);
+                    } else {
+                        self.ptrmap_sync()?;
+                        let entry = self.io.block(|| self.ptrmap_get(root_page_num))?;
+                        match entry.map(|entry| (entry.entry_type, entry)) {
+                            Some((PtrmapType::FreePage, _)) => {
+                                if self
+                                    .freelist_take(Some(root_page_num), root_page_num)?
+                                    .is_none()
+                                {
+                                    return Err(LimboError::Corrupt(format!(
+                                        "Free page {root_page_num} is not on the freelist"
+                                    )));
+                                }
+                            }
+                            Some((PtrmapType::RootPage, _)) | None => {
+                                return Err(LimboError::Corrupt(format!(
+                                    "Page {root_page_num} after the largest root page is a root page"
+                                )));
+                            }
+                            Some((_, entry)) => {
+                                let new_page = self.allocate_page()?;
+                                self.relocate_page(root_page_num, new_page.get().id as u32, entry)?;
+                            }
+                        }
+                        let page = self.read_page_blocking(root_page_num as usize)?;
+                        page.set_dirty();
+                        self.add_dirty(root_page_num as usize);
+                        let page = Arc::new(BTreePageInner {
+                            page: RefCell::new(page),
+                        });
+                        btree_init_page(&page, page_type, 0, self.usable_space() as u16);
                     }
-                }
-                AutoVacuumMode::Incremental => {
-                    unimplemented!()
+                    self.ptrmap_record(root_page_num, PtrmapType::RootPage, 0);
+                    header_accessor::set_vacuum_mode_largest_root_page(self, root_page_num)?;
+                    Ok(IOResult::Done(root_page_num))
                 }
             }
         }
     }
 
     /// Allocate a new overflow page.
-    /// This is done when a cell overflows and new space is needed.
+    /// This is done when a cell overflows and new space is needed. `prev_page_no` is the overflow
+    /// page before it in the chain, if any.
     // FIXME: handle no room in page cache
-    pub fn allocate_overflow_page(&self) -> PageRef {
+    pub fn allocate_overflow_page(&self, prev_page_no: Option<u32>) -> PageRef {
         let page = self.allocate_page().unwrap();
         tracing::debug!("Pager::allocate_overflow_page(id={})", page.get().id);
+        #[cfg(not(feature = "omit_autovacuum"))]
+        match prev_page_no {
+            Some(prev_page_no) => {
+                self.ptrmap_record(page.get().id as u32, PtrmapType::Overflow2, prev_page_no)
+            }
+            // The parent is recorded once the cell is on its page.
+            None => self.ptrmap_record(page.get().id as u32, PtrmapType::Overflow1, 0),
+        }
+        #[cfg(feature = "omit_autovacuum")]
+        let _ = prev_page_no;
 
         // setup overflow page
         let contents = page.get().contents.as_mut().unwrap();
@@ -635,6 +1007,9 @@ impl Pager {
             page: RefCell::new(page),
         });
         btree_init_page(&page, page_type, offset, self.usable_space() as u16);
+        // The parent is recorded once the page is linked into its b-tree.
+        #[cfg(not(feature = "omit_autovacuum"))]
+        self.ptrmap_record(page.get().get().id as u32, PtrmapType::BTreeNode, 0);
         tracing::debug!(
             "do_allocate_page(id={}, page_type={:?})",
             page.get().get().id,
@@ -653,9 +1028,14 @@ impl Pager {
             .get()
             .get_or_insert_with(|| header_accessor::get_page_size(self).unwrap_or_default());
 
-        let reserved_space = *self
-            .reserved_space
-            .get_or_init(|| header_accessor::get_reserved_space(self).unwrap_or_default());
+        let reserved_space = match self.reserved_space.get() {
+            Some(reserved_space) => reserved_space,
+            None => {
+                let reserved_space = header_accessor::get_reserved_space(self).unwrap_or_default();
+                self.reserved_space.set(Some(reserved_space));
+                reserved_space
+            }
+        };
 
         (page_size as usize) - (reserved_space as usize)
     }
@@ -674,7 +1054,25 @@ impl Pager {
             IOResult::Done(_) => {}
             IOResult::IO => return Ok(IOResult::IO),
         }
-        Ok(IOResult::Done(self.wal.borrow_mut().begin_read_tx()?))
+        let result = self.wal.borrow_mut().begin_read_tx()?;
+        if matches!(result, LimboResult::Ok) {
+            let snapshot = self.current_wal_snapshot();
+            if self.wal_snapshot.replace(Some(snapshot)) != Some(snapshot)
+                && self.dirty_pages.borrow().is_empty()
+            {
+                // Savepoints may already be open, so only the cache is cleared.
+                self.page_cache
+                    .write()
+                    .clear()
+                    .expect("Failed to clear page cache");
+            }
+        }
+        Ok(IOResult::Done(result))
+    }
+
+    pub(crate) fn current_wal_snapshot(&self) -> (u64, u32) {
+        let wal = self.wal.borrow();
+        (wal.get_max_frame(), wal.get_checkpoint_seq())
     }
 
     #[instrument(skip_all, level = Level::DEBUG)]
@@ -709,9 +1107,43 @@ impl Pager {
             IOResult::Done(_) => {}
             IOResult::IO => return Ok(IOResult::IO),
         }
+        if self.concurrent_reads.borrow().is_some() {
+            // The write lock is taken by begin_concurrent_commit.
+            return Ok(IOResult::Done(LimboResult::Ok));
+        }
         Ok(IOResult::Done(self.wal.borrow_mut().begin_write_tx()?))
     }
 
+    /// Makes the read transaction that just began a `BEGIN CONCURRENT` transaction, which
+    /// records the pages it reads and writes without taking the write lock until it commits.
+    pub fn begin_concurrent_tx(&self) {
+        self.concurrent_reads.replace(Some(HashSet::new()));
+    }
+
+    /// Takes the write lock for committing a `BEGIN CONCURRENT` transaction, moving its snapshot
+    /// to the end of the WAL. Fails with [LimboError::WriteWriteConflict] if a transaction that
+    /// committed after the snapshot was taken changed a page this one read or wrote, in which
+    /// case this one must be rolled back.
+    pub fn begin_concurrent_commit(&self) -> Result<LimboResult> {
+        let mut pages = match &*self.concurrent_reads.borrow() {
+            Some(pages) => pages.clone(),
+            None => return Ok(LimboResult::Ok),
+        };
+        pages.extend(self.dirty_pages.borrow().iter());
+        // The header on page 1 has the database size and the freelist, so this transaction can't
+        // allocate or free pages based on a stale one.
+        pages.insert(DATABASE_HEADER_PAGE_ID);
+        let checkpoint_seq = self.wal_snapshot.get().map(|(_, seq)| seq);
+        if checkpoint_seq != Some(self.wal.borrow().get_checkpoint_seq()) {
+            return Err(LimboError::WriteWriteConflict);
+        }
+        let result = self.wal.borrow_mut().begin_concurrent_write_tx(&pages)?;
+        if matches!(result, LimboResult::Ok) {
+            self.concurrent_reads.replace(None);
+        }
+        Ok(result)
+    }
+
     #[instrument(skip_all, level = Level::DEBUG)]
     pub fn end_tx(
         &self,
@@ -721,8 +1153,12 @@ impl Pager {
         wal_checkpoint_disabled: bool,
     ) -> Result<IOResult<PagerCommitResult>> {
         tracing::trace!("end_tx(rollback={})", rollback);
+        self.savepoints.borrow_mut().clear();
         if rollback {
-            self.wal.borrow().end_write_tx()?;
+            // A concurrent transaction that didn't get to commit doesn't hold the write lock.
+            if self.concurrent_reads.take().is_none() {
+                self.wal.borrow().end_write_tx()?;
+            }
             self.wal.borrow().end_read_tx()?;
             return Ok(IOResult::Done(PagerCommitResult::Rollback));
         }
@@ -730,8 +1166,17 @@ impl Pager {
         match commit_status {
             IOResult::IO => Ok(IOResult::IO),
             IOResult::Done(_) => {
+                // The page cache already has the pages this transaction appended to the WAL.
+                self.wal_snapshot.set(Some(self.current_wal_snapshot()));
                 self.wal.borrow().end_write_tx()?;
                 self.wal.borrow().end_read_tx()?;
+                if self.journal_mode.get() != JournalMode::Wal && !wal_checkpoint_disabled {
+                    // The frames read by other connections stay in the WAL until a later commit.
+                    match self.wal_checkpoint(false, CheckpointMode::Truncate) {
+                        Ok(_) | Err(LimboError::Busy) => {}
+                        Err(e) => return Err(e),
+                    }
+                }
 
                 if schema_did_change {
                     let schema = connection.schema.borrow().clone();
@@ -748,6 +1193,7 @@ impl Pager {
 
     #[instrument(skip_all, level = Level::DEBUG)]
     pub fn end_read_tx(&self) -> Result<()> {
+        self.concurrent_reads.replace(None);
         self.wal.borrow().end_read_tx()?;
         Ok(())
     }
@@ -756,19 +1202,33 @@ impl Pager {
     #[tracing::instrument(skip_all, level = Level::DEBUG)]
     pub fn read_page(&self, page_idx: usize) -> Result<PageRef, LimboError> {
         tracing::trace!("read_page(page_idx = {})", page_idx);
+        // Page 1 is never compressed, and is read to find out whether the others are.
+        let compression = if page_idx == DATABASE_HEADER_PAGE_ID {
+            CompressionMode::None
+        } else {
+            self.compression()?
+        };
+        if let Some(pages) = self.concurrent_reads.borrow_mut().as_mut() {
+            pages.insert(page_idx);
+        }
         let mut page_cache = self.page_cache.write();
         let page_key = PageCacheKey::new(page_idx);
         if let Some(page) = page_cache.get(&page_key) {
             tracing::trace!("read_page(page_idx = {}) = cached", page_idx);
+            self.count(|stats| stats.cache_hits += 1);
             return Ok(page.clone());
         }
+        self.count(|stats| stats.cache_misses += 1);
         let page = Arc::new(Page::new(page_idx));
         page.set_locked();
 
         if let Some(frame_id) = self.wal.borrow().find_frame(page_idx as u64)? {
-            self.wal
-                .borrow()
-                .read_frame(frame_id, page.clone(), self.buffer_pool.clone())?;
+            self.wal.borrow().read_frame(
+                frame_id,
+                page.clone(),
+                self.buffer_pool.clone(),
+                self.encryption(),
+            )?;
             {
                 page.set_uptodate();
             }
@@ -794,6 +1254,8 @@ impl Pager {
             self.buffer_pool.clone(),
             page.clone(),
             page_idx,
+            self.encryption(),
+            compression,
         )?;
         match page_cache.insert(page_key, page.clone()) {
             Ok(_) => {}
@@ -810,6 +1272,19 @@ impl Pager {
         Ok(page)
     }
 
+    pub(crate) fn read_page_blocking(&self, page_idx: usize) -> Result<PageRef> {
+        let page = self.read_page(page_idx)?;
+        while page.is_locked() {
+            if page.is_error() {
+                return Err(LimboError::Corrupt(format!(
+                    "failed to read page {page_idx}"
+                )));
+            }
+            self.io.run_once()?;
+        }
+        Ok(page)
+    }
+
     // Get a page from the cache, if it exists.
     pub fn cache_get(&self, page_idx: usize) -> Option<PageRef> {
         tracing::trace!("read_page(page_idx = {})", page_idx);
@@ -824,7 +1299,42 @@ impl Pager {
         Ok(page_cache.resize(capacity))
     }
 
+    /// Carries the settings made by pragmas over to a pager that replaces this one.
+    pub(crate) fn copy_settings_to(&self, pager: &Pager) -> Result<()> {
+        pager.change_page_cache_size(self.page_cache.read().capacity())?;
+        pager.set_auto_vacuum_mode(*self.auto_vacuum_mode.borrow());
+        pager.set_wal_autocheckpoint(self.wal_autocheckpoint());
+        pager.journal_mode.set(self.journal_mode.get());
+        pager.synchronous.set(self.synchronous.get());
+        pager.encryption.replace(self.encryption());
+        pager.encryption_salt.set(self.encryption_salt.get());
+        pager.compression_mode.set(self.compression_mode.get());
+        Ok(())
+    }
+
     pub fn add_dirty(&self, page_id: usize) {
+        // Pages must be added before they are modified, so that the open savepoints can record
+        // their contents first.
+        let mut savepoints = self.savepoints.borrow_mut();
+        for savepoint in savepoints.iter_mut() {
+            if savepoint.pages.contains_key(&page_id) {
+                continue;
+            }
+            let contents = if self.dirty_pages.borrow().contains(&page_id) {
+                let page = self
+                    .page_cache
+                    .write()
+                    .peek(&PageCacheKey::new(page_id), false)
+                    .expect("dirty page must be in the page cache");
+                Some(page.get_contents().as_ptr().to_vec())
+            } else {
+                None
+            };
+            savepoint.pages.insert(page_id, contents);
+        }
+        drop(savepoints);
+        #[cfg(not(feature = "omit_autovacuum"))]
+        self.ptrmap_changes.borrow_mut().stale.insert(page_id);
         // TODO: check duplicates?
         let mut dirty_pages = RefCell::borrow_mut(&self.dirty_pages);
         dirty_pages.insert(page_id);
@@ -852,7 +1362,9 @@ impl Pager {
                         page.clone(),
                         0,
                         self.flush_info.borrow().in_flight_writes.clone(),
+                        self.encryption.borrow().as_deref(),
                     )?;
+                    self.count(|stats| stats.pages_written += 1);
                     page.clear_dirty();
                 }
                 self.dirty_pages.borrow_mut().clear();
@@ -887,6 +1399,8 @@ impl Pager {
             trace!(?state);
             match state {
                 CommitState::Start => {
+                    #[cfg(not(feature = "omit_autovacuum"))]
+                    self.ptrmap_commit()?;
                     let db_size = header_accessor::get_database_size(self)?;
                     for (dirty_page_idx, page_id) in self.dirty_pages.borrow().iter().enumerate() {
                         let is_last_frame = dirty_page_idx == self.dirty_pages.borrow().len() - 1;
@@ -904,7 +1418,9 @@ impl Pager {
                             page.clone(),
                             db_size,
                             self.commit_info.borrow().in_flight_writes.clone(),
+                            self.encryption.borrow().as_deref(),
                         )?;
+                        self.count(|stats| stats.pages_written += 1);
                         page.clear_dirty();
                     }
                     // This is okay assuming we use shared cache by default.
@@ -913,6 +1429,8 @@ impl Pager {
                         cache.clear().unwrap();
                     }
                     self.dirty_pages.borrow_mut().clear();
+                    #[cfg(not(feature = "omit_autovacuum"))]
+                    self.ptrmap_changes.take();
                     self.commit_info.borrow_mut().state = CommitState::WaitAppendFrames;
                     return Ok(IOResult::IO);
                 }
@@ -925,20 +1443,30 @@ impl Pager {
                     }
                 }
                 CommitState::SyncWal => {
-                    return_if_io!(self.wal.borrow_mut().sync());
+                    let checkpoint =
+                        !wal_checkpoint_disabled && self.wal.borrow().should_checkpoint();
+                    // The frames must be durable before a checkpoint copies them to the database
+                    // file, whatever the synchronous level.
+                    if self.syncs_wal_on_commit()
+                        || (checkpoint && self.synchronous.get() != Synchronous::Off)
+                    {
+                        return_if_io!(self.wal.borrow_mut().sync());
+                    }
 
-                    if wal_checkpoint_disabled || !self.wal.borrow().should_checkpoint() {
+                    if !checkpoint {
                         self.commit_info.borrow_mut().state = CommitState::Start;
                         break PagerCommitResult::WalWritten;
                     }
                     self.commit_info.borrow_mut().state = CommitState::Checkpoint;
                 }
                 CommitState::Checkpoint => {
-                    checkpoint_result = return_if_io!(self.checkpoint());
+                    checkpoint_result = return_if_io!(self.checkpoint(CheckpointMode::Passive));
                     self.commit_info.borrow_mut().state = CommitState::SyncDbFile;
                 }
                 CommitState::SyncDbFile => {
-                    sqlite3_ondisk::begin_sync(self.db_file.clone(), self.syncing.clone())?;
+                    if self.synchronous.get() != Synchronous::Off {
+                        sqlite3_ondisk::begin_sync(self.db_file.clone(), self.syncing.clone())?;
+                    }
                     self.commit_info.borrow_mut().state = CommitState::WaitSyncDbFile;
                 }
                 CommitState::WaitSyncDbFile => {
@@ -973,7 +1501,7 @@ impl Pager {
     }
 
     #[instrument(skip_all, level = Level::DEBUG, name = "pager_checkpoint",)]
-    pub fn checkpoint(&self) -> Result<IOResult<CheckpointResult>> {
+    pub fn checkpoint(&self, mode: CheckpointMode) -> Result<IOResult<CheckpointResult>> {
         let mut checkpoint_result = CheckpointResult::default();
         loop {
             let state = *self.checkpoint_state.borrow();
@@ -981,11 +1509,7 @@ impl Pager {
             match state {
                 CheckpointState::Checkpoint => {
                     let in_flight = self.checkpoint_inflight.clone();
-                    match self.wal.borrow_mut().checkpoint(
-                        self,
-                        in_flight,
-                        CheckpointMode::Passive,
-                    )? {
+                    match self.wal.borrow_mut().checkpoint(self, in_flight, mode)? {
                         IOResult::IO => return Ok(IOResult::IO),
                         IOResult::Done(res) => {
                             checkpoint_result = res;
@@ -994,7 +1518,9 @@ impl Pager {
                     };
                 }
                 CheckpointState::SyncDbFile => {
-                    sqlite3_ondisk::begin_sync(self.db_file.clone(), self.syncing.clone())?;
+                    if self.synchronous.get() != Synchronous::Off {
+                        sqlite3_ondisk::begin_sync(self.db_file.clone(), self.syncing.clone())?;
+                    }
                     self.checkpoint_state
                         .replace(CheckpointState::WaitSyncDbFile);
                 }
@@ -1018,11 +1544,24 @@ impl Pager {
         }
     }
 
+    /// The number of frames in the WAL at which committing a transaction checkpoints it, or 0 if
+    /// that is disabled.
+    pub fn wal_autocheckpoint(&self) -> usize {
+        self.wal.borrow().checkpoint_threshold()
+    }
+
+    pub fn set_wal_autocheckpoint(&self, frames: usize) {
+        self.wal.borrow_mut().set_checkpoint_threshold(frames);
+    }
+
     /// Invalidates entire page cache by removing all dirty and clean pages. Usually used in case
     /// of a rollback or in case we want to invalidate page cache after starting a read transaction
     /// right after new writes happened which would invalidate current page cache.
     pub fn clear_page_cache(&self) {
+        self.savepoints.borrow_mut().clear();
         self.dirty_pages.borrow_mut().clear();
+        #[cfg(not(feature = "omit_autovacuum"))]
+        self.ptrmap_changes.take();
         self.page_cache.write().unset_dirty_all_pages();
         self.page_cache
             .write()
@@ -1048,12 +1587,16 @@ impl Pager {
                 _attempts += 1;
             }
         }
-        self.wal_checkpoint(wal_checkpoint_disabled)?;
+        self.wal_checkpoint(wal_checkpoint_disabled, CheckpointMode::Passive)?;
         Ok(())
     }
 
     #[instrument(skip_all, level = Level::DEBUG)]
-    pub fn wal_checkpoint(&self, wal_checkpoint_disabled: bool) -> Result<CheckpointResult> {
+    pub fn wal_checkpoint(
+        &self,
+        wal_checkpoint_disabled: bool,
+        mode: CheckpointMode,
+    ) -> Result<CheckpointResult> {
         if wal_checkpoint_disabled {
             return Ok(CheckpointResult {
                 num_wal_frames: 0,
@@ -1061,12 +1604,7 @@ impl Pager {
             });
         }
 
-        let checkpoint_result = self.io.block(|| {
-            self.wal
-                .borrow_mut()
-                .checkpoint(self, Rc::new(RefCell::new(0)), CheckpointMode::Passive)
-                .map_err(|err| panic!("error while clearing cache {err}"))
-        })?;
+        let checkpoint_result = self.io.block(|| self.checkpoint(mode))?;
 
         // TODO: only clear cache of things that are really invalidated
         self.page_cache
@@ -1106,6 +1644,8 @@ impl Pager {
                         }
                         None => self.read_page(page_id)?,
                     };
+                    #[cfg(not(feature = "omit_autovacuum"))]
+                    self.ptrmap_record(page_id as u32, PtrmapType::FreePage, 0);
                     header_accessor::set_freelist_pages(
                         self,
                         header_accessor::get_freelist_pages(self)? + 1,
@@ -1194,9 +1734,36 @@ impl Pager {
                 self.db_state.store(DB_STATE_INITIALIZING, Ordering::SeqCst);
                 let mut default_header = DatabaseHeader::default();
                 default_header.database_size += 1;
+                match *self.auto_vacuum_mode.borrow() {
+                    AutoVacuumMode::None => {}
+                    AutoVacuumMode::Full => default_header.vacuum_mode_largest_root_page = 1,
+                    AutoVacuumMode::Incremental => {
+                        default_header.vacuum_mode_largest_root_page = 1;
+                        default_header.incremental_vacuum_enabled = 1;
+                    }
+                }
                 if let Some(size) = self.page_size.get() {
                     default_header.update_page_size(size);
                 }
+                if let Some(encryption) = self.encryption() {
+                    let salt = self.encryption_salt.get().ok_or_else(|| {
+                        LimboError::InternalError("encrypted database without a salt".to_string())
+                    })?;
+                    default_header.reserved_space = encryption.mode().reserved_space();
+                    default_header.reserved_for_expansion[..SALT_SIZE].copy_from_slice(&salt);
+                }
+                let compression = self.compression_mode.get();
+                if compression != CompressionMode::None {
+                    default_header.reserved_space = COMPRESSION_RESERVED_SPACE;
+                    // Bytes 88 to 92 of the header, after the salt of an encrypted database.
+                    default_header.reserved_for_expansion[16..]
+                        .copy_from_slice(&compression.header_id().to_be_bytes());
+                }
+                self.compression.set(Some(compression));
+                let text_encoding = self.text_encoding_mode.get();
+                default_header.text_encoding = text_encoding.header_id();
+                self.text_encoding.set(Some(text_encoding));
+                self.reserved_space.set(Some(default_header.reserved_space));
                 let page = allocate_page(1, &self.buffer_pool, 0);
 
                 let contents = page.get_contents();
@@ -1274,7 +1841,7 @@ impl Pager {
             //  If the following conditions are met, allocate a pointer map page, add to cache and increment the database size
             //  - autovacuum is enabled
             //  - the last page is a pointer map page
-            if matches!(*self.auto_vacuum_mode.borrow(), AutoVacuumMode::Full)
+            if !matches!(self.get_auto_vacuum_mode(), AutoVacuumMode::None)
                 && is_ptrmap_page(new_db_size, header_accessor::get_page_size(self)? as usize)
             {
                 let page = allocate_page(new_db_size as usize, &self.buffer_pool, 0);
@@ -1345,6 +1912,85 @@ impl Pager {
         (page_size - reserved_space) as usize
     }
 
+    /// Opens a savepoint, which [Pager::rollback_to_savepoint] can restore the pages to, and
+    /// returns its index.
+    pub fn open_savepoint(&self) -> usize {
+        let mut savepoints = self.savepoints.borrow_mut();
+        savepoints.push(PagerSavepoint {
+            pages: HashMap::new(),
+            #[cfg(not(feature = "omit_autovacuum"))]
+            ptrmap: HashMap::new(),
+        });
+        savepoints.len() - 1
+    }
+
+    /// Releases the savepoints opened after the first `count` ones.
+    pub fn release_savepoints(&self, count: usize) {
+        self.savepoints.borrow_mut().truncate(count);
+    }
+
+    /// Restores the pages to their state when the savepoint at `index` was opened, releasing the
+    /// savepoints opened after it.
+    pub fn rollback_to_savepoint(&self, index: usize) -> Result<()> {
+        let mut savepoints = self.savepoints.borrow_mut();
+        savepoints.truncate(index + 1);
+        let pages = std::mem::take(&mut savepoints[index].pages);
+        #[cfg(not(feature = "omit_autovacuum"))]
+        {
+            let mut ptrmap_changes = self.ptrmap_changes.borrow_mut();
+            for (page_no, entry) in std::mem::take(&mut savepoints[index].ptrmap) {
+                match entry {
+                    Some(entry) => ptrmap_changes.pending.insert(page_no, entry),
+                    None => ptrmap_changes.pending.remove(&page_no),
+                };
+            }
+            // The pointer map pages are restored too, so pointers recorded on them since may
+            // need to be recorded again.
+            ptrmap_changes
+                .stale
+                .extend(self.dirty_pages.borrow().iter().copied());
+        }
+        let mut cache = self.page_cache.write();
+        let mut dirty_pages = self.dirty_pages.borrow_mut();
+        for (page_id, contents) in pages {
+            let page_key = PageCacheKey::new(page_id);
+            let page = cache.peek(&page_key, false);
+            match (page, contents) {
+                (Some(page), Some(contents)) => {
+                    let page_contents = page.get_contents();
+                    page_contents.as_ptr().copy_from_slice(&contents);
+                    page_contents.overflow_cells.clear();
+                }
+                // The page was cut off the end of the database by vacuuming.
+                (None, Some(contents)) => {
+                    let page = allocate_page(page_id, &self.buffer_pool, 0);
+                    page.get_contents().as_ptr().copy_from_slice(&contents);
+                    page.set_dirty();
+                    dirty_pages.insert(page_id);
+                    cache.insert(page_key, page).map_err(|e| {
+                        LimboError::InternalError(format!(
+                            "Failed to insert page {page_id} into cache: {e:?}"
+                        ))
+                    })?;
+                }
+                // The page is read again from the WAL or the database file the next time.
+                (Some(page), None) => {
+                    page.clear_dirty();
+                    dirty_pages.remove(&page_id);
+                    cache.delete(page_key).map_err(|e| {
+                        LimboError::InternalError(format!(
+                            "Failed to evict page {page_id} from cache: {e:?}"
+                        ))
+                    })?;
+                }
+                (None, None) => {
+                    dirty_pages.remove(&page_id);
+                }
+            }
+        }
+        Ok(())
+    }
+
     #[instrument(skip_all, level = Level::DEBUG)]
     pub fn rollback(
         &self,
@@ -1352,7 +1998,10 @@ impl Pager {
         connection: &Connection,
     ) -> Result<(), LimboError> {
         tracing::debug!(schema_did_change);
+        self.savepoints.borrow_mut().clear();
         self.dirty_pages.borrow_mut().clear();
+        #[cfg(not(feature = "omit_autovacuum"))]
+        self.ptrmap_changes.take();
         let mut cache = self.page_cache.write();
         cache.unset_dirty_all_pages();
         cache.clear().expect("failed to clear page cache");
@@ -1370,6 +2019,534 @@ impl Pager {
 
         Ok(())
     }
+
+    /// Replaces the contents of the database with the pages of `source`, which has the same page
+    /// size, as part of the current write transaction. This is how VACUUM swaps in the database
+    /// it rebuilt.
+    pub fn overwrite_with(&self, source: &Pager) -> Result<()> {
+        let old_db_size = header_accessor::get_database_size(self)?;
+        let db_size = header_accessor::get_database_size(source)?;
+        if db_size < old_db_size {
+            self.truncate_db(db_size)?;
+        }
+        for page_id in 1..=db_size as usize {
+            let source_page = source.read_page_blocking(page_id)?;
+            self.overwrite_page(page_id, source_page.get_contents().as_ptr())?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the contents of page `page_id` with `data` as part of the current write
+    /// transaction, without reading the page first. The page may be past the end of the
+    /// database.
+    pub(crate) fn overwrite_page(&self, page_id: usize, data: &[u8]) -> Result<()> {
+        let page = match self.cache_get(page_id) {
+            Some(page) => {
+                while page.is_locked() {
+                    self.io.run_once()?;
+                }
+                page
+            }
+            None => {
+                let offset = if page_id == DATABASE_HEADER_PAGE_ID {
+                    DATABASE_HEADER_SIZE
+                } else {
+                    0
+                };
+                let page = allocate_page(page_id, &self.buffer_pool, offset);
+                self.page_cache
+                    .write()
+                    .insert(PageCacheKey::new(page_id), page.clone())
+                    .map_err(|e| {
+                        LimboError::InternalError(format!(
+                            "Failed to insert page {page_id} into cache: {e:?}"
+                        ))
+                    })?;
+                page
+            }
+        };
+        self.add_dirty(page_id);
+        page.set_dirty();
+        let contents = page.get_contents();
+        contents.as_ptr().copy_from_slice(data);
+        contents.overflow_cells.clear();
+        Ok(())
+    }
+
+    /// Truncates the database to `db_size` pages, dropping the cached pages after it.
+    pub(crate) fn truncate_db(&self, db_size: u32) -> Result<()> {
+        let old_db_size = header_accessor::get_database_size(self)?;
+        header_accessor::set_database_size(self, db_size)?;
+        let mut cache = self.page_cache.write();
+        let mut dirty_pages = self.dirty_pages.borrow_mut();
+        for page_id in db_size as usize + 1..=old_db_size as usize {
+            let page_key = PageCacheKey::new(page_id);
+            if let Some(page) = cache.peek(&page_key, false) {
+                page.clear_dirty();
+                cache.delete_even_if_pinned(page_key).map_err(|e| {
+                    LimboError::InternalError(format!(
+                        "Failed to evict page {page_id} from cache: {e:?}"
+                    ))
+                })?;
+            }
+            dirty_pages.remove(&page_id);
+        }
+        Ok(())
+    }
+
+    /// Runs a step of `PRAGMA incremental_vacuum`, which moves the last page of the database into
+    /// a free page and truncates the database. Returns false if there are no free pages.
+    pub fn incremental_vacuum_step(&self) -> Result<bool> {
+        #[cfg(feature = "omit_autovacuum")]
+        return Ok(false);
+        #[cfg(not(feature = "omit_autovacuum"))]
+        {
+            if matches!(self.get_auto_vacuum_mode(), AutoVacuumMode::None) {
+                return Ok(false);
+            }
+            self.ptrmap_sync()?;
+            let free_pages = header_accessor::get_freelist_pages(self)?;
+            if free_pages == 0 {
+                return Ok(false);
+            }
+            let db_size = header_accessor::get_database_size(self)?;
+            let page_size = header_accessor::get_page_size(self)? as usize;
+            let final_size = vacuum_final_size(db_size, free_pages, page_size)?;
+            self.vacuum_page(db_size, final_size, false)?;
+            let mut new_size = db_size - 1;
+            while is_ptrmap_page(new_size, page_size) {
+                new_size -= 1;
+            }
+            self.truncate_db(new_size)?;
+            Ok(true)
+        }
+    }
+
+    /// Called once the root page of a dropped b-tree was freed. Root pages of an auto-vacuum
+    /// database stay at the start of the file, so the last one is moved into the freed page, and
+    /// its former page number is returned.
+    pub fn btree_destroyed(&self, root_page_no: u32) -> Result<Option<u32>> {
+        #[cfg(feature = "omit_autovacuum")]
+        {
+            let _ = root_page_no;
+            Ok(None)
+        }
+        #[cfg(not(feature = "omit_autovacuum"))]
+        {
+            if matches!(self.get_auto_vacuum_mode(), AutoVacuumMode::None) {
+                return Ok(None);
+            }
+            let largest_root_page = header_accessor::get_vacuum_mode_largest_root_page(self)?;
+            let moved = if root_page_no < largest_root_page {
+                self.ptrmap_sync()?;
+                if self
+                    .freelist_take(Some(root_page_no), root_page_no)?
+                    .is_none()
+                {
+                    return Err(LimboError::Corrupt(format!(
+                        "Freed root page {root_page_no} is not on the freelist"
+                    )));
+                }
+                let entry = PtrmapEntry {
+                    entry_type: PtrmapType::RootPage,
+                    parent_page_no: 0,
+                };
+                self.relocate_page(largest_root_page, root_page_no, entry)?;
+                self.io
+                    .block(|| self.free_page(None, largest_root_page as usize))?;
+                Some(largest_root_page)
+            } else {
+                None
+            };
+            let page_size = header_accessor::get_page_size(self)? as usize;
+            let mut largest_root_page = largest_root_page - 1;
+            while is_ptrmap_page(largest_root_page, page_size) {
+                largest_root_page -= 1;
+            }
+            header_accessor::set_vacuum_mode_largest_root_page(self, largest_root_page)?;
+            Ok(moved)
+        }
+    }
+}
+
+/// Keeping the pointer map up to date and vacuuming an auto-vacuum database. The pointer map
+/// pages are written through the page cache like any other page, so the methods here wait for
+/// the pages they read instead of returning [IOResult::IO].
+#[cfg(not(feature = "omit_autovacuum"))]
+impl Pager {
+    /// Records the pointer map entry of a page, if the database is an auto-vacuum one.
+    fn ptrmap_record(&self, page_no: u32, entry_type: PtrmapType, parent_page_no: u32) {
+        if matches!(self.get_auto_vacuum_mode(), AutoVacuumMode::None) {
+            return;
+        }
+        let entry = PtrmapEntry {
+            entry_type,
+            parent_page_no,
+        };
+        let previous = self
+            .ptrmap_changes
+            .borrow_mut()
+            .pending
+            .insert(page_no, entry);
+        for savepoint in self.savepoints.borrow_mut().iter_mut() {
+            savepoint.ptrmap.entry(page_no).or_insert(previous);
+        }
+    }
+
+    /// Records the pointer map entries of the child pages and the first overflow pages of a
+    /// b-tree page.
+    fn ptrmap_record_children(&self, page: &PageRef) -> Result<()> {
+        let page_no = page.get().id as u32;
+        let contents = page.get_contents();
+        let usable_space = self.usable_space();
+        for idx in 0..contents.cell_count() {
+            let (left_child_page, first_overflow_page) =
+                match contents.cell_get(idx, usable_space)? {
+                    BTreeCell::TableInteriorCell(cell) => (Some(cell.left_child_page), None),
+                    BTreeCell::IndexInteriorCell(cell) => {
+                        (Some(cell.left_child_page), cell.first_overflow_page)
+                    }
+                    BTreeCell::TableLeafCell(cell) => (None, cell.first_overflow_page),
+                    BTreeCell::IndexLeafCell(cell) => (None, cell.first_overflow_page),
+                };
+            if let Some(child) = left_child_page {
+                self.ptrmap_record(child, PtrmapType::BTreeNode, page_no);
+            }
+            if let Some(overflow_page) = first_overflow_page {
+                self.ptrmap_record(overflow_page, PtrmapType::Overflow1, page_no);
+            }
+        }
+        if let Some(child) = contents.rightmost_pointer() {
+            self.ptrmap_record(child, PtrmapType::BTreeNode, page_no);
+        }
+        Ok(())
+    }
+
+    /// Records the pointers on the b-tree pages changed since the last call, and writes the
+    /// pending entries to the pointer map pages.
+    fn ptrmap_sync(&self) -> Result<()> {
+        let stale = std::mem::take(&mut self.ptrmap_changes.borrow_mut().stale);
+        if matches!(self.get_auto_vacuum_mode(), AutoVacuumMode::None) {
+            return Ok(());
+        }
+        let db_size = header_accessor::get_database_size(self)?;
+        let page_size = header_accessor::get_page_size(self)? as usize;
+        for page_no in stale {
+            let page_no = page_no as u32;
+            if page_no > db_size || is_ptrmap_page(page_no, page_size) {
+                continue;
+            }
+            // Page 1 is the root page of sqlite_schema, which has no entry.
+            if page_no != 1 {
+                let entry = self.io.block(|| self.ptrmap_get(page_no))?;
+                if !matches!(
+                    entry.map(|entry| entry.entry_type),
+                    Some(PtrmapType::RootPage | PtrmapType::BTreeNode)
+                ) {
+                    continue;
+                }
+            }
+            let page = self.read_page_blocking(page_no as usize)?;
+            self.ptrmap_record_children(&page)?;
+        }
+        let mut pending: Vec<_> = self
+            .ptrmap_changes
+            .borrow()
+            .pending
+            .iter()
+            .map(|(page_no, entry)| (*page_no, *entry))
+            .collect();
+        pending.sort_by_key(|(page_no, _)| *page_no);
+        for (page_no, entry) in pending {
+            if page_no <= db_size {
+                self.io
+                    .block(|| self.ptrmap_put(page_no, entry.entry_type, entry.parent_page_no))?;
+            }
+            let removed = self.ptrmap_changes.borrow_mut().pending.remove(&page_no);
+            for savepoint in self.savepoints.borrow_mut().iter_mut() {
+                savepoint.ptrmap.entry(page_no).or_insert(removed);
+            }
+        }
+        Ok(())
+    }
+
+    /// Takes a page off the freelist: `page_no` if it is given and free, otherwise any free page
+    /// that isn't after `max_page_no`. Returns the page taken, if any.
+    fn freelist_take(&self, page_no: Option<u32>, max_page_no: u32) -> Result<Option<u32>> {
+        const TRUNK_PAGE_HEADER_SIZE: usize = 8;
+        const LEAF_ENTRY_SIZE: usize = 4;
+        const RESERVED_SLOTS: usize = 2;
+
+        const TRUNK_PAGE_NEXT_PAGE_OFFSET: usize = 0; // Offset to next trunk page pointer
+        const TRUNK_PAGE_LEAF_COUNT_OFFSET: usize = 4; // Offset to leaf count
+
+        let wanted = |candidate: u32| match page_no {
+            Some(page_no) => candidate == page_no,
+            None => candidate <= max_page_no,
+        };
+        let leaf_offset = |idx: usize| TRUNK_PAGE_HEADER_SIZE + idx * LEAF_ENTRY_SIZE;
+        let max_free_list_entries = (self.usable_space() / LEAF_ENTRY_SIZE) - RESERVED_SLOTS;
+
+        let mut prev_trunk_page: Option<PageRef> = None;
+        let mut trunk_page_id = header_accessor::get_freelist_trunk_page(self)?;
+        while trunk_page_id != 0 {
+            let trunk_page = self.read_page_blocking(trunk_page_id as usize)?;
+            let contents = trunk_page.get_contents();
+            let next_trunk_page_id = contents.read_u32(TRUNK_PAGE_NEXT_PAGE_OFFSET);
+            let number_of_leaf_pages = contents.read_u32(TRUNK_PAGE_LEAF_COUNT_OFFSET) as usize;
+            if number_of_leaf_pages > max_free_list_entries {
+                return Err(LimboError::Corrupt(format!(
+                    "Freelist trunk page {trunk_page_id} has {number_of_leaf_pages} leaf pages"
+                )));
+            }
+
+            let leaf_idx =
+                (0..number_of_leaf_pages).find(|&idx| wanted(contents.read_u32(leaf_offset(idx))));
+            if let Some(leaf_idx) = leaf_idx {
+                let leaf_page_id = contents.read_u32(leaf_offset(leaf_idx));
+                trunk_page.set_dirty();
+                self.add_dirty(trunk_page_id as usize);
+                let last_leaf_page_id = contents.read_u32(leaf_offset(number_of_leaf_pages - 1));
+                contents.write_u32(leaf_offset(leaf_idx), last_leaf_page_id);
+                contents.write_u32(
+                    TRUNK_PAGE_LEAF_COUNT_OFFSET,
+                    number_of_leaf_pages as u32 - 1,
+                );
+                header_accessor::set_freelist_pages(
+                    self,
+                    header_accessor::get_freelist_pages(self)? - 1,
+                )?;
+                return Ok(Some(leaf_page_id));
+            }
+
+            if wanted(trunk_page_id) {
+                // The last leaf page of the trunk page takes its place, if it has any.
+                let replacement = if number_of_leaf_pages == 0 {
+                    next_trunk_page_id
+                } else {
+                    let new_trunk_page_id =
+                        contents.read_u32(leaf_offset(number_of_leaf_pages - 1));
+                    let new_trunk_page = self.read_page_blocking(new_trunk_page_id as usize)?;
+                    new_trunk_page.set_dirty();
+                    self.add_dirty(new_trunk_page_id as usize);
+                    let new_contents = new_trunk_page.get_contents();
+                    new_contents.write_u32(TRUNK_PAGE_NEXT_PAGE_OFFSET, next_trunk_page_id);
+                    new_contents.write_u32(
+                        TRUNK_PAGE_LEAF_COUNT_OFFSET,
+                        number_of_leaf_pages as u32 - 1,
+                    );
+                    let leaves = leaf_offset(0)..leaf_offset(number_of_leaf_pages - 1);
+                    new_contents.as_ptr()[leaves.clone()]
+                        .copy_from_slice(&contents.as_ptr()[leaves]);
+                    new_trunk_page_id
+                };
+                match prev_trunk_page {
+                    Some(prev_trunk_page) => {
+                        prev_trunk_page.set_dirty();
+                        self.add_dirty(prev_trunk_page.get().id);
+                        prev_trunk_page
+                            .get_contents()
+                            .write_u32(TRUNK_PAGE_NEXT_PAGE_OFFSET, replacement);
+                    }
+                    None => header_accessor::set_freelist_trunk_page(self, replacement)?,
+                }
+                header_accessor::set_freelist_pages(
+                    self,
+                    header_accessor::get_freelist_pages(self)? - 1,
+                )?;
+                return Ok(Some(trunk_page_id));
+            }
+
+            prev_trunk_page = Some(trunk_page);
+            trunk_page_id = next_trunk_page_id;
+        }
+        Ok(None)
+    }
+
+    /// Moves the page `from` into the free page `to`, updating the pointer to it on its parent
+    /// and the pointer map entries of the pages it points to. `entry` is the pointer map entry of
+    /// `from`. This is `relocatePage()` in SQLite.
+    fn relocate_page(&self, from: u32, to: u32, entry: PtrmapEntry) -> Result<()> {
+        tracing::debug!("relocate_page(from={}, to={}, entry={:?})", from, to, entry);
+        let page = self.read_page_blocking(from as usize)?;
+        let new_page = self.read_page_blocking(to as usize)?;
+        new_page.set_dirty();
+        self.add_dirty(to as usize);
+        let new_contents = new_page.get_contents();
+        new_contents
+            .as_ptr()
+            .copy_from_slice(page.get_contents().as_ptr());
+        new_contents.overflow_cells.clear();
+
+        match entry.entry_type {
+            PtrmapType::RootPage | PtrmapType::BTreeNode => {
+                self.ptrmap_record_children(&new_page)?
+            }
+            PtrmapType::Overflow1 | PtrmapType::Overflow2 => {
+                let next_page_no = new_contents.read_u32(0);
+                if next_page_no != 0 {
+                    self.ptrmap_record(next_page_no, PtrmapType::Overflow2, to);
+                }
+            }
+            PtrmapType::FreePage => {
+                return Err(LimboError::InternalError(format!(
+                    "Cannot relocate free page {from}"
+                )))
+            }
+        }
+        self.ptrmap_record(to, entry.entry_type, entry.parent_page_no);
+        if entry.entry_type == PtrmapType::RootPage {
+            return Ok(());
+        }
+
+        let parent_page_no = entry.parent_page_no;
+        let parent = self.read_page_blocking(parent_page_no as usize)?;
+        parent.set_dirty();
+        self.add_dirty(parent_page_no as usize);
+        let contents = parent.get_contents();
+        if entry.entry_type == PtrmapType::Overflow2 {
+            if contents.read_u32(0) != from {
+                return Err(LimboError::Corrupt(format!(
+                    "Overflow page {parent_page_no} doesn't point to page {from}"
+                )));
+            }
+            contents.write_u32(0, to);
+            return Ok(());
+        }
+        let usable_space = self.usable_space();
+        for idx in 0..contents.cell_count() {
+            let pointer_pos = match contents.cell_get(idx, usable_space)? {
+                BTreeCell::TableInteriorCell(TableInteriorCell {
+                    left_child_page, ..
+                })
+                | BTreeCell::IndexInteriorCell(IndexInteriorCell {
+                    left_child_page, ..
+                }) if entry.entry_type == PtrmapType::BTreeNode && left_child_page == from => {
+                    contents.cell_get_raw_region(idx, usable_space).0
+                }
+                BTreeCell::TableLeafCell(TableLeafCell {
+                    first_overflow_page: Some(first_overflow_page),
+                    ..
+                })
+                | BTreeCell::IndexInteriorCell(IndexInteriorCell {
+                    first_overflow_page: Some(first_overflow_page),
+                    ..
+                })
+                | BTreeCell::IndexLeafCell(IndexLeafCell {
+                    first_overflow_page: Some(first_overflow_page),
+                    ..
+                }) if entry.entry_type == PtrmapType::Overflow1 && first_overflow_page == from => {
+                    let (start, len) = contents.cell_get_raw_region(idx, usable_space);
+                    start + len - 4
+                }
+                _ => continue,
+            };
+            contents.as_ptr()[pointer_pos..pointer_pos + 4].copy_from_slice(&to.to_be_bytes());
+            return Ok(());
+        }
+        if entry.entry_type == PtrmapType::BTreeNode && contents.rightmost_pointer() == Some(from) {
+            contents.write_u32(BTREE_RIGHTMOST_PTR, to);
+            return Ok(());
+        }
+        Err(LimboError::Corrupt(format!(
+            "Page {parent_page_no} doesn't point to page {from}"
+        )))
+    }
+
+    /// Gets the page `page_no` at the end of the database out of the way of truncating the
+    /// database to `final_size` pages: a free page is taken off the freelist, unless `commit` is
+    /// set and the whole freelist is dropped afterwards, and any other page is moved into a free
+    /// page before `final_size`. This is `incrVacuumStep()` in SQLite.
+    fn vacuum_page(&self, page_no: u32, final_size: u32, commit: bool) -> Result<()> {
+        let page_size = header_accessor::get_page_size(self)? as usize;
+        if is_ptrmap_page(page_no, page_size) {
+            return Ok(());
+        }
+        let Some(entry) = self.io.block(|| self.ptrmap_get(page_no))? else {
+            return Err(LimboError::Corrupt(format!(
+                "Page {page_no} has no pointer map entry"
+            )));
+        };
+        match entry.entry_type {
+            PtrmapType::RootPage => Err(LimboError::Corrupt(format!(
+                "Root page {page_no} is after the free pages"
+            ))),
+            PtrmapType::FreePage => {
+                if !commit && self.freelist_take(Some(page_no), page_no)?.is_none() {
+                    return Err(LimboError::Corrupt(format!(
+                        "Free page {page_no} is not on the freelist"
+                    )));
+                }
+                Ok(())
+            }
+            _ => {
+                // When committing, whichever free page comes first is taken since the free pages
+                // after the final size are dropped anyway.
+                let max_page_no = if commit { u32::MAX } else { final_size };
+                let free_page_no = loop {
+                    match self.freelist_take(None, max_page_no)? {
+                        Some(free_page_no) if free_page_no > final_size => continue,
+                        Some(free_page_no) => break free_page_no,
+                        None => {
+                            return Err(LimboError::Corrupt(format!(
+                                "No free page to move page {page_no} into"
+                            )))
+                        }
+                    }
+                };
+                self.relocate_page(page_no, free_page_no, entry)
+            }
+        }
+    }
+
+    /// Brings the pointer map up to date before committing, and in `auto_vacuum = FULL` mode
+    /// moves the pages after the free pages into them and truncates the database. This is
+    /// `autoVacuumCommit()` in SQLite.
+    fn ptrmap_commit(&self) -> Result<()> {
+        if self.dirty_pages.borrow().is_empty() {
+            return Ok(());
+        }
+        let mode = self.get_auto_vacuum_mode();
+        if matches!(mode, AutoVacuumMode::None) {
+            return Ok(());
+        }
+        self.ptrmap_sync()?;
+        let free_pages = header_accessor::get_freelist_pages(self)?;
+        if matches!(mode, AutoVacuumMode::Full) && free_pages > 0 {
+            let db_size = header_accessor::get_database_size(self)?;
+            let page_size = header_accessor::get_page_size(self)? as usize;
+            let final_size = vacuum_final_size(db_size, free_pages, page_size)?;
+            for page_no in (final_size + 1..=db_size).rev() {
+                self.vacuum_page(page_no, final_size, true)?;
+            }
+            header_accessor::set_freelist_trunk_page(self, 0)?;
+            header_accessor::set_freelist_pages(self, 0)?;
+            self.truncate_db(final_size)?;
+            self.ptrmap_sync()?;
+        }
+        Ok(())
+    }
+}
+
+/// The number of pages of a database of `db_size` pages once its `free_pages` free pages and the
+/// pointer map pages that aren't needed anymore are cut off. This is `finalDbSize()` in SQLite.
+#[cfg(not(feature = "omit_autovacuum"))]
+fn vacuum_final_size(db_size: u32, free_pages: u32, page_size: usize) -> Result<u32> {
+    let entries = entries_per_ptrmap_page(page_size) as i64;
+    let ptrmap_pages = (free_pages as i64 - db_size as i64
+        + get_ptrmap_page_no_for_db_page(db_size, page_size) as i64
+        + entries)
+        / entries;
+    let mut final_size = db_size as i64 - free_pages as i64 - ptrmap_pages;
+    if final_size < 1 || final_size > db_size as i64 {
+        return Err(LimboError::Corrupt(format!(
+            "Database of {db_size} pages can't have {free_pages} free pages"
+        )));
+    }
+    while is_ptrmap_page(final_size as u32, page_size) {
+        final_size -= 1;
+    }
+    Ok(final_size as u32)
 }
 
 pub fn allocate_page(page_id: usize, buffer_pool: &Arc<BufferPool>, offset: usize) -> PageRef {
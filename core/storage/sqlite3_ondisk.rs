@@ -56,7 +56,9 @@ use crate::storage::btree::offset::{
 };
 use crate::storage::btree::{payload_overflow_threshold_max, payload_overflow_threshold_min};
 use crate::storage::buffer_pool::BufferPool;
+use crate::storage::compression::{slot_hole, CompressionMode};
 use crate::storage::database::DatabaseStorage;
+use crate::storage::encryption::EncryptionContext;
 use crate::storage::pager::Pager;
 use crate::types::{RawSlice, RefValue, SerialType, SerialTypeKind, TextRef, TextSubtype};
 use crate::{turso_assert, File, Result, WalFileShared};
@@ -167,7 +169,9 @@ pub struct DatabaseHeader {
     /// The "Application ID" set by PRAGMA application_id.
     pub application_id: u32,
 
-    /// Reserved for expansion. Must be zero.
+    /// Reserved for expansion. Must be zero in SQLite databases. The last 4 bytes, at offset
+    /// 88 of the header, record the compression of the pages of a database created with
+    /// `PRAGMA compression`, which SQLite can't read: 1=lz4, 2=zstd.
     pub reserved_for_expansion: [u8; 20],
 
     /// The version-valid-for number.
@@ -725,6 +729,8 @@ pub fn begin_read_page(
     buffer_pool: Arc<BufferPool>,
     page: PageRef,
     page_idx: usize,
+    encryption: Option<Arc<EncryptionContext>>,
+    compression: CompressionMode,
 ) -> Result<()> {
     tracing::trace!("begin_read_btree_page(page_idx = {})", page_idx);
     let buf = buffer_pool.get();
@@ -741,6 +747,23 @@ pub fn begin_read_page(
             "read({bytes_read}) != expected({buf_len})"
         );
         let page = page.clone();
+        if let Some(encryption) = &encryption {
+            if let Err(e) = encryption.decrypt_page(buf.borrow_mut().as_mut_slice(), page_idx) {
+                tracing::error!("{e}");
+                page.set_error();
+                return;
+            }
+        }
+        if compression != CompressionMode::None && page_idx != DATABASE_HEADER_PAGE_ID {
+            let slot = buf.borrow().as_slice().to_vec();
+            let decompressed =
+                compression.decompress_page(&slot, buf.borrow_mut().as_mut_slice(), page_idx);
+            if let Err(e) = decompressed {
+                tracing::error!("{e}");
+                page.set_error();
+                return;
+            }
+        }
         if finish_read_page(page_idx, buf, page.clone()).is_err() {
             page.set_error();
         }
@@ -789,11 +812,41 @@ pub fn begin_write_btree_page(
         let contents = page.contents.as_ref().unwrap();
         contents.buffer.clone()
     };
+    let compression = if page_id == DATABASE_HEADER_PAGE_ID {
+        CompressionMode::None
+    } else {
+        pager.compression()?
+    };
+    // The page in the cache stays in plaintext, so an encrypted or compressed copy is written.
+    let mut hole = None;
+    let buffer = match pager.encryption() {
+        Some(encryption) => {
+            let drop_fn = Rc::new(|_buf| {});
+            let mut encrypted = Buffer::allocate(buffer.borrow().len(), drop_fn);
+            encrypted
+                .as_mut_slice()
+                .copy_from_slice(buffer.borrow().as_slice());
+            encryption.encrypt_page(encrypted.as_mut_slice(), page_id)?;
+            #[allow(clippy::arc_with_non_send_sync)]
+            Arc::new(RefCell::new(encrypted))
+        }
+        None if compression != CompressionMode::None => {
+            let drop_fn = Rc::new(|_buf| {});
+            let mut compressed = Buffer::allocate(buffer.borrow().len(), drop_fn);
+            let used =
+                compression.compress_page(buffer.borrow().as_slice(), compressed.as_mut_slice())?;
+            hole = slot_hole(compressed.len(), used);
+            #[allow(clippy::arc_with_non_send_sync)]
+            Arc::new(RefCell::new(compressed))
+        }
+        None => buffer,
+    };
 
     *write_counter.borrow_mut() += 1;
     let clone_counter = write_counter.clone();
     let write_complete = {
         let buf_copy = buffer.clone();
+        let page_source = page_source.clone();
         Box::new(move |bytes_written: i32| {
             tracing::trace!("finish_write_btree_page");
             let buf_copy = buf_copy.clone();
@@ -805,6 +858,13 @@ pub fn begin_write_btree_page(
                 bytes_written == buf_len as i32,
                 "wrote({bytes_written}) != expected({buf_len})"
             );
+            // The zeros after a compressed page don't need to take space on disk.
+            if let Some((offset, len)) = hole {
+                let pos = (page_id - 1) * buf_len + offset;
+                if let Err(e) = page_source.punch_hole(pos, len) {
+                    tracing::warn!("failed to punch a hole after page {page_id}: {e}");
+                }
+            }
         })
     };
     let c = Completion::new_write(write_complete);
@@ -1410,6 +1470,10 @@ pub fn read_entire_wal_dumb(file: &Arc<dyn File>) -> Result<Arc<UnsafeCell<WalFi
         }
 
         let mut cumulative_checksum = (header_locked.checksum_1, header_locked.checksum_2);
+        let mut committed_checksum = cumulative_checksum;
+        // The frames after the last commit frame, which are only kept once a commit frame
+        // follows them.
+        let mut uncommitted_frames = Vec::new();
         let page_size_u32 = header_locked.page_size;
 
         if !(MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size_u32)
@@ -1472,39 +1536,44 @@ pub fn read_entire_wal_dumb(file: &Arc<dyn File>) -> Result<Arc<UnsafeCell<WalFi
                 calculated_frame_checksum.0,
                 calculated_frame_checksum.1
             );
+            // A frame that was only partly written when a transaction was interrupted ends the
+            // valid part of the WAL, like in SQLite.
             if calculated_frame_checksum != (frame_h_checksum_1, frame_h_checksum_2) {
-                panic!(
-                    "WAL frame checksum mismatch. Expected ({}, {}), Got ({}, {})",
+                tracing::debug!(
+                    "WAL frame checksum mismatch: expected ({}, {}), got ({}, {}), ignoring the rest of the WAL",
                     frame_h_checksum_1,
                     frame_h_checksum_2,
                     calculated_frame_checksum.0,
                     calculated_frame_checksum.1
                 );
+                break;
             }
 
             cumulative_checksum = calculated_frame_checksum;
-
-            wfs_data
-                .frame_cache
-                .lock()
-                .entry(frame_h_page_number as u64)
-                .or_default()
-                .push(frame_idx);
-            wfs_data
-                .pages_in_frames
-                .lock()
-                .push(frame_h_page_number as u64);
+            uncommitted_frames.push((frame_h_page_number as u64, frame_idx));
 
             let is_commit_record = frame_h_db_size > 0;
             if is_commit_record {
+                let mut frame_cache = wfs_data.frame_cache.lock();
+                let mut pages_in_frames = wfs_data.pages_in_frames.lock();
+                for (page_id, frame_id) in uncommitted_frames.drain(..) {
+                    let frames = frame_cache.entry(page_id).or_default();
+                    if frames.is_empty() {
+                        pages_in_frames.push(page_id);
+                    }
+                    frames.push(frame_id);
+                }
                 wfs_data.max_frame.store(frame_idx, Ordering::SeqCst);
+                committed_checksum = cumulative_checksum;
             }
 
             frame_idx += 1;
             current_offset += WAL_FRAME_HEADER_SIZE + page_size;
         }
 
-        wfs_data.last_checksum = cumulative_checksum;
+        // New frames are appended after the last commit frame, overwriting the ones of the
+        // transaction that didn't commit.
+        wfs_data.last_checksum = committed_checksum;
         wfs_data.loaded.store(true, Ordering::SeqCst);
     });
     let c = Completion::new_read(buf_for_pread, complete);
@@ -1543,6 +1612,7 @@ pub fn begin_write_wal_frame(
     write_counter: Rc<RefCell<usize>>,
     wal_header: &WalHeader,
     checksums: (u32, u32),
+    encryption: Option<&EncryptionContext>,
 ) -> Result<(u32, u32)> {
     let page_finish = page.clone();
     let page_id = page.get().id;
@@ -1579,6 +1649,12 @@ pub fn begin_write_wal_frame(
             buf[WAL_FRAME_HEADER_SIZE + content_len..WAL_FRAME_HEADER_SIZE + page_size as usize]
                 .fill(0);
         }
+        if let Some(encryption) = encryption {
+            encryption.encrypt_page(
+                &mut buf[WAL_FRAME_HEADER_SIZE..WAL_FRAME_HEADER_SIZE + page_size as usize],
+                page_id,
+            )?;
+        }
 
         let expects_be = wal_header.magic & 1;
         let use_native_endian = cfg!(target_endian = "big") as u32 == expects_be;
@@ -1632,7 +1708,11 @@ pub fn begin_write_wal_frame(
     Ok(checksums)
 }
 
-pub fn begin_write_wal_header(io: &Arc<dyn File>, header: &WalHeader) -> Result<()> {
+pub fn begin_write_wal_header(
+    io: &Arc<dyn File>,
+    header: &WalHeader,
+    write_counter: Rc<RefCell<usize>>,
+) -> Result<()> {
     tracing::trace!("begin_write_wal_header");
     let buffer = {
         let drop_fn = Rc::new(|_buf| {});
@@ -1653,7 +1733,10 @@ pub fn begin_write_wal_header(io: &Arc<dyn File>, header: &WalHeader) -> Result<
         Arc::new(RefCell::new(buffer))
     };
 
+    let clone_counter = write_counter.clone();
+    *write_counter.borrow_mut() += 1;
     let write_complete = move |bytes_written: i32| {
+        *clone_counter.borrow_mut() -= 1;
         turso_assert!(
             bytes_written == WAL_HEADER_SIZE as i32,
             "wal header wrote({bytes_written}) != expected({WAL_HEADER_SIZE})"
@@ -1661,7 +1744,11 @@ pub fn begin_write_wal_header(io: &Arc<dyn File>, header: &WalHeader) -> Result<
     };
     #[allow(clippy::arc_with_non_send_sync)]
     let c = Completion::new_write(write_complete);
-    io.pwrite(0, buffer.clone(), c.into())?;
+    let res = io.pwrite(0, buffer.clone(), c.into());
+    if res.is_err() {
+        *write_counter.borrow_mut() -= 1;
+    }
+    res?;
     Ok(())
 }
 
@@ -0,0 +1,269 @@
+//! The encoding of the text of the database file, set with `PRAGMA encoding` before the database
+//! is created.
+//!
+//! Text is always UTF-8 in memory. The records of a UTF-16 database are converted to UTF-8 as
+//! btree cursors read them, and back to UTF-16 as they write them, so nothing above the btree
+//! knows about the encoding. The only difference it makes there is the order of the BINARY
+//! collation, which like in SQLite compares the bytes of the text in the encoding of the database,
+//! so that the indexes of a database created by SQLite are in the order they are searched in.
+//! Comparisons have no access to the connection, so they find the encoding in a thread-local set
+//! by [TextEncodingGuard] while a statement runs.
+
+use std::{borrow::Cow, cell::Cell, cmp::Ordering};
+
+use strum::{Display, EnumString};
+
+use crate::{
+    storage::sqlite3_ondisk::{read_varint, write_varint, write_varint_to_vec},
+    types::{SerialType, SerialTypeKind},
+    LimboError, Result,
+};
+
+/// The encoding of the text of a database, recorded in its header.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, EnumString, Display)]
+#[strum(ascii_case_insensitive)]
+pub enum TextEncoding {
+    #[default]
+    #[strum(to_string = "UTF-8", serialize = "UTF8")]
+    Utf8,
+    // Like in SQLite, UTF-16 without a byte order means the native one, which is little-endian
+    // on the platforms this runs on.
+    #[strum(
+        to_string = "UTF-16le",
+        serialize = "UTF16le",
+        serialize = "UTF-16",
+        serialize = "UTF16"
+    )]
+    Utf16le,
+    #[strum(to_string = "UTF-16be", serialize = "UTF16be")]
+    Utf16be,
+}
+
+impl TextEncoding {
+    /// The encoding recorded in the database header as `id`. A database that SQLite created
+    /// without writing anything to it yet has none, and is UTF-8.
+    pub fn from_header_id(id: u32) -> Result<Self> {
+        match id {
+            0 | 1 => Ok(TextEncoding::Utf8),
+            2 => Ok(TextEncoding::Utf16le),
+            3 => Ok(TextEncoding::Utf16be),
+            _ => Err(LimboError::Corrupt(format!(
+                "unknown text encoding {id} in the database header"
+            ))),
+        }
+    }
+
+    pub fn header_id(&self) -> u32 {
+        match self {
+            TextEncoding::Utf8 => 1,
+            TextEncoding::Utf16le => 2,
+            TextEncoding::Utf16be => 3,
+        }
+    }
+
+    /// Decodes text stored in this encoding. Like in SQLite, invalid text isn't an error, its
+    /// invalid sequences are replaced.
+    pub fn decode<'a>(&self, bytes: &'a [u8]) -> Cow<'a, str> {
+        let units = |to_u16: fn([u8; 2]) -> u16| {
+            bytes
+                .chunks_exact(2)
+                .map(move |unit| to_u16([unit[0], unit[1]]))
+        };
+        match self {
+            TextEncoding::Utf8 => String::from_utf8_lossy(bytes),
+            TextEncoding::Utf16le => Cow::Owned(
+                char::decode_utf16(units(u16::from_le_bytes))
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect(),
+            ),
+            TextEncoding::Utf16be => Cow::Owned(
+                char::decode_utf16(units(u16::from_be_bytes))
+                    .map(|c| c.unwrap_or(char::REPLACEMENT_CHARACTER))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Appends `text` encoded in this encoding to `buf`.
+    pub fn encode(&self, text: &str, buf: &mut Vec<u8>) {
+        match self {
+            TextEncoding::Utf8 => buf.extend_from_slice(text.as_bytes()),
+            TextEncoding::Utf16le => buf.extend(text.encode_utf16().flat_map(u16::to_le_bytes)),
+            TextEncoding::Utf16be => buf.extend(text.encode_utf16().flat_map(u16::to_be_bytes)),
+        }
+    }
+}
+
+thread_local! {
+    /// The encoding of the main database of the statement being run on this thread.
+    static CURRENT_TEXT_ENCODING: Cell<TextEncoding> = const { Cell::new(TextEncoding::Utf8) };
+}
+
+/// Makes `encoding` the one BINARY compares text in until it is dropped, when the one set before
+/// is restored.
+pub(crate) struct TextEncodingGuard(TextEncoding);
+
+impl TextEncodingGuard {
+    pub(crate) fn new(encoding: TextEncoding) -> Self {
+        Self(CURRENT_TEXT_ENCODING.replace(encoding))
+    }
+}
+
+impl Drop for TextEncodingGuard {
+    fn drop(&mut self) {
+        CURRENT_TEXT_ENCODING.set(self.0);
+    }
+}
+
+/// The bytes of `text` in the encoding of the database, which is what hex() shows of text.
+pub fn database_bytes(text: &str) -> Cow<'_, [u8]> {
+    match CURRENT_TEXT_ENCODING.get() {
+        TextEncoding::Utf8 => Cow::Borrowed(text.as_bytes()),
+        encoding => {
+            let mut buf = Vec::with_capacity(text.len() * 2);
+            encoding.encode(text, &mut buf);
+            Cow::Owned(buf)
+        }
+    }
+}
+
+/// Compares two strings as the BINARY collation does, by the bytes of their encoding in the
+/// database. Text in UTF-16le is ordered differently from the code points of its characters.
+pub fn binary_cmp(lhs: &str, rhs: &str) -> Ordering {
+    match CURRENT_TEXT_ENCODING.get() {
+        TextEncoding::Utf8 => lhs.cmp(rhs),
+        TextEncoding::Utf16le => lhs
+            .encode_utf16()
+            .flat_map(u16::to_le_bytes)
+            .cmp(rhs.encode_utf16().flat_map(u16::to_le_bytes)),
+        TextEncoding::Utf16be => lhs.encode_utf16().cmp(rhs.encode_utf16()),
+    }
+}
+
+/// Rewrites the record in `payload` with its text converted from the encoding `from` to `to`.
+/// The last value of the payload may be cut short, since the zeros of a zeroblob() at the end of
+/// a record being written aren't in it.
+pub fn transcode_record(payload: &[u8], from: TextEncoding, to: TextEncoding) -> Result<Vec<u8>> {
+    let (header_size, mut pos) = read_varint(payload)?;
+    let header_size = header_size as usize;
+    if header_size > payload.len() {
+        return Err(LimboError::Corrupt(format!(
+            "record header of {header_size} bytes in a payload of {}",
+            payload.len()
+        )));
+    }
+    let mut serial_types = Vec::new();
+    while pos < header_size {
+        let (serial_type, n) = read_varint(&payload[pos..])?;
+        serial_types.push(SerialType::try_from(serial_type)?);
+        pos += n;
+    }
+
+    let mut header = Vec::with_capacity(header_size);
+    let mut body = Vec::with_capacity(payload.len() - header_size);
+    let mut offset = header_size;
+    for serial_type in serial_types {
+        let end = (offset + serial_type.size()).min(payload.len());
+        let value = &payload[offset..end];
+        offset = end;
+        if serial_type.kind() == SerialTypeKind::Text {
+            let start = body.len();
+            to.encode(&from.decode(value), &mut body);
+            let text_serial_type = SerialType::text((body.len() - start) as u64);
+            write_varint_to_vec(text_serial_type.into(), &mut header);
+        } else {
+            write_varint_to_vec(serial_type.into(), &mut header);
+            body.extend_from_slice(value);
+        }
+    }
+
+    // The size of the header counts the varint it's written in.
+    let mut varint = [0; 9];
+    let mut header_size = header.len() + 1;
+    while header.len() + write_varint(&mut varint, header_size as u64) > header_size {
+        header_size += 1;
+    }
+    let mut record = Vec::with_capacity(header_size + body.len());
+    write_varint_to_vec(header_size as u64, &mut record);
+    record.extend_from_slice(&header);
+    record.extend_from_slice(&body);
+    Ok(record)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types::{ImmutableRecord, RefValue, Value};
+
+    fn record(values: &[Value]) -> Vec<u8> {
+        ImmutableRecord::from_values(values, values.len())
+            .get_payload()
+            .to_vec()
+    }
+
+    #[test]
+    fn test_names() {
+        assert_eq!("utf-16BE".parse(), Ok(TextEncoding::Utf16be));
+        assert_eq!("UTF16".parse(), Ok(TextEncoding::Utf16le));
+        assert_eq!(TextEncoding::Utf16le.to_string(), "UTF-16le");
+        assert!("latin1".parse::<TextEncoding>().is_err());
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let text = "héllo 𝄞";
+        for encoding in [
+            TextEncoding::Utf8,
+            TextEncoding::Utf16le,
+            TextEncoding::Utf16be,
+        ] {
+            let mut buf = Vec::new();
+            encoding.encode(text, &mut buf);
+            assert_eq!(encoding.decode(&buf), text);
+        }
+        let mut buf = Vec::new();
+        TextEncoding::Utf16be.encode("ab", &mut buf);
+        assert_eq!(buf, [0, b'a', 0, b'b']);
+        assert_eq!(TextEncoding::Utf16le.decode(&[0x00, 0xd8]), "\u{fffd}");
+    }
+
+    #[test]
+    fn test_transcode_record() {
+        let values = [
+            Value::Integer(1),
+            Value::build_text("abc"),
+            Value::Null,
+            Value::build_text("é".repeat(100)),
+            Value::Blob(vec![1, 2, 3]),
+        ];
+        let utf8 = record(&values);
+        let utf16 = transcode_record(&utf8, TextEncoding::Utf8, TextEncoding::Utf16be).unwrap();
+        assert_ne!(utf8, utf16);
+
+        let mut decoded = ImmutableRecord::new(utf16.len());
+        decoded.start_serialization(&utf16);
+        let RefValue::Text(text) = decoded.get_value(1).unwrap() else {
+            panic!("expected text");
+        };
+        assert_eq!(text.value.to_slice(), [0, b'a', 0, b'b', 0, b'c']);
+
+        let back = transcode_record(&utf16, TextEncoding::Utf16be, TextEncoding::Utf8).unwrap();
+        assert_eq!(back, utf8);
+    }
+
+    #[test]
+    fn test_binary_cmp() {
+        // U+0100 is 00 01 in UTF-16le and U+00FF is FF 00.
+        assert_eq!(binary_cmp("\u{ff}", "\u{100}"), Ordering::Less);
+        {
+            let _encoding = TextEncodingGuard::new(TextEncoding::Utf16le);
+            assert_eq!(binary_cmp("\u{ff}", "\u{100}"), Ordering::Greater);
+            assert_eq!(binary_cmp("ab", "abc"), Ordering::Less);
+        }
+        // Surrogates come before U+E000 in UTF-16, but not in UTF-8.
+        assert_eq!(binary_cmp("𝄞", "\u{e000}"), Ordering::Greater);
+        let _encoding = TextEncodingGuard::new(TextEncoding::Utf16be);
+        assert_eq!(binary_cmp("𝄞", "\u{e000}"), Ordering::Less);
+        assert_eq!(binary_cmp("a", "b"), Ordering::Less);
+    }
+}
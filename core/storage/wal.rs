@@ -3,7 +3,7 @@
 
 use std::array;
 use std::cell::UnsafeCell;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use strum::EnumString;
 use tracing::{instrument, Level};
 
@@ -30,8 +30,9 @@ use crate::{Completion, Page};
 use self::sqlite3_ondisk::{checksum_wal, PageContent, WAL_MAGIC_BE, WAL_MAGIC_LE};
 
 use super::buffer_pool::BufferPool;
+use super::encryption::EncryptionContext;
 use super::pager::{PageRef, Pager};
-use super::sqlite3_ondisk::{self, begin_write_btree_page, WalHeader};
+use super::sqlite3_ondisk::{self, begin_write_btree_page, WalHeader, DATABASE_HEADER_PAGE_ID};
 
 pub const READMARK_NOT_USED: u32 = 0xffffffff;
 
@@ -202,6 +203,11 @@ pub trait Wal {
     /// Begin a write transaction.
     fn begin_write_tx(&mut self) -> Result<LimboResult>;
 
+    /// Begin the write transaction of a `BEGIN CONCURRENT` transaction that is committing.
+    /// Unlike [Wal::begin_write_tx], the snapshot may be behind the WAL as long as none of the
+    /// frames committed since contains one of `pages`, and is then moved to the end of the WAL.
+    fn begin_concurrent_write_tx(&mut self, pages: &HashSet<usize>) -> Result<LimboResult>;
+
     /// End a read transaction.
     fn end_read_tx(&self) -> Result<LimboResult>;
 
@@ -211,8 +217,14 @@ pub trait Wal {
     /// Find the latest frame containing a page.
     fn find_frame(&self, page_id: u64) -> Result<Option<u64>>;
 
-    /// Read a frame from the WAL.
-    fn read_frame(&self, frame_id: u64, page: PageRef, buffer_pool: Arc<BufferPool>) -> Result<()>;
+    /// Read a frame from the WAL, decrypting the page with `encryption` if it's set.
+    fn read_frame(
+        &self,
+        frame_id: u64,
+        page: PageRef,
+        buffer_pool: Arc<BufferPool>,
+        encryption: Option<Arc<EncryptionContext>>,
+    ) -> Result<()>;
 
     /// Read a frame from the WAL.
     fn read_frame_raw(
@@ -228,11 +240,13 @@ pub trait Wal {
     /// db_size > 0    -> last frame written in transaction
     /// db_size == 0   -> non-last frame written in transaction
     /// write_counter is the counter we use to track when the I/O operation starts and completes
+    /// encryption encrypts the page in the frame if it's set
     fn append_frame(
         &mut self,
         page: PageRef,
         db_size: u32,
         write_counter: Rc<RefCell<usize>>,
+        encryption: Option<&EncryptionContext>,
     ) -> Result<()>;
 
     /// Complete append of frames by updating shared wal state. Before this
@@ -240,6 +254,10 @@ pub trait Wal {
     fn finish_append_frames_commit(&mut self) -> Result<()>;
 
     fn should_checkpoint(&self) -> bool;
+    /// The number of frames in the WAL at which committing a transaction checkpoints it, or 0
+    /// if that is disabled.
+    fn checkpoint_threshold(&self) -> usize;
+    fn set_checkpoint_threshold(&mut self, threshold: usize);
     fn checkpoint(
         &mut self,
         pager: &Pager,
@@ -250,6 +268,9 @@ pub trait Wal {
     fn get_max_frame_in_wal(&self) -> u64;
     fn get_max_frame(&self) -> u64;
     fn get_min_frame(&self) -> u64;
+    /// The number of times the WAL was reset by a checkpoint, which together with the max frame
+    /// identifies the contents of the WAL.
+    fn get_checkpoint_seq(&self) -> u32;
     fn rollback(&mut self) -> Result<()>;
 }
 
@@ -272,6 +293,10 @@ impl Wal for DummyWAL {
         Ok(LimboResult::Ok)
     }
 
+    fn begin_concurrent_write_tx(&mut self, _pages: &HashSet<usize>) -> Result<LimboResult> {
+        Ok(LimboResult::Ok)
+    }
+
     fn end_write_tx(&self) -> Result<LimboResult> {
         Ok(LimboResult::Ok)
     }
@@ -285,6 +310,7 @@ impl Wal for DummyWAL {
         _frame_id: u64,
         _page: crate::PageRef,
         _buffer_pool: Arc<BufferPool>,
+        _encryption: Option<Arc<EncryptionContext>>,
     ) -> Result<()> {
         Ok(())
     }
@@ -304,6 +330,7 @@ impl Wal for DummyWAL {
         _page: crate::PageRef,
         _db_size: u32,
         _write_counter: Rc<RefCell<usize>>,
+        _encryption: Option<&EncryptionContext>,
     ) -> Result<()> {
         Ok(())
     }
@@ -312,6 +339,12 @@ impl Wal for DummyWAL {
         false
     }
 
+    fn checkpoint_threshold(&self) -> usize {
+        0
+    }
+
+    fn set_checkpoint_threshold(&mut self, _threshold: usize) {}
+
     fn checkpoint(
         &mut self,
         _pager: &Pager,
@@ -337,6 +370,10 @@ impl Wal for DummyWAL {
         0
     }
 
+    fn get_checkpoint_seq(&self) -> u32 {
+        0
+    }
+
     fn finish_append_frames_commit(&mut self) -> Result<()> {
         tracing::trace!("finish_append_frames_commit_dumb");
         Ok(())
@@ -379,6 +416,8 @@ struct OngoingCheckpoint {
     min_frame: u64,
     max_frame: u64,
     current_page: u64,
+    /// The size of the database in bytes as of the copy of page 1 that was backfilled, if any.
+    db_size_bytes: Option<u64>,
 }
 
 impl fmt::Debug for OngoingCheckpoint {
@@ -564,11 +603,55 @@ impl Wal for WalFile {
     /// Begin a write transaction
     #[instrument(skip_all, level = Level::DEBUG)]
     fn begin_write_tx(&mut self) -> Result<LimboResult> {
-        let busy = !self.get_shared().write_lock.write();
+        let shared = self.get_shared();
+        let busy = !shared.write_lock.write();
         tracing::debug!("begin_write_transaction(busy={})", busy);
         if busy {
             return Ok(LimboResult::Busy);
         }
+        // A reader whose snapshot is behind the WAL can't write, since it would overwrite the
+        // changes committed after the snapshot was taken.
+        if shared.max_frame.load(Ordering::SeqCst) != self.max_frame {
+            shared.write_lock.unlock();
+            tracing::debug!("begin_write_transaction(busy=true, stale snapshot)");
+            return Ok(LimboResult::Busy);
+        }
+        Ok(LimboResult::Ok)
+    }
+
+    /// Begin the write transaction of a committing `BEGIN CONCURRENT` transaction
+    #[instrument(skip_all, level = Level::DEBUG)]
+    fn begin_concurrent_write_tx(&mut self, pages: &HashSet<usize>) -> Result<LimboResult> {
+        let shared = self.get_shared();
+        let busy = !shared.write_lock.write();
+        tracing::debug!("begin_concurrent_write_transaction(busy={})", busy);
+        if busy {
+            return Ok(LimboResult::Busy);
+        }
+        let max_frame = shared.max_frame.load(Ordering::SeqCst);
+        if max_frame == self.max_frame {
+            return Ok(LimboResult::Ok);
+        }
+        let conflict = {
+            let frame_cache = shared.frame_cache.lock();
+            pages.iter().any(|page_id| {
+                frame_cache
+                    .get(&(*page_id as u64))
+                    .and_then(|frames| frames.last())
+                    .is_some_and(|frame| *frame > self.max_frame)
+            })
+        };
+        if conflict {
+            shared.write_lock.unlock();
+            tracing::debug!("begin_concurrent_write_transaction(conflict)");
+            return Err(LimboError::WriteWriteConflict);
+        }
+        // The frames of this transaction are appended after the ones committed in the meantime.
+        let last_checksum = shared.last_checksum;
+        let start_pages_in_frames = shared.pages_in_frames.lock().len();
+        self.max_frame = max_frame;
+        self.last_checksum = last_checksum;
+        self.start_pages_in_frames = start_pages_in_frames;
         Ok(LimboResult::Ok)
     }
 
@@ -600,7 +683,13 @@ impl Wal for WalFile {
 
     /// Read a frame from the WAL.
     #[instrument(skip_all, level = Level::DEBUG)]
-    fn read_frame(&self, frame_id: u64, page: PageRef, buffer_pool: Arc<BufferPool>) -> Result<()> {
+    fn read_frame(
+        &self,
+        frame_id: u64,
+        page: PageRef,
+        buffer_pool: Arc<BufferPool>,
+        encryption: Option<Arc<EncryptionContext>>,
+    ) -> Result<()> {
         tracing::debug!("read_frame({})", frame_id);
         let offset = self.frame_offset(frame_id);
         page.set_locked();
@@ -612,6 +701,14 @@ impl Wal for WalFile {
                 "read({bytes_read}) less than expected({buf_len})"
             );
             let frame = frame.clone();
+            if let Some(encryption) = &encryption {
+                let page_id = page.get().id;
+                if let Err(e) = encryption.decrypt_page(buf.borrow_mut().as_mut_slice(), page_id) {
+                    tracing::error!("{e}");
+                    frame.set_error();
+                    return;
+                }
+            }
             finish_read_page(page.get().id, buf, frame).unwrap();
         });
         begin_read_wal_frame(
@@ -661,6 +758,7 @@ impl Wal for WalFile {
         page: PageRef,
         db_size: u32,
         write_counter: Rc<RefCell<usize>>,
+        encryption: Option<&EncryptionContext>,
     ) -> Result<()> {
         let page_id = page.get().id;
         let max_frame = self.max_frame;
@@ -671,6 +769,15 @@ impl Wal for WalFile {
             let shared = self.get_shared();
             let header = shared.wal_header.clone();
             let header = header.lock();
+            if frame_id == 1 {
+                // The WAL is written from the start again after a checkpoint restarted it, which
+                // also changed the salts of the header.
+                sqlite3_ondisk::begin_write_wal_header(
+                    &shared.file,
+                    &header,
+                    write_counter.clone(),
+                )?;
+            }
             let checksums = self.last_checksum;
             begin_write_wal_frame(
                 &shared.file,
@@ -681,6 +788,7 @@ impl Wal for WalFile {
                 write_counter,
                 &header,
                 checksums,
+                encryption,
             )?
         };
         self.last_checksum = checksums;
@@ -704,7 +812,15 @@ impl Wal for WalFile {
     fn should_checkpoint(&self) -> bool {
         let shared = self.get_shared();
         let frame_id = shared.max_frame.load(Ordering::SeqCst) as usize;
-        frame_id >= self.checkpoint_threshold
+        self.checkpoint_threshold > 0 && frame_id >= self.checkpoint_threshold
+    }
+
+    fn checkpoint_threshold(&self) -> usize {
+        self.checkpoint_threshold
+    }
+
+    fn set_checkpoint_threshold(&mut self, threshold: usize) {
+        self.checkpoint_threshold = threshold;
     }
 
     #[instrument(skip_all, level = Level::DEBUG)]
@@ -714,22 +830,24 @@ impl Wal for WalFile {
         write_counter: Rc<RefCell<usize>>,
         mode: CheckpointMode,
     ) -> Result<IOResult<CheckpointResult>> {
-        assert!(
-            matches!(mode, CheckpointMode::Passive),
-            "only passive mode supported for now"
-        );
         'checkpoint_loop: loop {
             let state = self.ongoing_checkpoint.state;
             tracing::debug!(?state);
             match state {
                 CheckpointState::Start => {
-                    // TODO(pere): check what frames are safe to checkpoint between many readers!
-                    self.ongoing_checkpoint.min_frame = self.min_frame;
                     let shared = self.get_shared();
                     let busy = !shared.checkpoint_lock.write();
                     if busy {
                         return Err(LimboError::Busy);
                     }
+                    // All modes but passive keep writers out until the checkpoint is done.
+                    if !matches!(mode, CheckpointMode::Passive) && !shared.write_lock.write() {
+                        shared.checkpoint_lock.unlock();
+                        return Err(LimboError::Busy);
+                    }
+                    // The frames up to nbackfills are in the database file already.
+                    let min_frame = shared.nbackfills.load(Ordering::SeqCst) + 1;
+                    // TODO(pere): check what frames are safe to checkpoint between many readers!
                     let mut max_safe_frame = shared.max_frame.load(Ordering::SeqCst);
                     for (read_lock_idx, read_lock) in shared.read_locks.iter_mut().enumerate() {
                         let this_mark = read_lock.value.load(Ordering::SeqCst);
@@ -748,8 +866,10 @@ impl Wal for WalFile {
                             }
                         }
                     }
+                    self.ongoing_checkpoint.min_frame = min_frame;
                     self.ongoing_checkpoint.max_frame = max_safe_frame;
                     self.ongoing_checkpoint.current_page = 0;
+                    self.ongoing_checkpoint.db_size_bytes = None;
                     self.ongoing_checkpoint.state = CheckpointState::ReadFrame;
                     tracing::trace!(
                         "checkpoint_start(min_frame={}, max_frame={})",
@@ -790,6 +910,7 @@ impl Wal for WalFile {
                                 *frame,
                                 self.ongoing_checkpoint.page.clone(),
                                 self.buffer_pool.clone(),
+                                pager.encryption(),
                             )?;
                             self.ongoing_checkpoint.state = CheckpointState::WaitReadFrame;
                             continue 'checkpoint_loop;
@@ -805,6 +926,13 @@ impl Wal for WalFile {
                     }
                 }
                 CheckpointState::WritePage => {
+                    if self.ongoing_checkpoint.page.get().id == DATABASE_HEADER_PAGE_ID {
+                        let contents = self.ongoing_checkpoint.page.get_contents();
+                        let buf = contents.as_ptr();
+                        let db_size = u32::from_be_bytes([buf[28], buf[29], buf[30], buf[31]]);
+                        self.ongoing_checkpoint.db_size_bytes =
+                            Some(db_size as u64 * buf.len() as u64);
+                    }
                     self.ongoing_checkpoint.page.set_dirty();
                     begin_write_btree_page(
                         pager,
@@ -836,8 +964,8 @@ impl Wal for WalFile {
                     if *write_counter.borrow() > 0 {
                         return Ok(IOResult::IO);
                     }
+                    self.ongoing_checkpoint.state = CheckpointState::Start;
                     let shared = self.get_shared();
-                    shared.checkpoint_lock.unlock();
 
                     // Record two num pages fields to return as checkpoint result to caller.
                     // Ref: pnLog, pnCkpt on https://www.sqlite.org/c3ref/wal_checkpoint_v2.html
@@ -847,30 +975,31 @@ impl Wal for WalFile {
                     };
                     let everything_backfilled = shared.max_frame.load(Ordering::SeqCst)
                         == self.ongoing_checkpoint.max_frame;
+                    shared
+                        .nbackfills
+                        .store(self.ongoing_checkpoint.max_frame, Ordering::SeqCst);
+                    // Vacuuming an auto-vacuum database may have left pages after the end of the
+                    // database file.
                     if everything_backfilled {
-                        // TODO: Even in Passive mode, if everything was backfilled we should
-                        // truncate and fsync the *db file*
-
-                        // To properly reset the *wal file* we will need restart and/or truncate mode.
-                        // Currently, it will grow the WAL file indefinetly, but don't resetting is better than breaking.
-                        // Check: https://github.com/sqlite/sqlite/blob/2bd9f69d40dd240c4122c6d02f1ff447e7b5c098/src/wal.c#L2193
-                        if !matches!(mode, CheckpointMode::Passive) {
-                            // Here we know that we backfilled everything, therefore we can safely
-                            // reset the wal.
-                            shared.frame_cache.lock().clear();
-                            shared.pages_in_frames.lock().clear();
-                            shared.max_frame.store(0, Ordering::SeqCst);
-                            shared.nbackfills.store(0, Ordering::SeqCst);
-                            // TODO: if all frames were backfilled into the db file, calls fsync
-                            // TODO(pere): truncate wal file here.
+                        if let Some(db_size_bytes) = self.ongoing_checkpoint.db_size_bytes.take() {
+                            self.truncate_db_file(pager, db_size_bytes)?;
                         }
-                    } else {
-                        shared
-                            .nbackfills
-                            .store(self.ongoing_checkpoint.max_frame, Ordering::SeqCst);
                     }
-                    self.ongoing_checkpoint.state = CheckpointState::Start;
-                    return Ok(IOResult::Done(checkpoint_result));
+                    let result = match mode {
+                        CheckpointMode::Passive => Ok(checkpoint_result),
+                        // A reader kept some of the frames from being backfilled.
+                        _ if !everything_backfilled => Err(LimboError::Busy),
+                        CheckpointMode::Full => Ok(checkpoint_result),
+                        CheckpointMode::Restart | CheckpointMode::Truncate => self
+                            .restart_log(pager, matches!(mode, CheckpointMode::Truncate))
+                            .map(|_| checkpoint_result),
+                    };
+                    let shared = self.get_shared();
+                    if !matches!(mode, CheckpointMode::Passive) {
+                        shared.write_lock.unlock();
+                    }
+                    shared.checkpoint_lock.unlock();
+                    return result.map(IOResult::Done);
                 }
             }
         }
@@ -916,6 +1045,10 @@ impl Wal for WalFile {
         self.min_frame
     }
 
+    fn get_checkpoint_seq(&self) -> u32 {
+        self.get_shared().wal_header.lock().checkpoint_seq
+    }
+
     #[instrument(err, skip_all, level = Level::DEBUG)]
     fn rollback(&mut self) -> Result<()> {
         // TODO(pere): have to remove things from frame_cache because they are no longer valid.
@@ -984,6 +1117,7 @@ impl WalFile {
                 min_frame: 0,
                 max_frame: 0,
                 current_page: 0,
+                db_size_bytes: None,
             },
             checkpoint_threshold: 1000,
             buffer_pool,
@@ -1012,6 +1146,95 @@ impl WalFile {
     fn get_shared(&self) -> &mut WalFileShared {
         unsafe { self.shared.get().as_mut().unwrap() }
     }
+
+    /// Starts the WAL over after all of it was backfilled, so that the next transaction writes
+    /// its frames from the start of the file instead of growing it, and truncates the file if
+    /// asked to. The caller must hold the write lock. Fails with [LimboError::Busy] if a reader
+    /// is still using the WAL.
+    // Check: https://github.com/sqlite/sqlite/blob/2bd9f69d40dd240c4122c6d02f1ff447e7b5c098/src/wal.c#L2193
+    fn truncate_db_file(&self, pager: &Pager, db_size_bytes: u64) -> Result<()> {
+        if db_size_bytes == 0 || pager.db_file.size()? <= db_size_bytes {
+            return Ok(());
+        }
+        tracing::debug!("truncate_db_file(db_size_bytes={})", db_size_bytes);
+        let c = Completion::new_trunc(|_| {});
+        let c = pager.db_file.truncate(db_size_bytes as usize, c)?;
+        while !c.is_completed() {
+            self.io.run_once()?;
+        }
+        Ok(())
+    }
+
+    fn restart_log(&mut self, pager: &Pager, truncate: bool) -> Result<()> {
+        // The frames are going to be overwritten, so the pages backfilled from them must be
+        // durable first.
+        let syncing = Rc::new(RefCell::new(false));
+        sqlite3_ondisk::begin_sync(pager.db_file.clone(), syncing.clone())?;
+        while *syncing.borrow() {
+            self.io.run_once()?;
+        }
+
+        let shared = self.get_shared();
+        let mut locked = 0;
+        while locked < shared.read_locks.len() && shared.read_locks[locked].write() {
+            locked += 1;
+        }
+        if locked < shared.read_locks.len() {
+            for read_lock in &mut shared.read_locks[..locked] {
+                read_lock.unlock();
+            }
+            return Err(LimboError::Busy);
+        }
+        shared.frame_cache.lock().clear();
+        shared.pages_in_frames.lock().clear();
+        shared.max_frame.store(0, Ordering::SeqCst);
+        shared.nbackfills.store(0, Ordering::SeqCst);
+        let checksums = {
+            let mut header = shared.wal_header.lock();
+            header.checkpoint_seq += 1;
+            // New salts keep the frames of the previous log from being read as part of this one.
+            header.salt_1 = header.salt_1.wrapping_add(1);
+            header.salt_2 = self.io.generate_random_number() as u32;
+            let checksums = wal_header_checksums(&header);
+            header.checksum_1 = checksums.0;
+            header.checksum_2 = checksums.1;
+            checksums
+        };
+        shared.last_checksum = checksums;
+        for (index, read_lock) in shared.read_locks.iter_mut().enumerate() {
+            let mark = if index == 0 { 0 } else { READMARK_NOT_USED };
+            read_lock.value.store(mark, Ordering::SeqCst);
+            read_lock.unlock();
+        }
+        let file = shared.file.clone();
+        self.max_frame = 0;
+        self.last_checksum = checksums;
+        tracing::debug!("restart_log(checkpoint_seq={})", self.get_checkpoint_seq());
+
+        if truncate {
+            // The header is written again together with the first frame.
+            let c = Completion::new_trunc(|_| {});
+            let c = file.truncate(0, c.into())?;
+            while !c.is_completed() {
+                self.io.run_once()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Computes the checksum of the first 24 bytes of a WAL header, which the header stores in its
+/// last 8 bytes.
+fn wal_header_checksums(header: &WalHeader) -> (u32, u32) {
+    // If target_endian is already big then we don't care but if it isn't, the header hasn't been
+    // encoded to big endian yet, therefore we want to swap bytes to compute this checksum.
+    let native = cfg!(target_endian = "big");
+    checksum_wal(
+        &header.as_bytes()[..WAL_HEADER_SIZE - 2 * 4], // first 24 bytes
+        header,
+        (0, 0),
+        native,
+    )
 }
 
 impl WalFileShared {
@@ -1060,20 +1283,10 @@ impl WalFileShared {
             checksum_1: 0,
             checksum_2: 0,
         };
-        let native = cfg!(target_endian = "big"); // if target_endian is
-                                                  // already big then we don't care but if isn't, header hasn't yet been
-                                                  // encoded to big endian, therefore we want to swap bytes to compute this
-                                                  // checksum.
-        let checksums = (0, 0);
-        let checksums = checksum_wal(
-            &wal_header.as_bytes()[..WAL_HEADER_SIZE - 2 * 4], // first 24 bytes
-            &wal_header,
-            checksums,
-            native, // this is false because we haven't encoded the wal header yet
-        );
+        let checksums = wal_header_checksums(&wal_header);
         wal_header.checksum_1 = checksums.0;
         wal_header.checksum_2 = checksums.1;
-        sqlite3_ondisk::begin_write_wal_header(&file, &wal_header)?;
+        sqlite3_ondisk::begin_write_wal_header(&file, &wal_header, Rc::new(RefCell::new(0)))?;
         let header = Arc::new(SpinLock::new(wal_header));
         let checksum = {
             let checksum = header.lock();
@@ -1,19 +1,32 @@
+use std::{cell::Cell, rc::Rc};
+
 use fallible_iterator::FallibleIterator as _;
-use turso_sqlite3_parser::{ast, lexer::sql::Parser};
+use turso_sqlite3_parser::{
+    ast::{self, ResolveType},
+    lexer::sql::Parser,
+};
 
 use crate::{
+    error::SQLITE_ERROR,
     function::{AlterTableFunc, Func},
-    schema::{Column, Schema},
+    schema::{BTreeTable, CheckConstraint, Column, Schema, Type},
     util::normalize_ident,
     vdbe::{
-        builder::ProgramBuilder,
-        insn::{Cookie, Insn, RegisterOrLiteral},
+        builder::{constant_default_value, CursorType, ProgramBuilder},
+        insn::{CmpInsFlags, Cookie, InsertFlags, Insn, RegisterOrLiteral},
     },
-    LimboError, Result, SymbolTable,
+    LimboError, Result, SymbolTable, Value,
 };
 
 use super::{
-    emitter::TransactionMode, schema::SQLITE_TABLEID, update::translate_update_with_after,
+    collate::CollationSeq,
+    emitter::{
+        emit_check_constraints, emit_generated_columns, emit_notnull_check, Resolver,
+        TransactionMode,
+    },
+    expr::{walk_expr, walk_expr_mut, WalkControl},
+    schema::{validate_generated_columns, SQLITE_SEQUENCE_TABLE, SQLITE_TABLEID},
+    update::translate_update_with_after,
 };
 
 pub fn translate_alter_table(
@@ -69,7 +82,8 @@ pub fn translate_alter_table(
             if column.unique
                 || btree.unique_sets.as_ref().is_some_and(|set| {
                     set.iter().any(|set| {
-                        set.iter()
+                        set.columns
+                            .iter()
                             .any(|(name, _)| name == &normalize_ident(&column_name))
                     })
                 })
@@ -79,6 +93,55 @@ pub fn translate_alter_table(
                 )));
             }
 
+            let normalized_column_name = normalize_ident(&column_name);
+            for index in schema.get_indices(&table_name) {
+                let mut refers_to_column = false;
+                for column in index.columns.iter() {
+                    refers_to_column |= match &column.expr {
+                        Some(expr) => expr_refers_to_column(expr, &normalized_column_name)?,
+                        None => column.name == normalized_column_name,
+                    };
+                }
+                if let Some(where_clause) = &index.where_clause {
+                    refers_to_column |=
+                        expr_refers_to_column(where_clause, &normalized_column_name)?;
+                }
+                if refers_to_column {
+                    return Err(LimboError::ParseError(format!(
+                        "error in index {} after drop column: no such column: {column_name}",
+                        index.name
+                    )));
+                }
+            }
+
+            // A table CHECK constraint or a generated column can't outlive the columns it
+            // refers to, while a column CHECK constraint is dropped along with its column.
+            let column_name = normalize_ident(&column_name);
+            btree
+                .check_constraints
+                .retain(|check| check.column.as_ref() != Some(&column_name));
+            let dependent_exprs = btree
+                .check_constraints
+                .iter()
+                .map(|check| &check.expr)
+                .chain(
+                    btree
+                        .columns
+                        .iter()
+                        .enumerate()
+                        .filter(|(idx, _)| *idx != dropped_index)
+                        .filter_map(|(_, column)| {
+                            column.generated.as_ref().map(|generated| &generated.expr)
+                        }),
+                );
+            for dependent_expr in dependent_exprs {
+                if expr_refers_to_column(dependent_expr, &column_name)? {
+                    return Err(LimboError::ParseError(format!(
+                        "error in table {table_name} after drop column: no such column: {column_name}"
+                    )));
+                }
+            }
+
             btree.columns.remove(dropped_index);
 
             let sql = btree.to_sql();
@@ -101,13 +164,15 @@ pub fn translate_alter_table(
                 let root_page = btree.root_page;
                 let table_name = btree.name.clone();
 
-                let cursor_id = program
-                    .alloc_cursor_id(crate::vdbe::builder::CursorType::BTreeTable(original_btree));
+                let cursor_id = program.alloc_cursor_id(
+                    crate::vdbe::builder::CursorType::BTreeTable(original_btree.clone()),
+                );
 
                 program.emit_insn(Insn::OpenWrite {
                     cursor_id,
                     root_page: RegisterOrLiteral::Literal(root_page),
                     name: table_name.clone(),
+                    db: 0,
                 });
 
                 program.cursor_loop(cursor_id, |program, rowid| {
@@ -115,8 +180,9 @@ pub fn translate_alter_table(
 
                     let mut iter = first_column;
 
-                    for i in 0..(column_count + 1) {
-                        if i == dropped_index {
+                    // VIRTUAL generated columns have no value in the records to carry over.
+                    for (i, column) in original_btree.columns.iter().enumerate() {
+                        if i == dropped_index || column.is_virtual_generated() {
                             continue;
                         }
 
@@ -129,7 +195,7 @@ pub fn translate_alter_table(
 
                     program.emit_insn(Insn::MakeRecord {
                         start_reg: first_column,
-                        count: column_count,
+                        count: iter - first_column,
                         dest_reg: record,
                         index_name: None,
                     });
@@ -150,33 +216,105 @@ pub fn translate_alter_table(
                 });
 
                 program.emit_insn(Insn::ParseSchema {
-                    db: usize::MAX, // TODO: This value is unused, change when we do something with it
+                    db: 0,
                     where_clause: None,
                 })
             })?
         }
         ast::AlterTableBody::AddColumn(col_def) => {
-            let column = Column::from(col_def);
-
-            if let Some(default) = &column.default {
-                if !matches!(
-                    default,
-                    ast::Expr::Literal(
-                        ast::Literal::Null
-                            | ast::Literal::Blob(_)
-                            | ast::Literal::Numeric(_)
-                            | ast::Literal::String(_)
-                    )
-                ) {
-                    // TODO: This is slightly inaccurate since sqlite returns a `Runtime
-                    // error`.
-                    return Err(LimboError::ParseError(
-                        "Cannot add a column with non-constant default".to_string(),
-                    ));
+            let column_name = normalize_ident(&col_def.col_name.0);
+            if btree.get_column(&column_name).is_some() {
+                return Err(LimboError::ParseError(format!(
+                    "duplicate column name: {}",
+                    col_def.col_name.0
+                )));
+            }
+
+            let has_check = col_def
+                .constraints
+                .iter()
+                .any(|c| matches!(c.constraint, ast::ColumnConstraint::Check(_)));
+            btree
+                .check_constraints
+                .extend(
+                    col_def
+                        .constraints
+                        .iter()
+                        .filter_map(|c| match &c.constraint {
+                            ast::ColumnConstraint::Check(expr) => Some(CheckConstraint {
+                                name: c.name.as_ref().map(|name| normalize_ident(&name.0)),
+                                expr: expr.clone(),
+                                column: Some(column_name.clone()),
+                            }),
+                            _ => None,
+                        }),
+                );
+            let mut column = Column::try_from(col_def)?;
+            if btree.is_strict {
+                // Like in CREATE TABLE, the columns of STRICT tables need one of their datatypes.
+                match column.ty_str.to_ascii_uppercase().as_str() {
+                    "INT" | "INTEGER" | "REAL" | "TEXT" | "BLOB" => {}
+                    "ANY" => column.ty = Type::Blob,
+                    "" => {
+                        return Err(LimboError::ParseError(format!(
+                            "missing datatype for {table_name}.{column_name}"
+                        )))
+                    }
+                    _ => {
+                        return Err(LimboError::ParseError(format!(
+                            "unknown datatype for {table_name}.{column_name}: \"{}\"",
+                            column.ty_str
+                        )))
+                    }
                 }
             }
 
+            // The existing rows would need new index entries, which they might violate.
+            if column.primary_key {
+                return Err(LimboError::ParseError(
+                    "Cannot add a PRIMARY KEY column".to_string(),
+                ));
+            }
+            if column.unique {
+                return Err(LimboError::ParseError(
+                    "Cannot add a UNIQUE column".to_string(),
+                ));
+            }
+
+            // The existing rows would need to be rewritten to store the new column.
+            if column
+                .generated
+                .as_ref()
+                .is_some_and(|generated| generated.stored)
+            {
+                return Err(LimboError::ParseError(
+                    "cannot add a STORED column".to_string(),
+                ));
+            }
+
+            // The existing rows read the new column's default from the schema, so it has to be
+            // a constant. Like in SQLite, this is only an error when the table has rows.
+            let default_value = column.default.as_ref().map(constant_default_value);
+            let non_constant_default = matches!(default_value, Some(None));
+
+            // The existing rows would get NULL for the new column, so this is only allowed
+            // when the table is empty.
+            let notnull_without_default = column.notnull
+                && column.generated.is_none()
+                && matches!(default_value, None | Some(Some(Value::Null)));
+
+            let validate_existing_rows = non_constant_default || column.notnull || has_check;
+
+            let is_generated = column.generated.is_some();
             btree.columns.push(column);
+            if is_generated {
+                validate_generated_columns(&btree).map_err(|err| match err {
+                    LimboError::ParseError(msg) => LimboError::ParseError(format!(
+                        "error in table {table_name} after add column: {msg}"
+                    )),
+                    err => err,
+                })?;
+            }
 
             let sql = btree.to_sql();
             let mut escaped = String::with_capacity(sql.len());
@@ -201,18 +339,32 @@ pub fn translate_alter_table(
                 unreachable!();
             };
 
-            translate_update_with_after(schema, &mut update, syms, program, |program| {
-                program.emit_insn(Insn::SetCookie {
-                    db: 0,
-                    cookie: Cookie::SchemaVersion,
-                    value: schema.schema_version as i32 + 1,
-                    p5: 0,
-                });
-                program.emit_insn(Insn::ParseSchema {
-                    db: usize::MAX, // TODO: This value is unused, change when we do something with it
-                    where_clause: None,
-                });
-            })?
+            let resolver = Resolver::new(schema, syms);
+            let mut validation = Ok(());
+            let program =
+                translate_update_with_after(schema, &mut update, syms, program, |program| {
+                    if validate_existing_rows {
+                        validation = emit_added_column_validation(
+                            program,
+                            &resolver,
+                            &btree,
+                            non_constant_default,
+                            notnull_without_default,
+                        );
+                    }
+                    program.emit_insn(Insn::SetCookie {
+                        db: 0,
+                        cookie: Cookie::SchemaVersion,
+                        value: schema.schema_version as i32 + 1,
+                        p5: 0,
+                    });
+                    program.emit_insn(Insn::ParseSchema {
+                        db: 0,
+                        where_clause: None,
+                    });
+                })?;
+            validation?;
+            program
         }
         ast::AlterTableBody::RenameColumn { old, new } => {
             let ast::Name(rename_from) = old;
@@ -226,7 +378,7 @@ pub fn translate_alter_table(
 
             if btree.get_column(&rename_to).is_some() {
                 return Err(LimboError::ParseError(format!(
-                    "duplicate column name: \"{rename_from}\""
+                    "duplicate column name: {rename_to}"
                 )));
             };
 
@@ -242,6 +394,7 @@ pub fn translate_alter_table(
                 cursor_id,
                 root_page: RegisterOrLiteral::Literal(sqlite_schema.root_page),
                 name: sqlite_schema.name.clone(),
+                db: 0,
             });
 
             program.cursor_loop(cursor_id, |program, rowid| {
@@ -300,7 +453,7 @@ pub fn translate_alter_table(
                 p5: 0,
             });
             program.emit_insn(Insn::ParseSchema {
-                db: usize::MAX, // TODO: This value is unused, change when we do something with it
+                db: 0,
                 where_clause: None,
             });
 
@@ -311,12 +464,29 @@ pub fn translate_alter_table(
         ast::AlterTableBody::RenameTo(new_name) => {
             let ast::Name(new_name) = new_name;
 
-            if schema.get_table(&new_name).is_some() {
+            let normalized_table_name = normalize_ident(&table_name);
+            if normalized_table_name.starts_with("sqlite_") {
+                return Err(LimboError::ParseError(format!(
+                    "table {normalized_table_name} may not be altered"
+                )));
+            }
+
+            let normalized_new_name = normalize_ident(&new_name);
+            if schema.get_table(&new_name).is_some()
+                || schema.get_view(&new_name).is_some()
+                || !schema.is_unique_idx_name(&normalized_new_name)
+            {
                 return Err(LimboError::ParseError(format!(
                     "there is already another table or index with this name: {new_name}"
                 )));
             };
 
+            if normalized_new_name.starts_with("sqlite_") {
+                return Err(LimboError::ParseError(format!(
+                    "object name reserved for internal use: {new_name}"
+                )));
+            }
+
             let sqlite_schema = schema
                 .get_btree_table(SQLITE_TABLEID)
                 .expect("sqlite_schema should be on schema");
@@ -329,6 +499,7 @@ pub fn translate_alter_table(
                 cursor_id,
                 root_page: RegisterOrLiteral::Literal(sqlite_schema.root_page),
                 name: sqlite_schema.name.clone(),
+                db: 0,
             });
 
             program.cursor_loop(cursor_id, |program, rowid| {
@@ -377,6 +548,17 @@ pub fn translate_alter_table(
                 });
             });
 
+            if btree.has_autoincrement {
+                if let Some(sequence_table) = schema.get_btree_table(SQLITE_SEQUENCE_TABLE) {
+                    emit_rename_sequence_entry(
+                        &mut program,
+                        sequence_table,
+                        &table_name,
+                        &new_name,
+                    );
+                }
+            }
+
             program.emit_insn(Insn::SetCookie {
                 db: 0,
                 cookie: Cookie::SchemaVersion,
@@ -385,7 +567,7 @@ pub fn translate_alter_table(
             });
 
             program.emit_insn(Insn::ParseSchema {
-                db: usize::MAX, // TODO: This value is unused, change when we do something with it
+                db: 0,
                 where_clause: None,
             });
 
@@ -395,3 +577,397 @@ pub fn translate_alter_table(
         }
     })
 }
+
+/// Checks the rows already in `table` against its last column, which was just added and which
+/// they get the default value of: its NOT NULL and CHECK constraints have to hold for them, and
+/// it can't have a non-constant default (or no default while being NOT NULL) unless there are
+/// no rows at all.
+fn emit_added_column_validation(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    table: &BTreeTable,
+    non_constant_default: bool,
+    notnull_without_default: bool,
+) -> Result<()> {
+    let column = table.columns.last().expect("table should have columns");
+    let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(Rc::new(table.clone())));
+    program.emit_insn(Insn::OpenRead {
+        cursor_id,
+        root_page: table.root_page,
+        db: 0,
+    });
+
+    let loop_start = program.allocate_label();
+    let loop_end = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id,
+        pc_if_empty: loop_end,
+    });
+    program.preassign_label_to_next_insn(loop_start);
+
+    if non_constant_default || notnull_without_default {
+        let description = if non_constant_default {
+            "Cannot add a column with non-constant default"
+        } else {
+            "Cannot add a NOT NULL column with default value NULL"
+        };
+        program.emit_insn(Insn::Halt {
+            err_code: SQLITE_ERROR,
+            description: description.to_string(),
+            on_error: ResolveType::Abort,
+        });
+    } else {
+        let rowid_reg = program.alloc_register();
+        program.emit_insn(Insn::RowId {
+            cursor_id,
+            dest: rowid_reg,
+        });
+        let columns_start_reg = program.alloc_registers(table.columns.len());
+        for (i, column) in table.columns.iter().enumerate() {
+            if !column.is_virtual_generated() {
+                program.emit_column(cursor_id, i, columns_start_reg + i);
+            }
+        }
+        emit_generated_columns(program, resolver, table, rowid_reg, columns_start_reg)?;
+        if column.notnull {
+            emit_notnull_check(
+                program,
+                resolver,
+                &table.name,
+                column,
+                columns_start_reg + table.columns.len() - 1,
+                ResolveType::Abort,
+                loop_end,
+            )?;
+        }
+        emit_check_constraints(
+            program,
+            resolver,
+            table,
+            rowid_reg,
+            columns_start_reg,
+            ResolveType::Abort,
+            loop_end,
+        )?;
+    }
+
+    program.emit_insn(Insn::Next {
+        cursor_id,
+        pc_if_next: loop_start,
+    });
+    program.preassign_label_to_next_insn(loop_end);
+    program.emit_insn(Insn::Close { cursor_id });
+    Ok(())
+}
+
+/// Renames the references to the column `from` of `table` in `expr` to `to`.
+pub(crate) fn rename_column_in_expr(
+    expr: &mut ast::Expr,
+    table: &str,
+    from: &str,
+    to: &str,
+) -> Result<()> {
+    walk_expr_mut(expr, &mut |expr: &mut ast::Expr| -> Result<()> {
+        match expr {
+            ast::Expr::Id(id) if normalize_ident(&id.0) == from => {
+                *id = ast::Id(to.to_string());
+            }
+            ast::Expr::Qualified(tbl_name, col_name)
+                if normalize_ident(&tbl_name.0) == table
+                    && normalize_ident(&col_name.0) == from =>
+            {
+                *col_name = ast::Name(to.to_string());
+            }
+            _ => {}
+        }
+        Ok(())
+    })
+}
+
+/// Returns whether `expr` refers to the column named `column_name`, which must be normalized.
+fn expr_refers_to_column(expr: &ast::Expr, column_name: &str) -> Result<bool> {
+    let mut refers_to_column = false;
+    walk_expr(expr, &mut |expr: &ast::Expr| -> Result<WalkControl> {
+        let name = match expr {
+            ast::Expr::Id(id) => &id.0,
+            ast::Expr::Qualified(_, name) => &name.0,
+            _ => return Ok(WalkControl::Continue),
+        };
+        if normalize_ident(name) == column_name {
+            refers_to_column = true;
+        }
+        Ok(WalkControl::Continue)
+    })?;
+    Ok(refers_to_column)
+}
+
+/// Renames the references to the table `from` to `to` in the SQL of the schema objects, like the
+/// table's own CREATE TABLE statement, foreign keys pointing at it, and the indexes, triggers and
+/// views using it.
+pub(crate) struct TableRename<'a> {
+    from: &'a str,
+    to: &'a str,
+    renamed: Cell<bool>,
+}
+
+impl<'a> TableRename<'a> {
+    pub fn new(from: &'a str, to: &'a str) -> Self {
+        Self {
+            from,
+            to,
+            renamed: Cell::new(false),
+        }
+    }
+
+    /// Renames the references in `stmt`, returning whether there were any.
+    pub fn rename_stmt(&self, stmt: &mut ast::Stmt) -> Result<bool> {
+        self.renamed.set(false);
+        match stmt {
+            ast::Stmt::CreateTable { tbl_name, body, .. } => {
+                self.rename_name(&mut tbl_name.name);
+                if let ast::CreateTableBody::ColumnsAndConstraints {
+                    columns,
+                    constraints,
+                    ..
+                } = body.as_mut()
+                {
+                    for column in columns.values_mut() {
+                        for constraint in &mut column.constraints {
+                            if let ast::ColumnConstraint::ForeignKey { clause, .. } =
+                                &mut constraint.constraint
+                            {
+                                self.rename_name(&mut clause.tbl_name);
+                            }
+                        }
+                    }
+                    for constraint in constraints.iter_mut().flatten() {
+                        if let ast::TableConstraint::ForeignKey { clause, .. } =
+                            &mut constraint.constraint
+                        {
+                            self.rename_name(&mut clause.tbl_name);
+                        }
+                    }
+                }
+            }
+            ast::Stmt::CreateIndex {
+                tbl_name,
+                columns,
+                where_clause,
+                ..
+            } => {
+                self.rename_name(tbl_name);
+                for column in columns.iter_mut() {
+                    self.rename_expr(&mut column.expr)?;
+                }
+                if let Some(where_clause) = where_clause {
+                    self.rename_expr(where_clause)?;
+                }
+            }
+            ast::Stmt::CreateTrigger(trigger) => {
+                self.rename_name(&mut trigger.tbl_name.name);
+                if let Some(when_clause) = &mut trigger.when_clause {
+                    self.rename_expr(when_clause)?;
+                }
+                for command in &mut trigger.commands {
+                    self.rename_command(command)?;
+                }
+            }
+            ast::Stmt::CreateView { select, .. } => self.rename_select(select)?,
+            _ => {}
+        }
+        Ok(self.renamed.get())
+    }
+
+    fn rename_name(&self, name: &mut ast::Name) {
+        if normalize_ident(&name.0) == self.from {
+            *name = ast::Name(self.to.to_string());
+            self.renamed.set(true);
+        }
+    }
+
+    fn rename_expr(&self, expr: &mut ast::Expr) -> Result<()> {
+        walk_expr_mut(expr, &mut |expr: &mut ast::Expr| -> Result<()> {
+            match expr {
+                ast::Expr::Qualified(tbl_name, _) => self.rename_name(tbl_name),
+                ast::Expr::DoublyQualified(_, tbl_name, _) => self.rename_name(tbl_name),
+                ast::Expr::Exists(select) | ast::Expr::Subquery(select) => {
+                    self.rename_select(select)?
+                }
+                ast::Expr::InSelect { rhs, .. } => self.rename_select(rhs)?,
+                ast::Expr::InTable { rhs, .. } => self.rename_name(&mut rhs.name),
+                _ => {}
+            }
+            Ok(())
+        })
+    }
+
+    fn rename_command(&self, command: &mut ast::TriggerCmd) -> Result<()> {
+        match command {
+            ast::TriggerCmd::Insert(insert) => {
+                self.rename_name(&mut insert.tbl_name);
+                self.rename_select(&mut insert.select)?;
+                let mut upsert = insert.upsert.as_mut();
+                while let Some(clause) = upsert {
+                    if let ast::UpsertDo::Set { sets, where_clause } = clause.do_clause.as_mut() {
+                        for set in sets {
+                            self.rename_expr(&mut set.expr)?;
+                        }
+                        if let Some(where_clause) = where_clause {
+                            self.rename_expr(where_clause)?;
+                        }
+                    }
+                    upsert = clause.next.as_deref_mut();
+                }
+            }
+            ast::TriggerCmd::Update(update) => {
+                self.rename_name(&mut update.tbl_name);
+                for set in &mut update.sets {
+                    self.rename_expr(&mut set.expr)?;
+                }
+                if let Some(from) = &mut update.from {
+                    self.rename_from(from)?;
+                }
+                if let Some(where_clause) = &mut update.where_clause {
+                    self.rename_expr(where_clause)?;
+                }
+            }
+            ast::TriggerCmd::Delete(delete) => {
+                self.rename_name(&mut delete.tbl_name);
+                if let Some(where_clause) = &mut delete.where_clause {
+                    self.rename_expr(where_clause)?;
+                }
+            }
+            ast::TriggerCmd::Select(select) => self.rename_select(select)?,
+        }
+        Ok(())
+    }
+
+    fn rename_select(&self, select: &mut ast::Select) -> Result<()> {
+        if let Some(with) = &mut select.with {
+            for cte in &mut with.ctes {
+                self.rename_select(&mut cte.select)?;
+            }
+        }
+        self.rename_one_select(&mut select.body.select)?;
+        for compound in select.body.compounds.iter_mut().flatten() {
+            self.rename_one_select(&mut compound.select)?;
+        }
+        for sorted_column in select.order_by.iter_mut().flatten() {
+            self.rename_expr(&mut sorted_column.expr)?;
+        }
+        if let Some(limit) = &mut select.limit {
+            self.rename_expr(&mut limit.expr)?;
+            if let Some(offset) = &mut limit.offset {
+                self.rename_expr(offset)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rename_one_select(&self, select: &mut ast::OneSelect) -> Result<()> {
+        match select {
+            ast::OneSelect::Select(select) => {
+                for column in &mut select.columns {
+                    match column {
+                        ast::ResultColumn::Expr(expr, _) => self.rename_expr(expr)?,
+                        ast::ResultColumn::TableStar(tbl_name) => self.rename_name(tbl_name),
+                        ast::ResultColumn::Star => {}
+                    }
+                }
+                if let Some(from) = &mut select.from {
+                    self.rename_from(from)?;
+                }
+                if let Some(where_clause) = &mut select.where_clause {
+                    self.rename_expr(where_clause)?;
+                }
+                if let Some(group_by) = &mut select.group_by {
+                    for expr in &mut group_by.exprs {
+                        self.rename_expr(expr)?;
+                    }
+                    if let Some(having) = &mut group_by.having {
+                        self.rename_expr(having)?;
+                    }
+                }
+            }
+            ast::OneSelect::Values(rows) => {
+                for expr in rows.iter_mut().flatten() {
+                    self.rename_expr(expr)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rename_from(&self, from: &mut ast::FromClause) -> Result<()> {
+        if let Some(select_table) = &mut from.select {
+            self.rename_select_table(select_table)?;
+        }
+        for join in from.joins.iter_mut().flatten() {
+            self.rename_select_table(&mut join.table)?;
+            if let Some(ast::JoinConstraint::On(expr)) = &mut join.constraint {
+                self.rename_expr(expr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rename_select_table(&self, select_table: &mut ast::SelectTable) -> Result<()> {
+        match select_table {
+            ast::SelectTable::Table(name, ..) => self.rename_name(&mut name.name),
+            ast::SelectTable::TableCall(_, args, _) => {
+                for arg in args.iter_mut().flatten() {
+                    self.rename_expr(arg)?;
+                }
+            }
+            ast::SelectTable::Select(select, _) => self.rename_select(select)?,
+            ast::SelectTable::Sub(from, _) => self.rename_from(from)?,
+        }
+        Ok(())
+    }
+}
+
+/// Renames the sqlite_sequence row of the AUTOINCREMENT table `from` to `to`.
+fn emit_rename_sequence_entry(
+    program: &mut ProgramBuilder,
+    sequence_table: Rc<BTreeTable>,
+    from: &str,
+    to: &str,
+) {
+    let from_reg = program.emit_string8_new_reg(from.to_string());
+    let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(sequence_table.clone()));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id,
+        root_page: sequence_table.root_page.into(),
+        name: sequence_table.name.clone(),
+        db: 0,
+    });
+    program.cursor_loop(cursor_id, |program, rowid| {
+        let next_label = program.allocate_label();
+        let name_reg = program.alloc_registers(2);
+        program.emit_column(cursor_id, 0, name_reg);
+        program.emit_insn(Insn::Ne {
+            lhs: from_reg,
+            rhs: name_reg,
+            target_pc: next_label,
+            flags: CmpInsFlags::default().jump_if_null(),
+            collation: Some(CollationSeq::NoCase),
+        });
+        program.emit_string8(to.to_string(), name_reg);
+        program.emit_column(cursor_id, 1, name_reg + 1);
+        let record_reg = program.alloc_register();
+        program.emit_insn(Insn::MakeRecord {
+            start_reg: name_reg,
+            count: 2,
+            dest_reg: record_reg,
+            index_name: None,
+        });
+        program.emit_insn(Insn::Insert {
+            cursor: cursor_id,
+            key_reg: rowid,
+            record_reg,
+            flag: InsertFlags(0),
+            table_name: sequence_table.name.clone(),
+        });
+        program.preassign_label_to_next_insn(next_label);
+    });
+}
@@ -0,0 +1,581 @@
+//! `ANALYZE`, which gathers statistics about tables and their indexes into `sqlite_stat1` for the
+//! optimizer.
+//!
+//! Like SQLite, the `stat` of the row for an index holds the number of rows of the table, followed
+//! for each prefix of the columns of the index by the average number of rows that have the same
+//! values in those columns. For example, `10000 50 1` for an index on `(a, b)` means that each
+//! value of `a` appears in 50 rows, and that the values of `(a, b)` are unique. A table without
+//! indexes gets a row with a NULL `idx` holding only its row count, and empty tables get no row.
+
+use std::rc::Rc;
+use std::sync::Arc;
+
+use turso_sqlite3_parser::ast;
+
+use crate::schema::{BTreeTable, Index, Schema, Table};
+use crate::storage::pager::CreateBTreeFlags;
+use crate::translate::collate::CollationSeq;
+use crate::translate::emitter::TransactionMode;
+use crate::translate::schema::{emit_schema_entry, SchemaEntryType, SQLITE_STAT1_TABLE};
+use crate::translate::{ProgramBuilder, ProgramBuilderOpts};
+use crate::util::normalize_ident;
+use crate::vdbe::builder::CursorType;
+use crate::vdbe::insn::{ChangeCount, CmpInsFlags, Cookie, InsertFlags, Insn, RegisterOrLiteral};
+use crate::vdbe::BranchOffset;
+use crate::{bail_parse_error, Result};
+
+/// A table to analyze, with the indexes to analyze.
+struct AnalyzedTable {
+    table: Rc<BTreeTable>,
+    indexes: Vec<Arc<Index>>,
+    /// Whether only the given index is analyzed, leaving the statistics of the table and its
+    /// other indexes as they are.
+    only_index: bool,
+}
+
+/// Translates `ANALYZE [schema | [schema.]table | [schema.]index]`. Without an argument, the
+/// tables of every database are analyzed.
+pub fn translate_analyze(
+    target: Option<ast::QualifiedName>,
+    schema: &Schema,
+    mut program: ProgramBuilder,
+) -> Result<ProgramBuilder> {
+    let databases = match target {
+        None => std::iter::once(0)
+            .chain(schema.attached.iter().map(|db| db.index))
+            .map(|db| (db, all_tables(schema.database(db))))
+            .collect(),
+        Some(ast::QualifiedName {
+            db_name: None,
+            name,
+            ..
+        }) if schema.database_index(&name.0).is_ok() => {
+            let db = schema.database_index(&name.0)?;
+            vec![(db, all_tables(schema.database(db)))]
+        }
+        Some(name) => {
+            let db = match &name.db_name {
+                Some(db_name) => schema.database_index(&db_name.0)?,
+                None => schema.object_database(&name)?,
+            };
+            let Some(table) = analyzed_table(schema.database(db), &name.name.0) else {
+                match &name.db_name {
+                    Some(db_name) => {
+                        bail_parse_error!("no such table: {}.{}", db_name.0, name.name.0)
+                    }
+                    None => bail_parse_error!("no such table: {}", name.name.0),
+                }
+            };
+            vec![(db, table.into_iter().collect())]
+        }
+    };
+
+    program.extend(&ProgramBuilderOpts {
+        num_cursors: 3,
+        approx_num_insns: 40,
+        approx_num_labels: 4,
+    });
+    for (db, tables) in databases {
+        emit_analyze_database(&mut program, schema.database(db), db, &tables, false)?;
+    }
+    program.epilogue(TransactionMode::Write);
+    Ok(program)
+}
+
+/// `PRAGMA optimize` analyzes a table again once its row count changed by this factor since it was
+/// last analyzed.
+const OPTIMIZE_ROW_COUNT_FACTOR: u64 = 10;
+
+/// Translates `PRAGMA optimize`, which analyzes the tables of the main database whose statistics
+/// are missing or stale. Like in SQLite, only the tables with indexes are considered, since the
+/// statistics only help choosing between indexes. Returns whether anything may be analyzed.
+pub fn translate_optimize(schema: &Schema, program: &mut ProgramBuilder) -> Result<bool> {
+    let tables: Vec<_> = all_tables(schema)
+        .into_iter()
+        .filter(|table| !table.indexes.is_empty())
+        .collect();
+    if tables.is_empty() {
+        return Ok(false);
+    }
+    program.extend(&ProgramBuilderOpts {
+        num_cursors: 3,
+        approx_num_insns: 40,
+        approx_num_labels: 4,
+    });
+    emit_analyze_database(program, schema, 0, &tables, true)?;
+    Ok(true)
+}
+
+/// Returns the tables of `schema` that ANALYZE gathers statistics about, in the order of their
+/// names. Like with SQLite, the internal `sqlite_` tables are skipped.
+fn all_tables(schema: &Schema) -> Vec<AnalyzedTable> {
+    let mut tables: Vec<_> = schema
+        .tables
+        .values()
+        .filter_map(|table| match table.as_ref() {
+            Table::BTree(table) if !table.name.starts_with("sqlite_") => Some(AnalyzedTable {
+                table: table.clone(),
+                indexes: schema.get_indices(&table.name).to_vec(),
+                only_index: false,
+            }),
+            _ => None,
+        })
+        .collect();
+    tables.sort_by(|a, b| a.table.name.cmp(&b.table.name));
+    tables
+}
+
+/// Looks up the table or index named `name` in `schema`. Returns None if there is no such table
+/// or index, and Some(None) if there is nothing to analyze.
+fn analyzed_table(schema: &Schema, name: &str) -> Option<Option<AnalyzedTable>> {
+    let normalized_name = normalize_ident(name);
+    if let Some(table) = schema.get_table(&normalized_name) {
+        let Some(table) = table.btree().filter(|t| !t.name.starts_with("sqlite_")) else {
+            return Some(None);
+        };
+        return Some(Some(AnalyzedTable {
+            indexes: schema.get_indices(&table.name).to_vec(),
+            table,
+            only_index: false,
+        }));
+    }
+    let index = schema
+        .indexes
+        .values()
+        .flatten()
+        .find(|index| normalize_ident(&index.name) == normalized_name)?;
+    let table = schema.get_btree_table(&index.table_name)?;
+    Some(Some(AnalyzedTable {
+        table,
+        indexes: vec![index.clone()],
+        only_index: true,
+    }))
+}
+
+/// Emits the analysis of `tables`, in the database with index `db`, whose schema is `schema`.
+/// The `sqlite_stat1` table of the database is created if it doesn't exist, and the statistics
+/// are loaded into the schema at the end. With `only_stale`, the tables whose statistics are up to
+/// date are skipped, see [emit_skip_if_fresh].
+fn emit_analyze_database(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    db: usize,
+    tables: &[AnalyzedTable],
+    only_stale: bool,
+) -> Result<()> {
+    let stat_sql = format!("CREATE TABLE {SQLITE_STAT1_TABLE}(tbl,idx,stat)");
+    let (stat_table, root_page) = match schema.get_btree_table(SQLITE_STAT1_TABLE) {
+        Some(stat_table) => {
+            let root_page = RegisterOrLiteral::Literal(stat_table.root_page);
+            (stat_table, root_page)
+        }
+        None => {
+            let root_reg = program.alloc_register();
+            program.emit_insn(Insn::CreateBtree {
+                db,
+                root: root_reg,
+                flags: CreateBTreeFlags::new_table(),
+            });
+            let sqlite_schema = schema.get_btree_table("sqlite_schema").unwrap();
+            let sqlite_schema_cursor_id =
+                program.alloc_cursor_id(CursorType::BTreeTable(sqlite_schema.clone()));
+            program.emit_insn(Insn::OpenWrite {
+                cursor_id: sqlite_schema_cursor_id,
+                root_page: 1usize.into(),
+                name: sqlite_schema.name.clone(),
+                db,
+            });
+            emit_schema_entry(
+                program,
+                sqlite_schema_cursor_id,
+                SchemaEntryType::Table,
+                SQLITE_STAT1_TABLE,
+                SQLITE_STAT1_TABLE,
+                root_reg,
+                Some(stat_sql.clone()),
+            );
+            let stat_table = Rc::new(BTreeTable::from_sql(&stat_sql, 0)?);
+            (stat_table, RegisterOrLiteral::Register(root_reg))
+        }
+    };
+    let stat_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(stat_table.clone()));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: stat_cursor_id,
+        root_page,
+        name: stat_table.name.clone(),
+        db,
+    });
+
+    let analyzed_reg = only_stale.then(|| {
+        let analyzed_reg = program.alloc_register();
+        program.emit_int(0, analyzed_reg);
+        analyzed_reg
+    });
+    for table in tables {
+        let skip_label = program.allocate_label();
+        if let Some(analyzed_reg) = analyzed_reg {
+            emit_skip_if_fresh(program, schema, db, table, skip_label);
+            program.emit_int(1, analyzed_reg);
+        }
+        emit_delete_stats(program, stat_cursor_id, table);
+        emit_analyze_table(program, stat_cursor_id, db, table);
+        program.preassign_label_to_next_insn(skip_label);
+    }
+
+    // The schema is left as it is if no table was analyzed.
+    let end_label = program.allocate_label();
+    if let Some(analyzed_reg) = analyzed_reg {
+        program.emit_insn(Insn::IfNot {
+            reg: analyzed_reg,
+            target_pc: end_label,
+            jump_if_null: true,
+        });
+    }
+    if schema.get_btree_table(SQLITE_STAT1_TABLE).is_none() {
+        program.emit_insn(Insn::ParseSchema {
+            db,
+            where_clause: Some(format!(
+                "tbl_name = '{SQLITE_STAT1_TABLE}' AND type != 'trigger'"
+            )),
+        });
+    }
+    program.emit_insn(Insn::SetCookie {
+        db,
+        cookie: Cookie::SchemaVersion,
+        value: schema.schema_version as i32 + 1,
+        p5: 0,
+    });
+    program.emit_insn(Insn::LoadAnalysis { db });
+    program.preassign_label_to_next_insn(end_label);
+    Ok(())
+}
+
+/// Jumps to `skip_label` if the statistics of the table are up to date: each of its indexes has
+/// statistics, and its row count is within a factor of [OPTIMIZE_ROW_COUNT_FACTOR] of the one
+/// recorded when it was last analyzed.
+fn emit_skip_if_fresh(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    db: usize,
+    table: &AnalyzedTable,
+    skip_label: BranchOffset,
+) {
+    let Some(stats) = schema.stats.get(&normalize_ident(&table.table.name)) else {
+        return;
+    };
+    let has_index_stats = table.indexes.iter().all(|index| {
+        stats
+            .rows_per_key
+            .contains_key(&normalize_ident(&index.name))
+    });
+    if !has_index_stats {
+        return;
+    }
+    let table_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.table.clone()));
+    program.emit_insn(Insn::OpenRead {
+        cursor_id: table_cursor_id,
+        root_page: table.table.root_page,
+        db,
+    });
+    let row_count_reg = program.alloc_register();
+    program.emit_insn(Insn::Count {
+        cursor_id: table_cursor_id,
+        target_reg: row_count_reg,
+        exact: true,
+    });
+    let min_reg = program.alloc_register();
+    program.emit_int(
+        (stats.row_count / OPTIMIZE_ROW_COUNT_FACTOR) as i64,
+        min_reg,
+    );
+    let max_reg = program.alloc_register();
+    program.emit_int(
+        stats
+            .row_count
+            .saturating_mul(OPTIMIZE_ROW_COUNT_FACTOR)
+            .min(i64::MAX as u64) as i64,
+        max_reg,
+    );
+    let analyze_label = program.allocate_label();
+    program.emit_insn(Insn::Lt {
+        lhs: row_count_reg,
+        rhs: min_reg,
+        target_pc: analyze_label,
+        flags: CmpInsFlags::default(),
+        collation: None,
+    });
+    program.emit_insn(Insn::Le {
+        lhs: row_count_reg,
+        rhs: max_reg,
+        target_pc: skip_label,
+        flags: CmpInsFlags::default(),
+        collation: None,
+    });
+    program.preassign_label_to_next_insn(analyze_label);
+}
+
+/// Deletes the rows of `sqlite_stat1` about the table, or about the index if only an index is
+/// analyzed.
+fn emit_delete_stats(program: &mut ProgramBuilder, stat_cursor_id: usize, table: &AnalyzedTable) {
+    let tbl_reg = program.emit_string8_new_reg(table.table.name.clone());
+    let idx_reg = table
+        .only_index
+        .then(|| program.emit_string8_new_reg(table.indexes[0].name.clone()));
+
+    let loop_start_label = program.allocate_label();
+    let loop_end_label = program.allocate_label();
+    let next_label = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id: stat_cursor_id,
+        pc_if_empty: loop_end_label,
+    });
+    program.preassign_label_to_next_insn(loop_start_label);
+    let dest_reg = program.alloc_register();
+    for (column, name_reg) in [(0, Some(tbl_reg)), (1, idx_reg)] {
+        let Some(name_reg) = name_reg else {
+            continue;
+        };
+        program.emit_column(stat_cursor_id, column, dest_reg);
+        program.emit_insn(Insn::Ne {
+            lhs: name_reg,
+            rhs: dest_reg,
+            target_pc: next_label,
+            flags: CmpInsFlags::default(),
+            collation: Some(CollationSeq::NoCase),
+        });
+    }
+    program.emit_insn(Insn::Delete {
+        cursor_id: stat_cursor_id,
+        nchange: ChangeCount::None,
+        table_name: None,
+        is_update: false,
+    });
+    program.preassign_label_to_next_insn(next_label);
+    program.emit_insn(Insn::Next {
+        cursor_id: stat_cursor_id,
+        pc_if_next: loop_start_label,
+    });
+    program.preassign_label_to_next_insn(loop_end_label);
+}
+
+/// Emits the rows of `sqlite_stat1` about the table and its indexes.
+fn emit_analyze_table(
+    program: &mut ProgramBuilder,
+    stat_cursor_id: usize,
+    db: usize,
+    table: &AnalyzedTable,
+) {
+    let table_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.table.clone()));
+    program.emit_insn(Insn::OpenRead {
+        cursor_id: table_cursor_id,
+        root_page: table.table.root_page,
+        db,
+    });
+    let row_count_reg = program.alloc_register();
+    program.emit_insn(Insn::Count {
+        cursor_id: table_cursor_id,
+        target_reg: row_count_reg,
+        exact: true,
+    });
+    let end_label = program.allocate_label();
+    program.emit_insn(Insn::IfNot {
+        reg: row_count_reg,
+        target_pc: end_label,
+        jump_if_null: true,
+    });
+
+    // The stat is built as text, starting from the row count.
+    let stat_reg = program.emit_string8_new_reg(String::new());
+    program.emit_insn(Insn::Concat {
+        lhs: stat_reg,
+        rhs: row_count_reg,
+        dest: stat_reg,
+    });
+    if table.indexes.is_empty() {
+        if !table.only_index {
+            emit_stat_row(program, stat_cursor_id, &table.table.name, None, stat_reg);
+        }
+    } else {
+        let one_reg = program.alloc_register();
+        program.emit_insn(Insn::Integer {
+            value: 1,
+            dest: one_reg,
+        });
+        let space_reg = program.emit_string8_new_reg(" ".to_string());
+        for index in &table.indexes {
+            let index_stat_reg = program.alloc_register();
+            program.emit_insn(Insn::Copy {
+                src_reg: stat_reg,
+                dst_reg: index_stat_reg,
+                extra_amount: 0,
+            });
+            let distinct_regs = emit_count_distinct_keys(program, db, index, one_reg);
+            // The average number of rows per key is rounded up, like SQLite does.
+            for i in 0..index.columns.len() {
+                let avg_reg = program.alloc_register();
+                program.emit_insn(Insn::Add {
+                    lhs: row_count_reg,
+                    rhs: distinct_regs + i,
+                    dest: avg_reg,
+                });
+                program.emit_insn(Insn::Subtract {
+                    lhs: avg_reg,
+                    rhs: one_reg,
+                    dest: avg_reg,
+                });
+                program.emit_insn(Insn::Divide {
+                    lhs: avg_reg,
+                    rhs: distinct_regs + i,
+                    dest: avg_reg,
+                });
+                program.emit_insn(Insn::Concat {
+                    lhs: index_stat_reg,
+                    rhs: space_reg,
+                    dest: index_stat_reg,
+                });
+                program.emit_insn(Insn::Concat {
+                    lhs: index_stat_reg,
+                    rhs: avg_reg,
+                    dest: index_stat_reg,
+                });
+            }
+            emit_stat_row(
+                program,
+                stat_cursor_id,
+                &table.table.name,
+                Some(&index.name),
+                index_stat_reg,
+            );
+        }
+    }
+    program.preassign_label_to_next_insn(end_label);
+}
+
+/// Scans `index` to count the distinct values of each prefix of its columns. Returns the first
+/// of the registers holding the counts, one per column.
+fn emit_count_distinct_keys(
+    program: &mut ProgramBuilder,
+    db: usize,
+    index: &Arc<Index>,
+    one_reg: usize,
+) -> usize {
+    let num_columns = index.columns.len();
+    let index_cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
+    program.emit_insn(Insn::OpenRead {
+        cursor_id: index_cursor_id,
+        root_page: index.root_page,
+        db,
+    });
+    let distinct_regs = program.alloc_registers(num_columns);
+    for i in 0..num_columns {
+        program.emit_insn(Insn::Integer {
+            value: 0,
+            dest: distinct_regs + i,
+        });
+    }
+    let prev_key_regs = program.alloc_registers(num_columns);
+    let key_regs = program.alloc_registers(num_columns);
+    let is_first_reg = program.alloc_register();
+    program.emit_insn(Insn::Integer {
+        value: 1,
+        dest: is_first_reg,
+    });
+
+    let loop_start_label = program.allocate_label();
+    let loop_end_label = program.allocate_label();
+    let next_label = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id: index_cursor_id,
+        pc_if_empty: loop_end_label,
+    });
+    program.preassign_label_to_next_insn(loop_start_label);
+    for i in 0..num_columns {
+        program.emit_column(index_cursor_id, i, key_regs + i);
+    }
+    // If the key differs from the previous one from its i-th column on, it's a new value for
+    // the prefixes of i + 1 columns and more.
+    let new_prefix_labels: Vec<BranchOffset> =
+        (0..num_columns).map(|_| program.allocate_label()).collect();
+    program.emit_insn(Insn::If {
+        reg: is_first_reg,
+        target_pc: new_prefix_labels[0],
+        jump_if_null: false,
+    });
+    for (i, column) in index.columns.iter().enumerate() {
+        program.emit_insn(Insn::Ne {
+            lhs: key_regs + i,
+            rhs: prev_key_regs + i,
+            target_pc: new_prefix_labels[i],
+            flags: CmpInsFlags::default().null_eq(),
+            collation: column.collation,
+        });
+    }
+    program.emit_insn(Insn::Goto {
+        target_pc: next_label,
+    });
+    for (i, label) in new_prefix_labels.into_iter().enumerate() {
+        program.preassign_label_to_next_insn(label);
+        program.emit_insn(Insn::Add {
+            lhs: distinct_regs + i,
+            rhs: one_reg,
+            dest: distinct_regs + i,
+        });
+    }
+    program.emit_insn(Insn::Copy {
+        src_reg: key_regs,
+        dst_reg: prev_key_regs,
+        extra_amount: num_columns - 1,
+    });
+    program.emit_insn(Insn::Integer {
+        value: 0,
+        dest: is_first_reg,
+    });
+    program.preassign_label_to_next_insn(next_label);
+    program.emit_insn(Insn::Next {
+        cursor_id: index_cursor_id,
+        pc_if_next: loop_start_label,
+    });
+    program.preassign_label_to_next_insn(loop_end_label);
+    distinct_regs
+}
+
+/// Inserts the row `(tbl, idx, stat)` into `sqlite_stat1`, with the stat taken from `stat_reg`.
+fn emit_stat_row(
+    program: &mut ProgramBuilder,
+    stat_cursor_id: usize,
+    tbl: &str,
+    idx: Option<&str>,
+    stat_reg: usize,
+) {
+    let row_regs = program.alloc_registers(3);
+    program.emit_string8(tbl.to_string(), row_regs);
+    match idx {
+        Some(idx) => program.emit_string8(idx.to_string(), row_regs + 1),
+        None => program.emit_null(row_regs + 1, None),
+    }
+    program.emit_insn(Insn::Copy {
+        src_reg: stat_reg,
+        dst_reg: row_regs + 2,
+        extra_amount: 0,
+    });
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: row_regs,
+        count: 3,
+        dest_reg: record_reg,
+        index_name: None,
+    });
+    let rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::NewRowid {
+        cursor: stat_cursor_id,
+        rowid_reg,
+        prev_largest_reg: 0,
+    });
+    program.emit_insn(Insn::Insert {
+        cursor: stat_cursor_id,
+        key_reg: rowid_reg,
+        record_reg,
+        flag: InsertFlags::new(),
+        table_name: SQLITE_STAT1_TABLE.to_string(),
+    });
+}
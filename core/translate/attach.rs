@@ -0,0 +1,103 @@
+use crate::schema::Schema;
+use crate::translate::emitter::{Resolver, TransactionMode};
+use crate::translate::expr::{is_double_quoted_identifier, translate_expr};
+use crate::translate::{ProgramBuilder, ProgramBuilderOpts};
+use crate::util::normalize_ident;
+use crate::vdbe::insn::Insn;
+use crate::{bail_parse_error, Result, SymbolTable};
+use turso_sqlite3_parser::ast::{self, Expr, QualifiedName};
+
+/// Translates `ATTACH DATABASE file AS name`. Like in SQLite, a bare identifier stands for a
+/// string, so `ATTACH aux.db AS aux` works without quotes.
+pub fn translate_attach(
+    file: &Expr,
+    name: &Expr,
+    key: Option<&Expr>,
+    schema: &Schema,
+    syms: &SymbolTable,
+    mut program: ProgramBuilder,
+) -> Result<ProgramBuilder> {
+    if key.is_some() {
+        bail_parse_error!("ATTACH with an encryption KEY is not supported");
+    }
+    program.extend(&ProgramBuilderOpts {
+        num_cursors: 0,
+        approx_num_insns: 4,
+        approx_num_labels: 0,
+    });
+    let resolver = Resolver::new(schema, syms);
+    let file_reg = program.alloc_registers(2);
+    let name_reg = file_reg + 1;
+    translate_name_expr(&mut program, file, file_reg, &resolver)?;
+    translate_name_expr(&mut program, name, name_reg, &resolver)?;
+    program.emit_insn(Insn::Attach { file_reg, name_reg });
+    program.epilogue(TransactionMode::None);
+    Ok(program)
+}
+
+/// Translates `DETACH DATABASE name`.
+pub fn translate_detach(
+    name: &Expr,
+    schema: &Schema,
+    syms: &SymbolTable,
+    mut program: ProgramBuilder,
+) -> Result<ProgramBuilder> {
+    program.extend(&ProgramBuilderOpts {
+        num_cursors: 0,
+        approx_num_insns: 3,
+        approx_num_labels: 0,
+    });
+    let resolver = Resolver::new(schema, syms);
+    let name_reg = program.alloc_register();
+    translate_name_expr(&mut program, name, name_reg, &resolver)?;
+    program.emit_insn(Insn::Detach { name_reg });
+    program.epilogue(TransactionMode::None);
+    Ok(program)
+}
+
+/// Evaluates `expr` into `reg`, where a bare identifier stands for its name.
+fn translate_name_expr(
+    program: &mut ProgramBuilder,
+    expr: &Expr,
+    reg: usize,
+    resolver: &Resolver,
+) -> Result<()> {
+    match expr {
+        Expr::Id(id) if !is_double_quoted_identifier(&id.0) => {
+            program.emit_string8(id.0.clone(), reg)
+        }
+        _ => {
+            translate_expr(program, None, expr, reg, resolver)?;
+        }
+    }
+    Ok(())
+}
+
+/// Fails if `name` is qualified with an attached database, for the statements that only support
+/// the main database.
+pub fn ensure_main_database(schema: &Schema, name: &QualifiedName, statement: &str) -> Result<()> {
+    if let Some(db_name) = &name.db_name {
+        if schema.database_index(&db_name.0)? != 0 {
+            bail_parse_error!("{statement} in attached databases is not supported");
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether `stmt` creates a table in the temp database.
+pub fn creates_temp_table(stmt: &ast::Stmt) -> bool {
+    match stmt {
+        ast::Stmt::CreateTable {
+            temporary,
+            tbl_name,
+            ..
+        } => {
+            *temporary
+                || tbl_name
+                    .db_name
+                    .as_ref()
+                    .is_some_and(|db_name| normalize_ident(&db_name.0) == "temp")
+        }
+        _ => false,
+    }
+}
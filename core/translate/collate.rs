@@ -1,12 +1,76 @@
-use std::{cmp::Ordering, str::FromStr as _};
+use std::{
+    cell::RefCell,
+    cmp::Ordering,
+    collections::HashMap,
+    fmt::{self, Display},
+    rc::Rc,
+    str::FromStr as _,
+    sync::Mutex,
+};
 
 use tracing::Level;
 
-// TODO: in the future allow user to define collation sequences
-// Will have to meddle with ffi for this
-#[derive(
-    Debug, Clone, Copy, Eq, PartialEq, strum_macros::Display, strum_macros::EnumString, Default,
-)]
+/// Compares two strings for a collation created with [crate::Connection::create_collation].
+pub type CollationFn = Box<dyn Fn(&str, &str) -> Ordering>;
+
+/// The collations created on a connection, by lowercase name.
+pub(crate) type Collations = Rc<RefCell<HashMap<String, Rc<CollationFn>>>>;
+
+thread_local! {
+    /// The collations of the connection whose statement is being prepared or run on this thread.
+    static CURRENT_COLLATIONS: RefCell<Option<Collations>> = const { RefCell::new(None) };
+}
+
+/// Makes the collations of a connection the ones [CollationSeq] finds until it is dropped, when
+/// the ones found before are restored. Comparisons have no access to the connection, so they
+/// find its collations here.
+pub(crate) struct CollationsGuard(Option<Collations>);
+
+impl CollationsGuard {
+    pub(crate) fn new(collations: &Collations) -> Self {
+        Self(CURRENT_COLLATIONS.with(|current| current.replace(Some(collations.clone()))))
+    }
+}
+
+impl Drop for CollationsGuard {
+    fn drop(&mut self) {
+        let previous = self.0.take();
+        CURRENT_COLLATIONS.with(|current| *current.borrow_mut() = previous);
+    }
+}
+
+fn find_collation(name: &str) -> Option<Rc<CollationFn>> {
+    CURRENT_COLLATIONS.with(|current| {
+        current
+            .borrow()
+            .as_ref()
+            .and_then(|collations| collations.borrow().get(name).cloned())
+    })
+}
+
+/// The name of a collation that isn't built in, interned so that [CollationSeq] stays `Copy`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CollationName(&'static str);
+
+impl CollationName {
+    fn intern(name: &str) -> Self {
+        static NAMES: Mutex<Vec<&'static str>> = Mutex::new(Vec::new());
+        let name = name.to_ascii_lowercase();
+        let mut names = NAMES.lock().unwrap();
+        if let Some(interned) = names.iter().find(|interned| **interned == name) {
+            return Self(interned);
+        }
+        let interned: &'static str = Box::leak(name.into_boxed_str());
+        names.push(interned);
+        Self(interned)
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        self.0
+    }
+}
+
+#[derive(Debug, Clone, Copy, Eq, PartialEq, strum_macros::EnumString, Default)]
 #[strum(ascii_case_insensitive)]
 /// **Pre defined collation sequences**\
 /// Collating functions only matter when comparing string values.
@@ -15,17 +79,52 @@ pub enum CollationSeq {
     /// Standard String compare
     #[default]
     Binary,
-    /// Ascii case insensitive
+    /// Case insensitive, for ASCII letters only unless the `unicode` feature is enabled
     NoCase,
     /// Same as Binary but with trimmed whitespace
     Rtrim,
+    /// A collation created with [crate::Connection::create_collation].
+    #[strum(disabled)]
+    Custom(CollationName),
+}
+
+impl Display for CollationSeq {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CollationSeq::Binary => f.write_str("Binary"),
+            CollationSeq::NoCase => f.write_str("NoCase"),
+            CollationSeq::Rtrim => f.write_str("Rtrim"),
+            CollationSeq::Custom(name) => f.write_str(name.as_str()),
+        }
+    }
 }
 
 impl CollationSeq {
+    /// Resolves the collation named in a statement, which must be built in or created on the
+    /// connection.
     pub fn new(collation: &str) -> crate::Result<Self> {
-        CollationSeq::from_str(collation).map_err(|_| {
-            crate::LimboError::ParseError(format!("no such collation sequence: {collation}"))
-        })
+        match Self::from_name(collation) {
+            CollationSeq::Custom(name) if find_collation(name.as_str()).is_none() => Err(
+                crate::LimboError::ParseError(format!("no such collation sequence: {collation}")),
+            ),
+            collation => Ok(collation),
+        }
+    }
+
+    /// Resolves the collation named in the schema. Unlike with [Self::new], a collation that
+    /// isn't built in doesn't have to exist, as the schema is loaded before the collations of
+    /// the connection are created.
+    pub fn from_name(collation: &str) -> Self {
+        CollationSeq::from_str(collation)
+            .unwrap_or_else(|_| CollationSeq::Custom(CollationName::intern(collation)))
+    }
+
+    /// Fails if the collation isn't built in and wasn't created on the connection.
+    pub fn ensure_exists(&self) -> crate::Result<()> {
+        match self {
+            CollationSeq::Custom(name) => Self::new(name.as_str()).map(|_| ()),
+            _ => Ok(()),
+        }
     }
 
     pub fn compare_strings(&self, lhs: &str, rhs: &str) -> Ordering {
@@ -34,20 +133,23 @@ impl CollationSeq {
             CollationSeq::Binary => Self::binary_cmp(lhs, rhs),
             CollationSeq::NoCase => Self::nocase_cmp(lhs, rhs),
             CollationSeq::Rtrim => Self::rtrim_cmp(lhs, rhs),
+            // A collation of the schema that doesn't exist compares like BINARY.
+            CollationSeq::Custom(name) => match find_collation(name.as_str()) {
+                Some(collation) => collation(lhs, rhs),
+                None => Self::binary_cmp(lhs, rhs),
+            },
         }
     }
 
     fn binary_cmp(lhs: &str, rhs: &str) -> Ordering {
-        lhs.cmp(rhs)
+        crate::storage::text_encoding::binary_cmp(lhs, rhs)
     }
 
     fn nocase_cmp(lhs: &str, rhs: &str) -> Ordering {
-        let nocase_lhs = uncased::UncasedStr::new(lhs);
-        let nocase_rhs = uncased::UncasedStr::new(rhs);
-        nocase_lhs.cmp(nocase_rhs)
+        crate::unicode::nocase_cmp(lhs, rhs)
     }
 
     fn rtrim_cmp(lhs: &str, rhs: &str) -> Ordering {
-        lhs.trim_end().cmp(rhs.trim_end())
+        Self::binary_cmp(lhs.trim_end(), rhs.trim_end())
     }
 }
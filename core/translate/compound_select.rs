@@ -1,10 +1,13 @@
 use crate::schema::{Index, IndexColumn, Schema};
-use crate::translate::emitter::{emit_query, LimitCtx, TransactionMode, TranslateCtx};
+use crate::translate::emitter::{
+    emit_limit_value, emit_query, LimitCtx, Resolver, TransactionMode, TranslateCtx,
+};
 use crate::translate::plan::{Plan, QueryDestination, SelectPlan};
+use crate::translate::planner::try_fold_expr_to_i64;
 use crate::vdbe::builder::{CursorType, ProgramBuilder};
 use crate::vdbe::insn::Insn;
 use crate::vdbe::BranchOffset;
-use crate::SymbolTable;
+use crate::{Limit, SymbolTable};
 use std::sync::Arc;
 use tracing::instrument;
 use turso_sqlite3_parser::ast::{CompoundOperator, SortOrder};
@@ -19,7 +22,7 @@ pub fn emit_program_for_compound_select(
     syms: &SymbolTable,
 ) -> crate::Result<()> {
     let Plan::CompoundSelect {
-        left: _left,
+        left,
         right_most,
         limit,
         ..
@@ -27,28 +30,37 @@ pub fn emit_program_for_compound_select(
     else {
         crate::bail_parse_error!("expected compound select plan");
     };
+    if left.len() + 1 > program.limit(Limit::CompoundSelect) {
+        crate::bail_parse_error!("too many terms in compound SELECT");
+    }
 
     let right_plan = right_most.clone();
     // Trivial exit on LIMIT 0
-    if let Some(limit) = limit {
-        if *limit == 0 {
-            program.epilogue(TransactionMode::Read);
-            program.result_columns = right_plan.result_columns;
-            program.table_references.extend(right_plan.table_references);
-            return Ok(());
-        }
+    if let Some(0) = limit.as_deref().and_then(try_fold_expr_to_i64) {
+        program.epilogue(TransactionMode::Read);
+        program.result_columns = right_plan.result_columns;
+        program.table_references.extend(right_plan.table_references);
+        return Ok(());
     }
 
     // Each subselect shares the same limit_ctx, because the LIMIT applies to the entire compound select,
     // not just a single subselect.
-    let limit_ctx = limit.map(|limit| {
-        let reg = program.alloc_register();
-        program.emit_insn(Insn::Integer {
-            value: limit as i64,
-            dest: reg,
-        });
-        LimitCtx::new_shared(reg)
-    });
+    let label_query_end = program.allocate_label();
+    let limit_ctx = match limit {
+        Some(limit) => {
+            let reg = program.alloc_register();
+            emit_limit_value(program, limit, reg, &Resolver::new(schema, syms))?;
+            if try_fold_expr_to_i64(limit).is_none() {
+                program.emit_insn(Insn::IfNot {
+                    reg,
+                    target_pc: label_query_end,
+                    jump_if_null: true,
+                });
+            }
+            Some(LimitCtx::new_shared(reg))
+        }
+        None => None,
+    };
 
     // When a compound SELECT is part of a query that yields results to a coroutine (e.g. within an INSERT clause),
     // we must allocate registers for the result columns to be yielded. Each subselect will then yield to
@@ -71,6 +83,7 @@ pub fn emit_program_for_compound_select(
         reg_result_cols_start,
     )?;
 
+    program.preassign_label_to_next_insn(label_query_end);
     program.epilogue(TransactionMode::Read);
     program.result_columns = right_plan.result_columns;
     program.table_references.extend(right_plan.table_references);
@@ -120,7 +133,7 @@ fn emit_compound_select(
                 let compound_select = Plan::CompoundSelect {
                     left,
                     right_most: plan,
-                    limit,
+                    limit: limit.clone(),
                     offset,
                     order_by,
                 };
@@ -162,6 +175,7 @@ fn emit_compound_select(
                     cursor_id: dedupe_index.0,
                     index: dedupe_index.1.clone(),
                     is_delete: false,
+                    affinity_str: None,
                 };
                 let compound_select = Plan::CompoundSelect {
                     left,
@@ -184,6 +198,7 @@ fn emit_compound_select(
                     cursor_id: dedupe_index.0,
                     index: dedupe_index.1.clone(),
                     is_delete: false,
+                    affinity_str: None,
                 };
                 emit_query(program, &mut right_most, &mut right_most_ctx)?;
 
@@ -211,6 +226,7 @@ fn emit_compound_select(
                     cursor_id: left_cursor_id,
                     index: left_index.clone(),
                     is_delete: false,
+                    affinity_str: None,
                 };
                 let compound_select = Plan::CompoundSelect {
                     left,
@@ -235,6 +251,7 @@ fn emit_compound_select(
                     cursor_id: right_cursor_id,
                     index: right_index,
                     is_delete: false,
+                    affinity_str: None,
                 };
                 emit_query(program, &mut right_most, &mut right_most_ctx)?;
                 read_intersect_rows(
@@ -262,6 +279,7 @@ fn emit_compound_select(
                     cursor_id,
                     index: index.clone(),
                     is_delete: false,
+                    affinity_str: None,
                 };
                 let compound_select = Plan::CompoundSelect {
                     left,
@@ -283,6 +301,7 @@ fn emit_compound_select(
                     cursor_id,
                     index: index.clone(),
                     is_delete: true,
+                    affinity_str: None,
                 };
                 emit_query(program, &mut right_most, &mut right_most_ctx)?;
                 if new_index {
@@ -327,14 +346,17 @@ fn create_dedupe_index(
                 pos_in_table: 0,
                 default: None,
                 collation: None, // FIXME: this should be inferred
+                expr: None,
             })
             .collect(),
         name: "compound_dedupe".to_string(),
         root_page: 0,
         ephemeral: true,
+        conflict_clause: None,
         table_name: String::new(),
         unique: false,
         has_rowid: false,
+        where_clause: None,
     });
     let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(dedupe_index.clone()));
     program.emit_insn(Insn::OpenEphemeral {
@@ -3,17 +3,21 @@ use crate::translate::emitter::emit_program;
 use crate::translate::optimizer::optimize_plan;
 use crate::translate::plan::{DeletePlan, Operation, Plan};
 use crate::translate::planner::{parse_limit, parse_where};
-use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts, TableRefIdCounter};
-use crate::{schema::Schema, Result, SymbolTable};
-use turso_sqlite3_parser::ast::{Expr, Limit, QualifiedName};
+use crate::translate::returning::prepare_returning;
+use crate::translate::trigger::has_triggers;
+use crate::translate::update::prepare_ephemeral_rowid_plan;
+use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts};
+use crate::{schema::Schema, AuthAction, Result, SymbolTable};
+use turso_sqlite3_parser::ast::{Expr, Limit, QualifiedName, ResultColumn, TriggerEvent};
 
-use super::plan::{ColumnUsedMask, IterationDirection, JoinedTable, TableReferences};
+use super::plan::{ColumnUsedMask, IterationDirection, JoinedTable, Search, TableReferences};
 
 pub fn translate_delete(
     schema: &Schema,
     tbl_name: &QualifiedName,
     where_clause: Option<Box<Expr>>,
     limit: Option<Box<Limit>>,
+    returning: Option<Vec<ResultColumn>>,
     syms: &SymbolTable,
     mut program: ProgramBuilder,
 ) -> Result<ProgramBuilder> {
@@ -25,13 +29,14 @@ pub fn translate_delete(
         );
     }
     let mut delete_plan = prepare_delete_plan(
+        &mut program,
         schema,
         tbl_name,
         where_clause,
         limit,
-        &mut program.table_reference_counter,
+        returning,
     )?;
-    optimize_plan(&mut delete_plan, schema)?;
+    optimize_plan(&mut delete_plan, schema, program.like_behavior())?;
     let Plan::Delete(ref delete) = delete_plan else {
         panic!("delete_plan is not a DeletePlan");
     };
@@ -46,16 +51,29 @@ pub fn translate_delete(
 }
 
 pub fn prepare_delete_plan(
+    program: &mut ProgramBuilder,
     schema: &Schema,
     tbl_name: &QualifiedName,
     where_clause: Option<Box<Expr>>,
     limit: Option<Box<Limit>>,
-    table_ref_counter: &mut TableRefIdCounter,
+    returning: Option<Vec<ResultColumn>>,
 ) -> Result<Plan> {
-    let table = match schema.get_table(tbl_name.name.0.as_str()) {
+    let database_id = schema.object_database(tbl_name)?;
+    let db_schema = schema.database(database_id);
+    if db_schema.get_view(tbl_name.name.0.as_str()).is_some() {
+        crate::bail_parse_error!("cannot modify {} because it is a view", tbl_name);
+    }
+    let table = match db_schema.get_table(tbl_name.name.0.as_str()) {
         Some(table) => table,
         None => crate::bail_parse_error!("no such table: {}", tbl_name),
     };
+    // Like in SQLite, the rows are deleted even if the authorizer ignores the DELETE.
+    program.authorize(
+        AuthAction::Delete {
+            table: table.get_name(),
+        },
+        Some(schema.database_name(database_id)),
+    )?;
     let table = if let Some(table) = table.virtual_table() {
         Table::Virtual(table.clone())
     } else if let Some(table) = table.btree() {
@@ -64,42 +82,91 @@ pub fn prepare_delete_plan(
         crate::bail_parse_error!("Table is neither a virtual table nor a btree table");
     };
     let name = tbl_name.name.0.as_str().to_string();
-    let indexes = schema.get_indices(table.get_name()).to_vec();
+    let result_columns = match (returning, table.btree()) {
+        (None, _) => vec![],
+        (Some(returning), Some(btree_table)) => {
+            let identifier = tbl_name.alias.as_ref().map_or(&name, |alias| &alias.0);
+            prepare_returning(returning, &btree_table, identifier)?
+        }
+        (Some(_), None) => {
+            crate::bail_parse_error!("RETURNING is not supported for virtual tables");
+        }
+    };
+    // Triggers may modify the table while it is being looped over, so the rowids of
+    // the rows to delete are gathered up front.
+    let has_delete_triggers = table.btree().is_some_and(|btree_table| {
+        has_triggers(db_schema, &btree_table, &TriggerEvent::Delete, &[])
+    });
+    if has_delete_triggers && database_id != 0 {
+        crate::bail_parse_error!("triggers in attached databases are not supported");
+    }
+    let indexes = db_schema.get_indices(table.get_name()).to_vec();
     let joined_tables = vec![JoinedTable {
         table,
-        identifier: name,
-        internal_id: table_ref_counter.next(),
+        identifier: name.clone(),
+        internal_id: program.table_reference_counter.next(),
         op: Operation::Scan {
             iter_dir: IterationDirection::Forwards,
             index: None,
         },
         join_info: None,
         col_used_mask: ColumnUsedMask::default(),
+        database_id,
+        vtab_order_by: Vec::new(),
     }];
     let mut table_references = TableReferences::new(joined_tables, vec![]);
 
+    let ephemeral_plan = if has_delete_triggers {
+        let ephemeral_plan = prepare_ephemeral_rowid_plan(
+            program,
+            schema,
+            &table_references.joined_tables()[0].table,
+            &name,
+            database_id,
+            IterationDirection::Forwards,
+            where_clause.as_ref().map(|e| *e.clone()),
+            &[],
+        )?;
+        let table = ephemeral_plan
+            .table_references
+            .joined_tables()
+            .first()
+            .unwrap();
+        // A single row looked up by rowid is not looped over.
+        if matches!(table.op, Operation::Search(Search::RowidEq { .. })) {
+            None
+        } else {
+            Some(ephemeral_plan)
+        }
+    } else {
+        None
+    };
+
     let mut where_predicates = vec![];
 
-    // Parse the WHERE clause
-    parse_where(
-        where_clause.map(|e| *e),
-        &mut table_references,
-        None,
-        &mut where_predicates,
-    )?;
+    if ephemeral_plan.is_none() {
+        // Parse the WHERE clause
+        parse_where(
+            where_clause.map(|e| *e),
+            &mut table_references,
+            None,
+            &mut where_predicates,
+        )?;
+    }
 
     // Parse the LIMIT/OFFSET clause
     let (resolved_limit, resolved_offset) = limit.map_or(Ok((None, None)), |l| parse_limit(&l))?;
 
     let plan = DeletePlan {
         table_references,
-        result_columns: vec![],
+        result_columns,
         where_clause: where_predicates,
         order_by: None,
         limit: resolved_limit,
         offset: resolved_offset,
         contains_constant_false_condition: false,
         indexes,
+        ephemeral_plan,
     };
 
     Ok(Plan::Delete(plan))
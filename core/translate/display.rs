@@ -5,17 +5,19 @@ use turso_sqlite3_parser::{
     ast::{
         self,
         fmt::{ToTokens, TokenStream},
-        SortOrder, TableInternalId,
+        TableInternalId,
     },
     dialect::TokenType,
     to_sql_string::ToSqlContext,
 };
 
-use crate::{schema::Table, translate::plan::TableReferences};
+use crate::{
+    schema::Table,
+    translate::{explain::QueryPlanDescription, plan::TableReferences},
+};
 
 use super::plan::{
-    Aggregate, DeletePlan, JoinedTable, Operation, Plan, ResultSetColumn, Search, SelectPlan,
-    UpdatePlan,
+    Aggregate, DeletePlan, JoinedTable, Plan, ResultSetColumn, SelectPlan, UpdatePlan,
 };
 
 impl Display for Aggregate {
@@ -33,203 +35,7 @@ impl Display for Aggregate {
 /// For EXPLAIN QUERY PLAN
 impl Display for Plan {
     fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        match self {
-            Self::Select(select_plan) => select_plan.fmt(f),
-            Self::CompoundSelect {
-                left,
-                right_most,
-                limit,
-                offset,
-                order_by,
-            } => {
-                for (plan, operator) in left {
-                    plan.fmt(f)?;
-                    writeln!(f, "{operator}")?;
-                }
-                right_most.fmt(f)?;
-                if let Some(limit) = limit {
-                    writeln!(f, "LIMIT: {limit}")?;
-                }
-                if let Some(offset) = offset {
-                    writeln!(f, "OFFSET: {offset}")?;
-                }
-                if let Some(order_by) = order_by {
-                    writeln!(f, "ORDER BY:")?;
-                    for (expr, dir) in order_by {
-                        writeln!(
-                            f,
-                            "  - {} {}",
-                            expr,
-                            if *dir == SortOrder::Asc {
-                                "ASC"
-                            } else {
-                                "DESC"
-                            }
-                        )?;
-                    }
-                }
-                Ok(())
-            }
-            Self::Delete(delete_plan) => delete_plan.fmt(f),
-            Self::Update(update_plan) => update_plan.fmt(f),
-        }
-    }
-}
-
-impl Display for SelectPlan {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        writeln!(f, "QUERY PLAN")?;
-
-        // Print each table reference with appropriate indentation based on join depth
-        for (i, reference) in self.table_references.joined_tables().iter().enumerate() {
-            let is_last = i == self.table_references.joined_tables().len() - 1;
-            let indent = if i == 0 {
-                if is_last { "`--" } else { "|--" }.to_string()
-            } else {
-                format!(
-                    "   {}{}",
-                    "|  ".repeat(i - 1),
-                    if is_last { "`--" } else { "|--" }
-                )
-            };
-
-            match &reference.op {
-                Operation::Scan { .. } => {
-                    let table_name = if reference.table.get_name() == reference.identifier {
-                        reference.identifier.clone()
-                    } else {
-                        format!("{} AS {}", reference.table.get_name(), reference.identifier)
-                    };
-
-                    writeln!(f, "{indent}SCAN {table_name}")?;
-                }
-                Operation::Search(search) => match search {
-                    Search::RowidEq { .. } | Search::Seek { index: None, .. } => {
-                        writeln!(
-                            f,
-                            "{}SEARCH {} USING INTEGER PRIMARY KEY (rowid=?)",
-                            indent, reference.identifier
-                        )?;
-                    }
-                    Search::Seek {
-                        index: Some(index), ..
-                    } => {
-                        writeln!(
-                            f,
-                            "{}SEARCH {} USING INDEX {}",
-                            indent, reference.identifier, index.name
-                        )?;
-                    }
-                },
-            }
-        }
-        Ok(())
-    }
-}
-
-impl Display for DeletePlan {
-    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
-        writeln!(f, "QUERY PLAN")?;
-
-        // Delete plan should only have one table reference
-        if let Some(reference) = self.table_references.joined_tables().first() {
-            let indent = "`--";
-
-            match &reference.op {
-                Operation::Scan { .. } => {
-                    let table_name = if reference.table.get_name() == reference.identifier {
-                        reference.identifier.clone()
-                    } else {
-                        format!("{} AS {}", reference.table.get_name(), reference.identifier)
-                    };
-
-                    writeln!(f, "{indent}DELETE FROM {table_name}")?;
-                }
-                Operation::Search { .. } => {
-                    panic!("DELETE plans should not contain search operations");
-                }
-            }
-        }
-        Ok(())
-    }
-}
-
-impl fmt::Display for UpdatePlan {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "QUERY PLAN")?;
-
-        for (i, reference) in self.table_references.joined_tables().iter().enumerate() {
-            let is_last = i == self.table_references.joined_tables().len() - 1;
-            let indent = if i == 0 {
-                if is_last { "`--" } else { "|--" }.to_string()
-            } else {
-                format!(
-                    "   {}{}",
-                    "|  ".repeat(i - 1),
-                    if is_last { "`--" } else { "|--" }
-                )
-            };
-
-            match &reference.op {
-                Operation::Scan { .. } => {
-                    let table_name = if reference.table.get_name() == reference.identifier {
-                        reference.identifier.clone()
-                    } else {
-                        format!("{} AS {}", reference.table.get_name(), reference.identifier)
-                    };
-
-                    if i == 0 {
-                        writeln!(f, "{indent}UPDATE {table_name}")?;
-                    } else {
-                        writeln!(f, "{indent}SCAN {table_name}")?;
-                    }
-                }
-                Operation::Search(search) => match search {
-                    Search::RowidEq { .. } | Search::Seek { index: None, .. } => {
-                        writeln!(
-                            f,
-                            "{}SEARCH {} USING INTEGER PRIMARY KEY (rowid=?)",
-                            indent, reference.identifier
-                        )?;
-                    }
-                    Search::Seek {
-                        index: Some(index), ..
-                    } => {
-                        writeln!(
-                            f,
-                            "{}SEARCH {} USING INDEX {}",
-                            indent, reference.identifier, index.name
-                        )?;
-                    }
-                },
-            }
-        }
-        if let Some(order_by) = &self.order_by {
-            writeln!(f, "ORDER BY:")?;
-            for (expr, dir) in order_by {
-                writeln!(
-                    f,
-                    "  - {} {}",
-                    expr,
-                    if *dir == SortOrder::Asc {
-                        "ASC"
-                    } else {
-                        "DESC"
-                    }
-                )?;
-            }
-        }
-        if let Some(limit) = self.limit {
-            writeln!(f, "LIMIT: {limit}")?;
-        }
-        if let Some(ret) = &self.returning {
-            writeln!(f, "RETURNING:")?;
-            for col in ret {
-                writeln!(f, "  - {}", col.expr)?;
-            }
-        }
-
-        Ok(())
+        QueryPlanDescription::new(self).fmt(f)
     }
 }
 
@@ -315,12 +121,12 @@ impl ToTokens for Plan {
 
                 if let Some(limit) = &limit {
                     s.append(TokenType::TK_LIMIT, None)?;
-                    s.append(TokenType::TK_FLOAT, Some(&limit.to_string()))?;
+                    limit.to_tokens_with_context(s, context)?;
                 }
 
                 if let Some(offset) = &offset {
                     s.append(TokenType::TK_OFFSET, None)?;
-                    s.append(TokenType::TK_FLOAT, Some(&offset.to_string()))?;
+                    offset.to_tokens_with_context(s, context)?;
                 }
             }
             Self::Delete(delete) => delete.to_tokens_with_context(s, context)?,
@@ -456,12 +262,12 @@ impl ToTokens for SelectPlan {
 
         if let Some(limit) = &self.limit {
             s.append(TokenType::TK_LIMIT, None)?;
-            s.append(TokenType::TK_FLOAT, Some(&limit.to_string()))?;
+            limit.to_tokens_with_context(s, context)?;
         }
 
         if let Some(offset) = &self.offset {
             s.append(TokenType::TK_OFFSET, None)?;
-            s.append(TokenType::TK_FLOAT, Some(&offset.to_string()))?;
+            offset.to_tokens_with_context(s, context)?;
         }
 
         Ok(())
@@ -518,12 +324,12 @@ impl ToTokens for DeletePlan {
 
         if let Some(limit) = &self.limit {
             s.append(TokenType::TK_LIMIT, None)?;
-            s.append(TokenType::TK_FLOAT, Some(&limit.to_string()))?;
+            limit.to_tokens_with_context(s, context)?;
         }
 
         if let Some(offset) = &self.offset {
             s.append(TokenType::TK_OFFSET, None)?;
-            s.append(TokenType::TK_FLOAT, Some(&offset.to_string()))?;
+            offset.to_tokens_with_context(s, context)?;
         }
 
         Ok(())
@@ -540,7 +346,7 @@ impl ToTokens for UpdatePlan {
             .table_references
             .joined_tables()
             .first()
-            .expect("UPDATE Plan should have only one table reference");
+            .expect("UPDATE Plan should have at least one table reference");
         let context = [&self.table_references];
         let context = &PlanContext(&context);
 
@@ -567,6 +373,17 @@ impl ToTokens for UpdatePlan {
             context,
         )?;
 
+        let from_tables = &self.table_references.joined_tables()[1..];
+        if !from_tables.is_empty() {
+            s.append(TokenType::TK_FROM, None)?;
+            for (i, table_ref) in from_tables.iter().enumerate() {
+                if i != 0 {
+                    s.append(TokenType::TK_COMMA, None)?;
+                }
+                table_ref.to_tokens_with_context(s, context)?;
+            }
+        }
+
         if !self.where_clause.is_empty() {
             s.append(TokenType::TK_WHERE, None)?;
 
@@ -599,11 +416,11 @@ impl ToTokens for UpdatePlan {
 
         if let Some(limit) = &self.limit {
             s.append(TokenType::TK_LIMIT, None)?;
-            s.append(TokenType::TK_FLOAT, Some(&limit.to_string()))?;
+            limit.to_tokens_with_context(s, context)?;
         }
         if let Some(offset) = &self.offset {
             s.append(TokenType::TK_OFFSET, None)?;
-            s.append(TokenType::TK_FLOAT, Some(&offset.to_string()))?;
+            offset.to_tokens_with_context(s, context)?;
         }
 
         Ok(())
@@ -1,34 +1,47 @@
 // This module contains code for emitting bytecode instructions for SQL query execution.
 // It handles translating high-level SQL operations into low-level bytecode that can be executed by the virtual machine.
 
+use std::num::NonZeroUsize;
 use std::rc::Rc;
 
 use tracing::{instrument, Level};
-use turso_sqlite3_parser::ast::{self, Expr};
+use turso_sqlite3_parser::ast::{self, Expr, ResolveType};
 
 use super::aggregation::emit_ungrouped_aggregation;
-use super::expr::translate_expr;
+use super::expr::{
+    translate_expr, translate_expr_no_constant_opt, walk_expr_mut, NoConstantOptReason,
+};
 use super::group_by::{
     group_by_agg_phase, group_by_emit_row_phase, init_group_by, GroupByMetadata, GroupByRowSource,
 };
 use super::main_loop::{
     close_loop, emit_loop, init_distinct, init_loop, open_loop, LeftJoinMetadata, LoopLabels,
 };
-use super::order_by::{emit_order_by, init_order_by, SortMetadata};
+use super::order_by::{distinct_by_order_by, emit_order_by, init_order_by, SortMetadata};
 use super::plan::{
     Distinctness, JoinOrderMember, Operation, SelectPlan, TableReferences, UpdatePlan,
 };
+use super::planner::try_fold_expr_to_i64;
+use super::returning::Returning;
+use super::schema::{SQLITE_SEQUENCE_TABLE, SQLITE_TABLEID};
 use super::select::emit_simple_count;
-use super::subquery::emit_subqueries;
-use crate::error::SQLITE_CONSTRAINT_PRIMARYKEY;
+use super::subquery::{
+    emit_correlated_subquery_subroutines, emit_non_from_clause_subqueries, emit_subqueries,
+};
+use super::trigger::{emit_triggers, has_triggers, TriggerRow};
+use super::window::{emit_window, init_window, WindowMetadata};
+use crate::error::{
+    SQLITE_CONSTRAINT_CHECK, SQLITE_CONSTRAINT_NOTNULL, SQLITE_CONSTRAINT_PRIMARYKEY,
+    SQLITE_CONSTRAINT_UNIQUE,
+};
 use crate::function::Func;
-use crate::schema::{Schema, Table};
+use crate::schema::{BTreeTable, Column, Index, Schema, Table, SQLITE_AFF_NONE};
 use crate::translate::compound_select::emit_program_for_compound_select;
 use crate::translate::plan::{DeletePlan, Plan, QueryDestination, Search};
 use crate::translate::values::emit_values;
-use crate::util::exprs_are_equivalent;
+use crate::util::{exprs_are_equivalent, normalize_ident};
 use crate::vdbe::builder::{CursorKey, CursorType, ProgramBuilder};
-use crate::vdbe::insn::{CmpInsFlags, IdxInsertFlags, InsertFlags, RegisterOrLiteral};
+use crate::vdbe::insn::{ChangeCount, CmpInsFlags, IdxInsertFlags, InsertFlags, RegisterOrLiteral};
 use crate::vdbe::CursorID;
 use crate::vdbe::{insn::Insn, BranchOffset};
 use crate::{bail_parse_error, Result, SymbolTable};
@@ -38,6 +51,34 @@ pub struct Resolver<'a> {
     pub symbol_table: &'a SymbolTable,
     pub expr_to_reg_cache_enabled: bool,
     pub expr_to_reg_cache: Vec<(&'a ast::Expr, usize)>,
+    /// Where the results of the subqueries referenced by [ast::Expr::SubqueryResult] are read from.
+    pub subquery_results: Vec<SubqueryResultLocation>,
+}
+
+/// The location of the result of a subquery outside of the FROM clause.
+#[derive(Debug, Clone)]
+pub struct SubqueryResultLocation {
+    /// The internal id of the subquery.
+    pub subquery_id: ast::TableInternalId,
+    /// Where the result is stored.
+    pub output: SubqueryOutput,
+    /// The start of the subroutine that computes the result, for the current row of the outer
+    /// query if the subquery is correlated, and the register holding its return address.
+    pub subroutine: (BranchOffset, usize),
+}
+
+/// Where the result of a subquery outside of the FROM clause is stored.
+#[derive(Debug, Clone)]
+pub enum SubqueryOutput {
+    /// The result of an EXISTS or scalar subquery is held in registers,
+    /// one per result column of a row-valued subquery.
+    Registers { start_reg: usize, num_regs: usize },
+    /// The rows of an IN subquery are materialized into an ephemeral index,
+    /// which is probed with the left-hand side of the IN after applying `affinity_str` to it.
+    EphemeralIndex {
+        cursor_id: CursorID,
+        affinity_str: Option<String>,
+    },
 }
 
 impl<'a> Resolver<'a> {
@@ -47,10 +88,22 @@ impl<'a> Resolver<'a> {
             symbol_table,
             expr_to_reg_cache_enabled: false,
             expr_to_reg_cache: Vec::new(),
+            subquery_results: Vec::new(),
         }
     }
 
     pub fn resolve_function(&self, func_name: &str, arg_count: usize) -> Option<Func> {
+        // Like in SQLite, like() and glob() can be overridden by the connection, which also
+        // changes what the LIKE and GLOB operators do, and so can upper() and lower(), e.g. by
+        // an extension that knows the case of non-ASCII letters.
+        if matches!(
+            normalize_ident(func_name).as_str(),
+            "like" | "glob" | "upper" | "lower"
+        ) {
+            if let Some(func) = self.symbol_table.resolve_function(func_name, arg_count) {
+                return Some(Func::External(func));
+            }
+        }
         match Func::resolve_function(func_name, arg_count).ok() {
             Some(func) => Some(func),
             None => self
@@ -128,6 +181,8 @@ pub struct TranslateCtx<'a> {
     pub meta_group_by: Option<GroupByMetadata>,
     // metadata for the order by operator
     pub meta_sort: Option<SortMetadata>,
+    // metadata for the window functions
+    pub meta_window: Option<WindowMetadata>,
     /// mapping between table loop index and associated metadata (for left joins only)
     /// this metadata exists for the right table in a given left join
     pub meta_left_joins: Vec<Option<LeftJoinMetadata>>,
@@ -173,6 +228,7 @@ impl<'a> TranslateCtx<'a> {
             meta_group_by: None,
             meta_left_joins: (0..table_count).map(|_| None).collect(),
             meta_sort: None,
+            meta_window: None,
             result_column_indexes_in_orderby_sorter: (0..result_column_count).collect(),
             result_columns_to_skip_in_orderby_sorter: None,
             resolver: Resolver::new(schema, syms),
@@ -233,15 +289,22 @@ fn emit_program_for_select(
         plan.table_references.joined_tables().len(),
         plan.result_columns.len(),
     );
+    // The rows yielded to a coroutine are read from the registers that follow its yield
+    // register, so they are allocated before the registers of any subquery.
+    if matches!(
+        plan.query_destination,
+        QueryDestination::CoroutineYield { .. }
+    ) && plan.values.is_empty()
+    {
+        t_ctx.reg_result_cols_start = Some(program.alloc_registers(plan.result_columns.len()));
+    }
 
     // Trivial exit on LIMIT 0
-    if let Some(limit) = plan.limit {
-        if limit == 0 {
-            program.epilogue(TransactionMode::Read);
-            program.result_columns = plan.result_columns;
-            program.table_references.extend(plan.table_references);
-            return Ok(());
-        }
+    if let Some(0) = plan.limit.as_deref().and_then(try_fold_expr_to_i64) {
+        program.epilogue(TransactionMode::Read);
+        program.result_columns = plan.result_columns;
+        program.table_references.extend(plan.table_references);
+        return Ok(());
     }
     // Emit main parts of query
     emit_query(program, &mut plan, &mut t_ctx)?;
@@ -271,8 +334,22 @@ pub fn emit_query<'a>(
 
     // Emit subqueries first so the results can be read in the main query loop.
     emit_subqueries(program, t_ctx, &mut plan.table_references)?;
+    emit_non_from_clause_subqueries(
+        program,
+        t_ctx,
+        &plan.table_references,
+        &mut plan.non_from_clause_subqueries,
+    )?;
 
-    init_limit(program, t_ctx, plan.limit, plan.offset);
+    // A LIMIT that turns out to be 0 when the query runs skips the query entirely.
+    let label_query_end = program.allocate_label();
+    init_limit(
+        program,
+        t_ctx,
+        plan.limit.as_deref(),
+        plan.offset.as_deref(),
+        label_query_end,
+    )?;
 
     // No rows will be read from source table loops if there is a constant false condition eg. WHERE 0
     // however an aggregation might still happen,
@@ -322,7 +399,13 @@ pub fn emit_query<'a>(
         t_ctx.reg_agg_start = Some(program.alloc_registers_and_init_w_null(plan.aggregates.len()));
     }
 
-    let distinct_ctx = if let Distinctness::Distinct { .. } = &plan.distinctness {
+    if plan.window.is_some() {
+        init_window(program, t_ctx, plan)?;
+    }
+
+    // The rows of a SELECT DISTINCT are deduplicated with an ephemeral index,
+    // unless the ORDER BY sorts the duplicates next to each other.
+    let distinct_ctx = if plan.distinctness.is_distinct() && !distinct_by_order_by(plan) {
         Some(init_distinct(program, plan))
     } else {
         None
@@ -339,9 +422,11 @@ pub fn emit_query<'a>(
         OperationMode::SELECT,
         &plan.where_clause,
     )?;
+    emit_correlated_subquery_subroutines(program, t_ctx, &mut plan.non_from_clause_subqueries)?;
 
     if plan.is_simple_count() {
         emit_simple_count(program, t_ctx, plan)?;
+        program.preassign_label_to_next_insn(label_query_end);
         return Ok(t_ctx.reg_result_cols_start.unwrap());
     }
 
@@ -372,8 +457,10 @@ pub fn emit_query<'a>(
     let mut order_by_necessary = plan.order_by.is_some() && !plan.contains_constant_false_condition;
     let order_by = plan.order_by.as_ref();
 
-    // Handle GROUP BY and aggregation processing
-    if plan.group_by.is_some() {
+    // Handle window functions, GROUP BY and aggregation processing
+    if plan.window.is_some() {
+        emit_window(program, t_ctx, plan)?;
+    } else if plan.group_by.is_some() {
         let row_source = &t_ctx
             .meta_group_by
             .as_ref()
@@ -395,13 +482,14 @@ pub fn emit_query<'a>(
         emit_order_by(program, t_ctx, plan)?;
     }
 
+    program.preassign_label_to_next_insn(label_query_end);
     Ok(t_ctx.reg_result_cols_start.unwrap())
 }
 
 #[instrument(skip_all, level = Level::DEBUG)]
 fn emit_program_for_delete(
     program: &mut ProgramBuilder,
-    plan: DeletePlan,
+    mut plan: DeletePlan,
     schema: &Schema,
     syms: &SymbolTable,
 ) -> Result<()> {
@@ -414,24 +502,60 @@ fn emit_program_for_delete(
     );
 
     // exit early if LIMIT 0
-    if let Some(0) = plan.limit {
+    if let Some(0) = plan.limit.as_deref().and_then(try_fold_expr_to_i64) {
         program.epilogue(TransactionMode::Write);
         program.result_columns = plan.result_columns;
         program.table_references.extend(plan.table_references);
         return Ok(());
     }
 
-    init_limit(program, &mut t_ctx, plan.limit, None);
+    let returning = Returning::open(program, &plan.result_columns);
 
     // No rows will be read from source table loops if there is a constant false condition eg. WHERE 0
     let after_main_loop_label = program.allocate_label();
     t_ctx.label_main_loop_end = Some(after_main_loop_label);
+    init_limit(
+        program,
+        &mut t_ctx,
+        plan.limit.as_deref(),
+        None,
+        after_main_loop_label,
+    )?;
     if plan.contains_constant_false_condition {
         program.emit_insn(Insn::Goto {
             target_pc: after_main_loop_label,
         });
     }
 
+    let ephemeral_plan = plan.ephemeral_plan.take();
+    // The rowids of the rows to update are gathered into an ephemeral table, or the PRIMARY
+    // KEY values into an ephemeral index for a WITHOUT ROWID table.
+    let temp_cursor_id = ephemeral_plan
+        .as_ref()
+        .map(|plan| match &plan.query_destination {
+            QueryDestination::EphemeralTable { cursor_id, .. }
+            | QueryDestination::EphemeralIndex { cursor_id, .. } => *cursor_id,
+            _ => unreachable!(),
+        });
+    if let Some(ephemeral_plan) = ephemeral_plan {
+        program.emit_insn(Insn::OpenEphemeral {
+            cursor_id: temp_cursor_id.unwrap(),
+            is_table: matches!(
+                ephemeral_plan.query_destination,
+                QueryDestination::EphemeralTable { .. }
+            ),
+        });
+        program.incr_nesting();
+        emit_program_for_select(program, ephemeral_plan, schema, syms)?;
+        program.decr_nesting();
+    }
+
+    let join_order = [JoinOrderMember {
+        table_id: plan.table_references.joined_tables()[0].internal_id,
+        original_idx: 0,
+        is_outer: false,
+    }];
+
     // Initialize cursors and other resources needed for query execution
     init_loop(
         program,
@@ -448,23 +572,33 @@ fn emit_program_for_delete(
         program,
         &mut t_ctx,
         &plan.table_references,
-        &[JoinOrderMember::default()],
+        &join_order,
         &plan.where_clause,
-        None,
+        temp_cursor_id,
     )?;
 
-    emit_delete_insns(program, &mut t_ctx, &plan.table_references)?;
+    emit_delete_insns(
+        program,
+        &mut t_ctx,
+        &plan.table_references,
+        temp_cursor_id,
+        returning.as_ref(),
+    )?;
 
     // Clean up and close the main execution loop
     close_loop(
         program,
         &mut t_ctx,
         &plan.table_references,
-        &[JoinOrderMember::default()],
-        None,
+        &join_order,
+        temp_cursor_id,
     )?;
     program.preassign_label_to_next_insn(after_main_loop_label);
 
+    if let Some(returning) = &returning {
+        returning.emit_result_rows(program);
+    }
+
     // Finalize program
     program.epilogue(TransactionMode::Write);
     program.result_columns = plan.result_columns;
@@ -476,6 +610,8 @@ fn emit_delete_insns(
     program: &mut ProgramBuilder,
     t_ctx: &mut TranslateCtx,
     table_references: &TableReferences,
+    temp_cursor_id: Option<CursorID>,
+    returning: Option<&Returning>,
 ) -> Result<()> {
     let table_reference = table_references.joined_tables().first().unwrap();
     let cursor_id = match &table_reference.op {
@@ -483,7 +619,9 @@ fn emit_delete_insns(
             program.resolve_cursor_id(&CursorKey::table(table_reference.internal_id))
         }
         Operation::Search(search) => match search {
-            Search::RowidEq { .. } | Search::Seek { index: None, .. } => {
+            Search::RowidEq { .. }
+            | Search::Seek { index: None, .. }
+            | Search::MultiIndexOr { .. } => {
                 program.resolve_cursor_id(&CursorKey::table(table_reference.internal_id))
             }
             Search::Seek {
@@ -500,10 +638,61 @@ fn emit_delete_insns(
     // Emit the instructions to delete the row
     let key_reg = program.alloc_register();
     program.emit_insn(Insn::RowId {
-        cursor_id: main_table_cursor_id,
+        cursor_id: temp_cursor_id.unwrap_or(main_table_cursor_id),
         dest: key_reg,
     });
 
+    let trigger_table = table_reference.btree().filter(|btree_table| {
+        has_triggers(
+            t_ctx.resolver.schema.database(table_reference.database_id),
+            btree_table,
+            &ast::TriggerEvent::Delete,
+            &[],
+        )
+    });
+    // Jumped to when the row is gone by the time it is deleted, or a trigger raised IGNORE.
+    let skip_row_label =
+        (temp_cursor_id.is_some() || trigger_table.is_some()).then(|| program.allocate_label());
+    if temp_cursor_id.is_some() {
+        // The rowids were gathered up front, position the table cursor on the row.
+        program.emit_insn(Insn::NotExists {
+            cursor: main_table_cursor_id,
+            rowid_reg: key_reg,
+            target_pc: skip_row_label.unwrap(),
+        });
+    }
+    let old_row = trigger_table
+        .as_ref()
+        .map(|btree_table| {
+            TriggerRow::from_cursor(
+                program,
+                &t_ctx.resolver,
+                btree_table,
+                main_table_cursor_id,
+                key_reg,
+            )
+        })
+        .transpose()?;
+    if let Some(btree_table) = &trigger_table {
+        emit_triggers(
+            program,
+            &t_ctx.resolver,
+            btree_table,
+            ast::TriggerEvent::Delete,
+            ast::TriggerTime::Before,
+            &[],
+            old_row,
+            None,
+            skip_row_label.unwrap(),
+        )?;
+        // The triggers may have deleted the row or moved the table cursor.
+        program.emit_insn(Insn::NotExists {
+            cursor: main_table_cursor_id,
+            rowid_reg: key_reg,
+            target_pc: skip_row_label.unwrap(),
+        });
+    }
+
     if table_reference.virtual_table().is_some() {
         let conflict_action = 0u16;
         let start_reg = key_reg;
@@ -520,6 +709,27 @@ fn emit_delete_insns(
             conflict_action,
         });
     } else {
+        if let Some(returning) = returning {
+            // BEFORE triggers may have changed the row, so it is read again.
+            let btree_table = table_reference
+                .btree()
+                .expect("RETURNING is only supported for b-tree tables");
+            let row = TriggerRow::from_cursor(
+                program,
+                &t_ctx.resolver,
+                &btree_table,
+                main_table_cursor_id,
+                key_reg,
+            )?;
+            returning.emit_row(
+                program,
+                &t_ctx.resolver,
+                &btree_table,
+                row.rowid_reg,
+                row.columns_start_reg,
+            )?;
+        }
+
         // Delete from all indexes before deleting from the main table.
         let indexes = t_ctx
             .resolver
@@ -542,31 +752,27 @@ fn emit_delete_insns(
         });
 
         if let Some(index_refs) = index_refs_opt {
+            let btree_table = table_reference
+                .btree()
+                .expect("only b-tree tables have indexes");
             for (index, index_cursor_id) in index_refs {
-                let num_regs = index.columns.len() + 1;
-                let start_reg = program.alloc_registers(num_regs);
-                // Emit columns that are part of the index
-                index
-                    .columns
-                    .iter()
-                    .enumerate()
-                    .for_each(|(reg_offset, column_index)| {
-                        program.emit_column(
-                            main_table_cursor_id,
-                            column_index.pos_in_table,
-                            start_reg + reg_offset,
-                        );
-                    });
-                program.emit_insn(Insn::RowId {
-                    cursor_id: main_table_cursor_id,
-                    dest: start_reg + num_regs - 1,
-                });
+                // A partial index has no entry for the rows its WHERE clause excludes.
+                let skip_index_label = program.allocate_label();
+                let start_reg = emit_index_key_for_cursor(
+                    program,
+                    &t_ctx.resolver,
+                    &btree_table,
+                    &index,
+                    main_table_cursor_id,
+                    skip_index_label,
+                )?;
                 program.emit_insn(Insn::IdxDelete {
                     start_reg,
-                    num_regs,
+                    num_regs: index.columns.len() + 1,
                     cursor_id: index_cursor_id,
                     raise_error_if_no_matching_entry: true,
                 });
+                program.preassign_label_to_next_insn(skip_index_label);
             }
         }
 
@@ -602,7 +808,24 @@ fn emit_delete_insns(
 
         program.emit_insn(Insn::Delete {
             cursor_id: main_table_cursor_id,
+            nchange: program.change_count(),
+            table_name: Some(table_reference.table.get_name().to_string()),
+            is_update: false,
         });
+
+        if let Some(btree_table) = &trigger_table {
+            emit_triggers(
+                program,
+                &t_ctx.resolver,
+                btree_table,
+                ast::TriggerEvent::Delete,
+                ast::TriggerTime::After,
+                &[],
+                old_row,
+                None,
+                skip_row_label.unwrap(),
+            )?;
+        }
     }
     if let Some(limit_ctx) = t_ctx.limit_ctx {
         program.emit_insn(Insn::DecrJumpZero {
@@ -611,6 +834,10 @@ fn emit_delete_insns(
         })
     }
 
+    if let Some(label) = skip_row_label {
+        program.preassign_label_to_next_insn(label);
+    }
+
     Ok(())
 }
 
@@ -631,16 +858,27 @@ fn emit_program_for_update(
     );
 
     // Exit on LIMIT 0
-    if let Some(0) = plan.limit {
+    if let Some(0) = plan.limit.as_deref().and_then(try_fold_expr_to_i64) {
         program.epilogue(TransactionMode::None);
         program.result_columns = plan.returning.unwrap_or_default();
         program.table_references.extend(plan.table_references);
         return Ok(());
     }
 
-    init_limit(program, &mut t_ctx, plan.limit, plan.offset);
+    let returning = plan
+        .returning
+        .as_ref()
+        .and_then(|result_columns| Returning::open(program, result_columns));
+
     let after_main_loop_label = program.allocate_label();
     t_ctx.label_main_loop_end = Some(after_main_loop_label);
+    init_limit(
+        program,
+        &mut t_ctx,
+        plan.limit.as_deref(),
+        plan.offset.as_deref(),
+        after_main_loop_label,
+    )?;
     if plan.contains_constant_false_condition {
         program.emit_insn(Insn::Goto {
             target_pc: after_main_loop_label,
@@ -648,22 +886,37 @@ fn emit_program_for_update(
     }
 
     let ephemeral_plan = plan.ephemeral_plan.take();
-    let temp_cursor_id = ephemeral_plan.as_ref().map(|plan| {
-        let QueryDestination::EphemeralTable { cursor_id, .. } = &plan.query_destination else {
-            unreachable!()
-        };
-        *cursor_id
-    });
+    // The rowids of the rows to update are gathered into an ephemeral table, or the PRIMARY
+    // KEY values into an ephemeral index for a WITHOUT ROWID table.
+    let temp_cursor_id = ephemeral_plan
+        .as_ref()
+        .map(|plan| match &plan.query_destination {
+            QueryDestination::EphemeralTable { cursor_id, .. }
+            | QueryDestination::EphemeralIndex { cursor_id, .. } => *cursor_id,
+            _ => unreachable!(),
+        });
     if let Some(ephemeral_plan) = ephemeral_plan {
         program.emit_insn(Insn::OpenEphemeral {
             cursor_id: temp_cursor_id.unwrap(),
-            is_table: true,
+            is_table: matches!(
+                ephemeral_plan.query_destination,
+                QueryDestination::EphemeralTable { .. }
+            ),
         });
         program.incr_nesting();
         emit_program_for_select(program, ephemeral_plan, schema, syms)?;
         program.decr_nesting();
     }
 
+    // Emit coroutines for any subqueries in the FROM clause
+    emit_subqueries(program, &mut t_ctx, &mut plan.table_references)?;
+    emit_non_from_clause_subqueries(
+        program,
+        &mut t_ctx,
+        &plan.table_references,
+        &mut plan.non_from_clause_subqueries,
+    )?;
+
     // Initialize the main loop
     init_loop(
         program,
@@ -674,18 +927,15 @@ fn emit_program_for_update(
         OperationMode::UPDATE,
         &plan.where_clause,
     )?;
+    emit_correlated_subquery_subroutines(program, &t_ctx, &mut plan.non_from_clause_subqueries)?;
 
     // Prepare index cursors
+    let target_table = plan.table_references.joined_tables().first().unwrap();
     let mut index_cursors = Vec::with_capacity(plan.indexes_to_update.len());
     for index in &plan.indexes_to_update {
-        let index_cursor = if let Some(cursor) = program.resolve_cursor_id_safe(&CursorKey::index(
-            plan.table_references
-                .joined_tables()
-                .first()
-                .unwrap()
-                .internal_id,
-            index.clone(),
-        )) {
+        let index_cursor = if let Some(cursor) = program
+            .resolve_cursor_id_safe(&CursorKey::index(target_table.internal_id, index.clone()))
+        {
             cursor
         } else {
             let cursor = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
@@ -693,6 +943,7 @@ fn emit_program_for_update(
                 cursor_id: cursor,
                 root_page: RegisterOrLiteral::Literal(index.root_page),
                 name: index.name.clone(),
+                db: target_table.database_id,
             });
             cursor
         };
@@ -705,20 +956,37 @@ fn emit_program_for_update(
         program,
         &mut t_ctx,
         &plan.table_references,
-        &[JoinOrderMember::default()],
+        &plan.join_order,
         &plan.where_clause,
         temp_cursor_id,
     )?;
 
     // Emit update instructions
-    emit_update_insns(&plan, &t_ctx, program, index_cursors, temp_cursor_id)?;
+    if target_table.btree().is_some_and(|btree| !btree.has_rowid) {
+        emit_without_rowid_update_insns(
+            &plan,
+            &t_ctx,
+            program,
+            temp_cursor_id.expect("the keys of a WITHOUT ROWID table are gathered up front"),
+            returning.as_ref(),
+        )?;
+    } else {
+        emit_update_insns(
+            &plan,
+            &t_ctx,
+            program,
+            index_cursors,
+            temp_cursor_id,
+            returning.as_ref(),
+        )?;
+    }
 
     // Close the main loop
     close_loop(
         program,
         &mut t_ctx,
         &plan.table_references,
-        &[JoinOrderMember::default()],
+        &plan.join_order,
         temp_cursor_id,
     )?;
 
@@ -726,6 +994,10 @@ fn emit_program_for_update(
 
     after(program);
 
+    if let Some(returning) = &returning {
+        returning.emit_result_rows(program);
+    }
+
     // Finalize program
     program.epilogue(TransactionMode::Write);
     program.result_columns = plan.returning.unwrap_or_default();
@@ -740,6 +1012,7 @@ fn emit_update_insns(
     program: &mut ProgramBuilder,
     index_cursors: Vec<(usize, usize)>,
     temp_cursor_id: Option<CursorID>,
+    returning: Option<&Returning>,
 ) -> crate::Result<()> {
     let table_ref = plan.table_references.joined_tables().first().unwrap();
     let loop_labels = t_ctx.labels_main_loop.first().unwrap();
@@ -756,7 +1029,9 @@ fn emit_update_insns(
             table_ref.virtual_table().is_some(),
         ),
         Operation::Search(search) => match search {
-            &Search::RowidEq { .. } | Search::Seek { index: None, .. } => (None, false),
+            &Search::RowidEq { .. }
+            | Search::Seek { index: None, .. }
+            | Search::MultiIndexOr { .. } => (None, false),
             Search::Seek {
                 index: Some(index), ..
             } => (
@@ -800,13 +1075,49 @@ fn emit_update_insns(
         None
     };
 
-    let check_rowid_not_exists_label = if has_user_provided_rowid {
+    let or_conflict = plan.or_conflict.unwrap_or(ResolveType::Abort);
+    // REPLACE may delete other rows of the table, which moves the table cursor away
+    // from the row being updated, so it has to be positioned on it again before writing.
+    // When the rowids were gathered up front, the loop runs over the ephemeral table and
+    // the table cursor has to be positioned on each row in the first place.
+    let updated_columns = plan
+        .set_clauses
+        .iter()
+        .map(|(idx, _)| *idx)
+        .collect::<Vec<_>>();
+    let trigger_table = table_ref.btree().filter(|btree_table| {
+        has_triggers(
+            t_ctx.resolver.schema.database(table_ref.database_id),
+            btree_table,
+            &ast::TriggerEvent::Update,
+            &updated_columns,
+        )
+    });
+    // Triggers may modify the table, which moves the table cursor away from the row
+    // being updated, so it has to be positioned on it again.
+    let may_replace = match plan.or_conflict {
+        Some(or_conflict) => or_conflict == ResolveType::Replace,
+        None => {
+            table_ref.btree().is_some_and(|btree_table| {
+                btree_table.primary_key_conflict_clause == Some(ResolveType::Replace)
+            }) || plan
+                .indexes_to_update
+                .iter()
+                .any(|index| index.conflict_clause == Some(ResolveType::Replace))
+        }
+    };
+    let reseek_row = has_user_provided_rowid
+        || temp_cursor_id.is_some()
+        || (may_replace && !is_virtual)
+        || trigger_table.is_some();
+
+    let check_rowid_not_exists_label = if reseek_row {
         Some(program.allocate_label())
     } else {
         None
     };
 
-    if has_user_provided_rowid {
+    if reseek_row {
         program.emit_insn(Insn::NotExists {
             cursor: cursor_id,
             rowid_reg: beg,
@@ -836,6 +1147,24 @@ fn emit_update_insns(
         });
     }
 
+    let old_row = trigger_table
+        .as_ref()
+        .map(|btree_table| {
+            TriggerRow::from_cursor(program, &t_ctx.resolver, btree_table, cursor_id, beg)
+        })
+        .transpose()?;
+
+    // Row values assigned to several columns are evaluated once, before any of those columns.
+    for (expr, start_reg) in plan.row_value_set_clauses.iter() {
+        translate_expr(
+            program,
+            Some(&plan.table_references),
+            expr,
+            *start_reg,
+            &t_ctx.resolver,
+        )?;
+    }
+
     // we scan a column at a time, loading either the column's values, or the new value
     // from the Set expression, into registers so we can emit a MakeRecord and update the row.
     let start = if is_virtual { beg + 2 } else { beg + 1 };
@@ -869,19 +1198,15 @@ fn emit_update_insns(
                     &t_ctx.resolver,
                 )?;
                 if table_column.notnull {
-                    use crate::error::SQLITE_CONSTRAINT_NOTNULL;
-                    program.emit_insn(Insn::HaltIfNull {
+                    emit_notnull_check(
+                        program,
+                        &t_ctx.resolver,
+                        table_ref.table.get_name(),
+                        table_column,
                         target_reg,
-                        err_code: SQLITE_CONSTRAINT_NOTNULL,
-                        description: format!(
-                            "{}.{}",
-                            table_ref.table.get_name(),
-                            table_column
-                                .name
-                                .as_ref()
-                                .expect("Column name must be present")
-                        ),
-                    });
+                        resolve_conflict(plan.or_conflict, table_column.notnull_conflict_clause),
+                        loop_labels.next,
+                    )?;
                 }
             }
         } else {
@@ -901,6 +1226,8 @@ fn emit_update_insns(
                     column: idx,
                     dest: target_reg,
                 });
+            } else if table_column.generated.is_some() {
+                // Computed below, once all the other columns are loaded.
             } else {
                 let cursor_id = *index
                     .as_ref()
@@ -917,10 +1244,96 @@ fn emit_update_insns(
         }
     }
 
+    let new_row = TriggerRow {
+        rowid_reg: rowid_set_clause_reg.unwrap_or(beg),
+        columns_start_reg: start,
+    };
+    if let Some(btree_table) = table_ref.btree() {
+        emit_table_affinity(program, &btree_table, start);
+        emit_generated_columns(
+            program,
+            &t_ctx.resolver,
+            &btree_table,
+            new_row.rowid_reg,
+            start,
+        )?;
+    }
+    if let Some(btree_table) = &trigger_table {
+        let check_rowid_not_exists_label = check_rowid_not_exists_label.unwrap();
+        emit_triggers(
+            program,
+            &t_ctx.resolver,
+            btree_table,
+            ast::TriggerEvent::Update,
+            ast::TriggerTime::Before,
+            &updated_columns,
+            old_row,
+            Some(new_row),
+            check_rowid_not_exists_label,
+        )?;
+        // The triggers may have deleted the row, or changed the columns that are not updated.
+        program.emit_insn(Insn::NotExists {
+            cursor: cursor_id,
+            rowid_reg: beg,
+            target_pc: check_rowid_not_exists_label,
+        });
+        for (idx, column) in btree_table.columns.iter().enumerate() {
+            if !column.is_rowid_alias
+                && column.generated.is_none()
+                && !updated_columns.contains(&idx)
+            {
+                program.emit_column(cursor_id, idx, start + idx);
+            }
+        }
+        emit_generated_columns(
+            program,
+            &t_ctx.resolver,
+            btree_table,
+            new_row.rowid_reg,
+            start,
+        )?;
+    }
+
+    if let Some(btree_table) = table_ref.btree() {
+        // The SET clauses can't assign generated columns, so their NOT NULL constraints
+        // are checked here instead.
+        for (idx, column) in btree_table.columns.iter().enumerate() {
+            if column.generated.is_some() && column.notnull {
+                emit_notnull_check(
+                    program,
+                    &t_ctx.resolver,
+                    &btree_table.name,
+                    column,
+                    start + idx,
+                    resolve_conflict(plan.or_conflict, column.notnull_conflict_clause),
+                    loop_labels.next,
+                )?;
+            }
+        }
+        emit_check_constraints(
+            program,
+            &t_ctx.resolver,
+            &btree_table,
+            new_row.rowid_reg,
+            start,
+            or_conflict,
+            loop_labels.next,
+        )?;
+    }
+
+    let replace_index_cursors = plan
+        .indexes_to_update
+        .iter()
+        .zip(&index_cursors)
+        .map(|(index, (idx_cursor_id, _))| (index.as_ref(), *idx_cursor_id))
+        .collect::<Vec<_>>();
+
+    let mut idx_start_regs = Vec::with_capacity(plan.indexes_to_update.len());
     for (index, (idx_cursor_id, record_reg)) in plan.indexes_to_update.iter().zip(&index_cursors) {
         let num_cols = index.columns.len();
         // allocate scratch registers for the index columns plus rowid
         let idx_start_reg = program.alloc_registers(num_cols + 1);
+        idx_start_regs.push(idx_start_reg);
 
         // Use the new rowid value (if the UPDATE statement sets the rowid alias),
         // otherwise keep using the original rowid. This guarantees that any
@@ -933,23 +1346,19 @@ fn emit_update_insns(
             beg
         };
         let idx_cols_start_reg = beg + 1;
+        let btree_table = table_ref.btree().expect("only b-tree tables have indexes");
 
-        // copy each index column from the table's column registers into these scratch regs
-        for (i, col) in index.columns.iter().enumerate() {
-            // copy from the table's column register over to the index's scratch register
-
-            program.emit_insn(Insn::Copy {
-                src_reg: idx_cols_start_reg + col.pos_in_table,
-                dst_reg: idx_start_reg + i,
-                extra_amount: 0,
-            });
-        }
-        // last register is the rowid
-        program.emit_insn(Insn::Copy {
-            src_reg: rowid_reg,
-            dst_reg: idx_start_reg + num_cols,
-            extra_amount: 0,
-        });
+        // compute the index columns from the table's column registers into these scratch regs,
+        // followed by the rowid
+        emit_index_key(
+            program,
+            &t_ctx.resolver,
+            &btree_table,
+            index,
+            rowid_reg,
+            idx_cols_start_reg,
+            idx_start_reg,
+        )?;
 
         // this record will be inserted into the index later
         program.emit_insn(Insn::MakeRecord {
@@ -965,6 +1374,16 @@ fn emit_update_insns(
 
         // check if the record already exists in the index for unique indexes and abort if so
         let constraint_check = program.allocate_label();
+        // The new row can't conflict with the entries of a partial index that excludes it.
+        emit_partial_index_check(
+            program,
+            &t_ctx.resolver,
+            &btree_table,
+            index,
+            rowid_reg,
+            idx_cols_start_reg,
+            constraint_check,
+        )?;
         program.emit_insn(Insn::NoConflict {
             cursor_id: *idx_cursor_id,
             target_pc: constraint_check,
@@ -972,19 +1391,7 @@ fn emit_update_insns(
             num_regs: num_cols,
         });
 
-        let column_names = index.columns.iter().enumerate().fold(
-            String::with_capacity(50),
-            |mut accum, (idx, col)| {
-                if idx > 0 {
-                    accum.push_str(", ");
-                }
-                accum.push_str(table_ref.table.get_name());
-                accum.push('.');
-                accum.push_str(&col.name);
-
-                accum
-            },
-        );
+        let column_names = unique_violation_description(&btree_table, index);
 
         let idx_rowid_reg = program.alloc_register();
         program.emit_insn(Insn::IdxRowId {
@@ -992,18 +1399,44 @@ fn emit_update_insns(
             dest: idx_rowid_reg,
         });
 
+        // The entry of the row being updated is not a conflict, even if its rowid changes.
         program.emit_insn(Insn::Eq {
-            lhs: rowid_reg,
+            lhs: beg,
             rhs: idx_rowid_reg,
             target_pc: constraint_check,
             flags: CmpInsFlags::default(), // TODO: not sure what type of comparison flag is needed
             collation: program.curr_collation(),
         });
 
-        program.emit_insn(Insn::Halt {
-            err_code: SQLITE_CONSTRAINT_PRIMARYKEY, // TODO: distinct between primary key and unique index for error code
-            description: column_names,
-        });
+        match resolve_conflict(plan.or_conflict, index.conflict_clause) {
+            ResolveType::Ignore => {
+                program.emit_insn(Insn::Goto {
+                    target_pc: loop_labels.next,
+                });
+            }
+            ResolveType::Replace => {
+                program.emit_insn(Insn::NotExists {
+                    cursor: cursor_id,
+                    rowid_reg: idx_rowid_reg,
+                    target_pc: constraint_check,
+                });
+                emit_replace_delete_row(
+                    program,
+                    &t_ctx.resolver,
+                    &table_ref.table,
+                    cursor_id,
+                    &replace_index_cursors,
+                    t_ctx.cdc_cursor_id,
+                )?;
+            }
+            on_error => {
+                program.emit_insn(Insn::Halt {
+                    err_code: unique_violation_err_code(&btree_table, index),
+                    description: column_names,
+                    on_error,
+                });
+            }
+        }
 
         program.preassign_label_to_next_insn(constraint_check);
     }
@@ -1036,33 +1469,48 @@ fn emit_update_insns(
                 target_pc: record_label,
             });
 
-            program.emit_insn(Insn::Halt {
-                err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
-                description: format!(
-                    "{}.{}",
-                    table_ref.table.get_name(),
-                    &table_ref
-                        .columns()
-                        .get(idx)
-                        .unwrap()
-                        .name
-                        .as_ref()
-                        .map_or("", |v| v)
-                ),
-            });
+            match resolve_conflict(plan.or_conflict, btree_table.primary_key_conflict_clause) {
+                ResolveType::Ignore => {
+                    program.emit_insn(Insn::Goto {
+                        target_pc: loop_labels.next,
+                    });
+                }
+                ResolveType::Replace => {
+                    emit_replace_delete_row(
+                        program,
+                        &t_ctx.resolver,
+                        &table_ref.table,
+                        cursor_id,
+                        &replace_index_cursors,
+                        t_ctx.cdc_cursor_id,
+                    )?;
+                }
+                on_error => {
+                    program.emit_insn(Insn::Halt {
+                        err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
+                        description: format!(
+                            "{}.{}",
+                            table_ref.table.get_name(),
+                            &table_ref
+                                .columns()
+                                .get(idx)
+                                .unwrap()
+                                .name
+                                .as_ref()
+                                .map_or("", |v| v)
+                        ),
+                        on_error,
+                    });
+                }
+            }
 
             program.preassign_label_to_next_insn(record_label);
         }
 
         let record_reg = program.alloc_register();
-        program.emit_insn(Insn::MakeRecord {
-            start_reg: start,
-            count: table_ref.columns().len(),
-            dest_reg: record_reg,
-            index_name: None,
-        });
+        emit_table_record(program, table_ref.columns(), start, record_reg);
 
-        if has_user_provided_rowid {
+        if reseek_row {
             program.emit_insn(Insn::NotExists {
                 cursor: cursor_id,
                 rowid_reg: beg,
@@ -1071,44 +1519,53 @@ fn emit_update_insns(
         }
 
         // For each index -> insert
-        for (index, (idx_cursor_id, record_reg)) in plan.indexes_to_update.iter().zip(index_cursors)
+        for ((index, (idx_cursor_id, record_reg)), idx_start_reg) in plan
+            .indexes_to_update
+            .iter()
+            .zip(index_cursors)
+            .zip(idx_start_regs)
         {
-            let num_regs = index.columns.len() + 1;
-            let start_reg = program.alloc_registers(num_regs);
+            // A partial index only has entries for the rows its WHERE clause is true for,
+            // before and after the update.
+            let skip_delete_label = program.allocate_label();
 
             // Delete existing index key
-            index
-                .columns
-                .iter()
-                .enumerate()
-                .for_each(|(reg_offset, column_index)| {
-                    program.emit_column(
-                        cursor_id,
-                        column_index.pos_in_table,
-                        start_reg + reg_offset,
-                    );
-                });
-
-            program.emit_insn(Insn::RowId {
+            let start_reg = emit_index_key_for_cursor(
+                program,
+                &t_ctx.resolver,
+                &btree_table,
+                index,
                 cursor_id,
-                dest: start_reg + num_regs - 1,
-            });
-
+                skip_delete_label,
+            )?;
             program.emit_insn(Insn::IdxDelete {
                 start_reg,
-                num_regs,
+                num_regs: index.columns.len() + 1,
                 cursor_id: idx_cursor_id,
                 raise_error_if_no_matching_entry: true,
             });
+            program.preassign_label_to_next_insn(skip_delete_label);
+
+            let skip_insert_label = program.allocate_label();
+            emit_partial_index_check(
+                program,
+                &t_ctx.resolver,
+                &btree_table,
+                index,
+                new_row.rowid_reg,
+                start,
+                skip_insert_label,
+            )?;
 
             // Insert new index key (filled further above with values from set_clauses)
             program.emit_insn(Insn::IdxInsert {
                 cursor_id: idx_cursor_id,
                 record_reg,
-                unpacked_start: Some(start),
+                unpacked_start: Some(idx_start_reg),
                 unpacked_count: Some((index.columns.len() + 1) as u16),
                 flags: IdxInsertFlags::new(),
             });
+            program.preassign_label_to_next_insn(skip_insert_label);
         }
 
         // create alias for CDC rowid after the change (will differ from cdc_rowid_before_reg only in case of UPDATE with change in rowid alias)
@@ -1146,7 +1603,12 @@ fn emit_update_insns(
         // Insert instruction to update the cell. We need to first delete the current cell
         // and later insert the updated record
         if has_user_provided_rowid {
-            program.emit_insn(Insn::Delete { cursor_id });
+            program.emit_insn(Insn::Delete {
+                cursor_id,
+                nchange: ChangeCount::None,
+                table_name: Some(table_ref.table.get_name().to_string()),
+                is_update: true,
+            });
         }
 
         program.emit_insn(Insn::Insert {
@@ -1159,8 +1621,10 @@ fn emit_update_insns(
                 InsertFlags::new().require_seek()
             } else {
                 InsertFlags::new()
-            },
-            table_name: table_ref.identifier.clone(),
+            }
+            .update()
+            .nchange(update_change_count(program, table_ref.table.get_name())),
+            table_name: table_ref.table.get_name().to_string(),
         });
 
         // create full CDC record after update if necessary
@@ -1214,13 +1678,40 @@ fn emit_update_insns(
                 )?;
             }
         }
+
+        if let Some(returning) = returning {
+            let btree_table = table_ref
+                .btree()
+                .expect("RETURNING is only supported for b-tree tables");
+            returning.emit_row(
+                program,
+                &t_ctx.resolver,
+                &btree_table,
+                new_row.rowid_reg,
+                new_row.columns_start_reg,
+            )?;
+        }
+
+        if let Some(btree_table) = &trigger_table {
+            emit_triggers(
+                program,
+                &t_ctx.resolver,
+                btree_table,
+                ast::TriggerEvent::Update,
+                ast::TriggerTime::After,
+                &updated_columns,
+                old_row,
+                Some(new_row),
+                check_rowid_not_exists_label.unwrap(),
+            )?;
+        }
     } else if table_ref.virtual_table().is_some() {
         let arg_count = table_ref.columns().len() + 2;
         program.emit_insn(Insn::VUpdate {
             cursor_id,
             arg_count,
             start_reg: beg,
-            conflict_action: 0u16,
+            conflict_action: plan.or_conflict.map_or(0, |c| c.bit_value()) as u16,
         });
     }
 
@@ -1230,7 +1721,15 @@ fn emit_update_insns(
             target_pc: t_ctx.label_main_loop_end.unwrap(),
         })
     }
-    // TODO(pthorpe): handle RETURNING clause
+
+    // In UPDATE ... FROM the target table drives the outermost loop. Once a row has been
+    // updated, move on to the next target row so that it is updated at most once, even if
+    // it matches several rows of the FROM clause.
+    if plan.table_references.joined_tables().len() > 1 {
+        program.emit_insn(Insn::Goto {
+            target_pc: loop_labels.next,
+        });
+    }
 
     if let Some(label) = check_rowid_not_exists_label {
         program.preassign_label_to_next_insn(label);
@@ -1239,14 +1738,723 @@ fn emit_update_insns(
     Ok(())
 }
 
-pub fn emit_cdc_patch_record(
+/// Emits the instructions that update a row of a WITHOUT ROWID table, whose PRIMARY KEY is read
+/// from the ephemeral index at `temp_cursor_id`. The btree of such a table is keyed by the
+/// PRIMARY KEY, so the row is deleted and inserted again, which moves it when its key changes.
+fn emit_without_rowid_update_insns(
+    plan: &UpdatePlan,
+    t_ctx: &TranslateCtx,
     program: &mut ProgramBuilder,
-    table: &Table,
-    columns_reg: usize,
-    record_reg: usize,
-    rowid_reg: usize,
-) -> usize {
-    let columns = table.columns();
+    temp_cursor_id: CursorID,
+    returning: Option<&Returning>,
+) -> crate::Result<()> {
+    let table_ref = plan.table_references.joined_tables().first().unwrap();
+    let btree_table = table_ref
+        .btree()
+        .expect("WITHOUT ROWID tables are b-tree tables");
+    let loop_labels = t_ctx.labels_main_loop.first().unwrap();
+    let cursor_id = program.resolve_cursor_id(&CursorKey::table(table_ref.internal_id));
+    let table_index = btree_table.without_rowid_index();
+    let num_key_columns = btree_table.primary_key_columns.len();
+    let key_columns = table_index.columns[..num_key_columns]
+        .iter()
+        .map(|column| column.pos_in_table)
+        .collect::<Vec<_>>();
+
+    let old_key_reg = program.alloc_registers(num_key_columns);
+    for i in 0..num_key_columns {
+        program.emit_insn(Insn::Column {
+            cursor_id: temp_cursor_id,
+            column: i,
+            dest: old_key_reg + i,
+            default: None,
+        });
+    }
+    program.emit_insn(Insn::NotFound {
+        cursor_id,
+        target_pc: loop_labels.next,
+        record_reg: old_key_reg,
+        num_regs: num_key_columns,
+    });
+
+    if let Some(offset) = t_ctx.reg_offset {
+        program.emit_insn(Insn::IfPos {
+            reg: offset,
+            target_pc: loop_labels.next,
+            decrement_by: 1,
+        });
+    }
+
+    // Row values assigned to several columns are evaluated once, before any of those columns.
+    for (expr, start_reg) in plan.row_value_set_clauses.iter() {
+        translate_expr(
+            program,
+            Some(&plan.table_references),
+            expr,
+            *start_reg,
+            &t_ctx.resolver,
+        )?;
+    }
+
+    let start = program.alloc_registers(btree_table.columns.len());
+    for (idx, column) in btree_table.columns.iter().enumerate() {
+        let target_reg = start + idx;
+        if let Some((_, expr)) = plan.set_clauses.iter().find(|(i, _)| *i == idx) {
+            translate_expr(
+                program,
+                Some(&plan.table_references),
+                expr,
+                target_reg,
+                &t_ctx.resolver,
+            )?;
+            // The PRIMARY KEY columns of a WITHOUT ROWID table can't be NULL.
+            if column.notnull || key_columns.contains(&idx) {
+                emit_notnull_check(
+                    program,
+                    &t_ctx.resolver,
+                    &btree_table.name,
+                    column,
+                    target_reg,
+                    resolve_conflict(plan.or_conflict, column.notnull_conflict_clause),
+                    loop_labels.next,
+                )?;
+            }
+        } else if column.generated.is_none() {
+            program.emit_column(cursor_id, idx, target_reg);
+        }
+    }
+
+    // A WITHOUT ROWID table has no rowid the expressions of the table could refer to.
+    let rowid_reg = program.alloc_register();
+    program.emit_null(rowid_reg, None);
+    emit_table_affinity(program, &btree_table, start);
+    emit_generated_columns(program, &t_ctx.resolver, &btree_table, rowid_reg, start)?;
+    for (idx, column) in btree_table.columns.iter().enumerate() {
+        if column.generated.is_some() && column.notnull {
+            emit_notnull_check(
+                program,
+                &t_ctx.resolver,
+                &btree_table.name,
+                column,
+                start + idx,
+                resolve_conflict(plan.or_conflict, column.notnull_conflict_clause),
+                loop_labels.next,
+            )?;
+        }
+    }
+    emit_check_constraints(
+        program,
+        &t_ctx.resolver,
+        &btree_table,
+        rowid_reg,
+        start,
+        plan.or_conflict.unwrap_or(ResolveType::Abort),
+        loop_labels.next,
+    )?;
+    if btree_table.is_strict {
+        program.emit_insn(Insn::TypeCheck {
+            start_reg: start,
+            count: btree_table.columns.len(),
+            check_generated: true,
+            table_reference: Rc::clone(&btree_table),
+        });
+    }
+
+    // The new record starts with the PRIMARY KEY columns, which are its key.
+    let record_start_reg = program.alloc_registers(table_index.columns.len());
+    for (i, column) in table_index.columns.iter().enumerate() {
+        program.emit_insn(Insn::Copy {
+            src_reg: start + column.pos_in_table,
+            dst_reg: record_start_reg + i,
+            extra_amount: 0,
+        });
+    }
+
+    // A row whose PRIMARY KEY changes must not collide with another row.
+    if plan
+        .set_clauses
+        .iter()
+        .any(|(idx, _)| key_columns.contains(idx))
+    {
+        let key_changed = program.allocate_label();
+        let key_unchanged = program.allocate_label();
+        for (i, column) in table_index.columns[..num_key_columns].iter().enumerate() {
+            program.emit_insn(Insn::Ne {
+                lhs: record_start_reg + i,
+                rhs: old_key_reg + i,
+                target_pc: key_changed,
+                flags: CmpInsFlags::default(),
+                collation: Some(column.collation.unwrap_or_default()),
+            });
+        }
+        program.emit_insn(Insn::Goto {
+            target_pc: key_unchanged,
+        });
+        program.preassign_label_to_next_insn(key_changed);
+        program.emit_insn(Insn::NoConflict {
+            cursor_id,
+            target_pc: key_unchanged,
+            record_reg: record_start_reg,
+            num_regs: num_key_columns,
+        });
+        match resolve_conflict(plan.or_conflict, btree_table.primary_key_conflict_clause) {
+            ResolveType::Ignore => {
+                program.emit_insn(Insn::Goto {
+                    target_pc: loop_labels.next,
+                });
+            }
+            on_error => {
+                let description = key_columns
+                    .iter()
+                    .map(|idx| {
+                        let name = btree_table.columns[*idx].name.as_deref().unwrap_or("");
+                        format!("{}.{name}", btree_table.name)
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", ");
+                program.emit_insn(Insn::Halt {
+                    err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
+                    description,
+                    on_error,
+                });
+            }
+        }
+        program.preassign_label_to_next_insn(key_unchanged);
+        // Looking for a conflicting row moved the cursor away from the row being updated.
+        program.emit_insn(Insn::NotFound {
+            cursor_id,
+            target_pc: loop_labels.next,
+            record_reg: old_key_reg,
+            num_regs: num_key_columns,
+        });
+    }
+
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: record_start_reg,
+        count: table_index.columns.len(),
+        dest_reg: record_reg,
+        index_name: None,
+    });
+    program.emit_insn(Insn::Delete {
+        cursor_id,
+        nchange: ChangeCount::None,
+        table_name: None,
+        is_update: true,
+    });
+    program.emit_insn(Insn::IdxInsert {
+        cursor_id,
+        record_reg,
+        unpacked_start: Some(record_start_reg),
+        unpacked_count: Some(table_index.columns.len() as u16),
+        flags: IdxInsertFlags::new().nchange(program.change_count()),
+    });
+
+    if let Some(returning) = returning {
+        returning.emit_row(program, &t_ctx.resolver, &btree_table, rowid_reg, start)?;
+    }
+
+    if let Some(limit_ctx) = t_ctx.limit_ctx {
+        program.emit_insn(Insn::DecrJumpZero {
+            reg: limit_ctx.reg_limit,
+            target_pc: t_ctx.label_main_loop_end.unwrap(),
+        })
+    }
+
+    Ok(())
+}
+
+/// Returns how a violation of a constraint is resolved: the OR clause of the statement
+/// takes precedence over the ON CONFLICT clause of the constraint, and ABORT is the default.
+pub fn resolve_conflict(
+    or_conflict: Option<ResolveType>,
+    conflict_clause: Option<ResolveType>,
+) -> ResolveType {
+    or_conflict
+        .or(conflict_clause)
+        .unwrap_or(ResolveType::Abort)
+}
+
+/// Returns the error code for a uniqueness violation of the unique `index` on `table`,
+/// which tells the automatic index of a PRIMARY KEY apart from any other unique index.
+pub fn unique_violation_err_code(table: &BTreeTable, index: &Index) -> usize {
+    let is_primary_key_index = table.get_rowid_alias_column().is_none()
+        && table.primary_key_columns.len() == index.columns.len()
+        && table
+            .primary_key_columns
+            .iter()
+            .zip(&index.columns)
+            .all(|((name, _), column)| normalize_ident(name) == column.name);
+    if is_primary_key_index {
+        SQLITE_CONSTRAINT_PRIMARYKEY
+    } else {
+        SQLITE_CONSTRAINT_UNIQUE
+    }
+}
+
+/// Returns the description of a uniqueness violation of the unique `index` on `table`, which
+/// names the columns of the index, e.g. `t.a, t.b`, or else the index itself if some of its
+/// columns are expressions.
+pub fn unique_violation_description(table: &BTreeTable, index: &Index) -> String {
+    if index.has_expr_columns() {
+        return format!("index '{}'", index.name);
+    }
+    index
+        .columns
+        .iter()
+        .map(|column| {
+            let name = table.columns[column.pos_in_table]
+                .name
+                .as_deref()
+                .unwrap_or(&column.name);
+            format!("{}.{name}", table.name)
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+/// Emits a NOT NULL check of `reg` for `column`, resolving a violation according to `or_conflict`:
+/// IGNORE jumps to `skip_row_label`, REPLACE substitutes the column's default value (if it has one)
+/// and everything else halts with a constraint error.
+pub fn emit_notnull_check(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    table_name: &str,
+    column: &Column,
+    reg: usize,
+    or_conflict: ResolveType,
+    skip_row_label: BranchOffset,
+) -> Result<()> {
+    match or_conflict {
+        ResolveType::Ignore => {
+            program.emit_insn(Insn::IsNull {
+                reg,
+                target_pc: skip_row_label,
+            });
+            return Ok(());
+        }
+        ResolveType::Replace => {
+            if let Some(default) = &column.default {
+                let not_null_label = program.allocate_label();
+                program.emit_insn(Insn::NotNull {
+                    reg,
+                    target_pc: not_null_label,
+                });
+                translate_expr_no_constant_opt(
+                    program,
+                    None,
+                    default,
+                    reg,
+                    resolver,
+                    NoConstantOptReason::RegisterReuse,
+                )?;
+                program.preassign_label_to_next_insn(not_null_label);
+            }
+        }
+        _ => {}
+    }
+    program.emit_insn(Insn::HaltIfNull {
+        target_reg: reg,
+        err_code: SQLITE_CONSTRAINT_NOTNULL,
+        description: format!(
+            "{}.{}",
+            table_name,
+            column.name.as_ref().expect("Column name must be present")
+        ),
+        on_error: halt_resolve_type(or_conflict),
+    });
+    Ok(())
+}
+
+/// The change counter the rows updated in `table_name` count towards. The rows of the schema
+/// table, which ALTER TABLE rewrites with a nested UPDATE, are not changes of the statement, so
+/// they are neither counted nor reported to the hooks.
+fn update_change_count(program: &ProgramBuilder, table_name: &str) -> ChangeCount {
+    if table_name.eq_ignore_ascii_case(SQLITE_TABLEID) {
+        ChangeCount::None
+    } else {
+        program.change_count()
+    }
+}
+
+/// Returns how a constraint violation that halts the statement is resolved under `or_conflict`.
+/// IGNORE and REPLACE halt like ABORT where they can't skip or replace the row.
+fn halt_resolve_type(or_conflict: ResolveType) -> ResolveType {
+    match or_conflict {
+        ResolveType::Ignore | ResolveType::Replace => ResolveType::Abort,
+        on_error => on_error,
+    }
+}
+
+/// Emits the CHECK constraints of `table` for the row whose rowid is in `rowid_reg` and whose
+/// columns are in the registers starting at `columns_start_reg`. A violation is resolved according
+/// to `or_conflict`: IGNORE jumps to `skip_row_label` and everything else halts with a constraint error.
+pub fn emit_check_constraints(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    table: &BTreeTable,
+    rowid_reg: usize,
+    columns_start_reg: usize,
+    or_conflict: ResolveType,
+    skip_row_label: BranchOffset,
+) -> Result<()> {
+    for check in &table.check_constraints {
+        let mut expr = check.expr.clone();
+        rewrite_column_refs_to_registers(table, &mut expr, rowid_reg, columns_start_reg)?;
+
+        let result_reg = program.alloc_register();
+        translate_expr(program, None, &expr, result_reg, resolver)?;
+        // The constraint only fails when the expression is false, NULL satisfies it.
+        let check_passed_label = program.allocate_label();
+        program.emit_insn(Insn::If {
+            reg: result_reg,
+            target_pc: check_passed_label,
+            jump_if_null: true,
+        });
+        if or_conflict == ResolveType::Ignore {
+            program.emit_insn(Insn::Goto {
+                target_pc: skip_row_label,
+            });
+        } else {
+            program.emit_insn(Insn::Halt {
+                err_code: SQLITE_CONSTRAINT_CHECK,
+                description: check.display_name(),
+                on_error: halt_resolve_type(or_conflict),
+            });
+        }
+        program.preassign_label_to_next_insn(check_passed_label);
+    }
+    Ok(())
+}
+
+/// Emits a jump to `skip_label` if the row of `table` whose rowid is in `rowid_reg` and whose
+/// columns are in the registers starting at `columns_start_reg` has no entry in `index`, i.e. if
+/// `index` is a partial index whose WHERE clause is not true for the row.
+pub fn emit_partial_index_check(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    table: &BTreeTable,
+    index: &Index,
+    rowid_reg: usize,
+    columns_start_reg: usize,
+    skip_label: BranchOffset,
+) -> Result<()> {
+    let Some(where_clause) = &index.where_clause else {
+        return Ok(());
+    };
+    let mut expr = where_clause.clone();
+    rewrite_column_refs_to_registers(table, &mut expr, rowid_reg, columns_start_reg)?;
+    let result_reg = program.alloc_register();
+    translate_expr(program, None, &expr, result_reg, resolver)?;
+    program.emit_insn(Insn::IfNot {
+        reg: result_reg,
+        target_pc: skip_label,
+        jump_if_null: true,
+    });
+    Ok(())
+}
+
+/// Emits the key of the entry of `index` for the row of `table` whose rowid is in `rowid_reg` and
+/// whose columns are in the registers starting at `columns_start_reg`, i.e. the values of the
+/// columns of the index followed by the rowid, into the registers starting at `key_start_reg`.
+pub fn emit_index_key(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    table: &BTreeTable,
+    index: &Index,
+    rowid_reg: usize,
+    columns_start_reg: usize,
+    key_start_reg: usize,
+) -> Result<()> {
+    for (i, column) in index.columns.iter().enumerate() {
+        match &column.expr {
+            Some(expr) => {
+                let mut expr = expr.as_ref().clone();
+                rewrite_column_refs_to_registers(table, &mut expr, rowid_reg, columns_start_reg)?;
+                translate_expr(program, None, &expr, key_start_reg + i, resolver)?;
+            }
+            None => {
+                program.emit_insn(Insn::Copy {
+                    src_reg: columns_start_reg + column.pos_in_table,
+                    dst_reg: key_start_reg + i,
+                    extra_amount: 0,
+                });
+            }
+        }
+    }
+    program.emit_insn(Insn::Copy {
+        src_reg: rowid_reg,
+        dst_reg: key_start_reg + index.columns.len(),
+        extra_amount: 0,
+    });
+    Ok(())
+}
+
+/// Emits the key of the entry of `index` for the row `table_cursor_id` points to, like
+/// [emit_index_key], into newly allocated registers and returns the first one of them.
+/// Jumps to `skip_label` instead if the row has no entry in `index`, see
+/// [emit_partial_index_check].
+pub fn emit_index_key_for_cursor(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    table: &BTreeTable,
+    index: &Index,
+    table_cursor_id: CursorID,
+    skip_label: BranchOffset,
+) -> Result<usize> {
+    let num_regs = index.columns.len() + 1;
+    let key_start_reg = program.alloc_registers(num_regs);
+    if index.where_clause.is_none() && !index.has_expr_columns() {
+        for (i, column) in index.columns.iter().enumerate() {
+            program.emit_column(table_cursor_id, column.pos_in_table, key_start_reg + i);
+        }
+        program.emit_insn(Insn::RowId {
+            cursor_id: table_cursor_id,
+            dest: key_start_reg + num_regs - 1,
+        });
+        return Ok(key_start_reg);
+    }
+    // The WHERE clause and the expressions of the index may refer to any column of the row.
+    let rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::RowId {
+        cursor_id: table_cursor_id,
+        dest: rowid_reg,
+    });
+    let row = TriggerRow::from_cursor(program, resolver, table, table_cursor_id, rowid_reg)?;
+    emit_partial_index_check(
+        program,
+        resolver,
+        table,
+        index,
+        row.rowid_reg,
+        row.columns_start_reg,
+        skip_label,
+    )?;
+    emit_index_key(
+        program,
+        resolver,
+        table,
+        index,
+        row.rowid_reg,
+        row.columns_start_reg,
+        key_start_reg,
+    )?;
+    Ok(key_start_reg)
+}
+
+/// Computes the generated columns of `table` for the row whose rowid is in `rowid_reg` and whose
+/// columns are in the registers starting at `columns_start_reg`, storing each value in its
+/// column's register.
+pub fn emit_generated_columns(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    table: &BTreeTable,
+    rowid_reg: usize,
+    columns_start_reg: usize,
+) -> Result<()> {
+    for column_idx in table.generated_columns_in_order()? {
+        let column = &table.columns[column_idx];
+        let generated = column
+            .generated
+            .as_ref()
+            .expect("column should be generated");
+        let mut expr = generated.expr.clone();
+        rewrite_column_refs_to_registers(table, &mut expr, rowid_reg, columns_start_reg)?;
+        let target_reg = columns_start_reg + column_idx;
+        translate_expr_no_constant_opt(
+            program,
+            None,
+            &expr,
+            target_reg,
+            resolver,
+            NoConstantOptReason::RegisterReuse,
+        )?;
+        emit_column_affinity(program, column, target_reg);
+    }
+    Ok(())
+}
+
+/// Applies the affinity of `column` to the value in `reg`.
+pub fn emit_column_affinity(program: &mut ProgramBuilder, column: &Column, reg: usize) {
+    let affinity = column.affinity();
+    if affinity.has_affinity() {
+        program.emit_insn(Insn::Affinity {
+            start_reg: reg,
+            count: NonZeroUsize::new(1).unwrap(),
+            affinities: affinity.aff_mask().to_string(),
+        });
+    }
+}
+
+/// Applies the affinity of each column of `table` to the row in the registers starting at
+/// `columns_start_reg`, before it is checked and written. The generated columns get theirs when
+/// they are computed, and STRICT tables check the types of the values with TypeCheck instead.
+pub fn emit_table_affinity(
+    program: &mut ProgramBuilder,
+    table: &BTreeTable,
+    columns_start_reg: usize,
+) {
+    if table.is_strict {
+        return;
+    }
+    let affinities = table
+        .columns
+        .iter()
+        .map(|column| match column.generated {
+            Some(_) => SQLITE_AFF_NONE,
+            None => column.affinity().aff_mask(),
+        })
+        .collect::<String>();
+    // Like SQLite, the columns without affinity at the end of the row are left out.
+    let affinities = affinities.trim_end_matches(SQLITE_AFF_NONE);
+    if let Some(count) = NonZeroUsize::new(affinities.len()) {
+        program.emit_insn(Insn::Affinity {
+            start_reg: columns_start_reg,
+            count,
+            affinities: affinities.to_string(),
+        });
+    }
+}
+
+/// Emits a MakeRecord of the row of `table` in the registers starting at `columns_start_reg`.
+/// VIRTUAL generated columns are left out, since the table's records don't store them.
+pub fn emit_table_record(
+    program: &mut ProgramBuilder,
+    columns: &[Column],
+    columns_start_reg: usize,
+    dest_reg: usize,
+) {
+    let stored_columns = columns
+        .iter()
+        .enumerate()
+        .filter(|(_, column)| !column.is_virtual_generated())
+        .map(|(idx, _)| idx)
+        .collect::<Vec<_>>();
+    let start_reg = if stored_columns.len() == columns.len() {
+        columns_start_reg
+    } else {
+        let start_reg = program.alloc_registers(stored_columns.len());
+        for (i, column_idx) in stored_columns.iter().enumerate() {
+            program.emit_insn(Insn::Copy {
+                src_reg: columns_start_reg + column_idx,
+                dst_reg: start_reg + i,
+                extra_amount: 0,
+            });
+        }
+        start_reg
+    };
+    program.emit_insn(Insn::MakeRecord {
+        start_reg,
+        count: stored_columns.len(),
+        dest_reg,
+        index_name: None,
+    });
+}
+
+/// Replaces the references to the columns of `table` in `expr` with the registers holding their
+/// values, for the row whose rowid is in `rowid_reg` and whose columns are in the registers
+/// starting at `columns_start_reg`.
+pub fn rewrite_column_refs_to_registers(
+    table: &BTreeTable,
+    expr: &mut Expr,
+    rowid_reg: usize,
+    columns_start_reg: usize,
+) -> Result<()> {
+    walk_expr_mut(expr, &mut |expr: &mut Expr| -> Result<()> {
+        let col_name = match expr {
+            Expr::Id(id) => normalize_ident(&id.0),
+            Expr::Qualified(tbl_name, col_name) if normalize_ident(&tbl_name.0) == table.name => {
+                normalize_ident(&col_name.0)
+            }
+            _ => return Ok(()),
+        };
+        let reg = match table.get_column(&col_name) {
+            Some((_, column)) if column.is_rowid_alias => rowid_reg,
+            Some((idx, _)) => columns_start_reg + idx,
+            None if ["rowid", "oid", "_rowid_"].contains(&col_name.as_str()) => rowid_reg,
+            None => bail_parse_error!("no such column: {}", col_name),
+        };
+        *expr = Expr::Register(reg);
+        Ok(())
+    })
+}
+
+/// Deletes the row `table_cursor_id` points to, along with its entries in `index_cursors`.
+/// This is how the REPLACE conflict resolution makes room for a row that conflicts with it.
+pub fn emit_replace_delete_row(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    table: &Table,
+    table_cursor_id: CursorID,
+    index_cursors: &[(&Index, CursorID)],
+    cdc_cursor_id: Option<CursorID>,
+) -> Result<()> {
+    let rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::RowId {
+        cursor_id: table_cursor_id,
+        dest: rowid_reg,
+    });
+    for (index, index_cursor_id) in index_cursors {
+        let btree_table = table.btree().expect("only b-tree tables have indexes");
+        let skip_index_label = program.allocate_label();
+        let start_reg = emit_index_key_for_cursor(
+            program,
+            resolver,
+            &btree_table,
+            index,
+            table_cursor_id,
+            skip_index_label,
+        )?;
+        program.emit_insn(Insn::IdxDelete {
+            start_reg,
+            num_regs: index.columns.len() + 1,
+            cursor_id: *index_cursor_id,
+            raise_error_if_no_matching_entry: true,
+        });
+        program.preassign_label_to_next_insn(skip_index_label);
+    }
+
+    if let Some(cdc_cursor_id) = cdc_cursor_id {
+        let before_record_reg = if program.capture_data_changes_mode().has_before() {
+            Some(emit_cdc_full_record(
+                program,
+                table,
+                table_cursor_id,
+                rowid_reg,
+            ))
+        } else {
+            None
+        };
+        emit_cdc_insns(
+            program,
+            resolver,
+            OperationMode::DELETE,
+            cdc_cursor_id,
+            rowid_reg,
+            before_record_reg,
+            None,
+            table.get_name(),
+        )?;
+    }
+
+    // Rows deleted to make room for another one are not counted as changes.
+    program.emit_insn(Insn::Delete {
+        cursor_id: table_cursor_id,
+        nchange: ChangeCount::None,
+        table_name: Some(table.get_name().to_string()),
+        is_update: false,
+    });
+    Ok(())
+}
+
+pub fn emit_cdc_patch_record(
+    program: &mut ProgramBuilder,
+    table: &Table,
+    columns_reg: usize,
+    record_reg: usize,
+    rowid_reg: usize,
+) -> usize {
+    let columns = table.columns();
     let rowid_alias_position = columns.iter().position(|x| x.is_rowid_alias);
     if let Some(rowid_alias_position) = rowid_alias_position {
         let record_reg = program.alloc_register();
@@ -1255,12 +2463,7 @@ pub fn emit_cdc_patch_record(
             dst_reg: columns_reg + rowid_alias_position,
             extra_amount: 0,
         });
-        program.emit_insn(Insn::MakeRecord {
-            start_reg: columns_reg,
-            count: table.columns().len(),
-            dest_reg: record_reg,
-            index_name: None,
-        });
+        emit_table_record(program, columns, columns_reg, record_reg);
         record_reg
     } else {
         record_reg
@@ -1282,16 +2485,11 @@ pub fn emit_cdc_full_record(
                 dst_reg: columns_reg + 1 + i,
                 extra_amount: 0,
             });
-        } else {
+        } else if !column.is_virtual_generated() {
             program.emit_column(table_cursor_id, i, columns_reg + 1 + i);
         }
     }
-    program.emit_insn(Insn::MakeRecord {
-        start_reg: columns_reg + 1,
-        count: columns.len(),
-        dest_reg: columns_reg,
-        index_name: None,
-    });
+    emit_table_record(program, columns, columns_reg + 1, columns_reg);
     columns_reg
 }
 
@@ -1368,11 +2566,22 @@ pub fn emit_cdc_insns(
         program.mark_last_insn_constant();
     }
 
+    let cdc_table = program
+        .capture_data_changes_mode()
+        .table()
+        .and_then(|name| resolver.schema.get_btree_table(name));
+    let sequence_table = resolver.schema.get_btree_table(SQLITE_SEQUENCE_TABLE);
+    let prev_largest_reg = match (cdc_table, sequence_table) {
+        (Some(cdc_table), Some(sequence_table)) if cdc_table.has_autoincrement => {
+            program.autoincrement_reg(&cdc_table.name, sequence_table)
+        }
+        _ => 0,
+    };
     let rowid_reg = program.alloc_register();
     program.emit_insn(Insn::NewRowid {
         cursor: cdc_cursor_id,
         rowid_reg,
-        prev_largest_reg: 0, // todo(sivukhin): properly set value here from sqlite_sequence table when AUTOINCREMENT will be properly implemented in Turso
+        prev_largest_reg,
     });
 
     let record_reg = program.alloc_register();
@@ -1396,37 +2605,70 @@ pub fn emit_cdc_insns(
 /// Initialize the limit/offset counters and registers.
 /// In case of compound SELECTs, the limit counter is initialized only once,
 /// hence [LimitCtx::initialize_counter] being false in those cases.
+/// A LIMIT that is only known when the query runs jumps to `label_zero_limit` if it is 0.
 fn init_limit(
     program: &mut ProgramBuilder,
     t_ctx: &mut TranslateCtx,
-    limit: Option<isize>,
-    offset: Option<isize>,
-) {
+    limit: Option<&ast::Expr>,
+    offset: Option<&ast::Expr>,
+    label_zero_limit: BranchOffset,
+) -> Result<()> {
     if t_ctx.limit_ctx.is_none() {
         t_ctx.limit_ctx = limit.map(|_| LimitCtx::new(program));
     }
     let Some(limit_ctx) = t_ctx.limit_ctx else {
-        return;
+        return Ok(());
     };
     if limit_ctx.initialize_counter {
-        program.emit_insn(Insn::Integer {
-            value: limit.expect("limit must be Some if limit_ctx is Some") as i64,
-            dest: limit_ctx.reg_limit,
-        });
+        let limit = limit.expect("limit must be Some if limit_ctx is Some");
+        emit_limit_value(program, limit, limit_ctx.reg_limit, &t_ctx.resolver)?;
+        if try_fold_expr_to_i64(limit).is_none() {
+            program.emit_insn(Insn::IfNot {
+                reg: limit_ctx.reg_limit,
+                target_pc: label_zero_limit,
+                jump_if_null: true,
+            });
+        }
     }
-    if t_ctx.reg_offset.is_none() && offset.is_some_and(|n| n.ne(&0)) {
+    let offset = offset.filter(|offset| try_fold_expr_to_i64(offset) != Some(0));
+    if let (None, Some(offset)) = (t_ctx.reg_offset, offset) {
         let reg = program.alloc_register();
         t_ctx.reg_offset = Some(reg);
-        program.emit_insn(Insn::Integer {
-            value: offset.unwrap() as i64,
-            dest: reg,
-        });
+        emit_limit_value(program, offset, reg, &t_ctx.resolver)?;
         let combined_reg = program.alloc_register();
         t_ctx.reg_limit_offset_sum = Some(combined_reg);
         program.emit_insn(Insn::OffsetLimit {
-            limit_reg: t_ctx.limit_ctx.unwrap().reg_limit,
+            limit_reg: limit_ctx.reg_limit,
             offset_reg: reg,
             combined_reg,
         });
     }
+    Ok(())
+}
+
+/// Evaluates a LIMIT or OFFSET expression into `reg`. Its value must be an integer, or
+/// convertible to one without loss, like in SQLite.
+pub fn emit_limit_value(
+    program: &mut ProgramBuilder,
+    expr: &ast::Expr,
+    reg: usize,
+    resolver: &Resolver,
+) -> Result<()> {
+    match try_fold_expr_to_i64(expr) {
+        Some(value) => program.emit_int(value, reg),
+        None => {
+            // The LIMIT counter is decremented as rows are produced, so it must be evaluated
+            // again every time the query runs.
+            translate_expr_no_constant_opt(
+                program,
+                None,
+                expr,
+                reg,
+                resolver,
+                NoConstantOptReason::RegisterReuse,
+            )?;
+            program.emit_insn(Insn::MustBeInt { reg });
+        }
+    }
+    Ok(())
 }
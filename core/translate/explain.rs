@@ -0,0 +1,451 @@
+//! `EXPLAIN QUERY PLAN`.
+//!
+//! Like SQLite, the plan of a statement is described as a tree of rows with the columns `id`,
+//! `parent`, `notused` and `detail`, where `parent` is the `id` of the parent row, or 0 for the
+//! rows at the top level. The details use the wording of SQLite, e.g. `SCAN t` or
+//! `SEARCH t USING INDEX i (a=? AND b>?)`, so that tools built for SQLite can read them.
+
+use std::fmt::{self, Display, Formatter};
+
+use turso_sqlite3_parser::ast::{self, CompoundOperator, Delete};
+
+use super::emitter::TransactionMode;
+use super::optimizer::optimize_plan;
+use super::order_by::distinct_by_order_by;
+use super::plan::{
+    IterationDirection, JoinOrderMember, JoinedTable, NonFromClauseSubquery, Operation, Plan,
+    QueryDestination, Search, SeekDef, SelectPlan, TableReferences,
+};
+use super::{
+    delete::prepare_delete_plan, select::prepare_select_plan, update::prepare_update_plan,
+};
+use crate::schema::{Index, Schema, Table};
+use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts};
+use crate::{Result, SymbolTable};
+
+/// The result columns of `EXPLAIN QUERY PLAN`.
+const COLUMNS: [&str; 4] = ["id", "parent", "notused", "detail"];
+
+/// Translates `EXPLAIN QUERY PLAN stmt` into a program that returns the rows describing the plan
+/// of `stmt`. Only queries, updates and deletes have a plan; other statements return no rows.
+pub fn translate_explain_query_plan(
+    schema: &Schema,
+    stmt: ast::Stmt,
+    syms: &SymbolTable,
+    mut program: ProgramBuilder,
+) -> Result<ProgramBuilder> {
+    let plan = match stmt {
+        ast::Stmt::Select(select) => Some(prepare_select_plan(
+            schema,
+            *select,
+            syms,
+            &[],
+            &mut program.table_reference_counter,
+            QueryDestination::ResultRows,
+        )?),
+        ast::Stmt::Update(mut update) => Some(prepare_update_plan(
+            &mut program,
+            schema,
+            syms,
+            &mut update,
+        )?),
+        ast::Stmt::Delete(delete) => {
+            let Delete {
+                tbl_name,
+                where_clause,
+                limit,
+                returning,
+                ..
+            } = *delete;
+            Some(prepare_delete_plan(
+                &mut program,
+                schema,
+                &tbl_name,
+                where_clause,
+                limit,
+                returning,
+            )?)
+        }
+        _ => None,
+    };
+    let description = match plan {
+        Some(mut plan) => {
+            optimize_plan(&mut plan, schema, program.like_behavior())?;
+            QueryPlanDescription::new(&plan)
+        }
+        None => QueryPlanDescription::default(),
+    };
+
+    program.extend(&ProgramBuilderOpts {
+        num_cursors: 0,
+        approx_num_insns: description.rows.len() * 5 + 2,
+        approx_num_labels: 0,
+    });
+    let base_reg = program.alloc_registers(COLUMNS.len());
+    for row in description.rows {
+        program.emit_int(row.id as i64, base_reg);
+        program.emit_int(row.parent as i64, base_reg + 1);
+        program.emit_int(0, base_reg + 2);
+        program.emit_string8(row.detail, base_reg + 3);
+        program.emit_result_row(base_reg, COLUMNS.len());
+    }
+    for name in COLUMNS {
+        program.add_pragma_result_column(name.to_string());
+    }
+    program.epilogue(TransactionMode::None);
+    Ok(program)
+}
+
+/// A row of `EXPLAIN QUERY PLAN`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QueryPlanRow {
+    pub id: usize,
+    /// The id of the parent row, or 0 for a row at the top level.
+    pub parent: usize,
+    pub detail: String,
+}
+
+/// The rows describing a query plan, in the order they are displayed.
+#[derive(Debug, Default)]
+pub struct QueryPlanDescription {
+    pub rows: Vec<QueryPlanRow>,
+    /// The number of subqueries outside of FROM clauses described so far.
+    num_subqueries: usize,
+}
+
+impl QueryPlanDescription {
+    pub fn new(plan: &Plan) -> Self {
+        let mut description = Self::default();
+        description.describe_plan(plan, 0);
+        description
+    }
+
+    /// Adds a row under `parent` and returns its id.
+    fn push(&mut self, parent: usize, detail: impl Into<String>) -> usize {
+        let id = self.rows.len() + 1;
+        self.rows.push(QueryPlanRow {
+            id,
+            parent,
+            detail: detail.into(),
+        });
+        id
+    }
+
+    /// Writes the rows under `parent`, and their own children, as branches of a tree.
+    fn fmt_children(&self, f: &mut Formatter<'_>, parent: usize, prefix: &str) -> fmt::Result {
+        let children: Vec<_> = self
+            .rows
+            .iter()
+            .filter(|row| row.parent == parent)
+            .collect();
+        for (i, row) in children.iter().enumerate() {
+            let is_last = i == children.len() - 1;
+            writeln!(
+                f,
+                "{prefix}{}{}",
+                if is_last { "`--" } else { "|--" },
+                row.detail
+            )?;
+            let prefix = format!("{prefix}{}", if is_last { "   " } else { "|  " });
+            self.fmt_children(f, row.id, &prefix)?;
+        }
+        Ok(())
+    }
+
+    fn describe_plan(&mut self, plan: &Plan, parent: usize) {
+        match plan {
+            Plan::Select(select) => self.describe_select(select, parent),
+            Plan::CompoundSelect {
+                left,
+                right_most,
+                order_by,
+                ..
+            } => {
+                let compound = self.push(parent, "COMPOUND QUERY");
+                let id = self.push(compound, "LEFT-MOST SUBQUERY");
+                self.describe_select(&left[0].0, id);
+                // Each operator is paired with the SELECT to its left.
+                for (i, (_, operator)) in left.iter().enumerate() {
+                    let detail = match operator {
+                        CompoundOperator::UnionAll => "UNION ALL",
+                        CompoundOperator::Union => "UNION USING TEMP B-TREE",
+                        CompoundOperator::Intersect => "INTERSECT USING TEMP B-TREE",
+                        CompoundOperator::Except => "EXCEPT USING TEMP B-TREE",
+                    };
+                    let id = self.push(compound, detail);
+                    let plan = left.get(i + 1).map_or(right_most, |(plan, _)| plan);
+                    self.describe_select(plan, id);
+                }
+                if order_by.is_some() {
+                    self.push(parent, "USE TEMP B-TREE FOR ORDER BY");
+                }
+            }
+            Plan::Delete(delete) => match &delete.ephemeral_plan {
+                Some(ephemeral_plan) => self.describe_select(ephemeral_plan, parent),
+                None => self.describe_tables(&delete.table_references, None, parent),
+            },
+            Plan::Update(update) => {
+                match &update.ephemeral_plan {
+                    Some(ephemeral_plan) => self.describe_select(ephemeral_plan, parent),
+                    None => self.describe_tables(
+                        &update.table_references,
+                        Some(&update.join_order),
+                        parent,
+                    ),
+                }
+                self.describe_non_from_clause_subqueries(
+                    &update.non_from_clause_subqueries,
+                    parent,
+                );
+                if update.order_by.is_some() {
+                    self.push(parent, "USE TEMP B-TREE FOR ORDER BY");
+                }
+            }
+        }
+    }
+
+    fn describe_non_from_clause_subqueries(
+        &mut self,
+        subqueries: &[NonFromClauseSubquery],
+        parent: usize,
+    ) {
+        for subquery in subqueries.iter() {
+            self.num_subqueries += 1;
+            let kind = match subquery.query_type {
+                ast::SubqueryType::In => "LIST",
+                ast::SubqueryType::Exists | ast::SubqueryType::Scalar => "SCALAR",
+            };
+            let detail = if subquery.correlated {
+                format!("CORRELATED {kind} SUBQUERY {}", self.num_subqueries)
+            } else {
+                format!("{kind} SUBQUERY {}", self.num_subqueries)
+            };
+            let id = self.push(parent, detail);
+            self.describe_select(&subquery.plan, id);
+        }
+    }
+
+    fn describe_select(&mut self, plan: &SelectPlan, parent: usize) {
+        if plan.joined_tables().is_empty() {
+            if plan.values.len() > 1 {
+                self.push(
+                    parent,
+                    format!("SCAN {}-ROW VALUES CLAUSE", plan.values.len()),
+                );
+            } else {
+                self.push(parent, "SCAN CONSTANT ROW");
+            }
+        }
+        self.describe_tables(&plan.table_references, Some(&plan.join_order), parent);
+        self.describe_non_from_clause_subqueries(&plan.non_from_clause_subqueries, parent);
+        if plan
+            .group_by
+            .as_ref()
+            .is_some_and(|group_by| group_by.sort_order.is_some())
+        {
+            self.push(parent, "USE TEMP B-TREE FOR GROUP BY");
+        }
+        for aggregate in plan.aggregates.iter().filter(|agg| agg.is_distinct()) {
+            self.push(
+                parent,
+                format!(
+                    "USE TEMP B-TREE FOR {}(DISTINCT)",
+                    aggregate.func.to_string()
+                ),
+            );
+        }
+        if plan.distinctness.is_distinct() && !distinct_by_order_by(plan) {
+            self.push(parent, "USE TEMP B-TREE FOR DISTINCT");
+        }
+        if plan.order_by.is_some() {
+            self.push(parent, "USE TEMP B-TREE FOR ORDER BY");
+        }
+    }
+
+    /// Describes the loops over `tables`, in the join order if there is one. The subqueries in
+    /// the FROM clause come first, since they are set up before the loops.
+    fn describe_tables(
+        &mut self,
+        tables: &TableReferences,
+        join_order: Option<&[JoinOrderMember]>,
+        parent: usize,
+    ) {
+        let joined_tables = tables.joined_tables();
+        for table in joined_tables {
+            if let Table::FromClauseSubquery(subquery) = &table.table {
+                let coroutine = self.push(parent, format!("CO-ROUTINE {}", table.identifier));
+                match &subquery.recursive {
+                    Some(recursive) => {
+                        let setup = self.push(coroutine, "SETUP");
+                        self.describe_select(&subquery.plan, setup);
+                        let step = self.push(coroutine, "RECURSIVE STEP");
+                        self.describe_select(&recursive.plan, step);
+                    }
+                    None => self.describe_select(&subquery.plan, coroutine),
+                }
+            }
+        }
+        match join_order {
+            Some(join_order) if !join_order.is_empty() => {
+                for member in join_order {
+                    self.describe_table(&joined_tables[member.original_idx], parent);
+                }
+            }
+            _ => {
+                for table in joined_tables {
+                    self.describe_table(table, parent);
+                }
+            }
+        }
+    }
+
+    /// Describes the loop over `table`. A MULTI-INDEX OR has a row for the search of each of
+    /// its branches.
+    fn describe_table(&mut self, table: &JoinedTable, parent: usize) {
+        let id = self.push(parent, describe_table(table));
+        if let Operation::Search(Search::MultiIndexOr { branches, .. }) = &table.op {
+            for (i, branch) in branches.iter().enumerate() {
+                let index_id = self.push(id, format!("INDEX {}", i + 1));
+                self.push(index_id, describe_multi_index_or_branch(table, branch));
+            }
+        }
+    }
+}
+
+/// Returns the detail of the loop over `table`, e.g. `SEARCH t USING INDEX i (a=?)`.
+fn describe_table(table: &JoinedTable) -> String {
+    let name = &table.identifier;
+    let mut detail = match (&table.table, &table.op) {
+        (Table::Virtual(_), _) => format!("SCAN {name} VIRTUAL TABLE"),
+        (_, Operation::Scan { index: None, .. }) => format!("SCAN {name}"),
+        (
+            _,
+            Operation::Scan {
+                index: Some(index), ..
+            },
+        ) => format!("SCAN {name} USING {}", describe_index(table, index)),
+        (_, Operation::Search(Search::RowidEq { .. })) => {
+            format!("SEARCH {name} USING INTEGER PRIMARY KEY (rowid=?)")
+        }
+        (
+            _,
+            Operation::Search(Search::Seek {
+                index: None,
+                seek_def,
+            }),
+        ) => format!(
+            "SEARCH {name} USING INTEGER PRIMARY KEY ({})",
+            describe_seek(seek_def, |_| "rowid")
+        ),
+        (
+            _,
+            Operation::Search(Search::Seek {
+                index: Some(index),
+                seek_def,
+            }),
+        ) => format!(
+            "SEARCH {name} USING {} ({})",
+            describe_index(table, index),
+            describe_seek(seek_def, |i| index_column_name(index, i))
+        ),
+        (_, Operation::Search(Search::MultiIndexOr { .. })) => "MULTI-INDEX OR".to_string(),
+    };
+    if table.join_info.as_ref().is_some_and(|info| info.outer) {
+        detail.push_str(" LEFT-JOIN");
+    }
+    detail
+}
+
+/// Returns the detail of the search of a branch of a MULTI-INDEX OR over `table`. The rows it
+/// finds are always read from the table, so its index is never described as covering.
+fn describe_multi_index_or_branch(table: &JoinedTable, branch: &Search) -> String {
+    let name = &table.identifier;
+    match branch {
+        Search::RowidEq { .. } => format!("SEARCH {name} USING INTEGER PRIMARY KEY (rowid=?)"),
+        Search::Seek {
+            index: None,
+            seek_def,
+        } => format!(
+            "SEARCH {name} USING INTEGER PRIMARY KEY ({})",
+            describe_seek(seek_def, |_| "rowid")
+        ),
+        Search::Seek {
+            index: Some(index),
+            seek_def,
+        } => format!(
+            "SEARCH {name} USING INDEX {} ({})",
+            index.name,
+            describe_seek(seek_def, |i| index_column_name(index, i))
+        ),
+        Search::MultiIndexOr { .. } => {
+            unreachable!("the branches of a MULTI-INDEX OR are plain searches")
+        }
+    }
+}
+
+/// Returns the name of the column of `index` at position `i` in a search description. Like in
+/// SQLite, indexed expressions are all described as `<expr>`.
+fn index_column_name(index: &Index, i: usize) -> &str {
+    match index.columns[i].expr {
+        Some(_) => "<expr>",
+        None => &index.columns[i].name,
+    }
+}
+
+/// Returns how `index` is used for `table`, e.g. `COVERING INDEX i`.
+fn describe_index(table: &JoinedTable, index: &Index) -> String {
+    let covering = if table.index_is_covering(index) {
+        "COVERING "
+    } else {
+        ""
+    };
+    if index.ephemeral {
+        format!("AUTOMATIC {covering}INDEX")
+    } else {
+        format!("{covering}INDEX {}", index.name)
+    }
+}
+
+/// Returns the constraints a seek uses, e.g. `a=? AND b>?`, given the names of the columns of
+/// its key.
+fn describe_seek<'a>(seek_def: &SeekDef, column_name: impl Fn(usize) -> &'a str) -> String {
+    let key_len = seek_def.key.len();
+    let seek_len = seek_def.seek.as_ref().map_or(0, |seek| seek.len);
+    let termination_len = seek_def
+        .termination
+        .as_ref()
+        .map_or(0, |termination| termination.len);
+    let mut constraints: Vec<_> = (0..key_len - 1)
+        .map(|i| format!("{}=?", column_name(i)))
+        .collect();
+    // All the columns of the key but the last are compared for equality. The last one is too if
+    // both the seek and the termination use it; otherwise, only the end of the range where the
+    // seek starts or where the scan terminates is bounded. Which end of the index that is depends
+    // on the direction of the scan, and which end of the values it holds on the order of the
+    // column in the index.
+    let last = column_name(key_len - 1);
+    if seek_def.termination_last_key.is_some() {
+        constraints.push(format!("{last}>? AND {last}<?"));
+    } else if seek_len == key_len && termination_len == key_len {
+        constraints.push(format!("{last}=?"));
+    } else {
+        let bounds_start = seek_len == key_len;
+        let bounds_lowest_keys =
+            bounds_start == (seek_def.iter_dir == IterationDirection::Forwards);
+        let (_, sort_order) = seek_def.key[key_len - 1];
+        if bounds_lowest_keys == (sort_order == ast::SortOrder::Asc) {
+            constraints.push(format!("{last}>?"));
+        } else {
+            constraints.push(format!("{last}<?"));
+        }
+    }
+    constraints.join(" AND ")
+}
+
+/// Displays the rows as a tree, like the SQLite shell does.
+impl Display for QueryPlanDescription {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        writeln!(f, "QUERY PLAN")?;
+        self.fmt_children(f, 0, "")
+    }
+}
@@ -1,22 +1,26 @@
+use std::num::NonZeroUsize;
+use std::sync::Arc;
+
 use tracing::{instrument, Level};
-use turso_sqlite3_parser::ast::{self, Expr, UnaryOperator};
+use turso_sqlite3_parser::ast::{self, Expr, TableInternalId, UnaryOperator};
 
-use super::emitter::Resolver;
+use super::emitter::{emit_column_affinity, Resolver, SubqueryOutput};
 use super::optimizer::Optimizable;
 use super::plan::TableReferences;
+use crate::error::SQLITE_CONSTRAINT_TRIGGER;
 #[cfg(feature = "json")]
 use crate::function::JsonFunc;
 use crate::function::{Func, FuncCtx, MathFuncArity, ScalarFunc, VectorFunc};
 use crate::functions::datetime;
-use crate::schema::{Affinity, Table, Type};
+use crate::schema::{Affinity, Index, Table, Type};
 use crate::util::{exprs_are_equivalent, parse_numeric_literal};
 use crate::vdbe::builder::CursorKey;
 use crate::vdbe::{
     builder::ProgramBuilder,
     insn::{CmpInsFlags, Insn},
-    BranchOffset,
+    BranchOffset, CursorID,
 };
-use crate::{Result, Value};
+use crate::{AuthAction, Result, Value};
 
 use super::collate::CollationSeq;
 
@@ -203,7 +207,8 @@ pub fn translate_condition_expr(
         | ast::Expr::FunctionCall { .. }
         | ast::Expr::Column { .. }
         | ast::Expr::RowId { .. }
-        | ast::Expr::Case { .. } => {
+        | ast::Expr::Case { .. }
+        | ast::Expr::SubqueryResult { .. } => {
             let reg = program.alloc_register();
             translate_expr(program, Some(referenced_tables), expr, reg, resolver)?;
             emit_cond_jump(program, condition_metadata, reg);
@@ -244,6 +249,29 @@ pub fn translate_condition_expr(
                 return Ok(());
             }
 
+            // A row value is compared with each row of the list instead,
+            // e.g. `(a, b) IN ((1, 2), (3, 4))` is `(a, b) = (1, 2) OR (a, b) = (3, 4)`.
+            if matches!(lhs.as_ref(), ast::Expr::Parenthesized(exprs) if exprs.len() > 1) {
+                let (op, connective) = if *not {
+                    (ast::Operator::NotEquals, ast::Operator::And)
+                } else {
+                    (ast::Operator::Equals, ast::Operator::Or)
+                };
+                let expr = rhs
+                    .iter()
+                    .flatten()
+                    .map(|row| ast::Expr::Binary(lhs.clone(), op, Box::new(row.clone())))
+                    .reduce(|acc, cmp| ast::Expr::Binary(Box::new(acc), connective, Box::new(cmp)))
+                    .expect("IN list must not be empty");
+                return translate_condition_expr(
+                    program,
+                    referenced_tables,
+                    &expr,
+                    condition_metadata,
+                    resolver,
+                );
+            }
+
             // The left hand side only needs to be evaluated once we have a list of values to compare against.
             let lhs_reg = program.alloc_register();
             let _ = translate_expr(program, Some(referenced_tables), lhs, lhs_reg, resolver)?;
@@ -404,6 +432,253 @@ pub fn translate_condition_expr(
     Ok(())
 }
 
+/// Translate `lhs [NOT] IN (SELECT ...)`, where the rows of the subquery have been
+/// materialized into the ephemeral index `cursor_id`. `lhs` is either a scalar or a row value
+/// with as many columns as the index.
+///
+/// If the subquery returns no rows, the result is false (true for NOT IN), even if `lhs` is NULL.
+/// Otherwise, when `lhs` is not found, the result is NULL if some row might equal `lhs` were
+/// its NULLs known, i.e. if every column of the row equals that of `lhs` or either is NULL.
+#[allow(clippy::too_many_arguments)]
+fn translate_in_subquery(
+    program: &mut ProgramBuilder,
+    referenced_tables: Option<&TableReferences>,
+    lhs: &ast::Expr,
+    not_in: bool,
+    cursor_id: CursorID,
+    affinity_str: Option<&str>,
+    target_register: usize,
+    resolver: &Resolver,
+) -> Result<()> {
+    let label_true = program.allocate_label();
+    let label_false = program.allocate_label();
+    let label_null = program.allocate_label();
+    let label_end = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id,
+        pc_if_empty: label_false,
+    });
+    let lhs_exprs = match lhs {
+        ast::Expr::Parenthesized(exprs) => exprs.as_slice(),
+        _ => std::slice::from_ref(lhs),
+    };
+    let num_regs = lhs_exprs.len();
+    let lhs_reg = program.alloc_registers(num_regs);
+    for (i, expr) in lhs_exprs.iter().enumerate() {
+        translate_expr(program, referenced_tables, expr, lhs_reg + i, resolver)?;
+    }
+    if let Some(affinity_str) = affinity_str {
+        program.emit_insn(Insn::Affinity {
+            start_reg: lhs_reg,
+            count: NonZeroUsize::new(num_regs).unwrap(),
+            affinities: affinity_str.to_string(),
+        });
+    }
+    // A row value with a NULL in it is never found, but it may match some rows partially.
+    let label_partial_match = if num_regs == 1 {
+        label_null
+    } else {
+        program.allocate_label()
+    };
+    for i in 0..num_regs {
+        program.emit_insn(Insn::IsNull {
+            reg: lhs_reg + i,
+            target_pc: label_partial_match,
+        });
+    }
+    program.emit_insn(Insn::Found {
+        cursor_id,
+        target_pc: label_true,
+        record_reg: lhs_reg,
+        num_regs,
+    });
+    if num_regs == 1 {
+        // NULLs sort first, so the subquery returned a NULL if the first entry of the index is NULL.
+        program.emit_insn(Insn::Rewind {
+            cursor_id,
+            pc_if_empty: label_false,
+        });
+        let first_reg = program.alloc_register();
+        program.emit_insn(Insn::Column {
+            cursor_id,
+            column: 0,
+            dest: first_reg,
+            default: None,
+        });
+        program.emit_insn(Insn::NotNull {
+            reg: first_reg,
+            target_pc: label_false,
+        });
+    } else {
+        // Look for a row whose columns all equal those of `lhs` or are compared with a NULL.
+        program.preassign_label_to_next_insn(label_partial_match);
+        program.emit_insn(Insn::Rewind {
+            cursor_id,
+            pc_if_empty: label_false,
+        });
+        let label_next_row = program.allocate_label();
+        let loop_start = program.allocate_label();
+        program.preassign_label_to_next_insn(loop_start);
+        let column_reg = program.alloc_register();
+        for i in 0..num_regs {
+            program.emit_column(cursor_id, i, column_reg);
+            program.emit_insn(Insn::Ne {
+                lhs: lhs_reg + i,
+                rhs: column_reg,
+                target_pc: label_next_row,
+                flags: CmpInsFlags::default(),
+                collation: None,
+            });
+        }
+        program.emit_insn(Insn::Goto {
+            target_pc: label_null,
+        });
+        program.preassign_label_to_next_insn(label_next_row);
+        program.emit_insn(Insn::Next {
+            cursor_id,
+            pc_if_next: loop_start,
+        });
+        program.emit_insn(Insn::Goto {
+            target_pc: label_false,
+        });
+    }
+    program.preassign_label_to_next_insn(label_null);
+    program.emit_insn(Insn::Null {
+        dest: target_register,
+        dest_end: None,
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: label_end,
+    });
+    program.preassign_label_to_next_insn(label_true);
+    program.emit_int(!not_in as i64, target_register);
+    program.emit_insn(Insn::Goto {
+        target_pc: label_end,
+    });
+    program.preassign_label_to_next_insn(label_false);
+    program.emit_int(not_in as i64, target_register);
+    program.preassign_label_to_next_insn(label_end);
+    Ok(())
+}
+
+/// If `lhs` and `rhs` are row values, returns their comparison expanded into comparisons of
+/// their columns, e.g. `(a, b) = (x, y)` becomes `a = x AND b = y` and `(a, b) < (x, y)` becomes
+/// `a < x OR (a = x AND b < y)`.
+fn expand_row_value_comparison(
+    program: &mut ProgramBuilder,
+    referenced_tables: Option<&TableReferences>,
+    lhs: &ast::Expr,
+    op: ast::Operator,
+    rhs: &ast::Expr,
+    resolver: &Resolver,
+) -> Result<Option<ast::Expr>> {
+    let lhs = translate_row_value_columns(program, referenced_tables, lhs, resolver)?;
+    let rhs = translate_row_value_columns(program, referenced_tables, rhs, resolver)?;
+    let (lhs, rhs) = match (lhs, rhs) {
+        (None, None) => return Ok(None),
+        (Some(lhs), Some(rhs)) if lhs.len() == rhs.len() => (lhs, rhs),
+        _ => crate::bail_parse_error!("row value misused"),
+    };
+    let compare =
+        |i: usize, op| ast::Expr::Binary(Box::new(lhs[i].clone()), op, Box::new(rhs[i].clone()));
+    let connect = |connective, compared: ast::Expr, rest: ast::Expr| {
+        ast::Expr::Binary(
+            Box::new(compared),
+            connective,
+            Box::new(ast::Expr::Parenthesized(vec![rest])),
+        )
+    };
+    let last = lhs.len() - 1;
+    let expr = match op {
+        ast::Operator::Equals | ast::Operator::Is => {
+            (0..last).rev().fold(compare(last, op), |acc, i| {
+                connect(ast::Operator::And, compare(i, op), acc)
+            })
+        }
+        ast::Operator::NotEquals | ast::Operator::IsNot => {
+            (0..last).rev().fold(compare(last, op), |acc, i| {
+                connect(ast::Operator::Or, compare(i, op), acc)
+            })
+        }
+        ast::Operator::Less
+        | ast::Operator::LessEquals
+        | ast::Operator::Greater
+        | ast::Operator::GreaterEquals => {
+            // The columns are compared in order, the first one that differs decides.
+            let strict_op = match op {
+                ast::Operator::Less | ast::Operator::LessEquals => ast::Operator::Less,
+                _ => ast::Operator::Greater,
+            };
+            (0..last).rev().fold(compare(last, op), |acc, i| {
+                let tie = connect(ast::Operator::And, compare(i, ast::Operator::Equals), acc);
+                connect(ast::Operator::Or, compare(i, strict_op), tie)
+            })
+        }
+        _ => crate::bail_parse_error!("row value misused"),
+    };
+    Ok(Some(expr))
+}
+
+/// Returns the columns of `expr` if it is a row value: either `(a, b, ...)` or a subquery that
+/// returns several columns, whose result is copied into registers the columns then refer to.
+fn translate_row_value_columns(
+    program: &mut ProgramBuilder,
+    referenced_tables: Option<&TableReferences>,
+    expr: &ast::Expr,
+    resolver: &Resolver,
+) -> Result<Option<Vec<ast::Expr>>> {
+    match expr {
+        ast::Expr::Parenthesized(exprs) if exprs.len() > 1 => Ok(Some(exprs.clone())),
+        ast::Expr::SubqueryResult { subquery_id, .. } => {
+            let num_regs = resolver
+                .subquery_results
+                .iter()
+                .find(|s| s.subquery_id == *subquery_id)
+                .map_or(1, |s| match s.output {
+                    SubqueryOutput::Registers { num_regs, .. } => num_regs,
+                    SubqueryOutput::EphemeralIndex { .. } => 1,
+                });
+            if num_regs == 1 {
+                return Ok(None);
+            }
+            let start_reg = program.alloc_registers(num_regs);
+            translate_expr(program, referenced_tables, expr, start_reg, resolver)?;
+            Ok(Some(
+                (start_reg..start_reg + num_regs)
+                    .map(ast::Expr::Register)
+                    .collect(),
+            ))
+        }
+        _ => Ok(None),
+    }
+}
+
+/// Returns the index that the table is read through, and whether it is a covering index.
+/// A table of an outer query is read through the cursors opened by that query, which only
+/// has an index cursor open if it reads the table through a covering index.
+fn table_read_index(
+    program: &ProgramBuilder,
+    referenced_tables: &TableReferences,
+    table_ref_id: TableInternalId,
+) -> (Option<Arc<Index>>, bool) {
+    if let Some(table_reference) = referenced_tables.find_joined_table_by_internal_id(table_ref_id)
+    {
+        return (
+            table_reference.op.index().cloned(),
+            table_reference.utilizes_covering_index(),
+        );
+    }
+    if program
+        .resolve_cursor_id_safe(&CursorKey::table(table_ref_id))
+        .is_none()
+    {
+        if let Some(index) = program.resolve_index_for_table_reference(table_ref_id) {
+            return (Some(index), true);
+        }
+    }
+    (None, false)
+}
+
 /// Reason why [translate_expr_no_constant_opt()] was called.
 #[derive(Debug)]
 pub enum NoConstantOptReason {
@@ -442,6 +717,19 @@ pub fn translate_expr(
     expr: &ast::Expr,
     target_register: usize,
     resolver: &Resolver,
+) -> Result<usize> {
+    program.enter_expr()?;
+    let result = translate_expr_inner(program, referenced_tables, expr, target_register, resolver);
+    program.leave_expr();
+    result
+}
+
+fn translate_expr_inner(
+    program: &mut ProgramBuilder,
+    referenced_tables: Option<&TableReferences>,
+    expr: &ast::Expr,
+    target_register: usize,
+    resolver: &Resolver,
 ) -> Result<usize> {
     let constant_span = if expr.is_constant(resolver) {
         if !program.constant_span_is_open() {
@@ -471,6 +759,17 @@ pub fn translate_expr(
             unreachable!("expression should have been rewritten in optmizer")
         }
         ast::Expr::Binary(e1, op, e2) => {
+            if let Some(expr) =
+                expand_row_value_comparison(program, referenced_tables, e1, *op, e2, resolver)?
+            {
+                return translate_expr(
+                    program,
+                    referenced_tables,
+                    &expr,
+                    target_register,
+                    resolver,
+                );
+            }
             // Check if both sides of the expression are equivalent and reuse the same register if so
             if exprs_are_equivalent(e1, e2) {
                 let shared_reg = program.alloc_register();
@@ -583,18 +882,21 @@ pub fn translate_expr(
                     resolver,
                     NoConstantOptReason::RegisterReuse,
                 )?;
-                match base_reg {
-                    // CASE 1 WHEN 0 THEN 0 ELSE 1 becomes 1==0, Ne branch to next clause
-                    Some(base_reg) => program.emit_insn(Insn::Ne {
+                match (base_reg, base) {
+                    // CASE 1 WHEN 0 THEN 0 ELSE 1 becomes 1==0, Ne branch to next clause.
+                    // Like in `base = when`, the affinity of the operands applies.
+                    (Some(base_reg), Some(base_expr)) => program.emit_insn(Insn::Ne {
                         lhs: base_reg,
                         rhs: expr_reg,
                         target_pc: next_case_label,
                         // A NULL result is considered untrue when evaluating WHEN terms.
-                        flags: CmpInsFlags::default().jump_if_null(),
+                        flags: CmpInsFlags::default().jump_if_null().with_affinity(
+                            comparison_affinity(base_expr, when_expr, referenced_tables),
+                        ),
                         collation: program.curr_collation(),
                     }),
                     // CASE WHEN 0 THEN 0 ELSE 1 becomes ifnot 0 branch to next clause
-                    None => program.emit_insn(Insn::IfNot {
+                    _ => program.emit_insn(Insn::IfNot {
                         reg: expr_reg,
                         target_pc: next_case_label,
                         jump_if_null: true,
@@ -640,14 +942,17 @@ pub fn translate_expr(
             Ok(target_register)
         }
         ast::Expr::Cast { expr, type_name } => {
-            let type_name = type_name.as_ref().unwrap(); // TODO: why is this optional?
+            // The type name can be left out, like in `CAST(x AS)`, which converts to a BLOB.
+            let type_name = type_name
+                .as_ref()
+                .map_or_else(String::new, |type_name| type_name.name.to_uppercase());
             let reg_expr = program.alloc_registers(2);
             translate_expr(program, referenced_tables, expr, reg_expr, resolver)?;
             program.emit_insn(Insn::String8 {
                 // we make a comparison against uppercase static strs in the affinity() function,
                 // so we need to make sure we're comparing against the uppercase version,
                 // and it's better to do this once instead of every time we check affinity
-                value: type_name.name.to_uppercase(),
+                value: type_name,
                 dest: reg_expr + 1,
             });
             program.mark_last_insn_constant();
@@ -671,14 +976,24 @@ pub fn translate_expr(
             Ok(target_register)
         }
         ast::Expr::DoublyQualified(_, _, _) => todo!(),
-        ast::Expr::Exists(_) => todo!(),
+        ast::Expr::Exists(_) => {
+            crate::bail_parse_error!("EXISTS is only supported in WHERE and SET clauses")
+        }
         ast::Expr::FunctionCall {
             name,
             distinctness: _,
             args,
-            filter_over: _,
+            filter_over,
             order_by: _,
         } => {
+            // The window functions of a query are computed before its result columns are, so
+            // one that is translated here is not where a window function may be used.
+            if filter_over
+                .as_ref()
+                .is_some_and(|tail| tail.over_clause.is_some())
+            {
+                crate::bail_parse_error!("misuse of window function {}()", name.0);
+            }
             let args_count = if let Some(args) = args { args.len() } else { 0 };
             let func_type = resolver.resolve_function(&name.0, args_count);
 
@@ -695,6 +1010,9 @@ pub fn translate_expr(
                 Func::Agg(_) => {
                     crate::bail_parse_error!("misuse of aggregate function {}()", name.0)
                 }
+                Func::External(func) if func.func.is_aggregate() => {
+                    crate::bail_parse_error!("misuse of aggregate function {}()", name.0)
+                }
                 Func::External(_) => {
                     let regs = program.alloc_registers(args_count);
                     if let Some(args) = args {
@@ -803,14 +1121,18 @@ pub fn translate_expr(
                             func_ctx,
                         )
                     }
-                    JsonFunc::JsonValid => translate_function(
-                        program,
-                        args.as_deref().unwrap_or_default(),
-                        referenced_tables,
-                        resolver,
-                        target_register,
-                        func_ctx,
-                    ),
+                    JsonFunc::JsonValid => {
+                        let args = expect_arguments_max!(args, 2, j);
+
+                        translate_function(
+                            program,
+                            args,
+                            referenced_tables,
+                            resolver,
+                            target_register,
+                            func_ctx,
+                        )
+                    }
                     JsonFunc::JsonPatch | JsonFunc::JsonbPatch => {
                         let args = expect_arguments_exact!(args, 2, j);
                         translate_function(
@@ -1094,51 +1416,32 @@ pub fn translate_expr(
                             Ok(target_register)
                         }
                         ScalarFunc::Iif => {
+                            // iif(B1, V1, B2, V2, ..., [E]) is the same as
+                            // CASE WHEN B1 THEN V1 WHEN B2 THEN V2 ... [ELSE E] END.
                             let args = match args {
-                                Some(args) if args.len() == 3 => args,
+                                Some(args) if args.len() >= 2 => args,
                                 _ => crate::bail_parse_error!(
-                                    "{} requires exactly 3 arguments",
+                                    "{} requires at least 2 arguments",
                                     srf.to_string()
                                 ),
                             };
-                            let temp_reg = program.alloc_register();
-                            translate_expr_no_constant_opt(
-                                program,
-                                referenced_tables,
-                                &args[0],
-                                temp_reg,
-                                resolver,
-                                NoConstantOptReason::RegisterReuse,
-                            )?;
-                            let jump_target_when_false = program.allocate_label();
-                            program.emit_insn(Insn::IfNot {
-                                reg: temp_reg,
-                                target_pc: jump_target_when_false,
-                                jump_if_null: true,
-                            });
-                            translate_expr_no_constant_opt(
-                                program,
-                                referenced_tables,
-                                &args[1],
-                                target_register,
-                                resolver,
-                                NoConstantOptReason::RegisterReuse,
-                            )?;
-                            let jump_target_result = program.allocate_label();
-                            program.emit_insn(Insn::Goto {
-                                target_pc: jump_target_result,
-                            });
-                            program.preassign_label_to_next_insn(jump_target_when_false);
+                            let pairs = args.chunks_exact(2);
+                            let else_expr = pairs.remainder().first();
+                            let case = ast::Expr::Case {
+                                base: None,
+                                when_then_pairs: pairs
+                                    .map(|pair| (pair[0].clone(), pair[1].clone()))
+                                    .collect(),
+                                else_expr: else_expr.map(|expr| Box::new(expr.clone())),
+                            };
                             translate_expr_no_constant_opt(
                                 program,
                                 referenced_tables,
-                                &args[2],
+                                &case,
                                 target_register,
                                 resolver,
                                 NoConstantOptReason::RegisterReuse,
-                            )?;
-                            program.preassign_label_to_next_insn(jump_target_result);
-                            Ok(target_register)
+                            )
                         }
                         ScalarFunc::Glob | ScalarFunc::Like => {
                             let args = if let Some(args) = args {
@@ -1239,7 +1542,10 @@ pub fn translate_expr(
                             });
                             Ok(target_register)
                         }
-                        ScalarFunc::Date | ScalarFunc::DateTime | ScalarFunc::JulianDay => {
+                        ScalarFunc::Date
+                        | ScalarFunc::DateTime
+                        | ScalarFunc::JulianDay
+                        | ScalarFunc::UnixEpoch => {
                             let start_reg = program
                                 .alloc_registers(args.as_ref().map(|x| x.len()).unwrap_or(1));
                             if let Some(args) = args {
@@ -1339,33 +1645,6 @@ pub fn translate_expr(
                             });
                             Ok(target_register)
                         }
-                        ScalarFunc::UnixEpoch => {
-                            let mut start_reg = 0;
-                            match args {
-                                Some(args) if args.len() > 1 => {
-                                    crate::bail_parse_error!("epoch function with > 1 arguments. Modifiers are not yet supported.");
-                                }
-                                Some(args) if args.len() == 1 => {
-                                    let arg_reg = program.alloc_register();
-                                    let _ = translate_expr(
-                                        program,
-                                        referenced_tables,
-                                        &args[0],
-                                        arg_reg,
-                                        resolver,
-                                    )?;
-                                    start_reg = arg_reg;
-                                }
-                                _ => {}
-                            }
-                            program.emit_insn(Insn::Function {
-                                constant_mask: 0,
-                                start_reg,
-                                dest: target_register,
-                                func: func_ctx,
-                            });
-                            Ok(target_register)
-                        }
                         ScalarFunc::Time => {
                             let start_reg = program
                                 .alloc_registers(args.as_ref().map(|x| x.len()).unwrap_or(1));
@@ -1670,7 +1949,7 @@ pub fn translate_expr(
                             });
                             Ok(target_register)
                         }
-                        ScalarFunc::Printf => translate_function(
+                        ScalarFunc::Printf | ScalarFunc::Format => translate_function(
                             program,
                             args.as_deref().unwrap_or(&[]),
                             referenced_tables,
@@ -1884,6 +2163,12 @@ pub fn translate_expr(
                 Func::AlterTable(_) => unreachable!(),
             }
         }
+        ast::Expr::FunctionCallStar {
+            name,
+            filter_over: Some(tail),
+        } if tail.over_clause.is_some() => {
+            crate::bail_parse_error!("misuse of window function {}()", name.0)
+        }
         ast::Expr::FunctionCallStar { .. } => todo!(),
         ast::Expr::Id(id) => {
             // Treat double-quoted identifiers as string literals (SQLite compatibility)
@@ -1899,19 +2184,8 @@ pub fn translate_expr(
             column,
             is_rowid_alias,
         } => {
-            let (index, use_covering_index) = {
-                if let Some(table_reference) = referenced_tables
-                    .unwrap()
-                    .find_joined_table_by_internal_id(*table_ref_id)
-                {
-                    (
-                        table_reference.op.index(),
-                        table_reference.utilizes_covering_index(),
-                    )
-                } else {
-                    (None, false)
-                }
-            };
+            let (index, use_covering_index) =
+                table_read_index(program, referenced_tables.unwrap(), *table_ref_id);
 
             let table = referenced_tables
                 .unwrap()
@@ -1921,6 +2195,26 @@ pub fn translate_expr(
             let Some(table_column) = table.get_column_at(*column) else {
                 crate::bail_parse_error!("column index out of bounds");
             };
+            // The columns of subqueries in the FROM clause are authorized within the subquery.
+            if matches!(table, Table::BTree(_) | Table::Virtual(_)) {
+                let database = referenced_tables
+                    .unwrap()
+                    .find_database_by_internal_id(*table_ref_id)
+                    .unwrap_or_default();
+                if program.authorize(
+                    AuthAction::Read {
+                        table: table.get_name(),
+                        column: table_column.name.as_deref().unwrap_or_default(),
+                    },
+                    Some(resolver.schema.database_name(database)),
+                )? {
+                    program.emit_insn(Insn::Null {
+                        dest: target_register,
+                        dest_end: None,
+                    });
+                    return Ok(target_register);
+                }
+            }
             // Counter intuitive but a column always needs to have a collation
             program.set_collation(Some((table_column.collation.unwrap_or_default(), false)));
 
@@ -1928,13 +2222,38 @@ pub fn translate_expr(
             // the table and read the column from the cursor.
             // If we have a covering index, we don't have an open table cursor so we read from the index cursor.
             match &table {
+                Table::BTree(btree_table) if table_column.is_virtual_generated() => {
+                    // VIRTUAL generated columns are not stored, their expression is evaluated
+                    // against the other columns of the row instead.
+                    let generated = table_column.generated.as_ref().unwrap();
+                    let mut expr = generated.expr.clone();
+                    walk_expr_mut(&mut expr, &mut |expr: &mut ast::Expr| -> Result<()> {
+                        if let ast::Expr::Id(id) = expr {
+                            let Some((idx, column)) = btree_table.get_column(&id.0) else {
+                                crate::bail_parse_error!("no such column: {}", id.0);
+                            };
+                            *expr = ast::Expr::Column {
+                                database: None,
+                                table: *table_ref_id,
+                                column: idx,
+                                is_rowid_alias: column.is_rowid_alias,
+                            };
+                        }
+                        Ok(())
+                    })?;
+                    translate_expr(program, referenced_tables, &expr, target_register, resolver)?;
+                    emit_column_affinity(program, table_column, target_register);
+                    program
+                        .set_collation(Some((table_column.collation.unwrap_or_default(), false)));
+                    Ok(target_register)
+                }
                 Table::BTree(_) => {
                     let table_cursor_id = if use_covering_index {
                         None
                     } else {
                         Some(program.resolve_cursor_id(&CursorKey::table(*table_ref_id)))
                     };
-                    let index_cursor_id = index.map(|index| {
+                    let index_cursor_id = index.as_ref().map(|index| {
                         program.resolve_cursor_id(&CursorKey::index(*table_ref_id, index.clone()))
                     });
                     if *is_rowid_alias {
@@ -2008,19 +2327,8 @@ pub fn translate_expr(
             database: _,
             table: table_ref_id,
         } => {
-            let (index, use_covering_index) = {
-                if let Some(table_reference) = referenced_tables
-                    .unwrap()
-                    .find_joined_table_by_internal_id(*table_ref_id)
-                {
-                    (
-                        table_reference.op.index(),
-                        table_reference.utilizes_covering_index(),
-                    )
-                } else {
-                    (None, false)
-                }
-            };
+            let (index, use_covering_index) =
+                table_read_index(program, referenced_tables.unwrap(), *table_ref_id);
 
             if use_covering_index {
                 let index =
@@ -2041,7 +2349,9 @@ pub fn translate_expr(
             Ok(target_register)
         }
         ast::Expr::InList { .. } => todo!(),
-        ast::Expr::InSelect { .. } => todo!(),
+        ast::Expr::InSelect { .. } => {
+            crate::bail_parse_error!("IN (SELECT ...) is only supported in WHERE and SET clauses")
+        }
         ast::Expr::InTable { .. } => todo!(),
         ast::Expr::IsNull(expr) => {
             let reg = program.alloc_register();
@@ -2183,17 +2493,105 @@ pub fn translate_expr(
                     resolver,
                 )?;
             } else {
-                // Parenthesized expressions with multiple arguments are reserved for special cases
-                // like `(a, b) IN ((1, 2), (3, 4))`.
-                todo!("TODO: parenthesized expression with multiple arguments not yet supported");
+                // Row values can only be compared, which is handled where they are compared.
+                crate::bail_parse_error!("row value misused");
             }
             Ok(target_register)
         }
         ast::Expr::Qualified(_, _) => {
             unreachable!("Qualified should be resolved to a Column before translation")
         }
-        ast::Expr::Raise(_, _) => todo!(),
-        ast::Expr::Subquery(_) => todo!(),
+        ast::Expr::Raise(resolve_type, message) => {
+            let Some(ignore_label) = program.trigger_ignore_label() else {
+                crate::bail_parse_error!("RAISE() may only be used within a trigger-program");
+            };
+            match resolve_type {
+                ast::ResolveType::Ignore => {
+                    program.emit_insn(Insn::Goto {
+                        target_pc: ignore_label,
+                    });
+                }
+                on_error => {
+                    let Some(ast::Expr::Literal(ast::Literal::String(message))) =
+                        message.as_deref()
+                    else {
+                        crate::bail_parse_error!("RAISE() message must be a string literal");
+                    };
+                    program.emit_insn(Insn::Halt {
+                        err_code: SQLITE_CONSTRAINT_TRIGGER,
+                        description: sanitize_string(message),
+                        on_error: *on_error,
+                    });
+                }
+            }
+            // Execution never continues past RAISE(), but the expression still needs a value.
+            program.emit_null(target_register, None);
+            Ok(target_register)
+        }
+        ast::Expr::Register(src_reg) => {
+            program.emit_insn(Insn::Copy {
+                src_reg: *src_reg,
+                dst_reg: target_register,
+                extra_amount: 0,
+            });
+            Ok(target_register)
+        }
+        ast::Expr::Subquery(_) => {
+            crate::bail_parse_error!("subqueries are only supported in WHERE and SET clauses")
+        }
+        ast::Expr::SubqueryResult {
+            subquery_id,
+            lhs,
+            not_in,
+            ..
+        } => {
+            let Some(subquery) = resolver
+                .subquery_results
+                .iter()
+                .find(|s| s.subquery_id == *subquery_id)
+                .cloned()
+            else {
+                crate::bail_parse_error!("subquery {} has not been emitted", subquery_id);
+            };
+            // A correlated subquery is re-executed for the current row of the outer query, and
+            // an uncorrelated one is executed the first time its result is needed.
+            let (subroutine_start, return_reg) = subquery.subroutine;
+            program.emit_insn(Insn::Gosub {
+                target_pc: subroutine_start,
+                return_reg,
+            });
+            match &subquery.output {
+                SubqueryOutput::Registers {
+                    start_reg,
+                    num_regs,
+                } => {
+                    program.emit_insn(Insn::Copy {
+                        src_reg: *start_reg,
+                        dst_reg: target_register,
+                        extra_amount: *num_regs - 1,
+                    });
+                }
+                SubqueryOutput::EphemeralIndex {
+                    cursor_id,
+                    affinity_str,
+                } => {
+                    let lhs = lhs
+                        .as_ref()
+                        .expect("IN subquery must have a left-hand side");
+                    translate_in_subquery(
+                        program,
+                        referenced_tables,
+                        lhs,
+                        *not_in,
+                        *cursor_id,
+                        affinity_str.as_deref(),
+                        target_register,
+                        resolver,
+                    )?;
+                }
+            }
+            Ok(target_register)
+        }
         ast::Expr::Unary(op, expr) => match (op, expr.as_ref()) {
             (UnaryOperator::Positive, expr) => {
                 translate_expr(program, referenced_tables, expr, target_register, resolver)
@@ -2553,7 +2951,10 @@ fn emit_binary_insn(
     Ok(())
 }
 
-/// The base logic for translating LIKE and GLOB expressions.
+/// The base logic for translating LIKE, GLOB and REGEXP expressions.
+/// Like in SQLite, `X op Y ESCAPE Z` calls the function named after the operator as `op(Y, X, Z)`,
+/// so the builtin like() and glob() are used unless the connection overrides them, and REGEXP
+/// only works once a regexp() function is registered, e.g. by the regexp extension.
 /// The logic for handling "NOT LIKE" is different depending on whether the expression
 /// is a conditional jump or not. This is why the caller handles the "NOT LIKE" behavior;
 /// see [translate_condition_expr] and [translate_expr] for implementations.
@@ -2574,40 +2975,46 @@ fn translate_like_base(
     else {
         crate::bail_parse_error!("expected Like expression");
     };
-    match op {
-        ast::LikeOperator::Like | ast::LikeOperator::Glob => {
-            let arg_count = if escape.is_some() { 3 } else { 2 };
-            let start_reg = program.alloc_registers(arg_count);
-            let mut constant_mask = 0;
-            translate_expr(program, referenced_tables, lhs, start_reg + 1, resolver)?;
-            let _ = translate_expr(program, referenced_tables, rhs, start_reg, resolver)?;
-            if arg_count == 3 {
-                if let Some(escape) = escape {
-                    translate_expr(program, referenced_tables, escape, start_reg + 2, resolver)?;
-                }
-            }
-            if matches!(rhs.as_ref(), ast::Expr::Literal(_)) {
-                program.mark_last_insn_constant();
-                constant_mask = 1;
-            }
-            let func = match op {
-                ast::LikeOperator::Like => ScalarFunc::Like,
-                ast::LikeOperator::Glob => ScalarFunc::Glob,
-                _ => unreachable!(),
-            };
-            program.emit_insn(Insn::Function {
-                constant_mask,
-                start_reg,
-                dest: target_register,
-                func: FuncCtx {
-                    func: Func::Scalar(func),
-                    arg_count,
-                },
-            });
+    let func_name = match op {
+        ast::LikeOperator::Like => "like",
+        ast::LikeOperator::Glob => "glob",
+        ast::LikeOperator::Regexp => "regexp",
+        // MATCH is only meaningful when a virtual table consumes it as a constraint
+        ast::LikeOperator::Match => {
+            crate::bail_parse_error!("unable to use function MATCH in the requested context")
+        }
+    };
+    let arg_count = if escape.is_some() { 3 } else { 2 };
+    let func = match resolver.resolve_function(func_name, arg_count) {
+        // The builtin glob() has no escape character
+        Some(Func::Scalar(ScalarFunc::Glob)) if arg_count == 3 => {
+            crate::bail_parse_error!("wrong number of arguments to function glob()")
+        }
+        Some(func) => func,
+        None => crate::bail_parse_error!("no such function: {}", func_name.to_uppercase()),
+    };
+
+    let start_reg = program.alloc_registers(arg_count);
+    translate_expr(program, referenced_tables, lhs, start_reg + 1, resolver)?;
+    translate_expr(program, referenced_tables, rhs, start_reg, resolver)?;
+    // A constant pattern is loaded once, and lets the builtin functions cache what they
+    // compile it to.
+    let mut constant_mask = 0;
+    if matches!(rhs.as_ref(), ast::Expr::Literal(_)) {
+        program.mark_last_insn_constant();
+        if matches!(func, Func::Scalar(_)) {
+            constant_mask = 1;
         }
-        ast::LikeOperator::Match => todo!(),
-        ast::LikeOperator::Regexp => todo!(),
     }
+    if let Some(escape) = escape {
+        translate_expr(program, referenced_tables, escape, start_reg + 2, resolver)?;
+    }
+    program.emit_insn(Insn::Function {
+        constant_mask,
+        start_reg,
+        dest: target_register,
+        func: FuncCtx { func, arg_count },
+    });
 
     Ok(target_register)
 }
@@ -2927,6 +3334,16 @@ where
                 ast::Expr::Unary(_, expr) => {
                     walk_expr(expr, func)?;
                 }
+                ast::Expr::SubqueryResult {
+                    lhs, outer_columns, ..
+                } => {
+                    if let Some(lhs) = lhs {
+                        walk_expr(lhs, func)?;
+                    }
+                    for expr in outer_columns {
+                        walk_expr(expr, func)?;
+                    }
+                }
                 ast::Expr::Id(_)
                 | ast::Expr::Column { .. }
                 | ast::Expr::RowId { .. }
@@ -2934,6 +3351,7 @@ where
                 | ast::Expr::DoublyQualified(..)
                 | ast::Expr::Name(_)
                 | ast::Expr::Qualified(..)
+                | ast::Expr::Register(_)
                 | ast::Expr::Variable(_) => {
                     // No nested expressions
                 }
@@ -3123,6 +3541,16 @@ where
         ast::Expr::Unary(_, expr) => {
             walk_expr_mut(expr, func)?;
         }
+        ast::Expr::SubqueryResult {
+            lhs, outer_columns, ..
+        } => {
+            if let Some(lhs) = lhs {
+                walk_expr_mut(lhs, func)?;
+            }
+            for expr in outer_columns {
+                walk_expr_mut(expr, func)?;
+            }
+        }
         ast::Expr::Id(_)
         | ast::Expr::Column { .. }
         | ast::Expr::RowId { .. }
@@ -3130,6 +3558,7 @@ where
         | ast::Expr::DoublyQualified(..)
         | ast::Expr::Name(_)
         | ast::Expr::Qualified(..)
+        | ast::Expr::Register(_)
         | ast::Expr::Variable(_) => {
             // No nested expressions
         }
@@ -12,7 +12,7 @@ use crate::{
         insn::Insn,
         BranchOffset,
     },
-    Result,
+    LimboError, Result,
 };
 
 use super::{
@@ -774,8 +774,10 @@ pub fn group_by_emit_row_phase<'a>(
         program.offset(),
     );
     // SELECT DISTINCT also jumps here if there is a duplicate.
-    if let Distinctness::Distinct { ctx } = &plan.distinctness {
-        let distinct_ctx = ctx.as_ref().expect("distinct context must exist");
+    if let Distinctness::Distinct {
+        ctx: Some(distinct_ctx),
+    } = &plan.distinctness
+    {
         program.resolve_label(distinct_ctx.label_on_conflict, program.offset());
     }
     program.emit_insn(Insn::Return {
@@ -1109,8 +1111,32 @@ pub fn translate_aggregation_step_groupby(
             });
             target_register
         }
-        AggFunc::External(_) => {
-            todo!("External aggregate functions are not yet supported in GROUP BY");
+        AggFunc::External(ref func) => {
+            let argc = func.agg_args().map_err(|_| {
+                LimboError::ExtensionError(
+                    "External aggregate function called with wrong number of arguments".to_string(),
+                )
+            })?;
+            if argc != num_args {
+                crate::bail_parse_error!(
+                    "External aggregate function called with wrong number of arguments"
+                );
+            }
+            let expr_reg = agg_arg_source.translate(program, 0)?;
+            for i in 1..argc {
+                agg_arg_source.translate(program, i)?;
+            }
+            // invariant: distinct aggregates are only supported for single-argument functions
+            if argc == 1 {
+                handle_distinct(program, agg_arg_source.aggregate(), expr_reg);
+            }
+            program.emit_insn(Insn::AggStep {
+                acc_reg: target_register,
+                col: expr_reg,
+                delimiter: 0,
+                func: AggFunc::External(func.clone()),
+            });
+            target_register
         }
     };
     Ok(dest)
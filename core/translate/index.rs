@@ -1,25 +1,34 @@
 use std::sync::Arc;
 
-use crate::vdbe::insn::{CmpInsFlags, Cookie};
+use crate::vdbe::insn::{ChangeCount, CmpInsFlags, Cookie};
 use crate::{
-    schema::{BTreeTable, Column, Index, IndexColumn, PseudoCursorType, Schema},
+    function::Func,
+    schema::{BTreeTable, Index, IndexColumn, PseudoCursorType, Schema},
     storage::pager::CreateBTreeFlags,
     util::normalize_ident,
     vdbe::{
         builder::{CursorType, ProgramBuilder},
         insn::{IdxInsertFlags, Insn, RegisterOrLiteral},
     },
+    SymbolTable,
 };
-use turso_sqlite3_parser::ast::{self, Expr, Id, SortOrder, SortedColumn};
+use turso_sqlite3_parser::ast::{self, Expr, SortOrder, SortedColumn};
 
-use super::schema::{emit_schema_entry, SchemaEntryType, SQLITE_TABLEID};
+use super::emitter::{emit_index_key_for_cursor, Resolver};
+use super::expr::{walk_expr, WalkControl};
+use super::schema::{
+    emit_schema_entry, emit_update_moved_root_page, SchemaEntryType, SQLITE_TABLEID,
+};
 
+#[allow(clippy::too_many_arguments)]
 pub fn translate_create_index(
     unique_if_not_exists: (bool, bool),
-    idx_name: &str,
+    idx_name: &ast::QualifiedName,
     tbl_name: &str,
     columns: &[SortedColumn],
+    where_clause: Option<&ast::Expr>,
     schema: &Schema,
+    syms: &SymbolTable,
     mut program: ProgramBuilder,
 ) -> crate::Result<ProgramBuilder> {
     if !schema.indexes_enabled() {
@@ -27,7 +36,15 @@ pub fn translate_create_index(
             "CREATE INDEX is disabled by default. Run with `--experimental-indexes` to enable this feature."
         );
     }
-    let idx_name = normalize_ident(idx_name);
+    // The index is created in the database it's qualified with, or else in that of the table.
+    let database_id = match &idx_name.db_name {
+        Some(db_name) => schema.database_index(&db_name.0)?,
+        None => {
+            schema.object_database(&ast::QualifiedName::single(ast::Name(tbl_name.to_string())))?
+        }
+    };
+    let db_schema = schema.database(database_id);
+    let idx_name = normalize_ident(&idx_name.name.0);
     let tbl_name = normalize_ident(tbl_name);
     let opts = crate::vdbe::builder::ProgramBuilderOpts {
         num_cursors: 5,
@@ -38,34 +55,38 @@ pub fn translate_create_index(
 
     // Check if the index is being created on a valid btree table and
     // the name is globally unique in the schema.
-    if !schema.is_unique_idx_name(&idx_name) {
+    if !db_schema.is_unique_idx_name(&idx_name) {
         crate::bail_parse_error!("Error: index with name '{idx_name}' already exists.");
     }
-    let Some(tbl) = schema.tables.get(&tbl_name) else {
+    if db_schema.get_view(&tbl_name).is_some() {
+        crate::bail_parse_error!("views may not be indexed");
+    }
+    let Some(tbl) = db_schema.tables.get(&tbl_name) else {
         crate::bail_parse_error!("Error: table '{tbl_name}' does not exist.");
     };
     let Some(tbl) = tbl.btree() else {
         crate::bail_parse_error!("Error: table '{tbl_name}' is not a b-tree table.");
     };
     let columns = resolve_sorted_columns(&tbl, columns)?;
+    for column in &columns {
+        if let Some(collation) = column.collation {
+            collation.ensure_exists()?;
+        }
+    }
+    if let Some(where_clause) = where_clause {
+        validate_index_expr(&tbl, where_clause, "partial index WHERE clauses")?;
+    }
 
     let idx = Arc::new(Index {
         name: idx_name.clone(),
         table_name: tbl.name.clone(),
         root_page: 0, //  we dont have access till its created, after we parse the schema table
-        columns: columns
-            .iter()
-            .map(|((pos_in_table, col), order)| IndexColumn {
-                name: col.name.as_ref().unwrap().clone(),
-                order: *order,
-                pos_in_table: *pos_in_table,
-                collation: col.collation,
-                default: col.default.clone(),
-            })
-            .collect(),
+        columns,
         unique: unique_if_not_exists.0,
         ephemeral: false,
+        conflict_clause: None,
         has_rowid: tbl.has_rowid,
+        where_clause: where_clause.cloned(),
     });
 
     // Allocate the necessary cursors:
@@ -88,7 +109,7 @@ pub fn translate_create_index(
     // Create a new B-Tree and store the root page index in a register
     let root_page_reg = program.alloc_register();
     program.emit_insn(Insn::CreateBtree {
-        db: 0,
+        db: database_id,
         root: root_page_reg,
         flags: CreateBTreeFlags::new_index(),
     });
@@ -98,8 +119,15 @@ pub fn translate_create_index(
         cursor_id: sqlite_schema_cursor_id,
         root_page: RegisterOrLiteral::Literal(sqlite_table.root_page),
         name: sqlite_table.name.clone(),
+        db: database_id,
     });
-    let sql = create_idx_stmt_to_sql(&tbl_name, &idx_name, unique_if_not_exists, &columns);
+    let sql = create_idx_stmt_to_sql(
+        &tbl_name,
+        &idx_name,
+        unique_if_not_exists,
+        &idx.columns,
+        where_clause,
+    );
     emit_schema_entry(
         &mut program,
         sqlite_schema_cursor_id,
@@ -115,7 +143,7 @@ pub fn translate_create_index(
     // open the sorter and the pseudo table
     program.emit_insn(Insn::SorterOpen {
         cursor_id: sorter_cursor_id,
-        columns: columns.len(),
+        columns: idx.columns.len(),
         order,
         collations: idx.columns.iter().map(|c| c.collation).collect(),
     });
@@ -123,13 +151,14 @@ pub fn translate_create_index(
     program.emit_insn(Insn::OpenPseudo {
         cursor_id: pseudo_cursor_id,
         content_reg,
-        num_fields: columns.len() + 1,
+        num_fields: idx.columns.len() + 1,
     });
 
     // open the table we are creating the index on for reading
     program.emit_insn(Insn::OpenRead {
         cursor_id: table_cursor_id,
         root_page: tbl.root_page,
+        db: database_id,
     });
 
     let loop_start_label = program.allocate_label();
@@ -141,23 +170,24 @@ pub fn translate_create_index(
     program.preassign_label_to_next_insn(loop_start_label);
 
     // Loop start:
+    // Skip the rows a partial index has no entry for.
     // Collect index values into start_reg..rowid_reg
     // emit MakeRecord (index key + rowid) into record_reg.
     //
     // Then insert the record into the sorter
-    let start_reg = program.alloc_registers(columns.len() + 1);
-    for (i, (col, _)) in columns.iter().enumerate() {
-        program.emit_column(table_cursor_id, col.0, start_reg + i);
-    }
-    let rowid_reg = start_reg + columns.len();
-    program.emit_insn(Insn::RowId {
-        cursor_id: table_cursor_id,
-        dest: rowid_reg,
-    });
+    let next_row_label = program.allocate_label();
+    let start_reg = emit_index_key_for_cursor(
+        &mut program,
+        &Resolver::new(schema, syms),
+        &tbl,
+        &idx,
+        table_cursor_id,
+        next_row_label,
+    )?;
     let record_reg = program.alloc_register();
     program.emit_insn(Insn::MakeRecord {
         start_reg,
-        count: columns.len() + 1,
+        count: idx.columns.len() + 1,
         dest_reg: record_reg,
         index_name: Some(idx_name.clone()),
     });
@@ -166,6 +196,7 @@ pub fn translate_create_index(
         record_reg,
     });
 
+    program.preassign_label_to_next_insn(next_row_label);
     program.emit_insn(Insn::Next {
         cursor_id: table_cursor_id,
         pc_if_next: loop_start_label,
@@ -178,6 +209,7 @@ pub fn translate_create_index(
         cursor_id: btree_cursor_id,
         root_page: RegisterOrLiteral::Register(root_page_reg),
         name: idx_name.clone(),
+        db: database_id,
     });
 
     let sorted_loop_start = program.allocate_label();
@@ -220,15 +252,15 @@ pub fn translate_create_index(
     program.close_cursors(&[sorter_cursor_id, table_cursor_id, btree_cursor_id]);
 
     program.emit_insn(Insn::SetCookie {
-        db: 0,
+        db: database_id,
         cookie: Cookie::SchemaVersion,
-        value: schema.schema_version as i32 + 1,
+        value: db_schema.schema_version as i32 + 1,
         p5: 0,
     });
     // Parse the schema table to get the index root page and add new index to Schema
     let parse_schema_where_clause = format!("name = '{idx_name}' AND type = 'index'");
     program.emit_insn(Insn::ParseSchema {
-        db: sqlite_schema_cursor_id,
+        db: database_id,
         where_clause: Some(parse_schema_where_clause),
     });
     // Close the final sqlite_schema cursor
@@ -242,25 +274,28 @@ pub fn translate_create_index(
     Ok(program)
 }
 
-fn resolve_sorted_columns<'a>(
-    table: &'a BTreeTable,
+fn resolve_sorted_columns(
+    table: &BTreeTable,
     cols: &[SortedColumn],
-) -> crate::Result<Vec<((usize, &'a Column), SortOrder)>> {
+) -> crate::Result<Vec<IndexColumn>> {
     let mut resolved = Vec::with_capacity(cols.len());
     for sc in cols {
-        let ident = normalize_ident(match &sc.expr {
-            // SQLite supports indexes on arbitrary expressions, but we don't (yet).
+        if let Expr::Collate(..) = sc.expr {
+            crate::bail_parse_error!("COLLATE in CREATE INDEX is not supported yet");
+        }
+        let column = IndexColumn::from_sorted_column(table, sc)?;
+        match &column.expr {
             // See "How to use indexes on expressions" in https://www.sqlite.org/expridx.html
-            Expr::Id(Id(col_name)) | Expr::Name(ast::Name(col_name)) => col_name,
-            _ => crate::bail_parse_error!("Error: cannot use expressions in CREATE INDEX"),
-        });
-        let Some(col) = table.get_column(&ident) else {
-            crate::bail_parse_error!(
-                "Error: column '{ident}' does not exist in table '{}'",
-                table.name
-            );
-        };
-        resolved.push((col, sc.order.unwrap_or(SortOrder::Asc)));
+            Some(expr) => validate_index_expr(table, expr, "index expressions")?,
+            None => {
+                if table.columns[column.pos_in_table].is_virtual_generated() {
+                    crate::bail_parse_error!(
+                        "indexes on VIRTUAL generated columns are not supported yet"
+                    );
+                }
+            }
+        }
+        resolved.push(column);
     }
     Ok(resolved)
 }
@@ -269,7 +304,8 @@ fn create_idx_stmt_to_sql(
     tbl_name: &str,
     idx_name: &str,
     unique_if_not_exists: (bool, bool),
-    cols: &[((usize, &Column), SortOrder)],
+    cols: &[IndexColumn],
+    where_clause: Option<&ast::Expr>,
 ) -> String {
     let mut sql = String::with_capacity(128);
     sql.push_str("CREATE ");
@@ -284,19 +320,86 @@ fn create_idx_stmt_to_sql(
     sql.push_str(" ON ");
     sql.push_str(tbl_name);
     sql.push_str(" (");
-    for (i, (col, order)) in cols.iter().enumerate() {
+    for (i, col) in cols.iter().enumerate() {
         if i > 0 {
             sql.push_str(", ");
         }
-        sql.push_str(col.1.name.as_ref().unwrap());
-        if *order == SortOrder::Desc {
+        match &col.expr {
+            Some(expr) => sql.push_str(&expr.to_string()),
+            None => sql.push_str(&col.name),
+        }
+        if col.order == SortOrder::Desc {
             sql.push_str(" DESC");
         }
     }
     sql.push(')');
+    if let Some(where_clause) = where_clause {
+        sql.push_str(" WHERE ");
+        sql.push_str(&where_clause.to_string());
+    }
     sql
 }
 
+/// Checks an expression of an index on `table`, i.e. the WHERE clause of a partial index or an
+/// indexed expression: it may only refer to the columns of the row, and must always have the same
+/// value for the same row. `context` names the kind of expression in error messages.
+fn validate_index_expr(table: &BTreeTable, expr: &ast::Expr, context: &str) -> crate::Result<()> {
+    let is_row_column = |name: &str| {
+        table.get_column(name).is_some()
+            || ["rowid", "oid", "_rowid_"].contains(&normalize_ident(name).as_str())
+    };
+    walk_expr(
+        expr,
+        &mut |expr: &ast::Expr| -> crate::Result<WalkControl> {
+            match expr {
+                Expr::Id(id) if !is_row_column(&id.0) => {
+                    crate::bail_parse_error!("no such column: {}", id.0);
+                }
+                Expr::Qualified(tbl_name, col_name)
+                    if normalize_ident(&tbl_name.0) != table.name
+                        || !is_row_column(&col_name.0) =>
+                {
+                    crate::bail_parse_error!("no such column: {}.{}", tbl_name.0, col_name.0);
+                }
+                Expr::DoublyQualified(db_name, tbl_name, col_name) => {
+                    crate::bail_parse_error!(
+                        "no such column: {}.{}.{}",
+                        db_name.0,
+                        tbl_name.0,
+                        col_name.0
+                    );
+                }
+                Expr::Subquery(_) | Expr::Exists(_) | Expr::InSelect { .. } => {
+                    crate::bail_parse_error!("subqueries prohibited in {context}");
+                }
+                Expr::Variable(_) => {
+                    crate::bail_parse_error!("parameters prohibited in {context}");
+                }
+                Expr::FunctionCall { name, args, .. } => {
+                    let arg_count = args.as_ref().map_or(0, |args| args.len());
+                    check_index_expr_function(&name.0, arg_count, context)?;
+                }
+                Expr::FunctionCallStar { name, .. } => {
+                    check_index_expr_function(&name.0, 0, context)?;
+                }
+                _ => {}
+            }
+            Ok(WalkControl::Continue)
+        },
+    )?;
+    Ok(())
+}
+
+fn check_index_expr_function(name: &str, arg_count: usize, context: &str) -> crate::Result<()> {
+    match Func::resolve_function(name, arg_count) {
+        Ok(Func::Agg(_)) => crate::bail_parse_error!("misuse of aggregate function {}()", name),
+        Ok(func) if !func.is_deterministic() => {
+            crate::bail_parse_error!("non-deterministic functions prohibited in {context}")
+        }
+        _ => Ok(()),
+    }
+}
+
 pub fn translate_drop_index(
     idx_name: &str,
     if_exists: bool,
@@ -367,6 +470,7 @@ pub fn translate_drop_index(
         cursor_id: sqlite_schema_cursor_id,
         root_page: RegisterOrLiteral::Literal(sqlite_table.root_page),
         name: sqlite_table.name.clone(),
+        db: 0,
     });
 
     let loop_start_label = program.allocate_label();
@@ -417,6 +521,9 @@ pub fn translate_drop_index(
 
     program.emit_insn(Insn::Delete {
         cursor_id: sqlite_schema_cursor_id,
+        nchange: ChangeCount::None,
+        table_name: None,
+        is_update: false,
     });
 
     program.resolve_label(next_label, program.offset());
@@ -435,11 +542,14 @@ pub fn translate_drop_index(
     });
 
     // Destroy index btree
+    let root_page = maybe_index.unwrap().root_page;
+    let former_root_reg = program.alloc_register();
     program.emit_insn(Insn::Destroy {
-        root: maybe_index.unwrap().root_page,
-        former_root_reg: 0,
-        is_temp: 0,
+        root: root_page,
+        former_root_reg,
+        db: 0,
     });
+    emit_update_moved_root_page(&mut program, &sqlite_table, 0, former_root_reg, root_page);
 
     // Remove from the Schema any mention of the index
     if let Some(idx) = maybe_index {
@@ -1,29 +1,42 @@
 use std::rc::Rc;
 
 use turso_sqlite3_parser::ast::{
-    DistinctNames, Expr, InsertBody, OneSelect, QualifiedName, ResolveType, ResultColumn, With,
+    DistinctNames, Expr, InsertBody, OneSelect, QualifiedName, ResolveType, ResultColumn,
+    TriggerEvent, TriggerTime, With,
 };
 
-use crate::error::{SQLITE_CONSTRAINT_NOTNULL, SQLITE_CONSTRAINT_PRIMARYKEY};
-use crate::schema::{IndexColumn, Table};
-use crate::translate::emitter::{emit_cdc_insns, emit_cdc_patch_record, OperationMode};
+use crate::error::SQLITE_CONSTRAINT_PRIMARYKEY;
+use crate::schema::Table;
+use crate::translate::emitter::{
+    emit_cdc_insns, emit_cdc_patch_record, emit_check_constraints, emit_generated_columns,
+    emit_index_key, emit_notnull_check, emit_partial_index_check, emit_replace_delete_row,
+    emit_table_affinity, emit_table_record, resolve_conflict, unique_violation_description,
+    unique_violation_err_code, OperationMode,
+};
+use crate::translate::schema::SQLITE_SEQUENCE_TABLE;
+use crate::translate::trigger::{emit_triggers, has_triggers, TriggerRow};
+use crate::translate::upsert::{
+    emit_upsert, find_upsert_clause, resolve_upsert_clauses, ConflictTarget, UpsertClause,
+    UpsertCtx,
+};
 use crate::util::normalize_ident;
 use crate::vdbe::builder::ProgramBuilderOpts;
-use crate::vdbe::insn::{IdxInsertFlags, InsertFlags, RegisterOrLiteral};
+use crate::vdbe::insn::{ChangeCount, IdxInsertFlags, InsertFlags, RegisterOrLiteral};
 use crate::vdbe::BranchOffset;
 use crate::{
     schema::{Column, Schema},
     vdbe::{
-        builder::{CursorType, ProgramBuilder},
+        builder::{CursorKey, CursorType, ProgramBuilder},
         insn::Insn,
     },
 };
-use crate::{Result, SymbolTable, VirtualTable};
+use crate::{AuthAction, Result, SymbolTable, VirtualTable};
 
 use super::emitter::Resolver;
 use super::expr::{translate_expr, translate_expr_no_constant_opt, NoConstantOptReason};
 use super::optimizer::rewrite_expr;
 use super::plan::QueryDestination;
+use super::returning::{prepare_returning, Returning};
 use super::select::translate_select;
 
 struct TempTableCtx {
@@ -40,7 +53,7 @@ pub fn translate_insert(
     tbl_name: QualifiedName,
     columns: Option<DistinctNames>,
     mut body: InsertBody,
-    _returning: Option<Vec<ResultColumn>>,
+    returning: Option<Vec<ResultColumn>>,
     syms: &SymbolTable,
     mut program: ProgramBuilder,
 ) -> Result<ProgramBuilder> {
@@ -53,10 +66,6 @@ pub fn translate_insert(
     if with.is_some() {
         crate::bail_parse_error!("WITH clause is not supported");
     }
-    if on_conflict.is_some() {
-        crate::bail_parse_error!("ON CONFLICT clause is not supported");
-    }
-
     if schema.table_has_indexes(&tbl_name.name.to_string()) && !schema.indexes_enabled() {
         // Let's disable altering a table with indices altogether instead of checking column by
         // column to be extra safe.
@@ -65,14 +74,31 @@ pub fn translate_insert(
         );
     }
     let table_name = &tbl_name.name;
-    let table = match schema.get_table(table_name.0.as_str()) {
+    let database_id = schema.object_database(&tbl_name)?;
+    let db_schema = schema.database(database_id);
+    if db_schema.get_view(table_name.0.as_str()).is_some() {
+        crate::bail_parse_error!("cannot modify {} because it is a view", table_name);
+    }
+    let table = match db_schema.get_table(table_name.0.as_str()) {
         Some(table) => table,
         None => crate::bail_parse_error!("no such table: {}", table_name),
     };
+    if program.authorize(
+        AuthAction::Insert {
+            table: table.get_name(),
+        },
+        Some(schema.database_name(database_id)),
+    )? {
+        program.epilogue(super::emitter::TransactionMode::None);
+        return Ok(program);
+    }
 
     let resolver = Resolver::new(schema, syms);
 
     if let Some(virtual_table) = &table.virtual_table() {
+        if returning.is_some() {
+            crate::bail_parse_error!("RETURNING is not supported for virtual tables");
+        }
         program = translate_virtual_table_insert(
             program,
             virtual_table.clone(),
@@ -92,7 +118,33 @@ pub fn translate_insert(
         crate::bail_parse_error!("INSERT into WITHOUT ROWID table is not supported");
     }
 
+    let identifier = tbl_name
+        .alias
+        .as_ref()
+        .map_or(table_name.0.as_str(), |alias| alias.0.as_str());
+    let result_columns = returning
+        .map(|returning| prepare_returning(returning, &btree_table, identifier))
+        .transpose()?
+        .unwrap_or_default();
+    let returning = Returning::open(&mut program, &result_columns);
+
     let root_page = btree_table.root_page;
+    let table_internal_id = program.table_reference_counter.next();
+    let has_insert_triggers = has_triggers(db_schema, &btree_table, &TriggerEvent::Insert, &[]);
+    if database_id != 0 && (has_insert_triggers || btree_table.has_autoincrement) {
+        crate::bail_parse_error!(
+            "triggers and AUTOINCREMENT in attached databases are not supported"
+        );
+    }
+
+    let upsert = match &mut body {
+        InsertBody::Select(_, upsert) => upsert.take(),
+        InsertBody::DefaultValues => None,
+    };
+    let upsert_clauses = upsert
+        .map(|upsert| resolve_upsert_clauses(db_schema, &btree_table, upsert))
+        .transpose()?
+        .unwrap_or_default();
 
     let mut values: Option<Vec<Expr>> = None;
     let inserting_multiple_rows = match &mut body {
@@ -102,9 +154,8 @@ pub fn translate_insert(
                 if values_expr.is_empty() {
                     crate::bail_parse_error!("no values to insert");
                 }
-                let mut param_idx = 1;
                 for expr in values_expr.iter_mut().flat_map(|v| v.iter_mut()) {
-                    rewrite_expr(expr, &mut param_idx)?;
+                    rewrite_expr(expr)?;
                 }
                 values = values_expr.pop();
                 false
@@ -140,14 +191,16 @@ pub fn translate_insert(
     let mut yield_reg_opt = None;
     let mut temp_table_ctx = None;
     let (num_values, cursor_id) = match body {
-        // TODO: upsert
         InsertBody::Select(select, _) => {
             // Simple Common case of INSERT INTO <table> VALUES (...)
             if matches!(select.body.select.as_ref(),  OneSelect::Values(values) if values.len() <= 1)
             {
                 (
                     values.as_ref().unwrap().len(),
-                    program.alloc_cursor_id(CursorType::BTreeTable(btree_table.clone())),
+                    program.alloc_cursor_id_keyed(
+                        CursorKey::table(table_internal_id),
+                        CursorType::BTreeTable(btree_table.clone()),
+                    ),
                 )
             } else {
                 // Multiple rows - use coroutine for value population
@@ -174,8 +227,10 @@ pub fn translate_insert(
                 program.emit_insn(Insn::EndCoroutine { yield_reg });
                 program.preassign_label_to_next_insn(jump_on_definition_label);
 
-                let cursor_id =
-                    program.alloc_cursor_id(CursorType::BTreeTable(btree_table.clone()));
+                let cursor_id = program.alloc_cursor_id_keyed(
+                    CursorKey::table(table_internal_id),
+                    CursorType::BTreeTable(btree_table.clone()),
+                );
 
                 // From SQLite
                 /* Set useTempTable to TRUE if the result of the SELECT statement
@@ -187,7 +242,7 @@ pub fn translate_insert(
                  ** of the tables being read by the SELECT statement.  Also use a
                  ** temp table in the case of row triggers.
                  */
-                if program.is_table_open(&table) {
+                if program.is_table_open(&table) || has_insert_triggers {
                     let temp_cursor_id =
                         program.alloc_cursor_id(CursorType::BTreeTable(btree_table.clone()));
                     temp_table_ctx = Some(TempTableCtx {
@@ -247,12 +302,14 @@ pub fn translate_insert(
                         cursor_id,
                         root_page: RegisterOrLiteral::Literal(root_page),
                         name: table_name.0.clone(),
+                        db: database_id,
                     });
                 } else {
                     program.emit_insn(Insn::OpenWrite {
                         cursor_id,
                         root_page: RegisterOrLiteral::Literal(root_page),
                         name: table_name.0.clone(),
+                        db: database_id,
                     });
 
                     // Main loop
@@ -271,13 +328,16 @@ pub fn translate_insert(
         }
         InsertBody::DefaultValues => (
             0,
-            program.alloc_cursor_id(CursorType::BTreeTable(btree_table.clone())),
+            program.alloc_cursor_id_keyed(
+                CursorKey::table(table_internal_id),
+                CursorType::BTreeTable(btree_table.clone()),
+            ),
         ),
     };
 
     // allocate cursor id's for each btree index cursor we'll need to populate the indexes
     // (idx name, root_page, idx cursor id)
-    let idx_cursors = schema
+    let idx_cursors = db_schema
         .get_indices(&table_name.0)
         .iter()
         .map(|idx| {
@@ -288,6 +348,12 @@ pub fn translate_insert(
             )
         })
         .collect::<Vec<(&String, usize, usize)>>();
+    let index_cursors = db_schema
+        .get_indices(&table_name.0)
+        .iter()
+        .zip(idx_cursors.iter())
+        .map(|(index, (_, _, idx_cursor_id))| (index.as_ref(), *idx_cursor_id))
+        .collect::<Vec<_>>();
 
     let column_mappings = resolve_columns_for_insert(&table, &columns, num_values)?;
     // Check if rowid was provided (through INTEGER PRIMARY KEY as a rowid alias)
@@ -339,6 +405,7 @@ pub fn translate_insert(
             cursor_id,
             root_page: RegisterOrLiteral::Literal(root_page),
             name: table_name.0.clone(),
+            db: database_id,
         });
 
         populate_column_registers(
@@ -350,12 +417,14 @@ pub fn translate_insert(
             &resolver,
         )?;
     }
+    emit_table_affinity(&mut program, &btree_table, column_registers_start);
     // Open turso_cdc table btree for writing if necessary
     if let Some((cdc_cursor_id, cdc_btree)) = &cdc_table {
         program.emit_insn(Insn::OpenWrite {
             cursor_id: *cdc_cursor_id,
             root_page: cdc_btree.root_page.into(),
             name: cdc_btree.name.clone(),
+            db: 0,
         });
     }
 
@@ -365,8 +434,67 @@ pub fn translate_insert(
             cursor_id: idx_cursor.2,
             root_page: idx_cursor.1.into(),
             name: idx_cursor.0.clone(),
+            db: database_id,
         });
     }
+    // Jumped to when the row is not inserted, because a conflict was either ignored
+    // or handled by an ON CONFLICT clause, or because a trigger raised IGNORE.
+    let row_done_label = program.allocate_label();
+
+    if has_insert_triggers {
+        // In BEFORE triggers NEW.rowid is the rowid provided by the user, or -1 if the
+        // rowid is yet to be allocated.
+        let new_rowid_reg = program.alloc_register();
+        if let Some(reg) = rowid_alias_reg {
+            let provided_rowid_reg = if inserting_multiple_rows {
+                reg
+            } else {
+                rowid_reg
+            };
+            program.emit_insn(Insn::Copy {
+                src_reg: provided_rowid_reg,
+                dst_reg: new_rowid_reg,
+                extra_amount: 0,
+            });
+            let rowid_provided_label = program.allocate_label();
+            program.emit_insn(Insn::NotNull {
+                reg: new_rowid_reg,
+                target_pc: rowid_provided_label,
+            });
+            program.emit_insn(Insn::Integer {
+                value: -1,
+                dest: new_rowid_reg,
+            });
+            program.preassign_label_to_next_insn(rowid_provided_label);
+        } else {
+            program.emit_insn(Insn::Integer {
+                value: -1,
+                dest: new_rowid_reg,
+            });
+        }
+        emit_generated_columns(
+            &mut program,
+            &resolver,
+            &btree_table,
+            new_rowid_reg,
+            column_registers_start,
+        )?;
+        emit_triggers(
+            &mut program,
+            &resolver,
+            &btree_table,
+            TriggerEvent::Insert,
+            TriggerTime::Before,
+            &[],
+            None,
+            Some(TriggerRow {
+                rowid_reg: new_rowid_reg,
+                columns_start_reg: column_registers_start,
+            }),
+            row_done_label,
+        )?;
+    }
+
     // Common record insertion logic for both single and multiple rows
     let check_rowid_is_integer_label = rowid_alias_reg.and(Some(program.allocate_label()));
     if let Some(reg) = rowid_alias_reg {
@@ -392,19 +520,92 @@ pub fn translate_insert(
         });
     }
 
+    // Tables created with AUTOINCREMENT never reuse a rowid, so the largest rowid ever used
+    // is tracked in sqlite_sequence. Tables created before sqlite_sequence existed don't have it.
+    let autoincrement_reg = if btree_table.has_autoincrement {
+        schema
+            .get_btree_table(SQLITE_SEQUENCE_TABLE)
+            .map(|sequence_table| program.autoincrement_reg(&btree_table.name, sequence_table))
+    } else {
+        None
+    };
+
     // Create new rowid if a) not provided by user or b) provided by user but is NULL
     program.emit_insn(Insn::NewRowid {
         cursor: cursor_id,
         rowid_reg,
-        prev_largest_reg: 0,
+        prev_largest_reg: autoincrement_reg.unwrap_or(0),
     });
 
     if let Some(must_be_int_label) = check_rowid_is_integer_label {
         program.resolve_label(must_be_int_label, program.offset());
         // If the user provided a rowid, it must be an integer.
         program.emit_insn(Insn::MustBeInt { reg: rowid_reg });
+        if let Some(autoincrement_reg) = autoincrement_reg {
+            program.emit_insn(Insn::MemMax {
+                dest_reg: autoincrement_reg,
+                src_reg: rowid_reg,
+            });
+        }
     }
 
+    // The generated columns are computed once the rowid they may refer to is known.
+    emit_generated_columns(
+        &mut program,
+        &resolver,
+        &btree_table,
+        rowid_reg,
+        column_registers_start,
+    )?;
+
+    let upsert_ctx = UpsertCtx {
+        table: &btree_table,
+        identifier,
+        table_internal_id,
+        cursor_id,
+        index_cursors: &index_cursors,
+        rowid_reg,
+        column_registers_start,
+        cdc_cursor_id: cdc_table.as_ref().map(|(cdc_cursor_id, _)| *cdc_cursor_id),
+        returning: returning.as_ref(),
+        row_done_label,
+    };
+
+    // NOT NULL constraints are checked before any uniqueness constraint.
+    for (i, col) in column_mappings
+        .iter()
+        .enumerate()
+        .filter(|(_, col)| col.column.notnull)
+    {
+        // if this is rowid alias - turso-db will emit NULL as a column value and always use rowid for the row as a column value
+        if col.column.is_rowid_alias {
+            continue;
+        }
+        emit_notnull_check(
+            &mut program,
+            &resolver,
+            &table_name.to_string(),
+            col.column,
+            i + column_registers_start,
+            resolve_conflict(on_conflict, col.column.notnull_conflict_clause),
+            row_done_label,
+        )?;
+    }
+
+    emit_check_constraints(
+        &mut program,
+        &resolver,
+        &btree_table,
+        rowid_reg,
+        column_registers_start,
+        // CHECK constraints don't have an ON CONFLICT clause of their own.
+        resolve_conflict(on_conflict, None),
+        row_done_label,
+    )?;
+
+    // Whether a conflicting row may be deleted, which moves the table cursor away.
+    let mut may_replace = false;
+
     // Check uniqueness constraint for rowid if it was provided by user.
     // When the DB allocates it there are no need for separate uniqueness checks.
     if has_user_provided_rowid {
@@ -426,10 +627,25 @@ pub fn translate_insert(
             "rowid"
         };
 
-        program.emit_insn(Insn::Halt {
-            err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
-            description: format!("{}.{}", table_name.0, rowid_column_name),
-        });
+        // Only an INTEGER PRIMARY KEY constraint has a say in how a rowid conflict is resolved.
+        let rowid_conflict = resolve_conflict(
+            on_conflict,
+            rowid_alias_index.and(btree_table.primary_key_conflict_clause),
+        );
+        may_replace |= rowid_conflict == ResolveType::Replace;
+        // NotExists left the table cursor on the conflicting row.
+        emit_unique_conflict(
+            &mut program,
+            schema,
+            syms,
+            &resolver,
+            &upsert_clauses,
+            ConflictTarget::Rowid,
+            rowid_conflict,
+            &upsert_ctx,
+            SQLITE_CONSTRAINT_PRIMARYKEY,
+            format!("{}.{}", table_name.0, rowid_column_name),
+        )?;
         program.preassign_label_to_next_insn(make_record_label);
     }
 
@@ -445,87 +661,110 @@ pub fn translate_insert(
         _ => (),
     }
 
-    let index_col_mappings = resolve_indicies_for_insert(schema, table.as_ref(), &column_mappings)?;
-    for index_col_mapping in index_col_mappings {
+    // All uniqueness constraints are checked before any index is written to, so that a
+    // conflict which is ignored or handled by an UPSERT leaves the indexes untouched.
+    let mut index_keys = Vec::new();
+    for index in db_schema.get_indices(&table_name.0) {
         // find which cursor we opened earlier for this index
         let idx_cursor_id = idx_cursors
             .iter()
-            .find(|(name, _, _)| *name == &index_col_mapping.idx_name)
+            .find(|(name, _, _)| *name == &index.name)
             .map(|(_, _, c_id)| *c_id)
             .expect("no cursor found for index");
 
-        let num_cols = index_col_mapping.columns.len();
+        let num_cols = index.columns.len();
         // allocate scratch registers for the index columns plus rowid
         let idx_start_reg = program.alloc_registers(num_cols + 1);
 
-        // copy each index column from the table's column registers into these scratch regs
-        for (i, col) in index_col_mapping.columns.iter().enumerate() {
-            // copy from the table's column register over to the index's scratch register
-
-            program.emit_insn(Insn::Copy {
-                src_reg: column_registers_start + col.0,
-                dst_reg: idx_start_reg + i,
-                extra_amount: 0,
-            });
-        }
-        // last register is the rowid
-        program.emit_insn(Insn::Copy {
-            src_reg: rowid_reg,
-            dst_reg: idx_start_reg + num_cols,
-            extra_amount: 0,
-        });
-
-        let index = schema
-            .get_index(&table_name.0, &index_col_mapping.idx_name)
-            .expect("index should be present");
-
-        let record_reg = program.alloc_register();
-        program.emit_insn(Insn::MakeRecord {
-            start_reg: idx_start_reg,
-            count: num_cols + 1,
-            dest_reg: record_reg,
-            index_name: Some(index_col_mapping.idx_name),
-        });
+        // compute each index column from the table's column registers into these scratch regs,
+        // followed by the rowid
+        emit_index_key(
+            &mut program,
+            &resolver,
+            &btree_table,
+            index,
+            rowid_reg,
+            column_registers_start,
+            idx_start_reg,
+        )?;
 
         if index.unique {
             let label_idx_insert = program.allocate_label();
+            // The row can't conflict with the entries of a partial index that excludes it.
+            emit_partial_index_check(
+                &mut program,
+                &resolver,
+                &btree_table,
+                index,
+                rowid_reg,
+                column_registers_start,
+                label_idx_insert,
+            )?;
             program.emit_insn(Insn::NoConflict {
                 cursor_id: idx_cursor_id,
                 target_pc: label_idx_insert,
                 record_reg: idx_start_reg,
                 num_regs: num_cols,
             });
-            let column_names = index_col_mapping.columns.iter().enumerate().fold(
-                String::with_capacity(50),
-                |mut accum, (idx, (index, _))| {
-                    if idx > 0 {
-                        accum.push_str(", ");
-                    }
-
-                    accum.push_str(&btree_table.name);
-                    accum.push('.');
-
-                    let name = btree_table
-                        .columns
-                        .get(*index)
-                        .unwrap()
-                        .name
-                        .as_ref()
-                        .expect("column name is None");
-                    accum.push_str(name);
-
-                    accum
-                },
-            );
-
-            program.emit_insn(Insn::Halt {
-                err_code: SQLITE_CONSTRAINT_PRIMARYKEY,
-                description: column_names,
-            });
+            let column_names = unique_violation_description(&btree_table, index);
+
+            let conflict_target = ConflictTarget::Index(index.name.clone());
+            let index_conflict = resolve_conflict(on_conflict, index.conflict_clause);
+            may_replace |= index_conflict == ResolveType::Replace;
+            let needs_conflicting_row = index_conflict == ResolveType::Replace
+                || find_upsert_clause(&upsert_clauses, &conflict_target).is_some();
+            if needs_conflicting_row {
+                // NoConflict left the index cursor on the conflicting entry;
+                // move the table cursor to the row it belongs to.
+                let conflict_rowid_reg = program.alloc_register();
+                program.emit_insn(Insn::IdxRowId {
+                    cursor_id: idx_cursor_id,
+                    dest: conflict_rowid_reg,
+                });
+                program.emit_insn(Insn::NotExists {
+                    cursor: cursor_id,
+                    rowid_reg: conflict_rowid_reg,
+                    target_pc: label_idx_insert,
+                });
+            }
+            emit_unique_conflict(
+                &mut program,
+                schema,
+                syms,
+                &resolver,
+                &upsert_clauses,
+                conflict_target,
+                index_conflict,
+                &upsert_ctx,
+                unique_violation_err_code(&btree_table, index),
+                column_names,
+            )?;
 
             program.resolve_label(label_idx_insert, program.offset());
         }
 
+        index_keys.push((index.clone(), idx_cursor_id, idx_start_reg, num_cols));
+    }
+
+    for (index, idx_cursor_id, idx_start_reg, num_cols) in index_keys {
+        let skip_index_label = program.allocate_label();
+        emit_partial_index_check(
+            &mut program,
+            &resolver,
+            &btree_table,
+            &index,
+            rowid_reg,
+            column_registers_start,
+            skip_index_label,
+        )?;
+        let record_reg = program.alloc_register();
+        program.emit_insn(Insn::MakeRecord {
+            start_reg: idx_start_reg,
+            count: num_cols + 1,
+            dest_reg: record_reg,
+            index_name: Some(index.name.clone()),
+        });
+
         // now do the actual index insertion using the unpacked registers
         program.emit_insn(Insn::IdxInsert {
             cursor_id: idx_cursor_id,
@@ -535,44 +774,32 @@ pub fn translate_insert(
             // TODO: figure out how to determine whether or not we need to seek prior to insert.
             flags: IdxInsertFlags::new(),
         });
+        program.preassign_label_to_next_insn(skip_index_label);
     }
 
-    for (i, col) in column_mappings
-        .iter()
-        .enumerate()
-        .filter(|(_, col)| col.column.notnull)
-    {
-        // if this is rowid alias - turso-db will emit NULL as a column value and always use rowid for the row as a column value
-        if col.column.is_rowid_alias {
-            continue;
-        }
-        let target_reg = i + column_registers_start;
-        program.emit_insn(Insn::HaltIfNull {
-            target_reg,
-            err_code: SQLITE_CONSTRAINT_NOTNULL,
-            description: format!(
-                "{}.{}",
-                table_name,
-                col.column
-                    .name
-                    .as_ref()
-                    .expect("Column name must be present")
-            ),
-        });
-    }
     // Create and insert the record
-    program.emit_insn(Insn::MakeRecord {
-        start_reg: column_registers_start,
-        count: num_cols,
-        dest_reg: record_register,
-        index_name: None,
-    });
+    emit_table_record(
+        &mut program,
+        &btree_table.columns,
+        column_registers_start,
+        record_register,
+    );
+    let change_count = program.change_count();
+    let mut flag = InsertFlags::new().nchange(change_count);
+    // The rows inserted by triggers don't change last_insert_rowid().
+    if change_count == ChangeCount::Statement {
+        flag = flag.last_rowid();
+    }
+    if may_replace {
+        // Deleting a conflicting row moves the cursor away from the insertion point.
+        flag = flag.require_seek();
+    }
     program.emit_insn(Insn::Insert {
         cursor: cursor_id,
         key_reg: rowid_reg,
         record_reg: record_register,
-        flag: InsertFlags::new(),
-        table_name: table_name.to_string(),
+        flag,
+        table_name: btree_table.name.clone(),
     });
 
     // Emit update in the CDC table if necessary (after the INSERT updated the table)
@@ -601,6 +828,35 @@ pub fn translate_insert(
         )?;
     }
 
+    if let Some(returning) = &returning {
+        returning.emit_row(
+            &mut program,
+            &resolver,
+            &btree_table,
+            rowid_reg,
+            column_registers_start,
+        )?;
+    }
+
+    if has_insert_triggers {
+        emit_triggers(
+            &mut program,
+            &resolver,
+            &btree_table,
+            TriggerEvent::Insert,
+            TriggerTime::After,
+            &[],
+            None,
+            Some(TriggerRow {
+                rowid_reg,
+                columns_start_reg: column_registers_start,
+            }),
+            row_done_label,
+        )?;
+    }
+
+    program.preassign_label_to_next_insn(row_done_label);
+
     if inserting_multiple_rows {
         if let Some(temp_table_ctx) = temp_table_ctx {
             program.emit_insn(Insn::Next {
@@ -621,11 +877,61 @@ pub fn translate_insert(
     }
 
     program.resolve_label(halt_label, program.offset());
+    if let Some(returning) = &returning {
+        returning.emit_result_rows(&mut program);
+    }
     program.epilogue(super::emitter::TransactionMode::Write);
+    program.result_columns = result_columns;
 
     Ok(program)
 }
 
+/// Emits what happens when the row being inserted violates the uniqueness constraint `target`:
+/// a matching ON CONFLICT clause takes precedence, otherwise the conflict is resolved according
+/// to `or_conflict`. For DO UPDATE and REPLACE, the table cursor must point to the conflicting row.
+#[allow(clippy::too_many_arguments)]
+fn emit_unique_conflict(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    syms: &SymbolTable,
+    resolver: &Resolver,
+    upsert_clauses: &[UpsertClause],
+    target: ConflictTarget,
+    or_conflict: ResolveType,
+    upsert_ctx: &UpsertCtx,
+    err_code: usize,
+    description: String,
+) -> Result<()> {
+    if let Some(clause) = find_upsert_clause(upsert_clauses, &target) {
+        return emit_upsert(program, schema, syms, clause, upsert_ctx);
+    }
+    match or_conflict {
+        ResolveType::Ignore => {
+            program.emit_insn(Insn::Goto {
+                target_pc: upsert_ctx.row_done_label,
+            });
+        }
+        ResolveType::Replace => {
+            emit_replace_delete_row(
+                program,
+                resolver,
+                &Table::BTree(upsert_ctx.table.clone()),
+                upsert_ctx.cursor_id,
+                upsert_ctx.index_cursors,
+                upsert_ctx.cdc_cursor_id,
+            )?;
+        }
+        on_error @ (ResolveType::Abort | ResolveType::Fail | ResolveType::Rollback) => {
+            program.emit_insn(Insn::Halt {
+                err_code,
+                description,
+                on_error,
+            });
+        }
+    }
+    Ok(())
+}
+
 #[derive(Debug)]
 /// Represents how a column should be populated during an INSERT.
 /// Contains both the column definition and optionally the index into the VALUES tuple.
@@ -666,12 +972,14 @@ fn resolve_columns_for_insert<'a>(
         let mut value_idx = 0;
         let mut column_mappings = Vec::with_capacity(table_columns.len());
         for col in table_columns {
+            // Generated columns can't be written to, so they don't take a value either.
+            let takes_value = !col.hidden && col.generated.is_none();
             let mapping = ColumnMapping {
                 column: col,
-                value_index: if col.hidden { None } else { Some(value_idx) },
+                value_index: if takes_value { Some(value_idx) } else { None },
                 default_value: col.default.as_ref(),
             };
-            if !col.hidden {
+            if takes_value {
                 value_idx += 1;
             }
             column_mappings.push(mapping);
@@ -715,6 +1023,9 @@ fn resolve_columns_for_insert<'a>(
                 column_name
             );
         };
+        if table_columns[table_index].generated.is_some() {
+            crate::bail_parse_error!("cannot INSERT into generated column \"{}\"", column_name);
+        }
 
         mappings[table_index].value_index = Some(value_index);
     }
@@ -722,69 +1033,6 @@ fn resolve_columns_for_insert<'a>(
     Ok(mappings)
 }
 
-/// Represents how a column in an index should be populated during an INSERT.
-/// Similar to ColumnMapping above but includes the index name, as well as multiple
-/// possible value indices for each.
-#[derive(Debug, Default)]
-struct IndexColMapping {
-    idx_name: String,
-    columns: Vec<(usize, IndexColumn)>,
-    value_indicies: Vec<Option<usize>>,
-}
-
-impl IndexColMapping {
-    fn new(name: String) -> Self {
-        IndexColMapping {
-            idx_name: name,
-            ..Default::default()
-        }
-    }
-}
-
-/// Example:
-/// Table 'test': (a, b, c);
-/// Index 'idx': test(a, b);
-///________________________________
-/// Insert (a, c): (2, 3)
-/// Record: (2, NULL, 3)
-/// IndexColMapping: (a, b) = (2, NULL)
-fn resolve_indicies_for_insert(
-    schema: &Schema,
-    table: &Table,
-    columns: &[ColumnMapping<'_>],
-) -> Result<Vec<IndexColMapping>> {
-    let mut index_col_mappings = Vec::new();
-    // Iterate over all indices for this table
-    for index in schema.get_indices(table.get_name()) {
-        let mut idx_map = IndexColMapping::new(index.name.clone());
-        // For each column in the index (in the order defined by the index),
-        // try to find the corresponding column in the insert’s column mapping.
-        for idx_col in &index.columns {
-            let target_name = normalize_ident(idx_col.name.as_str());
-            if let Some((i, col_mapping)) = columns.iter().enumerate().find(|(_, mapping)| {
-                mapping
-                    .column
-                    .name
-                    .as_ref()
-                    .is_some_and(|name| name.eq_ignore_ascii_case(&target_name))
-            }) {
-                idx_map.columns.push((i, idx_col.clone()));
-                idx_map.value_indicies.push(col_mapping.value_index);
-            } else {
-                return Err(crate::LimboError::ParseError(format!(
-                    "Column {} not found in index {}",
-                    target_name, index.name
-                )));
-            }
-        }
-        // Add the mapping if at least one column was found.
-        if !idx_map.columns.is_empty() {
-            index_col_mappings.push(idx_map);
-        }
-    }
-    Ok(index_col_mappings)
-}
-
 fn populate_columns_multiple_rows(
     program: &mut ProgramBuilder,
     column_mappings: &[ColumnMapping],
@@ -803,11 +1051,13 @@ fn populate_columns_multiple_rows(
             // Decrement as we have now seen a value index instead
             other_values_seen -= 1;
             if let Some(temp_table_ctx) = temp_table_ctx {
-                program.emit_column(
-                    temp_table_ctx.cursor_id,
-                    value_index_seen,
-                    column_registers_start + i,
-                );
+                // The temp table holds the rows of the SELECT, not records of the table.
+                program.emit_insn(Insn::Column {
+                    cursor_id: temp_table_ctx.cursor_id,
+                    column: value_index_seen,
+                    dest: column_registers_start + i,
+                    default: None,
+                });
             } else {
                 program.emit_insn(Insn::Copy {
                     src_reg: yield_reg + value_index_seen,
@@ -911,24 +1161,77 @@ fn populate_column_registers(
     Ok(())
 }
 
-// TODO: comeback here later to apply the same improvements on select
+/// Translates an INSERT into a virtual table, which hands each row to the module with a
+/// single VUpdate. A single VALUES row is computed in place, while the rows of a multi-row
+/// VALUES clause or of a SELECT are read from a coroutine like for ordinary tables.
 fn translate_virtual_table_insert(
     mut program: ProgramBuilder,
     virtual_table: Rc<VirtualTable>,
     columns: Option<DistinctNames>,
-    mut body: InsertBody,
+    body: InsertBody,
     on_conflict: Option<ResolveType>,
     resolver: &Resolver,
 ) -> Result<ProgramBuilder> {
-    let (num_values, value) = match &mut body {
-        InsertBody::Select(select, None) => match select.body.select.as_mut() {
-            OneSelect::Values(values) => (values[0].len(), values.pop().unwrap()),
-            _ => crate::bail_parse_error!("Virtual tables only support VALUES clause in INSERT"),
+    let table = Table::Virtual(virtual_table.clone());
+    let halt_label = program.allocate_label();
+    let loop_start_label = program.allocate_label();
+    let mut yield_reg_opt = None;
+    let mut value = vec![];
+    let num_values = match body {
+        InsertBody::Select(mut select, None) => match select.body.select.as_mut() {
+            OneSelect::Values(values) if values.len() <= 1 => {
+                let Some(row) = values.pop() else {
+                    crate::bail_parse_error!("no values to insert");
+                };
+                value = row;
+                value.len()
+            }
+            _ => {
+                let yield_reg = program.alloc_register();
+                let jump_on_definition_label = program.allocate_label();
+                let start_offset_label = program.allocate_label();
+                program.emit_insn(Insn::InitCoroutine {
+                    yield_reg,
+                    jump_on_definition: jump_on_definition_label,
+                    start_offset: start_offset_label,
+                });
+                program.preassign_label_to_next_insn(start_offset_label);
+                let query_destination = QueryDestination::CoroutineYield {
+                    yield_reg,
+                    coroutine_implementation_start: halt_label,
+                };
+                program.incr_nesting();
+                let result = translate_select(
+                    resolver.schema,
+                    *select,
+                    resolver.symbol_table,
+                    program,
+                    query_destination,
+                )?;
+                program = result.program;
+                program.decr_nesting();
+                program.emit_insn(Insn::EndCoroutine { yield_reg });
+                program.preassign_label_to_next_insn(jump_on_definition_label);
+                // The module would see the rows inserted so far while the SELECT still scans
+                // the table, which needs the rows to be buffered in a temp table first.
+                if program.is_table_open(&table) {
+                    crate::bail_parse_error!(
+                        "INSERT into a virtual table from a SELECT on the same table is not supported"
+                    );
+                }
+
+                program.preassign_label_to_next_insn(loop_start_label);
+                program.emit_insn(Insn::Yield {
+                    yield_reg,
+                    end_offset: halt_label,
+                });
+                yield_reg_opt = Some(yield_reg);
+                result.num_result_cols
+            }
         },
-        InsertBody::DefaultValues => (0, vec![]),
+        InsertBody::DefaultValues => 0,
         _ => crate::bail_parse_error!("Unsupported INSERT body for virtual tables"),
     };
-    let table = Table::Virtual(virtual_table.clone());
     let column_mappings = resolve_columns_for_insert(&table, &columns, num_values)?;
     let registers_start = program.alloc_registers(2);
 
@@ -945,14 +1248,25 @@ fn translate_virtual_table_insert(
     });
 
     let values_reg = program.alloc_registers(column_mappings.len());
-    populate_column_registers(
-        &mut program,
-        &value,
-        &column_mappings,
-        values_reg,
-        registers_start,
-        resolver,
-    )?;
+    if let Some(yield_reg) = yield_reg_opt {
+        populate_columns_multiple_rows(
+            &mut program,
+            &column_mappings,
+            values_reg,
+            yield_reg + 1,
+            resolver,
+            &None,
+        )?;
+    } else {
+        populate_column_registers(
+            &mut program,
+            &value,
+            &column_mappings,
+            values_reg,
+            registers_start,
+            resolver,
+        )?;
+    }
     let conflict_action = on_conflict.as_ref().map(|c| c.bit_value()).unwrap_or(0) as u16;
 
     let cursor_id = program.alloc_cursor_id(CursorType::VirtualTable(virtual_table.clone()));
@@ -964,7 +1278,11 @@ fn translate_virtual_table_insert(
         conflict_action,
     });
 
-    let halt_label = program.allocate_label();
+    if yield_reg_opt.is_some() {
+        program.emit_insn(Insn::Goto {
+            target_pc: loop_start_label,
+        });
+    }
     program.resolve_label(halt_label, program.offset());
 
     Ok(program)
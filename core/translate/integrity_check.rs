@@ -3,25 +3,47 @@ use crate::{
     vdbe::{builder::ProgramBuilder, insn::Insn},
 };
 
-/// Maximum number of errors to report with integrity check. If we exceed this number we will short
-/// circuit the procedure and return early to not waste time.
-const MAX_INTEGRITY_CHECK_ERRORS: usize = 10;
+/// Maximum number of errors to report with integrity check, unless the pragma is given another
+/// one. If we exceed this number we will short circuit the procedure and return early to not
+/// waste time.
+const MAX_INTEGRITY_CHECK_ERRORS: usize = 100;
 
+/// Translates `PRAGMA integrity_check`, or `PRAGMA quick_check` if `quick` is set, which doesn't
+/// check that each index has as many entries as its table has rows.
 pub fn translate_integrity_check(
     schema: &Schema,
     program: &mut ProgramBuilder,
+    max_errors: Option<usize>,
+    quick: bool,
 ) -> crate::Result<()> {
     let mut root_pages = Vec::with_capacity(schema.tables.len() + schema.indexes.len());
+    let mut index_counts = Vec::new();
+    let mut tables = schema.tables.iter().collect::<Vec<_>>();
+    tables.sort_by(|(a, _), (b, _)| a.cmp(b));
     // Collect root pages to run integrity check on
-    for table in schema.tables.values() {
-        if let crate::schema::Table::BTree(table) = table.as_ref() {
-            root_pages.push(table.root_page);
+    for (_, table) in tables {
+        let crate::schema::Table::BTree(table) = table.as_ref() else {
+            continue;
         };
+        let table_idx = root_pages.len();
+        root_pages.push(table.root_page);
+        for index in schema.get_indices(&table.name) {
+            if index.ephemeral {
+                continue;
+            }
+            // A partial index only has entries for some of the rows
+            if !quick && index.where_clause.is_none() {
+                index_counts.push((index.name.clone(), table_idx, root_pages.len()));
+            }
+            root_pages.push(index.root_page);
+        }
     }
+    root_pages.extend(schema.unloaded_index_root_pages.iter().copied());
     let message_register = program.alloc_register();
     program.emit_insn(Insn::IntegrityCk {
-        max_errors: MAX_INTEGRITY_CHECK_ERRORS,
+        max_errors: max_errors.unwrap_or(MAX_INTEGRITY_CHECK_ERRORS),
         roots: root_pages,
+        index_counts,
         message_register,
     });
     program.emit_insn(Insn::ResultRow {
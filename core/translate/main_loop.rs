@@ -28,9 +28,10 @@ use super::{
     optimizer::Optimizable,
     order_by::{order_by_sorter_insert, sorter_insert},
     plan::{
-        convert_where_to_vtab_constraint, Aggregate, GroupBy, IterationDirection, JoinOrderMember,
+        vtab_constraints, Aggregate, GroupBy, IterationDirection, JoinOrderMember, JoinedTable,
         Operation, QueryDestination, Search, SeekDef, SelectPlan, TableReferences, WhereTerm,
     },
+    window::window_sorter_insert,
 };
 
 // Metadata for handling LEFT JOIN operations
@@ -71,6 +72,7 @@ pub fn init_distinct(program: &mut ProgramBuilder, plan: &SelectPlan) -> Distinc
         name: index_name.clone(),
         table_name: String::new(),
         ephemeral: true,
+        conflict_clause: None,
         root_page: 0,
         columns: plan
             .result_columns
@@ -82,10 +84,12 @@ pub fn init_distinct(program: &mut ProgramBuilder, plan: &SelectPlan) -> Distinc
                 pos_in_table: i,
                 collation: None, // FIXME: this should be determined based on the result column expression!
                 default: None, // FIXME: this should be determined based on the result column expression!
+                expr: None,
             })
             .collect(),
         unique: false,
         has_rowid: false,
+        where_clause: None,
     });
     let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
     let ctx = DistinctCtx {
@@ -124,7 +128,7 @@ pub fn init_loop(
             OperationMode::INSERT | OperationMode::UPDATE | OperationMode::DELETE
         )
     {
-        assert!(tables.joined_tables().len() == 1);
+        assert!(mode == OperationMode::UPDATE || tables.joined_tables().len() == 1);
         let cdc_table_name = cdc_table.unwrap();
         if tables.joined_tables()[0].table.get_name() != cdc_table_name {
             let Some(cdc_table) = t_ctx.resolver.schema.get_table(cdc_table_name) else {
@@ -138,6 +142,7 @@ pub fn init_loop(
                 cursor_id: cdc_cursor_id,
                 root_page: cdc_btree.root_page.into(),
                 name: cdc_btree.name.clone(),
+                db: 0,
             });
             t_ctx.cdc_cursor_id = Some(cdc_cursor_id);
         }
@@ -158,6 +163,7 @@ pub fn init_loop(
             name: index_name.clone(),
             table_name: String::new(),
             ephemeral: true,
+            conflict_clause: None,
             root_page: 0,
             columns: vec![IndexColumn {
                 name: agg.args[0].to_string(),
@@ -165,9 +171,11 @@ pub fn init_loop(
                 pos_in_table: 0,
                 collation: None, // FIXME: this should be inferred from the expression
                 default: None,   // FIXME: this should be inferred from the expression
+                expr: None,
             }],
             has_rowid: false,
             unique: false,
+            where_clause: None,
         });
         let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
         if group_by.is_none() {
@@ -198,6 +206,12 @@ pub fn init_loop(
                 t_ctx.meta_left_joins[table_index] = Some(lj_metadata);
             }
         }
+        // In UPDATE ... FROM, only the target table (always the first one) is written to.
+        let mode = if mode == OperationMode::UPDATE && table_index > 0 {
+            OperationMode::SELECT
+        } else {
+            mode
+        };
         let (table_cursor_id, index_cursor_id) = table.open_cursors(program, mode)?;
         match &table.op {
             Operation::Scan { index, .. } => match (mode, &table.table) {
@@ -207,12 +221,14 @@ pub fn init_loop(
                         program.emit_insn(Insn::OpenRead {
                             cursor_id,
                             root_page,
+                            db: table.database_id,
                         });
                     }
                     if let Some(index_cursor_id) = index_cursor_id {
                         program.emit_insn(Insn::OpenRead {
                             cursor_id: index_cursor_id,
                             root_page: index.as_ref().unwrap().root_page,
+                            db: table.database_id,
                         });
                     }
                 }
@@ -223,16 +239,24 @@ pub fn init_loop(
                             .expect("table cursor is always opened in OperationMode::DELETE"),
                         root_page: root_page.into(),
                         name: btree.name.clone(),
+                        db: table.database_id,
                     });
                     if let Some(index_cursor_id) = index_cursor_id {
                         program.emit_insn(Insn::OpenWrite {
                             cursor_id: index_cursor_id,
                             root_page: index.as_ref().unwrap().root_page.into(),
                             name: index.as_ref().unwrap().name.clone(),
+                            db: table.database_id,
                         });
                     }
                     // For delete, we need to open all the other indexes too for writing
-                    if let Some(indexes) = t_ctx.resolver.schema.indexes.get(&btree.name) {
+                    if let Some(indexes) = t_ctx
+                        .resolver
+                        .schema
+                        .database(table.database_id)
+                        .indexes
+                        .get(&btree.name)
+                    {
                         for index in indexes {
                             if table
                                 .op
@@ -249,6 +273,7 @@ pub fn init_loop(
                                 cursor_id,
                                 root_page: index.root_page.into(),
                                 name: index.name.clone(),
+                                db: table.database_id,
                             });
                         }
                     }
@@ -260,12 +285,14 @@ pub fn init_loop(
                             .expect("table cursor is always opened in OperationMode::UPDATE"),
                         root_page: root_page.into(),
                         name: btree.name.clone(),
+                        db: table.database_id,
                     });
                     if let Some(index_cursor_id) = index_cursor_id {
                         program.emit_insn(Insn::OpenWrite {
                             cursor_id: index_cursor_id,
                             root_page: index.as_ref().unwrap().root_page.into(),
                             name: index.as_ref().unwrap().name.clone(),
+                            db: table.database_id,
                         });
                     }
                 }
@@ -283,6 +310,7 @@ pub fn init_loop(
                             program.emit_insn(Insn::OpenRead {
                                 cursor_id: table_cursor_id,
                                 root_page: table.table.get_root_page(),
+                                db: table.database_id,
                             });
                         }
                     }
@@ -295,13 +323,18 @@ pub fn init_loop(
                             cursor_id: table_cursor_id,
                             root_page: table.table.get_root_page().into(),
                             name: table.table.get_name().to_string(),
+                            db: table.database_id,
                         });
 
                         // For DELETE, we need to open all the indexes for writing
                         // UPDATE opens these in emit_program_for_update() separately
                         if mode == OperationMode::DELETE {
-                            if let Some(indexes) =
-                                t_ctx.resolver.schema.indexes.get(table.table.get_name())
+                            if let Some(indexes) = t_ctx
+                                .resolver
+                                .schema
+                                .database(table.database_id)
+                                .indexes
+                                .get(table.table.get_name())
                             {
                                 for index in indexes {
                                     if table
@@ -319,6 +352,7 @@ pub fn init_loop(
                                         cursor_id,
                                         root_page: index.root_page.into(),
                                         name: index.name.clone(),
+                                        db: table.database_id,
                                     });
                                 }
                             }
@@ -329,6 +363,43 @@ pub fn init_loop(
                     }
                 }
 
+                if let Search::MultiIndexOr { branches, rowids } = search {
+                    assert!(
+                        mode == OperationMode::SELECT,
+                        "Search::MultiIndexOr is only used for reading"
+                    );
+                    let mut opened_indexes: Vec<&Arc<Index>> = Vec::new();
+                    for branch in branches {
+                        let Search::Seek {
+                            index: Some(index), ..
+                        } = branch
+                        else {
+                            continue;
+                        };
+                        if opened_indexes
+                            .iter()
+                            .any(|opened| opened.name == index.name)
+                        {
+                            continue;
+                        }
+                        opened_indexes.push(index);
+                        let cursor_id = program.alloc_cursor_id_keyed(
+                            CursorKey::index(table.internal_id, index.clone()),
+                            CursorType::BTreeIndex(index.clone()),
+                        );
+                        program.emit_insn(Insn::OpenRead {
+                            cursor_id,
+                            root_page: index.root_page,
+                            db: table.database_id,
+                        });
+                    }
+                    // The ephemeral index of rowids is opened anew every time the loop is entered.
+                    program.alloc_cursor_id_keyed(
+                        CursorKey::index(table.internal_id, rowids.clone()),
+                        CursorType::BTreeIndex(rowids.clone()),
+                    );
+                }
+
                 if let Search::Seek {
                     index: Some(index), ..
                 } = search
@@ -341,6 +412,7 @@ pub fn init_loop(
                                     cursor_id: index_cursor_id
                                         .expect("index cursor is always opened in Seek with index"),
                                     root_page: index.root_page,
+                                    db: table.database_id,
                                 });
                             }
                             OperationMode::UPDATE | OperationMode::DELETE => {
@@ -349,6 +421,7 @@ pub fn init_loop(
                                         .expect("index cursor is always opened in Seek with index"),
                                     root_page: index.root_page.into(),
                                     name: index.name.clone(),
+                                    db: table.database_id,
                                 });
                             }
                             _ => {
@@ -361,9 +434,19 @@ pub fn init_loop(
         }
     }
 
+    // Terms that do not read any of the joined tables, e.g. constants or the columns of outer
+    // queries, are evaluated before the loops are opened.
+    let all_tables = tables
+        .joined_tables()
+        .iter()
+        .map(|table| JoinOrderMember {
+            table_id: table.internal_id,
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
     for cond in where_clause
         .iter()
-        .filter(|c| c.should_eval_before_loop(&[JoinOrderMember::default()]))
+        .filter(|c| c.should_eval_before_loop(&all_tables))
     {
         let jump_target = program.allocate_label();
         let meta = ConditionMetadata {
@@ -453,24 +536,16 @@ pub fn open_loop(
                             // xBestIndex decides which ones it wants by setting argvIndex and whether the
                             // core layer may omit them (omit = true).
                             // We then materialise the RHS/LHS into registers before issuing VFilter.
-                            let converted_constraints = predicates
-                                .iter()
-                                .enumerate()
-                                .filter(|(_, p)| p.should_eval_at_loop(join_index, join_order))
-                                .filter_map(|(i, p)| {
-                                    // Build ConstraintInfo from the predicates
-                                    convert_where_to_vtab_constraint(
-                                        p,
-                                        joined_table_index,
-                                        i,
-                                        join_order,
-                                    )
-                                    .unwrap_or(None)
-                                })
-                                .collect::<Vec<_>>();
-                            // TODO: get proper order_by information to pass to the vtab.
-                            // maybe encode more info on t_ctx? we need: [col_idx, is_descending]
-                            let index_info = vtab.best_index(&converted_constraints, &[]);
+                            let converted_constraints = vtab_constraints(
+                                predicates,
+                                join_index,
+                                joined_table_index,
+                                join_order,
+                            );
+                            // The ORDER BY is only offered again if the module consumed it when
+                            // the query was planned.
+                            let index_info =
+                                vtab.best_index(&converted_constraints, &table.vtab_order_by);
 
                             // Determine the number of VFilter arguments (constraints with an argv_index).
                             let args_needed = index_info
@@ -485,7 +560,8 @@ pub fn open_loop(
                                 if let Some(argv_index) = usage.argv_index {
                                     if let Some(cinfo) = converted_constraints.get(i) {
                                         let (pred_idx, is_rhs) = cinfo.unpack_plan_info();
-                                        if let ast::Expr::Binary(lhs, _, rhs) =
+                                        if let ast::Expr::Binary(lhs, _, rhs)
+                                        | ast::Expr::Like { lhs, rhs, .. } =
                                             &predicates[pred_idx].expr
                                         {
                                             // translate the opposite side of the referenced vtab column
@@ -605,7 +681,43 @@ pub fn open_loop(
                 );
                 // Open the loop for the index search.
                 // Rowid equality point lookups are handled with a SeekRowid instruction which does not loop, since it is a single row lookup.
-                if let Search::RowidEq { cmp_expr } = search {
+                if let Search::MultiIndexOr { branches, rowids } = search {
+                    // Collect the rowids found by the search of every branch, and then loop over them,
+                    // looking up each row in the table.
+                    let table_cursor_id =
+                        table_cursor_id.expect("Search::MultiIndexOr requires a table cursor");
+                    let rowids_cursor_id = program
+                        .resolve_cursor_id(&CursorKey::index(table.internal_id, rowids.clone()));
+                    program.emit_insn(Insn::OpenEphemeral {
+                        cursor_id: rowids_cursor_id,
+                        is_table: false,
+                    });
+                    let rowid_reg = program.alloc_register();
+                    for branch in branches {
+                        emit_multi_index_or_branch(
+                            program,
+                            table_references,
+                            table,
+                            branch,
+                            t_ctx,
+                            table_cursor_id,
+                            rowids,
+                            rowids_cursor_id,
+                            rowid_reg,
+                        )?;
+                    }
+                    program.emit_insn(Insn::Rewind {
+                        cursor_id: rowids_cursor_id,
+                        pc_if_empty: loop_end,
+                    });
+                    program.preassign_label_to_next_insn(loop_start);
+                    program.emit_column(rowids_cursor_id, 0, rowid_reg);
+                    program.emit_insn(Insn::SeekRowid {
+                        cursor_id: table_cursor_id,
+                        src_reg: rowid_reg,
+                        target_pc: next,
+                    });
+                } else if let Search::RowidEq { cmp_expr } = search {
                     let src_reg = program.alloc_register();
                     translate_expr(
                         program,
@@ -623,7 +735,8 @@ pub fn open_loop(
                 } else {
                     // Otherwise, it's an index/rowid scan, i.e. first a seek is performed and then a scan until the comparison expression is not satisfied anymore.
                     if let Search::Seek {
-                        index: Some(index), ..
+                        index: Some(index),
+                        seek_def,
                     } = search
                     {
                         if index.ephemeral {
@@ -632,6 +745,16 @@ pub fn open_loop(
                             } else {
                                 false
                             };
+                            // The columns the index is searched by come first.
+                            let columns = index.columns[..seek_def.key.len()]
+                                .iter()
+                                .map(|column| column.name.as_str())
+                                .collect::<Vec<_>>();
+                            program.add_automatic_index(format!(
+                                "{}({})",
+                                index.table_name,
+                                columns.join(", ")
+                            ));
                             Some(emit_autoindex(
                                 program,
                                 index,
@@ -740,11 +863,13 @@ pub fn open_loop(
 /// - a GROUP BY phase with no sorting (when the rows are already in the order required by the GROUP BY keys)
 /// - an ORDER BY sorter (when there is no GROUP BY, but there is an ORDER BY)
 /// - an AggStep (the columns are collected for aggregation, which is finished later)
+/// - a window sorter (the window functions are computed once the rows are sorted by their window)
 /// - a QueryResult (there is none of the above, so the loop either emits a ResultRow, or if it's a subquery, yields to the parent query)
 enum LoopEmitTarget {
     GroupBy,
     OrderBySorter,
     AggStep,
+    Window,
     QueryResult,
 }
 
@@ -760,6 +885,11 @@ pub fn emit_loop(
     if plan.group_by.is_some() {
         return emit_loop_source(program, t_ctx, plan, LoopEmitTarget::GroupBy);
     }
+    // if we have window functions, we emit a record into the window sorter.
+    // the rows are emitted after the window functions are computed.
+    if plan.window.is_some() {
+        return emit_loop_source(program, t_ctx, plan, LoopEmitTarget::Window);
+    }
     // if we DONT have a group by, but we have aggregates, we emit without ResultRow.
     // we also do not need to sort because we are emitting a single row.
     if !plan.aggregates.is_empty() {
@@ -873,8 +1003,10 @@ fn emit_loop_source(
                 plan,
             )?;
 
-            if let Distinctness::Distinct { ctx } = &plan.distinctness {
-                let distinct_ctx = ctx.as_ref().expect("distinct context must exist");
+            if let Distinctness::Distinct {
+                ctx: Some(distinct_ctx),
+            } = &plan.distinctness
+            {
                 program.preassign_label_to_next_insn(distinct_ctx.label_on_conflict);
             }
 
@@ -946,6 +1078,7 @@ fn emit_loop_source(
 
             Ok(())
         }
+        LoopEmitTarget::Window => window_sorter_insert(program, t_ctx, plan),
         LoopEmitTarget::QueryResult => {
             assert!(
                 plan.aggregates.is_empty(),
@@ -1060,21 +1193,29 @@ pub fn close_loop(
                             .expect("Either ephemeral or index or table cursor must be opened")
                     })
                 });
-                // Rowid equality point lookups are handled with a SeekRowid instruction which does not loop, so there is no need to emit a Next instruction.
-                if !matches!(search, Search::RowidEq { .. }) {
-                    let iter_dir = match search {
-                        Search::Seek { seek_def, .. } => seek_def.iter_dir,
-                        Search::RowidEq { .. } => unreachable!(),
-                    };
-
-                    if iter_dir == IterationDirection::Backwards {
-                        program.emit_insn(Insn::Prev {
-                            cursor_id: iteration_cursor_id,
-                            pc_if_prev: loop_labels.loop_start,
-                        });
-                    } else {
+                match search {
+                    // Rowid equality point lookups are handled with a SeekRowid instruction which does not loop, so there is no need to emit a Next instruction.
+                    Search::RowidEq { .. } => {}
+                    Search::Seek { seek_def, .. } => {
+                        if seek_def.iter_dir == IterationDirection::Backwards {
+                            program.emit_insn(Insn::Prev {
+                                cursor_id: iteration_cursor_id,
+                                pc_if_prev: loop_labels.loop_start,
+                            });
+                        } else {
+                            program.emit_insn(Insn::Next {
+                                cursor_id: iteration_cursor_id,
+                                pc_if_next: loop_labels.loop_start,
+                            });
+                        }
+                    }
+                    Search::MultiIndexOr { rowids, .. } => {
+                        let rowids_cursor_id = program.resolve_cursor_id(&CursorKey::index(
+                            table.internal_id,
+                            rowids.clone(),
+                        ));
                         program.emit_insn(Insn::Next {
-                            cursor_id: iteration_cursor_id,
+                            cursor_id: rowids_cursor_id,
                             pc_if_next: loop_labels.loop_start,
                         });
                     }
@@ -1306,6 +1447,23 @@ fn emit_seek_termination(
                 &t_ctx.resolver,
                 NoConstantOptReason::RegisterReuse,
             )?;
+        // if the range is bounded at both ends, the last index key value of the termination key is
+        // the other bound of the range. Like for the seek, a NULL bound matches no rows.
+        } else if let Some(expr) = seek_def.termination_last_key.as_ref().filter(|_| is_last) {
+            translate_expr_no_constant_opt(
+                program,
+                Some(tables),
+                expr,
+                reg,
+                &t_ctx.resolver,
+                NoConstantOptReason::RegisterReuse,
+            )?;
+            if !expr.is_nonnull(tables) {
+                program.emit_insn(Insn::IsNull {
+                    reg,
+                    target_pc: loop_end,
+                });
+            }
         }
     }
     program.preassign_label_to_next_insn(loop_start);
@@ -1398,6 +1556,123 @@ fn emit_seek_termination(
     Ok(())
 }
 
+/// Emits the search of a branch of a [Search::MultiIndexOr], adding the rowid of every row it
+/// finds to the ephemeral index `rowids`, unless it is already there.
+#[allow(clippy::too_many_arguments)]
+fn emit_multi_index_or_branch(
+    program: &mut ProgramBuilder,
+    tables: &TableReferences,
+    table: &JoinedTable,
+    branch: &Search,
+    t_ctx: &mut TranslateCtx,
+    table_cursor_id: CursorID,
+    rowids: &Arc<Index>,
+    rowids_cursor_id: CursorID,
+    rowid_reg: usize,
+) -> Result<()> {
+    let branch_end = program.allocate_label();
+    let emit_rowid_insert = |program: &mut ProgramBuilder| {
+        let label_duplicate = program.allocate_label();
+        program.emit_insn(Insn::Found {
+            cursor_id: rowids_cursor_id,
+            target_pc: label_duplicate,
+            record_reg: rowid_reg,
+            num_regs: 1,
+        });
+        let record_reg = program.alloc_register();
+        program.emit_insn(Insn::MakeRecord {
+            start_reg: rowid_reg,
+            count: 1,
+            dest_reg: record_reg,
+            index_name: Some(rowids.name.clone()),
+        });
+        program.emit_insn(Insn::IdxInsert {
+            cursor_id: rowids_cursor_id,
+            record_reg,
+            unpacked_start: Some(rowid_reg),
+            unpacked_count: Some(1),
+            flags: IdxInsertFlags::new(),
+        });
+        program.preassign_label_to_next_insn(label_duplicate);
+    };
+    match branch {
+        Search::RowidEq { cmp_expr } => {
+            translate_expr_no_constant_opt(
+                program,
+                Some(tables),
+                cmp_expr,
+                rowid_reg,
+                &t_ctx.resolver,
+                NoConstantOptReason::RegisterReuse,
+            )?;
+            program.emit_insn(Insn::SeekRowid {
+                cursor_id: table_cursor_id,
+                src_reg: rowid_reg,
+                target_pc: branch_end,
+            });
+            // Read the rowid back from the table, since the compared value may not be an integer.
+            program.emit_insn(Insn::RowId {
+                cursor_id: table_cursor_id,
+                dest: rowid_reg,
+            });
+            emit_rowid_insert(program);
+        }
+        Search::Seek { index, seek_def } => {
+            let seek_cursor_id = match index {
+                Some(index) => {
+                    program.resolve_cursor_id(&CursorKey::index(table.internal_id, index.clone()))
+                }
+                None => table_cursor_id,
+            };
+            let is_index = index.is_some();
+            let branch_loop_start = program.allocate_label();
+            let start_reg = program.alloc_registers(seek_def.key.len());
+            emit_seek(
+                program,
+                tables,
+                seek_def,
+                t_ctx,
+                seek_cursor_id,
+                start_reg,
+                branch_end,
+                is_index,
+            )?;
+            emit_seek_termination(
+                program,
+                tables,
+                seek_def,
+                t_ctx,
+                seek_cursor_id,
+                start_reg,
+                branch_loop_start,
+                branch_end,
+                is_index,
+            )?;
+            if is_index {
+                program.emit_insn(Insn::IdxRowId {
+                    cursor_id: seek_cursor_id,
+                    dest: rowid_reg,
+                });
+            } else {
+                program.emit_insn(Insn::RowId {
+                    cursor_id: seek_cursor_id,
+                    dest: rowid_reg,
+                });
+            }
+            emit_rowid_insert(program);
+            program.emit_insn(Insn::Next {
+                cursor_id: seek_cursor_id,
+                pc_if_next: branch_loop_start,
+            });
+        }
+        Search::MultiIndexOr { .. } => {
+            unreachable!("the branches of Search::MultiIndexOr are plain searches")
+        }
+    }
+    program.preassign_label_to_next_insn(branch_end);
+    Ok(())
+}
+
 /// Open an ephemeral index cursor and build an automatic index on a table.
 /// This is used as a last-resort to avoid a nested full table scan
 /// Returns the cursor id of the ephemeral index cursor.
@@ -9,11 +9,14 @@
 
 pub(crate) mod aggregation;
 pub(crate) mod alter;
+pub(crate) mod analyze;
+pub(crate) mod attach;
 pub(crate) mod collate;
 mod compound_select;
 pub(crate) mod delete;
 pub(crate) mod display;
 pub(crate) mod emitter;
+pub(crate) mod explain;
 pub(crate) mod expr;
 pub(crate) mod group_by;
 pub(crate) mod index;
@@ -26,21 +29,36 @@ pub(crate) mod plan;
 pub(crate) mod planner;
 pub(crate) mod pragma;
 pub(crate) mod result_row;
+pub(crate) mod returning;
 pub(crate) mod rollback;
 pub(crate) mod schema;
 pub(crate) mod select;
 pub(crate) mod subquery;
 pub(crate) mod transaction;
+pub(crate) mod trigger;
 pub(crate) mod update;
+pub(crate) mod upsert;
+pub(crate) mod vacuum;
 mod values;
+pub(crate) mod view;
+pub(crate) mod window;
 
 use crate::schema::Schema;
 use crate::storage::pager::Pager;
+use crate::translate::collate::CollationsGuard;
 use crate::translate::delete::translate_delete;
+use crate::translate::emitter::TransactionMode;
+use crate::translate::expr::{
+    is_double_quoted_identifier, sanitize_double_quoted_string, sanitize_string,
+};
+use crate::translate::optimizer::like_prefix::LikeBehavior;
+use crate::util::normalize_ident;
 use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts, QueryMode};
 use crate::vdbe::Program;
-use crate::{bail_parse_error, Connection, Result, SymbolTable};
+use crate::{bail_parse_error, AuthAction, Connection, LimboError, Limit, Result, SymbolTable};
 use alter::translate_alter_table;
+use analyze::translate_analyze;
+use attach::{creates_temp_table, ensure_main_database, translate_attach, translate_detach};
 use index::{translate_create_index, translate_drop_index};
 use insert::translate_insert;
 use rollback::translate_rollback;
@@ -49,9 +67,14 @@ use select::translate_select;
 use std::rc::Rc;
 use std::sync::Arc;
 use tracing::{instrument, Level};
-use transaction::{translate_tx_begin, translate_tx_commit};
-use turso_sqlite3_parser::ast::{self, Delete, Insert};
+use transaction::{
+    translate_release, translate_savepoint, translate_tx_begin, translate_tx_commit,
+};
+use trigger::{translate_create_trigger, translate_drop_trigger};
+use turso_sqlite3_parser::ast::{self, fmt::ToTokens, Delete, Insert};
 use update::translate_update;
+use vacuum::translate_vacuum;
+use view::{translate_create_view, translate_drop_view};
 
 #[instrument(skip_all, level = Level::DEBUG)]
 #[allow(clippy::too_many_arguments)]
@@ -62,20 +85,35 @@ pub fn translate(
     connection: Arc<Connection>,
     syms: &SymbolTable,
     query_mode: QueryMode,
-    _input: &str, // TODO: going to be used for CREATE VIEW
+    input: &str,
 ) -> Result<Program> {
-    tracing::trace!("querying {}", _input);
-    let change_cnt_on = matches!(
-        stmt,
-        ast::Stmt::CreateIndex { .. }
-            | ast::Stmt::Delete(..)
-            | ast::Stmt::Insert(..)
-            | ast::Stmt::Update(..)
-    );
+    tracing::trace!("querying {}", input);
+    let _collations = CollationsGuard::new(connection.get_collations());
+    let limits = connection.get_limits();
+    if input.len() > limits.get(Limit::SqlLength) {
+        return Err(LimboError::TooBig("statement too long".to_string()));
+    }
+    // Like SQLCipher, nothing but the key can be used before the key of an encrypted database is
+    // given.
+    if !matches!(&stmt, ast::Stmt::Pragma(name, _) if pragma::is_key_pragma(name)) {
+        connection.check_encryption_key()?;
+    }
+    let change_cnt_on = query_mode != QueryMode::ExplainQueryPlan
+        && matches!(
+            stmt,
+            ast::Stmt::Delete(..) | ast::Stmt::Insert(..) | ast::Stmt::Update(..)
+        );
 
     let mut program = ProgramBuilder::new(
         query_mode,
         connection.get_capture_data_changes().clone(),
+        LikeBehavior {
+            case_sensitive_like: connection.get_case_sensitive_like(),
+            like_overridden: syms.resolve_function("like", 2).is_some(),
+            glob_overridden: syms.resolve_function("glob", 2).is_some(),
+        },
+        connection.get_authorizer(),
+        limits,
         // These options will be extended whithin each translate program
         ProgramBuilderOpts {
             num_cursors: 1,
@@ -86,21 +124,65 @@ pub fn translate(
 
     program.prologue();
 
+    // Parameters are numbered by where they appear in the statement, not by where they are
+    // translated, so they are collected up front.
+    let Ok(()) = stmt.to_tokens(&mut program.parameters);
+
+    // The temp database is only created once a statement needs it.
+    let temp_schema;
+    let schema = if query_mode != QueryMode::ExplainQueryPlan
+        && creates_temp_table(&stmt)
+        && connection.ensure_temp_database()?
+    {
+        temp_schema = connection.translation_schema()?;
+        &temp_schema
+    } else {
+        schema
+    };
+
     program = match stmt {
+        stmt if query_mode == QueryMode::ExplainQueryPlan => {
+            explain::translate_explain_query_plan(schema, stmt, syms, program)?
+        }
         // There can be no nesting with pragma, so lift it up here
-        ast::Stmt::Pragma(name, body) => pragma::translate_pragma(
-            schema,
-            &name,
-            body.map(|b| *b),
-            pager,
-            connection.clone(),
-            program,
-        )?,
+        ast::Stmt::Pragma(name, body) => {
+            let arg = body.as_deref().map(|body| match body {
+                ast::PragmaBody::Equals(value) | ast::PragmaBody::Call(value) => expr_text(value),
+            });
+            let pragma_name = normalize_ident(&name.name.0);
+            let database = name.db_name.as_ref().map(|db| normalize_ident(&db.0));
+            if program.authorize(
+                AuthAction::Pragma {
+                    name: &pragma_name,
+                    arg: arg.as_deref(),
+                },
+                database.as_deref(),
+            )? {
+                program.epilogue(TransactionMode::None);
+                program
+            } else {
+                pragma::translate_pragma(
+                    schema,
+                    &name,
+                    body.map(|b| *b),
+                    pager,
+                    connection.clone(),
+                    program,
+                )?
+            }
+        }
         stmt => translate_inner(schema, stmt, syms, program)?,
     };
 
     // TODO: bring epilogue here when I can sort out what instructions correspond to a Write or a Read transaction
 
+    if program.insn_count() > program.limit(Limit::VdbeOp) {
+        return Err(LimboError::TooBig("too many instructions".to_string()));
+    }
+    for index in program.automatic_indexes() {
+        connection.advise_index(index);
+    }
+
     Ok(program.build(connection, change_cnt_on))
 }
 
@@ -111,13 +193,32 @@ pub fn translate_inner(
     schema: &Schema,
     stmt: ast::Stmt,
     syms: &SymbolTable,
-    program: ProgramBuilder,
+    mut program: ProgramBuilder,
 ) -> Result<ProgramBuilder> {
+    if authorize_statement(schema, &stmt, &program)? {
+        // Like in SQLite, a statement the authorizer ignores does nothing.
+        program.epilogue(TransactionMode::None);
+        return Ok(program);
+    }
     let program = match stmt {
-        ast::Stmt::AlterTable(alter) => translate_alter_table(*alter, syms, schema, program)?,
-        ast::Stmt::Analyze(_) => bail_parse_error!("ANALYZE not supported yet"),
-        ast::Stmt::Attach { .. } => bail_parse_error!("ATTACH not supported yet"),
-        ast::Stmt::Begin(tx_type, tx_name) => translate_tx_begin(tx_type, tx_name, program)?,
+        ast::Stmt::AlterTable(alter) => {
+            ensure_main_database(schema, &alter.0, "ALTER TABLE")?;
+            if schema.object_database(&alter.0)? != 0 {
+                bail_parse_error!("ALTER TABLE of TEMP tables is not supported");
+            }
+            // The statements rewriting the schema and the rows are not authorized themselves.
+            let authorizer = program.take_authorizer();
+            let mut program = translate_alter_table(*alter, syms, schema, program)?;
+            program.set_authorizer(authorizer);
+            program
+        }
+        ast::Stmt::Analyze(target) => translate_analyze(target, schema, program)?,
+        ast::Stmt::Attach { expr, db_name, key } => {
+            translate_attach(&expr, &db_name, key.as_deref(), schema, syms, program)?
+        }
+        ast::Stmt::Begin(tx_type, tx_name) => {
+            translate_tx_begin(tx_type, tx_name, schema, program)?
+        }
         ast::Stmt::Commit(tx_name) => translate_tx_commit(tx_name, program)?,
         ast::Stmt::CreateIndex {
             unique,
@@ -125,13 +226,15 @@ pub fn translate_inner(
             idx_name,
             tbl_name,
             columns,
-            ..
+            where_clause,
         } => translate_create_index(
             (unique, if_not_exists),
-            &idx_name.name.0,
+            &idx_name,
             &tbl_name.0,
             &columns,
+            where_clause.as_deref(),
             schema,
+            syms,
             program,
         )?,
         ast::Stmt::CreateTable {
@@ -140,9 +243,31 @@ pub fn translate_inner(
             tbl_name,
             body,
         } => translate_create_table(tbl_name, temporary, *body, if_not_exists, schema, program)?,
-        ast::Stmt::CreateTrigger { .. } => bail_parse_error!("CREATE TRIGGER not supported yet"),
-        ast::Stmt::CreateView { .. } => bail_parse_error!("CREATE VIEW not supported yet"),
+        ast::Stmt::CreateTrigger(create_trigger) => {
+            ensure_main_database(schema, &create_trigger.trigger_name, "CREATE TRIGGER")?;
+            translate_create_trigger(*create_trigger, schema, program)?
+        }
+        ast::Stmt::CreateView {
+            temporary,
+            if_not_exists,
+            view_name,
+            columns,
+            select,
+        } => {
+            ensure_main_database(schema, &view_name, "CREATE VIEW")?;
+            translate_create_view(
+                temporary,
+                if_not_exists,
+                view_name,
+                columns,
+                *select,
+                schema,
+                syms,
+                program,
+            )?
+        }
         ast::Stmt::CreateVirtualTable(vtab) => {
+            ensure_main_database(schema, &vtab.tbl_name, "CREATE VIRTUAL TABLE")?;
             translate_create_virtual_table(*vtab, schema, syms, program)?
         }
         ast::Stmt::Delete(delete) => {
@@ -150,31 +275,55 @@ pub fn translate_inner(
                 tbl_name,
                 where_clause,
                 limit,
+                returning,
                 ..
             } = *delete;
-            translate_delete(schema, &tbl_name, where_clause, limit, syms, program)?
+            translate_delete(
+                schema,
+                &tbl_name,
+                where_clause,
+                limit,
+                returning,
+                syms,
+                program,
+            )?
         }
-        ast::Stmt::Detach(_) => bail_parse_error!("DETACH not supported yet"),
+        ast::Stmt::Detach(name) => translate_detach(&name, schema, syms, program)?,
         ast::Stmt::DropIndex {
             if_exists,
             idx_name,
-        } => translate_drop_index(&idx_name.name.0, if_exists, schema, program)?,
+        } => {
+            ensure_main_database(schema, &idx_name, "DROP INDEX")?;
+            translate_drop_index(&idx_name.name.0, if_exists, schema, program)?
+        }
         ast::Stmt::DropTable {
             if_exists,
             tbl_name,
         } => translate_drop_table(tbl_name, if_exists, schema, program)?,
-        ast::Stmt::DropTrigger { .. } => bail_parse_error!("DROP TRIGGER not supported yet"),
-        ast::Stmt::DropView { .. } => bail_parse_error!("DROP VIEW not supported yet"),
+        ast::Stmt::DropTrigger {
+            if_exists,
+            trigger_name,
+        } => {
+            ensure_main_database(schema, &trigger_name, "DROP TRIGGER")?;
+            translate_drop_trigger(&trigger_name, if_exists, schema, program)?
+        }
+        ast::Stmt::DropView {
+            if_exists,
+            view_name,
+        } => {
+            ensure_main_database(schema, &view_name, "DROP VIEW")?;
+            translate_drop_view(&view_name, if_exists, schema, program)?
+        }
         ast::Stmt::Pragma(..) => {
             bail_parse_error!("PRAGMA statement cannot be evaluated in a nested context")
         }
         ast::Stmt::Reindex { .. } => bail_parse_error!("REINDEX not supported yet"),
-        ast::Stmt::Release(_) => bail_parse_error!("RELEASE not supported yet"),
+        ast::Stmt::Release(name) => translate_release(name, program)?,
         ast::Stmt::Rollback {
             tx_name,
             savepoint_name,
         } => translate_rollback(schema, syms, program, tx_name, savepoint_name)?,
-        ast::Stmt::Savepoint(_) => bail_parse_error!("SAVEPOINT not supported yet"),
+        ast::Stmt::Savepoint(name) => translate_savepoint(name, program)?,
         ast::Stmt::Select(select) => {
             translate_select(
                 schema,
@@ -186,7 +335,9 @@ pub fn translate_inner(
             .program
         }
         ast::Stmt::Update(mut update) => translate_update(schema, &mut update, syms, program)?,
-        ast::Stmt::Vacuum(_, _) => bail_parse_error!("VACUUM not supported yet"),
+        ast::Stmt::Vacuum(schema_name, into) => {
+            translate_vacuum(schema_name, into, schema, syms, program)?
+        }
         ast::Stmt::Insert(insert) => {
             let Insert {
                 with,
@@ -212,3 +363,236 @@ pub fn translate_inner(
 
     Ok(program)
 }
+
+/// Asks the authorizer about a statement before it is translated, returning whether it is to be
+/// ignored. The tables and columns it reads and changes are authorized as they are translated.
+fn authorize_statement(
+    schema: &Schema,
+    stmt: &ast::Stmt,
+    program: &ProgramBuilder,
+) -> Result<bool> {
+    // The database an object is created in.
+    fn create_database<'a>(
+        schema: &'a Schema,
+        temporary: bool,
+        name: &ast::QualifiedName,
+    ) -> Result<&'a str> {
+        Ok(match &name.db_name {
+            _ if temporary => "temp",
+            Some(db_name) => schema.database_name(schema.database_index(&db_name.0)?),
+            None => "main",
+        })
+    }
+    // The database an existing table or view is found in.
+    fn object_database<'a>(schema: &'a Schema, name: &ast::QualifiedName) -> Result<&'a str> {
+        Ok(schema.database_name(schema.object_database(name)?))
+    }
+    let name = |name: &ast::QualifiedName| normalize_ident(&name.name.0);
+    match stmt {
+        ast::Stmt::AlterTable(alter) => program.authorize(
+            AuthAction::AlterTable {
+                table: &name(&alter.0),
+            },
+            Some(object_database(schema, &alter.0)?),
+        ),
+        ast::Stmt::Analyze(target) => {
+            let target = target.as_ref().map(name);
+            program.authorize(
+                AuthAction::Analyze {
+                    target: target.as_deref(),
+                },
+                None,
+            )
+        }
+        ast::Stmt::Attach { expr, .. } => program.authorize(
+            AuthAction::Attach {
+                file: &expr_text(expr),
+            },
+            None,
+        ),
+        ast::Stmt::Detach(database) => program.authorize(
+            AuthAction::Detach {
+                database: &expr_text(database),
+            },
+            None,
+        ),
+        ast::Stmt::Begin(..) => {
+            program.authorize(AuthAction::Transaction { operation: "BEGIN" }, None)
+        }
+        ast::Stmt::Commit(..) => program.authorize(
+            AuthAction::Transaction {
+                operation: "COMMIT",
+            },
+            None,
+        ),
+        ast::Stmt::Rollback {
+            savepoint_name: None,
+            ..
+        } => program.authorize(
+            AuthAction::Transaction {
+                operation: "ROLLBACK",
+            },
+            None,
+        ),
+        ast::Stmt::Rollback {
+            savepoint_name: Some(savepoint),
+            ..
+        } => program.authorize(
+            AuthAction::Savepoint {
+                operation: "ROLLBACK",
+                name: &normalize_ident(&savepoint.0),
+            },
+            None,
+        ),
+        ast::Stmt::Savepoint(savepoint) => program.authorize(
+            AuthAction::Savepoint {
+                operation: "BEGIN",
+                name: &normalize_ident(&savepoint.0),
+            },
+            None,
+        ),
+        ast::Stmt::Release(savepoint) => program.authorize(
+            AuthAction::Savepoint {
+                operation: "RELEASE",
+                name: &normalize_ident(&savepoint.0),
+            },
+            None,
+        ),
+        ast::Stmt::CreateIndex {
+            idx_name, tbl_name, ..
+        } => {
+            let database = match &idx_name.db_name {
+                Some(_) => create_database(schema, false, idx_name)?,
+                None => object_database(schema, &ast::QualifiedName::single(tbl_name.clone()))?,
+            };
+            program.authorize(
+                AuthAction::CreateIndex {
+                    index: &name(idx_name),
+                    table: &normalize_ident(&tbl_name.0),
+                },
+                Some(database),
+            )
+        }
+        ast::Stmt::CreateTable {
+            temporary,
+            tbl_name,
+            ..
+        } => program.authorize(
+            AuthAction::CreateTable {
+                table: &name(tbl_name),
+            },
+            Some(create_database(schema, *temporary, tbl_name)?),
+        ),
+        ast::Stmt::CreateTrigger(trigger) => program.authorize(
+            AuthAction::CreateTrigger {
+                trigger: &name(&trigger.trigger_name),
+                table: &name(&trigger.tbl_name),
+            },
+            Some(create_database(
+                schema,
+                trigger.temporary,
+                &trigger.trigger_name,
+            )?),
+        ),
+        ast::Stmt::CreateView {
+            temporary,
+            view_name,
+            ..
+        } => program.authorize(
+            AuthAction::CreateView {
+                view: &name(view_name),
+            },
+            Some(create_database(schema, *temporary, view_name)?),
+        ),
+        ast::Stmt::CreateVirtualTable(vtab) => program.authorize(
+            AuthAction::CreateVTable {
+                table: &name(&vtab.tbl_name),
+                module: &normalize_ident(&vtab.module_name.0),
+            },
+            Some(create_database(schema, false, &vtab.tbl_name)?),
+        ),
+        // Dropping an object that doesn't exist fails, or does nothing with IF EXISTS, when
+        // the statement is translated.
+        ast::Stmt::DropIndex { idx_name, .. } => {
+            let index_name = name(idx_name);
+            let Some(index) = schema
+                .indexes
+                .values()
+                .flatten()
+                .find(|index| index.name == index_name)
+            else {
+                return Ok(false);
+            };
+            program.authorize(
+                AuthAction::DropIndex {
+                    index: &index_name,
+                    table: &index.table_name,
+                },
+                Some("main"),
+            )
+        }
+        ast::Stmt::DropTable { tbl_name, .. } => {
+            let database_id = schema.object_database(tbl_name)?;
+            let table_name = name(tbl_name);
+            let action = match schema.database(database_id).get_table(&table_name) {
+                Some(table) if table.virtual_table().is_some() => {
+                    AuthAction::DropVTable { table: &table_name }
+                }
+                Some(_) => AuthAction::DropTable { table: &table_name },
+                None => return Ok(false),
+            };
+            program.authorize(action, Some(schema.database_name(database_id)))
+        }
+        ast::Stmt::DropTrigger { trigger_name, .. } => {
+            let Some(trigger) = schema.get_trigger(&name(trigger_name)) else {
+                return Ok(false);
+            };
+            program.authorize(
+                AuthAction::DropTrigger {
+                    trigger: &trigger.name,
+                    table: &trigger.table_name,
+                },
+                Some("main"),
+            )
+        }
+        ast::Stmt::DropView { view_name, .. } => {
+            if schema.get_view(&name(view_name)).is_none() {
+                return Ok(false);
+            }
+            program.authorize(
+                AuthAction::DropView {
+                    view: &name(view_name),
+                },
+                Some("main"),
+            )
+        }
+        ast::Stmt::Select(..) => program.authorize(AuthAction::Select, None),
+        // INSERT, UPDATE and DELETE are authorized as they are translated, along with the
+        // ones of the programs of triggers.
+        ast::Stmt::Delete(..)
+        | ast::Stmt::Insert(..)
+        | ast::Stmt::Update(..)
+        | ast::Stmt::Pragma(..)
+        | ast::Stmt::Reindex { .. }
+        | ast::Stmt::Vacuum(..) => Ok(false),
+    }
+}
+
+/// The text of an expression naming something: a bare identifier stands for its name, and a
+/// string for its value.
+fn expr_text(expr: &ast::Expr) -> String {
+    match expr {
+        ast::Expr::Id(id) => identifier_text(&id.0),
+        ast::Expr::Name(name) => identifier_text(&name.0),
+        ast::Expr::Literal(ast::Literal::String(value)) => sanitize_string(value),
+        expr => expr.to_string(),
+    }
+}
+
+fn identifier_text(name: &str) -> String {
+    if is_double_quoted_identifier(name) {
+        sanitize_double_quoted_string(name)
+    } else {
+        name.to_string()
+    }
+}
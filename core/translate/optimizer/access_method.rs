@@ -1,17 +1,20 @@
 use std::sync::Arc;
 
-use turso_sqlite3_parser::ast::SortOrder;
-
 use crate::{
     schema::Index,
-    translate::plan::{IterationDirection, JoinOrderMember, JoinedTable},
+    translate::{
+        plan::{IterationDirection, JoinOrderMember, JoinedTable},
+        planner::TableMask,
+    },
     Result,
 };
 
 use super::{
-    constraints::{usable_constraints_for_join_order, ConstraintRef, TableConstraints},
-    cost::{estimate_cost_for_scan_or_seek, Cost, IndexInfo},
-    order::OrderTarget,
+    constraints::{
+        usable_constraints_for_join_order, ConstraintRef, OrConstraint, TableConstraints,
+    },
+    cost::{estimate_cost_for_multi_index_or, estimate_cost_for_scan_or_seek, Cost, IndexInfo},
+    order::{AccessOrder, EliminatesSortBy, OrderTarget},
 };
 
 #[derive(Debug, Clone)]
@@ -29,19 +32,43 @@ pub struct AccessMethod<'a> {
     /// An empty list of constraint refs means a scan (full table or index);
     /// a non-empty list means a search.
     pub constraint_refs: &'a [ConstraintRef],
+    /// The OR term whose branches are searched, if the table is read through the union of
+    /// the rowids found by a search per branch.
+    pub multi_index_or: Option<MultiIndexOr<'a>>,
+}
+
+/// Reading a table through the union of the rowids found by a search per branch of an
+/// [OrConstraint].
+#[derive(Debug, Clone)]
+pub struct MultiIndexOr<'a> {
+    pub or_constraint: &'a OrConstraint,
+    /// The index searched for each branch, or None for a search of the table by rowid.
+    pub indexes: Vec<Option<Arc<Index>>>,
 }
 
 impl AccessMethod<'_> {
     pub fn is_scan(&self) -> bool {
-        self.constraint_refs.is_empty()
+        self.constraint_refs.is_empty() && self.multi_index_or.is_none()
     }
 
-    pub fn new_table_scan(input_cardinality: f64, iter_dir: IterationDirection) -> Self {
+    pub fn new_table_scan(
+        input_cardinality: f64,
+        row_count: f64,
+        iter_dir: IterationDirection,
+    ) -> Self {
         Self {
-            cost: estimate_cost_for_scan_or_seek(None, &[], &[], input_cardinality),
+            cost: estimate_cost_for_scan_or_seek(
+                None,
+                &[],
+                &[],
+                input_cardinality,
+                row_count,
+                None,
+            ),
             iter_dir,
             index: None,
             constraint_refs: &[],
+            multi_index_or: None,
         }
     }
 }
@@ -55,8 +82,11 @@ pub fn find_best_access_method_for_join_order<'a>(
     input_cardinality: f64,
 ) -> Result<AccessMethod<'a>> {
     let table_no = join_order.last().unwrap().table_id;
-    let mut best_access_method =
-        AccessMethod::new_table_scan(input_cardinality, IterationDirection::Forwards);
+    let mut best_access_method = AccessMethod::new_table_scan(
+        input_cardinality,
+        rhs_constraints.row_count,
+        IterationDirection::Forwards,
+    );
     let rowid_column_idx = rhs_table.columns().iter().position(|c| c.is_rowid_alias);
 
     // Estimate cost for each candidate index (including the rowid index) and replace best_access_method if the cost is lower.
@@ -78,11 +108,13 @@ pub fn find_best_access_method_for_join_order<'a>(
             &candidate.refs,
             join_order,
         );
-        let cost = estimate_cost_for_scan_or_seek(
+        let mut cost = estimate_cost_for_scan_or_seek(
             Some(index_info),
             &rhs_constraints.constraints,
             usable_constraint_refs,
             input_cardinality,
+            rhs_constraints.row_count,
+            candidate.rows_per_key.as_deref(),
         );
 
         // All other things being equal, prefer an access method that satisfies the order target.
@@ -90,47 +122,46 @@ pub fn find_best_access_method_for_join_order<'a>(
         {
             // If the index delivers rows in the same direction (or the exact reverse direction) as the order target, then it
             // satisfies the order target.
-            let mut all_same_direction = true;
-            let mut all_opposite_direction = true;
-            for i in 0..order_target.0.len().min(index_info.column_count) {
-                let correct_table = order_target.0[i].table_id == table_no;
-                let correct_column = {
-                    match &candidate.index {
-                        Some(index) => index.columns[i].pos_in_table == order_target.0[i].column_no,
-                        None => {
-                            rowid_column_idx.is_some_and(|idx| idx == order_target.0[i].column_no)
-                        }
-                    }
+            let access_order = AccessOrder::new(
+                rhs_table,
+                rhs_constraints,
+                candidate.index.as_deref(),
+                usable_constraint_refs,
+            );
+            if order_target.1 == EliminatesSortBy::Group {
+                // The rows of a group may be read in either direction.
+                let mut target_cols = order_target.0.iter().collect::<Vec<_>>();
+                let bonus = if access_order.consume_group_target(table_no, &mut target_cols)
+                    && target_cols.len() < order_target.0.len()
+                {
+                    Cost(1.0)
+                } else {
+                    Cost(0.0)
                 };
-                if !correct_table || !correct_column {
-                    all_same_direction = false;
-                    all_opposite_direction = false;
-                    break;
-                }
-                let correct_order = {
-                    match &candidate.index {
-                        Some(index) => order_target.0[i].order == index.columns[i].order,
-                        None => order_target.0[i].order == SortOrder::Asc,
+                (IterationDirection::Forwards, bonus)
+            } else {
+                let mut target_cols = order_target.0.iter().peekable();
+                match access_order.consume_order_target(table_no, &mut target_cols) {
+                    Some(iter_dir) if target_cols.len() < order_target.0.len() => {
+                        // With a LIMIT, reading the outermost table in order stops after the
+                        // first rows, instead of reading all of them to sort them.
+                        if let (Some(row_limit), None, 1) =
+                            (order_target.2, target_cols.peek(), join_order.len())
+                        {
+                            let estimated_rows = rhs_constraints.row_count
+                                * rhs_constraints
+                                    .constraints
+                                    .iter()
+                                    .filter(|constraint| constraint.lhs_mask.is_empty())
+                                    .map(|constraint| constraint.selectivity)
+                                    .product::<f64>();
+                            cost = Cost(*cost * (row_limit as f64 / estimated_rows).min(1.0));
+                        }
+                        (iter_dir.unwrap_or(IterationDirection::Forwards), Cost(1.0))
                     }
-                };
-                if correct_order {
-                    all_opposite_direction = false;
-                } else {
-                    all_same_direction = false;
+                    _ => (IterationDirection::Forwards, Cost(0.0)),
                 }
             }
-            if all_same_direction || all_opposite_direction {
-                (
-                    if all_same_direction {
-                        IterationDirection::Forwards
-                    } else {
-                        IterationDirection::Backwards
-                    },
-                    Cost(1.0),
-                )
-            } else {
-                (IterationDirection::Forwards, Cost(0.0))
-            }
         } else {
             (IterationDirection::Forwards, Cost(0.0))
         };
@@ -140,6 +171,64 @@ pub fn find_best_access_method_for_join_order<'a>(
                 index: candidate.index.clone(),
                 iter_dir,
                 constraint_refs: usable_constraint_refs,
+                multi_index_or: None,
+            };
+        }
+    }
+
+    // An OR term can't be used by a single search, but the table may still be cheaper to read
+    // through the union of the rowids found by searching an index for each of its branches.
+    let lhs_mask = TableMask::from_table_number_iter(
+        join_order
+            .iter()
+            .take(join_order.len() - 1)
+            .map(|j| j.original_idx),
+    );
+    for or_constraint in rhs_constraints.or_constraints.iter() {
+        if !lhs_mask.contains_all(&or_constraint.lhs_mask) {
+            continue;
+        }
+        let Some(indexes) = or_constraint
+            .branches
+            .iter()
+            .map(|branch| {
+                if rowid_column_idx == Some(branch.table_col_pos) {
+                    return Some(None);
+                }
+                rhs_constraints
+                    .candidates
+                    .iter()
+                    .filter_map(|candidate| candidate.index.as_ref())
+                    .find(|index| {
+                        index.columns[0].pos_in_table == branch.table_col_pos
+                            && index.columns[0].collation.unwrap_or_default() == branch.collation
+                    })
+                    .map(|index| Some(index.clone()))
+            })
+            .collect::<Option<Vec<_>>>()
+        else {
+            continue;
+        };
+        let cost = estimate_cost_for_multi_index_or(
+            or_constraint
+                .branches
+                .iter()
+                .zip(indexes.iter())
+                .map(|(branch, index)| (branch.selectivity, index.is_some())),
+            input_cardinality,
+            rhs_constraints.row_count,
+        );
+        if cost < best_access_method.cost {
+            best_access_method = AccessMethod {
+                cost,
+                index: None,
+                // The rowids are looped over in ascending order.
+                iter_dir: IterationDirection::Forwards,
+                constraint_refs: &[],
+                multi_index_or: Some(MultiIndexOr {
+                    or_constraint,
+                    indexes,
+                }),
             };
         }
     }
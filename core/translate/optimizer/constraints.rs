@@ -1,17 +1,19 @@
 use std::{cmp::Ordering, collections::HashMap, sync::Arc};
 
 use crate::{
-    schema::{Column, Index},
+    schema::{Column, Index, Schema, Table, EXPR_INDEX_COLUMN},
     translate::{
+        collate::CollationSeq,
         expr::as_binary_components,
-        plan::{JoinOrderMember, TableReferences, WhereTerm},
+        plan::{JoinOrderMember, JoinedTable, TableReferences, WhereTerm},
         planner::{table_mask_from_expr, TableMask},
     },
-    Result,
+    util::{exprs_are_equivalent, normalize_ident},
+    LimboError, Result,
 };
 use turso_sqlite3_parser::ast::{self, SortOrder, TableInternalId};
 
-use super::cost::ESTIMATED_HARDCODED_ROWS_PER_TABLE;
+use super::{cost::ESTIMATED_HARDCODED_ROWS_PER_TABLE, partial_index::bind_index_expr};
 
 /// Represents a single condition derived from a `WHERE` clause term
 /// that constrains a specific column of a table.
@@ -38,7 +40,8 @@ pub struct Constraint {
     pub where_clause_pos: (usize, BinaryExprSide),
     /// The comparison operator (e.g., `=`, `>`, `<`) used in the constraint.
     pub operator: ast::Operator,
-    /// The zero-based index of the constrained column within the table's schema.
+    /// The zero-based index of the constrained column within the table's schema, or
+    /// [EXPR_INDEX_COLUMN] if an indexed expression is constrained instead.
     pub table_col_pos: usize,
     /// A bitmask representing the set of tables that appear on the *constraining* side
     /// of the comparison expression. For example, in SELECT * FROM t1,t2,t3 WHERE t1.x = t2.x + t3.x,
@@ -48,6 +51,9 @@ pub struct Constraint {
     /// An estimated selectivity factor (0.0 to 1.0) indicating the fraction of rows
     /// expected to satisfy this constraint. Used for cost and cardinality estimation.
     pub selectivity: f64,
+    /// The collation the comparison is made with. Only the index columns with the same
+    /// collation can be searched with the constraint.
+    pub collation: CollationSeq,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq)]
@@ -127,7 +133,12 @@ pub struct ConstraintUseCandidate {
     /// The index that may be used to satisfy the constraints. If none, the table's rowid index is used.
     pub index: Option<Arc<Index>>,
     /// References to the constraints that may be used as an access path for the index.
+    /// The last column referenced may be referenced twice, by two inequalities bounding both ends
+    /// of a range, e.g. `y > 10 AND y < 20`.
     pub refs: Vec<ConstraintRef>,
+    /// The average number of rows per key of each prefix of the columns of the index, if the
+    /// index was analyzed. See [crate::schema::TableStats].
+    pub rows_per_key: Option<Vec<u64>>,
 }
 
 #[derive(Debug)]
@@ -139,6 +150,42 @@ pub struct TableConstraints {
     pub constraints: Vec<Constraint>,
     /// Candidates for indexes that may use the constraints to perform a lookup.
     pub candidates: Vec<ConstraintUseCandidate>,
+    /// Disjunctions in the WHERE clause whose every branch constrains a column of the table.
+    pub or_constraints: Vec<OrConstraint>,
+    /// The estimated number of rows in the table.
+    pub row_count: f64,
+}
+
+/// A `WHERE` clause term that is a disjunction of comparisons on columns of a single table,
+/// e.g. `a = 1 OR b > 2`, or an IN list, e.g. `x IN (1, 2, 3)`, which is handled as
+/// `x = 1 OR x = 2 OR x = 3`.
+///
+/// No single index seek can answer such a term, but if every branch can be answered by a seek
+/// of its own, the table can be read through the union of the rowids found by those seeks.
+/// The term itself is never consumed, so it still filters the rows that are read.
+#[derive(Debug, Clone)]
+pub struct OrConstraint {
+    /// The branches of the disjunction.
+    pub branches: Vec<OrConstraintBranch>,
+    /// The tables referred to by the constraining expressions of all the branches, which must
+    /// be joined before the constrained table for the seeks to be usable.
+    pub lhs_mask: TableMask,
+}
+
+/// A single comparison in an [OrConstraint], e.g. `b > 2` in `a = 1 OR b > 2`.
+#[derive(Debug, Clone)]
+pub struct OrConstraintBranch {
+    /// The comparison operator, with the constrained column on its left-hand side.
+    pub operator: ast::Operator,
+    /// The zero-based index of the constrained column within the table's schema.
+    pub table_col_pos: usize,
+    /// The expression that constrains the column, e.g. `2` in `b > 2`.
+    pub expr: ast::Expr,
+    /// An estimated selectivity factor (0.0 to 1.0) indicating the fraction of rows
+    /// expected to satisfy this branch.
+    pub selectivity: f64,
+    /// The collation the comparison is made with, like [Constraint::collation].
+    pub collation: CollationSeq,
 }
 
 /// In lieu of statistics, we estimate that an equality filter will reduce the output set to 1% of its size.
@@ -153,13 +200,18 @@ const SELECTIVITY_UNIQUE_EQUALITY: f64 = 1.0 / ESTIMATED_HARDCODED_ROWS_PER_TABL
 /// Estimate the selectivity of a constraint based on the operator and the column type.
 fn estimate_selectivity(column: &Column, op: ast::Operator) -> f64 {
     match op {
-        ast::Operator::Equals => {
-            if column.is_rowid_alias || column.primary_key {
-                SELECTIVITY_UNIQUE_EQUALITY
-            } else {
-                SELECTIVITY_EQ
-            }
+        ast::Operator::Equals if column.is_rowid_alias || column.primary_key => {
+            SELECTIVITY_UNIQUE_EQUALITY
         }
+        _ => estimate_operator_selectivity(op),
+    }
+}
+
+/// Estimate the selectivity of a constraint from its operator alone, e.g. for a constraint on an
+/// indexed expression.
+fn estimate_operator_selectivity(op: ast::Operator) -> f64 {
+    match op {
+        ast::Operator::Equals => SELECTIVITY_EQ,
         ast::Operator::Greater => SELECTIVITY_RANGE,
         ast::Operator::GreaterEquals => SELECTIVITY_RANGE,
         ast::Operator::Less => SELECTIVITY_RANGE,
@@ -195,20 +247,49 @@ pub fn constraints_from_where_clause(
                         .map(|index| ConstraintUseCandidate {
                             index: Some(index.clone()),
                             refs: Vec::new(),
+                            rows_per_key: None,
                         })
                         .collect()
                 }),
+            or_constraints: Vec::new(),
+            row_count: ESTIMATED_HARDCODED_ROWS_PER_TABLE as f64,
         };
         // Add a candidate for the rowid index, which is always available when the table has a rowid alias.
         cs.candidates.push(ConstraintUseCandidate {
             index: None,
             refs: Vec::new(),
+            rows_per_key: None,
         });
 
+        // The indexed expressions of the table, bound to it so that they can be compared with the
+        // sides of the WHERE clause terms, along with their index and position in it.
+        let mut indexed_exprs = Vec::new();
+        for index in available_indexes
+            .get(table_reference.table.get_name())
+            .unwrap_or(&Vec::new())
+        {
+            for (position_in_index, column) in index.columns.iter().enumerate() {
+                // The optimizer does not check collations, so only the expressions that are
+                // compared with the default one can be searched.
+                let (Some(expr), None) = (&column.expr, column.collation) else {
+                    continue;
+                };
+                if let Some(expr) = bind_index_expr(expr, table_reference)? {
+                    indexed_exprs.push((index.clone(), position_in_index, expr));
+                }
+            }
+        }
+        let is_indexed_expr = |expr: &ast::Expr| {
+            indexed_exprs
+                .iter()
+                .any(|(_, _, indexed_expr)| exprs_are_equivalent(expr, indexed_expr))
+        };
+
         for (i, term) in where_clause.iter().enumerate() {
             let Some((lhs, operator, rhs)) = as_binary_components(&term.expr)? else {
                 continue;
             };
+            let collation = comparison_collation(lhs, rhs, table_references);
 
             // Constraints originating from a LEFT JOIN must always be evaluated in that join's RHS table's loop,
             // regardless of which tables the constraint references.
@@ -229,6 +310,7 @@ pub fn constraints_from_where_clause(
                             table_col_pos: *column,
                             lhs_mask: table_mask_from_expr(rhs, table_references)?,
                             selectivity: estimate_selectivity(table_column, operator),
+                            collation,
                         });
                     }
                 }
@@ -245,9 +327,20 @@ pub fn constraints_from_where_clause(
                             table_col_pos: rowid_alias_column.unwrap(),
                             lhs_mask: table_mask_from_expr(rhs, table_references)?,
                             selectivity: estimate_selectivity(table_column, operator),
+                            collation,
                         });
                     }
                 }
+                _ if is_indexed_expr(lhs) => {
+                    cs.constraints.push(Constraint {
+                        where_clause_pos: (i, BinaryExprSide::Rhs),
+                        operator,
+                        table_col_pos: EXPR_INDEX_COLUMN,
+                        lhs_mask: table_mask_from_expr(rhs, table_references)?,
+                        selectivity: estimate_operator_selectivity(operator),
+                        collation,
+                    });
+                }
                 _ => {}
             };
             match rhs {
@@ -260,6 +353,7 @@ pub fn constraints_from_where_clause(
                             table_col_pos: *column,
                             lhs_mask: table_mask_from_expr(lhs, table_references)?,
                             selectivity: estimate_selectivity(table_column, operator),
+                            collation,
                         });
                     }
                 }
@@ -273,9 +367,20 @@ pub fn constraints_from_where_clause(
                             table_col_pos: rowid_alias_column.unwrap(),
                             lhs_mask: table_mask_from_expr(lhs, table_references)?,
                             selectivity: estimate_selectivity(table_column, operator),
+                            collation,
                         });
                     }
                 }
+                _ if is_indexed_expr(rhs) => {
+                    cs.constraints.push(Constraint {
+                        where_clause_pos: (i, BinaryExprSide::Lhs),
+                        operator: opposite_cmp_op(operator),
+                        table_col_pos: EXPR_INDEX_COLUMN,
+                        lhs_mask: table_mask_from_expr(lhs, table_references)?,
+                        selectivity: estimate_operator_selectivity(operator),
+                        collation,
+                    });
+                }
                 _ => {}
             };
         }
@@ -311,13 +416,39 @@ pub fn constraints_from_where_clause(
                     sort_order: SortOrder::Asc,
                 });
             }
+            // A constraint on an expression can only use the indexes of an equivalent expression.
+            let constrained_expr = if constraint.table_col_pos == EXPR_INDEX_COLUMN {
+                let (lhs, _, rhs) =
+                    as_binary_components(&where_clause[constraint.where_clause_pos.0].expr)?
+                        .expect("constraints are only made from binary comparisons");
+                match constraint.where_clause_pos.1 {
+                    BinaryExprSide::Lhs => Some(rhs),
+                    BinaryExprSide::Rhs => Some(lhs),
+                }
+            } else {
+                None
+            };
             for index in available_indexes
                 .get(table_reference.table.get_name())
                 .unwrap_or(&Vec::new())
             {
-                if let Some(position_in_index) =
-                    index.column_table_pos_to_index_pos(constraint.table_col_pos)
-                {
+                let position_in_index = match constrained_expr {
+                    Some(constrained_expr) => indexed_exprs.iter().find_map(
+                        |(indexed_expr_index, position_in_index, indexed_expr)| {
+                            (Arc::ptr_eq(index, indexed_expr_index)
+                                && exprs_are_equivalent(constrained_expr, indexed_expr))
+                            .then_some(*position_in_index)
+                        },
+                    ),
+                    None => index.column_table_pos_to_index_pos(constraint.table_col_pos),
+                };
+                let position_in_index = position_in_index.filter(|position_in_index| {
+                    index.columns[*position_in_index]
+                        .collation
+                        .unwrap_or_default()
+                        == constraint.collation
+                });
+                if let Some(position_in_index) = position_in_index {
                     let index_candidate = cs
                         .candidates
                         .iter_mut()
@@ -345,6 +476,7 @@ pub fn constraints_from_where_clause(
         for candidate in cs.candidates.iter_mut() {
             // Sort by index_col_pos, ascending -- index columns must be consumed in contiguous order.
             candidate.refs.sort_by_key(|cref| cref.index_col_pos);
+            let all_refs = candidate.refs.clone();
             // Deduplicate by position, keeping first occurrence (which will be equality if one exists, since the constraints vec is sorted that way)
             candidate.refs.dedup_by_key(|cref| cref.index_col_pos);
             // Truncate at first gap in positions -- again, index columns must be consumed in contiguous order.
@@ -362,6 +494,35 @@ pub fn constraints_from_where_clause(
                 cs.constraints[cref.constraint_vec_pos].operator != ast::Operator::Equals
             }) {
                 candidate.refs.truncate(first_inequality + 1);
+                // The inequality can be paired with another one on the same column that bounds
+                // the other end of the range.
+                let range_start = &candidate.refs[first_inequality];
+                let range_start_op = cs.constraints[range_start.constraint_vec_pos].operator;
+                if let Some(range_end) = all_refs.iter().find(|cref| {
+                    cref.index_col_pos == range_start.index_col_pos
+                        && bounds_other_end(
+                            range_start_op,
+                            cs.constraints[cref.constraint_vec_pos].operator,
+                        )
+                }) {
+                    candidate.refs.push(range_end.clone());
+                }
+            }
+        }
+
+        // Rows found by the seeks of the branches of an OR term are looked up by rowid.
+        if table_reference.btree().is_some_and(|btree| btree.has_rowid) {
+            for term in where_clause.iter() {
+                if let Some(outer_join_tbl) = term.from_outer_join {
+                    if outer_join_tbl != table_reference.internal_id {
+                        continue;
+                    }
+                }
+                if let Some(or_constraint) =
+                    or_constraint_from_where_term(term, table_reference, table_references)?
+                {
+                    cs.or_constraints.push(or_constraint);
+                }
             }
         }
         constraints.push(cs);
@@ -370,6 +531,219 @@ pub fn constraints_from_where_clause(
     Ok(constraints)
 }
 
+/// Returns the [OrConstraint] of `term` for `table_reference`, if `term` is a disjunction or an
+/// IN list whose every branch compares a column of the table to an expression that doesn't
+/// refer to the table itself.
+fn or_constraint_from_where_term(
+    term: &WhereTerm,
+    table_reference: &JoinedTable,
+    table_references: &TableReferences,
+) -> Result<Option<OrConstraint>> {
+    let expr = unwrap_single_parens(&term.expr);
+    let is_disjunction = matches!(
+        expr,
+        ast::Expr::Binary(_, ast::Operator::Or, _) | ast::Expr::InList { not: false, .. }
+    );
+    if !is_disjunction {
+        return Ok(None);
+    }
+    let table_no = table_references
+        .joined_tables()
+        .iter()
+        .position(|t| t.internal_id == table_reference.internal_id)
+        .expect("table reference is one of the joined tables");
+
+    let mut disjuncts = Vec::new();
+    flatten_disjunction(expr, &mut disjuncts);
+    let mut branches = Vec::new();
+    let mut lhs_mask = TableMask::new();
+    let mut add_branch = |column: &ast::Expr, operator, constraining_expr: &ast::Expr| {
+        let collation = comparison_collation(column, constraining_expr, table_references);
+        let Some(table_col_pos) = constrained_column(column, table_reference) else {
+            return Ok(false);
+        };
+        let mask = table_mask_from_expr(constraining_expr, table_references)?;
+        if mask.contains_table(table_no) {
+            return Ok(false);
+        }
+        lhs_mask |= mask;
+        branches.push(OrConstraintBranch {
+            operator,
+            table_col_pos,
+            expr: constraining_expr.clone(),
+            selectivity: estimate_selectivity(&table_reference.columns()[table_col_pos], operator),
+            collation,
+        });
+        Ok::<_, LimboError>(true)
+    };
+    for disjunct in disjuncts {
+        let usable = match disjunct {
+            ast::Expr::InList {
+                lhs,
+                not: false,
+                rhs: Some(values),
+            } => {
+                let mut usable = !values.is_empty();
+                for value in values {
+                    usable = usable && add_branch(lhs, ast::Operator::Equals, value)?;
+                }
+                usable
+            }
+            _ => match as_binary_components(disjunct)? {
+                Some((lhs, operator, rhs)) => {
+                    add_branch(lhs, operator, rhs)?
+                        || add_branch(rhs, opposite_cmp_op(operator), lhs)?
+                }
+                None => false,
+            },
+        };
+        if !usable {
+            return Ok(None);
+        }
+    }
+
+    Ok(Some(OrConstraint { branches, lhs_mask }))
+}
+
+/// Collects the branches of a disjunction, e.g. `a`, `b` and `c` from `a OR (b OR c)`.
+fn flatten_disjunction<'a>(expr: &'a ast::Expr, disjuncts: &mut Vec<&'a ast::Expr>) {
+    match unwrap_single_parens(expr) {
+        ast::Expr::Binary(lhs, ast::Operator::Or, rhs) => {
+            flatten_disjunction(lhs, disjuncts);
+            flatten_disjunction(rhs, disjuncts);
+        }
+        expr => disjuncts.push(expr),
+    }
+}
+
+fn unwrap_single_parens(mut expr: &ast::Expr) -> &ast::Expr {
+    while let ast::Expr::Parenthesized(exprs) = expr {
+        if exprs.len() != 1 {
+            break;
+        }
+        expr = &exprs[0];
+    }
+    expr
+}
+
+/// Returns the collation a comparison of `lhs` and `rhs` is made with: the one of a COLLATE
+/// operator on either side, or else the one of a column on either side, with precedence to the
+/// left-hand side, or else BINARY.
+fn comparison_collation(
+    lhs: &ast::Expr,
+    rhs: &ast::Expr,
+    table_references: &TableReferences,
+) -> CollationSeq {
+    let explicit_collation = |expr: &ast::Expr| match unwrap_single_parens(expr) {
+        ast::Expr::Collate(_, collation) => Some(CollationSeq::from_name(collation)),
+        _ => None,
+    };
+    let column_collation = |expr: &ast::Expr| match unwrap_single_parens(expr) {
+        ast::Expr::Column { table, column, .. } => Some(
+            table_references
+                .find_table_by_internal_id(*table)?
+                .get_column_at(*column)?
+                .collation
+                .unwrap_or_default(),
+        ),
+        _ => None,
+    };
+    explicit_collation(lhs)
+        .or_else(|| explicit_collation(rhs))
+        .or_else(|| column_collation(lhs))
+        .or_else(|| column_collation(rhs))
+        .unwrap_or_default()
+}
+
+/// Returns the position of the column of `table_reference` that `expr` refers to, if any.
+fn constrained_column(expr: &ast::Expr, table_reference: &JoinedTable) -> Option<usize> {
+    match expr {
+        ast::Expr::Column { table, column, .. } if *table == table_reference.internal_id => {
+            Some(*column)
+        }
+        ast::Expr::RowId { table, .. } if *table == table_reference.internal_id => table_reference
+            .columns()
+            .iter()
+            .position(|c| c.is_rowid_alias),
+        _ => None,
+    }
+}
+
+/// Refine the estimates of [constraints_from_where_clause] with the statistics gathered by
+/// `ANALYZE`, for the tables that were analyzed: the row count of the table replaces the hardcoded
+/// one, and an equality on the first column of an analyzed index is estimated to match as many
+/// rows as the average number of rows per value of that column.
+pub fn apply_table_stats(
+    table_constraints: &mut [TableConstraints],
+    table_references: &TableReferences,
+    schema: &Schema,
+) {
+    for cs in table_constraints.iter_mut() {
+        let Some(table_reference) = table_references.find_joined_table_by_internal_id(cs.table_id)
+        else {
+            continue;
+        };
+        let Table::BTree(table) = &table_reference.table else {
+            continue;
+        };
+        let Some(stats) = schema
+            .database(table_reference.database_id)
+            .stats
+            .get(&normalize_ident(&table.name))
+        else {
+            continue;
+        };
+        cs.row_count = stats.row_count.max(1) as f64;
+        for candidate in cs.candidates.iter_mut() {
+            if let Some(index) = &candidate.index {
+                candidate.rows_per_key = stats
+                    .rows_per_key
+                    .get(&normalize_ident(&index.name))
+                    .cloned();
+            }
+        }
+        let row_count = cs.row_count;
+        let equality_selectivity = |table_col_pos: usize| {
+            // There are no statistics about the values of indexed expressions.
+            if table_col_pos == EXPR_INDEX_COLUMN {
+                return None;
+            }
+            let column = &table.columns[table_col_pos];
+            if column.is_rowid_alias || column.primary_key {
+                return Some(1.0 / row_count);
+            }
+            let rows_per_value = cs.candidates.iter().find_map(|candidate| {
+                let index = candidate.index.as_ref()?;
+                if index.columns[0].pos_in_table != table_col_pos {
+                    return None;
+                }
+                candidate.rows_per_key.as_ref()?.first()
+            })?;
+            Some((*rows_per_value as f64 / row_count).min(1.0))
+        };
+        for constraint in cs.constraints.iter_mut() {
+            if constraint.operator != ast::Operator::Equals {
+                continue;
+            }
+            if let Some(selectivity) = equality_selectivity(constraint.table_col_pos) {
+                constraint.selectivity = selectivity;
+            }
+        }
+        for branch in cs
+            .or_constraints
+            .iter_mut()
+            .flat_map(|or_constraint| or_constraint.branches.iter_mut())
+        {
+            if branch.operator != ast::Operator::Equals {
+                continue;
+            }
+            if let Some(selectivity) = equality_selectivity(branch.table_col_pos) {
+                branch.selectivity = selectivity;
+            }
+        }
+    }
+}
+
 /// Find which [Constraint]s are usable for a given join order.
 /// Returns a slice of the references to the constraints that are usable.
 /// A constraint is considered usable for a given table if all of the other tables referenced by the constraint
@@ -402,6 +776,54 @@ pub fn usable_constraints_for_join_order<'a>(
     &refs[..usable_until]
 }
 
+/// Returns the references to the constraints of `refs` that can be used as the key of a seek in
+/// an ephemeral index built for them: one equality per column, followed by the inequalities
+/// bounding a range on another column.
+pub fn ephemeral_index_seek_refs(
+    constraints: &[Constraint],
+    refs: &[ConstraintRef],
+) -> Vec<ConstraintRef> {
+    let mut seek_refs: Vec<ConstraintRef> = Vec::new();
+    for cref in refs.iter() {
+        if constraints[cref.constraint_vec_pos].operator == ast::Operator::Equals
+            && !seek_refs
+                .iter()
+                .any(|equality| equality.index_col_pos == cref.index_col_pos)
+        {
+            seek_refs.push(cref.clone());
+        }
+    }
+    let range_start = refs.iter().find(|cref| {
+        constraints[cref.constraint_vec_pos].operator != ast::Operator::Equals
+            && !seek_refs
+                .iter()
+                .any(|equality| equality.index_col_pos == cref.index_col_pos)
+    });
+    if let Some(range_start) = range_start {
+        let range_start_op = constraints[range_start.constraint_vec_pos].operator;
+        let range_end = refs.iter().find(|cref| {
+            cref.index_col_pos == range_start.index_col_pos
+                && bounds_other_end(
+                    range_start_op,
+                    constraints[cref.constraint_vec_pos].operator,
+                )
+        });
+        seek_refs.push(range_start.clone());
+        seek_refs.extend(range_end.cloned());
+    }
+    seek_refs
+}
+
+/// Whether a constraint with operator `other` bounds the opposite end of a range than one with
+/// operator `op`, e.g. `x < 20` and `x > 10`.
+fn bounds_other_end(op: ast::Operator, other: ast::Operator) -> bool {
+    use ast::Operator::{Greater, GreaterEquals, Less, LessEquals};
+    matches!(
+        (op, other),
+        (Greater | GreaterEquals, Less | LessEquals) | (Less | LessEquals, Greater | GreaterEquals)
+    )
+}
+
 fn opposite_cmp_op(op: ast::Operator) -> ast::Operator {
     match op {
         ast::Operator::Equals => ast::Operator::Equals,
@@ -1,3 +1,5 @@
+use turso_sqlite3_parser::ast;
+
 use super::constraints::{Constraint, ConstraintRef};
 
 /// A simple newtype wrapper over a f64 that represents the cost of an operation.
@@ -40,19 +42,35 @@ pub fn estimate_page_io_cost(rowcount: f64) -> Cost {
 ///
 /// This is a very simple model that estimates the number of pages read
 /// based on the number of rows read, ignoring any CPU costs.
+///
+/// If the index was analyzed, `rows_per_key` holds the average number of rows per key of each
+/// prefix of its columns, which is used as the number of rows matched by the equality constraints
+/// on those columns instead of the selectivities of the constraints.
 pub fn estimate_cost_for_scan_or_seek(
     index_info: Option<IndexInfo>,
     constraints: &[Constraint],
     usable_constraint_refs: &[ConstraintRef],
     input_cardinality: f64,
+    row_count: f64,
+    rows_per_key: Option<&[u64]>,
 ) -> Cost {
     let Some(index_info) = index_info else {
-        return estimate_page_io_cost(
-            input_cardinality * ESTIMATED_HARDCODED_ROWS_PER_TABLE as f64,
-        );
+        return estimate_page_io_cost(input_cardinality * row_count);
+    };
+
+    let num_equalities = usable_constraint_refs
+        .iter()
+        .take_while(|cref| constraints[cref.constraint_vec_pos].operator == ast::Operator::Equals)
+        .count();
+    let (rows, remaining_constraint_refs) = match rows_per_key {
+        Some(rows_per_key) if num_equalities > 0 && num_equalities <= rows_per_key.len() => (
+            rows_per_key[num_equalities - 1] as f64,
+            &usable_constraint_refs[num_equalities..],
+        ),
+        _ => (row_count, usable_constraint_refs),
     };
 
-    let selectivity_multiplier: f64 = usable_constraint_refs
+    let selectivity_multiplier: f64 = remaining_constraint_refs
         .iter()
         .map(|cref| {
             let constraint = &constraints[cref.constraint_vec_pos];
@@ -63,10 +81,26 @@ pub fn estimate_cost_for_scan_or_seek(
     // little cheeky bonus for covering indexes
     let covering_multiplier = if index_info.covering { 0.9 } else { 1.0 };
 
-    estimate_page_io_cost(
-        selectivity_multiplier
-            * ESTIMATED_HARDCODED_ROWS_PER_TABLE as f64
-            * input_cardinality
-            * covering_multiplier,
-    )
+    estimate_page_io_cost(selectivity_multiplier * rows * input_cardinality * covering_multiplier)
+}
+
+/// Estimate the cost of reading a table through the union of the rows found by a search per
+/// branch of an OR term.
+///
+/// Each branch is given as its selectivity and whether it searches an index, in which case
+/// every row it finds is also looked up in the table, or the table itself by rowid.
+pub fn estimate_cost_for_multi_index_or(
+    branches: impl Iterator<Item = (f64, bool)>,
+    input_cardinality: f64,
+    row_count: f64,
+) -> Cost {
+    branches.fold(Cost(0.0), |cost, (selectivity, uses_index)| {
+        let rows = selectivity * row_count * input_cardinality;
+        let table_lookups = if uses_index {
+            estimate_page_io_cost(rows)
+        } else {
+            Cost(0.0)
+        };
+        cost + estimate_page_io_cost(rows) + table_lookups
+    })
 }
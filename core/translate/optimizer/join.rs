@@ -1,8 +1,10 @@
 use std::{cell::RefCell, collections::HashMap};
 
-use turso_sqlite3_parser::ast::TableInternalId;
+use turso_ext::VTabKind;
+use turso_sqlite3_parser::ast::{self, TableInternalId};
 
 use crate::{
+    schema::Table,
     translate::{
         optimizer::{cost::Cost, order::plan_satisfies_order_target},
         plan::{JoinOrderMember, JoinedTable},
@@ -14,7 +16,6 @@ use crate::{
 use super::{
     access_method::{find_best_access_method_for_join_order, AccessMethod},
     constraints::TableConstraints,
-    cost::ESTIMATED_HARDCODED_ROWS_PER_TABLE,
     order::OrderTarget,
 };
 
@@ -94,10 +95,9 @@ pub fn join_lhs_and_rhs<'a>(
     // Produce a number of rows estimated to be returned when this table is filtered by the WHERE clause.
     // If this table is the rightmost table in the join order, we multiply by the input cardinality,
     // which is the output cardinality of the previous tables.
-    let output_cardinality = (input_cardinality as f64
-        * ESTIMATED_HARDCODED_ROWS_PER_TABLE as f64
-        * output_cardinality_multiplier)
-        .ceil() as usize;
+    let output_cardinality =
+        (input_cardinality as f64 * rhs_constraints.row_count * output_cardinality_multiplier)
+            .ceil() as usize;
 
     Ok(Some(JoinN {
         data: best_access_methods,
@@ -106,6 +106,50 @@ pub fn join_lhs_and_rhs<'a>(
     }))
 }
 
+/// Computes the tables that must be joined before each table.
+/// The arguments of a table-valued function constrain its hidden columns, and the function can
+/// only be called once the tables that these arguments reference are positioned, e.g. in
+/// `SELECT * FROM t, json_each(t.doc)`, `json_each` must be joined after `t`.
+fn table_dependencies(
+    joined_tables: &[JoinedTable],
+    constraints: &[TableConstraints],
+) -> Vec<TableMask> {
+    joined_tables
+        .iter()
+        .zip(constraints)
+        .enumerate()
+        .map(|(table_no, (joined_table, table_constraints))| {
+            let mut dependencies = TableMask::new();
+            let Table::Virtual(vtab) = &joined_table.table else {
+                return dependencies;
+            };
+            if vtab.kind != VTabKind::TableValuedFunction {
+                return dependencies;
+            }
+            let columns = joined_table.table.columns();
+            for constraint in table_constraints.constraints.iter() {
+                let is_argument = constraint.operator == ast::Operator::Equals
+                    && columns
+                        .get(constraint.table_col_pos)
+                        .is_some_and(|col| col.hidden);
+                if !is_argument {
+                    continue;
+                }
+                for other in (0..joined_tables.len()).filter(|other| *other != table_no) {
+                    if constraint.lhs_mask.contains_table(other) {
+                        dependencies.add_table(other);
+                    }
+                }
+            }
+            dependencies
+        })
+        .collect()
+}
+
+/// The most tables for which [compute_best_join_order] considers every possible join order.
+/// Larger joins are ordered greedily with [compute_greedy_left_deep_plan].
+const MAX_TABLES_FOR_EXHAUSTIVE_SEARCH: usize = 12;
+
 /// The result of [compute_best_join_order].
 #[derive(Debug)]
 pub struct BestJoinOrderResult {
@@ -116,9 +160,12 @@ pub struct BestJoinOrderResult {
 }
 
 /// Compute the best way to join a given set of tables.
+/// If `first_table_is_outermost` is true, the first table in `joined_tables` is never
+/// reordered and always drives the outermost loop.
 /// Returns the best [JoinN] if one exists, otherwise returns None.
 pub fn compute_best_join_order<'a>(
     joined_tables: &[JoinedTable],
+    first_table_is_outermost: bool,
     maybe_order_target: Option<&OrderTarget>,
     constraints: &'a [TableConstraints],
     access_methods_arena: &'a RefCell<Vec<AccessMethod<'a>>>,
@@ -129,14 +176,24 @@ pub fn compute_best_join_order<'a>(
     }
 
     let num_tables = joined_tables.len();
+    let dependencies = table_dependencies(joined_tables, constraints);
 
     // Compute naive left-to-right plan to use as pruning threshold
-    let naive_plan = compute_naive_left_deep_plan(
+    let mut naive_plan = compute_naive_left_deep_plan(
         joined_tables,
         maybe_order_target,
         access_methods_arena,
         constraints,
     )?;
+    // If a table-valued function references tables to its right, the order of the query can't
+    // be used, so it is only kept as a last resort.
+    let naive_plan_is_legal = dependencies
+        .iter()
+        .enumerate()
+        .all(|(table_no, deps)| TableMask::from_table_number_iter(0..table_no).contains_all(deps));
+    if !naive_plan_is_legal && num_tables > 1 {
+        naive_plan.cost = Cost(f64::MAX);
+    }
 
     // Keep track of both 1. the best plan overall (not considering sorting), and 2. the best ordered plan (which might not be the same).
     // We assign Some Cost (tm) to any required sort operation, so the best ordered plan may end up being
@@ -147,6 +204,7 @@ pub fn compute_best_join_order<'a>(
             &naive_plan,
             access_methods_arena,
             joined_tables,
+            constraints,
             order_target,
         )
     } else {
@@ -160,6 +218,51 @@ pub fn compute_best_join_order<'a>(
             best_ordered_plan: None,
         }));
     }
+    // Enumerating every subset of tables gets too expensive for large joins, so build those
+    // greedily instead, once from each possible outermost table, and keep the literal query
+    // order if none of them turns out cheaper.
+    if num_tables > MAX_TABLES_FOR_EXHAUSTIVE_SEARCH {
+        let mut best_greedy_plan: Option<JoinN> = None;
+        for first_table in 0..num_tables {
+            if first_table_is_outermost && first_table != 0 {
+                break;
+            }
+            if joined_tables[first_table]
+                .join_info
+                .as_ref()
+                .is_some_and(|j| j.outer)
+                || !dependencies[first_table].is_empty()
+            {
+                continue;
+            }
+            let greedy_plan = compute_greedy_left_deep_plan(
+                joined_tables,
+                &dependencies,
+                first_table,
+                maybe_order_target,
+                access_methods_arena,
+                constraints,
+            )?;
+            if greedy_plan.cost
+                < best_greedy_plan
+                    .as_ref()
+                    .map_or(naive_plan.cost, |p| p.cost)
+            {
+                best_greedy_plan = Some(greedy_plan);
+            }
+        }
+        let Some(greedy_plan) = best_greedy_plan else {
+            return Ok(Some(BestJoinOrderResult {
+                best_plan: naive_plan,
+                best_ordered_plan: None,
+            }));
+        };
+        return Ok(Some(BestJoinOrderResult {
+            best_plan: greedy_plan,
+            best_ordered_plan: best_plan_is_also_ordered.then_some(naive_plan),
+        }));
+    }
+
     let mut best_plan = naive_plan;
 
     // Reuse a single mutable join order to avoid allocating join orders per permutation.
@@ -204,7 +307,8 @@ pub fn compute_best_join_order<'a>(
             access_methods_arena,
             cost_upper_bound_ordered,
         )?;
-        if let Some(rel) = rel {
+        // A table that depends on other tables can't be the outermost one.
+        if let Some(rel) = rel.filter(|_| dependencies[i].is_empty()) {
             best_plan_memo.insert(mask, rel);
         }
     }
@@ -271,6 +375,11 @@ pub fn compute_best_join_order<'a>(
                     continue;
                 }
 
+                // If the first table must stay the outermost loop, it can never be joined as RHS.
+                if first_table_is_outermost && rhs_idx == 0 {
+                    continue;
+                }
+
                 // If this join ordering would violate LEFT JOIN ordering restrictions, skip.
                 if let Some(illegal_lhs) = left_join_illegal_map
                     .as_ref()
@@ -282,6 +391,11 @@ pub fn compute_best_join_order<'a>(
                     }
                 }
 
+                // A table-valued function can only be joined after the tables its arguments reference.
+                if !lhs_mask.contains_all(&dependencies[rhs_idx]) {
+                    continue;
+                }
+
                 // If the already cached plan for this subset was too crappy to consider,
                 // then joining it with RHS won't help. Skip.
                 let Some(lhs) = best_plan_memo.get(&lhs_mask) else {
@@ -330,6 +444,7 @@ pub fn compute_best_join_order<'a>(
                         &rel,
                         access_methods_arena,
                         joined_tables,
+                        constraints,
                         order_target,
                     )
                 } else {
@@ -388,6 +503,88 @@ pub fn compute_best_join_order<'a>(
     }))
 }
 
+/// Builds a left-deep plan for joins with too many tables for [compute_best_join_order] to
+/// consider every subset of them: starting from `first_table`, the table that is the cheapest
+/// to join with the tables chosen so far is added, until all tables are joined.
+pub fn compute_greedy_left_deep_plan<'a>(
+    joined_tables: &[JoinedTable],
+    dependencies: &[TableMask],
+    first_table: usize,
+    maybe_order_target: Option<&OrderTarget>,
+    access_methods_arena: &'a RefCell<Vec<AccessMethod<'a>>>,
+    constraints: &'a [TableConstraints],
+) -> Result<JoinN> {
+    let n = joined_tables.len();
+    assert!(n > 0);
+
+    let is_outer = |i: usize| joined_tables[i].join_info.as_ref().is_some_and(|j| j.outer);
+    let join_order_member = |i: usize| JoinOrderMember {
+        table_id: joined_tables[i].internal_id,
+        original_idx: i,
+        is_outer: is_outer(i),
+    };
+
+    let mut join_order = vec![join_order_member(first_table)];
+    let mut plan = join_lhs_and_rhs(
+        None,
+        &joined_tables[first_table],
+        &constraints[first_table],
+        &join_order,
+        maybe_order_target,
+        access_methods_arena,
+        Cost(f64::MAX),
+    )?
+    .expect("call to join_lhs_and_rhs in compute_greedy_left_deep_plan always returns Some(JoinN)");
+
+    while join_order.len() < n {
+        let lhs_mask = TableMask::from_table_number_iter(plan.table_numbers());
+        // The RHS of a LEFT JOIN can only be joined after all the tables on its left, since
+        // none of them can be joined after it.
+        let can_join = |rhs_idx: usize| {
+            !(lhs_mask.contains_table(rhs_idx)
+                || is_outer(rhs_idx) && (0..rhs_idx).any(|i| !lhs_mask.contains_table(i)))
+        };
+        // A table-valued function can only be joined after the tables its arguments reference,
+        // unless the dependencies can't be satisfied at all.
+        let has_ready_table =
+            (0..n).any(|i| can_join(i) && lhs_mask.contains_all(&dependencies[i]));
+        let mut best_for_step: Option<JoinN> = None;
+        for rhs_idx in 0..n {
+            if !can_join(rhs_idx) {
+                continue;
+            }
+            if has_ready_table && !lhs_mask.contains_all(&dependencies[rhs_idx]) {
+                continue;
+            }
+            join_order.push(join_order_member(rhs_idx));
+            let rel = join_lhs_and_rhs(
+                Some(&plan),
+                &joined_tables[rhs_idx],
+                &constraints[rhs_idx],
+                &join_order,
+                maybe_order_target,
+                access_methods_arena,
+                Cost(f64::MAX),
+            )?
+            .expect("call to join_lhs_and_rhs in compute_greedy_left_deep_plan always returns Some(JoinN)");
+            join_order.pop();
+            // Among equally expensive tables, prefer the one that filters out the most rows,
+            // so that the following tables are looped over fewer times.
+            if best_for_step.as_ref().is_none_or(|best| {
+                rel.cost < best.cost
+                    || (rel.cost == best.cost && rel.output_cardinality < best.output_cardinality)
+            }) {
+                best_for_step = Some(rel);
+            }
+        }
+        // The leftmost table that isn't joined yet can always be joined next.
+        plan = best_for_step.expect("some table can always be joined next");
+        join_order.push(join_order_member(plan.table_numbers().last().unwrap()));
+    }
+
+    Ok(plan)
+}
+
 /// Specialized version of [compute_best_join_order] that just joins tables in the order they are given
 /// in the SQL query. This is used as an upper bound for any other plans -- we can give up enumerating
 /// permutations if they exceed this cost during enumeration.
@@ -496,9 +693,11 @@ mod tests {
 
     use super::*;
     use crate::{
-        schema::{BTreeTable, Column, Index, IndexColumn, Table, Type},
+        schema::{BTreeTable, Column, Index, IndexColumn, Schema, Table, TableStats, Type},
         translate::{
-            optimizer::constraints::{constraints_from_where_clause, BinaryExprSide},
+            optimizer::constraints::{
+                apply_table_stats, constraints_from_where_clause, BinaryExprSide,
+            },
             plan::{
                 ColumnUsedMask, IterationDirection, JoinInfo, Operation, TableReferences, WhereTerm,
             },
@@ -532,6 +731,7 @@ mod tests {
 
         let result = compute_best_join_order(
             table_references.joined_tables(),
+            false,
             None,
             &table_constraints,
             &access_methods_arena,
@@ -563,6 +763,7 @@ mod tests {
         // expecting best_best_plan() not to do any work due to empty where clause.
         let BestJoinOrderResult { best_plan, .. } = compute_best_join_order(
             table_references.joined_tables(),
+            false,
             None,
             &table_constraints,
             &access_methods_arena,
@@ -603,6 +804,7 @@ mod tests {
         // expecting a RowidEq access method because id is a rowid alias.
         let result = compute_best_join_order(
             table_references.joined_tables(),
+            false,
             None,
             &table_constraints,
             &access_methods_arena,
@@ -654,11 +856,14 @@ mod tests {
                 pos_in_table: 0,
                 collation: None,
                 default: None,
+                expr: None,
             }],
             unique: true,
             ephemeral: false,
+            conflict_clause: None,
             root_page: 1,
             has_rowid: true,
+            where_clause: None,
         });
         available_indexes.insert("test_table".to_string(), vec![index]);
 
@@ -669,6 +874,7 @@ mod tests {
         // expecting an IndexScan access method because id is a primary key with an index
         let result = compute_best_join_order(
             table_references.joined_tables(),
+            false,
             None,
             &table_constraints,
             &access_methods_arena,
@@ -722,11 +928,14 @@ mod tests {
                 pos_in_table: 0,
                 collation: None,
                 default: None,
+                expr: None,
             }],
             unique: true,
             ephemeral: false,
+            conflict_clause: None,
             root_page: 1,
             has_rowid: true,
+            where_clause: None,
         });
         available_indexes.insert("table1".to_string(), vec![index1]);
 
@@ -746,6 +955,7 @@ mod tests {
 
         let result = compute_best_join_order(
             table_references.joined_tables(),
+            false,
             None,
             &table_constraints,
             &access_methods_arena,
@@ -838,11 +1048,14 @@ mod tests {
                         pos_in_table: 0,
                         collation: None,
                         default: None,
+                        expr: None,
                     }],
                     unique: true,
                     ephemeral: false,
+                    conflict_clause: None,
                     root_page: 1,
                     has_rowid: true,
+                    where_clause: None,
                 });
                 available_indexes.insert(table_name.to_string(), vec![index]);
             });
@@ -855,11 +1068,14 @@ mod tests {
                 pos_in_table: 1,
                 collation: None,
                 default: None,
+                expr: None,
             }],
             unique: false,
             ephemeral: false,
+            conflict_clause: None,
             root_page: 1,
             has_rowid: true,
+            where_clause: None,
         });
         let order_id_idx = Arc::new(Index {
             name: "order_items_order_id_idx".to_string(),
@@ -870,11 +1086,14 @@ mod tests {
                 pos_in_table: 1,
                 collation: None,
                 default: None,
+                expr: None,
             }],
             unique: false,
             ephemeral: false,
+            conflict_clause: None,
             root_page: 1,
             has_rowid: true,
+            where_clause: None,
         });
 
         available_indexes
@@ -918,6 +1137,7 @@ mod tests {
 
         let result = compute_best_join_order(
             table_references.joined_tables(),
+            false,
             None,
             &table_constraints,
             &access_methods_arena,
@@ -1027,6 +1247,7 @@ mod tests {
 
         let BestJoinOrderResult { best_plan, .. } = compute_best_join_order(
             table_references.joined_tables(),
+            false,
             None,
             &table_constraints,
             &access_methods_arena,
@@ -1130,6 +1351,7 @@ mod tests {
 
         let result = compute_best_join_order(
             table_references.joined_tables(),
+            false,
             None,
             &table_constraints,
             &access_methods_arena,
@@ -1215,6 +1437,7 @@ mod tests {
         // Run the optimizer
         let BestJoinOrderResult { best_plan, .. } = compute_best_join_order(
             table_references.joined_tables(),
+            false,
             None,
             &table_constraints,
             &access_methods_arena,
@@ -1282,6 +1505,7 @@ mod tests {
                     pos_in_table: 0,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
                 IndexColumn {
                     name: "y".to_string(),
@@ -1289,12 +1513,15 @@ mod tests {
                     pos_in_table: 1,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
             ],
             unique: false,
             root_page: 2,
             ephemeral: false,
+            conflict_clause: None,
             has_rowid: true,
+            where_clause: None,
         });
 
         let mut available_indexes = HashMap::new();
@@ -1310,6 +1537,8 @@ mod tests {
             identifier: "t1".to_string(),
             join_info: None,
             col_used_mask: ColumnUsedMask::default(),
+            database_id: 0,
+            vtab_order_by: Vec::new(),
         });
 
         // Create where clause that only references second column
@@ -1336,6 +1565,7 @@ mod tests {
 
         let BestJoinOrderResult { best_plan, .. } = compute_best_join_order(
             table_references.joined_tables(),
+            false,
             None,
             &table_constraints,
             &access_methods_arena,
@@ -1368,6 +1598,7 @@ mod tests {
                     pos_in_table: 0,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
                 IndexColumn {
                     name: "c2".to_string(),
@@ -1375,6 +1606,7 @@ mod tests {
                     pos_in_table: 1,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
                 IndexColumn {
                     name: "c3".to_string(),
@@ -1382,12 +1614,15 @@ mod tests {
                     pos_in_table: 2,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
             ],
             unique: false,
             root_page: 2,
             ephemeral: false,
+            conflict_clause: None,
             has_rowid: true,
+            where_clause: None,
         });
         available_indexes.insert("t1".to_string(), vec![index]);
 
@@ -1401,6 +1636,8 @@ mod tests {
             identifier: "t1".to_string(),
             join_info: None,
             col_used_mask: ColumnUsedMask::default(),
+            database_id: 0,
+            vtab_order_by: Vec::new(),
         });
 
         // Create where clause that references first and third columns
@@ -1443,6 +1680,7 @@ mod tests {
 
         let BestJoinOrderResult { best_plan, .. } = compute_best_join_order(
             table_references.joined_tables(),
+            false,
             None,
             &table_constraints,
             &access_methods_arena,
@@ -1484,6 +1722,7 @@ mod tests {
                     pos_in_table: 0,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
                 IndexColumn {
                     name: "c2".to_string(),
@@ -1491,6 +1730,7 @@ mod tests {
                     pos_in_table: 1,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
                 IndexColumn {
                     name: "c3".to_string(),
@@ -1498,12 +1738,15 @@ mod tests {
                     pos_in_table: 2,
                     collation: None,
                     default: None,
+                    expr: None,
                 },
             ],
             root_page: 2,
             ephemeral: false,
+            conflict_clause: None,
             has_rowid: true,
             unique: false,
+            where_clause: None,
         });
         available_indexes.insert("t1".to_string(), vec![index]);
 
@@ -1517,6 +1760,8 @@ mod tests {
             identifier: "t1".to_string(),
             join_info: None,
             col_used_mask: ColumnUsedMask::default(),
+            database_id: 0,
+            vtab_order_by: Vec::new(),
         });
 
         // Create where clause: c1 = 5 AND c2 > 10 AND c3 = 7
@@ -1573,6 +1818,7 @@ mod tests {
 
         let BestJoinOrderResult { best_plan, .. } = compute_best_join_order(
             table_references.joined_tables(),
+            false,
             None,
             &table_constraints,
             &access_methods_arena,
@@ -1599,6 +1845,237 @@ mod tests {
         assert!(constraint.table_col_pos == 1); // c2
     }
 
+    #[test]
+    /// Test that the statistics gathered by ANALYZE decide which index is used: without them,
+    /// equalities on either column look as selective, with them the index whose column has a
+    /// single row per value is preferred.
+    fn test_analyzed_index_is_preferred() {
+        let t1 = _create_btree_table("t1", _create_column_list(&["a", "b"], Type::Integer));
+        let mut table_id_counter = TableRefIdCounter::new();
+        let joined_tables = vec![_create_table_reference(
+            t1.clone(),
+            None,
+            table_id_counter.next(),
+        )];
+        let where_clause = vec![
+            _create_binary_expr(
+                _create_column_expr(joined_tables[0].internal_id, 0, false),
+                ast::Operator::Equals,
+                _create_numeric_literal("1"),
+            ),
+            _create_binary_expr(
+                _create_column_expr(joined_tables[0].internal_id, 1, false),
+                ast::Operator::Equals,
+                _create_numeric_literal("1"),
+            ),
+        ];
+        let table_references = TableReferences::new(joined_tables, vec![]);
+        let index = |name: &str, column: &str, pos_in_table: usize| {
+            Arc::new(Index {
+                name: name.to_string(),
+                table_name: "t1".to_string(),
+                columns: vec![IndexColumn {
+                    name: column.to_string(),
+                    order: SortOrder::Asc,
+                    pos_in_table,
+                    collation: None,
+                    default: None,
+                    expr: None,
+                }],
+                unique: false,
+                ephemeral: false,
+                conflict_clause: None,
+                root_page: 2,
+                has_rowid: true,
+                where_clause: None,
+            })
+        };
+        let mut available_indexes = HashMap::new();
+        available_indexes.insert(
+            "t1".to_string(),
+            vec![index("t1_a", "a", 0), index("t1_b", "b", 1)],
+        );
+        let mut schema = Schema::new(true);
+        schema.stats.insert(
+            "t1".to_string(),
+            TableStats {
+                row_count: 1000,
+                rows_per_key: HashMap::from([
+                    ("t1_a".to_string(), vec![1000]),
+                    ("t1_b".to_string(), vec![1]),
+                ]),
+            },
+        );
+
+        for analyzed in [false, true] {
+            let access_methods_arena = RefCell::new(Vec::new());
+            let mut table_constraints =
+                constraints_from_where_clause(&where_clause, &table_references, &available_indexes)
+                    .unwrap();
+            if analyzed {
+                apply_table_stats(&mut table_constraints, &table_references, &schema);
+            }
+            // SELECT * FROM t1 WHERE a = 1 AND b = 1
+            let BestJoinOrderResult { best_plan, .. } = compute_best_join_order(
+                table_references.joined_tables(),
+                false,
+                None,
+                &table_constraints,
+                &access_methods_arena,
+            )
+            .unwrap()
+            .unwrap();
+            let access_method = &access_methods_arena.borrow()[best_plan.data[0].1];
+            let expected_index = if analyzed { "t1_b" } else { "t1_a" };
+            assert_eq!(access_method.index.as_ref().unwrap().name, expected_index);
+            if analyzed {
+                assert_eq!(best_plan.output_cardinality, 1);
+            }
+        }
+    }
+
+    #[test]
+    /// Test that an index containing every column the query uses is preferred over one that
+    /// would also need the table btree, and that it is scanned instead of the table when there
+    /// are no constraints to seek with.
+    fn test_covering_index_is_preferred() {
+        let t1 = _create_btree_table("t1", _create_column_list(&["a", "b", "c"], Type::Integer));
+        let mut table_id_counter = TableRefIdCounter::new();
+        let mut joined_tables = vec![_create_table_reference(t1, None, table_id_counter.next())];
+        // SELECT a, b FROM t1 ...
+        joined_tables[0].mark_column_used(0);
+        joined_tables[0].mark_column_used(1);
+        let table_references = TableReferences::new(joined_tables, vec![]);
+        let index = |name: &str, columns: &[(&str, usize)]| {
+            Arc::new(Index {
+                name: name.to_string(),
+                table_name: "t1".to_string(),
+                columns: columns
+                    .iter()
+                    .map(|(column, pos_in_table)| IndexColumn {
+                        name: column.to_string(),
+                        order: SortOrder::Asc,
+                        pos_in_table: *pos_in_table,
+                        collation: None,
+                        default: None,
+                        expr: None,
+                    })
+                    .collect(),
+                unique: false,
+                ephemeral: false,
+                conflict_clause: None,
+                root_page: 2,
+                has_rowid: true,
+                where_clause: None,
+            })
+        };
+        let mut available_indexes = HashMap::new();
+        available_indexes.insert(
+            "t1".to_string(),
+            vec![
+                index("t1_a", &[("a", 0)]),
+                index("t1_a_b", &[("a", 0), ("b", 1)]),
+            ],
+        );
+
+        // ... WHERE a = 1, and then without a WHERE clause.
+        let seek_where_clause = vec![_create_binary_expr(
+            _create_column_expr(table_references.joined_tables()[0].internal_id, 0, false),
+            ast::Operator::Equals,
+            _create_numeric_literal("1"),
+        )];
+        for (where_clause, is_scan) in [(seek_where_clause, false), (vec![], true)] {
+            let access_methods_arena = RefCell::new(Vec::new());
+            let table_constraints =
+                constraints_from_where_clause(&where_clause, &table_references, &available_indexes)
+                    .unwrap();
+            let BestJoinOrderResult { best_plan, .. } = compute_best_join_order(
+                table_references.joined_tables(),
+                false,
+                None,
+                &table_constraints,
+                &access_methods_arena,
+            )
+            .unwrap()
+            .unwrap();
+            let access_method = &access_methods_arena.borrow()[best_plan.data[0].1];
+            assert_eq!(access_method.is_scan(), is_scan);
+            assert_eq!(access_method.index.as_ref().unwrap().name, "t1_a_b");
+        }
+    }
+
+    #[test]
+    /// Test that [compute_best_join_order] orders joins with too many tables to consider every
+    /// join order greedily, starting from the filtered table instead of the first one.
+    fn test_compute_best_join_order_greedy() {
+        const NUM_TABLES: usize = MAX_TABLES_FOR_EXHAUSTIVE_SEARCH + 2;
+        const FILTERED_TABLE_IDX: usize = NUM_TABLES - 1;
+
+        let mut table_id_counter = TableRefIdCounter::new();
+        let joined_tables: Vec<_> = (0..NUM_TABLES)
+            .map(|i| {
+                let table = _create_btree_table(
+                    &format!("t{i}"),
+                    vec![
+                        _create_column_rowid_alias("id"),
+                        _create_column_of_type("x", Type::Integer),
+                        _create_column_of_type("prev_id", Type::Integer),
+                    ],
+                );
+                let join_info = (i > 0).then_some(JoinInfo {
+                    outer: false,
+                    using: None,
+                });
+                _create_table_reference(table, join_info, table_id_counter.next())
+            })
+            .collect();
+
+        // SELECT * FROM t0, t1, ..., t13 WHERE t13.x = 5 AND t0.id = t1.prev_id AND ...
+        let mut where_clause = vec![_create_binary_expr(
+            _create_column_expr(joined_tables[FILTERED_TABLE_IDX].internal_id, 1, false),
+            ast::Operator::Equals,
+            _create_numeric_literal("5"),
+        )];
+        for i in 0..NUM_TABLES - 1 {
+            where_clause.push(_create_binary_expr(
+                _create_column_expr(joined_tables[i].internal_id, 0, true), // ti.id
+                ast::Operator::Equals,
+                _create_column_expr(joined_tables[i + 1].internal_id, 2, false), // t(i+1).prev_id
+            ));
+        }
+
+        let table_references = TableReferences::new(joined_tables, vec![]);
+        let access_methods_arena = RefCell::new(Vec::new());
+        let available_indexes = HashMap::new();
+        let table_constraints =
+            constraints_from_where_clause(&where_clause, &table_references, &available_indexes)
+                .unwrap();
+
+        let BestJoinOrderResult { best_plan, .. } = compute_best_join_order(
+            table_references.joined_tables(),
+            false,
+            None,
+            &table_constraints,
+            &access_methods_arena,
+        )
+        .unwrap()
+        .unwrap();
+
+        // The filtered table drives the join, and every other table is reached with a rowid seek.
+        let expected_order = (0..NUM_TABLES).rev().collect::<Vec<_>>();
+        assert_eq!(
+            best_plan.table_numbers().collect::<Vec<_>>(),
+            expected_order
+        );
+        let access_method = &access_methods_arena.borrow()[best_plan.data[0].1];
+        assert!(access_method.is_scan());
+        for (_, access_method_index) in best_plan.data.iter().skip(1) {
+            let access_method = &access_methods_arena.borrow()[*access_method_index];
+            assert!(!access_method.is_scan());
+            assert!(access_method.index.is_none());
+        }
+    }
+
     fn _create_column(c: &TestColumn) -> Column {
         Column {
             name: Some(c.name.clone()),
@@ -1607,10 +2084,13 @@ mod tests {
             is_rowid_alias: c.is_rowid_alias,
             primary_key: false,
             notnull: false,
+            notnull_conflict_clause: None,
             default: None,
             unique: false,
+            unique_conflict_clause: None,
             collation: None,
             hidden: false,
+            generated: None,
         }
     }
     fn _create_column_of_type(name: &str, ty: Type) -> Column {
@@ -1646,6 +2126,9 @@ mod tests {
             has_rowid: true,
             is_strict: false,
             unique_sets: None,
+            check_constraints: vec![],
+            primary_key_conflict_clause: None,
+            has_autoincrement: false,
         })
     }
 
@@ -1666,6 +2149,8 @@ mod tests {
             internal_id,
             join_info,
             col_used_mask: ColumnUsedMask::default(),
+            database_id: 0,
+            vtab_order_by: Vec::new(),
         }
     }
 
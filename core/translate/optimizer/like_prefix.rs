@@ -0,0 +1,215 @@
+use std::{cell::Cell, collections::HashMap, sync::Arc};
+
+use turso_sqlite3_parser::ast::{self, LikeOperator};
+
+use crate::{
+    schema::{Affinity, Index},
+    translate::{
+        collate::CollationSeq,
+        expr::sanitize_string,
+        plan::{TableReferences, WhereTerm},
+    },
+};
+
+/// What the LIKE and GLOB operators of a statement do, which tells which of their terms imply a
+/// range of the values they can match.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LikeBehavior {
+    /// Whether LIKE is case sensitive, set by `PRAGMA case_sensitive_like`.
+    pub case_sensitive_like: bool,
+    /// Whether LIKE calls a like() function of the connection instead of the builtin one.
+    pub like_overridden: bool,
+    /// Whether GLOB calls a glob() function of the connection instead of the builtin one.
+    pub glob_overridden: bool,
+}
+
+/// Adds the range terms implied by the LIKE and GLOB terms that match an indexed column against
+/// a pattern starting with a constant prefix, so that the index can be searched for the rows
+/// that may match instead of scanning the whole table.
+/// For example, `name GLOB 'abc*'` implies `name >= 'abc' AND name < 'abd'`.
+///
+/// The range terms are appended to `where_clause`. They are only meant to be used as index
+/// search keys, since the LIKE or GLOB term they come from still filters the rows, so the
+/// caller consumes the ones that no search used.
+pub(crate) fn add_like_prefix_range_terms(
+    where_clause: &mut Vec<WhereTerm>,
+    table_references: &TableReferences,
+    available_indexes: &HashMap<String, Vec<Arc<Index>>>,
+    like_behavior: LikeBehavior,
+) {
+    for i in 0..where_clause.len() {
+        let ast::Expr::Like {
+            lhs,
+            not: false,
+            op,
+            rhs,
+            escape: None,
+        } = &where_clause[i].expr
+        else {
+            continue;
+        };
+        let ast::Expr::Column { table, column, .. } = lhs.as_ref() else {
+            continue;
+        };
+        let ast::Expr::Literal(ast::Literal::String(pattern)) = rhs.as_ref() else {
+            continue;
+        };
+        let Some(table_reference) = table_references.find_joined_table_by_internal_id(*table)
+        else {
+            continue;
+        };
+        // The range only holds for columns whose values are all text, and is only worth
+        // adding if an index can be searched with it.
+        let table_column = &table_reference.columns()[*column];
+        if table_column.affinity() != Affinity::Text {
+            continue;
+        }
+        let is_indexed = available_indexes
+            .get(table_reference.table.get_name())
+            .is_some_and(|indexes| {
+                indexes
+                    .iter()
+                    .any(|index| index.column_table_pos_to_index_pos(*column).is_some())
+            });
+        if !is_indexed {
+            continue;
+        }
+        // Nothing is known about what an overridden function matches
+        let case_sensitive = match op {
+            LikeOperator::Glob if !like_behavior.glob_overridden => true,
+            LikeOperator::Like if !like_behavior.like_overridden => {
+                like_behavior.case_sensitive_like
+            }
+            _ => continue,
+        };
+        let Some((lower_bound, upper_bound)) = prefix_range(
+            &sanitize_string(pattern),
+            *op,
+            case_sensitive,
+            table_column.collation.unwrap_or_default(),
+        ) else {
+            continue;
+        };
+
+        let column_expr = lhs.as_ref().clone();
+        let from_outer_join = where_clause[i].from_outer_join;
+        for (operator, bound) in [
+            (ast::Operator::GreaterEquals, lower_bound),
+            (ast::Operator::Less, upper_bound),
+        ] {
+            where_clause.push(WhereTerm {
+                expr: ast::Expr::Binary(
+                    Box::new(column_expr.clone()),
+                    operator,
+                    Box::new(ast::Expr::Literal(ast::Literal::String(format!(
+                        "'{}'",
+                        bound.replace('\'', "''")
+                    )))),
+                ),
+                from_outer_join,
+                consumed: Cell::new(false),
+            });
+        }
+    }
+}
+
+/// Returns the lower (inclusive) and upper (exclusive) bounds of the values of a column with
+/// the given collation that can match `pattern`, if the pattern starts with a constant prefix
+/// and every matching value is between them.
+///
+/// The upper bound is the prefix with its last character incremented. When the match is not
+/// case sensitive, the values between the bounds include the ones matching the prefix in another
+/// case only if the collation is NOCASE, or if the prefix has no letters.
+fn prefix_range(
+    pattern: &str,
+    op: LikeOperator,
+    case_sensitive: bool,
+    collation: CollationSeq,
+) -> Option<(String, String)> {
+    let wildcards: &[char] = match op {
+        LikeOperator::Glob => &['*', '?', '['],
+        _ => &['%', '_'],
+    };
+    let prefix = &pattern[..pattern.find(wildcards).unwrap_or(pattern.len())];
+    let no_case = collation == CollationSeq::NoCase;
+    let range_is_implied = if case_sensitive {
+        collation == CollationSeq::Binary
+    } else {
+        // LIKE and NOCASE fold the case of ASCII letters alike, with or without the `unicode`
+        // feature.
+        prefix.is_ascii()
+            && (no_case
+                || (collation == CollationSeq::Binary
+                    && !prefix.chars().any(|c| c.is_ascii_alphabetic())))
+    };
+    if !range_is_implied {
+        return None;
+    }
+
+    let mut upper_bound = prefix.to_string();
+    let last = upper_bound.pop()?;
+    // Under NOCASE, letters compare as their lowercase form, so incrementing e.g. 'Z' to '['
+    // would give an upper bound lower than the values starting with 'Z'.
+    let last = if no_case {
+        last.to_ascii_lowercase()
+    } else {
+        last
+    };
+    upper_bound.push(char::from_u32(last as u32 + 1)?);
+    Some((prefix.to_string(), upper_bound))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn range(lower: &str, upper: &str) -> Option<(String, String)> {
+        Some((lower.to_string(), upper.to_string()))
+    }
+
+    #[test]
+    fn test_prefix_range_glob() {
+        let binary = CollationSeq::Binary;
+        assert_eq!(
+            prefix_range("abc*", LikeOperator::Glob, true, binary),
+            range("abc", "abd")
+        );
+        assert_eq!(
+            prefix_range("a?c", LikeOperator::Glob, true, binary),
+            range("a", "b")
+        );
+        assert_eq!(
+            prefix_range("abc", LikeOperator::Glob, true, binary),
+            range("abc", "abd")
+        );
+        assert_eq!(prefix_range("*abc", LikeOperator::Glob, true, binary), None);
+        assert_eq!(
+            prefix_range("abc*", LikeOperator::Glob, true, CollationSeq::NoCase),
+            None
+        );
+    }
+
+    #[test]
+    fn test_prefix_range_like() {
+        let binary = CollationSeq::Binary;
+        let no_case = CollationSeq::NoCase;
+        assert_eq!(
+            prefix_range("abc%", LikeOperator::Like, true, binary),
+            range("abc", "abd")
+        );
+        assert_eq!(
+            prefix_range("abc%", LikeOperator::Like, false, binary),
+            None
+        );
+        assert_eq!(
+            prefix_range("12_4%", LikeOperator::Like, false, binary),
+            range("12", "13")
+        );
+        assert_eq!(
+            prefix_range("abZ%", LikeOperator::Like, false, no_case),
+            range("abZ", "ab{")
+        );
+        assert_eq!(prefix_range("é%", LikeOperator::Like, false, no_case), None);
+        assert_eq!(prefix_range("%abc", LikeOperator::Like, true, binary), None);
+    }
+}
@@ -1,17 +1,19 @@
-use std::{cell::RefCell, cmp::Ordering, collections::HashMap, sync::Arc};
+use std::{borrow::Cow, cell::RefCell, cmp::Ordering, collections::HashMap, sync::Arc};
 
 use constraints::{
-    constraints_from_where_clause, usable_constraints_for_join_order, Constraint, ConstraintRef,
+    apply_table_stats, constraints_from_where_clause, ephemeral_index_seek_refs,
+    usable_constraints_for_join_order, Constraint, ConstraintRef,
 };
 use cost::Cost;
 use join::{compute_best_join_order, BestJoinOrderResult};
 use lift_common_subexpressions::lift_common_subexpressions_from_binary_or_terms;
+use like_prefix::{add_like_prefix_range_terms, LikeBehavior};
 use order::{compute_order_target, plan_satisfies_order_target, EliminatesSortBy};
-use turso_sqlite3_parser::ast::{self, fmt::ToTokens as _, Expr, SortOrder};
+use turso_ext::OrderByInfo;
+use turso_sqlite3_parser::ast::{self, fmt::ToTokens as _, Expr, SortOrder, SubqueryType};
 
 use crate::{
-    parameters::PARAM_PREFIX,
-    schema::{Index, IndexColumn, Schema, Table},
+    schema::{Index, IndexColumn, Schema, Table, EXPR_INDEX_COLUMN},
     translate::{expr::is_double_quoted_identifier, expr::walk_expr_mut, plan::TerminationKey},
     types::SeekOp,
     Result,
@@ -20,9 +22,12 @@ use crate::{
 use super::{
     emitter::Resolver,
     plan::{
-        DeletePlan, GroupBy, IterationDirection, JoinOrderMember, JoinedTable, Operation, Plan,
-        Search, SeekDef, SeekKey, SelectPlan, TableReferences, UpdatePlan, WhereTerm,
+        vtab_constraints, DeletePlan, GroupBy, IterationDirection, JoinOrderMember, JoinedTable,
+        Operation, Plan, Search, SeekDef, SeekKey, SelectPlan, TableReferences, UpdatePlan,
+        WhereTerm,
     },
+    planner::try_fold_expr_to_i64,
+    update::index_has_updated_column,
 };
 
 pub(crate) mod access_method;
@@ -30,21 +35,28 @@ pub(crate) mod constraints;
 pub(crate) mod cost;
 pub(crate) mod join;
 pub(crate) mod lift_common_subexpressions;
+pub(crate) mod like_prefix;
 pub(crate) mod order;
+pub(crate) mod partial_index;
 
 #[tracing::instrument(skip_all, level = tracing::Level::DEBUG)]
-pub fn optimize_plan(plan: &mut Plan, schema: &Schema) -> Result<()> {
+pub fn optimize_plan(plan: &mut Plan, schema: &Schema, like_behavior: LikeBehavior) -> Result<()> {
     match plan {
-        Plan::Select(plan) => optimize_select_plan(plan, schema)?,
+        Plan::Select(plan) => optimize_select_plan(plan, schema, like_behavior)?,
         Plan::Delete(plan) => optimize_delete_plan(plan, schema)?,
-        Plan::Update(plan) => optimize_update_plan(plan, schema)?,
+        Plan::Update(plan) => optimize_update_plan(plan, schema, like_behavior)?,
         Plan::CompoundSelect {
-            left, right_most, ..
+            left,
+            right_most,
+            limit,
+            offset,
+            ..
         } => {
-            optimize_select_plan(right_most, schema)?;
+            optimize_select_plan(right_most, schema, like_behavior)?;
             for (plan, _) in left {
-                optimize_select_plan(plan, schema)?;
+                optimize_select_plan(plan, schema, like_behavior)?;
             }
+            rewrite_limit_exprs(limit, offset)?;
         }
     }
     // When debug tracing is enabled, print the optimized plan as a SQL string for debugging
@@ -61,8 +73,15 @@ pub fn optimize_plan(plan: &mut Plan, schema: &Schema) -> Result<()> {
  * TODO: these could probably be done in less passes,
  * but having them separate makes them easier to understand
  */
-pub fn optimize_select_plan(plan: &mut SelectPlan, schema: &Schema) -> Result<()> {
-    optimize_subqueries(plan, schema)?;
+pub fn optimize_select_plan(
+    plan: &mut SelectPlan,
+    schema: &Schema,
+    like_behavior: LikeBehavior,
+) -> Result<()> {
+    optimize_subqueries(&mut plan.table_references, schema, like_behavior)?;
+    for subquery in plan.non_from_clause_subqueries.iter_mut() {
+        optimize_select_plan(&mut subquery.plan, schema, like_behavior)?;
+    }
     rewrite_exprs_select(plan)?;
     if let ConstantConditionEliminationResult::ImpossibleCondition =
         eliminate_constant_conditions(&mut plan.where_clause)?
@@ -71,22 +90,89 @@ pub fn optimize_select_plan(plan: &mut SelectPlan, schema: &Schema) -> Result<()
         return Ok(());
     }
 
+    let available_indexes = available_indexes(schema, &plan.table_references);
+    // Aggregates, window functions and DISTINCT may need more rows than the LIMIT to produce its rows.
+    let row_limit = if plan.aggregates.is_empty()
+        && plan.window.is_none()
+        && !plan.distinctness.is_distinct()
+    {
+        row_limit(plan.limit.as_deref(), plan.offset.as_deref())
+    } else {
+        None
+    };
+    // The rows of a query with window functions are sorted by the window before its ORDER BY
+    // is applied, so the order they are read in cannot eliminate it.
+    let mut window_order_by = None;
+    let order_by = if plan.window.is_some() {
+        &mut window_order_by
+    } else {
+        &mut plan.order_by
+    };
     let best_join_order = optimize_table_access(
         schema,
         &mut plan.table_references,
-        &schema.indexes,
+        false,
+        &available_indexes,
         &mut plan.where_clause,
-        &mut plan.order_by,
+        order_by,
         &mut plan.group_by,
+        row_limit,
+        like_behavior,
     )?;
 
     if let Some(best_join_order) = best_join_order {
         plan.join_order = best_join_order;
     }
 
+    offer_order_by_to_vtab(plan);
+
     Ok(())
 }
 
+/// Offers the ORDER BY of a query that only reads a virtual table to the xBestIndex of its
+/// module, along with the constraints the main loop will offer. If the module consumes it,
+/// the rows are returned in that order and the sort is removed.
+fn offer_order_by_to_vtab(plan: &mut SelectPlan) {
+    let Some(order_by) = &plan.order_by else {
+        return;
+    };
+    if plan.join_order.len() != 1
+        || plan.group_by.is_some()
+        || !plan.aggregates.is_empty()
+        || plan.window.is_some()
+    {
+        return;
+    }
+    let table_idx = plan.join_order[0].original_idx;
+    let table = &plan.table_references.joined_tables()[table_idx];
+    let Some(vtab) = table.virtual_table() else {
+        return;
+    };
+    let Some(order_by_info) = order_by
+        .iter()
+        .map(|(expr, order)| match expr {
+            Expr::Column { table, column, .. } if *table == plan.join_order[0].table_id => {
+                Some(OrderByInfo {
+                    column_index: *column as u32,
+                    desc: *order == SortOrder::Desc,
+                })
+            }
+            _ => None,
+        })
+        .collect::<Option<Vec<_>>>()
+    else {
+        return;
+    };
+    let constraints = vtab_constraints(&plan.where_clause, 0, table_idx, &plan.join_order);
+    if vtab
+        .best_index(&constraints, &order_by_info)
+        .order_by_consumed
+    {
+        plan.order_by = None;
+        plan.table_references.joined_tables_mut()[table_idx].vtab_order_by = order_by_info;
+    }
+}
+
 fn optimize_delete_plan(plan: &mut DeletePlan, _schema: &Schema) -> Result<()> {
     rewrite_exprs_delete(plan)?;
     if let ConstantConditionEliminationResult::ImpossibleCondition =
@@ -109,7 +195,15 @@ fn optimize_delete_plan(plan: &mut DeletePlan, _schema: &Schema) -> Result<()> {
     Ok(())
 }
 
-fn optimize_update_plan(plan: &mut UpdatePlan, schema: &Schema) -> Result<()> {
+fn optimize_update_plan(
+    plan: &mut UpdatePlan,
+    schema: &Schema,
+    like_behavior: LikeBehavior,
+) -> Result<()> {
+    optimize_subqueries(&mut plan.table_references, schema, like_behavior)?;
+    for subquery in plan.non_from_clause_subqueries.iter_mut() {
+        optimize_select_plan(&mut subquery.plan, schema, like_behavior)?;
+    }
     rewrite_exprs_update(plan)?;
     if let ConstantConditionEliminationResult::ImpossibleCondition =
         eliminate_constant_conditions(&mut plan.where_clause)?
@@ -117,21 +211,82 @@ fn optimize_update_plan(plan: &mut UpdatePlan, schema: &Schema) -> Result<()> {
         plan.contains_constant_false_condition = true;
         return Ok(());
     }
-    let _ = optimize_table_access(
+    // An index whose columns are updated can't be iterated while the UPDATE runs,
+    // see prepare_update_plan().
+    let target_table = plan.table_references.joined_tables()[0].table.clone();
+    let mut available_indexes = available_indexes(schema, &plan.table_references).into_owned();
+    if let Some(indexes) = available_indexes.get_mut(target_table.get_name()) {
+        indexes.retain(|index| !index_has_updated_column(&target_table, index, &plan.set_clauses));
+    }
+    // The target table must drive the outermost loop so that each of its rows
+    // is updated at most once, even if it matches several rows of the FROM clause.
+    let best_join_order = optimize_table_access(
         schema,
         &mut plan.table_references,
-        &schema.indexes,
+        true,
+        &available_indexes,
         &mut plan.where_clause,
         &mut plan.order_by,
         &mut None,
+        row_limit(plan.limit.as_deref(), plan.offset.as_deref()),
+        like_behavior,
     )?;
+    if let Some(best_join_order) = best_join_order {
+        plan.join_order = best_join_order;
+    }
     Ok(())
 }
 
-fn optimize_subqueries(plan: &mut SelectPlan, schema: &Schema) -> Result<()> {
-    for table in plan.table_references.joined_tables_mut() {
+/// Returns the number of rows a LIMIT and OFFSET read, if there is a LIMIT and both are
+/// constants. A negative LIMIT means there is none, and a negative OFFSET is ignored.
+fn row_limit(limit: Option<&ast::Expr>, offset: Option<&ast::Expr>) -> Option<usize> {
+    let limit = usize::try_from(try_fold_expr_to_i64(limit?)?).ok()?;
+    let offset = match offset {
+        Some(offset) => try_fold_expr_to_i64(offset)?.max(0) as usize,
+        None => 0,
+    };
+    Some(limit.saturating_add(offset))
+}
+
+/// Returns the indexes of the tables in `table_references`, by table name. Indexes are looked up
+/// by table name, so when tables with the same name from different databases are joined, none
+/// of their indexes are used.
+fn available_indexes<'a>(
+    schema: &'a Schema,
+    table_references: &TableReferences,
+) -> Cow<'a, HashMap<String, Vec<Arc<Index>>>> {
+    let joined_tables = table_references.joined_tables();
+    if joined_tables.iter().all(|table| table.database_id == 0) {
+        return Cow::Borrowed(&schema.indexes);
+    }
+    let mut available_indexes = schema.indexes.clone();
+    let mut databases = HashMap::new();
+    for table in joined_tables {
+        let name = table.table.get_name();
+        let ambiguous = *databases.entry(name).or_insert(table.database_id) != table.database_id;
+        match schema.database(table.database_id).indexes.get(name) {
+            Some(indexes) if !ambiguous => {
+                available_indexes.insert(name.to_string(), indexes.clone());
+            }
+            _ => {
+                available_indexes.remove(name);
+            }
+        }
+    }
+    Cow::Owned(available_indexes)
+}
+
+fn optimize_subqueries(
+    table_references: &mut TableReferences,
+    schema: &Schema,
+    like_behavior: LikeBehavior,
+) -> Result<()> {
+    for table in table_references.joined_tables_mut() {
         if let Table::FromClauseSubquery(from_clause_subquery) = &mut table.table {
-            optimize_select_plan(&mut from_clause_subquery.plan, schema)?;
+            optimize_select_plan(&mut from_clause_subquery.plan, schema, like_behavior)?;
+            if let Some(recursive) = &mut from_clause_subquery.recursive {
+                optimize_select_plan(&mut recursive.plan, schema, like_behavior)?;
+            }
         }
     }
 
@@ -148,26 +303,53 @@ fn optimize_subqueries(plan: &mut SelectPlan, schema: &Schema) -> Result<()> {
 /// - Removes predicates from the `where_clause` that are now redundant due to the selected access methods.
 /// - Removes sorting operations if the selected join order and access methods satisfy the [crate::translate::optimizer::order::OrderTarget].
 ///
+/// If `first_table_is_outermost` is true, the first joined table is kept as the outermost loop.
+/// `like_behavior` tells which LIKE and GLOB terms can be used for index searches, see
+/// [add_like_prefix_range_terms].
+///
 /// Returns the join order if it was optimized, or None if the default join order was considered best.
+#[allow(clippy::too_many_arguments)]
 fn optimize_table_access(
     schema: &Schema,
     table_references: &mut TableReferences,
+    first_table_is_outermost: bool,
     available_indexes: &HashMap<String, Vec<Arc<Index>>>,
-    where_clause: &mut [WhereTerm],
+    where_clause: &mut Vec<WhereTerm>,
     order_by: &mut Option<Vec<(ast::Expr, SortOrder)>>,
     group_by: &mut Option<GroupBy>,
+    row_limit: Option<usize>,
+    like_behavior: LikeBehavior,
 ) -> Result<Option<Vec<JoinOrderMember>>> {
+    let available_indexes =
+        partial_index::usable_indexes(available_indexes, table_references, where_clause)?;
+    let available_indexes = &*available_indexes;
+    let num_where_terms = where_clause.len();
+    add_like_prefix_range_terms(
+        where_clause,
+        table_references,
+        available_indexes,
+        like_behavior,
+    );
     let access_methods_arena = RefCell::new(Vec::new());
-    let maybe_order_target = compute_order_target(order_by, group_by.as_mut());
-    let constraints_per_table =
+    let maybe_order_target =
+        compute_order_target(order_by, group_by.as_mut(), row_limit, table_references);
+    let mut constraints_per_table =
         constraints_from_where_clause(where_clause, table_references, available_indexes)?;
+    if first_table_is_outermost {
+        // The first table is the target of an UPDATE, whose index cursors are opened for
+        // writing, so it isn't read through the union of several index searches.
+        constraints_per_table[0].or_constraints.clear();
+    }
+    apply_table_stats(&mut constraints_per_table, table_references, schema);
     let Some(best_join_order_result) = compute_best_join_order(
         table_references.joined_tables_mut(),
+        first_table_is_outermost,
         maybe_order_target.as_ref(),
         &constraints_per_table,
         &access_methods_arena,
     )?
     else {
+        where_clause.truncate(num_where_terms);
         return Ok(None);
     };
 
@@ -196,14 +378,17 @@ fn optimize_table_access(
     };
 
     // Eliminate sorting if possible.
+    let mut ordered_tables = Vec::new();
     if let Some(order_target) = maybe_order_target {
         let satisfies_order_target = plan_satisfies_order_target(
             &best_plan,
             &access_methods_arena,
             joined_tables,
+            &constraints_per_table,
             &order_target,
         );
         if satisfies_order_target {
+            ordered_tables.extend(order_target.0.iter().map(|column| column.table_id));
             match order_target.1 {
                 EliminatesSortBy::Group => {
                     let _ = group_by.as_mut().and_then(|g| g.sort_order.take());
@@ -240,15 +425,79 @@ fn optimize_table_access(
     for (i, join_order_member) in best_join_order.iter().enumerate() {
         let table_idx = join_order_member.original_idx;
         let access_method = &access_methods_arena.borrow()[best_access_methods[i]];
+        if let Some(multi_index_or) = &access_method.multi_index_or {
+            // The OR term is not consumed: it still filters the rows found by its branches.
+            let table_reference = &joined_tables[table_idx];
+            let branches = multi_index_or
+                .or_constraint
+                .branches
+                .iter()
+                .zip(multi_index_or.indexes.iter())
+                .map(|(branch, index)| {
+                    Ok(match index {
+                        None if branch.operator == ast::Operator::Equals => Search::RowidEq {
+                            cmp_expr: branch.expr.clone(),
+                        },
+                        None => Search::Seek {
+                            index: None,
+                            seek_def: build_seek_def(
+                                branch.operator,
+                                IterationDirection::Forwards,
+                                vec![(branch.expr.clone(), SortOrder::Asc)],
+                            )?,
+                        },
+                        Some(index) => Search::Seek {
+                            index: Some(index.clone()),
+                            seek_def: build_seek_def(
+                                branch.operator,
+                                IterationDirection::Forwards,
+                                vec![(branch.expr.clone(), index.columns[0].order)],
+                            )?,
+                        },
+                    })
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let rowids = Arc::new(Index {
+                name: format!(
+                    "ephemeral_rowids_{}_{}",
+                    table_reference.table.get_name(),
+                    table_reference.internal_id
+                ),
+                table_name: table_reference.table.get_name().to_string(),
+                columns: vec![IndexColumn {
+                    name: "rowid".to_string(),
+                    order: SortOrder::Asc,
+                    pos_in_table: 0,
+                    collation: None,
+                    default: None,
+                    expr: None,
+                }],
+                unique: false,
+                ephemeral: true,
+                conflict_clause: None,
+                root_page: 0,
+                has_rowid: false,
+                where_clause: None,
+            });
+            joined_tables[table_idx].op =
+                Operation::Search(Search::MultiIndexOr { branches, rowids });
+            continue;
+        }
         if access_method.is_scan() {
             let try_to_build_ephemeral_index = if schema.indexes_enabled() {
                 let is_leftmost_table = i == 0;
                 let uses_index = access_method.index.is_some();
+                // An ephemeral index would read the rows in another order than the one that
+                // replaces the sorting.
+                let is_ordered = ordered_tables.contains(&join_order_member.table_id);
                 let source_table_does_not_support_search = matches!(
                     &joined_tables[table_idx].table,
                     Table::FromClauseSubquery(_) | Table::Virtual(_)
                 );
-                !is_leftmost_table && !uses_index && !source_table_does_not_support_search
+                !is_leftmost_table
+                    && !uses_index
+                    && !is_ordered
+                    && !source_table_does_not_support_search
             } else {
                 false
             };
@@ -272,17 +521,30 @@ fn optimize_table_access(
                 };
                 continue;
             };
+            // The ephemeral index only has the columns of the table, not indexed expressions,
+            // and compares them with their own collations.
             let temp_constraint_refs = (0..table_constraints.constraints.len())
+                .filter(|&i| {
+                    let constraint = &table_constraints.constraints[i];
+                    constraint.table_col_pos != EXPR_INDEX_COLUMN
+                        && constraint.collation
+                            == joined_tables[table_idx].columns()[constraint.table_col_pos]
+                                .collation
+                                .unwrap_or_default()
+                })
                 .map(|i| ConstraintRef {
                     constraint_vec_pos: i,
                     index_col_pos: table_constraints.constraints[i].table_col_pos,
                     sort_order: SortOrder::Asc,
                 })
                 .collect::<Vec<_>>();
-            let usable_constraint_refs = usable_constraints_for_join_order(
+            let usable_constraint_refs = ephemeral_index_seek_refs(
                 &table_constraints.constraints,
-                &temp_constraint_refs,
-                &best_join_order[..=i],
+                usable_constraints_for_join_order(
+                    &table_constraints.constraints,
+                    &temp_constraint_refs,
+                    &best_join_order[..=i],
+                ),
             );
             if usable_constraint_refs.is_empty() {
                 joined_tables[table_idx].op = Operation::Scan {
@@ -294,14 +556,14 @@ fn optimize_table_access(
             let ephemeral_index = ephemeral_index_build(
                 &joined_tables[table_idx],
                 &table_constraints.constraints,
-                usable_constraint_refs,
+                &usable_constraint_refs,
             );
             let ephemeral_index = Arc::new(ephemeral_index);
             joined_tables[table_idx].op = Operation::Search(Search::Seek {
                 index: Some(ephemeral_index),
                 seek_def: build_seek_def_from_constraints(
                     &table_constraints.constraints,
-                    usable_constraint_refs,
+                    &usable_constraint_refs,
                     access_method.iter_dir,
                     where_clause,
                 )?,
@@ -334,8 +596,8 @@ fn optimize_table_access(
                 continue;
             }
             assert!(
-                constraint_refs.len() == 1,
-                "expected exactly one constraint for rowid seek, got {constraint_refs:?}"
+                constraint_refs.len() <= 2,
+                "expected at most two constraints for rowid seek, got {constraint_refs:?}"
             );
             let constraint = &constraints_per_table[table_idx].constraints
                 [constraint_refs[0].constraint_vec_pos];
@@ -356,6 +618,12 @@ fn optimize_table_access(
         }
     }
 
+    // The range terms added for LIKE and GLOB prefixes are not evaluated: the terms they were
+    // derived from filter the rows.
+    for term in where_clause[num_where_terms..].iter() {
+        term.consumed.set(true);
+    }
+
     Ok(Some(best_join_order))
 }
 
@@ -398,25 +666,44 @@ fn eliminate_constant_conditions(
 }
 
 fn rewrite_exprs_select(plan: &mut SelectPlan) -> Result<()> {
-    let mut param_count = 1;
     for rc in plan.result_columns.iter_mut() {
-        rewrite_expr(&mut rc.expr, &mut param_count)?;
+        rewrite_expr(&mut rc.expr)?;
     }
     for agg in plan.aggregates.iter_mut() {
-        rewrite_expr(&mut agg.original_expr, &mut param_count)?;
+        rewrite_expr(&mut agg.original_expr)?;
     }
     lift_common_subexpressions_from_binary_or_terms(&mut plan.where_clause)?;
     for cond in plan.where_clause.iter_mut() {
-        rewrite_expr(&mut cond.expr, &mut param_count)?;
+        rewrite_expr(&mut cond.expr)?;
     }
     if let Some(group_by) = &mut plan.group_by {
         for expr in group_by.exprs.iter_mut() {
-            rewrite_expr(expr, &mut param_count)?;
+            rewrite_expr(expr)?;
         }
     }
     if let Some(order_by) = &mut plan.order_by {
         for (expr, _) in order_by.iter_mut() {
-            rewrite_expr(expr, &mut param_count)?;
+            rewrite_expr(expr)?;
+        }
+    }
+    rewrite_limit_exprs(&mut plan.limit, &mut plan.offset)?;
+    // The expressions of the window functions are rewritten like the result columns, so that
+    // they are found when the result columns are translated.
+    if let Some(window) = &mut plan.window {
+        for expr in window.partition_by.iter_mut() {
+            rewrite_expr(expr)?;
+        }
+        for (expr, _) in window.order_by.iter_mut() {
+            rewrite_expr(expr)?;
+        }
+        for function in window.functions.iter_mut() {
+            rewrite_expr(&mut function.original_expr)?;
+            for arg in function.args.iter_mut() {
+                rewrite_expr(arg)?;
+            }
+            if let Some(filter) = &mut function.filter {
+                rewrite_expr(filter)?;
+            }
         }
     }
 
@@ -424,31 +711,41 @@ fn rewrite_exprs_select(plan: &mut SelectPlan) -> Result<()> {
 }
 
 fn rewrite_exprs_delete(plan: &mut DeletePlan) -> Result<()> {
-    let mut param_idx = 1;
     for cond in plan.where_clause.iter_mut() {
-        rewrite_expr(&mut cond.expr, &mut param_idx)?;
+        rewrite_expr(&mut cond.expr)?;
     }
+    rewrite_limit_exprs(&mut plan.limit, &mut plan.offset)?;
     Ok(())
 }
 
 fn rewrite_exprs_update(plan: &mut UpdatePlan) -> Result<()> {
-    let mut param_idx = 1;
     for (_, expr) in plan.set_clauses.iter_mut() {
-        rewrite_expr(expr, &mut param_idx)?;
+        rewrite_expr(expr)?;
     }
     for cond in plan.where_clause.iter_mut() {
-        rewrite_expr(&mut cond.expr, &mut param_idx)?;
+        rewrite_expr(&mut cond.expr)?;
     }
     if let Some(order_by) = &mut plan.order_by {
         for (expr, _) in order_by.iter_mut() {
-            rewrite_expr(expr, &mut param_idx)?;
+            rewrite_expr(expr)?;
         }
     }
     if let Some(rc) = plan.returning.as_mut() {
         for rc in rc.iter_mut() {
-            rewrite_expr(&mut rc.expr, &mut param_idx)?;
+            rewrite_expr(&mut rc.expr)?;
         }
     }
+    rewrite_limit_exprs(&mut plan.limit, &mut plan.offset)?;
+    Ok(())
+}
+
+fn rewrite_limit_exprs(
+    limit: &mut Option<Box<ast::Expr>>,
+    offset: &mut Option<Box<ast::Expr>>,
+) -> Result<()> {
+    for expr in limit.iter_mut().chain(offset.iter_mut()) {
+        rewrite_expr(expr)?;
+    }
     Ok(())
 }
 
@@ -522,7 +819,8 @@ impl Optimizable for ast::Expr {
                     return true;
                 }
 
-                let table_ref = tables.find_joined_table_by_internal_id(*table).unwrap();
+                // The column may belong to the outer query of a correlated subquery.
+                let table_ref = tables.find_table_by_internal_id(*table).unwrap();
                 let columns = table_ref.columns();
                 let column = &columns[*column];
                 column.primary_key || column.notnull
@@ -555,7 +853,9 @@ impl Optimizable for ast::Expr {
                 panic!("Do not call is_nonnull before Qualified has been rewritten as Column")
             }
             Expr::Raise(..) => false,
+            Expr::Register(..) => false,
             Expr::Subquery(..) => false,
+            Expr::SubqueryResult { query_type, .. } => *query_type == SubqueryType::Exists,
             Expr::Unary(_, expr) => expr.is_nonnull(tables),
             Expr::Variable(..) => false,
         }
@@ -638,8 +938,12 @@ impl Optimizable for ast::Expr {
             Expr::Qualified(_, _) => {
                 panic!("Qualified should have been rewritten as Column")
             }
-            Expr::Raise(_, expr) => expr.as_ref().is_none_or(|expr| expr.is_constant(resolver)),
+            // RAISE() has the side effect of aborting the statement, so it must not be hoisted.
+            Expr::Raise(..) => false,
+            Expr::Register(_) => false,
             Expr::Subquery(_) => false,
+            // A subquery reads tables, so its result is never a compile-time constant.
+            Expr::SubqueryResult { .. } => false,
             Expr::Unary(_, expr) => expr.is_constant(resolver),
             Expr::Variable(_) => false,
         }
@@ -778,6 +1082,7 @@ fn ephemeral_index_build(
             pos_in_table: i,
             collation: c.collation,
             default: c.default.clone(),
+            expr: None,
         })
         // only include columns that are used in the query
         .filter(|c| table_reference.column_is_used(c.pos_in_table))
@@ -808,12 +1113,14 @@ fn ephemeral_index_build(
         columns: ephemeral_columns,
         unique: false,
         ephemeral: true,
+        conflict_clause: None,
         table_name: table_reference.table.get_name().to_string(),
         root_page: 0,
         has_rowid: table_reference
             .table
             .btree()
             .is_some_and(|btree| btree.has_rowid),
+        where_clause: None,
     };
 
     ephemeral_index
@@ -830,6 +1137,42 @@ pub fn build_seek_def_from_constraints(
         !constraint_refs.is_empty(),
         "cannot build seek def from empty list of constraint refs"
     );
+    // When the last column is constrained at both ends of a range, e.g. (x=10 AND y>20 AND y<30),
+    // one of the bounds is used for seeking and the other one for terminating the scan.
+    let num_refs = constraint_refs.len();
+    if num_refs >= 2
+        && constraint_refs[num_refs - 1].index_col_pos
+            == constraint_refs[num_refs - 2].index_col_pos
+    {
+        let prefix = &constraint_refs[..num_refs - 2];
+        let mut seek_defs = [num_refs - 2, num_refs - 1].map(|bound| {
+            let refs = prefix
+                .iter()
+                .chain(std::iter::once(&constraint_refs[bound]));
+            build_seek_def_from_constraints(
+                constraints,
+                &refs.cloned().collect::<Vec<_>>(),
+                iter_dir,
+                where_clause,
+            )
+        });
+        let key_len = num_refs - 1;
+        // For a given iteration direction and index column order, exactly one of the bounds
+        // is used in full by the seek, and the other one by the termination.
+        if seek_defs[0]
+            .as_ref()
+            .is_ok_and(|def| def.seek.as_ref().is_none_or(|seek| seek.len < key_len))
+        {
+            seek_defs.swap(0, 1);
+        }
+        let [seek_def, termination_def] = seek_defs;
+        let mut seek_def = seek_def?;
+        let mut termination_def = termination_def?;
+        seek_def.termination = termination_def.termination;
+        seek_def.termination_last_key = termination_def.key.pop().map(|(expr, _)| expr);
+        return Ok(seek_def);
+    }
+
     // Extract the key values and operators
     let key = constraint_refs
         .iter()
@@ -892,6 +1235,7 @@ fn build_seek_def(
         (IterationDirection::Forwards, ast::Operator::Equals) => SeekDef {
             key,
             iter_dir,
+            termination_last_key: None,
             seek: Some(SeekKey {
                 len: key_len,
                 null_pad: false,
@@ -926,6 +1270,7 @@ fn build_seek_def(
             SeekDef {
                 key,
                 iter_dir,
+                termination_last_key: None,
                 seek: if seek_key_len > 0 {
                     Some(SeekKey {
                         len: seek_key_len,
@@ -974,6 +1319,7 @@ fn build_seek_def(
             SeekDef {
                 key,
                 iter_dir,
+                termination_last_key: None,
                 seek: if seek_key_len > 0 {
                     Some(SeekKey {
                         len: seek_key_len,
@@ -1022,6 +1368,7 @@ fn build_seek_def(
             SeekDef {
                 key,
                 iter_dir,
+                termination_last_key: None,
                 seek: if seek_key_len > 0 {
                     Some(SeekKey {
                         len: seek_key_len,
@@ -1065,6 +1412,7 @@ fn build_seek_def(
             SeekDef {
                 key,
                 iter_dir,
+                termination_last_key: None,
                 seek: if seek_key_len > 0 {
                     Some(SeekKey {
                         len: seek_key_len,
@@ -1093,6 +1441,7 @@ fn build_seek_def(
         (IterationDirection::Backwards, ast::Operator::Equals) => SeekDef {
             key,
             iter_dir,
+            termination_last_key: None,
             seek: Some(SeekKey {
                 len: key_len,
                 op: SeekOp::LE { eq_only: true },
@@ -1132,6 +1481,7 @@ fn build_seek_def(
             SeekDef {
                 key,
                 iter_dir,
+                termination_last_key: None,
                 seek: if seek_key_len > 0 {
                     Some(SeekKey {
                         len: seek_key_len,
@@ -1180,6 +1530,7 @@ fn build_seek_def(
             SeekDef {
                 key,
                 iter_dir,
+                termination_last_key: None,
                 seek: if seek_key_len > 0 {
                     Some(SeekKey {
                         len: seek_key_len,
@@ -1228,6 +1579,7 @@ fn build_seek_def(
             SeekDef {
                 key,
                 iter_dir,
+                termination_last_key: None,
                 seek: if seek_key_len > 0 {
                     Some(SeekKey {
                         len: seek_key_len,
@@ -1276,6 +1628,7 @@ fn build_seek_def(
             SeekDef {
                 key,
                 iter_dir,
+                termination_last_key: None,
                 seek: if seek_key_len > 0 {
                     Some(SeekKey {
                         len: seek_key_len,
@@ -1302,7 +1655,7 @@ fn build_seek_def(
     })
 }
 
-pub fn rewrite_expr(top_level_expr: &mut ast::Expr, param_idx: &mut usize) -> Result<()> {
+pub fn rewrite_expr(top_level_expr: &mut ast::Expr) -> Result<()> {
     walk_expr_mut(top_level_expr, &mut |expr: &mut ast::Expr| -> Result<()> {
         match expr {
             ast::Expr::Id(id) => {
@@ -1315,14 +1668,6 @@ pub fn rewrite_expr(top_level_expr: &mut ast::Expr, param_idx: &mut usize) -> Re
                     *expr = ast::Expr::Literal(ast::Literal::Numeric(0.to_string()));
                 }
             }
-            ast::Expr::Variable(var) => {
-                if var.is_empty() {
-                    // rewrite anonymous variables only, ensure that the `param_idx` starts at 1 and
-                    // all the expressions are rewritten in the order they come in the statement
-                    *expr = ast::Expr::Variable(format!("{PARAM_PREFIX}{param_idx}"));
-                    *param_idx += 1;
-                }
-            }
             ast::Expr::Between {
                 lhs,
                 not,
@@ -1,20 +1,30 @@
-use std::cell::RefCell;
+use std::{cell::RefCell, iter::Peekable};
 
 use turso_sqlite3_parser::ast::{self, SortOrder, TableInternalId};
 
 use crate::{
-    translate::plan::{GroupBy, IterationDirection, JoinedTable},
+    schema::Index,
+    translate::{
+        collate::CollationSeq,
+        plan::{GroupBy, IterationDirection, JoinedTable, TableReferences},
+    },
     util::exprs_are_equivalent,
 };
 
-use super::{access_method::AccessMethod, join::JoinN};
+use super::{
+    access_method::AccessMethod,
+    constraints::{ConstraintRef, TableConstraints},
+    join::JoinN,
+};
 
 #[derive(Debug, PartialEq, Clone)]
-/// A convenience struct for representing a (table_no, column_no, [SortOrder]) tuple.
+/// A convenience struct for representing a (table_no, column_no, [SortOrder]) tuple, along
+/// with the collation the column is ordered with.
 pub struct ColumnOrder {
     pub table_id: TableInternalId,
     pub column_no: usize,
     pub order: SortOrder,
+    pub collation: CollationSeq,
 }
 
 #[derive(Debug, PartialEq, Clone)]
@@ -29,36 +39,50 @@ pub enum EliminatesSortBy {
 /// An [OrderTarget] is considered in join optimization and index selection,
 /// so that if a given join ordering and its access methods satisfy the [OrderTarget],
 /// then the join ordering and its access methods are preferred, all other things being equal.
-pub struct OrderTarget(pub Vec<ColumnOrder>, pub EliminatesSortBy);
+pub struct OrderTarget(
+    pub Vec<ColumnOrder>,
+    pub EliminatesSortBy,
+    /// The number of ordered rows the query needs, if it has a LIMIT: reading the rows in order
+    /// lets the loop stop after them, instead of reading all the rows to sort them.
+    pub Option<usize>,
+);
 
 impl OrderTarget {
+    /// Makes a target of the terms of `list` if they are all columns, with or without a
+    /// COLLATE operator. Like when sorting, a column without one is ordered with its own
+    /// collation.
     fn maybe_from_iterator<'a>(
-        list: impl Iterator<Item = (&'a ast::Expr, SortOrder)> + Clone,
+        list: impl Iterator<Item = (&'a ast::Expr, SortOrder)>,
         eliminates_sort: EliminatesSortBy,
+        row_limit: Option<usize>,
+        table_references: &TableReferences,
     ) -> Option<Self> {
-        if list.clone().count() == 0 {
-            return None;
-        }
-        if list
-            .clone()
-            .any(|(expr, _)| !matches!(expr, ast::Expr::Column { .. }))
-        {
-            return None;
-        }
-        Some(OrderTarget(
-            list.map(|(expr, order)| {
+        let columns = list
+            .map(|(expr, order)| {
+                let (expr, collation) = match expr {
+                    ast::Expr::Collate(expr, collation) => {
+                        (expr.as_ref(), Some(CollationSeq::from_name(collation)))
+                    }
+                    expr => (expr, None),
+                };
                 let ast::Expr::Column { table, column, .. } = expr else {
-                    unreachable!();
+                    return None;
                 };
-                ColumnOrder {
+                let table_column = table_references
+                    .find_table_by_internal_id(*table)?
+                    .get_column_at(*column)?;
+                Some(ColumnOrder {
                     table_id: *table,
                     column_no: *column,
                     order,
-                }
+                    collation: collation.or(table_column.collation).unwrap_or_default(),
+                })
             })
-            .collect(),
-            eliminates_sort,
-        ))
+            .collect::<Option<Vec<_>>>()?;
+        if columns.is_empty() {
+            return None;
+        }
+        Some(OrderTarget(columns, eliminates_sort, row_limit))
     }
 }
 
@@ -67,11 +91,16 @@ impl OrderTarget {
 /// but also returns the results in an order that minimizes the amount of
 /// sorting that needs to be done later (either in GROUP BY, ORDER BY, or both).
 ///
+/// `row_limit` is the number of rows the query needs, if it has a LIMIT. It only bounds the
+/// number of ordered rows when there is no GROUP BY, since it otherwise limits the groups.
+///
 /// TODO: this does not currently handle the case where we definitely cannot eliminate
 /// the ORDER BY sorter, but we could still eliminate the GROUP BY sorter.
 pub fn compute_order_target(
     order_by_opt: &mut Option<Vec<(ast::Expr, SortOrder)>>,
     group_by_opt: Option<&mut GroupBy>,
+    row_limit: Option<usize>,
+    table_references: &TableReferences,
 ) -> Option<OrderTarget> {
     match (&order_by_opt, group_by_opt) {
         // No ordering demands - we don't care what order the joined result rows are in
@@ -80,11 +109,15 @@ pub fn compute_order_target(
         (Some(order_by), None) => OrderTarget::maybe_from_iterator(
             order_by.iter().map(|(expr, order)| (expr, *order)),
             EliminatesSortBy::Order,
+            row_limit,
+            table_references,
         ),
         // Only GROUP BY - we would like the joined result rows to be in the order specified by the GROUP BY
         (None, Some(group_by)) => OrderTarget::maybe_from_iterator(
             group_by.exprs.iter().map(|expr| (expr, SortOrder::Asc)),
             EliminatesSortBy::Group,
+            None,
+            table_references,
         ),
         // Both ORDER BY and GROUP BY:
         // If the GROUP BY does not contain all the expressions in the ORDER BY,
@@ -108,6 +141,8 @@ pub fn compute_order_target(
                 return OrderTarget::maybe_from_iterator(
                     group_by.exprs.iter().map(|expr| (expr, SortOrder::Asc)),
                     EliminatesSortBy::Group,
+                    None,
+                    table_references,
                 );
             }
             // If yes, let's try to target an ordering that matches the GROUP BY columns,
@@ -147,6 +182,8 @@ pub fn compute_order_target(
                     )
                     .map(|(expr, dir)| (expr, *dir)),
                 EliminatesSortBy::GroupByAndOrder,
+                None,
+                table_references,
             )
         }
     }
@@ -158,77 +195,263 @@ pub fn plan_satisfies_order_target(
     plan: &JoinN,
     access_methods_arena: &RefCell<Vec<AccessMethod>>,
     joined_tables: &[JoinedTable],
+    constraints: &[TableConstraints],
     order_target: &OrderTarget,
 ) -> bool {
-    let mut target_col_idx = 0;
-    let num_cols_in_order_target = order_target.0.len();
+    if order_target.1 == EliminatesSortBy::Group {
+        return plan_satisfies_group_target(
+            plan,
+            access_methods_arena,
+            joined_tables,
+            constraints,
+            order_target,
+        );
+    }
+    let mut target_cols = order_target.0.iter().peekable();
     for (table_index, access_method_index) in plan.data.iter() {
-        let target_col = &order_target.0[target_col_idx];
         let table_ref = &joined_tables[*table_index];
-        let correct_table = target_col.table_id == table_ref.internal_id;
-        if !correct_table {
+        let access_method = &access_methods_arena.borrow()[*access_method_index];
+        let access_order = AccessOrder::new(
+            table_ref,
+            &constraints[*table_index],
+            access_method.index.as_deref(),
+            access_method.constraint_refs,
+        );
+        let Some(iter_dir) =
+            access_order.consume_order_target(table_ref.internal_id, &mut target_cols)
+        else {
+            return false;
+        };
+        if iter_dir.is_some_and(|iter_dir| iter_dir != access_method.iter_dir) {
+            return false;
+        }
+        // All order columns matched.
+        if target_cols.peek().is_none() {
+            return true;
+        }
+        // The rows of the next table are only read in order for each row of this one, so no
+        // two rows of this one may have the same values in the columns of its order.
+        if !access_order.is_unique {
             return false;
         }
+    }
+    false
+}
 
-        // Check if this table has an access method that provides the right ordering.
+/// Like [plan_satisfies_order_target], but for the target of a GROUP BY, which only requires
+/// the rows with the same values in its columns to be read one after another. Unlike for an
+/// ORDER BY, the columns may be read in any order and direction.
+fn plan_satisfies_group_target(
+    plan: &JoinN,
+    access_methods_arena: &RefCell<Vec<AccessMethod>>,
+    joined_tables: &[JoinedTable],
+    constraints: &[TableConstraints],
+    order_target: &OrderTarget,
+) -> bool {
+    let mut target_cols = order_target.0.iter().collect::<Vec<_>>();
+    for (table_index, access_method_index) in plan.data.iter() {
+        let table_ref = &joined_tables[*table_index];
         let access_method = &access_methods_arena.borrow()[*access_method_index];
-        let iter_dir = access_method.iter_dir;
-        let index = access_method.index.as_ref();
-        match index {
-            None => {
-                // No index, so the next required column must be the rowid alias column.
-                let rowid_alias_col = table_ref
-                    .table
-                    .columns()
-                    .iter()
-                    .position(|c| c.is_rowid_alias);
-                let Some(rowid_alias_col) = rowid_alias_col else {
-                    return false;
-                };
-                let correct_column = target_col.column_no == rowid_alias_col;
-                if !correct_column {
-                    return false;
-                }
-
-                // Btree table rows are always in ascending order of rowid.
-                let correct_order = if iter_dir == IterationDirection::Forwards {
-                    target_col.order == SortOrder::Asc
-                } else {
-                    target_col.order == SortOrder::Desc
-                };
-                if !correct_order {
-                    return false;
-                }
-                target_col_idx += 1;
-                // All order columns matched.
-                if target_col_idx == num_cols_in_order_target {
-                    return true;
-                }
+        let access_order = AccessOrder::new(
+            table_ref,
+            &constraints[*table_index],
+            access_method.index.as_deref(),
+            access_method.constraint_refs,
+        );
+        if !access_order.consume_group_target(table_ref.internal_id, &mut target_cols) {
+            return false;
+        }
+        if target_cols.is_empty() {
+            return true;
+        }
+        if !access_order.is_unique {
+            return false;
+        }
+    }
+    false
+}
+
+/// The collation the values of a key column are ordered with, which must be the one of a
+/// [ColumnOrder] for the column to satisfy it.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum KeyCollation {
+    Collation(CollationSeq),
+    /// The rowid, which is an integer, so its order is the same with any collation.
+    Any,
+}
+
+impl KeyCollation {
+    fn satisfies(self, collation: CollationSeq) -> bool {
+        match self {
+            KeyCollation::Collation(key_collation) => key_collation == collation,
+            KeyCollation::Any => true,
+        }
+    }
+}
+
+/// The order of the rows of a table read through an index, or through the table itself.
+pub struct AccessOrder {
+    /// The columns whose value is the same in every row read, because the search of the
+    /// index compares them to a single value, e.g. `a` in `WHERE a = 1`, along with the
+    /// collation they are compared with.
+    constant_columns: Vec<(usize, KeyCollation)>,
+    /// The columns that the rows are ordered by when read forwards, along with their sort
+    /// orders and collations. The entries of an index with the same key are in ascending rowid
+    /// order, so the rowid alias column, if the table has one, always comes last.
+    columns: Vec<(usize, SortOrder, KeyCollation)>,
+    /// Whether no two rows read have the same values in the columns of the order.
+    is_unique: bool,
+}
+
+impl AccessOrder {
+    /// Returns the order of the rows of `table` read through `index`, or through the table
+    /// itself if `index` is None, when searched with `constraint_refs`, which refer to the
+    /// constraints of `table_constraints`.
+    pub fn new(
+        table: &JoinedTable,
+        table_constraints: &TableConstraints,
+        index: Option<&Index>,
+        constraint_refs: &[ConstraintRef],
+    ) -> Self {
+        let rowid_alias_col = table.columns().iter().position(|c| c.is_rowid_alias);
+        // The refs of a search are on the leading columns of the index, and only the last of
+        // them may not be an equality.
+        let num_constant_cols = constraint_refs
+            .iter()
+            .take_while(|cref| {
+                table_constraints.constraints[cref.constraint_vec_pos].operator
+                    == ast::Operator::Equals
+            })
+            .count();
+        // An indexed expression, or a rowid without an alias, is not a column the query can
+        // be ordered by.
+        let rowid = (rowid_alias_col, SortOrder::Asc, KeyCollation::Any);
+        let key_columns: Vec<(Option<usize>, SortOrder, KeyCollation)> = match index {
+            Some(index) => index
+                .columns
+                .iter()
+                .map(|column| {
+                    (
+                        column.expr.is_none().then_some(column.pos_in_table),
+                        column.order,
+                        KeyCollation::Collation(column.collation.unwrap_or_default()),
+                    )
+                })
+                .chain(index.has_rowid.then_some(rowid))
+                .collect(),
+            None => vec![rowid],
+        };
+        let mut constant_columns = Vec::new();
+        let mut columns = Vec::new();
+        for (i, (column_no, order, collation)) in key_columns.into_iter().enumerate() {
+            if i < num_constant_cols {
+                constant_columns.extend(column_no.map(|column_no| (column_no, collation)));
+                continue;
             }
-            Some(index) => {
-                // All of the index columns must match the next required columns in the order target.
-                for index_col in index.columns.iter() {
-                    let target_col = &order_target.0[target_col_idx];
-                    let correct_column = target_col.column_no == index_col.pos_in_table;
-                    if !correct_column {
-                        return false;
-                    }
-                    let correct_order = if iter_dir == IterationDirection::Forwards {
-                        target_col.order == index_col.order
-                    } else {
-                        target_col.order != index_col.order
-                    };
-                    if !correct_order {
-                        return false;
-                    }
-                    target_col_idx += 1;
-                    // All order columns matched.
-                    if target_col_idx == num_cols_in_order_target {
-                        return true;
-                    }
-                }
+            let Some(column_no) = column_no else {
+                break;
+            };
+            columns.push((column_no, order, collation));
+        }
+        let is_unique = rowid_alias_col.is_some_and(|rowid_alias_col| {
+            constant_columns
+                .iter()
+                .any(|(column_no, _)| *column_no == rowid_alias_col)
+                || columns
+                    .last()
+                    .is_some_and(|(column_no, _, _)| *column_no == rowid_alias_col)
+        });
+        Self {
+            constant_columns,
+            columns,
+            is_unique,
+        }
+    }
+
+    /// Consumes the leading columns of `target_cols` that the rows of the table `table_id` are
+    /// ordered by with the same collation, including its constant columns in any position.
+    /// Returns None if another column comes first, or else the direction the rows must be read
+    /// in, if the consumed columns require one.
+    pub fn consume_order_target<'a>(
+        &self,
+        table_id: TableInternalId,
+        target_cols: &mut Peekable<impl Iterator<Item = &'a ColumnOrder>>,
+    ) -> Option<Option<IterationDirection>> {
+        let mut columns = self.columns.iter();
+        let mut iter_dir = None;
+        while let Some(&target_col) = target_cols.peek() {
+            let is_this_table = target_col.table_id == table_id;
+            if is_this_table && self.is_constant_column(target_col) {
+                target_cols.next();
+                continue;
+            }
+            let Some((column_no, order, collation)) = columns.next() else {
+                break;
+            };
+            if !is_this_table
+                || target_col.column_no != *column_no
+                || !collation.satisfies(target_col.collation)
+            {
+                return None;
             }
+            let required_dir = if target_col.order == *order {
+                IterationDirection::Forwards
+            } else {
+                IterationDirection::Backwards
+            };
+            if iter_dir.is_some_and(|iter_dir| iter_dir != required_dir) {
+                return None;
+            }
+            iter_dir = Some(required_dir);
+            target_cols.next();
         }
+        Some(iter_dir)
+    }
+
+    /// Removes the columns of `target_cols`, the columns of a GROUP BY, that the rows of the
+    /// table `table_id` are grouped by with the same collation: its constant columns, and the
+    /// leading columns of its order, in any order and direction. Returns false if a column of
+    /// the table is left, or if columns of other tables are left but the rows are not ordered
+    /// by all the columns of the order.
+    pub fn consume_group_target(
+        &self,
+        table_id: TableInternalId,
+        target_cols: &mut Vec<&ColumnOrder>,
+    ) -> bool {
+        let is_table_column =
+            |target_col: &ColumnOrder, column_no: usize, collation: KeyCollation| {
+                target_col.table_id == table_id
+                    && target_col.column_no == column_no
+                    && collation.satisfies(target_col.collation)
+            };
+        target_cols.retain(|target_col| {
+            !(target_col.table_id == table_id && self.is_constant_column(target_col))
+        });
+        let mut num_consumed = 0;
+        for (column_no, _, collation) in self.columns.iter() {
+            let Some(pos) = target_cols
+                .iter()
+                .position(|target_col| is_table_column(target_col, *column_no, *collation))
+            else {
+                break;
+            };
+            target_cols.remove(pos);
+            num_consumed += 1;
+        }
+        if target_cols
+            .iter()
+            .any(|target_col| target_col.table_id == table_id)
+        {
+            return false;
+        }
+        target_cols.is_empty() || num_consumed == self.columns.len()
+    }
+
+    /// Whether `target_col`, a column of the table, is a constant column compared with its
+    /// collation. With another one, its values may still differ.
+    fn is_constant_column(&self, target_col: &ColumnOrder) -> bool {
+        self.constant_columns.iter().any(|(column_no, collation)| {
+            *column_no == target_col.column_no && collation.satisfies(target_col.collation)
+        })
     }
-    false
 }
@@ -0,0 +1,172 @@
+use std::{borrow::Cow, collections::HashMap, sync::Arc};
+
+use turso_sqlite3_parser::ast::{self, Expr};
+
+use crate::{
+    schema::Index,
+    translate::{
+        expr::walk_expr_mut,
+        plan::{JoinedTable, TableReferences, WhereTerm},
+    },
+    util::{exprs_are_equivalent, normalize_ident},
+    Result,
+};
+
+use super::rewrite_expr;
+
+/// Returns the indexes of `available_indexes` that can be used to read the tables in
+/// `table_references`. A partial index has no entry for the rows its WHERE clause excludes, so
+/// it is only usable if `where_clause` implies the WHERE clause of the index for every reference
+/// to its table.
+pub(crate) fn usable_indexes<'a>(
+    available_indexes: &'a HashMap<String, Vec<Arc<Index>>>,
+    table_references: &TableReferences,
+    where_clause: &[WhereTerm],
+) -> Result<Cow<'a, HashMap<String, Vec<Arc<Index>>>>> {
+    let mut usable_indexes = Cow::Borrowed(available_indexes);
+    for table in table_references.joined_tables() {
+        let table_name = table.table.get_name();
+        let Some(indexes) = available_indexes.get(table_name) else {
+            continue;
+        };
+        for index in indexes.iter() {
+            let Some(index_where_clause) = &index.where_clause else {
+                continue;
+            };
+            if where_clause_implies(where_clause, table, index_where_clause)? {
+                continue;
+            }
+            if let Some(indexes) = usable_indexes.to_mut().get_mut(table_name) {
+                indexes.retain(|usable_index| !Arc::ptr_eq(usable_index, index));
+            }
+        }
+    }
+    Ok(usable_indexes)
+}
+
+/// Returns whether the terms of `where_clause` that filter the rows of `table` imply `condition`,
+/// the WHERE clause of a partial index on it.
+///
+/// Like in SQLite, this is the case if each of the AND-connected terms of `condition` has an
+/// OR-connected term that is also a term of `where_clause`, or that is `x IS NOT NULL` while
+/// `where_clause` has a comparison of `x`.
+fn where_clause_implies(
+    where_clause: &[WhereTerm],
+    table: &JoinedTable,
+    condition: &Expr,
+) -> Result<bool> {
+    let Some(condition) = bind_index_expr(condition, table)? else {
+        return Ok(false);
+    };
+
+    // The rows of the right table of an OUTER JOIN are read before the terms of the WHERE clause
+    // are evaluated, so only the terms of its ON clause filter them.
+    let is_outer = table.join_info.as_ref().is_some_and(|join| join.outer);
+    let mut terms = Vec::new();
+    for term in where_clause.iter() {
+        let filters_table = match term.from_outer_join {
+            Some(table_id) => table_id == table.internal_id,
+            None => !is_outer,
+        };
+        if filters_table {
+            split_terms(&term.expr, ast::Operator::And, &mut terms);
+        }
+    }
+
+    let mut conjuncts = Vec::new();
+    split_terms(&condition, ast::Operator::And, &mut conjuncts);
+    Ok(conjuncts.into_iter().all(|conjunct| {
+        let mut disjuncts = Vec::new();
+        split_terms(conjunct, ast::Operator::Or, &mut disjuncts);
+        disjuncts
+            .into_iter()
+            .any(|disjunct| terms.iter().any(|term| term_implies(term, disjunct)))
+    }))
+}
+
+/// Splits `expr` into the terms connected by `op`, which is either AND or OR.
+fn split_terms<'a>(expr: &'a Expr, op: ast::Operator, terms: &mut Vec<&'a Expr>) {
+    match expr {
+        Expr::Binary(lhs, binary_op, rhs) if *binary_op == op => {
+            split_terms(lhs, op, terms);
+            split_terms(rhs, op, terms);
+        }
+        Expr::Parenthesized(exprs) if exprs.len() == 1 => split_terms(&exprs[0], op, terms),
+        _ => terms.push(expr),
+    }
+}
+
+/// Returns whether `term` being true implies that `implied` is true.
+fn term_implies(term: &Expr, implied: &Expr) -> bool {
+    if exprs_are_equivalent(term, implied) {
+        return true;
+    }
+    let not_null_expr = match implied {
+        Expr::NotNull(expr) => expr.as_ref(),
+        Expr::Binary(lhs, ast::Operator::IsNot, rhs)
+            if matches!(rhs.as_ref(), Expr::Literal(ast::Literal::Null)) =>
+        {
+            lhs.as_ref()
+        }
+        _ => return false,
+    };
+    // A comparison is never true when one of its operands is NULL.
+    match term {
+        Expr::Binary(lhs, op, rhs) => {
+            matches!(
+                op,
+                ast::Operator::Equals
+                    | ast::Operator::NotEquals
+                    | ast::Operator::Less
+                    | ast::Operator::LessEquals
+                    | ast::Operator::Greater
+                    | ast::Operator::GreaterEquals
+            ) && (exprs_are_equivalent(lhs, not_null_expr)
+                || exprs_are_equivalent(rhs, not_null_expr))
+        }
+        Expr::Like {
+            lhs, not: false, ..
+        } => exprs_are_equivalent(lhs, not_null_expr),
+        _ => false,
+    }
+}
+
+/// Returns `expr`, the WHERE clause of a partial index or an indexed expression, rewritten and
+/// with its column references bound to `table` like the expressions of the query are, so that
+/// they can be compared. Returns None if some reference could not be bound.
+pub(super) fn bind_index_expr(expr: &Expr, table: &JoinedTable) -> Result<Option<Expr>> {
+    let mut expr = expr.clone();
+    rewrite_expr(&mut expr)?;
+    let mut bound = true;
+    walk_expr_mut(&mut expr, &mut |expr: &mut Expr| -> Result<()> {
+        let column_name = match expr {
+            Expr::Id(id) => normalize_ident(&id.0),
+            Expr::Qualified(_, column_name) => normalize_ident(&column_name.0),
+            _ => return Ok(()),
+        };
+        let column = table.columns().iter().position(|column| {
+            column
+                .name
+                .as_ref()
+                .is_some_and(|name| name.eq_ignore_ascii_case(&column_name))
+        });
+        *expr = match column {
+            Some(column) => Expr::Column {
+                database: None,
+                table: table.internal_id,
+                column,
+                is_rowid_alias: table.columns()[column].is_rowid_alias,
+            },
+            None if ["rowid", "oid", "_rowid_"].contains(&column_name.as_str()) => Expr::RowId {
+                database: None,
+                table: table.internal_id,
+            },
+            None => {
+                bound = false;
+                return Ok(());
+            }
+        };
+        Ok(())
+    })?;
+    Ok(bound.then_some(expr))
+}
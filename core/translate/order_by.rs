@@ -6,7 +6,7 @@ use crate::{
     util::exprs_are_equivalent,
     vdbe::{
         builder::{CursorType, ProgramBuilder},
-        insn::Insn,
+        insn::{CmpInsFlags, Insn},
     },
     Result,
 };
@@ -49,19 +49,7 @@ pub fn init_order_by(
      */
     let collations = order_by
         .iter()
-        .map(|(expr, _)| match expr {
-            ast::Expr::Collate(_, collation_name) => CollationSeq::new(collation_name).map(Some),
-            ast::Expr::Column { table, column, .. } => {
-                let table = referenced_tables.find_table_by_internal_id(*table).unwrap();
-
-                let Some(table_column) = table.get_column_at(*column) else {
-                    crate::bail_parse_error!("column index out of bounds");
-                };
-
-                Ok(table_column.collation)
-            }
-            _ => Ok(Some(CollationSeq::default())),
-        })
+        .map(|(expr, _)| order_by_collation(expr, referenced_tables))
         .collect::<Result<Vec<_>>>()?;
     program.emit_insn(Insn::SorterOpen {
         cursor_id: sort_cursor,
@@ -72,6 +60,53 @@ pub fn init_order_by(
     Ok(())
 }
 
+/// Returns the collation an ORDER BY term is sorted with.
+fn order_by_collation(
+    expr: &ast::Expr,
+    referenced_tables: &TableReferences,
+) -> Result<Option<CollationSeq>> {
+    match expr {
+        ast::Expr::Collate(_, collation_name) => CollationSeq::new(collation_name).map(Some),
+        ast::Expr::Column { table, column, .. } => {
+            let table = referenced_tables.find_table_by_internal_id(*table).unwrap();
+
+            let Some(table_column) = table.get_column_at(*column) else {
+                crate::bail_parse_error!("column index out of bounds");
+            };
+
+            Ok(table_column.collation)
+        }
+        _ => Ok(Some(CollationSeq::default())),
+    }
+}
+
+/// Returns whether the rows of a SELECT DISTINCT can be deduplicated as they come out of the
+/// ORDER BY sorter, instead of with an ephemeral index before they go into it.
+/// That is the case when the leading ORDER BY terms are result columns and include all of them,
+/// so that duplicate rows are sorted next to each other.
+pub fn distinct_by_order_by(plan: &SelectPlan) -> bool {
+    let Some(order_by) = &plan.order_by else {
+        return false;
+    };
+    let mut covered = vec![false; plan.result_columns.len()];
+    for (expr, _) in order_by {
+        if covered.iter().all(|covered| *covered) {
+            break;
+        }
+        let mut is_result_column = false;
+        for (i, rc) in plan.result_columns.iter().enumerate() {
+            if exprs_are_equivalent(expr, &rc.expr) {
+                covered[i] = true;
+                is_result_column = true;
+            }
+        }
+        if !is_result_column {
+            return false;
+        }
+    }
+    covered.iter().all(|covered| *covered)
+}
+
 /// Emits the bytecode for outputting rows from an ORDER BY sorter.
 /// This is called when the main query execution loop has finished processing,
 /// and we can now emit rows from the ORDER BY sorter.
@@ -111,9 +146,20 @@ pub fn emit_order_by(
         cursor_id: sort_cursor,
         pc_if_empty: sort_loop_end_label,
     });
+    // A SELECT DISTINCT compares each row with the previous one, see [distinct_by_order_by].
+    let distinct_regs = if plan.distinctness.is_distinct() && distinct_by_order_by(plan) {
+        let reg_seen_row = program.alloc_register();
+        program.emit_int(0, reg_seen_row);
+        Some((reg_seen_row, program.alloc_registers(result_columns.len())))
+    } else {
+        None
+    };
     program.preassign_label_to_next_insn(sort_loop_start_label);
 
-    emit_offset(program, plan, sort_loop_next_label, t_ctx.reg_offset)?;
+    // The rows skipped by an OFFSET do not include the duplicates of a SELECT DISTINCT.
+    if distinct_regs.is_none() {
+        emit_offset(program, sort_loop_next_label, t_ctx.reg_offset);
+    }
 
     program.emit_insn(Insn::SorterData {
         cursor_id: sort_cursor,
@@ -134,6 +180,37 @@ pub fn emit_order_by(
         );
     }
 
+    if let Some((reg_seen_row, prev_row_reg)) = distinct_regs {
+        let label_new_row = program.allocate_label();
+        program.emit_insn(Insn::IfNot {
+            reg: reg_seen_row,
+            target_pc: label_new_row,
+            jump_if_null: true,
+        });
+        for i in 0..result_columns.len() {
+            // Every result column is a sort key, and is compared with the collation it is sorted with.
+            let (expr, _) = &order_by[t_ctx.result_column_indexes_in_orderby_sorter[i]];
+            program.emit_insn(Insn::Ne {
+                lhs: start_reg + i,
+                rhs: prev_row_reg + i,
+                target_pc: label_new_row,
+                flags: CmpInsFlags::default().null_eq(),
+                collation: order_by_collation(expr, &plan.table_references)?,
+            });
+        }
+        program.emit_insn(Insn::Goto {
+            target_pc: sort_loop_next_label,
+        });
+        program.preassign_label_to_next_insn(label_new_row);
+        program.emit_int(1, reg_seen_row);
+        program.emit_insn(Insn::Copy {
+            src_reg: start_reg,
+            dst_reg: prev_row_reg,
+            extra_amount: result_columns.len() - 1,
+        });
+        emit_offset(program, sort_loop_next_label, t_ctx.reg_offset);
+    }
+
     emit_result_row_and_limit(
         program,
         plan,
@@ -186,7 +263,6 @@ pub fn order_by_sorter_insert(
     }
     let mut cur_reg = start_reg + order_by_len;
     let mut cur_idx_in_orderby_sorter = order_by_len;
-    let mut translated_result_col_count = 0;
     for (i, rc) in result_columns.iter().enumerate() {
         if let Some(ref v) = result_columns_to_skip {
             let found = v.iter().find(|(skipped_idx, _)| *skipped_idx == i);
@@ -203,17 +279,29 @@ pub fn order_by_sorter_insert(
             cur_reg,
             resolver,
         )?;
-        translated_result_col_count += 1;
         res_col_indexes_in_orderby_sorter.insert(i, cur_idx_in_orderby_sorter);
         cur_idx_in_orderby_sorter += 1;
         cur_reg += 1;
     }
 
-    // Handle SELECT DISTINCT deduplication
-    if let Distinctness::Distinct { ctx } = &plan.distinctness {
-        let distinct_ctx = ctx.as_ref().expect("distinct context must exist");
-        let num_regs = order_by_len + translated_result_col_count;
-        distinct_ctx.emit_deduplication_insns(program, num_regs, start_reg);
+    // Handle SELECT DISTINCT deduplication, which only considers the result columns.
+    if let Distinctness::Distinct {
+        ctx: Some(distinct_ctx),
+    } = &plan.distinctness
+    {
+        let distinct_reg = program.alloc_registers(result_columns.len());
+        for (i, sorter_col) in res_col_indexes_in_orderby_sorter
+            .iter()
+            .take(result_columns.len())
+            .enumerate()
+        {
+            program.emit_insn(Insn::Copy {
+                src_reg: start_reg + sorter_col,
+                dst_reg: distinct_reg + i,
+                extra_amount: 0,
+            });
+        }
+        distinct_ctx.emit_deduplication_insns(program, result_columns.len(), distinct_reg);
     }
 
     let SortMetadata {
@@ -1,9 +1,9 @@
 use std::{cell::Cell, cmp::Ordering, rc::Rc, sync::Arc};
-use turso_ext::{ConstraintInfo, ConstraintOp};
-use turso_sqlite3_parser::ast::{self, SortOrder};
+use turso_ext::{ConstraintInfo, ConstraintOp, OrderByInfo};
+use turso_sqlite3_parser::ast::{self, ResolveType, SortOrder};
 
 use crate::{
-    function::AggFunc,
+    function::{AggFunc, WindowFunc},
     schema::{BTreeTable, Column, FromClauseSubquery, Index, Table},
     vdbe::{
         builder::{CursorKey, CursorType, ProgramBuilder},
@@ -53,6 +53,42 @@ impl ResultSetColumn {
             _ => None,
         }
     }
+
+    /// The table the column comes from and the column of that table, if the column is a
+    /// column or the rowid of a table. Columns of subqueries in FROM have no table.
+    fn origin<'a>(&self, tables: &'a TableReferences) -> Option<(&'a Table, Option<&'a Column>)> {
+        let origin = match &self.expr {
+            ast::Expr::Column { table, column, .. } => {
+                let table_ref = tables.find_table_by_internal_id(*table)?;
+                Some((table_ref, table_ref.get_column_at(*column)))
+            }
+            ast::Expr::RowId { table, .. } => {
+                let table_ref = tables.find_table_by_internal_id(*table)?;
+                let rowid_alias = match table_ref {
+                    Table::BTree(table) => table.get_rowid_alias_column().map(|(_, c)| c),
+                    _ => None,
+                };
+                Some((table_ref, rowid_alias))
+            }
+            _ => None,
+        };
+        origin.filter(|(table, _)| !matches!(table, Table::FromClauseSubquery(_)))
+    }
+
+    /// The name of the table the column comes from, like `sqlite3_column_table_name`.
+    pub fn table_name<'a>(&self, tables: &'a TableReferences) -> Option<&'a str> {
+        self.origin(tables).map(|(table, _)| table.get_name())
+    }
+
+    /// The declared type of the table column the column comes from, like
+    /// `sqlite3_column_decltype`. The rowid is an `INTEGER`.
+    pub fn decltype<'a>(&self, tables: &'a TableReferences) -> Option<&'a str> {
+        match self.origin(tables)? {
+            (_, Some(column)) => Some(column.ty_str.as_str()).filter(|ty| !ty.is_empty()),
+            (_, None) if matches!(self.expr, ast::Expr::RowId { .. }) => Some("INTEGER"),
+            (_, None) => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -150,6 +186,30 @@ fn to_ext_constraint_op(op: &Operator) -> Option<ConstraintOp> {
     }
 }
 
+/// Returns the constraints of `predicates` that are offered to the xBestIndex of the virtual
+/// table at `table_idx`, when its loop is at `join_index` in `join_order`. The planner and the
+/// main loop must offer the same ones, so that the module makes the same choices.
+pub fn vtab_constraints(
+    predicates: &[WhereTerm],
+    join_index: usize,
+    table_idx: usize,
+    join_order: &[JoinOrderMember],
+) -> Vec<ConstraintInfo> {
+    let mut constraints = Vec::new();
+    for (i, predicate) in predicates.iter().enumerate() {
+        if !predicate.should_eval_at_loop(join_index, join_order) {
+            continue;
+        }
+        // Build ConstraintInfo from the predicates
+        if let Ok(Some(constraint)) =
+            convert_where_to_vtab_constraint(predicate, table_idx, i, join_order)
+        {
+            constraints.push(constraint);
+        }
+    }
+    constraints
+}
+
 /// This function takes a WhereTerm for a select involving a VTab at index 'table_index'.
 /// It determines whether or not it involves the given table and whether or not it can
 /// be converted into a ConstraintInfo which can be passed to the vtab module's xBestIndex
@@ -168,6 +228,28 @@ pub fn convert_where_to_vtab_constraint(
     if term.from_outer_join.is_some() {
         return Ok(None);
     }
+    // vtab.col MATCH expr: only the virtual table module knows what to do with it
+    if let Expr::Like {
+        lhs,
+        not: false,
+        op: ast::LikeOperator::Match,
+        rhs,
+        escape: None,
+    } = &term.expr
+    {
+        let Expr::Column { table, column, .. } = &**lhs else {
+            return Ok(None);
+        };
+        if join_order.iter().position(|j| j.table_id == *table) != Some(table_idx) {
+            return Ok(None);
+        }
+        return Ok(Some(ConstraintInfo {
+            column_index: *column as u32,
+            op: ConstraintOp::Match,
+            usable: can_pushdown_predicate(rhs, table_idx, join_order)?,
+            plan_info: ConstraintInfo::pack_plan_info(pred_idx as u32, false),
+        }));
+    }
     let Expr::Binary(lhs, op, rhs) = &term.expr else {
         return Ok(None);
     };
@@ -187,27 +269,22 @@ pub fn convert_where_to_vtab_constraint(
             },
         ) => {
             // one side must be the virtual table
-            let tbl_l_idx = join_order
-                .iter()
-                .position(|j| j.table_id == *tbl_l)
-                .unwrap();
-            let tbl_r_idx = join_order
-                .iter()
-                .position(|j| j.table_id == *tbl_r)
-                .unwrap();
-            let vtab_on_l = tbl_l_idx == table_idx;
-            let vtab_on_r = tbl_r_idx == table_idx;
+            // (tables of an outer query are not in the join order, and are always positioned)
+            let tbl_l_idx = join_order.iter().position(|j| j.table_id == *tbl_l);
+            let tbl_r_idx = join_order.iter().position(|j| j.table_id == *tbl_r);
+            let vtab_on_l = tbl_l_idx == Some(table_idx);
+            let vtab_on_r = tbl_r_idx == Some(table_idx);
             if vtab_on_l == vtab_on_r {
                 return Ok(None); // either both or none -> not convertible
             }
 
             if vtab_on_l {
                 // vtab on left side: operator unchanged
-                let usable = tbl_r_idx < table_idx; // usable if the other table is already positioned
+                let usable = tbl_r_idx.is_none_or(|idx| idx < table_idx); // usable if the other table is already positioned
                 (col_l, op, usable, false)
             } else {
                 // vtab on right side of the expr: reverse operator
-                let usable = tbl_l_idx < table_idx;
+                let usable = tbl_l_idx.is_none_or(|idx| idx < table_idx);
                 (col_r, &reverse_operator(op).unwrap_or(*op), usable, true)
             }
         }
@@ -293,8 +370,8 @@ pub enum Plan {
     CompoundSelect {
         left: Vec<(SelectPlan, ast::CompoundOperator)>,
         right_most: SelectPlan,
-        limit: Option<isize>,
-        offset: Option<isize>,
+        limit: Option<Box<ast::Expr>>,
+        offset: Option<Box<ast::Expr>>,
         order_by: Option<Vec<(ast::Expr, SortOrder)>>,
     },
     Delete(DeletePlan),
@@ -326,6 +403,8 @@ pub enum QueryDestination {
         index: Arc<Index>,
         /// Whether this is a delete operation that will remove the index entries
         is_delete: bool,
+        /// The affinities applied to the result columns before they are inserted, if any.
+        affinity_str: Option<String>,
     },
     /// The results of the query are stored in an ephemeral table,
     /// later used by the parent query.
@@ -335,6 +414,68 @@ pub enum QueryDestination {
         /// The table that will be used to store the results.
         table: Rc<BTreeTable>,
     },
+    /// The results of the query are appended to the queue of a recursive CTE.
+    RecursiveCteQueue {
+        /// The cursor ID of the ephemeral table that holds the queue.
+        cursor_id: CursorID,
+        /// The table that describes the queue rows.
+        table: Rc<BTreeTable>,
+        /// For UNION (as opposed to UNION ALL), the cursor ID of the ephemeral index
+        /// holding every row ever queued, used to discard duplicates.
+        distinct_cursor_id: Option<CursorID>,
+    },
+    /// The query is the subquery of an `EXISTS (SELECT ...)` expression:
+    /// the result register is set to 1 if the query returns a row.
+    ExistsSubqueryResult {
+        /// The register that holds the result of the EXISTS expression.
+        result_reg: usize,
+    },
+    /// The query is a scalar subquery: the columns of the row it returns are
+    /// copied into a set of registers.
+    RowValueSubqueryResult {
+        /// The first register that holds the result columns.
+        result_reg_start: usize,
+        /// The number of result columns.
+        num_regs: usize,
+    },
+}
+
+/// The recursive part of a recursive CTE, i.e. the SELECT to the right of the
+/// UNION [ALL] in `WITH RECURSIVE c AS (initial UNION [ALL] recursive)`.
+///
+/// The rows of the initial SELECT are put in a queue. Then rows are taken off the
+/// queue one at a time: each row is yielded to the parent query and the recursive
+/// SELECT is run with the CTE reference bound to that single row, appending its
+/// results to the queue. This repeats until the queue is empty.
+#[derive(Debug, Clone)]
+pub struct RecursiveCte {
+    /// The plan of the recursive SELECT.
+    pub plan: Box<SelectPlan>,
+    /// The internal id of the table in [RecursiveCte::plan] that refers to the CTE itself.
+    pub self_table_id: TableInternalId,
+    /// Whether the parts are combined with UNION ALL; with UNION, duplicate rows are discarded.
+    pub union_all: bool,
+    /// Maximum number of rows the CTE produces.
+    pub limit: Option<Box<ast::Expr>>,
+}
+
+/// A subquery that does not appear in the FROM clause, e.g. `EXISTS (SELECT ...)` or
+/// `x = (SELECT ...)` in a WHERE clause. The expression it appeared in refers to it through
+/// an [ast::Expr::SubqueryResult] with the same internal id.
+///
+/// Both are emitted as subroutines. An uncorrelated subquery is evaluated once per run of the
+/// parent query, the first time the expression is evaluated. A correlated subquery reads columns
+/// of the parent query, so it is re-executed every time the expression is evaluated.
+#[derive(Debug, Clone)]
+pub struct NonFromClauseSubquery {
+    /// The internal id the [ast::Expr::SubqueryResult] refers to.
+    pub internal_id: TableInternalId,
+    /// The kind of subquery.
+    pub query_type: ast::SubqueryType,
+    /// The plan of the subquery.
+    pub plan: Box<SelectPlan>,
+    /// Whether the subquery reads columns of an outer query.
+    pub correlated: bool,
 }
 
 #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
@@ -356,6 +497,8 @@ pub enum Distinctness {
     NonDistinct,
     /// The column is a DISTINCT column,
     /// and includes a translation context for handling duplicates.
+    /// For a SELECT DISTINCT whose duplicates are dropped as they come out of the
+    /// ORDER BY sorter, there is no such context.
     Distinct { ctx: Option<DistinctCtx> },
 }
 
@@ -430,10 +573,12 @@ pub struct SelectPlan {
     pub order_by: Option<Vec<(ast::Expr, SortOrder)>>,
     /// all the aggregates collected from the result columns, order by, and (TODO) having clauses
     pub aggregates: Vec<Aggregate>,
+    /// the window of the window functions in the result columns, if any
+    pub window: Option<Window>,
     /// limit clause
-    pub limit: Option<isize>,
+    pub limit: Option<Box<ast::Expr>>,
     /// offset clause
-    pub offset: Option<isize>,
+    pub offset: Option<Box<ast::Expr>>,
     /// query contains a constant condition that is always false
     pub contains_constant_false_condition: bool,
     /// the destination of the resulting rows from this plan.
@@ -442,6 +587,8 @@ pub struct SelectPlan {
     pub distinctness: Distinctness,
     /// values: https://sqlite.org/syntax/select-core.html
     pub values: Vec<Vec<Expr>>,
+    /// subqueries outside of the FROM clause, e.g. `WHERE EXISTS (SELECT ...)`
+    pub non_from_clause_subqueries: Vec<NonFromClauseSubquery>,
 }
 
 impl SelectPlan {
@@ -459,10 +606,7 @@ impl SelectPlan {
     pub fn is_simple_count(&self) -> bool {
         if !self.where_clause.is_empty()
             || self.aggregates.len() != 1
-            || matches!(
-                self.query_destination,
-                QueryDestination::CoroutineYield { .. }
-            )
+            || !matches!(self.query_destination, QueryDestination::ResultRows)
             || self.table_references.joined_tables().len() != 1
             || self.table_references.outer_query_refs().is_empty()
             || self.result_columns.len() != 1
@@ -511,31 +655,48 @@ pub struct DeletePlan {
     /// order by clause
     pub order_by: Option<Vec<(ast::Expr, SortOrder)>>,
     /// limit clause
-    pub limit: Option<isize>,
+    pub limit: Option<Box<ast::Expr>>,
     /// offset clause
-    pub offset: Option<isize>,
+    pub offset: Option<Box<ast::Expr>>,
     /// query contains a constant condition that is always false
     pub contains_constant_false_condition: bool,
     /// Indexes that must be updated by the delete operation.
     pub indexes: Vec<Arc<Index>>,
+    /// If the table has DELETE triggers, which may modify the table while it is being looped over,
+    /// gather all the target rowids into an ephemeral table, and then loop over that table instead.
+    pub ephemeral_plan: Option<SelectPlan>,
 }
 
 #[derive(Debug, Clone)]
 pub struct UpdatePlan {
+    /// The table being updated is always the first joined table; any further
+    /// joined tables come from the `UPDATE ... FROM` clause.
     pub table_references: TableReferences,
+    /// The order in which the joined tables are looped over.
+    /// The target table is always the outermost loop.
+    pub join_order: Vec<JoinOrderMember>,
     // (colum index, new value) pairs
     pub set_clauses: Vec<(usize, ast::Expr)>,
     pub where_clause: Vec<WhereTerm>,
     pub order_by: Option<Vec<(ast::Expr, SortOrder)>>,
-    pub limit: Option<isize>,
-    pub offset: Option<isize>,
+    pub limit: Option<Box<ast::Expr>>,
+    pub offset: Option<Box<ast::Expr>>,
     // TODO: optional RETURNING clause
     pub returning: Option<Vec<ResultSetColumn>>,
     // whether the WHERE clause is always false
     pub contains_constant_false_condition: bool,
     pub indexes_to_update: Vec<Arc<Index>>,
+    // The conflict resolution from `UPDATE OR <resolution>`, if any.
+    pub or_conflict: Option<ResolveType>,
     // If the table's rowid alias is used, gather all the target rowids into an ephemeral table, and then use that table as the single JoinedTable for the actual UPDATE loop.
+    // The PRIMARY KEY values of the rows of a WITHOUT ROWID table are always gathered, into an ephemeral index.
     pub ephemeral_plan: Option<SelectPlan>,
+    /// Row values assigned to several columns at once, as in `SET (a, b) = (SELECT x, y ...)`,
+    /// with the first of the consecutive registers they are evaluated into. The `set_clauses`
+    /// of those columns read the registers as [ast::Expr::Register]s.
+    pub row_value_set_clauses: Vec<(ast::Expr, usize)>,
+    /// Subqueries that appear in the SET clauses.
+    pub non_from_clause_subqueries: Vec<NonFromClauseSubquery>,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -617,6 +778,12 @@ pub struct JoinedTable {
     /// Bitmask of columns that are referenced in the query.
     /// Used to decide whether a covering index can be used.
     pub col_used_mask: ColumnUsedMask,
+    /// The database the table is in: 0 for main, or the index of an attached database.
+    pub database_id: usize,
+    /// The ORDER BY terms that the module of a virtual table returns its rows in, which made
+    /// sorting them unnecessary. They must be offered to its xBestIndex again when the loop
+    /// is opened.
+    pub vtab_order_by: Vec<OrderByInfo>,
 }
 
 #[derive(Debug, Clone)]
@@ -633,6 +800,8 @@ pub struct OuterQueryReference {
     /// i.e., if the subquery depends on tables T and U,
     /// then both T and U need to be in scope for the subquery to be evaluated.
     pub col_used_mask: ColumnUsedMask,
+    /// The database the table is in: 0 for main, or the index of an attached database.
+    pub database_id: usize,
 }
 
 impl OuterQueryReference {
@@ -740,6 +909,20 @@ impl TableReferences {
             })
     }
 
+    /// Returns the database of the table with the given internal ID.
+    pub fn find_database_by_internal_id(&self, internal_id: TableInternalId) -> Option<usize> {
+        self.joined_tables
+            .iter()
+            .find(|t| t.internal_id == internal_id)
+            .map(|t| t.database_id)
+            .or_else(|| {
+                self.outer_query_refs
+                    .iter()
+                    .find(|t| t.internal_id == internal_id)
+                    .map(|t| t.database_id)
+            })
+    }
+
     /// Returns an immutable reference to the [Table] with the given identifier,
     /// where identifier is either the literal name of the table or an alias.
     pub fn find_table_by_identifier(&self, identifier: &str) -> Option<&Table> {
@@ -879,6 +1062,7 @@ impl Operation {
             Operation::Scan { index, .. } => index.as_ref(),
             Operation::Search(Search::RowidEq { .. }) => None,
             Operation::Search(Search::Seek { index, .. }) => index.as_ref(),
+            Operation::Search(Search::MultiIndexOr { .. }) => None,
         }
     }
 }
@@ -915,10 +1099,13 @@ impl JoinedTable {
                 is_rowid_alias: false,
                 primary_key: false,
                 notnull: false,
+                notnull_conflict_clause: None,
                 default: None,
                 unique: false,
+                unique_conflict_clause: None,
                 collation: None, // FIXME: infer collation from subquery
                 hidden: false,
+                generated: None,
             })
             .collect();
 
@@ -927,6 +1114,7 @@ impl JoinedTable {
             plan: Box::new(plan),
             columns,
             result_columns_start_reg: None,
+            recursive: None,
         });
         Self {
             op: Operation::Scan {
@@ -938,6 +1126,8 @@ impl JoinedTable {
             internal_id,
             join_info,
             col_used_mask: ColumnUsedMask::default(),
+            database_id: 0,
+            vtab_order_by: Vec::new(),
         }
     }
 
@@ -1016,7 +1206,8 @@ impl JoinedTable {
             return false;
         }
         let mut index_cols_mask = ColumnUsedMask::default();
-        for col in index.columns.iter() {
+        // An indexed expression covers none of the columns it is computed from.
+        for col in index.columns.iter().filter(|col| col.expr.is_none()) {
             index_cols_mask.set(col.pos_in_table);
         }
 
@@ -1070,6 +1261,14 @@ pub struct SeekDef {
     pub seek: Option<SeekKey>,
     /// The condition to use when terminating the scan that follows the seek. See [TerminationKey] for more details.
     pub termination: Option<TerminationKey>,
+    /// The value of the last column of the termination key, when it differs from the one in
+    /// [SeekDef::key] because the range is bounded at both ends.
+    /// For example, given:
+    /// - CREATE INDEX i ON t (x, y)
+    /// - SELECT * FROM t WHERE x = 1 AND y > 10 AND y < 20
+    ///
+    /// The key is [(1, ASC), (10, ASC)] and the last column of the termination key is 20.
+    pub termination_last_key: Option<ast::Expr>,
     /// The direction of the scan that follows the seek.
     pub iter_dir: IterationDirection,
 }
@@ -1119,6 +1318,14 @@ pub enum Search {
         index: Option<Arc<Index>>,
         seek_def: SeekDef,
     },
+    /// A union of searches, one per branch of an OR term, e.g. `a = 1 OR b = 2`, or per value of
+    /// an IN list. The rowids found by the searches are collected into the ephemeral index
+    /// `rowids`, which is then looped over to look up the rows of the table by rowid.
+    MultiIndexOr {
+        /// The search of each branch, either a [Search::RowidEq] or a [Search::Seek].
+        branches: Vec<Search>,
+        rowids: Arc<Index>,
+    },
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -1134,3 +1341,53 @@ impl Aggregate {
         self.distinctness.is_distinct()
     }
 }
+
+/// The window that the window functions of a SELECT are computed over. Every row of the query is
+/// buffered in its partition, in ORDER BY order, before the functions are computed for it.
+#[derive(Debug, Clone)]
+pub struct Window {
+    pub partition_by: Vec<ast::Expr>,
+    pub order_by: Vec<(ast::Expr, SortOrder)>,
+    pub functions: Vec<WindowFunction>,
+}
+
+#[derive(Debug, Clone)]
+pub struct WindowFunction {
+    pub func: WindowFunc,
+    pub args: Vec<ast::Expr>,
+    pub filter: Option<ast::Expr>,
+    pub frame: WindowFrame,
+    pub original_expr: ast::Expr,
+}
+
+/// The rows of its partition that an aggregate or value window function is computed over.
+/// Rows are numbered from 1 in ORDER BY order, and peers are rows whose ORDER BY terms are equal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct WindowFrame {
+    pub mode: ast::FrameMode,
+    pub start: WindowFrameBound,
+    pub end: WindowFrameBound,
+}
+
+/// The offset of a PRECEDING or FOLLOWING bound is a number of rows in ROWS mode, and a difference
+/// from the ORDER BY term of the current row in RANGE mode.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WindowFrameBound {
+    UnboundedPreceding,
+    Preceding(f64),
+    CurrentRow,
+    Following(f64),
+    UnboundedFollowing,
+}
+
+impl WindowFrame {
+    /// The frame of a window without a frame specification: the rows from the start of the
+    /// partition up to the last peer of the current row.
+    pub fn default_frame() -> Self {
+        Self {
+            mode: ast::FrameMode::Range,
+            start: WindowFrameBound::UnboundedPreceding,
+            end: WindowFrameBound::CurrentRow,
+        }
+    }
+}
@@ -5,15 +5,16 @@ use super::{
     plan::{
         Aggregate, ColumnUsedMask, Distinctness, EvalAt, IterationDirection, JoinInfo,
         JoinOrderMember, JoinedTable, Operation, OuterQueryReference, Plan, QueryDestination,
-        ResultSetColumn, TableReferences, WhereTerm,
+        RecursiveCte, ResultSetColumn, SelectPlan, TableReferences, WhereTerm,
     },
     select::prepare_select_plan,
+    window::is_window_function_call,
     SymbolTable,
 };
 use crate::translate::expr::WalkControl;
 use crate::{
     function::Func,
-    schema::{Schema, Table},
+    schema::{Schema, Table, View},
     translate::expr::walk_expr_mut,
     util::{exprs_are_equivalent, normalize_ident},
     vdbe::{builder::TableRefIdCounter, BranchOffset},
@@ -21,8 +22,8 @@ use crate::{
 };
 use turso_sqlite3_parser::ast::Literal::Null;
 use turso_sqlite3_parser::ast::{
-    self, As, Expr, FromClause, JoinType, Limit, Literal, Materialized, QualifiedName,
-    TableInternalId, UnaryOperator, With,
+    self, As, CommonTableExpr, CompoundSelect, Expr, FromClause, JoinType, Limit, Literal,
+    QualifiedName, TableInternalId, UnaryOperator, With,
 };
 
 pub const ROWID: &str = "rowid";
@@ -41,6 +42,10 @@ pub fn resolve_aggregates(
             contains_aggregates = true;
             return Ok(WalkControl::Continue);
         }
+        // Window functions are computed over the window of each row rather than over a group.
+        if is_window_function_call(expr) {
+            return Ok(WalkControl::SkipChildren);
+        }
         match expr {
             Expr::FunctionCall {
                 name,
@@ -88,7 +93,9 @@ pub fn resolve_aggregates(
                 if let Ok(Func::Agg(f)) = Func::resolve_function(&name.0, 0) {
                     aggs.push(Aggregate {
                         func: f,
-                        args: vec![],
+                        // Like for count(*) in the result columns, the aggregate is stepped
+                        // with a constant, which a GROUP BY sorter stores like other arguments.
+                        args: vec![Expr::Literal(ast::Literal::Numeric("1".to_string()))],
                         original_expr: expr.clone(),
                         distinctness: Distinctness::NonDistinct,
                     });
@@ -110,6 +117,11 @@ pub fn bind_column_references(
     result_columns: Option<&[ResultSetColumn]>,
 ) -> Result<()> {
     walk_expr_mut(top_level_expr, &mut |expr: &mut Expr| -> Result<()> {
+        // A column qualified with its database, like `aux.t.x`, is looked up by its table
+        // name alone: the tables are already resolved in their databases.
+        if let Expr::DoublyQualified(_, tbl, id) = expr {
+            *expr = Expr::Qualified(tbl.clone(), id.clone());
+        }
         match expr {
             Expr::Id(id) => {
                 // true and false are special constants that are effectively aliases for 1 and 0
@@ -254,7 +266,7 @@ fn parse_from_clause_table(
     table: ast::SelectTable,
     table_references: &mut TableReferences,
     out_where_clause: &mut Vec<WhereTerm>,
-    ctes: &mut Vec<JoinedTable>,
+    with: &With,
     syms: &SymbolTable,
     table_ref_counter: &mut TableRefIdCounter,
 ) -> Result<()> {
@@ -262,7 +274,8 @@ fn parse_from_clause_table(
         ast::SelectTable::Table(qualified_name, maybe_alias, _) => parse_table(
             schema,
             table_references,
-            ctes,
+            with,
+            syms,
             table_ref_counter,
             out_where_clause,
             qualified_name,
@@ -270,9 +283,11 @@ fn parse_from_clause_table(
             None,
         ),
         ast::SelectTable::Select(subselect, maybe_alias) => {
+            let mut subselect = *subselect;
+            add_ctes_to_select(&mut subselect, with.recursive, &with.ctes);
             let Plan::Select(subplan) = prepare_select_plan(
                 schema,
-                *subselect,
+                subselect,
                 syms,
                 table_references.outer_query_refs(),
                 table_ref_counter,
@@ -302,7 +317,8 @@ fn parse_from_clause_table(
         ast::SelectTable::TableCall(qualified_name, maybe_args, maybe_alias) => parse_table(
             schema,
             table_references,
-            ctes,
+            with,
+            syms,
             table_ref_counter,
             out_where_clause,
             qualified_name,
@@ -317,7 +333,8 @@ fn parse_from_clause_table(
 fn parse_table(
     schema: &Schema,
     table_references: &mut TableReferences,
-    ctes: &mut Vec<JoinedTable>,
+    with: &With,
+    syms: &SymbolTable,
     table_ref_counter: &mut TableRefIdCounter,
     out_where_clause: &mut Vec<WhereTerm>,
     qualified_name: QualifiedName,
@@ -326,18 +343,50 @@ fn parse_table(
 ) -> Result<()> {
     let normalized_qualified_name = normalize_ident(qualified_name.name.0.as_str());
     // Check if the FROM clause table is referring to a CTE in the current scope.
-    if let Some(cte_idx) = ctes
+    if let Some(cte_idx) = with
+        .ctes
         .iter()
-        .position(|cte| cte.identifier == normalized_qualified_name)
+        .position(|cte| normalize_ident(&cte.tbl_name.0) == normalized_qualified_name)
+        .filter(|_| qualified_name.db_name.is_none())
     {
-        // TODO: what if the CTE is referenced multiple times?
-        let cte_table = ctes.remove(cte_idx);
+        let identifier = maybe_alias
+            .map(|a| match a {
+                ast::As::As(id) => normalize_ident(&id.0),
+                ast::As::Elided(id) => normalize_ident(&id.0),
+            })
+            .unwrap_or(normalized_qualified_name);
+        let cte_table = parse_cte_reference(
+            schema,
+            syms,
+            with,
+            cte_idx,
+            identifier,
+            table_references.outer_query_refs(),
+            table_ref_counter,
+        )?;
         table_references.add_joined_table(cte_table);
         return Ok(());
     };
 
+    let database_id = schema.object_database(&qualified_name)?;
+    let db_schema = schema.database(database_id);
+    if let Some(view) = db_schema.get_view(&normalized_qualified_name) {
+        if database_id != 0 {
+            crate::bail_parse_error!("views in attached databases are not supported");
+        }
+        let identifier = maybe_alias
+            .map(|a| match a {
+                ast::As::As(id) => normalize_ident(&id.0),
+                ast::As::Elided(id) => normalize_ident(&id.0),
+            })
+            .unwrap_or(normalized_qualified_name);
+        let view_table = parse_view_reference(schema, syms, view, identifier, table_ref_counter)?;
+        table_references.add_joined_table(view_table);
+        return Ok(());
+    }
+
     // Check if our top level schema has this table.
-    if let Some(table) = schema.get_table(&normalized_qualified_name) {
+    if let Some(table) = db_schema.get_table(&normalized_qualified_name) {
         let alias = maybe_alias
             .map(|a| match a {
                 ast::As::As(id) => id,
@@ -352,6 +401,7 @@ fn parse_table(
                     internal_id,
                     out_where_clause,
                     table.as_ref(),
+                    table_references,
                 )?;
             }
             Table::Virtual(tbl.clone())
@@ -372,31 +422,38 @@ fn parse_table(
             internal_id,
             join_info: None,
             col_used_mask: ColumnUsedMask::default(),
+            database_id,
+            vtab_order_by: Vec::new(),
         });
         return Ok(());
     };
 
-    // CTEs are transformed into FROM clause subqueries.
-    // If we find a CTE with this name in our outer query references,
-    // we can use it as a joined table, but we must clone it since it's not MATERIALIZED.
-    //
-    // For other types of tables in the outer query references, we do not add them as joined tables,
-    // because the query can simply _reference_ them in e.g. the SELECT columns or the WHERE clause,
-    // but it's not part of the join order.
+    // Within the recursive part of a recursive CTE, the CTE itself is an outer query reference.
+    // It is joined like a regular table, and yields the current row of the CTE.
     if let Some(outer_ref) =
         table_references.find_outer_query_ref_by_identifier(&normalized_qualified_name)
     {
         if matches!(outer_ref.table, Table::FromClauseSubquery(_)) {
+            let identifier = maybe_alias
+                .map(|a| match a {
+                    ast::As::As(id) => normalize_ident(&id.0),
+                    ast::As::Elided(id) => normalize_ident(&id.0),
+                })
+                .unwrap_or(normalized_qualified_name);
+            let table = outer_ref.table.clone();
+            let internal_id = outer_ref.internal_id;
             table_references.add_joined_table(JoinedTable {
                 op: Operation::Scan {
                     iter_dir: IterationDirection::Forwards,
                     index: None,
                 },
-                table: outer_ref.table.clone(),
-                identifier: outer_ref.identifier.clone(),
-                internal_id: table_ref_counter.next(),
+                table,
+                identifier,
+                internal_id,
                 join_info: None,
                 col_used_mask: ColumnUsedMask::default(),
+                database_id: 0,
+                vtab_order_by: Vec::new(),
             });
             return Ok(());
         }
@@ -405,11 +462,293 @@ fn parse_table(
     crate::bail_parse_error!("no such table: {}", normalized_qualified_name);
 }
 
+/// Plans a reference to the CTE `with.ctes[cte_idx]` as a FROM clause subquery.
+/// CTEs are not materialized: every reference plans the CTE body again,
+/// so a CTE can be referenced any number of times.
+fn parse_cte_reference(
+    schema: &Schema,
+    syms: &SymbolTable,
+    with: &With,
+    cte_idx: usize,
+    identifier: String,
+    outer_query_refs: &[OuterQueryReference],
+    table_ref_counter: &mut TableRefIdCounter,
+) -> Result<JoinedTable> {
+    let cte = &with.ctes[cte_idx];
+    // A CTE can refer to the CTEs that came before it.
+    let mut cte_select = *cte.select.clone();
+    add_ctes_to_select(&mut cte_select, with.recursive, &with.ctes[..cte_idx]);
+
+    if with.recursive
+        && matches!(
+            cte_select.body.compounds.as_deref(),
+            Some([CompoundSelect {
+                operator: ast::CompoundOperator::Union | ast::CompoundOperator::UnionAll,
+                ..
+            }])
+        )
+    {
+        return parse_recursive_cte(
+            schema,
+            syms,
+            cte,
+            cte_select,
+            identifier,
+            outer_query_refs,
+            table_ref_counter,
+        );
+    }
+
+    let Plan::Select(mut cte_plan) = prepare_select_plan(
+        schema,
+        cte_select,
+        syms,
+        outer_query_refs,
+        table_ref_counter,
+        QueryDestination::CoroutineYield {
+            yield_reg: usize::MAX, // will be set later in bytecode emission
+            coroutine_implementation_start: BranchOffset::Placeholder, // will be set later in bytecode emission
+        },
+    )?
+    else {
+        crate::bail_parse_error!(
+            "Only non-compound SELECT queries are currently supported in CTEs"
+        );
+    };
+    apply_cte_column_names(cte, &mut cte_plan)?;
+    Ok(JoinedTable::new_subquery(
+        identifier,
+        cte_plan,
+        None,
+        table_ref_counter.next(),
+    ))
+}
+
+/// Plans a reference to a view as a FROM clause subquery.
+/// Like CTEs, views are not materialized: every reference plans the view body again.
+pub fn parse_view_reference(
+    schema: &Schema,
+    syms: &SymbolTable,
+    view: &View,
+    identifier: String,
+    table_ref_counter: &mut TableRefIdCounter,
+) -> Result<JoinedTable> {
+    // The body of a view can't refer to the query the view is used in.
+    let Plan::Select(mut view_plan) = prepare_select_plan(
+        schema,
+        view.select.clone(),
+        syms,
+        &[],
+        table_ref_counter,
+        QueryDestination::CoroutineYield {
+            yield_reg: usize::MAX, // will be set later in bytecode emission
+            coroutine_implementation_start: BranchOffset::Placeholder, // will be set later in bytecode emission
+        },
+    )?
+    else {
+        crate::bail_parse_error!(
+            "Only non-compound SELECT queries are currently supported in views"
+        );
+    };
+    if let Some(columns) = &view.columns {
+        if columns.len() != view_plan.result_columns.len() {
+            crate::bail_parse_error!(
+                "expected {} columns for '{}' but got {}",
+                view_plan.result_columns.len(),
+                view.name,
+                columns.len()
+            );
+        }
+        for (result_column, column) in view_plan.result_columns.iter_mut().zip(columns) {
+            result_column.alias = Some(column.clone());
+        }
+    }
+    Ok(JoinedTable::new_subquery(
+        identifier,
+        view_plan,
+        None,
+        table_ref_counter.next(),
+    ))
+}
+
+/// Plans a recursive CTE of the form `initial UNION [ALL] recursive`, where the recursive
+/// SELECT refers to the CTE exactly once in its FROM clause. See [RecursiveCte].
+fn parse_recursive_cte(
+    schema: &Schema,
+    syms: &SymbolTable,
+    cte: &CommonTableExpr,
+    cte_select: ast::Select,
+    identifier: String,
+    outer_query_refs: &[OuterQueryReference],
+    table_ref_counter: &mut TableRefIdCounter,
+) -> Result<JoinedTable> {
+    let ast::Select {
+        with,
+        body,
+        order_by,
+        limit,
+    } = cte_select;
+    if order_by.is_some() {
+        crate::bail_parse_error!("ORDER BY is not supported in recursive CTEs yet");
+    }
+    let (limit, offset) = limit.map_or(Ok((None, None)), |l| parse_limit(&l))?;
+    if offset.is_some_and(|o| try_fold_expr_to_i64(&o).is_none_or(|o| o > 0)) {
+        crate::bail_parse_error!("OFFSET is not supported in recursive CTEs yet");
+    }
+    let ast::SelectBody {
+        select: initial_select,
+        compounds,
+    } = body;
+    let CompoundSelect {
+        operator,
+        select: recursive_select,
+    } = compounds
+        .and_then(|mut compounds| compounds.pop())
+        .expect("recursive CTE must be a compound SELECT");
+    let cte_name = normalize_ident(&cte.tbl_name.0);
+
+    let Plan::Select(mut initial_plan) = prepare_select_plan(
+        schema,
+        ast::Select {
+            with: with.clone(),
+            body: ast::SelectBody {
+                select: initial_select,
+                compounds: None,
+            },
+            order_by: None,
+            limit: None,
+        },
+        syms,
+        outer_query_refs,
+        table_ref_counter,
+        QueryDestination::CoroutineYield {
+            yield_reg: usize::MAX, // will be set later in bytecode emission
+            coroutine_implementation_start: BranchOffset::Placeholder, // will be set later in bytecode emission
+        },
+    )?
+    else {
+        unreachable!("non-compound SELECT must produce a SelectPlan");
+    };
+    apply_cte_column_names(cte, &mut initial_plan)?;
+
+    // The recursive SELECT sees the CTE itself as an outer query reference,
+    // which it joins like a regular table (see parse_table()).
+    let self_table = JoinedTable::new_subquery(
+        cte_name.clone(),
+        initial_plan.clone(),
+        None,
+        table_ref_counter.next(),
+    );
+    let self_table_id = self_table.internal_id;
+    let mut recursive_outer_query_refs = outer_query_refs.to_vec();
+    recursive_outer_query_refs.push(OuterQueryReference {
+        identifier: cte_name.clone(),
+        internal_id: self_table_id,
+        table: self_table.table,
+        col_used_mask: ColumnUsedMask::default(),
+        database_id: 0,
+    });
+    let Plan::Select(recursive_plan) = prepare_select_plan(
+        schema,
+        ast::Select {
+            with,
+            body: ast::SelectBody {
+                select: recursive_select,
+                compounds: None,
+            },
+            order_by: None,
+            limit: None,
+        },
+        syms,
+        &recursive_outer_query_refs,
+        table_ref_counter,
+        QueryDestination::CoroutineYield {
+            yield_reg: usize::MAX, // will be set later in bytecode emission
+            coroutine_implementation_start: BranchOffset::Placeholder, // will be set later in bytecode emission
+        },
+    )?
+    else {
+        unreachable!("non-compound SELECT must produce a SelectPlan");
+    };
+
+    if subqueries_join_table(&recursive_plan.table_references, self_table_id) {
+        crate::bail_parse_error!("recursive reference in a subquery: {}", cte_name);
+    }
+    let self_references = recursive_plan
+        .joined_tables()
+        .iter()
+        .filter(|t| t.internal_id == self_table_id)
+        .count();
+    match self_references {
+        // Without a reference to itself, this is just a compound SELECT.
+        0 => crate::bail_parse_error!(
+            "Only non-compound SELECT queries are currently supported in CTEs"
+        ),
+        1 => {}
+        _ => crate::bail_parse_error!("multiple references to recursive table: {}", cte_name),
+    }
+    if !recursive_plan.aggregates.is_empty() {
+        crate::bail_parse_error!("recursive aggregate queries not supported");
+    }
+
+    let mut cte_table =
+        JoinedTable::new_subquery(identifier, initial_plan, None, table_ref_counter.next());
+    let Table::FromClauseSubquery(from_clause_subquery) = &mut cte_table.table else {
+        unreachable!("new_subquery must produce a FromClauseSubquery");
+    };
+    from_clause_subquery.recursive = Some(Box::new(RecursiveCte {
+        plan: Box::new(recursive_plan),
+        self_table_id,
+        union_all: operator == ast::CompoundOperator::UnionAll,
+        // A negative LIMIT means no limit.
+        limit: limit.filter(|l| try_fold_expr_to_i64(l).is_none_or(|l| l >= 0)),
+    }));
+    Ok(cte_table)
+}
+
+/// Renames the result columns of a CTE plan after the column list of the CTE, if any.
+fn apply_cte_column_names(cte: &CommonTableExpr, cte_plan: &mut SelectPlan) -> Result<()> {
+    let Some(columns) = &cte.columns else {
+        return Ok(());
+    };
+    if columns.len() != cte_plan.result_columns.len() {
+        crate::bail_parse_error!(
+            "table {} has {} values for {} columns",
+            cte.tbl_name.0,
+            cte_plan.result_columns.len(),
+            columns.len()
+        );
+    }
+    for (result_column, column) in cte_plan.result_columns.iter_mut().zip(columns) {
+        result_column.alias = Some(column.col_name.0.clone());
+    }
+    Ok(())
+}
+
+/// Returns true if a FROM clause subquery, at any depth, joins the table with the given internal id.
+fn subqueries_join_table(table_references: &TableReferences, internal_id: TableInternalId) -> bool {
+    table_references
+        .joined_tables()
+        .iter()
+        .any(|t| match &t.table {
+            Table::FromClauseSubquery(subquery) => {
+                subquery
+                    .plan
+                    .joined_tables()
+                    .iter()
+                    .any(|t| t.internal_id == internal_id)
+                    || subqueries_join_table(&subquery.plan.table_references, internal_id)
+            }
+            _ => false,
+        })
+}
+
 fn transform_args_into_where_terms(
     args: Vec<Expr>,
     internal_id: TableInternalId,
     out_where_clause: &mut Vec<WhereTerm>,
     table: &Table,
+    table_references: &mut TableReferences,
 ) -> Result<()> {
     let mut args_iter = args.into_iter();
     let mut hidden_count = 0;
@@ -419,12 +758,9 @@ fn transform_args_into_where_terms(
         }
         hidden_count += 1;
 
-        if let Some(arg_expr) = args_iter.next() {
-            if contains_column_reference(&arg_expr)? {
-                crate::bail_parse_error!(
-                    "Column references are not supported as table-valued function arguments yet"
-                );
-            }
+        if let Some(mut arg_expr) = args_iter.next() {
+            // The arguments can reference the columns of the tables to the left of the function.
+            bind_column_references(&mut arg_expr, table_references, None)?;
             let column_expr = Expr::Column {
                 database: None,
                 table: internal_id,
@@ -459,18 +795,6 @@ fn transform_args_into_where_terms(
     Ok(())
 }
 
-fn contains_column_reference(top_level_expr: &Expr) -> Result<bool> {
-    let mut contains = false;
-    walk_expr(top_level_expr, &mut |expr: &Expr| -> Result<WalkControl> {
-        match expr {
-            Expr::Id(_) | Expr::Qualified(_, _) | Expr::Column { .. } => contains = true,
-            _ => {}
-        };
-        Ok(WalkControl::Continue)
-    })?;
-    Ok(contains)
-}
-
 pub fn parse_from(
     schema: &Schema,
     mut from: Option<FromClause>,
@@ -484,20 +808,11 @@ pub fn parse_from(
         return Ok(());
     }
 
-    let mut ctes_as_subqueries = vec![];
+    let mut ctes: Vec<CommonTableExpr> = vec![];
+    let recursive = with.as_ref().is_some_and(|with| with.recursive);
 
     if let Some(with) = with {
-        if with.recursive {
-            crate::bail_parse_error!("Recursive CTEs are not yet supported");
-        }
         for cte in with.ctes {
-            if cte.materialized == Materialized::Yes {
-                crate::bail_parse_error!("Materialized CTEs are not yet supported");
-            }
-            if cte.columns.is_some() {
-                crate::bail_parse_error!("CTE columns are not yet supported");
-            }
-
             // Check if normalized name conflicts with catalog tables or other CTEs
             // TODO: sqlite actually allows overriding a catalog table with a CTE.
             // We should carry over the 'Scope' struct to all of our identifier resolution.
@@ -508,51 +823,18 @@ pub fn parse_from(
                     cte.tbl_name.0
                 );
             }
-            if table_references
-                .outer_query_refs()
+            if ctes
                 .iter()
-                .any(|t| t.identifier == cte_name_normalized)
+                .any(|t| normalize_ident(&t.tbl_name.0) == cte_name_normalized)
             {
-                crate::bail_parse_error!(
-                    "CTE name {} conflicts with WITH table name {}",
-                    cte.tbl_name.0,
-                    cte_name_normalized
-                );
+                crate::bail_parse_error!("duplicate WITH table name: {}", cte.tbl_name.0);
             }
-
-            let mut outer_query_refs_for_cte = table_references.outer_query_refs().to_vec();
-            outer_query_refs_for_cte.extend(ctes_as_subqueries.iter().map(|t: &JoinedTable| {
-                OuterQueryReference {
-                    identifier: t.identifier.clone(),
-                    internal_id: t.internal_id,
-                    table: t.table.clone(),
-                    col_used_mask: ColumnUsedMask::default(),
-                }
-            }));
-
-            // CTE can refer to other CTEs that came before it, plus any schema tables or tables in the outer scope.
-            let cte_plan = prepare_select_plan(
-                schema,
-                *cte.select,
-                syms,
-                &outer_query_refs_for_cte,
-                table_ref_counter,
-                QueryDestination::CoroutineYield {
-                    yield_reg: usize::MAX, // will be set later in bytecode emission
-                    coroutine_implementation_start: BranchOffset::Placeholder, // will be set later in bytecode emission
-                },
-            )?;
-            let Plan::Select(cte_plan) = cte_plan else {
-                crate::bail_parse_error!("Only SELECT queries are currently supported in CTEs");
-            };
-            ctes_as_subqueries.push(JoinedTable::new_subquery(
-                cte_name_normalized,
-                cte_plan,
-                None,
-                table_ref_counter.next(),
-            ));
+            // The MATERIALIZED / NOT MATERIALIZED hints are accepted but ignored,
+            // the CTE body is planned separately for every reference.
+            ctes.push(cte);
         }
     }
+    let with = With { recursive, ctes };
 
     let mut from_owned = std::mem::take(&mut from).unwrap();
     let select_owned = *std::mem::take(&mut from_owned.select).unwrap();
@@ -562,7 +844,7 @@ pub fn parse_from(
         select_owned,
         table_references,
         out_where_clause,
-        &mut ctes_as_subqueries,
+        &with,
         syms,
         table_ref_counter,
     )?;
@@ -572,7 +854,7 @@ pub fn parse_from(
             schema,
             join,
             syms,
-            &mut ctes_as_subqueries,
+            &with,
             out_where_clause,
             table_references,
             table_ref_counter,
@@ -582,6 +864,24 @@ pub fn parse_from(
     Ok(())
 }
 
+/// Makes the CTEs of the enclosing scope visible to a nested SELECT by
+/// prepending them to the nested SELECT's own WITH clause.
+pub(crate) fn add_ctes_to_select(
+    select: &mut ast::Select,
+    recursive: bool,
+    ctes: &[CommonTableExpr],
+) {
+    if ctes.is_empty() {
+        return;
+    }
+    let with = select.with.get_or_insert_with(|| With {
+        recursive: false,
+        ctes: vec![],
+    });
+    with.recursive |= recursive;
+    with.ctes.splice(0..0, ctes.iter().cloned());
+}
+
 pub fn parse_where(
     where_clause: Option<Expr>,
     table_references: &mut TableReferences,
@@ -767,11 +1067,11 @@ pub fn determine_where_to_eval_expr(
     walk_expr(top_level_expr, &mut |expr: &Expr| -> Result<WalkControl> {
         match expr {
             Expr::Column { table, .. } | Expr::RowId { table, .. } => {
-                let join_idx = join_order
-                    .iter()
-                    .position(|t| t.table_id == *table)
-                    .unwrap_or(usize::MAX);
-                eval_at = eval_at.max(EvalAt::Loop(join_idx));
+                // Tables of outer query scopes are not in the join order: their current row is
+                // already available before the loops of this query are opened.
+                if let Some(join_idx) = join_order.iter().position(|t| t.table_id == *table) {
+                    eval_at = eval_at.max(EvalAt::Loop(join_idx));
+                }
             }
             _ => {}
         }
@@ -785,7 +1085,7 @@ fn parse_join(
     schema: &Schema,
     join: ast::JoinedSelectTable,
     syms: &SymbolTable,
-    ctes: &mut Vec<JoinedTable>,
+    with: &With,
     out_where_clause: &mut Vec<WhereTerm>,
     table_references: &mut TableReferences,
     table_ref_counter: &mut TableRefIdCounter,
@@ -801,7 +1101,7 @@ fn parse_join(
         table,
         table_references,
         out_where_clause,
-        ctes,
+        with,
         syms,
         table_ref_counter,
     )?;
@@ -982,42 +1282,58 @@ fn parse_join(
     Ok(())
 }
 
-pub fn parse_limit(limit: &Limit) -> Result<(Option<isize>, Option<isize>)> {
-    let offset_val = match &limit.offset {
-        Some(offset_expr) => match offset_expr {
-            Expr::Literal(ast::Literal::Numeric(n)) => n.parse().ok(),
-            // If OFFSET is negative, the result is as if OFFSET is zero
-            Expr::Unary(UnaryOperator::Negative, expr) => {
-                if let Expr::Literal(ast::Literal::Numeric(ref n)) = &**expr {
-                    n.parse::<isize>().ok().map(|num| -num)
-                } else {
-                    crate::bail_parse_error!("Invalid OFFSET clause");
-                }
-            }
-            _ => crate::bail_parse_error!("Invalid OFFSET clause"),
-        },
-        None => Some(0),
-    };
+/// The LIMIT and OFFSET expressions of a query.
+pub type LimitAndOffset = (Option<Box<Expr>>, Option<Box<Expr>>);
 
-    if let Expr::Literal(ast::Literal::Numeric(n)) = &limit.expr {
-        Ok((n.parse().ok(), offset_val))
-    } else if let Expr::Unary(UnaryOperator::Negative, expr) = &limit.expr {
-        if let Expr::Literal(ast::Literal::Numeric(n)) = &**expr {
-            let limit_val = n.parse::<isize>().ok().map(|num| -num);
-            Ok((limit_val, offset_val))
-        } else {
-            crate::bail_parse_error!("Invalid LIMIT clause");
-        }
-    } else if let Expr::Id(id) = &limit.expr {
-        if id.0.eq_ignore_ascii_case("true") {
-            Ok((Some(1), offset_val))
-        } else if id.0.eq_ignore_ascii_case("false") {
-            Ok((Some(0), offset_val))
-        } else {
-            crate::bail_parse_error!("Invalid LIMIT clause");
+/// Returns the LIMIT and OFFSET expressions of a LIMIT clause. They may be any expression that
+/// does not refer to a table, e.g. `10`, `5 * 2` or a bound parameter, and are evaluated once
+/// before the query runs.
+pub fn parse_limit(limit: &Limit) -> Result<LimitAndOffset> {
+    check_limit_expr(&limit.expr, "LIMIT")?;
+    if let Some(offset) = &limit.offset {
+        check_limit_expr(offset, "OFFSET")?;
+    }
+    Ok((
+        Some(Box::new(limit.expr.clone())),
+        limit.offset.clone().map(Box::new),
+    ))
+}
+
+fn check_limit_expr(expr: &Expr, clause: &str) -> Result<()> {
+    walk_expr(expr, &mut |expr: &Expr| -> Result<WalkControl> {
+        match expr {
+            Expr::Id(id)
+                if !id.0.eq_ignore_ascii_case("true") && !id.0.eq_ignore_ascii_case("false") =>
+            {
+                crate::bail_parse_error!("no such column: {}", id.0);
+            }
+            Expr::Qualified(tbl, col) => {
+                crate::bail_parse_error!("no such column: {}.{}", tbl.0, col.0);
+            }
+            Expr::DoublyQualified(db, tbl, col) => {
+                crate::bail_parse_error!("no such column: {}.{}.{}", db.0, tbl.0, col.0);
+            }
+            Expr::Exists(_) | Expr::Subquery(_) | Expr::InSelect { .. } => {
+                crate::bail_parse_error!("Subqueries in {} are not supported", clause);
+            }
+            _ => {}
         }
-    } else {
-        crate::bail_parse_error!("Invalid LIMIT clause");
+        Ok(WalkControl::Continue)
+    })?;
+    Ok(())
+}
+
+/// Returns the value of a LIMIT or OFFSET expression if it is an integer constant, e.g. `10`,
+/// `-1` or `true`, so that it can be known without evaluating the expression.
+pub fn try_fold_expr_to_i64(expr: &Expr) -> Option<i64> {
+    match expr {
+        Expr::Literal(ast::Literal::Numeric(n)) => n.parse().ok(),
+        Expr::Unary(UnaryOperator::Negative, expr) => try_fold_expr_to_i64(expr)?.checked_neg(),
+        Expr::Unary(UnaryOperator::Positive, expr) => try_fold_expr_to_i64(expr),
+        Expr::Parenthesized(exprs) if exprs.len() == 1 => try_fold_expr_to_i64(&exprs[0]),
+        Expr::Id(id) if id.0.eq_ignore_ascii_case("true") => Some(1),
+        Expr::Id(id) if id.0.eq_ignore_ascii_case("false") => Some(0),
+        _ => None,
     }
 }
 
@@ -1027,6 +1343,24 @@ pub fn break_predicate_at_and_boundaries(predicate: Expr, out_predicates: &mut V
             break_predicate_at_and_boundaries(*left, out_predicates);
             break_predicate_at_and_boundaries(*right, out_predicates);
         }
+        // Row values are equal if each of their columns is, and each of those equalities
+        // may constrain a table on its own, e.g. by seeking a multi-column index.
+        Expr::Binary(left, ast::Operator::Equals, right)
+            if matches!(
+                (left.as_ref(), right.as_ref()),
+                (Expr::Parenthesized(l), Expr::Parenthesized(r)) if l.len() > 1 && l.len() == r.len()
+            ) =>
+        {
+            let (Expr::Parenthesized(left), Expr::Parenthesized(right)) = (*left, *right) else {
+                unreachable!();
+            };
+            for (left, right) in left.into_iter().zip(right) {
+                break_predicate_at_and_boundaries(
+                    Expr::Binary(Box::new(left), ast::Operator::Equals, Box::new(right)),
+                    out_predicates,
+                );
+            }
+        }
         _ => {
             out_predicates.push(predicate);
         }
@@ -7,21 +7,32 @@ use turso_sqlite3_parser::ast::{self, ColumnDefinition, Expr};
 use turso_sqlite3_parser::ast::{PragmaName, QualifiedName};
 
 use crate::pragma::pragma_for;
-use crate::schema::Schema;
-use crate::storage::pager::AutoVacuumMode;
+use crate::schema::{Schema, EXPR_INDEX_COLUMN};
+use crate::storage::pager::{AutoVacuumMode, JournalMode, Synchronous, DB_STATE_INITIALIZED};
 use crate::storage::sqlite3_ondisk::MIN_PAGE_CACHE_SIZE;
 use crate::storage::wal::CheckpointMode;
 use crate::translate::schema::translate_create_table;
-use crate::util::{normalize_ident, parse_signed_number, parse_string};
+use crate::util::{
+    normalize_ident, parse_pragma_bool, parse_signed_number, parse_string,
+    PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX,
+};
 use crate::vdbe::builder::{ProgramBuilder, ProgramBuilderOpts};
 use crate::vdbe::insn::{Cookie, Insn};
 use crate::{bail_parse_error, storage, CaptureDataChangesMode, LimboError, Value};
 use std::str::FromStr;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
 use strum::IntoEnumIterator;
 
+use super::analyze::translate_optimize;
+use super::attach::ensure_main_database;
+use super::expr::{is_double_quoted_identifier, sanitize_double_quoted_string, sanitize_string};
 use super::integrity_check::translate_integrity_check;
+use crate::storage::compression::CompressionMode;
+use crate::storage::encryption::{CipherMode, EncryptionKey};
 use crate::storage::header_accessor;
 use crate::storage::pager::Pager;
+use crate::storage::text_encoding::TextEncoding;
 use crate::translate::emitter::TransactionMode;
 
 fn list_pragmas(program: &mut ProgramBuilder) {
@@ -58,11 +69,16 @@ pub fn translate_pragma(
         Ok(pragma) => pragma,
         Err(_) => bail_parse_error!("Not a valid pragma name"),
     };
+    ensure_main_database(schema, name, "PRAGMA")?;
 
     let (mut program, mode) = match body {
         None => query_pragma(pragma, schema, None, pager, connection, program)?,
         Some(ast::PragmaBody::Equals(value) | ast::PragmaBody::Call(value)) => match pragma {
-            PragmaName::TableInfo => {
+            PragmaName::TableInfo
+            | PragmaName::IndexList
+            | PragmaName::IndexInfo
+            | PragmaName::IntegrityCheck
+            | PragmaName::QuickCheck => {
                 query_pragma(pragma, schema, Some(value), pager, connection, program)?
             }
             _ => update_pragma(pragma, schema, value, pager, connection, program)?,
@@ -82,6 +98,22 @@ fn update_pragma(
     mut program: ProgramBuilder,
 ) -> crate::Result<(ProgramBuilder, TransactionMode)> {
     match pragma {
+        PragmaName::BusyTimeout => {
+            let timeout = match parse_signed_number(&value)? {
+                Value::Integer(timeout) => timeout,
+                Value::Float(timeout) => timeout as i64,
+                _ => bail_parse_error!("Invalid value for busy timeout pragma"),
+            };
+            connection.set_busy_timeout(Duration::from_millis(timeout.max(0) as u64));
+            query_pragma(
+                PragmaName::BusyTimeout,
+                schema,
+                None,
+                pager,
+                connection,
+                program,
+            )
+        }
         PragmaName::CacheSize => {
             let cache_size = match parse_signed_number(&value)? {
                 Value::Integer(size) => size,
@@ -91,15 +123,155 @@ fn update_pragma(
             update_cache_size(cache_size, pager, connection)?;
             Ok((program, TransactionMode::None))
         }
-        PragmaName::JournalMode => query_pragma(
-            PragmaName::JournalMode,
+        PragmaName::CacheSpill => {
+            // Like in SQLite, an integer other than 0 or 1 sets the spill size and a boolean
+            // enables or disables spilling.
+            match parse_signed_number(&value) {
+                Ok(Value::Integer(pages)) if !(0..=1).contains(&pages) => {
+                    connection.set_cache_spill_size(pages.clamp(0, u32::MAX as i64) as u32);
+                    connection.set_cache_spill(true);
+                }
+                _ => connection.set_cache_spill(parse_pragma_bool(&value)?),
+            }
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::CaseSensitiveLike => {
+            connection.set_case_sensitive_like(parse_pragma_bool(&value)?);
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::Cipher => {
+            let Some(cipher) =
+                pragma_arg_name(Some(value)).and_then(|name| CipherMode::from_str(&name).ok())
+            else {
+                bail_parse_error!(
+                    "unknown cipher, expected {} or {}",
+                    CipherMode::Aes256Gcm,
+                    CipherMode::XChaCha20Poly1305
+                );
+            };
+            connection.set_cipher(cipher);
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::Compression => {
+            let Some(compression) =
+                pragma_arg_name(Some(value)).and_then(|name| CompressionMode::from_str(&name).ok())
+            else {
+                bail_parse_error!(
+                    "unknown compression, expected {}, {} or {}",
+                    CompressionMode::None,
+                    CompressionMode::Lz4,
+                    CompressionMode::Zstd
+                );
+            };
+            pager.set_compression(compression)?;
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::Encoding => {
+            let name = pragma_arg_name(Some(value)).unwrap_or_default();
+            let Ok(encoding) = TextEncoding::from_str(&name) else {
+                bail_parse_error!("unsupported encoding: {}", name);
+            };
+            pager.set_text_encoding(encoding);
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::Hexkey | PragmaName::Hexrekey | PragmaName::Key | PragmaName::Rekey => {
+            let Some(key) = pragma_arg_text(&value) else {
+                bail_parse_error!("the key must be a string");
+            };
+            let key = match pragma {
+                PragmaName::Hexkey | PragmaName::Hexrekey => EncryptionKey::parse_hex(&key)?,
+                _ => EncryptionKey::parse(&key)?,
+            };
+            match pragma {
+                PragmaName::Hexkey | PragmaName::Key => connection.set_encryption_key(key)?,
+                _ => connection.rekey(key)?,
+            }
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::DatabaseList => query_pragma(
+            PragmaName::DatabaseList,
             schema,
             None,
             pager,
             connection,
             program,
         ),
+        PragmaName::JournalMode => {
+            // Like in SQLite, an unknown mode leaves the journal mode as it is.
+            let mode =
+                pragma_arg_name(Some(value)).and_then(|name| JournalMode::from_str(&name).ok());
+            // Like in SQLite, the journal of an in-memory database stays in memory.
+            if let Some(mode) = mode.filter(|_| !connection.is_in_memory()) {
+                let into_wal = mode == JournalMode::Wal;
+                if into_wal != (pager.journal_mode() == JournalMode::Wal)
+                    && !connection.get_auto_commit()
+                {
+                    return Err(LimboError::TxError(format!(
+                        "cannot change {} wal mode from within a transaction",
+                        if into_wal { "into" } else { "out of" }
+                    )));
+                }
+                pager.set_journal_mode(mode)?;
+            }
+            query_pragma(
+                PragmaName::JournalMode,
+                schema,
+                None,
+                pager,
+                connection,
+                program,
+            )
+        }
+        PragmaName::IndexAdvisor => {
+            connection.set_index_advisor(parse_pragma_bool(&value)?);
+            Ok((program, TransactionMode::None))
+        }
         PragmaName::LegacyFileFormat => Ok((program, TransactionMode::None)),
+        // The argument of SQLite, a mask of the optimizations to run, is ignored.
+        PragmaName::Optimize => query_pragma(
+            PragmaName::Optimize,
+            schema,
+            None,
+            pager,
+            connection,
+            program,
+        ),
+        PragmaName::Synchronous => {
+            let synchronous = match parse_signed_number(&value) {
+                Ok(Value::Integer(level)) => match level {
+                    ..=0 => Synchronous::Off,
+                    1 => Synchronous::Normal,
+                    2 => Synchronous::Full,
+                    _ => Synchronous::Extra,
+                },
+                _ => match pragma_arg_name(Some(value.clone()))
+                    .and_then(|name| Synchronous::from_str(&name).ok())
+                {
+                    Some(synchronous) => synchronous,
+                    None if parse_pragma_bool(&value)? => Synchronous::Full,
+                    None => Synchronous::Off,
+                },
+            };
+            pager.set_synchronous(synchronous);
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::WalAutocheckpoint => {
+            let frames = match parse_signed_number(&value)? {
+                Value::Integer(frames) => frames,
+                Value::Float(frames) => frames as i64,
+                _ => bail_parse_error!("Invalid value for wal_autocheckpoint pragma"),
+            };
+            // Zero or a negative value turns auto-checkpointing off.
+            pager.set_wal_autocheckpoint(frames.max(0) as usize);
+            query_pragma(
+                PragmaName::WalAutocheckpoint,
+                schema,
+                None,
+                pager,
+                connection,
+                program,
+            )
+        }
         PragmaName::WalCheckpoint => query_pragma(
             PragmaName::WalCheckpoint,
             schema,
@@ -117,26 +289,34 @@ fn update_pragma(
             program,
         ),
         PragmaName::UserVersion => {
-            let data = parse_signed_number(&value)?;
-            let version_value = match data {
-                Value::Integer(i) => i as i32,
-                Value::Float(f) => f as i32,
-                _ => unreachable!(),
-            };
-
             program.emit_insn(Insn::SetCookie {
                 db: 0,
                 cookie: Cookie::UserVersion,
-                value: version_value,
+                value: parse_cookie_value(&value)?,
+                p5: 1,
+            });
+            Ok((program, TransactionMode::Write))
+        }
+        PragmaName::ApplicationId => {
+            program.emit_insn(Insn::SetCookie {
+                db: 0,
+                cookie: Cookie::ApplicationId,
+                value: parse_cookie_value(&value)?,
                 p5: 1,
             });
             Ok((program, TransactionMode::Write))
         }
         PragmaName::SchemaVersion => {
-            // TODO: Implement updating schema_version
-            todo!("updating schema_version not yet implemented")
+            // Like in SQLite, the schema cookie is set as is, even if it goes backwards.
+            program.emit_insn(Insn::SetCookie {
+                db: 0,
+                cookie: Cookie::SchemaVersion,
+                value: parse_cookie_value(&value)?,
+                p5: 1,
+            });
+            Ok((program, TransactionMode::Write))
         }
-        PragmaName::TableInfo => {
+        PragmaName::TableInfo | PragmaName::IndexList | PragmaName::IndexInfo => {
             // because we need control over the write parameter for the transaction,
             // this should be unreachable. We have to force-call query_pragma before
             // getting here
@@ -152,13 +332,13 @@ fn update_pragma(
             Ok((program, TransactionMode::None))
         }
         PragmaName::AutoVacuum => {
-            let auto_vacuum_mode = match value {
+            let auto_vacuum_mode = match &value {
                 Expr::Name(name) => {
                     let name = name.0.to_lowercase();
                     match name.as_str() {
-                        "none" => 0,
-                        "full" => 1,
-                        "incremental" => 2,
+                        "none" => AutoVacuumMode::None,
+                        "full" => AutoVacuumMode::Full,
+                        "incremental" => AutoVacuumMode::Incremental,
                         _ => {
                             return Err(LimboError::InvalidArgument(
                                 "invalid auto vacuum mode".to_string(),
@@ -166,48 +346,76 @@ fn update_pragma(
                         }
                     }
                 }
-                _ => {
-                    return Err(LimboError::InvalidArgument(
-                        "invalid auto vacuum mode".to_string(),
-                    ))
-                }
+                _ => match parse_signed_number(&value) {
+                    Ok(Value::Integer(1)) => AutoVacuumMode::Full,
+                    Ok(Value::Integer(2)) => AutoVacuumMode::Incremental,
+                    Ok(_) => AutoVacuumMode::None,
+                    Err(_) => {
+                        return Err(LimboError::InvalidArgument(
+                            "invalid auto vacuum mode".to_string(),
+                        ))
+                    }
+                },
             };
-            match auto_vacuum_mode {
-                0 => update_auto_vacuum_mode(AutoVacuumMode::None, 0, pager)?,
-                1 => update_auto_vacuum_mode(AutoVacuumMode::Full, 1, pager)?,
-                2 => update_auto_vacuum_mode(AutoVacuumMode::Incremental, 1, pager)?,
-                _ => {
-                    return Err(LimboError::InvalidArgument(
-                        "invalid auto vacuum mode".to_string(),
-                    ))
+            if pager.db_state.load(Ordering::SeqCst) < DB_STATE_INITIALIZED {
+                // The header of the database records the mode once its first page is written.
+                pager.set_auto_vacuum_mode(auto_vacuum_mode);
+                return Ok((program, TransactionMode::None));
+            }
+            let enable = !matches!(auto_vacuum_mode, AutoVacuumMode::None);
+            let enabled = !matches!(pager.get_auto_vacuum_mode(), AutoVacuumMode::None);
+            if enable != enabled {
+                // Like in SQLite, auto-vacuum can only be turned on or off before the first table
+                // is created, since every page needs a pointer map entry. Otherwise this is a no-op.
+                if header_accessor::get_database_size(&pager)? > 1 {
+                    return Ok((program, TransactionMode::None));
                 }
+                program.emit_insn(Insn::SetCookie {
+                    db: 0,
+                    cookie: Cookie::LargestRootPageNumber,
+                    value: enable as i32,
+                    p5: 0,
+                });
+            } else {
+                let largest_root_page_number_reg = program.alloc_register();
+                program.emit_insn(Insn::ReadCookie {
+                    db: 0,
+                    dest: largest_root_page_number_reg,
+                    cookie: Cookie::LargestRootPageNumber,
+                });
+                let set_cookie_label = program.allocate_label();
+                program.emit_insn(Insn::If {
+                    reg: largest_root_page_number_reg,
+                    target_pc: set_cookie_label,
+                    jump_if_null: false,
+                });
+                program.emit_insn(Insn::Halt {
+                    err_code: 0,
+                    description: "Early halt because auto vacuum mode is not enabled".to_string(),
+                    on_error: ast::ResolveType::Abort,
+                });
+                program.resolve_label(set_cookie_label, program.offset());
             }
-            let largest_root_page_number_reg = program.alloc_register();
-            program.emit_insn(Insn::ReadCookie {
-                db: 0,
-                dest: largest_root_page_number_reg,
-                cookie: Cookie::LargestRootPageNumber,
-            });
-            let set_cookie_label = program.allocate_label();
-            program.emit_insn(Insn::If {
-                reg: largest_root_page_number_reg,
-                target_pc: set_cookie_label,
-                jump_if_null: false,
-            });
-            program.emit_insn(Insn::Halt {
-                err_code: 0,
-                description: "Early halt because auto vacuum mode is not enabled".to_string(),
-            });
-            program.resolve_label(set_cookie_label, program.offset());
             program.emit_insn(Insn::SetCookie {
                 db: 0,
                 cookie: Cookie::IncrementalVacuum,
-                value: auto_vacuum_mode - 1,
+                value: matches!(auto_vacuum_mode, AutoVacuumMode::Incremental) as i32,
                 p5: 0,
             });
-            Ok((program, TransactionMode::None))
+            Ok((program, TransactionMode::Write))
+        }
+        PragmaName::IncrementalVacuum => {
+            let limit = match parse_signed_number(&value)? {
+                Value::Integer(limit) => limit,
+                Value::Float(limit) => limit as i64,
+                _ => bail_parse_error!("Invalid value for incremental_vacuum pragma"),
+            };
+            translate_incremental_vacuum(&mut program, limit);
+            Ok((program, TransactionMode::Write))
+        }
+        PragmaName::IntegrityCheck | PragmaName::QuickCheck => {
+            unreachable!("integrity_check cannot be set")
         }
-        PragmaName::IntegrityCheck => unreachable!("integrity_check cannot be set"),
         PragmaName::UnstableCaptureDataChangesConn => {
             let value = parse_string(&value)?;
             // todo(sivukhin): ideally, we should consistently update capture_data_changes connection flag only after successfull execution of schema change statement
@@ -245,19 +453,110 @@ fn query_pragma(
 ) -> crate::Result<(ProgramBuilder, TransactionMode)> {
     let register = program.alloc_register();
     match pragma {
+        PragmaName::BusyTimeout => {
+            program.emit_int(connection.get_busy_timeout().as_millis() as i64, register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma_for(&pragma).columns[0].to_string());
+            Ok((program, TransactionMode::None))
+        }
         PragmaName::CacheSize => {
             program.emit_int(connection.get_cache_size() as i64, register);
             program.emit_result_row(register, 1);
             program.add_pragma_result_column(pragma.to_string());
             Ok((program, TransactionMode::None))
         }
+        PragmaName::CacheSpill => {
+            let pages = if connection.get_cache_spill() {
+                let page_size = header_accessor::get_page_size(&pager)
+                    .unwrap_or(storage::sqlite3_ondisk::DEFAULT_PAGE_SIZE)
+                    as usize;
+                connection.get_cache_spill_pages(page_size) as i64
+            } else {
+                0
+            };
+            program.emit_int(pages, register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma.to_string());
+            Ok((program, TransactionMode::None))
+        }
+        // The pragma can only be set, like in SQLite.
+        PragmaName::CaseSensitiveLike => Ok((program, TransactionMode::None)),
+        PragmaName::Cipher => {
+            program.emit_string8(connection.get_cipher().to_string(), register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma.to_string());
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::Compression => {
+            program.emit_string8(pager.compression()?.to_string(), register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma.to_string());
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::Encoding => {
+            program.emit_string8(pager.text_encoding()?.to_string(), register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma.to_string());
+            Ok((program, TransactionMode::None))
+        }
+        // The keys can only be set.
+        PragmaName::Hexkey | PragmaName::Hexrekey | PragmaName::Key | PragmaName::Rekey => {
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::DatabaseList => {
+            let base_reg = register;
+            program.alloc_registers(2);
+            for (seq, name, file) in connection.database_list() {
+                program.emit_int(seq as i64, base_reg);
+                program.emit_string8(name, base_reg + 1);
+                program.emit_string8(file, base_reg + 2);
+                program.emit_result_row(base_reg, 3);
+            }
+            for name in pragma_for(&pragma).columns {
+                program.add_pragma_result_column(name.to_string());
+            }
+            Ok((program, TransactionMode::None))
+        }
         PragmaName::JournalMode => {
-            program.emit_string8("wal".into(), register);
+            let mode = if connection.is_in_memory() {
+                JournalMode::Memory
+            } else {
+                pager.journal_mode()
+            };
+            program.emit_string8(mode.to_string(), register);
             program.emit_result_row(register, 1);
             program.add_pragma_result_column(pragma.to_string());
             Ok((program, TransactionMode::None))
         }
+        PragmaName::IndexAdvisor => {
+            for advice in connection.get_index_advice() {
+                program.emit_string8(advice, register);
+                program.emit_result_row(register, 1);
+            }
+            program.add_pragma_result_column(pragma_for(&pragma).columns[0].to_string());
+            Ok((program, TransactionMode::None))
+        }
         PragmaName::LegacyFileFormat => Ok((program, TransactionMode::None)),
+        PragmaName::Optimize => {
+            let mode = if translate_optimize(schema, &mut program)? {
+                TransactionMode::Write
+            } else {
+                TransactionMode::None
+            };
+            Ok((program, mode))
+        }
+        PragmaName::Synchronous => {
+            program.emit_int(pager.synchronous() as i64, register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma.to_string());
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::WalAutocheckpoint => {
+            program.emit_int(pager.wal_autocheckpoint() as i64, register);
+            program.emit_result_row(register, 1);
+            program.add_pragma_result_column(pragma_for(&pragma).columns[0].to_string());
+            Ok((program, TransactionMode::None))
+        }
         PragmaName::WalCheckpoint => {
             // Checkpoint uses 3 registers: P1, P2, P3. Ref Insn::Checkpoint for more info.
             // Allocate two more here as one was allocated at the top.
@@ -271,12 +570,6 @@ fn query_pragma(
                 _ => CheckpointMode::Passive,
             };
 
-            if !matches!(mode, CheckpointMode::Passive) {
-                return Err(LimboError::ParseError(
-                    "only Passive mode supported".to_string(),
-                ));
-            }
-
             program.alloc_registers(2);
             program.emit_insn(Insn::Checkpoint {
                 database: 0,
@@ -296,25 +589,21 @@ fn query_pragma(
             Ok((program, TransactionMode::Read))
         }
         PragmaName::TableInfo => {
-            let table = match value {
-                Some(ast::Expr::Name(name)) => {
-                    let tbl = normalize_ident(&name.0);
-                    schema.get_table(&tbl)
-                }
-                _ => None,
-            };
+            let table = pragma_arg_name(value).and_then(|name| schema.get_table(&name));
 
             let base_reg = register;
             program.alloc_registers(5);
             if let Some(table) = table {
+                let btree = table.btree();
                 // According to the SQLite documentation: "The 'cid' column should not be taken to
                 // mean more than 'rank within the current result set'."
                 // Therefore, we enumerate only after filtering out hidden columns.
                 for (i, column) in table.columns().iter().filter(|col| !col.hidden).enumerate() {
+                    let name = column.name.clone().unwrap_or_default();
                     // cid
                     program.emit_int(i as i64, base_reg);
                     // name
-                    program.emit_string8(column.name.clone().unwrap_or_default(), base_reg + 1);
+                    program.emit_string8(name.clone(), base_reg + 1);
 
                     // type
                     program.emit_string8(column.ty_str.clone(), base_reg + 2);
@@ -332,15 +621,94 @@ fn query_pragma(
                         }
                     }
 
-                    // pk
-                    program.emit_bool(column.primary_key, base_reg + 5);
+                    // pk: the position of the column in the primary key, starting at 1
+                    let pk = match &btree {
+                        Some(btree) => btree
+                            .primary_key_columns
+                            .iter()
+                            .position(|(pk_name, _)| pk_name.eq_ignore_ascii_case(&name))
+                            .map_or(0, |pos| pos + 1),
+                        None => column.primary_key as usize,
+                    };
+                    program.emit_int(pk as i64, base_reg + 5);
 
                     program.emit_result_row(base_reg, 6);
                 }
             }
-            let col_names = ["cid", "name", "type", "notnull", "dflt_value", "pk"];
-            for name in col_names {
-                program.add_pragma_result_column(name.into());
+            for name in pragma_for(&pragma).columns {
+                program.add_pragma_result_column(name.to_string());
+            }
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::IndexList => {
+            let table_name = pragma_arg_name(value);
+            let base_reg = register;
+            program.alloc_registers(4);
+            if let Some(table) = table_name.and_then(|name| schema.get_btree_table(&name)) {
+                // Indexes are listed in the order they were created. The automatic ones are
+                // created with the table, but loading the schema adds them last.
+                let mut indexes = schema.get_indices(&table.name).iter().collect::<Vec<_>>();
+                indexes.sort_by_key(|index| {
+                    !index
+                        .name
+                        .starts_with(PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX)
+                });
+                for (seq, index) in indexes.into_iter().enumerate() {
+                    let origin =
+                        if !index
+                            .name
+                            .starts_with(PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX)
+                        {
+                            "c"
+                        } else if index.columns.len() == table.primary_key_columns.len()
+                            && index.columns.iter().zip(&table.primary_key_columns).all(
+                                |(column, (pk_name, _))| column.name.eq_ignore_ascii_case(pk_name),
+                            )
+                        {
+                            "pk"
+                        } else {
+                            "u"
+                        };
+                    program.emit_int(seq as i64, base_reg);
+                    program.emit_string8(index.name.clone(), base_reg + 1);
+                    program.emit_bool(index.unique, base_reg + 2);
+                    program.emit_string8(origin.to_string(), base_reg + 3);
+                    program.emit_bool(index.where_clause.is_some(), base_reg + 4);
+                    program.emit_result_row(base_reg, 5);
+                }
+            }
+            for name in pragma_for(&pragma).columns {
+                program.add_pragma_result_column(name.to_string());
+            }
+            Ok((program, TransactionMode::None))
+        }
+        PragmaName::IndexInfo => {
+            let index_name = pragma_arg_name(value);
+            let index = index_name.and_then(|name| {
+                schema
+                    .indexes
+                    .values()
+                    .flatten()
+                    .find(|index| index.name.eq_ignore_ascii_case(&name))
+            });
+            let base_reg = register;
+            program.alloc_registers(2);
+            if let Some(index) = index {
+                for (seqno, column) in index.columns.iter().enumerate() {
+                    program.emit_int(seqno as i64, base_reg);
+                    // Like SQLite, an expression column has the cid -2 and no name.
+                    if column.pos_in_table == EXPR_INDEX_COLUMN {
+                        program.emit_int(-2, base_reg + 1);
+                        program.emit_null(base_reg + 2, None);
+                    } else {
+                        program.emit_int(column.pos_in_table as i64, base_reg + 1);
+                        program.emit_string8(column.name.clone(), base_reg + 2);
+                    }
+                    program.emit_result_row(base_reg, 3);
+                }
+            }
+            for name in pragma_for(&pragma).columns {
+                program.add_pragma_result_column(name.to_string());
             }
             Ok((program, TransactionMode::None))
         }
@@ -354,6 +722,16 @@ fn query_pragma(
             program.emit_result_row(register, 1);
             Ok((program, TransactionMode::Read))
         }
+        PragmaName::ApplicationId => {
+            program.emit_insn(Insn::ReadCookie {
+                db: 0,
+                dest: register,
+                cookie: Cookie::ApplicationId,
+            });
+            program.add_pragma_result_column(pragma.to_string());
+            program.emit_result_row(register, 1);
+            Ok((program, TransactionMode::Read))
+        }
         PragmaName::SchemaVersion => {
             program.emit_insn(Insn::ReadCookie {
                 db: 0,
@@ -390,8 +768,18 @@ fn query_pragma(
             program.emit_result_row(register, 1);
             Ok((program, TransactionMode::None))
         }
-        PragmaName::IntegrityCheck => {
-            translate_integrity_check(schema, &mut program)?;
+        PragmaName::IncrementalVacuum => {
+            translate_incremental_vacuum(&mut program, 0);
+            Ok((program, TransactionMode::Write))
+        }
+        PragmaName::IntegrityCheck | PragmaName::QuickCheck => {
+            // The argument is the maximum number of errors to report
+            let max_errors = match value.as_ref().map(parse_signed_number) {
+                Some(Ok(Value::Integer(n))) if n > 0 => Some(n as usize),
+                _ => None,
+            };
+            let quick = pragma == PragmaName::QuickCheck;
+            translate_integrity_check(schema, &mut program, max_errors, quick)?;
             Ok((program, TransactionMode::Read))
         }
         PragmaName::UnstableCaptureDataChangesConn => {
@@ -412,14 +800,70 @@ fn query_pragma(
     }
 }
 
-fn update_auto_vacuum_mode(
-    auto_vacuum_mode: AutoVacuumMode,
-    largest_root_page_number: u32,
-    pager: Rc<Pager>,
-) -> crate::Result<()> {
-    header_accessor::set_vacuum_mode_largest_root_page(&pager, largest_root_page_number)?;
-    pager.set_auto_vacuum_mode(auto_vacuum_mode);
-    Ok(())
+/// The value a pragma stores in a 32-bit field of the database header, which wraps around like in
+/// SQLite.
+fn parse_cookie_value(value: &ast::Expr) -> crate::Result<i32> {
+    match parse_signed_number(value)? {
+        Value::Integer(i) => Ok(i as i32),
+        Value::Float(f) => Ok(f as i32),
+        _ => bail_parse_error!("Invalid value for header cookie pragma"),
+    }
+}
+
+/// The text of a pragma argument as it was written, for the keys, whose case matters.
+fn pragma_arg_text(value: &ast::Expr) -> Option<String> {
+    let text = match value {
+        ast::Expr::Name(ast::Name(text))
+        | ast::Expr::Id(ast::Id(text))
+        | ast::Expr::Literal(ast::Literal::String(text)) => text,
+        _ => return None,
+    };
+    if text.len() >= 2 && text.starts_with('\'') && text.ends_with('\'') {
+        Some(sanitize_string(text))
+    } else if is_double_quoted_identifier(text) {
+        Some(sanitize_double_quoted_string(text))
+    } else {
+        Some(text.clone())
+    }
+}
+
+/// Whether the pragma named `name` sets up the key of an encrypted database, which is all a
+/// connection can do before the key is given.
+pub(crate) fn is_key_pragma(name: &QualifiedName) -> bool {
+    matches!(
+        PragmaName::from_str(&name.name.0),
+        Ok(PragmaName::Cipher | PragmaName::Hexkey | PragmaName::Key)
+    )
+}
+
+/// The name a pragma argument gives, e.g. a table or a mode, which can be written as an identifier,
+/// a string or a keyword like `DELETE`.
+fn pragma_arg_name(value: Option<ast::Expr>) -> Option<String> {
+    match value? {
+        ast::Expr::Literal(ast::Literal::Keyword(keyword)) => Some(keyword.to_lowercase()),
+        value => pragma_arg_text(&value).map(|text| text.to_lowercase()),
+    }
+}
+
+/// Emits the loop of `PRAGMA incremental_vacuum(N)`, which frees up to `limit` pages, or all the
+/// free pages if `limit` isn't positive.
+fn translate_incremental_vacuum(program: &mut ProgramBuilder, limit: i64) {
+    let limit = if limit <= 0 { i64::MAX } else { limit };
+    let register = program.alloc_register();
+    program.emit_int(limit - 1, register);
+    let loop_start = program.allocate_label();
+    let loop_end = program.allocate_label();
+    program.preassign_label_to_next_insn(loop_start);
+    program.emit_insn(Insn::IncrVacuum {
+        db: 0,
+        target_pc: loop_end,
+    });
+    program.emit_insn(Insn::IfPos {
+        reg: register,
+        target_pc: loop_start,
+        decrement_by: 1,
+    });
+    program.preassign_label_to_next_insn(loop_end);
 }
 
 fn update_cache_size(
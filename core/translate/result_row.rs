@@ -1,8 +1,11 @@
+use std::num::NonZeroUsize;
+
 use crate::{
+    schema::{BTreeTable, Index},
     vdbe::{
         builder::ProgramBuilder,
         insn::{IdxInsertFlags, InsertFlags, Insn},
-        BranchOffset,
+        BranchOffset, CursorID,
     },
     Result,
 };
@@ -30,7 +33,7 @@ pub fn emit_select_result(
     limit_ctx: Option<LimitCtx>,
 ) -> Result<()> {
     if let (Some(jump_to), Some(_)) = (offset_jump_to, label_on_limit_reached) {
-        emit_offset(program, plan, jump_to, reg_offset)?;
+        emit_offset(program, jump_to, reg_offset);
     }
 
     let start_reg = reg_result_cols_start;
@@ -55,8 +58,10 @@ pub fn emit_select_result(
     }
 
     // Handle SELECT DISTINCT deduplication
-    if let Distinctness::Distinct { ctx } = &plan.distinctness {
-        let distinct_ctx = ctx.as_ref().expect("distinct context must exist");
+    if let Distinctness::Distinct {
+        ctx: Some(distinct_ctx),
+    } = &plan.distinctness
+    {
         let num_regs = plan.result_columns.len();
         distinct_ctx.emit_deduplication_insns(program, num_regs, start_reg);
     }
@@ -86,6 +91,7 @@ pub fn emit_result_row_and_limit(
             cursor_id: index_cursor_id,
             index: dedupe_index,
             is_delete,
+            affinity_str,
         } => {
             if *is_delete {
                 program.emit_insn(Insn::IdxDelete {
@@ -95,20 +101,14 @@ pub fn emit_result_row_and_limit(
                     raise_error_if_no_matching_entry: false,
                 });
             } else {
-                let record_reg = program.alloc_register();
-                program.emit_insn(Insn::MakeRecord {
-                    start_reg: result_columns_start_reg,
-                    count: plan.result_columns.len(),
-                    dest_reg: record_reg,
-                    index_name: Some(dedupe_index.name.clone()),
-                });
-                program.emit_insn(Insn::IdxInsert {
-                    cursor_id: *index_cursor_id,
-                    record_reg,
-                    unpacked_start: None,
-                    unpacked_count: None,
-                    flags: IdxInsertFlags::new().no_op_duplicate(),
-                });
+                emit_ephemeral_index_insert(
+                    program,
+                    *index_cursor_id,
+                    dedupe_index,
+                    affinity_str.as_deref(),
+                    result_columns_start_reg,
+                    plan.result_columns.len(),
+                );
             }
         }
         QueryDestination::EphemeralTable {
@@ -139,6 +139,36 @@ pub fn emit_result_row_and_limit(
                 end_offset: BranchOffset::Offset(0),
             });
         }
+        QueryDestination::RecursiveCteQueue {
+            cursor_id,
+            table,
+            distinct_cursor_id,
+        } => {
+            emit_recursive_cte_queue_insert(
+                program,
+                *cursor_id,
+                table,
+                *distinct_cursor_id,
+                result_columns_start_reg,
+                plan.result_columns.len(),
+            );
+        }
+        QueryDestination::ExistsSubqueryResult { result_reg } => {
+            program.emit_insn(Insn::Integer {
+                value: 1,
+                dest: *result_reg,
+            });
+        }
+        QueryDestination::RowValueSubqueryResult {
+            result_reg_start,
+            num_regs,
+        } => {
+            program.emit_insn(Insn::Copy {
+                src_reg: result_columns_start_reg,
+                dst_reg: *result_reg_start,
+                extra_amount: num_regs - 1,
+            });
+        }
     }
 
     if plan.limit.is_some() {
@@ -158,22 +188,104 @@ pub fn emit_result_row_and_limit(
     Ok(())
 }
 
-pub fn emit_offset(
+/// Skips the row by jumping to `jump_to` while the OFFSET counter in `reg_offset`, if any,
+/// is positive.
+pub fn emit_offset(program: &mut ProgramBuilder, jump_to: BranchOffset, reg_offset: Option<usize>) {
+    if let Some(reg_offset) = reg_offset {
+        program.add_comment(program.offset(), "OFFSET");
+        program.emit_insn(Insn::IfPos {
+            reg: reg_offset,
+            target_pc: jump_to,
+            decrement_by: 1,
+        });
+    }
+}
+
+/// Inserts the row in the registers starting at `start_reg` into an ephemeral index,
+/// unless the index already contains it.
+pub fn emit_ephemeral_index_insert(
     program: &mut ProgramBuilder,
-    plan: &SelectPlan,
-    jump_to: BranchOffset,
-    reg_offset: Option<usize>,
-) -> Result<()> {
-    match plan.offset {
-        Some(offset) if offset > 0 => {
-            program.add_comment(program.offset(), "OFFSET");
-            program.emit_insn(Insn::IfPos {
-                reg: reg_offset.expect("reg_offset must be Some"),
-                target_pc: jump_to,
-                decrement_by: 1,
-            });
-        }
-        _ => {}
+    cursor_id: CursorID,
+    index: &Index,
+    affinity_str: Option<&str>,
+    start_reg: usize,
+    num_regs: usize,
+) {
+    if let Some(affinity_str) = affinity_str {
+        program.emit_insn(Insn::Affinity {
+            start_reg,
+            count: NonZeroUsize::new(num_regs).unwrap(),
+            affinities: affinity_str.to_string(),
+        });
     }
-    Ok(())
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg,
+        count: num_regs,
+        dest_reg: record_reg,
+        index_name: Some(index.name.clone()),
+    });
+    program.emit_insn(Insn::IdxInsert {
+        cursor_id,
+        record_reg,
+        unpacked_start: None,
+        unpacked_count: None,
+        flags: IdxInsertFlags::new().no_op_duplicate(),
+    });
+}
+
+/// Appends a row to the queue of a recursive CTE.
+/// If the queue has a distinct index (UNION), rows that were queued before are skipped.
+pub fn emit_recursive_cte_queue_insert(
+    program: &mut ProgramBuilder,
+    cursor_id: CursorID,
+    table: &BTreeTable,
+    distinct_cursor_id: Option<CursorID>,
+    start_reg: usize,
+    num_regs: usize,
+) {
+    let label_skip = program.allocate_label();
+    if let Some(distinct_cursor_id) = distinct_cursor_id {
+        program.emit_insn(Insn::Found {
+            cursor_id: distinct_cursor_id,
+            target_pc: label_skip,
+            record_reg: start_reg,
+            num_regs,
+        });
+        let distinct_record_reg = program.alloc_register();
+        program.emit_insn(Insn::MakeRecord {
+            start_reg,
+            count: num_regs,
+            dest_reg: distinct_record_reg,
+            index_name: None,
+        });
+        program.emit_insn(Insn::IdxInsert {
+            cursor_id: distinct_cursor_id,
+            record_reg: distinct_record_reg,
+            unpacked_start: None,
+            unpacked_count: None,
+            flags: IdxInsertFlags::new(),
+        });
+    }
+    let record_reg = program.alloc_register();
+    program.emit_insn(Insn::MakeRecord {
+        start_reg,
+        count: num_regs,
+        dest_reg: record_reg,
+        index_name: Some(table.name.clone()),
+    });
+    let rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::NewRowid {
+        cursor: cursor_id,
+        rowid_reg,
+        prev_largest_reg: 0,
+    });
+    program.emit_insn(Insn::Insert {
+        cursor: cursor_id,
+        key_reg: rowid_reg,
+        record_reg,
+        flag: InsertFlags::new(),
+        table_name: table.name.clone(),
+    });
+    program.preassign_label_to_next_insn(label_skip);
 }
@@ -0,0 +1,198 @@
+//! The RETURNING clause of INSERT, UPDATE and DELETE.
+//!
+//! Like in SQLite, no row is returned before every change has been made: the RETURNING
+//! expressions are evaluated for each changed row as it is written, and the results are
+//! buffered in an ephemeral table that is read back once the statement is done changing rows.
+
+use std::rc::Rc;
+
+use turso_sqlite3_parser::ast::{self, Expr, ResultColumn};
+
+use crate::{
+    bail_parse_error,
+    schema::{BTreeTable, Column, Type},
+    util::normalize_ident,
+    vdbe::{
+        builder::{CursorType, ProgramBuilder},
+        insn::{InsertFlags, Insn},
+        CursorID,
+    },
+    Result,
+};
+
+use super::{
+    emitter::{rewrite_column_refs_to_registers, Resolver},
+    expr::{translate_expr, walk_expr_mut},
+    optimizer::rewrite_expr,
+    plan::ResultSetColumn,
+};
+
+/// Resolves the RETURNING clause of a statement that changes `table`, which the statement
+/// refers to as `identifier`. `*` expands to the columns of the table.
+///
+/// The expressions are not bound to a table reference, because they are evaluated against the
+/// registers holding each changed row, see [Returning::emit_row].
+pub fn prepare_returning(
+    returning: Vec<ResultColumn>,
+    table: &BTreeTable,
+    identifier: &str,
+) -> Result<Vec<ResultSetColumn>> {
+    let identifier = normalize_ident(identifier);
+    let mut result_columns = Vec::with_capacity(returning.len());
+    for column in returning {
+        match column {
+            ResultColumn::Star => {
+                for column in table.columns.iter().filter(|c| !c.hidden) {
+                    let name = column.name.clone().expect("column name is None");
+                    result_columns.push(ResultSetColumn {
+                        expr: Expr::Id(ast::Id(name.clone())),
+                        alias: Some(name),
+                        contains_aggregates: false,
+                    });
+                }
+            }
+            ResultColumn::TableStar(_) => {
+                bail_parse_error!("RETURNING may not use \"TABLE.*\" wildcards");
+            }
+            ResultColumn::Expr(mut expr, alias) => {
+                rewrite_expr(&mut expr)?;
+                walk_expr_mut(&mut expr, &mut |expr: &mut Expr| -> Result<()> {
+                    let Expr::Qualified(tbl_name, col_name) = expr else {
+                        return Ok(());
+                    };
+                    let tbl_name_normalized = normalize_ident(&tbl_name.0);
+                    if tbl_name_normalized != identifier && tbl_name_normalized != table.name {
+                        bail_parse_error!("no such column: {}.{}", tbl_name.0, col_name.0);
+                    }
+                    *expr = Expr::Id(ast::Id(col_name.0.clone()));
+                    Ok(())
+                })?;
+                // Unknown columns are reported now rather than when the rows are emitted.
+                rewrite_column_refs_to_registers(table, &mut expr.clone(), 0, 0)?;
+                result_columns.push(ResultSetColumn {
+                    expr,
+                    alias: alias.map(|alias| match alias {
+                        ast::As::As(alias) | ast::As::Elided(alias) => alias.0,
+                    }),
+                    contains_aggregates: false,
+                });
+            }
+        }
+    }
+    Ok(result_columns)
+}
+
+/// The buffer the rows of a RETURNING clause are written to while the statement runs.
+pub struct Returning {
+    exprs: Vec<Expr>,
+    cursor_id: CursorID,
+    table_name: String,
+}
+
+impl Returning {
+    /// Opens the buffer for `result_columns`, or returns `None` if there is no RETURNING clause.
+    pub fn open(program: &mut ProgramBuilder, result_columns: &[ResultSetColumn]) -> Option<Self> {
+        if result_columns.is_empty() {
+            return None;
+        }
+        let buffer_column = || Column {
+            name: None,
+            ty: Type::Blob,
+            ty_str: "BLOB".to_string(),
+            is_rowid_alias: false,
+            primary_key: false,
+            notnull: false,
+            notnull_conflict_clause: None,
+            default: None,
+            unique: false,
+            unique_conflict_clause: None,
+            collation: None,
+            hidden: false,
+            generated: None,
+        };
+        let buffer_table = Rc::new(BTreeTable {
+            root_page: 0, // Not relevant for ephemeral table definition
+            name: "returning".to_string(),
+            has_rowid: true,
+            primary_key_columns: vec![],
+            columns: result_columns.iter().map(|_| buffer_column()).collect(),
+            is_strict: false,
+            unique_sets: None,
+            check_constraints: vec![],
+            primary_key_conflict_clause: None,
+            has_autoincrement: false,
+        });
+        let table_name = buffer_table.name.clone();
+        let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(buffer_table));
+        program.emit_insn(Insn::OpenEphemeral {
+            cursor_id,
+            is_table: true,
+        });
+        Some(Self {
+            exprs: result_columns.iter().map(|c| c.expr.clone()).collect(),
+            cursor_id,
+            table_name,
+        })
+    }
+
+    /// Evaluates the RETURNING expressions for a changed row of `table`, whose rowid is in
+    /// `rowid_reg` and whose columns are in the registers starting at `columns_start_reg`,
+    /// and buffers the result.
+    pub fn emit_row(
+        &self,
+        program: &mut ProgramBuilder,
+        resolver: &Resolver,
+        table: &BTreeTable,
+        rowid_reg: usize,
+        columns_start_reg: usize,
+    ) -> Result<()> {
+        let start_reg = program.alloc_registers(self.exprs.len());
+        for (i, expr) in self.exprs.iter().enumerate() {
+            let mut expr = expr.clone();
+            rewrite_column_refs_to_registers(table, &mut expr, rowid_reg, columns_start_reg)?;
+            translate_expr(program, None, &expr, start_reg + i, resolver)?;
+        }
+        let record_reg = program.alloc_register();
+        program.emit_insn(Insn::MakeRecord {
+            start_reg,
+            count: self.exprs.len(),
+            dest_reg: record_reg,
+            index_name: None,
+        });
+        let buffer_rowid_reg = program.alloc_register();
+        program.emit_insn(Insn::NewRowid {
+            cursor: self.cursor_id,
+            rowid_reg: buffer_rowid_reg,
+            prev_largest_reg: 0,
+        });
+        program.emit_insn(Insn::Insert {
+            cursor: self.cursor_id,
+            key_reg: buffer_rowid_reg,
+            record_reg,
+            flag: InsertFlags::new().require_seek(),
+            table_name: self.table_name.clone(),
+        });
+        Ok(())
+    }
+
+    /// Returns the buffered rows. Emitted once every row has been changed.
+    pub fn emit_result_rows(&self, program: &mut ProgramBuilder) {
+        let loop_start_label = program.allocate_label();
+        let loop_end_label = program.allocate_label();
+        program.emit_insn(Insn::Rewind {
+            cursor_id: self.cursor_id,
+            pc_if_empty: loop_end_label,
+        });
+        program.preassign_label_to_next_insn(loop_start_label);
+        let start_reg = program.alloc_registers(self.exprs.len());
+        for i in 0..self.exprs.len() {
+            program.emit_column(self.cursor_id, i, start_reg + i);
+        }
+        program.emit_result_row(start_reg, self.exprs.len());
+        program.emit_insn(Insn::Next {
+            cursor_id: self.cursor_id,
+            pc_if_next: loop_start_label,
+        });
+        program.preassign_label_to_next_insn(loop_end_label);
+    }
+}
@@ -3,7 +3,11 @@ use turso_sqlite3_parser::ast::Name;
 use crate::{
     schema::Schema,
     translate::emitter::TransactionMode,
-    vdbe::{builder::ProgramBuilder, insn::Insn},
+    util::normalize_ident,
+    vdbe::{
+        builder::ProgramBuilder,
+        insn::{Insn, SavepointOp},
+    },
     Result, SymbolTable,
 };
 
@@ -14,13 +18,19 @@ pub fn translate_rollback(
     txn_name: Option<Name>,
     savepoint_name: Option<Name>,
 ) -> Result<ProgramBuilder> {
-    assert!(
-        txn_name.is_none() && savepoint_name.is_none(),
-        "txn_name and savepoint not supported yet"
-    );
+    assert!(txn_name.is_none(), "txn_name not supported yet");
+    if let Some(savepoint_name) = savepoint_name {
+        program.emit_insn(Insn::Savepoint {
+            op: SavepointOp::Rollback,
+            name: normalize_ident(&savepoint_name.0),
+        });
+        program.epilogue(TransactionMode::None);
+        return Ok(program);
+    }
     program.emit_insn(Insn::AutoCommit {
         auto_commit: true,
         rollback: true,
+        concurrent: false,
     });
     program.epilogue_maybe_rollback(TransactionMode::None, true);
     Ok(program)
@@ -3,20 +3,23 @@ use std::ops::Range;
 use std::rc::Rc;
 
 use crate::ast;
-use crate::ext::VTabImpl;
+use crate::ext::{VTabImpl, VTabModule};
+use crate::function::Func;
 use crate::schema::BTreeTable;
 use crate::schema::Column;
 use crate::schema::Schema;
 use crate::schema::Table;
 use crate::schema::Type;
 use crate::storage::pager::CreateBTreeFlags;
+use crate::translate::collate::CollationSeq;
+use crate::translate::expr::{walk_expr, WalkControl};
 use crate::translate::ProgramBuilder;
 use crate::translate::ProgramBuilderOpts;
 use crate::util::normalize_ident;
 use crate::util::PRIMARY_KEY_AUTOMATIC_INDEX_NAME_PREFIX;
 use crate::vdbe::builder::CursorType;
 use crate::vdbe::insn::Cookie;
-use crate::vdbe::insn::{CmpInsFlags, InsertFlags, Insn};
+use crate::vdbe::insn::{ChangeCount, CmpInsFlags, InsertFlags, Insn};
 use crate::LimboError;
 use crate::SymbolTable;
 use crate::{bail_parse_error, Result};
@@ -32,17 +35,21 @@ pub fn translate_create_table(
     schema: &Schema,
     mut program: ProgramBuilder,
 ) -> Result<ProgramBuilder> {
-    if temporary {
-        bail_parse_error!("TEMPORARY table not supported yet");
-    }
     let opts = ProgramBuilderOpts {
         num_cursors: 1,
         approx_num_insns: 30,
         approx_num_labels: 1,
     };
     program.extend(&opts);
+    // Unqualified names are created in the main database, or in the temp one for TEMP tables.
+    let database_id = match &tbl_name.db_name {
+        Some(db_name) => schema.database_index(&db_name.0)?,
+        None if temporary => schema.database_index("temp")?,
+        None => 0,
+    };
+    let db_schema = schema.database(database_id);
     let normalized_tbl_name = normalize_ident(&tbl_name.name.0);
-    if schema.get_table(&normalized_tbl_name).is_some() {
+    if db_schema.get_table(&normalized_tbl_name).is_some() {
         if if_not_exists {
             program.epilogue(crate::translate::emitter::TransactionMode::Write);
 
@@ -50,9 +57,71 @@ pub fn translate_create_table(
         }
         bail_parse_error!("Table {} already exists", normalized_tbl_name);
     }
+    if db_schema.get_view(&normalized_tbl_name).is_some() {
+        bail_parse_error!("view {} already exists", normalized_tbl_name);
+    }
+
+    if normalized_tbl_name.starts_with("sqlite_") {
+        bail_parse_error!(
+            "object name reserved for internal use: {}",
+            normalized_tbl_name
+        );
+    }
 
     let sql = create_table_body_to_str(&tbl_name, &body);
 
+    let mut has_autoincrement = false;
+    if let ast::CreateTableBody::ColumnsAndConstraints {
+        columns,
+        constraints,
+        ..
+    } = &body
+    {
+        // The schema accepts any collation name, so check here that those of the columns exist.
+        for c in columns.values().flat_map(|col_def| &col_def.constraints) {
+            if let ast::ColumnConstraint::Collate { collation_name } = &c.constraint {
+                CollationSeq::new(&collation_name.0)?;
+            }
+        }
+        let has_generated_columns = columns.values().any(|col_def| {
+            col_def
+                .constraints
+                .iter()
+                .any(|c| matches!(c.constraint, ast::ColumnConstraint::Generated { .. }))
+        });
+        has_autoincrement = columns.values().any(|col_def| {
+            col_def.constraints.iter().any(|c| {
+                matches!(
+                    c.constraint,
+                    ast::ColumnConstraint::PrimaryKey {
+                        auto_increment: true,
+                        ..
+                    }
+                )
+            })
+        }) || constraints.iter().flatten().any(|c| {
+            matches!(
+                c.constraint,
+                ast::TableConstraint::PrimaryKey {
+                    auto_increment: true,
+                    ..
+                }
+            )
+        });
+        if has_generated_columns || has_autoincrement {
+            let table = BTreeTable::from_sql(&sql, 0)?;
+            if has_generated_columns {
+                validate_generated_columns(&table)?;
+            }
+            if has_autoincrement && table.get_rowid_alias_column().is_none() {
+                bail_parse_error!("AUTOINCREMENT is only allowed on an INTEGER PRIMARY KEY");
+            }
+            if has_autoincrement && database_id != 0 {
+                bail_parse_error!("AUTOINCREMENT in attached databases is not supported");
+            }
+        }
+    }
+
     let parse_schema_label = program.allocate_label();
     // TODO: ReadCookie
     // TODO: If
@@ -62,7 +131,7 @@ pub fn translate_create_table(
     // Create the table B-tree
     let table_root_reg = program.alloc_register();
     program.emit_insn(Insn::CreateBtree {
-        db: 0,
+        db: database_id,
         root: table_root_reg,
         flags: CreateBTreeFlags::new_table(),
     });
@@ -98,7 +167,7 @@ pub fn translate_create_table(
         }
         for index_reg in index_regs.clone() {
             program.emit_insn(Insn::CreateBtree {
-                db: 0,
+                db: database_id,
                 root: index_reg,
                 flags: CreateBTreeFlags::new_index(),
             });
@@ -111,6 +180,7 @@ pub fn translate_create_table(
         cursor_id: sqlite_schema_cursor_id,
         root_page: 1usize.into(),
         name: tbl_name.name.0.clone(),
+        db: database_id,
     });
 
     // Add the table entry to sqlite_schema
@@ -145,19 +215,44 @@ pub fn translate_create_table(
         }
     }
 
+    // The first AUTOINCREMENT table in the database also creates the sqlite_sequence table,
+    // which tracks the largest rowid ever used by each AUTOINCREMENT table.
+    let create_sequence_table =
+        has_autoincrement && schema.get_table(SQLITE_SEQUENCE_TABLE).is_none();
+    if create_sequence_table {
+        let sequence_root_reg = program.alloc_register();
+        program.emit_insn(Insn::CreateBtree {
+            db: 0,
+            root: sequence_root_reg,
+            flags: CreateBTreeFlags::new_table(),
+        });
+        emit_schema_entry(
+            &mut program,
+            sqlite_schema_cursor_id,
+            SchemaEntryType::Table,
+            SQLITE_SEQUENCE_TABLE,
+            SQLITE_SEQUENCE_TABLE,
+            sequence_root_reg,
+            Some(format!("CREATE TABLE {SQLITE_SEQUENCE_TABLE}(name,seq)")),
+        );
+    }
+
     program.resolve_label(parse_schema_label, program.offset());
     // TODO: SetCookie
     program.emit_insn(Insn::SetCookie {
-        db: 0,
+        db: database_id,
         cookie: Cookie::SchemaVersion,
-        value: schema.schema_version as i32 + 1,
+        value: db_schema.schema_version as i32 + 1,
         p5: 0,
     });
     // TODO: remove format, it sucks for performance but is convenient
-    let parse_schema_where_clause =
-        format!("tbl_name = '{normalized_tbl_name}' AND type != 'trigger'");
+    let parse_schema_where_clause = if create_sequence_table {
+        format!("tbl_name IN ('{normalized_tbl_name}', '{SQLITE_SEQUENCE_TABLE}') AND type != 'trigger'")
+    } else {
+        format!("tbl_name = '{normalized_tbl_name}' AND type != 'trigger'")
+    };
     program.emit_insn(Insn::ParseSchema {
-        db: sqlite_schema_cursor_id,
+        db: database_id,
         where_clause: Some(parse_schema_where_clause),
     });
 
@@ -167,10 +262,79 @@ pub fn translate_create_table(
     Ok(program)
 }
 
+/// Checks the generated columns of `table`: their expressions may only refer to the other
+/// columns of the row, and the generated columns may not depend on each other in a loop.
+pub fn validate_generated_columns(table: &BTreeTable) -> Result<()> {
+    for column in &table.columns {
+        let Some(generated) = &column.generated else {
+            continue;
+        };
+        if column.default.is_some() {
+            bail_parse_error!("cannot use DEFAULT on a generated column");
+        }
+        if column.primary_key {
+            bail_parse_error!("generated columns cannot be part of the PRIMARY KEY");
+        }
+        let in_unique_set = table.unique_sets.iter().flatten().any(|set| {
+            set.columns
+                .iter()
+                .any(|(name, _)| Some(name) == column.name.as_ref())
+        });
+        if !generated.stored && (column.unique || in_unique_set) {
+            bail_parse_error!(
+                "UNIQUE constraints on VIRTUAL generated columns are not supported yet"
+            );
+        }
+        walk_expr(
+            &generated.expr,
+            &mut |expr: &ast::Expr| -> Result<WalkControl> {
+                match expr {
+                    ast::Expr::Id(id) if table.get_column(&id.0).is_none() => {
+                        bail_parse_error!("no such column: {}", id.0);
+                    }
+                    ast::Expr::Qualified(..) | ast::Expr::DoublyQualified(..) => {
+                        bail_parse_error!("the \".\" operator prohibited in generated columns");
+                    }
+                    ast::Expr::Subquery(_) | ast::Expr::Exists(_) | ast::Expr::InSelect { .. } => {
+                        bail_parse_error!("subqueries prohibited in generated columns");
+                    }
+                    ast::Expr::Variable(_) => {
+                        bail_parse_error!("parameters prohibited in generated columns");
+                    }
+                    ast::Expr::FunctionCall { name, args, .. } => {
+                        let arg_count = args.as_ref().map_or(0, |args| args.len());
+                        check_generated_column_function(&name.0, arg_count)?;
+                    }
+                    ast::Expr::FunctionCallStar { name, .. } => {
+                        check_generated_column_function(&name.0, 0)?;
+                    }
+                    _ => {}
+                }
+                Ok(WalkControl::Continue)
+            },
+        )?;
+    }
+    table.generated_columns_in_order()?;
+    Ok(())
+}
+
+fn check_generated_column_function(name: &str, arg_count: usize) -> Result<()> {
+    // Functions that don't resolve may still be registered by an extension at runtime.
+    match Func::resolve_function(name, arg_count) {
+        Ok(Func::Agg(_)) => bail_parse_error!("misuse of aggregate function {}()", name),
+        Ok(func) if !func.is_deterministic() => {
+            bail_parse_error!("non-deterministic functions prohibited in generated columns")
+        }
+        _ => Ok(()),
+    }
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum SchemaEntryType {
     Table,
     Index,
+    Trigger,
+    View,
 }
 
 impl SchemaEntryType {
@@ -178,10 +342,14 @@ impl SchemaEntryType {
         match self {
             SchemaEntryType::Table => "table",
             SchemaEntryType::Index => "index",
+            SchemaEntryType::Trigger => "trigger",
+            SchemaEntryType::View => "view",
         }
     }
 }
 pub const SQLITE_TABLEID: &str = "sqlite_schema";
+pub const SQLITE_SEQUENCE_TABLE: &str = "sqlite_sequence";
+pub const SQLITE_STAT1_TABLE: &str = "sqlite_stat1";
 
 pub fn emit_schema_entry(
     program: &mut ProgramBuilder,
@@ -241,6 +409,103 @@ pub fn emit_schema_entry(
     });
 }
 
+/// Deletes the sqlite_schema row of the schema object of type `entry_type` named `name`.
+pub fn emit_delete_schema_entry(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    entry_type: SchemaEntryType,
+    name: &str,
+) {
+    let name_reg = program.emit_string8_new_reg(name.to_string());
+    let type_reg = program.emit_string8_new_reg(entry_type.as_str().to_string());
+
+    let sqlite_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+    let sqlite_schema_cursor_id =
+        program.alloc_cursor_id(CursorType::BTreeTable(sqlite_table.clone()));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: sqlite_schema_cursor_id,
+        root_page: 1usize.into(),
+        name: sqlite_table.name.clone(),
+        db: 0,
+    });
+
+    let loop_start_label = program.allocate_label();
+    let loop_end_label = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id: sqlite_schema_cursor_id,
+        pc_if_empty: loop_end_label,
+    });
+    program.preassign_label_to_next_insn(loop_start_label);
+
+    let next_label = program.allocate_label();
+    let dest_reg = program.alloc_register();
+    program.emit_column(sqlite_schema_cursor_id, 1, dest_reg);
+    program.emit_insn(Insn::Ne {
+        lhs: name_reg,
+        rhs: dest_reg,
+        target_pc: next_label,
+        flags: CmpInsFlags::default(),
+        collation: program.curr_collation(),
+    });
+    program.emit_column(sqlite_schema_cursor_id, 0, dest_reg);
+    program.emit_insn(Insn::Ne {
+        lhs: type_reg,
+        rhs: dest_reg,
+        target_pc: next_label,
+        flags: CmpInsFlags::default(),
+        collation: program.curr_collation(),
+    });
+    program.emit_insn(Insn::Delete {
+        cursor_id: sqlite_schema_cursor_id,
+        nchange: ChangeCount::None,
+        table_name: None,
+        is_update: false,
+    });
+
+    program.preassign_label_to_next_insn(next_label);
+    program.emit_insn(Insn::Next {
+        cursor_id: sqlite_schema_cursor_id,
+        pc_if_next: loop_start_label,
+    });
+    program.preassign_label_to_next_insn(loop_end_label);
+}
+
+/// Deletes the sqlite_sequence row of the AUTOINCREMENT table `table_name`.
+fn emit_delete_sequence_entry(
+    program: &mut ProgramBuilder,
+    sequence_table: Rc<BTreeTable>,
+    db: usize,
+    table_name: &str,
+) {
+    let name_reg = program.emit_string8_new_reg(table_name.to_string());
+    let cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(sequence_table.clone()));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id,
+        root_page: sequence_table.root_page.into(),
+        name: sequence_table.name.clone(),
+        db,
+    });
+    program.cursor_loop(cursor_id, |program, _| {
+        let next_label = program.allocate_label();
+        let dest_reg = program.alloc_register();
+        program.emit_column(cursor_id, 0, dest_reg);
+        program.emit_insn(Insn::Ne {
+            lhs: name_reg,
+            rhs: dest_reg,
+            target_pc: next_label,
+            flags: CmpInsFlags::default().jump_if_null(),
+            collation: Some(CollationSeq::NoCase),
+        });
+        program.emit_insn(Insn::Delete {
+            cursor_id,
+            nchange: ChangeCount::None,
+            table_name: None,
+            is_update: false,
+        });
+        program.preassign_label_to_next_insn(next_label);
+    });
+}
+
 #[derive(Debug)]
 struct PrimaryKeyColumnInfo<'a> {
     name: &'a String,
@@ -337,13 +602,9 @@ fn check_automatic_pk_index_required(
                         }
                     } else if let ast::TableConstraint::Unique {
                         columns: unique_columns,
-                        conflict_clause,
+                        ..
                     } = &constraint.constraint
                     {
-                        if conflict_clause.is_some() {
-                            unimplemented!("ON CONFLICT not implemented");
-                        }
-
                         let col_names = unique_columns
                             .iter()
                             .map(|column| match &column.expr {
@@ -484,17 +745,21 @@ fn create_vtable_body_to_str(vtab: &CreateVirtualTable, module: Rc<VTabImpl>) ->
     } else {
         ""
     };
-    let ext_args = vtab
-        .args
-        .as_ref()
-        .unwrap_or(&vec![])
-        .iter()
-        .map(|a| turso_ext::Value::from_text(a.to_string()))
-        .collect::<Vec<_>>();
-    let schema = module
-        .implementation
-        .create_schema(ext_args)
-        .unwrap_or_default();
+    let schema = match &module.implementation {
+        VTabModule::Ext(implementation) => {
+            let ext_args = vtab
+                .args
+                .as_ref()
+                .unwrap_or(&vec![])
+                .iter()
+                .map(|a| turso_ext::Value::from_text(a.to_string()))
+                .collect::<Vec<_>>();
+            implementation.create_schema(ext_args).unwrap_or_default()
+        }
+        // The modules of SQLite extensions only declare their schema when a table is created
+        #[cfg(feature = "fs")]
+        VTabModule::Sqlite(_) => String::new(),
+    };
     let vtab_args = if let Some(first_paren) = schema.find('(') {
         let closing_paren = schema.rfind(')').unwrap_or_default();
         &schema[first_paren..=closing_paren]
@@ -586,6 +851,7 @@ pub fn translate_create_virtual_table(
         cursor_id: sqlite_schema_cursor_id,
         root_page: 1usize.into(),
         name: table_name.clone(),
+        db: 0,
     });
 
     let sql = create_vtable_body_to_str(&vtab, vtab_module.clone());
@@ -607,7 +873,7 @@ pub fn translate_create_virtual_table(
     });
     let parse_schema_where_clause = format!("tbl_name = '{table_name}' AND type != 'trigger'");
     program.emit_insn(Insn::ParseSchema {
-        db: sqlite_schema_cursor_id,
+        db: 0,
         where_clause: Some(parse_schema_where_clause),
     });
 
@@ -633,7 +899,12 @@ pub fn translate_drop_table(
         approx_num_labels: 4,
     };
     program.extend(&opts);
-    let table = schema.get_table(tbl_name.name.0.as_str());
+    let database_id = schema.object_database(&tbl_name)?;
+    let db_schema = schema.database(database_id);
+    if db_schema.get_view(tbl_name.name.0.as_str()).is_some() {
+        bail_parse_error!("use DROP VIEW to delete view {}", tbl_name.name.0);
+    }
+    let table = db_schema.get_table(tbl_name.name.0.as_str());
     if table.is_none() {
         if if_exists {
             program.epilogue(crate::translate::emitter::TransactionMode::Write);
@@ -644,15 +915,18 @@ pub fn translate_drop_table(
     }
 
     let table = table.unwrap(); // safe since we just checked for None
+    let normalized_tbl_name = normalize_ident(&tbl_name.name.0);
+    if normalized_tbl_name.starts_with("sqlite_") && !normalized_tbl_name.starts_with("sqlite_stat")
+    {
+        bail_parse_error!("table {} may not be dropped", normalized_tbl_name);
+    }
 
     let null_reg = program.alloc_register(); //  r1
     program.emit_null(null_reg, None);
     let table_name_and_root_page_register = program.alloc_register(); //  r2, this register is special because it's first used to track table name and then moved root page
     let table_reg = program.emit_string8_new_reg(tbl_name.name.0.clone()); //  r3
     program.mark_last_insn_constant();
-    let table_type = program.emit_string8_new_reg("trigger".to_string()); //  r4
-    program.mark_last_insn_constant();
-    let row_id_reg = program.alloc_register(); //  r5
+    let row_id_reg = program.alloc_register(); //  r4
 
     let schema_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
     let sqlite_schema_cursor_id_0 = program.alloc_cursor_id(
@@ -663,9 +937,10 @@ pub fn translate_drop_table(
         cursor_id: sqlite_schema_cursor_id_0,
         root_page: 1usize.into(),
         name: SQLITE_TABLEID.to_string(),
+        db: database_id,
     });
 
-    //  1. Remove all entries from the schema table related to the table we are dropping, except for triggers
+    //  1. Remove all entries from the schema table related to the table we are dropping, including its triggers
     //  loop to beginning of schema table
     let end_metadata_label = program.allocate_label();
     let metadata_loop = program.allocate_label();
@@ -689,24 +964,15 @@ pub fn translate_drop_table(
         flags: CmpInsFlags::default(),
         collation: program.curr_collation(),
     });
-    program.emit_column(
-        sqlite_schema_cursor_id_0,
-        0,
-        table_name_and_root_page_register,
-    );
-    program.emit_insn(Insn::Eq {
-        lhs: table_name_and_root_page_register,
-        rhs: table_type,
-        target_pc: next_label,
-        flags: CmpInsFlags::default(),
-        collation: program.curr_collation(),
-    });
     program.emit_insn(Insn::RowId {
         cursor_id: sqlite_schema_cursor_id_0,
         dest: row_id_reg,
     });
     program.emit_insn(Insn::Delete {
         cursor_id: sqlite_schema_cursor_id_0,
+        nchange: ChangeCount::None,
+        table_name: None,
+        is_update: false,
     });
 
     program.resolve_label(next_label, program.offset());
@@ -717,30 +983,44 @@ pub fn translate_drop_table(
     program.preassign_label_to_next_insn(end_metadata_label);
     //  end of loop on schema table
 
-    //  2. Destroy the indices within a loop
-    let indices = schema.get_indices(&tbl_name.name.0);
-    for index in indices {
-        program.emit_insn(Insn::Destroy {
-            root: index.root_page,
-            former_root_reg: 0, //  no autovacuum (https://www.sqlite.org/opcode.html#Destroy)
-            is_temp: 0,
-        });
-
-        //  3. TODO: Open an ephemeral table, and read over triggers from schema table into ephemeral table
-        //  Requires support via https://github.com/tursodatabase/turso/pull/768
-
-        //  4. TODO: Open a write cursor to the schema table and re-insert all triggers into the sqlite schema table from the ephemeral table and delete old trigger
-        //  Requires support via https://github.com/tursodatabase/turso/pull/768
+    //  Forget the largest rowid used by an AUTOINCREMENT table
+    if table.btree().is_some_and(|btree| btree.has_autoincrement) {
+        if let Some(sequence_table) = db_schema.get_btree_table(SQLITE_SEQUENCE_TABLE) {
+            emit_delete_sequence_entry(
+                &mut program,
+                sequence_table,
+                database_id,
+                &normalized_tbl_name,
+            );
+        }
     }
 
-    //  3. Destroy the table structure
+    //  2. Destroy the table structure and the indices. In an auto-vacuum database, destroying a b-tree moves the
+    //  last root page into its root page, so they are destroyed from the largest root page down, and the schema
+    //  table is patched after each one
     match table.as_ref() {
         Table::BTree(table) => {
-            program.emit_insn(Insn::Destroy {
-                root: table.root_page,
-                former_root_reg: table_name_and_root_page_register,
-                is_temp: 0,
-            });
+            let mut root_pages: Vec<usize> = db_schema
+                .get_indices(&tbl_name.name.0)
+                .iter()
+                .map(|index| index.root_page)
+                .collect();
+            root_pages.push(table.root_page);
+            root_pages.sort_unstable_by(|a, b| b.cmp(a));
+            for root_page in root_pages {
+                program.emit_insn(Insn::Destroy {
+                    root: root_page,
+                    former_root_reg: table_name_and_root_page_register,
+                    db: database_id,
+                });
+                emit_update_moved_root_page(
+                    &mut program,
+                    &schema_table,
+                    database_id,
+                    table_name_and_root_page_register,
+                    root_page,
+                );
+            }
         }
         Table::Virtual(vtab) => {
             // From what I see, TableValuedFunction is not stored in the schema as a table.
@@ -760,172 +1040,9 @@ pub fn translate_drop_table(
         Table::FromClauseSubquery(..) => panic!("FromClauseSubquery can't be dropped"),
     };
 
-    let schema_data_register = program.alloc_register();
-    let schema_row_id_register = program.alloc_register();
-    program.emit_null(schema_data_register, Some(schema_row_id_register));
-
-    //  All of the following processing needs to be done only if the table is not a virtual table
-    if table.btree().is_some() {
-        //  4. Open an ephemeral table, and read over the entry from the schema table whose root page was moved in the destroy operation
-
-        //  cursor id 1
-        let sqlite_schema_cursor_id_1 =
-            program.alloc_cursor_id(CursorType::BTreeTable(schema_table.clone()));
-        let simple_table_rc = Rc::new(BTreeTable {
-            root_page: 0, // Not relevant for ephemeral table definition
-            name: "ephemeral_scratch".to_string(),
-            has_rowid: true,
-            primary_key_columns: vec![],
-            columns: vec![Column {
-                name: Some("rowid".to_string()),
-                ty: Type::Integer,
-                ty_str: "INTEGER".to_string(),
-                primary_key: false,
-                is_rowid_alias: false,
-                notnull: false,
-                default: None,
-                unique: false,
-                collation: None,
-                hidden: false,
-            }],
-            is_strict: false,
-            unique_sets: None,
-        });
-        //  cursor id 2
-        let ephemeral_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(simple_table_rc));
-        program.emit_insn(Insn::OpenEphemeral {
-            cursor_id: ephemeral_cursor_id,
-            is_table: true,
-        });
-        let if_not_label = program.allocate_label();
-        program.emit_insn(Insn::IfNot {
-            reg: table_name_and_root_page_register,
-            target_pc: if_not_label,
-            jump_if_null: true, //  jump anyway
-        });
-        program.emit_insn(Insn::OpenRead {
-            cursor_id: sqlite_schema_cursor_id_1,
-            root_page: 1usize,
-        });
-
-        let schema_column_0_register = program.alloc_register();
-        let schema_column_1_register = program.alloc_register();
-        let schema_column_2_register = program.alloc_register();
-        let moved_to_root_page_register = program.alloc_register(); //  the register that will contain the root page number the last root page is moved to
-        let schema_column_4_register = program.alloc_register();
-        let prev_root_page_register = program.alloc_register(); //  the register that will contain the root page number that the last root page was on before VACUUM
-        let _r14 = program.alloc_register(); //  Unsure why this register is allocated but putting it in here to make comparison with SQLite easier
-        let new_record_register = program.alloc_register();
-
-        //  Loop to copy over row id's from the schema table for rows that have the same root page as the one that was moved
-        let copy_schema_to_temp_table_loop_end_label = program.allocate_label();
-        let copy_schema_to_temp_table_loop = program.allocate_label();
-        program.emit_insn(Insn::Rewind {
-            cursor_id: sqlite_schema_cursor_id_1,
-            pc_if_empty: copy_schema_to_temp_table_loop_end_label,
-        });
-        program.preassign_label_to_next_insn(copy_schema_to_temp_table_loop);
-        //  start loop on schema table
-        program.emit_column(sqlite_schema_cursor_id_1, 3, prev_root_page_register);
-        //  The label and Insn::Ne are used to skip over any rows in the schema table that don't have the root page that was moved
-        let next_label = program.allocate_label();
-        program.emit_insn(Insn::Ne {
-            lhs: prev_root_page_register,
-            rhs: table_name_and_root_page_register,
-            target_pc: next_label,
-            flags: CmpInsFlags::default(),
-            collation: program.curr_collation(),
-        });
-        program.emit_insn(Insn::RowId {
-            cursor_id: sqlite_schema_cursor_id_1,
-            dest: schema_row_id_register,
-        });
-        program.emit_insn(Insn::Insert {
-            cursor: ephemeral_cursor_id,
-            key_reg: schema_row_id_register,
-            record_reg: schema_data_register,
-            flag: InsertFlags::new(),
-            table_name: "scratch_table".to_string(),
-        });
-
-        program.resolve_label(next_label, program.offset());
-        program.emit_insn(Insn::Next {
-            cursor_id: sqlite_schema_cursor_id_1,
-            pc_if_next: copy_schema_to_temp_table_loop,
-        });
-        program.preassign_label_to_next_insn(copy_schema_to_temp_table_loop_end_label);
-        //  End loop to copy over row id's from the schema table for rows that have the same root page as the one that was moved
-
-        program.resolve_label(if_not_label, program.offset());
-
-        //  5. Open a write cursor to the schema table and re-insert the records placed in the ephemeral table but insert the correct root page now
-        program.emit_insn(Insn::OpenWrite {
-            cursor_id: sqlite_schema_cursor_id_1,
-            root_page: 1usize.into(),
-            name: SQLITE_TABLEID.to_string(),
-        });
-
-        //  Loop to copy over row id's from the ephemeral table and then re-insert into the schema table with the correct root page
-        let copy_temp_table_to_schema_loop_end_label = program.allocate_label();
-        let copy_temp_table_to_schema_loop = program.allocate_label();
-        program.emit_insn(Insn::Rewind {
-            cursor_id: ephemeral_cursor_id,
-            pc_if_empty: copy_temp_table_to_schema_loop_end_label,
-        });
-        program.preassign_label_to_next_insn(copy_temp_table_to_schema_loop);
-        //  start loop on schema table
-        program.emit_insn(Insn::RowId {
-            cursor_id: ephemeral_cursor_id,
-            dest: schema_row_id_register,
-        });
-        //  the next_label and Insn::NotExists are used to skip patching any rows in the schema table that don't have the row id that was written to the ephemeral table
-        let next_label = program.allocate_label();
-        program.emit_insn(Insn::NotExists {
-            cursor: sqlite_schema_cursor_id_1,
-            rowid_reg: schema_row_id_register,
-            target_pc: next_label,
-        });
-        program.emit_column(sqlite_schema_cursor_id_1, 0, schema_column_0_register);
-        program.emit_column(sqlite_schema_cursor_id_1, 1, schema_column_1_register);
-        program.emit_column(sqlite_schema_cursor_id_1, 2, schema_column_2_register);
-        let root_page = table
-            .get_root_page()
-            .try_into()
-            .expect("Failed to cast the root page to an i64");
-        program.emit_insn(Insn::Integer {
-            value: root_page,
-            dest: moved_to_root_page_register,
-        });
-        program.emit_column(sqlite_schema_cursor_id_1, 4, schema_column_4_register);
-        program.emit_insn(Insn::MakeRecord {
-            start_reg: schema_column_0_register,
-            count: 5,
-            dest_reg: new_record_register,
-            index_name: None,
-        });
-        program.emit_insn(Insn::Delete {
-            cursor_id: sqlite_schema_cursor_id_1,
-        });
-        program.emit_insn(Insn::Insert {
-            cursor: sqlite_schema_cursor_id_1,
-            key_reg: schema_row_id_register,
-            record_reg: new_record_register,
-            flag: InsertFlags::new(),
-            table_name: SQLITE_TABLEID.to_string(),
-        });
-
-        program.resolve_label(next_label, program.offset());
-        program.emit_insn(Insn::Next {
-            cursor_id: ephemeral_cursor_id,
-            pc_if_next: copy_temp_table_to_schema_loop,
-        });
-        program.preassign_label_to_next_insn(copy_temp_table_to_schema_loop_end_label);
-        //  End loop to copy over row id's from the ephemeral table and then re-insert into the schema table with the correct root page
-    }
-
     //  Drop the in-memory structures for the table
     program.emit_insn(Insn::DropTable {
-        db: 0,
+        db: database_id,
         _p2: 0,
         _p3: 0,
         table_name: tbl_name.name.0,
@@ -936,3 +1053,175 @@ pub fn translate_drop_table(
 
     Ok(program)
 }
+
+/// Patches the schema table entry whose root page the last root page was moved from, if
+/// `former_root_reg` holds one, after destroying the b-tree at `root_page`.
+pub(crate) fn emit_update_moved_root_page(
+    program: &mut ProgramBuilder,
+    schema_table: &Rc<BTreeTable>,
+    db: usize,
+    former_root_reg: usize,
+    root_page: usize,
+) {
+    let schema_data_register = program.alloc_register();
+    let schema_row_id_register = program.alloc_register();
+    program.emit_null(schema_data_register, Some(schema_row_id_register));
+
+    //  Open an ephemeral table, and read over the entry from the schema table whose root page was moved in the destroy operation
+
+    //  cursor id 1
+    let sqlite_schema_cursor_id_1 =
+        program.alloc_cursor_id(CursorType::BTreeTable(schema_table.clone()));
+    let simple_table_rc = Rc::new(BTreeTable {
+        root_page: 0, // Not relevant for ephemeral table definition
+        name: "ephemeral_scratch".to_string(),
+        has_rowid: true,
+        primary_key_columns: vec![],
+        columns: vec![Column {
+            name: Some("rowid".to_string()),
+            ty: Type::Integer,
+            ty_str: "INTEGER".to_string(),
+            primary_key: false,
+            is_rowid_alias: false,
+            notnull: false,
+            notnull_conflict_clause: None,
+            default: None,
+            unique: false,
+            unique_conflict_clause: None,
+            collation: None,
+            hidden: false,
+            generated: None,
+        }],
+        is_strict: false,
+        unique_sets: None,
+        check_constraints: vec![],
+        primary_key_conflict_clause: None,
+        has_autoincrement: false,
+    });
+    //  cursor id 2
+    let ephemeral_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(simple_table_rc));
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id: ephemeral_cursor_id,
+        is_table: true,
+    });
+    let if_not_label = program.allocate_label();
+    program.emit_insn(Insn::IfNot {
+        reg: former_root_reg,
+        target_pc: if_not_label,
+        jump_if_null: true, //  jump anyway
+    });
+    program.emit_insn(Insn::OpenRead {
+        cursor_id: sqlite_schema_cursor_id_1,
+        root_page: 1usize,
+        db,
+    });
+
+    let schema_column_0_register = program.alloc_register();
+    let schema_column_1_register = program.alloc_register();
+    let schema_column_2_register = program.alloc_register();
+    let moved_to_root_page_register = program.alloc_register(); //  the register that will contain the root page number the last root page is moved to
+    let schema_column_4_register = program.alloc_register();
+    let prev_root_page_register = program.alloc_register(); //  the register that will contain the root page number that the last root page was on before VACUUM
+    let _r14 = program.alloc_register(); //  Unsure why this register is allocated but putting it in here to make comparison with SQLite easier
+    let new_record_register = program.alloc_register();
+
+    //  Loop to copy over row id's from the schema table for rows that have the same root page as the one that was moved
+    let copy_schema_to_temp_table_loop_end_label = program.allocate_label();
+    let copy_schema_to_temp_table_loop = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id: sqlite_schema_cursor_id_1,
+        pc_if_empty: copy_schema_to_temp_table_loop_end_label,
+    });
+    program.preassign_label_to_next_insn(copy_schema_to_temp_table_loop);
+    //  start loop on schema table
+    program.emit_column(sqlite_schema_cursor_id_1, 3, prev_root_page_register);
+    //  The label and Insn::Ne are used to skip over any rows in the schema table that don't have the root page that was moved
+    let next_label = program.allocate_label();
+    program.emit_insn(Insn::Ne {
+        lhs: prev_root_page_register,
+        rhs: former_root_reg,
+        target_pc: next_label,
+        flags: CmpInsFlags::default(),
+        collation: program.curr_collation(),
+    });
+    program.emit_insn(Insn::RowId {
+        cursor_id: sqlite_schema_cursor_id_1,
+        dest: schema_row_id_register,
+    });
+    program.emit_insn(Insn::Insert {
+        cursor: ephemeral_cursor_id,
+        key_reg: schema_row_id_register,
+        record_reg: schema_data_register,
+        flag: InsertFlags::new(),
+        table_name: "scratch_table".to_string(),
+    });
+
+    program.resolve_label(next_label, program.offset());
+    program.emit_insn(Insn::Next {
+        cursor_id: sqlite_schema_cursor_id_1,
+        pc_if_next: copy_schema_to_temp_table_loop,
+    });
+    program.preassign_label_to_next_insn(copy_schema_to_temp_table_loop_end_label);
+    //  End loop to copy over row id's from the schema table for rows that have the same root page as the one that was moved
+
+    program.resolve_label(if_not_label, program.offset());
+
+    //  Open a write cursor to the schema table and re-insert the records placed in the ephemeral table but insert the correct root page now
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: sqlite_schema_cursor_id_1,
+        root_page: 1usize.into(),
+        name: SQLITE_TABLEID.to_string(),
+        db,
+    });
+
+    //  Loop to copy over row id's from the ephemeral table and then re-insert into the schema table with the correct root page
+    let copy_temp_table_to_schema_loop_end_label = program.allocate_label();
+    let copy_temp_table_to_schema_loop = program.allocate_label();
+    program.emit_insn(Insn::Rewind {
+        cursor_id: ephemeral_cursor_id,
+        pc_if_empty: copy_temp_table_to_schema_loop_end_label,
+    });
+    program.preassign_label_to_next_insn(copy_temp_table_to_schema_loop);
+    //  start loop on schema table
+    program.emit_insn(Insn::RowId {
+        cursor_id: ephemeral_cursor_id,
+        dest: schema_row_id_register,
+    });
+    //  the next_label and Insn::NotExists are used to skip patching any rows in the schema table that don't have the row id that was written to the ephemeral table
+    let next_label = program.allocate_label();
+    program.emit_insn(Insn::NotExists {
+        cursor: sqlite_schema_cursor_id_1,
+        rowid_reg: schema_row_id_register,
+        target_pc: next_label,
+    });
+    program.emit_column(sqlite_schema_cursor_id_1, 0, schema_column_0_register);
+    program.emit_column(sqlite_schema_cursor_id_1, 1, schema_column_1_register);
+    program.emit_column(sqlite_schema_cursor_id_1, 2, schema_column_2_register);
+    program.emit_insn(Insn::Integer {
+        value: root_page as i64,
+        dest: moved_to_root_page_register,
+    });
+    program.emit_column(sqlite_schema_cursor_id_1, 4, schema_column_4_register);
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: schema_column_0_register,
+        count: 5,
+        dest_reg: new_record_register,
+        index_name: None,
+    });
+    //  The record is overwritten in place, like UPDATE does
+    program.emit_insn(Insn::Insert {
+        cursor: sqlite_schema_cursor_id_1,
+        key_reg: schema_row_id_register,
+        record_reg: new_record_register,
+        flag: InsertFlags::new(),
+        table_name: SQLITE_TABLEID.to_string(),
+    });
+
+    program.resolve_label(next_label, program.offset());
+    program.emit_insn(Insn::Next {
+        cursor_id: ephemeral_cursor_id,
+        pc_if_next: copy_temp_table_to_schema_loop,
+    });
+    program.preassign_label_to_next_insn(copy_temp_table_to_schema_loop_end_label);
+    //  End loop to copy over row id's from the ephemeral table and then re-insert into the schema table with the correct root page
+}
@@ -3,14 +3,16 @@ use super::plan::{
     select_star, Distinctness, JoinOrderMember, Operation, OuterQueryReference, QueryDestination,
     Search, TableReferences,
 };
-use crate::function::{AggFunc, ExtFunc, Func};
+use crate::function::Func;
 use crate::schema::Table;
 use crate::translate::optimizer::optimize_plan;
 use crate::translate::plan::{Aggregate, GroupBy, Plan, ResultSetColumn, SelectPlan};
 use crate::translate::planner::{
     bind_column_references, break_predicate_at_and_boundaries, parse_from, parse_limit,
-    parse_where, resolve_aggregates,
+    parse_where, resolve_aggregates, try_fold_expr_to_i64,
 };
+use crate::translate::subquery::plan_subqueries_from_where_clause;
+use crate::translate::window::{is_window_function_call, plan_windows, resolve_window_names};
 use crate::util::normalize_ident;
 use crate::vdbe::builder::{ProgramBuilderOpts, TableRefIdCounter};
 use crate::vdbe::insn::Insn;
@@ -39,7 +41,7 @@ pub fn translate_select(
         &mut program.table_reference_counter,
         query_destination,
     )?;
-    optimize_plan(&mut select_plan, schema)?;
+    optimize_plan(&mut select_plan, schema, program.like_behavior())?;
     let num_result_cols;
     let opts = match &select_plan {
         Plan::Select(select) => {
@@ -110,12 +112,14 @@ pub fn prepare_select_plan(
             )?))
         }
         Some(compounds) => {
+            // The WITH clause is visible to every SELECT of the compound.
+            let with = select.with.take();
             let mut last = prepare_one_select_plan(
                 schema,
                 *select.body.select,
                 None,
                 None,
-                None,
+                with.clone(),
                 syms,
                 outer_query_refs,
                 table_ref_counter,
@@ -130,7 +134,7 @@ pub fn prepare_select_plan(
                     *select,
                     None,
                     None,
-                    None,
+                    with.clone(),
                     syms,
                     outer_query_refs,
                     table_ref_counter,
@@ -148,17 +152,16 @@ pub fn prepare_select_plan(
             let (limit, offset) = select.limit.map_or(Ok((None, None)), |l| parse_limit(&l))?;
 
             // FIXME: handle OFFSET for compound selects
-            if offset.is_some_and(|o| o > 0) {
+            if offset
+                .as_ref()
+                .is_some_and(|o| try_fold_expr_to_i64(o).is_none_or(|o| o > 0))
+            {
                 crate::bail_parse_error!("OFFSET is not supported for compound SELECTs yet");
             }
             // FIXME: handle ORDER BY for compound selects
             if select.order_by.is_some() {
                 crate::bail_parse_error!("ORDER BY is not supported for compound SELECTs yet");
             }
-            // FIXME: handle WITH for compound selects
-            if select.with.is_some() {
-                crate::bail_parse_error!("WITH is not supported for compound SELECTs yet");
-            }
             Ok(Plan::CompoundSelect {
                 left,
                 right_most: last,
@@ -190,6 +193,7 @@ fn prepare_one_select_plan(
                 where_clause,
                 group_by,
                 distinctness,
+                window_clause,
                 ..
             } = *select_inner;
             if !schema.indexes_enabled() && distinctness.is_some() {
@@ -214,6 +218,9 @@ fn prepare_one_select_plan(
                 }
             }
 
+            // The CTEs are also visible to the subqueries in the WHERE clause.
+            let with_for_subqueries = with.clone();
+
             // Parse the FROM clause into a vec of TableReferences. Fold all the join conditions expressions into the WHERE clause.
             parse_from(
                 schema,
@@ -266,12 +273,14 @@ fn prepare_one_select_plan(
                 group_by: None,
                 order_by: None,
                 aggregates: vec![],
+                window: None,
                 limit: None,
                 offset: None,
                 contains_constant_false_condition: false,
                 query_destination,
                 distinctness: Distinctness::from_ast(distinctness.as_ref()),
                 values: vec![],
+                non_from_clause_subqueries: vec![],
             };
 
             let mut aggregate_expressions = Vec::new();
@@ -324,11 +333,24 @@ fn prepare_one_select_plan(
                         }
                     }
                     ResultColumn::Expr(ref mut expr, maybe_alias) => {
+                        resolve_window_names(expr, window_clause.as_deref().unwrap_or_default())?;
                         bind_column_references(
                             expr,
                             &mut plan.table_references,
                             Some(&plan.result_columns),
                         )?;
+                        // Window functions are planned once the ORDER BY clause is parsed
+                        if is_window_function_call(expr) {
+                            plan.result_columns.push(ResultSetColumn {
+                                alias: maybe_alias.as_ref().map(|alias| match alias {
+                                    ast::As::Elided(alias) => alias.0.clone(),
+                                    ast::As::As(alias) => alias.0.clone(),
+                                }),
+                                expr: expr.clone(),
+                                contains_aggregates: false,
+                            });
+                            continue;
+                        }
                         match expr {
                             ast::Expr::FunctionCall {
                                 name,
@@ -402,7 +424,7 @@ fn prepare_one_select_plan(
                                     Err(e) => {
                                         if let Some(f) = syms.resolve_function(&name.0, args_count)
                                         {
-                                            if let ExtFunc::Scalar(_) = f.as_ref().func {
+                                            if !f.func.is_aggregate() {
                                                 let contains_aggregates = resolve_aggregates(
                                                     schema,
                                                     expr,
@@ -422,8 +444,10 @@ fn prepare_one_select_plan(
                                                 });
                                             } else {
                                                 let agg = Aggregate {
-                                                    func: AggFunc::External(f.func.clone().into()),
-                                                    args: args.as_ref().unwrap().clone(),
+                                                    func: f
+                                                        .aggregate(args_count)
+                                                        .expect("function must be an aggregate"),
+                                                    args: args.clone().unwrap_or_default(),
                                                     original_expr: expr.clone(),
                                                     distinctness,
                                                 };
@@ -513,6 +537,13 @@ fn prepare_one_select_plan(
                 Some(&plan.result_columns),
                 &mut plan.where_clause,
             )?;
+            plan_subqueries_from_where_clause(
+                schema,
+                syms,
+                with_for_subqueries.as_ref(),
+                &mut plan,
+                table_ref_counter,
+            )?;
 
             if let Some(mut group_by) = group_by {
                 for expr in group_by.exprs.iter_mut() {
@@ -536,17 +567,9 @@ fn prepare_one_select_plan(
                                 &mut plan.table_references,
                                 Some(&plan.result_columns),
                             )?;
-                            let contains_aggregates =
-                                resolve_aggregates(schema, expr, &mut aggregate_expressions)?;
-                            if !contains_aggregates {
-                                // TODO: sqlite allows HAVING clauses with non aggregate expressions like
-                                // HAVING id = 5. We should support this too eventually (I guess).
-                                // sqlite3-parser does not support HAVING without group by though, so we'll
-                                // need to either make a PR or add it to our vendored version.
-                                crate::bail_parse_error!(
-                                    "HAVING clause must contain an aggregate function"
-                                );
-                            }
+                            // Like in SQLite, a term without aggregates, e.g. `HAVING id = 5`,
+                            // is evaluated on the row the group's bare columns are read from.
+                            resolve_aggregates(schema, expr, &mut aggregate_expressions)?;
                         }
                         Some(predicates)
                     } else {
@@ -562,6 +585,10 @@ fn prepare_one_select_plan(
                 let mut key = Vec::new();
 
                 for mut o in order_by {
+                    resolve_window_names(
+                        &mut o.expr,
+                        window_clause.as_deref().unwrap_or_default(),
+                    )?;
                     replace_column_number_with_copy_of_column_expr(
                         &mut o.expr,
                         &plan.result_columns,
@@ -583,7 +610,7 @@ fn prepare_one_select_plan(
             (plan.limit, plan.offset) = limit.map_or(Ok((None, None)), parse_limit)?;
 
             // Return the unoptimized query plan
-            Ok(plan)
+            plan_windows(plan, schema, syms, table_ref_counter)
         }
         ast::OneSelect::Values(values) => {
             let len = values[0].len();
@@ -592,7 +619,8 @@ fn prepare_one_select_plan(
                 result_columns.push(ResultSetColumn {
                     // these result_columns work as placeholders for the values, so the expr doesn't matter
                     expr: ast::Expr::Literal(ast::Literal::Numeric(i.to_string())),
-                    alias: None,
+                    // the columns of a VALUES clause are named column1, column2, ...
+                    alias: Some(format!("column{}", i + 1)),
                     contains_aggregates: false,
                 });
             }
@@ -604,12 +632,14 @@ fn prepare_one_select_plan(
                 group_by: None,
                 order_by: None,
                 aggregates: vec![],
+                window: None,
                 limit: None,
                 offset: None,
                 contains_constant_false_condition: false,
                 query_destination,
                 distinctness: Distinctness::NonDistinct,
                 values,
+                non_from_clause_subqueries: vec![],
             };
 
             Ok(plan)
@@ -651,6 +681,7 @@ fn count_plan_required_cursors(plan: &SelectPlan) -> usize {
             Operation::Search(search) => match search {
                 Search::RowidEq { .. } => 1,
                 Search::Seek { index, .. } => 1 + index.is_some() as usize,
+                Search::MultiIndexOr { branches, .. } => 2 + branches.len(),
             }
         } + if let Table::FromClauseSubquery(from_clause_subquery) = &t.table {
             count_plan_required_cursors(&from_clause_subquery.plan)
@@ -660,8 +691,19 @@ fn count_plan_required_cursors(plan: &SelectPlan) -> usize {
         .sum();
     let num_sorter_cursors = plan.group_by.is_some() as usize + plan.order_by.is_some() as usize;
     let num_pseudo_cursors = plan.group_by.is_some() as usize + plan.order_by.is_some() as usize;
+    // A sorter, a pseudo cursor and an ephemeral table
+    let num_window_cursors = 3 * plan.window.is_some() as usize;
+    let num_subquery_cursors: usize = plan
+        .non_from_clause_subqueries
+        .iter()
+        .map(|subquery| count_plan_required_cursors(&subquery.plan))
+        .sum();
 
-    num_table_cursors + num_sorter_cursors + num_pseudo_cursors
+    num_table_cursors
+        + num_sorter_cursors
+        + num_pseudo_cursors
+        + num_window_cursors
+        + num_subquery_cursors
 }
 
 fn estimate_num_instructions(select: &SelectPlan) -> usize {
@@ -1,14 +1,524 @@
+use std::{rc::Rc, sync::Arc};
+
 use crate::{
-    schema::Table,
-    vdbe::{builder::ProgramBuilder, insn::Insn},
-    Result,
+    schema::{BTreeTable, FromClauseSubquery, Index, IndexColumn, Schema, Table},
+    util::exprs_are_equivalent,
+    vdbe::{
+        builder::{CursorType, ProgramBuilder, TableRefIdCounter},
+        insn::{ChangeCount, Insn},
+        BranchOffset,
+    },
+    Result, SymbolTable,
 };
 
 use super::{
-    emitter::{emit_query, Resolver, TranslateCtx},
+    emitter::{
+        emit_limit_value, emit_query, Resolver, SubqueryOutput, SubqueryResultLocation,
+        TranslateCtx,
+    },
+    expr::{compare_affinity, get_expr_affinity, walk_expr, walk_expr_mut, WalkControl},
     main_loop::LoopLabels,
-    plan::{QueryDestination, SelectPlan, TableReferences},
+    plan::{
+        ColumnUsedMask, NonFromClauseSubquery, OuterQueryReference, Plan, QueryDestination,
+        SelectPlan, TableReferences,
+    },
+    planner::{add_ctes_to_select, try_fold_expr_to_i64},
+    select::prepare_select_plan,
 };
+use turso_sqlite3_parser::ast::{self, Expr, SortOrder, SubqueryType};
+
+/// Plan the subqueries in the WHERE clause of a SELECT, see [plan_subqueries_in_expr].
+pub fn plan_subqueries_from_where_clause(
+    schema: &Schema,
+    syms: &SymbolTable,
+    with: Option<&ast::With>,
+    plan: &mut SelectPlan,
+    table_ref_counter: &mut TableRefIdCounter,
+) -> Result<()> {
+    for term in plan.where_clause.iter_mut() {
+        plan_subqueries_in_expr(
+            schema,
+            syms,
+            with,
+            &mut term.expr,
+            &mut plan.table_references,
+            &mut plan.non_from_clause_subqueries,
+            table_ref_counter,
+        )?;
+    }
+    Ok(())
+}
+
+/// Plan the subqueries in an expression, i.e. `EXISTS (SELECT ...)`, `x [NOT] IN (SELECT ...)`
+/// and scalar subqueries like `x = (SELECT ...)`, replace each of them with an
+/// [Expr::SubqueryResult] and add their plans to `subqueries`.
+///
+/// The tables in `table_references`, and those of their own outer queries, are visible to the
+/// subqueries as outer query references. The columns of those tables that a subquery reads are
+/// recorded in the [Expr::SubqueryResult], so that a WHERE term is evaluated once they are all
+/// available.
+pub fn plan_subqueries_in_expr(
+    schema: &Schema,
+    syms: &SymbolTable,
+    with: Option<&ast::With>,
+    expr: &mut Expr,
+    table_references: &mut TableReferences,
+    subqueries: &mut Vec<NonFromClauseSubquery>,
+    table_ref_counter: &mut TableRefIdCounter,
+) -> Result<()> {
+    plan_subqueries_in_row_value(
+        schema,
+        syms,
+        with,
+        expr,
+        1,
+        table_references,
+        subqueries,
+        table_ref_counter,
+    )
+}
+
+/// Like [plan_subqueries_in_expr], for an expression that must evaluate to a row value of
+/// `num_columns` columns, e.g. the right-hand side of `SET (a, b) = (SELECT x, y ...)`.
+#[allow(clippy::too_many_arguments)]
+pub fn plan_subqueries_in_row_value(
+    schema: &Schema,
+    syms: &SymbolTable,
+    with: Option<&ast::With>,
+    expr: &mut Expr,
+    num_columns: usize,
+    table_references: &mut TableReferences,
+    subqueries: &mut Vec<NonFromClauseSubquery>,
+    table_ref_counter: &mut TableRefIdCounter,
+) -> Result<()> {
+    let outer_query_refs = table_references
+        .joined_tables()
+        .iter()
+        .map(|table| OuterQueryReference {
+            identifier: table.identifier.clone(),
+            internal_id: table.internal_id,
+            table: table.table.clone(),
+            col_used_mask: ColumnUsedMask::default(),
+            database_id: table.database_id,
+        })
+        .chain(table_references.outer_query_refs().iter().cloned())
+        .collect::<Vec<_>>();
+    let mut used_outer_columns = vec![];
+    let mut plan_subquery = |expr: &mut Expr, expected_columns: usize| -> Result<()> {
+        let (query_type, select, lhs, not_in) = match expr {
+            Expr::Exists(select) => (SubqueryType::Exists, select, None, false),
+            Expr::Subquery(select) => (SubqueryType::Scalar, select, None, false),
+            Expr::InSelect { lhs, not, rhs } => (SubqueryType::In, rhs, Some(lhs.clone()), *not),
+            _ => return Ok(()),
+        };
+        let expected_columns = match lhs.as_deref() {
+            Some(Expr::Parenthesized(exprs)) => exprs.len(),
+            Some(_) => 1,
+            None => expected_columns,
+        };
+        let mut select = select.as_ref().clone();
+        if let Some(with) = with {
+            add_ctes_to_select(&mut select, with.recursive, &with.ctes);
+        }
+        let query_destination = match query_type {
+            SubqueryType::Exists => QueryDestination::ExistsSubqueryResult {
+                result_reg: usize::MAX, // will be set later in bytecode emission
+            },
+            SubqueryType::Scalar => QueryDestination::RowValueSubqueryResult {
+                result_reg_start: usize::MAX, // will be set later in bytecode emission
+                num_regs: 1,
+            },
+            SubqueryType::In => QueryDestination::EphemeralIndex {
+                cursor_id: usize::MAX, // will be set later in bytecode emission
+                index: Arc::new(Index {
+                    name: "in_subquery".to_string(),
+                    table_name: String::new(),
+                    ephemeral: true,
+                    conflict_clause: None,
+                    root_page: 0,
+                    columns: (0..expected_columns)
+                        .map(|i| IndexColumn {
+                            name: String::new(),
+                            order: SortOrder::Asc,
+                            pos_in_table: i,
+                            collation: None,
+                            default: None,
+                            expr: None,
+                        })
+                        .collect(),
+                    unique: false,
+                    has_rowid: false,
+                    where_clause: None,
+                }),
+                is_delete: false,
+                affinity_str: None, // set below, once the result columns are known
+            },
+        };
+        let Plan::Select(mut subplan) = prepare_select_plan(
+            schema,
+            select,
+            syms,
+            &outer_query_refs,
+            table_ref_counter,
+            query_destination,
+        )?
+        else {
+            crate::bail_parse_error!("compound SELECTs are not supported in subqueries yet");
+        };
+        if query_type != SubqueryType::Exists && subplan.result_columns.len() != expected_columns {
+            crate::bail_parse_error!(
+                "sub-select returns {} columns - expected {}",
+                subplan.result_columns.len(),
+                expected_columns
+            );
+        }
+        if let Some(lhs) = &lhs {
+            // Both sides of the IN are compared with the affinity of the comparison,
+            // column by column for a row value.
+            let lhs_exprs = match lhs.as_ref() {
+                Expr::Parenthesized(exprs) => exprs.as_slice(),
+                lhs => std::slice::from_ref(lhs),
+            };
+            let affinities = lhs_exprs
+                .iter()
+                .zip(subplan.result_columns.iter())
+                .map(|(lhs, result_column)| {
+                    compare_affinity(
+                        &result_column.expr,
+                        get_expr_affinity(lhs, Some(&*table_references)),
+                        Some(&subplan.table_references),
+                    )
+                })
+                .collect::<Vec<_>>();
+            if let QueryDestination::EphemeralIndex { affinity_str, .. } =
+                &mut subplan.query_destination
+            {
+                *affinity_str = affinities
+                    .iter()
+                    .any(|affinity| affinity.has_affinity())
+                    .then(|| {
+                        affinities
+                            .iter()
+                            .map(|affinity| affinity.aff_mask())
+                            .collect()
+                    });
+            }
+        } else {
+            // Only the first row of the subquery is ever read. A LIMIT that is not known until
+            // the query runs reads no rows if it is 0, and one row otherwise.
+            subplan.limit = Some(match subplan.limit.take() {
+                Some(limit) => match try_fold_expr_to_i64(&limit) {
+                    Some(0) => limit,
+                    Some(_) => Box::new(Expr::Literal(ast::Literal::Numeric("1".to_string()))),
+                    None => Box::new(Expr::Binary(
+                        limit,
+                        ast::Operator::NotEquals,
+                        Box::new(Expr::Literal(ast::Literal::Numeric("0".to_string()))),
+                    )),
+                },
+                None => Box::new(Expr::Literal(ast::Literal::Numeric("1".to_string()))),
+            });
+        }
+
+        let mut outer_columns = vec![];
+        collect_outer_columns(&subplan, &outer_query_refs, &mut outer_columns)?;
+        used_outer_columns.extend(outer_columns.iter().cloned());
+
+        let subquery_id = table_ref_counter.next();
+        subqueries.push(NonFromClauseSubquery {
+            internal_id: subquery_id,
+            query_type,
+            plan: Box::new(subplan),
+            correlated: !outer_columns.is_empty(),
+        });
+        *expr = Expr::SubqueryResult {
+            subquery_id,
+            query_type,
+            lhs,
+            not_in,
+            outer_columns,
+        };
+        Ok(())
+    };
+    // Only the expression itself can be a row value, the subqueries nested in it are scalar,
+    // unless they are compared with a row value.
+    let mut is_row_value = true;
+    walk_expr_mut(expr, &mut |expr: &mut Expr| -> Result<()> {
+        let expected_columns = if std::mem::take(&mut is_row_value) {
+            num_columns
+        } else {
+            1
+        };
+        if let Expr::Binary(lhs, _, rhs) = expr {
+            if let (Expr::Parenthesized(exprs), subquery @ Expr::Subquery(_))
+            | (subquery @ Expr::Subquery(_), Expr::Parenthesized(exprs)) =
+                (lhs.as_mut(), rhs.as_mut())
+            {
+                return plan_subquery(subquery, exprs.len());
+            }
+        }
+        plan_subquery(expr, expected_columns)
+    })?;
+    for expr in used_outer_columns {
+        if let Expr::Column { table, column, .. } = expr {
+            table_references.mark_column_used(table, column);
+        }
+    }
+    Ok(())
+}
+
+/// Collects the columns of `outer_query_refs` that are read anywhere in `plan`,
+/// including in its own subqueries.
+fn collect_outer_columns(
+    plan: &SelectPlan,
+    outer_query_refs: &[OuterQueryReference],
+    out: &mut Vec<Expr>,
+) -> Result<()> {
+    let mut collect = |expr: &Expr| -> Result<WalkControl> {
+        if let Expr::Column { table, .. } | Expr::RowId { table, .. } = expr {
+            if outer_query_refs.iter().any(|r| r.internal_id == *table)
+                && !out.iter().any(|e| exprs_are_equivalent(e, expr))
+            {
+                out.push(expr.clone());
+            }
+        }
+        Ok(WalkControl::Continue)
+    };
+    for rc in plan.result_columns.iter() {
+        walk_expr(&rc.expr, &mut collect)?;
+    }
+    for term in plan.where_clause.iter() {
+        walk_expr(&term.expr, &mut collect)?;
+    }
+    if let Some(group_by) = &plan.group_by {
+        for expr in group_by.exprs.iter() {
+            walk_expr(expr, &mut collect)?;
+        }
+        for expr in group_by.having.iter().flatten() {
+            walk_expr(expr, &mut collect)?;
+        }
+    }
+    for (expr, _) in plan.order_by.iter().flatten() {
+        walk_expr(expr, &mut collect)?;
+    }
+    for expr in plan.values.iter().flatten() {
+        walk_expr(expr, &mut collect)?;
+    }
+    for table in plan.joined_tables() {
+        if let Table::FromClauseSubquery(from_clause_subquery) = &table.table {
+            collect_outer_columns(&from_clause_subquery.plan, outer_query_refs, out)?;
+            if let Some(recursive) = &from_clause_subquery.recursive {
+                collect_outer_columns(&recursive.plan, outer_query_refs, out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Emit the subroutines of the uncorrelated subqueries outside of the FROM clause, see
+/// [NonFromClauseSubquery], and register where translate_expr() reads the results of all of them.
+///
+/// An uncorrelated subquery only runs the first time an expression that reads it is evaluated,
+/// after each start of the parent query, so that a subquery in a CASE branch that is never
+/// taken doesn't run at all. The correlated subqueries read the cursors of the outer query, so
+/// their subroutines are only emitted by [emit_correlated_subquery_subroutines] once those
+/// cursors have been opened.
+pub fn emit_non_from_clause_subqueries(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    tables: &TableReferences,
+    subqueries: &mut [NonFromClauseSubquery],
+) -> Result<()> {
+    for subquery in subqueries.iter_mut() {
+        let plan = &mut subquery.plan;
+        // The subquery reads the FROM clause subqueries of the outer query from their result registers.
+        let outer_ids = plan
+            .table_references
+            .outer_query_refs()
+            .iter()
+            .map(|outer_ref| outer_ref.internal_id)
+            .collect::<Vec<_>>();
+        for internal_id in outer_ids {
+            let Some(Table::FromClauseSubquery(from_clause_subquery)) =
+                tables.find_table_by_internal_id(internal_id)
+            else {
+                continue;
+            };
+            let result_columns_start_reg = from_clause_subquery.result_columns_start_reg;
+            if let Some(Table::FromClauseSubquery(outer_subquery)) = plan
+                .table_references
+                .find_outer_query_ref_by_internal_id_mut(internal_id)
+                .map(|outer_ref| &mut outer_ref.table)
+            {
+                outer_subquery.result_columns_start_reg = result_columns_start_reg;
+            }
+        }
+
+        let output = match &mut plan.query_destination {
+            QueryDestination::EphemeralIndex {
+                cursor_id,
+                index,
+                affinity_str,
+                ..
+            } => {
+                *cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
+                SubqueryOutput::EphemeralIndex {
+                    cursor_id: *cursor_id,
+                    affinity_str: affinity_str.clone(),
+                }
+            }
+            destination => {
+                let num_regs = match subquery.query_type {
+                    SubqueryType::Exists => 1,
+                    _ => plan.result_columns.len(),
+                };
+                let start_reg = program.alloc_registers(num_regs);
+                *destination = match subquery.query_type {
+                    SubqueryType::Exists => QueryDestination::ExistsSubqueryResult {
+                        result_reg: start_reg,
+                    },
+                    _ => QueryDestination::RowValueSubqueryResult {
+                        result_reg_start: start_reg,
+                        num_regs,
+                    },
+                };
+                SubqueryOutput::Registers {
+                    start_reg,
+                    num_regs,
+                }
+            }
+        };
+        let subroutine = (program.allocate_label(), program.alloc_register());
+        if !subquery.correlated {
+            let done_reg = program.alloc_register();
+            program.emit_int(0, done_reg);
+            emit_subquery_subroutine(
+                program,
+                plan,
+                subquery.query_type,
+                &output,
+                subroutine,
+                Some(done_reg),
+                t_ctx,
+            )?;
+        }
+        t_ctx
+            .resolver
+            .subquery_results
+            .push(SubqueryResultLocation {
+                subquery_id: subquery.internal_id,
+                output,
+                subroutine,
+            });
+    }
+    Ok(())
+}
+
+/// Emit the subroutines of the correlated subqueries outside of the FROM clause.
+/// Calling a subroutine computes the result of the subquery for the current row of the outer query.
+pub fn emit_correlated_subquery_subroutines(
+    program: &mut ProgramBuilder,
+    t_ctx: &TranslateCtx,
+    subqueries: &mut [NonFromClauseSubquery],
+) -> Result<()> {
+    for subquery in subqueries.iter_mut().filter(|s| s.correlated) {
+        let Some(location) = t_ctx
+            .resolver
+            .subquery_results
+            .iter()
+            .find(|location| location.subquery_id == subquery.internal_id)
+            .cloned()
+        else {
+            crate::bail_parse_error!("subquery {} has not been emitted", subquery.internal_id);
+        };
+        emit_subquery_subroutine(
+            program,
+            &mut subquery.plan,
+            subquery.query_type,
+            &location.output,
+            location.subroutine,
+            None,
+            t_ctx,
+        )?;
+    }
+    Ok(())
+}
+
+/// Emit the subroutine that computes the result of a subquery outside of the FROM clause.
+/// When `done_reg` is given, the subroutine only runs the subquery while the register is 0.
+fn emit_subquery_subroutine(
+    program: &mut ProgramBuilder,
+    plan: &mut SelectPlan,
+    query_type: SubqueryType,
+    output: &SubqueryOutput,
+    (subroutine_start, return_reg): (BranchOffset, usize),
+    done_reg: Option<usize>,
+    t_ctx: &TranslateCtx,
+) -> Result<()> {
+    let subroutine_end = program.allocate_label();
+    let subroutine_return = program.allocate_label();
+    program.emit_insn(Insn::Goto {
+        target_pc: subroutine_end,
+    });
+    program.preassign_label_to_next_insn(subroutine_start);
+    if let Some(done_reg) = done_reg {
+        program.emit_insn(Insn::If {
+            reg: done_reg,
+            target_pc: subroutine_return,
+            jump_if_null: false,
+        });
+        program.emit_int(1, done_reg);
+    }
+    emit_non_from_clause_subquery(program, plan, query_type, output, t_ctx)?;
+    program.preassign_label_to_next_insn(subroutine_return);
+    program.emit_insn(Insn::Return {
+        return_reg,
+        can_fallthrough: false,
+    });
+    program.preassign_label_to_next_insn(subroutine_end);
+    Ok(())
+}
+
+/// Emit a subquery outside of the FROM clause, storing its result in `output`.
+fn emit_non_from_clause_subquery(
+    program: &mut ProgramBuilder,
+    plan: &mut SelectPlan,
+    query_type: SubqueryType,
+    output: &SubqueryOutput,
+    t_ctx: &TranslateCtx,
+) -> Result<()> {
+    // EXISTS is false, and a scalar subquery is NULL, if the subquery returns no rows.
+    // The rows of an IN subquery go into a new, empty ephemeral index every time it runs.
+    match (query_type, output) {
+        (SubqueryType::Exists, SubqueryOutput::Registers { start_reg, .. }) => {
+            program.emit_int(0, *start_reg)
+        }
+        (
+            SubqueryType::Scalar,
+            SubqueryOutput::Registers {
+                start_reg,
+                num_regs,
+            },
+        ) => program.emit_null(*start_reg, Some(*start_reg + *num_regs - 1)),
+        (SubqueryType::In, SubqueryOutput::EphemeralIndex { cursor_id, .. }) => {
+            program.emit_insn(Insn::OpenEphemeral {
+                cursor_id: *cursor_id,
+                is_table: false,
+            })
+        }
+        _ => unreachable!("subquery output does not match the subquery type"),
+    }
+    if plan
+        .limit
+        .as_ref()
+        .is_some_and(|limit| try_fold_expr_to_i64(limit) == Some(0))
+    {
+        return Ok(());
+    }
+    let mut metadata = subquery_translate_ctx(program, plan, t_ctx);
+    emit_query(program, plan, &mut metadata)?;
+    Ok(())
+}
 
 /// Emit the subqueries contained in the FROM clause.
 /// This is done first so the results can be read in the main query loop.
@@ -19,9 +529,17 @@ pub fn emit_subqueries(
 ) -> Result<()> {
     for table_reference in tables.joined_tables_mut() {
         if let Table::FromClauseSubquery(from_clause_subquery) = &mut table_reference.table {
+            // The reference to a recursive CTE from within its own recursive part reads
+            // the current row of the CTE, whose registers were set up by emit_recursive_cte().
+            if from_clause_subquery.result_columns_start_reg.is_some() {
+                continue;
+            }
             // Emit the subquery and get the start register of the result columns.
-            let result_columns_start =
-                emit_subquery(program, &mut from_clause_subquery.plan, t_ctx)?;
+            let result_columns_start = if from_clause_subquery.recursive.is_some() {
+                emit_recursive_cte(program, from_clause_subquery, t_ctx)?
+            } else {
+                emit_subquery(program, &mut from_clause_subquery.plan, t_ctx)?
+            };
             // Set the start register of the subquery's result columns.
             // This is done so that translate_expr() can read the result columns of the subquery,
             // as if it were reading from a regular table.
@@ -64,7 +582,28 @@ pub fn emit_subquery(
         _ => unreachable!("emit_subquery called on non-subquery"),
     }
     let end_coroutine_label = program.allocate_label();
-    let mut metadata = TranslateCtx {
+    let mut metadata = subquery_translate_ctx(program, plan, t_ctx);
+    let subquery_body_end_label = program.allocate_label();
+    program.emit_insn(Insn::InitCoroutine {
+        yield_reg,
+        jump_on_definition: subquery_body_end_label,
+        start_offset: coroutine_implementation_start_offset,
+    });
+    program.preassign_label_to_next_insn(coroutine_implementation_start_offset);
+    let result_column_start_reg = emit_query(program, plan, &mut metadata)?;
+    program.resolve_label(end_coroutine_label, program.offset());
+    program.emit_insn(Insn::EndCoroutine { yield_reg });
+    program.preassign_label_to_next_insn(subquery_body_end_label);
+    Ok(result_column_start_reg)
+}
+
+/// Creates the translation context for emitting a subquery plan.
+fn subquery_translate_ctx<'a>(
+    program: &mut ProgramBuilder,
+    plan: &SelectPlan,
+    t_ctx: &TranslateCtx<'a>,
+) -> TranslateCtx<'a> {
+    TranslateCtx {
         labels_main_loop: (0..plan.joined_tables().len())
             .map(|_| LoopLabels::new(program))
             .collect(),
@@ -72,6 +611,7 @@ pub fn emit_subquery(
         meta_group_by: None,
         meta_left_joins: (0..plan.joined_tables().len()).map(|_| None).collect(),
         meta_sort: None,
+        meta_window: None,
         reg_agg_start: None,
         reg_nonagg_emit_once_flag: None,
         reg_result_cols_start: None,
@@ -83,6 +623,34 @@ pub fn emit_subquery(
         resolver: Resolver::new(t_ctx.resolver.schema, t_ctx.resolver.symbol_table),
         non_aggregate_expressions: Vec::new(),
         cdc_cursor_id: None,
+    }
+}
+
+/// Emit a recursive CTE as a coroutine and return the start register of its result columns.
+///
+/// The coroutine works like SQLite's recursive CTE evaluation:
+/// - the rows of the initial SELECT are appended to a queue (an ephemeral table);
+/// - while the queue is not empty, the first row is removed from the queue, copied into the
+///   result registers and yielded to the parent query;
+/// - the recursive SELECT then runs with its reference to the CTE reading that single row,
+///   appending its own results to the queue.
+///
+/// With UNION (instead of UNION ALL), an ephemeral index of all rows ever queued is used to
+/// discard duplicates, which also guarantees termination when the set of rows is finite.
+pub fn emit_recursive_cte(
+    program: &mut ProgramBuilder,
+    subquery: &mut FromClauseSubquery,
+    t_ctx: &mut TranslateCtx,
+) -> Result<usize> {
+    let recursive = subquery
+        .recursive
+        .as_mut()
+        .expect("emit_recursive_cte called on non-recursive subquery");
+    let yield_reg = program.alloc_register();
+    let coroutine_implementation_start_offset = program.allocate_label();
+    let coroutine_destination = QueryDestination::CoroutineYield {
+        yield_reg,
+        coroutine_implementation_start: coroutine_implementation_start_offset,
     };
     let subquery_body_end_label = program.allocate_label();
     program.emit_insn(Insn::InitCoroutine {
@@ -91,9 +659,169 @@ pub fn emit_subquery(
         start_offset: coroutine_implementation_start_offset,
     });
     program.preassign_label_to_next_insn(coroutine_implementation_start_offset);
-    let result_column_start_reg = emit_query(program, plan, &mut metadata)?;
-    program.resolve_label(end_coroutine_label, program.offset());
+
+    let num_columns = subquery.columns.len();
+    let queue_table = Rc::new(BTreeTable {
+        root_page: 0, // Not relevant for ephemeral table definition
+        name: format!("{}_queue", subquery.name),
+        has_rowid: true,
+        primary_key_columns: vec![],
+        columns: subquery.columns.clone(),
+        is_strict: false,
+        unique_sets: None,
+        check_constraints: vec![],
+        primary_key_conflict_clause: None,
+        has_autoincrement: false,
+    });
+    let queue_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(queue_table.clone()));
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id: queue_cursor_id,
+        is_table: true,
+    });
+    let distinct_cursor_id = if recursive.union_all {
+        None
+    } else {
+        let index = Arc::new(Index {
+            name: format!("{}_distinct", subquery.name),
+            table_name: String::new(),
+            ephemeral: true,
+            conflict_clause: None,
+            root_page: 0,
+            columns: subquery
+                .columns
+                .iter()
+                .enumerate()
+                .map(|(i, col)| IndexColumn {
+                    name: col.name.clone().unwrap_or_default(),
+                    order: SortOrder::Asc,
+                    pos_in_table: i,
+                    collation: None,
+                    default: None,
+                    expr: None,
+                })
+                .collect(),
+            unique: false,
+            has_rowid: false,
+            where_clause: None,
+        });
+        let cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index));
+        program.emit_insn(Insn::OpenEphemeral {
+            cursor_id,
+            is_table: false,
+        });
+        Some(cursor_id)
+    };
+    let queue_destination = QueryDestination::RecursiveCteQueue {
+        cursor_id: queue_cursor_id,
+        table: queue_table.clone(),
+        distinct_cursor_id,
+    };
+
+    // The recursive SELECT reads the CTE through a coroutine that yields the current row once.
+    let current_row_start_reg = program.alloc_registers(num_columns);
+    let current_row_yield_reg = program.alloc_register();
+    let current_row_start_offset = program.allocate_label();
+    let current_row_definition_end = program.allocate_label();
+    program.emit_insn(Insn::InitCoroutine {
+        yield_reg: current_row_yield_reg,
+        jump_on_definition: current_row_definition_end,
+        start_offset: current_row_start_offset,
+    });
+    program.preassign_label_to_next_insn(current_row_start_offset);
+    program.emit_insn(Insn::Yield {
+        yield_reg: current_row_yield_reg,
+        end_offset: BranchOffset::Offset(0),
+    });
+    program.emit_insn(Insn::EndCoroutine {
+        yield_reg: current_row_yield_reg,
+    });
+    program.preassign_label_to_next_insn(current_row_definition_end);
+    for table in recursive.plan.table_references.joined_tables_mut() {
+        if table.internal_id != recursive.self_table_id {
+            continue;
+        }
+        let Table::FromClauseSubquery(self_reference) = &mut table.table else {
+            unreachable!("recursive CTE self reference must be a subquery");
+        };
+        self_reference.result_columns_start_reg = Some(current_row_start_reg);
+        self_reference.plan.query_destination = QueryDestination::CoroutineYield {
+            yield_reg: current_row_yield_reg,
+            coroutine_implementation_start: current_row_start_offset,
+        };
+    }
+
+    let queue_loop_start = program.allocate_label();
+    let queue_loop_end = program.allocate_label();
+    let limit_reg = match &recursive.limit {
+        Some(limit) => {
+            let reg = program.alloc_register();
+            emit_limit_value(program, limit, reg, &t_ctx.resolver)?;
+            match try_fold_expr_to_i64(limit) {
+                Some(0) => program.emit_insn(Insn::Goto {
+                    target_pc: queue_loop_end,
+                }),
+                Some(_) => {}
+                None => program.emit_insn(Insn::IfNot {
+                    reg,
+                    target_pc: queue_loop_end,
+                    jump_if_null: true,
+                }),
+            }
+            Some(reg)
+        }
+        None => None,
+    };
+
+    // Queue the rows of the initial SELECT.
+    subquery.plan.query_destination = queue_destination.clone();
+    {
+        let mut metadata = subquery_translate_ctx(program, &subquery.plan, t_ctx);
+        emit_query(program, &mut subquery.plan, &mut metadata)?;
+    }
+    // The parent query loops over the CTE using the coroutine destination.
+    subquery.plan.query_destination = coroutine_destination;
+
+    program.preassign_label_to_next_insn(queue_loop_start);
+    program.emit_insn(Insn::Rewind {
+        cursor_id: queue_cursor_id,
+        pc_if_empty: queue_loop_end,
+    });
+    for i in 0..num_columns {
+        program.emit_insn(Insn::Column {
+            cursor_id: queue_cursor_id,
+            column: i,
+            dest: current_row_start_reg + i,
+            default: None,
+        });
+    }
+    program.emit_insn(Insn::Delete {
+        cursor_id: queue_cursor_id,
+        nchange: ChangeCount::None,
+        table_name: None,
+        is_update: false,
+    });
+    program.emit_insn(Insn::Yield {
+        yield_reg,
+        end_offset: BranchOffset::Offset(0),
+    });
+    if let Some(limit_reg) = limit_reg {
+        program.emit_insn(Insn::DecrJumpZero {
+            reg: limit_reg,
+            target_pc: queue_loop_end,
+        });
+    }
+
+    // Queue the rows produced by the recursive SELECT for the current row.
+    recursive.plan.query_destination = queue_destination;
+    {
+        let mut metadata = subquery_translate_ctx(program, &recursive.plan, t_ctx);
+        emit_query(program, &mut recursive.plan, &mut metadata)?;
+    }
+    program.emit_insn(Insn::Goto {
+        target_pc: queue_loop_start,
+    });
+    program.preassign_label_to_next_insn(queue_loop_end);
     program.emit_insn(Insn::EndCoroutine { yield_reg });
     program.preassign_label_to_next_insn(subquery_body_end_label);
-    Ok(result_column_start_reg)
+    Ok(current_row_start_reg)
 }
@@ -1,11 +1,14 @@
+use crate::schema::Schema;
 use crate::translate::{ProgramBuilder, ProgramBuilderOpts};
-use crate::vdbe::insn::Insn;
+use crate::util::normalize_ident;
+use crate::vdbe::insn::{Insn, SavepointOp};
 use crate::Result;
 use turso_sqlite3_parser::ast::{Name, TransactionType};
 
 pub fn translate_tx_begin(
     tx_type: Option<TransactionType>,
     _tx_name: Option<Name>,
+    schema: &Schema,
     mut program: ProgramBuilder,
 ) -> Result<ProgramBuilder> {
     program.extend(&ProgramBuilderOpts {
@@ -15,18 +18,39 @@ pub fn translate_tx_begin(
     });
     let tx_type = tx_type.unwrap_or(TransactionType::Deferred);
     match tx_type {
+        // The locks are taken by the first statement that reads or writes.
         TransactionType::Deferred => {
             program.emit_insn(Insn::AutoCommit {
                 auto_commit: false,
                 rollback: false,
+                concurrent: false,
             });
         }
+        // The write lock is taken right away, failing with SQLITE_BUSY if another connection
+        // holds it. With a WAL, readers aren't blocked either way, so EXCLUSIVE is the same as
+        // IMMEDIATE.
         TransactionType::Immediate | TransactionType::Exclusive => {
-            program.emit_insn(Insn::Transaction { write: true });
-            // TODO: Emit transaction instruction on temporary tables when we support them.
+            program.emit_insn(Insn::Transaction { db: 0, write: true });
+            for db in &schema.attached {
+                program.emit_insn(Insn::Transaction {
+                    db: db.index,
+                    write: true,
+                });
+            }
             program.emit_insn(Insn::AutoCommit {
                 auto_commit: false,
                 rollback: false,
+                concurrent: false,
+            });
+        }
+        // Like DEFERRED, but writing doesn't take the write lock either. It is taken when
+        // committing, which fails if a transaction committed in the meantime changed a page this
+        // one read or wrote.
+        TransactionType::Concurrent => {
+            program.emit_insn(Insn::AutoCommit {
+                auto_commit: false,
+                rollback: false,
+                concurrent: true,
             });
         }
     }
@@ -46,6 +70,35 @@ pub fn translate_tx_commit(
     program.emit_insn(Insn::AutoCommit {
         auto_commit: true,
         rollback: false,
+        concurrent: false,
+    });
+    program.epilogue(super::emitter::TransactionMode::None);
+    Ok(program)
+}
+
+pub fn translate_savepoint(name: Name, mut program: ProgramBuilder) -> Result<ProgramBuilder> {
+    program.extend(&ProgramBuilderOpts {
+        num_cursors: 0,
+        approx_num_insns: 0,
+        approx_num_labels: 0,
+    });
+    program.emit_insn(Insn::Savepoint {
+        op: SavepointOp::Begin,
+        name: normalize_ident(&name.0),
+    });
+    program.epilogue(super::emitter::TransactionMode::None);
+    Ok(program)
+}
+
+pub fn translate_release(name: Name, mut program: ProgramBuilder) -> Result<ProgramBuilder> {
+    program.extend(&ProgramBuilderOpts {
+        num_cursors: 0,
+        approx_num_insns: 0,
+        approx_num_labels: 0,
+    });
+    program.emit_insn(Insn::Savepoint {
+        op: SavepointOp::Release,
+        name: normalize_ident(&name.0),
     });
     program.epilogue(super::emitter::TransactionMode::None);
     Ok(program)
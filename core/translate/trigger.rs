@@ -0,0 +1,571 @@
+use turso_sqlite3_parser::ast::{
+    self, fmt::ToTokens, Expr, InsertBody, QualifiedName, TriggerCmd, TriggerEvent, TriggerTime,
+};
+
+use crate::schema::{BTreeTable, Schema};
+use crate::translate::delete::translate_delete;
+use crate::translate::emitter::{emit_generated_columns, Resolver, TransactionMode};
+use crate::translate::expr::{translate_expr, walk_expr_mut};
+use crate::translate::insert::translate_insert;
+use crate::translate::optimizer::like_prefix::LikeBehavior;
+use crate::translate::plan::{QueryDestination, TableReferences};
+use crate::translate::planner::bind_column_references;
+use crate::translate::schema::{
+    emit_delete_schema_entry, emit_schema_entry, SchemaEntryType, SQLITE_TABLEID,
+};
+use crate::translate::select::translate_select;
+use crate::translate::update::translate_update;
+use crate::util::normalize_ident;
+use crate::vdbe::builder::{CursorType, ProgramBuilder, ProgramBuilderOpts, QueryMode};
+use crate::vdbe::insn::{Cookie, Insn};
+use crate::vdbe::BranchOffset;
+use crate::{bail_parse_error, CaptureDataChangesMode, Limits, Result};
+
+pub fn translate_create_trigger(
+    create_trigger: ast::CreateTrigger,
+    schema: &Schema,
+    mut program: ProgramBuilder,
+) -> Result<ProgramBuilder> {
+    if create_trigger.temporary {
+        bail_parse_error!("TEMPORARY triggers are not supported yet");
+    }
+    let trigger_name = normalize_ident(&create_trigger.trigger_name.name.0);
+    let tbl_name = normalize_ident(&create_trigger.tbl_name.name.0);
+
+    if schema.get_trigger(&trigger_name).is_some() {
+        if create_trigger.if_not_exists {
+            program.epilogue(TransactionMode::Write);
+            return Ok(program);
+        }
+        bail_parse_error!("trigger {} already exists", trigger_name);
+    }
+    if schema.get_view(&tbl_name).is_some() {
+        bail_parse_error!(
+            "cannot create {} trigger on view: {}",
+            create_trigger
+                .time
+                .unwrap_or(TriggerTime::Before)
+                .format()
+                .unwrap(),
+            tbl_name
+        );
+    }
+    let Some(table) = schema.get_table(&tbl_name) else {
+        bail_parse_error!("no such table: {}", tbl_name);
+    };
+    if table.virtual_table().is_some() {
+        bail_parse_error!("cannot create triggers on virtual tables");
+    }
+    if tbl_name.starts_with("sqlite_") {
+        bail_parse_error!("cannot create trigger on system table");
+    }
+    if create_trigger.time == Some(TriggerTime::InsteadOf) {
+        bail_parse_error!("cannot create INSTEAD OF trigger on table: {}", tbl_name);
+    }
+
+    let opts = ProgramBuilderOpts {
+        num_cursors: 1,
+        approx_num_insns: 20,
+        approx_num_labels: 1,
+    };
+    program.extend(&opts);
+
+    let sql = ast::Stmt::CreateTrigger(Box::new(create_trigger))
+        .format()
+        .unwrap();
+
+    let sqlite_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+    let sqlite_schema_cursor_id =
+        program.alloc_cursor_id(CursorType::BTreeTable(sqlite_table.clone()));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: sqlite_schema_cursor_id,
+        root_page: 1usize.into(),
+        name: sqlite_table.name.clone(),
+        db: 0,
+    });
+    emit_schema_entry(
+        &mut program,
+        sqlite_schema_cursor_id,
+        SchemaEntryType::Trigger,
+        &trigger_name,
+        &tbl_name,
+        0, // triggers don't have a root page
+        Some(sql),
+    );
+
+    program.emit_insn(Insn::SetCookie {
+        db: 0,
+        cookie: Cookie::SchemaVersion,
+        value: schema.schema_version as i32 + 1,
+        p5: 0,
+    });
+    program.emit_insn(Insn::ParseSchema {
+        db: 0,
+        where_clause: Some(format!("type = 'trigger' AND name = '{trigger_name}'")),
+    });
+
+    program.epilogue(TransactionMode::Write);
+
+    Ok(program)
+}
+
+pub fn translate_drop_trigger(
+    trigger_name: &QualifiedName,
+    if_exists: bool,
+    schema: &Schema,
+    mut program: ProgramBuilder,
+) -> Result<ProgramBuilder> {
+    let trigger_name = normalize_ident(&trigger_name.name.0);
+    if schema.get_trigger(&trigger_name).is_none() {
+        if if_exists {
+            program.epilogue(TransactionMode::Write);
+            return Ok(program);
+        }
+        bail_parse_error!("no such trigger: {}", trigger_name);
+    }
+
+    let opts = ProgramBuilderOpts {
+        num_cursors: 1,
+        approx_num_insns: 20,
+        approx_num_labels: 3,
+    };
+    program.extend(&opts);
+
+    emit_delete_schema_entry(
+        &mut program,
+        schema,
+        SchemaEntryType::Trigger,
+        &trigger_name,
+    );
+
+    program.emit_insn(Insn::SetCookie {
+        db: 0,
+        cookie: Cookie::SchemaVersion,
+        value: schema.schema_version as i32 + 1,
+        p5: 0,
+    });
+    program.emit_insn(Insn::DropTrigger {
+        db: 0,
+        trigger_name,
+    });
+
+    program.epilogue(TransactionMode::Write);
+
+    Ok(program)
+}
+
+/// The registers holding a row of the table a trigger is defined on, which the trigger
+/// program reads through the `OLD` and `NEW` pseudo-tables.
+#[derive(Debug, Clone, Copy)]
+pub struct TriggerRow {
+    pub rowid_reg: usize,
+    /// First of the registers holding the columns of the row, in table order.
+    /// The register of a rowid alias column is never read, `rowid_reg` is used instead.
+    pub columns_start_reg: usize,
+}
+
+impl TriggerRow {
+    /// Loads the row `cursor_id` points at, whose rowid is in `rowid_reg`.
+    pub fn from_cursor(
+        program: &mut ProgramBuilder,
+        resolver: &Resolver,
+        table: &BTreeTable,
+        cursor_id: usize,
+        rowid_reg: usize,
+    ) -> Result<Self> {
+        let columns_start_reg = program.alloc_registers(table.columns.len());
+        for (i, column) in table.columns.iter().enumerate() {
+            if !column.is_virtual_generated() {
+                program.emit_column(cursor_id, i, columns_start_reg + i);
+            }
+        }
+        emit_generated_columns(program, resolver, table, rowid_reg, columns_start_reg)?;
+        Ok(Self {
+            rowid_reg,
+            columns_start_reg,
+        })
+    }
+}
+
+/// Returns true if any trigger on `table` fires on `event`.
+/// See [crate::schema::Trigger::fires_on] for `updated_columns`.
+pub fn has_triggers(
+    schema: &Schema,
+    table: &BTreeTable,
+    event: &TriggerEvent,
+    updated_columns: &[usize],
+) -> bool {
+    schema
+        .get_triggers(&table.name)
+        .iter()
+        .any(|trigger| trigger.fires_on(event, table, updated_columns))
+}
+
+/// Emits the programs of the triggers on `table` that fire on `event` at `time`, for the row
+/// being processed by the statement. `old` and `new` are the rows the `OLD` and `NEW`
+/// pseudo-tables refer to, and `ignore_label` is where RAISE(IGNORE) jumps to in order to
+/// skip the rest of the processing of the row.
+#[allow(clippy::too_many_arguments)]
+pub fn emit_triggers(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    table: &BTreeTable,
+    event: TriggerEvent,
+    time: TriggerTime,
+    updated_columns: &[usize],
+    old: Option<TriggerRow>,
+    new: Option<TriggerRow>,
+    ignore_label: BranchOffset,
+) -> Result<()> {
+    let row_refs = TriggerRowRefs { table, old, new };
+    // Like SQLite, fire the most recently created trigger first.
+    for trigger in resolver.schema.get_triggers(&table.name).iter().rev() {
+        if trigger.time != time
+            || !trigger.fires_on(&event, table, updated_columns)
+            // Triggers are not recursive.
+            || program.is_trigger_active(&trigger.name)
+        {
+            continue;
+        }
+
+        let trigger_end_label = program.allocate_label();
+        if let Some(when_clause) = &trigger.when_clause {
+            let mut when_clause = when_clause.clone();
+            row_refs.rewrite_expr(&mut when_clause)?;
+            bind_column_references(
+                &mut when_clause,
+                &mut TableReferences::new(vec![], vec![]),
+                None,
+            )?;
+            let when_reg = program.alloc_register();
+            translate_expr(program, None, &when_clause, when_reg, resolver)?;
+            program.emit_insn(Insn::IfNot {
+                reg: when_reg,
+                target_pc: trigger_end_label,
+                jump_if_null: true,
+            });
+        }
+
+        program.push_trigger(&trigger.name, ignore_label);
+        for command in &trigger.commands {
+            let mut command = command.clone();
+            row_refs.rewrite_command(&mut command)?;
+            emit_trigger_command(program, resolver, command)?;
+        }
+        program.pop_trigger();
+
+        program.preassign_label_to_next_insn(trigger_end_label);
+    }
+    Ok(())
+}
+
+/// Translates a statement of a trigger program into `program`.
+fn emit_trigger_command(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    command: TriggerCmd,
+) -> Result<()> {
+    let schema = resolver.schema;
+    let syms = resolver.symbol_table;
+    match command {
+        TriggerCmd::Insert(insert) => {
+            let ast::TriggerCmdInsert {
+                or_conflict,
+                tbl_name,
+                col_names,
+                select,
+                upsert,
+                ..
+            } = *insert;
+            translate_nested(program, |program| {
+                translate_insert(
+                    schema,
+                    None,
+                    or_conflict,
+                    QualifiedName::single(tbl_name),
+                    col_names,
+                    InsertBody::Select(select, upsert),
+                    None,
+                    syms,
+                    program,
+                )
+            })
+        }
+        TriggerCmd::Update(update) => {
+            let ast::TriggerCmdUpdate {
+                or_conflict,
+                tbl_name,
+                sets,
+                from,
+                where_clause,
+            } = *update;
+            let mut update = ast::Update {
+                with: None,
+                or_conflict,
+                tbl_name: QualifiedName::single(tbl_name),
+                indexed: None,
+                sets,
+                from,
+                where_clause: where_clause.map(Box::new),
+                returning: None,
+                order_by: None,
+                limit: None,
+            };
+            translate_nested(program, |program| {
+                translate_update(schema, &mut update, syms, program)
+            })
+        }
+        TriggerCmd::Delete(delete) => {
+            let ast::TriggerCmdDelete {
+                tbl_name,
+                where_clause,
+            } = *delete;
+            let tbl_name = QualifiedName::single(tbl_name);
+            translate_nested(program, |program| {
+                translate_delete(
+                    schema,
+                    &tbl_name,
+                    where_clause.map(Box::new),
+                    None,
+                    None,
+                    syms,
+                    program,
+                )
+            })
+        }
+        TriggerCmd::Select(select) => {
+            // The rows of a SELECT are discarded, it only runs for its side effects
+            // (typically RAISE()). Run it as a coroutine and drain it.
+            let yield_reg = program.alloc_register();
+            let jump_on_definition_label = program.allocate_label();
+            let start_offset_label = program.allocate_label();
+            program.emit_insn(Insn::InitCoroutine {
+                yield_reg,
+                jump_on_definition: jump_on_definition_label,
+                start_offset: start_offset_label,
+            });
+            program.preassign_label_to_next_insn(start_offset_label);
+            translate_nested(program, |program| {
+                let query_destination = QueryDestination::CoroutineYield {
+                    yield_reg,
+                    coroutine_implementation_start: start_offset_label,
+                };
+                Ok(translate_select(schema, *select, syms, program, query_destination)?.program)
+            })?;
+            program.emit_insn(Insn::EndCoroutine { yield_reg });
+            program.preassign_label_to_next_insn(jump_on_definition_label);
+
+            let loop_start_label = program.allocate_label();
+            let loop_end_label = program.allocate_label();
+            program.preassign_label_to_next_insn(loop_start_label);
+            program.emit_insn(Insn::Yield {
+                yield_reg,
+                end_offset: loop_end_label,
+            });
+            program.emit_insn(Insn::Goto {
+                target_pc: loop_start_label,
+            });
+            program.preassign_label_to_next_insn(loop_end_label);
+            Ok(())
+        }
+    }
+}
+
+/// Runs one of the `translate_*` functions, which take the [ProgramBuilder] by value, to
+/// translate a statement nested into the one `program` is being built for.
+fn translate_nested(
+    program: &mut ProgramBuilder,
+    translate: impl FnOnce(ProgramBuilder) -> Result<ProgramBuilder>,
+) -> Result<()> {
+    let placeholder = ProgramBuilder::new(
+        QueryMode::Normal,
+        CaptureDataChangesMode::Off,
+        LikeBehavior::default(),
+        None,
+        Limits::default(),
+        ProgramBuilderOpts {
+            num_cursors: 0,
+            approx_num_insns: 0,
+            approx_num_labels: 0,
+        },
+    );
+    let mut nested = std::mem::replace(program, placeholder);
+    let result_columns = std::mem::take(&mut nested.result_columns);
+    nested.incr_nesting();
+    let mut nested = translate(nested)?;
+    nested.decr_nesting();
+    nested.result_columns = result_columns;
+    *program = nested;
+    Ok(())
+}
+
+/// Resolves references to the `OLD` and `NEW` pseudo-tables in the program of a trigger on
+/// `table` to the registers holding those rows.
+struct TriggerRowRefs<'a> {
+    table: &'a BTreeTable,
+    old: Option<TriggerRow>,
+    new: Option<TriggerRow>,
+}
+
+impl TriggerRowRefs<'_> {
+    fn resolve(&self, tbl_name: &str, col_name: &str) -> Result<Option<usize>> {
+        let tbl_name = normalize_ident(tbl_name);
+        let row = match tbl_name.as_str() {
+            "old" => self.old,
+            "new" => self.new,
+            _ => return Ok(None),
+        };
+        let col_name = normalize_ident(col_name);
+        let Some(row) = row else {
+            bail_parse_error!("no such column: {}.{}", tbl_name, col_name);
+        };
+        match self.table.get_column(&col_name) {
+            Some((_, column)) if column.is_rowid_alias => Ok(Some(row.rowid_reg)),
+            Some((idx, _)) => Ok(Some(row.columns_start_reg + idx)),
+            None if ["rowid", "oid", "_rowid_"].contains(&col_name.as_str()) => {
+                Ok(Some(row.rowid_reg))
+            }
+            None => bail_parse_error!("no such column: {}.{}", tbl_name, col_name),
+        }
+    }
+
+    fn rewrite_expr(&self, expr: &mut Expr) -> Result<()> {
+        walk_expr_mut(expr, &mut |expr: &mut Expr| -> Result<()> {
+            match expr {
+                Expr::Qualified(tbl_name, col_name) => {
+                    if let Some(reg) = self.resolve(&tbl_name.0, &col_name.0)? {
+                        *expr = Expr::Register(reg);
+                    }
+                }
+                Expr::Exists(select) | Expr::Subquery(select) => self.rewrite_select(select)?,
+                Expr::InSelect { rhs, .. } => self.rewrite_select(rhs)?,
+                _ => {}
+            }
+            Ok(())
+        })
+    }
+
+    fn rewrite_command(&self, command: &mut TriggerCmd) -> Result<()> {
+        match command {
+            TriggerCmd::Insert(insert) => {
+                self.rewrite_select(&mut insert.select)?;
+                let mut upsert = insert.upsert.as_mut();
+                while let Some(clause) = upsert {
+                    if let Some(index) = &mut clause.index {
+                        if let Some(where_clause) = &mut index.where_clause {
+                            self.rewrite_expr(where_clause)?;
+                        }
+                    }
+                    if let ast::UpsertDo::Set { sets, where_clause } = clause.do_clause.as_mut() {
+                        for set in sets {
+                            self.rewrite_expr(&mut set.expr)?;
+                        }
+                        if let Some(where_clause) = where_clause {
+                            self.rewrite_expr(where_clause)?;
+                        }
+                    }
+                    upsert = clause.next.as_deref_mut();
+                }
+            }
+            TriggerCmd::Update(update) => {
+                for set in &mut update.sets {
+                    self.rewrite_expr(&mut set.expr)?;
+                }
+                if let Some(from) = &mut update.from {
+                    self.rewrite_from(from)?;
+                }
+                if let Some(where_clause) = &mut update.where_clause {
+                    self.rewrite_expr(where_clause)?;
+                }
+            }
+            TriggerCmd::Delete(delete) => {
+                if let Some(where_clause) = &mut delete.where_clause {
+                    self.rewrite_expr(where_clause)?;
+                }
+            }
+            TriggerCmd::Select(select) => self.rewrite_select(select)?,
+        }
+        Ok(())
+    }
+
+    fn rewrite_select(&self, select: &mut ast::Select) -> Result<()> {
+        if let Some(with) = &mut select.with {
+            for cte in &mut with.ctes {
+                self.rewrite_select(&mut cte.select)?;
+            }
+        }
+        self.rewrite_one_select(&mut select.body.select)?;
+        for compound in select.body.compounds.iter_mut().flatten() {
+            self.rewrite_one_select(&mut compound.select)?;
+        }
+        for sorted_column in select.order_by.iter_mut().flatten() {
+            self.rewrite_expr(&mut sorted_column.expr)?;
+        }
+        if let Some(limit) = &mut select.limit {
+            self.rewrite_expr(&mut limit.expr)?;
+            if let Some(offset) = &mut limit.offset {
+                self.rewrite_expr(offset)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rewrite_one_select(&self, select: &mut ast::OneSelect) -> Result<()> {
+        match select {
+            ast::OneSelect::Select(select) => {
+                for column in &mut select.columns {
+                    if let ast::ResultColumn::Expr(expr, _) = column {
+                        self.rewrite_expr(expr)?;
+                    }
+                }
+                if let Some(from) = &mut select.from {
+                    self.rewrite_from(from)?;
+                }
+                if let Some(where_clause) = &mut select.where_clause {
+                    self.rewrite_expr(where_clause)?;
+                }
+                if let Some(group_by) = &mut select.group_by {
+                    for expr in &mut group_by.exprs {
+                        self.rewrite_expr(expr)?;
+                    }
+                    if let Some(having) = &mut group_by.having {
+                        self.rewrite_expr(having)?;
+                    }
+                }
+            }
+            ast::OneSelect::Values(rows) => {
+                for expr in rows.iter_mut().flatten() {
+                    self.rewrite_expr(expr)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn rewrite_from(&self, from: &mut ast::FromClause) -> Result<()> {
+        if let Some(select_table) = &mut from.select {
+            self.rewrite_select_table(select_table)?;
+        }
+        for join in from.joins.iter_mut().flatten() {
+            self.rewrite_select_table(&mut join.table)?;
+            if let Some(ast::JoinConstraint::On(expr)) = &mut join.constraint {
+                self.rewrite_expr(expr)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn rewrite_select_table(&self, select_table: &mut ast::SelectTable) -> Result<()> {
+        match select_table {
+            ast::SelectTable::Table(..) => {}
+            ast::SelectTable::TableCall(_, args, _) => {
+                for arg in args.iter_mut().flatten() {
+                    self.rewrite_expr(arg)?;
+                }
+            }
+            ast::SelectTable::Select(select, _) => self.rewrite_select(select)?,
+            ast::SelectTable::Sub(from, _) => self.rewrite_from(from)?,
+        }
+        Ok(())
+    }
+}
@@ -1,6 +1,7 @@
 use std::rc::Rc;
+use std::sync::Arc;
 
-use crate::schema::{BTreeTable, Column, Type};
+use crate::schema::{BTreeTable, Column, Index, Type};
 use crate::translate::optimizer::optimize_select_plan;
 use crate::translate::plan::{Operation, QueryDestination, Search, SelectPlan};
 use crate::vdbe::builder::CursorType;
@@ -9,18 +10,21 @@ use crate::{
     schema::{Schema, Table},
     util::normalize_ident,
     vdbe::builder::{ProgramBuilder, ProgramBuilderOpts},
-    SymbolTable,
+    AuthAction, SymbolTable,
 };
-use turso_sqlite3_parser::ast::{self, Expr, ResultColumn, SortOrder, Update};
+use turso_sqlite3_parser::ast::{self, Expr, ResolveType, SortOrder, TableInternalId, Update};
 
 use super::emitter::emit_program;
 use super::optimizer::optimize_plan;
 use super::plan::{
-    ColumnUsedMask, IterationDirection, JoinedTable, Plan, ResultSetColumn, TableReferences,
-    UpdatePlan,
+    ColumnUsedMask, IterationDirection, JoinOrderMember, JoinedTable, Plan, ResultSetColumn,
+    TableReferences, UpdatePlan, WhereTerm,
 };
 use super::planner::bind_column_references;
-use super::planner::{parse_limit, parse_where};
+use super::planner::{parse_from, parse_limit, parse_where};
+use super::returning::prepare_returning;
+use super::subquery::{plan_subqueries_in_expr, plan_subqueries_in_row_value};
+use super::trigger::has_triggers;
 /*
 * Update is simple. By default we scan the table, and for each row, we check the WHERE
 * clause. If it evaluates to true, we build the new record with the updated value and insert.
@@ -56,8 +60,8 @@ pub fn translate_update(
     syms: &SymbolTable,
     mut program: ProgramBuilder,
 ) -> crate::Result<ProgramBuilder> {
-    let mut plan = prepare_update_plan(&mut program, schema, body)?;
-    optimize_plan(&mut plan, schema)?;
+    let mut plan = prepare_update_plan(&mut program, schema, syms, body)?;
+    optimize_plan(&mut plan, schema, program.like_behavior())?;
     // TODO: freestyling these numbers
     let opts = ProgramBuilderOpts {
         num_cursors: 1,
@@ -76,8 +80,8 @@ pub fn translate_update_with_after(
     mut program: ProgramBuilder,
     after: impl FnOnce(&mut ProgramBuilder),
 ) -> crate::Result<ProgramBuilder> {
-    let mut plan = prepare_update_plan(&mut program, schema, body)?;
-    optimize_plan(&mut plan, schema)?;
+    let mut plan = prepare_update_plan(&mut program, schema, syms, body)?;
+    optimize_plan(&mut plan, schema, program.like_behavior())?;
     // TODO: freestyling these numbers
     let opts = ProgramBuilderOpts {
         num_cursors: 1,
@@ -92,14 +96,9 @@ pub fn translate_update_with_after(
 pub fn prepare_update_plan(
     program: &mut ProgramBuilder,
     schema: &Schema,
+    syms: &SymbolTable,
     body: &mut Update,
 ) -> crate::Result<Plan> {
-    if body.with.is_some() {
-        bail_parse_error!("WITH clause is not supported");
-    }
-    if body.or_conflict.is_some() {
-        bail_parse_error!("ON CONFLICT clause is not supported");
-    }
     let table_name = &body.tbl_name.name;
     if schema.table_has_indexes(&table_name.to_string()) && !schema.indexes_enabled() {
         // Let's disable altering a table with indices altogether instead of checking column by
@@ -108,10 +107,18 @@ pub fn prepare_update_plan(
             "UPDATE table disabled for table with indexes is disabled by default. Run with `--experimental-indexes` to enable this feature."
         );
     }
-    let table = match schema.get_table(table_name.0.as_str()) {
+    let database_id = schema.object_database(&body.tbl_name)?;
+    let db_schema = schema.database(database_id);
+    if db_schema.get_view(table_name.0.as_str()).is_some() {
+        bail_parse_error!("cannot modify {} because it is a view", table_name);
+    }
+    let table = match db_schema.get_table(table_name.0.as_str()) {
         Some(table) => table,
         None => bail_parse_error!("Parse error: no such table: {}", table_name),
     };
+    // WITHOUT ROWID tables can't be created yet, but may exist in a database created by SQLite.
+    // Their rows are addressed by their PRIMARY KEY instead of a rowid.
+    let without_rowid = table.btree().is_some_and(|btree| !btree.has_rowid);
     let iter_dir = body
         .order_by
         .as_ref()
@@ -139,56 +146,148 @@ pub fn prepare_update_plan(
         },
         join_info: None,
         col_used_mask: ColumnUsedMask::default(),
+        database_id,
+        vtab_order_by: Vec::new(),
     }];
     let mut table_references = TableReferences::new(joined_tables, vec![]);
-    let set_clauses = body
-        .sets
-        .iter_mut()
-        .map(|set| {
-            let ident = normalize_ident(set.col_names[0].0.as_str());
-            let col_index = table
-                .columns()
-                .iter()
-                .enumerate()
-                .find_map(|(i, col)| {
-                    col.name
-                        .as_ref()
-                        .filter(|name| name.eq_ignore_ascii_case(&ident))
-                        .map(|_| i)
-                })
-                .ok_or_else(|| {
-                    crate::LimboError::ParseError(format!(
-                        "column '{}' not found in table '{}'",
-                        ident, table_name.0
-                    ))
-                })?;
-
-            let _ = bind_column_references(&mut set.expr, &mut table_references, None);
-            Ok((col_index, set.expr.clone()))
-        })
-        .collect::<Result<Vec<(usize, Expr)>, crate::LimboError>>()?;
-
-    let mut result_columns = vec![];
-    if let Some(returning) = &mut body.returning {
-        for rc in returning.iter_mut() {
-            if let ResultColumn::Expr(expr, alias) = rc {
-                bind_column_references(expr, &mut table_references, None)?;
-                result_columns.push(ResultSetColumn {
-                    expr: expr.clone(),
-                    alias: alias.as_ref().and_then(|a| {
-                        if let ast::As::As(name) = a {
-                            Some(name.to_string())
-                        } else {
-                            None
-                        }
-                    }),
-                    contains_aggregates: false,
-                });
-            } else {
-                bail_parse_error!("Only expressions are allowed in RETURNING clause");
+
+    // Tables in the FROM clause are joined to the target table, and any join
+    // constraints are folded into the WHERE clause. CTEs from the WITH clause
+    // can be referenced as FROM clause tables.
+    let mut where_clause = vec![];
+    let has_from_clause = body.from.is_some();
+    let with = body.with.clone();
+    parse_from(
+        schema,
+        body.from.take(),
+        syms,
+        body.with.take(),
+        &mut where_clause,
+        &mut table_references,
+        &mut program.table_reference_counter,
+    )?;
+
+    let mut non_from_clause_subqueries = vec![];
+    let mut set_clauses = vec![];
+    let mut row_value_set_clauses = vec![];
+    // The columns the authorizer ignores the update of are left unchanged.
+    let mut ignored_columns = vec![];
+    for set in body.sets.iter_mut() {
+        let col_indexes = set
+            .col_names
+            .iter()
+            .map(|name| {
+                let ident = normalize_ident(name.0.as_str());
+                let col_index = table
+                    .columns()
+                    .iter()
+                    .enumerate()
+                    .find_map(|(i, col)| {
+                        col.name
+                            .as_ref()
+                            .filter(|name| name.eq_ignore_ascii_case(&ident))
+                            .map(|_| i)
+                    })
+                    .ok_or_else(|| {
+                        crate::LimboError::ParseError(format!(
+                            "column '{}' not found in table '{}'",
+                            ident, table_name.0
+                        ))
+                    })?;
+
+                if table.columns()[col_index].generated.is_some() {
+                    bail_parse_error!("cannot UPDATE generated column \"{}\"", ident);
+                }
+                let column = table.columns()[col_index]
+                    .name
+                    .as_deref()
+                    .unwrap_or_default();
+                if program.authorize(
+                    AuthAction::Update {
+                        table: table.get_name(),
+                        column,
+                    },
+                    Some(schema.database_name(database_id)),
+                )? {
+                    ignored_columns.push(col_index);
+                }
+                Ok(col_index)
+            })
+            .collect::<crate::Result<Vec<usize>>>()?;
+
+        bind_column_references(&mut set.expr, &mut table_references, None)?;
+        match &mut set.expr {
+            expr if col_indexes.len() == 1 => {
+                plan_subqueries_in_expr(
+                    schema,
+                    syms,
+                    with.as_ref(),
+                    expr,
+                    &mut table_references,
+                    &mut non_from_clause_subqueries,
+                    &mut program.table_reference_counter,
+                )?;
+                set_clauses.push((col_indexes[0], expr.clone()));
+            }
+            // SET (a, b) = (x, y) assigns each value to its column.
+            Expr::Parenthesized(exprs) if exprs.len() == col_indexes.len() => {
+                for (col_index, expr) in col_indexes.into_iter().zip(exprs.iter_mut()) {
+                    plan_subqueries_in_expr(
+                        schema,
+                        syms,
+                        with.as_ref(),
+                        expr,
+                        &mut table_references,
+                        &mut non_from_clause_subqueries,
+                        &mut program.table_reference_counter,
+                    )?;
+                    set_clauses.push((col_index, expr.clone()));
+                }
+            }
+            // SET (a, b) = (SELECT x, y ...) evaluates the subquery once into consecutive
+            // registers, which the SET clauses of the columns then read.
+            expr @ Expr::Subquery(_) => {
+                plan_subqueries_in_row_value(
+                    schema,
+                    syms,
+                    with.as_ref(),
+                    expr,
+                    col_indexes.len(),
+                    &mut table_references,
+                    &mut non_from_clause_subqueries,
+                    &mut program.table_reference_counter,
+                )?;
+                let start_reg = program.alloc_registers(col_indexes.len());
+                row_value_set_clauses.push((expr.clone(), start_reg));
+                for (i, col_index) in col_indexes.into_iter().enumerate() {
+                    set_clauses.push((col_index, Expr::Register(start_reg + i)));
+                }
+            }
+            expr => {
+                let num_values = match expr {
+                    Expr::Parenthesized(exprs) => exprs.len(),
+                    _ => 1,
+                };
+                bail_parse_error!(
+                    "{} columns assigned {} values",
+                    col_indexes.len(),
+                    num_values
+                );
             }
         }
     }
+    set_clauses.retain(|(col_index, _)| !ignored_columns.contains(col_index));
+
+    let result_columns = match (body.returning.take(), table.btree()) {
+        (None, _) => vec![],
+        (Some(returning), Some(btree_table)) => {
+            let identifier = body.tbl_name.alias.as_ref().unwrap_or(table_name);
+            prepare_returning(returning, &btree_table, &identifier.0)?
+        }
+        (Some(_), None) => {
+            bail_parse_error!("RETURNING is not supported for virtual tables");
+        }
+    };
     let order_by = body.order_by.as_ref().map(|order| {
         order
             .iter()
@@ -206,98 +305,122 @@ pub fn prepare_update_plan(
         accum || columns[*idx].is_rowid_alias
     });
 
-    let (ephemeral_plan, mut where_clause) = if rowid_alias_used {
-        let mut where_clause = vec![];
-        let internal_id = program.table_reference_counter.next();
+    if rowid_alias_used && has_from_clause {
+        bail_parse_error!("UPDATE ... FROM is not supported when updating the rowid");
+    }
 
-        let joined_tables = vec![JoinedTable {
-            table: match table.as_ref() {
-                Table::Virtual(vtab) => Table::Virtual(vtab.clone()),
-                Table::BTree(btree_table) => Table::BTree(btree_table.clone()),
-                _ => unreachable!(),
-            },
-            identifier: table_name.0.clone(),
-            internal_id,
-            op: Operation::Scan {
-                iter_dir,
-                index: None,
-            },
-            join_info: None,
-            col_used_mask: ColumnUsedMask::default(),
-        }];
-        let mut table_references = TableReferences::new(joined_tables, vec![]);
+    // REPLACE deletes the rows an updated row conflicts with, so the rows to update are
+    // gathered up front instead of being modified while the table is being scanned.
+    // Without an OR clause, the constraints of the table decide how conflicts are resolved.
+    let or_replace = table
+        .btree()
+        .is_some_and(|btree_table| match body.or_conflict {
+            Some(or_conflict) => or_conflict == ResolveType::Replace,
+            None => {
+                btree_table.primary_key_conflict_clause == Some(ResolveType::Replace)
+                    || db_schema
+                        .get_indices(&table_name.0)
+                        .iter()
+                        .any(|index| index.conflict_clause == Some(ResolveType::Replace))
+            }
+        });
+    if or_replace && has_from_clause {
+        bail_parse_error!("UPDATE OR REPLACE ... FROM is not supported");
+    }
 
-        // Parse the WHERE clause
-        parse_where(
-            body.where_clause.as_ref().map(|w| *w.clone()),
-            &mut table_references,
-            Some(&result_columns),
-            &mut where_clause,
-        )?;
+    // Iterating an index while updating its columns would move the index entries around
+    // under the cursor, so such an index can't drive the UPDATE loop. The optimizer ignores
+    // those indexes for the UPDATE loop itself, but when one of them is the best way to find
+    // the rows, the rowids are gathered up front using the index instead.
+    let indexes_with_updated_columns = db_schema
+        .get_indices(&table_name.0)
+        .iter()
+        .any(|index| index_has_updated_column(&table, index, &set_clauses));
 
-        let table = Rc::new(BTreeTable {
-            root_page: 0, // Not relevant for ephemeral table definition
-            name: "ephemeral_scratch".to_string(),
-            has_rowid: true,
-            primary_key_columns: vec![],
-            columns: vec![Column {
-                name: Some("rowid".to_string()),
-                ty: Type::Integer,
-                ty_str: "INTEGER".to_string(),
-                primary_key: true,
-                is_rowid_alias: false,
-                notnull: true,
-                default: None,
-                unique: false,
-                collation: None,
-                hidden: false,
-            }],
-            is_strict: false,
-            unique_sets: None,
-        });
+    // Triggers may modify the table too, so the rowids are gathered up front for them as well.
+    let updated_columns = set_clauses.iter().map(|(idx, _)| *idx).collect::<Vec<_>>();
+    let has_update_triggers = table.btree().is_some_and(|btree_table| {
+        has_triggers(
+            db_schema,
+            &btree_table,
+            &ast::TriggerEvent::Update,
+            &updated_columns,
+        )
+    });
+    if has_update_triggers && database_id != 0 {
+        bail_parse_error!("triggers in attached databases are not supported");
+    }
+    let table_changes_during_scan = indexes_with_updated_columns || has_update_triggers;
 
-        let temp_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
-
-        let mut ephemeral_plan = SelectPlan {
-            table_references,
-            result_columns: vec![ResultSetColumn {
-                expr: Expr::RowId {
-                    database: None,
-                    table: internal_id,
-                },
-                alias: None,
-                contains_aggregates: false,
-            }],
-            where_clause,       // original WHERE terms from the UPDATE clause
-            group_by: None,     // N/A
-            order_by: None,     // N/A
-            aggregates: vec![], // N/A
-            limit: None,        // N/A
-            query_destination: QueryDestination::EphemeralTable {
-                cursor_id: temp_cursor_id,
-                table,
-            },
-            join_order: vec![],
-            offset: None,
-            contains_constant_false_condition: false,
-            distinctness: super::plan::Distinctness::NonDistinct,
-            values: vec![],
-        };
+    if without_rowid {
+        if has_from_clause {
+            bail_parse_error!("UPDATE ... FROM is not supported on WITHOUT ROWID tables");
+        }
+        if or_replace {
+            bail_parse_error!("UPDATE OR REPLACE is not supported on WITHOUT ROWID tables");
+        }
+        if !db_schema.get_indices(&table_name.0).is_empty() {
+            bail_parse_error!("UPDATE on WITHOUT ROWID table with indexes is not supported");
+        }
+        if has_update_triggers {
+            bail_parse_error!("UPDATE on WITHOUT ROWID table with triggers is not supported");
+        }
+        if program.capture_data_changes_mode().table().is_some() {
+            bail_parse_error!(
+                "UPDATE on WITHOUT ROWID table is not supported when capturing data changes"
+            );
+        }
+    }
 
-        optimize_select_plan(&mut ephemeral_plan, schema)?;
-        let table = ephemeral_plan
-            .table_references
-            .joined_tables()
-            .first()
-            .unwrap();
-        // We do not need to emit an ephemeral plan if we are not going to loop over the table values
-        if matches!(table.op, Operation::Search(Search::RowidEq { .. })) {
-            (None, vec![])
+    let ephemeral_plan =
+        if rowid_alias_used || or_replace || (table_changes_during_scan && !has_from_clause) {
+            let ephemeral_plan = prepare_ephemeral_rowid_plan(
+                program,
+                schema,
+                &table,
+                &table_name.0,
+                database_id,
+                iter_dir,
+                body.where_clause.as_ref().map(|w| *w.clone()),
+                &result_columns,
+            )?;
+            let table = ephemeral_plan
+                .table_references
+                .joined_tables()
+                .first()
+                .unwrap();
+            // We do not need to emit an ephemeral plan if we are not going to loop over the table values
+            if matches!(table.op, Operation::Search(Search::RowidEq { .. })) {
+                None
+            } else if rowid_alias_used
+                || or_replace
+                || has_update_triggers
+                || table.op.index().is_some_and(|index| {
+                    index_has_updated_column(&table.table, index, &set_clauses)
+                })
+            {
+                Some(ephemeral_plan)
+            } else {
+                None
+            }
         } else {
-            (Some(ephemeral_plan), vec![])
-        }
+            None
+        };
+    // The row moves within the btree whenever its PRIMARY KEY changes, so the keys of the rows
+    // of a WITHOUT ROWID table are always gathered up front.
+    let ephemeral_plan = if without_rowid {
+        Some(prepare_ephemeral_primary_key_plan(
+            program,
+            schema,
+            &table,
+            &table_name.0,
+            database_id,
+            iter_dir,
+            body.where_clause.as_ref().map(|w| *w.clone()),
+            &result_columns,
+        )?)
     } else {
-        (None, vec![])
+        ephemeral_plan
     };
 
     if ephemeral_plan.is_none() {
@@ -319,21 +442,31 @@ pub fn prepare_update_plan(
 
     // Check what indexes will need to be updated by checking set_clauses and see
     // if a column is contained in an index.
-    let indexes = schema.get_indices(&table_name.0);
+    // Every index entry points at a rowid, so all of them change when the rowid does.
+    // With REPLACE every index is needed too, since a conflicting row is deleted from all of them.
+    let indexes = db_schema.get_indices(&table_name.0);
     let indexes_to_update = indexes
         .iter()
         .filter(|index| {
-            index.columns.iter().any(|index_column| {
-                set_clauses
-                    .iter()
-                    .any(|(set_index_column, _)| index_column.pos_in_table == *set_index_column)
-            })
+            rowid_alias_used || or_replace || index_has_updated_column(&table, index, &set_clauses)
         })
         .cloned()
         .collect();
 
+    let join_order = table_references
+        .joined_tables()
+        .iter()
+        .enumerate()
+        .map(|(i, t)| JoinOrderMember {
+            table_id: t.internal_id,
+            original_idx: i,
+            is_outer: t.join_info.as_ref().is_some_and(|j| j.outer),
+        })
+        .collect();
+
     Ok(Plan::Update(UpdatePlan {
         table_references,
+        join_order,
         set_clauses,
         where_clause,
         returning: Some(result_columns),
@@ -342,6 +475,251 @@ pub fn prepare_update_plan(
         offset,
         contains_constant_false_condition: false,
         indexes_to_update,
+        or_conflict: body.or_conflict,
         ephemeral_plan,
+        row_value_set_clauses,
+        non_from_clause_subqueries,
     }))
 }
+
+/// Prepares a plan that gathers the rowids of the rows of `table` matching `where_clause` into
+/// an ephemeral table, for statements that change the table while looping over it.
+#[allow(clippy::too_many_arguments)]
+pub fn prepare_ephemeral_rowid_plan(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    table: &Table,
+    identifier: &str,
+    database_id: usize,
+    iter_dir: IterationDirection,
+    where_clause: Option<Expr>,
+    result_columns: &[ResultSetColumn],
+) -> crate::Result<SelectPlan> {
+    let (table_references, where_terms, internal_id) = ephemeral_plan_table_references(
+        program,
+        table,
+        identifier,
+        database_id,
+        iter_dir,
+        where_clause,
+        result_columns,
+    )?;
+
+    let table = Rc::new(BTreeTable {
+        root_page: 0, // Not relevant for ephemeral table definition
+        name: "ephemeral_scratch".to_string(),
+        has_rowid: true,
+        primary_key_columns: vec![],
+        columns: vec![Column {
+            name: Some("rowid".to_string()),
+            ty: Type::Integer,
+            ty_str: "INTEGER".to_string(),
+            primary_key: true,
+            is_rowid_alias: false,
+            notnull: true,
+            notnull_conflict_clause: None,
+            default: None,
+            unique: false,
+            unique_conflict_clause: None,
+            collation: None,
+            hidden: false,
+            generated: None,
+        }],
+        is_strict: false,
+        unique_sets: None,
+        check_constraints: vec![],
+        primary_key_conflict_clause: None,
+        has_autoincrement: false,
+    });
+
+    let temp_cursor_id = program.alloc_cursor_id(CursorType::BTreeTable(table.clone()));
+
+    let result_columns = vec![ResultSetColumn {
+        expr: Expr::RowId {
+            database: None,
+            table: internal_id,
+        },
+        alias: None,
+        contains_aggregates: false,
+    }];
+    let query_destination = QueryDestination::EphemeralTable {
+        cursor_id: temp_cursor_id,
+        table,
+    };
+    ephemeral_plan(
+        program,
+        schema,
+        table_references,
+        where_terms,
+        result_columns,
+        query_destination,
+    )
+}
+
+/// Prepares a plan that gathers the PRIMARY KEY values of the rows of the WITHOUT ROWID `table`
+/// matching `where_clause` into an ephemeral index, for statements that change the table while
+/// looping over it.
+#[allow(clippy::too_many_arguments)]
+pub fn prepare_ephemeral_primary_key_plan(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    table: &Table,
+    identifier: &str,
+    database_id: usize,
+    iter_dir: IterationDirection,
+    where_clause: Option<Expr>,
+    result_columns: &[ResultSetColumn],
+) -> crate::Result<SelectPlan> {
+    let (table_references, where_terms, internal_id) = ephemeral_plan_table_references(
+        program,
+        table,
+        identifier,
+        database_id,
+        iter_dir,
+        where_clause,
+        result_columns,
+    )?;
+
+    let btree_table = table
+        .btree()
+        .expect("WITHOUT ROWID tables are b-tree tables");
+    let table_index = btree_table.without_rowid_index();
+    let primary_key = &table_index.columns[..btree_table.primary_key_columns.len()];
+    let index = Arc::new(Index {
+        name: "ephemeral_primary_keys".to_string(),
+        table_name: String::new(),
+        root_page: 0,
+        columns: primary_key.to_vec(),
+        unique: false,
+        ephemeral: true,
+        conflict_clause: None,
+        has_rowid: false,
+        where_clause: None,
+    });
+
+    let temp_cursor_id = program.alloc_cursor_id(CursorType::BTreeIndex(index.clone()));
+
+    let result_columns = primary_key
+        .iter()
+        .map(|column| ResultSetColumn {
+            expr: Expr::Column {
+                database: None,
+                table: internal_id,
+                column: column.pos_in_table,
+                is_rowid_alias: false,
+            },
+            alias: None,
+            contains_aggregates: false,
+        })
+        .collect();
+    let query_destination = QueryDestination::EphemeralIndex {
+        cursor_id: temp_cursor_id,
+        index,
+        is_delete: false,
+        affinity_str: None,
+    };
+    ephemeral_plan(
+        program,
+        schema,
+        table_references,
+        where_terms,
+        result_columns,
+        query_destination,
+    )
+}
+
+/// Returns the references to `table` alone and the terms of `where_clause`, for a plan that
+/// gathers the rows to change up front, along with the internal id of the table.
+fn ephemeral_plan_table_references(
+    program: &mut ProgramBuilder,
+    table: &Table,
+    identifier: &str,
+    database_id: usize,
+    iter_dir: IterationDirection,
+    where_clause: Option<Expr>,
+    result_columns: &[ResultSetColumn],
+) -> crate::Result<(TableReferences, Vec<WhereTerm>, TableInternalId)> {
+    let mut where_terms = vec![];
+    let internal_id = program.table_reference_counter.next();
+
+    let joined_tables = vec![JoinedTable {
+        table: match table {
+            Table::Virtual(vtab) => Table::Virtual(vtab.clone()),
+            Table::BTree(btree_table) => Table::BTree(btree_table.clone()),
+            _ => unreachable!(),
+        },
+        identifier: identifier.to_string(),
+        internal_id,
+        op: Operation::Scan {
+            iter_dir,
+            index: None,
+        },
+        join_info: None,
+        col_used_mask: ColumnUsedMask::default(),
+        database_id,
+        vtab_order_by: Vec::new(),
+    }];
+    let mut table_references = TableReferences::new(joined_tables, vec![]);
+
+    // Parse the WHERE clause
+    parse_where(
+        where_clause,
+        &mut table_references,
+        Some(result_columns),
+        &mut where_terms,
+    )?;
+    Ok((table_references, where_terms, internal_id))
+}
+
+/// Builds and optimizes a plan that writes the `result_columns` of the rows matching
+/// `where_terms` to `query_destination`.
+fn ephemeral_plan(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    table_references: TableReferences,
+    where_terms: Vec<WhereTerm>,
+    result_columns: Vec<ResultSetColumn>,
+    query_destination: QueryDestination,
+) -> crate::Result<SelectPlan> {
+    let mut ephemeral_plan = SelectPlan {
+        table_references,
+        result_columns,
+        where_clause: where_terms, // original WHERE terms of the statement
+        group_by: None,            // N/A
+        order_by: None,            // N/A
+        aggregates: vec![],        // N/A
+        window: None,              // N/A
+        limit: None,               // N/A
+        query_destination,
+        join_order: vec![],
+        offset: None,
+        contains_constant_false_condition: false,
+        distinctness: super::plan::Distinctness::NonDistinct,
+        values: vec![],
+        non_from_clause_subqueries: vec![],
+    };
+
+    optimize_select_plan(&mut ephemeral_plan, schema, program.like_behavior())?;
+    Ok(ephemeral_plan)
+}
+
+/// Returns true if any of the columns of `index` may be changed by the SET clauses of an UPDATE.
+/// A generated column is recomputed whenever its row is updated, so it counts as changed.
+/// An update may move the row in or out of a partial index, so partial indexes always count as
+/// changed, and so do indexed expressions.
+pub fn index_has_updated_column(
+    table: &Table,
+    index: &Index,
+    set_clauses: &[(usize, Expr)],
+) -> bool {
+    index.where_clause.is_some()
+        || index.columns.iter().any(|index_column| {
+            index_column.expr.is_some()
+                || table.columns()[index_column.pos_in_table]
+                    .generated
+                    .is_some()
+                || set_clauses
+                    .iter()
+                    .any(|(set_index_column, _)| index_column.pos_in_table == *set_index_column)
+        })
+}
@@ -0,0 +1,504 @@
+use std::rc::Rc;
+
+use turso_sqlite3_parser::ast::{
+    Expr, ResolveType, SortedColumn, TableInternalId, Upsert, UpsertDo, UpsertIndex,
+};
+
+use crate::schema::{BTreeTable, Index, Schema, Table};
+use crate::translate::emitter::{
+    emit_cdc_full_record, emit_cdc_insns, emit_cdc_patch_record, emit_check_constraints,
+    emit_generated_columns, emit_index_key, emit_index_key_for_cursor, emit_notnull_check,
+    emit_partial_index_check, emit_table_affinity, emit_table_record, unique_violation_description,
+    unique_violation_err_code, OperationMode, Resolver,
+};
+use crate::translate::expr::translate_expr;
+use crate::translate::plan::{
+    ColumnUsedMask, IterationDirection, JoinedTable, Operation, OuterQueryReference,
+    TableReferences,
+};
+use crate::translate::planner::bind_column_references;
+use crate::translate::returning::Returning;
+use crate::util::{exprs_are_equivalent, normalize_ident};
+use crate::vdbe::builder::ProgramBuilder;
+use crate::vdbe::insn::{CmpInsFlags, IdxInsertFlags, InsertFlags, Insn};
+use crate::vdbe::{BranchOffset, CursorID};
+use crate::{bail_parse_error, Result, SymbolTable};
+
+/// The uniqueness constraint an `ON CONFLICT` clause applies to.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConflictTarget {
+    /// The INTEGER PRIMARY KEY, i.e. the rowid.
+    Rowid,
+    /// A UNIQUE index (including the automatic index of a non-rowid PRIMARY KEY), by name.
+    Index(String),
+}
+
+/// A single `ON CONFLICT ... DO ...` clause of an UPSERT.
+#[derive(Debug)]
+pub struct UpsertClause {
+    /// `None` if the clause has no conflict target, in which case it handles every
+    /// uniqueness constraint.
+    pub target: Option<ConflictTarget>,
+    pub do_clause: UpsertDo,
+}
+
+/// Flattens the chain of `ON CONFLICT` clauses of an UPSERT and resolves each conflict target
+/// against the PRIMARY KEY and UNIQUE indexes of `table`.
+pub fn resolve_upsert_clauses(
+    schema: &Schema,
+    table: &BTreeTable,
+    upsert: Upsert,
+) -> Result<Vec<UpsertClause>> {
+    let mut clauses = vec![];
+    let mut next = Some(Box::new(upsert));
+    while let Some(upsert) = next {
+        let Upsert {
+            index,
+            do_clause,
+            next: rest,
+        } = *upsert;
+        let target = match index {
+            Some(index) => Some(resolve_conflict_target(schema, table, &index)?),
+            None if rest.is_some() => {
+                bail_parse_error!("ON CONFLICT clause without a conflict target must be the last");
+            }
+            None => None,
+        };
+        clauses.push(UpsertClause {
+            target,
+            do_clause: *do_clause,
+        });
+        next = rest;
+    }
+    Ok(clauses)
+}
+
+fn resolve_conflict_target(
+    schema: &Schema,
+    table: &BTreeTable,
+    target: &UpsertIndex,
+) -> Result<ConflictTarget> {
+    let mut columns = Vec::with_capacity(target.targets.len());
+    for SortedColumn { expr, .. } in target.targets.iter() {
+        let expr = match expr {
+            Expr::Collate(expr, _) => expr.as_ref(),
+            expr => expr,
+        };
+        let Expr::Id(id) = expr else {
+            bail_parse_error!("ON CONFLICT target must be a column name");
+        };
+        let Some((pos, _)) = table.get_column(&id.0) else {
+            bail_parse_error!("no such column: {}", id.0);
+        };
+        columns.push(pos);
+    }
+
+    if let [pos] = columns[..] {
+        if table.columns[pos].is_rowid_alias {
+            return Ok(ConflictTarget::Rowid);
+        }
+    }
+    // Like SQLite, the target only has to name the same set of columns as the index;
+    // the order does not matter. A partial index only matches a target with the same
+    // WHERE clause.
+    let matching_index = schema.get_indices(&table.name).iter().find(|index| {
+        index.unique
+            && index.columns.len() == columns.len()
+            && index
+                .columns
+                .iter()
+                .all(|column| columns.contains(&column.pos_in_table))
+            && index
+                .where_clause
+                .as_ref()
+                .is_none_or(|index_where_clause| {
+                    target.where_clause.as_ref().is_some_and(|where_clause| {
+                        exprs_are_equivalent(where_clause, index_where_clause)
+                    })
+                })
+    });
+    match matching_index {
+        Some(index) => Ok(ConflictTarget::Index(index.name.clone())),
+        None => bail_parse_error!(
+            "ON CONFLICT clause does not match any PRIMARY KEY or UNIQUE constraint"
+        ),
+    }
+}
+
+/// Returns the `ON CONFLICT` clause that handles a violation of `target`, if any.
+pub fn find_upsert_clause<'a>(
+    clauses: &'a [UpsertClause],
+    target: &ConflictTarget,
+) -> Option<&'a UpsertClause> {
+    clauses
+        .iter()
+        .find(|clause| clause.target.as_ref().is_none_or(|t| t == target))
+}
+
+/// The state of the INSERT that an UPSERT needs in order to update the conflicting row.
+pub struct UpsertCtx<'a> {
+    pub table: &'a Rc<BTreeTable>,
+    /// The name the target table is referred to by, i.e. its alias if it has one.
+    pub identifier: &'a str,
+    pub table_internal_id: TableInternalId,
+    pub cursor_id: CursorID,
+    /// Every index of the table, along with its cursor opened for writing.
+    pub index_cursors: &'a [(&'a Index, CursorID)],
+    /// Registers holding the row that failed to be inserted, i.e. the `excluded` row.
+    pub rowid_reg: usize,
+    pub column_registers_start: usize,
+    pub cdc_cursor_id: Option<CursorID>,
+    /// The RETURNING clause of the INSERT, which returns the updated row.
+    pub returning: Option<&'a Returning>,
+    /// Where to continue once the conflict has been handled.
+    pub row_done_label: BranchOffset,
+}
+
+/// Emits the DO clause of `clause` for a row that violated a uniqueness constraint.
+/// For DO UPDATE, `ctx.cursor_id` must point to the row it conflicted with.
+pub fn emit_upsert(
+    program: &mut ProgramBuilder,
+    schema: &Schema,
+    syms: &SymbolTable,
+    clause: &UpsertClause,
+    ctx: &UpsertCtx,
+) -> Result<()> {
+    let (sets, where_clause) = match &clause.do_clause {
+        UpsertDo::Nothing => {
+            program.emit_insn(Insn::Goto {
+                target_pc: ctx.row_done_label,
+            });
+            return Ok(());
+        }
+        UpsertDo::Set { sets, where_clause } => (sets, where_clause),
+    };
+    let table = ctx.table;
+    let columns = &table.columns;
+
+    // The target table is in scope under its own name, and the row that could not be
+    // inserted is in scope as `excluded`. Unqualified column names refer to the target table.
+    let excluded_internal_id = program.table_reference_counter.next();
+    let mut table_references = TableReferences::new(
+        vec![JoinedTable {
+            table: Table::BTree(table.clone()),
+            identifier: ctx.identifier.to_string(),
+            internal_id: ctx.table_internal_id,
+            op: Operation::Scan {
+                iter_dir: IterationDirection::Forwards,
+                index: None,
+            },
+            join_info: None,
+            col_used_mask: ColumnUsedMask::default(),
+            database_id: 0,
+            vtab_order_by: Vec::new(),
+        }],
+        vec![OuterQueryReference {
+            identifier: "excluded".to_string(),
+            internal_id: excluded_internal_id,
+            table: Table::BTree(table.clone()),
+            col_used_mask: ColumnUsedMask::default(),
+            database_id: 0,
+        }],
+    );
+
+    // References to `excluded` are read straight from the registers of the row being inserted.
+    let excluded_exprs = columns
+        .iter()
+        .enumerate()
+        .map(|(i, column)| {
+            let reg = if column.is_rowid_alias {
+                ctx.rowid_reg
+            } else {
+                ctx.column_registers_start + i
+            };
+            let expr = Expr::Column {
+                database: None,
+                table: excluded_internal_id,
+                column: i,
+                is_rowid_alias: column.is_rowid_alias,
+            };
+            (expr, reg)
+        })
+        .chain(std::iter::once((
+            Expr::RowId {
+                database: None,
+                table: excluded_internal_id,
+            },
+            ctx.rowid_reg,
+        )))
+        .collect::<Vec<_>>();
+    let mut resolver = Resolver::new(schema, syms);
+    resolver.enable_expr_to_reg_cache();
+    resolver
+        .expr_to_reg_cache
+        .extend(excluded_exprs.iter().map(|(expr, reg)| (expr, *reg)));
+
+    let mut set_clauses = Vec::with_capacity(sets.len());
+    for set in sets.iter() {
+        if set.col_names.len() > 1 {
+            bail_parse_error!("row value assignments in ON CONFLICT DO UPDATE are not supported");
+        }
+        let ident = normalize_ident(set.col_names[0].0.as_str());
+        let Some((col_index, column)) = table.get_column(&ident) else {
+            bail_parse_error!("column '{}' not found in table '{}'", ident, ctx.identifier);
+        };
+        if column.is_rowid_alias {
+            bail_parse_error!("ON CONFLICT DO UPDATE of the rowid is not supported");
+        }
+        if column.generated.is_some() {
+            bail_parse_error!("cannot UPDATE generated column \"{}\"", ident);
+        }
+        let mut expr = set.expr.clone();
+        bind_column_references(&mut expr, &mut table_references, None)?;
+        set_clauses.push((col_index, expr));
+    }
+
+    let conflict_rowid_reg = program.alloc_register();
+    program.emit_insn(Insn::RowId {
+        cursor_id: ctx.cursor_id,
+        dest: conflict_rowid_reg,
+    });
+
+    if let Some(where_clause) = where_clause {
+        let mut where_clause = where_clause.clone();
+        bind_column_references(&mut where_clause, &mut table_references, None)?;
+        let where_reg = program.alloc_register();
+        translate_expr(
+            program,
+            Some(&table_references),
+            &where_clause,
+            where_reg,
+            &resolver,
+        )?;
+        program.emit_insn(Insn::IfNot {
+            reg: where_reg,
+            target_pc: ctx.row_done_label,
+            jump_if_null: true,
+        });
+    }
+
+    // Build the updated row: either the new value from the SET clause, or the existing value.
+    let start = program.alloc_registers(columns.len());
+    for (idx, column) in columns.iter().enumerate() {
+        let target_reg = start + idx;
+        if let Some((_, expr)) = set_clauses.iter().find(|(i, _)| *i == idx) {
+            translate_expr(
+                program,
+                Some(&table_references),
+                expr,
+                target_reg,
+                &resolver,
+            )?;
+            if column.notnull {
+                emit_notnull_check(
+                    program,
+                    &resolver,
+                    &table.name,
+                    column,
+                    target_reg,
+                    ResolveType::Abort,
+                    ctx.row_done_label,
+                )?;
+            }
+        } else if column.is_rowid_alias {
+            program.emit_null(target_reg, None);
+        } else if column.generated.is_none() {
+            program.emit_column(ctx.cursor_id, idx, target_reg);
+        }
+    }
+    emit_table_affinity(program, table, start);
+    emit_generated_columns(program, &resolver, table, conflict_rowid_reg, start)?;
+    for (idx, column) in columns.iter().enumerate() {
+        if column.generated.is_some() && column.notnull {
+            emit_notnull_check(
+                program,
+                &resolver,
+                &table.name,
+                column,
+                start + idx,
+                ResolveType::Abort,
+                ctx.row_done_label,
+            )?;
+        }
+    }
+    emit_check_constraints(
+        program,
+        &resolver,
+        table,
+        conflict_rowid_reg,
+        start,
+        ResolveType::Abort,
+        ctx.row_done_label,
+    )?;
+
+    // The updated row must not conflict with any other row.
+    let mut index_records = Vec::with_capacity(ctx.index_cursors.len());
+    for (index, idx_cursor_id) in ctx.index_cursors.iter() {
+        let num_cols = index.columns.len();
+        let idx_start_reg = program.alloc_registers(num_cols + 1);
+        emit_index_key(
+            program,
+            &resolver,
+            table,
+            index,
+            conflict_rowid_reg,
+            start,
+            idx_start_reg,
+        )?;
+        let record_reg = program.alloc_register();
+        program.emit_insn(Insn::MakeRecord {
+            start_reg: idx_start_reg,
+            count: num_cols + 1,
+            dest_reg: record_reg,
+            index_name: Some(index.name.clone()),
+        });
+        index_records.push((*idx_cursor_id, idx_start_reg, num_cols, record_reg));
+
+        if !index.unique {
+            continue;
+        }
+        let no_conflict_label = program.allocate_label();
+        emit_partial_index_check(
+            program,
+            &resolver,
+            table,
+            index,
+            conflict_rowid_reg,
+            start,
+            no_conflict_label,
+        )?;
+        program.emit_insn(Insn::NoConflict {
+            cursor_id: *idx_cursor_id,
+            target_pc: no_conflict_label,
+            record_reg: idx_start_reg,
+            num_regs: num_cols,
+        });
+        let idx_rowid_reg = program.alloc_register();
+        program.emit_insn(Insn::IdxRowId {
+            cursor_id: *idx_cursor_id,
+            dest: idx_rowid_reg,
+        });
+        program.emit_insn(Insn::Eq {
+            lhs: conflict_rowid_reg,
+            rhs: idx_rowid_reg,
+            target_pc: no_conflict_label,
+            flags: CmpInsFlags::default(),
+            collation: program.curr_collation(),
+        });
+        program.emit_insn(Insn::Halt {
+            err_code: unique_violation_err_code(table, index),
+            description: unique_violation_description(table, index),
+            on_error: ResolveType::Abort,
+        });
+        program.preassign_label_to_next_insn(no_conflict_label);
+    }
+
+    if table.is_strict {
+        program.emit_insn(Insn::TypeCheck {
+            start_reg: start,
+            count: columns.len(),
+            check_generated: true,
+            table_reference: Rc::clone(table),
+        });
+    }
+
+    let cdc_before_reg = match ctx.cdc_cursor_id {
+        Some(_) if program.capture_data_changes_mode().has_before() => Some(emit_cdc_full_record(
+            program,
+            &Table::BTree(table.clone()),
+            ctx.cursor_id,
+            conflict_rowid_reg,
+        )),
+        _ => None,
+    };
+
+    // Replace the index entries of the old row with the ones of the updated row.
+    for (index, (idx_cursor_id, idx_start_reg, num_cols, record_reg)) in ctx
+        .index_cursors
+        .iter()
+        .map(|(index, _)| index)
+        .zip(index_records)
+    {
+        let skip_delete_label = program.allocate_label();
+        let old_key_start_reg = emit_index_key_for_cursor(
+            program,
+            &resolver,
+            table,
+            index,
+            ctx.cursor_id,
+            skip_delete_label,
+        )?;
+        program.emit_insn(Insn::IdxDelete {
+            start_reg: old_key_start_reg,
+            num_regs: num_cols + 1,
+            cursor_id: idx_cursor_id,
+            raise_error_if_no_matching_entry: true,
+        });
+        program.preassign_label_to_next_insn(skip_delete_label);
+        let skip_insert_label = program.allocate_label();
+        emit_partial_index_check(
+            program,
+            &resolver,
+            table,
+            index,
+            conflict_rowid_reg,
+            start,
+            skip_insert_label,
+        )?;
+        program.emit_insn(Insn::IdxInsert {
+            cursor_id: idx_cursor_id,
+            record_reg,
+            unpacked_start: Some(idx_start_reg),
+            unpacked_count: Some((num_cols + 1) as u16),
+            flags: IdxInsertFlags::new(),
+        });
+        program.preassign_label_to_next_insn(skip_insert_label);
+    }
+
+    let record_reg = program.alloc_register();
+    emit_table_record(program, columns, start, record_reg);
+    program.emit_insn(Insn::Insert {
+        cursor: ctx.cursor_id,
+        key_reg: conflict_rowid_reg,
+        record_reg,
+        flag: InsertFlags::new()
+            .require_seek()
+            .update()
+            .nchange(program.change_count()),
+        table_name: table.name.clone(),
+    });
+
+    if let Some(cdc_cursor_id) = ctx.cdc_cursor_id {
+        let table = Table::BTree(table.clone());
+        let cdc_after_reg = if program.capture_data_changes_mode().has_after() {
+            Some(emit_cdc_patch_record(
+                program,
+                &table,
+                start,
+                record_reg,
+                conflict_rowid_reg,
+            ))
+        } else {
+            None
+        };
+        emit_cdc_insns(
+            program,
+            &resolver,
+            OperationMode::UPDATE,
+            cdc_cursor_id,
+            conflict_rowid_reg,
+            cdc_before_reg,
+            cdc_after_reg,
+            table.get_name(),
+        )?;
+    }
+
+    if let Some(returning) = ctx.returning {
+        returning.emit_row(program, &resolver, table, conflict_rowid_reg, start)?;
+    }
+
+    program.emit_insn(Insn::Goto {
+        target_pc: ctx.row_done_label,
+    });
+    Ok(())
+}
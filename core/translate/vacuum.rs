@@ -0,0 +1,61 @@
+use crate::schema::Schema;
+use crate::translate::emitter::{Resolver, TransactionMode};
+use crate::translate::expr::translate_expr;
+use crate::translate::{ProgramBuilder, ProgramBuilderOpts};
+use crate::vdbe::insn::{Cookie, Insn};
+use crate::{bail_parse_error, Result, SymbolTable};
+use turso_sqlite3_parser::ast::{Expr, Name};
+
+/// Translates `VACUUM [schema] [INTO expr]`. The database is rebuilt by the `Vacuum`
+/// instruction; a plain `VACUUM` then reloads the schema, since the root pages may have moved.
+pub fn translate_vacuum(
+    schema_name: Option<Name>,
+    into: Option<Box<Expr>>,
+    schema: &Schema,
+    syms: &SymbolTable,
+    mut program: ProgramBuilder,
+) -> Result<ProgramBuilder> {
+    if let Some(schema_name) = schema_name {
+        if schema.database_index(&schema_name.0)? != 0 {
+            bail_parse_error!("VACUUM of attached databases is not supported");
+        }
+    }
+    if !schema.indexes_enabled() && schema.tables.keys().any(|t| schema.table_has_indexes(t)) {
+        bail_parse_error!(
+            "VACUUM on a database with indexes is disabled by default. Run with `--experimental-indexes` to enable this feature."
+        );
+    }
+    program.extend(&ProgramBuilderOpts {
+        num_cursors: 0,
+        approx_num_insns: 6,
+        approx_num_labels: 0,
+    });
+
+    match into {
+        Some(into) => {
+            let resolver = Resolver::new(schema, syms);
+            let into_reg = program.alloc_register();
+            translate_expr(&mut program, None, &into, into_reg, &resolver)?;
+            program.emit_insn(Insn::Vacuum {
+                db: 0,
+                into: Some(into_reg),
+            });
+            program.epilogue(TransactionMode::Read);
+        }
+        None => {
+            program.emit_insn(Insn::Vacuum { db: 0, into: None });
+            program.emit_insn(Insn::ParseSchema {
+                db: 0,
+                where_clause: None,
+            });
+            program.emit_insn(Insn::SetCookie {
+                db: 0,
+                cookie: Cookie::SchemaVersion,
+                value: schema.schema_version as i32 + 1,
+                p5: 0,
+            });
+            program.epilogue(TransactionMode::Write);
+        }
+    }
+    Ok(program)
+}
@@ -1,6 +1,9 @@
+use turso_sqlite3_parser::ast;
+
 use crate::translate::emitter::Resolver;
 use crate::translate::expr::{translate_expr_no_constant_opt, NoConstantOptReason};
 use crate::translate::plan::{QueryDestination, SelectPlan};
+use crate::translate::result_row::{emit_ephemeral_index_insert, emit_recursive_cte_queue_insert};
 use crate::vdbe::builder::ProgramBuilder;
 use crate::vdbe::insn::Insn;
 use crate::vdbe::BranchOffset;
@@ -12,7 +15,7 @@ pub fn emit_values(
     resolver: &Resolver,
 ) -> Result<usize> {
     if plan.values.len() == 1 {
-        let start_reg = emit_values_when_single_row(program, plan, resolver)?;
+        let start_reg = emit_values_rows(program, plan, resolver, &plan.values)?;
         return Ok(start_reg);
     }
 
@@ -21,30 +24,48 @@ pub fn emit_values(
         QueryDestination::CoroutineYield { yield_reg, .. } => {
             emit_values_in_subquery(program, plan, resolver, yield_reg)?
         }
-        QueryDestination::EphemeralIndex { .. } => unreachable!(),
-        QueryDestination::EphemeralTable { .. } => unreachable!(),
+        // Only the first row of an EXISTS or scalar subquery is ever read.
+        QueryDestination::ExistsSubqueryResult { .. }
+        | QueryDestination::RowValueSubqueryResult { .. } => {
+            emit_values_rows(program, plan, resolver, &plan.values[..1])?
+        }
+        _ => emit_values_rows(program, plan, resolver, &plan.values)?,
     };
     Ok(reg_result_cols_start)
 }
 
-fn emit_values_when_single_row(
+/// Emits the rows one after another into the same registers, and stores each of them in the
+/// destination of the query. Returns the start register of the rows.
+fn emit_values_rows(
     program: &mut ProgramBuilder,
     plan: &SelectPlan,
     resolver: &Resolver,
+    rows: &[Vec<ast::Expr>],
 ) -> Result<usize> {
-    let first_row = &plan.values[0];
-    let row_len = first_row.len();
+    let row_len = rows[0].len();
     let start_reg = program.alloc_registers(row_len);
-    for (i, v) in first_row.iter().enumerate() {
-        translate_expr_no_constant_opt(
-            program,
-            None,
-            v,
-            start_reg + i,
-            resolver,
-            NoConstantOptReason::RegisterReuse,
-        )?;
+    for row in rows {
+        for (i, v) in row.iter().enumerate() {
+            translate_expr_no_constant_opt(
+                program,
+                None,
+                v,
+                start_reg + i,
+                resolver,
+                NoConstantOptReason::RegisterReuse,
+            )?;
+        }
+        emit_values_row_to_destination(program, plan, start_reg, row_len);
     }
+    Ok(start_reg)
+}
+
+fn emit_values_row_to_destination(
+    program: &mut ProgramBuilder,
+    plan: &SelectPlan,
+    start_reg: usize,
+    row_len: usize,
+) {
     match plan.query_destination {
         QueryDestination::ResultRows => {
             program.emit_insn(Insn::ResultRow {
@@ -58,10 +79,65 @@ fn emit_values_when_single_row(
                 end_offset: BranchOffset::Offset(0),
             });
         }
-        QueryDestination::EphemeralIndex { .. } => unreachable!(),
+        QueryDestination::EphemeralIndex {
+            cursor_id,
+            is_delete: true,
+            ..
+        } => {
+            program.emit_insn(Insn::IdxDelete {
+                start_reg,
+                num_regs: row_len,
+                cursor_id,
+                raise_error_if_no_matching_entry: false,
+            });
+        }
+        QueryDestination::EphemeralIndex {
+            cursor_id,
+            ref index,
+            is_delete: false,
+            ref affinity_str,
+        } => {
+            emit_ephemeral_index_insert(
+                program,
+                cursor_id,
+                index,
+                affinity_str.as_deref(),
+                start_reg,
+                row_len,
+            );
+        }
         QueryDestination::EphemeralTable { .. } => unreachable!(),
+        QueryDestination::RecursiveCteQueue {
+            cursor_id,
+            ref table,
+            distinct_cursor_id,
+        } => {
+            emit_recursive_cte_queue_insert(
+                program,
+                cursor_id,
+                table,
+                distinct_cursor_id,
+                start_reg,
+                row_len,
+            );
+        }
+        QueryDestination::ExistsSubqueryResult { result_reg } => {
+            program.emit_insn(Insn::Integer {
+                value: 1,
+                dest: result_reg,
+            });
+        }
+        QueryDestination::RowValueSubqueryResult {
+            result_reg_start,
+            num_regs,
+        } => {
+            program.emit_insn(Insn::Copy {
+                src_reg: start_reg,
+                dst_reg: result_reg_start,
+                extra_amount: num_regs - 1,
+            });
+        }
     }
-    Ok(start_reg)
 }
 
 fn emit_toplevel_values(
@@ -0,0 +1,146 @@
+use turso_sqlite3_parser::ast::{self, fmt::ToTokens, QualifiedName};
+
+use crate::schema::{Schema, View};
+use crate::translate::emitter::TransactionMode;
+use crate::translate::planner::parse_view_reference;
+use crate::translate::schema::{
+    emit_delete_schema_entry, emit_schema_entry, SchemaEntryType, SQLITE_TABLEID,
+};
+use crate::util::normalize_ident;
+use crate::vdbe::builder::{CursorType, ProgramBuilder, ProgramBuilderOpts, TableRefIdCounter};
+use crate::vdbe::insn::{Cookie, Insn};
+use crate::{bail_parse_error, Result, SymbolTable};
+
+#[allow(clippy::too_many_arguments)]
+pub fn translate_create_view(
+    temporary: bool,
+    if_not_exists: bool,
+    view_name: QualifiedName,
+    columns: Option<Vec<ast::IndexedColumn>>,
+    select: ast::Select,
+    schema: &Schema,
+    syms: &SymbolTable,
+    mut program: ProgramBuilder,
+) -> Result<ProgramBuilder> {
+    if temporary {
+        bail_parse_error!("TEMPORARY views are not supported yet");
+    }
+    let normalized_view_name = normalize_ident(&view_name.name.0);
+
+    let existing = if schema.get_view(&normalized_view_name).is_some() {
+        Some("view")
+    } else if schema.get_table(&normalized_view_name).is_some() {
+        Some("table")
+    } else {
+        None
+    };
+    if let Some(existing) = existing {
+        if if_not_exists {
+            program.epilogue(TransactionMode::Write);
+            return Ok(program);
+        }
+        bail_parse_error!("{} {} already exists", existing, normalized_view_name);
+    }
+
+    // Plan the view once so that a view which can't be queried is rejected up front.
+    // This also rules out views that refer to themselves.
+    let view = View::new(&normalized_view_name, columns.as_deref(), select.clone());
+    parse_view_reference(
+        schema,
+        syms,
+        &view,
+        normalized_view_name.clone(),
+        &mut TableRefIdCounter::new(),
+    )?;
+
+    let opts = ProgramBuilderOpts {
+        num_cursors: 1,
+        approx_num_insns: 20,
+        approx_num_labels: 1,
+    };
+    program.extend(&opts);
+
+    let sql = ast::Stmt::CreateView {
+        temporary,
+        if_not_exists: false,
+        view_name,
+        columns,
+        select: Box::new(select),
+    }
+    .format()
+    .unwrap();
+
+    let sqlite_table = schema.get_btree_table(SQLITE_TABLEID).unwrap();
+    let sqlite_schema_cursor_id =
+        program.alloc_cursor_id(CursorType::BTreeTable(sqlite_table.clone()));
+    program.emit_insn(Insn::OpenWrite {
+        cursor_id: sqlite_schema_cursor_id,
+        root_page: 1usize.into(),
+        name: sqlite_table.name.clone(),
+        db: 0,
+    });
+    emit_schema_entry(
+        &mut program,
+        sqlite_schema_cursor_id,
+        SchemaEntryType::View,
+        &normalized_view_name,
+        &normalized_view_name,
+        0, // views don't have a root page
+        Some(sql),
+    );
+
+    program.emit_insn(Insn::SetCookie {
+        db: 0,
+        cookie: Cookie::SchemaVersion,
+        value: schema.schema_version as i32 + 1,
+        p5: 0,
+    });
+    program.emit_insn(Insn::ParseSchema {
+        db: 0,
+        where_clause: Some(format!("type = 'view' AND name = '{normalized_view_name}'")),
+    });
+
+    program.epilogue(TransactionMode::Write);
+
+    Ok(program)
+}
+
+pub fn translate_drop_view(
+    view_name: &QualifiedName,
+    if_exists: bool,
+    schema: &Schema,
+    mut program: ProgramBuilder,
+) -> Result<ProgramBuilder> {
+    let view_name = normalize_ident(&view_name.name.0);
+    if schema.get_view(&view_name).is_none() {
+        if schema.get_table(&view_name).is_some() {
+            bail_parse_error!("use DROP TABLE to delete table {}", view_name);
+        }
+        if if_exists {
+            program.epilogue(TransactionMode::Write);
+            return Ok(program);
+        }
+        bail_parse_error!("no such view: {}", view_name);
+    }
+
+    let opts = ProgramBuilderOpts {
+        num_cursors: 1,
+        approx_num_insns: 20,
+        approx_num_labels: 3,
+    };
+    program.extend(&opts);
+
+    emit_delete_schema_entry(&mut program, schema, SchemaEntryType::View, &view_name);
+
+    program.emit_insn(Insn::SetCookie {
+        db: 0,
+        cookie: Cookie::SchemaVersion,
+        value: schema.schema_version as i32 + 1,
+        p5: 0,
+    });
+    program.emit_insn(Insn::DropView { db: 0, view_name });
+
+    program.epilogue(TransactionMode::Write);
+
+    Ok(program)
+}
@@ -0,0 +1,2198 @@
+use std::rc::Rc;
+
+use turso_sqlite3_parser::ast::{self, Expr, SortOrder, TableInternalId};
+
+use crate::{
+    error::SQLITE_ERROR,
+    function::{AggFunc, WindowFunc},
+    schema::{BTreeTable, Column, PseudoCursorType, Schema, Type},
+    translate::collate::CollationSeq,
+    util::exprs_are_equivalent,
+    vdbe::{
+        builder::{CursorType, ProgramBuilder, TableRefIdCounter},
+        insn::{CmpInsFlags, InsertFlags, Insn},
+        BranchOffset,
+    },
+    Result, SymbolTable,
+};
+
+use super::{
+    aggregation::translate_aggregation_step,
+    emitter::{Resolver, TranslateCtx},
+    expr::{translate_expr, walk_expr, walk_expr_mut, WalkControl},
+    order_by::{order_by_sorter_insert, sorter_insert},
+    plan::{
+        Aggregate, Distinctness, JoinOrderMember, JoinedTable, QueryDestination, ResultSetColumn,
+        SelectPlan, TableReferences, Window, WindowFrame, WindowFrameBound, WindowFunction,
+    },
+    planner::resolve_aggregates,
+    result_row::emit_select_result,
+};
+
+/// Returns whether `expr` is a call of a function with an OVER clause.
+pub fn is_window_function_call(expr: &Expr) -> bool {
+    match expr {
+        Expr::FunctionCall { filter_over, .. } | Expr::FunctionCallStar { filter_over, .. } => {
+            filter_over
+                .as_ref()
+                .is_some_and(|tail| tail.over_clause.is_some())
+        }
+        _ => false,
+    }
+}
+
+/// Replaces the references to the windows of the WINDOW clause in the OVER clauses of `expr`,
+/// e.g. `OVER w` or `OVER (w ORDER BY x)`, with the windows they refer to.
+pub fn resolve_window_names(expr: &mut Expr, window_clause: &[ast::WindowDef]) -> Result<()> {
+    walk_expr_mut(expr, &mut |expr: &mut Expr| -> Result<()> {
+        let (Expr::FunctionCall { filter_over, .. } | Expr::FunctionCallStar { filter_over, .. }) =
+            expr
+        else {
+            return Ok(());
+        };
+        let Some(over_clause) = filter_over
+            .as_mut()
+            .and_then(|tail| tail.over_clause.as_mut())
+        else {
+            return Ok(());
+        };
+        let window = match over_clause.as_ref() {
+            ast::Over::Name(name) => find_window(window_clause, name)?.clone(),
+            ast::Over::Window(window) => match &window.base {
+                Some(base) => {
+                    let mut resolved = find_window(window_clause, base)?.clone();
+                    if window.partition_by.is_some() {
+                        crate::bail_parse_error!(
+                            "cannot override PARTITION clause of window: {}",
+                            base.0
+                        );
+                    }
+                    if window.order_by.is_some() {
+                        if resolved.order_by.is_some() {
+                            crate::bail_parse_error!(
+                                "cannot override ORDER BY clause of window: {}",
+                                base.0
+                            );
+                        }
+                        resolved.order_by = window.order_by.clone();
+                    }
+                    if window.frame_clause.is_some() {
+                        resolved.frame_clause = window.frame_clause.clone();
+                    }
+                    resolved
+                }
+                None => return Ok(()),
+            },
+        };
+        **over_clause = ast::Over::Window(window);
+        Ok(())
+    })
+}
+
+fn find_window<'a>(
+    window_clause: &'a [ast::WindowDef],
+    name: &ast::Name,
+) -> Result<&'a ast::Window> {
+    let Some(window_def) = window_clause
+        .iter()
+        .find(|window_def| window_def.name.0.eq_ignore_ascii_case(&name.0))
+    else {
+        crate::bail_parse_error!("no such window: {}", name.0);
+    };
+    if window_def.window.base.is_some() {
+        crate::bail_parse_error!("window {} may not be based on another window", name.0);
+    }
+    Ok(&window_def.window)
+}
+
+/// Plans the window functions of a SELECT. The window functions of a SELECT are computed over a
+/// single window, once its rows are aggregated. So, like in SQLite, a SELECT whose window
+/// functions are over several windows, or that also aggregates, is split into nested SELECTs
+/// read as FROM clause subqueries: the innermost one computes everything but the window
+/// functions, and each of the others computes the functions of a window over the rows of the
+/// one it reads from.
+pub fn plan_windows(
+    mut plan: SelectPlan,
+    schema: &Schema,
+    syms: &SymbolTable,
+    table_ref_counter: &mut TableRefIdCounter,
+) -> Result<SelectPlan> {
+    let windows = collect_windows(&plan.result_columns, plan.order_by.as_deref(), syms)?;
+    if windows.is_empty()
+        || (windows.len() == 1 && plan.group_by.is_none() && plan.aggregates.is_empty())
+    {
+        plan.window = windows.into_iter().next();
+        return Ok(plan);
+    }
+
+    // The columns of a nested SELECT are the expressions the window functions are computed from,
+    // followed by the functions of the windows computed so far.
+    let mut columns = Vec::new();
+    let exprs = plan
+        .result_columns
+        .iter()
+        .map(|rc| &rc.expr)
+        .chain(plan.order_by.iter().flatten().map(|(expr, _)| expr));
+    for expr in exprs {
+        collect_window_inputs(expr, &mut columns)?;
+    }
+    if columns.is_empty() {
+        columns.push(Expr::Literal(ast::Literal::Null));
+    }
+    // The result columns keep the names they had before their expressions are replaced.
+    let names = plan
+        .result_columns
+        .iter()
+        .map(|rc| match rc.name(&plan.table_references) {
+            Some(name) => name.to_string(),
+            None => rc.expr.to_string(),
+        })
+        .collect::<Vec<_>>();
+    let outer_query_refs = plan.table_references.outer_query_refs().to_vec();
+    let mut result_columns = Some(std::mem::take(&mut plan.result_columns));
+    let mut order_by = plan.order_by.take();
+    let mut limit = plan.limit.take();
+    let mut offset = plan.offset.take();
+    let mut distinctness = Some(std::mem::replace(
+        &mut plan.distinctness,
+        Distinctness::NonDistinct,
+    ));
+    let mut query_destination = Some(std::mem::replace(
+        &mut plan.query_destination,
+        subquery_destination(),
+    ));
+    for expr in columns.iter() {
+        let contains_aggregates = resolve_aggregates(schema, expr, &mut plan.aggregates)?;
+        plan.result_columns.push(ResultSetColumn {
+            expr: expr.clone(),
+            alias: None,
+            contains_aggregates,
+        });
+    }
+
+    let window_count = windows.len();
+    let mut windows = windows.into_iter();
+    for level in 0..window_count {
+        let table_id = table_ref_counter.next();
+        let mut table = JoinedTable::new_subquery(format!("window_{level}"), plan, None, table_id);
+        for column in 0..columns.len() {
+            table.mark_column_used(column);
+        }
+        plan = SelectPlan {
+            join_order: vec![JoinOrderMember {
+                table_id,
+                original_idx: 0,
+                is_outer: false,
+            }],
+            table_references: TableReferences::new(vec![table], outer_query_refs.clone()),
+            result_columns: vec![],
+            where_clause: vec![],
+            group_by: None,
+            order_by: None,
+            aggregates: vec![],
+            window: None,
+            limit: None,
+            offset: None,
+            contains_constant_false_condition: false,
+            query_destination: subquery_destination(),
+            distinctness: Distinctness::NonDistinct,
+            values: vec![],
+            non_from_clause_subqueries: vec![],
+        };
+        if level + 1 == window_count {
+            let result_columns = result_columns.take().expect("result columns must exist");
+            for (rc, name) in result_columns.into_iter().zip(names.iter()) {
+                plan.result_columns.push(ResultSetColumn {
+                    expr: replace_window_inputs(&rc.expr, &columns, table_id)?,
+                    alias: Some(name.clone()),
+                    contains_aggregates: false,
+                });
+            }
+            plan.order_by = order_by
+                .take()
+                .map(|order_by| {
+                    order_by
+                        .iter()
+                        .map(|(expr, order)| {
+                            Ok((replace_window_inputs(expr, &columns, table_id)?, *order))
+                        })
+                        .collect::<Result<Vec<_>>>()
+                })
+                .transpose()?;
+            plan.limit = limit.take();
+            plan.offset = offset.take();
+            plan.distinctness = distinctness.take().expect("distinctness must exist");
+            plan.query_destination = query_destination
+                .take()
+                .expect("query destination must exist");
+        } else {
+            let window = windows.next().expect("window must exist");
+            for column in 0..columns.len() {
+                plan.result_columns.push(ResultSetColumn {
+                    expr: Expr::Column {
+                        database: None,
+                        table: table_id,
+                        column,
+                        is_rowid_alias: false,
+                    },
+                    alias: None,
+                    contains_aggregates: false,
+                });
+            }
+            for function in window.functions.iter() {
+                plan.result_columns.push(ResultSetColumn {
+                    expr: replace_window_inputs(&function.original_expr, &columns, table_id)?,
+                    alias: None,
+                    contains_aggregates: false,
+                });
+            }
+            columns.extend(
+                window
+                    .functions
+                    .into_iter()
+                    .map(|function| function.original_expr),
+            );
+        }
+        let mut level_windows =
+            collect_windows(&plan.result_columns, plan.order_by.as_deref(), syms)?;
+        assert!(
+            level_windows.len() <= 1,
+            "a nested SELECT computes a single window"
+        );
+        plan.window = level_windows.pop();
+    }
+    Ok(plan)
+}
+
+fn subquery_destination() -> QueryDestination {
+    QueryDestination::CoroutineYield {
+        yield_reg: usize::MAX, // will be set later in bytecode emission
+        coroutine_implementation_start: BranchOffset::Placeholder, // will be set later in bytecode emission
+    }
+}
+
+/// Adds the largest parts of `expr` without window functions to `inputs`, along with the
+/// arguments, FILTER clauses and window terms of its window functions. Literals are left out.
+fn collect_window_inputs(expr: &Expr, inputs: &mut Vec<Expr>) -> Result<()> {
+    let mut add = |expr: &Expr| {
+        if !matches!(expr, Expr::Literal(_))
+            && !inputs.iter().any(|input| exprs_are_equivalent(input, expr))
+        {
+            inputs.push(expr.clone());
+        }
+    };
+    walk_expr(expr, &mut |expr: &Expr| -> Result<WalkControl> {
+        if is_window_function_call(expr) {
+            let (args, tail) = match expr {
+                Expr::FunctionCall {
+                    args,
+                    filter_over: Some(tail),
+                    ..
+                } => (args.as_deref().unwrap_or_default(), tail),
+                Expr::FunctionCallStar {
+                    filter_over: Some(tail),
+                    ..
+                } => (&[][..], tail),
+                _ => unreachable!("a window function call has an OVER clause"),
+            };
+            args.iter().for_each(&mut add);
+            tail.filter_clause.iter().for_each(|filter| add(filter));
+            if let Some(ast::Over::Window(window)) = tail.over_clause.as_deref() {
+                window.partition_by.iter().flatten().for_each(&mut add);
+                window
+                    .order_by
+                    .iter()
+                    .flatten()
+                    .for_each(|sorted_column| add(&sorted_column.expr));
+            }
+            return Ok(WalkControl::SkipChildren);
+        }
+        if !contains_window_function_call(expr)? {
+            add(expr);
+            return Ok(WalkControl::SkipChildren);
+        }
+        Ok(WalkControl::Continue)
+    })?;
+    Ok(())
+}
+
+fn contains_window_function_call(expr: &Expr) -> Result<bool> {
+    let mut contains = false;
+    walk_expr(expr, &mut |expr: &Expr| -> Result<WalkControl> {
+        if is_window_function_call(expr) {
+            contains = true;
+            return Ok(WalkControl::SkipChildren);
+        }
+        Ok(WalkControl::Continue)
+    })?;
+    Ok(contains)
+}
+
+/// Replaces the parts of `expr` that are columns of the nested SELECT `table_id` with references
+/// to them.
+fn replace_window_inputs(expr: &Expr, columns: &[Expr], table_id: TableInternalId) -> Result<Expr> {
+    let mut expr = expr.clone();
+    walk_expr_mut(&mut expr, &mut |expr: &mut Expr| -> Result<()> {
+        if let Some(column) = columns
+            .iter()
+            .position(|column| exprs_are_equivalent(column, expr))
+        {
+            *expr = Expr::Column {
+                database: None,
+                table: table_id,
+                column,
+                is_rowid_alias: false,
+            };
+        }
+        Ok(())
+    })?;
+    Ok(expr)
+}
+
+/// Collects the window functions of the result columns and the ORDER BY clause into the windows
+/// they are computed over.
+fn collect_windows(
+    result_columns: &[ResultSetColumn],
+    order_by: Option<&[(Expr, SortOrder)]>,
+    syms: &SymbolTable,
+) -> Result<Vec<Window>> {
+    let mut windows: Vec<Window> = Vec::new();
+    let exprs = result_columns
+        .iter()
+        .map(|rc| &rc.expr)
+        .chain(order_by.into_iter().flatten().map(|(expr, _)| expr));
+    for expr in exprs {
+        walk_expr(expr, &mut |expr: &Expr| -> Result<WalkControl> {
+            if !is_window_function_call(expr) {
+                return Ok(WalkControl::Continue);
+            }
+            if windows.iter().any(|window| {
+                window
+                    .functions
+                    .iter()
+                    .any(|function| exprs_are_equivalent(&function.original_expr, expr))
+            }) {
+                return Ok(WalkControl::SkipChildren);
+            }
+            let (function, partition_by, order_by) = plan_window_function(expr, syms)?;
+            let same_window = |window: &&mut Window| {
+                window.partition_by.len() == partition_by.len()
+                    && window
+                        .partition_by
+                        .iter()
+                        .zip(partition_by.iter())
+                        .all(|(a, b)| exprs_are_equivalent(a, b))
+                    && window.order_by.len() == order_by.len()
+                    && window.order_by.iter().zip(order_by.iter()).all(
+                        |((a, a_order), (b, b_order))| {
+                            a_order == b_order && exprs_are_equivalent(a, b)
+                        },
+                    )
+            };
+            match windows.iter_mut().find(same_window) {
+                Some(window) => window.functions.push(function),
+                None => windows.push(Window {
+                    partition_by,
+                    order_by,
+                    functions: vec![function],
+                }),
+            }
+            Ok(WalkControl::SkipChildren)
+        })?;
+    }
+    Ok(windows)
+}
+
+/// Plans a single window function call, returning it along with the PARTITION BY and ORDER BY
+/// terms of its window.
+#[allow(clippy::type_complexity)]
+fn plan_window_function(
+    expr: &Expr,
+    syms: &SymbolTable,
+) -> Result<(WindowFunction, Vec<Expr>, Vec<(Expr, SortOrder)>)> {
+    let (name, args, distinctness, tail) = match expr {
+        Expr::FunctionCall {
+            name,
+            distinctness,
+            args,
+            filter_over: Some(tail),
+            ..
+        } => (
+            name,
+            args.clone().unwrap_or_default(),
+            distinctness.as_ref(),
+            tail,
+        ),
+        Expr::FunctionCallStar {
+            name,
+            filter_over: Some(tail),
+        } => (name, vec![], None, tail),
+        _ => unreachable!("plan_window_function called on a call without an OVER clause"),
+    };
+    if matches!(distinctness, Some(ast::Distinctness::Distinct)) {
+        crate::bail_parse_error!("DISTINCT is not supported for window functions");
+    }
+    let func = match WindowFunc::resolve_function(&name.0, args.len()) {
+        Ok(func) => func,
+        Err(err) => match syms.resolve_function(&name.0, args.len()) {
+            Some(func) if func.func.is_window() => WindowFunc::Agg(
+                func.aggregate(args.len())
+                    .expect("window must be an aggregate"),
+            ),
+            Some(_) => {
+                crate::bail_parse_error!("{}() may not be used as a window function", name.0)
+            }
+            None => return Err(err),
+        },
+    };
+    if tail.filter_clause.is_some() && !matches!(func, WindowFunc::Agg(_)) {
+        crate::bail_parse_error!("FILTER clause may only be used with aggregate window functions");
+    }
+    let window = match tail.over_clause.as_deref() {
+        Some(ast::Over::Window(window)) => window,
+        Some(ast::Over::Name(name)) => crate::bail_parse_error!("no such window: {}", name.0),
+        None => unreachable!("plan_window_function called on a call without an OVER clause"),
+    };
+    let frame = match &window.frame_clause {
+        Some(frame_clause) => plan_window_frame(frame_clause)?,
+        None => WindowFrame::default_frame(),
+    };
+    let partition_by = window.partition_by.clone().unwrap_or_default();
+    let order_by: Vec<_> = window
+        .order_by
+        .iter()
+        .flatten()
+        .map(|sorted_column| {
+            (
+                sorted_column.expr.clone(),
+                sorted_column.order.unwrap_or(SortOrder::Asc),
+            )
+        })
+        .collect();
+    if has_range_offset(&frame) && order_by.len() != 1 {
+        crate::bail_parse_error!(
+            "RANGE with offset PRECEDING/FOLLOWING requires one ORDER BY expression"
+        );
+    }
+    let function = WindowFunction {
+        func,
+        args,
+        filter: tail.filter_clause.as_deref().cloned(),
+        frame,
+        original_expr: expr.clone(),
+    };
+    Ok((function, partition_by, order_by))
+}
+
+fn plan_window_frame(frame_clause: &ast::FrameClause) -> Result<WindowFrame> {
+    if frame_clause.mode == ast::FrameMode::Groups {
+        crate::bail_parse_error!("GROUPS frames are not supported");
+    }
+    if !matches!(
+        frame_clause.exclude,
+        None | Some(ast::FrameExclude::NoOthers)
+    ) {
+        crate::bail_parse_error!("EXCLUDE is not supported in window frames");
+    }
+    let start = plan_frame_bound(frame_clause.mode, &frame_clause.start, "starting")?;
+    let end = match &frame_clause.end {
+        Some(end) => plan_frame_bound(frame_clause.mode, end, "ending")?,
+        None => WindowFrameBound::CurrentRow,
+    };
+    // The frame cannot start after the current row if it ends at or before it.
+    let invalid = matches!(
+        (start, end),
+        (WindowFrameBound::UnboundedFollowing, _)
+            | (_, WindowFrameBound::UnboundedPreceding)
+            | (
+                WindowFrameBound::Following(_),
+                WindowFrameBound::Preceding(_) | WindowFrameBound::CurrentRow,
+            )
+            | (WindowFrameBound::CurrentRow, WindowFrameBound::Preceding(_))
+    );
+    if invalid {
+        crate::bail_parse_error!("unsupported frame specification");
+    }
+    Ok(WindowFrame {
+        mode: frame_clause.mode,
+        start,
+        end,
+    })
+}
+
+fn plan_frame_bound(
+    mode: ast::FrameMode,
+    bound: &ast::FrameBound,
+    which: &str,
+) -> Result<WindowFrameBound> {
+    let offset = |expr: &Expr| -> Result<f64> {
+        let value = match expr {
+            Expr::Literal(ast::Literal::Numeric(value)) => Some(value),
+            _ => None,
+        };
+        if mode == ast::FrameMode::Range {
+            match value.and_then(|value| value.parse::<f64>().ok()) {
+                Some(offset) if offset >= 0.0 => Ok(offset),
+                _ => {
+                    crate::bail_parse_error!("frame {} offset must be a non-negative number", which)
+                }
+            }
+        } else {
+            match value.and_then(|value| value.parse::<u64>().ok()) {
+                Some(offset) => Ok(offset as f64),
+                None => {
+                    crate::bail_parse_error!(
+                        "frame {} offset must be a non-negative integer",
+                        which
+                    )
+                }
+            }
+        }
+    };
+    Ok(match bound {
+        ast::FrameBound::UnboundedPreceding => WindowFrameBound::UnboundedPreceding,
+        ast::FrameBound::Preceding(expr) => WindowFrameBound::Preceding(offset(expr)?),
+        ast::FrameBound::CurrentRow => WindowFrameBound::CurrentRow,
+        ast::FrameBound::Following(expr) => WindowFrameBound::Following(offset(expr)?),
+        ast::FrameBound::UnboundedFollowing => WindowFrameBound::UnboundedFollowing,
+    })
+}
+
+/// Whether a bound of the frame is a RANGE offset, which is applied to the ORDER BY term.
+fn has_range_offset(frame: &WindowFrame) -> bool {
+    frame.mode == ast::FrameMode::Range
+        && [frame.start, frame.end].iter().any(|bound| {
+            matches!(
+                bound,
+                WindowFrameBound::Preceding(_) | WindowFrameBound::Following(_)
+            )
+        })
+}
+
+/// The order of the ORDER BY term of the window if the frame of one of its functions has a RANGE
+/// offset, in which case the term is buffered along with the rows.
+fn range_key_order(window: &Window) -> Option<SortOrder> {
+    window
+        .functions
+        .iter()
+        .any(|function| has_range_offset(&function.frame))
+        .then(|| window.order_by[0].1)
+}
+
+/// Returns the expressions whose values are buffered for every row: the columns read by the
+/// result columns and the ORDER BY clause outside of window functions, and by the arguments and
+/// FILTER clauses of the window functions.
+fn collect_window_sources<'a>(plan: &'a SelectPlan, window: &'a Window) -> Result<Vec<&'a Expr>> {
+    let mut sources: Vec<&'a Expr> = Vec::new();
+    let exprs = plan
+        .result_columns
+        .iter()
+        .map(|rc| &rc.expr)
+        .chain(plan.order_by.iter().flatten().map(|(expr, _)| expr))
+        .chain(
+            window
+                .functions
+                .iter()
+                .flat_map(|function| function.args.iter().chain(function.filter.iter())),
+        );
+    for expr in exprs {
+        walk_expr(expr, &mut |expr: &'a Expr| -> Result<WalkControl> {
+            match expr {
+                Expr::Column { table, .. } | Expr::RowId { table, .. } => {
+                    if plan
+                        .table_references
+                        .find_joined_table_by_internal_id(*table)
+                        .is_some()
+                        && !sources
+                            .iter()
+                            .any(|source| exprs_are_equivalent(source, expr))
+                    {
+                        sources.push(expr);
+                    }
+                }
+                _ if is_window_function_call(expr) => return Ok(WalkControl::SkipChildren),
+                _ => {}
+            }
+            Ok(WalkControl::Continue)
+        })?;
+    }
+    Ok(sources)
+}
+
+/// The collation of a PARTITION BY or ORDER BY term of a window, like for the ORDER BY clause.
+fn window_key_collation(
+    expr: &Expr,
+    referenced_tables: &TableReferences,
+) -> Result<Option<CollationSeq>> {
+    match expr {
+        Expr::Collate(_, collation_name) => CollationSeq::new(collation_name).map(Some),
+        Expr::Column { table, column, .. } => {
+            let table = referenced_tables.find_table_by_internal_id(*table).unwrap();
+            let Some(table_column) = table.get_column_at(*column) else {
+                crate::bail_parse_error!("column index out of bounds");
+            };
+            Ok(table_column.collation)
+        }
+        _ => Ok(Some(CollationSeq::default())),
+    }
+}
+
+fn buffer_column_count(window: &Window, source_count: usize) -> usize {
+    source_count + 2 + range_key_order(window).is_some() as usize
+}
+
+// Metadata for computing window functions
+#[derive(Debug)]
+pub struct WindowMetadata {
+    /// The sorter that orders the rows of the main loop by partition and then by the ORDER BY
+    /// terms of the window. Its rows are the PARTITION BY terms, the ORDER BY terms and the
+    /// buffered expressions.
+    pub sort_cursor: usize,
+    /// Cursor of the pseudo table the sorted rows are read from
+    pub pseudo_cursor: usize,
+    /// Register where the sorter data is inserted and later retrieved from
+    pub reg_sorter_data: usize,
+    pub sorter_column_count: usize,
+    /// The ephemeral table the rows of a partition are buffered in. A row is stored with its
+    /// buffered expressions, the number of the first row of its peer group, the number of its
+    /// peer group and, for RANGE offsets, its ORDER BY term, and keyed by a rowid that keeps
+    /// increasing across partitions.
+    pub buffer_cursor: usize,
+    pub buffer_table: Rc<BTreeTable>,
+}
+
+/// Initialize resources needed for computing window functions
+pub fn init_window(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx,
+    plan: &SelectPlan,
+) -> Result<()> {
+    let window = plan.window.as_ref().expect("window must exist");
+    let source_count = collect_window_sources(plan, window)?.len();
+    let key_exprs = window
+        .partition_by
+        .iter()
+        .chain(window.order_by.iter().map(|(expr, _)| expr));
+    let collations = key_exprs
+        .map(|expr| window_key_collation(expr, &plan.table_references))
+        .collect::<Result<Vec<_>>>()?;
+    let order = window
+        .partition_by
+        .iter()
+        .map(|_| SortOrder::Asc)
+        .chain(window.order_by.iter().map(|(_, order)| *order))
+        .collect::<Vec<_>>();
+    let sorter_column_count = order.len() + source_count;
+
+    let sort_cursor = program.alloc_cursor_id(CursorType::Sorter);
+    program.emit_insn(Insn::SorterOpen {
+        cursor_id: sort_cursor,
+        columns: sorter_column_count,
+        order,
+        collations,
+    });
+    let pseudo_cursor = program.alloc_cursor_id(CursorType::Pseudo(PseudoCursorType {
+        column_count: sorter_column_count,
+    }));
+
+    let buffer_column = || Column {
+        name: None,
+        ty: Type::Blob,
+        ty_str: "BLOB".to_string(),
+        is_rowid_alias: false,
+        primary_key: false,
+        notnull: false,
+        notnull_conflict_clause: None,
+        default: None,
+        unique: false,
+        unique_conflict_clause: None,
+        collation: None,
+        hidden: false,
+        generated: None,
+    };
+    let buffer_table = Rc::new(BTreeTable {
+        root_page: 0, // Not relevant for ephemeral table definition
+        name: "window_buffer".to_string(),
+        has_rowid: true,
+        primary_key_columns: vec![],
+        columns: (0..buffer_column_count(window, source_count))
+            .map(|_| buffer_column())
+            .collect(),
+        is_strict: false,
+        unique_sets: None,
+        check_constraints: vec![],
+        primary_key_conflict_clause: None,
+        has_autoincrement: false,
+    });
+    let buffer_cursor = program.alloc_cursor_id(CursorType::BTreeTable(buffer_table.clone()));
+    program.emit_insn(Insn::OpenEphemeral {
+        cursor_id: buffer_cursor,
+        is_table: true,
+    });
+
+    t_ctx.meta_window = Some(WindowMetadata {
+        sort_cursor,
+        pseudo_cursor,
+        reg_sorter_data: program.alloc_register(),
+        sorter_column_count,
+        buffer_cursor,
+        buffer_table,
+    });
+    Ok(())
+}
+
+/// Emits the bytecode for inserting a row of the main loop into the window sorter.
+pub fn window_sorter_insert(
+    program: &mut ProgramBuilder,
+    t_ctx: &TranslateCtx,
+    plan: &SelectPlan,
+) -> Result<()> {
+    let window = plan.window.as_ref().expect("window must exist");
+    let sources = collect_window_sources(plan, window)?;
+    let WindowMetadata {
+        sort_cursor,
+        reg_sorter_data,
+        sorter_column_count,
+        ..
+    } = t_ctx
+        .meta_window
+        .as_ref()
+        .expect("window metadata must exist");
+    let start_reg = program.alloc_registers(*sorter_column_count);
+    let exprs = window
+        .partition_by
+        .iter()
+        .chain(window.order_by.iter().map(|(expr, _)| expr))
+        .chain(sources);
+    for (i, expr) in exprs.enumerate() {
+        translate_expr(
+            program,
+            Some(&plan.table_references),
+            expr,
+            start_reg + i,
+            &t_ctx.resolver,
+        )?;
+    }
+    sorter_insert(
+        program,
+        start_reg,
+        *sorter_column_count,
+        *sort_cursor,
+        *reg_sorter_data,
+    );
+    Ok(())
+}
+
+/// Registers used while the window functions of the rows of a partition are computed.
+struct PartitionRegisters {
+    /// Rowid of the buffered row before the first row of the partition
+    reg_base: usize,
+    /// Number of rows in the partition
+    reg_row_count: usize,
+    /// Number of the current row, from 1
+    reg_row: usize,
+    /// Number of the first row of the current row's peer group
+    reg_peer_start: usize,
+    /// Number of the current row's peer group, from 1
+    reg_peer_group: usize,
+    /// Number of the last row of the current row's peer group
+    reg_peer_end: usize,
+    /// The peer group `reg_peer_end` was found for
+    reg_peer_end_group: usize,
+    reg_one: usize,
+    /// Start of the registers holding the buffered expressions of the row the buffer cursor
+    /// was last moved to
+    reg_sources: usize,
+    source_count: usize,
+    buffer_cursor: usize,
+    /// The order of the ORDER BY term, if it is buffered for RANGE offsets
+    range_key_order: Option<SortOrder>,
+}
+
+impl PartitionRegisters {
+    /// Moves the buffer cursor to the row numbered `reg_row_number`, and reads its buffered
+    /// expressions. Jumps to `label_not_found` if there is no such row.
+    fn emit_read_row(
+        &self,
+        program: &mut ProgramBuilder,
+        reg_row_number: usize,
+        label_not_found: BranchOffset,
+    ) {
+        let reg_rowid = program.alloc_register();
+        program.emit_insn(Insn::Add {
+            lhs: self.reg_base,
+            rhs: reg_row_number,
+            dest: reg_rowid,
+        });
+        program.emit_insn(Insn::SeekRowid {
+            cursor_id: self.buffer_cursor,
+            src_reg: reg_rowid,
+            target_pc: label_not_found,
+        });
+        for i in 0..self.source_count {
+            program.emit_column(self.buffer_cursor, i, self.reg_sources + i);
+        }
+    }
+
+    /// Computes the number of the row at `bound` of the frame of the current row into `dest`.
+    /// `reg_range` is where the search for a RANGE offset bound starts.
+    fn emit_frame_bound(
+        &self,
+        program: &mut ProgramBuilder,
+        frame: &WindowFrame,
+        bound: WindowFrameBound,
+        is_start: bool,
+        reg_range: usize,
+        dest: usize,
+    ) {
+        match bound {
+            WindowFrameBound::UnboundedPreceding => program.emit_int(1, dest),
+            WindowFrameBound::UnboundedFollowing => program.emit_insn(Insn::Copy {
+                src_reg: self.reg_row_count,
+                dst_reg: dest,
+                extra_amount: 0,
+            }),
+            WindowFrameBound::CurrentRow => {
+                // In RANGE mode, the current row stands for all of its peers.
+                let src_reg = match (frame.mode, is_start) {
+                    (ast::FrameMode::Range, true) => self.reg_peer_start,
+                    (ast::FrameMode::Range, false) => self.reg_peer_end,
+                    _ => self.reg_row,
+                };
+                program.emit_insn(Insn::Copy {
+                    src_reg,
+                    dst_reg: dest,
+                    extra_amount: 0,
+                });
+            }
+            WindowFrameBound::Preceding(_) | WindowFrameBound::Following(_)
+                if frame.mode == ast::FrameMode::Range =>
+            {
+                self.emit_range_bound(program, bound, is_start, reg_range, dest);
+            }
+            WindowFrameBound::Preceding(offset) | WindowFrameBound::Following(offset) => {
+                program.emit_int(offset as i64, dest);
+                if matches!(bound, WindowFrameBound::Preceding(_)) {
+                    program.emit_insn(Insn::Subtract {
+                        lhs: self.reg_row,
+                        rhs: dest,
+                        dest,
+                    });
+                } else {
+                    program.emit_insn(Insn::Add {
+                        lhs: self.reg_row,
+                        rhs: dest,
+                        dest,
+                    });
+                }
+            }
+        }
+    }
+
+    /// Computes the number of the row at a RANGE `bound` with an offset into `dest`: the first
+    /// row whose ORDER BY term is not before the term of the current row moved by the offset, or
+    /// the last row whose term is not after it. Like in SQLite, a NULL term stands for the peers
+    /// of the current row, and a text or blob term is not moved. As the bound can't move back
+    /// for the following rows, the search continues from the row found for the previous one,
+    /// in `reg_range`.
+    fn emit_range_bound(
+        &self,
+        program: &mut ProgramBuilder,
+        bound: WindowFrameBound,
+        is_start: bool,
+        reg_range: usize,
+        dest: usize,
+    ) {
+        let order = self
+            .range_key_order
+            .expect("the ORDER BY term must be buffered for RANGE offsets");
+        let (WindowFrameBound::Preceding(offset) | WindowFrameBound::Following(offset)) = bound
+        else {
+            unreachable!("emit_range_bound called on a bound without an offset");
+        };
+        let reg_key = program.alloc_register();
+        let reg_bound = program.alloc_register();
+        let reg_offset = program.alloc_register();
+        let reg_text = program.alloc_register();
+        let reg_next_row = program.alloc_register();
+        let reg_next_key = program.alloc_register();
+        let label_not_moved = program.allocate_label();
+        let label_null = program.allocate_label();
+        let label_scan = program.allocate_label();
+        let label_found = program.allocate_label();
+        let label_done = program.allocate_label();
+
+        self.emit_read_range_key(program, self.reg_row, reg_key, label_null);
+        program.emit_insn(Insn::IsNull {
+            reg: reg_key,
+            target_pc: label_null,
+        });
+        program.emit_insn(Insn::Copy {
+            src_reg: reg_key,
+            dst_reg: reg_bound,
+            extra_amount: 0,
+        });
+        // Text and blobs sort after numbers, and after the empty string.
+        program.emit_insn(Insn::String8 {
+            value: String::new(),
+            dest: reg_text,
+        });
+        program.emit_insn(Insn::Ge {
+            lhs: reg_key,
+            rhs: reg_text,
+            target_pc: label_not_moved,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        if offset.fract() == 0.0 && offset <= i64::MAX as f64 {
+            program.emit_int(offset as i64, reg_offset);
+        } else {
+            program.emit_insn(Insn::Real {
+                value: offset,
+                dest: reg_offset,
+            });
+        }
+        // PRECEDING rows have smaller terms in ascending order, and larger ones in descending
+        // order.
+        let preceding = matches!(bound, WindowFrameBound::Preceding(_));
+        program.emit_insn(if preceding == (order == SortOrder::Asc) {
+            Insn::Subtract {
+                lhs: reg_key,
+                rhs: reg_offset,
+                dest: reg_bound,
+            }
+        } else {
+            Insn::Add {
+                lhs: reg_key,
+                rhs: reg_offset,
+                dest: reg_bound,
+            }
+        });
+        program.preassign_label_to_next_insn(label_not_moved);
+
+        // NULL sorts before any other value, so it is before the bound in ascending order and
+        // after it in descending order.
+        program.preassign_label_to_next_insn(label_scan);
+        if is_start {
+            // Skip the rows before the bound.
+            program.emit_insn(Insn::Gt {
+                lhs: reg_range,
+                rhs: self.reg_row_count,
+                target_pc: label_found,
+                flags: CmpInsFlags::default(),
+                collation: None,
+            });
+            self.emit_read_range_key(program, reg_range, reg_next_key, label_found);
+            program.emit_insn(if order == SortOrder::Asc {
+                Insn::Ge {
+                    lhs: reg_next_key,
+                    rhs: reg_bound,
+                    target_pc: label_found,
+                    flags: CmpInsFlags::default(),
+                    collation: None,
+                }
+            } else {
+                Insn::Le {
+                    lhs: reg_next_key,
+                    rhs: reg_bound,
+                    target_pc: label_found,
+                    flags: CmpInsFlags::default().jump_if_null(),
+                    collation: None,
+                }
+            });
+            program.emit_insn(Insn::Add {
+                lhs: reg_range,
+                rhs: self.reg_one,
+                dest: reg_range,
+            });
+        } else {
+            // Take the rows that are not after the bound.
+            program.emit_insn(Insn::Add {
+                lhs: reg_range,
+                rhs: self.reg_one,
+                dest: reg_next_row,
+            });
+            program.emit_insn(Insn::Gt {
+                lhs: reg_next_row,
+                rhs: self.reg_row_count,
+                target_pc: label_found,
+                flags: CmpInsFlags::default(),
+                collation: None,
+            });
+            self.emit_read_range_key(program, reg_next_row, reg_next_key, label_found);
+            program.emit_insn(if order == SortOrder::Asc {
+                Insn::Gt {
+                    lhs: reg_next_key,
+                    rhs: reg_bound,
+                    target_pc: label_found,
+                    flags: CmpInsFlags::default(),
+                    collation: None,
+                }
+            } else {
+                Insn::Lt {
+                    lhs: reg_next_key,
+                    rhs: reg_bound,
+                    target_pc: label_found,
+                    flags: CmpInsFlags::default().jump_if_null(),
+                    collation: None,
+                }
+            });
+            program.emit_insn(Insn::Copy {
+                src_reg: reg_next_row,
+                dst_reg: reg_range,
+                extra_amount: 0,
+            });
+        }
+        program.emit_insn(Insn::Goto {
+            target_pc: label_scan,
+        });
+        program.preassign_label_to_next_insn(label_found);
+        program.emit_insn(Insn::Copy {
+            src_reg: reg_range,
+            dst_reg: dest,
+            extra_amount: 0,
+        });
+        program.emit_insn(Insn::Goto {
+            target_pc: label_done,
+        });
+
+        program.preassign_label_to_next_insn(label_null);
+        program.emit_insn(Insn::Copy {
+            src_reg: if is_start {
+                self.reg_peer_start
+            } else {
+                self.reg_peer_end
+            },
+            dst_reg: dest,
+            extra_amount: 0,
+        });
+        program.preassign_label_to_next_insn(label_done);
+    }
+
+    /// Reads the buffered ORDER BY term of the row numbered `reg_row_number` into `dest`.
+    fn emit_read_range_key(
+        &self,
+        program: &mut ProgramBuilder,
+        reg_row_number: usize,
+        dest: usize,
+        label_not_found: BranchOffset,
+    ) {
+        let reg_rowid = program.alloc_register();
+        program.emit_insn(Insn::Add {
+            lhs: self.reg_base,
+            rhs: reg_row_number,
+            dest: reg_rowid,
+        });
+        program.emit_insn(Insn::SeekRowid {
+            cursor_id: self.buffer_cursor,
+            src_reg: reg_rowid,
+            target_pc: label_not_found,
+        });
+        program.emit_column(self.buffer_cursor, self.source_count + 2, dest);
+    }
+
+    /// Computes the numbers of the first and the last row of the frame of the current row,
+    /// limited to the rows of the partition, unless `with_start` is false, in which case only the
+    /// last one is. The frame is empty if the first is after the last.
+    fn emit_frame(
+        &self,
+        program: &mut ProgramBuilder,
+        frame: &WindowFrame,
+        function_regs: &WindowFunctionRegisters,
+        with_start: bool,
+    ) {
+        let reg_frame_start = function_regs.reg_frame_start;
+        let reg_frame_end = function_regs.reg_frame_end;
+        if with_start {
+            self.emit_frame_bound(
+                program,
+                frame,
+                frame.start,
+                true,
+                function_regs.reg_range_start,
+                reg_frame_start,
+            );
+            let label_in_partition = program.allocate_label();
+            program.emit_insn(Insn::Ge {
+                lhs: reg_frame_start,
+                rhs: self.reg_one,
+                target_pc: label_in_partition,
+                flags: CmpInsFlags::default(),
+                collation: None,
+            });
+            program.emit_int(1, reg_frame_start);
+            program.preassign_label_to_next_insn(label_in_partition);
+        }
+        self.emit_frame_bound(
+            program,
+            frame,
+            frame.end,
+            false,
+            function_regs.reg_range_end,
+            reg_frame_end,
+        );
+        let label_in_partition = program.allocate_label();
+        program.emit_insn(Insn::Le {
+            lhs: reg_frame_end,
+            rhs: self.reg_row_count,
+            target_pc: label_in_partition,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.emit_insn(Insn::Copy {
+            src_reg: self.reg_row_count,
+            dst_reg: reg_frame_end,
+            extra_amount: 0,
+        });
+        program.preassign_label_to_next_insn(label_in_partition);
+    }
+
+    /// Finds the number of the last peer of the current row, unless it was already found for
+    /// another row of its peer group.
+    fn emit_peer_end(&self, program: &mut ProgramBuilder) {
+        let label_found = program.allocate_label();
+        let label_scan = program.allocate_label();
+        let label_scan_end = program.allocate_label();
+        program.emit_insn(Insn::Eq {
+            lhs: self.reg_peer_group,
+            rhs: self.reg_peer_end_group,
+            target_pc: label_found,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.emit_insn(Insn::Copy {
+            src_reg: self.reg_row,
+            dst_reg: self.reg_peer_end,
+            extra_amount: 0,
+        });
+        let reg_next_row = program.alloc_register();
+        let reg_rowid = program.alloc_register();
+        let reg_next_peer_group = program.alloc_register();
+        program.preassign_label_to_next_insn(label_scan);
+        program.emit_insn(Insn::Add {
+            lhs: self.reg_peer_end,
+            rhs: self.reg_one,
+            dest: reg_next_row,
+        });
+        program.emit_insn(Insn::Gt {
+            lhs: reg_next_row,
+            rhs: self.reg_row_count,
+            target_pc: label_scan_end,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.emit_insn(Insn::Add {
+            lhs: self.reg_base,
+            rhs: reg_next_row,
+            dest: reg_rowid,
+        });
+        program.emit_insn(Insn::SeekRowid {
+            cursor_id: self.buffer_cursor,
+            src_reg: reg_rowid,
+            target_pc: label_scan_end,
+        });
+        program.emit_column(
+            self.buffer_cursor,
+            self.source_count + 1,
+            reg_next_peer_group,
+        );
+        program.emit_insn(Insn::Ne {
+            lhs: reg_next_peer_group,
+            rhs: self.reg_peer_group,
+            target_pc: label_scan_end,
+            flags: CmpInsFlags::default(),
+            collation: None,
+        });
+        program.emit_insn(Insn::Copy {
+            src_reg: reg_next_row,
+            dst_reg: self.reg_peer_end,
+            extra_amount: 0,
+        });
+        program.emit_insn(Insn::Goto {
+            target_pc: label_scan,
+        });
+        program.preassign_label_to_next_insn(label_scan_end);
+        program.emit_insn(Insn::Copy {
+            src_reg: self.reg_peer_group,
+            dst_reg: self.reg_peer_end_group,
+            extra_amount: 0,
+        });
+        program.preassign_label_to_next_insn(label_found);
+    }
+}
+
+/// Registers of a single window function.
+struct WindowFunctionRegisters {
+    /// The arguments that are evaluated on the current row, e.g. the offset and default value
+    /// of lag() and lead(), or the argument of ntile()
+    reg_current_row_args: usize,
+    /// For aggregates that are computed incrementally, the accumulator and the numbers of the
+    /// first and the last row that were added to it
+    reg_acc: usize,
+    reg_acc_start: usize,
+    reg_acc_end: usize,
+    reg_frame_start: usize,
+    reg_frame_end: usize,
+    /// For RANGE offsets, the first and the last row of the frame of the previous row
+    reg_range_start: usize,
+    reg_range_end: usize,
+    /// Number of the row the function reads its value from
+    reg_target_row: usize,
+}
+
+/// Whether the aggregate window function is computed incrementally: when its frame starts at
+/// the start of the partition, the frame of a row only adds rows to the frame of the previous one,
+/// and when it has an inverse, the rows that leave the frame can be removed from the accumulator.
+fn is_incremental(function: &WindowFunction) -> bool {
+    matches!(function.func, WindowFunc::Agg(_))
+        && (function.frame.start == WindowFrameBound::UnboundedPreceding || has_inverse(function))
+}
+
+/// Whether the aggregate window function was created with `create_window_function`, which gives
+/// it an inverse.
+fn has_inverse(function: &WindowFunction) -> bool {
+    matches!(&function.func, WindowFunc::Agg(AggFunc::External(func)) if func.is_window())
+}
+
+/// Emits the bytecode for computing the window functions once the main loop has finished:
+/// the rows of the window sorter are buffered one partition at a time, and when a partition is
+/// complete, the window functions are computed for each of its rows, which are then emitted as
+/// result rows or inserted into the ORDER BY sorter.
+pub fn emit_window<'a>(
+    program: &mut ProgramBuilder,
+    t_ctx: &mut TranslateCtx<'a>,
+    plan: &'a SelectPlan,
+) -> Result<()> {
+    let window = plan.window.as_ref().expect("window must exist");
+    let sources = collect_window_sources(plan, window)?;
+    let WindowMetadata {
+        sort_cursor,
+        pseudo_cursor,
+        reg_sorter_data,
+        sorter_column_count,
+        buffer_cursor,
+        ref buffer_table,
+    } = *t_ctx
+        .meta_window
+        .as_ref()
+        .expect("window metadata must exist");
+    let buffer_table_name = buffer_table.name.clone();
+    let partition_len = window.partition_by.len();
+    let order_len = window.order_by.len();
+    let key_len = partition_len + order_len;
+    let source_count = sources.len();
+
+    let label_sort_loop_start = program.allocate_label();
+    let label_sort_loop_end = program.allocate_label();
+    let label_subrtn_partition = program.allocate_label();
+    let label_window_end = program.allocate_label();
+
+    let regs = PartitionRegisters {
+        reg_base: program.alloc_register(),
+        reg_row_count: program.alloc_register(),
+        reg_row: program.alloc_register(),
+        reg_peer_start: program.alloc_register(),
+        reg_peer_group: program.alloc_register(),
+        reg_peer_end: program.alloc_register(),
+        reg_peer_end_group: program.alloc_register(),
+        reg_one: program.alloc_register(),
+        reg_sources: program.alloc_registers(source_count),
+        source_count,
+        buffer_cursor,
+        range_key_order: range_key_order(window),
+    };
+    let reg_keys = program.alloc_registers(key_len);
+    let reg_prev_keys = program.alloc_registers(key_len);
+    let buffer_column_count = buffer_column_count(window, source_count);
+    let reg_buffered_row = program.alloc_registers(buffer_column_count);
+    let reg_record = program.alloc_register();
+    let reg_rowid = program.alloc_register();
+    let reg_subrtn_partition_return_offset = program.alloc_register();
+    program.emit_int(0, regs.reg_base);
+    program.emit_int(0, regs.reg_row_count);
+    program.emit_int(0, regs.reg_peer_group);
+    program.emit_int(1, regs.reg_one);
+
+    program.emit_insn(Insn::OpenPseudo {
+        cursor_id: pseudo_cursor,
+        content_reg: reg_sorter_data,
+        num_fields: sorter_column_count,
+    });
+    program.emit_insn(Insn::SorterSort {
+        cursor_id: sort_cursor,
+        pc_if_empty: label_sort_loop_end,
+    });
+    program.preassign_label_to_next_insn(label_sort_loop_start);
+    program.emit_insn(Insn::SorterData {
+        cursor_id: sort_cursor,
+        dest_reg: reg_sorter_data,
+        pseudo_cursor,
+    });
+    for i in 0..key_len {
+        program.emit_column(pseudo_cursor, i, reg_keys + i);
+    }
+
+    // A row with different PARTITION BY terms than the previous one starts a new partition,
+    // so the window functions are computed for the rows of the previous one.
+    if partition_len > 0 {
+        let label_new_partition = program.allocate_label();
+        let label_next_partition = program.allocate_label();
+        let label_same_partition = program.allocate_label();
+        program.emit_insn(Insn::IfNot {
+            reg: regs.reg_row_count,
+            target_pc: label_new_partition,
+            jump_if_null: false,
+        });
+        program.emit_insn(Insn::Compare {
+            start_reg_a: reg_keys,
+            start_reg_b: reg_prev_keys,
+            count: partition_len,
+            collation: None,
+        });
+        program.emit_insn(Insn::Jump {
+            target_pc_lt: label_next_partition,
+            target_pc_eq: label_same_partition,
+            target_pc_gt: label_next_partition,
+        });
+        program.preassign_label_to_next_insn(label_next_partition);
+        program.emit_insn(Insn::Gosub {
+            target_pc: label_subrtn_partition,
+            return_reg: reg_subrtn_partition_return_offset,
+        });
+        program.preassign_label_to_next_insn(label_new_partition);
+        program.emit_insn(Insn::Copy {
+            src_reg: reg_keys,
+            dst_reg: reg_prev_keys,
+            extra_amount: partition_len - 1,
+        });
+        program.preassign_label_to_next_insn(label_same_partition);
+    }
+    program.emit_insn(Insn::Add {
+        lhs: regs.reg_row_count,
+        rhs: regs.reg_one,
+        dest: regs.reg_row_count,
+    });
+
+    // A row with different ORDER BY terms than the previous one starts a new peer group.
+    // Without ORDER BY, all the rows of a partition are peers.
+    let label_new_peer_group = program.allocate_label();
+    let label_same_peer_group = program.allocate_label();
+    if order_len > 0 {
+        program.emit_insn(Insn::IfNot {
+            reg: regs.reg_peer_group,
+            target_pc: label_new_peer_group,
+            jump_if_null: false,
+        });
+        program.emit_insn(Insn::Compare {
+            start_reg_a: reg_keys + partition_len,
+            start_reg_b: reg_prev_keys + partition_len,
+            count: order_len,
+            collation: None,
+        });
+        program.emit_insn(Insn::Jump {
+            target_pc_lt: label_new_peer_group,
+            target_pc_eq: label_same_peer_group,
+            target_pc_gt: label_new_peer_group,
+        });
+    } else {
+        program.emit_insn(Insn::If {
+            reg: regs.reg_peer_group,
+            target_pc: label_same_peer_group,
+            jump_if_null: false,
+        });
+    }
+    program.preassign_label_to_next_insn(label_new_peer_group);
+    program.emit_insn(Insn::Copy {
+        src_reg: regs.reg_row_count,
+        dst_reg: regs.reg_peer_start,
+        extra_amount: 0,
+    });
+    program.emit_insn(Insn::Add {
+        lhs: regs.reg_peer_group,
+        rhs: regs.reg_one,
+        dest: regs.reg_peer_group,
+    });
+    if order_len > 0 {
+        program.emit_insn(Insn::Copy {
+            src_reg: reg_keys + partition_len,
+            dst_reg: reg_prev_keys + partition_len,
+            extra_amount: order_len - 1,
+        });
+    }
+    program.preassign_label_to_next_insn(label_same_peer_group);
+
+    // Buffer the row.
+    for i in 0..source_count {
+        program.emit_column(pseudo_cursor, key_len + i, reg_buffered_row + i);
+    }
+    program.emit_insn(Insn::Copy {
+        src_reg: regs.reg_peer_start,
+        dst_reg: reg_buffered_row + source_count,
+        extra_amount: 0,
+    });
+    program.emit_insn(Insn::Copy {
+        src_reg: regs.reg_peer_group,
+        dst_reg: reg_buffered_row + source_count + 1,
+        extra_amount: 0,
+    });
+    if regs.range_key_order.is_some() {
+        program.emit_insn(Insn::Copy {
+            src_reg: reg_keys + partition_len,
+            dst_reg: reg_buffered_row + source_count + 2,
+            extra_amount: 0,
+        });
+    }
+    program.emit_insn(Insn::MakeRecord {
+        start_reg: reg_buffered_row,
+        count: buffer_column_count,
+        dest_reg: reg_record,
+        index_name: None,
+    });
+    program.emit_insn(Insn::Add {
+        lhs: regs.reg_base,
+        rhs: regs.reg_row_count,
+        dest: reg_rowid,
+    });
+    program.emit_insn(Insn::Insert {
+        cursor: buffer_cursor,
+        key_reg: reg_rowid,
+        record_reg: reg_record,
+        flag: InsertFlags::new().require_seek(),
+        table_name: buffer_table_name,
+    });
+    program.emit_insn(Insn::SorterNext {
+        cursor_id: sort_cursor,
+        pc_if_next: label_sort_loop_start,
+    });
+    program.preassign_label_to_next_insn(label_sort_loop_end);
+
+    // Compute the window functions for the rows of the last partition.
+    program.emit_insn(Insn::IfNot {
+        reg: regs.reg_row_count,
+        target_pc: label_window_end,
+        jump_if_null: false,
+    });
+    program.emit_insn(Insn::Gosub {
+        target_pc: label_subrtn_partition,
+        return_reg: reg_subrtn_partition_return_offset,
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: label_window_end,
+    });
+
+    // Subroutine that computes the window functions for each row of a partition.
+    program.preassign_label_to_next_insn(label_subrtn_partition);
+    let function_regs = window
+        .functions
+        .iter()
+        .map(|function| WindowFunctionRegisters {
+            reg_current_row_args: program.alloc_registers(function.args.len().max(2)),
+            reg_acc: program.alloc_register(),
+            reg_acc_start: program.alloc_register(),
+            reg_acc_end: program.alloc_register(),
+            reg_frame_start: program.alloc_register(),
+            reg_frame_end: program.alloc_register(),
+            reg_range_start: program.alloc_register(),
+            reg_range_end: program.alloc_register(),
+            reg_target_row: program.alloc_register(),
+        })
+        .collect::<Vec<_>>();
+    let reg_functions_start = program.alloc_registers(window.functions.len());
+    for (function, function_regs) in window.functions.iter().zip(function_regs.iter()) {
+        if is_incremental(function) {
+            program.emit_insn(Insn::Null {
+                dest: function_regs.reg_acc,
+                dest_end: None,
+            });
+            program.emit_int(1, function_regs.reg_acc_start);
+            program.emit_int(0, function_regs.reg_acc_end);
+        }
+        if has_range_offset(&function.frame) {
+            program.emit_int(1, function_regs.reg_range_start);
+            program.emit_int(0, function_regs.reg_range_end);
+        }
+    }
+    program.emit_int(0, regs.reg_peer_end_group);
+    program.emit_int(1, regs.reg_row);
+
+    for (i, source) in sources.iter().enumerate() {
+        t_ctx
+            .resolver
+            .expr_to_reg_cache
+            .push((source, regs.reg_sources + i));
+    }
+    for (i, function) in window.functions.iter().enumerate() {
+        t_ctx
+            .resolver
+            .expr_to_reg_cache
+            .push((&function.original_expr, reg_functions_start + i));
+    }
+    t_ctx.resolver.enable_expr_to_reg_cache();
+
+    let label_row_start = program.allocate_label();
+    let label_row_next = program.allocate_label();
+    let label_partition_end = program.allocate_label();
+    program.preassign_label_to_next_insn(label_row_start);
+    program.emit_insn(Insn::Gt {
+        lhs: regs.reg_row,
+        rhs: regs.reg_row_count,
+        target_pc: label_partition_end,
+        flags: CmpInsFlags::default(),
+        collation: None,
+    });
+    regs.emit_read_row(program, regs.reg_row, label_partition_end);
+    program.emit_column(buffer_cursor, source_count, regs.reg_peer_start);
+    program.emit_column(buffer_cursor, source_count + 1, regs.reg_peer_group);
+
+    // The arguments that are evaluated on the current row come first, as computing the
+    // functions moves the buffer cursor to other rows.
+    for (function, function_regs) in window.functions.iter().zip(function_regs.iter()) {
+        let current_row_args = match function.func {
+            WindowFunc::Ntile => &function.args[..],
+            WindowFunc::Lag | WindowFunc::Lead | WindowFunc::NthValue => &function.args[1..],
+            _ => &[],
+        };
+        for (i, arg) in current_row_args.iter().enumerate() {
+            translate_expr(
+                program,
+                Some(&plan.table_references),
+                arg,
+                function_regs.reg_current_row_args + i,
+                &t_ctx.resolver,
+            )?;
+        }
+    }
+    let needs_peer_end = window.functions.iter().any(|function| match function.func {
+        WindowFunc::CumeDist => true,
+        WindowFunc::Agg(_)
+        | WindowFunc::FirstValue
+        | WindowFunc::LastValue
+        | WindowFunc::NthValue => {
+            // A RANGE offset from a NULL term stands for the peers of the current row.
+            function.frame.mode == ast::FrameMode::Range
+                && (function.frame.end == WindowFrameBound::CurrentRow
+                    || has_range_offset(&function.frame))
+        }
+        _ => false,
+    });
+    if needs_peer_end {
+        regs.emit_peer_end(program);
+    }
+
+    for (i, (function, function_regs)) in window
+        .functions
+        .iter()
+        .zip(function_regs.iter())
+        .enumerate()
+    {
+        emit_window_function(
+            program,
+            &t_ctx.resolver,
+            plan,
+            &regs,
+            function,
+            function_regs,
+            reg_functions_start + i,
+        )?;
+    }
+
+    // Read the current row again for the result columns.
+    regs.emit_read_row(program, regs.reg_row, label_row_next);
+    if plan.order_by.is_some() {
+        order_by_sorter_insert(
+            program,
+            &t_ctx.resolver,
+            t_ctx
+                .meta_sort
+                .as_ref()
+                .expect("sort metadata must exist for ORDER BY"),
+            &mut t_ctx.result_column_indexes_in_orderby_sorter,
+            plan,
+        )?;
+    } else {
+        emit_select_result(
+            program,
+            &t_ctx.resolver,
+            plan,
+            Some(label_window_end),
+            Some(label_row_next),
+            t_ctx.reg_nonagg_emit_once_flag,
+            t_ctx.reg_offset,
+            t_ctx.reg_result_cols_start.unwrap(),
+            t_ctx.limit_ctx,
+        )?;
+    }
+    if let Distinctness::Distinct {
+        ctx: Some(distinct_ctx),
+    } = &plan.distinctness
+    {
+        program.preassign_label_to_next_insn(distinct_ctx.label_on_conflict);
+    }
+    program.preassign_label_to_next_insn(label_row_next);
+    program.emit_insn(Insn::Add {
+        lhs: regs.reg_row,
+        rhs: regs.reg_one,
+        dest: regs.reg_row,
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: label_row_start,
+    });
+
+    // The rows of the next partition are buffered after the rows of this one.
+    program.preassign_label_to_next_insn(label_partition_end);
+    program.emit_insn(Insn::Add {
+        lhs: regs.reg_base,
+        rhs: regs.reg_row_count,
+        dest: regs.reg_base,
+    });
+    program.emit_int(0, regs.reg_row_count);
+    program.emit_int(0, regs.reg_peer_group);
+    program.emit_insn(Insn::Return {
+        return_reg: reg_subrtn_partition_return_offset,
+        can_fallthrough: false,
+    });
+    program.preassign_label_to_next_insn(label_window_end);
+
+    Ok(())
+}
+
+/// Emits the bytecode for computing a window function for the current row into `dest`.
+fn emit_window_function(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    plan: &SelectPlan,
+    regs: &PartitionRegisters,
+    function: &WindowFunction,
+    function_regs: &WindowFunctionRegisters,
+    dest: usize,
+) -> Result<()> {
+    let copy = |program: &mut ProgramBuilder, src_reg: usize| {
+        program.emit_insn(Insn::Copy {
+            src_reg,
+            dst_reg: dest,
+            extra_amount: 0,
+        });
+    };
+    match &function.func {
+        WindowFunc::RowNumber => copy(program, regs.reg_row),
+        WindowFunc::Rank => copy(program, regs.reg_peer_start),
+        WindowFunc::DenseRank => copy(program, regs.reg_peer_group),
+        WindowFunc::PercentRank => {
+            // (rank - 1) / (partition rows - 1), or 0 for a partition of a single row
+            let label_done = program.allocate_label();
+            let reg_numerator = program.alloc_register();
+            let reg_denominator = program.alloc_register();
+            program.emit_insn(Insn::Real { value: 0.0, dest });
+            program.emit_insn(Insn::Le {
+                lhs: regs.reg_row_count,
+                rhs: regs.reg_one,
+                target_pc: label_done,
+                flags: CmpInsFlags::default(),
+                collation: None,
+            });
+            program.emit_insn(Insn::Subtract {
+                lhs: regs.reg_peer_start,
+                rhs: regs.reg_one,
+                dest: reg_numerator,
+            });
+            program.emit_insn(Insn::Subtract {
+                lhs: regs.reg_row_count,
+                rhs: regs.reg_one,
+                dest: reg_denominator,
+            });
+            emit_real_division(program, reg_numerator, reg_denominator, dest);
+            program.preassign_label_to_next_insn(label_done);
+        }
+        WindowFunc::CumeDist => {
+            // (last peer number) / (partition rows)
+            emit_real_division(program, regs.reg_peer_end, regs.reg_row_count, dest);
+        }
+        WindowFunc::Ntile => {
+            // The rows are split in ntile groups, where the first (rows % ntile) groups have one
+            // more row than the others.
+            let reg_ntile = function_regs.reg_current_row_args;
+            emit_positive_argument_check(
+                program,
+                regs,
+                reg_ntile,
+                "argument of ntile must be a positive integer",
+            );
+            let reg_size = program.alloc_register();
+            let reg_large_groups = program.alloc_register();
+            let reg_large_size = program.alloc_register();
+            let reg_row_index = program.alloc_register();
+            let reg_large_rows = program.alloc_register();
+            program.emit_insn(Insn::Divide {
+                lhs: regs.reg_row_count,
+                rhs: reg_ntile,
+                dest: reg_size,
+            });
+            program.emit_insn(Insn::Remainder {
+                lhs: regs.reg_row_count,
+                rhs: reg_ntile,
+                dest: reg_large_groups,
+            });
+            program.emit_insn(Insn::Add {
+                lhs: reg_size,
+                rhs: regs.reg_one,
+                dest: reg_large_size,
+            });
+            program.emit_insn(Insn::Subtract {
+                lhs: regs.reg_row,
+                rhs: regs.reg_one,
+                dest: reg_row_index,
+            });
+            program.emit_insn(Insn::Multiply {
+                lhs: reg_large_groups,
+                rhs: reg_large_size,
+                dest: reg_large_rows,
+            });
+            let label_small_group = program.allocate_label();
+            let label_done = program.allocate_label();
+            program.emit_insn(Insn::Ge {
+                lhs: reg_row_index,
+                rhs: reg_large_rows,
+                target_pc: label_small_group,
+                flags: CmpInsFlags::default(),
+                collation: None,
+            });
+            program.emit_insn(Insn::Divide {
+                lhs: reg_row_index,
+                rhs: reg_large_size,
+                dest,
+            });
+            program.emit_insn(Insn::Goto {
+                target_pc: label_done,
+            });
+            program.preassign_label_to_next_insn(label_small_group);
+            program.emit_insn(Insn::Subtract {
+                lhs: reg_row_index,
+                rhs: reg_large_groups,
+                dest: reg_row_index,
+            });
+            program.emit_insn(Insn::Divide {
+                lhs: reg_row_index,
+                rhs: reg_size,
+                dest,
+            });
+            program.preassign_label_to_next_insn(label_done);
+            program.emit_insn(Insn::Add {
+                lhs: dest,
+                rhs: regs.reg_one,
+                dest,
+            });
+        }
+        WindowFunc::Lag | WindowFunc::Lead => {
+            let reg_offset = function_regs.reg_current_row_args;
+            let reg_default = function_regs.reg_current_row_args + 1;
+            if function.args.len() < 2 {
+                program.emit_int(1, reg_offset);
+            }
+            if function.args.len() < 3 {
+                program.emit_insn(Insn::Null {
+                    dest: reg_default,
+                    dest_end: None,
+                });
+            }
+            program.emit_insn(if function.func == WindowFunc::Lag {
+                Insn::Subtract {
+                    lhs: regs.reg_row,
+                    rhs: reg_offset,
+                    dest: function_regs.reg_target_row,
+                }
+            } else {
+                Insn::Add {
+                    lhs: regs.reg_row,
+                    rhs: reg_offset,
+                    dest: function_regs.reg_target_row,
+                }
+            });
+            emit_row_value(
+                program,
+                resolver,
+                plan,
+                regs,
+                function_regs.reg_target_row,
+                None,
+                &function.args[0],
+                Some(reg_default),
+                dest,
+            )?;
+        }
+        WindowFunc::FirstValue | WindowFunc::LastValue | WindowFunc::NthValue => {
+            regs.emit_frame(program, &function.frame, function_regs, true);
+            let src_reg = match function.func {
+                WindowFunc::FirstValue => function_regs.reg_frame_start,
+                WindowFunc::LastValue => function_regs.reg_frame_end,
+                _ => {
+                    // The nth row of the frame
+                    let reg_nth = function_regs.reg_current_row_args;
+                    emit_positive_argument_check(
+                        program,
+                        regs,
+                        reg_nth,
+                        "second argument to nth_value must be a positive integer",
+                    );
+                    program.emit_insn(Insn::Add {
+                        lhs: function_regs.reg_frame_start,
+                        rhs: reg_nth,
+                        dest: function_regs.reg_target_row,
+                    });
+                    program.emit_insn(Insn::Subtract {
+                        lhs: function_regs.reg_target_row,
+                        rhs: regs.reg_one,
+                        dest: function_regs.reg_target_row,
+                    });
+                    function_regs.reg_target_row
+                }
+            };
+            if src_reg != function_regs.reg_target_row {
+                program.emit_insn(Insn::Copy {
+                    src_reg,
+                    dst_reg: function_regs.reg_target_row,
+                    extra_amount: 0,
+                });
+            }
+            emit_row_value(
+                program,
+                resolver,
+                plan,
+                regs,
+                function_regs.reg_target_row,
+                Some(function_regs),
+                &function.args[0],
+                None,
+                dest,
+            )?;
+        }
+        WindowFunc::Agg(agg_func) => {
+            emit_window_aggregate(
+                program,
+                resolver,
+                plan,
+                regs,
+                function,
+                agg_func,
+                function_regs,
+                dest,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Divides `lhs` by `rhs` as real numbers.
+fn emit_real_division(program: &mut ProgramBuilder, lhs: usize, rhs: usize, dest: usize) {
+    let reg_real = program.alloc_register();
+    program.emit_insn(Insn::Real {
+        value: 1.0,
+        dest: reg_real,
+    });
+    program.emit_insn(Insn::Multiply {
+        lhs,
+        rhs: reg_real,
+        dest: reg_real,
+    });
+    program.emit_insn(Insn::Divide {
+        lhs: reg_real,
+        rhs,
+        dest,
+    });
+}
+
+fn emit_positive_argument_check(
+    program: &mut ProgramBuilder,
+    regs: &PartitionRegisters,
+    reg: usize,
+    description: &str,
+) {
+    let label_positive = program.allocate_label();
+    program.emit_insn(Insn::Ge {
+        lhs: reg,
+        rhs: regs.reg_one,
+        target_pc: label_positive,
+        flags: CmpInsFlags::default(),
+        collation: None,
+    });
+    program.emit_insn(Insn::Halt {
+        err_code: SQLITE_ERROR,
+        description: description.to_string(),
+        on_error: ast::ResolveType::Abort,
+    });
+    program.preassign_label_to_next_insn(label_positive);
+}
+
+/// Evaluates `expr` on the row numbered `reg_target_row` into `dest`. If there is no such row
+/// in the partition, or in the frame if `frame_regs` is given, `dest` is set to the value of
+/// `reg_default`, or to NULL.
+#[allow(clippy::too_many_arguments)]
+fn emit_row_value(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    plan: &SelectPlan,
+    regs: &PartitionRegisters,
+    reg_target_row: usize,
+    frame_regs: Option<&WindowFunctionRegisters>,
+    expr: &Expr,
+    reg_default: Option<usize>,
+    dest: usize,
+) -> Result<()> {
+    let label_default = program.allocate_label();
+    let label_done = program.allocate_label();
+    let (reg_first, reg_last) = match frame_regs {
+        Some(frame_regs) => (frame_regs.reg_frame_start, frame_regs.reg_frame_end),
+        None => (regs.reg_one, regs.reg_row_count),
+    };
+    program.emit_insn(Insn::Lt {
+        lhs: reg_target_row,
+        rhs: reg_first,
+        target_pc: label_default,
+        flags: CmpInsFlags::default().jump_if_null(),
+        collation: None,
+    });
+    program.emit_insn(Insn::Gt {
+        lhs: reg_target_row,
+        rhs: reg_last,
+        target_pc: label_default,
+        flags: CmpInsFlags::default().jump_if_null(),
+        collation: None,
+    });
+    regs.emit_read_row(program, reg_target_row, label_default);
+    translate_expr(program, Some(&plan.table_references), expr, dest, resolver)?;
+    program.emit_insn(Insn::Goto {
+        target_pc: label_done,
+    });
+    program.preassign_label_to_next_insn(label_default);
+    match reg_default {
+        Some(reg_default) => program.emit_insn(Insn::Copy {
+            src_reg: reg_default,
+            dst_reg: dest,
+            extra_amount: 0,
+        }),
+        None => program.emit_insn(Insn::Null {
+            dest,
+            dest_end: None,
+        }),
+    }
+    program.preassign_label_to_next_insn(label_done);
+    Ok(())
+}
+
+/// Computes an aggregate over the frame of the current row into `dest`.
+#[allow(clippy::too_many_arguments)]
+fn emit_window_aggregate(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    plan: &SelectPlan,
+    regs: &PartitionRegisters,
+    function: &WindowFunction,
+    agg_func: &AggFunc,
+    function_regs: &WindowFunctionRegisters,
+    dest: usize,
+) -> Result<()> {
+    let aggregate = Aggregate {
+        func: agg_func.clone(),
+        args: function.args.clone(),
+        original_expr: function.original_expr.clone(),
+        distinctness: Distinctness::NonDistinct,
+    };
+    let incremental = is_incremental(function);
+    let inverse = has_inverse(function);
+    // An incremental aggregate continues from the last row added to its accumulator, any other
+    // is computed again over the whole frame.
+    let (reg_next_row, reg_acc) = if incremental {
+        (function_regs.reg_acc_end, function_regs.reg_acc)
+    } else {
+        (function_regs.reg_frame_start, dest)
+    };
+    regs.emit_frame(
+        program,
+        &function.frame,
+        function_regs,
+        !incremental || inverse,
+    );
+    if inverse {
+        emit_window_aggregate_inverse(
+            program,
+            resolver,
+            plan,
+            regs,
+            function,
+            agg_func,
+            function_regs,
+        )?;
+    }
+    if incremental {
+        program.emit_insn(Insn::Add {
+            lhs: function_regs.reg_acc_end,
+            rhs: regs.reg_one,
+            dest: reg_next_row,
+        });
+    } else {
+        program.emit_insn(Insn::Null {
+            dest: reg_acc,
+            dest_end: None,
+        });
+    }
+
+    let label_step_start = program.allocate_label();
+    let label_step_next = program.allocate_label();
+    let label_step_end = program.allocate_label();
+    program.preassign_label_to_next_insn(label_step_start);
+    program.emit_insn(Insn::Gt {
+        lhs: reg_next_row,
+        rhs: function_regs.reg_frame_end,
+        target_pc: label_step_end,
+        flags: CmpInsFlags::default(),
+        collation: None,
+    });
+    regs.emit_read_row(program, reg_next_row, label_step_end);
+    if let Some(filter) = &function.filter {
+        let reg_filter = program.alloc_register();
+        translate_expr(
+            program,
+            Some(&plan.table_references),
+            filter,
+            reg_filter,
+            resolver,
+        )?;
+        program.emit_insn(Insn::IfNot {
+            reg: reg_filter,
+            target_pc: label_step_next,
+            jump_if_null: true,
+        });
+    }
+    translate_aggregation_step(
+        program,
+        &plan.table_references,
+        &aggregate,
+        reg_acc,
+        resolver,
+    )?;
+    program.preassign_label_to_next_insn(label_step_next);
+    program.emit_insn(Insn::Add {
+        lhs: reg_next_row,
+        rhs: regs.reg_one,
+        dest: reg_next_row,
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: label_step_start,
+    });
+    program.preassign_label_to_next_insn(label_step_end);
+
+    if incremental {
+        // The rows up to the end of the frame are in the accumulator, which is finalized in a
+        // copy so that the following rows can be added to it. The state of an aggregate with an
+        // inverse can't be copied, but it has a value that leaves the state as it is.
+        program.emit_insn(Insn::Subtract {
+            lhs: reg_next_row,
+            rhs: regs.reg_one,
+            dest: function_regs.reg_acc_end,
+        });
+        if inverse {
+            program.emit_insn(Insn::AggValue {
+                acc_reg: reg_acc,
+                dest,
+                func: agg_func.clone(),
+            });
+            return Ok(());
+        }
+        program.emit_insn(Insn::Copy {
+            src_reg: reg_acc,
+            dst_reg: dest,
+            extra_amount: 0,
+        });
+    }
+    program.emit_insn(Insn::AggFinal {
+        register: dest,
+        func: agg_func.clone(),
+    });
+    Ok(())
+}
+
+/// Removes the rows before the start of the frame of the current row from the accumulator of an
+/// aggregate with an inverse. If they were all removed, the rows up to the start of the frame
+/// are skipped, so that the following rows are added from there.
+fn emit_window_aggregate_inverse(
+    program: &mut ProgramBuilder,
+    resolver: &Resolver,
+    plan: &SelectPlan,
+    regs: &PartitionRegisters,
+    function: &WindowFunction,
+    agg_func: &AggFunc,
+    function_regs: &WindowFunctionRegisters,
+) -> Result<()> {
+    let label_inverse_start = program.allocate_label();
+    let label_inverse_next = program.allocate_label();
+    let label_acc_empty = program.allocate_label();
+    let label_inverse_end = program.allocate_label();
+    program.preassign_label_to_next_insn(label_inverse_start);
+    program.emit_insn(Insn::Ge {
+        lhs: function_regs.reg_acc_start,
+        rhs: function_regs.reg_frame_start,
+        target_pc: label_inverse_end,
+        flags: CmpInsFlags::default(),
+        collation: None,
+    });
+    program.emit_insn(Insn::Gt {
+        lhs: function_regs.reg_acc_start,
+        rhs: function_regs.reg_acc_end,
+        target_pc: label_acc_empty,
+        flags: CmpInsFlags::default(),
+        collation: None,
+    });
+    regs.emit_read_row(program, function_regs.reg_acc_start, label_inverse_end);
+    // The rows the FILTER clause left out were not added.
+    if let Some(filter) = &function.filter {
+        let reg_filter = program.alloc_register();
+        translate_expr(
+            program,
+            Some(&plan.table_references),
+            filter,
+            reg_filter,
+            resolver,
+        )?;
+        program.emit_insn(Insn::IfNot {
+            reg: reg_filter,
+            target_pc: label_inverse_next,
+            jump_if_null: true,
+        });
+    }
+    let reg_args = program.alloc_registers(function.args.len().max(1));
+    for (i, arg) in function.args.iter().enumerate() {
+        translate_expr(
+            program,
+            Some(&plan.table_references),
+            arg,
+            reg_args + i,
+            resolver,
+        )?;
+    }
+    program.emit_insn(Insn::AggInverse {
+        acc_reg: function_regs.reg_acc,
+        col: reg_args,
+        func: agg_func.clone(),
+    });
+    program.preassign_label_to_next_insn(label_inverse_next);
+    program.emit_insn(Insn::Add {
+        lhs: function_regs.reg_acc_start,
+        rhs: regs.reg_one,
+        dest: function_regs.reg_acc_start,
+    });
+    program.emit_insn(Insn::Goto {
+        target_pc: label_inverse_start,
+    });
+    program.preassign_label_to_next_insn(label_acc_empty);
+    program.emit_insn(Insn::Copy {
+        src_reg: function_regs.reg_frame_start,
+        dst_reg: function_regs.reg_acc_start,
+        extra_amount: 0,
+    });
+    program.emit_insn(Insn::Subtract {
+        lhs: function_regs.reg_frame_start,
+        rhs: regs.reg_one,
+        dest: function_regs.reg_acc_end,
+    });
+    program.preassign_label_to_next_insn(label_inverse_end);
+    Ok(())
+}
@@ -5,6 +5,7 @@ use turso_sqlite3_parser::ast::SortOrder;
 
 use crate::error::LimboError;
 use crate::ext::{ExtValue, ExtValueType};
+use crate::function::AggregateState;
 use crate::pseudo::PseudoCursor;
 use crate::schema::Index;
 use crate::storage::btree::BTreeCursor;
@@ -15,7 +16,9 @@ use crate::vdbe::sorter::Sorter;
 use crate::vdbe::Register;
 use crate::vtab::VirtualTableCursor;
 use crate::{turso_assert, Result};
+use std::cell::RefCell;
 use std::fmt::{Debug, Display};
+use std::rc::Rc;
 
 const MAX_REAL_SIZE: u8 = 15;
 
@@ -311,6 +314,23 @@ impl ExternalAggState {
     }
 }
 
+/// The state of an aggregate function created with [crate::Connection::create_aggregate_function]
+/// for a group of rows.
+#[derive(Clone)]
+pub struct ClosureAggState(pub Rc<RefCell<Box<dyn AggregateState>>>);
+
+impl Debug for ClosureAggState {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("ClosureAggState")
+    }
+}
+
+impl PartialEq for ClosureAggState {
+    fn eq(&self, other: &Self) -> bool {
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
 /// Please use Display trait for all limbo output so we have single origin of truth
 /// When you need value as string:
 /// ---GOOD---
@@ -504,6 +524,7 @@ pub enum AggContext {
     Min(Option<Value>),
     GroupConcat(Value),
     External(ExternalAggState),
+    Closure(ClosureAggState),
 }
 
 const NULL: Value = Value::Null;
@@ -528,6 +549,7 @@ impl AggContext {
             Self::Min(min) => min.as_ref().unwrap_or(&NULL),
             Self::GroupConcat(s) => s,
             Self::External(ext_state) => ext_state.finalized_value.as_ref().unwrap_or(&NULL),
+            Self::Closure(_) => &NULL,
         }
     }
 }
@@ -537,10 +559,10 @@ impl PartialEq<Value> for Value {
         match (self, other) {
             (Self::Integer(int_left), Self::Integer(int_right)) => int_left == int_right,
             (Self::Integer(int_left), Self::Float(float_right)) => {
-                (*int_left as f64) == (*float_right)
+                sqlite_int_float_compare(*int_left, *float_right).is_eq()
             }
             (Self::Float(float_left), Self::Integer(int_right)) => {
-                float_left == (&(*int_right as f64))
+                sqlite_int_float_compare(*int_right, *float_left).is_eq()
             }
             (Self::Float(float_left), Self::Float(float_right)) => float_left == float_right,
             (Self::Integer(_) | Self::Float(_), Self::Text(_) | Self::Blob(_)) => false,
@@ -561,10 +583,10 @@ impl PartialOrd<Value> for Value {
         match (self, other) {
             (Self::Integer(int_left), Self::Integer(int_right)) => int_left.partial_cmp(int_right),
             (Self::Integer(int_left), Self::Float(float_right)) => {
-                (*int_left as f64).partial_cmp(float_right)
+                Some(sqlite_int_float_compare(*int_left, *float_right))
             }
             (Self::Float(float_left), Self::Integer(int_right)) => {
-                float_left.partial_cmp(&(*int_right as f64))
+                Some(sqlite_int_float_compare(*int_right, *float_left).reverse())
             }
             (Self::Float(float_left), Self::Float(float_right)) => {
                 float_left.partial_cmp(float_right)
@@ -770,6 +792,10 @@ pub struct ImmutableRecord {
     //
     // payload is the Vec<u8> but in order to use Register which holds ImmutableRecord as a Value - we store Vec<u8> as Value::Blob
     payload: Value,
+    // The zeros of the zeroblob() values at the end of the record, which follow the payload but
+    // aren't stored in it. Only the btree writes them, everything else reads the record after
+    // expand_zero_tail().
+    zero_tail: usize,
 }
 
 #[derive(PartialEq)]
@@ -849,6 +875,7 @@ impl ImmutableRecord {
     pub fn new(payload_capacity: usize) -> Self {
         Self {
             payload: Value::Blob(Vec::with_capacity(payload_capacity)),
+            zero_tail: 0,
         }
     }
 
@@ -870,9 +897,37 @@ impl ImmutableRecord {
         Self::from_values(registers.into_iter().map(|x| x.get_owned_value()), len)
     }
 
+    /// Like [Self::from_registers], but the zeros of the `zeroblob()` values at the end of the
+    /// record are left out of its payload, so that they are never allocated.
+    pub fn from_registers_with_zero_tail(registers: &[Register]) -> Self {
+        let zero_blobs = registers
+            .iter()
+            .rev()
+            .map_while(|register| match register {
+                Register::ZeroBlob(zero_blob) => Some(zero_blob.len()),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let stored = &registers[..registers.len() - zero_blobs.len()];
+        Self::build(
+            stored.iter().map(|x| x.get_owned_value()),
+            stored.len(),
+            zero_blobs.into_iter().rev(),
+        )
+    }
+
     pub fn from_values<'a>(
         values: impl IntoIterator<Item = &'a Value> + Clone,
         len: usize,
+    ) -> Self {
+        Self::build(values, len, std::iter::empty())
+    }
+
+    /// Builds a record of `values`, followed by blobs of zeros of the sizes in `zero_blobs`.
+    fn build<'a>(
+        values: impl IntoIterator<Item = &'a Value> + Clone,
+        len: usize,
+        zero_blobs: impl Iterator<Item = usize>,
     ) -> Self {
         let mut ref_values = Vec::with_capacity(len);
         let mut serials = Vec::with_capacity(len);
@@ -891,6 +946,14 @@ impl ImmutableRecord {
             size_header += n;
             size_values += value_size;
         }
+        let mut zero_tail = 0;
+        for size in zero_blobs {
+            let serial_type = SerialType::blob(size as u64);
+            let n = write_varint(&mut serial_type_buf[0..], serial_type.into());
+            serials.push((serial_type_buf, n));
+            size_header += n;
+            zero_tail += size;
+        }
 
         let header_size = Record::calc_header_size(size_header);
 
@@ -960,6 +1023,21 @@ impl ImmutableRecord {
         writer.assert_finish_capacity();
         Self {
             payload: Value::Blob(buf),
+            zero_tail,
+        }
+    }
+
+    /// The number of zeros at the end of the record that aren't stored in its payload.
+    pub fn zero_tail(&self) -> usize {
+        self.zero_tail
+    }
+
+    /// Appends the zeros left out of the payload to it.
+    pub fn expand_zero_tail(&mut self) {
+        let zero_tail = std::mem::take(&mut self.zero_tail);
+        if zero_tail > 0 {
+            let payload = self.as_blob_mut();
+            payload.resize(payload.len() + zero_tail, 0);
         }
     }
 
@@ -987,6 +1065,7 @@ impl ImmutableRecord {
 
     pub fn invalidate(&mut self) {
         self.as_blob_mut().clear();
+        self.zero_tail = 0;
     }
 
     pub fn is_invalidated(&self) -> bool {
@@ -1380,10 +1459,10 @@ impl PartialOrd<RefValue> for RefValue {
         match (self, other) {
             (Self::Integer(int_left), Self::Integer(int_right)) => int_left.partial_cmp(int_right),
             (Self::Integer(int_left), Self::Float(float_right)) => {
-                (*int_left as f64).partial_cmp(float_right)
+                Some(sqlite_int_float_compare(*int_left, *float_right))
             }
             (Self::Float(float_left), Self::Integer(int_right)) => {
-                float_left.partial_cmp(&(*int_right as f64))
+                Some(sqlite_int_float_compare(*int_right, *float_left).reverse())
             }
             (Self::Float(float_left), Self::Float(float_right)) => {
                 float_left.partial_cmp(float_right)
@@ -1414,7 +1493,7 @@ impl PartialOrd<RefValue> for RefValue {
     }
 }
 
-fn sqlite_int_float_compare(int_val: i64, float_val: f64) -> std::cmp::Ordering {
+pub(crate) fn sqlite_int_float_compare(int_val: i64, float_val: f64) -> std::cmp::Ordering {
     if float_val.is_nan() {
         return std::cmp::Ordering::Greater;
     }
@@ -3179,6 +3258,36 @@ mod tests {
         assert_eq!(buf.len(), header_length + blob.len());
     }
 
+    #[test]
+    fn test_record_with_zero_tail() {
+        use crate::vdbe::ZeroBlob;
+
+        let registers = [
+            Register::Value(Value::Integer(42)),
+            Register::ZeroBlob(ZeroBlob::new(3)),
+            Register::ZeroBlob(ZeroBlob::new(1000)),
+        ];
+        let mut record = ImmutableRecord::from_registers_with_zero_tail(&registers);
+        assert_eq!(record.zero_tail(), 1003);
+        // The header and the integer, but none of the zeros.
+        assert_eq!(record.get_payload().len(), 6);
+
+        record.expand_zero_tail();
+        assert_eq!(record.zero_tail(), 0);
+        let full = ImmutableRecord::from_registers(&registers, registers.len());
+        assert_eq!(record.get_payload(), full.get_payload());
+        assert_eq!(record.get_values().len(), 3);
+
+        // A zeroblob() followed by another value is stored.
+        let registers = [
+            Register::ZeroBlob(ZeroBlob::new(3)),
+            Register::Value(Value::Integer(42)),
+        ];
+        let record = ImmutableRecord::from_registers_with_zero_tail(&registers);
+        assert_eq!(record.zero_tail(), 0);
+        assert_eq!(record.get_payload()[3..6], [0, 0, 0]);
+    }
+
     #[test]
     fn test_serialize_mixed_types() {
         let text = "test";
@@ -0,0 +1,91 @@
+//! Case mapping for upper(), lower(), the NOCASE collation and LIKE.
+//!
+//! upper() and lower() map the case of every letter the way the Unicode standard defines it. Like
+//! in SQLite, NOCASE and LIKE only know the case of ASCII letters by default; the `unicode`
+//! feature plays the part of SQLite's ICU extension for them. Applications can also change
+//! upper(), lower() and like() for a connection only, by creating functions with these names.
+
+use std::cmp::Ordering;
+
+/// Converts the letters of `text` to uppercase.
+pub fn to_upper(text: &str) -> String {
+    text.to_uppercase()
+}
+
+/// Converts the letters of `text` to lowercase.
+pub fn to_lower(text: &str) -> String {
+    text.to_lowercase()
+}
+
+/// Returns the other cases of the letter `c`, which a case insensitive comparison matches with it.
+/// The cases that take more than one character, like the uppercase "SS" of 'ß', are left out.
+pub fn other_cases(c: char) -> impl Iterator<Item = char> {
+    let (lower, upper) = if cfg!(feature = "unicode") {
+        (single_char(c.to_lowercase()), single_char(c.to_uppercase()))
+    } else {
+        (Some(c.to_ascii_lowercase()), Some(c.to_ascii_uppercase()))
+    };
+    [lower, upper]
+        .into_iter()
+        .flatten()
+        .filter(move |other| *other != c)
+}
+
+fn single_char(mut chars: impl Iterator<Item = char>) -> Option<char> {
+    match (chars.next(), chars.next()) {
+        (Some(c), None) => Some(c),
+        _ => None,
+    }
+}
+
+/// Compares two strings as the NOCASE collation does, as if all their letters were lowercase.
+#[cfg(not(feature = "unicode"))]
+pub fn nocase_cmp(lhs: &str, rhs: &str) -> Ordering {
+    uncased::UncasedStr::new(lhs).cmp(uncased::UncasedStr::new(rhs))
+}
+
+/// Compares two strings as the NOCASE collation does, as if all their letters were lowercase.
+#[cfg(feature = "unicode")]
+pub fn nocase_cmp(lhs: &str, rhs: &str) -> Ordering {
+    lhs.chars()
+        .flat_map(char::to_lowercase)
+        .cmp(rhs.chars().flat_map(char::to_lowercase))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ascii_case() {
+        assert_eq!(nocase_cmp("ABC", "abc"), Ordering::Equal);
+        assert_eq!(nocase_cmp("abc", "ABD"), Ordering::Less);
+        assert_eq!(other_cases('a').collect::<Vec<_>>(), vec!['A']);
+        assert_eq!(other_cases('A').collect::<Vec<_>>(), vec!['a']);
+        assert_eq!(other_cases('1').count(), 0);
+    }
+
+    #[test]
+    fn test_upper_lower() {
+        assert_eq!(to_upper("Limbo 1"), "LIMBO 1");
+        assert_eq!(to_lower("Limbo 1"), "limbo 1");
+        assert_eq!(to_upper("straße é"), "STRASSE É");
+        assert_eq!(to_lower("ÄÖ"), "äö");
+    }
+
+    #[cfg(not(feature = "unicode"))]
+    #[test]
+    fn test_non_ascii_case_is_unknown() {
+        assert_eq!(nocase_cmp("ä", "Ä"), Ordering::Greater);
+        assert_eq!(other_cases('é').count(), 0);
+    }
+
+    #[cfg(feature = "unicode")]
+    #[test]
+    fn test_unicode_case() {
+        assert_eq!(nocase_cmp("ä", "Ä"), Ordering::Equal);
+        assert_eq!(nocase_cmp("Äb", "äc"), Ordering::Less);
+        assert_eq!(other_cases('é').collect::<Vec<_>>(), vec!['É']);
+        assert_eq!(other_cases('ß').count(), 0);
+    }
+}
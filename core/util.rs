@@ -1,16 +1,16 @@
 use crate::translate::expr::WalkControl;
-use crate::types::IOResult;
+use crate::types::{sqlite_int_float_compare, IOResult};
 use crate::IO;
 use crate::{
     schema::{self, Column, Schema, Type},
     translate::{collate::CollationSeq, expr::walk_expr, plan::JoinOrderMember},
     types::{Value, ValueType},
-    LimboError, OpenFlags, Result, Statement, StepResult, SymbolTable,
+    Database, LimboError, OpenFlags, Result, Statement, StepResult, SymbolTable,
 };
 use std::{rc::Rc, sync::Arc};
 use tracing::{instrument, Level};
 use turso_sqlite3_parser::ast::{
-    self, CreateTableBody, Expr, FunctionTail, Literal, UnaryOperator,
+    self, CreateTableBody, Expr, FunctionTail, Literal, Name, UnaryOperator,
 };
 
 pub trait IOExt {
@@ -85,7 +85,7 @@ pub fn parse_schema_rows(
                 StepResult::Row => {
                     let row = rows.row().unwrap();
                     let ty = row.get::<&str>(0)?;
-                    if !["table", "index"].contains(&ty) {
+                    if !["table", "index", "trigger", "view"].contains(&ty) {
                         continue;
                     }
                     match ty {
@@ -142,6 +142,14 @@ pub fn parse_schema_rows(
                                 }
                             }
                         }
+                        "trigger" => {
+                            let sql: &str = row.get::<&str>(4)?;
+                            schema.add_trigger(Arc::new(schema::Trigger::from_sql(sql)?));
+                        }
+                        "view" => {
+                            let sql: &str = row.get::<&str>(4)?;
+                            schema.add_view(Arc::new(schema::View::from_sql(sql)?));
+                        }
                         _ => continue,
                     }
                 }
@@ -157,7 +165,10 @@ pub fn parse_schema_rows(
         }
         for unparsed_sql_from_index in from_sql_indexes {
             if !schema.indexes_enabled() {
-                schema.table_set_has_index(&unparsed_sql_from_index.table_name);
+                schema.table_set_has_index(
+                    &unparsed_sql_from_index.table_name,
+                    unparsed_sql_from_index.root_page,
+                );
             } else {
                 let table = schema
                     .get_btree_table(&unparsed_sql_from_index.table_name)
@@ -172,7 +183,9 @@ pub fn parse_schema_rows(
         }
         for automatic_index in automatic_indices {
             if !schema.indexes_enabled() {
-                schema.table_set_has_index(&automatic_index.0);
+                for (_, root_page) in automatic_index.1 {
+                    schema.table_set_has_index(&automatic_index.0, root_page);
+                }
             } else {
                 let table = schema.get_btree_table(&automatic_index.0).unwrap();
                 let ret_index = schema::Index::automatic_from_primary_key_and_unique(
@@ -189,9 +202,20 @@ pub fn parse_schema_rows(
 }
 
 fn cmp_numeric_strings(num_str: &str, other: &str) -> bool {
-    match (num_str.parse::<f64>(), other.parse::<f64>()) {
+    // Integers are compared exactly, as converting them to floats may round them
+    fn int_eq_float(int: i64, float: &str) -> bool {
+        float
+            .parse::<f64>()
+            .is_ok_and(|float| sqlite_int_float_compare(int, float).is_eq())
+    }
+    match (num_str.parse::<i64>(), other.parse::<i64>()) {
         (Ok(num), Ok(other)) => num == other,
-        _ => num_str == other,
+        (Ok(int), Err(_)) => int_eq_float(int, other),
+        (Err(_), Ok(int)) => int_eq_float(int, num_str),
+        (Err(_), Err(_)) => match (num_str.parse::<f64>(), other.parse::<f64>()) {
+            (Ok(num), Ok(other)) => num == other,
+            _ => num_str == other,
+        },
     }
 }
 
@@ -429,13 +453,14 @@ pub fn exprs_are_equivalent(expr1: &Expr, expr2: &Expr) -> bool {
                             filter_clause: fc2,
                             over_clause: oc2,
                         }),
-                    ) => match ((fc1, fc2), (oc1, oc2)) {
-                        ((Some(fc1), Some(fc2)), (Some(oc1), Some(oc2))) => {
-                            exprs_are_equivalent(fc1, fc2) && oc1 == oc2
-                        }
-                        ((Some(fc1), Some(fc2)), _) => exprs_are_equivalent(fc1, fc2),
-                        _ => false,
-                    },
+                    ) => {
+                        let filters_are_equivalent = match (fc1, fc2) {
+                            (Some(fc1), Some(fc2)) => exprs_are_equivalent(fc1, fc2),
+                            (None, None) => true,
+                            _ => false,
+                        };
+                        filters_are_equivalent && oc1 == oc2
+                    }
                     _ => false,
                 }
         }
@@ -548,9 +573,13 @@ pub fn columns_from_create_table_body(body: &ast::CreateTableBody) -> crate::Res
                 notnull: column_def.constraints.iter().any(|c| {
                     matches!(
                         c.constraint,
-                        turso_sqlite3_parser::ast::ColumnConstraint::NotNull { .. }
+                        turso_sqlite3_parser::ast::ColumnConstraint::NotNull {
+                            nullable: false,
+                            ..
+                        }
                     )
                 }),
+                notnull_conflict_clause: None,
                 ty_str: column_def
                     .col_type
                     .clone()
@@ -569,21 +598,13 @@ pub fn columns_from_create_table_body(body: &ast::CreateTableBody) -> crate::Res
                         turso_sqlite3_parser::ast::ColumnConstraint::Unique(..)
                     )
                 }),
+                unique_conflict_clause: None,
                 collation: column_def
                     .constraints
                     .iter()
                     .find_map(|c| match &c.constraint {
-                        // TODO: see if this should be the correct behavior
-                        // currently there cannot be any user defined collation sequences.
-                        // But in the future, when a user defines a collation sequence, creates a table with it,
-                        // then closes the db and opens it again. This may panic here if the collation seq is not registered
-                        // before reading the columns
                         turso_sqlite3_parser::ast::ColumnConstraint::Collate { collation_name } => {
-                            Some(
-                                CollationSeq::new(collation_name.0.as_str()).expect(
-                                    "collation should have been set correctly in create table",
-                                ),
-                            )
+                            Some(CollationSeq::from_name(collation_name.0.as_str()))
                         }
                         _ => None,
                     }),
@@ -592,6 +613,7 @@ pub fn columns_from_create_table_body(body: &ast::CreateTableBody) -> crate::Res
                     .as_ref()
                     .map(|data_type| data_type.name.as_str().contains("HIDDEN"))
                     .unwrap_or(false),
+                generated: None,
             }
         })
         .collect::<Vec<_>>())
@@ -608,11 +630,9 @@ pub fn can_pushdown_predicate(
     walk_expr(top_level_expr, &mut |expr: &Expr| -> Result<WalkControl> {
         match expr {
             Expr::Column { table, .. } | Expr::RowId { table, .. } => {
-                let join_idx = join_order
-                    .iter()
-                    .position(|t| t.table_id == *table)
-                    .expect("table not found in join_order");
-                can_pushdown &= join_idx <= table_idx;
+                // Tables of outer query scopes are not in the join order, their row is already positioned.
+                let join_idx = join_order.iter().position(|t| t.table_id == *table);
+                can_pushdown &= join_idx.is_none_or(|join_idx| join_idx <= table_idx);
             }
             Expr::FunctionCall { args, name, .. } => {
                 let function = crate::function::Func::resolve_function(
@@ -769,6 +789,25 @@ impl<'a> OpenOptions<'a> {
         Ok(opts)
     }
 
+    /// Opens the in-memory database the options refer to, if they refer to one: a private one,
+    /// or with `cache=shared`, the shared one named after the path.
+    pub fn open_memory(
+        &self,
+        enable_mvcc: bool,
+        enable_indexes: bool,
+    ) -> Result<Option<Arc<Database>>> {
+        if self.path != MEMORY_PATH && self.mode != OpenMode::Memory {
+            return Ok(None);
+        }
+        let db = match self.cache {
+            CacheMode::Shared => {
+                Database::open_shared_memory(&self.path, enable_mvcc, enable_indexes)?
+            }
+            CacheMode::Private => Database::open_memory(enable_mvcc, enable_indexes)?,
+        };
+        Ok(Some(db))
+    }
+
     pub fn get_flags(&self) -> Result<OpenFlags> {
         // Only use modeof if we're in a mode that can create files
         if self.mode != OpenMode::ReadWriteCreate && self.modeof.is_some() {
@@ -872,24 +911,20 @@ pub fn decode_percent(uri: &str) -> String {
 /// because it is no part of the integer prefix. For example, "CAST('123e+5' AS INTEGER)" results in 123, not in 12300000.
 /// The CAST operator understands decimal integers only — conversion of hexadecimal integers stops at the "x" in the "0x" prefix of the hexadecimal integer string and thus result of the CAST is always zero.
 pub fn cast_text_to_integer(text: &str) -> Value {
-    let text = text.trim();
-    if text.is_empty() {
-        return Value::Integer(0);
-    }
-    if let Ok(i) = text.parse::<i64>() {
-        return Value::Integer(i);
-    }
+    let text = text.trim_matches(|c: char| c.is_ascii_whitespace());
     let bytes = text.as_bytes();
-    let mut end = 0;
-    if bytes[0] == b'-' {
-        end = 1;
-    }
-    while end < bytes.len() && bytes[end].is_ascii_digit() {
-        end += 1;
+    let (negative, digits) = match bytes.first() {
+        Some(b'-') => (true, &bytes[1..]),
+        Some(b'+') => (false, &bytes[1..]),
+        _ => (false, bytes),
+    };
+    let mut value: i128 = 0;
+    for digit in digits.iter().take_while(|b| b.is_ascii_digit()) {
+        // Past the range of i64 the result saturates, so the rest of the digits don't matter.
+        value = (value * 10 + (digit - b'0') as i128).min(i64::MAX as i128 + 1);
     }
-    text[..end]
-        .parse::<i64>()
-        .map_or(Value::Integer(0), Value::Integer)
+    let value = if negative { -value } else { value };
+    Value::Integer(value.clamp(i64::MIN as i128, i64::MAX as i128) as i64)
 }
 
 /// When casting a TEXT value to REAL, the longest possible prefix of the value that can be interpreted
@@ -946,7 +981,7 @@ pub fn checked_cast_text_to_numeric(text: &str) -> std::result::Result<Value, ()
 }
 
 fn parse_numeric_str(text: &str) -> Result<(ValueType, &str), ()> {
-    let text = text.trim();
+    let text = text.trim_matches(|c: char| c.is_ascii_whitespace());
     let bytes = text.as_bytes();
 
     if matches!(
@@ -957,14 +992,18 @@ fn parse_numeric_str(text: &str) -> Result<(ValueType, &str), ()> {
     }
 
     let mut end = 0;
+    let mut has_digits = false;
     let mut has_decimal = false;
     let mut has_exponent = false;
-    if bytes[0] == b'-' {
+    if bytes[0] == b'-' || bytes[0] == b'+' {
         end = 1;
     }
     while end < bytes.len() {
         match bytes[end] {
-            b'0'..=b'9' => end += 1,
+            b'0'..=b'9' => {
+                has_digits = true;
+                end += 1;
+            }
             b'.' if !has_decimal && !has_exponent => {
                 has_decimal = true;
                 end += 1;
@@ -980,7 +1019,7 @@ fn parse_numeric_str(text: &str) -> Result<(ValueType, &str), ()> {
             _ => break,
         }
     }
-    if end == 0 || (end == 1 && bytes[0] == b'-') {
+    if !has_digits {
         return Err(());
     }
     // edge case: if it ends with exponent, strip and cast valid digits as float
@@ -1005,6 +1044,17 @@ pub fn cast_text_to_numeric(txt: &str) -> Value {
     checked_cast_text_to_numeric(txt).unwrap_or(Value::Integer(0))
 }
 
+/// Converts `text` to a number if all of it is one, surrounding spaces aside, the way the
+/// INTEGER, REAL and NUMERIC affinities do. Unlike CAST, which is happy with a prefix, a text like
+/// '12abc' is left alone, and so are hexadecimal integers.
+pub fn text_to_numeric_exact(text: &str) -> Option<Value> {
+    let (_, prefix) = parse_numeric_str(text).ok()?;
+    if prefix.len() != text.trim_matches(|c: char| c.is_ascii_whitespace()).len() {
+        return None;
+    }
+    checked_cast_text_to_numeric(prefix).ok()
+}
+
 // Check if float can be losslessly converted to 51-bit integer
 pub fn cast_real_to_integer(float: f64) -> std::result::Result<i64, ()> {
     let i = float as i64;
@@ -1070,7 +1120,6 @@ pub fn parse_string(expr: &Expr) -> Result<String> {
     }
 }
 
-#[allow(unused)]
 pub fn parse_pragma_bool(expr: &Expr) -> Result<bool> {
     const TRUE_VALUES: &[&str] = &["yes", "true", "on"];
     const FALSE_VALUES: &[&str] = &["no", "false", "off"];
@@ -1078,8 +1127,9 @@ pub fn parse_pragma_bool(expr: &Expr) -> Result<bool> {
         if let Value::Integer(x @ (0 | 1)) = number {
             return Ok(x != 0);
         }
-    } else if let Expr::Name(name) = expr {
-        let ident = normalize_ident(&name.0);
+    } else if let Expr::Name(Name(name)) | Expr::Literal(Literal::Keyword(name)) = expr {
+        // ON is a keyword, unlike the other values.
+        let ident = normalize_ident(name);
         if TRUE_VALUES.contains(&ident.as_str()) {
             return Ok(true);
         }
@@ -1154,6 +1204,23 @@ pub mod tests {
         assert!(exprs_are_equivalent(&expr1, &expr2));
     }
 
+    #[test]
+    fn test_large_integer_not_equivalent_to_rounded_real() {
+        let expr1 = Expr::Literal(Literal::Numeric("9007199254740993".to_string()));
+        let expr2 = Expr::Literal(Literal::Numeric("9007199254740992.0".to_string()));
+        assert!(!exprs_are_equivalent(&expr1, &expr2));
+        let expr3 = Expr::Literal(Literal::Numeric("9007199254740992".to_string()));
+        assert!(exprs_are_equivalent(&expr3, &expr2));
+    }
+
+    #[test]
+    fn test_max_integer_not_equivalent_to_two_to_the_63() {
+        let expr1 = Expr::Literal(Literal::Numeric("9223372036854775807".to_string()));
+        let expr2 = Expr::Literal(Literal::Numeric("9223372036854775808.0".to_string()));
+        assert!(!exprs_are_equivalent(&expr1, &expr2));
+        assert!(!exprs_are_equivalent(&expr2, &expr1));
+    }
+
     #[test]
     fn test_subtraction_expressions_not_equivalent() {
         let expr3 = Expr::Binary(
@@ -1678,7 +1745,7 @@ pub mod tests {
         );
         assert_eq!(
             cast_text_to_integer("9223372036854775808"),
-            Value::Integer(0),
+            Value::Integer(i64::MAX),
         );
         assert_eq!(
             cast_text_to_integer("-9223372036854775808"),
@@ -1686,9 +1753,12 @@ pub mod tests {
         );
         assert_eq!(
             cast_text_to_integer("-9223372036854775809"),
-            Value::Integer(0),
+            Value::Integer(i64::MIN),
         );
         assert_eq!(cast_text_to_integer("-"), Value::Integer(0),);
+        assert_eq!(cast_text_to_integer("+12"), Value::Integer(12),);
+        assert_eq!(cast_text_to_integer(" 12e5 "), Value::Integer(12),);
+        assert_eq!(cast_text_to_integer("0x10"), Value::Integer(0),);
     }
 
     #[test]
@@ -1850,6 +1920,8 @@ pub mod tests {
         assert_eq!(parse_numeric_str("-"), Err(()));
         assert_eq!(parse_numeric_str("e10"), Err(()));
         assert_eq!(parse_numeric_str(".e10"), Err(()));
+        assert_eq!(parse_numeric_str("+"), Err(()));
+        assert_eq!(parse_numeric_str("."), Err(()));
     }
 
     #[test]
@@ -1894,6 +1966,17 @@ pub mod tests {
         );
     }
 
+    #[test]
+    fn test_text_to_numeric_exact() {
+        assert_eq!(text_to_numeric_exact(" 12 "), Some(Value::Integer(12)));
+        assert_eq!(text_to_numeric_exact("+1.5"), Some(Value::Float(1.5)));
+        assert_eq!(text_to_numeric_exact("1e2"), Some(Value::Float(100.0)));
+        assert_eq!(text_to_numeric_exact("12abc"), None);
+        assert_eq!(text_to_numeric_exact("1e"), None);
+        assert_eq!(text_to_numeric_exact("0x10"), None);
+        assert_eq!(text_to_numeric_exact(""), None);
+    }
+
     #[test]
     fn test_module_name_basic() {
         let sql = "CREATE VIRTUAL TABLE x USING y;";
@@ -2057,6 +2140,7 @@ pub mod tests {
         assert!(parse_pragma_bool(&Expr::Literal(Literal::Numeric("1".into()))).unwrap(),);
         assert!(parse_pragma_bool(&Expr::Name(Name("true".into()))).unwrap(),);
         assert!(parse_pragma_bool(&Expr::Name(Name("on".into()))).unwrap(),);
+        assert!(parse_pragma_bool(&Expr::Literal(Literal::Keyword("ON".into()))).unwrap(),);
         assert!(parse_pragma_bool(&Expr::Name(Name("yes".into()))).unwrap(),);
 
         assert!(!parse_pragma_bool(&Expr::Literal(Literal::Numeric("0".into()))).unwrap(),);
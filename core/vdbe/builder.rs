@@ -1,4 +1,4 @@
-use std::{cell::Cell, cmp::Ordering, rc::Rc, sync::Arc};
+use std::{cmp::Ordering, rc::Rc, sync::Arc};
 
 use tracing::{instrument, Level};
 use turso_sqlite3_parser::ast::{self, TableInternalId};
@@ -10,9 +10,11 @@ use crate::{
     translate::{
         collate::CollationSeq,
         emitter::TransactionMode,
+        optimizer::like_prefix::LikeBehavior,
         plan::{ResultSetColumn, TableReferences},
     },
-    CaptureDataChangesMode, Connection, Value, VirtualTable,
+    AuthAction, Authorization, Authorizer, CaptureDataChangesMode, Connection, LimboError, Limit,
+    Limits, Result, Value, VirtualTable,
 };
 
 #[derive(Default)]
@@ -34,6 +36,7 @@ impl TableRefIdCounter {
     }
 }
 
+use super::insn::{ChangeCount, CmpInsFlags, InsertFlags, RegisterOrLiteral};
 use super::{BranchOffset, CursorID, Insn, InsnFunction, InsnReference, JumpTarget, Program};
 
 /// A key that uniquely identifies a cursor.
@@ -111,6 +114,35 @@ pub struct ProgramBuilder {
     init_label: BranchOffset,
     start_offset: BranchOffset,
     capture_data_changes_mode: CaptureDataChangesMode,
+    /// What the LIKE and GLOB operators do for the connection the program is built for.
+    like_behavior: LikeBehavior,
+    /// Triggers whose programs are being emitted, innermost last, along with the label
+    /// that RAISE(IGNORE) jumps to from within each of them.
+    trigger_stack: Vec<(String, BranchOffset)>,
+    /// AUTOINCREMENT tables that the program inserts into, along with the sqlite_sequence table.
+    /// Their sequence values are loaded when the program starts and saved when it ends.
+    autoincrement: Option<(Rc<BTreeTable>, Vec<AutoincrementTable>)>,
+    /// The databases other than main that the program uses, and whether it writes to them.
+    /// A transaction is started on each of them along with the one on main.
+    databases: Vec<(usize, bool)>,
+    /// The authorizer of the connection the program is built for, asked about the actions of
+    /// the statements as they are translated.
+    authorizer: Option<Rc<Authorizer>>,
+    /// The limits of the connection the program is built for.
+    limits: Limits,
+    /// The depth of the expression being translated, see [Self::enter_expr].
+    expr_depth: usize,
+    /// The automatic indexes the program builds, as `table(column, ...)`.
+    automatic_indexes: Vec<String>,
+}
+
+/// The registers holding the sqlite_sequence row of an AUTOINCREMENT table while the program runs.
+struct AutoincrementTable {
+    table_name: String,
+    /// The table name, immediately followed by the largest rowid ever used by the table.
+    name_reg: usize,
+    /// The rowid of the sqlite_sequence row, or NULL if the table has none yet.
+    sequence_rowid_reg: usize,
 }
 
 #[derive(Debug, Clone)]
@@ -132,13 +164,15 @@ impl CursorType {
 pub enum QueryMode {
     Normal,
     Explain,
+    ExplainQueryPlan,
 }
 
-impl From<ast::Cmd> for QueryMode {
-    fn from(stmt: ast::Cmd) -> Self {
+impl From<&ast::Cmd> for QueryMode {
+    fn from(stmt: &ast::Cmd) -> Self {
         match stmt {
-            ast::Cmd::ExplainQueryPlan(_) | ast::Cmd::Explain(_) => QueryMode::Explain,
-            _ => QueryMode::Normal,
+            ast::Cmd::Explain(_) => QueryMode::Explain,
+            ast::Cmd::ExplainQueryPlan(_) => QueryMode::ExplainQueryPlan,
+            ast::Cmd::Stmt(_) => QueryMode::Normal,
         }
     }
 }
@@ -153,6 +187,9 @@ impl ProgramBuilder {
     pub fn new(
         query_mode: QueryMode,
         capture_data_changes_mode: CaptureDataChangesMode,
+        like_behavior: LikeBehavior,
+        authorizer: Option<Rc<Authorizer>>,
+        limits: Limits,
         opts: ProgramBuilderOpts,
     ) -> Self {
         Self {
@@ -178,6 +215,14 @@ impl ProgramBuilder {
             init_label: BranchOffset::Placeholder,
             start_offset: BranchOffset::Placeholder,
             capture_data_changes_mode,
+            like_behavior,
+            trigger_stack: Vec::new(),
+            autoincrement: None,
+            databases: Vec::new(),
+            authorizer,
+            limits,
+            expr_depth: 0,
+            automatic_indexes: Vec::new(),
         }
     }
 
@@ -185,6 +230,18 @@ impl ProgramBuilder {
         &self.capture_data_changes_mode
     }
 
+    pub fn like_behavior(&self) -> LikeBehavior {
+        self.like_behavior
+    }
+
+    pub fn add_automatic_index(&mut self, index: String) {
+        self.automatic_indexes.push(index);
+    }
+
+    pub fn automatic_indexes(&self) -> &[String] {
+        &self.automatic_indexes
+    }
+
     pub fn extend(&mut self, opts: &ProgramBuilderOpts) {
         self.insns.reserve(opts.approx_num_insns);
         self.cursor_ref.reserve(opts.num_cursors);
@@ -303,6 +360,20 @@ impl ProgramBuilder {
 
     #[instrument(skip(self), level = Level::DEBUG)]
     pub fn emit_insn(&mut self, insn: Insn) {
+        let database = match &insn {
+            Insn::OpenRead { db, .. } | Insn::ReadCookie { db, .. } => Some((*db, false)),
+            Insn::OpenWrite { db, .. }
+            | Insn::CreateBtree { db, .. }
+            | Insn::Destroy { db, .. }
+            | Insn::SetCookie { db, .. } => Some((*db, true)),
+            _ => None,
+        };
+        if let Some((db, write)) = database.filter(|(db, _)| *db != 0) {
+            match self.databases.iter_mut().find(|(used, _)| *used == db) {
+                Some((_, writes)) => *writes |= write,
+                None => self.databases.push((db, write)),
+            }
+        }
         let function = insn.to_function();
         // This seemingly empty trace here is needed so that a function span is emmited with it
         tracing::trace!("");
@@ -352,6 +423,7 @@ impl ProgramBuilder {
             } else {
                 String::new()
             },
+            on_error: ast::ResolveType::Abort,
         });
     }
 
@@ -363,6 +435,7 @@ impl ProgramBuilder {
         self.emit_insn(Insn::Halt {
             err_code,
             description,
+            on_error: ast::ResolveType::Abort,
         });
     }
 
@@ -604,6 +677,9 @@ impl ProgramBuilder {
                 Insn::IfPos { target_pc, .. } => {
                     resolve(target_pc, "IfPos");
                 }
+                Insn::IncrVacuum { target_pc, .. } => {
+                    resolve(target_pc, "IncrVacuum");
+                }
                 Insn::Next { pc_if_next, .. } => {
                     resolve(pc_if_next, "Next");
                 }
@@ -707,6 +783,18 @@ impl ProgramBuilder {
             .position(|(k, _)| k.as_ref().is_some_and(|k| k.equals(key)))
     }
 
+    /// Returns the index of an index cursor opened for the given table reference, if any.
+    pub fn resolve_index_for_table_reference(
+        &self,
+        table_reference_id: TableInternalId,
+    ) -> Option<Arc<Index>> {
+        self.cursor_ref.iter().find_map(|(key, _)| {
+            key.as_ref()
+                .filter(|key| key.table_reference_id == table_reference_id)
+                .and_then(|key| key.index.clone())
+        })
+    }
+
     pub fn resolve_cursor_id(&self, key: &CursorKey) -> CursorID {
         self.resolve_cursor_id_safe(key)
             .unwrap_or_else(|| panic!("Cursor not found: {key:?}"))
@@ -738,6 +826,209 @@ impl ProgramBuilder {
         self.nested_level -= 1;
     }
 
+    /// Marks the start of the program of the trigger `name`. RAISE(IGNORE) within it jumps
+    /// to `ignore_label`.
+    pub fn push_trigger(&mut self, name: &str, ignore_label: BranchOffset) {
+        self.trigger_stack.push((name.to_string(), ignore_label));
+    }
+
+    /// Marks the end of the program of the innermost trigger.
+    pub fn pop_trigger(&mut self) {
+        self.trigger_stack.pop();
+    }
+
+    /// Whether the program of the trigger `name` is being emitted. Triggers are not recursive,
+    /// so such a trigger must not fire again.
+    pub fn is_trigger_active(&self, name: &str) -> bool {
+        self.trigger_stack.iter().any(|(active, _)| active == name)
+    }
+
+    /// The label RAISE(IGNORE) jumps to, or None outside of a trigger program.
+    pub fn trigger_ignore_label(&self) -> Option<BranchOffset> {
+        self.trigger_stack.last().map(|(_, label)| *label)
+    }
+
+    /// The change counter the rows changed by the statement being emitted count towards: the
+    /// changes of a trigger program only count towards the total changes of the connection.
+    pub fn change_count(&self) -> ChangeCount {
+        if self.trigger_stack.is_empty() {
+            ChangeCount::Statement
+        } else {
+            ChangeCount::Trigger
+        }
+    }
+
+    /// Asks the authorizer whether the statement may perform `action` on the database named
+    /// `database`. Returns whether the action is to be ignored, or an error if it is denied.
+    pub fn authorize(&self, action: AuthAction, database: Option<&str>) -> Result<bool> {
+        let Some(authorizer) = &self.authorizer else {
+            return Ok(false);
+        };
+        let trigger = self.trigger_stack.last().map(|(name, _)| name.as_str());
+        match (**authorizer)(&action, database, trigger) {
+            Authorization::Allow => Ok(false),
+            Authorization::Ignore => Ok(true),
+            Authorization::Deny => Err(LimboError::AuthorizationDenied(match action {
+                AuthAction::Read { table, column } => {
+                    format!("access to {table}.{column} is prohibited")
+                }
+                _ => "not authorized".to_string(),
+            })),
+        }
+    }
+
+    pub fn limit(&self, limit: Limit) -> usize {
+        self.limits.get(limit)
+    }
+
+    /// The number of instructions emitted so far.
+    pub fn insn_count(&self) -> usize {
+        self.insns.len()
+    }
+
+    /// Marks the start of the translation of an expression nested in the ones being translated,
+    /// failing if the expression tree is deeper than allowed. Paired with [Self::leave_expr].
+    pub fn enter_expr(&mut self) -> Result<()> {
+        self.expr_depth += 1;
+        let max_depth = self.limit(Limit::ExprDepth);
+        if self.expr_depth > max_depth {
+            self.expr_depth -= 1;
+            crate::bail_parse_error!("Expression tree is too large (maximum depth {max_depth})");
+        }
+        Ok(())
+    }
+
+    pub fn leave_expr(&mut self) {
+        self.expr_depth -= 1;
+    }
+
+    /// Removes the authorizer, for the statements the program runs internally, like the
+    /// rewrite of the schema by ALTER TABLE. It is put back with [Self::set_authorizer].
+    pub fn take_authorizer(&mut self) -> Option<Rc<Authorizer>> {
+        self.authorizer.take()
+    }
+
+    pub fn set_authorizer(&mut self, authorizer: Option<Rc<Authorizer>>) {
+        self.authorizer = authorizer;
+    }
+
+    /// Returns the register holding the largest rowid ever used by the AUTOINCREMENT table
+    /// `table_name`, to be passed to [Insn::NewRowid]. The register is shared by every statement
+    /// of the program, trigger programs included.
+    pub fn autoincrement_reg(&mut self, table_name: &str, sequence_table: Rc<BTreeTable>) -> usize {
+        if let Some((_, tables)) = &self.autoincrement {
+            if let Some(table) = tables.iter().find(|t| t.table_name == table_name) {
+                return table.name_reg + 1;
+            }
+        }
+        let name_reg = self.alloc_registers(2);
+        let sequence_rowid_reg = self.alloc_register();
+        let (_, tables) = self
+            .autoincrement
+            .get_or_insert_with(|| (sequence_table, Vec::new()));
+        tables.push(AutoincrementTable {
+            table_name: table_name.to_string(),
+            name_reg,
+            sequence_rowid_reg,
+        });
+        name_reg + 1
+    }
+
+    /// Loads the sequence value of each AUTOINCREMENT table from sqlite_sequence.
+    fn emit_autoincrement_load(&mut self) {
+        let Some((sequence_table, tables)) = self.autoincrement.take() else {
+            return;
+        };
+        let cursor_id = self.alloc_cursor_id(CursorType::BTreeTable(sequence_table.clone()));
+        for table in &tables {
+            let seq_reg = table.name_reg + 1;
+            self.emit_string8(table.table_name.clone(), table.name_reg);
+            self.emit_null(table.sequence_rowid_reg, None);
+            self.emit_int(0, seq_reg);
+            self.emit_insn(Insn::OpenRead {
+                cursor_id,
+                root_page: sequence_table.root_page,
+                db: 0,
+            });
+            let loop_start = self.allocate_label();
+            let loop_end = self.allocate_label();
+            let next = self.allocate_label();
+            self.emit_insn(Insn::Rewind {
+                cursor_id,
+                pc_if_empty: loop_end,
+            });
+            self.preassign_label_to_next_insn(loop_start);
+            let name_reg = self.alloc_register();
+            self.emit_column(cursor_id, 0, name_reg);
+            self.emit_insn(Insn::Ne {
+                lhs: table.name_reg,
+                rhs: name_reg,
+                target_pc: next,
+                flags: CmpInsFlags::default().jump_if_null(),
+                collation: Some(CollationSeq::NoCase),
+            });
+            self.emit_insn(Insn::RowId {
+                cursor_id,
+                dest: table.sequence_rowid_reg,
+            });
+            self.emit_column(cursor_id, 1, seq_reg);
+            self.emit_insn(Insn::Goto {
+                target_pc: loop_end,
+            });
+            self.preassign_label_to_next_insn(next);
+            self.emit_insn(Insn::Next {
+                cursor_id,
+                pc_if_next: loop_start,
+            });
+            self.preassign_label_to_next_insn(loop_end);
+            self.emit_insn(Insn::Close { cursor_id });
+        }
+        self.autoincrement = Some((sequence_table, tables));
+    }
+
+    /// Writes the sequence value of each AUTOINCREMENT table back to sqlite_sequence.
+    fn emit_autoincrement_save(&mut self) {
+        let Some((sequence_table, tables)) = self.autoincrement.take() else {
+            return;
+        };
+        let cursor_id = self.alloc_cursor_id(CursorType::BTreeTable(sequence_table.clone()));
+        self.emit_insn(Insn::OpenWrite {
+            cursor_id,
+            root_page: RegisterOrLiteral::Literal(sequence_table.root_page),
+            name: sequence_table.name.clone(),
+            db: 0,
+        });
+        for table in &tables {
+            let has_row = self.allocate_label();
+            self.emit_insn(Insn::NotNull {
+                reg: table.sequence_rowid_reg,
+                target_pc: has_row,
+            });
+            self.emit_insn(Insn::NewRowid {
+                cursor: cursor_id,
+                rowid_reg: table.sequence_rowid_reg,
+                prev_largest_reg: 0,
+            });
+            self.preassign_label_to_next_insn(has_row);
+            let record_reg = self.alloc_register();
+            self.emit_insn(Insn::MakeRecord {
+                start_reg: table.name_reg,
+                count: 2,
+                dest_reg: record_reg,
+                index_name: None,
+            });
+            self.emit_insn(Insn::Insert {
+                cursor: cursor_id,
+                key_reg: table.sequence_rowid_reg,
+                record_reg,
+                flag: InsertFlags::new().require_seek(),
+                table_name: sequence_table.name.clone(),
+            });
+        }
+        self.emit_insn(Insn::Close { cursor_id });
+        self.autoincrement = Some((sequence_table, tables));
+    }
+
     /// Initialize the program with basic setup and return initial metadata and labels
     pub fn prologue(&mut self) {
         if self.nested_level == 0 {
@@ -764,14 +1055,24 @@ impl ProgramBuilder {
     /// "rollback" flag is used to determine if halt should rollback the transaction.
     pub fn epilogue_maybe_rollback(&mut self, txn_mode: TransactionMode, rollback: bool) {
         if self.nested_level == 0 {
+            if !rollback {
+                self.emit_autoincrement_save();
+            }
             self.emit_halt(rollback);
             self.preassign_label_to_next_insn(self.init_label);
 
             match txn_mode {
-                TransactionMode::Read => self.emit_insn(Insn::Transaction { write: false }),
-                TransactionMode::Write => self.emit_insn(Insn::Transaction { write: true }),
+                TransactionMode::Read => self.emit_insn(Insn::Transaction {
+                    db: 0,
+                    write: false,
+                }),
+                TransactionMode::Write => self.emit_insn(Insn::Transaction { db: 0, write: true }),
                 TransactionMode::None => {}
             }
+            for (db, write) in std::mem::take(&mut self.databases) {
+                self.emit_insn(Insn::Transaction { db, write });
+            }
+            self.emit_autoincrement_load();
 
             self.emit_constant_insns();
             self.emit_insn(Insn::Goto {
@@ -820,41 +1121,25 @@ impl ProgramBuilder {
     pub fn emit_column(&mut self, cursor_id: CursorID, column: usize, out: usize) {
         let (_, cursor_type) = self.cursor_ref.get(cursor_id).unwrap();
 
-        use crate::translate::expr::sanitize_string;
-
-        let default = 'value: {
-            let default = match cursor_type {
-                CursorType::BTreeTable(btree) => &btree.columns[column].default,
-                CursorType::BTreeIndex(index) => &index.columns[column].default,
-                _ => break 'value None,
-            };
-
-            let Some(ast::Expr::Literal(ref literal)) = default else {
-                break 'value None;
-            };
-
-            Some(match literal {
-                ast::Literal::Numeric(s) => match Numeric::from(s) {
-                    Numeric::Null => Value::Null,
-                    Numeric::Integer(v) => Value::Integer(v),
-                    Numeric::Float(v) => Value::Float(v.into()),
-                },
-                ast::Literal::Null => Value::Null,
-                ast::Literal::String(s) => Value::Text(sanitize_string(s).into()),
-                ast::Literal::Blob(s) => Value::Blob(
-                    // Taken from `translate_expr`
-                    s.as_bytes()
-                        .chunks_exact(2)
-                        .map(|pair| {
-                            // We assume that sqlite3-parser has already validated that
-                            // the input is valid hex string, thus unwrap is safe.
-                            let hex_byte = std::str::from_utf8(pair).unwrap();
-                            u8::from_str_radix(hex_byte, 16).unwrap()
-                        })
-                        .collect(),
-                ),
-                _ => break 'value None,
-            })
+        let default = match cursor_type {
+            CursorType::BTreeTable(btree) => btree.columns[column].default.as_ref(),
+            CursorType::BTreeIndex(index) => index.columns[column].default.as_ref(),
+            _ => None,
+        }
+        .and_then(constant_default_value);
+
+        // VIRTUAL generated columns are not stored and the records of a WITHOUT ROWID table
+        // start with its PRIMARY KEY, so the column's position in the record may differ from
+        // its position in the table.
+        let column = match cursor_type {
+            CursorType::BTreeTable(btree) => {
+                debug_assert!(
+                    !btree.columns[column].is_virtual_generated(),
+                    "VIRTUAL generated columns must be computed instead of read"
+                );
+                btree.column_record_pos(column)
+            }
+            _ => column,
         };
 
         self.emit_insn(Insn::Column {
@@ -868,7 +1153,6 @@ impl ProgramBuilder {
     pub fn build(mut self, connection: Arc<Connection>, change_cnt_on: bool) -> Program {
         self.resolve_labels();
 
-        self.parameters.list.dedup();
         Program {
             max_registers: self.next_free_register,
             insns: self
@@ -880,10 +1164,54 @@ impl ProgramBuilder {
             comments: self.comments,
             connection,
             parameters: self.parameters,
-            n_change: Cell::new(0),
             change_cnt_on,
             result_columns: self.result_columns,
             table_references: self.table_references,
         }
     }
 }
+
+/// Folds the DEFAULT expression of a column into its value, if it is a constant: a literal,
+/// optionally signed and parenthesized, such as `DEFAULT 'x'`, `DEFAULT -1` or `DEFAULT (5)`.
+pub fn constant_default_value(expr: &ast::Expr) -> Option<Value> {
+    use crate::translate::expr::sanitize_string;
+
+    match expr {
+        ast::Expr::Literal(literal) => Some(match literal {
+            ast::Literal::Numeric(s) => match Numeric::from(s) {
+                Numeric::Null => Value::Null,
+                Numeric::Integer(v) => Value::Integer(v),
+                Numeric::Float(v) => Value::Float(v.into()),
+            },
+            ast::Literal::Null => Value::Null,
+            ast::Literal::String(s) => Value::Text(sanitize_string(s).into()),
+            ast::Literal::Blob(s) => Value::Blob(
+                // Taken from `translate_expr`
+                s.as_bytes()
+                    .chunks_exact(2)
+                    .map(|pair| {
+                        // We assume that sqlite3-parser has already validated that
+                        // the input is valid hex string, thus unwrap is safe.
+                        let hex_byte = std::str::from_utf8(pair).unwrap();
+                        u8::from_str_radix(hex_byte, 16).unwrap()
+                    })
+                    .collect(),
+            ),
+            _ => return None,
+        }),
+        ast::Expr::Parenthesized(exprs) if exprs.len() == 1 => constant_default_value(&exprs[0]),
+        ast::Expr::Unary(ast::UnaryOperator::Positive, expr) => constant_default_value(expr),
+        ast::Expr::Unary(ast::UnaryOperator::Negative, expr) => {
+            match constant_default_value(expr)? {
+                Value::Integer(v) => Some(
+                    v.checked_neg()
+                        .map_or(Value::Float(-(v as f64)), Value::Integer),
+                ),
+                Value::Float(v) => Some(Value::Float(-v)),
+                Value::Null => Some(Value::Null),
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
@@ -4,20 +4,25 @@ use crate::numeric::{NullableInteger, Numeric};
 use crate::storage::btree::{integrity_check, IntegrityCheckError, IntegrityCheckState};
 use crate::storage::database::FileMemoryStorage;
 use crate::storage::page_cache::DumbLruPageCache;
-use crate::storage::pager::CreateBTreeFlags;
+use crate::storage::pager::{AutoVacuumMode, CreateBTreeFlags, DB_STATE_INITIALIZED};
 use crate::storage::sqlite3_ondisk::read_varint;
+use crate::storage::text_encoding;
 use crate::storage::wal::DummyWAL;
 use crate::storage::{self, header_accessor};
+use crate::translate::alter::{rename_column_in_expr, TableRename};
 use crate::translate::collate::CollationSeq;
 use crate::types::{
     compare_immutable, compare_records_generic, ImmutableRecord, SeekResult, Text, TextSubtype,
 };
+use crate::unicode;
 use crate::util::normalize_ident;
-use crate::vdbe::insn::InsertFlags;
+use crate::vdbe::insn::{ChangeCount, InsertFlags};
 use crate::vdbe::registers_to_ref_values;
 use crate::{
     error::{
-        LimboError, SQLITE_CONSTRAINT, SQLITE_CONSTRAINT_NOTNULL, SQLITE_CONSTRAINT_PRIMARYKEY,
+        LimboError, SQLITE_CONSTRAINT, SQLITE_CONSTRAINT_CHECK, SQLITE_CONSTRAINT_DATATYPE,
+        SQLITE_CONSTRAINT_NOTNULL, SQLITE_CONSTRAINT_PRIMARYKEY, SQLITE_CONSTRAINT_TRIGGER,
+        SQLITE_CONSTRAINT_UNIQUE, SQLITE_ERROR,
     },
     ext::ExtValue,
     function::{AggFunc, ExtFunc, MathFunc, MathFuncArity, ScalarFunc, VectorFunc},
@@ -37,16 +42,19 @@ use std::{borrow::BorrowMut, rc::Rc, sync::Arc};
 use crate::{pseudo::PseudoCursor, result::LimboResult};
 
 use crate::{
-    schema::{affinity, Affinity},
+    schema::{affinity, Affinity, BTreeTable, Schema},
     storage::btree::{BTreeCursor, BTreeKey},
 };
 
 use crate::{
     storage::wal::CheckpointResult,
-    types::{AggContext, Cursor, ExternalAggState, IOResult, SeekKey, SeekOp, Value, ValueType},
+    types::{
+        AggContext, ClosureAggState, Cursor, ExternalAggState, IOResult, SeekKey, SeekOp, Value,
+        ValueType,
+    },
     util::{
         cast_real_to_integer, cast_text_to_integer, cast_text_to_numeric, cast_text_to_real,
-        checked_cast_text_to_numeric, parse_schema_rows, RoundToPrecision,
+        checked_cast_text_to_numeric, parse_schema_rows, text_to_numeric_exact, RoundToPrecision,
     },
     vdbe::{
         builder::CursorType,
@@ -55,11 +63,16 @@ use crate::{
     vector::{vector32, vector64, vector_distance_cos, vector_distance_l2, vector_extract},
 };
 
-use crate::{info, BufferPool, MvCursor, OpenFlags, RefValue, Row, StepResult, TransactionState};
+use crate::{
+    info, BufferPool, Connection, MvCursor, OpenFlags, PreUpdate, RefValue, Row, Savepoint,
+    StepResult, TransactionState, UpdateKind,
+};
+
+use crate::vtab::VTabSavepointOp;
 
 use super::{
-    insn::{Cookie, RegisterOrLiteral},
-    CommitState,
+    insn::{Cookie, RegisterOrLiteral, SavepointOp},
+    CommitState, CursorID,
 };
 use fallible_iterator::FallibleIterator;
 use parking_lot::RwLock;
@@ -69,7 +82,7 @@ use turso_sqlite3_parser::ast::fmt::ToTokens;
 use turso_sqlite3_parser::lexer::sql::Parser;
 
 use super::{
-    likeop::{construct_like_escape_arg, exec_glob, exec_like_with_escape},
+    likeop::{construct_like_escape_arg, exec_glob, exec_like_with_escape, push_like_literal},
     sorter::Sorter,
 };
 use regex::{Regex, RegexBuilder};
@@ -86,7 +99,7 @@ use crate::{
     json::jsonb_patch, json::jsonb_remove, json::jsonb_replace, json::jsonb_set,
 };
 
-use super::{make_record, Program, ProgramState, Register};
+use super::{expand_zero_tail, make_record, vacuum, Program, ProgramState, Register, ZeroBlob};
 use crate::{
     bail_constraint_error, must_be_btree_cursor, resolve_ext_path, MvStore, Pager, Result,
 };
@@ -224,6 +237,44 @@ pub fn op_drop_index(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_drop_trigger(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::DropTrigger {
+        db: _,
+        trigger_name,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    program
+        .connection
+        .with_schema_mut(|schema| schema.remove_trigger(trigger_name));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_drop_view(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::DropView { db: _, view_name } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    program
+        .connection
+        .with_schema_mut(|schema| schema.remove_view(view_name));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_remainder(
     program: &Program,
     state: &mut ProgramState,
@@ -306,13 +357,13 @@ pub fn op_checkpoint(
 ) -> Result<InsnFunctionStepResult> {
     let Insn::Checkpoint {
         database: _,
-        checkpoint_mode: _,
+        checkpoint_mode,
         dest,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    let result = program.connection.checkpoint();
+    let result = program.connection.checkpoint(*checkpoint_mode);
     match result {
         Ok(CheckpointResult {
             num_wal_frames: num_wal_pages,
@@ -327,7 +378,12 @@ pub fn op_checkpoint(
             state.registers[*dest + 2] =
                 Register::Value(Value::Integer(num_checkpointed_pages as i64));
         }
-        Err(_err) => state.registers[*dest] = Register::Value(Value::Integer(1)),
+        Err(_err) => {
+            state.registers[*dest] = Register::Value(Value::Integer(1));
+            // The number of frames isn't known when the checkpoint couldn't run to completion.
+            state.registers[*dest + 1] = Register::Value(Value::Integer(-1));
+            state.registers[*dest + 2] = Register::Value(Value::Integer(-1));
+        }
     }
 
     state.pc += 1;
@@ -518,6 +574,23 @@ pub fn op_if_pos(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_mem_max(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::MemMax { dest_reg, src_reg } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let dest = extract_int_value(state.registers[*dest_reg].get_owned_value());
+    let src = extract_int_value(state.registers[*src_reg].get_owned_value());
+    state.registers[*dest_reg] = Register::Value(Value::Integer(dest.max(src)));
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_not_null(
     program: &Program,
     state: &mut ProgramState,
@@ -737,7 +810,8 @@ pub fn op_comparison(
 
     // Apply affinity conversions
     match affinity {
-        Affinity::Numeric | Affinity::Integer => {
+        // Like SQLite, an integer is compared to a real exactly, without converting it first.
+        Affinity::Numeric | Affinity::Integer | Affinity::Real => {
             let lhs_is_text = matches!(lhs_temp_reg.get_owned_value(), Value::Text(_));
             let rhs_is_text = matches!(rhs_temp_reg.get_owned_value(), Value::Text(_));
 
@@ -766,26 +840,6 @@ pub fn op_comparison(
             }
         }
 
-        Affinity::Real => {
-            if matches!(lhs_temp_reg.get_owned_value(), Value::Text(_)) {
-                lhs_converted = apply_numeric_affinity(&mut lhs_temp_reg, false);
-            }
-
-            if matches!(rhs_temp_reg.get_owned_value(), Value::Text(_)) {
-                rhs_converted = apply_numeric_affinity(&mut rhs_temp_reg, false);
-            }
-
-            if let Value::Integer(i) = lhs_temp_reg.get_owned_value() {
-                lhs_temp_reg = Register::Value(Value::Float(*i as f64));
-                lhs_converted = true;
-            }
-
-            if let Value::Integer(i) = rhs_temp_reg.get_owned_value() {
-                rhs_temp_reg = Register::Value(Value::Float(*i as f64));
-                rhs_converted = true;
-            }
-        }
-
         Affinity::Blob => {} // Do nothing for blob affinity.
     }
 
@@ -876,10 +930,12 @@ pub fn op_open_read(
     let Insn::OpenRead {
         cursor_id,
         root_page,
+        db,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
+    let (_, pager) = &database_connection(program, *db, pager)?;
     let (_, cursor_type) = program.cursor_ref.get(*cursor_id).unwrap();
     let mv_cursor = match state.mv_tx_id {
         Some(tx_id) => {
@@ -900,8 +956,8 @@ pub fn op_open_read(
     };
 
     match cursor_type {
-        CursorType::BTreeTable(_) => {
-            let cursor = BTreeCursor::new_table(mv_cursor, pager.clone(), *root_page, num_columns);
+        CursorType::BTreeTable(table) => {
+            let cursor = new_table_cursor(mv_cursor, pager.clone(), *root_page, table);
             cursors
                 .get_mut(*cursor_id)
                 .unwrap()
@@ -934,6 +990,22 @@ pub fn op_open_read(
     Ok(InsnFunctionStepResult::Step)
 }
 
+/// Creates a cursor on the btree of `table`. The btree of a WITHOUT ROWID table is keyed by its
+/// PRIMARY KEY, so its cursor compares keys like an index cursor.
+fn new_table_cursor(
+    mv_cursor: Option<Rc<RefCell<MvCursor>>>,
+    pager: Rc<Pager>,
+    root_page: usize,
+    table: &BTreeTable,
+) -> BTreeCursor {
+    if table.has_rowid {
+        BTreeCursor::new_table(mv_cursor, pager, root_page, table.columns.len())
+    } else {
+        let index = table.without_rowid_index();
+        BTreeCursor::new_index(mv_cursor, pager, root_page, &index, index.columns.len())
+    }
+}
+
 pub fn op_vopen(
     program: &Program,
     state: &mut ProgramState,
@@ -988,8 +1060,7 @@ pub fn op_vcreate(
         vec![]
     };
     let conn = program.connection.clone();
-    let table =
-        crate::VirtualTable::table(Some(&table_name), &module_name, args, &conn.syms.borrow())?;
+    let table = crate::VirtualTable::create(&table_name, &module_name, args, &conn.syms.borrow())?;
     {
         conn.syms
             .borrow_mut()
@@ -1103,6 +1174,16 @@ pub fn op_vupdate(
             )));
         }
     }
+    let conn = &program.connection;
+    let savepoints = conn.savepoints.borrow().len();
+    // Inside an explicit transaction, the changes of the statement can be rolled back alone if
+    // it fails.
+    if !conn.auto_commit.get() && state.vtab_statement_savepoint.is_none() {
+        conn.vtab_savepoint(VTabSavepointOp::Begin, savepoints)?;
+        state.vtab_statement_savepoint = Some(savepoints);
+    }
+    let savepoints = savepoints + state.vtab_statement_savepoint.is_some() as usize;
+    conn.begin_vtab_transaction(virtual_table, savepoints)?;
     let result = virtual_table.update(&argv);
     match result {
         Ok(Some(new_rowid)) => {
@@ -1663,11 +1744,20 @@ pub fn op_type_check(
                 // Handle INTEGER PRIMARY KEY for null as usual (Rowid will be auto-assigned)
                 return Ok(());
             }
-            let col_affinity = col.affinity();
-            let ty_str = col.ty_str.as_str();
-            let applied = apply_affinity_char(reg, col_affinity);
-            let value_type = reg.get_owned_value().value_type();
-            match (ty_str, value_type) {
+            if matches!(reg, Register::Value(Value::Null)) {
+                return Ok(());
+            }
+            // A value is converted to the column's datatype when that loses nothing, like '12'
+            // for an INTEGER column, and ANY columns keep their values as they are.
+            let ty_str = col.ty_str.to_ascii_uppercase();
+            if ty_str != "ANY" {
+                apply_affinity_char(reg, col.affinity());
+            }
+            let value_type = match reg {
+                Register::ZeroBlob(_) => ValueType::Blob,
+                reg => reg.get_owned_value().value_type(),
+            };
+            match (ty_str.as_str(), value_type) {
                 ("INTEGER" | "INT", ValueType::Integer) => {}
                 ("REAL", ValueType::Float) => {}
                 ("BLOB", ValueType::Blob) => {}
@@ -1679,7 +1769,7 @@ pub fn op_type_check(
                     t,
                     &table_reference.name,
                     col.name.as_deref().unwrap_or(""),
-                    SQLITE_CONSTRAINT
+                    SQLITE_CONSTRAINT_DATATYPE
                 ),
             };
             Ok(())
@@ -1705,7 +1795,13 @@ pub fn op_make_record(
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    let record = make_record(&state.registers, start_reg, count);
+    // A record stored as a value of another one, like the rows of the CDC table, is written whole.
+    for reg in *start_reg..*start_reg + *count {
+        expand_zero_tail(&mut state.registers, reg);
+    }
+    let record = ImmutableRecord::from_registers_with_zero_tail(
+        &state.registers[*start_reg..*start_reg + *count],
+    );
     state.registers[*dest_reg] = Register::Record(record);
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
@@ -1799,29 +1895,20 @@ pub fn halt(
     mv_store: Option<&Rc<MvStore>>,
     err_code: usize,
     description: &str,
+    on_error: ast::ResolveType,
 ) -> Result<InsnFunctionStepResult> {
     if err_code > 0 {
-        // invalidate page cache in case of error
-        pager.clear_page_cache();
-    }
-    match err_code {
-        0 => {}
-        SQLITE_CONSTRAINT_PRIMARYKEY => {
-            return Err(LimboError::Constraint(format!(
-                "UNIQUE constraint failed: {description} (19)"
-            )));
-        }
-        SQLITE_CONSTRAINT_NOTNULL => {
-            return Err(LimboError::Constraint(format!(
-                "NOT NULL constraint failed: {description} (19)"
-            )));
-        }
-        _ => {
-            return Err(LimboError::Constraint(format!(
-                "undocumented halt error code {description}"
-            )));
-        }
+        return halt_with_error(
+            program,
+            state,
+            pager,
+            mv_store,
+            err_code,
+            description,
+            on_error,
+        );
     }
+    release_statement_savepoints(program, state, pager)?;
     match program.commit_txn(pager.clone(), state, mv_store, false)? {
         StepResult::Done => Ok(InsnFunctionStepResult::Done),
         StepResult::IO => Ok(InsnFunctionStepResult::IO),
@@ -1831,6 +1918,88 @@ pub fn halt(
     }
 }
 
+/// Fails the statement with the error of `err_code`. Like in SQLite, ABORT leaves it to the
+/// caller of the program to undo the changes of the statement, FAIL keeps the changes made before
+/// the error, committing them outside of an explicit transaction, and ROLLBACK rolls back the
+/// whole transaction, explicit or not.
+fn halt_with_error(
+    program: &Program,
+    state: &mut ProgramState,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+    err_code: usize,
+    description: &str,
+    on_error: ast::ResolveType,
+) -> Result<InsnFunctionStepResult> {
+    match on_error {
+        ast::ResolveType::Fail => {
+            release_statement_savepoints(program, state, pager)?;
+            // Committing may need I/O, after which the Halt runs again and ends up here.
+            match program.commit_txn(pager.clone(), state, mv_store, false)? {
+                StepResult::Done => {}
+                StepResult::IO => return Ok(InsnFunctionStepResult::IO),
+                StepResult::Row => return Ok(InsnFunctionStepResult::Row),
+                StepResult::Interrupt => return Ok(InsnFunctionStepResult::Interrupt),
+                StepResult::Busy => return Ok(InsnFunctionStepResult::Busy),
+            }
+        }
+        ast::ResolveType::Rollback => {
+            // Without the savepoint of the statement to go back to, failing it rolls back the
+            // transaction, which is no longer explicit.
+            state.statement_savepoint = None;
+            state.attached_statement_savepoints.clear();
+            state.vtab_statement_savepoint = None;
+            program.connection.auto_commit.set(true);
+            pager.clear_page_cache();
+        }
+        _ => {
+            // invalidate page cache in case of error, unless only the statement is rolled back
+            if state.statement_savepoint.is_none() {
+                pager.clear_page_cache();
+            }
+        }
+    }
+    Err(constraint_error(err_code, description))
+}
+
+/// Keeps the changes of the statement in the transaction, by releasing the savepoints that would
+/// have undone them had it failed.
+fn release_statement_savepoints(
+    program: &Program,
+    state: &mut ProgramState,
+    pager: &Rc<Pager>,
+) -> Result<()> {
+    if let Some((savepoint, _)) = state.statement_savepoint.take() {
+        pager.release_savepoints(savepoint);
+    }
+    for (db, savepoint, _) in state.attached_statement_savepoints.drain(..) {
+        let (_, pager) = database_connection(program, db, pager)?;
+        pager.release_savepoints(savepoint);
+    }
+    if let Some(savepoint) = state.vtab_statement_savepoint.take() {
+        program
+            .connection
+            .vtab_savepoint(VTabSavepointOp::Release, savepoint)?;
+    }
+    Ok(())
+}
+
+fn constraint_error(err_code: usize, description: &str) -> LimboError {
+    match err_code {
+        SQLITE_ERROR | SQLITE_CONSTRAINT_TRIGGER => LimboError::Constraint(description.to_string()),
+        SQLITE_CONSTRAINT_PRIMARYKEY | SQLITE_CONSTRAINT_UNIQUE => {
+            LimboError::Constraint(format!("UNIQUE constraint failed: {description} (19)"))
+        }
+        SQLITE_CONSTRAINT_NOTNULL => {
+            LimboError::Constraint(format!("NOT NULL constraint failed: {description} (19)"))
+        }
+        SQLITE_CONSTRAINT_CHECK => {
+            LimboError::Constraint(format!("CHECK constraint failed: {description} (19)"))
+        }
+        _ => LimboError::Constraint(format!("undocumented halt error code {description}")),
+    }
+}
+
 pub fn op_halt(
     program: &Program,
     state: &mut ProgramState,
@@ -1841,32 +2010,23 @@ pub fn op_halt(
     let Insn::Halt {
         err_code,
         description,
+        on_error,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
     if *err_code > 0 {
-        // invalidate page cache in case of error
-        pager.clear_page_cache();
-    }
-    match *err_code {
-        0 => {}
-        SQLITE_CONSTRAINT_PRIMARYKEY => {
-            return Err(LimboError::Constraint(format!(
-                "UNIQUE constraint failed: {description} (19)"
-            )));
-        }
-        SQLITE_CONSTRAINT_NOTNULL => {
-            return Err(LimboError::Constraint(format!(
-                "NOTNULL constraint failed: {description} (19)"
-            )));
-        }
-        _ => {
-            return Err(LimboError::Constraint(format!(
-                "undocumented halt error code {description}"
-            )));
-        }
+        return halt_with_error(
+            program,
+            state,
+            pager,
+            mv_store,
+            *err_code,
+            description,
+            *on_error,
+        );
     }
+    release_statement_savepoints(program, state, pager)?;
     let auto_commit = program.connection.auto_commit.get();
     tracing::trace!("op_halt(auto_commit={})", auto_commit);
     if auto_commit {
@@ -1893,18 +2053,42 @@ pub fn op_halt_if_null(
         target_reg,
         err_code,
         description,
+        on_error,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if state.registers[*target_reg].get_owned_value() == &Value::Null {
-        halt(program, state, pager, mv_store, *err_code, description)
+    if matches!(state.registers[*target_reg], Register::Value(Value::Null)) {
+        halt(
+            program,
+            state,
+            pager,
+            mv_store,
+            *err_code,
+            description,
+            *on_error,
+        )
     } else {
         state.pc += 1;
         Ok(InsnFunctionStepResult::Step)
     }
 }
 
+/// Returns the connection and the pager of database `db` of the program: its main database, or
+/// an attached one.
+fn database_connection(
+    program: &Program,
+    db: usize,
+    pager: &Rc<Pager>,
+) -> Result<(Arc<Connection>, Rc<Pager>)> {
+    if db == 0 {
+        return Ok((program.connection.clone(), pager.clone()));
+    }
+    let conn = program.connection.attached_connection(db)?;
+    let pager = conn.pager.borrow().clone();
+    Ok((conn, pager))
+}
+
 pub fn op_transaction(
     program: &Program,
     state: &mut ProgramState,
@@ -1912,10 +2096,10 @@ pub fn op_transaction(
     pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    let Insn::Transaction { write } = insn else {
+    let Insn::Transaction { db, write } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    let conn = program.connection.clone();
+    let (conn, pager) = &database_connection(program, *db, pager)?;
     if *write && conn._db.open_flags.contains(OpenFlags::ReadOnly) {
         return Err(LimboError::ReadOnly);
     }
@@ -1957,11 +2141,17 @@ pub fn op_transaction(
             if let LimboResult::Busy = return_if_io!(pager.begin_read_tx()) {
                 return Ok(InsnFunctionStepResult::Busy);
             }
+            if conn.concurrent.get() && !conn.auto_commit.get() {
+                pager.begin_concurrent_tx();
+            }
         }
 
         if updated && matches!(new_transaction_state, TransactionState::Write { .. }) {
             if let LimboResult::Busy = return_if_io!(pager.begin_write_tx()) {
-                pager.end_read_tx()?;
+                // Keep the read transaction if it was started by an earlier statement.
+                if matches!(current_state, TransactionState::None) {
+                    pager.end_read_tx()?;
+                }
                 tracing::trace!("begin_write_tx busy");
                 return Ok(InsnFunctionStepResult::Busy);
             }
@@ -1969,6 +2159,25 @@ pub fn op_transaction(
         if updated {
             conn.transaction_state.replace(new_transaction_state);
         }
+        // Inside an explicit transaction a failing statement only rolls back its own changes.
+        if *write && !program.connection.auto_commit.get() {
+            if *db == 0 {
+                if state.statement_savepoint.is_none() {
+                    state.statement_savepoint =
+                        Some((pager.open_savepoint(), conn.schema.borrow().clone()));
+                }
+            } else if !state
+                .attached_statement_savepoints
+                .iter()
+                .any(|(attached, ..)| attached == db)
+            {
+                state.attached_statement_savepoints.push((
+                    *db,
+                    pager.open_savepoint(),
+                    conn.schema.borrow().clone(),
+                ));
+            }
+        }
     }
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
@@ -1984,6 +2193,7 @@ pub fn op_auto_commit(
     let Insn::AutoCommit {
         auto_commit,
         rollback,
+        concurrent,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
@@ -2006,12 +2216,15 @@ pub fn op_auto_commit(
         };
 
     if *auto_commit != conn.auto_commit.get() {
+        conn.savepoints.borrow_mut().clear();
         if *rollback {
             // TODO(pere): add rollback I/O logic once we implement rollback journal
             pager.rollback(schema_did_change, &conn)?;
             conn.auto_commit.replace(true);
+            conn.call_rollback_hook();
         } else {
             conn.auto_commit.replace(*auto_commit);
+            conn.concurrent.set(*concurrent);
         }
     } else if !*auto_commit {
         return Err(LimboError::TxError(
@@ -2031,8 +2244,73 @@ pub fn op_auto_commit(
         super::StepResult::IO => Ok(InsnFunctionStepResult::IO),
         super::StepResult::Row => Ok(InsnFunctionStepResult::Row),
         super::StepResult::Interrupt => Ok(InsnFunctionStepResult::Interrupt),
-        super::StepResult::Busy => Ok(InsnFunctionStepResult::Busy),
+        super::StepResult::Busy => {
+            // A concurrent transaction couldn't take the write lock to commit. It stays open so
+            // that COMMIT can be retried.
+            conn.auto_commit.replace(false);
+            Ok(InsnFunctionStepResult::Busy)
+        }
+    }
+}
+
+pub fn op_savepoint(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::Savepoint { op, name } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    if mv_store.is_some() {
+        return Err(LimboError::TxError(
+            "savepoints are not supported with MVCC".to_string(),
+        ));
+    }
+    let conn = program.connection.clone();
+    let mut savepoints = conn.savepoints.borrow_mut();
+    match op {
+        SavepointOp::Begin => {
+            let starts_transaction = conn.auto_commit.get();
+            if starts_transaction {
+                conn.concurrent.set(false);
+            }
+            conn.auto_commit.replace(false);
+            conn.vtab_savepoint(VTabSavepointOp::Begin, savepoints.len())?;
+            savepoints.push(Savepoint {
+                name: name.clone(),
+                schema: conn.schema.borrow().clone(),
+                starts_transaction,
+                pager_savepoint: pager.open_savepoint(),
+            });
+        }
+        SavepointOp::Release | SavepointOp::Rollback => {
+            let Some(index) = savepoints
+                .iter()
+                .rposition(|savepoint| savepoint.name.eq_ignore_ascii_case(name))
+            else {
+                return Err(LimboError::TxError(format!("no such savepoint: {name}")));
+            };
+            if *op == SavepointOp::Release {
+                // Releasing the savepoint that started the transaction commits it when halting.
+                if index == 0 && savepoints[0].starts_transaction {
+                    conn.auto_commit.replace(true);
+                }
+                conn.vtab_savepoint(VTabSavepointOp::Release, index)?;
+                pager.release_savepoints(savepoints[index].pager_savepoint);
+                savepoints.truncate(index);
+            } else {
+                // The savepoint stays open after rolling back to it.
+                conn.vtab_savepoint(VTabSavepointOp::RollbackTo, index)?;
+                pager.rollback_to_savepoint(savepoints[index].pager_savepoint)?;
+                conn.schema.replace(savepoints[index].schema.clone());
+                savepoints.truncate(index + 1);
+            }
+        }
     }
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
 }
 
 pub fn op_goto(
@@ -2540,6 +2818,7 @@ pub fn seek_internal(
                                 };
                             }
                             RecordSource::Packed { record_reg } => {
+                                expand_zero_tail(&mut state.registers, record_reg);
                                 state.seek_state = OpSeekState::Seek {
                                     key: OpSeekKey::IndexKeyFromRegister(record_reg),
                                     op,
@@ -3082,6 +3361,9 @@ pub fn op_agg_step(
                     finalize_fn: *finalize,
                     finalized_value: None,
                 })),
+                ExtFunc::ClosureAggregate { init, .. } => Register::Aggregate(AggContext::Closure(
+                    ClosureAggState(Rc::new(RefCell::new((**init)()))),
+                )),
                 _ => unreachable!("scalar function called in aggregate context"),
             },
         };
@@ -3247,6 +3529,10 @@ pub fn op_agg_step(
                 _ => unreachable!(),
             };
         }
+        AggFunc::External(func) if matches!(func.as_ref(), ExtFunc::ClosureAggregate { .. }) => {
+            let (agg_state, args) = closure_agg_args(state, *acc_reg, *col, func);
+            RefCell::borrow_mut(&agg_state.0).step(&args)?;
+        }
         AggFunc::External(_) => {
             let (step_fn, state_ptr, argc) = {
                 let Register::Aggregate(agg) = &state.registers[*acc_reg] else {
@@ -3369,6 +3655,13 @@ pub fn op_agg_final(
                 let data = acc.to_blob().expect("Should be blob");
                 state.registers[*register] = Register::Value(json_from_raw_bytes_agg(data, true)?);
             }
+            AggFunc::External(_) if matches!(agg, AggContext::Closure(_)) => {
+                let AggContext::Closure(agg_state) = agg else {
+                    unreachable!();
+                };
+                let value = RefCell::borrow_mut(&agg_state.0).finalize()?;
+                state.registers[*register] = Register::Value(value);
+            }
             AggFunc::External(_) => {
                 agg.compute_external()?;
                 let AggContext::External(agg_state) = agg else {
@@ -3389,6 +3682,14 @@ pub fn op_agg_final(
                 AggFunc::Count | AggFunc::Count0 => {
                     state.registers[*register] = Register::Value(Value::Integer(0));
                 }
+                // Like in SQLite, the functions created with create_aggregate_function are
+                // finalized even if no rows were added.
+                AggFunc::External(func) => {
+                    if let ExtFunc::ClosureAggregate { init, .. } = func.as_ref() {
+                        let value = (**init)().finalize()?;
+                        state.registers[*register] = Register::Value(value);
+                    }
+                }
                 _ => {}
             }
         }
@@ -3400,6 +3701,80 @@ pub fn op_agg_final(
     Ok(InsnFunctionStepResult::Step)
 }
 
+/// The state of the aggregate function created with [crate::Connection::create_aggregate_function]
+/// in `acc_reg`, along with the values of the arguments starting at `col`.
+fn closure_agg_args(
+    state: &ProgramState,
+    acc_reg: usize,
+    col: usize,
+    func: &ExtFunc,
+) -> (ClosureAggState, Vec<Value>) {
+    let argc = func
+        .agg_args()
+        .expect("aggregate must have a number of arguments");
+    let args = state.registers[col..col + argc]
+        .iter()
+        .map(|reg| reg.get_owned_value().clone())
+        .collect();
+    match &state.registers[acc_reg] {
+        Register::Aggregate(AggContext::Closure(agg_state)) => (agg_state.clone(), args),
+        other => panic!("Unexpected value {other:?} at register {acc_reg}"),
+    }
+}
+
+pub fn op_agg_inverse(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::AggInverse { acc_reg, col, func } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let AggFunc::External(func) = func else {
+        unreachable!("AggInverse on built-in aggregate {func:?}")
+    };
+    let (agg_state, args) = closure_agg_args(state, *acc_reg, *col, func);
+    RefCell::borrow_mut(&agg_state.0).inverse(&args)?;
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_agg_value(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::AggValue {
+        acc_reg,
+        dest,
+        func,
+    } = insn
+    else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let AggFunc::External(func) = func else {
+        unreachable!("AggValue on built-in aggregate {func:?}")
+    };
+    let value = match &state.registers[*acc_reg] {
+        Register::Aggregate(AggContext::Closure(agg_state)) => {
+            RefCell::borrow(&agg_state.0).value()?
+        }
+        // No rows were added yet.
+        Register::Value(Value::Null) => match func.as_ref() {
+            ExtFunc::ClosureAggregate { init, .. } => (**init)().value()?,
+            _ => Value::Null,
+        },
+        other => panic!("Unexpected value {other:?} in AggValue"),
+    };
+    state.registers[*dest] = Register::Value(value);
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_sorter_open(
     program: &Program,
     state: &mut ProgramState,
@@ -3416,15 +3791,11 @@ pub fn op_sorter_open(
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    let cache_size = program.connection.get_cache_size();
-    // Set the buffer size threshold to be roughly the same as the limit configured for the page-cache.
+    // Set the buffer size threshold to be roughly the same as the limit configured for the page-cache,
+    // or the spill size configured with `PRAGMA cache_spill` if it is larger.
     let page_size = header_accessor::get_page_size(pager)
         .unwrap_or(storage::sqlite3_ondisk::DEFAULT_PAGE_SIZE) as usize;
-    let max_buffer_size_bytes = if cache_size < 0 {
-        (cache_size.abs() * 1024) as usize
-    } else {
-        (cache_size as usize) * page_size
-    };
+    let max_buffer_size_bytes = program.connection.sorter_buffer_size(page_size);
     let cursor = Sorter::new(
         order,
         collations
@@ -3494,6 +3865,7 @@ pub fn op_sorter_insert(
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
+    expand_zero_tail(&mut state.registers, *record_reg);
     {
         let mut cursor = state.get_cursor(*cursor_id);
         let cursor = cursor.as_sorter_mut();
@@ -3716,8 +4088,13 @@ pub fn op_function(
             }
             JsonFunc::JsonValid => {
                 let json_value = &state.registers[*start_reg];
+                let flags = if arg_count > 1 {
+                    Some(state.registers[*start_reg + 1].get_owned_value())
+                } else {
+                    None
+                };
                 state.registers[*dest] =
-                    Register::Value(is_json_valid(json_value.get_owned_value()));
+                    Register::Value(is_json_valid(json_value.get_owned_value(), flags)?);
             }
             JsonFunc::JsonPatch => {
                 assert_eq!(arg_count, 2);
@@ -3952,19 +4329,26 @@ pub fn op_function(
                     _ => &match_expression.get_owned_value().exec_cast("TEXT"),
                 };
 
-                let result = match (pattern, match_expression) {
-                    (Value::Text(pattern), Value::Text(match_expression)) if arg_count == 3 => {
-                        let escape = construct_like_escape_arg(
-                            state.registers[*start_reg + 2].get_owned_value(),
-                        )?;
+                let escape = (arg_count == 3).then(|| {
+                    state.registers[*start_reg + 2]
+                        .get_owned_value()
+                        .exec_cast("TEXT")
+                });
+
+                let result = match (pattern, match_expression, &escape) {
+                    // Like in SQLite, a NULL escape character makes the result NULL too
+                    (_, _, Some(Value::Null)) => Value::Null,
+                    (Value::Text(pattern), Value::Text(match_expression), Some(escape)) => {
+                        let escape = construct_like_escape_arg(escape)?;
 
                         Value::Integer(exec_like_with_escape(
                             pattern.as_str(),
                             match_expression.as_str(),
                             escape,
+                            program.connection.get_case_sensitive_like(),
                         ) as i64)
                     }
-                    (Value::Text(pattern), Value::Text(match_expression)) => {
+                    (Value::Text(pattern), Value::Text(match_expression), None) => {
                         let cache = if *constant_mask > 0 {
                             Some(&mut state.regex_cache.like)
                         } else {
@@ -3974,9 +4358,10 @@ pub fn op_function(
                             cache,
                             pattern.as_str(),
                             match_expression.as_str(),
+                            program.connection.get_case_sensitive_like(),
                         ) as i64)
                     }
-                    (Value::Null, _) | (_, Value::Null) => Value::Null,
+                    (Value::Null, _, _) | (_, Value::Null, _) => Value::Null,
                     _ => {
                         unreachable!("Like failed");
                     }
@@ -3994,8 +4379,7 @@ pub fn op_function(
             | ScalarFunc::Quote
             | ScalarFunc::RandomBlob
             | ScalarFunc::Sign
-            | ScalarFunc::Soundex
-            | ScalarFunc::ZeroBlob => {
+            | ScalarFunc::Soundex => {
                 let reg_value = state.registers[*start_reg].borrow_mut().get_owned_value();
                 let result = match scalar_func {
                     ScalarFunc::Sign => reg_value.exec_sign(),
@@ -4008,12 +4392,17 @@ pub fn op_function(
                     ScalarFunc::Unicode => Some(reg_value.exec_unicode()),
                     ScalarFunc::Quote => Some(reg_value.exec_quote()),
                     ScalarFunc::RandomBlob => Some(reg_value.exec_randomblob()),
-                    ScalarFunc::ZeroBlob => Some(reg_value.exec_zeroblob()),
                     ScalarFunc::Soundex => Some(reg_value.exec_soundex()),
                     _ => unreachable!(),
                 };
                 state.registers[*dest] = Register::Value(result.unwrap_or(Value::Null));
             }
+            ScalarFunc::ZeroBlob => {
+                let len = state.registers[*start_reg]
+                    .get_owned_value()
+                    .zeroblob_len()?;
+                state.registers[*dest] = Register::ZeroBlob(ZeroBlob::new(len));
+            }
             ScalarFunc::Hex => {
                 let reg_value = state.registers[*start_reg].borrow_mut();
                 let result = reg_value.get_owned_value().exec_hex();
@@ -4021,7 +4410,11 @@ pub fn op_function(
             }
             ScalarFunc::Unhex => {
                 let reg_value = &state.registers[*start_reg];
-                let ignored_chars = state.registers.get(*start_reg + 1);
+                let ignored_chars = if func.arg_count == 2 {
+                    state.registers.get(*start_reg + 1)
+                } else {
+                    None
+                };
                 let result = reg_value
                     .get_owned_value()
                     .exec_unhex(ignored_chars.map(|x| x.get_owned_value()));
@@ -4153,21 +4546,8 @@ pub fn op_function(
                 state.registers[*dest] = Register::Value(result);
             }
             ScalarFunc::UnixEpoch => {
-                if *start_reg == 0 {
-                    let result = exec_unixepoch(&Value::build_text("now"))?;
-                    state.registers[*dest] = Register::Value(result);
-                } else {
-                    let datetime_value = &state.registers[*start_reg];
-                    let unixepoch = exec_unixepoch(datetime_value.get_owned_value());
-                    match unixepoch {
-                        Ok(time) => state.registers[*dest] = Register::Value(time),
-                        Err(e) => {
-                            return Err(LimboError::ParseError(format!(
-                                "Error encountered while parsing datetime value: {e}"
-                            )));
-                        }
-                    }
-                }
+                let result = exec_unixepoch(&state.registers[*start_reg..*start_reg + arg_count]);
+                state.registers[*dest] = Register::Value(result);
             }
             ScalarFunc::SqliteVersion => {
                 let version_integer: i64 = header_accessor::get_version_number(pager)? as i64;
@@ -4203,7 +4583,7 @@ pub fn op_function(
                 let result = exec_strftime(&state.registers[*start_reg..*start_reg + arg_count]);
                 state.registers[*dest] = Register::Value(result);
             }
-            ScalarFunc::Printf => {
+            ScalarFunc::Printf | ScalarFunc::Format => {
                 let result = exec_printf(&state.registers[*start_reg..*start_reg + arg_count])?;
                 state.registers[*dest] = Register::Value(result);
             }
@@ -4406,6 +4786,13 @@ pub fn op_function(
                     }
                 }
             }
+            ExtFunc::Closure { ref func, .. } => {
+                let args = state.registers[*start_reg..*start_reg + arg_count]
+                    .iter()
+                    .map(|reg| reg.get_owned_value().clone())
+                    .collect::<Vec<_>>();
+                state.registers[*dest] = Register::Value((**func)(&args)?);
+            }
             _ => unreachable!("aggregate called in scalar context"),
         },
         crate::function::Func::Math(math_func) => match math_func.arity() {
@@ -4518,68 +4905,16 @@ pub fn op_function(
                         };
 
                         let mut parser = Parser::new(sql.as_str().as_bytes());
-                        let ast::Cmd::Stmt(stmt) = parser.next().unwrap().unwrap() else {
+                        let ast::Cmd::Stmt(mut stmt) = parser.next().unwrap().unwrap() else {
                             todo!()
                         };
 
-                        match stmt {
-                            ast::Stmt::CreateIndex {
-                                unique,
-                                if_not_exists,
-                                idx_name,
-                                tbl_name,
-                                columns,
-                                where_clause,
-                            } => {
-                                let table_name = normalize_ident(&tbl_name.0);
+                        if !TableRename::new(&rename_from, &rename_to).rename_stmt(&mut stmt)? {
+                            break 'sql None;
+                        }
 
-                                if rename_from != table_name {
-                                    break 'sql None;
-                                }
-
-                                Some(
-                                    ast::Stmt::CreateIndex {
-                                        unique,
-                                        if_not_exists,
-                                        idx_name,
-                                        tbl_name: ast::Name(rename_to),
-                                        columns,
-                                        where_clause,
-                                    }
-                                    .format()
-                                    .unwrap(),
-                                )
-                            }
-                            ast::Stmt::CreateTable {
-                                temporary,
-                                if_not_exists,
-                                tbl_name,
-                                body,
-                            } => {
-                                let table_name = normalize_ident(&tbl_name.name.0);
-
-                                if rename_from != table_name {
-                                    break 'sql None;
-                                }
-
-                                Some(
-                                    ast::Stmt::CreateTable {
-                                        temporary,
-                                        if_not_exists,
-                                        tbl_name: ast::QualifiedName {
-                                            db_name: None,
-                                            name: ast::Name(rename_to),
-                                            alias: None,
-                                        },
-                                        body,
-                                    }
-                                    .format()
-                                    .unwrap(),
-                                )
-                            }
-                            _ => todo!(),
-                        }
-                    };
+                        Some(stmt.format().unwrap())
+                    };
 
                     (new_name, new_tbl_name, new_sql)
                 }
@@ -4606,7 +4941,7 @@ pub fn op_function(
                     };
 
                     let new_sql = 'sql: {
-                        if table != tbl_name {
+                        if table != normalize_ident(&tbl_name) {
                             break 'sql None;
                         }
 
@@ -4619,6 +4954,10 @@ pub fn op_function(
                             todo!()
                         };
 
+                        let rename = |expr: &mut ast::Expr| {
+                            rename_column_in_expr(expr, &table, &rename_from, &rename_to)
+                        };
+
                         match stmt {
                             ast::Stmt::CreateIndex {
                                 unique,
@@ -4626,21 +4965,17 @@ pub fn op_function(
                                 idx_name,
                                 tbl_name,
                                 mut columns,
-                                where_clause,
+                                mut where_clause,
                             } => {
                                 if table != normalize_ident(&tbl_name.0) {
                                     break 'sql None;
                                 }
 
                                 for column in &mut columns {
-                                    match &mut column.expr {
-                                        ast::Expr::Id(ast::Id(id))
-                                            if normalize_ident(id) == rename_from =>
-                                        {
-                                            *id = rename_to.clone();
-                                        }
-                                        _ => {}
-                                    }
+                                    rename(&mut column.expr)?;
+                                }
+                                if let Some(where_clause) = &mut where_clause {
+                                    rename(where_clause)?;
                                 }
 
                                 Some(
@@ -4667,30 +5002,54 @@ pub fn op_function(
                                 }
 
                                 let ast::CreateTableBody::ColumnsAndConstraints {
-                                    mut columns,
-                                    constraints,
+                                    columns,
+                                    mut constraints,
                                     options,
                                 } = *body
                                 else {
                                     todo!()
                                 };
 
-                                let column_index = columns
-                                    .get_index_of(&ast::Name(rename_from))
-                                    .expect("column being renamed should be present");
-
-                                let mut column_definition =
-                                    columns.get_index(column_index).unwrap().1.clone();
-
-                                column_definition.col_name = ast::Name(rename_to.clone());
-
-                                assert!(columns
-                                    .insert(ast::Name(rename_to), column_definition.clone())
-                                    .is_none());
-
-                                // Swaps indexes with the last one and pops the end, effectively
-                                // replacing the entry.
-                                columns.swap_remove_index(column_index).unwrap();
+                                let columns = columns
+                                    .into_iter()
+                                    .map(|(mut name, mut column_definition)| {
+                                        if normalize_ident(&name.0) == rename_from {
+                                            name = ast::Name(rename_to.clone());
+                                            column_definition.col_name = name.clone();
+                                        }
+                                        for constraint in &mut column_definition.constraints {
+                                            match &mut constraint.constraint {
+                                                ast::ColumnConstraint::Check(expr)
+                                                | ast::ColumnConstraint::Generated {
+                                                    expr, ..
+                                                } => rename(expr)?,
+                                                _ => {}
+                                            }
+                                        }
+                                        Ok((name, column_definition))
+                                    })
+                                    .collect::<Result<_>>()?;
+
+                                for constraint in constraints.iter_mut().flatten() {
+                                    match &mut constraint.constraint {
+                                        ast::TableConstraint::PrimaryKey { columns, .. }
+                                        | ast::TableConstraint::Unique { columns, .. } => {
+                                            for column in columns {
+                                                rename(&mut column.expr)?;
+                                            }
+                                        }
+                                        ast::TableConstraint::Check(expr) => rename(expr)?,
+                                        ast::TableConstraint::ForeignKey { columns, .. } => {
+                                            for column in columns {
+                                                if normalize_ident(&column.col_name.0)
+                                                    == rename_from
+                                                {
+                                                    column.col_name = ast::Name(rename_to.clone());
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
 
                                 Some(
                                     ast::Stmt::CreateTable {
@@ -4709,7 +5068,8 @@ pub fn op_function(
                                     .unwrap(),
                                 )
                             }
-                            _ => todo!(),
+                            // TODO: rewrite the column's references in triggers and views.
+                            _ => None,
                         }
                     };
 
@@ -4824,6 +5184,8 @@ pub fn op_yield(
 #[derive(Debug, PartialEq, Copy, Clone)]
 pub enum OpInsertState {
     Insert,
+    /// The preupdate hook was called for the row, which is being inserted.
+    InsertAfterPreUpdate,
     /// Updating last_insert_rowid may return IO, so we need a separate state for it so that we don't
     /// start inserting the same row multiple times.
     UpdateLastRowid,
@@ -4841,7 +5203,7 @@ pub fn op_insert(
         key_reg,
         record_reg,
         flag,
-        table_name: _,
+        table_name,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
@@ -4855,24 +5217,69 @@ pub fn op_insert(
         };
         if let Some(rowid) = maybe_rowid {
             program.connection.update_last_rowid(rowid);
-
-            let prev_changes = program.n_change.get();
-            program.n_change.set(prev_changes + 1);
         }
         state.op_insert_state = OpInsertState::Insert;
         state.pc += 1;
         return Ok(InsnFunctionStepResult::Step);
     }
 
+    let key = match &state.registers[*key_reg].get_owned_value() {
+        Value::Integer(i) => *i,
+        _ => unreachable!("expected integer key"),
+    };
+    let kind = if flag.has(InsertFlags::UPDATE) {
+        UpdateKind::Update
+    } else {
+        UpdateKind::Insert
+    };
+    if state.op_insert_state == OpInsertState::Insert
+        && flag.change_count() != ChangeCount::None
+        && program.connection.has_preupdate_hook()
+    {
+        let old = match kind {
+            // The row was deleted if the UPDATE changes its rowid, otherwise the cursor is still
+            // on it.
+            UpdateKind::Update => match state.preupdate_old.take() {
+                Some(old) => Some(old),
+                None => {
+                    let mut cursor = state.get_cursor(*cursor_id);
+                    let cursor = cursor.as_btree_mut();
+                    let rowid = return_if_io!(cursor.rowid());
+                    let record = return_if_io!(cursor.record()).map(|record| record.clone());
+                    rowid.zip(record)
+                }
+            },
+            _ => None,
+        };
+        {
+            let new = match &state.registers[*record_reg] {
+                // The hook reads the values of the row, so the zeros left out of it are written.
+                Register::Record(r) if r.zero_tail() > 0 => {
+                    let mut r = r.clone();
+                    r.expand_zero_tail();
+                    std::borrow::Cow::Owned(r)
+                }
+                Register::Record(r) => std::borrow::Cow::Borrowed(r),
+                _ => {
+                    let regs = [&state.registers[*record_reg]];
+                    std::borrow::Cow::Owned(ImmutableRecord::from_registers(regs, regs.len()))
+                }
+            };
+            call_preupdate_hook(
+                program,
+                state,
+                *cursor_id,
+                kind,
+                old.as_ref().map(|(rowid, record)| (*rowid, record)),
+                Some((key, &new)),
+            );
+        }
+        state.op_insert_state = OpInsertState::InsertAfterPreUpdate;
+    }
     {
         let mut cursor_ref = state.get_cursor(*cursor_id);
         let cursor = cursor_ref.as_btree_mut();
 
-        let key = match &state.registers[*key_reg].get_owned_value() {
-            Value::Integer(i) => *i,
-            _ => unreachable!("expected integer key"),
-        };
-
         let record = match &state.registers[*record_reg] {
             Register::Record(r) => std::borrow::Cow::Borrowed(r),
             Register::Value(value) => {
@@ -4882,6 +5289,10 @@ pub fn op_insert(
                 let record = ImmutableRecord::from_registers(new_regs, new_regs.len());
                 std::borrow::Cow::Owned(record)
             }
+            Register::ZeroBlob(_) => {
+                let regs = std::slice::from_ref(&state.registers[*record_reg]);
+                std::borrow::Cow::Owned(ImmutableRecord::from_registers_with_zero_tail(regs))
+            }
             Register::Aggregate(..) => unreachable!("Cannot insert an aggregate value."),
         };
 
@@ -4896,15 +5307,14 @@ pub fn op_insert(
         ));
     }
 
-    // Only update last_insert_rowid for regular table inserts, not schema modifications
-    let root_page = {
-        let mut cursor = state.get_cursor(*cursor_id);
-        let cursor = cursor.as_btree_mut();
-        cursor.root_page()
-    };
-    if root_page != 1 {
+    state.count_change(flag.change_count());
+    if flag.change_count() != ChangeCount::None && program.connection.has_update_hook() {
+        call_update_hook(program, state, *cursor_id, kind, table_name, key);
+    }
+    if flag.has(InsertFlags::LAST_ROWID) {
         state.op_insert_state = OpInsertState::UpdateLastRowid;
     } else {
+        state.op_insert_state = OpInsertState::Insert;
         state.pc += 1;
     }
     Ok(InsnFunctionStepResult::Step)
@@ -4938,20 +5348,114 @@ pub fn op_delete(
     pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    let Insn::Delete { cursor_id } = insn else {
+    let Insn::Delete {
+        cursor_id,
+        nchange,
+        table_name,
+        is_update,
+    } = insn
+    else {
         unreachable!("unexpected Insn {:?}", insn)
     };
+    let update_hook = *nchange != ChangeCount::None && program.connection.has_update_hook();
+    let preupdate_hook = program.connection.has_preupdate_hook();
+    if table_name.is_some() && state.op_delete_rowid.is_none() && (update_hook || preupdate_hook) {
+        let (rowid, record) = {
+            let mut cursor = state.get_cursor(*cursor_id);
+            let cursor = cursor.as_btree_mut();
+            let rowid = return_if_io!(cursor.rowid());
+            let record = if preupdate_hook {
+                return_if_io!(cursor.record()).map(|record| record.clone())
+            } else {
+                None
+            };
+            (rowid, record)
+        };
+        if let (Some(rowid), Some(record)) = (rowid, record) {
+            if *is_update {
+                // Reported along with the new row when it is inserted.
+                state.preupdate_old = Some((rowid, record));
+            } else {
+                call_preupdate_hook(
+                    program,
+                    state,
+                    *cursor_id,
+                    UpdateKind::Delete,
+                    Some((rowid, &record)),
+                    None,
+                );
+            }
+        }
+        state.op_delete_rowid = rowid;
+    }
     {
         let mut cursor = state.get_cursor(*cursor_id);
         let cursor = cursor.as_btree_mut();
         return_if_io!(cursor.delete());
     }
-    let prev_changes = program.n_change.get();
-    program.n_change.set(prev_changes + 1);
+    state.count_change(*nchange);
+    if let (Some(rowid), Some(table_name)) = (state.op_delete_rowid.take(), table_name) {
+        if update_hook {
+            call_update_hook(
+                program,
+                state,
+                *cursor_id,
+                UpdateKind::Delete,
+                table_name,
+                rowid,
+            );
+        }
+    }
     state.pc += 1;
     Ok(InsnFunctionStepResult::Step)
 }
 
+/// Returns the name of the database the table cursor `cursor_id` is on, for the hooks.
+fn cursor_database(program: &Program, state: &ProgramState, cursor_id: CursorID) -> String {
+    let pager = {
+        let mut cursor = state.get_cursor(cursor_id);
+        cursor.as_btree_mut().pager().clone()
+    };
+    program.connection.database_name(&pager)
+}
+
+/// Reports a row changed through the table cursor `cursor_id` to the update hook.
+fn call_update_hook(
+    program: &Program,
+    state: &ProgramState,
+    cursor_id: CursorID,
+    kind: UpdateKind,
+    table_name: &str,
+    rowid: i64,
+) {
+    let database = cursor_database(program, state, cursor_id);
+    program
+        .connection
+        .call_update_hook(kind, &database, table_name, rowid);
+}
+
+/// Reports a row about to be changed through the table cursor `cursor_id` to the preupdate hook.
+fn call_preupdate_hook(
+    program: &Program,
+    state: &ProgramState,
+    cursor_id: CursorID,
+    kind: UpdateKind,
+    old: Option<(i64, &ImmutableRecord)>,
+    new: Option<(i64, &ImmutableRecord)>,
+) {
+    let (_, CursorType::BTreeTable(table)) = &program.cursor_ref[cursor_id] else {
+        return;
+    };
+    let database = cursor_database(program, state, cursor_id);
+    program.connection.call_preupdate_hook(&PreUpdate {
+        kind,
+        database: &database,
+        table,
+        old,
+        new,
+    });
+}
+
 #[derive(Debug)]
 pub enum OpIdxDeleteState {
     Seeking,
@@ -5041,8 +5545,6 @@ pub fn op_idx_delete(
                     let cursor = cursor.as_btree_mut();
                     return_if_io!(cursor.delete());
                 }
-                let n_change = program.n_change.get();
-                program.n_change.set(n_change + 1);
                 state.pc += 1;
                 state.op_idx_delete_state = None;
                 return Ok(InsnFunctionStepResult::Step);
@@ -5081,6 +5583,7 @@ pub fn op_idx_insert(
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
+    expand_zero_tail(&mut state.registers, record_reg);
 
     let record_to_insert = match &state.registers[record_reg] {
         Register::Record(ref r) => r,
@@ -5094,10 +5597,13 @@ pub fn op_idx_insert(
     match state.op_idx_insert_state {
         OpIdxInsertState::SeekIfUnique => {
             let (_, cursor_type) = program.cursor_ref.get(cursor_id).unwrap();
-            let CursorType::BTreeIndex(index_meta) = cursor_type else {
-                panic!("IdxInsert: not a BTreeIndex cursor");
+            let unique = match cursor_type {
+                CursorType::BTreeIndex(index_meta) => index_meta.unique,
+                // The PRIMARY KEY of a WITHOUT ROWID table is checked before its rows are written.
+                CursorType::BTreeTable(table) if !table.has_rowid => false,
+                _ => panic!("IdxInsert: not a BTreeIndex cursor"),
             };
-            if !index_meta.unique {
+            if !unique {
                 state.op_idx_insert_state = OpIdxInsertState::Insert {
                     moved_before: false,
                 };
@@ -5188,8 +5694,9 @@ pub fn op_idx_insert(
                 );
             }
             state.op_idx_insert_state = OpIdxInsertState::SeekIfUnique;
+            state.count_change(flags.change_count());
             state.pc += 1;
-            // TODO: flag optimizations, update n_change if OPFLAG_NCHANGE
+            // TODO: flag optimizations
             Ok(InsnFunctionStepResult::Step)
         }
     }
@@ -5221,7 +5728,9 @@ pub fn op_new_rowid(
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
     let Insn::NewRowid {
-        cursor, rowid_reg, ..
+        cursor,
+        rowid_reg,
+        prev_largest_reg,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
@@ -5263,6 +5772,26 @@ pub fn op_new_rowid(
                     return_if_io!(cursor.rowid())
                 };
 
+                if *prev_largest_reg > 0 {
+                    // AUTOINCREMENT: the new rowid must be larger than both the current largest
+                    // rowid and the largest rowid ever used by the table. Random rowids are never
+                    // used, so running out of rowids is an error.
+                    let largest_ever =
+                        extract_int_value(state.registers[*prev_largest_reg].get_owned_value());
+                    if current_max == Some(MAX_ROWID) || largest_ever == MAX_ROWID {
+                        return Err(LimboError::DatabaseFull(
+                            "the largest possible rowid has already been used".to_string(),
+                        ));
+                    }
+                    let rowid = current_max
+                        .map_or(1, |rowid| rowid + 1)
+                        .max(largest_ever + 1);
+                    state.registers[*rowid_reg] = Register::Value(Value::Integer(rowid));
+                    state.registers[*prev_largest_reg] = Register::Value(Value::Integer(rowid));
+                    state.op_new_rowid_state = OpNewRowidState::GoNext;
+                    continue;
+                }
+
                 match current_max {
                     Some(rowid) if rowid < MAX_ROWID => {
                         // Can use sequential
@@ -5414,6 +5943,9 @@ pub fn op_no_conflict(
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
+    if *num_regs == 0 {
+        expand_zero_tail(&mut state.registers, *record_reg);
+    }
     let mut cursor_ref = state.get_cursor(*cursor_id);
     let cursor = cursor_ref.as_btree_mut();
 
@@ -5563,6 +6095,7 @@ pub fn op_open_write(
     let Insn::OpenWrite {
         cursor_id,
         root_page,
+        db,
         ..
     } = insn
     else {
@@ -5571,6 +6104,7 @@ pub fn op_open_write(
     if program.connection.readonly.get() {
         return Err(LimboError::ReadOnly);
     }
+    let (_, pager) = &database_connection(program, *db, pager)?;
     let root_page = match root_page {
         RegisterOrLiteral::Literal(lit) => *lit as u64,
         RegisterOrLiteral::Register(reg) => match &state.registers[*reg].get_owned_value() {
@@ -5619,13 +6153,11 @@ pub fn op_open_write(
             .unwrap()
             .replace(Cursor::new_btree(cursor));
     } else {
-        let num_columns = match cursor_type {
-            CursorType::BTreeTable(table_rc) => table_rc.columns.len(),
-            _ => unreachable!("Expected BTreeTable. This should not have happened."),
+        let CursorType::BTreeTable(table) = cursor_type else {
+            unreachable!("Expected BTreeTable. This should not have happened.");
         };
 
-        let cursor =
-            BTreeCursor::new_table(mv_cursor, pager.clone(), root_page as usize, num_columns);
+        let cursor = new_table_cursor(mv_cursor, pager.clone(), root_page as usize, table);
         cursors
             .get_mut(*cursor_id)
             .unwrap()
@@ -5670,10 +6202,7 @@ pub fn op_create_btree(
     if program.connection.readonly.get() {
         return Err(LimboError::ReadOnly);
     }
-    if *db > 0 {
-        // TODO: implement temp databases
-        todo!("temp databases not implemented yet");
-    }
+    let (_, pager) = &database_connection(program, *db, pager)?;
     // FIXME: handle page cache is full
     let root_page = return_if_io!(pager.btree_create(flags));
     state.registers[*root] = Register::Value(Value::Integer(root_page as i64));
@@ -5691,18 +6220,19 @@ pub fn op_destroy(
     let Insn::Destroy {
         root,
         former_root_reg,
-        is_temp,
+        db,
     } = insn
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if *is_temp == 1 {
-        todo!("temp databases not implemented yet.");
-    }
+    let (conn, pager) = &database_connection(program, *db, pager)?;
     // TODO not sure if should be BTreeCursor::new_table or BTreeCursor::new_index here or neither and just pass an emtpy vec
     let mut cursor = BTreeCursor::new(None, pager.clone(), *root, 0);
     let former_root_page_result = cursor.btree_destroy()?;
     if let IOResult::Done(former_root_page) = former_root_page_result {
+        if let Some(former_root_page) = former_root_page {
+            conn.with_schema_mut(|schema| schema.root_page_moved(former_root_page, *root));
+        }
         state.registers[*former_root_reg] =
             Register::Value(Value::Integer(former_root_page.unwrap_or(0) as i64));
     }
@@ -5710,23 +6240,111 @@ pub fn op_destroy(
     Ok(InsnFunctionStepResult::Step)
 }
 
-pub fn op_drop_table(
+pub fn op_incr_vacuum(
     program: &Program,
     state: &mut ProgramState,
     insn: &Insn,
     pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    let Insn::DropTable { db, table_name, .. } = insn else {
+    let Insn::IncrVacuum { db, target_pc } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
     if *db > 0 {
         todo!("temp databases not implemented yet");
     }
-    let conn = program.connection.clone();
+    assert!(target_pc.is_offset());
+    if pager.incremental_vacuum_step()? {
+        state.pc += 1;
+    } else {
+        state.pc = target_pc.as_offset_int();
+    }
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_vacuum(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::Vacuum { db, into } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    if *db > 0 {
+        todo!("temp databases not implemented yet");
+    }
+    if mv_store.is_some() {
+        return Err(LimboError::TxError(
+            "VACUUM is not supported with MVCC".to_string(),
+        ));
+    }
+    if !program.connection.auto_commit.get() {
+        return Err(LimboError::TxError(
+            "cannot VACUUM from within a transaction".to_string(),
+        ));
+    }
+    let into = match into {
+        Some(reg) => match state.registers[*reg].get_owned_value() {
+            Value::Text(path) => Some(path.as_str().to_string()),
+            _ => return Err(LimboError::InvalidArgument("non-text filename".to_string())),
+        },
+        None => None,
+    };
+    vacuum::run_vacuum(&program.connection, pager, into.as_deref())?;
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_attach(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::Attach { file_reg, name_reg } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let file = state.registers[*file_reg].get_owned_value().to_string();
+    let name = state.registers[*name_reg].get_owned_value().to_string();
+    program.connection.attach_database(&file, &name)?;
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_detach(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::Detach { name_reg } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let name = state.registers[*name_reg].get_owned_value().to_string();
+    program.connection.detach_database(&name)?;
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
+pub fn op_drop_table(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::DropTable { db, table_name, .. } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    let (conn, _) = database_connection(program, *db, pager)?;
     {
         conn.with_schema_mut(|schema| {
             schema.remove_indices_for_table(table_name);
+            schema.remove_triggers_for_table(table_name);
             schema.remove_table(table_name);
         });
     }
@@ -5795,14 +6413,10 @@ pub fn op_parse_schema(
     pager: &Rc<Pager>,
     mv_store: Option<&Rc<MvStore>>,
 ) -> Result<InsnFunctionStepResult> {
-    let Insn::ParseSchema {
-        db: _,
-        where_clause,
-    } = insn
-    else {
+    let Insn::ParseSchema { db, where_clause } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    let conn = program.connection.clone();
+    let (conn, db_pager) = database_connection(program, *db, pager)?;
     // set auto commit to false in order for parse schema to not commit changes as transaction state is stored in connection,
     // and we use the same connection for nested query.
     let previous_auto_commit = conn.auto_commit.get();
@@ -5819,8 +6433,14 @@ pub fn op_parse_schema(
         let stmt = conn.prepare("SELECT * FROM sqlite_schema")?;
 
         conn.with_schema_mut(|schema| {
+            // Everything gets parsed again, so forget the objects that are gone from
+            // sqlite_schema, like a table that was renamed.
+            let schema_version = schema.schema_version;
+            *schema = Schema::new(schema.indexes_enabled);
+            schema.schema_version = schema_version;
             // TODO: This function below is synchronous, make it async
-            parse_schema_rows(Some(stmt), schema, &conn.syms.borrow(), state.mv_tx_id)
+            parse_schema_rows(Some(stmt), schema, &conn.syms.borrow(), state.mv_tx_id)?;
+            schema.load_stats(&db_pager)
         })?;
     }
     conn.auto_commit.set(previous_auto_commit);
@@ -5828,6 +6448,27 @@ pub fn op_parse_schema(
     Ok(InsnFunctionStepResult::Step)
 }
 
+pub fn op_load_analysis(
+    program: &Program,
+    state: &mut ProgramState,
+    insn: &Insn,
+    pager: &Rc<Pager>,
+    mv_store: Option<&Rc<MvStore>>,
+) -> Result<InsnFunctionStepResult> {
+    let Insn::LoadAnalysis { db } = insn else {
+        unreachable!("unexpected Insn {:?}", insn)
+    };
+    if mv_store.is_some() {
+        return Err(LimboError::TxError(
+            "ANALYZE is not supported with MVCC".to_string(),
+        ));
+    }
+    let (conn, db_pager) = database_connection(program, *db, pager)?;
+    conn.with_schema_mut(|schema| schema.load_stats(&db_pager))?;
+    state.pc += 1;
+    Ok(InsnFunctionStepResult::Step)
+}
+
 pub fn op_read_cookie(
     program: &Program,
     state: &mut ProgramState,
@@ -5838,12 +6479,10 @@ pub fn op_read_cookie(
     let Insn::ReadCookie { db, dest, cookie } = insn else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if *db > 0 {
-        // TODO: implement temp databases
-        todo!("temp databases not implemented yet");
-    }
+    let (_, pager) = &database_connection(program, *db, pager)?;
     let cookie_value = match cookie {
         Cookie::UserVersion => header_accessor::get_user_version(pager)?.into(),
+        Cookie::ApplicationId => (header_accessor::get_application_id(pager)? as i32).into(),
         Cookie::SchemaVersion => header_accessor::get_schema_cookie(pager)?.into(),
         Cookie::LargestRootPageNumber => {
             header_accessor::get_vacuum_mode_largest_root_page(pager)?.into()
@@ -5871,13 +6510,14 @@ pub fn op_set_cookie(
     else {
         unreachable!("unexpected Insn {:?}", insn)
     };
-    if *db > 0 {
-        todo!("temp databases not implemented yet");
-    }
+    let (conn, pager) = &database_connection(program, *db, pager)?;
     match cookie {
         Cookie::UserVersion => {
             header_accessor::set_user_version(pager, *value)?;
         }
+        Cookie::ApplicationId => {
+            header_accessor::set_application_id(pager, *value as u32)?;
+        }
         Cookie::LargestRootPageNumber => {
             header_accessor::set_vacuum_mode_largest_root_page(pager, *value as u32)?;
         }
@@ -5887,17 +6527,15 @@ pub fn op_set_cookie(
         Cookie::SchemaVersion => {
             if mv_store.is_none() {
                 // we update transaction state to indicate that the schema has changed
-                match program.connection.transaction_state.get() {
+                match conn.transaction_state.get() {
                     TransactionState::Write { schema_did_change } => {
-                        program.connection.transaction_state.set(TransactionState::Write { schema_did_change: true });
+                        conn.transaction_state.set(TransactionState::Write { schema_did_change: true });
                     },
                     TransactionState::Read => unreachable!("invalid transaction state for SetCookie: TransactionState::Read, should be write"),
                     TransactionState::None => unreachable!("invalid transaction state for SetCookie: TransactionState::None, should be write"),
                 }
             }
-            program
-                .connection
-                .with_schema_mut(|schema| schema.schema_version = *value as u32);
+            conn.with_schema_mut(|schema| schema.schema_version = *value as u32);
             header_accessor::set_schema_cookie(pager, *value as u32)?;
         }
         cookie => todo!("{cookie:?} is not yet implement for SetCookie"),
@@ -6357,8 +6995,12 @@ pub enum OpIntegrityCheckState {
     Start,
     Checking {
         errors: Vec<IntegrityCheckError>,
-        current_root_idx: usize,
+        /// The position in the roots of the next b-tree to check, the freelist being checked
+        /// first.
+        next_root_idx: usize,
         state: IntegrityCheckState,
+        /// The number of entries of each b-tree checked so far.
+        entries: Vec<u64>,
     },
 }
 pub fn op_integrity_check(
@@ -6371,6 +7013,7 @@ pub fn op_integrity_check(
     let Insn::IntegrityCk {
         max_errors,
         roots,
+        index_counts,
         message_register,
     } = insn
     else {
@@ -6378,36 +7021,69 @@ pub fn op_integrity_check(
     };
     match &mut state.op_integrity_check_state {
         OpIntegrityCheckState::Start => {
+            // A database that was never written to has nothing to check
+            if pager.db_state.load(std::sync::atomic::Ordering::SeqCst) < DB_STATE_INITIALIZED {
+                state.registers[*message_register] = Register::Value(Value::build_text("ok"));
+                state.pc += 1;
+                return Ok(InsnFunctionStepResult::Step);
+            }
+            let db_size = header_accessor::get_database_size(pager)? as usize;
+            let mut errors = Vec::new();
+            let mut integrity_check_state = IntegrityCheckState::new(db_size, *max_errors);
+            integrity_check_state.start_freelist(
+                header_accessor::get_freelist_trunk_page(pager)? as usize,
+                header_accessor::get_freelist_pages(pager)? as usize,
+                &mut errors,
+            );
             state.op_integrity_check_state = OpIntegrityCheckState::Checking {
-                errors: Vec::new(),
-                current_root_idx: 0,
-                state: IntegrityCheckState::new(roots[0]),
+                errors,
+                next_root_idx: 0,
+                state: integrity_check_state,
+                entries: Vec::with_capacity(roots.len()),
             };
         }
         OpIntegrityCheckState::Checking {
             errors,
-            current_root_idx,
+            next_root_idx,
             state: integrity_check_state,
+            entries,
         } => {
             return_if_io!(integrity_check(integrity_check_state, errors, pager));
-            *current_root_idx += 1;
-            if *current_root_idx < roots.len() {
-                *integrity_check_state = IntegrityCheckState::new(roots[*current_root_idx]);
+            if *next_root_idx > 0 {
+                entries.push(integrity_check_state.entries);
+            }
+            if *next_root_idx < roots.len() {
+                integrity_check_state.start_btree(roots[*next_root_idx], errors);
+                *next_root_idx += 1;
                 return Ok(InsnFunctionStepResult::Step);
-            } else {
-                let message = if errors.is_empty() {
-                    "ok".to_string()
-                } else {
-                    errors
-                        .iter()
-                        .map(|e| e.to_string())
-                        .collect::<Vec<String>>()
-                        .join("\n")
-                };
-                state.registers[*message_register] = Register::Value(Value::build_text(message));
-                state.op_integrity_check_state = OpIntegrityCheckState::Start;
-                state.pc += 1;
             }
+            // The pages of the pointer map of an auto-vacuum database aren't referenced by
+            // anything.
+            if matches!(pager.get_auto_vacuum_mode(), AutoVacuumMode::None) {
+                for page_id in integrity_check_state.unused_pages() {
+                    errors.push(IntegrityCheckError::PageNeverUsed { page_id });
+                }
+            }
+            for (index_name, table_idx, index_idx) in index_counts {
+                if entries.get(*table_idx) != entries.get(*index_idx) {
+                    errors.push(IntegrityCheckError::IndexEntryCount {
+                        index_name: index_name.clone(),
+                    });
+                }
+            }
+            errors.truncate(*max_errors);
+            let message = if errors.is_empty() {
+                "ok".to_string()
+            } else {
+                errors
+                    .iter()
+                    .map(|e| e.to_string())
+                    .collect::<Vec<String>>()
+                    .join("\n")
+            };
+            state.registers[*message_register] = Register::Value(Value::build_text(message));
+            state.op_integrity_check_state = OpIntegrityCheckState::Start;
+            state.pc += 1;
         }
     }
 
@@ -6417,7 +7093,7 @@ pub fn op_integrity_check(
 impl Value {
     pub fn exec_lower(&self) -> Option<Self> {
         match self {
-            Value::Text(t) => Some(Value::build_text(t.as_str().to_lowercase())),
+            Value::Text(t) => Some(Value::build_text(unicode::to_lower(t.as_str()))),
             t => Some(t.to_owned()),
         }
     }
@@ -6444,7 +7120,7 @@ impl Value {
 
     pub fn exec_upper(&self) -> Option<Self> {
         match self {
-            Value::Text(t) => Some(Value::build_text(t.as_str().to_uppercase())),
+            Value::Text(t) => Some(Value::build_text(unicode::to_upper(t.as_str()))),
             t => Some(t.to_owned()),
         }
     }
@@ -6625,8 +7301,21 @@ impl Value {
     pub fn exec_quote(&self) -> Self {
         match self {
             Value::Null => Value::build_text("NULL"),
-            Value::Integer(_) | Value::Float(_) => self.to_owned(),
-            Value::Blob(_) => todo!(),
+            Value::Integer(i) => Value::build_text(i.to_string()),
+            Value::Float(f) => {
+                // Like in SQLite, a float that doesn't read back the same from its usual 15
+                // significant digits is quoted with 20.
+                let text = self.to_string();
+                if text.parse::<f64>() == Ok(*f) {
+                    return Value::build_text(text);
+                }
+                exec_printf(&[
+                    Register::Value(Value::build_text("%!.20e")),
+                    Register::Value(self.clone()),
+                ])
+                .unwrap_or_else(|_| Value::build_text(text))
+            }
+            Value::Blob(b) => Value::build_text(format!("X'{}'", hex::encode_upper(b))),
             Value::Text(s) => {
                 let mut quoted = String::with_capacity(s.as_str().len() + 2);
                 quoted.push('\'');
@@ -6736,40 +7425,43 @@ impl Value {
 
     pub fn exec_hex(&self) -> Value {
         match self {
-            Value::Text(_) | Value::Integer(_) | Value::Float(_) => {
+            Value::Text(text) => Value::build_text(hex::encode_upper(
+                text_encoding::database_bytes(text.as_str()),
+            )),
+            Value::Integer(_) | Value::Float(_) => {
                 let text = self.to_string();
                 Value::build_text(hex::encode_upper(text))
             }
             Value::Blob(blob_bytes) => Value::build_text(hex::encode_upper(blob_bytes)),
-            _ => Value::Null,
+            Value::Null => Value::build_text(""),
         }
     }
 
+    /// Decodes pairs of hex digits into a blob. The characters of `ignored_chars` may appear
+    /// between the pairs, but anything else makes the result NULL.
     pub fn exec_unhex(&self, ignored_chars: Option<&Value>) -> Value {
-        match self {
-            Value::Null => Value::Null,
-            _ => match ignored_chars {
-                None => match hex::decode(self.to_string()) {
-                    Ok(bytes) => Value::Blob(bytes),
-                    Err(_) => Value::Null,
-                },
-                Some(ignore) => match ignore {
-                    Value::Text(_) => {
-                        let pat = ignore.to_string();
-                        let trimmed = self
-                            .to_string()
-                            .trim_start_matches(|x| pat.contains(x))
-                            .trim_end_matches(|x| pat.contains(x))
-                            .to_string();
-                        match hex::decode(trimmed) {
-                            Ok(bytes) => Value::Blob(bytes),
-                            Err(_) => Value::Null,
-                        }
-                    }
-                    _ => Value::Null,
-                },
-            },
+        if matches!(self, Value::Null) || matches!(ignored_chars, Some(Value::Null)) {
+            return Value::Null;
+        }
+        let text = self.to_string();
+        let ignored = ignored_chars
+            .map(|ignored| ignored.to_string())
+            .unwrap_or_default();
+        let mut bytes = Vec::with_capacity(text.len() / 2);
+        let mut chars = text.chars();
+        while let Some(c) = chars.next() {
+            let Some(high) = c.to_digit(16) else {
+                if ignored.contains(c) {
+                    continue;
+                }
+                return Value::Null;
+            };
+            let Some(low) = chars.next().and_then(|c| c.to_digit(16)) else {
+                return Value::Null;
+            };
+            bytes.push((high * 16 + low) as u8);
         }
+        Value::Blob(bytes)
     }
 
     pub fn exec_unicode(&self) -> Value {
@@ -6862,14 +7554,21 @@ impl Value {
         }
     }
 
-    pub fn exec_zeroblob(&self) -> Value {
-        let length: i64 = match self {
-            Value::Integer(i) => *i,
-            Value::Float(f) => *f as i64,
-            Value::Text(s) => s.as_str().parse().unwrap_or(0),
+    pub fn exec_zeroblob(&self) -> Result<Value> {
+        Ok(Value::Blob(vec![0; self.zeroblob_len()?]))
+    }
+
+    /// The size of the blob of `zeroblob()` with this argument.
+    pub fn zeroblob_len(&self) -> Result<usize> {
+        let length: i64 = match self.exec_cast("INTEGER") {
+            Value::Integer(i) => i,
             _ => 0,
         };
-        Value::Blob(vec![0; length.max(0) as usize])
+        // The largest blob, like SQLite's default SQLITE_MAX_LENGTH.
+        if length > 1_000_000_000 {
+            return Err(LimboError::TooBig("zeroblob()".to_string()));
+        }
+        Ok(length.max(0) as usize)
     }
 
     // exec_if returns whether you should jump
@@ -6935,16 +7634,19 @@ impl Value {
                 }
                 _ => Value::Integer(0),
             },
-            Affinity::Numeric => match self {
-                Value::Blob(b) => {
-                    let text = String::from_utf8_lossy(b);
-                    cast_text_to_numeric(&text)
+            // A text that looks like a real is an INTEGER when it can go back and forth between
+            // a real and a 51-bit integer losslessly, while a REAL or INTEGER is left as it is.
+            Affinity::Numeric => {
+                let numeric = match self {
+                    Value::Blob(b) => cast_text_to_numeric(&String::from_utf8_lossy(b)),
+                    Value::Text(t) => cast_text_to_numeric(t.as_str()),
+                    _ => return self.clone(),
+                };
+                match numeric {
+                    Value::Float(f) => cast_real_to_integer(f).map_or(numeric, Value::Integer),
+                    numeric => numeric,
                 }
-                Value::Text(t) => cast_text_to_numeric(t.as_str()),
-                Value::Integer(i) => Value::Integer(*i),
-                Value::Float(f) => Value::Float(*f),
-                _ => self.clone(), // TODO probably wrong
-            },
+            }
         }
     }
 
@@ -6981,29 +7683,55 @@ impl Value {
         }
     }
 
-    fn to_f64(&self) -> Option<f64> {
+    /// Converts the argument of a math function to a number. Like in SQLite, text is only
+    /// converted if all of it, but for surrounding whitespace, is a number.
+    fn to_math_numeric(&self) -> Option<Value> {
         match self {
-            Value::Integer(i) => Some(*i as f64),
-            Value::Float(f) => Some(*f),
-            Value::Text(t) => t.as_str().parse::<f64>().ok(),
+            Value::Integer(_) | Value::Float(_) => Some(self.clone()),
+            Value::Text(t) => {
+                let text = t.as_str().trim();
+                if let Ok(i) = text.parse::<i64>() {
+                    return Some(Value::Integer(i));
+                }
+                // Rust also parses words like "inf" and "nan", which aren't numbers in SQL.
+                if !text.bytes().any(|b| b.is_ascii_digit()) {
+                    return None;
+                }
+                text.parse::<f64>().ok().map(Value::Float)
+            }
+            _ => None,
+        }
+    }
+
+    fn to_f64(&self) -> Option<f64> {
+        match self.to_math_numeric()? {
+            Value::Integer(i) => Some(i as f64),
+            Value::Float(f) => Some(f),
             _ => None,
         }
     }
 
     fn exec_math_unary(&self, function: &MathFunc) -> Value {
-        // In case of some functions and integer input, return the input as is
-        if let Value::Integer(_) = self {
-            if matches! { function, MathFunc::Ceil | MathFunc::Ceiling | MathFunc::Floor | MathFunc::Trunc }
+        let f = match self.to_math_numeric() {
+            // In case of some functions and integer input, return the input as is
+            Some(Value::Integer(i))
+                if matches!(
+                    function,
+                    MathFunc::Ceil | MathFunc::Ceiling | MathFunc::Floor | MathFunc::Trunc
+                ) =>
             {
-                return self.clone();
+                return Value::Integer(i);
             }
-        }
-
-        let f = match self.to_f64() {
-            Some(f) => f,
-            None => return Value::Null,
+            Some(Value::Integer(i)) => i as f64,
+            Some(Value::Float(f)) => f,
+            _ => return Value::Null,
         };
 
+        // The logarithm of a number that isn't positive is NULL rather than -Inf or NaN.
+        if matches!(function, MathFunc::Ln | MathFunc::Log10 | MathFunc::Log2) && f <= 0.0 {
+            return Value::Null;
+        }
+
         let result = match function {
             MathFunc::Acos => libm::acos(f),
             MathFunc::Acosh => libm::acosh(f),
@@ -7076,15 +7804,16 @@ impl Value {
             None => 10.0,
         };
 
+        if f <= 0.0 || base <= 0.0 || base == 1.0 {
+            return Value::Null;
+        }
+
         if base == 2.0 {
             return Value::Float(libm::log2(f));
         } else if base == 10.0 {
             return Value::Float(libm::log10(f));
         };
 
-        if f <= 0.0 || base <= 0.0 || base == 1.0 {
-            return Value::Null;
-        }
         let log_x = libm::log(f);
         let log_base = libm::log(base);
         let result = log_x / log_base;
@@ -7221,19 +7950,20 @@ impl Value {
         regex_cache: Option<&mut HashMap<String, Regex>>,
         pattern: &str,
         text: &str,
+        case_sensitive: bool,
     ) -> bool {
         if let Some(cache) = regex_cache {
             match cache.get(pattern) {
                 Some(re) => re.is_match(text),
                 None => {
-                    let re = construct_like_regex(pattern);
+                    let re = construct_like_regex(pattern, case_sensitive);
                     let res = re.is_match(text);
                     cache.insert(pattern.to_string(), re);
                     res
                 }
             }
         } else {
-            let re = construct_like_regex(pattern);
+            let re = construct_like_regex(pattern, case_sensitive);
             re.is_match(text)
         }
     }
@@ -7299,34 +8029,29 @@ fn exec_char(values: &[Register]) -> Value {
     Value::build_text(result)
 }
 
-fn construct_like_regex(pattern: &str) -> Regex {
+fn construct_like_regex(pattern: &str, case_sensitive: bool) -> Regex {
     let mut regex_pattern = String::with_capacity(pattern.len() * 2);
 
     regex_pattern.push('^');
 
     for c in pattern.chars() {
         match c {
-            '\\' => regex_pattern.push_str("\\\\"),
             '%' => regex_pattern.push_str(".*"),
             '_' => regex_pattern.push('.'),
-            ch => {
-                if regex_syntax::is_meta_character(c) {
-                    regex_pattern.push('\\');
-                }
-                regex_pattern.push(ch);
-            }
+            c => push_like_literal(c, case_sensitive, &mut regex_pattern),
         }
     }
 
     regex_pattern.push('$');
 
     RegexBuilder::new(&regex_pattern)
-        .case_insensitive(true)
         .dot_matches_new_line(true)
         .build()
         .unwrap()
 }
 
+/// Applies a column affinity to the value in `target`, like SQLite's applyAffinity(). A text is
+/// only converted to a number if all of it is a well-formed number, surrounding spaces aside.
 fn apply_affinity_char(target: &mut Register, affinity: Affinity) -> bool {
     if let Register::Value(value) = target {
         if matches!(value, Value::Blob(_)) {
@@ -7346,71 +8071,25 @@ fn apply_affinity_char(target: &mut Register, affinity: Affinity) -> bool {
             }
 
             Affinity::Integer | Affinity::Numeric => {
-                if matches!(value, Value::Integer(_)) {
-                    return true;
-                }
-                if !matches!(value, Value::Text(_) | Value::Float(_)) {
-                    return true;
-                }
-
                 if let Value::Float(fl) = *value {
                     // For floats, try to convert to integer if it's exact
                     // This is similar to sqlite3VdbeIntegerAffinity
                     return try_float_to_integer_affinity(value, fl);
                 }
-
-                if let Value::Text(t) = value {
-                    let text = t.as_str();
-
-                    // Handle hex numbers - they shouldn't be converted
-                    if text.starts_with("0x") {
-                        return false;
-                    }
-
-                    // Try to parse as number (similar to applyNumericAffinity)
-                    let Ok(num) = checked_cast_text_to_numeric(text) else {
-                        return false;
-                    };
-
-                    match num {
-                        Value::Integer(i) => {
-                            *value = Value::Integer(i);
-                            return true;
-                        }
-                        Value::Float(fl) => {
-                            // For Numeric affinity, try to convert float to int if exact
-                            if affinity == Affinity::Numeric {
-                                return try_float_to_integer_affinity(value, fl);
-                            } else {
-                                *value = Value::Float(fl);
-                                return true;
-                            }
-                        }
-                        other => {
-                            *value = other;
-                            return true;
-                        }
-                    }
+                if !matches!(value, Value::Text(_)) {
+                    return true;
                 }
-
-                return false;
+                return apply_numeric_affinity(target, true);
             }
 
             Affinity::Real => {
-                if let Value::Integer(i) = *value {
-                    *value = Value::Float(i as f64);
-                    return true;
+                if matches!(value, Value::Text(_)) && !apply_numeric_affinity(target, true) {
+                    return false;
                 }
-                if let Value::Text(t) = value {
-                    let s = t.as_str();
-                    if s.starts_with("0x") {
-                        return false;
-                    }
-                    if let Ok(num) = checked_cast_text_to_numeric(s) {
-                        *value = num;
-                        return true;
-                    } else {
-                        return false;
+                // A REAL column stores the integers it's given as reals.
+                if let Register::Value(value) = target {
+                    if let Value::Integer(i) = *value {
+                        *value = Value::Float(i as f64);
                     }
                 }
                 return true;
@@ -7423,13 +8102,10 @@ fn apply_affinity_char(target: &mut Register, affinity: Affinity) -> bool {
 
 fn try_float_to_integer_affinity(value: &mut Value, fl: f64) -> bool {
     // Check if the float can be exactly represented as an integer
-    if let Ok(int_val) = cast_real_to_integer(fl) {
-        // Additional check: ensure round-trip conversion is exact
-        // and value is within safe bounds (similar to SQLite's checks)
-        if (int_val as f64) == fl && int_val > i64::MIN + 1 && int_val < i64::MAX - 1 {
-            *value = Value::Integer(int_val);
-            return true;
-        }
+    let int_val = real_to_i64(fl);
+    if (int_val as f64) == fl && int_val > i64::MIN && int_val < i64::MAX {
+        *value = Value::Integer(int_val);
+        return true;
     }
 
     // If we can't convert to exact integer, keep as float for Numeric affinity
@@ -7475,267 +8151,6 @@ pub fn extract_int_value(value: &Value) -> i64 {
     }
 }
 
-#[derive(Debug, PartialEq)]
-enum NumericParseResult {
-    NotNumeric,      // not a valid number
-    PureInteger,     // pure integer (entire string)
-    HasDecimalOrExp, // has decimal point or exponent (entire string)
-    ValidPrefixOnly, // valid prefix but not entire string
-}
-
-#[derive(Debug)]
-enum ParsedNumber {
-    None,
-    Integer(i64),
-    Float(f64),
-}
-
-impl ParsedNumber {
-    fn as_integer(&self) -> Option<i64> {
-        match self {
-            ParsedNumber::Integer(i) => Some(*i),
-            _ => None,
-        }
-    }
-
-    fn as_float(&self) -> Option<f64> {
-        match self {
-            ParsedNumber::Float(f) => Some(*f),
-            _ => None,
-        }
-    }
-}
-
-fn try_for_float(text: &str) -> (NumericParseResult, ParsedNumber) {
-    let bytes = text.as_bytes();
-    if bytes.is_empty() {
-        return (NumericParseResult::NotNumeric, ParsedNumber::None);
-    }
-
-    let mut pos = 0;
-    let len = bytes.len();
-
-    while pos < len && is_space(bytes[pos]) {
-        pos += 1;
-    }
-
-    if pos >= len {
-        return (NumericParseResult::NotNumeric, ParsedNumber::None);
-    }
-
-    let start_pos = pos;
-
-    let mut sign = 1i64;
-
-    if bytes[pos] == b'-' {
-        sign = -1;
-        pos += 1;
-    } else if bytes[pos] == b'+' {
-        pos += 1;
-    }
-
-    if pos >= len {
-        return (NumericParseResult::NotNumeric, ParsedNumber::None);
-    }
-
-    let mut significand = 0u64;
-    let mut digit_count = 0;
-    let mut decimal_adjust = 0i32;
-    let mut has_digits = false;
-
-    // Parse digits before decimal point
-    while pos < len && bytes[pos].is_ascii_digit() {
-        has_digits = true;
-        let digit = (bytes[pos] - b'0') as u64;
-
-        if significand <= (u64::MAX - 9) / 10 {
-            significand = significand * 10 + digit;
-            digit_count += 1;
-        } else {
-            // Skip overflow digits but adjust exponent
-            decimal_adjust += 1;
-        }
-        pos += 1;
-    }
-
-    let mut has_decimal = false;
-    let mut has_exponent = false;
-
-    // Check for decimal point
-    if pos < len && bytes[pos] == b'.' {
-        has_decimal = true;
-        pos += 1;
-
-        // Parse fractional digits
-        while pos < len && bytes[pos].is_ascii_digit() {
-            has_digits = true;
-            let digit = (bytes[pos] - b'0') as u64;
-
-            if significand <= (u64::MAX - 9) / 10 {
-                significand = significand * 10 + digit;
-                digit_count += 1;
-                decimal_adjust -= 1;
-            }
-            pos += 1;
-        }
-    }
-
-    if !has_digits {
-        return (NumericParseResult::NotNumeric, ParsedNumber::None);
-    }
-
-    // Check for exponent
-    let mut exponent = 0i32;
-    if pos < len && (bytes[pos] == b'e' || bytes[pos] == b'E') {
-        has_exponent = true;
-        pos += 1;
-
-        if pos >= len {
-            // Incomplete exponent, but we have valid digits before
-            return create_result_from_significand(
-                significand,
-                sign,
-                decimal_adjust,
-                has_decimal,
-                has_exponent,
-                NumericParseResult::ValidPrefixOnly,
-            );
-        }
-
-        let mut exp_sign = 1i32;
-        if bytes[pos] == b'-' {
-            exp_sign = -1;
-            pos += 1;
-        } else if bytes[pos] == b'+' {
-            pos += 1;
-        }
-
-        if pos >= len || !bytes[pos].is_ascii_digit() {
-            // Incomplete exponent
-            return create_result_from_significand(
-                significand,
-                sign,
-                decimal_adjust,
-                has_decimal,
-                false,
-                NumericParseResult::ValidPrefixOnly,
-            );
-        }
-
-        // Parse exponent digits
-        while pos < len && bytes[pos].is_ascii_digit() {
-            let digit = (bytes[pos] - b'0') as i32;
-            if exponent < 10000 {
-                exponent = exponent * 10 + digit;
-            } else {
-                exponent = 10000; // Cap at large value
-            }
-            pos += 1;
-        }
-        exponent *= exp_sign;
-    }
-
-    // Skip trailing whitespace
-    while pos < len && is_space(bytes[pos]) {
-        pos += 1;
-    }
-
-    // Determine if we consumed the entire string
-    let consumed_all = pos >= len;
-    let final_exponent = decimal_adjust + exponent;
-
-    let parse_result = if !consumed_all {
-        NumericParseResult::ValidPrefixOnly
-    } else if has_decimal || has_exponent {
-        NumericParseResult::HasDecimalOrExp
-    } else {
-        NumericParseResult::PureInteger
-    };
-
-    create_result_from_significand(
-        significand,
-        sign,
-        final_exponent,
-        has_decimal,
-        has_exponent,
-        parse_result,
-    )
-}
-
-fn create_result_from_significand(
-    significand: u64,
-    sign: i64,
-    exponent: i32,
-    has_decimal: bool,
-    has_exponent: bool,
-    parse_result: NumericParseResult,
-) -> (NumericParseResult, ParsedNumber) {
-    if significand == 0 {
-        match parse_result {
-            NumericParseResult::PureInteger => {
-                return (parse_result, ParsedNumber::Integer(0));
-            }
-            _ => {
-                return (parse_result, ParsedNumber::Float(0.0));
-            }
-        }
-    }
-
-    // For pure integers without exponent, try to return as integer
-    if !has_decimal && !has_exponent && exponent == 0 {
-        let signed_val = (significand as i64).wrapping_mul(sign);
-        if (significand as i64) * sign == signed_val {
-            return (parse_result, ParsedNumber::Integer(signed_val));
-        }
-    }
-
-    // Convert to float
-    let mut result = significand as f64;
-
-    let mut exp = exponent;
-    match exp.cmp(&0) {
-        std::cmp::Ordering::Greater => {
-            while exp >= 100 {
-                result *= 1e100;
-                exp -= 100;
-            }
-            while exp >= 10 {
-                result *= 1e10;
-                exp -= 10;
-            }
-            while exp >= 1 {
-                result *= 10.0;
-                exp -= 1;
-            }
-        }
-        std::cmp::Ordering::Less => {
-            while exp <= -100 {
-                result *= 1e-100;
-                exp += 100;
-            }
-            while exp <= -10 {
-                result *= 1e-10;
-                exp += 10;
-            }
-            while exp <= -1 {
-                result *= 0.1;
-                exp += 1;
-            }
-        }
-        std::cmp::Ordering::Equal => {}
-    }
-
-    if sign < 0 {
-        result = -result;
-    }
-
-    (parse_result, ParsedNumber::Float(result))
-}
-
-pub fn is_space(byte: u8) -> bool {
-    matches!(byte, b' ' | b'\t' | b'\n' | b'\r' | b'\x0c')
-}
-
 fn real_to_i64(r: f64) -> i64 {
     if r < -9223372036854774784.0 {
         i64::MIN
@@ -7771,35 +8186,16 @@ pub fn apply_numeric_affinity(register: &mut Register, try_for_int: bool) -> boo
         return false; // Only apply to text values
     };
 
-    let text_str = text.as_str();
-    let (parse_result, parsed_value) = try_for_float(text_str);
-
     // Only convert if we have a complete valid number (not just a prefix)
-    match parse_result {
-        NumericParseResult::NotNumeric | NumericParseResult::ValidPrefixOnly => {
-            false // Leave as text
-        }
-        NumericParseResult::PureInteger => {
-            if let Some(int_val) = parsed_value.as_integer() {
-                *register = Register::Value(Value::Integer(int_val));
-                true
-            } else {
-                false
-            }
-        }
-        NumericParseResult::HasDecimalOrExp => {
-            if let Some(float_val) = parsed_value.as_float() {
-                *register = Register::Value(Value::Float(float_val));
-                // If try_for_int is true, try to convert float to int if exact
-                if try_for_int {
-                    apply_integer_affinity(register);
-                }
-                true
-            } else {
-                false
-            }
-        }
+    let Some(value) = text_to_numeric_exact(text.as_str()) else {
+        return false; // Leave as text
+    };
+    *register = Register::Value(value);
+    // If try_for_int is true, try to convert float to int if exact
+    if try_for_int {
+        apply_integer_affinity(register);
     }
+    true
 }
 
 fn is_numeric_value(reg: &Register) -> bool {
@@ -7859,6 +8255,33 @@ mod tests {
         assert_eq!(*reg.get_owned_value(), Value::Integer(0));
     }
 
+    #[test]
+    fn test_apply_affinity_char() {
+        let mut reg = Register::Value(Value::Text("12abc".into()));
+        assert!(!apply_affinity_char(&mut reg, Affinity::Integer));
+        assert_eq!(*reg.get_owned_value(), Value::Text("12abc".into()));
+
+        let mut reg = Register::Value(Value::Text(" 1e2 ".into()));
+        assert!(apply_affinity_char(&mut reg, Affinity::Numeric));
+        assert_eq!(*reg.get_owned_value(), Value::Integer(100));
+
+        let mut reg = Register::Value(Value::Text("12".into()));
+        assert!(apply_affinity_char(&mut reg, Affinity::Real));
+        assert_eq!(*reg.get_owned_value(), Value::Float(12.0));
+
+        let mut reg = Register::Value(Value::Integer(12));
+        assert!(apply_affinity_char(&mut reg, Affinity::Text));
+        assert_eq!(*reg.get_owned_value(), Value::Text("12".into()));
+
+        let mut reg = Register::Value(Value::Float(9007199254740993.0));
+        assert!(apply_affinity_char(&mut reg, Affinity::Numeric));
+        assert_eq!(*reg.get_owned_value(), Value::Integer(9007199254740992));
+
+        let mut reg = Register::Value(Value::Blob(b"12".to_vec()));
+        assert!(apply_affinity_char(&mut reg, Affinity::Integer));
+        assert_eq!(*reg.get_owned_value(), Value::Blob(b"12".to_vec()));
+    }
+
     #[test]
     fn test_exec_add() {
         let inputs = vec![
@@ -8244,7 +8667,11 @@ mod tests {
         assert_eq!(input.exec_quote(), expected);
 
         let input = Value::Integer(123);
-        let expected = Value::Integer(123);
+        let expected = Value::build_text("123");
+        assert_eq!(input.exec_quote(), expected);
+
+        let input = Value::Blob(vec![0x0a, 0xff]);
+        let expected = Value::build_text("X'0AFF'");
         assert_eq!(input.exec_quote(), expected);
 
         let input = Value::build_text("hello''world");
@@ -8496,6 +8923,14 @@ mod tests {
         let input = Value::Null;
         let expected = Value::Null;
         assert_eq!(input.exec_unhex(None), expected);
+
+        let input = Value::build_text("12-34 56");
+        let expected = Value::Blob(vec![0x12, 0x34, 0x56]);
+        assert_eq!(input.exec_unhex(Some(&Value::build_text("- "))), expected);
+
+        let input = Value::build_text("1-234");
+        let expected = Value::Null;
+        assert_eq!(input.exec_unhex(Some(&Value::build_text("-"))), expected);
     }
 
     #[test]
@@ -8542,34 +8977,41 @@ mod tests {
 
     #[test]
     fn test_like_with_escape_or_regexmeta_chars() {
-        assert!(Value::exec_like(None, r#"\%A"#, r#"\A"#));
-        assert!(Value::exec_like(None, "%a%a", "aaaa"));
+        assert!(Value::exec_like(None, r#"\%A"#, r#"\A"#, false));
+        assert!(Value::exec_like(None, "%a%a", "aaaa", false));
     }
 
     #[test]
     fn test_like_no_cache() {
-        assert!(Value::exec_like(None, "a%", "aaaa"));
-        assert!(Value::exec_like(None, "%a%a", "aaaa"));
-        assert!(!Value::exec_like(None, "%a.a", "aaaa"));
-        assert!(!Value::exec_like(None, "a.a%", "aaaa"));
-        assert!(!Value::exec_like(None, "%a.ab", "aaaa"));
+        assert!(Value::exec_like(None, "a%", "aaaa", false));
+        assert!(Value::exec_like(None, "%a%a", "aaaa", false));
+        assert!(!Value::exec_like(None, "%a.a", "aaaa", false));
+        assert!(!Value::exec_like(None, "a.a%", "aaaa", false));
+        assert!(!Value::exec_like(None, "%a.ab", "aaaa", false));
+    }
+
+    #[test]
+    fn test_like_case_sensitive() {
+        assert!(Value::exec_like(None, "A%", "aaaa", false));
+        assert!(!Value::exec_like(None, "A%", "aaaa", true));
+        assert!(Value::exec_like(None, "a%", "aaaa", true));
     }
 
     #[test]
     fn test_like_with_cache() {
         let mut cache = HashMap::new();
-        assert!(Value::exec_like(Some(&mut cache), "a%", "aaaa"));
-        assert!(Value::exec_like(Some(&mut cache), "%a%a", "aaaa"));
-        assert!(!Value::exec_like(Some(&mut cache), "%a.a", "aaaa"));
-        assert!(!Value::exec_like(Some(&mut cache), "a.a%", "aaaa"));
-        assert!(!Value::exec_like(Some(&mut cache), "%a.ab", "aaaa"));
+        assert!(Value::exec_like(Some(&mut cache), "a%", "aaaa", false));
+        assert!(Value::exec_like(Some(&mut cache), "%a%a", "aaaa", false));
+        assert!(!Value::exec_like(Some(&mut cache), "%a.a", "aaaa", false));
+        assert!(!Value::exec_like(Some(&mut cache), "a.a%", "aaaa", false));
+        assert!(!Value::exec_like(Some(&mut cache), "%a.ab", "aaaa", false));
 
         // again after values have been cached
-        assert!(Value::exec_like(Some(&mut cache), "a%", "aaaa"));
-        assert!(Value::exec_like(Some(&mut cache), "%a%a", "aaaa"));
-        assert!(!Value::exec_like(Some(&mut cache), "%a.a", "aaaa"));
-        assert!(!Value::exec_like(Some(&mut cache), "a.a%", "aaaa"));
-        assert!(!Value::exec_like(Some(&mut cache), "%a.ab", "aaaa"));
+        assert!(Value::exec_like(Some(&mut cache), "a%", "aaaa", false));
+        assert!(Value::exec_like(Some(&mut cache), "%a%a", "aaaa", false));
+        assert!(!Value::exec_like(Some(&mut cache), "%a.a", "aaaa", false));
+        assert!(!Value::exec_like(Some(&mut cache), "a.a%", "aaaa", false));
+        assert!(!Value::exec_like(Some(&mut cache), "%a.ab", "aaaa", false));
     }
 
     #[test]
@@ -8956,39 +9398,39 @@ mod tests {
     fn test_exec_zeroblob() {
         let input = Value::Integer(0);
         let expected = Value::Blob(vec![]);
-        assert_eq!(input.exec_zeroblob(), expected);
+        assert_eq!(input.exec_zeroblob().unwrap(), expected);
 
         let input = Value::Null;
         let expected = Value::Blob(vec![]);
-        assert_eq!(input.exec_zeroblob(), expected);
+        assert_eq!(input.exec_zeroblob().unwrap(), expected);
 
         let input = Value::Integer(4);
         let expected = Value::Blob(vec![0; 4]);
-        assert_eq!(input.exec_zeroblob(), expected);
+        assert_eq!(input.exec_zeroblob().unwrap(), expected);
 
         let input = Value::Integer(-1);
         let expected = Value::Blob(vec![]);
-        assert_eq!(input.exec_zeroblob(), expected);
+        assert_eq!(input.exec_zeroblob().unwrap(), expected);
 
         let input = Value::build_text("5");
         let expected = Value::Blob(vec![0; 5]);
-        assert_eq!(input.exec_zeroblob(), expected);
+        assert_eq!(input.exec_zeroblob().unwrap(), expected);
 
         let input = Value::build_text("-5");
         let expected = Value::Blob(vec![]);
-        assert_eq!(input.exec_zeroblob(), expected);
+        assert_eq!(input.exec_zeroblob().unwrap(), expected);
 
         let input = Value::build_text("text");
         let expected = Value::Blob(vec![]);
-        assert_eq!(input.exec_zeroblob(), expected);
+        assert_eq!(input.exec_zeroblob().unwrap(), expected);
 
         let input = Value::Float(2.6);
         let expected = Value::Blob(vec![0; 2]);
-        assert_eq!(input.exec_zeroblob(), expected);
+        assert_eq!(input.exec_zeroblob().unwrap(), expected);
 
         let input = Value::Blob(vec![1]);
         let expected = Value::Blob(vec![]);
-        assert_eq!(input.exec_zeroblob(), expected);
+        assert_eq!(input.exec_zeroblob().unwrap(), expected);
     }
 
     #[test]
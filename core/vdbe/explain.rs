@@ -1,6 +1,9 @@
-use turso_sqlite3_parser::ast::SortOrder;
+use turso_sqlite3_parser::ast::{ResolveType, SortOrder};
 
-use crate::vdbe::{builder::CursorType, insn::RegisterOrLiteral};
+use crate::vdbe::{
+    builder::CursorType,
+    insn::{ChangeCount, RegisterOrLiteral},
+};
 
 use super::{Insn, InsnReference, Program, Value};
 use crate::function::{Func, ScalarFunc};
@@ -218,6 +221,15 @@ pub fn insn_to_str(
                     target_pc.as_debug_int()
                 ),
             ),
+            Insn::MemMax { dest_reg, src_reg } => (
+                "MemMax",
+                *dest_reg as i32,
+                *src_reg as i32,
+                0,
+                Value::build_text(""),
+                0,
+                format!("r[{dest_reg}]=Max(r[{dest_reg}],r[{src_reg}])"),
+            ),
             Insn::Eq {
                 lhs,
                 rhs,
@@ -357,11 +369,12 @@ pub fn insn_to_str(
             Insn::OpenRead {
                 cursor_id,
                 root_page,
+                db,
             } => (
                 "OpenRead",
                 *cursor_id as i32,
                 *root_page as i32,
-                0,
+                *db as i32,
                 Value::build_text(""),
                 0,
                 {
@@ -623,10 +636,11 @@ pub fn insn_to_str(
             Insn::Halt {
                 err_code,
                 description,
+                on_error,
             } => (
                 "Halt",
                 *err_code as i32,
-                0,
+                on_error_code(*on_error),
                 0,
                 Value::build_text(description),
                 0,
@@ -636,23 +650,24 @@ pub fn insn_to_str(
                 err_code,
                 target_reg,
                 description,
+                on_error,
             } => (
                 "HaltIfNull",
                 *err_code as i32,
-                0,
+                on_error_code(*on_error),
                 *target_reg as i32,
                 Value::build_text(description),
                 0,
                 "".to_string(),
             ),
-            Insn::Transaction { write } => (
+            Insn::Transaction { db, write } => (
                 "Transaction",
-                0,
+                *db as i32,
                 *write as i32,
                 0,
                 Value::build_text(""),
                 0,
-                format!("write={write}"),
+                format!("iDb={db} write={write}"),
             ),
             Insn::Goto { target_pc } => (
                 "Goto",
@@ -945,6 +960,28 @@ pub fn insn_to_str(
                 0,
                 format!("accum=r[{}]", *register),
             ),
+            Insn::AggInverse { acc_reg, col, func } => (
+                "AggInverse",
+                0,
+                *col as i32,
+                *acc_reg as i32,
+                Value::build_text(func.to_string()),
+                0,
+                format!("accum=r[{}] inverse(r[{}])", *acc_reg, *col),
+            ),
+            Insn::AggValue {
+                acc_reg,
+                dest,
+                func,
+            } => (
+                "AggValue",
+                *acc_reg as i32,
+                0,
+                *dest as i32,
+                Value::build_text(func.to_string()),
+                0,
+                format!("r[{}]=value(r[{}])", *dest, *acc_reg),
+            ),
             Insn::SorterOpen {
                 cursor_id,
                 columns,
@@ -1107,13 +1144,18 @@ pub fn insn_to_str(
                 flag.0 as u16,
                 format!("intkey=r[{key_reg}] data=r[{record_reg}]"),
             ),
-            Insn::Delete { cursor_id } => (
+            Insn::Delete {
+                cursor_id,
+                nchange,
+                table_name,
+                is_update,
+            } => (
                 "Delete",
                 *cursor_id as i32,
+                (*nchange != ChangeCount::None) as i32,
                 0,
-                0,
-                Value::build_text(""),
-                0,
+                Value::build_text(table_name.as_deref().unwrap_or("")),
+                *is_update as u16,
                 "".to_string(),
             ),
             Insn::IdxDelete {
@@ -1214,7 +1256,7 @@ pub fn insn_to_str(
                 cursor_id,
                 root_page,
                 name,
-                ..
+                db,
             } => (
                 "OpenWrite",
                 *cursor_id as i32,
@@ -1222,7 +1264,7 @@ pub fn insn_to_str(
                     RegisterOrLiteral::Literal(i) => *i as _,
                     RegisterOrLiteral::Register(i) => *i as _,
                 },
-                0,
+                *db as i32,
                 Value::build_text(""),
                 0,
                 format!("root={root_page}; {name}"),
@@ -1252,17 +1294,51 @@ pub fn insn_to_str(
             Insn::Destroy {
                 root,
                 former_root_reg,
-                is_temp,
+                db,
             } => (
                 "Destroy",
                 *root as i32,
                 *former_root_reg as i32,
-                *is_temp as i32,
+                *db as i32,
                 Value::build_text(""),
                 0,
-                format!(
-                    "root iDb={root} former_root={former_root_reg} is_temp={is_temp}"
-                ),
+                format!("root={root} former_root={former_root_reg} iDb={db}"),
+            ),
+            Insn::IncrVacuum { db, target_pc } => (
+                "IncrVacuum",
+                *db as i32,
+                target_pc.as_debug_int(),
+                0,
+                Value::build_text(""),
+                0,
+                "".to_string(),
+            ),
+            Insn::Vacuum { db, into } => (
+                "Vacuum",
+                *db as i32,
+                into.map_or(0, |reg| reg as i32),
+                0,
+                Value::build_text(""),
+                0,
+                "".to_string(),
+            ),
+            Insn::Attach { file_reg, name_reg } => (
+                "Attach",
+                *file_reg as i32,
+                *name_reg as i32,
+                0,
+                Value::build_text(""),
+                0,
+                format!("attach r[{file_reg}] as r[{name_reg}]"),
+            ),
+            Insn::Detach { name_reg } => (
+                "Detach",
+                *name_reg as i32,
+                0,
+                0,
+                Value::build_text(""),
+                0,
+                format!("detach r[{name_reg}]"),
             ),
             Insn::DropTable {
                 db,
@@ -1287,6 +1363,24 @@ pub fn insn_to_str(
                 0,
                 format!("DROP INDEX {}", index.name),
             ),
+            Insn::DropTrigger { db, trigger_name } => (
+                "DropTrigger",
+                *db as i32,
+                0,
+                0,
+                Value::build_text(trigger_name),
+                0,
+                format!("DROP TRIGGER {trigger_name}"),
+            ),
+            Insn::DropView { db, view_name } => (
+                "DropView",
+                *db as i32,
+                0,
+                0,
+                Value::build_text(view_name),
+                0,
+                format!("DROP VIEW {view_name}"),
+            ),
             Insn::Close { cursor_id } => (
                 "Close",
                 *cursor_id as i32,
@@ -1326,6 +1420,15 @@ pub fn insn_to_str(
                 0,
                 where_clause.clone().unwrap_or("NULL".to_string()),
             ),
+            Insn::LoadAnalysis { db } => (
+                "LoadAnalysis",
+                *db as i32,
+                0,
+                0,
+                Value::build_text(""),
+                0,
+                "".to_string(),
+            ),
             Insn::Prev {
                 cursor_id,
                 pc_if_prev,
@@ -1448,14 +1551,24 @@ pub fn insn_to_str(
             Insn::AutoCommit {
                 auto_commit,
                 rollback,
+                concurrent,
             } => (
                 "AutoCommit",
                 *auto_commit as i32,
                 *rollback as i32,
-                0,
+                *concurrent as i32,
                 Value::build_text(""),
                 0,
-                format!("auto_commit={auto_commit}, rollback={rollback}"),
+                format!("auto_commit={auto_commit}, rollback={rollback}, concurrent={concurrent}"),
+            ),
+            Insn::Savepoint { op, name } => (
+                "Savepoint",
+                *op as i32,
+                0,
+                0,
+                Value::build_text(name),
+                0,
+                format!("{op:?} {name}"),
             ),
             Insn::OpenEphemeral {
                 cursor_id,
@@ -1589,6 +1702,7 @@ pub fn insn_to_str(
                 max_errors,
                 roots,
                 message_register,
+                ..
             } => (
                 "IntegrityCk",
                 *max_errors as i32,
@@ -1620,3 +1734,14 @@ pub fn insn_to_str(
         manual_comment.map_or(comment.to_string(), |mc| format!("{comment}; {mc}"))
     )
 }
+
+/// The number SQLite shows for a conflict resolution in the P2 of Halt, its `OE_` constant.
+fn on_error_code(on_error: ResolveType) -> i32 {
+    match on_error {
+        ResolveType::Rollback => 1,
+        ResolveType::Abort => 2,
+        ResolveType::Fail => 3,
+        ResolveType::Ignore => 4,
+        ResolveType::Replace => 5,
+    }
+}
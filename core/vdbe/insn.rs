@@ -12,7 +12,7 @@ use crate::{
     Value,
 };
 use turso_macros::Description;
-use turso_sqlite3_parser::ast::SortOrder;
+use turso_sqlite3_parser::ast::{ResolveType, SortOrder};
 
 /// Flags provided to comparison instructions (e.g. Eq, Ne) which determine behavior related to NULL values.
 #[derive(Clone, Copy, Debug, Default)]
@@ -64,6 +64,7 @@ impl IdxInsertFlags {
     pub const NCHANGE: u8 = 0x02; // Increment the change counter
     pub const USE_SEEK: u8 = 0x04; // Skip seek if last one was same key
     pub const NO_OP_DUPLICATE: u8 = 0x08; // Do not error on duplicate key
+    pub const TRIGGER_CHANGE: u8 = 0x10; // Increment the change counter of the triggers
     pub fn new() -> Self {
         IdxInsertFlags(0)
     }
@@ -86,14 +87,26 @@ impl IdxInsertFlags {
         }
         self
     }
-    pub fn nchange(mut self, change: bool) -> Self {
-        if change {
-            self.0 |= IdxInsertFlags::NCHANGE;
-        } else {
-            self.0 &= !IdxInsertFlags::NCHANGE;
+    pub fn nchange(mut self, count: ChangeCount) -> Self {
+        self.0 &= !(IdxInsertFlags::NCHANGE | IdxInsertFlags::TRIGGER_CHANGE);
+        match count {
+            ChangeCount::None => {}
+            ChangeCount::Statement => self.0 |= IdxInsertFlags::NCHANGE,
+            ChangeCount::Trigger => self.0 |= IdxInsertFlags::TRIGGER_CHANGE,
         }
         self
     }
+    /// The change counter the inserted entry counts towards, which is only set for the rows
+    /// of WITHOUT ROWID tables.
+    pub fn change_count(&self) -> ChangeCount {
+        if self.has(IdxInsertFlags::NCHANGE) {
+            ChangeCount::Statement
+        } else if self.has(IdxInsertFlags::TRIGGER_CHANGE) {
+            ChangeCount::Trigger
+        } else {
+            ChangeCount::None
+        }
+    }
     /// If this is set, we will not error on duplicate key.
     /// This is a bit of a hack we use to make ephemeral indexes for UNION work --
     /// instead we should allow overwriting index interior cells, which we currently don't;
@@ -104,12 +117,29 @@ impl IdxInsertFlags {
     }
 }
 
+/// Which change counter a row inserted or deleted by an instruction counts towards.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ChangeCount {
+    /// The row is not a change, e.g. it belongs to an ephemeral table or the schema, or it is
+    /// deleted by the REPLACE conflict resolution.
+    #[default]
+    None,
+    /// The row is changed by the statement, see [crate::Connection::changes].
+    Statement,
+    /// The row is changed by a trigger, which only counts towards
+    /// [crate::Connection::total_changes].
+    Trigger,
+}
+
 #[derive(Clone, Copy, Debug, Default)]
 pub struct InsertFlags(pub u8);
 
 impl InsertFlags {
     pub const UPDATE: u8 = 0x01; // Flag indicating this is part of an UPDATE statement
     pub const REQUIRE_SEEK: u8 = 0x02; // Flag indicating that a seek is required to insert the row
+    pub const NCHANGE: u8 = 0x04; // Flag indicating that the row is changed by the statement
+    pub const TRIGGER_CHANGE: u8 = 0x08; // Flag indicating that the row is changed by a trigger
+    pub const LAST_ROWID: u8 = 0x10; // Flag indicating that the rowid becomes the last_insert_rowid()
 
     pub fn new() -> Self {
         InsertFlags(0)
@@ -123,6 +153,36 @@ impl InsertFlags {
         self.0 |= InsertFlags::REQUIRE_SEEK;
         self
     }
+
+    pub fn update(mut self) -> Self {
+        self.0 |= InsertFlags::UPDATE;
+        self
+    }
+
+    pub fn nchange(mut self, count: ChangeCount) -> Self {
+        match count {
+            ChangeCount::None => {}
+            ChangeCount::Statement => self.0 |= InsertFlags::NCHANGE,
+            ChangeCount::Trigger => self.0 |= InsertFlags::TRIGGER_CHANGE,
+        }
+        self
+    }
+
+    pub fn last_rowid(mut self) -> Self {
+        self.0 |= InsertFlags::LAST_ROWID;
+        self
+    }
+
+    /// The change counter the inserted row counts towards.
+    pub fn change_count(&self) -> ChangeCount {
+        if self.has(InsertFlags::NCHANGE) {
+            ChangeCount::Statement
+        } else if self.has(InsertFlags::TRIGGER_CHANGE) {
+            ChangeCount::Trigger
+        } else {
+            ChangeCount::None
+        }
+    }
 }
 
 #[derive(Clone, Copy, Debug)]
@@ -245,6 +305,12 @@ pub enum Insn {
         target_pc: BranchOffset,
         decrement_by: usize,
     },
+    /// Set the integer value held in dest_reg to the maximum of its current value and the
+    /// integer value in src_reg.
+    MemMax {
+        dest_reg: usize,
+        src_reg: usize,
+    },
     /// If the given register is not NULL, jump to the given PC.
     NotNull {
         reg: usize,
@@ -330,6 +396,8 @@ pub enum Insn {
     OpenRead {
         cursor_id: CursorID,
         root_page: PageIdx,
+        /// The database of the b-tree (P3).
+        db: usize,
     },
 
     /// Open a cursor for a virtual table.
@@ -444,28 +512,41 @@ pub enum Insn {
         pc_if_prev: BranchOffset,
     },
 
-    /// Halt the program.
+    /// Halt the program. A non-zero `err_code` fails the statement, whose changes are then
+    /// resolved by `on_error`: ABORT undoes them, FAIL keeps them and ROLLBACK rolls back the
+    /// whole transaction.
     Halt {
         err_code: usize,
         description: String,
+        on_error: ResolveType,
     },
 
     /// Halt the program if P3 is null.
     HaltIfNull {
-        target_reg: usize,   // P3
-        description: String, // p4
-        err_code: usize,     // p1
+        target_reg: usize,     // P3
+        description: String,   // p4
+        err_code: usize,       // p1
+        on_error: ResolveType, // p2
     },
 
-    /// Start a transaction.
+    /// Start a transaction on the P1 database.
     Transaction {
+        db: usize,
         write: bool,
     },
 
-    /// Set database auto-commit mode and potentially rollback.
+    /// Set database auto-commit mode and potentially rollback. `concurrent` starts the
+    /// transaction in `BEGIN CONCURRENT` mode.
     AutoCommit {
         auto_commit: bool,
         rollback: bool,
+        concurrent: bool,
+    },
+
+    /// Open, release or roll back to the savepoint `name`.
+    Savepoint {
+        op: SavepointOp,
+        name: String,
     },
 
     /// Branch to the given PC.
@@ -664,6 +745,22 @@ pub enum Insn {
         func: AggFunc,
     },
 
+    /// Removes the row whose arguments start at `col` from the aggregate in `acc_reg`, for an
+    /// aggregate window function created with [crate::Connection::create_window_function].
+    AggInverse {
+        acc_reg: usize,
+        col: usize,
+        func: AggFunc,
+    },
+
+    /// Stores the current value of the aggregate in `acc_reg` in `dest`, leaving the aggregate
+    /// as it is so that more rows can be added to it or removed from it.
+    AggValue {
+        acc_reg: usize,
+        dest: usize,
+        func: AggFunc,
+    },
+
     /// Open a sorter.
     SorterOpen {
         cursor_id: CursorID,                   // P1
@@ -737,6 +834,14 @@ pub enum Insn {
 
     Delete {
         cursor_id: CursorID,
+        /// Whether the deleted row is counted as a change.
+        nchange: ChangeCount,
+        /// The table the row is deleted from, which is reported to the hooks of the connection.
+        /// `None` for the schema and ephemeral tables, whose changes aren't reported.
+        table_name: Option<String>,
+        /// Whether the row is deleted by an UPDATE that changes its rowid, which inserts it again
+        /// right after.
+        is_update: bool,
     },
 
     /// If P5 is not zero, then raise an SQLITE_CORRUPT_INDEX error if no matching index entry
@@ -753,7 +858,7 @@ pub enum Insn {
     NewRowid {
         cursor: CursorID,        // P1
         rowid_reg: usize,        // P2  Destination register to store the new rowid
-        prev_largest_reg: usize, // P3 Previous largest rowid in the table (Not used for now)
+        prev_largest_reg: usize, // P3 If non-zero, the largest rowid ever used by an AUTOINCREMENT table; updated with the new rowid
     },
 
     MustBeInt {
@@ -792,6 +897,8 @@ pub enum Insn {
         cursor_id: CursorID,
         root_page: RegisterOrLiteral<PageIdx>,
         name: String,
+        /// The database of the b-tree (P3).
+        db: usize,
     },
 
     Copy {
@@ -802,7 +909,7 @@ pub enum Insn {
 
     /// Allocate a new b-tree.
     CreateBtree {
-        /// Allocate b-tree in main database if zero or in temp database if non-zero (P1).
+        /// The database to allocate the b-tree in (P1).
         db: usize,
         /// The root page of the new b-tree (P2).
         root: usize,
@@ -816,8 +923,33 @@ pub enum Insn {
         root: usize,
         /// Register to store the former value of any moved root page (for AUTOVACUUM)
         former_root_reg: usize,
-        /// Whether this is a temporary table (1) or main database table (0)
-        is_temp: usize,
+        /// The database of the table/index (P3)
+        db: usize,
+    },
+
+    /// Perform a single step of the incremental vacuum procedure on the P1 database. If the vacuum
+    /// has finished, jump to instruction P2. Otherwise, fall through to the next instruction.
+    IncrVacuum {
+        db: usize,
+        target_pc: BranchOffset,
+    },
+
+    /// Vacuum the P1 database. If P2 is set, the vacuumed copy is written to the file named by
+    /// register P2 instead of replacing the database (`VACUUM INTO`).
+    Vacuum {
+        db: usize,
+        into: Option<usize>,
+    },
+
+    /// Attach the database file named by register P1 under the schema name in register P2.
+    Attach {
+        file_reg: usize,
+        name_reg: usize,
+    },
+
+    /// Detach the database with the schema name in register P1.
+    Detach {
+        name_reg: usize,
     },
 
     ///  Drop a table
@@ -837,6 +969,20 @@ pub enum Insn {
         //  The name of the index being dropped
         index: Arc<Index>,
     },
+    ///  Drop a trigger
+    DropTrigger {
+        ///  The database within which this trigger needs to be dropped (P1).
+        db: usize,
+        //  The name of the trigger being dropped
+        trigger_name: String,
+    },
+    ///  Drop a view
+    DropView {
+        ///  The database within which this view needs to be dropped (P1).
+        db: usize,
+        //  The name of the view being dropped
+        view_name: String,
+    },
 
     /// Close a cursor.
     Close {
@@ -856,6 +1002,11 @@ pub enum Insn {
         where_clause: Option<String>,
     },
 
+    /// Read the sqlite_stat1 table of database P1 and load its content into the schema.
+    LoadAnalysis {
+        db: usize,
+    },
+
     /// Place the result of lhs >> rhs in dest register.
     ShiftRight {
         lhs: usize,
@@ -987,6 +1138,9 @@ pub enum Insn {
     IntegrityCk {
         max_errors: usize,
         roots: Vec<usize>,
+        /// The indexes that must have as many entries as their table has rows, as the name of the
+        /// index and the positions of the table and of the index in `roots`.
+        index_counts: Vec<(String, usize, usize)>,
         message_register: usize,
     },
 }
@@ -1003,6 +1157,8 @@ impl Insn {
             Insn::Multiply { .. } => execute::op_multiply,
             Insn::Divide { .. } => execute::op_divide,
             Insn::DropIndex { .. } => execute::op_drop_index,
+            Insn::DropTrigger { .. } => execute::op_drop_trigger,
+            Insn::DropView { .. } => execute::op_drop_view,
             Insn::Compare { .. } => execute::op_compare,
             Insn::BitAnd { .. } => execute::op_bit_and,
             Insn::BitOr { .. } => execute::op_bit_or,
@@ -1012,6 +1168,7 @@ impl Insn {
             Insn::Jump { .. } => execute::op_jump,
             Insn::Move { .. } => execute::op_move,
             Insn::IfPos { .. } => execute::op_if_pos,
+            Insn::MemMax { .. } => execute::op_mem_max,
             Insn::NotNull { .. } => execute::op_not_null,
             Insn::Eq { .. }
             | Insn::Ne { .. }
@@ -1043,6 +1200,7 @@ impl Insn {
             Insn::HaltIfNull { .. } => execute::op_halt_if_null,
             Insn::Transaction { .. } => execute::op_transaction,
             Insn::AutoCommit { .. } => execute::op_auto_commit,
+            Insn::Savepoint { .. } => execute::op_savepoint,
             Insn::Goto { .. } => execute::op_goto,
             Insn::Gosub { .. } => execute::op_gosub,
             Insn::Return { .. } => execute::op_return,
@@ -1068,6 +1226,8 @@ impl Insn {
             Insn::DecrJumpZero { .. } => execute::op_decr_jump_zero,
             Insn::AggStep { .. } => execute::op_agg_step,
             Insn::AggFinal { .. } => execute::op_agg_final,
+            Insn::AggInverse { .. } => execute::op_agg_inverse,
+            Insn::AggValue { .. } => execute::op_agg_value,
             Insn::SorterOpen { .. } => execute::op_sorter_open,
             Insn::SorterInsert { .. } => execute::op_sorter_insert,
             Insn::SorterSort { .. } => execute::op_sorter_sort,
@@ -1091,11 +1251,16 @@ impl Insn {
             Insn::Copy { .. } => execute::op_copy,
             Insn::CreateBtree { .. } => execute::op_create_btree,
             Insn::Destroy { .. } => execute::op_destroy,
+            Insn::IncrVacuum { .. } => execute::op_incr_vacuum,
+            Insn::Vacuum { .. } => execute::op_vacuum,
+            Insn::Attach { .. } => execute::op_attach,
+            Insn::Detach { .. } => execute::op_detach,
 
             Insn::DropTable { .. } => execute::op_drop_table,
             Insn::Close { .. } => execute::op_close,
             Insn::IsNull { .. } => execute::op_is_null,
             Insn::ParseSchema { .. } => execute::op_parse_schema,
+            Insn::LoadAnalysis { .. } => execute::op_load_analysis,
             Insn::ShiftRight { .. } => execute::op_shift_right,
             Insn::ShiftLeft { .. } => execute::op_shift_left,
             Insn::Variable { .. } => execute::op_variable,
@@ -1119,6 +1284,16 @@ impl Insn {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SavepointOp {
+    /// `SAVEPOINT name`
+    Begin,
+    /// `RELEASE name`
+    Release,
+    /// `ROLLBACK TO name`
+    Rollback,
+}
+
 // TODO: Add remaining cookies.
 #[derive(Description, Debug, Clone, Copy)]
 pub enum Cookie {
@@ -1136,4 +1311,6 @@ pub enum Cookie {
     UserVersion = 6,
     /// The auto-vacuum mode setting.
     IncrementalVacuum = 7,
+    /// The "Application ID" as read and set by the application_id pragma.
+    ApplicationId = 8,
 }
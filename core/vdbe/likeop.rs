@@ -2,7 +2,7 @@ use std::collections::HashMap;
 
 use regex::{Regex, RegexBuilder};
 
-use crate::{types::Value, LimboError};
+use crate::{types::Value, unicode, LimboError};
 
 pub fn construct_like_escape_arg(escape_value: &Value) -> Result<char, LimboError> {
     match escape_value {
@@ -22,11 +22,16 @@ pub fn construct_like_escape_arg(escape_value: &Value) -> Result<char, LimboErro
 }
 
 // Implements LIKE pattern matching with escape
-pub fn exec_like_with_escape(pattern: &str, text: &str, escape: char) -> bool {
-    construct_like_regex_with_escape(pattern, escape).is_match(text)
+pub fn exec_like_with_escape(
+    pattern: &str,
+    text: &str,
+    escape: char,
+    case_sensitive: bool,
+) -> bool {
+    construct_like_regex_with_escape(pattern, escape, case_sensitive).is_match(text)
 }
 
-fn construct_like_regex_with_escape(pattern: &str, escape: char) -> Regex {
+fn construct_like_regex_with_escape(pattern: &str, escape: char, case_sensitive: bool) -> Regex {
     let mut regex_pattern = String::with_capacity(pattern.len() * 2);
 
     regex_pattern.push('^');
@@ -37,27 +42,18 @@ fn construct_like_regex_with_escape(pattern: &str, escape: char) -> Regex {
         match ch {
             esc_ch if esc_ch == escape => {
                 if let Some(escaped_char) = chars.next() {
-                    if regex_syntax::is_meta_character(escaped_char) {
-                        regex_pattern.push('\\');
-                    }
-                    regex_pattern.push(escaped_char);
+                    push_like_literal(escaped_char, case_sensitive, &mut regex_pattern);
                 }
             }
             '%' => regex_pattern.push_str(".*"),
             '_' => regex_pattern.push('.'),
-            c => {
-                if regex_syntax::is_meta_character(c) {
-                    regex_pattern.push('\\');
-                }
-                regex_pattern.push(c);
-            }
+            c => push_like_literal(c, case_sensitive, &mut regex_pattern),
         }
     }
 
     regex_pattern.push('$');
 
     RegexBuilder::new(&regex_pattern)
-        .case_insensitive(true)
         .dot_matches_new_line(true)
         .build()
         .unwrap()
@@ -95,6 +91,22 @@ fn push_char_to_regex_pattern(c: char, regex_pattern: &mut String) {
     regex_pattern.push(c);
 }
 
+/// Pushes what matches the literal character `c` of a LIKE pattern. Like SQLite's builtin LIKE,
+/// a case insensitive match only folds the case of ASCII letters, so that `'ä' LIKE 'Ä'` is false,
+/// unless the `unicode` feature is enabled or like() is overridden.
+pub fn push_like_literal(c: char, case_sensitive: bool, regex_pattern: &mut String) {
+    let mut other_cases = unicode::other_cases(c).peekable();
+    if case_sensitive || other_cases.peek().is_none() {
+        push_char_to_regex_pattern(c, regex_pattern);
+    } else {
+        // Only letters have other cases, so nothing needs escaping in the class
+        regex_pattern.push('[');
+        regex_pattern.push(c);
+        regex_pattern.extend(other_cases);
+        regex_pattern.push(']');
+    }
+}
+
 fn construct_glob_regex(pattern: &str) -> Result<Regex, LimboError> {
     let mut regex_pattern = String::with_capacity(pattern.len() * 2);
 
@@ -187,20 +199,32 @@ mod test {
 
     #[test]
     fn test_exec_like_with_escape() {
-        assert!(exec_like_with_escape("abcX%", "abc%", 'X'));
-        assert!(!exec_like_with_escape("abcX%", "abc5", 'X'));
-        assert!(!exec_like_with_escape("abcX%", "abc", 'X'));
-        assert!(!exec_like_with_escape("abcX%", "abcX%", 'X'));
-        assert!(!exec_like_with_escape("abcX%", "abc%%", 'X'));
-        assert!(exec_like_with_escape("abcX_", "abc_", 'X'));
-        assert!(!exec_like_with_escape("abcX_", "abc5", 'X'));
-        assert!(!exec_like_with_escape("abcX_", "abc", 'X'));
-        assert!(!exec_like_with_escape("abcX_", "abcX_", 'X'));
-        assert!(!exec_like_with_escape("abcX_", "abc__", 'X'));
-        assert!(exec_like_with_escape("abcXX", "abcX", 'X'));
-        assert!(!exec_like_with_escape("abcXX", "abc5", 'X'));
-        assert!(!exec_like_with_escape("abcXX", "abc", 'X'));
-        assert!(!exec_like_with_escape("abcXX", "abcXX", 'X'));
+        assert!(exec_like_with_escape("abcX%", "abc%", 'X', false));
+        assert!(!exec_like_with_escape("abcX%", "abc5", 'X', false));
+        assert!(!exec_like_with_escape("abcX%", "abc", 'X', false));
+        assert!(!exec_like_with_escape("abcX%", "abcX%", 'X', false));
+        assert!(!exec_like_with_escape("abcX%", "abc%%", 'X', false));
+        assert!(exec_like_with_escape("abcX_", "abc_", 'X', false));
+        assert!(!exec_like_with_escape("abcX_", "abc5", 'X', false));
+        assert!(!exec_like_with_escape("abcX_", "abc", 'X', false));
+        assert!(!exec_like_with_escape("abcX_", "abcX_", 'X', false));
+        assert!(!exec_like_with_escape("abcX_", "abc__", 'X', false));
+        assert!(exec_like_with_escape("abcXX", "abcX", 'X', false));
+        assert!(!exec_like_with_escape("abcXX", "abc5", 'X', false));
+        assert!(!exec_like_with_escape("abcXX", "abc", 'X', false));
+        assert!(!exec_like_with_escape("abcXX", "abcXX", 'X', false));
+    }
+
+    #[test]
+    fn test_like_folds_ascii_case_only() {
+        assert!(exec_like_with_escape("ABCX%", "abc%", 'X', false));
+        assert!(exec_like_with_escape("abcXD", "abcd", 'X', false));
+        assert!(!exec_like_with_escape("abcXD", "abcd", 'X', true));
+        assert!(exec_like_with_escape("ä%", "äb", 'X', false));
+        assert_eq!(
+            exec_like_with_escape("ä%", "Äb", 'X', false),
+            cfg!(feature = "unicode")
+        );
     }
 
     #[test]
@@ -23,12 +23,14 @@ pub mod explain;
 pub mod insn;
 pub mod likeop;
 pub mod sorter;
+pub mod vacuum;
 
 use crate::{
     error::LimboError,
     function::{AggFunc, FuncCtx},
-    storage::{pager, sqlite3_ondisk::SmallVec},
-    translate::plan::TableReferences,
+    result::LimboResult,
+    storage::{pager, sqlite3_ondisk::SmallVec, text_encoding::TextEncodingGuard},
+    translate::{collate::CollationsGuard, plan::TableReferences},
     types::{IOResult, RawSlice, TextRef},
     vdbe::execute::{OpIdxInsertState, OpInsertState, OpNewRowidState, OpSeekState},
     RefValue,
@@ -38,12 +40,16 @@ use crate::{
     storage::pager::Pager,
     translate::plan::ResultSetColumn,
     types::{AggContext, Cursor, ImmutableRecord, Value},
-    vdbe::{builder::CursorType, insn::Insn},
+    vdbe::{
+        builder::CursorType,
+        insn::{ChangeCount, Insn},
+    },
 };
 
 #[cfg(feature = "json")]
 use crate::json::JsonCacheCell;
-use crate::{Connection, MvStore, Result, TransactionState};
+use crate::vtab::VTabSavepointOp;
+use crate::{Connection, MvStore, Result, Schema, TransactionState};
 use builder::CursorKey;
 use execute::{
     InsnFunction, InsnFunctionStepResult, OpIdxDeleteState, OpIntegrityCheckState,
@@ -52,7 +58,7 @@ use execute::{
 
 use regex::Regex;
 use std::{
-    cell::{Cell, RefCell},
+    cell::{OnceCell, RefCell},
     collections::HashMap,
     num::NonZero,
     rc::Rc,
@@ -221,6 +227,37 @@ pub enum Register {
     Value(Value),
     Aggregate(AggContext),
     Record(ImmutableRecord),
+    ZeroBlob(ZeroBlob),
+}
+
+/// The blob of `zeroblob(N)`, whose zeros are only allocated when the value is used. At the end
+/// of a record, they are left out of it for the btree to write, so that space can be reserved
+/// for a large blob written later with incremental blob I/O without holding it in memory.
+#[derive(Debug, Clone)]
+pub struct ZeroBlob {
+    len: usize,
+    value: OnceCell<Value>,
+}
+
+impl ZeroBlob {
+    pub fn new(len: usize) -> Self {
+        Self {
+            len,
+            value: OnceCell::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn value(&self) -> &Value {
+        self.value.get_or_init(|| Value::Blob(vec![0; self.len]))
+    }
 }
 
 /// A row is a the list of registers that hold the values for a filtered row. This row is a pointer, therefore
@@ -245,8 +282,27 @@ pub struct ProgramState {
     regex_cache: RegexCache,
     pub(crate) mv_tx_id: Option<crate::mvcc::database::TxID>,
     interrupted: bool,
+    /// The number of times the connection had been interrupted when the statement started,
+    /// see [Connection::interrupt]. None until the statement is stepped.
+    interrupt_count: Option<u64>,
+    /// The number of instructions run since the statement started, for the progress handler
+    /// and [crate::Statement::stats].
+    pub(crate) n_steps: u64,
     parameters: HashMap<NonZero<usize>, Value>,
     commit_state: CommitState,
+    /// The rows changed by the statement, see [Connection::changes].
+    n_change: i64,
+    /// The rows changed by the triggers the statement fired, which only count towards
+    /// [Connection::total_changes].
+    n_trigger_change: i64,
+    /// The pager savepoint and the schema at the start of a write statement inside an explicit
+    /// transaction, which a failing statement rolls back to instead of the whole transaction.
+    pub(crate) statement_savepoint: Option<(usize, Arc<Schema>)>,
+    /// The same for each attached database the statement writes to, along with its index.
+    pub(crate) attached_statement_savepoints: Vec<(usize, usize, Arc<Schema>)>,
+    /// The number of the savepoint of the virtual tables written by a statement inside an
+    /// explicit transaction, which a failing statement rolls them back to.
+    pub(crate) vtab_statement_savepoint: Option<usize>,
     #[cfg(feature = "json")]
     json_cache: JsonCacheCell,
     op_idx_delete_state: Option<OpIdxDeleteState>,
@@ -255,6 +311,12 @@ pub struct ProgramState {
     op_new_rowid_state: OpNewRowidState,
     op_idx_insert_state: OpIdxInsertState,
     op_insert_state: OpInsertState,
+    /// The rowid of the row being deleted by [Insn::Delete], read before the row is gone for the
+    /// hooks of the connection.
+    op_delete_rowid: Option<i64>,
+    /// The row an UPDATE deleted to insert it again with another rowid, which the preupdate hook
+    /// gets as the old row.
+    preupdate_old: Option<(i64, ImmutableRecord)>,
     seek_state: OpSeekState,
 }
 
@@ -275,8 +337,15 @@ impl ProgramState {
             regex_cache: RegexCache::new(),
             mv_tx_id: None,
             interrupted: false,
+            interrupt_count: None,
+            n_steps: 0,
             parameters: HashMap::new(),
             commit_state: CommitState::Ready,
+            n_change: 0,
+            n_trigger_change: 0,
+            statement_savepoint: None,
+            attached_statement_savepoints: Vec::new(),
+            vtab_statement_savepoint: None,
             #[cfg(feature = "json")]
             json_cache: JsonCacheCell::new(),
             op_idx_delete_state: None,
@@ -285,6 +354,8 @@ impl ProgramState {
             op_new_rowid_state: OpNewRowidState::Start,
             op_idx_insert_state: OpIdxInsertState::SeekIfUnique,
             op_insert_state: OpInsertState::Insert,
+            op_delete_rowid: None,
+            preupdate_old: None,
             seek_state: OpSeekState::Start,
         }
     }
@@ -313,6 +384,10 @@ impl ProgramState {
         self.parameters.get(&index).cloned().unwrap_or(Value::Null)
     }
 
+    pub fn clear_bindings(&mut self) {
+        self.parameters.clear();
+    }
+
     pub fn reset(&mut self) {
         self.pc = 0;
         self.cursors.borrow_mut().iter_mut().for_each(|c| *c = None);
@@ -322,13 +397,31 @@ impl ProgramState {
         self.last_compare = None;
         self.deferred_seeks.iter_mut().for_each(|s| *s = None);
         self.ended_coroutine.0 = [0; 4];
+        self.once = SmallVec::new();
         self.regex_cache.like.clear();
         self.interrupted = false;
-        self.parameters.clear();
+        self.interrupt_count = None;
+        self.n_steps = 0;
+        self.n_change = 0;
+        self.n_trigger_change = 0;
+        self.op_delete_rowid = None;
+        self.preupdate_old = None;
+        self.statement_savepoint = None;
+        self.attached_statement_savepoints.clear();
+        self.vtab_statement_savepoint = None;
         #[cfg(feature = "json")]
         self.json_cache.clear()
     }
 
+    /// Counts a row changed by an instruction towards the change counters.
+    pub(crate) fn count_change(&mut self, count: ChangeCount) {
+        match count {
+            ChangeCount::None => {}
+            ChangeCount::Statement => self.n_change += 1,
+            ChangeCount::Trigger => self.n_trigger_change += 1,
+        }
+    }
+
     pub fn get_cursor(&self, cursor_id: CursorID) -> std::cell::RefMut<Cursor> {
         let cursors = self.cursors.borrow_mut();
         std::cell::RefMut::map(cursors, |c| {
@@ -348,6 +441,7 @@ impl Register {
                 assert!(!r.is_invalidated());
                 r.as_blob_value()
             }
+            Register::ZeroBlob(zero_blob) => zero_blob.value(),
             _ => panic!("register holds unexpected value: {self:?}"),
         }
     }
@@ -375,7 +469,6 @@ pub struct Program {
     pub comments: Option<Vec<(InsnReference, &'static str)>>,
     pub parameters: crate::parameters::Parameters,
     pub connection: Arc<Connection>,
-    pub n_change: Cell<i64>,
     pub change_cnt_on: bool,
     pub result_columns: Vec<ResultSetColumn>,
     pub table_references: TableReferences,
@@ -389,6 +482,11 @@ impl Program {
         mv_store: Option<Rc<MvStore>>,
         pager: Rc<Pager>,
     ) -> Result<StepResult> {
+        let interrupt_count = self.connection.interrupt_count();
+        let interrupt_count = *state.interrupt_count.get_or_insert(interrupt_count);
+        let progress_handler = self.connection.get_progress_handler();
+        let _collations = CollationsGuard::new(self.connection.get_collations());
+        let _text_encoding = TextEncodingGuard::new(pager.text_encoding()?);
         loop {
             if self.connection.closed.get() {
                 // Connection is closed for whatever reason, rollback the transaction.
@@ -401,6 +499,19 @@ impl Program {
             if state.is_interrupted() {
                 return Ok(StepResult::Interrupt);
             }
+            // The statements running when the connection is interrupted are interrupted, and so
+            // are those whose progress handler asks for it.
+            let mut interrupted = self.connection.interrupt_count() != interrupt_count;
+            state.n_steps += 1;
+            if let Some((n, handler)) = &progress_handler {
+                interrupted = interrupted || (state.n_steps % n == 0 && (**handler)());
+            }
+            if interrupted {
+                // Like a failed statement, an interrupted one doesn't leave its changes behind.
+                state.interrupt();
+                self.abort_statement(state, &pager, false)?;
+                return Ok(StepResult::Interrupt);
+            }
             // invalidate row
             let _ = state.result_row.take();
             let (insn, insn_function) = &self.insns[state.pc as usize];
@@ -413,22 +524,78 @@ impl Program {
                 Ok(InsnFunctionStepResult::Interrupt) => return Ok(StepResult::Interrupt),
                 Ok(InsnFunctionStepResult::Busy) => return Ok(StepResult::Busy),
                 Err(err) => {
-                    match err {
-                        LimboError::TxError(_) => {}
-                        _ => {
-                            let state = self.connection.transaction_state.get();
-                            if let TransactionState::Write { schema_did_change } = state {
-                                pager.rollback(schema_did_change, &self.connection)?
-                            }
-                        }
-                    }
-                    let err = Err(err);
-                    return err;
+                    self.abort_statement(state, &pager, matches!(err, LimboError::TxError(_)))?;
+                    return Err(err);
                 }
             }
         }
     }
 
+    /// Undoes the changes of a statement that failed or was interrupted. A transaction error
+    /// leaves the transaction alone.
+    fn abort_statement(
+        &self,
+        state: &mut ProgramState,
+        pager: &Pager,
+        tx_error: bool,
+    ) -> Result<()> {
+        if let Some(savepoint) = state.vtab_statement_savepoint.take() {
+            let connection = &self.connection;
+            connection.vtab_savepoint(VTabSavepointOp::RollbackTo, savepoint)?;
+            connection.vtab_savepoint(VTabSavepointOp::Release, savepoint)?;
+        }
+        if let Some((savepoint, schema)) = state.statement_savepoint.take() {
+            // Only the changes of the failed statement are undone. Its cursors are
+            // closed first so that they don't keep the pages to restore pinned.
+            state
+                .cursors
+                .borrow_mut()
+                .iter_mut()
+                .for_each(|c| *c = None);
+            pager.rollback_to_savepoint(savepoint)?;
+            pager.release_savepoints(savepoint);
+            self.connection.schema.replace(schema);
+            for (db, savepoint, schema) in state.attached_statement_savepoints.drain(..) {
+                let conn = self.connection.attached_connection(db)?;
+                let pager = conn.pager.borrow().clone();
+                pager.rollback_to_savepoint(savepoint)?;
+                pager.release_savepoints(savepoint);
+                conn.schema.replace(schema);
+            }
+            return Ok(());
+        }
+        // Outside of an explicit transaction the statement is the whole transaction, so it is
+        // rolled back and its locks are released. Inside one, the statement didn't write, so
+        // there's nothing to undo.
+        if !tx_error && self.connection.auto_commit.get() {
+            self.abort_txn(pager)?;
+        }
+        Ok(())
+    }
+
+    /// Rolls back the transaction of the connection and ends it.
+    fn abort_txn(&self, pager: &Pager) -> Result<()> {
+        let connection = &self.connection;
+        match connection.transaction_state.get() {
+            TransactionState::Write { schema_did_change } => {
+                pager.rollback(schema_did_change, connection)?;
+                pager.end_tx(
+                    true,
+                    schema_did_change,
+                    connection,
+                    connection.wal_checkpoint_disabled.get(),
+                )?;
+                connection.call_rollback_hook();
+            }
+            TransactionState::Read => pager.end_read_tx()?,
+            TransactionState::None => {}
+        }
+        connection.transaction_state.replace(TransactionState::None);
+        connection.savepoints.borrow_mut().clear();
+        connection.end_vtab_transactions(true)?;
+        connection.end_attached_transactions(true)
+    }
+
     #[instrument(skip_all, level = Level::DEBUG)]
     pub fn commit_txn(
         &self,
@@ -447,6 +614,7 @@ impl Program {
                 }
                 mv_transactions.clear();
             }
+            self.end_changes(program_state);
             Ok(StepResult::Done)
         } else {
             let connection = self.connection.clone();
@@ -464,18 +632,33 @@ impl Program {
                 };
                 self.step_end_write_txn(
                     &pager,
-                    &mut program_state.commit_state,
+                    program_state,
                     &connection,
                     rollback,
                     schema_did_change,
                 )
             } else if auto_commit {
+                if !rollback {
+                    connection.sync_vtab_transactions()?;
+                }
+                if !rollback
+                    && matches!(
+                        connection.transaction_state.get(),
+                        TransactionState::Write { .. }
+                    )
+                    && connection.call_commit_hook()
+                {
+                    // Failing the statement rolls the transaction back.
+                    return Err(LimboError::Constraint("constraint failed".to_string()));
+                }
+                connection.end_vtab_transactions(rollback)?;
+                connection.end_attached_transactions(rollback)?;
                 let current_state = connection.transaction_state.get();
                 tracing::trace!("Auto-commit state: {:?}", current_state);
                 match current_state {
                     TransactionState::Write { schema_did_change } => self.step_end_write_txn(
                         &pager,
-                        &mut program_state.commit_state,
+                        program_state,
                         &connection,
                         rollback,
                         schema_did_change,
@@ -488,23 +671,35 @@ impl Program {
                     TransactionState::None => Ok(StepResult::Done),
                 }
             } else {
-                if self.change_cnt_on {
-                    self.connection.set_changes(self.n_change.get());
-                }
+                self.end_changes(program_state);
                 Ok(StepResult::Done)
             }
         }
     }
 
-    #[instrument(skip(self, pager, connection), level = Level::DEBUG)]
+    /// Records the rows changed by the statement in the change counters of the connection, once
+    /// it is done.
+    fn end_changes(&self, program_state: &ProgramState) {
+        if self.change_cnt_on {
+            self.connection
+                .set_changes(program_state.n_change, program_state.n_trigger_change);
+        }
+    }
+
+    #[instrument(skip(self, pager, program_state, connection), level = Level::DEBUG)]
     fn step_end_write_txn(
         &self,
         pager: &Rc<Pager>,
-        commit_state: &mut CommitState,
+        program_state: &mut ProgramState,
         connection: &Connection,
         rollback: bool,
         schema_did_change: bool,
     ) -> Result<StepResult> {
+        if !rollback {
+            if let LimboResult::Busy = pager.begin_concurrent_commit()? {
+                return Ok(StepResult::Busy);
+            }
+        }
         let cacheflush_status = pager.end_tx(
             rollback,
             schema_did_change,
@@ -513,18 +708,16 @@ impl Program {
         )?;
         match cacheflush_status {
             IOResult::Done(status) => {
-                if self.change_cnt_on {
-                    self.connection.set_changes(self.n_change.get());
-                }
+                self.end_changes(program_state);
                 if matches!(status, pager::PagerCommitResult::Rollback) {
                     pager.rollback(schema_did_change, connection)?;
                 }
                 connection.transaction_state.replace(TransactionState::None);
-                *commit_state = CommitState::Ready;
+                program_state.commit_state = CommitState::Ready;
             }
             IOResult::IO => {
                 tracing::trace!("Cacheflush IO");
-                *commit_state = CommitState::Committing;
+                program_state.commit_state = CommitState::Committing;
                 return Ok(StepResult::IO);
             }
         }
@@ -558,6 +751,14 @@ fn make_record(registers: &[Register], start_reg: &usize, count: &usize) -> Immu
     ImmutableRecord::from_registers(regs, regs.len())
 }
 
+/// Writes the zeros left out of the record in `reg`, if any, for the instructions that read
+/// the record instead of inserting it into a table.
+fn expand_zero_tail(registers: &mut [Register], reg: usize) {
+    if let Register::Record(record) = &mut registers[reg] {
+        record.expand_zero_tail();
+    }
+}
+
 pub fn registers_to_ref_values(registers: &[Register]) -> Vec<RefValue> {
     registers
         .iter()
@@ -0,0 +1,218 @@
+//! VACUUM rebuilds the database into a new one without free pages, with every b-tree packed and
+//! stored contiguously, by copying each b-tree into a temporary database like SQLite does.
+//!
+//! `VACUUM INTO` keeps the copy in the named file. A plain `VACUUM` instead writes the pages of
+//! the copy over those of the database, as part of the write transaction of the statement, so
+//! the rebuilt database is swapped in atomically when it commits.
+
+use crate::result::LimboResult;
+use crate::schema::Schema;
+use crate::storage::btree::{BTreeCursor, BTreeKey};
+#[cfg(feature = "fs")]
+use crate::storage::database::DatabaseFile;
+use crate::storage::database::{DatabaseStorage, FileMemoryStorage};
+use crate::storage::header_accessor;
+use crate::storage::pager::{CreateBTreeFlags, Pager};
+use crate::types::ImmutableRecord;
+use crate::util::{IOExt, MEMORY_PATH};
+use crate::{CheckpointMode, Connection, Database, LimboError, OpenFlags, RefValue, Result};
+use crate::{Value, IO};
+use std::rc::Rc;
+use std::sync::Arc;
+
+/// A row of the `sqlite_schema` table being copied.
+struct SchemaEntry {
+    rowid: i64,
+    values: Vec<Value>,
+}
+
+impl SchemaEntry {
+    fn text(&self, idx: usize) -> &str {
+        match &self.values[idx] {
+            Value::Text(text) => text.as_str(),
+            _ => "",
+        }
+    }
+
+    fn root_page(&self) -> i64 {
+        match self.values[3] {
+            Value::Integer(root_page) => root_page,
+            _ => 0,
+        }
+    }
+}
+
+/// Runs `VACUUM`, or `VACUUM INTO` if `into` is set, on the database of `pager`. The connection
+/// must hold a write transaction for a plain `VACUUM`, and a read transaction otherwise.
+pub fn run_vacuum(conn: &Arc<Connection>, pager: &Rc<Pager>, into: Option<&str>) -> Result<()> {
+    if header_accessor::get_reserved_space(pager)? != 0 {
+        return Err(LimboError::InternalError(
+            "VACUUM of a database with reserved space is not supported".to_string(),
+        ));
+    }
+    let (io, db_file): (Arc<dyn IO>, Arc<dyn DatabaseStorage>) = match into {
+        Some(path) => open_output_file(conn, path)?,
+        None => {
+            let io = pager.io.get_memory_io();
+            let file = io.open_file(MEMORY_PATH, OpenFlags::Create, false)?;
+            (io, Arc::new(FileMemoryStorage::new(file)))
+        }
+    };
+    let schema = conn.schema.borrow().clone();
+    let db = Database::open(
+        io.clone(),
+        into.unwrap_or(MEMORY_PATH),
+        db_file,
+        false,
+        schema.indexes_enabled(),
+    )?;
+    let temp_conn = db.connect()?;
+    temp_conn.reset_page_size(header_accessor::get_page_size(pager)?)?;
+    let temp_pager = temp_conn.pager.borrow().clone();
+    temp_pager.set_auto_vacuum_mode(pager.get_auto_vacuum_mode());
+
+    if matches!(io.block(|| temp_pager.begin_read_tx())?, LimboResult::Busy)
+        || matches!(io.block(|| temp_pager.begin_write_tx())?, LimboResult::Busy)
+    {
+        return Err(LimboError::Busy);
+    }
+    copy_btrees(pager, &temp_pager, &schema)?;
+    header_accessor::set_schema_cookie(
+        &temp_pager,
+        header_accessor::get_schema_cookie(pager)? + 1,
+    )?;
+    header_accessor::set_user_version(&temp_pager, header_accessor::get_user_version(pager)?)?;
+    header_accessor::set_application_id(&temp_pager, header_accessor::get_application_id(pager)?)?;
+    header_accessor::set_default_page_cache_size(
+        &temp_pager,
+        header_accessor::get_default_page_cache_size(pager)?,
+    )?;
+    io.block(|| temp_pager.end_tx(false, false, &temp_conn, false))?;
+
+    match into {
+        Some(_) => {
+            temp_conn.checkpoint(CheckpointMode::Truncate)?;
+            temp_conn.close()
+        }
+        None => {
+            if matches!(io.block(|| temp_pager.begin_read_tx())?, LimboResult::Busy) {
+                return Err(LimboError::Busy);
+            }
+            pager.overwrite_with(&temp_pager)?;
+            temp_pager.end_read_tx()
+        }
+    }
+}
+
+/// Opens the file `VACUUM INTO` writes to, which must not exist yet or be empty.
+#[cfg(feature = "fs")]
+fn open_output_file(
+    conn: &Arc<Connection>,
+    path: &str,
+) -> Result<(Arc<dyn IO>, Arc<dyn DatabaseStorage>)> {
+    let io = conn._db.io.clone();
+    let file = io.open_file(path, OpenFlags::Create, false)?;
+    if file.size()? > 0 {
+        return Err(LimboError::InvalidArgument(
+            "output file already exists".to_string(),
+        ));
+    }
+    Ok((io, Arc::new(DatabaseFile::new(file))))
+}
+
+#[cfg(not(feature = "fs"))]
+fn open_output_file(
+    _conn: &Arc<Connection>,
+    _path: &str,
+) -> Result<(Arc<dyn IO>, Arc<dyn DatabaseStorage>)> {
+    Err(LimboError::InternalError(
+        "VACUUM INTO requires the fs feature".to_string(),
+    ))
+}
+
+/// Copies every b-tree of the database of `pager`, and the schema rows pointing to them, into
+/// the empty database of `temp_pager`. All the b-trees are created before any is filled, so
+/// that the root pages end up at the start of the file like SQLite's VACUUM leaves them.
+fn copy_btrees(pager: &Rc<Pager>, temp_pager: &Rc<Pager>, schema: &Schema) -> Result<()> {
+    let mut entries = Vec::new();
+    let mut cursor = BTreeCursor::new_table(None, pager.clone(), 1, 5);
+    pager.io.block(|| cursor.rewind())?;
+    while let Some(rowid) = pager.io.block(|| cursor.rowid())? {
+        let Some(record) = pager.io.block(|| cursor.record())? else {
+            break;
+        };
+        let values = record.get_values().iter().map(RefValue::to_owned).collect();
+        drop(record);
+        entries.push(SchemaEntry { rowid, values });
+        pager.io.block(|| cursor.next())?;
+    }
+
+    let mut btrees = Vec::new();
+    for entry in entries.iter_mut() {
+        let root_page = entry.root_page();
+        if root_page <= 0 {
+            continue;
+        }
+        let flags = if entry.text(0) == "index" {
+            CreateBTreeFlags::new_index()
+        } else {
+            CreateBTreeFlags::new_table()
+        };
+        let new_root_page = temp_pager.io.block(|| temp_pager.btree_create(&flags))?;
+        entry.values[3] = Value::Integer(new_root_page as i64);
+        btrees.push((root_page as usize, new_root_page as usize, flags));
+    }
+
+    let mut temp_cursor = BTreeCursor::new_table(None, temp_pager.clone(), 1, 5);
+    for entry in entries.iter() {
+        let record = ImmutableRecord::from_values(&entry.values, entry.values.len());
+        let key = BTreeKey::new_table_rowid(entry.rowid, Some(&record));
+        temp_pager.io.block(|| temp_cursor.insert(&key, false))?;
+    }
+
+    for (entry, (root_page, new_root_page, flags)) in entries
+        .iter()
+        .filter(|entry| entry.root_page() > 0)
+        .zip(btrees)
+    {
+        let (mut cursor, mut temp_cursor) = if flags.is_table() {
+            (
+                BTreeCursor::new_table(None, pager.clone(), root_page, 0),
+                BTreeCursor::new_table(None, temp_pager.clone(), new_root_page, 0),
+            )
+        } else {
+            let index = schema
+                .get_index(entry.text(2), entry.text(1))
+                .ok_or_else(|| {
+                    LimboError::InternalError(format!("index {} not found", entry.text(1)))
+                })?;
+            (
+                BTreeCursor::new_index(None, pager.clone(), root_page, index, 0),
+                BTreeCursor::new_index(None, temp_pager.clone(), new_root_page, index, 0),
+            )
+        };
+        pager.io.block(|| cursor.rewind())?;
+        loop {
+            let rowid = if flags.is_table() {
+                match pager.io.block(|| cursor.rowid())? {
+                    Some(rowid) => rowid,
+                    None => break,
+                }
+            } else {
+                0
+            };
+            let Some(record) = pager.io.block(|| cursor.record())? else {
+                break;
+            };
+            let key = if flags.is_table() {
+                BTreeKey::new_table_rowid(rowid, Some(&record))
+            } else {
+                BTreeKey::new_index_key(&record)
+            };
+            temp_pager.io.block(|| temp_cursor.insert(&key, false))?;
+            drop(record);
+            pager.io.block(|| cursor.next())?;
+        }
+    }
+    Ok(())
+}
@@ -1,9 +1,12 @@
+#[cfg(feature = "fs")]
+use crate::ext::{SqliteVirtualTable, SqliteVirtualTableCursor};
+use crate::ext::{VTabImpl, VTabModule};
 use crate::pragma::{PragmaVirtualTable, PragmaVirtualTableCursor};
 use crate::schema::Column;
-use crate::util::columns_from_create_table_body;
+use crate::util::{columns_from_create_table_body, normalize_ident};
 use crate::{Connection, LimboError, SymbolTable, Value};
 use fallible_iterator::FallibleIterator;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::ffi::c_void;
 use std::rc::Rc;
 use std::sync::Arc;
@@ -14,6 +17,34 @@ use turso_sqlite3_parser::{ast, lexer::sql::Parser};
 enum VirtualTableType {
     Pragma(PragmaVirtualTable),
     External(ExtVirtualTable),
+    #[cfg(feature = "fs")]
+    Sqlite(Rc<SqliteVirtualTable>),
+}
+
+/// The methods of a module called when a transaction that wrote one of its tables ends, or
+/// when it starts writing it.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum VTabTransactionOp {
+    Begin,
+    Sync,
+    Commit,
+    Rollback,
+}
+
+/// The methods of a module called for the savepoints of a transaction that wrote one of its
+/// tables.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum VTabSavepointOp {
+    Begin,
+    Release,
+    RollbackTo,
+}
+
+/// A virtual table written in the current transaction.
+pub(crate) struct VTabTransaction {
+    table: Rc<VirtualTable>,
+    /// The number of savepoints the table is in, which are the ones it is told about.
+    savepoints: Cell<usize>,
 }
 
 #[derive(Clone, Debug)]
@@ -42,15 +73,19 @@ impl VirtualTable {
     }
 
     pub(crate) fn function(name: &str, syms: &SymbolTable) -> crate::Result<Rc<VirtualTable>> {
-        let module = syms.vtab_modules.get(name);
-        let (vtab_type, schema) = if module.is_some() {
-            ExtVirtualTable::create(name, module, Vec::new(), VTabKind::TableValuedFunction)
-                .map(|(vtab, columns)| (VirtualTableType::External(vtab), columns))?
-        } else {
+        let Some(module) = syms.vtab_modules.get(name) else {
             return Err(LimboError::ParseError(format!(
                 "No such table-valued function: {name}"
             )));
         };
+        let (vtab_type, schema) = Self::connect(
+            name,
+            name,
+            module,
+            Vec::new(),
+            VTabKind::TableValuedFunction,
+            false,
+        )?;
 
         let vtab = VirtualTable {
             name: name.to_owned(),
@@ -61,24 +96,105 @@ impl VirtualTable {
         Ok(Rc::new(vtab))
     }
 
+    /// Connects to an existing virtual table, like when the schema is loaded.
     pub fn table(
         tbl_name: Option<&str>,
         module_name: &str,
         args: Vec<turso_ext::Value>,
         syms: &SymbolTable,
     ) -> crate::Result<Rc<VirtualTable>> {
-        let module = syms.vtab_modules.get(module_name);
-        let (table, schema) =
-            ExtVirtualTable::create(module_name, module, args, VTabKind::VirtualTable)?;
+        Self::new_table(tbl_name, module_name, args, syms, false)
+    }
+
+    /// Creates a new virtual table, for CREATE VIRTUAL TABLE.
+    pub(crate) fn create(
+        tbl_name: &str,
+        module_name: &str,
+        args: Vec<turso_ext::Value>,
+        syms: &SymbolTable,
+    ) -> crate::Result<Rc<VirtualTable>> {
+        Self::new_table(Some(tbl_name), module_name, args, syms, true)
+    }
+
+    fn new_table(
+        tbl_name: Option<&str>,
+        module_name: &str,
+        args: Vec<turso_ext::Value>,
+        syms: &SymbolTable,
+        create: bool,
+    ) -> crate::Result<Rc<VirtualTable>> {
+        let name = tbl_name.unwrap_or(module_name).to_owned();
+        let module = syms.vtab_modules.get(module_name).ok_or_else(|| {
+            LimboError::ExtensionError(format!("Virtual table module not found: {module_name}"))
+        })?;
+        let (vtab_type, schema) = Self::connect(
+            module_name,
+            &name,
+            module,
+            args,
+            VTabKind::VirtualTable,
+            create,
+        )?;
+        let mut columns = Self::resolve_columns(schema)?;
+        // Modules don't know the name of the table they create. A hidden column named
+        // after the module stands for the table itself (e.g. `docs MATCH 'query'` for fts5),
+        // so it takes the name of the table.
+        for column in columns
+            .iter_mut()
+            .filter(|c| c.hidden && c.name.as_deref() == Some(module_name))
+        {
+            column.name = Some(normalize_ident(&name));
+        }
         let vtab = VirtualTable {
-            name: tbl_name.unwrap_or(module_name).to_owned(),
-            columns: Self::resolve_columns(schema)?,
+            name,
+            columns,
             kind: VTabKind::VirtualTable,
-            vtab_type: VirtualTableType::External(table),
+            vtab_type,
         };
         Ok(Rc::new(vtab))
     }
 
+    /// Creates, or connects to, the table `name` of the module, which takes ownership of the
+    /// provided args. Returns the table with its schema.
+    fn connect(
+        module_name: &str,
+        #[cfg_attr(not(feature = "fs"), allow(unused_variables))] name: &str,
+        module: &VTabImpl,
+        args: Vec<turso_ext::Value>,
+        kind: VTabKind,
+        #[cfg_attr(not(feature = "fs"), allow(unused_variables))] create: bool,
+    ) -> crate::Result<(VirtualTableType, String)> {
+        if kind != module.module_kind {
+            for arg in args {
+                unsafe { arg.__free_internal_type() };
+            }
+            let expected = match kind {
+                VTabKind::VirtualTable => "virtual table",
+                VTabKind::TableValuedFunction => "table-valued function",
+            };
+            return Err(LimboError::ExtensionError(format!(
+                "{module_name} is not a {expected} module"
+            )));
+        }
+        match &module.implementation {
+            VTabModule::Ext(implementation) => ExtVirtualTable::create(implementation, args)
+                .map(|(vtab, schema)| (VirtualTableType::External(vtab), schema)),
+            #[cfg(feature = "fs")]
+            VTabModule::Sqlite(module) => {
+                let args = args
+                    .into_iter()
+                    .map(|arg| {
+                        let text = arg.to_text().unwrap_or_default().to_string();
+                        unsafe { arg.__free_internal_type() };
+                        text
+                    })
+                    .collect::<Vec<_>>();
+                SqliteVirtualTable::connect(module, name, &args, create)
+                    .map(|(vtab, schema)| (VirtualTableType::Sqlite(vtab), schema))
+            }
+        }
+    }
+
     fn resolve_columns(schema: String) -> crate::Result<Vec<Column>> {
         let mut parser = Parser::new(schema.as_bytes());
         if let ast::Cmd::Stmt(ast::Stmt::CreateTable { body, .. }) = parser.next()?.ok_or(
@@ -100,6 +216,8 @@ impl VirtualTable {
             VirtualTableType::External(table) => {
                 Ok(VirtualTableCursor::External(table.open(conn)?))
             }
+            #[cfg(feature = "fs")]
+            VirtualTableType::Sqlite(table) => Ok(VirtualTableCursor::Sqlite(table.open()?)),
         }
     }
 
@@ -107,6 +225,8 @@ impl VirtualTable {
         match &self.vtab_type {
             VirtualTableType::Pragma(_) => Err(LimboError::ReadOnly),
             VirtualTableType::External(table) => table.update(args),
+            #[cfg(feature = "fs")]
+            VirtualTableType::Sqlite(table) => table.update(args),
         }
     }
 
@@ -114,6 +234,26 @@ impl VirtualTable {
         match &self.vtab_type {
             VirtualTableType::Pragma(_) => Ok(()),
             VirtualTableType::External(table) => table.destroy(),
+            #[cfg(feature = "fs")]
+            VirtualTableType::Sqlite(table) => table.destroy(),
+        }
+    }
+
+    pub(crate) fn transaction(&self, op: VTabTransactionOp) -> crate::Result<()> {
+        match &self.vtab_type {
+            VirtualTableType::Pragma(_) => Ok(()),
+            VirtualTableType::External(table) => table.transaction(op),
+            #[cfg(feature = "fs")]
+            VirtualTableType::Sqlite(table) => table.transaction(op),
+        }
+    }
+
+    pub(crate) fn savepoint(&self, op: VTabSavepointOp, savepoint: usize) -> crate::Result<()> {
+        match &self.vtab_type {
+            VirtualTableType::Pragma(_) => Ok(()),
+            VirtualTableType::External(table) => table.savepoint(op, savepoint),
+            #[cfg(feature = "fs")]
+            VirtualTableType::Sqlite(table) => table.savepoint(op, savepoint),
         }
     }
 
@@ -125,6 +265,8 @@ impl VirtualTable {
         match &self.vtab_type {
             VirtualTableType::Pragma(table) => table.best_index(constraints),
             VirtualTableType::External(table) => table.best_index(constraints, order_by),
+            #[cfg(feature = "fs")]
+            VirtualTableType::Sqlite(table) => table.best_index(constraints, order_by),
         }
     }
 }
@@ -132,6 +274,8 @@ impl VirtualTable {
 pub enum VirtualTableCursor {
     Pragma(Box<PragmaVirtualTableCursor>),
     External(ExtVirtualTableCursor),
+    #[cfg(feature = "fs")]
+    Sqlite(SqliteVirtualTableCursor),
 }
 
 impl VirtualTableCursor {
@@ -139,6 +283,8 @@ impl VirtualTableCursor {
         match self {
             VirtualTableCursor::Pragma(cursor) => cursor.next(),
             VirtualTableCursor::External(cursor) => cursor.next(),
+            #[cfg(feature = "fs")]
+            VirtualTableCursor::Sqlite(cursor) => cursor.next(),
         }
     }
 
@@ -146,6 +292,8 @@ impl VirtualTableCursor {
         match self {
             VirtualTableCursor::Pragma(cursor) => cursor.rowid(),
             VirtualTableCursor::External(cursor) => cursor.rowid(),
+            #[cfg(feature = "fs")]
+            VirtualTableCursor::Sqlite(cursor) => cursor.rowid(),
         }
     }
 
@@ -153,6 +301,8 @@ impl VirtualTableCursor {
         match self {
             VirtualTableCursor::Pragma(cursor) => cursor.column(column),
             VirtualTableCursor::External(cursor) => cursor.column(column),
+            #[cfg(feature = "fs")]
+            VirtualTableCursor::Sqlite(cursor) => cursor.column(column),
         }
     }
 
@@ -168,6 +318,8 @@ impl VirtualTableCursor {
             VirtualTableCursor::External(cursor) => {
                 cursor.filter(idx_num, idx_str, arg_count, args)
             }
+            #[cfg(feature = "fs")]
+            VirtualTableCursor::Sqlite(cursor) => cursor.filter(idx_num, idx_str, args),
         }
     }
 }
@@ -208,27 +360,13 @@ impl ExtVirtualTable {
 
     /// takes ownership of the provided Args
     fn create(
-        module_name: &str,
-        module: Option<&Rc<crate::ext::VTabImpl>>,
+        implementation: &Rc<VTabModuleImpl>,
         args: Vec<turso_ext::Value>,
-        kind: VTabKind,
     ) -> crate::Result<(Self, String)> {
-        let module = module.ok_or(LimboError::ExtensionError(format!(
-            "Virtual table module not found: {module_name}"
-        )))?;
-        if kind != module.module_kind {
-            let expected = match kind {
-                VTabKind::VirtualTable => "virtual table",
-                VTabKind::TableValuedFunction => "table-valued function",
-            };
-            return Err(LimboError::ExtensionError(format!(
-                "{module_name} is not a {expected} module"
-            )));
-        }
-        let (schema, table_ptr) = module.implementation.create(args)?;
+        let (schema, table_ptr) = implementation.create(args)?;
         let vtab = ExtVirtualTable {
             connection_ptr: RefCell::new(None),
-            implementation: module.implementation.clone(),
+            implementation: implementation.clone(),
             table_ptr,
         };
         Ok((vtab, schema))
@@ -276,6 +414,33 @@ impl ExtVirtualTable {
         }
     }
 
+    fn transaction(&self, op: VTabTransactionOp) -> crate::Result<()> {
+        let method = match op {
+            VTabTransactionOp::Begin => self.implementation.begin,
+            VTabTransactionOp::Sync => self.implementation.sync,
+            VTabTransactionOp::Commit => self.implementation.commit,
+            VTabTransactionOp::Rollback => self.implementation.rollback,
+        };
+        let rc = unsafe { method(self.table_ptr) };
+        match rc {
+            ResultCode::OK => Ok(()),
+            _ => Err(LimboError::ExtensionError(rc.to_string())),
+        }
+    }
+
+    fn savepoint(&self, op: VTabSavepointOp, savepoint: usize) -> crate::Result<()> {
+        let method = match op {
+            VTabSavepointOp::Begin => self.implementation.savepoint,
+            VTabSavepointOp::Release => self.implementation.release,
+            VTabSavepointOp::RollbackTo => self.implementation.rollback_to,
+        };
+        let rc = unsafe { method(self.table_ptr, savepoint as i32) };
+        match rc {
+            ResultCode::OK => Ok(()),
+            _ => Err(LimboError::ExtensionError(rc.to_string())),
+        }
+    }
+
     fn destroy(&self) -> crate::Result<()> {
         let rc = unsafe { (self.implementation.destroy)(self.table_ptr) };
         match rc {
@@ -365,3 +530,79 @@ impl Drop for ExtVirtualTableCursor {
         }
     }
 }
+
+impl Connection {
+    /// Adds `table` to the transaction the first time it is written in it, telling its module
+    /// about the transaction and the innermost of the `savepoints` open.
+    pub(crate) fn begin_vtab_transaction(
+        &self,
+        table: &Rc<VirtualTable>,
+        savepoints: usize,
+    ) -> crate::Result<()> {
+        if self
+            .vtab_transactions
+            .borrow()
+            .iter()
+            .any(|written| Rc::ptr_eq(&written.table, table))
+        {
+            return Ok(());
+        }
+        table.transaction(VTabTransactionOp::Begin)?;
+        self.vtab_transactions.borrow_mut().push(VTabTransaction {
+            table: table.clone(),
+            savepoints: Cell::new(0),
+        });
+        if savepoints > 0 {
+            self.vtab_savepoint(VTabSavepointOp::Begin, savepoints - 1)?;
+        }
+        Ok(())
+    }
+
+    /// Opens, releases or rolls back to the savepoint numbered `savepoint` in the tables written
+    /// in the transaction. Like the pager savepoints, releasing or rolling back to a savepoint
+    /// also releases the ones opened after it.
+    pub(crate) fn vtab_savepoint(
+        &self,
+        op: VTabSavepointOp,
+        savepoint: usize,
+    ) -> crate::Result<()> {
+        for written in self.vtab_transactions.borrow().iter() {
+            match op {
+                VTabSavepointOp::Begin => written.savepoints.set(savepoint + 1),
+                // The table wasn't written yet when the savepoint was opened
+                _ if written.savepoints.get() <= savepoint => continue,
+                VTabSavepointOp::Release => written.savepoints.set(savepoint),
+                VTabSavepointOp::RollbackTo => {}
+            }
+            written.table.savepoint(op, savepoint)?;
+        }
+        Ok(())
+    }
+
+    /// Syncs the tables written in the transaction before it is committed, so that the commit
+    /// fails if one of them can't commit.
+    pub(crate) fn sync_vtab_transactions(&self) -> crate::Result<()> {
+        for written in self.vtab_transactions.borrow().iter() {
+            written.table.transaction(VTabTransactionOp::Sync)?;
+        }
+        Ok(())
+    }
+
+    /// Commits or rolls back the tables written in the transaction, which then ends for them.
+    pub(crate) fn end_vtab_transactions(&self, rollback: bool) -> crate::Result<()> {
+        let op = if rollback {
+            VTabTransactionOp::Rollback
+        } else {
+            VTabTransactionOp::Commit
+        };
+        let written = std::mem::take(&mut *self.vtab_transactions.borrow_mut());
+        let mut result = Ok(());
+        for written in written {
+            // Every table ends the transaction, even if another one fails to
+            if let Err(err) = written.table.transaction(op) {
+                result = result.and(Err(err));
+            }
+        }
+        result
+    }
+}
@@ -8,7 +8,7 @@ use std::sync::Arc;
 use keywords::KEYWORDS;
 use turso_ext::{
     register_extension, Connection, ConstraintInfo, ConstraintOp, ConstraintUsage, IndexInfo,
-    OrderByInfo, ResultCode, VTabCursor, VTabModule, VTabModuleDerive, VTable, Value,
+    OrderByInfo, ResultCode, StepResult, VTabCursor, VTabModule, VTabModuleDerive, VTable, Value,
 };
 
 register_extension! {
@@ -36,8 +36,8 @@ enum CompletionPhase {
     // Indexes = 5,
     // Triggers = 6,
     // Databases = 7,
-    // Tables = 8, // Also VIEWs and TRIGGERs
-    // Columns = 9,
+    Tables = 8, // Also VIEWs and TRIGGERs
+    Columns = 9,
     // Modules = 10,
     Eof = 11,
 }
@@ -53,8 +53,8 @@ impl From<CompletionPhase> for i64 {
             // Indexes => 5,
             // Triggers => 6,
             // Databases => 7,
-            // Tables => 8,
-            // Columns => 9,
+            Tables => 8,
+            Columns => 9,
             // Modules => 10,
             Eof => 11,
         }
@@ -88,8 +88,11 @@ impl VTable for CompletionTable {
     type Cursor = CompletionCursor;
     type Error = ResultCode;
 
-    fn open(&self, _conn: Option<Arc<Connection>>) -> Result<Self::Cursor, Self::Error> {
-        Ok(CompletionCursor::default())
+    fn open(&self, conn: Option<Arc<Connection>>) -> Result<Self::Cursor, Self::Error> {
+        Ok(CompletionCursor {
+            conn,
+            ..Default::default()
+        })
     }
 
     fn best_index(constraints: &[ConstraintInfo], _order_by: &[OrderByInfo]) -> IndexInfo {
@@ -156,8 +159,10 @@ struct CompletionCursor {
     rowid: i64,
     phase: CompletionPhase,
     inter_phase_counter: usize,
-    // stmt: Statement
-    // conn: Connection
+    /// The names of the schema objects of the current phase, read from the database when the
+    /// phase starts.
+    names: Vec<String>,
+    conn: Option<Arc<Connection>>,
 }
 
 impl CompletionCursor {
@@ -165,6 +170,52 @@ impl CompletionCursor {
         self.line.clear();
         self.prefix.clear();
         self.inter_phase_counter = 0;
+        self.names.clear();
+    }
+
+    /// Reads the names of the tables, views and triggers, or of the columns of the tables.
+    fn load_names(&mut self) {
+        self.names.clear();
+        self.inter_phase_counter = 0;
+        match self.phase {
+            CompletionPhase::Tables => {
+                self.names = self.query_names(
+                    "SELECT name FROM sqlite_schema WHERE type IN ('table', 'view', 'trigger')",
+                    0,
+                );
+            }
+            CompletionPhase::Columns => {
+                let tables =
+                    self.query_names("SELECT name FROM sqlite_schema WHERE type = 'table'", 0);
+                for table in tables {
+                    let pragma = format!("PRAGMA table_info(\"{}\")", table.replace('"', "\"\""));
+                    for column in self.query_names(&pragma, 1) {
+                        if !self.names.contains(&column) {
+                            self.names.push(column);
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The text values of a column of the rows of a query, if the cursor has a connection.
+    fn query_names(&self, sql: &str, column: usize) -> Vec<String> {
+        let Some(conn) = &self.conn else {
+            return Vec::new();
+        };
+        let Ok(mut stmt) = conn.prepare(sql) else {
+            return Vec::new();
+        };
+        let mut names = Vec::new();
+        while stmt.step() == StepResult::Row {
+            if let Some(name) = stmt.get_row().get(column).and_then(|value| value.to_text()) {
+                names.push(name.to_string());
+            }
+        }
+        stmt.close();
+        names
     }
 }
 
@@ -216,13 +267,30 @@ impl VTabCursor for CompletionCursor {
                 CompletionPhase::Keywords => {
                     if self.inter_phase_counter >= KEYWORDS.len() {
                         self.curr_row.clear();
-                        self.phase = CompletionPhase::Eof;
+                        self.phase = CompletionPhase::Tables;
+                        self.load_names();
+                        continue;
                     } else {
                         self.curr_row.clear();
                         self.curr_row.push_str(KEYWORDS[self.inter_phase_counter]);
                         self.inter_phase_counter += 1;
                     }
                 }
+                CompletionPhase::Tables | CompletionPhase::Columns => {
+                    if self.inter_phase_counter >= self.names.len() {
+                        self.curr_row.clear();
+                        self.phase = match self.phase {
+                            CompletionPhase::Tables => CompletionPhase::Columns,
+                            _ => CompletionPhase::Eof,
+                        };
+                        self.load_names();
+                        continue;
+                    }
+                    self.curr_row.clear();
+                    self.curr_row
+                        .push_str(&self.names[self.inter_phase_counter]);
+                    self.inter_phase_counter += 1;
+                }
                 // TODO implement this when db conn is available
                 // CompletionPhase::Databases => {
                 //
@@ -25,6 +25,14 @@ pub type ExtResult<T> = std::result::Result<T, ResultCode>;
 
 pub type ExtensionEntryPoint = unsafe extern "C" fn(api: *const ExtensionApi) -> ResultCode;
 
+/// Returns the nul-terminated `turso_ext` version a dynamically loaded extension was built
+/// against. Exported by `register_extension!` as `turso_ext_api_version`.
+pub type ExtensionVersionFn = unsafe extern "C" fn() -> *const std::ffi::c_char;
+
+/// The version of `turso_ext`, nul-terminated. The layout of `ExtensionApi` and of the structs
+/// behind it is only guaranteed to match between an extension and Turso when they agree on it.
+pub const API_VERSION: &str = concat!(env!("CARGO_PKG_VERSION"), "\0");
+
 #[repr(C)]
 pub struct ExtensionApi {
     pub ctx: *mut c_void,
@@ -27,6 +27,13 @@ pub struct VTabModuleImpl {
     pub rowid: VtabRowIDFn,
     pub destroy: VtabFnDestroy,
     pub best_idx: BestIdxFn,
+    pub begin: VtabFnTransaction,
+    pub sync: VtabFnTransaction,
+    pub commit: VtabFnTransaction,
+    pub rollback: VtabFnTransaction,
+    pub savepoint: VtabFnSavepoint,
+    pub release: VtabFnSavepoint,
+    pub rollback_to: VtabFnSavepoint,
 }
 
 #[repr(C)]
@@ -101,6 +108,10 @@ pub type VtabFnUpdate = unsafe extern "C" fn(
 
 pub type VtabFnDestroy = unsafe extern "C" fn(table: *const c_void) -> ResultCode;
 
+pub type VtabFnTransaction = unsafe extern "C" fn(table: *const c_void) -> ResultCode;
+
+pub type VtabFnSavepoint = unsafe extern "C" fn(table: *const c_void, savepoint: i32) -> ResultCode;
+
 pub type BestIdxFn = unsafe extern "C" fn(
     constraints: *const ConstraintInfo,
     constraint_len: i32,
@@ -144,6 +155,36 @@ pub trait VTable {
     fn destroy(&mut self) -> Result<(), Self::Error> {
         Ok(())
     }
+    /// Called when the table is first written in a transaction. If savepoints are open at that
+    /// point, `savepoint` is then called once, with the number of the innermost one.
+    fn begin(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    /// Called on each table written in the transaction before any of them is committed. A
+    /// failure rolls the transaction back.
+    fn sync(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn commit(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    fn rollback(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    /// Savepoints are numbered from 0 for the outermost one. A failing statement of an explicit
+    /// transaction also opens one, to roll back only its own changes.
+    fn savepoint(&mut self, _savepoint: i32) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    /// Releases the savepoint and the ones opened after it.
+    fn release(&mut self, _savepoint: i32) -> Result<(), Self::Error> {
+        Ok(())
+    }
+    /// Undoes the changes made since the savepoint was opened. The savepoint stays open, but
+    /// the ones opened after it are released.
+    fn rollback_to(&mut self, _savepoint: i32) -> Result<(), Self::Error> {
+        Ok(())
+    }
     fn best_index(_constraints: &[ConstraintInfo], _order_by: &[OrderByInfo]) -> IndexInfo {
         IndexInfo {
             idx_num: 0,
@@ -195,7 +236,7 @@ pub enum ConstraintOp {
 }
 
 #[repr(C)]
-#[derive(Copy, Clone)]
+#[derive(Debug, Copy, Clone)]
 /// Describes an ORDER BY clause in a query involving a virtual table.
 /// Passed along with the constraints to xBestIndex.
 pub struct OrderByInfo {
@@ -19,7 +19,8 @@ register_extension! {
     vfs: { TestFS },
 }
 
-type Store = Rc<RefCell<BTreeMap<i64, (String, String, String)>>>;
+type Rows = BTreeMap<i64, (String, String, String)>;
+type Store = Rc<RefCell<Rows>>;
 
 #[derive(VTabModuleDerive, Default)]
 pub struct KVStoreVTabModule;
@@ -52,6 +53,7 @@ impl VTabModule for KVStoreVTabModule {
             schema,
             KVStoreTable {
                 store: Rc::new(RefCell::new(BTreeMap::new())),
+                snapshots: Vec::new(),
             },
         ))
     }
@@ -151,6 +153,28 @@ impl VTabCursor for KVStoreCursor {
 
 pub struct KVStoreTable {
     store: Store,
+    /// The contents of the store when the transaction began, numbered -1, and when each of the
+    /// savepoints it is in was opened.
+    snapshots: Vec<(i32, Rows)>,
+}
+
+impl KVStoreTable {
+    fn snapshot(&mut self, savepoint: i32) {
+        let store = self.store.borrow().clone();
+        self.snapshots.push((savepoint, store));
+    }
+
+    /// Restores the earliest snapshot taken at or after `savepoint` was opened, and drops the
+    /// later ones.
+    fn restore(&mut self, savepoint: i32) {
+        let Some(pos) = self.snapshots.iter().position(|(n, _)| *n >= savepoint) else {
+            return;
+        };
+        self.snapshots.truncate(pos + 1);
+        let (n, store) = self.snapshots.last_mut().unwrap();
+        *n = savepoint;
+        *self.store.borrow_mut() = store.clone();
+    }
 }
 
 impl VTable for KVStoreTable {
@@ -247,6 +271,38 @@ impl VTable for KVStoreTable {
         println!("VDestroy called");
         Ok(())
     }
+
+    fn begin(&mut self) -> Result<(), Self::Error> {
+        self.snapshot(-1);
+        Ok(())
+    }
+
+    fn commit(&mut self) -> Result<(), Self::Error> {
+        self.snapshots.clear();
+        Ok(())
+    }
+
+    fn rollback(&mut self) -> Result<(), Self::Error> {
+        self.restore(-1);
+        self.snapshots.clear();
+        Ok(())
+    }
+
+    fn savepoint(&mut self, savepoint: i32) -> Result<(), Self::Error> {
+        self.snapshots.retain(|(n, _)| *n < savepoint);
+        self.snapshot(savepoint);
+        Ok(())
+    }
+
+    fn release(&mut self, savepoint: i32) -> Result<(), Self::Error> {
+        self.snapshots.retain(|(n, _)| *n < savepoint);
+        Ok(())
+    }
+
+    fn rollback_to(&mut self, savepoint: i32) -> Result<(), Self::Error> {
+        self.restore(savepoint);
+        Ok(())
+    }
 }
 
 pub struct TestFile {
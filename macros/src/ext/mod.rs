@@ -103,6 +103,12 @@ pub fn register_extension(input: TokenStream) -> TokenStream {
                 ::turso_ext::ResultCode::OK
               }
 
+            #[cfg(not(feature = "static"))]
+            #[no_mangle]
+            pub extern "C" fn turso_ext_api_version() -> *const ::std::ffi::c_char {
+                ::turso_ext::API_VERSION.as_ptr().cast()
+            }
+
             #[cfg(not(feature = "static"))]
             #[no_mangle]
             pub unsafe extern "C" fn register_extension(api: &::turso_ext::ExtensionApi) -> ::turso_ext::ResultCode {
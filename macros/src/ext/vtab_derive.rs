@@ -18,6 +18,16 @@ pub fn derive_vtab_module(input: TokenStream) -> TokenStream {
     let rowid_fn_name = format_ident!("rowid_{}", struct_name);
     let destroy_fn_name = format_ident!("destroy_{}", struct_name);
     let best_idx_fn_name = format_ident!("best_idx_{}", struct_name);
+    let transaction_methods =
+        ["begin", "sync", "commit", "rollback"].map(|method| format_ident!("{}", method));
+    let transaction_fn_names = transaction_methods
+        .clone()
+        .map(|method| format_ident!("{}_{}", method, struct_name));
+    let savepoint_methods =
+        ["savepoint", "release", "rollback_to"].map(|method| format_ident!("{}", method));
+    let savepoint_fn_names = savepoint_methods
+        .clone()
+        .map(|method| format_ident!("{}_{}", method, struct_name));
 
     let expanded = quote! {
         impl #struct_name {
@@ -224,6 +234,39 @@ pub fn derive_vtab_module(input: TokenStream) -> TokenStream {
                 <#struct_name as ::turso_ext::VTabModule>::Table::best_index(constraints, order_by).to_ffi()
             }
 
+            #(
+                #[no_mangle]
+                unsafe extern "C" fn #transaction_fn_names(
+                    table: *const ::std::ffi::c_void,
+                ) -> ::turso_ext::ResultCode {
+                    if table.is_null() {
+                        return ::turso_ext::ResultCode::Error;
+                    }
+                    let table = &mut *(table as *mut <#struct_name as ::turso_ext::VTabModule>::Table);
+                    if <<#struct_name as ::turso_ext::VTabModule>::Table as ::turso_ext::VTable>::#transaction_methods(table).is_err() {
+                        return ::turso_ext::ResultCode::Error;
+                    }
+                    ::turso_ext::ResultCode::OK
+                }
+            )*
+
+            #(
+                #[no_mangle]
+                unsafe extern "C" fn #savepoint_fn_names(
+                    table: *const ::std::ffi::c_void,
+                    savepoint: i32,
+                ) -> ::turso_ext::ResultCode {
+                    if table.is_null() {
+                        return ::turso_ext::ResultCode::Error;
+                    }
+                    let table = &mut *(table as *mut <#struct_name as ::turso_ext::VTabModule>::Table);
+                    if <<#struct_name as ::turso_ext::VTabModule>::Table as ::turso_ext::VTable>::#savepoint_methods(table, savepoint).is_err() {
+                        return ::turso_ext::ResultCode::Error;
+                    }
+                    ::turso_ext::ResultCode::OK
+                }
+            )*
+
             #[no_mangle]
             pub unsafe extern "C" fn #register_fn_name(
                 api: *const ::turso_ext::ExtensionApi
@@ -247,6 +290,8 @@ pub fn derive_vtab_module(input: TokenStream) -> TokenStream {
                     rowid: Self::#rowid_fn_name,
                     destroy: Self::#destroy_fn_name,
                     best_idx: Self::#best_idx_fn_name,
+                    #(#transaction_methods: Self::#transaction_fn_names,)*
+                    #(#savepoint_methods: Self::#savepoint_fn_names,)*
                 };
                 (api.register_vtab_module)(api.ctx, name_c, module, <#struct_name as ::turso_ext::VTabModule>::VTAB_KIND)
             }
@@ -92,6 +92,8 @@ int sqlite3_stmt_readonly(sqlite3_stmt *_stmt);
 
 int sqlite3_stmt_busy(sqlite3_stmt *_stmt);
 
+int sqlite3_clear_bindings(sqlite3_stmt *stmt);
+
 int sqlite3_serialize(sqlite3 *_db, const char *_schema, void **_out, int *_out_bytes, unsigned int _flags);
 
 int sqlite3_deserialize(sqlite3 *_db, const char *_schema, const void *_in_, int _in_bytes, unsigned int _flags);
@@ -216,6 +218,8 @@ int sqlite3_blob_read(void *_blob, void *_data, int _n, int _offset);
 
 int sqlite3_blob_write(void *_blob, const void *_data, int _n, int _offset);
 
+int sqlite3_blob_reseek(void *_blob, int64_t _rowid);
+
 int sqlite3_blob_bytes(void *_blob);
 
 int sqlite3_blob_close(void *_blob);
@@ -18,6 +18,7 @@ pub const SQLITE_ERROR: ffi::c_int = 1;
 pub const SQLITE_ABORT: ffi::c_int = 4;
 pub const SQLITE_BUSY: ffi::c_int = 5;
 pub const SQLITE_NOMEM: ffi::c_int = 7;
+pub const SQLITE_READONLY: ffi::c_int = 8;
 pub const SQLITE_INTERRUPT: ffi::c_int = 9;
 pub const SQLITE_NOTFOUND: ffi::c_int = 12;
 pub const SQLITE_CANTOPEN: ffi::c_int = 14;
@@ -34,8 +35,16 @@ pub const SQLITE_CHECKPOINT_FULL: ffi::c_int = 1;
 pub const SQLITE_CHECKPOINT_RESTART: ffi::c_int = 2;
 pub const SQLITE_CHECKPOINT_TRUNCATE: ffi::c_int = 3;
 
+pub const SQLITE_LIMIT_SQL_LENGTH: ffi::c_int = 1;
+pub const SQLITE_LIMIT_EXPR_DEPTH: ffi::c_int = 3;
+pub const SQLITE_LIMIT_COMPOUND_SELECT: ffi::c_int = 4;
+pub const SQLITE_LIMIT_VDBE_OP: ffi::c_int = 5;
+pub const SQLITE_LIMIT_ATTACHED: ffi::c_int = 7;
+
 pub struct sqlite3 {
     pub(crate) inner: Arc<Mutex<sqlite3Inner>>,
+    /// Outside of the lock, which the running statements hold.
+    pub(crate) interrupt: turso_core::InterruptHandle,
 }
 
 struct sqlite3Inner {
@@ -55,6 +64,7 @@ impl sqlite3 {
         db: Arc<turso_core::Database>,
         conn: Arc<turso_core::Connection>,
     ) -> Self {
+        let interrupt = conn.interrupt_handle();
         let inner = sqlite3Inner {
             io,
             _db: db,
@@ -67,7 +77,7 @@ impl sqlite3 {
         };
         #[allow(clippy::arc_with_non_send_sync)]
         let inner = Arc::new(Mutex::new(inner));
-        Self { inner }
+        Self { inner, interrupt }
     }
 }
 
@@ -248,19 +258,19 @@ pub unsafe extern "C" fn sqlite3_step(stmt: *mut sqlite3_stmt) -> ffi::c_int {
     let db = &mut *stmt.db;
     loop {
         let _db = db.inner.lock().unwrap();
-        if let Ok(result) = stmt.stmt.step() {
-            match result {
-                turso_core::StepResult::IO => {
-                    stmt.stmt.run_once().unwrap();
-                    continue;
-                }
-                turso_core::StepResult::Done => return SQLITE_DONE,
-                turso_core::StepResult::Interrupt => return SQLITE_INTERRUPT,
-                turso_core::StepResult::Row => return SQLITE_ROW,
-                turso_core::StepResult::Busy => return SQLITE_BUSY,
+        match stmt.stmt.step() {
+            Ok(turso_core::StepResult::IO) => {
+                stmt.stmt.run_once().unwrap();
+                continue;
             }
-        } else {
-            return SQLITE_ERROR;
+            Ok(turso_core::StepResult::Done) => return SQLITE_DONE,
+            Ok(turso_core::StepResult::Interrupt) => return SQLITE_INTERRUPT,
+            Ok(turso_core::StepResult::Row) => return SQLITE_ROW,
+            Ok(turso_core::StepResult::Busy) | Err(turso_core::LimboError::Busy) => {
+                return SQLITE_BUSY
+            }
+            Err(turso_core::LimboError::Misuse(_)) => return SQLITE_MISUSE,
+            Err(_) => return SQLITE_ERROR,
         }
     }
 }
@@ -307,8 +317,10 @@ pub unsafe extern "C" fn sqlite3_reset(stmt: *mut sqlite3_stmt) -> ffi::c_int {
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sqlite3_changes(_db: *mut sqlite3) -> ffi::c_int {
-    stub!();
+pub unsafe extern "C" fn sqlite3_changes(db: *mut sqlite3) -> ffi::c_int {
+    let db: &mut sqlite3 = &mut *db;
+    let db = db.inner.lock().unwrap();
+    db.conn.changes() as ffi::c_int
 }
 
 #[no_mangle]
@@ -317,8 +329,22 @@ pub unsafe extern "C" fn sqlite3_stmt_readonly(_stmt: *mut sqlite3_stmt) -> ffi:
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sqlite3_stmt_busy(_stmt: *mut sqlite3_stmt) -> ffi::c_int {
-    stub!();
+pub unsafe extern "C" fn sqlite3_stmt_busy(stmt: *mut sqlite3_stmt) -> ffi::c_int {
+    if stmt.is_null() {
+        return 0;
+    }
+    let stmt = &*stmt;
+    stmt.stmt.is_busy() as ffi::c_int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sqlite3_clear_bindings(stmt: *mut sqlite3_stmt) -> ffi::c_int {
+    if stmt.is_null() {
+        return SQLITE_MISUSE;
+    }
+    let stmt = &mut *stmt;
+    stmt.stmt.clear_bindings();
+    SQLITE_OK
 }
 
 #[no_mangle]
@@ -349,18 +375,25 @@ pub unsafe extern "C" fn sqlite3_get_autocommit(_db: *mut sqlite3) -> ffi::c_int
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sqlite3_total_changes(_db: *mut sqlite3) -> ffi::c_int {
-    stub!();
+pub unsafe extern "C" fn sqlite3_total_changes(db: *mut sqlite3) -> ffi::c_int {
+    let db: &mut sqlite3 = &mut *db;
+    let db = db.inner.lock().unwrap();
+    db.conn.total_changes() as ffi::c_int
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sqlite3_last_insert_rowid(_db: *mut sqlite3) -> i64 {
-    stub!();
+pub unsafe extern "C" fn sqlite3_last_insert_rowid(db: *mut sqlite3) -> i64 {
+    let db: &mut sqlite3 = &mut *db;
+    let db = db.inner.lock().unwrap();
+    db.conn.last_insert_rowid()
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sqlite3_interrupt(_db: *mut sqlite3) {
-    stub!();
+pub unsafe extern "C" fn sqlite3_interrupt(db: *mut sqlite3) {
+    if db.is_null() {
+        return;
+    }
+    (*db).interrupt.interrupt();
 }
 
 #[no_mangle]
@@ -380,11 +413,26 @@ pub unsafe extern "C" fn sqlite3_sleep(_ms: ffi::c_int) {
 
 #[no_mangle]
 pub unsafe extern "C" fn sqlite3_limit(
-    _db: *mut sqlite3,
-    _id: ffi::c_int,
-    _new_value: ffi::c_int,
+    db: *mut sqlite3,
+    id: ffi::c_int,
+    new_value: ffi::c_int,
 ) -> ffi::c_int {
-    stub!();
+    if db.is_null() {
+        return -1;
+    }
+    let limit = match id {
+        SQLITE_LIMIT_SQL_LENGTH => turso_core::Limit::SqlLength,
+        SQLITE_LIMIT_EXPR_DEPTH => turso_core::Limit::ExprDepth,
+        SQLITE_LIMIT_COMPOUND_SELECT => turso_core::Limit::CompoundSelect,
+        SQLITE_LIMIT_VDBE_OP => turso_core::Limit::VdbeOp,
+        SQLITE_LIMIT_ATTACHED => turso_core::Limit::Attached,
+        _ => return -1,
+    };
+    let db: &mut sqlite3 = &mut *db;
+    let db = db.inner.lock().unwrap();
+    // A negative value leaves the limit unchanged.
+    let new_value = usize::try_from(new_value).ok();
+    db.conn.limit(limit, new_value) as ffi::c_int
 }
 
 #[no_mangle]
@@ -470,8 +518,9 @@ pub unsafe extern "C" fn sqlite3_data_count(stmt: *mut sqlite3_stmt) -> ffi::c_i
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sqlite3_bind_parameter_count(_stmt: *mut sqlite3_stmt) -> ffi::c_int {
-    stub!();
+pub unsafe extern "C" fn sqlite3_bind_parameter_count(stmt: *mut sqlite3_stmt) -> ffi::c_int {
+    let stmt = &*stmt;
+    stmt.stmt.parameter_count() as ffi::c_int
 }
 
 #[no_mangle]
@@ -865,47 +914,150 @@ pub unsafe extern "C" fn sqlite3_aggregate_context(
     stub!();
 }
 
+pub struct sqlite3_blob {
+    pub(crate) db: *mut sqlite3,
+    pub(crate) blob: turso_core::Blob,
+}
+
+fn blob_error_code(err: &turso_core::LimboError) -> ffi::c_int {
+    match err {
+        turso_core::LimboError::Busy => SQLITE_BUSY,
+        turso_core::LimboError::ReadOnly => SQLITE_READONLY,
+        // The handle expired, or was aborted by a failed reseek.
+        turso_core::LimboError::TxError(_) => SQLITE_ABORT,
+        _ => SQLITE_ERROR,
+    }
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn sqlite3_blob_open(
-    _db: *mut sqlite3,
-    _db_name: *const ffi::c_char,
-    _table_name: *const ffi::c_char,
-    _column_name: *const ffi::c_char,
-    _rowid: i64,
-    _flags: ffi::c_int,
-    _blob_out: *mut *mut ffi::c_void,
+    db: *mut sqlite3,
+    db_name: *const ffi::c_char,
+    table_name: *const ffi::c_char,
+    column_name: *const ffi::c_char,
+    rowid: i64,
+    flags: ffi::c_int,
+    blob_out: *mut *mut ffi::c_void,
 ) -> ffi::c_int {
-    stub!();
+    if blob_out.is_null() {
+        return SQLITE_MISUSE;
+    }
+    *blob_out = std::ptr::null_mut();
+    if db.is_null() || table_name.is_null() || column_name.is_null() {
+        return SQLITE_MISUSE;
+    }
+    let db_name = if db_name.is_null() {
+        "main"
+    } else {
+        match CStr::from_ptr(db_name).to_str() {
+            Ok(name) => name,
+            Err(_) => return SQLITE_MISUSE,
+        }
+    };
+    let (Ok(table_name), Ok(column_name)) = (
+        CStr::from_ptr(table_name).to_str(),
+        CStr::from_ptr(column_name).to_str(),
+    ) else {
+        return SQLITE_MISUSE;
+    };
+    let inner = (*db).inner.lock().unwrap();
+    match inner
+        .conn
+        .blob_open(db_name, table_name, column_name, rowid, flags != 0)
+    {
+        Ok(blob) => {
+            *blob_out = Box::into_raw(Box::new(sqlite3_blob { db, blob })) as *mut ffi::c_void;
+            SQLITE_OK
+        }
+        Err(err) => blob_error_code(&err),
+    }
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn sqlite3_blob_read(
-    _blob: *mut ffi::c_void,
-    _data: *mut ffi::c_void,
-    _n: ffi::c_int,
-    _offset: ffi::c_int,
+    blob: *mut ffi::c_void,
+    data: *mut ffi::c_void,
+    n: ffi::c_int,
+    offset: ffi::c_int,
 ) -> ffi::c_int {
-    stub!();
+    if blob.is_null() || (data.is_null() && n != 0) {
+        return SQLITE_MISUSE;
+    }
+    let (Ok(n), Ok(offset)) = (usize::try_from(n), usize::try_from(offset)) else {
+        return SQLITE_ERROR;
+    };
+    let blob = &mut *(blob as *mut sqlite3_blob);
+    let _db = (*blob.db).inner.lock().unwrap();
+    let buf = if n == 0 {
+        &mut []
+    } else {
+        std::slice::from_raw_parts_mut(data as *mut u8, n)
+    };
+    match blob.blob.read(buf, offset) {
+        Ok(()) => SQLITE_OK,
+        Err(err) => blob_error_code(&err),
+    }
 }
 
 #[no_mangle]
 pub unsafe extern "C" fn sqlite3_blob_write(
-    _blob: *mut ffi::c_void,
-    _data: *const ffi::c_void,
-    _n: ffi::c_int,
-    _offset: ffi::c_int,
+    blob: *mut ffi::c_void,
+    data: *const ffi::c_void,
+    n: ffi::c_int,
+    offset: ffi::c_int,
 ) -> ffi::c_int {
-    stub!();
+    if blob.is_null() || (data.is_null() && n != 0) {
+        return SQLITE_MISUSE;
+    }
+    let (Ok(n), Ok(offset)) = (usize::try_from(n), usize::try_from(offset)) else {
+        return SQLITE_ERROR;
+    };
+    let blob = &mut *(blob as *mut sqlite3_blob);
+    let _db = (*blob.db).inner.lock().unwrap();
+    let data = if n == 0 {
+        &[]
+    } else {
+        std::slice::from_raw_parts(data as *const u8, n)
+    };
+    match blob.blob.write(data, offset) {
+        Ok(()) => SQLITE_OK,
+        Err(err) => blob_error_code(&err),
+    }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sqlite3_blob_bytes(_blob: *mut ffi::c_void) -> ffi::c_int {
-    stub!();
+pub unsafe extern "C" fn sqlite3_blob_reseek(blob: *mut ffi::c_void, rowid: i64) -> ffi::c_int {
+    if blob.is_null() {
+        return SQLITE_MISUSE;
+    }
+    let blob = &mut *(blob as *mut sqlite3_blob);
+    let _db = (*blob.db).inner.lock().unwrap();
+    match blob.blob.reseek(rowid) {
+        Ok(()) => SQLITE_OK,
+        Err(err) => blob_error_code(&err),
+    }
 }
 
 #[no_mangle]
-pub unsafe extern "C" fn sqlite3_blob_close(_blob: *mut ffi::c_void) -> ffi::c_int {
-    stub!();
+pub unsafe extern "C" fn sqlite3_blob_bytes(blob: *mut ffi::c_void) -> ffi::c_int {
+    if blob.is_null() {
+        return 0;
+    }
+    let blob = &*(blob as *mut sqlite3_blob);
+    blob.blob.len() as ffi::c_int
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn sqlite3_blob_close(blob: *mut ffi::c_void) -> ffi::c_int {
+    if blob.is_null() {
+        return SQLITE_OK;
+    }
+    let blob = Box::from_raw(blob as *mut sqlite3_blob);
+    let _db = (*blob.db).inner.lock().unwrap();
+    match blob.blob.close() {
+        Ok(()) => SQLITE_OK,
+        Err(err) => blob_error_code(&err),
+    }
 }
 
 #[no_mangle]
@@ -1102,20 +1254,34 @@ pub unsafe extern "C" fn sqlite3_wal_checkpoint(
 pub unsafe extern "C" fn sqlite3_wal_checkpoint_v2(
     db: *mut sqlite3,
     _db_name: *const ffi::c_char,
-    _mode: ffi::c_int,
-    _log_size: *mut ffi::c_int,
-    _checkpoint_count: *mut ffi::c_int,
+    mode: ffi::c_int,
+    log_size: *mut ffi::c_int,
+    checkpoint_count: *mut ffi::c_int,
 ) -> ffi::c_int {
     if db.is_null() {
         return SQLITE_MISUSE;
     }
+    let mode = match mode {
+        SQLITE_CHECKPOINT_PASSIVE => turso_core::CheckpointMode::Passive,
+        SQLITE_CHECKPOINT_FULL => turso_core::CheckpointMode::Full,
+        SQLITE_CHECKPOINT_RESTART => turso_core::CheckpointMode::Restart,
+        SQLITE_CHECKPOINT_TRUNCATE => turso_core::CheckpointMode::Truncate,
+        _ => return SQLITE_MISUSE,
+    };
     let db: &mut sqlite3 = &mut *db;
     let db = db.inner.lock().unwrap();
-    // TODO: Checkpointing modes and reporting back log size and checkpoint count to caller.
-    if db.conn.checkpoint().is_err() {
-        return SQLITE_ERROR;
+    let (rc, result) = match db.conn.checkpoint(mode) {
+        Ok(result) => (SQLITE_OK, Some(result)),
+        Err(turso_core::LimboError::Busy) => (SQLITE_BUSY, None),
+        Err(_) => (SQLITE_ERROR, None),
+    };
+    if !log_size.is_null() {
+        *log_size = result.map_or(-1, |r| r.num_wal_frames as ffi::c_int);
     }
-    SQLITE_OK
+    if !checkpoint_count.is_null() {
+        *checkpoint_count = result.map_or(-1, |r| r.num_checkpointed_frames as ffi::c_int);
+    }
+    rc
 }
 
 /// Get the number of frames in the WAL.
@@ -1,5 +1,6 @@
 #!/usr/bin/env python3
 import os
+import subprocess
 import time
 from pathlib import Path
 
@@ -252,12 +253,233 @@ def test_import_csv_create_table_from_header():
     shell.quit()
 
 
+def test_import_tsv():
+    shell = TestTursoShell()
+    shell.run_test("open-memory", ".open :memory:", "")
+    shell.run_test(
+        "import-tsv-create-table",
+        ".import --tsv ./testing/test_files/test_w_header.tsv tsv_table",
+        "",
+    )
+    shell.run_test(
+        "verify-tsv-data",
+        "select * from tsv_table;",
+        "1|2.0|String'1\n3|4.0|String2",
+    )
+    shell.quit()
+
+
+def test_import_csv_skips_header_of_existing_table():
+    shell = TestTursoShell()
+    shell.run_test("open-memory", ".open :memory:", "")
+    shell.run_test(
+        "create-csv-table",
+        "CREATE TABLE csv_table (id INT, interesting_number REAL, interesting_string TEXT);",
+        "",
+    )
+    shell.run_test(
+        "import-csv-header-detected",
+        ".import --csv ./testing/test_files/test_w_header.csv csv_table",
+        "",
+    )
+    shell.run_test(
+        "verify-csv-header-detected",
+        "select * from csv_table;",
+        "1|2.0|String'1\n3|4.0|String2",
+    )
+    shell.run_test(
+        "import-colsep",
+        ".import --colsep \\t --skip 1 ./testing/test_files/test_w_header.tsv csv_table",
+        "",
+    )
+    shell.run_test("verify-colsep", "select count(*) from csv_table;", "4")
+    shell.quit()
+
+
+def test_dump():
+    shell = TestTursoShell()
+    shell.run_test("open-memory", ".open :memory:", "")
+    shell.run_test("create-dump-table", "CREATE TABLE t (a INTEGER PRIMARY KEY, b TEXT, c REAL, d BLOB);", "")
+    shell.run_test("insert-dump-table", "INSERT INTO t VALUES (1, 'it''s', 2.5, x'0102'), (2, NULL, 3, 'x');", "")
+    shell.run_test_fn(
+        ".dump",
+        lambda res: "INSERT INTO t VALUES(1,'it''s',2.5,X'0102');\nINSERT INTO t VALUES(2,NULL,3.0,'x');" in res
+        and res.endswith("COMMIT;"),
+        "dump quotes values by their type",
+    )
+    shell.run_test("query-after-dump", "SELECT count(*) FROM t;", "2")
+    shell.quit()
+
+
+def test_recover():
+    shell = TestTursoShell()
+    shell.run_test("create-recover-table", "CREATE TABLE r (a INTEGER PRIMARY KEY, b TEXT);", "")
+    shell.run_test("insert-recover-table", "INSERT INTO r VALUES (7, 'seven'), (8, 'eight');", "")
+    shell.run_test_fn(
+        ".recover",
+        lambda res: "INSERT INTO r VALUES(7,'seven');\nINSERT INTO r VALUES(8,'eight');" in res
+        and "lost_and_found" not in res,
+        "recover reads the rows back from the pages",
+    )
+    shell.quit()
+
+
+def test_output_modes():
+    shell = TestTursoShell()
+    shell.execute_dot(".mode csv")
+    shell.run_test("mode-csv", "SELECT 1, 'a,b', NULL;", '1,"a,b",TURSO')
+    shell.execute_dot(".mode list")
+    shell.execute_dot(".separator ,")
+    shell.run_test("separator", "SELECT 1, 2;", "1,2")
+    shell.execute_dot(".separator |")
+
+    # The results of the other modes don't end with the end marker, so they go to a file
+    output_file = shell.config.test_dir / shell.config.py_folder / "turso_modes.txt"
+    shell.execute_dot(f".output {output_file}")
+    shell.execute_dot(".headers on")
+    shell.execute_dot(".mode json")
+    shell.execute_dot("SELECT id, first_name FROM users WHERE id < 3;")
+    shell.execute_dot(".mode markdown")
+    shell.execute_dot("SELECT id, first_name AS name FROM users WHERE id < 3;")
+    shell.execute_dot(".mode table")
+    shell.execute_dot("SELECT 1 AS a;")
+    shell.execute_dot(".mode line")
+    shell.execute_dot("SELECT 1 AS a, 'x' AS bb;")
+    shell.execute_dot(".headers off")
+    shell.execute_dot(".mode insert t")
+    shell.execute_dot("SELECT 1, 'it''s', NULL;")
+    shell.execute_dot(".mode html")
+    shell.execute_dot("SELECT '<b>';")
+    shell.execute_dot(".output stdout")
+    time.sleep(3)
+
+    with open(output_file, "r") as f:
+        contents = f.read()
+    os.remove(output_file)
+
+    expected = [
+        ("mode-json", '[{"id":1,"first_name":"Alice"},\n{"id":2,"first_name":"Bob"}]\n'),
+        ("mode-markdown", "| id | name  |\n|----|-------|\n| 1  | Alice |\n| 2  | Bob   |\n"),
+        ("mode-table", "+---+\n| a |\n+---+\n| 1 |\n+---+\n"),
+        ("mode-line", "    a = 1\n   bb = x\n"),
+        ("mode-insert", "INSERT INTO t VALUES(1,'it''s',NULL);\n"),
+        ("mode-html", "<TR><TD>&lt;b&gt;</TD>\n</TR>\n"),
+    ]
+    for name, output in expected:
+        console.test(f"Running test: {name}")
+        assert output in contents, f"Test failed: {name}\nExpected:\n{output!r}\nActual:\n{contents!r}"
+    shell.quit()
+
+
 def test_table_patterns():
     shell = TestTursoShell()
     shell.run_test("tables-pattern", ".tables us%", "users")
     shell.quit()
 
 
+def test_schema_patterns():
+    shell = TestTursoShell()
+    shell.run_test("create-view", "CREATE VIEW young_users AS SELECT * FROM users WHERE age < 30;", "")
+    shell.run_test("tables-with-views", ".tables", "products users young_users")
+    shell.run_test(
+        "schema-pattern",
+        ".schema prod%",
+        "CREATE TABLE products (id INTEGER PRIMARY KEY, name TEXT, price INTEGER);",
+    )
+    # The lines of results are stripped, so the indented schema goes to a file
+    output_file = shell.config.test_dir / shell.config.py_folder / "turso_schema.txt"
+    shell.execute_dot(f".output {output_file}")
+    shell.execute_dot(".schema --indent products")
+    shell.execute_dot(".output stdout")
+    time.sleep(1)
+    with open(output_file, "r") as f:
+        contents = f.read()
+    os.remove(output_file)
+    console.test("Running test: schema-indent")
+    expected = "CREATE TABLE products (\n  id INTEGER PRIMARY KEY,\n  name TEXT,\n  price INTEGER\n);\n"
+    assert contents == expected, f"Test failed: schema-indent\nExpected:\n{expected!r}\nActual:\n{contents!r}"
+    shell.quit()
+
+
+def test_read_and_bail():
+    script = os.path.join("testing", "read_script.sql")
+    with open(script, "w") as f:
+        f.write(
+            "CREATE TABLE r (x);\n"
+            "INSERT INTO r VALUES (1);\n"
+            "INSERT INTO missing VALUES (2);\n"
+            "INSERT INTO r VALUES (3);\n"
+        )
+    shell = TestTursoShell()
+    shell.run_test_fn(f".read {script}", lambda res: "missing" in res, "read reports the failing statement")
+    shell.run_test("read-continues", "SELECT x FROM r;", "1\n3")
+    shell.quit()
+
+    # With .bail on, a shell reading its input from a pipe exits at the first error
+    console.test("Running test: read-bails")
+    result = subprocess.run(
+        [shell.config.sqlite_exec],
+        input=f".bail on\n.read {script}\nSELECT x FROM r;\n",
+        capture_output=True,
+        text=True,
+    )
+    output = result.stdout + result.stderr
+    assert "stopped at the first error" in output, output
+    assert "1" not in output.split("\n"), output
+    assert result.returncode != 0
+    os.remove(script)
+
+
+def test_timer_and_stats():
+    # The settings are turned off along with each statement, so that the end marker query of the
+    # test shell isn't followed by a run time or counters
+    shell = TestTursoShell("CREATE TABLE t (x); INSERT INTO t VALUES (1), (2);")
+    shell.execute_dot(".timer on")
+    shell.run_test_fn(
+        "SELECT count(*) FROM t;\n.timer off",
+        lambda res: res.startswith("2\nRun Time: real ") and " user " in res and " sys " in res,
+        "timer prints the run time of the statement",
+    )
+    shell.execute_dot(".stats on")
+    shell.run_test_fn(
+        "SELECT count(*) FROM t;\n.stats off",
+        lambda res: "Virtual Machine Steps:" in res and "Page Cache Hits:" in res,
+        "stats prints the counters of the statement",
+    )
+    shell.execute_dot(".stats on")
+    shell.run_test_fn(
+        "INSERT INTO t VALUES (3);\n.stats off",
+        lambda res: "Pages Written:" in res and "Pages Written:                       0" not in res,
+        "stats counts the pages written by the statement",
+    )
+    shell.run_test("stats-off", "SELECT count(*) FROM t;", "3")
+    shell.quit()
+
+
+def test_multiline_statements_and_completion():
+    shell = TestTursoShell("CREATE TABLE users (id INTEGER PRIMARY KEY, username TEXT);")
+    shell.run_test("semicolon-in-string", "SELECT 'a;\nb';", "a;\nb")
+    shell.run_test("newline-in-string", "SELECT hex('a\nb');", "610A62")
+    shell.run_test("indented-line-in-string", "SELECT hex('a\n  b');", "610A202062")
+    shell.run_test("comment-line", "-- nothing to run\nSELECT 1;", "1")
+    shell.run_test(
+        "trigger-body",
+        "CREATE TRIGGER tr AFTER INSERT ON users BEGIN\n  UPDATE users SET username = 'x';\nEND;",
+        "",
+    )
+    shell.run_test(
+        "complete-table",
+        "SELECT candidate FROM completion('use', 'SELECT * FROM use') WHERE candidate LIKE 'use%';",
+        "users\nusername",
+    )
+    shell.run_test(
+        "complete-column",
+        "SELECT candidate FROM completion('userna', 'SELECT userna');",
+        "username",
+    )
+    shell.quit()
+
+
 def test_update_with_limit():
     turso = TestTursoShell(
         "CREATE TABLE t (a,b,c); insert into t values (1,2,3), (4,5,6), (7,8,9), (1,2,3),(4,5,6), (7,8,9);"
@@ -329,6 +551,15 @@ def main():
     test_import_csv_verbose()
     test_import_csv_skip()
     test_import_csv_create_table_from_header()
+    test_import_tsv()
+    test_import_csv_skips_header_of_existing_table()
+    test_dump()
+    test_recover()
+    test_output_modes()
+    test_schema_patterns()
+    test_read_and_bail()
+    test_timer_and_stats()
+    test_multiline_statements_and_completion()
     test_table_patterns()
     test_update_with_limit()
     test_update_with_limit_and_offset()
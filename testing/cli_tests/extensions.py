@@ -377,6 +377,7 @@ def _test_series(limbo: TestTursoShell):
 
 def test_kv():
     _test_kv(exec_name=None, ext_path="target/debug/libturso_ext_tests")
+    _test_kv(exec_name=None, ext_path="target/debug/liblimbo_sqlite_test_ext")
     _test_kv(exec_name="sqlite3", ext_path="target/debug/liblimbo_sqlite_test_ext")
 
 
@@ -474,6 +475,90 @@ def _test_kv(exec_name, ext_path):
         "select * from t a, other b where b.c = 23 and a.key='100';",
         lambda res: "100|updated2|23|32|23" == res,
     )
+    limbo.run_test_fn(
+        "update t set value = value || '-' || key where key = '100';",
+        null,
+        "can update using the current column values",
+    )
+    limbo.run_test_fn(
+        "select * from t where key = '100';",
+        lambda res: res == "100|updated2-100",
+        "update expressions see the old row",
+    )
+    limbo.run_test_fn(
+        "update t set value = 'from-' || b.a from other b where t.key = '100';",
+        null,
+        "can update a virtual table with a FROM clause",
+    )
+    limbo.run_test_fn(
+        "select * from t where key = '100';",
+        lambda res: res == "100|from-23",
+        "update from another table sets the value",
+    )
+    limbo.execute_dot("insert into t values ('del1', 'gone');")
+    limbo.execute_dot("insert into t values ('del2', 'kept');")
+    limbo.run_test_fn(
+        "delete from t where value = 'gone';",
+        null,
+        "can delete with a predicate not handled by xBestIndex",
+    )
+    limbo.run_test_fn(
+        "select key from t where key = 'del1';",
+        lambda res: res == "",
+        "row matching the predicate is deleted",
+    )
+    limbo.run_test_fn(
+        "delete from t where key = 'del2' and value = 'kept';",
+        null,
+        "can delete with both consumed and unconsumed constraints",
+    )
+    limbo.run_test_fn(
+        "select count(*) from t where key like 'del%';",
+        lambda res: res == "0",
+        "all matching rows are deleted",
+    )
+    limbo.run_test_fn(
+        "select * from t where key = '100';",
+        lambda res: res == "100|from-23",
+        "other rows are left untouched by deletes",
+    )
+    limbo.quit()
+
+
+def test_kv_transactions():
+    ext_path = "target/debug/libturso_ext_tests"
+    limbo = TestTursoShell()
+    limbo.execute_dot(f".load {ext_path}")
+    limbo.execute_dot("create virtual table t using kv_store;")
+    limbo.execute_dot("insert into t values ('a', '1');")
+    limbo.execute_dot("BEGIN;")
+    limbo.execute_dot("insert into t values ('b', '2');")
+    limbo.execute_dot("ROLLBACK;")
+    limbo.run_test_fn(
+        "select key from t order by key;",
+        lambda res: res == "a",
+        "rollback discards vtab changes",
+    )
+    limbo.execute_dot("BEGIN;")
+    limbo.execute_dot("insert into t values ('b', '2');")
+    limbo.execute_dot("COMMIT;")
+    limbo.run_test_fn(
+        "select key from t order by key;",
+        lambda res: res == "a\nb",
+        "commit keeps vtab changes",
+    )
+    limbo.execute_dot("BEGIN;")
+    limbo.execute_dot("insert into t values ('c', '3');")
+    limbo.execute_dot("SAVEPOINT sp;")
+    limbo.execute_dot("insert into t values ('d', '4');")
+    limbo.execute_dot("ROLLBACK TO sp;")
+    limbo.execute_dot("RELEASE sp;")
+    limbo.execute_dot("COMMIT;")
+    limbo.run_test_fn(
+        "select key from t order by key;",
+        lambda res: res == "a\nb\nc",
+        "rollback to savepoint discards only later vtab changes",
+    )
     limbo.quit()
 
 
@@ -841,6 +926,7 @@ def test_tablestats():
 
 def test_hidden_columns():
     _test_hidden_columns(exec_name=None, ext_path="target/debug/libturso_ext_tests")
+    _test_hidden_columns(exec_name=None, ext_path="target/debug/liblimbo_sqlite_test_ext")
     _test_hidden_columns(exec_name="sqlite3", ext_path="target/debug/liblimbo_sqlite_test_ext")
 
 
@@ -1008,6 +1094,7 @@ def main():
         test_vfs()
         test_sqlite_vfs_compat()
         test_kv()
+        test_kv_transactions()
         test_drop_virtual_table()
         test_create_virtual_table()
         test_csv()
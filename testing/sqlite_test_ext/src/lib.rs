@@ -18,6 +18,6 @@ pub unsafe extern "C" fn sqlite3_limbosqlitetestext_init(
     db: *mut std::ffi::c_void,
     err_msg: *mut *mut i8,
     api: *const std::ffi::c_void,
-) {
-    let _ = sqlite3_kvstore_init(db, err_msg, api);
+) -> i32 {
+    sqlite3_kvstore_init(db, err_msg, api)
 }
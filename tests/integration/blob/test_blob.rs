@@ -0,0 +1,248 @@
+use crate::common::{limbo_exec_rows, maybe_setup_tracing, TempDatabase};
+use rusqlite::types::Value;
+use std::sync::Arc;
+use turso_core::{Connection, LimboError, Result};
+
+const BLOB_SIZE: usize = 20_000;
+
+fn create_blob_db() -> Result<(TempDatabase, Arc<Connection>)> {
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, data BLOB, name TEXT, n INTEGER)")?;
+    conn.execute("CREATE INDEX t_name ON t (name)")?;
+    conn.execute(format!(
+        "INSERT INTO t VALUES (1, randomblob({BLOB_SIZE}), 'one', 1)"
+    ))?;
+    conn.execute("INSERT INTO t VALUES (2, x'00112233', 'two', 2)")?;
+    conn.execute("INSERT INTO t VALUES (3, NULL, 'three', 3)")?;
+    Ok((tmp_db, conn))
+}
+
+fn select_blob(tmp_db: &TempDatabase, conn: &Arc<Connection>, id: i64) -> Vec<u8> {
+    match &limbo_exec_rows(tmp_db, conn, &format!("SELECT data FROM t WHERE id = {id}"))[0][0] {
+        Value::Blob(data) => data.clone(),
+        value => panic!("expected a blob, got {value:?}"),
+    }
+}
+
+#[test]
+fn test_blob_read_in_chunks() -> Result<()> {
+    maybe_setup_tracing();
+    let (tmp_db, conn) = create_blob_db()?;
+    let expected = select_blob(&tmp_db, &conn, 1);
+
+    let mut blob = conn.blob_open("main", "t", "data", 1, false)?;
+    assert_eq!(blob.len(), BLOB_SIZE);
+    // Chunks that don't line up with the overflow pages the value spans.
+    let mut read = vec![0; BLOB_SIZE];
+    for (i, chunk) in read.chunks_mut(1000 + 7).enumerate() {
+        blob.read(chunk, i * (1000 + 7))?;
+    }
+    assert_eq!(read, expected);
+
+    let mut tail = [0; 10];
+    blob.read(&mut tail, BLOB_SIZE - 10)?;
+    assert_eq!(&tail, &expected[BLOB_SIZE - 10..]);
+    blob.close()
+}
+
+#[test]
+fn test_blob_write() -> Result<()> {
+    maybe_setup_tracing();
+    let (tmp_db, conn) = create_blob_db()?;
+    let mut expected = select_blob(&tmp_db, &conn, 1);
+
+    let mut blob = conn.blob_open("main", "t", "data", 1, true)?;
+    let patch = vec![0xab; 5000];
+    blob.write(&patch, 3000)?;
+    expected[3000..8000].copy_from_slice(&patch);
+    blob.write(b"end", BLOB_SIZE - 3)?;
+    expected[BLOB_SIZE - 3..].copy_from_slice(b"end");
+
+    let mut read = vec![0; BLOB_SIZE];
+    blob.read(&mut read, 0)?;
+    assert_eq!(read, expected);
+    blob.close()?;
+
+    assert_eq!(select_blob(&tmp_db, &conn, 1), expected);
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "PRAGMA integrity_check"),
+        vec![vec![Value::Text("ok".to_string())]]
+    );
+
+    let tmp_db = TempDatabase::new_with_existent(&tmp_db.path, false);
+    let conn = tmp_db.connect_limbo();
+    assert_eq!(select_blob(&tmp_db, &conn, 1), expected);
+    Ok(())
+}
+
+#[test]
+fn test_blob_reseek() -> Result<()> {
+    maybe_setup_tracing();
+    let (tmp_db, conn) = create_blob_db()?;
+
+    let mut blob = conn.blob_open("main", "t", "data", 1, false)?;
+    blob.reseek(2)?;
+    assert_eq!(blob.len(), 4);
+    let mut read = [0; 4];
+    blob.read(&mut read, 0)?;
+    assert_eq!(read, [0x00, 0x11, 0x22, 0x33]);
+
+    // A failed reseek aborts the handle until it's moved again.
+    assert!(matches!(
+        blob.reseek(3),
+        Err(LimboError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        blob.read(&mut read, 0),
+        Err(LimboError::TxError(_))
+    ));
+    blob.reseek(1)?;
+    assert_eq!(blob.len(), BLOB_SIZE);
+    blob.close()?;
+
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "SELECT count(*) FROM t"),
+        vec![vec![Value::Integer(3)]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_blob_out_of_range() -> Result<()> {
+    maybe_setup_tracing();
+    let (_tmp_db, conn) = create_blob_db()?;
+    let mut blob = conn.blob_open("main", "t", "data", 2, true)?;
+    let mut read = [0; 4];
+    assert!(matches!(
+        blob.read(&mut read, 1),
+        Err(LimboError::InvalidArgument(_))
+    ));
+    assert!(matches!(
+        blob.write(b"abcde", 0),
+        Err(LimboError::InvalidArgument(_))
+    ));
+    blob.read(&mut read[..0], 4)?;
+    blob.close()
+}
+
+#[test]
+fn test_blob_open_errors() -> Result<()> {
+    maybe_setup_tracing();
+    let (_tmp_db, conn) = create_blob_db()?;
+    for (database, table, column, rowid, writable) in [
+        ("main", "missing", "data", 1, false),
+        ("missing", "t", "data", 1, false),
+        ("main", "t", "missing", 1, false),
+        ("main", "t", "data", 42, false),
+        ("main", "t", "data", 3, false),
+        ("main", "t", "n", 1, false),
+        ("main", "t", "name", 1, true),
+    ] {
+        assert!(
+            matches!(
+                conn.blob_open(database, table, column, rowid, writable),
+                Err(LimboError::InvalidArgument(_))
+            ),
+            "{database}.{table}.{column} of row {rowid} was opened"
+        );
+    }
+    // Indexed columns can still be read.
+    let mut blob = conn.blob_open("main", "t", "name", 1, false)?;
+    let mut read = [0; 3];
+    blob.read(&mut read, 0)?;
+    assert_eq!(&read, b"one");
+    assert!(matches!(blob.write(b"two", 0), Err(LimboError::ReadOnly)));
+    blob.close()
+}
+
+#[test]
+fn test_blob_expires_when_row_changes() -> Result<()> {
+    maybe_setup_tracing();
+    let (tmp_db, conn) = create_blob_db()?;
+    let mut blob = conn.blob_open("main", "t", "data", 2, false)?;
+    let mut read = [0; 4];
+    blob.read(&mut read, 0)?;
+
+    conn.execute("UPDATE t SET data = x'0011223344' WHERE id = 2")?;
+    assert!(matches!(
+        blob.read(&mut read, 0),
+        Err(LimboError::TxError(_))
+    ));
+    blob.reseek(2)?;
+    assert_eq!(blob.len(), 5);
+    blob.close()?;
+
+    assert_eq!(
+        select_blob(&tmp_db, &conn, 2),
+        vec![0x00, 0x11, 0x22, 0x33, 0x44]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_blob_write_into_zeroblob() -> Result<()> {
+    maybe_setup_tracing();
+    const SIZE: usize = 5 * 1024 * 1024;
+    let tmp_db = TempDatabase::new_empty(false);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("CREATE TABLE files (id INTEGER PRIMARY KEY, name TEXT, data BLOB)")?;
+    conn.execute(format!(
+        "INSERT INTO files VALUES (1, 'big.bin', zeroblob({SIZE}))"
+    ))?;
+    assert_eq!(
+        limbo_exec_rows(
+            &tmp_db,
+            &conn,
+            "SELECT length(data), typeof(data) FROM files"
+        ),
+        vec![vec![
+            Value::Integer(SIZE as i64),
+            Value::Text("blob".to_string())
+        ]]
+    );
+
+    // Stream the content in, a chunk at a time.
+    let chunk = (0..64 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    let mut blob = conn.blob_open("main", "files", "data", 1, true)?;
+    assert_eq!(blob.len(), SIZE);
+    for offset in (0..SIZE).step_by(chunk.len()) {
+        blob.write(&chunk, offset)?;
+    }
+    blob.close()?;
+
+    let mut blob = conn.blob_open("main", "files", "data", 1, false)?;
+    let mut read = vec![0; chunk.len()];
+    blob.read(&mut read, SIZE - chunk.len())?;
+    assert_eq!(read, chunk);
+    blob.close()?;
+
+    match &limbo_exec_rows(&tmp_db, &conn, "SELECT data, name FROM files")[0][..] {
+        [Value::Blob(data), Value::Text(name)] => {
+            assert_eq!(data.len(), SIZE);
+            assert_eq!(&data[65536..65540], &[0, 1, 2, 3]);
+            assert_eq!(name, "big.bin");
+        }
+        row => panic!("unexpected row {row:?}"),
+    }
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "PRAGMA integrity_check"),
+        vec![vec![Value::Text("ok".to_string())]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_update_to_zeroblob() -> Result<()> {
+    maybe_setup_tracing();
+    let (tmp_db, conn) = create_blob_db()?;
+    conn.execute("UPDATE t SET data = zeroblob(10000) WHERE id = 2")?;
+    let mut blob = conn.blob_open("main", "t", "data", 2, true)?;
+    assert_eq!(blob.len(), 10000);
+    blob.write(b"limbo", 9995)?;
+    blob.close()?;
+    let data = select_blob(&tmp_db, &conn, 2);
+    assert_eq!(data.len(), 10000);
+    assert_eq!(&data[9994..], b"\0limbo");
+    Ok(())
+}
@@ -0,0 +1,131 @@
+use crate::common::{limbo_exec_rows, maybe_setup_tracing, TempDatabase};
+use rusqlite::types::Value;
+use turso_core::{LimboError, Result};
+
+const ROWS: usize = 500;
+
+fn create_compressed_db(mode: &str) -> Result<TempDatabase> {
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+    conn.execute(format!("PRAGMA compression = {mode}"))?;
+    conn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, doc TEXT)")?;
+    conn.execute("CREATE INDEX t_doc ON t (doc)")?;
+    for i in 0..ROWS {
+        conn.execute(format!(
+            "INSERT INTO t VALUES ({i}, '{{\"id\": {i}, \"name\": \"user {i}\", \"tags\": [\"a\", \"b\", \"c\"]}}')"
+        ))?;
+    }
+    conn.execute("PRAGMA wal_checkpoint(TRUNCATE)")?;
+    Ok(tmp_db)
+}
+
+fn check_compressed_db(mode: &str) -> Result<()> {
+    let tmp_db = create_compressed_db(mode)?;
+    let file = std::fs::read(&tmp_db.path).unwrap();
+    // Page 1 and the pages that didn't compress aside, most of every slot is left zeroed.
+    let zeros = file.iter().filter(|b| **b == 0).count();
+    assert!(
+        zeros > file.len() / 2,
+        "{zeros} of {} bytes are zeros",
+        file.len()
+    );
+
+    let tmp_db = TempDatabase::new_with_existent(&tmp_db.path, true);
+    let conn = tmp_db.connect_limbo();
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "PRAGMA compression"),
+        vec![vec![Value::Text(mode.to_string())]]
+    );
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "SELECT count(*), sum(id) FROM t"),
+        vec![vec![
+            Value::Integer(ROWS as i64),
+            Value::Integer((ROWS * (ROWS - 1) / 2) as i64)
+        ]]
+    );
+    assert_eq!(
+        limbo_exec_rows(
+            &tmp_db,
+            &conn,
+            "SELECT id FROM t WHERE doc LIKE '%user 42\"%'"
+        ),
+        vec![vec![Value::Integer(42)]]
+    );
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "PRAGMA integrity_check"),
+        vec![vec![Value::Text("ok".to_string())]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_compressed_db_lz4() -> Result<()> {
+    maybe_setup_tracing();
+    check_compressed_db("lz4")
+}
+
+#[test]
+fn test_compressed_db_zstd() -> Result<()> {
+    maybe_setup_tracing();
+    check_compressed_db("zstd")
+}
+
+/// Bytes actually allocated on disk for a database with 32 KiB pages, compressed with `mode`.
+#[cfg(target_os = "linux")]
+fn allocated_size(mode: Option<&str>) -> Result<u64> {
+    use std::os::unix::fs::MetadataExt;
+
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("PRAGMA page_size = 32768")?;
+    if let Some(mode) = mode {
+        conn.execute(format!("PRAGMA compression = {mode}"))?;
+    }
+    conn.execute("CREATE TABLE t (id INTEGER PRIMARY KEY, doc TEXT)")?;
+    for i in 0..ROWS * 4 {
+        conn.execute(format!(
+            "INSERT INTO t VALUES ({i}, '{{\"id\": {i}, \"name\": \"user {i}\", \"tags\": [\"a\", \"b\", \"c\"]}}')"
+        ))?;
+    }
+    conn.execute("PRAGMA wal_checkpoint(TRUNCATE)")?;
+    Ok(std::fs::metadata(&tmp_db.path).unwrap().blocks() * 512)
+}
+
+#[test]
+#[cfg(target_os = "linux")]
+fn test_compressed_db_is_smaller() -> Result<()> {
+    maybe_setup_tracing();
+    let plain = allocated_size(None)?;
+    let compressed = allocated_size(Some("zstd"))?;
+    assert!(
+        compressed < plain / 2,
+        "compressed db uses {compressed} bytes, uncompressed {plain}"
+    );
+    Ok(())
+}
+
+#[test]
+fn test_compression_of_existing_db() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = create_compressed_db("lz4")?;
+    let conn = tmp_db.connect_limbo();
+    conn.execute("PRAGMA compression = lz4")?;
+    assert!(matches!(
+        conn.execute("PRAGMA compression = zstd"),
+        Err(LimboError::InvalidArgument(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_compressed_db_cannot_be_encrypted() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("PRAGMA compression = zstd")?;
+    assert!(matches!(
+        conn.execute("PRAGMA key = 'secret'"),
+        Err(LimboError::InvalidArgument(_))
+    ));
+    Ok(())
+}
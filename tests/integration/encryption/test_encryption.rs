@@ -0,0 +1,134 @@
+use crate::common::{limbo_exec_rows, maybe_setup_tracing, TempDatabase};
+use rusqlite::types::Value;
+use turso_core::{LimboError, Result};
+
+const KEY: &str = "x'000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f'";
+const HEXKEY: &str = "f0e0d0c0b0a090807060504030201000f0e0d0c0b0a090807060504030201000";
+
+fn create_encrypted_db(key_pragma: &str) -> Result<TempDatabase> {
+    let tmp_db = TempDatabase::new_empty(false);
+    let conn = tmp_db.connect_limbo();
+    conn.execute(key_pragma)?;
+    conn.execute("CREATE TABLE t (x TEXT)")?;
+    conn.execute("INSERT INTO t VALUES ('top secret'), ('classified')")?;
+    conn.execute("PRAGMA wal_checkpoint(TRUNCATE)")?;
+    Ok(tmp_db)
+}
+
+fn rows(tmp_db: &TempDatabase, conn: &std::sync::Arc<turso_core::Connection>) -> Vec<Vec<Value>> {
+    limbo_exec_rows(tmp_db, conn, "SELECT x FROM t")
+}
+
+#[test]
+fn test_encrypted_db_reopen_with_key() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = create_encrypted_db(&format!("PRAGMA key = \"{KEY}\""))?;
+    let file = std::fs::read(&tmp_db.path).unwrap();
+    assert!(!file.windows(10).any(|window| window == b"top secret"));
+    // The header stays readable, like in SQLCipher with a plaintext header.
+    assert!(file.starts_with(b"SQLite format 3\0"));
+
+    let tmp_db = TempDatabase::new_with_existent(&tmp_db.path, false);
+    let conn = tmp_db.connect_limbo();
+    conn.execute(format!("PRAGMA key = \"{KEY}\""))?;
+    assert_eq!(
+        rows(&tmp_db, &conn),
+        vec![
+            vec![Value::Text("top secret".to_string())],
+            vec![Value::Text("classified".to_string())],
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_encrypted_db_without_key() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = create_encrypted_db(&format!("PRAGMA hexkey = '{HEXKEY}'"))?;
+
+    let tmp_db = TempDatabase::new_with_existent(&tmp_db.path, false);
+    let conn = tmp_db.connect_limbo();
+    assert!(matches!(
+        conn.prepare("SELECT x FROM t"),
+        Err(LimboError::NotADB)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_encrypted_db_wrong_key() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = create_encrypted_db(&format!("PRAGMA hexkey = '{HEXKEY}'"))?;
+
+    let tmp_db = TempDatabase::new_with_existent(&tmp_db.path, false);
+    let conn = tmp_db.connect_limbo();
+    assert!(matches!(
+        conn.execute(format!("PRAGMA key = \"{KEY}\"")),
+        Err(LimboError::NotADB)
+    ));
+    assert!(matches!(
+        conn.prepare("SELECT x FROM t"),
+        Err(LimboError::NotADB)
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_encrypted_db_rekey() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = create_encrypted_db(&format!("PRAGMA key = \"{KEY}\""))?;
+    {
+        let tmp_db = TempDatabase::new_with_existent(&tmp_db.path, false);
+        let conn = tmp_db.connect_limbo();
+        conn.execute(format!("PRAGMA key = \"{KEY}\""))?;
+        conn.execute(format!("PRAGMA hexrekey = '{HEXKEY}'"))?;
+        assert_eq!(rows(&tmp_db, &conn).len(), 2);
+    }
+
+    let tmp_db = TempDatabase::new_with_existent(&tmp_db.path, false);
+    let conn = tmp_db.connect_limbo();
+    assert!(matches!(
+        conn.execute(format!("PRAGMA key = \"{KEY}\"")),
+        Err(LimboError::NotADB)
+    ));
+    conn.execute(format!("PRAGMA hexkey = '{HEXKEY}'"))?;
+    assert_eq!(rows(&tmp_db, &conn).len(), 2);
+    Ok(())
+}
+
+#[test]
+fn test_rekey_plaintext_db() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new_empty(false);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("CREATE TABLE t (x TEXT)")?;
+    assert!(matches!(
+        conn.execute(format!("PRAGMA rekey = \"{KEY}\"")),
+        Err(LimboError::InvalidArgument(_))
+    ));
+    Ok(())
+}
+
+#[test]
+fn test_encrypted_db_xchacha20poly1305() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new_empty(false);
+    {
+        let conn = tmp_db.connect_limbo();
+        conn.execute("PRAGMA cipher = xchacha20poly1305")?;
+        conn.execute(format!("PRAGMA hexkey = '{HEXKEY}'"))?;
+        conn.execute("CREATE TABLE t (x TEXT)")?;
+        conn.execute("INSERT INTO t VALUES ('top secret')")?;
+        conn.execute("PRAGMA wal_checkpoint(TRUNCATE)")?;
+    }
+
+    let tmp_db = TempDatabase::new_with_existent(&tmp_db.path, false);
+    let conn = tmp_db.connect_limbo();
+    // The cipher of an existing database is read from its header.
+    conn.execute(format!("PRAGMA hexkey = '{HEXKEY}'"))?;
+    assert_eq!(
+        rows(&tmp_db, &conn),
+        vec![vec![Value::Text("top secret".to_string())]]
+    );
+    Ok(())
+}
@@ -1,4 +1,7 @@
+mod blob;
 mod common;
+mod compression;
+mod encryption;
 mod functions;
 mod fuzz;
 mod query_processing;
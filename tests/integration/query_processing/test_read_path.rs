@@ -1,4 +1,4 @@
-use crate::common::TempDatabase;
+use crate::common::{limbo_exec_rows, TempDatabase};
 use turso_core::{StepResult, Value};
 
 #[test]
@@ -703,3 +703,333 @@ fn test_bind_parameters_delete_rowid_alias_seek_out_of_order() -> anyhow::Result
     assert_eq!(ins.parameters().count(), 4);
     Ok(())
 }
+
+#[test]
+fn test_bind_parameters_limit_offset() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name TEXT);",
+        false,
+    );
+    let conn = tmp_db.connect_limbo();
+    for id in 1..=5 {
+        conn.execute(format!(
+            "insert into test (id, name) values ({id}, 'test{id}');"
+        ))?;
+    }
+
+    let mut sel = conn.prepare("select id from test where id > ? order by id limit ? offset ?;")?;
+    assert_eq!(sel.parameters().count(), 3);
+    for (min_id, limit, offset, expected) in [
+        (1, 2, 1, vec![3, 4]),
+        (0, -1, 3, vec![4, 5]),
+        (0, 0, 0, vec![]),
+        (0, 10, -1, vec![1, 2, 3, 4, 5]),
+    ] {
+        sel.reset();
+        sel.bind_at(1.try_into()?, Value::Integer(min_id));
+        sel.bind_at(2.try_into()?, Value::Integer(limit));
+        sel.bind_at(3.try_into()?, Value::Integer(offset));
+        let mut ids = vec![];
+        loop {
+            match sel.step()? {
+                StepResult::Row => {
+                    let row = sel.row().unwrap();
+                    let Value::Integer(id) = row.get::<&Value>(0).unwrap() else {
+                        panic!("expected an integer id");
+                    };
+                    ids.push(*id);
+                }
+                StepResult::IO => sel.run_once()?,
+                StepResult::Done | StepResult::Interrupt => break,
+                StepResult::Busy => panic!("database busy"),
+            }
+        }
+        assert_eq!(ids, expected);
+    }
+
+    let mut upd = conn.prepare("update test set name = 'updated' limit :n;")?;
+    upd.bind_at(1.try_into()?, Value::Integer(2));
+    loop {
+        match upd.step()? {
+            StepResult::IO => upd.run_once()?,
+            StepResult::Done | StepResult::Interrupt => break,
+            StepResult::Busy => panic!("database busy"),
+            _ => {}
+        }
+    }
+
+    let mut sel = conn.prepare("select count(*) from test where name = 'updated';")?;
+    loop {
+        match sel.step()? {
+            StepResult::Row => {
+                let row = sel.row().unwrap();
+                assert_eq!(row.get::<&Value>(0).unwrap(), &Value::Integer(2));
+            }
+            StepResult::IO => sel.run_once()?,
+            StepResult::Done | StepResult::Interrupt => break,
+            StepResult::Busy => panic!("database busy"),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_bind_parameters_numbered_in_statement_order() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table test (i integer);", false);
+    let conn = tmp_db.connect_limbo();
+
+    let mut stmt = conn.prepare("select :b, ?, @a, $b, ?5, ?, :b")?;
+    assert_eq!(stmt.parameter_count(), 6);
+    assert_eq!(stmt.parameter_name(1.try_into()?), Some(":b".to_string()));
+    assert_eq!(stmt.parameter_name(2.try_into()?), None);
+    assert_eq!(stmt.parameter_name(3.try_into()?), Some("@a".to_string()));
+    assert_eq!(stmt.parameter_name(4.try_into()?), Some("$b".to_string()));
+    assert_eq!(stmt.parameter_name(5.try_into()?), Some("?5".to_string()));
+    assert_eq!(stmt.parameter_name(6.try_into()?), None);
+    assert_eq!(stmt.parameter_index(":b"), Some(1.try_into()?));
+    assert_eq!(stmt.parameter_index("$b"), Some(4.try_into()?));
+    assert_eq!(stmt.parameter_index("?5"), Some(5.try_into()?));
+    assert_eq!(stmt.parameter_index(":a"), None);
+
+    for i in 1..=6usize {
+        stmt.bind_at(i.try_into()?, Value::Integer(i as i64));
+    }
+    let mut rows = vec![];
+    loop {
+        match stmt.step()? {
+            StepResult::Row => {
+                let row = stmt.row().unwrap();
+                rows.push(row.get_values().cloned().collect::<Vec<_>>());
+            }
+            StepResult::IO => stmt.run_once()?,
+            StepResult::Done | StepResult::Interrupt => break,
+            StepResult::Busy => panic!("database busy"),
+        }
+    }
+    assert_eq!(
+        rows,
+        vec![[1, 2, 3, 4, 5, 6, 1].map(Value::Integer).to_vec()]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_bind_parameters_rebind_and_execute_again() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name TEXT);",
+        false,
+    );
+    let conn = tmp_db.connect_limbo();
+    for id in 1..=5 {
+        conn.execute(format!(
+            "insert into test (id, name) values ({id}, 'test{id}');"
+        ))?;
+    }
+
+    let mut sel = conn
+        .prepare("select count(*) from test where id in (select id from test where id > :min);")?;
+    let min = sel.parameter_index(":min").unwrap();
+    for (min_id, expected) in [(1, 4), (3, 2), (5, 0)] {
+        sel.reset();
+        sel.bind_at(min, Value::Integer(min_id));
+        let mut count = None;
+        loop {
+            match sel.step()? {
+                StepResult::Row => {
+                    count = Some(sel.row().unwrap().get::<&Value>(0).unwrap().clone());
+                }
+                StepResult::IO => sel.run_once()?,
+                StepResult::Done | StepResult::Interrupt => break,
+                StepResult::Busy => panic!("database busy"),
+            }
+        }
+        assert_eq!(count, Some(Value::Integer(expected)));
+    }
+    Ok(())
+}
+
+#[test]
+fn test_statement_step_after_done_and_reset() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table test (i integer);", false);
+    let conn = tmp_db.connect_limbo();
+
+    let mut stmt = conn.prepare("select ?")?;
+    stmt.bind_at(1.try_into()?, Value::Integer(7));
+    let step = |stmt: &mut turso_core::Statement| -> anyhow::Result<Option<Value>> {
+        loop {
+            match stmt.step()? {
+                StepResult::Row => return Ok(Some(stmt.row().unwrap().get::<&Value>(0)?.clone())),
+                StepResult::IO => stmt.run_once()?,
+                StepResult::Done => return Ok(None),
+                StepResult::Interrupt | StepResult::Busy => panic!("unexpected step result"),
+            }
+        }
+    };
+
+    assert!(!stmt.is_busy());
+    assert_eq!(step(&mut stmt)?, Some(Value::Integer(7)));
+    assert!(stmt.is_busy());
+    assert_eq!(step(&mut stmt)?, None);
+    assert!(!stmt.is_busy());
+    // A finished statement stays finished until it is reset.
+    assert_eq!(step(&mut stmt)?, None);
+
+    // Resetting keeps the bindings.
+    stmt.reset();
+    assert_eq!(step(&mut stmt)?, Some(Value::Integer(7)));
+
+    stmt.reset();
+    stmt.clear_bindings();
+    assert_eq!(step(&mut stmt)?, Some(Value::Null));
+    Ok(())
+}
+
+#[test]
+fn test_statement_step_after_error_is_misuse() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "CREATE TABLE test (id INTEGER PRIMARY KEY, name TEXT);",
+        false,
+    );
+    let conn = tmp_db.connect_limbo();
+
+    let mut ins = conn.prepare("insert into test (id) values (?);")?;
+    let run = |stmt: &mut turso_core::Statement| -> turso_core::Result<()> {
+        loop {
+            match stmt.step()? {
+                StepResult::IO => stmt.run_once()?,
+                StepResult::Done => return Ok(()),
+                _ => {}
+            }
+        }
+    };
+
+    ins.bind_at(1.try_into()?, Value::Integer(1));
+    run(&mut ins)?;
+    ins.reset();
+    assert!(matches!(
+        run(&mut ins),
+        Err(turso_core::LimboError::Constraint(_))
+    ));
+    assert!(matches!(ins.step(), Err(turso_core::LimboError::Misuse(_))));
+
+    ins.reset();
+    ins.bind_at(1.try_into()?, Value::Integer(2));
+    run(&mut ins)?;
+
+    let mut sel = conn.prepare("select count(*) from test;")?;
+    loop {
+        match sel.step()? {
+            StepResult::Row => {
+                assert_eq!(sel.row().unwrap().get::<&Value>(0)?, &Value::Integer(2));
+            }
+            StepResult::IO => sel.run_once()?,
+            StepResult::Done | StepResult::Interrupt => break,
+            StepResult::Busy => panic!("database busy"),
+        }
+    }
+    Ok(())
+}
+
+#[test]
+fn test_statement_column_metadata() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "create table test (a int, b, c text, id integer primary key);",
+        false,
+    );
+    let conn = tmp_db.connect_limbo();
+
+    let stmt = conn.prepare("select a, b, c as x, id, a + 1 from test;")?;
+    assert_eq!(stmt.num_columns(), 5);
+    let names = (0..4).map(|i| stmt.get_column_name(i)).collect::<Vec<_>>();
+    assert_eq!(names, ["a", "b", "x", "id"]);
+    let decltypes = (0..6)
+        .map(|i| stmt.get_column_decltype(i))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        decltypes,
+        [Some("int"), None, Some("text"), Some("integer"), None, None]
+    );
+    let tables = (0..6)
+        .map(|i| stmt.get_column_table_name(i))
+        .collect::<Vec<_>>();
+    assert_eq!(
+        tables,
+        [
+            Some("test"),
+            Some("test"),
+            Some("test"),
+            Some("test"),
+            None,
+            None
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_explain_query_plan() -> anyhow::Result<()> {
+    let tmp_db =
+        TempDatabase::new_with_rusqlite("CREATE TABLE t (id INTEGER PRIMARY KEY, x);", false);
+    let conn = tmp_db.connect_limbo();
+
+    let mut stmt = conn.prepare("EXPLAIN QUERY PLAN SELECT x FROM t WHERE id = 1 ORDER BY x;")?;
+    let columns: Vec<_> = (0..stmt.num_columns())
+        .map(|i| stmt.get_column_name(i).to_string())
+        .collect();
+    assert_eq!(columns, ["id", "parent", "notused", "detail"]);
+    let mut rows = Vec::new();
+    loop {
+        match stmt.step()? {
+            StepResult::Row => {
+                let row = stmt.row().unwrap();
+                rows.push((row.get::<i64>(1)?, row.get::<&str>(3)?.to_string()));
+            }
+            StepResult::IO => stmt.run_once()?,
+            StepResult::Done | StepResult::Interrupt => break,
+            StepResult::Busy => panic!("database busy"),
+        }
+    }
+    assert_eq!(
+        rows,
+        [
+            (
+                0,
+                "SEARCH t USING INTEGER PRIMARY KEY (rowid=?)".to_string()
+            ),
+            (0, "USE TEMP B-TREE FOR ORDER BY".to_string()),
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_index_list_in_creation_order_after_reopen() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("CREATE TABLE t (a PRIMARY KEY, b UNIQUE, c)")?;
+    conn.execute("CREATE INDEX t_c ON t (c)")?;
+    conn.execute("CREATE INDEX t_bc ON t (b, c)")?;
+    conn.close()?;
+
+    let tmp_db = TempDatabase::new_with_existent(&tmp_db.path, true);
+    let conn = tmp_db.connect_limbo();
+    let rows = limbo_exec_rows(
+        &tmp_db,
+        &conn,
+        "SELECT seq, name FROM pragma_index_list('t')",
+    );
+    assert_eq!(
+        rows,
+        [
+            (0, "sqlite_autoindex_t_1"),
+            (1, "sqlite_autoindex_t_2"),
+            (2, "t_c"),
+            (3, "t_bc"),
+        ]
+        .map(|(seq, name)| vec![
+            rusqlite::types::Value::Integer(seq),
+            rusqlite::types::Value::Text(name.to_string())
+        ])
+    );
+    Ok(())
+}
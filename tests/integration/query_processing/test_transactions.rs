@@ -1,6 +1,10 @@
+use std::cell::Cell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+
 use turso_core::{LimboError, Result, StepResult, Value};
 
-use crate::common::TempDatabase;
+use crate::common::{limbo_exec_rows, TempDatabase};
 
 #[test]
 fn test_txn_error_doesnt_rollback_txn() -> Result<()> {
@@ -24,3 +28,276 @@ fn test_txn_error_doesnt_rollback_txn() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_savepoint_rollback_persists_after_commit() -> Result<()> {
+    let tmp_db =
+        TempDatabase::new_with_rusqlite("create table t(x integer primary key, y);", false);
+    let conn = tmp_db.connect_limbo();
+
+    conn.execute("begin")?;
+    conn.execute("insert into t select value, randomblob(500) from generate_series(1, 100)")?;
+    conn.execute("savepoint s")?;
+    conn.execute("insert into t select value, randomblob(500) from generate_series(101, 1000)")?;
+    conn.execute("delete from t where x % 2 = 0")?;
+    conn.execute("rollback to s")?;
+    conn.execute("insert into t values (1001, 'z')")?;
+    conn.execute("release s")?;
+    conn.execute("commit")?;
+    drop(conn);
+
+    let tmp_db = TempDatabase::new_with_existent(&tmp_db.path, false);
+    let conn = tmp_db.connect_limbo();
+    let rows = limbo_exec_rows(&tmp_db, &conn, "select count(*), sum(x) from t");
+    assert_eq!(
+        rows,
+        vec![vec![
+            rusqlite::types::Value::Integer(101),
+            rusqlite::types::Value::Integer(5050 + 1001),
+        ]]
+    );
+    let rows = limbo_exec_rows(&tmp_db, &conn, "pragma integrity_check");
+    assert_eq!(
+        rows,
+        vec![vec![rusqlite::types::Value::Text("ok".to_string())]]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_failed_statement_doesnt_rollback_txn() -> Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "create table t(x integer primary key, y unique, z);",
+        true,
+    );
+    let conn = tmp_db.connect_limbo();
+
+    conn.execute("begin")?;
+    conn.execute(
+        "insert into t select value, value, randomblob(500) from generate_series(1, 100)",
+    )?;
+    // Fails after inserting enough rows to split pages.
+    assert!(conn
+        .execute("insert into t select value, value % 500, randomblob(500) from generate_series(101, 1000)")
+        .is_err());
+    // Fails halfway through updating the rows.
+    assert!(conn
+        .execute("update t set y = case when x > 60 then 1 else -y end, z = randomblob(600)")
+        .is_err());
+    conn.execute("insert into t values (101, 101, 'z')")?;
+    conn.execute("commit")?;
+    drop(conn);
+
+    let tmp_db = TempDatabase::new_with_existent(&tmp_db.path, true);
+    let conn = tmp_db.connect_limbo();
+    let rows = limbo_exec_rows(&tmp_db, &conn, "select count(*), sum(x), sum(y) from t");
+    assert_eq!(
+        rows,
+        vec![vec![
+            rusqlite::types::Value::Integer(101),
+            rusqlite::types::Value::Integer(5151),
+            rusqlite::types::Value::Integer(5151),
+        ]]
+    );
+    let rows = limbo_exec_rows(&tmp_db, &conn, "pragma integrity_check");
+    assert_eq!(
+        rows,
+        vec![vec![rusqlite::types::Value::Text("ok".to_string())]]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_begin_immediate_busy() -> Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t(x);", false);
+    let conn1 = tmp_db.connect_limbo();
+    let conn2 = tmp_db.connect_limbo();
+
+    conn1.execute("begin immediate")?;
+    assert!(matches!(
+        conn2.execute("begin immediate"),
+        Err(LimboError::Busy)
+    ));
+    assert!(matches!(
+        conn2.execute("insert into t values (1)"),
+        Err(LimboError::Busy)
+    ));
+    // Readers aren't blocked by the writer.
+    let rows = limbo_exec_rows(&tmp_db, &conn2, "select count(*) from t");
+    assert_eq!(rows, vec![vec![rusqlite::types::Value::Integer(0)]]);
+    conn1.execute("insert into t values (1)")?;
+    conn1.execute("commit")?;
+
+    conn2.execute("begin exclusive")?;
+    conn2.execute("insert into t values (2)")?;
+    conn2.execute("commit")?;
+    let rows = limbo_exec_rows(&tmp_db, &conn1, "select sum(x) from t");
+    assert_eq!(rows, vec![vec![rusqlite::types::Value::Integer(3)]]);
+
+    Ok(())
+}
+
+#[test]
+fn test_begin_deferred_takes_locks_lazily() -> Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t(x);", false);
+    let conn1 = tmp_db.connect_limbo();
+    let conn2 = tmp_db.connect_limbo();
+
+    conn1.execute("begin")?;
+    conn2.execute("begin immediate")?;
+    conn2.execute("commit")?;
+
+    // Reading starts the snapshot of the transaction, so it can't write after another
+    // connection has committed.
+    let rows = limbo_exec_rows(&tmp_db, &conn1, "select count(*) from t");
+    assert_eq!(rows, vec![vec![rusqlite::types::Value::Integer(0)]]);
+    conn2.execute("insert into t values (1)")?;
+    assert!(matches!(
+        conn1.execute("insert into t values (2)"),
+        Err(LimboError::Busy)
+    ));
+    conn1.execute("rollback")?;
+    conn1.execute("insert into t values (2)")?;
+    let rows = limbo_exec_rows(&tmp_db, &conn2, "select sum(x) from t");
+    assert_eq!(rows, vec![vec![rusqlite::types::Value::Integer(3)]]);
+
+    Ok(())
+}
+
+#[test]
+fn test_begin_concurrent() -> Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t(x);", false);
+    let conn1 = tmp_db.connect_limbo();
+    let conn2 = tmp_db.connect_limbo();
+    conn1.execute("create table u(x)")?;
+    conn1.execute("insert into t values (1)")?;
+    conn1.execute("insert into u values (1)")?;
+
+    // Transactions writing different pages both commit.
+    conn1.execute("begin concurrent")?;
+    conn2.execute("begin concurrent")?;
+    conn1.execute("insert into t values (2)")?;
+    conn2.execute("insert into u values (2)")?;
+    conn1.execute("commit")?;
+    conn2.execute("commit")?;
+    let rows = limbo_exec_rows(&tmp_db, &conn1, "select sum(x) from t");
+    assert_eq!(rows, vec![vec![rusqlite::types::Value::Integer(3)]]);
+    let rows = limbo_exec_rows(&tmp_db, &conn2, "select sum(x) from u");
+    assert_eq!(rows, vec![vec![rusqlite::types::Value::Integer(3)]]);
+
+    // The second transaction writing the same page is rolled back when committing.
+    conn1.execute("begin concurrent")?;
+    conn2.execute("begin concurrent")?;
+    conn1.execute("insert into t values (3)")?;
+    conn2.execute("insert into t values (4)")?;
+    conn1.execute("commit")?;
+    assert!(matches!(
+        conn2.execute("commit"),
+        Err(LimboError::WriteWriteConflict)
+    ));
+    assert!(matches!(
+        conn2.execute("commit"),
+        Err(LimboError::TxError(_))
+    ));
+    let rows = limbo_exec_rows(&tmp_db, &conn2, "select sum(x) from t");
+    assert_eq!(rows, vec![vec![rusqlite::types::Value::Integer(6)]]);
+
+    // The write lock is only needed to commit.
+    conn1.execute("begin immediate")?;
+    conn2.execute("begin concurrent")?;
+    conn2.execute("insert into u values (3)")?;
+    assert!(matches!(conn2.execute("commit"), Err(LimboError::Busy)));
+    conn1.execute("commit")?;
+    conn2.execute("commit")?;
+    let rows = limbo_exec_rows(&tmp_db, &conn1, "select sum(x) from u");
+    assert_eq!(rows, vec![vec![rusqlite::types::Value::Integer(6)]]);
+    let rows = limbo_exec_rows(&tmp_db, &conn1, "pragma integrity_check");
+    assert_eq!(
+        rows,
+        vec![vec![rusqlite::types::Value::Text("ok".to_string())]]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_failed_statement_releases_write_lock() -> Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t(x integer primary key);", false);
+    let conn1 = tmp_db.connect_limbo();
+    let conn2 = tmp_db.connect_limbo();
+
+    conn1.execute("insert into t values (1)")?;
+    assert!(conn1.execute("insert into t values (1)").is_err());
+    conn2.execute("begin immediate")?;
+    conn2.execute("insert into t values (2)")?;
+    conn2.execute("commit")?;
+    let rows = limbo_exec_rows(&tmp_db, &conn1, "select count(*) from t");
+    assert_eq!(rows, vec![vec![rusqlite::types::Value::Integer(2)]]);
+
+    Ok(())
+}
+
+#[test]
+fn test_busy_timeout() -> Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t(x);", false);
+    let conn1 = tmp_db.connect_limbo();
+    let conn2 = tmp_db.connect_limbo();
+
+    conn1.execute("begin immediate")?;
+    conn2.execute("pragma busy_timeout = 100")?;
+    assert_eq!(conn2.get_busy_timeout(), Duration::from_millis(100));
+    let start = Instant::now();
+    assert!(matches!(
+        conn2.execute("insert into t values (1)"),
+        Err(LimboError::Busy)
+    ));
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    conn1.execute("commit")?;
+    conn2.execute("insert into t values (1)")?;
+
+    Ok(())
+}
+
+#[test]
+fn test_busy_handler_retries() -> Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table t(x);", false);
+    let conn1 = tmp_db.connect_limbo();
+    let conn2 = tmp_db.connect_limbo();
+
+    conn1.execute("begin immediate")?;
+    conn1.execute("insert into t values (1)")?;
+    let calls = Rc::new(Cell::new(0));
+    conn2.set_busy_handler(Some(Box::new({
+        let calls = calls.clone();
+        move |count| {
+            assert_eq!(count, calls.get());
+            calls.set(count + 1);
+            // The writer finishes while the statement is waiting.
+            if count == 2 {
+                conn1.execute("commit").unwrap();
+            }
+            true
+        }
+    })));
+    conn2.execute("insert into t values (2)")?;
+    assert_eq!(calls.get(), 3);
+    let rows = limbo_exec_rows(&tmp_db, &conn2, "select sum(x) from t");
+    assert_eq!(rows, vec![vec![rusqlite::types::Value::Integer(3)]]);
+
+    // A transaction that already read isn't retried, since it could never get the lock.
+    conn2.execute("begin")?;
+    limbo_exec_rows(&tmp_db, &conn2, "select count(*) from t");
+    let conn3 = tmp_db.connect_limbo();
+    conn3.execute("insert into t values (3)")?;
+    calls.set(0);
+    assert!(matches!(
+        conn2.execute("insert into t values (4)"),
+        Err(LimboError::Busy)
+    ));
+    assert_eq!(calls.get(), 0);
+    conn2.execute("rollback")?;
+
+    Ok(())
+}
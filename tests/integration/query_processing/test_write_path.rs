@@ -1,9 +1,17 @@
 use crate::common::{self, maybe_setup_tracing};
-use crate::common::{compare_string, do_flush, TempDatabase};
+use crate::common::{
+    compare_string, do_flush, limbo_exec_rows, limbo_exec_rows_error, sqlite_exec_rows,
+    TempDatabase,
+};
 use log::debug;
+use std::cell::{Cell, RefCell};
 use std::io::{Read, Seek, Write};
+use std::rc::Rc;
 use std::sync::Arc;
-use turso_core::{Connection, Database, Row, Statement, StepResult, Value};
+use turso_core::{
+    AggregateState, AuthAction, Authorization, Backup, CheckpointMode, Connection, Database,
+    FunctionFlags, Limit, PreUpdate, Row, Statement, StepResult, Value,
+};
 
 const WAL_HEADER_SIZE: usize = 32;
 const WAL_FRAME_HEADER_SIZE: usize = 24;
@@ -226,6 +234,738 @@ fn test_regression_multi_row_insert() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_execute_batch() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_empty(false);
+    let conn = tmp_db.connect_limbo();
+
+    let mut rows = vec![];
+    conn.execute_batch(
+        "create table test (i integer, t text);
+         insert into test values (1, 'a'), (2, 'b');
+         select i, t from test order by i;
+         -- a comment between statements
+         update test set t = 'c' where i = 2;
+         select t as name from test where i = 2",
+        Some(&mut |stmt: &Statement| -> turso_core::Result<()> {
+            let row = stmt.row().unwrap();
+            let values = (0..stmt.num_columns())
+                .map(|i| {
+                    Ok(format!(
+                        "{}={}",
+                        stmt.get_column_name(i),
+                        row.get::<&Value>(i)?
+                    ))
+                })
+                .collect::<turso_core::Result<Vec<_>>>()?;
+            rows.push(values.join(","));
+            Ok(())
+        }),
+    )?;
+    assert_eq!(rows, ["i=1,t=a", "i=2,t=b", "name=c"]);
+
+    // The statements before the failing one have run, the ones after it haven't.
+    let result = conn.execute_batch(
+        "insert into test values (3, 'd'); insert into missing values (1); insert into test values (4, 'e');",
+        None,
+    );
+    assert!(result.is_err());
+    let rows = limbo_exec_rows(&tmp_db, &conn, "select i from test order by i");
+    assert_eq!(
+        rows,
+        [1, 2, 3].map(|i| vec![rusqlite::types::Value::Integer(i)])
+    );
+
+    // An error from the callback stops the batch.
+    let mut calls = 0;
+    let result = conn.execute_batch(
+        "select i from test; delete from test;",
+        Some(&mut |_: &Statement| -> turso_core::Result<()> {
+            calls += 1;
+            Err(turso_core::LimboError::InternalError("stop".to_string()))
+        }),
+    );
+    assert!(result.is_err());
+    assert_eq!(calls, 1);
+    let rows = limbo_exec_rows(&tmp_db, &conn, "select count(*) from test");
+    assert_eq!(rows, [vec![rusqlite::types::Value::Integer(3)]]);
+    Ok(())
+}
+
+#[test]
+fn test_returning() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table test (x integer, y text);", false);
+    let conn = tmp_db.connect_limbo();
+
+    let mut rows = vec![];
+    conn.execute_batch(
+        "insert into test values (1, 'a'), (2, 'b') returning *;
+         update test set y = 'c' where x = 2 returning x as xx, y;
+         delete from test returning x + 1, y;",
+        Some(&mut |stmt: &Statement| -> turso_core::Result<()> {
+            let row = stmt.row().unwrap();
+            let values = (0..stmt.num_columns())
+                .map(|i| {
+                    Ok(format!(
+                        "{}={}",
+                        stmt.get_column_name(i),
+                        row.get::<&Value>(i)?
+                    ))
+                })
+                .collect::<turso_core::Result<Vec<_>>>()?;
+            rows.push(values.join(","));
+            Ok(())
+        }),
+    )?;
+    assert_eq!(
+        rows,
+        [
+            "x=1,y=a",
+            "x=2,y=b",
+            "xx=2,y=c",
+            "x + 1=2,y=a",
+            "x + 1=3,y=c"
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_changes() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "create table test (x integer primary key, y text);",
+        false,
+    );
+    let conn = tmp_db.connect_limbo();
+
+    conn.execute("create table log (x)")?;
+    conn.execute(
+        "create trigger test_log after update on test begin insert into log values (new.x); end",
+    )?;
+    conn.execute("insert into test values (1, 'a'), (2, 'b'), (3, 'c')")?;
+    assert_eq!((conn.changes(), conn.total_changes()), (3, 3));
+    assert_eq!(conn.last_insert_rowid(), 3);
+
+    conn.execute("update test set y = 'z' where x < 3")?;
+    assert_eq!((conn.changes(), conn.total_changes()), (2, 7));
+    assert_eq!(conn.last_insert_rowid(), 3);
+
+    conn.execute("delete from test where x > 1")?;
+    assert_eq!((conn.changes(), conn.total_changes()), (2, 9));
+    Ok(())
+}
+
+#[test]
+fn test_update_hook() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "create table test (x integer primary key, y text);",
+        false,
+    );
+    let conn = tmp_db.connect_limbo();
+    conn.execute("create table log (x)")?;
+    conn.execute(
+        "create trigger test_log after delete on test begin insert into log values (old.x); end",
+    )?;
+
+    let events = Rc::new(RefCell::new(vec![]));
+    let hook_events = events.clone();
+    conn.set_update_hook(Some(Box::new(
+        move |kind, database: &str, table: &str, rowid| {
+            hook_events
+                .borrow_mut()
+                .push(format!("{kind:?} {database}.{table} {rowid}"));
+        },
+    )));
+    conn.execute("insert into test values (1, 'a'), (2, 'b')")?;
+    conn.execute("update test set y = 'c' where x = 2")?;
+    conn.execute("update test set x = 5 where x = 2")?;
+    conn.execute("delete from test where x = 1")?;
+    conn.set_update_hook(None);
+    conn.execute("insert into test values (6, 'd')")?;
+
+    assert_eq!(
+        *events.borrow(),
+        [
+            "Insert main.test 1",
+            "Insert main.test 2",
+            "Update main.test 2",
+            "Update main.test 5",
+            "Delete main.test 1",
+            "Insert main.log 1",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_commit_and_rollback_hooks() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table test (x integer);", false);
+    let conn = tmp_db.connect_limbo();
+
+    let veto = Rc::new(Cell::new(false));
+    let commits = Rc::new(Cell::new(0));
+    let rollbacks = Rc::new(Cell::new(0));
+    let (hook_veto, hook_commits, hook_rollbacks) =
+        (veto.clone(), commits.clone(), rollbacks.clone());
+    conn.set_commit_hook(Some(Box::new(move || {
+        hook_commits.set(hook_commits.get() + 1);
+        hook_veto.get()
+    })));
+    conn.set_rollback_hook(Some(Box::new(move || {
+        hook_rollbacks.set(hook_rollbacks.get() + 1)
+    })));
+
+    conn.execute("insert into test values (1)")?;
+    conn.execute("select * from test")?;
+    assert_eq!((commits.get(), rollbacks.get()), (1, 0));
+
+    conn.execute("begin")?;
+    conn.execute("insert into test values (2)")?;
+    conn.execute("rollback")?;
+    assert_eq!((commits.get(), rollbacks.get()), (1, 1));
+
+    veto.set(true);
+    assert!(conn.execute("insert into test values (3)").is_err());
+    conn.execute("begin")?;
+    conn.execute("insert into test values (4)")?;
+    assert!(conn.execute("commit").is_err());
+    assert_eq!((commits.get(), rollbacks.get()), (3, 3));
+
+    let rows = limbo_exec_rows(&tmp_db, &conn, "select * from test");
+    assert_eq!(rows, [vec![rusqlite::types::Value::Integer(1)]]);
+    Ok(())
+}
+
+#[test]
+fn test_preupdate_hook() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "create table test (x integer primary key, y text, z integer);",
+        false,
+    );
+    let conn = tmp_db.connect_limbo();
+
+    fn values(row: &PreUpdate, old: bool) -> String {
+        (0..row.count())
+            .map(|i| {
+                if old {
+                    row.old_value(i)
+                } else {
+                    row.new_value(i)
+                }
+            })
+            .map(|value| value.map(|value| value.to_string()))
+            .collect::<turso_core::Result<Vec<_>>>()
+            .map_or_else(|_| "-".to_string(), |values| values.join(","))
+    }
+    let events = Rc::new(RefCell::new(vec![]));
+    let hook_events = events.clone();
+    conn.set_preupdate_hook(Some(Box::new(move |row: &PreUpdate| {
+        hook_events.borrow_mut().push(format!(
+            "{:?} {}.{} {}->{} old=({}) new=({})",
+            row.kind(),
+            row.database(),
+            row.table_name(),
+            row.old_rowid(),
+            row.new_rowid(),
+            values(row, true),
+            values(row, false),
+        ));
+    })));
+    conn.execute("insert into test values (1, 'a', 10)")?;
+    conn.execute("update test set z = 20 where x = 1")?;
+    conn.execute("update test set x = 5 where x = 1")?;
+    conn.execute("alter table test add column w default 7")?;
+    conn.execute("delete from test")?;
+
+    assert_eq!(
+        *events.borrow(),
+        [
+            "Insert main.test 1->1 old=(-) new=(1,a,10)",
+            "Update main.test 1->1 old=(1,a,10) new=(1,a,20)",
+            "Update main.test 1->5 old=(1,a,20) new=(5,a,20)",
+            "Delete main.test 5->5 old=(5,a,20,7) new=(-)",
+        ]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_authorizer() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "create table test (x integer primary key, y text, secret text);",
+        false,
+    );
+    let conn = tmp_db.connect_limbo();
+    conn.execute("insert into test values (1, 'a', 's')")?;
+
+    let actions = Rc::new(RefCell::new(vec![]));
+    let hook_actions = actions.clone();
+    conn.set_authorizer(Some(Box::new(
+        move |action: &AuthAction, database: Option<&str>, _trigger: Option<&str>| {
+            hook_actions
+                .borrow_mut()
+                .push(format!("{action:?} {database:?}"));
+            match action {
+                AuthAction::Read {
+                    column: "secret", ..
+                } => Authorization::Ignore,
+                AuthAction::Update { column: "y", .. } => Authorization::Ignore,
+                AuthAction::DropTable { .. } => Authorization::Deny,
+                _ => Authorization::Allow,
+            }
+        },
+    )));
+
+    let rows = limbo_exec_rows(&tmp_db, &conn, "select y, secret from test");
+    assert_eq!(
+        rows,
+        vec![vec![
+            rusqlite::types::Value::Text("a".to_string()),
+            rusqlite::types::Value::Null,
+        ]]
+    );
+    assert_eq!(
+        *actions.borrow(),
+        [
+            "Select None",
+            "Read { table: \"test\", column: \"y\" } Some(\"main\")",
+            "Read { table: \"test\", column: \"secret\" } Some(\"main\")",
+        ]
+    );
+
+    conn.execute("update test set y = 'b', secret = 't'")?;
+    assert!(matches!(
+        conn.execute("drop table test"),
+        Err(turso_core::LimboError::AuthorizationDenied(_))
+    ));
+
+    conn.set_authorizer(None);
+    let rows = limbo_exec_rows(&tmp_db, &conn, "select y, secret from test");
+    assert_eq!(
+        rows,
+        vec![vec![
+            rusqlite::types::Value::Text("a".to_string()),
+            rusqlite::types::Value::Text("t".to_string()),
+        ]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_progress_handler_and_interrupt() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table test (x integer);", false);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("insert into test values (1), (2), (3)")?;
+
+    let calls = Rc::new(Cell::new(0));
+    let interrupt = Rc::new(Cell::new(false));
+    let (handler_calls, handler_interrupt) = (calls.clone(), interrupt.clone());
+    conn.set_progress_handler(
+        1,
+        Some(Box::new(move || {
+            handler_calls.set(handler_calls.get() + 1);
+            handler_interrupt.get()
+        })),
+    );
+    conn.execute("select * from test")?;
+    assert!(calls.get() > 0);
+
+    interrupt.set(true);
+    assert!(matches!(
+        conn.execute("insert into test values (4)"),
+        Err(turso_core::LimboError::Interrupt)
+    ));
+    conn.set_progress_handler(0, None);
+    let rows = limbo_exec_rows(&tmp_db, &conn, "select count(*) from test");
+    assert_eq!(rows, vec![vec![rusqlite::types::Value::Integer(3)]]);
+
+    // Only the statements running when the connection is interrupted are interrupted.
+    let mut stmt = conn.prepare("select * from test")?;
+    loop {
+        match stmt.step()? {
+            StepResult::IO => stmt.run_once()?,
+            StepResult::Row => break,
+            result => panic!("unexpected step result {result:?}"),
+        }
+    }
+    let handle = conn.interrupt_handle();
+    std::thread::spawn(move || handle.interrupt())
+        .join()
+        .unwrap();
+    assert!(matches!(stmt.step()?, StepResult::Interrupt));
+    let rows = limbo_exec_rows(&tmp_db, &conn, "select count(*) from test");
+    assert_eq!(rows, vec![vec![rusqlite::types::Value::Integer(3)]]);
+    Ok(())
+}
+
+#[test]
+fn test_limits() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table test (x integer);", false);
+    let conn = tmp_db.connect_limbo();
+
+    // Limits can be lowered, but not raised above their defaults.
+    assert_eq!(conn.limit(Limit::ExprDepth, None), 1000);
+    assert_eq!(conn.limit(Limit::ExprDepth, Some(4)), 1000);
+    assert_eq!(conn.limit(Limit::ExprDepth, None), 4);
+    conn.execute("select 1 + 2 + 3 + 4")?;
+    assert!(conn.execute("select 1 + 2 + 3 + 4 + 5").is_err());
+    conn.limit(Limit::ExprDepth, Some(usize::MAX));
+    assert_eq!(conn.limit(Limit::ExprDepth, None), 1000);
+    conn.execute("select 1 + 2 + 3 + 4 + 5")?;
+
+    conn.limit(Limit::CompoundSelect, Some(2));
+    conn.execute("select 1 union all select 2")?;
+    assert!(conn
+        .execute("select 1 union all select 2 union all select 3")
+        .is_err());
+
+    conn.limit(Limit::SqlLength, Some(16));
+    conn.execute("select 1")?;
+    assert!(matches!(
+        conn.execute("select 1, 2, 3, 4, 5"),
+        Err(turso_core::LimboError::TooBig(_))
+    ));
+    conn.limit(Limit::SqlLength, Some(usize::MAX));
+
+    conn.limit(Limit::VdbeOp, Some(10));
+    assert!(matches!(
+        conn.prepare("select 1, 2, 3, 4, 5, 6, 7, 8, 9, 10"),
+        Err(turso_core::LimboError::TooBig(_))
+    ));
+    conn.limit(Limit::VdbeOp, Some(usize::MAX));
+
+    conn.limit(Limit::Attached, Some(0));
+    assert!(conn.execute("attach ':memory:' as aux").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_create_scalar_function() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table test (x integer);", false);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("insert into test values (1), (2), (3)")?;
+
+    let calls = Rc::new(Cell::new(0));
+    for (name, flags) in [
+        ("add_one", FunctionFlags::empty()),
+        ("add_one_deterministic", FunctionFlags::DETERMINISTIC),
+    ] {
+        let calls = calls.clone();
+        conn.create_scalar_function(
+            name,
+            Some(1),
+            flags,
+            Box::new(move |args| {
+                calls.set(calls.get() + 1);
+                match &args[0] {
+                    Value::Integer(i) => Ok(Value::Integer(i + 1)),
+                    _ => Err(turso_core::LimboError::InvalidArgument(
+                        "expected an integer".to_string(),
+                    )),
+                }
+            }),
+        );
+    }
+    conn.create_scalar_function(
+        "arg_count",
+        None,
+        FunctionFlags::DETERMINISTIC,
+        Box::new(|args| Ok(Value::Integer(args.len() as i64))),
+    );
+
+    let rows = limbo_exec_rows(
+        &tmp_db,
+        &conn,
+        "select ADD_ONE(x) from test where add_one(x) > 2",
+    );
+    assert_eq!(
+        rows,
+        vec![
+            vec![rusqlite::types::Value::Integer(3)],
+            vec![rusqlite::types::Value::Integer(4)],
+        ]
+    );
+
+    // Calls of deterministic functions on constants are evaluated once.
+    calls.set(0);
+    limbo_exec_rows(&tmp_db, &conn, "select add_one(1) from test");
+    assert_eq!(calls.get(), 3);
+    calls.set(0);
+    limbo_exec_rows(&tmp_db, &conn, "select add_one_deterministic(1) from test");
+    assert_eq!(calls.get(), 1);
+
+    let rows = limbo_exec_rows(&tmp_db, &conn, "select arg_count(), arg_count(1, 2, 3)");
+    assert_eq!(
+        rows,
+        vec![vec![
+            rusqlite::types::Value::Integer(0),
+            rusqlite::types::Value::Integer(3),
+        ]]
+    );
+    assert!(conn.execute("select add_one(1, 2)").is_err());
+    assert!(conn.execute("select add_one('a')").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_override_like_and_regexp() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table test (x text);", true);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("create index test_x on test(x)")?;
+    conn.execute("insert into test values ('abc'), ('xabc'), ('abd')")?;
+    assert!(conn.execute("select 'abc' regexp 'b'").is_err());
+
+    // The operators pass the pattern first.
+    for name in ["like", "regexp"] {
+        conn.create_scalar_function(
+            name,
+            Some(2),
+            FunctionFlags::DETERMINISTIC,
+            Box::new(|args| match (&args[0], &args[1]) {
+                (Value::Text(pattern), Value::Text(text)) => Ok(Value::Integer(
+                    text.as_str().contains(pattern.as_str()) as i64,
+                )),
+                _ => Ok(Value::Null),
+            }),
+        );
+    }
+
+    let rows = limbo_exec_rows(
+        &tmp_db,
+        &conn,
+        "select x from test where x regexp 'bc' order by x",
+    );
+    assert_eq!(
+        rows,
+        vec![
+            vec![rusqlite::types::Value::Text("abc".to_string())],
+            vec![rusqlite::types::Value::Text("xabc".to_string())],
+        ]
+    );
+    // The index can't be searched for the rows an overridden LIKE matches.
+    let rows = limbo_exec_rows(
+        &tmp_db,
+        &conn,
+        "select x from test where x like 'abc' order by x",
+    );
+    assert_eq!(
+        rows,
+        vec![
+            vec![rusqlite::types::Value::Text("abc".to_string())],
+            vec![rusqlite::types::Value::Text("xabc".to_string())],
+        ]
+    );
+    // The builtin like() is still used with an ESCAPE, which takes a third argument.
+    let rows = limbo_exec_rows(
+        &tmp_db,
+        &conn,
+        "select 'ABC' like 'abc', 'ABC' like 'abc' escape '\\'",
+    );
+    assert_eq!(
+        rows,
+        vec![vec![
+            rusqlite::types::Value::Integer(0),
+            rusqlite::types::Value::Integer(1),
+        ]]
+    );
+
+    // upper() and lower() can be overridden too, e.g. to map the case of ASCII letters only.
+    conn.create_scalar_function(
+        "upper",
+        Some(1),
+        FunctionFlags::DETERMINISTIC,
+        Box::new(|args| match &args[0] {
+            Value::Text(text) => Ok(Value::build_text(text.as_str().to_ascii_uppercase())),
+            value => Ok(value.clone()),
+        }),
+    );
+    let rows = limbo_exec_rows(&tmp_db, &conn, "select upper('straße')");
+    assert_eq!(
+        rows,
+        vec![vec![rusqlite::types::Value::Text("STRAßE".to_string())]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_create_aggregate_and_window_functions() -> anyhow::Result<()> {
+    struct Sum(i64);
+
+    impl AggregateState for Sum {
+        fn step(&mut self, args: &[Value]) -> turso_core::Result<()> {
+            if let Value::Integer(i) = args[0] {
+                self.0 += i;
+            }
+            Ok(())
+        }
+
+        fn finalize(&mut self) -> turso_core::Result<Value> {
+            Ok(Value::Integer(self.0))
+        }
+
+        fn value(&self) -> turso_core::Result<Value> {
+            Ok(Value::Integer(self.0))
+        }
+
+        fn inverse(&mut self, args: &[Value]) -> turso_core::Result<()> {
+            if let Value::Integer(i) = args[0] {
+                self.0 -= i;
+            }
+            Ok(())
+        }
+    }
+
+    let tmp_db = TempDatabase::new_with_rusqlite("create table test (x integer);", false);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("insert into test values (1), (2), (3), (4)")?;
+    conn.create_aggregate_function(
+        "my_sum",
+        Some(1),
+        FunctionFlags::empty(),
+        Box::new(|| Box::new(Sum(0))),
+    );
+    conn.create_window_function(
+        "my_window_sum",
+        Some(1),
+        FunctionFlags::empty(),
+        Box::new(|| Box::new(Sum(0))),
+    );
+
+    let rows = limbo_exec_rows(&tmp_db, &conn, "select my_sum(x) from test");
+    assert_eq!(rows, vec![vec![rusqlite::types::Value::Integer(10)]]);
+    // Like in SQLite, an aggregate of no rows is finalized too.
+    let rows = limbo_exec_rows(&tmp_db, &conn, "select my_sum(x) from test where x > 10");
+    assert_eq!(rows, vec![vec![rusqlite::types::Value::Integer(0)]]);
+    let rows = limbo_exec_rows(
+        &tmp_db,
+        &conn,
+        "select x % 2, my_window_sum(x) from test group by x % 2 order by 1",
+    );
+    assert_eq!(
+        rows,
+        vec![
+            vec![
+                rusqlite::types::Value::Integer(0),
+                rusqlite::types::Value::Integer(6),
+            ],
+            vec![
+                rusqlite::types::Value::Integer(1),
+                rusqlite::types::Value::Integer(4),
+            ],
+        ]
+    );
+
+    let window_sums = |frame: &str| {
+        limbo_exec_rows(
+            &tmp_db,
+            &conn,
+            &format!("select my_window_sum(x) over (order by x {frame}) from test"),
+        )
+        .into_iter()
+        .map(|row| match row[0] {
+            rusqlite::types::Value::Integer(i) => i,
+            ref value => panic!("unexpected value {value:?}"),
+        })
+        .collect::<Vec<_>>()
+    };
+    assert_eq!(window_sums(""), vec![1, 3, 6, 10]);
+    assert_eq!(
+        window_sums("rows between 1 preceding and current row"),
+        vec![1, 3, 5, 7]
+    );
+    assert_eq!(
+        window_sums("rows between 1 following and 2 following"),
+        vec![5, 7, 4, 0]
+    );
+    assert!(conn
+        .execute("select my_sum(x) over (order by x) from test")
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn test_create_collation() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table test (x integer);", false);
+    let conn = tmp_db.connect_limbo();
+    conn.create_collation("reverse", Some(Box::new(|a: &str, b: &str| b.cmp(a))))?;
+    conn.create_collation(
+        "length",
+        Some(Box::new(|a: &str, b: &str| a.len().cmp(&b.len()))),
+    )?;
+    assert!(conn
+        .create_collation("NOCASE", Some(Box::new(|a: &str, b: &str| a.cmp(b))))
+        .is_err());
+
+    conn.execute("create table words (w text collate REVERSE)")?;
+    conn.execute("insert into words values ('bb'), ('a'), ('ccc')")?;
+    let words = |sql: &str| {
+        limbo_exec_rows(&tmp_db, &conn, sql)
+            .into_iter()
+            .map(|row| match &row[0] {
+                rusqlite::types::Value::Text(text) => text.clone(),
+                value => panic!("unexpected value {value:?}"),
+            })
+            .collect::<Vec<_>>()
+    };
+    // The collation of the column is used to sort and compare it.
+    assert_eq!(words("select w from words order by w"), ["ccc", "bb", "a"]);
+    assert_eq!(words("select w from words where w > 'b'"), ["a"]);
+    assert_eq!(
+        words("select w from words order by w collate binary"),
+        ["a", "bb", "ccc"]
+    );
+    assert_eq!(
+        words("select w from words where w = 'zz' collate length"),
+        ["bb"]
+    );
+
+    assert!(conn
+        .execute("create table other (w text collate missing)")
+        .is_err());
+    assert!(conn.execute("select 'a' = 'b' collate missing").is_err());
+    conn.create_collation("length", None)?;
+    assert!(conn
+        .execute("select w from words where w = 'zz' collate length")
+        .is_err());
+    Ok(())
+}
+
+#[test]
+fn test_index_collation() -> anyhow::Result<()> {
+    let tmp_db = TempDatabase::new_with_rusqlite("create table cased (a text primary key);", true);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("create table uncased (a text collate nocase primary key)")?;
+    conn.execute("insert into cased values ('B'), ('a'), ('C')")?;
+    conn.execute("insert into uncased values ('B'), ('a'), ('C')")?;
+    let values = |sql: &str| {
+        limbo_exec_rows(&tmp_db, &conn, sql)
+            .into_iter()
+            .map(|row| match &row[0] {
+                rusqlite::types::Value::Text(text) => text.clone(),
+                value => panic!("unexpected value {value:?}"),
+            })
+            .collect::<Vec<_>>()
+    };
+    // An index only orders or finds the rows of the comparisons made with its collation.
+    assert_eq!(values("select a from cased order by a"), ["B", "C", "a"]);
+    assert_eq!(
+        values("select a from cased order by a collate nocase"),
+        ["a", "B", "C"]
+    );
+    assert_eq!(
+        values("select a from cased where a = 'b' collate nocase"),
+        ["B"]
+    );
+    assert_eq!(values("select a from uncased order by a"), ["a", "B", "C"]);
+    assert_eq!(
+        values("select a from uncased order by a collate binary"),
+        ["B", "C", "a"]
+    );
+    assert_eq!(values("select a from uncased where a = 'b'"), ["B"]);
+    assert!(values("select a from uncased where a = 'b' collate binary").is_empty());
+    Ok(())
+}
+
 #[test]
 fn test_statement_reset() -> anyhow::Result<()> {
     let _ = env_logger::try_init();
@@ -285,7 +1025,7 @@ fn test_wal_checkpoint() -> anyhow::Result<()> {
     for i in 0..iterations {
         let insert_query = format!("INSERT INTO test VALUES ({i})");
         do_flush(&conn, &tmp_db)?;
-        conn.checkpoint()?;
+        conn.checkpoint(CheckpointMode::Passive)?;
         run_query(&tmp_db, &conn, &insert_query)?;
     }
 
@@ -696,43 +1436,46 @@ fn test_wal_bad_frame() -> anyhow::Result<()> {
         db_path
     };
     {
-        let result = std::panic::catch_unwind(|| {
-            let io: Arc<dyn turso_core::IO> = Arc::new(turso_core::PlatformIO::new().unwrap());
-            let db = Database::open_file_with_flags(
-                io.clone(),
-                db_path.to_str().unwrap(),
-                turso_core::OpenFlags::default(),
-                false,
-                false,
-            )
-            .unwrap();
-            let tmp_db = TempDatabase {
-                path: db_path,
-                io,
-                db,
-            };
-            let conn = tmp_db.connect_limbo();
-            run_query_on_row(&tmp_db, &conn, "SELECT count(1) from t2", |row| {
-                let x = row.get::<i64>(0).unwrap();
-                assert_eq!(x, 0);
-            })
-        });
-
-        match result {
-            Err(panic_info) => {
-                let panic_msg = panic_info
-                    .downcast_ref::<String>()
-                    .map(|s| s.as_str())
-                    .or_else(|| panic_info.downcast_ref::<&str>().copied())
-                    .unwrap_or("Unknown panic message");
-
-                assert!(
-                    panic_msg.contains("WAL frame checksum mismatch."),
-                    "Expected panic message not found. Got: {panic_msg}"
-                );
-            }
-            Ok(_) => panic!("Expected query to panic, but it succeeded"),
-        }
+        let io: Arc<dyn turso_core::IO> = Arc::new(turso_core::PlatformIO::new().unwrap());
+        let db = Database::open_file_with_flags(
+            io.clone(),
+            db_path.to_str().unwrap(),
+            turso_core::OpenFlags::default(),
+            false,
+            false,
+        )
+        .unwrap();
+        let tmp_db = TempDatabase {
+            path: db_path.clone(),
+            io,
+            db,
+        };
+        let conn = tmp_db.connect_limbo();
+        // The transaction whose commit frame is invalid is ignored.
+        assert!(conn.query("SELECT count(1) from t2").is_err());
+        conn.execute("INSERT INTO t1(x) VALUES (1)")?;
+    }
+    {
+        // The frames appended after the last valid commit frame are read back.
+        let io: Arc<dyn turso_core::IO> = Arc::new(turso_core::PlatformIO::new().unwrap());
+        let db = Database::open_file_with_flags(
+            io.clone(),
+            db_path.to_str().unwrap(),
+            turso_core::OpenFlags::default(),
+            false,
+            false,
+        )
+        .unwrap();
+        let tmp_db = TempDatabase {
+            path: db_path,
+            io,
+            db,
+        };
+        let conn = tmp_db.connect_limbo();
+        run_query_on_row(&tmp_db, &conn, "SELECT count(1) from t1", |row| {
+            let x = row.get::<i64>(0).unwrap();
+            assert_eq!(x, 1);
+        })?;
     }
 
     Ok(())
@@ -765,6 +1508,357 @@ fn test_read_wal_dumb_no_frames() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_auto_vacuum_full_shrinks_database() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+
+    conn.execute("PRAGMA auto_vacuum = full")?;
+    conn.execute("CREATE TABLE t(a INTEGER PRIMARY KEY, b)")?;
+    conn.execute("CREATE INDEX tb ON t(b)")?;
+    conn.execute("CREATE TABLE big(x)")?;
+    for i in 0..200 {
+        conn.execute(format!("INSERT INTO t VALUES ({i}, randomblob(300))"))?;
+    }
+    for _ in 0..10 {
+        conn.execute("INSERT INTO big VALUES (randomblob(9000))")?;
+    }
+    let page_count =
+        |conn: &Arc<Connection>| match limbo_exec_rows(&tmp_db, conn, "PRAGMA page_count")[0][0] {
+            rusqlite::types::Value::Integer(n) => n,
+            ref v => panic!("unexpected page_count {v:?}"),
+        };
+    let before = page_count(&conn);
+
+    conn.execute("DELETE FROM t WHERE a % 2 = 1")?;
+    conn.execute("DROP TABLE big")?;
+    assert!(page_count(&conn) < before, "the database should shrink");
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "SELECT count(*) FROM t"),
+        vec![vec![rusqlite::types::Value::Integer(100)]]
+    );
+    do_flush(&conn, &tmp_db)?;
+
+    let sqlite_conn = rusqlite::Connection::open(&tmp_db.path)?;
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "PRAGMA integrity_check"),
+        vec![vec![rusqlite::types::Value::Text("ok".to_string())]]
+    );
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "PRAGMA auto_vacuum"),
+        vec![vec![rusqlite::types::Value::Integer(1)]]
+    );
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "PRAGMA freelist_count"),
+        vec![vec![rusqlite::types::Value::Integer(0)]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_auto_vacuum_incremental() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+
+    conn.execute("PRAGMA auto_vacuum = incremental")?;
+    conn.execute("CREATE TABLE t(x)")?;
+    for _ in 0..20 {
+        conn.execute("INSERT INTO t VALUES (randomblob(5000))")?;
+    }
+    conn.execute("DELETE FROM t")?;
+    let page_count =
+        |conn: &Arc<Connection>| match limbo_exec_rows(&tmp_db, conn, "PRAGMA page_count")[0][0] {
+            rusqlite::types::Value::Integer(n) => n,
+            ref v => panic!("unexpected page_count {v:?}"),
+        };
+    let before = page_count(&conn);
+
+    // Deleted pages stay on the freelist until they are vacuumed explicitly.
+    conn.execute("DELETE FROM t")?;
+    assert_eq!(page_count(&conn), before);
+    conn.execute("PRAGMA incremental_vacuum(5)")?;
+    assert_eq!(page_count(&conn), before - 5);
+    conn.execute("PRAGMA incremental_vacuum")?;
+    assert!(page_count(&conn) < before - 5);
+    do_flush(&conn, &tmp_db)?;
+
+    let sqlite_conn = rusqlite::Connection::open(&tmp_db.path)?;
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "PRAGMA integrity_check"),
+        vec![vec![rusqlite::types::Value::Text("ok".to_string())]]
+    );
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "PRAGMA auto_vacuum"),
+        vec![vec![rusqlite::types::Value::Integer(2)]]
+    );
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "PRAGMA freelist_count"),
+        vec![vec![rusqlite::types::Value::Integer(0)]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_auto_vacuum_drop_table_moves_root_page() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+
+    conn.execute("PRAGMA auto_vacuum = full")?;
+    conn.execute("CREATE TABLE t1(x)")?;
+    conn.execute("CREATE TABLE t2(x)")?;
+    conn.execute("CREATE TABLE t3(x UNIQUE)")?;
+    conn.execute("INSERT INTO t3 VALUES (1), (2), (3)")?;
+    conn.execute("DROP TABLE t1")?;
+
+    // The last root page takes the place of the dropped one, so roots stay contiguous.
+    let roots = limbo_exec_rows(
+        &tmp_db,
+        &conn,
+        "SELECT rootpage FROM sqlite_schema ORDER BY rootpage",
+    );
+    assert_eq!(
+        roots,
+        vec![
+            vec![rusqlite::types::Value::Integer(3)],
+            vec![rusqlite::types::Value::Integer(4)],
+            vec![rusqlite::types::Value::Integer(5)],
+        ]
+    );
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "SELECT x FROM t3 WHERE x = 2"),
+        vec![vec![rusqlite::types::Value::Integer(2)]]
+    );
+    do_flush(&conn, &tmp_db)?;
+
+    let sqlite_conn = rusqlite::Connection::open(&tmp_db.path)?;
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "PRAGMA integrity_check"),
+        vec![vec![rusqlite::types::Value::Text("ok".to_string())]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_vacuum() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+    let other_conn = tmp_db.connect_limbo();
+
+    conn.execute("CREATE TABLE big(x)")?;
+    conn.execute("CREATE TABLE t(a INTEGER PRIMARY KEY, b UNIQUE)")?;
+    for i in 0..200 {
+        conn.execute(format!("INSERT INTO t VALUES ({i}, randomblob(300))"))?;
+    }
+    for _ in 0..10 {
+        conn.execute("INSERT INTO big VALUES (randomblob(9000))")?;
+    }
+    conn.execute("DELETE FROM t WHERE a % 2 = 1")?;
+    conn.execute("DROP TABLE big")?;
+    let page_count =
+        |conn: &Arc<Connection>| match limbo_exec_rows(&tmp_db, conn, "PRAGMA page_count")[0][0] {
+            rusqlite::types::Value::Integer(n) => n,
+            ref v => panic!("unexpected page_count {v:?}"),
+        };
+    let before = page_count(&conn);
+
+    conn.execute("VACUUM")?;
+    assert!(page_count(&conn) < before, "the database should shrink");
+    // The root pages moved, which the other connection has to notice.
+    for conn in [&conn, &other_conn] {
+        assert_eq!(
+            limbo_exec_rows(&tmp_db, conn, "SELECT count(*), sum(a) FROM t"),
+            vec![vec![
+                rusqlite::types::Value::Integer(100),
+                rusqlite::types::Value::Integer(9900)
+            ]]
+        );
+    }
+    do_flush(&conn, &tmp_db)?;
+
+    let sqlite_conn = rusqlite::Connection::open(&tmp_db.path)?;
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "PRAGMA integrity_check"),
+        vec![vec![rusqlite::types::Value::Text("ok".to_string())]]
+    );
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "PRAGMA freelist_count"),
+        vec![vec![rusqlite::types::Value::Integer(0)]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_vacuum_into() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+
+    conn.execute("CREATE TABLE t(a INTEGER PRIMARY KEY, b)")?;
+    conn.execute("CREATE INDEX tb ON t(b)")?;
+    for i in 0..100 {
+        conn.execute(format!("INSERT INTO t VALUES ({i}, {})", i * 2))?;
+    }
+    conn.execute("PRAGMA user_version = 3")?;
+    let into_path = tmp_db.path.with_extension("copy.db");
+    conn.execute(format!("VACUUM INTO '{}'", into_path.display()))?;
+    assert!(conn
+        .execute(format!("VACUUM INTO '{}'", into_path.display()))
+        .is_err());
+
+    let sqlite_conn = rusqlite::Connection::open(&into_path)?;
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "PRAGMA integrity_check"),
+        vec![vec![rusqlite::types::Value::Text("ok".to_string())]]
+    );
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "SELECT count(*), sum(b) FROM t"),
+        vec![vec![
+            rusqlite::types::Value::Integer(100),
+            rusqlite::types::Value::Integer(9900)
+        ]]
+    );
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "PRAGMA user_version"),
+        vec![vec![rusqlite::types::Value::Integer(3)]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_backup() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let source_db = TempDatabase::new_empty(true);
+    let source = source_db.connect_limbo();
+    source.execute("CREATE TABLE t(a INTEGER PRIMARY KEY, b)")?;
+    source.execute("CREATE INDEX tb ON t(b)")?;
+    for i in 0..200 {
+        source.execute(format!("INSERT INTO t VALUES ({i}, randomblob(300))"))?;
+    }
+    // The destination already has more pages than the source, which the backup drops.
+    let dest_db = TempDatabase::new_empty(true);
+    let dest = dest_db.connect_limbo();
+    let other_dest = dest_db.connect_limbo();
+    dest.execute("CREATE TABLE old(x)")?;
+    for _ in 0..20 {
+        dest.execute("INSERT INTO old VALUES (randomblob(9000))")?;
+    }
+    assert_eq!(
+        limbo_exec_rows(&dest_db, &other_dest, "SELECT count(*) FROM old"),
+        vec![vec![rusqlite::types::Value::Integer(20)]]
+    );
+
+    let mut backup = Backup::new(source.clone(), dest.clone())?;
+    assert!(!backup.step(5)?);
+    let page_count = backup.page_count();
+    assert_eq!(backup.remaining(), page_count - 5);
+    // Reads of the source continue while the backup is in progress.
+    assert_eq!(
+        limbo_exec_rows(&source_db, &source, "SELECT count(*) FROM t"),
+        vec![vec![rusqlite::types::Value::Integer(200)]]
+    );
+    while !backup.step(5)? {}
+    assert_eq!(backup.remaining(), 0);
+    backup.finish()?;
+
+    for conn in [&dest, &other_dest] {
+        assert_eq!(
+            limbo_exec_rows(&dest_db, conn, "SELECT count(*), sum(a) FROM t"),
+            vec![vec![
+                rusqlite::types::Value::Integer(200),
+                rusqlite::types::Value::Integer(19900)
+            ]]
+        );
+        assert!(conn.execute("SELECT * FROM old").is_err());
+    }
+    assert_eq!(
+        limbo_exec_rows(&dest_db, &dest, "PRAGMA page_count"),
+        vec![vec![rusqlite::types::Value::Integer(page_count as i64)]]
+    );
+    do_flush(&dest, &dest_db)?;
+    dest.checkpoint(CheckpointMode::Truncate)?;
+
+    let sqlite_conn = rusqlite::Connection::open(&dest_db.path)?;
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "PRAGMA integrity_check"),
+        vec![vec![rusqlite::types::Value::Text("ok".to_string())]]
+    );
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "SELECT count(*) FROM t WHERE b IS NOT NULL"),
+        vec![vec![rusqlite::types::Value::Integer(200)]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_backup_restarts_when_source_changes() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let source_db = TempDatabase::new_empty(true);
+    let source = source_db.connect_limbo();
+    let writer = source_db.connect_limbo();
+    source.execute("CREATE TABLE t(a INTEGER PRIMARY KEY, b)")?;
+    for i in 0..100 {
+        source.execute(format!("INSERT INTO t VALUES ({i}, randomblob(300))"))?;
+    }
+    let io: Arc<dyn turso_core::IO> = Arc::new(turso_core::MemoryIO::new());
+    let dest_db = Database::open_file(io, ":memory:", false, true)?;
+    let dest = dest_db.connect()?;
+
+    let mut backup = Backup::new(source.clone(), dest.clone())?;
+    assert!(!backup.step(3)?);
+    writer.execute("INSERT INTO t VALUES (100, 'new')")?;
+    assert!(!backup.step(3)?);
+    // The source changed, so the backup started over.
+    assert_eq!(backup.remaining(), backup.page_count() - 3);
+    assert!(backup.step(-1)?);
+    backup.finish()?;
+
+    assert_eq!(
+        limbo_exec_rows(&source_db, &dest, "SELECT count(*) FROM t"),
+        vec![vec![rusqlite::types::Value::Integer(101)]]
+    );
+    assert_eq!(
+        limbo_exec_rows(&source_db, &dest, "SELECT b FROM t WHERE a = 100"),
+        vec![vec![rusqlite::types::Value::Text("new".to_string())]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_backup_not_finished() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let source_db = TempDatabase::new_empty(true);
+    let source = source_db.connect_limbo();
+    source.execute("CREATE TABLE t(a INTEGER PRIMARY KEY, b)")?;
+    for i in 0..100 {
+        source.execute(format!("INSERT INTO t VALUES ({i}, randomblob(300))"))?;
+    }
+    let dest_db = TempDatabase::new_empty(true);
+    let dest = dest_db.connect_limbo();
+    dest.execute("CREATE TABLE old(x)")?;
+    dest.execute("INSERT INTO old VALUES (1)")?;
+
+    assert!(Backup::new(source.clone(), source_db.connect_limbo()).is_err());
+    let mut backup = Backup::new(source.clone(), dest.clone())?;
+    assert!(!backup.step(3)?);
+    // The destination is locked until the backup is finished.
+    assert!(dest_db
+        .connect_limbo()
+        .execute("INSERT INTO old VALUES (2)")
+        .is_err());
+    backup.finish()?;
+
+    assert_eq!(
+        limbo_exec_rows(&dest_db, &dest, "SELECT count(*) FROM old"),
+        vec![vec![rusqlite::types::Value::Integer(1)]]
+    );
+    dest.execute("INSERT INTO old VALUES (2)")?;
+    Ok(())
+}
+
 fn run_query(tmp_db: &TempDatabase, conn: &Arc<Connection>, query: &str) -> anyhow::Result<()> {
     run_query_core(tmp_db, conn, query, None::<fn(&Row)>)
 }
@@ -807,3 +1901,380 @@ fn run_query_core(
     };
     Ok(())
 }
+
+#[test]
+fn test_attach_database() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+    let aux_path = tmp_db.path.with_extension("aux.db");
+    {
+        let sqlite_conn = rusqlite::Connection::open(&aux_path)?;
+        sqlite_conn.execute("CREATE TABLE t(a INTEGER PRIMARY KEY, b)", ())?;
+        sqlite_conn.execute("INSERT INTO t VALUES (1, 'one'), (2, 'two')", ())?;
+    }
+
+    conn.execute("CREATE TABLE t(x)")?;
+    conn.execute("INSERT INTO t VALUES (10)")?;
+    conn.execute(format!("ATTACH '{}' AS aux", aux_path.display()))?;
+    conn.execute("ATTACH ':memory:' AS mem")?;
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "PRAGMA database_list"),
+        vec![
+            vec![
+                rusqlite::types::Value::Integer(0),
+                rusqlite::types::Value::Text("main".to_string()),
+                rusqlite::types::Value::Text(tmp_db.path.display().to_string()),
+            ],
+            vec![
+                rusqlite::types::Value::Integer(2),
+                rusqlite::types::Value::Text("aux".to_string()),
+                rusqlite::types::Value::Text(aux_path.display().to_string()),
+            ],
+            vec![
+                rusqlite::types::Value::Integer(3),
+                rusqlite::types::Value::Text("mem".to_string()),
+                rusqlite::types::Value::Text("".to_string()),
+            ],
+        ]
+    );
+    assert!(conn.execute("ATTACH ':memory:' AS aux").is_err());
+
+    // The main database shadows the attached ones for unqualified names.
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "SELECT * FROM t"),
+        vec![vec![rusqlite::types::Value::Integer(10)]]
+    );
+    conn.execute("CREATE TABLE mem.m(k, v)")?;
+    conn.execute("INSERT INTO m VALUES (1, 'mem1'), (2, 'mem2')")?;
+    conn.execute("INSERT INTO aux.t VALUES (3, 'three')")?;
+    conn.execute("UPDATE aux.t SET b = 'uno' WHERE a = 1")?;
+    conn.execute("DELETE FROM aux.t WHERE a = 2")?;
+    conn.execute("CREATE INDEX aux.tb ON t(b)")?;
+    assert_eq!(
+        limbo_exec_rows(
+            &tmp_db,
+            &conn,
+            "SELECT aux.t.b, m.v FROM aux.t JOIN m ON aux.t.a = m.k"
+        ),
+        vec![vec![
+            rusqlite::types::Value::Text("uno".to_string()),
+            rusqlite::types::Value::Text("mem1".to_string()),
+        ]]
+    );
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "SELECT a FROM aux.t WHERE b = 'three'"),
+        vec![vec![rusqlite::types::Value::Integer(3)]]
+    );
+
+    // A transaction spans all the databases.
+    conn.execute("BEGIN")?;
+    conn.execute("INSERT INTO t VALUES (11)")?;
+    conn.execute("INSERT INTO aux.t VALUES (4, 'four')")?;
+    assert!(conn.execute("DETACH aux").is_err());
+    conn.execute("ROLLBACK")?;
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "SELECT count(*) FROM main.t"),
+        vec![vec![rusqlite::types::Value::Integer(1)]]
+    );
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "SELECT count(*) FROM aux.t"),
+        vec![vec![rusqlite::types::Value::Integer(2)]]
+    );
+    conn.execute("BEGIN")?;
+    conn.execute("INSERT INTO t VALUES (11)")?;
+    conn.execute("INSERT INTO aux.t VALUES (4, 'four')")?;
+    conn.execute("COMMIT")?;
+
+    conn.execute("DETACH mem")?;
+    assert!(conn.execute("SELECT * FROM m").is_err());
+    assert!(conn.execute("DETACH main").is_err());
+    assert!(conn.execute("DETACH nope").is_err());
+    conn.execute("DETACH DATABASE aux")?;
+    assert!(conn.execute("SELECT * FROM aux.t").is_err());
+
+    let sqlite_conn = rusqlite::Connection::open(&aux_path)?;
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "PRAGMA integrity_check"),
+        vec![vec![rusqlite::types::Value::Text("ok".to_string())]]
+    );
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "SELECT a, b FROM t ORDER BY a"),
+        vec![
+            vec![
+                rusqlite::types::Value::Integer(1),
+                rusqlite::types::Value::Text("uno".to_string())
+            ],
+            vec![
+                rusqlite::types::Value::Integer(3),
+                rusqlite::types::Value::Text("three".to_string())
+            ],
+            vec![
+                rusqlite::types::Value::Integer(4),
+                rusqlite::types::Value::Text("four".to_string())
+            ],
+        ]
+    );
+    assert_eq!(
+        sqlite_exec_rows(
+            &sqlite_conn,
+            "SELECT name FROM sqlite_schema WHERE type = 'index'"
+        ),
+        vec![vec![rusqlite::types::Value::Text("tb".to_string())]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_shared_memory_database() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    fn count(conn: &Arc<Connection>) -> anyhow::Result<i64> {
+        let mut stmt = conn.prepare("SELECT count(*) FROM t")?;
+        let mut count = None;
+        loop {
+            match stmt.step()? {
+                StepResult::Row => count = Some(stmt.row().unwrap().get::<i64>(0)?),
+                StepResult::IO => stmt.run_once()?,
+                StepResult::Done => return Ok(count.unwrap()),
+                r => panic!("unexpected step result {r:?}"),
+            }
+        }
+    }
+
+    let (_, conn1) = Connection::from_uri("file::memory:?cache=shared", false, false)?;
+    let (_, conn2) = Connection::from_uri("file::memory:?cache=shared", false, false)?;
+    conn1.execute("CREATE TABLE t(x)")?;
+    conn1.execute("INSERT INTO t VALUES (1), (2)")?;
+    assert_eq!(count(&conn2)?, 2);
+    conn2.execute("INSERT INTO t VALUES (3)")?;
+    assert_eq!(count(&conn1)?, 3);
+
+    // Other names, and private in-memory databases, are separate databases.
+    let (_, other) = Connection::from_uri("file:other?mode=memory&cache=shared", false, false)?;
+    assert!(other.execute("SELECT * FROM t").is_err());
+    let (_, private) = Connection::from_uri("file::memory:", false, false)?;
+    assert!(private.execute("SELECT * FROM t").is_err());
+
+    // A shared database can be attached by its URI too.
+    other.execute("ATTACH 'file::memory:?cache=shared' AS shared")?;
+    other.execute("INSERT INTO shared.t VALUES (4)")?;
+    other.execute("DETACH shared")?;
+    assert_eq!(count(&conn1)?, 4);
+
+    // The database is gone once its last connection is closed.
+    conn1.close()?;
+    drop(conn1);
+    assert_eq!(count(&conn2)?, 4);
+    conn2.close()?;
+    drop(conn2);
+    let (_, conn3) = Connection::from_uri("file::memory:?cache=shared", false, false)?;
+    assert!(conn3.execute("SELECT * FROM t").is_err());
+    Ok(())
+}
+
+#[test]
+fn test_temp_tables() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn1 = tmp_db.connect_limbo();
+    let conn2 = tmp_db.connect_limbo();
+
+    conn1.execute("CREATE TABLE t(x)")?;
+    conn1.execute("INSERT INTO t VALUES ('main')")?;
+    conn1.execute("CREATE TEMP TABLE t(x)")?;
+    conn1.execute("CREATE INDEX temp.tx ON t(x)")?;
+    conn1.execute("INSERT INTO t VALUES ('temp1'), ('temp2')")?;
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn1, "SELECT x FROM t WHERE x = 'temp2'"),
+        vec![vec![rusqlite::types::Value::Text("temp2".to_string())]]
+    );
+
+    // TEMP tables are private to the connection that created them.
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn2, "SELECT x FROM t"),
+        vec![vec![rusqlite::types::Value::Text("main".to_string())]]
+    );
+    conn2.execute("CREATE TEMP TABLE u(y)")?;
+    assert!(conn1.execute("SELECT * FROM u").is_err());
+
+    // A transaction spans the temp database too.
+    conn1.execute("BEGIN")?;
+    conn1.execute("INSERT INTO t VALUES ('temp3')")?;
+    conn1.execute("INSERT INTO main.t VALUES ('main2')")?;
+    conn1.execute("ROLLBACK")?;
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn1, "SELECT count(*) FROM t"),
+        vec![vec![rusqlite::types::Value::Integer(2)]]
+    );
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn1, "SELECT count(*) FROM main.t"),
+        vec![vec![rusqlite::types::Value::Integer(1)]]
+    );
+
+    // They are discarded when the connection is closed, and never reach the database file.
+    conn1.close()?;
+    let conn3 = tmp_db.connect_limbo();
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn3, "SELECT x FROM t"),
+        vec![vec![rusqlite::types::Value::Text("main".to_string())]]
+    );
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn3, "SELECT name FROM sqlite_schema"),
+        vec![vec![rusqlite::types::Value::Text("t".to_string())]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_analyze() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_empty(true);
+    let conn = tmp_db.connect_limbo();
+    conn.execute("CREATE TABLE t(a, b)")?;
+    conn.execute("CREATE INDEX ta ON t(a)")?;
+    conn.execute("CREATE INDEX tb ON t(b)")?;
+    for i in 0..100 {
+        conn.execute(format!("INSERT INTO t VALUES (1, {i})"))?;
+    }
+    let plan = |db: &TempDatabase, conn: &Arc<Connection>| {
+        let rows = limbo_exec_rows(
+            db,
+            conn,
+            "EXPLAIN QUERY PLAN SELECT * FROM t WHERE a = 1 AND b = 5",
+        );
+        rows[0][3].clone()
+    };
+    let text = |s: &str| rusqlite::types::Value::Text(s.to_string());
+
+    // Without statistics, the equalities on both columns look as selective.
+    assert_eq!(plan(&tmp_db, &conn), text("SEARCH t USING INDEX ta (a=?)"));
+
+    conn.execute("ANALYZE")?;
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "SELECT * FROM sqlite_stat1 ORDER BY idx"),
+        vec![
+            vec![text("t"), text("ta"), text("100 100")],
+            vec![text("t"), text("tb"), text("100 1")],
+        ]
+    );
+    assert_eq!(plan(&tmp_db, &conn), text("SEARCH t USING INDEX tb (b=?)"));
+
+    // The statistics are loaded with the schema when the database is opened again.
+    conn.close()?;
+    let tmp_db = TempDatabase::new_with_existent(&tmp_db.path, true);
+    let conn = tmp_db.connect_limbo();
+    assert_eq!(plan(&tmp_db, &conn), text("SEARCH t USING INDEX tb (b=?)"));
+    Ok(())
+}
+
+#[test]
+fn test_update_without_rowid_table() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    // WITHOUT ROWID tables can't be created yet, so SQLite creates this one. Its records start
+    // with the PRIMARY KEY, which is not the first column.
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "CREATE TABLE t (b INT, a TEXT PRIMARY KEY, c) WITHOUT ROWID;",
+        false,
+    );
+    {
+        let sqlite_conn = rusqlite::Connection::open(&tmp_db.path)?;
+        sqlite_conn
+            .execute_batch("INSERT INTO t VALUES (1, 'x', 'p'), (2, 'y', 'q'), (3, 'z', 'r');")?;
+    }
+    let tmp_db = TempDatabase::new_with_existent(&tmp_db.path, false);
+    let conn = tmp_db.connect_limbo();
+    let int = rusqlite::types::Value::Integer;
+    let text = |s: &str| rusqlite::types::Value::Text(s.to_string());
+
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "SELECT * FROM t WHERE a = 'y'"),
+        vec![vec![int(2), text("y"), text("q")]]
+    );
+
+    conn.execute("UPDATE t SET c = c || '!', b = b + 10 WHERE b >= 2")?;
+    assert_eq!(conn.changes(), 2);
+    // Changing the PRIMARY KEY moves the row.
+    conn.execute("UPDATE t SET a = 'w' WHERE a = 'z'")?;
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "SELECT * FROM t"),
+        vec![
+            vec![int(13), text("w"), text("r!")],
+            vec![int(1), text("x"), text("p")],
+            vec![int(12), text("y"), text("q!")],
+        ]
+    );
+
+    let err =
+        limbo_exec_rows_error(&tmp_db, &conn, "UPDATE t SET a = 'y' WHERE a = 'x'").unwrap_err();
+    assert!(
+        err.to_string().contains("UNIQUE constraint failed: t.a"),
+        "{err}"
+    );
+    let err =
+        limbo_exec_rows_error(&tmp_db, &conn, "UPDATE t SET a = NULL WHERE a = 'x'").unwrap_err();
+    assert!(
+        err.to_string().contains("NOT NULL constraint failed: t.a"),
+        "{err}"
+    );
+    conn.execute("UPDATE OR IGNORE t SET a = 'y' WHERE a = 'x'")?;
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "SELECT a FROM t"),
+        vec![vec![text("w")], vec![text("x")], vec![text("y")]]
+    );
+
+    // SQLite finds the rows where they were moved to.
+    let sqlite_conn = rusqlite::Connection::open(&tmp_db.path)?;
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "PRAGMA integrity_check"),
+        vec![vec![text("ok")]]
+    );
+    assert_eq!(
+        sqlite_exec_rows(&sqlite_conn, "SELECT b, c FROM t WHERE a = 'w'"),
+        vec![vec![int(13), text("r!")]]
+    );
+    Ok(())
+}
+
+#[test]
+fn test_update_without_rowid_table_composite_primary_key() -> anyhow::Result<()> {
+    let _ = env_logger::try_init();
+    let tmp_db = TempDatabase::new_with_rusqlite(
+        "CREATE TABLE t (k1, v, k2, PRIMARY KEY (k2 DESC, k1)) WITHOUT ROWID;",
+        false,
+    );
+    {
+        let sqlite_conn = rusqlite::Connection::open(&tmp_db.path)?;
+        sqlite_conn.execute_batch("INSERT INTO t VALUES (1, 'a', 8), (2, 'b', 7), (3, 'c', 8);")?;
+    }
+    let tmp_db = TempDatabase::new_with_existent(&tmp_db.path, false);
+    let conn = tmp_db.connect_limbo();
+    let int = rusqlite::types::Value::Integer;
+    let text = |s: &str| rusqlite::types::Value::Text(s.to_string());
+
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "SELECT k1, v FROM t WHERE k2 = 8"),
+        vec![vec![int(1), text("a")], vec![int(3), text("c")]]
+    );
+
+    // Every row moves, and none of them is updated twice.
+    conn.execute("UPDATE t SET k2 = k2 + 1")?;
+    assert_eq!(conn.changes(), 3);
+    // Only one of the key columns changes.
+    conn.execute("UPDATE t SET k1 = 5 WHERE k1 = 1")?;
+    let err =
+        limbo_exec_rows_error(&tmp_db, &conn, "UPDATE t SET k1 = 3 WHERE k1 = 5").unwrap_err();
+    assert!(
+        err.to_string()
+            .contains("UNIQUE constraint failed: t.k2, t.k1"),
+        "{err}"
+    );
+    assert_eq!(
+        limbo_exec_rows(&tmp_db, &conn, "SELECT * FROM t"),
+        vec![
+            vec![int(3), text("c"), int(9)],
+            vec![int(5), text("a"), int(9)],
+            vec![int(2), text("b"), int(8)],
+        ]
+    );
+    Ok(())
+}
@@ -3,7 +3,7 @@ use std::cell::RefCell;
 use std::ops::Deref;
 use std::rc::Rc;
 use std::sync::{Arc, Mutex};
-use turso_core::{Connection, LimboError, Result, StepResult};
+use turso_core::{CheckpointMode, Connection, LimboError, Result, StepResult};
 
 #[allow(clippy::arc_with_non_send_sync)]
 #[test]
@@ -109,6 +109,197 @@ fn test_wal_1_writer_1_reader() -> Result<()> {
     Ok(())
 }
 
+#[test]
+fn test_wal_readers_keep_their_snapshot() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t(x)", false);
+    let writer = tmp_db.connect_limbo();
+    let readers = (0..3).map(|_| tmp_db.connect_limbo()).collect::<Vec<_>>();
+
+    // Each reader starts its snapshot after one more row was committed.
+    for (i, reader) in readers.iter().enumerate() {
+        writer.execute(format!("INSERT INTO t VALUES ({i})"))?;
+        reader.execute("BEGIN")?;
+        assert_eq!(
+            execute_and_get_ints(reader, "SELECT count(*) FROM t")?,
+            vec![i as i64 + 1]
+        );
+    }
+    // The writer isn't blocked by the readers, which don't see its changes.
+    writer.execute("INSERT INTO t VALUES (3)")?;
+    for (i, reader) in readers.iter().enumerate() {
+        assert_eq!(
+            execute_and_get_ints(reader, "SELECT count(*) FROM t")?,
+            vec![i as i64 + 1]
+        );
+        reader.execute("COMMIT")?;
+        assert_eq!(
+            execute_and_get_ints(reader, "SELECT count(*) FROM t")?,
+            vec![4]
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_wal_checkpoint_modes() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t(x)", false);
+    let wal_path = tmp_db.path.with_extension("db-wal");
+    let conn = tmp_db.connect_limbo();
+    let reader = tmp_db.connect_limbo();
+    for i in 0..10 {
+        conn.execute(format!("INSERT INTO t VALUES ({i})"))?;
+    }
+    let res = execute_and_get_ints(&conn, "PRAGMA wal_checkpoint(FULL)")?;
+    assert_eq!(res[0], 0);
+    assert_eq!(res[1], res[2]);
+
+    // A reader keeps the frames after its snapshot from being backfilled, and the WAL from
+    // being restarted.
+    reader.execute("BEGIN")?;
+    assert_eq!(
+        execute_and_get_ints(&reader, "SELECT count(*) FROM t")?,
+        vec![10]
+    );
+    conn.execute("INSERT INTO t VALUES (10)")?;
+    let res = execute_and_get_ints(&conn, "PRAGMA wal_checkpoint(RESTART)")?;
+    assert_eq!(res[0], 1);
+    assert!(matches!(
+        conn.checkpoint(CheckpointMode::Full),
+        Err(LimboError::Busy)
+    ));
+    reader.execute("COMMIT")?;
+    let res = execute_and_get_ints(&conn, "PRAGMA wal_checkpoint(RESTART)")?;
+    assert_eq!(res[0], 0);
+    assert_eq!(res[1], res[2]);
+
+    // The WAL is written from the start again.
+    let wal_size = std::fs::metadata(&wal_path).unwrap().len();
+    conn.execute("INSERT INTO t VALUES (11)")?;
+    assert_eq!(conn.wal_frame_count()?, 1);
+    assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), wal_size);
+    assert_eq!(
+        execute_and_get_ints(&reader, "SELECT count(*) FROM t")?,
+        vec![12]
+    );
+
+    let res = conn.checkpoint(CheckpointMode::Truncate)?;
+    assert_eq!(res.num_wal_frames, 1);
+    assert_eq!(res.num_checkpointed_frames, 1);
+    assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 0);
+    conn.execute("INSERT INTO t VALUES (12)")?;
+    assert_eq!(
+        execute_and_get_ints(&reader, "SELECT count(*) FROM t")?,
+        vec![13]
+    );
+
+    // The frames written after the WAL was truncated are recovered.
+    drop(conn);
+    drop(reader);
+    let path = tmp_db.path.clone();
+    drop(tmp_db);
+    let tmp_db = TempDatabase::new_with_existent(&path, false);
+    let conn = tmp_db.connect_limbo();
+    assert_eq!(
+        execute_and_get_ints(&conn, "SELECT count(*), sum(x) FROM t")?,
+        vec![13, 78]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_wal_autocheckpoint() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t(x)", false);
+    let conn = tmp_db.connect_limbo();
+    assert_eq!(
+        execute_and_get_ints(&conn, "PRAGMA wal_autocheckpoint")?,
+        vec![1000]
+    );
+    assert_eq!(
+        execute_and_get_ints(&conn, "PRAGMA wal_autocheckpoint = -5")?,
+        vec![0]
+    );
+
+    let db_size = std::fs::metadata(&tmp_db.path).unwrap().len();
+    for _ in 0..5 {
+        conn.execute("INSERT INTO t VALUES (randomblob(4096))")?;
+    }
+    // Nothing was checkpointed into the database file.
+    assert_eq!(std::fs::metadata(&tmp_db.path).unwrap().len(), db_size);
+
+    assert_eq!(
+        execute_and_get_ints(&conn, "PRAGMA wal_autocheckpoint = 3")?,
+        vec![3]
+    );
+    conn.execute("INSERT INTO t VALUES (randomblob(4096))")?;
+    assert!(std::fs::metadata(&tmp_db.path).unwrap().len() > db_size);
+    assert_eq!(
+        execute_and_get_ints(&conn, "SELECT count(*) FROM t")?,
+        vec![6]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_journal_mode_delete() -> Result<()> {
+    maybe_setup_tracing();
+    let tmp_db = TempDatabase::new_with_rusqlite("CREATE TABLE t(x)", false);
+    let wal_path = tmp_db.path.with_extension("db-wal");
+    let conn = tmp_db.connect_limbo();
+    let reader = tmp_db.connect_limbo();
+    conn.execute("INSERT INTO t VALUES (0)")?;
+    assert_eq!(
+        execute_and_get_strings(&conn, "PRAGMA journal_mode = DELETE")?,
+        vec!["delete"]
+    );
+    // Leaving WAL mode empties the WAL, and so does every commit after that.
+    assert_eq!(conn.wal_frame_count()?, 0);
+    conn.execute("INSERT INTO t VALUES (1)")?;
+    assert_eq!(conn.wal_frame_count()?, 0);
+    assert_eq!(std::fs::metadata(&wal_path).unwrap().len(), 0);
+
+    // A reader keeps the frames it may need in the WAL until a later commit.
+    reader.execute("BEGIN")?;
+    assert_eq!(
+        execute_and_get_ints(&reader, "SELECT count(*) FROM t")?,
+        vec![2]
+    );
+    conn.execute("INSERT INTO t VALUES (2)")?;
+    assert_eq!(conn.wal_frame_count()?, 1);
+    reader.execute("COMMIT")?;
+    conn.execute("INSERT INTO t VALUES (3)")?;
+    assert_eq!(conn.wal_frame_count()?, 0);
+    assert_eq!(
+        execute_and_get_ints(&reader, "SELECT count(*) FROM t")?,
+        vec![4]
+    );
+
+    // Unknown modes are ignored, and WAL mode can't be entered within a transaction.
+    assert_eq!(
+        execute_and_get_strings(&conn, "PRAGMA journal_mode = bogus")?,
+        vec!["delete"]
+    );
+    conn.execute("BEGIN")?;
+    assert!(matches!(
+        conn.prepare("PRAGMA journal_mode = WAL"),
+        Err(LimboError::TxError(_))
+    ));
+    conn.execute("COMMIT")?;
+    assert_eq!(
+        execute_and_get_strings(&conn, "PRAGMA journal_mode = WAL")?,
+        vec!["wal"]
+    );
+    conn.execute("INSERT INTO t VALUES (4)")?;
+    assert_eq!(conn.wal_frame_count()?, 1);
+
+    Ok(())
+}
+
 /// Execute a statement and get strings result
 pub(crate) fn execute_and_get_strings(conn: &Arc<Connection>, sql: &str) -> Result<Vec<String>> {
     let statement = conn.prepare(sql)?;
@@ -3,7 +3,7 @@ use fallible_iterator::FallibleIterator;
 use super::{Error, Parser};
 use crate::parser::ast::fmt::ToTokens;
 use crate::parser::{
-    ast::{Cmd, ParameterInfo, Stmt},
+    ast::{Cmd, Expr, OneSelect, ParameterInfo, ResultColumn, Stmt},
     ParserError,
 };
 
@@ -42,6 +42,34 @@ fn count_named_placeholders() {
     assert!(info.names.contains(":y"));
 }
 
+#[test]
+fn number_placeholders_in_order() {
+    let ast = parse_cmd(b"SELECT ?, :x, ?5, ?, :x, ?");
+    let Cmd::Stmt(Stmt::Select(select)) = ast else {
+        panic!("unexpected AST")
+    };
+    let OneSelect::Select(one_select) = *select.body.select else {
+        panic!("unexpected AST")
+    };
+    let variables: Vec<_> = one_select
+        .columns
+        .into_iter()
+        .map(|column| match column {
+            ResultColumn::Expr(Expr::Variable(variable), _) => variable,
+            _ => panic!("unexpected AST"),
+        })
+        .collect();
+    assert_eq!(variables, ["?1", ":x", "5", "?6", ":x", "?7"]);
+}
+
+#[test]
+fn placeholder_number_out_of_range() {
+    expect_parser_err_msg(
+        b"SELECT ?32767",
+        "variable number must be between ?1 and ?32766",
+    );
+}
+
 #[test]
 fn duplicate_column() {
     expect_parser_err_msg(
@@ -850,12 +850,35 @@ impl ToTokens for Expr {
                 }
                 s.append(TK_RP, None)
             }
+            Self::Register(reg) => s.append(TK_VARIABLE, Some(&format!("r[{reg}]"))),
             Self::RowId { .. } => Ok(()),
             Self::Subquery(query) => {
                 s.append(TK_LP, None)?;
                 query.to_tokens_with_context(s, context)?;
                 s.append(TK_RP, None)
             }
+            Self::SubqueryResult {
+                subquery_id,
+                query_type,
+                lhs,
+                not_in,
+                ..
+            } => {
+                match query_type {
+                    SubqueryType::Exists => s.append(TK_EXISTS, None)?,
+                    SubqueryType::Scalar => {}
+                    SubqueryType::In => {
+                        if let Some(lhs) = lhs {
+                            lhs.to_tokens_with_context(s, context)?;
+                        }
+                        if *not_in {
+                            s.append(TK_NOT, None)?;
+                        }
+                        s.append(TK_IN, None)?;
+                    }
+                }
+                s.append(TK_VARIABLE, Some(&format!("subquery[{subquery_id}]")))
+            }
             Self::Unary(op, sub_expr) => {
                 op.to_tokens_with_context(s, context)?;
                 sub_expr.to_tokens_with_context(s, context)
@@ -864,8 +887,8 @@ impl ToTokens for Expr {
                 Some(c) if c == '$' || c == '@' || c == '#' || c == ':' => {
                     s.append(TK_VARIABLE, Some(var))
                 }
+                Some('?') | None => s.append(TK_VARIABLE, Some("?")),
                 Some(_) => s.append(TK_VARIABLE, Some(&("?".to_owned() + var))),
-                None => s.append(TK_VARIABLE, Some("?")),
             },
         }
     }
@@ -2139,14 +2162,12 @@ impl ToTokens for TransactionType {
         s: &mut S,
         _: &C,
     ) -> Result<(), S::Error> {
-        s.append(
-            match self {
-                Self::Deferred => TK_DEFERRED,
-                Self::Immediate => TK_IMMEDIATE,
-                Self::Exclusive => TK_EXCLUSIVE,
-            },
-            None,
-        )
+        match self {
+            Self::Deferred => s.append(TK_DEFERRED, None),
+            Self::Immediate => s.append(TK_IMMEDIATE, None),
+            Self::Exclusive => s.append(TK_EXCLUSIVE, None),
+            Self::Concurrent => s.append(TK_ID, Some("CONCURRENT")),
+        }
     }
 }
 
@@ -472,14 +472,44 @@ pub enum Expr {
     Qualified(Name, Name),
     /// `RAISE` function call
     Raise(ResolveType, Option<Box<Expr>>),
+    /// A value already computed into a register of the program being built,
+    /// e.g. a column of the `OLD`/`NEW` row inside a trigger program.
+    Register(usize),
     /// Subquery expression
     Subquery(Box<Select>),
+    /// The result of a subquery of an expression, e.g. `EXISTS (SELECT ...)`,
+    /// once the subquery has been planned separately from the expression.
+    SubqueryResult {
+        /// internal id of the subquery
+        subquery_id: TableInternalId,
+        /// kind of subquery
+        query_type: SubqueryType,
+        /// the left-hand side of `lhs [NOT] IN (SELECT ...)`
+        lhs: Option<Box<Expr>>,
+        /// `NOT IN`
+        not_in: bool,
+        /// the columns of the outer queries that the subquery reads
+        outer_columns: Vec<Expr>,
+    },
     /// Unary expression
     Unary(UnaryOperator, Box<Expr>),
-    /// Parameters
+    /// Parameters: `NNN` for `?NNN`, the name including its prefix for a named parameter,
+    /// and `?` followed by the number the parser gave it for `?`
     Variable(String),
 }
 
+/// Kind of [Expr::SubqueryResult]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum SubqueryType {
+    /// `EXISTS (SELECT ...)`
+    Exists,
+    /// `(SELECT ...)`, whose value is the first column of the first row
+    Scalar,
+    /// `lhs [NOT] IN (SELECT ...)`
+    In,
+}
+
 impl Expr {
     /// Constructor
     pub fn parenthesized(x: Self) -> Self {
@@ -1743,28 +1773,68 @@ pub type PragmaValue = Expr; // TODO
 #[strum(serialize_all = "snake_case")]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum PragmaName {
+    /// Returns the application ID of the database file.
+    ApplicationId,
     /// set the autovacuum mode
     AutoVacuum,
+    /// `busy_timeout` pragma
+    BusyTimeout,
     /// `cache_size` pragma
     CacheSize,
+    /// `cache_spill` pragma
+    CacheSpill,
+    /// make the LIKE operator case sensitive
+    CaseSensitiveLike,
+    /// the cipher a new encrypted database is created with
+    Cipher,
+    /// the algorithm the pages of a new database are compressed with
+    Compression,
+    /// list the databases attached to the connection
+    DatabaseList,
+    /// the encoding of the text of a new database
+    Encoding,
+    /// give the key of an encrypted database as hex digits
+    Hexkey,
+    /// change the key of an encrypted database to one given as hex digits
+    Hexrekey,
+    /// free pages of an incremental auto-vacuum database
+    IncrementalVacuum,
+    /// report the automatic indexes built by queries since it was turned on
+    IndexAdvisor,
+    /// returns information about the columns of an index
+    IndexInfo,
+    /// list the indexes of a table
+    IndexList,
     /// Run integrity check on the database file
     IntegrityCheck,
     /// `journal_mode` pragma
     JournalMode,
+    /// give the key of an encrypted database, or encrypt a new one
+    Key,
     /// Noop as per SQLite docs
     LegacyFileFormat,
+    /// analyze the tables whose statistics are missing or stale
+    Optimize,
     /// Return the total number of pages in the database file.
     PageCount,
     /// Return the page size of the database in bytes.
     PageSize,
+    /// Run a faster integrity check that doesn't compare indexes with their tables
+    QuickCheck,
+    /// change the key of an encrypted database
+    Rekey,
     /// Returns schema version of the database file.
     SchemaVersion,
+    /// `synchronous` pragma
+    Synchronous,
     /// returns information about the columns of a table
     TableInfo,
     /// enable capture-changes logic for the connection
     UnstableCaptureDataChangesConn,
     /// Returns the user version of the database file.
     UserVersion,
+    /// `wal_autocheckpoint` pragma
+    WalAutocheckpoint,
     /// trigger a checkpoint to run on database(s) if WAL is enabled
     WalCheckpoint,
 }
@@ -1965,6 +2035,8 @@ pub enum TransactionType {
     Immediate,
     /// `EXCLUSIVE`
     Exclusive,
+    /// `CONCURRENT`
+    Concurrent,
 }
 
 /// Upsert clause
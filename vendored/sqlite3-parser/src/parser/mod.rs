@@ -12,9 +12,14 @@ pub mod parse {
     include!(concat!(env!("OUT_DIR"), "/parse.rs"));
 }
 
+use std::collections::HashSet;
+
 use crate::dialect::Token;
 use ast::{Cmd, ExplainKind, Name, Stmt};
 
+/// The largest parameter number, like `SQLITE_MAX_VARIABLE_NUMBER`
+pub const MAX_VARIABLE_NUMBER: usize = 32766;
+
 /// Parser error
 #[derive(Debug, PartialEq)]
 pub enum ParserError {
@@ -64,6 +69,8 @@ pub struct Context<'input> {
     module_args: Option<Vec<String>>,   // CREATE VIRTUAL TABLE args
     done: bool,
     error: Option<ParserError>,
+    variable_count: usize,           // largest parameter number so far
+    variable_names: HashSet<String>, // named parameters seen so far
 }
 
 impl<'input> Context<'input> {
@@ -77,6 +84,8 @@ impl<'input> Context<'input> {
             module_args: None,
             done: false,
             error: None,
+            variable_count: 0,
+            variable_names: HashSet::new(),
         }
     }
 
@@ -123,6 +132,35 @@ impl<'input> Context<'input> {
         self.module_args.take()
     }
 
+    /// Numbers a parameter the way SQLite does: `?NNN` is parameter NNN, and `?` or the first
+    /// occurrence of a named parameter is the parameter after the largest one so far.
+    /// A `?` is returned as `?` followed by its number, see [ast::Expr::Variable].
+    fn variable(&mut self, variable: String) -> Result<String, ParserError> {
+        if variable.is_empty() {
+            self.variable_count += 1;
+            return Ok(format!("?{}", self.variable_count));
+        }
+        if variable.as_bytes()[0].is_ascii_digit() {
+            match variable.parse::<usize>() {
+                Ok(n) if (1..=MAX_VARIABLE_NUMBER).contains(&n) => {
+                    self.variable_count = self.variable_count.max(n);
+                }
+                _ => {
+                    return Err(custom_err!(
+                        "variable number must be between ?1 and ?{}",
+                        MAX_VARIABLE_NUMBER
+                    ))
+                }
+            }
+        } else if self.variable_names.insert(variable.clone()) {
+            self.variable_count += 1;
+        }
+        if self.variable_count > MAX_VARIABLE_NUMBER {
+            return Err(custom_err!("too many SQL variables"));
+        }
+        Ok(variable)
+    }
+
     /// This routine is called after a single SQL statement has been parsed.
     fn sqlite3_finish_coding(&mut self) {
         self.done = true;
@@ -150,5 +188,7 @@ impl<'input> Context<'input> {
         self.module_args = None;
         self.done = false;
         self.error = None;
+        self.variable_count = 0;
+        self.variable_names.clear();
     }
 }
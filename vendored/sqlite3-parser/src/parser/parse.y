@@ -91,6 +91,14 @@ transtype(A) ::= .             {A = None;}
 transtype(A) ::= DEFERRED.  {A = Some(TransactionType::Deferred);}
 transtype(A) ::= IMMEDIATE. {A = Some(TransactionType::Immediate);}
 transtype(A) ::= EXCLUSIVE. {A = Some(TransactionType::Exclusive);}
+transtype(A) ::= nm(X). {
+  let tx_type = X;
+  if tx_type == "concurrent" {
+    A = Some(TransactionType::Concurrent);
+  }else{
+    return Err(custom_err!("unknown transaction type: {}", tx_type));
+  }
+}
 cmd ::= COMMIT|END trans_opt(X).   {self.ctx.stmt = Some(Stmt::Commit(X));}
 cmd ::= ROLLBACK trans_opt(X).     {self.ctx.stmt = Some(Stmt::Rollback{tx_name: X, savepoint_name: None});}
 
@@ -904,7 +912,8 @@ term(A) ::= FLOAT|INTEGER(X). {
   A = Expr::Literal(Literal::Numeric(X.unwrap())); /*A-overwrites-X*/
 }
 expr(A) ::= VARIABLE(X).     {
-  A = Expr::Variable(X.unwrap()); /*A-overwrites-X*/
+  let name = X.unwrap();
+  A = Expr::Variable(self.ctx.variable(name)?); /*A-overwrites-X*/
 }
 expr(A) ::= expr(X) COLLATE ids(C). {
   A = Expr::collate(X, @C, C); /*A-overwrites-X*/
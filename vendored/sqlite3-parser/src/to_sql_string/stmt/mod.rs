@@ -87,6 +87,8 @@ mod tests {
 
     to_sql_string_test!(test_transaction_exclusive, "BEGIN EXCLUSIVE");
 
+    to_sql_string_test!(test_transaction_concurrent, "BEGIN CONCURRENT");
+
     to_sql_string_test!(test_commit, "COMMIT");
 
     // Test a simple index on a single column